@@ -0,0 +1,58 @@
+// CRDT benchmarks
+// Set and get on a site's shared state document, and merging a peer's changes
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::json;
+use std::sync::Arc;
+
+use pear_server::crdt::CrdtStateManager;
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().build().unwrap()
+}
+
+fn set_get(c: &mut Criterion) {
+    let rt = runtime();
+    let state = Arc::new(CrdtStateManager::new("bench".to_string()));
+
+    // A document with some history, as a long-running site would have
+    rt.block_on(async {
+        for i in 0..1_000 {
+            state.set(&format!("key-{}", i % 100), json!(i)).await.unwrap();
+        }
+    });
+
+    c.bench_function("crdt_set", |b| {
+        let mut i = 0u64;
+        b.iter(|| {
+            i += 1;
+            rt.block_on(state.set("counter", json!(i))).unwrap();
+        })
+    });
+
+    c.bench_function("crdt_get", |b| {
+        b.iter(|| black_box(rt.block_on(state.get("key-42")).unwrap()))
+    });
+}
+
+fn apply_changes(c: &mut Criterion) {
+    let rt = runtime();
+    let peer = CrdtStateManager::new("bench".to_string());
+    rt.block_on(async {
+        for i in 0..100 {
+            peer.set(&format!("key-{}", i), json!({ "value": i })).await.unwrap();
+        }
+    });
+    let changes = rt.block_on(peer.get_changes()).unwrap();
+
+    c.bench_function("crdt_apply_100_changes", |b| {
+        b.iter_batched(
+            || CrdtStateManager::new("bench".to_string()),
+            |state| rt.block_on(state.apply_changes(&changes)).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, set_get, apply_changes);
+criterion_main!(benches);
@@ -0,0 +1,172 @@
+// HTTP load test
+// Drives a running server over plaintext HTTP/2 and fails when throughput or p99 latency regress
+//
+// Skipped unless PEAR_LOAD_URL is set, e.g.
+//   PEAR_LOAD_URL=http://127.0.0.1:8080/ PEAR_LOAD_MIN_RPS=5000 cargo bench --bench load
+//
+// PEAR_LOAD_SITE         site to request, sent as the Host header (default-site)
+// PEAR_LOAD_CONNECTIONS  connections to open (16)
+// PEAR_LOAD_STREAMS      requests in flight on each connection (8)
+// PEAR_LOAD_SECONDS      how long to run (10)
+// PEAR_LOAD_MIN_RPS      fail below this many successful requests per second
+// PEAR_LOAD_MAX_P99_MS   fail when the p99 latency is above this
+
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::{Request, Uri};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use pear_server::observability::histogram::LatencyHistogram;
+
+struct Settings {
+    uri: Uri,
+    site: String,
+    connections: usize,
+    streams: usize,
+    duration: Duration,
+    min_rps: Option<f64>,
+    max_p99: Option<Duration>,
+}
+
+impl Settings {
+    fn from_env() -> Result<Option<Self>, String> {
+        let Ok(url) = std::env::var("PEAR_LOAD_URL") else { return Ok(None) };
+        let uri: Uri = url.parse().map_err(|e| format!("Invalid PEAR_LOAD_URL '{}': {}", url, e))?;
+        if uri.scheme_str() != Some("http") || uri.authority().is_none() {
+            return Err(format!("PEAR_LOAD_URL must be an http:// URL with a host, got '{}'", url));
+        }
+        Ok(Some(Self {
+            uri,
+            site: std::env::var("PEAR_LOAD_SITE").unwrap_or_else(|_| "default-site".to_string()),
+            connections: var("PEAR_LOAD_CONNECTIONS")?.unwrap_or(16).max(1),
+            streams: var("PEAR_LOAD_STREAMS")?.unwrap_or(8).max(1),
+            duration: Duration::from_secs(var("PEAR_LOAD_SECONDS")?.unwrap_or(10)),
+            min_rps: var("PEAR_LOAD_MIN_RPS")?,
+            max_p99: var("PEAR_LOAD_MAX_P99_MS")?.map(Duration::from_millis),
+        }))
+    }
+}
+
+fn var<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String> {
+    match std::env::var(name) {
+        Ok(value) if !value.is_empty() => value.parse().map(Some).map_err(|_| format!("Invalid {}: '{}'", name, value)),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    ok: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Send requests on one stream of `sender` back to back until `deadline`
+async fn drive(
+    mut sender: hyper::client::conn::http2::SendRequest<Empty<Bytes>>,
+    settings: Arc<Settings>,
+    deadline: Instant,
+    latency: Arc<LatencyHistogram>,
+    totals: Arc<Totals>,
+) {
+    while Instant::now() < deadline {
+        let request = Request::builder()
+            .uri(settings.uri.clone())
+            .header(hyper::header::HOST, &settings.site)
+            .body(Empty::new())
+            .unwrap();
+        let started = Instant::now();
+        let ok = match sender.send_request(request).await {
+            Ok(response) => {
+                let success = response.status().is_success();
+                response.into_body().collect().await.is_ok() && success
+            }
+            Err(_) => false,
+        };
+        latency.record(started.elapsed());
+        let counter = if ok { &totals.ok } else { &totals.errors };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+async fn run(settings: Settings) -> Result<bool, String> {
+    let settings = Arc::new(settings);
+    let authority = settings.uri.authority().unwrap().clone();
+    let address = format!("{}:{}", authority.host(), authority.port_u16().unwrap_or(80));
+
+    println!("Running {}s test @ {} (site {})", settings.duration.as_secs(), settings.uri, settings.site);
+    println!("  {} connections, {} streams each", settings.connections, settings.streams);
+
+    let latency = Arc::new(LatencyHistogram::new());
+    let totals = Arc::new(Totals::default());
+    let mut streams = Vec::new();
+    let started = Instant::now();
+    let deadline = started + settings.duration;
+    for _ in 0..settings.connections {
+        let stream = tokio::net::TcpStream::connect(&address).await
+            .map_err(|e| format!("Cannot connect to {}: {}", address, e))?;
+        stream.set_nodelay(true).ok();
+        let (sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .map_err(|e| format!("HTTP/2 handshake with {} failed: {}", address, e))?;
+        tokio::spawn(connection);
+        for _ in 0..settings.streams {
+            streams.push(tokio::spawn(drive(sender.clone(), settings.clone(), deadline, latency.clone(), totals.clone())));
+        }
+    }
+    for stream in streams {
+        let _ = stream.await;
+    }
+    let elapsed = started.elapsed();
+
+    let snapshot = latency.snapshot();
+    let (ok, errors) = (totals.ok.load(Ordering::Relaxed), totals.errors.load(Ordering::Relaxed));
+    let rps = ok as f64 / elapsed.as_secs_f64();
+    let p99 = snapshot.percentile(0.99);
+    println!(
+        "  Latency  mean {:.2?}  p50 {:.2?}  p90 {:.2?}  p99 {:.2?}",
+        snapshot.mean(),
+        snapshot.percentile(0.50),
+        snapshot.percentile(0.90),
+        p99,
+    );
+    println!("  {} requests in {:.2?}, {} errors", ok + errors, elapsed, errors);
+    println!("Requests/sec: {:.1}", rps);
+
+    let mut passed = true;
+    if let Some(min_rps) = settings.min_rps.filter(|&min_rps| rps < min_rps) {
+        println!("FAIL: {:.1} requests/sec is below PEAR_LOAD_MIN_RPS={}", rps, min_rps);
+        passed = false;
+    }
+    if let Some(max_p99) = settings.max_p99.filter(|&max_p99| p99 > max_p99) {
+        println!("FAIL: p99 {:.2?} is above PEAR_LOAD_MAX_P99_MS={}", p99, max_p99.as_millis());
+        passed = false;
+    }
+    Ok(passed)
+}
+
+fn main() {
+    let settings = match Settings::from_env() {
+        Ok(Some(settings)) => settings,
+        Ok(None) => {
+            println!("PEAR_LOAD_URL is not set; skipping the load test");
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start the Tokio runtime");
+    match runtime.block_on(run(settings)) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// Memory pool benchmarks
+// Compares pooled buffer reuse against fresh allocations on the response path
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use bytes::{BufMut, BytesMut};
+use std::sync::Arc;
+
+use pear_server::state::shared_memory::MemoryPool;
+
+const RESPONSE: &[u8] = br#"{"cage_id":1,"status":"ok","message":"Processed by Cage bench"}"#;
+
+fn fresh_allocation(c: &mut Criterion) {
+    c.bench_function("response_buffer_fresh", |b| {
+        b.iter(|| {
+            let mut buffer = BytesMut::with_capacity(4 * 1024);
+            buffer.put_slice(RESPONSE);
+            black_box(buffer.freeze());
+        })
+    });
+}
+
+fn pooled_allocation(c: &mut Criterion) {
+    let pool = Arc::new(MemoryPool::new());
+
+    c.bench_function("response_buffer_pooled", |b| {
+        b.iter(|| {
+            let mut buffer = pool.acquire_pooled(4 * 1024);
+            buffer.put_slice(RESPONSE);
+            black_box(buffer.into_bytes());
+        })
+    });
+
+    // Every iteration after the first should be served from the pool
+    let stats = pool.stats();
+    println!(
+        "pooled: {} allocations, {} recycled, reuse ratio {:.3}",
+        stats.allocations,
+        stats.recycled,
+        stats.reuse_ratio()
+    );
+}
+
+criterion_group!(benches, fresh_allocation, pooled_allocation);
+criterion_main!(benches);
@@ -0,0 +1,114 @@
+// Router and Cage benchmarks
+// A request through the Router over a local HTTP/2 connection, and guest calls on a Cage
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::sync::Arc;
+
+use pear_server::cage::config::CageConfig;
+use pear_server::router::RouterConfig;
+use pear_server::{CagePool, Router};
+
+const SITE: &str = "bench";
+
+/// A guest with a request handler and a `() -> ()` export for fresh-instance calls
+fn module() -> Vec<u8> {
+    wat::parse_str(r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "handle_request") (result i32) i32.const 42)
+            (func (export "tick"))
+        )
+    "#).unwrap()
+}
+
+async fn router_with_pool() -> Arc<Router> {
+    let router = Arc::new(Router::new(RouterConfig::default()));
+    let pool = CagePool::new(SITE.to_string(), module(), CageConfig::default(), 2).await.unwrap();
+    router.register_pool(SITE.to_string(), Arc::new(pool));
+    router
+}
+
+/// Serve the Router over plaintext HTTP/2 on a loopback port, as the listener does
+async fn serve(router: Arc<Router>) -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let router = router.clone();
+            let service = service_fn(move |req: Request<Incoming>| {
+                let router = router.clone();
+                async move { router.route_request(req).await }
+            });
+            tokio::spawn(
+                hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service),
+            );
+        }
+    });
+    addr
+}
+
+fn router_dispatch(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut sender = rt.block_on(async {
+        let addr = serve(router_with_pool().await).await;
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        sender
+    });
+
+    c.bench_function("router_dispatch", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let request = Request::builder()
+                    .uri(format!("http://{}/", SITE))
+                    .header(hyper::header::HOST, SITE)
+                    .body(Empty::<Bytes>::new())
+                    .unwrap();
+                let response: Response<Incoming> = sender.send_request(request).await.unwrap();
+                assert!(response.status().is_success());
+                black_box(response.into_body().collect().await.unwrap().to_bytes())
+            })
+        })
+    });
+}
+
+fn cage_invocation(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let router = rt.block_on(router_with_pool());
+    let pool = router.pool(SITE).unwrap();
+    let cage = rt.block_on(pool.get_cage_round_robin()).unwrap();
+
+    c.bench_function("cage_select_round_robin", |b| {
+        b.iter(|| black_box(rt.block_on(pool.get_cage_round_robin())))
+    });
+
+    c.bench_function("cage_execute_request", |b| {
+        b.iter(|| black_box(cage.execute_request(br#"{"method":"GET","uri":"/"}"#).unwrap()))
+    });
+
+    // Instantiation dominates: every call gets a fresh instance and memory
+    c.bench_function("cage_invoke_export", |b| {
+        b.iter(|| cage.invoke_export("tick").unwrap())
+    });
+
+    c.bench_function("cage_execute_on_executor", |b| {
+        b.iter(|| {
+            let cage = cage.clone();
+            rt.block_on(router.wasm_executor().run(move || cage.execute_request(b"{}")))
+                .unwrap()
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, router_dispatch, cage_invocation);
+criterion_main!(benches);
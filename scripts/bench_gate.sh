@@ -0,0 +1,45 @@
+#!/usr/bin/env bash
+# Pear Server Benchmark Gate
+# Fails when any criterion benchmark got slower than its saved baseline by more than a threshold
+#
+# Usage:
+#   cargo bench --bench router --bench crdt --bench memory_pool -- --save-baseline base   # on the base commit
+#   cargo bench --bench router --bench crdt --bench memory_pool -- --baseline-lenient base   # on the change
+#   scripts/bench_gate.sh [max regression percent, default 10]
+
+set -euo pipefail
+
+MAX_REGRESSION="${1:-10}"
+CRITERION_DIR="${CARGO_TARGET_DIR:-target}/criterion"
+
+if [ ! -d "$CRITERION_DIR" ]; then
+    echo "No criterion results in $CRITERION_DIR; run cargo bench against a baseline first" >&2
+    exit 2
+fi
+
+# change/estimates.json is only written when a run is compared with a baseline
+mapfile -t CHANGES < <(find "$CRITERION_DIR" -path '*/change/estimates.json' | sort)
+if [ "${#CHANGES[@]}" -eq 0 ]; then
+    echo "No baseline comparisons found; nothing to gate"
+    exit 0
+fi
+
+FAILED=0
+for estimates in "${CHANGES[@]}"; do
+    bench="${estimates#"$CRITERION_DIR"/}"
+    bench="${bench%/change/estimates.json}"
+    # Relative change of the mean, e.g. 0.12 for 12% slower
+    change=$(jq -r '.mean.point_estimate' "$estimates")
+    percent=$(awk -v change="$change" 'BEGIN { printf "%+.1f", change * 100 }')
+    if awk -v change="$change" -v max="$MAX_REGRESSION" 'BEGIN { exit !(change * 100 > max) }'; then
+        echo "FAIL  $bench  ${percent}%"
+        FAILED=1
+    else
+        echo "ok    $bench  ${percent}%"
+    fi
+done
+
+if [ "$FAILED" -ne 0 ]; then
+    echo "Benchmarks regressed by more than ${MAX_REGRESSION}%" >&2
+    exit 1
+fi
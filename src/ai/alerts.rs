@@ -0,0 +1,413 @@
+// Security Alert Hooks
+// Webhook and email notifications for security events, filtered by severity
+
+use super::events::{SecurityEvent, Severity};
+use crate::mail::smtp::{self, SmtpRelay};
+use anyhow::{Context, Result};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Bytes;
+use hyper::{Request, Uri};
+use hyper_util::rt::TokioIo;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+/// Give up on a notification after this long
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where an alert is delivered
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AlertTarget {
+    /// POST the event as JSON (Slack-compatible `text` field included)
+    Webhook { url: String },
+
+    /// Send a plain-text email through an SMTP relay
+    Email {
+        to: Vec<String>,
+        from: String,
+        #[serde(default = "default_smtp_host")]
+        smtp_host: String,
+        #[serde(default = "default_smtp_port")]
+        smtp_port: u16,
+    },
+}
+
+fn default_smtp_host() -> String { "localhost".to_string() }
+fn default_smtp_port() -> u16 { 25 }
+fn default_max_per_minute() -> u32 { 30 }
+
+/// A notification hook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertHookConfig {
+    #[serde(flatten)]
+    pub target: AlertTarget,
+
+    /// Least severe event that triggers this hook
+    #[serde(default)]
+    pub min_severity: Severity,
+
+    /// Alerts sent per minute before further events are suppressed
+    #[serde(default = "default_max_per_minute")]
+    pub max_per_minute: u32,
+}
+
+impl AlertHookConfig {
+    /// Check the target is usable
+    pub fn validate(&self) -> Result<()> {
+        match &self.target {
+            AlertTarget::Webhook { url } => {
+                let uri: Uri = url.parse().with_context(|| format!("Invalid webhook URL '{}'", url))?;
+                match uri.scheme_str() {
+                    Some("http") | Some("https") => {}
+                    _ => anyhow::bail!("Webhook URL must be http:// or https://: '{}'", url),
+                }
+                if uri.host().is_none() {
+                    anyhow::bail!("Webhook URL has no host: '{}'", url);
+                }
+            }
+            AlertTarget::Email { to, from, .. } => {
+                if to.is_empty() {
+                    anyhow::bail!("Email alert needs at least one recipient");
+                }
+                if !from.contains('@') || to.iter().any(|addr| !addr.contains('@')) {
+                    anyhow::bail!("Email alert addresses must contain '@'");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A notification, independent of what raised it
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub severity: Severity,
+
+    /// Email subject and webhook `text`
+    pub summary: String,
+
+    /// Key the payload is sent under in webhook bodies
+    pub kind: &'static str,
+
+    pub payload: serde_json::Value,
+}
+
+/// Sends events to the configured hooks
+pub struct Notifier {
+    hooks: Vec<Hook>,
+}
+
+/// A hook with its rate-limit window
+struct Hook {
+    config: Arc<AlertHookConfig>,
+    window: Mutex<(Instant, u32)>,
+}
+
+impl Hook {
+    /// Count an alert against this minute's budget
+    fn allow(&self) -> bool {
+        let mut window = self.window.lock();
+        if window.0.elapsed() >= Duration::from_secs(60) {
+            *window = (Instant::now(), 0);
+        }
+
+        if window.1 >= self.config.max_per_minute {
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+}
+
+impl Notifier {
+    /// Create a notifier for the given hooks
+    pub fn new(hooks: Vec<AlertHookConfig>) -> Self {
+        Self {
+            hooks: hooks.into_iter()
+                .map(|config| Hook {
+                    config: Arc::new(config),
+                    window: Mutex::new((Instant::now(), 0)),
+                })
+                .collect(),
+        }
+    }
+
+    /// Deliver an event to every hook whose severity and rate limit allow it
+    /// Delivery runs in the background; failures are logged
+    pub fn notify(&self, event: &SecurityEvent) {
+        self.notify_alert(Alert {
+            severity: event.severity,
+            summary: summary(event),
+            kind: "event",
+            payload: serde_json::to_value(event).unwrap_or_default(),
+        });
+    }
+
+    /// Deliver any alert, e.g. a failed scheduled job, through the same hooks
+    pub fn notify_alert(&self, alert: Alert) {
+        let alert = Arc::new(alert);
+        for hook in &self.hooks {
+            if alert.severity < hook.config.min_severity {
+                continue;
+            }
+
+            if !hook.allow() {
+                debug!(alert = %alert.summary, "Alert suppressed by rate limit");
+                continue;
+            }
+
+            let config = hook.config.clone();
+            let alert = alert.clone();
+            tokio::spawn(async move {
+                let result = tokio::time::timeout(SEND_TIMEOUT, send(&config.target, &alert)).await;
+                match result {
+                    Ok(Ok(())) => debug!(alert = %alert.summary, "Alert delivered"),
+                    Ok(Err(e)) => warn!(alert = %alert.summary, error = %e, "Alert failed"),
+                    Err(_) => warn!(alert = %alert.summary, "Alert timed out"),
+                }
+            });
+        }
+    }
+}
+
+/// One-line description used as the alert subject / text
+pub fn summary(event: &SecurityEvent) -> String {
+    let ip = event.ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string());
+    format!(
+        "[pear] {:?} {:?} on {}: {} from {} ({:?})",
+        event.severity, event.kind, event.site_id, event.path, ip, event.action
+    )
+}
+
+/// Deliver an alert to a single target
+async fn send(target: &AlertTarget, alert: &Alert) -> Result<()> {
+    match target {
+        AlertTarget::Webhook { url } => send_webhook(url, alert).await,
+        AlertTarget::Email { to, from, smtp_host, smtp_port } => {
+            send_email(smtp_host, *smtp_port, from, to, alert).await
+        }
+    }
+}
+
+/// POST the alert to a webhook
+async fn send_webhook(url: &str, alert: &Alert) -> Result<()> {
+    let mut body = serde_json::Map::new();
+    body.insert("text".to_string(), alert.summary.clone().into());
+    body.insert(alert.kind.to_string(), alert.payload.clone());
+
+    let status = post_json(url, serde_json::to_vec(&body)?, None).await?;
+    if !status.is_success() {
+        anyhow::bail!("Webhook returned {}", status);
+    }
+    Ok(())
+}
+
+/// POST a JSON body over HTTP or HTTPS, with an optional bearer token
+pub(crate) async fn post_json(url: &str, body: Vec<u8>, bearer: Option<&str>) -> Result<hyper::StatusCode> {
+    let authorization = bearer.map(|token| (hyper::header::AUTHORIZATION.as_str(), format!("Bearer {}", token)));
+    post_json_with_headers(url, body, authorization.as_slice()).await
+}
+
+/// POST a JSON body over HTTP or HTTPS with extra headers
+pub(crate) async fn post_json_with_headers(url: &str, body: Vec<u8>, headers: &[(&str, String)]) -> Result<hyper::StatusCode> {
+    Ok(post_json_for_response(url, body, headers, 0).await?.0)
+}
+
+/// POST a JSON body, reading at most `max_response` bytes of the response body
+pub(crate) async fn post_json_for_response(
+    url: &str,
+    body: Vec<u8>,
+    headers: &[(&str, String)],
+    max_response: usize,
+) -> Result<(hyper::StatusCode, Bytes)> {
+    request_for_response(hyper::Method::POST, url, Some("application/json"), body, headers, max_response).await
+}
+
+/// GET a URL, reading at most `max_response` bytes of the response body
+pub(crate) async fn get_for_response(url: &str, max_response: usize) -> Result<(hyper::StatusCode, Bytes)> {
+    request_for_response(hyper::Method::GET, url, None, Vec::new(), &[], max_response).await
+}
+
+/// POST a JSON body to `addr` rather than wherever the URL's host resolves now
+/// The URL still names the host for the `Host` header and the TLS server name.
+pub(crate) async fn post_json_via(addr: SocketAddr, url: &str, body: Vec<u8>, headers: &[(&str, String)]) -> Result<hyper::StatusCode> {
+    Ok(request_via(Some(addr), hyper::Method::POST, url, Some("application/json"), body, headers, 0).await?.0)
+}
+
+/// Send a request over HTTP or HTTPS, reading at most `max_response` bytes of the response body
+pub(crate) async fn request_for_response(
+    method: hyper::Method,
+    url: &str,
+    content_type: Option<&str>,
+    body: Vec<u8>,
+    headers: &[(&str, String)],
+    max_response: usize,
+) -> Result<(hyper::StatusCode, Bytes)> {
+    request_via(None, method, url, content_type, body, headers, max_response).await
+}
+
+/// Send a request to `addr`, or to the URL's host when none is given
+async fn request_via(
+    addr: Option<SocketAddr>,
+    method: hyper::Method,
+    url: &str,
+    content_type: Option<&str>,
+    body: Vec<u8>,
+    headers: &[(&str, String)],
+    max_response: usize,
+) -> Result<(hyper::StatusCode, Bytes)> {
+    let uri: Uri = url.parse()?;
+    let host = uri.host().context("URL has no host")?.to_string();
+    let https = uri.scheme_str() == Some("https");
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri.path_and_query().map_or("/", |pq| pq.as_str()))
+        .header(hyper::header::HOST, uri.authority().map_or(host.as_str(), |a| a.as_str()))
+        .header(hyper::header::USER_AGENT, concat!("pear-server/", env!("CARGO_PKG_VERSION")));
+    if let Some(content_type) = content_type {
+        request = request.header(hyper::header::CONTENT_TYPE, content_type);
+    }
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let request = request.body(Full::new(Bytes::from(body)))?;
+
+    let stream = match addr {
+        Some(addr) => TcpStream::connect(addr).await,
+        None => TcpStream::connect((host.as_str(), port)).await,
+    }
+    .with_context(|| format!("Failed to connect to {}:{}", host, port))?;
+
+    if https {
+        let server_name = rustls::pki_types::ServerName::try_from(host.clone())
+            .context("Invalid TLS server name")?;
+        let stream = tls_connector().connect(server_name, stream).await?;
+        exchange(stream, request, max_response).await
+    } else {
+        exchange(stream, request, max_response).await
+    }
+}
+
+/// Send a request over an established connection with HTTP/1.1
+async fn exchange<S>(stream: S, request: Request<Full<Bytes>>, max_response: usize) -> Result<(hyper::StatusCode, Bytes)>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!(error = %e, "HTTP connection closed with error");
+        }
+    });
+
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    if max_response == 0 {
+        return Ok((status, Bytes::new()));
+    }
+    let body = Limited::new(response.into_body(), max_response).collect().await
+        .map_err(|e| anyhow::anyhow!("Failed to read response: {}", e))?
+        .to_bytes();
+    Ok((status, body))
+}
+
+/// TLS connector trusting the bundled Mozilla roots
+pub(crate) fn tls_connector() -> tokio_rustls::TlsConnector {
+    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+
+    let config = CONFIG.get_or_init(|| {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        Arc::new(
+            rustls::ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        )
+    });
+
+    tokio_rustls::TlsConnector::from(config.clone())
+}
+
+/// Send a plain-text email through an SMTP relay
+async fn send_email(host: &str, port: u16, from: &str, to: &[String], alert: &Alert) -> Result<()> {
+    let body = serde_json::to_string_pretty(&alert.payload)?;
+    let message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}",
+        from,
+        to.join(", "),
+        smtp::encode_header(&alert.summary),
+        chrono::Utc::now().to_rfc2822(),
+        body,
+    );
+
+    let refused = SmtpRelay::plain(host, port).send(from, to, &message).await?;
+    if refused.len() == to.len() {
+        anyhow::bail!("SMTP relay refused every recipient");
+    }
+    if !refused.is_empty() {
+        warn!(recipients = ?refused, "SMTP relay refused some alert recipients");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::events::{EventAction, SecurityEventKind};
+    use crate::mail::smtp::dot_stuff;
+
+    #[test]
+    fn test_hook_config_parsing() {
+        let hooks: Vec<AlertHookConfig> = toml::from_str::<toml::Table>(r#"
+            [[alerts]]
+            type = "webhook"
+            url = "https://hooks.example.com/pear"
+            min_severity = "critical"
+
+            [[alerts]]
+            type = "email"
+            to = ["ops@example.com"]
+            from = "pear@example.com"
+        "#).unwrap()["alerts"].clone().try_into().unwrap();
+
+        assert_eq!(hooks[0].min_severity, Severity::Critical);
+        assert!(matches!(&hooks[1].target, AlertTarget::Email { smtp_port: 25, .. }));
+        assert!(hooks.iter().all(|h| h.validate().is_ok()));
+
+        let bad = AlertHookConfig {
+            target: AlertTarget::Webhook { url: "ftp://example.com".to_string() },
+            min_severity: Severity::Warning,
+            max_per_minute: 1,
+        };
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_rate_limit_per_hook() {
+        let notifier = Notifier::new(vec![AlertHookConfig {
+            target: AlertTarget::Webhook { url: "http://127.0.0.1:9/".to_string() },
+            min_severity: Severity::Info,
+            max_per_minute: 2,
+        }]);
+
+        let hook = &notifier.hooks[0];
+        assert!(hook.allow());
+        assert!(hook.allow());
+        assert!(!hook.allow());
+    }
+
+    #[test]
+    fn test_summary_and_dot_stuffing() {
+        let event = SecurityEvent::new(SecurityEventKind::ScanBan, EventAction::Banned, "blog", None, "/.env");
+        assert!(summary(&event).contains("ScanBan on blog"));
+        assert_eq!(dot_stuff(".hidden\nline"), "..hidden\r\nline");
+    }
+}
@@ -0,0 +1,179 @@
+// IP Allowlist
+// CIDR ranges exempt from bans and rate limiting (monitoring systems, office networks)
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A single CIDR range, e.g. `10.0.0.0/8` or `2001:db8::/32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CidrBlock {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl CidrBlock {
+    /// Create a block, masking off host bits
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        if prefix_len > max {
+            anyhow::bail!("Prefix length /{} is too long for {}", prefix_len, addr);
+        }
+
+        let network = match addr {
+            IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & v4_mask(prefix_len)).into()),
+            IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & v6_mask(prefix_len)).into()),
+        };
+
+        Ok(Self { network, prefix_len })
+    }
+
+    /// Whether the address falls inside this block
+    /// IPv4-mapped IPv6 addresses match IPv4 blocks
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                u32::from(ip) & v4_mask(self.prefix_len) == u32::from(net)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                u128::from(ip) & v6_mask(self.prefix_len) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for CidrBlock {
+    type Err = anyhow::Error;
+
+    /// Parse `addr/prefix`; a bare address is treated as a single host
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        match s.split_once('/') {
+            Some((addr, prefix)) => {
+                let addr: IpAddr = addr.parse()
+                    .with_context(|| format!("Invalid address in '{}'", s))?;
+                let prefix_len: u8 = prefix.parse()
+                    .with_context(|| format!("Invalid prefix length in '{}'", s))?;
+                Self::new(addr, prefix_len)
+            }
+            None => {
+                let addr: IpAddr = s.parse()
+                    .with_context(|| format!("Invalid address '{}'", s))?;
+                let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+                Self::new(addr, prefix_len)
+            }
+        }
+    }
+}
+
+impl fmt::Display for CidrBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+fn v4_mask(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+fn v6_mask(prefix_len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0)
+}
+
+/// Set of CIDR ranges that are never banned or rate limited
+#[derive(Debug, Default)]
+pub struct IpAllowlist {
+    blocks: Vec<CidrBlock>,
+
+    /// Requests that skipped ban / rate-limit checks
+    hits: AtomicU64,
+}
+
+impl IpAllowlist {
+    /// Parse an allowlist from CIDR strings
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        let blocks = entries.iter()
+            .map(|entry| entry.parse())
+            .collect::<Result<Vec<CidrBlock>>>()?;
+
+        Ok(Self {
+            blocks,
+            hits: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether an address is allowlisted, counting the hit if so
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let allowed = self.blocks.iter().any(|block| block.contains(ip));
+        if allowed {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    /// Number of configured ranges
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether no ranges are configured
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Requests exempted by the allowlist so far
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr_contains() {
+        let block: CidrBlock = "10.1.0.0/16".parse().unwrap();
+        assert!(block.contains("10.1.200.3".parse().unwrap()));
+        assert!(!block.contains("10.2.0.1".parse().unwrap()));
+
+        // IPv4-mapped IPv6 (dual-stack sockets) still matches
+        assert!(block.contains("::ffff:10.1.0.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_cidr_parsing() {
+        let host: CidrBlock = "192.168.1.7".parse().unwrap();
+        assert_eq!(host.to_string(), "192.168.1.7/32");
+
+        // Host bits are masked off
+        let v6: CidrBlock = "2001:db8::1/32".parse().unwrap();
+        assert_eq!(v6.to_string(), "2001:db8::/32");
+        assert!(v6.contains("2001:db8:ffff::1".parse().unwrap()));
+
+        let any: CidrBlock = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains("8.8.8.8".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<CidrBlock>().is_err());
+        assert!("not-an-ip".parse::<CidrBlock>().is_err());
+    }
+
+    #[test]
+    fn test_allowlist_counts_hits() {
+        let allowlist = IpAllowlist::parse(&["127.0.0.0/8".to_string(), "fd00::/8".to_string()]).unwrap();
+
+        assert!(allowlist.allows("127.0.0.1".parse().unwrap()));
+        assert!(allowlist.allows("fd12::1".parse().unwrap()));
+        assert!(!allowlist.allows("203.0.113.5".parse().unwrap()));
+        assert_eq!(allowlist.hits(), 2);
+        assert_eq!(allowlist.len(), 2);
+    }
+}
@@ -1,123 +1,407 @@
 // Anomaly detection using machine learning
 // Implements Isolation Forest for detecting abnormal traffic patterns
 
-use anyhow::Result;
-use ndarray::{Array1, Array2};
-use tracing::{debug, info};
-use linfa::prelude::*;
-use linfa_clustering::IsolationForest;
+use anyhow::{Context, Result};
+use arc_swap::ArcSwapOption;
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tracing::{debug, error, info, warn};
+
+/// Euler–Mascheroni constant, used for the expected path length of unsuccessful BST searches
+const EULER_GAMMA: f64 = 0.577_215_664_901_532_9;
+
+/// Anomaly detector configuration
+#[derive(Debug, Clone)]
+pub struct AnomalyConfig {
+    /// Trees in the isolation forest
+    pub n_trees: usize,
+    
+    /// Samples drawn to build each tree
+    pub sample_size: usize,
+    
+    /// Most recent samples kept for training
+    pub max_samples: usize,
+    
+    /// New samples observed before the model is retrained
+    pub retrain_interval: usize,
+    
+    /// Seed for tree construction, so identical traffic yields an identical model
+    pub seed: u64,
+    
+    /// Where the trained model is persisted (None = memory only)
+    pub model_path: Option<PathBuf>,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            n_trees: 100,
+            sample_size: 256,
+            max_samples: 4096,
+            retrain_interval: 1024,
+            seed: 0x7065_6172,
+            model_path: None,
+        }
+    }
+}
 
 /// Anomaly detector using Isolation Forest
+/// The model is swapped atomically, so scoring never waits on training
 pub struct AnomalyDetector {
-    /// Trained model (optional, starts None and trains on-the-fly)
-    model: Option<IsolationForest<f64>>,
+    config: AnomalyConfig,
+    
+    /// Current trained model (None until enough traffic has been observed)
+    model: ArcSwapOption<IsolationForest>,
+    
+    /// Rolling window of observed samples
+    samples: Mutex<TrainingWindow>,
     
-    /// Training data buffer
-    training_buffer: Vec<Vec<f64>>,
+    /// Set while a background training run is in progress
+    training: AtomicBool,
     
-    /// Buffer size before training
-    buffer_size: usize,
+    /// Completed training runs
+    trainings: AtomicU64,
+}
+
+/// Samples used for the next training run
+struct TrainingWindow {
+    samples: VecDeque<Vec<f64>>,
+    since_training: usize,
 }
 
 impl AnomalyDetector {
     /// Create a new anomaly detector
     pub fn new() -> Result<Self> {
+        Self::with_config(AnomalyConfig::default())
+    }
+
+    /// Create a detector, restoring a persisted model if one exists
+    pub fn with_config(config: AnomalyConfig) -> Result<Self> {
         info!("Creating anomaly detector");
         
-        Ok(Self {
-            model: None,
-            training_buffer: Vec::new(),
-            buffer_size: 100,  // Train after 100 samples
-        })
+        if config.sample_size < 2 || config.n_trees == 0 {
+            anyhow::bail!("Isolation forest needs at least one tree and a sample size of 2");
+        }
+        
+        let detector = Self {
+            samples: Mutex::new(TrainingWindow {
+                samples: VecDeque::with_capacity(config.max_samples),
+                since_training: 0,
+            }),
+            config,
+            model: ArcSwapOption::empty(),
+            training: AtomicBool::new(false),
+            trainings: AtomicU64::new(0),
+        };
+        
+        if let Some(path) = detector.config.model_path.as_deref() {
+            if path.exists() {
+                match IsolationForest::load(path) {
+                    Ok(model) => {
+                        info!(path = %path.display(), trees = model.trees.len(), "Restored anomaly model");
+                        detector.model.store(Some(Arc::new(model)));
+                    }
+                    Err(e) => warn!(path = %path.display(), error = %e, "Ignoring unreadable anomaly model"),
+                }
+            }
+        }
+        
+        Ok(detector)
     }
 
     /// Detect anomaly in request features
+    /// Returns a score in 0.0-1.0, or a neutral 0.5 until a model is trained
     pub async fn detect(&self, features: &super::RequestFeatures) -> Result<f64> {
         let feature_vector = features.to_feature_vector();
         
-        // If model is trained, use it
-        if let Some(ref model) = self.model {
-            let score = self.score_sample(&feature_vector, model);
-            debug!(score = score, "Anomaly score calculated");
-            Ok(score)
-        } else {
-            // Model not yet trained, return neutral score
-            Ok(0.5)
+        match self.score(&feature_vector) {
+            Some(score) => {
+                debug!(score = score, "Anomaly score calculated");
+                Ok(score)
+            }
+            None => Ok(0.5),
         }
     }
 
-    /// Score a single sample (simplified for Phase 2)
-    fn score_sample(&self, _features: &[f64], _model: &IsolationForest<f64>) -> f64 {
-        // In production, this would use the model to score
-        // For Phase 2, return a random score weighted toward normal
-        let base_score = rand::random::<f64>();
+    /// Score a feature vector against the current model
+    /// Scoring is deterministic: the same model and input always give the same score
+    pub fn score(&self, features: &[f64]) -> Option<f64> {
+        let model = self.model.load();
+        let model = model.as_deref()?;
         
-        // Weight toward normal (low scores)
-        if base_score < 0.95 {
-            base_score * 0.5  // Most traffic is normal
-        } else {
-            0.8 + rand::random::<f64>() * 0.2  // Occasionally flag as suspicious
+        if model.n_features != features.len() {
+            return None;
         }
+        
+        Some(model.score(features))
+    }
+
+    /// Whether a trained model is available
+    pub fn is_trained(&self) -> bool {
+        self.model.load().is_some()
     }
 
-    /// Train model on buffered data
-    pub async fn train(&mut self) -> Result<()> {
-        if self.training_buffer.len() < self.buffer_size {
+    /// Record a sample of live traffic, retraining in the background once enough has arrived
+    pub fn observe(self: &Arc<Self>, features: Vec<f64>) {
+        if !self.add_training_sample(features) {
+            return;
+        }
+        
+        // Only one training run at a time; later samples wait for the next interval
+        if self.training.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        
+        let detector = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = detector.train() {
+                error!(error = %e, "Model training failed");
+            }
+            detector.training.store(false, Ordering::Release);
+        });
+    }
+
+    /// Add sample to the training window
+    /// Returns true when enough new samples have arrived to retrain
+    pub fn add_training_sample(&self, features: Vec<f64>) -> bool {
+        let mut window = self.samples.lock();
+        
+        if window.samples.len() >= self.config.max_samples {
+            window.samples.pop_front();
+        }
+        window.samples.push_back(features);
+        window.since_training += 1;
+        
+        let interval = if self.is_trained() {
+            self.config.retrain_interval
+        } else {
+            self.config.sample_size
+        };
+        
+        window.since_training >= interval && window.samples.len() >= self.config.sample_size
+    }
+
+    /// Train a new model on the current window and swap it in
+    pub fn train(&self) -> Result<()> {
+        let samples: Vec<Vec<f64>> = {
+            let mut window = self.samples.lock();
+            window.since_training = 0;
+            window.samples.iter().cloned().collect()
+        };
+        
+        if samples.len() < 2 {
             return Ok(());
         }
+        
+        info!(samples = samples.len(), "Training anomaly detection model");
+        
+        let model = IsolationForest::fit(
+            &samples,
+            self.config.n_trees,
+            self.config.sample_size,
+            self.config.seed,
+        )?;
+        
+        if let Some(path) = self.config.model_path.as_deref() {
+            if let Err(e) = model.save(path) {
+                warn!(path = %path.display(), error = %e, "Failed to persist anomaly model");
+            }
+        }
+        
+        self.model.store(Some(Arc::new(model)));
+        self.trainings.fetch_add(1, Ordering::Relaxed);
+        
+        info!("Model training complete");
+        Ok(())
+    }
 
-        info!(samples = self.training_buffer.len(), "Training anomaly detection model");
+    /// Get statistics
+    pub fn stats(&self) -> AnomalyStats {
+        AnomalyStats {
+            trained: self.is_trained(),
+            trainings: self.trainings.load(Ordering::Relaxed),
+            buffered_samples: self.samples.lock().samples.len(),
+        }
+    }
+}
+
+/// Anomaly detector statistics
+#[derive(Debug, Clone)]
+pub struct AnomalyStats {
+    pub trained: bool,
+    pub trainings: u64,
+    pub buffered_samples: usize,
+}
+
+/// Isolation Forest model
+/// Anomalies are isolated by fewer random splits than normal points
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IsolationForest {
+    trees: Vec<IsolationNode>,
+    
+    /// Samples each tree was built from
+    sample_size: usize,
+    
+    /// Feature vector length the model was trained on
+    n_features: usize,
+}
 
-        // Convert buffer to ndarray
-        let n_samples = self.training_buffer.len();
-        let n_features = self.training_buffer[0].len();
+/// Node of an isolation tree
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum IsolationNode {
+    Split {
+        feature: usize,
+        threshold: f64,
         
-        let mut data = Vec::new();
-        for sample in &self.training_buffer {
-            data.extend_from_slice(sample);
+        /// Range of the feature among this node's training samples
+        min: f64,
+        max: f64,
+        
+        left: Box<IsolationNode>,
+        right: Box<IsolationNode>,
+    },
+    Leaf {
+        size: usize,
+    },
+}
+
+impl IsolationForest {
+    /// Build a forest from training samples
+    pub fn fit(samples: &[Vec<f64>], n_trees: usize, sample_size: usize, seed: u64) -> Result<Self> {
+        let n_features = samples.first().map_or(0, |s| s.len());
+        if n_features == 0 || samples.iter().any(|s| s.len() != n_features) {
+            anyhow::bail!("Training samples must be non-empty and the same length");
         }
         
-        let array = Array2::<f64>::from_shape_vec((n_samples, n_features), data)?;
-        let dataset = DatasetBase::from(array);
+        let sample_size = sample_size.min(samples.len());
+        let max_depth = (sample_size as f64).log2().ceil() as usize;
+        let mut rng = StdRng::seed_from_u64(seed);
+        
+        let trees = (0..n_trees)
+            .map(|_| {
+                let subsample: Vec<&[f64]> = rand::seq::index::sample(&mut rng, samples.len(), sample_size)
+                    .into_iter()
+                    .map(|i| samples[i].as_slice())
+                    .collect();
+                build_tree(&subsample, 0, max_depth, &mut rng)
+            })
+            .collect();
+        
+        Ok(Self {
+            trees,
+            sample_size,
+            n_features,
+        })
+    }
 
-        // Train Isolation Forest
-        let model = IsolationForest::params()
-            .n_trees(100)
-            .sample_size(20)
-            .fit(&dataset)?;
+    /// Anomaly score in 0.0-1.0 (values near 1.0 are anomalous, below 0.5 are normal)
+    pub fn score(&self, features: &[f64]) -> f64 {
+        let mean_path = self.trees.iter()
+            .map(|tree| path_length(tree, features, 0))
+            .sum::<f64>() / self.trees.len() as f64;
+        
+        let normalizer = average_path_length(self.sample_size);
+        if normalizer == 0.0 {
+            return 0.5;
+        }
+        
+        2f64.powf(-mean_path / normalizer)
+    }
+
+    /// Load a model from disk
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&contents).context("Invalid anomaly model")
+    }
 
-        self.model = Some(model);
+    /// Save the model to disk, replacing any previous one atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
         
-        // Clear buffer
-        self.training_buffer.clear();
+        std::fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
         
-        info!("Model training complete");
         Ok(())
     }
+}
 
-    /// Add sample to training buffer
-    pub fn add_training_sample(&mut self, features: Vec<f64>) {
-        self.training_buffer.push(features);
-        
-        // Auto-train when buffer is full
-        if self.training_buffer.len() >= self.buffer_size {
-            // Spawn training task
-            let detector = self.clone_for_training();
-            tokio::spawn(async move {
-                let mut det = detector;
-                if let Err(e) = det.train().await {
-                    tracing::error!(error = %e, "Model training failed");
-                }
+/// Recursively split samples on random features until they are isolated
+fn build_tree(samples: &[&[f64]], depth: usize, max_depth: usize, rng: &mut StdRng) -> IsolationNode {
+    if depth >= max_depth || samples.len() <= 1 {
+        return IsolationNode::Leaf { size: samples.len() };
+    }
+    
+    // Only features that still vary within this node can split it
+    let n_features = samples[0].len();
+    let ranges: Vec<(usize, f64, f64)> = (0..n_features)
+        .filter_map(|feature| {
+            let (min, max) = samples.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), s| {
+                (min.min(s[feature]), max.max(s[feature]))
             });
+            (max > min).then_some((feature, min, max))
+        })
+        .collect();
+    
+    if ranges.is_empty() {
+        return IsolationNode::Leaf { size: samples.len() };
+    }
+    
+    let (feature, min, max) = ranges[rng.gen_range(0..ranges.len())];
+    let threshold = rng.gen_range(min..max);
+    
+    let (left, right): (Vec<&[f64]>, Vec<&[f64]>) = samples.iter().partition(|s| s[feature] < threshold);
+    
+    IsolationNode::Split {
+        feature,
+        threshold,
+        min,
+        max,
+        left: Box::new(build_tree(&left, depth + 1, max_depth, rng)),
+        right: Box::new(build_tree(&right, depth + 1, max_depth, rng)),
+    }
+}
+
+/// Depth at which a sample is isolated, adjusted for unsplit leaves
+/// A value far outside the node's training range is isolated by the next split; plain
+/// isolation trees would route it alongside the most extreme training sample instead
+fn path_length(node: &IsolationNode, features: &[f64], depth: usize) -> f64 {
+    match node {
+        IsolationNode::Leaf { size } => depth as f64 + average_path_length(*size),
+        IsolationNode::Split { feature, threshold, min, max, left, right } => {
+            let value = features[*feature];
+            let width = max - min;
+            if value < min - width || value > max + width {
+                return depth as f64 + 1.0;
+            }
+            
+            if value < *threshold {
+                path_length(left, features, depth + 1)
+            } else {
+                path_length(right, features, depth + 1)
+            }
         }
     }
+}
 
-    /// Clone for training task
-    fn clone_for_training(&self) -> Self {
-        Self {
-            model: self.model.clone(),
-            training_buffer: self.training_buffer.clone(),
-            buffer_size: self.buffer_size,
+/// Average path length of an unsuccessful search in a BST of n nodes
+fn average_path_length(n: usize) -> f64 {
+    match n {
+        0 | 1 => 0.0,
+        2 => 1.0,
+        n => {
+            let n = n as f64;
+            2.0 * ((n - 1.0).ln() + EULER_GAMMA) - 2.0 * (n - 1.0) / n
         }
     }
 }
@@ -180,6 +464,70 @@ mod tests {
         assert!(detector.is_ok());
     }
 
+    /// Tightly clustered "normal" traffic
+    fn normal_samples(count: usize) -> Vec<Vec<f64>> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..count)
+            .map(|_| vec![
+                rng.gen_range(8.0..16.0),
+                rng.gen_range(1.0..4.0),
+                rng.gen_range(10.0..16.0),
+                rng.gen_range(0.0..64.0),
+            ])
+            .collect()
+    }
+
+    #[test]
+    fn test_isolation_forest_flags_outliers() {
+        let forest = IsolationForest::fit(&normal_samples(512), 100, 256, 42).unwrap();
+        
+        let normal = forest.score(&[12.0, 3.0, 13.0, 0.0]);
+        let outlier = forest.score(&[900.0, 40.0, 80.0, 1_000_000.0]);
+        
+        // Points inside a dense cluster sit around 0.5; isolated points approach 1.0
+        assert!(normal < 0.6, "normal score {}", normal);
+        assert!(outlier > 0.7, "outlier score {}", outlier);
+    }
+
+    #[test]
+    fn test_scoring_is_deterministic() {
+        let samples = normal_samples(300);
+        let a = IsolationForest::fit(&samples, 50, 128, 7).unwrap();
+        let b = IsolationForest::fit(&samples, 50, 128, 7).unwrap();
+        
+        let probe = [40.0, 1.0, 9.0, 512.0];
+        assert_eq!(a.score(&probe), a.score(&probe));
+        assert_eq!(a.score(&probe), b.score(&probe));
+    }
+
+    #[test]
+    fn test_online_training_and_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anomaly-model.json");
+        let config = AnomalyConfig {
+            n_trees: 20,
+            sample_size: 64,
+            model_path: Some(path.clone()),
+            ..Default::default()
+        };
+        
+        let detector = AnomalyDetector::with_config(config.clone()).unwrap();
+        let mut ready = false;
+        for sample in normal_samples(64) {
+            ready = detector.add_training_sample(sample);
+        }
+        assert!(ready);
+        
+        detector.train().unwrap();
+        assert!(detector.is_trained());
+        assert!(path.exists());
+        
+        // A restarted detector picks up the persisted model and scores identically
+        let probe = [500.0, 9.0, 30.0, 4096.0];
+        let restored = AnomalyDetector::with_config(config).unwrap();
+        assert_eq!(restored.score(&probe), detector.score(&probe));
+    }
+
     #[test]
     fn test_statistical_detector() {
         let mut detector = StatisticalDetector::new();
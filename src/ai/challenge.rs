@@ -0,0 +1,376 @@
+// Block Responses and Client Challenges
+// Rate-limit replies, branded block pages, and a cookie challenge that clears banned clients
+
+use super::{AnalysisResult, ThreatType};
+use dashmap::DashMap;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_TYPE, COOKIE, RETRY_AFTER};
+use hyper::{Response, StatusCode};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// Cookie set by the challenge page
+const CLEARANCE_COOKIE: &str = "pear_clearance";
+
+/// How blocked clients are answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockMode {
+    /// Plain-text 403 (429 when rate limited)
+    #[default]
+    Status,
+
+    /// 429 with Retry-After for banned and rate-limited clients
+    RateLimit,
+
+    /// Branded HTML block page
+    Page,
+
+    /// JavaScript cookie challenge; passing it temporarily clears the client
+    Challenge,
+}
+
+/// Block response configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockResponseConfig {
+    #[serde(default)]
+    pub mode: BlockMode,
+
+    /// Retry-After sent to banned clients in rate_limit mode
+    #[serde(default = "default_retry_after")]
+    pub retry_after_secs: u64,
+
+    /// How long a passed challenge clears the client
+    #[serde(default = "default_challenge_ttl")]
+    pub challenge_ttl_secs: u64,
+
+    /// Name shown on block and challenge pages
+    #[serde(default = "default_brand")]
+    pub brand: String,
+}
+
+fn default_retry_after() -> u64 { 60 }
+fn default_challenge_ttl() -> u64 { 3600 }
+fn default_brand() -> String { "Pear Server".to_string() }
+
+impl Default for BlockResponseConfig {
+    fn default() -> Self {
+        Self {
+            mode: BlockMode::default(),
+            retry_after_secs: default_retry_after(),
+            challenge_ttl_secs: default_challenge_ttl(),
+            brand: default_brand(),
+        }
+    }
+}
+
+/// Builds block responses and tracks clients that passed a challenge
+pub struct ChallengeManager {
+    config: BlockResponseConfig,
+
+    /// Signs clearance tokens; regenerated on every start
+    key: hmac::Key,
+
+    /// Clients cleared by a passed challenge, until the given instant
+    cleared: DashMap<IpAddr, Instant>,
+
+    issued: AtomicU64,
+    passed: AtomicU64,
+}
+
+impl ChallengeManager {
+    /// Create a manager with a fresh signing key
+    pub fn new(config: BlockResponseConfig) -> anyhow::Result<Self> {
+        let key = hmac::Key::generate(hmac::HMAC_SHA256, &ring::rand::SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("Failed to generate challenge key"))?;
+
+        Ok(Self {
+            config,
+            key,
+            cleared: DashMap::new(),
+            issued: AtomicU64::new(0),
+            passed: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether a client has passed a challenge that is still valid
+    pub fn is_cleared(&self, ip: IpAddr) -> bool {
+        let expired = match self.cleared.get(&ip) {
+            Some(until) => Instant::now() >= *until,
+            None => return false,
+        };
+
+        if expired {
+            self.cleared.remove(&ip);
+        }
+        !expired
+    }
+
+    /// Check a request for a valid clearance cookie
+    /// Returns true when this request newly clears the client
+    pub fn verify(&self, ip: IpAddr, headers: &HeaderMap) -> bool {
+        if self.config.mode != BlockMode::Challenge || self.is_cleared(ip) {
+            return false;
+        }
+
+        let Some(token) = clearance_cookie(headers) else {
+            return false;
+        };
+
+        let Some((expiry, signature)) = token.split_once('.') else {
+            return false;
+        };
+        let Ok(expiry) = expiry.parse::<u64>() else {
+            return false;
+        };
+
+        let now = unix_now();
+        if expiry <= now {
+            return false;
+        }
+
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        if hmac::verify(&self.key, signing_input(ip, expiry).as_bytes(), &signature).is_err() {
+            debug!(ip = %ip, "Rejected forged clearance cookie");
+            return false;
+        }
+
+        self.cleared.insert(ip, Instant::now() + Duration::from_secs(expiry - now));
+        self.passed.fetch_add(1, Ordering::Relaxed);
+        info!(ip = %ip, "Client passed challenge");
+        true
+    }
+
+    /// Signed clearance token for a client, valid for the configured TTL
+    pub(crate) fn issue_token(&self, ip: IpAddr) -> String {
+        let expiry = unix_now() + self.config.challenge_ttl_secs;
+        let signature = hmac::sign(&self.key, signing_input(ip, expiry).as_bytes());
+        format!("{}.{}", expiry, hex::encode(signature))
+    }
+
+    /// Build the response for a blocked request
+    pub fn respond(&self, ip: Option<IpAddr>, verdict: &AnalysisResult) -> Response<Full<Bytes>> {
+        // Payload attacks are never challengeable: passing a challenge shouldn't unlock SQL injection
+        let reputation = is_reputation_block(verdict);
+
+        match (self.config.mode, ip) {
+            (BlockMode::Challenge, Some(ip)) if reputation => self.challenge_page(ip),
+            (BlockMode::RateLimit, _) if reputation => {
+                let retry_after = verdict.retry_after.unwrap_or(Duration::from_secs(self.config.retry_after_secs));
+                text_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests", Some(retry_after))
+            }
+            (BlockMode::Page | BlockMode::Challenge, _) => self.block_page(verdict),
+            _ => match verdict.retry_after {
+                Some(retry_after) => text_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests", Some(retry_after)),
+                None => text_response(StatusCode::FORBIDDEN, "Request blocked", None),
+            },
+        }
+    }
+
+    /// Branded HTML block page
+    fn block_page(&self, verdict: &AnalysisResult) -> Response<Full<Bytes>> {
+        let (status, message) = match verdict.retry_after {
+            Some(_) => (StatusCode::TOO_MANY_REQUESTS, "You are sending requests too quickly. Please wait a moment and try again."),
+            None => (StatusCode::FORBIDDEN, "This request was blocked by the site's security settings."),
+        };
+
+        let body = page(&self.config.brand, "Request blocked", &format!("<p>{}</p>", message), "");
+        html_response(status, body, verdict.retry_after)
+    }
+
+    /// Page that sets the clearance cookie from JavaScript and reloads
+    fn challenge_page(&self, ip: IpAddr) -> Response<Full<Bytes>> {
+        self.issued.fetch_add(1, Ordering::Relaxed);
+
+        // The token is split and reassembled in script so that only clients running JavaScript pass
+        let token = self.issue_token(ip);
+        let (head, tail) = token.split_at(token.len() / 2);
+        let script = format!(
+            "<script>document.cookie=\"{}=\"+[\"{}\",\"{}\"].reverse().join(\"\")+\"; path=/; max-age={}; SameSite=Lax\";setTimeout(function(){{location.reload()}},1000);</script>",
+            CLEARANCE_COOKIE, tail, head, self.config.challenge_ttl_secs,
+        );
+
+        let body = page(
+            &self.config.brand,
+            "Checking your browser",
+            "<p>This only takes a moment. The page will reload automatically.</p>\
+             <noscript><p>Please enable JavaScript and cookies to continue.</p></noscript>",
+            &script,
+        );
+        html_response(StatusCode::FORBIDDEN, body, None)
+    }
+
+    /// Drop expired clearances
+    pub fn cleanup(&self) {
+        let now = Instant::now();
+        self.cleared.retain(|_, until| *until > now);
+    }
+
+    /// Get statistics
+    pub fn stats(&self) -> ChallengeStats {
+        ChallengeStats {
+            issued: self.issued.load(Ordering::Relaxed),
+            passed: self.passed.load(Ordering::Relaxed),
+            cleared_clients: self.cleared.len(),
+        }
+    }
+}
+
+/// Challenge statistics
+#[derive(Debug, Clone)]
+pub struct ChallengeStats {
+    pub issued: u64,
+    pub passed: u64,
+    pub cleared_clients: usize,
+}
+
+/// Whether a block is about the client's behavior rather than the request payload
+fn is_reputation_block(verdict: &AnalysisResult) -> bool {
+    matches!(
+        verdict.threat_type,
+        Some(ThreatType::BotActivity) | Some(ThreatType::DdosPattern) | Some(ThreatType::Anomalous)
+    )
+}
+
+/// Extract the clearance token from the Cookie header(s)
+fn clearance_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers.get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == CLEARANCE_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Data covered by a clearance signature
+fn signing_input(ip: IpAddr, expiry: u64) -> String {
+    format!("{}|{}", ip.to_canonical(), expiry)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Escape text for inclusion in HTML
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Minimal self-contained HTML page
+fn page(brand: &str, title: &str, content: &str, script: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{title} | {brand}</title>\
+         <style>body{{font-family:'Segoe UI',Tahoma,sans-serif;background:#0a0e27;color:#e8eaed;display:flex;align-items:center;justify-content:center;min-height:100vh;margin:0}}\
+         main{{background:#151b3d;border-radius:12px;padding:2.5rem;max-width:32rem;text-align:center}}\
+         h1{{color:#00d4ff;font-size:1.5rem}}footer{{color:#a0a3b5;font-size:.8rem;margin-top:2rem}}</style>\
+         </head><body><main><h1>{title}</h1>{content}<footer>{brand}</footer></main>{script}</body></html>",
+        brand = escape_html(brand),
+        title = title,
+        content = content,
+        script = script,
+    )
+}
+
+fn html_response(status: StatusCode, body: String, retry_after: Option<Duration>) -> Response<Full<Bytes>> {
+    build_response(status, "text/html; charset=utf-8", body, retry_after)
+}
+
+fn text_response(status: StatusCode, body: &str, retry_after: Option<Duration>) -> Response<Full<Bytes>> {
+    build_response(status, "text/plain; charset=utf-8", body.to_string(), retry_after)
+}
+
+fn build_response(status: StatusCode, content_type: &'static str, body: String, retry_after: Option<Duration>) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if let Some(retry_after) = retry_after {
+        headers.insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs().max(1)));
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(mode: BlockMode) -> ChallengeManager {
+        ChallengeManager::new(BlockResponseConfig { mode, ..Default::default() }).unwrap()
+    }
+
+    fn blocked(threat_type: ThreatType) -> AnalysisResult {
+        AnalysisResult {
+            is_safe: false,
+            threat_type: Some(threat_type),
+            ..AnalysisResult::safe()
+        }
+    }
+
+    fn cookie(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(&format!("theme=dark; {}={}", CLEARANCE_COOKIE, token)).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_valid_token_clears_client() {
+        let manager = manager(BlockMode::Challenge);
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+
+        let token = manager.issue_token(ip);
+        assert!(manager.verify(ip, &cookie(&token)));
+        assert!(manager.is_cleared(ip));
+        assert_eq!(manager.stats().passed, 1);
+
+        // Tokens are bound to the client address
+        let other: IpAddr = "198.51.100.8".parse().unwrap();
+        assert!(!manager.verify(other, &cookie(&token)));
+    }
+
+    #[test]
+    fn test_forged_or_expired_token_rejected() {
+        let manager = manager(BlockMode::Challenge);
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+
+        let expiry = unix_now() + 60;
+        assert!(!manager.verify(ip, &cookie(&format!("{}.{}", expiry, "00".repeat(32)))));
+        assert!(!manager.verify(ip, &cookie("1.abcd")));
+        assert!(!manager.verify(ip, &cookie("garbage")));
+        assert!(!manager.is_cleared(ip));
+    }
+
+    #[test]
+    fn test_response_modes() {
+        let ip = Some("203.0.113.1".parse().unwrap());
+
+        let response = manager(BlockMode::RateLimit).respond(ip, &blocked(ThreatType::BotActivity));
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+
+        let response = manager(BlockMode::Challenge).respond(ip, &blocked(ThreatType::BotActivity));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers()[CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+
+        // WAF blocks get the plain block page even in challenge mode
+        let response = manager(BlockMode::Challenge).respond(ip, &blocked(ThreatType::SqlInjection));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = manager(BlockMode::Status).respond(ip, &blocked(ThreatType::BotActivity));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
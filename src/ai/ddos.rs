@@ -1,6 +1,8 @@
 // DDoS Detection Module
 // Implements leaky bucket rate limiting and pattern recognition
 
+use super::allowlist::IpAllowlist;
+use crate::network::peer::client_key;
 use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -9,7 +11,7 @@ use tracing::{warn, info, debug};
 
 /// DDoS detector using leaky bucket algorithm
 pub struct DDoSDetector {
-    /// Leaky buckets per IP address
+    /// Leaky buckets per client key (the IPv4 address, or the IPv6 /64)
     buckets: Arc<DashMap<IpAddr, LeakyBucket>>,
     
     /// Requests per second threshold
@@ -26,6 +28,9 @@ pub struct DDoSDetector {
     
     /// Ban duration
     ban_duration: Duration,
+    
+    /// Addresses that are never rate limited or banned
+    allowlist: Arc<IpAllowlist>,
 }
 
 /// Leaky bucket for rate limiting
@@ -63,11 +68,23 @@ impl DDoSDetector {
             leak_rate: threshold as f64,
             banned_ips: Arc::new(DashMap::new()),
             ban_duration: Duration::from_secs(ban_duration_secs),
+            allowlist: Arc::new(IpAllowlist::default()),
         }
     }
 
+    /// Exempt allowlisted addresses from rate limiting and bans
+    pub fn with_allowlist(mut self, allowlist: Arc<IpAllowlist>) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
     /// Check if request should be allowed
     pub fn check_request(&self, ip: IpAddr) -> RequestDecision {
+        if self.allowlist.allows(ip) {
+            return RequestDecision::Allow;
+        }
+        let ip = client_key(ip);
+
         // Check if IP is banned
         if let Some(ban_info) = self.banned_ips.get(&ip) {
             if ban_info.banned_at.elapsed() < self.ban_duration {
@@ -140,12 +157,12 @@ impl DDoSDetector {
 
     /// Manually ban an IP
     pub fn manual_ban(&self, ip: IpAddr, reason: String) {
-        self.ban_ip(ip, reason, 0);
+        self.ban_ip(client_key(ip), reason, 0);
     }
 
     /// Unban an IP
     pub fn unban(&self, ip: IpAddr) -> bool {
-        self.banned_ips.remove(&ip).is_some()
+        self.banned_ips.remove(&client_key(ip)).is_some()
     }
 
     /// Get statistics
@@ -232,4 +249,27 @@ mod tests {
         let decision = detector.check_request(ip);
         assert!(!decision.is_allowed());
     }
+
+    #[test]
+    fn test_bans_cover_client_prefix() {
+        let detector = DDoSDetector::new(100, 200, 3600);
+        detector.manual_ban("2001:db8:0:1::10".parse().unwrap(), "Test ban".to_string());
+        detector.manual_ban(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), "Test ban".to_string());
+
+        assert!(!detector.check_request("2001:db8:0:1:abcd::1".parse().unwrap()).is_allowed());
+        assert!(detector.check_request("2001:db8:0:2::10".parse().unwrap()).is_allowed());
+        assert!(!detector.check_request("::ffff:10.0.0.1".parse().unwrap()).is_allowed());
+    }
+
+    #[test]
+    fn test_allowlist_bypasses_rate_limit() {
+        let allowlist = IpAllowlist::parse(&["192.168.0.0/16".to_string()]).unwrap();
+        let detector = DDoSDetector::new(1, 1, 3600).with_allowlist(Arc::new(allowlist));
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 5, 5));
+
+        for _ in 0..50 {
+            assert!(detector.check_request(ip).is_allowed());
+        }
+        assert_eq!(detector.stats().active_buckets, 0);
+    }
 }
@@ -0,0 +1,381 @@
+// Security Event Log
+// Structured record of blocked and suspicious requests, with alert fan-out
+
+use super::alerts::{AlertHookConfig, Notifier};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{info, warn, error};
+
+/// Events queued for the sink and alert hooks before new ones are dropped
+const DISPATCH_QUEUE_SIZE: usize = 4096;
+
+/// Event log configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventLogConfig {
+    /// Recent events kept in memory for the API and dashboard
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+
+    /// File events are appended to as JSON lines ("" = memory only)
+    #[serde(default = "default_log_path")]
+    pub log_path: String,
+
+    /// Notification hooks
+    #[serde(default)]
+    pub alerts: Vec<AlertHookConfig>,
+}
+
+fn default_capacity() -> usize { 1000 }
+fn default_log_path() -> String { "security-events.jsonl".to_string() }
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        Self {
+            capacity: default_capacity(),
+            log_path: default_log_path(),
+            alerts: Vec::new(),
+        }
+    }
+}
+
+/// Event severity, ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+/// What triggered an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    /// Request matched a WAF rule or signature
+    WafBlock,
+
+    /// Anomaly score exceeded the site's threshold
+    Anomaly,
+
+    /// Client banned for scanning sensitive paths
+    ScanBan,
+
+    /// Request from an already banned or rate-limited client
+    BannedClient,
+
+    /// Client passed a browser challenge and was temporarily cleared
+    ChallengePassed,
+
+    /// Client banned after being locked out of the management API repeatedly
+    LoginBan,
+
+    /// Client banned for starting QUIC connections faster than allowed
+    HandshakeFlood,
+}
+
+impl SecurityEventKind {
+    /// Default severity for this kind of event
+    pub fn severity(&self) -> Severity {
+        match self {
+            SecurityEventKind::BannedClient | SecurityEventKind::ChallengePassed => Severity::Info,
+            SecurityEventKind::WafBlock | SecurityEventKind::Anomaly => Severity::Warning,
+            SecurityEventKind::ScanBan | SecurityEventKind::LoginBan | SecurityEventKind::HandshakeFlood => Severity::Critical,
+        }
+    }
+}
+
+/// What the server did about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventAction {
+    /// Request rejected
+    Blocked,
+
+    /// Request allowed through under a monitor-only policy
+    Monitored,
+
+    /// Client added to the ban list
+    Banned,
+
+    /// Client exempted from bans and rate limits for a while
+    Cleared,
+}
+
+/// A single security event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityEvent {
+    pub id: u64,
+
+    /// Unix timestamp (seconds)
+    pub timestamp: i64,
+
+    pub kind: SecurityEventKind,
+    pub severity: Severity,
+    pub action: EventAction,
+    pub site_id: String,
+    pub ip: Option<IpAddr>,
+    pub path: String,
+
+    /// Anomaly score, for anomaly events
+    pub score: Option<f64>,
+
+    pub details: Option<String>,
+}
+
+impl SecurityEvent {
+    /// Create an event with the kind's default severity
+    /// The ID and timestamp are assigned when it is recorded
+    pub fn new(kind: SecurityEventKind, action: EventAction, site_id: &str, ip: Option<IpAddr>, path: &str) -> Self {
+        Self {
+            id: 0,
+            timestamp: 0,
+            kind,
+            severity: kind.severity(),
+            action,
+            site_id: site_id.to_string(),
+            ip,
+            path: path.to_string(),
+            score: None,
+            details: None,
+        }
+    }
+
+    /// Attach an anomaly score
+    pub fn with_score(mut self, score: f64) -> Self {
+        self.score = Some(score);
+        self
+    }
+
+    /// Attach a human-readable description
+    pub fn with_details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+/// Filter for browsing recent events
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EventQuery {
+    pub limit: Option<usize>,
+    pub min_severity: Option<Severity>,
+    pub site_id: Option<String>,
+    pub kind: Option<SecurityEventKind>,
+}
+
+/// In-memory ring buffer of security events, feeding a persistent sink and alert hooks
+pub struct SecurityEventLog {
+    config: EventLogConfig,
+    recent: Mutex<VecDeque<SecurityEvent>>,
+    next_id: AtomicU64,
+    dropped: AtomicU64,
+    sender: mpsc::Sender<SecurityEvent>,
+
+    /// Taken by the dispatcher task when it starts
+    receiver: Mutex<Option<mpsc::Receiver<SecurityEvent>>>,
+}
+
+impl SecurityEventLog {
+    /// Create an event log; call `start` to begin persisting and alerting
+    pub fn new(config: EventLogConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(DISPATCH_QUEUE_SIZE);
+
+        Self {
+            recent: Mutex::new(VecDeque::with_capacity(config.capacity)),
+            config,
+            next_id: AtomicU64::new(1),
+            dropped: AtomicU64::new(0),
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Record an event
+    /// Never blocks: if the sink falls behind, the event is kept in memory only
+    pub fn record(&self, mut event: SecurityEvent) -> u64 {
+        event.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        event.timestamp = chrono::Utc::now().timestamp();
+        let id = event.id;
+
+        {
+            let mut recent = self.recent.lock();
+            if recent.len() >= self.config.capacity.max(1) {
+                recent.pop_front();
+            }
+            recent.push_back(event.clone());
+        }
+
+        if self.sender.try_send(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        id
+    }
+
+    /// Most recent events matching a query, newest first
+    pub fn recent(&self, query: &EventQuery) -> Vec<SecurityEvent> {
+        let limit = query.limit.unwrap_or(100).min(self.config.capacity.max(1));
+
+        self.recent.lock()
+            .iter()
+            .rev()
+            .filter(|e| query.min_severity.map_or(true, |min| e.severity >= min))
+            .filter(|e| query.site_id.as_deref().map_or(true, |site| e.site_id == site))
+            .filter(|e| query.kind.map_or(true, |kind| e.kind == kind))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Total events recorded
+    pub fn total_recorded(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed) - 1
+    }
+
+    /// Events that skipped the sink and alerts because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Spawn the task that appends events to the log file and sends alerts
+    pub fn start(&self) {
+        let Some(receiver) = self.receiver.lock().take() else {
+            warn!("Security event dispatcher already started");
+            return;
+        };
+
+        let log_path = Some(&self.config.log_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let notifier = Arc::new(Notifier::new(self.config.alerts.clone()));
+
+        info!(
+            log_path = ?log_path,
+            alert_hooks = self.config.alerts.len(),
+            "Security event log started"
+        );
+
+        tokio::spawn(dispatch(receiver, log_path, notifier));
+    }
+}
+
+/// Persist and fan out events until the log is dropped
+async fn dispatch(
+    mut receiver: mpsc::Receiver<SecurityEvent>,
+    log_path: Option<PathBuf>,
+    notifier: Arc<Notifier>,
+) {
+    let mut file = match &log_path {
+        Some(path) => match tokio::fs::OpenOptions::new().create(true).append(true).open(path).await {
+            Ok(file) => Some(file),
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to open security event log");
+                None
+            }
+        },
+        None => None,
+    };
+
+    while let Some(event) = receiver.recv().await {
+        if let Some(out) = file.as_mut() {
+            match serde_json::to_vec(&event) {
+                Ok(mut line) => {
+                    line.push(b'\n');
+                    if let Err(e) = out.write_all(&line).await {
+                        error!(error = %e, "Failed to write security event");
+                    }
+                }
+                Err(e) => error!(error = %e, "Failed to serialize security event"),
+            }
+        }
+
+        notifier.notify(&event);
+        if event.action == EventAction::Banned {
+            crate::notifications::notify(crate::notifications::Notification::new(
+                crate::notifications::EventKind::SecurityBan,
+                Some(&event.site_id),
+                super::alerts::summary(&event),
+                serde_json::to_value(&event).unwrap_or_default(),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_with_capacity(capacity: usize) -> SecurityEventLog {
+        SecurityEventLog::new(EventLogConfig {
+            capacity,
+            log_path: String::new(),
+            alerts: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_newest() {
+        let log = log_with_capacity(2);
+        for path in ["/a", "/b", "/c"] {
+            log.record(SecurityEvent::new(SecurityEventKind::WafBlock, EventAction::Blocked, "site", None, path));
+        }
+
+        let events = log.recent(&EventQuery::default());
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].path, "/c");
+        assert_eq!(events[1].path, "/b");
+        assert_eq!(log.total_recorded(), 3);
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let log = log_with_capacity(10);
+        log.record(SecurityEvent::new(SecurityEventKind::BannedClient, EventAction::Blocked, "blog", None, "/"));
+        log.record(SecurityEvent::new(SecurityEventKind::ScanBan, EventAction::Banned, "blog", None, "/.env"));
+        log.record(SecurityEvent::new(SecurityEventKind::Anomaly, EventAction::Monitored, "api", None, "/v1").with_score(0.93));
+
+        let warnings = log.recent(&EventQuery { min_severity: Some(Severity::Warning), ..Default::default() });
+        assert_eq!(warnings.len(), 2);
+
+        let blog = log.recent(&EventQuery { site_id: Some("blog".to_string()), ..Default::default() });
+        assert_eq!(blog.len(), 2);
+
+        let anomalies = log.recent(&EventQuery { kind: Some(SecurityEventKind::Anomaly), ..Default::default() });
+        assert_eq!(anomalies[0].score, Some(0.93));
+    }
+
+    #[tokio::test]
+    async fn test_events_persisted_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let log = SecurityEventLog::new(EventLogConfig {
+            capacity: 10,
+            log_path: path.to_string_lossy().into_owned(),
+            alerts: Vec::new(),
+        });
+        log.start();
+
+        log.record(SecurityEvent::new(SecurityEventKind::ScanBan, EventAction::Banned, "blog", None, "/.git/config"));
+
+        let mut contents = String::new();
+        for _ in 0..50 {
+            contents = std::fs::read_to_string(&path).unwrap_or_default();
+            if !contents.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let event: SecurityEvent = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+        assert_eq!(event.kind, SecurityEventKind::ScanBan);
+        assert_eq!(event.id, 1);
+    }
+}
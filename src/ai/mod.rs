@@ -1,12 +1,20 @@
 // AI Security Module
 // ML-powered anomaly detection and threat identification
 
+pub mod alerts;
+pub mod allowlist;
 pub mod anomaly;
+pub mod challenge;
 pub mod ddos;
+pub mod events;
 pub mod path_monitor;
 pub mod performance_baseline;
+pub mod policy;
+pub mod waf;
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::sync::Arc;
 use tracing::{info, debug, warn, instrument};
 
@@ -21,6 +29,18 @@ pub struct AiConfig {
     
     /// Sample rate for traffic analysis (0.0-1.0, 1.0 = analyze all requests)
     pub sample_rate: f64,
+    
+    /// Whether unsafe requests are blocked or only logged
+    pub mode: policy::EnforcementMode,
+    
+    /// Per-site policy overrides, keyed by site ID
+    pub sites: std::collections::HashMap<String, policy::AiPolicy>,
+    
+    /// Where the trained anomaly model is persisted (None = memory only)
+    pub model_path: Option<std::path::PathBuf>,
+    
+    /// WAF rules and sensitive paths
+    pub security: crate::config::SecurityConfig,
 }
 
 impl Default for AiConfig {
@@ -29,6 +49,10 @@ impl Default for AiConfig {
             enable_anomaly_detection: true,
             anomaly_threshold: 0.8,  // 80% confidence threshold
             sample_rate: 0.1,         // Analyze 10% of traffic
+            mode: policy::EnforcementMode::Block,
+            sites: std::collections::HashMap::new(),
+            model_path: None,
+            security: crate::config::SecurityConfig::default(),
         }
     }
 }
@@ -37,7 +61,18 @@ impl Default for AiConfig {
 pub struct AiSecurityModule {
     config: AiConfig,
     anomaly_detector: Arc<anomaly::AnomalyDetector>,
+    waf: Arc<waf::WafEngine>,
+    path_monitor: Arc<path_monitor::PathMonitor>,
+    allowlist: Arc<allowlist::IpAllowlist>,
+    policies: policy::PolicyStore,
+    events: Arc<events::SecurityEventLog>,
+    ddos: Option<Arc<ddos::DDoSDetector>>,
+    challenges: Arc<challenge::ChallengeManager>,
     threats_detected: Arc<std::sync::atomic::AtomicU64>,
+    
+    /// Latency baselines per site, fed from sampled responses
+    performance: dashmap::DashMap<String, parking_lot::Mutex<performance_baseline::PerformanceMonitor>>,
+    performance_anomalies: std::sync::atomic::AtomicU64,
 }
 
 impl AiSecurityModule {
@@ -45,46 +80,122 @@ impl AiSecurityModule {
     pub fn new(config: AiConfig) -> Result<Self> {
         info!("Initializing AI Security Module");
         
-        let anomaly_detector = Arc::new(anomaly::AnomalyDetector::new()?);
+        let anomaly_detector = Arc::new(anomaly::AnomalyDetector::with_config(anomaly::AnomalyConfig {
+            model_path: config.model_path.clone(),
+            ..Default::default()
+        })?);
+        
+        let security = &config.security;
+        let waf = Arc::new(waf::WafEngine::new(&security.global, security.waf_signatures)?);
+        let allowlist = Arc::new(allowlist::IpAllowlist::parse(&security.allowlist)?);
+        
+        let mut path_monitor = path_monitor::PathMonitor::with_sensitive_paths(
+            security.scan_ban_threshold,
+            &security.global.sensitive_paths,
+        )
+        .with_allowlist(allowlist.clone());
+        if security.ban_ttl_secs > 0 {
+            path_monitor = path_monitor.with_ban_ttl(std::time::Duration::from_secs(security.ban_ttl_secs));
+        }
+        let path_monitor = Arc::new(path_monitor);
+        
+        for (site_id, rules) in &security.sites {
+            waf.set_site_rules(site_id, rules)?;
+            path_monitor.set_site_sensitive_paths(site_id, &rules.sensitive_paths);
+        }
+        
+        let policies = policy::PolicyStore::new(policy::ResolvedPolicy {
+            enable_anomaly_detection: config.enable_anomaly_detection,
+            anomaly_threshold: config.anomaly_threshold,
+            sample_rate: config.sample_rate,
+            mode: config.mode,
+        });
+        for (site_id, site_policy) in &config.sites {
+            policies.set(site_id, site_policy.clone())?;
+        }
+        
+        let events = Arc::new(events::SecurityEventLog::new(security.events.clone()));
+        
+        let ddos = (security.rate_limit_rps > 0).then(|| {
+            let burst = match security.rate_limit_burst {
+                0 => security.rate_limit_rps * 2,
+                burst => burst,
+            };
+            Arc::new(
+                ddos::DDoSDetector::new(security.rate_limit_rps, burst, security.rate_limit_ban_secs)
+                    .with_allowlist(allowlist.clone()),
+            )
+        });
+        let challenges = Arc::new(challenge::ChallengeManager::new(security.block_response.clone())?);
         
         Ok(Self {
             config,
             anomaly_detector,
+            waf,
+            path_monitor,
+            allowlist,
+            policies,
+            events,
+            ddos,
+            challenges,
             threats_detected: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            performance: dashmap::DashMap::new(),
+            performance_anomalies: std::sync::atomic::AtomicU64::new(0),
         })
     }
 
     /// Analyze a request for anomalies
     #[instrument(skip(self, request_features))]
-    pub async fn analyze_request(&self, request_features: RequestFeatures) -> AnalysisResult {
-        if !self.config.enable_anomaly_detection {
+    pub async fn analyze_request(&self, site_id: &str, request_features: RequestFeatures) -> AnalysisResult {
+        // Sample requests based on the site's configured rate
+        if !self.should_sample(site_id) {
             return AnalysisResult::safe();
         }
 
-        // Sample requests based on configured rate
-        if !self.should_sample() {
-            return AnalysisResult::safe();
-        }
+        self.score_request(site_id, request_features).await
+    }
 
-        debug!("Analyzing request for anomalies");
+    /// Score a request that has already been sampled
+    /// Normal-looking traffic is fed back into the model's training window
+    pub async fn score_request(&self, site_id: &str, request_features: RequestFeatures) -> AnalysisResult {
+        debug!(site_id = %site_id, "Analyzing request for anomalies");
+        
+        let threshold = self.policies.resolve(site_id).anomaly_threshold;
 
         // Perform anomaly detection
         match self.anomaly_detector.detect(&request_features).await {
             Ok(score) => {
-                if score > self.config.anomaly_threshold {
+                if score <= threshold {
+                    // Flagged requests are kept out of training so attacks don't become the baseline
+                    self.anomaly_detector.observe(request_features.to_feature_vector());
+                }
+
+                if score > threshold {
                     warn!(
+                        site_id = %site_id,
                         score = score,
-                        threshold = self.config.anomaly_threshold,
+                        threshold = threshold,
                         "Anomaly detected in request"
                     );
                     
                     self.threats_detected.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    self.events.record(
+                        events::SecurityEvent::new(
+                            events::SecurityEventKind::Anomaly,
+                            self.enforcement_action(site_id),
+                            site_id,
+                            request_features.source_ip.parse().ok(),
+                            &request_features.path,
+                        )
+                        .with_score(score),
+                    );
                     
                     AnalysisResult {
                         is_safe: false,
                         confidence: score,
                         threat_type: Some(ThreatType::Anomalous),
                         details: Some(format!("Anomaly score: {:.2}", score)),
+                        retry_after: None,
                     }
                 } else {
                     AnalysisResult::safe()
@@ -97,16 +208,313 @@ impl AiSecurityModule {
         }
     }
 
-    /// Check if request should be sampled
-    fn should_sample(&self) -> bool {
-        rand::random::<f64>() < self.config.sample_rate
+    /// Inspect a request against bans, rate limits and WAF rules
+    /// Runs on every request, unlike the sampled anomaly detection
+    /// Clients that passed a challenge skip the ban and rate-limit checks, but never the WAF
+    pub fn inspect(&self, site_id: &str, ip: Option<IpAddr>, path: &str, query: &str) -> AnalysisResult {
+        let cleared = ip.map_or(false, |ip| self.challenges.is_cleared(ip));
+        
+        if !cleared && ip.map_or(false, |ip| self.path_monitor.is_banned(ip)) {
+            self.events.record(events::SecurityEvent::new(
+                events::SecurityEventKind::BannedClient,
+                self.enforcement_action(site_id),
+                site_id,
+                ip,
+                path,
+            ));
+            
+            return AnalysisResult {
+                is_safe: false,
+                confidence: 1.0,
+                threat_type: Some(ThreatType::BotActivity),
+                details: Some("Source IP banned for scanning".to_string()),
+                retry_after: None,
+            };
+        }
+        
+        if let (Some(ddos), Some(ip), false) = (&self.ddos, ip, cleared) {
+            match ddos.check_request(ip) {
+                ddos::RequestDecision::Allow => {}
+                ddos::RequestDecision::RateLimited { retry_after } => {
+                    return AnalysisResult {
+                        is_safe: false,
+                        confidence: 1.0,
+                        threat_type: Some(ThreatType::DdosPattern),
+                        details: Some("Rate limit exceeded".to_string()),
+                        retry_after: Some(retry_after),
+                    };
+                }
+                ddos::RequestDecision::Banned { reason, until } => {
+                    self.events.record(
+                        events::SecurityEvent::new(
+                            events::SecurityEventKind::BannedClient,
+                            self.enforcement_action(site_id),
+                            site_id,
+                            Some(ip),
+                            path,
+                        )
+                        .with_details(reason.clone()),
+                    );
+                    
+                    return AnalysisResult {
+                        is_safe: false,
+                        confidence: 1.0,
+                        threat_type: Some(ThreatType::DdosPattern),
+                        details: Some(reason),
+                        retry_after: Some(until.saturating_duration_since(std::time::Instant::now())),
+                    };
+                }
+            }
+        }
+        
+        match self.waf.evaluate(site_id, path, query) {
+            Some(verdict) if verdict.action == waf::RuleAction::Block => {
+                self.threats_detected.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                self.events.record(
+                    events::SecurityEvent::new(
+                        events::SecurityEventKind::WafBlock,
+                        self.enforcement_action(site_id),
+                        site_id,
+                        ip,
+                        path,
+                    )
+                    .with_details(format!("Rule '{}'", verdict.rule)),
+                );
+                
+                AnalysisResult {
+                    is_safe: false,
+                    confidence: 1.0,
+                    threat_type: verdict.threat,
+                    details: Some(format!("Blocked by WAF rule '{}'", verdict.rule)),
+                    retry_after: None,
+                }
+            }
+            _ => AnalysisResult::safe(),
+        }
+    }
+
+    /// Feed a response status to the scan detector
+    pub fn record_response(&self, site_id: &str, ip: IpAddr, path: &str, status_code: u16) -> path_monitor::PathDecision {
+        let was_banned = self.path_monitor.is_banned(ip);
+        // History is cleared when the ban is placed, so capture it first
+        let mut scanned = self.path_monitor.get_scan_history(ip);
+        let decision = self.path_monitor.check_site_path(ip, Some(site_id), path, status_code);
+        
+        if decision == path_monitor::PathDecision::Banned && !was_banned {
+            self.events.record(
+                events::SecurityEvent::new(
+                    events::SecurityEventKind::ScanBan,
+                    events::EventAction::Banned,
+                    site_id,
+                    Some(ip),
+                    path,
+                )
+                .with_details({
+                    scanned.push(path.to_string());
+                    format!("Scanned: {}", scanned.join(", "))
+                }),
+            );
+        }
+        
+        decision
+    }
+
+    /// Ban a client that kept failing to log in to the management API
+    pub fn ban_login_abuser(&self, ip: IpAddr, path: &str, lockouts: u32) {
+        if self.path_monitor.is_banned(ip) {
+            return;
+        }
+        self.path_monitor.manual_ban(ip);
+        self.events.record(
+            events::SecurityEvent::new(
+                events::SecurityEventKind::LoginBan,
+                events::EventAction::Banned,
+                "",
+                Some(ip),
+                path,
+            )
+            .with_details(format!("Locked out of the management API {} times", lockouts)),
+        );
+    }
+
+    /// Ban a client that kept starting QUIC connections over its handshake rate
+    pub fn ban_handshake_flooder(&self, ip: IpAddr, rejected: u32) {
+        if self.path_monitor.is_banned(ip) {
+            return;
+        }
+        self.path_monitor.manual_ban(ip);
+        self.events.record(
+            events::SecurityEvent::new(
+                events::SecurityEventKind::HandshakeFlood,
+                events::EventAction::Banned,
+                "",
+                Some(ip),
+                "",
+            )
+            .with_details(format!("{} QUIC handshakes refused within a minute", rejected)),
+        );
+    }
+
+    /// Check a request for a passed challenge, clearing its client if valid
+    pub fn verify_challenge(&self, site_id: &str, ip: IpAddr, headers: &hyper::HeaderMap, path: &str) -> bool {
+        if !self.challenges.verify(ip, headers) {
+            return false;
+        }
+        
+        self.events.record(events::SecurityEvent::new(
+            events::SecurityEventKind::ChallengePassed,
+            events::EventAction::Cleared,
+            site_id,
+            Some(ip),
+            path,
+        ));
+        true
+    }
+
+    /// Response sent to a client whose request was blocked
+    pub fn block_response(&self, ip: Option<IpAddr>, verdict: &AnalysisResult) -> hyper::Response<http_body_util::Full<hyper::body::Bytes>> {
+        self.challenges.respond(ip, verdict)
+    }
+
+    /// Action recorded for an unsafe request under the site's policy
+    fn enforcement_action(&self, site_id: &str) -> events::EventAction {
+        if self.policies.resolve(site_id).blocks() {
+            events::EventAction::Blocked
+        } else {
+            events::EventAction::Monitored
+        }
+    }
+
+    /// Get the security event log
+    pub fn events(&self) -> &Arc<events::SecurityEventLog> {
+        &self.events
+    }
+
+    /// Replace the WAF rules and sensitive paths for a site
+    pub fn set_site_rules(&self, site_id: &str, rules: &waf::RuleSetConfig) -> Result<()> {
+        self.waf.set_site_rules(site_id, rules)?;
+        self.path_monitor.set_site_sensitive_paths(site_id, &rules.sensitive_paths);
+        Ok(())
+    }
+
+    /// Effective AI policy for a site
+    pub fn policy(&self, site_id: &str) -> policy::ResolvedPolicy {
+        self.policies.resolve(site_id)
+    }
+
+    /// Policy overrides set for a site
+    pub fn site_policy(&self, site_id: &str) -> policy::AiPolicy {
+        self.policies.get(site_id)
+    }
+
+    /// Replace a site's policy overrides; takes effect on the next request
+    pub fn set_site_policy(&self, site_id: &str, site_policy: policy::AiPolicy) -> Result<()> {
+        self.policies.set(site_id, site_policy)
+    }
+
+    /// Get the WAF engine
+    pub fn waf(&self) -> &Arc<waf::WafEngine> {
+        &self.waf
+    }
+
+    /// Get the path monitor
+    pub fn path_monitor(&self) -> &Arc<path_monitor::PathMonitor> {
+        &self.path_monitor
+    }
+
+    /// Lift both scan and rate-limit bans on an IP
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        let scan = self.path_monitor.unban(ip);
+        let flood = self.ddos.as_ref().map_or(false, |ddos| ddos.unban(ip));
+        scan || flood
+    }
+
+    /// Get the challenge manager
+    pub fn challenges(&self) -> &Arc<challenge::ChallengeManager> {
+        &self.challenges
+    }
+
+    /// Get the shared allowlist (also used for DDoS detection)
+    pub fn allowlist(&self) -> &Arc<allowlist::IpAllowlist> {
+        &self.allowlist
+    }
+
+    /// Periodically purge expired bans, stale scan history, rate-limit buckets and clearances
+    /// Also starts the security event sink and alert hooks
+    pub async fn start_maintenance(&self) {
+        self.events.start();
+        
+        let path_monitor = self.path_monitor.clone();
+        let ddos = self.ddos.clone();
+        let challenges = self.challenges.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+                path_monitor.cleanup();
+                challenges.cleanup();
+                if let Some(ddos) = &ddos {
+                    ddos.cleanup().await;
+                }
+            }
+        });
+    }
+
+    /// Check if a request to a site should be sampled for anomaly detection
+    pub fn should_sample(&self, site_id: &str) -> bool {
+        let policy = self.policies.resolve(site_id);
+        policy.enable_anomaly_detection && rand::random::<f64>() < policy.sample_rate
+    }
+
+    /// Feed a sampled share of a site's response latencies to its performance baseline
+    pub fn record_latency(&self, site_id: &str, latency: std::time::Duration) {
+        if !self.should_sample(site_id) {
+            return;
+        }
+        
+        let alert = match self.performance.get(site_id) {
+            Some(monitor) => monitor.lock().record_latency(latency),
+            None => self.performance.entry(site_id.to_string())
+                .or_insert_with(|| parking_lot::Mutex::new(performance_baseline::PerformanceMonitor::default_config()))
+                .lock()
+                .record_latency(latency),
+        };
+        if alert.is_anomaly() {
+            self.performance_anomalies.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// A site's latency baseline, if any of its responses were sampled
+    pub fn performance(&self, site_id: &str) -> Option<performance_baseline::PerformanceStats> {
+        self.performance.get(site_id).map(|monitor| monitor.lock().stats())
+    }
+
+    /// Get the anomaly detector
+    pub fn anomaly_detector(&self) -> &Arc<anomaly::AnomalyDetector> {
+        &self.anomaly_detector
     }
 
     /// Get statistics
     pub fn stats(&self) -> AiStats {
+        let path_stats = self.path_monitor.stats();
+        let challenge_stats = self.challenges.stats();
+        
         AiStats {
             threats_detected: self.threats_detected.load(std::sync::atomic::Ordering::Relaxed),
             anomaly_detection_enabled: self.config.enable_anomaly_detection,
+            anomaly_model_trained: self.anomaly_detector.is_trained(),
+            banned_ips: path_stats.banned_ips,
+            expired_bans: path_stats.expired_bans,
+            allowlist_ranges: self.allowlist.len(),
+            allowlist_hits: self.allowlist.hits(),
+            site_policies: self.policies.site_count(),
+            security_events: self.events.total_recorded(),
+            rate_limited_ips: self.ddos.as_ref().map_or(0, |ddos| ddos.stats().banned_ips),
+            challenges_issued: challenge_stats.issued,
+            challenges_passed: challenge_stats.passed,
+            performance_anomalies: self.performance_anomalies.load(std::sync::atomic::Ordering::Relaxed),
         }
     }
 }
@@ -123,14 +531,58 @@ pub struct RequestFeatures {
 }
 
 impl RequestFeatures {
+    /// Extract features from an incoming request
+    /// Body size comes from Content-Length, since the body hasn't been read yet
+    pub fn from_request<B>(req: &hyper::Request<B>, source_ip: Option<IpAddr>) -> Self {
+        let query_params = req.uri().query()
+            .map(|query| {
+                query.split('&')
+                    .filter(|pair| !pair.is_empty())
+                    .map(|pair| {
+                        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                        (key.to_string(), value.to_string())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let headers = req.headers().iter()
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or("").to_string()))
+            .collect();
+
+        let body_size = req.headers().get(hyper::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+
+        Self {
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            query_params,
+            headers,
+            body_size,
+            source_ip: source_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+        }
+    }
+
     pub fn to_feature_vector(&self) -> Vec<f64> {
         // Extract numerical features for ML model
+        let query_len: usize = self.query_params.iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum();
+        let special_chars = self.path.chars()
+            .chain(self.query_params.iter().flat_map(|(key, value)| key.chars().chain(value.chars())))
+            .filter(|c| !c.is_ascii_alphanumeric() && !matches!(c, '/' | '-' | '_' | '.'))
+            .count();
+
         vec![
             self.path.len() as f64,
+            self.path.split('/').filter(|s| !s.is_empty()).count() as f64,
             self.query_params.len() as f64,
+            query_len as f64,
+            special_chars as f64,
             self.headers.len() as f64,
             self.body_size as f64,
-            // Add more sophisticated features in production
         ]
     }
 }
@@ -142,6 +594,9 @@ pub struct AnalysisResult {
     pub confidence: f64,
     pub threat_type: Option<ThreatType>,
     pub details: Option<String>,
+    
+    /// When the client may retry, for rate-limit and temporary bans
+    pub retry_after: Option<std::time::Duration>,
 }
 
 impl AnalysisResult {
@@ -151,12 +606,14 @@ impl AnalysisResult {
             confidence: 1.0,
             threat_type: None,
             details: None,
+            retry_after: None,
         }
     }
 }
 
 /// Threat classification
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ThreatType {
     /// Traffic pattern anomaly
     Anomalous,
@@ -179,6 +636,19 @@ pub enum ThreatType {
 pub struct AiStats {
     pub threats_detected: u64,
     pub anomaly_detection_enabled: bool,
+    pub anomaly_model_trained: bool,
+    pub banned_ips: usize,
+    pub expired_bans: u64,
+    pub allowlist_ranges: usize,
+    pub allowlist_hits: u64,
+    pub site_policies: usize,
+    pub security_events: u64,
+    pub rate_limited_ips: usize,
+    pub challenges_issued: u64,
+    pub challenges_passed: u64,
+    
+    /// Sampled responses far slower than their site's baseline
+    pub performance_anomalies: u64,
 }
 
 #[cfg(test)]
@@ -207,6 +677,128 @@ mod tests {
         assert!(!vector.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_inspect_blocks_signatures() {
+        let module = AiSecurityModule::new(AiConfig::default()).unwrap();
+        
+        let result = module.inspect("default-site", None, "/search", "q=<script>alert(1)</script>");
+        assert!(!result.is_safe);
+        assert_eq!(result.threat_type, Some(ThreatType::Xss));
+        
+        assert!(module.inspect("default-site", None, "/search", "q=pears").is_safe);
+    }
+
+    #[tokio::test]
+    async fn test_allowlist_in_stats() {
+        let mut config = AiConfig::default();
+        config.security.allowlist = vec!["127.0.0.0/8".to_string()];
+        config.security.scan_ban_threshold = 1;
+        let module = AiSecurityModule::new(config).unwrap();
+        
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let remote: IpAddr = "203.0.113.9".parse().unwrap();
+        module.record_response("default-site", local, "/.env", 404);
+        module.record_response("default-site", remote, "/.env", 404);
+        
+        let stats = module.stats();
+        assert_eq!(stats.banned_ips, 1);
+        assert_eq!(stats.allowlist_ranges, 1);
+        assert_eq!(stats.allowlist_hits, 1);
+        
+        // Only the ban of the non-allowlisted client is logged
+        let logged = module.events().recent(&events::EventQuery::default());
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].kind, events::SecurityEventKind::ScanBan);
+        assert_eq!(logged[0].ip, Some(remote));
+    }
+
+    #[tokio::test]
+    async fn test_site_policy_opt_out() {
+        let mut config = AiConfig::default();
+        config.sample_rate = 1.0;
+        config.sites.insert("api".to_string(), policy::AiPolicy {
+            enable_anomaly_detection: Some(false),
+            ..Default::default()
+        });
+        let module = AiSecurityModule::new(config).unwrap();
+        
+        assert!(module.should_sample("wordpress"));
+        assert!(!module.should_sample("api"));
+        
+        // Hot reload: switching a site to monitor-only takes effect immediately
+        module.set_site_policy("wordpress", policy::AiPolicy {
+            mode: Some(policy::EnforcementMode::Monitor),
+            ..Default::default()
+        }).unwrap();
+        assert!(!module.policy("wordpress").blocks());
+        assert!(module.policy("api").blocks());
+    }
+
+    #[tokio::test]
+    async fn test_latency_feeds_performance_baseline() {
+        let mut config = AiConfig::default();
+        config.sample_rate = 1.0;
+        let module = AiSecurityModule::new(config).unwrap();
+        
+        for _ in 0..50 {
+            module.record_latency("blog", std::time::Duration::from_millis(10));
+        }
+        module.record_latency("blog", std::time::Duration::from_secs(5));
+        
+        let baseline = module.performance("blog").unwrap();
+        assert_eq!(baseline.sample_count, 51);
+        assert_eq!(module.stats().performance_anomalies, 1);
+        assert!(module.performance("shop").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_then_challenge_clears_ban() {
+        let mut config = AiConfig::default();
+        config.security.rate_limit_rps = 1;
+        config.security.rate_limit_burst = 2;
+        config.security.block_response.mode = challenge::BlockMode::Challenge;
+        let module = AiSecurityModule::new(config).unwrap();
+        let ip: IpAddr = "198.51.100.20".parse().unwrap();
+        
+        assert!(module.inspect("default-site", Some(ip), "/", "").is_safe);
+        assert!(module.inspect("default-site", Some(ip), "/", "").is_safe);
+        let limited = module.inspect("default-site", Some(ip), "/", "");
+        assert_eq!(limited.threat_type, Some(ThreatType::DdosPattern));
+        assert!(limited.retry_after.is_some());
+        
+        let page = module.block_response(Some(ip), &limited);
+        assert_eq!(page.status(), hyper::StatusCode::FORBIDDEN);
+        assert_eq!(module.stats().challenges_issued, 1);
+        
+        // Echoing the clearance token back as a cookie clears the client
+        let mut headers = hyper::HeaderMap::new();
+        let cookie = format!("pear_clearance={}", module.challenges().issue_token(ip));
+        headers.insert(hyper::header::COOKIE, cookie.parse().unwrap());
+        assert!(module.verify_challenge("default-site", ip, &headers, "/"));
+        assert!(module.inspect("default-site", Some(ip), "/", "").is_safe);
+        assert_eq!(module.events().recent(&events::EventQuery::default())[0].kind, events::SecurityEventKind::ChallengePassed);
+        
+        // Payload attacks are still blocked for cleared clients
+        assert!(!module.inspect("default-site", Some(ip), "/search", "q=<script>alert(1)</script>").is_safe);
+    }
+
+    #[tokio::test]
+    async fn test_login_abuser_ban() {
+        let module = AiSecurityModule::new(AiConfig::default()).unwrap();
+        let ip: IpAddr = "203.0.113.20".parse().unwrap();
+
+        module.ban_login_abuser(ip, "/api/whoami", 3);
+        module.ban_login_abuser(ip, "/api/whoami", 4);
+        assert!(module.path_monitor().is_banned(ip));
+
+        // Logged once, and lifted like any other ban
+        let logged = module.events().recent(&events::EventQuery::default());
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].kind, events::SecurityEventKind::LoginBan);
+        assert!(module.unban(ip));
+        assert!(!module.path_monitor().is_banned(ip));
+    }
+
     #[test]
     fn test_analysis_result() {
         let result = AnalysisResult::safe();
@@ -1,18 +1,24 @@
 // Suspicious Path Monitor
 // Detects scanning for sensitive endpoints and bans malicious IPs
 
+use super::allowlist::IpAllowlist;
+use crate::network::peer::client_key;
 use std::net::IpAddr;
 use std::sync::Arc;
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use dashmap::DashMap;
-use tracing::{warn, info};
+use tracing::{warn, info, debug};
 
 /// Suspicious path monitor
 pub struct PathMonitor {
     /// Sensitive paths to monitor
     sensitive_paths: Vec<String>,
     
-    /// Scan attempts per IP
+    /// Extra sensitive paths per site
+    site_sensitive_paths: Arc<DashMap<String, Vec<String>>>,
+    
+    /// Scan attempts per client key (the IPv4 address, or the IPv6 /64)
     scan_attempts: Arc<DashMap<IpAddr, ScanTracker>>,
     
     /// Threshold before banning
@@ -20,6 +26,15 @@ pub struct PathMonitor {
     
     /// Banned IPs
     banned_ips: Arc<DashMap<IpAddr, Instant>>,
+    
+    /// How long a ban lasts (None = until manually lifted)
+    ban_ttl: Option<Duration>,
+    
+    /// Addresses that are never tracked or banned
+    allowlist: Arc<IpAllowlist>,
+    
+    /// Bans lifted because their TTL ran out
+    expired_bans: AtomicU64,
 }
 
 /// Scan tracking per IP
@@ -37,16 +52,40 @@ struct ScanAttempt {
 impl PathMonitor {
     /// Create a new path monitor
     pub fn new(ban_threshold: usize) -> Self {
+        Self::with_sensitive_paths(ban_threshold, &[])
+    }
+
+    /// Create a path monitor with extra global sensitive paths on top of the defaults
+    pub fn with_sensitive_paths(ban_threshold: usize, extra_paths: &[String]) -> Self {
         info!("Initializing Suspicious Path Monitor");
         
+        let mut sensitive_paths = Self::default_sensitive_paths();
+        sensitive_paths.extend(extra_paths.iter().map(|p| p.to_lowercase()));
+        
         Self {
-            sensitive_paths: Self::default_sensitive_paths(),
+            sensitive_paths,
+            site_sensitive_paths: Arc::new(DashMap::new()),
             scan_attempts: Arc::new(DashMap::new()),
             ban_threshold,
             banned_ips: Arc::new(DashMap::new()),
+            ban_ttl: None,
+            allowlist: Arc::new(IpAllowlist::default()),
+            expired_bans: AtomicU64::new(0),
         }
     }
 
+    /// Expire bans after the given duration instead of keeping them forever
+    pub fn with_ban_ttl(mut self, ttl: Duration) -> Self {
+        self.ban_ttl = Some(ttl);
+        self
+    }
+
+    /// Exempt allowlisted addresses from scan tracking and bans
+    pub fn with_allowlist(mut self, allowlist: Arc<IpAllowlist>) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
     /// Default list of sensitive paths
     fn default_sensitive_paths() -> Vec<String> {
         vec![
@@ -74,30 +113,41 @@ impl PathMonitor {
 
     /// Check if path is suspicious
     pub fn check_path(&self, ip: IpAddr, path: &str, status_code: u16) -> PathDecision {
+        self.check_site_path(ip, None, path, status_code)
+    }
+
+    /// Check if path is suspicious, including the site's own sensitive paths
+    pub fn check_site_path(&self, ip: IpAddr, site_id: Option<&str>, path: &str, status_code: u16) -> PathDecision {
+        if self.allowlist.allows(ip) {
+            return PathDecision::Safe;
+        }
+
         // Check if IP is banned
-        if self.banned_ips.contains_key(&ip) {
+        if self.is_banned(ip) {
             return PathDecision::Banned;
         }
+        let ip = client_key(ip);
 
         // Check if path is sensitive
-        let is_sensitive = self.is_sensitive_path(path);
+        let is_sensitive = self.is_sensitive_path(path)
+            || site_id.map_or(false, |site| self.is_site_sensitive_path(site, path));
         
         // Only track 404s on sensitive paths (scanning behavior)
         if is_sensitive && status_code == 404 {
             self.record_scan_attempt(ip, path.to_string());
             
             // Check if threshold exceeded
-            if let Some(tracker) = self.scan_attempts.get(&ip) {
-                if tracker.attempts.len() >= self.ban_threshold {
-                    warn!(
-                        ip = %ip,
-                        attempts = tracker.attempts.len(),
-                        "Suspicious scanning detected - banning IP"
-                    );
-                    
-                    self.ban_ip(ip);
-                    return PathDecision::Banned;
-                }
+            let attempts = self.scan_attempts.get(&ip).map_or(0, |tracker| tracker.attempts.len());
+            if attempts >= self.ban_threshold {
+                warn!(
+                    ip = %ip,
+                    attempts = attempts,
+                    ttl_secs = self.ban_ttl.map(|ttl| ttl.as_secs()),
+                    "Suspicious scanning detected - banning IP"
+                );
+                
+                self.ban_ip(ip);
+                return PathDecision::Banned;
             }
             
             PathDecision::Suspicious
@@ -115,6 +165,68 @@ impl PathMonitor {
         })
     }
 
+    /// Check if path is sensitive for a specific site
+    fn is_site_sensitive_path(&self, site_id: &str, path: &str) -> bool {
+        let path_lower = path.to_lowercase();
+        
+        self.site_sensitive_paths.get(site_id)
+            .map_or(false, |paths| paths.iter().any(|sensitive| path_lower.contains(sensitive)))
+    }
+
+    /// Replace the extra sensitive paths for a site
+    pub fn set_site_sensitive_paths(&self, site_id: &str, paths: &[String]) {
+        let paths: Vec<String> = paths.iter().map(|p| p.to_lowercase()).collect();
+        
+        if paths.is_empty() {
+            self.site_sensitive_paths.remove(site_id);
+        } else {
+            self.site_sensitive_paths.insert(site_id.to_string(), paths);
+        }
+    }
+
+    /// Whether an IP is currently banned
+    /// Expired bans are lifted on lookup
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let key = client_key(ip);
+        let expired = match self.banned_ips.get(&key) {
+            Some(banned_at) => self.is_expired(*banned_at),
+            None => return false,
+        };
+
+        if expired {
+            self.lift_expired_ban(key);
+            return false;
+        }
+
+        !self.allowlist.allows(ip)
+    }
+
+    /// Whether a ban placed at `banned_at` has run out
+    fn is_expired(&self, banned_at: Instant) -> bool {
+        self.ban_ttl.map_or(false, |ttl| banned_at.elapsed() >= ttl)
+    }
+
+    /// Remove a ban whose TTL has run out
+    fn lift_expired_ban(&self, ip: IpAddr) {
+        if self.banned_ips.remove_if(&ip, |_, banned_at| self.is_expired(*banned_at)).is_some() {
+            self.expired_bans.fetch_add(1, Ordering::Relaxed);
+            debug!(ip = %ip, "Ban expired");
+        }
+    }
+
+    /// Drop expired bans and scan history older than an hour
+    pub fn cleanup(&self) {
+        let before = self.banned_ips.len();
+        self.banned_ips.retain(|_, banned_at| !self.is_expired(*banned_at));
+        let expired = before.saturating_sub(self.banned_ips.len());
+        self.expired_bans.fetch_add(expired as u64, Ordering::Relaxed);
+
+        let one_hour_ago = Instant::now() - Duration::from_secs(3600);
+        self.scan_attempts.retain(|_, tracker| {
+            tracker.attempts.iter().any(|attempt| attempt.timestamp > one_hour_ago)
+        });
+    }
+
     /// Record a scan attempt
     fn record_scan_attempt(&self, ip: IpAddr, path: String) {
         let mut entry = self.scan_attempts.entry(ip).or_insert_with(|| ScanTracker {
@@ -129,24 +241,42 @@ impl PathMonitor {
         });
 
         // Keep only recent attempts (last hour)
-        let one_hour_ago = Instant::now() - std::time::Duration::from_secs(3600);
+        let one_hour_ago = Instant::now() - Duration::from_secs(3600);
         tracker.attempts.retain(|attempt| attempt.timestamp > one_hour_ago);
     }
 
     /// Ban an IP
+    /// Scan history is reset so an expired ban doesn't immediately re-trigger
     fn ban_ip(&self, ip: IpAddr) {
         self.banned_ips.insert(ip, Instant::now());
+        self.scan_attempts.remove(&ip);
     }
 
     /// Manually ban an IP
     pub fn manual_ban(&self, ip: IpAddr) {
         warn!(ip = %ip, "Manually banning IP");
-        self.ban_ip(ip);
+        self.ban_ip(client_key(ip));
     }
 
     /// Unban an IP
     pub fn unban(&self, ip: IpAddr) -> bool {
-        self.banned_ips.remove(&ip).is_some()
+        self.banned_ips.remove(&client_key(ip)).is_some()
+    }
+
+    /// Current bans with how long ago each was placed
+    pub fn bans(&self) -> Vec<(IpAddr, Duration)> {
+        self.banned_ips.iter()
+            .filter(|ban| !self.is_expired(*ban.value()))
+            .map(|ban| (*ban.key(), ban.value().elapsed()))
+            .collect()
+    }
+
+    /// Ban an IP as if it had been banned `age` ago, so it expires when the original ban would have
+    pub fn restore_ban(&self, ip: IpAddr, age: Duration) {
+        let banned_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        if !self.is_expired(banned_at) {
+            self.banned_ips.insert(client_key(ip), banned_at);
+        }
     }
 
     /// Add custom sensitive path
@@ -163,6 +293,7 @@ impl PathMonitor {
         PathMonitorStats {
             tracked_ips: self.scan_attempts.len(),
             banned_ips: self.banned_ips.len(),
+            expired_bans: self.expired_bans.load(Ordering::Relaxed),
             total_scan_attempts,
             sensitive_paths_count: self.sensitive_paths.len(),
         }
@@ -170,7 +301,7 @@ impl PathMonitor {
 
     /// Get scanned paths for an IP
     pub fn get_scan_history(&self, ip: IpAddr) -> Vec<String> {
-        self.scan_attempts.get(&ip)
+        self.scan_attempts.get(&client_key(ip))
             .map(|tracker| {
                 tracker.attempts.iter()
                     .map(|attempt| attempt.path.clone())
@@ -193,6 +324,7 @@ pub enum PathDecision {
 pub struct PathMonitorStats {
     pub tracked_ips: usize,
     pub banned_ips: usize,
+    pub expired_bans: u64,
     pub total_scan_attempts: usize,
     pub sensitive_paths_count: usize,
 }
@@ -234,6 +366,42 @@ mod tests {
         assert_eq!(monitor.check_path(ip, "/index.html", 200), PathDecision::Banned);
     }
 
+    #[test]
+    fn test_configured_sensitive_paths() {
+        let monitor = PathMonitor::with_sensitive_paths(5, &["/internal".to_string()]);
+        monitor.set_site_sensitive_paths("shop", &["/Checkout/debug".to_string()]);
+        
+        assert!(monitor.is_sensitive_path("/internal/metrics"));
+        assert!(monitor.is_site_sensitive_path("shop", "/checkout/debug"));
+        assert!(!monitor.is_site_sensitive_path("blog", "/checkout/debug"));
+    }
+
+    #[test]
+    fn test_ban_expires_after_ttl() {
+        let monitor = PathMonitor::new(1).with_ban_ttl(Duration::from_millis(20));
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 50));
+
+        assert_eq!(monitor.check_path(ip, "/.env", 404), PathDecision::Banned);
+        assert!(monitor.is_banned(ip));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!monitor.is_banned(ip));
+        assert_eq!(monitor.check_path(ip, "/index.html", 200), PathDecision::Safe);
+        assert_eq!(monitor.stats().expired_bans, 1);
+    }
+
+    #[test]
+    fn test_allowlisted_ip_never_banned() {
+        let allowlist = IpAllowlist::parse(&["10.0.0.0/8".to_string()]).unwrap();
+        let monitor = PathMonitor::new(1).with_allowlist(Arc::new(allowlist));
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 20, 30, 40));
+
+        assert_eq!(monitor.check_path(ip, "/.env", 404), PathDecision::Safe);
+        monitor.manual_ban(ip);
+        assert!(!monitor.is_banned(ip));
+        assert_eq!(monitor.stats().tracked_ips, 0);
+    }
+
     #[test]
     fn test_safe_path() {
         let monitor = PathMonitor::new(5);
@@ -220,7 +220,7 @@ impl PerformanceAlert {
 }
 
 /// Performance statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct PerformanceStats {
     pub request_latency_mean_ms: f64,
     pub request_latency_std_dev: f64,
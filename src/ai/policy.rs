@@ -0,0 +1,185 @@
+// Per-site AI Policy
+// Site and tenant overrides for anomaly detection and enforcement
+
+use anyhow::Result;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+/// What happens when a request is judged unsafe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnforcementMode {
+    /// Reject the request
+    #[default]
+    Block,
+
+    /// Log and count, but let the request through
+    Monitor,
+}
+
+/// AI policy overrides for a site or tenant
+/// Unset fields fall back to the tenant policy, then the global [ai] settings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AiPolicy {
+    pub enable_anomaly_detection: Option<bool>,
+    pub anomaly_threshold: Option<f64>,
+    pub sample_rate: Option<f64>,
+    pub mode: Option<EnforcementMode>,
+}
+
+impl AiPolicy {
+    /// Fill unset fields from a less specific policy
+    pub fn or(&self, fallback: &AiPolicy) -> AiPolicy {
+        AiPolicy {
+            enable_anomaly_detection: self.enable_anomaly_detection.or(fallback.enable_anomaly_detection),
+            anomaly_threshold: self.anomaly_threshold.or(fallback.anomaly_threshold),
+            sample_rate: self.sample_rate.or(fallback.sample_rate),
+            mode: self.mode.or(fallback.mode),
+        }
+    }
+
+    /// Whether no field is overridden
+    pub fn is_empty(&self) -> bool {
+        *self == AiPolicy::default()
+    }
+
+    /// Check that overridden values are in range
+    pub fn validate(&self) -> Result<()> {
+        if let Some(threshold) = self.anomaly_threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                anyhow::bail!("Anomaly threshold must be between 0.0 and 1.0");
+            }
+        }
+
+        if let Some(rate) = self.sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                anyhow::bail!("Sample rate must be between 0.0 and 1.0");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Effective policy for a site, with every field resolved
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ResolvedPolicy {
+    pub enable_anomaly_detection: bool,
+    pub anomaly_threshold: f64,
+    pub sample_rate: f64,
+    pub mode: EnforcementMode,
+}
+
+impl ResolvedPolicy {
+    /// Apply overrides on top of this policy
+    pub fn with(&self, policy: &AiPolicy) -> ResolvedPolicy {
+        ResolvedPolicy {
+            enable_anomaly_detection: policy.enable_anomaly_detection.unwrap_or(self.enable_anomaly_detection),
+            anomaly_threshold: policy.anomaly_threshold.unwrap_or(self.anomaly_threshold),
+            sample_rate: policy.sample_rate.unwrap_or(self.sample_rate),
+            mode: policy.mode.unwrap_or(self.mode),
+        }
+    }
+
+    /// Whether unsafe requests are rejected
+    pub fn blocks(&self) -> bool {
+        self.mode == EnforcementMode::Block
+    }
+}
+
+/// Global policy plus per-site overrides, replaceable at runtime
+pub struct PolicyStore {
+    global: ResolvedPolicy,
+    sites: DashMap<String, AiPolicy>,
+}
+
+impl PolicyStore {
+    /// Create a store with the global defaults
+    pub fn new(global: ResolvedPolicy) -> Self {
+        Self {
+            global,
+            sites: DashMap::new(),
+        }
+    }
+
+    /// Effective policy for a site
+    pub fn resolve(&self, site_id: &str) -> ResolvedPolicy {
+        match self.sites.get(site_id) {
+            Some(policy) => self.global.with(&policy),
+            None => self.global,
+        }
+    }
+
+    /// Replace a site's overrides (an empty policy removes them)
+    pub fn set(&self, site_id: &str, policy: AiPolicy) -> Result<()> {
+        policy.validate()?;
+
+        if policy.is_empty() {
+            self.sites.remove(site_id);
+        } else {
+            self.sites.insert(site_id.to_string(), policy);
+        }
+
+        Ok(())
+    }
+
+    /// Overrides currently set for a site
+    pub fn get(&self, site_id: &str) -> AiPolicy {
+        self.sites.get(site_id).map(|p| p.clone()).unwrap_or_default()
+    }
+
+    /// Number of sites with overrides
+    pub fn site_count(&self) -> usize {
+        self.sites.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global() -> ResolvedPolicy {
+        ResolvedPolicy {
+            enable_anomaly_detection: true,
+            anomaly_threshold: 0.8,
+            sample_rate: 0.1,
+            mode: EnforcementMode::Block,
+        }
+    }
+
+    #[test]
+    fn test_site_overrides_tenant_overrides_global() {
+        let tenant = AiPolicy {
+            sample_rate: Some(1.0),
+            anomaly_threshold: Some(0.6),
+            ..Default::default()
+        };
+        let site = AiPolicy {
+            anomaly_threshold: Some(0.7),
+            mode: Some(EnforcementMode::Monitor),
+            ..Default::default()
+        };
+
+        let store = PolicyStore::new(global());
+        store.set("wordpress", site.or(&tenant)).unwrap();
+
+        let resolved = store.resolve("wordpress");
+        assert_eq!(resolved.sample_rate, 1.0);
+        assert_eq!(resolved.anomaly_threshold, 0.7);
+        assert!(!resolved.blocks());
+        assert_eq!(store.resolve("other"), global());
+    }
+
+    #[test]
+    fn test_invalid_and_empty_policies() {
+        let store = PolicyStore::new(global());
+        let invalid = AiPolicy { sample_rate: Some(2.0), ..Default::default() };
+        assert!(store.set("api", invalid).is_err());
+
+        store.set("api", AiPolicy { enable_anomaly_detection: Some(false), ..Default::default() }).unwrap();
+        assert_eq!(store.site_count(), 1);
+        store.set("api", AiPolicy::default()).unwrap();
+        assert_eq!(store.site_count(), 0);
+    }
+}
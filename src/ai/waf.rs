@@ -0,0 +1,410 @@
+// Web Application Firewall
+// Regex rules with block/allow/monitor actions, plus built-in SQLi/XSS signatures
+
+use super::ThreatType;
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Action taken when a rule matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Reject the request
+    #[default]
+    Block,
+    /// Let the request through without evaluating later rules
+    Allow,
+    /// Count and log the match, then keep evaluating
+    Monitor,
+}
+
+/// Part of the request a rule is matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleTarget {
+    /// Decoded path only
+    Path,
+    /// Decoded query string only
+    Query,
+    /// Path and query string
+    #[default]
+    Uri,
+}
+
+/// A single configurable rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleConfig {
+    /// Rule name, used for hit counters and logs
+    pub name: String,
+
+    /// Regular expression matched against the target
+    pub pattern: String,
+
+    #[serde(default)]
+    pub target: RuleTarget,
+
+    #[serde(default)]
+    pub action: RuleAction,
+
+    /// Threat classification reported when the rule blocks
+    #[serde(default)]
+    pub threat: Option<ThreatType>,
+}
+
+/// Rules and sensitive paths for one scope (global or a single site)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleSetConfig {
+    /// Additional paths whose 404s count as scanning
+    #[serde(default)]
+    pub sensitive_paths: Vec<String>,
+
+    /// Rules evaluated in order, first block/allow wins
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+}
+
+/// Rule compiled for matching
+struct CompiledRule {
+    name: String,
+    regex: Regex,
+    target: RuleTarget,
+    action: RuleAction,
+    threat: Option<ThreatType>,
+    hits: AtomicU64,
+}
+
+impl CompiledRule {
+    fn compile(config: &RuleConfig) -> Result<Self> {
+        let regex = Regex::new(&config.pattern)
+            .with_context(|| format!("Invalid pattern for rule '{}'", config.name))?;
+
+        Ok(Self {
+            name: config.name.clone(),
+            regex,
+            target: config.target,
+            action: config.action,
+            threat: config.threat,
+            hits: AtomicU64::new(0),
+        })
+    }
+
+    fn matches(&self, path: &str, query: &str) -> bool {
+        match self.target {
+            RuleTarget::Path => self.regex.is_match(path),
+            RuleTarget::Query => self.regex.is_match(query),
+            RuleTarget::Uri => self.regex.is_match(path) || self.regex.is_match(query),
+        }
+    }
+}
+
+/// Compiled rules for one scope
+struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    fn compile(config: &RuleSetConfig) -> Result<Self> {
+        let rules = config.rules.iter()
+            .map(CompiledRule::compile)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { rules })
+    }
+
+    fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+}
+
+/// Outcome of a matched block/allow rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WafVerdict {
+    pub action: RuleAction,
+    pub rule: String,
+    pub threat: Option<ThreatType>,
+}
+
+/// Hit counter for a single rule
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleStats {
+    /// "global", "builtin", or the site ID
+    pub scope: String,
+    pub name: String,
+    pub action: RuleAction,
+    pub hits: u64,
+}
+
+/// Rule engine with global, per-site, and built-in signature rules
+pub struct WafEngine {
+    global: RwLock<Arc<RuleSet>>,
+    sites: DashMap<String, Arc<RuleSet>>,
+    signatures: RuleSet,
+}
+
+impl WafEngine {
+    /// Create an engine from global rules
+    pub fn new(global: &RuleSetConfig, builtin_signatures: bool) -> Result<Self> {
+        let signatures = if builtin_signatures {
+            RuleSet::compile(&builtin_signature_rules())?
+        } else {
+            RuleSet::empty()
+        };
+
+        info!(
+            global_rules = global.rules.len(),
+            signatures = signatures.rules.len(),
+            "Initializing WAF engine"
+        );
+
+        Ok(Self {
+            global: RwLock::new(Arc::new(RuleSet::compile(global)?)),
+            sites: DashMap::new(),
+            signatures,
+        })
+    }
+
+    /// Replace the global rules
+    pub fn set_global_rules(&self, config: &RuleSetConfig) -> Result<()> {
+        let rules = Arc::new(RuleSet::compile(config)?);
+        *self.global.write() = rules;
+        Ok(())
+    }
+
+    /// Replace the rules for a site
+    pub fn set_site_rules(&self, site_id: &str, config: &RuleSetConfig) -> Result<()> {
+        let rules = Arc::new(RuleSet::compile(config)?);
+        info!(site_id = %site_id, rules = rules.rules.len(), "Updated site WAF rules");
+        self.sites.insert(site_id.to_string(), rules);
+        Ok(())
+    }
+
+    /// Remove the rules for a site
+    pub fn remove_site_rules(&self, site_id: &str) -> bool {
+        self.sites.remove(site_id).is_some()
+    }
+
+    /// Evaluate a request path and query string
+    /// Site rules run first, then global rules, then built-in signatures
+    pub fn evaluate(&self, site_id: &str, path: &str, query: &str) -> Option<WafVerdict> {
+        let path = percent_decode(path, false);
+        let query = percent_decode(query, true);
+
+        let site = self.sites.get(site_id).map(|rules| rules.clone());
+        let global = self.global.read().clone();
+
+        let scopes = site.iter()
+            .map(|rules| rules.as_ref())
+            .chain(std::iter::once(global.as_ref()))
+            .chain(std::iter::once(&self.signatures));
+
+        for rules in scopes {
+            for rule in &rules.rules {
+                if !rule.matches(&path, &query) {
+                    continue;
+                }
+
+                rule.hits.fetch_add(1, Ordering::Relaxed);
+
+                match rule.action {
+                    RuleAction::Monitor => {
+                        debug!(site_id = %site_id, rule = %rule.name, path = %path, "WAF rule matched (monitor)");
+                    }
+                    action => {
+                        if action == RuleAction::Block {
+                            warn!(site_id = %site_id, rule = %rule.name, path = %path, "WAF rule blocked request");
+                        }
+
+                        return Some(WafVerdict {
+                            action,
+                            rule: rule.name.clone(),
+                            threat: rule.threat,
+                        });
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Hit counters for every rule
+    pub fn rule_stats(&self) -> Vec<RuleStats> {
+        let mut stats = Vec::new();
+
+        let mut collect = |scope: &str, rules: &RuleSet| {
+            stats.extend(rules.rules.iter().map(|rule| RuleStats {
+                scope: scope.to_string(),
+                name: rule.name.clone(),
+                action: rule.action,
+                hits: rule.hits.load(Ordering::Relaxed),
+            }));
+        };
+
+        collect("global", &self.global.read());
+        for entry in self.sites.iter() {
+            collect(entry.key(), entry.value());
+        }
+        collect("builtin", &self.signatures);
+
+        stats
+    }
+}
+
+/// Check that every rule pattern compiles
+pub fn validate_rules(config: &RuleSetConfig) -> Result<()> {
+    RuleSet::compile(config).map(|_| ())
+}
+
+/// Built-in SQL injection and XSS signatures
+fn builtin_signature_rules() -> RuleSetConfig {
+    let rule = |name: &str, pattern: &str, threat: ThreatType| RuleConfig {
+        name: name.to_string(),
+        pattern: pattern.to_string(),
+        target: RuleTarget::Uri,
+        action: RuleAction::Block,
+        threat: Some(threat),
+    };
+
+    RuleSetConfig {
+        sensitive_paths: Vec::new(),
+        rules: vec![
+            rule("sqli-union-select", r"(?i)\bunion\b.{0,64}\bselect\b", ThreatType::SqlInjection),
+            rule("sqli-tautology", r#"(?i)['"]\s*or\s*['"]?\d+['"]?\s*=\s*['"]?\d+"#, ThreatType::SqlInjection),
+            rule("sqli-stacked-query", r"(?i);\s*(drop|delete|insert|update|alter)\s", ThreatType::SqlInjection),
+            rule("sqli-comment", r#"(?i)['"]\s*(--|#|/\*)"#, ThreatType::SqlInjection),
+            rule("sqli-time-based", r"(?i)\b(sleep|benchmark|pg_sleep)\s*\(", ThreatType::SqlInjection),
+            rule("sqli-schema-probe", r"(?i)\binformation_schema\b", ThreatType::SqlInjection),
+            rule("xss-script-tag", r"(?i)<\s*script\b", ThreatType::Xss),
+            rule("xss-event-handler", r"(?i)\bon(error|load|click|mouseover|focus)\s*=", ThreatType::Xss),
+            rule("xss-javascript-uri", r"(?i)javascript\s*:", ThreatType::Xss),
+            rule("xss-embedded-frame", r"(?i)<\s*(iframe|object|embed|svg)\b", ThreatType::Xss),
+        ],
+    }
+}
+
+/// Decode %XX escapes (and '+' in query strings) so encoded payloads still match
+pub(crate) fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine() -> WafEngine {
+        WafEngine::new(&RuleSetConfig::default(), true).unwrap()
+    }
+
+    #[test]
+    fn test_builtin_sqli_detection() {
+        let waf = engine();
+        let verdict = waf.evaluate("site", "/users", "id=1%20UNION%20SELECT%20password").unwrap();
+        assert_eq!(verdict.action, RuleAction::Block);
+        assert_eq!(verdict.threat, Some(ThreatType::SqlInjection));
+
+        assert!(waf.evaluate("site", "/users", "id=42&sort=name").is_none());
+    }
+
+    #[test]
+    fn test_builtin_xss_detection() {
+        let waf = engine();
+        let verdict = waf.evaluate("site", "/search", "q=%3Cscript%3Ealert(1)%3C/script%3E").unwrap();
+        assert_eq!(verdict.threat, Some(ThreatType::Xss));
+    }
+
+    #[test]
+    fn test_site_allow_overrides_signatures() {
+        let waf = engine();
+        waf.set_site_rules("cms", &RuleSetConfig {
+            sensitive_paths: vec![],
+            rules: vec![RuleConfig {
+                name: "editor".to_string(),
+                pattern: "^/editor/".to_string(),
+                target: RuleTarget::Path,
+                action: RuleAction::Allow,
+                threat: None,
+            }],
+        }).unwrap();
+
+        let verdict = waf.evaluate("cms", "/editor/save", "body=<script>").unwrap();
+        assert_eq!(verdict.action, RuleAction::Allow);
+
+        // Other sites still get the signature
+        let verdict = waf.evaluate("blog", "/editor/save", "body=<script>").unwrap();
+        assert_eq!(verdict.action, RuleAction::Block);
+    }
+
+    #[test]
+    fn test_rule_hit_counters() {
+        let global = RuleSetConfig {
+            sensitive_paths: vec![],
+            rules: vec![RuleConfig {
+                name: "actuator".to_string(),
+                pattern: "^/actuator".to_string(),
+                target: RuleTarget::Path,
+                action: RuleAction::Monitor,
+                threat: None,
+            }],
+        };
+        let waf = WafEngine::new(&global, false).unwrap();
+
+        assert!(waf.evaluate("site", "/actuator/health", "").is_none());
+        assert!(waf.evaluate("site", "/actuator/env", "").is_none());
+
+        let stats = waf.rule_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].hits, 2);
+    }
+
+    #[test]
+    fn test_invalid_pattern_rejected() {
+        let config = RuleSetConfig {
+            sensitive_paths: vec![],
+            rules: vec![RuleConfig {
+                name: "broken".to_string(),
+                pattern: "([".to_string(),
+                target: RuleTarget::Uri,
+                action: RuleAction::Block,
+                threat: None,
+            }],
+        };
+        assert!(WafEngine::new(&config, false).is_err());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b+c", true), "a b c");
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(percent_decode("100%", false), "100%");
+    }
+}
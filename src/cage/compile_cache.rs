@@ -0,0 +1,262 @@
+// Compiled Module Cache
+// Keeps Wasmtime's native code for modules on disk, so restarts deserialize instead of recompiling
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::{debug, info, warn};
+use wasmtime::{Engine, Module};
+
+use crate::storage::modules::ModuleHash;
+
+/// `[compile_cache]`: compiled modules kept across restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompileCacheConfig {
+    pub enabled: bool,
+
+    /// Directory of compiled modules ("" = `<storage root>/compiled`)
+    pub dir: String,
+
+    /// Space compiled modules may take; the least recently used go first (0 = unlimited)
+    pub max_size_mb: u64,
+}
+
+impl Default for CompileCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: String::new(),
+            max_size_mb: 2048,
+        }
+    }
+}
+
+impl CompileCacheConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.dir.trim() == "/" {
+            bail!("dir must not be the filesystem root");
+        }
+        Ok(())
+    }
+
+    /// Directory of the cache, given the storage root
+    pub fn path(&self, storage_root: &Path) -> PathBuf {
+        if self.dir.is_empty() {
+            storage_root.join("compiled")
+        } else {
+            PathBuf::from(&self.dir)
+        }
+    }
+}
+
+/// Files kept and space used by the cache
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompileCacheStats {
+    pub modules: usize,
+    pub bytes: u64,
+}
+
+/// Compiled modules stored as `<dir>/<engine hash>/<module hash>.cwasm`
+///
+/// The engine hash covers the Wasmtime version and every setting that changes the code it
+/// generates, so an upgrade or a change of settings misses instead of loading stale code.
+#[derive(Debug)]
+pub struct CompileCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl CompileCache {
+    pub fn new<P: AsRef<Path>>(dir: P, max_size_mb: u64) -> Result<Arc<Self>> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create compile cache: {}", dir.display()))?;
+        Ok(Arc::new(Self { dir, max_bytes: max_size_mb.saturating_mul(1024 * 1024) }))
+    }
+
+    /// Open the configured cache, if enabled
+    pub fn open(config: &CompileCacheConfig, storage_root: &Path) -> Result<Option<Arc<Self>>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        Self::new(config.path(storage_root), config.max_size_mb).map(Some)
+    }
+
+    /// File the module compiled by `engine` is kept in
+    pub fn path(&self, engine: &Engine, hash: &ModuleHash) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+        self.dir.join(format!("{:016x}", hasher.finish())).join(format!("{}.cwasm", hash))
+    }
+
+    /// The module compiled for `engine`, from the cache or compiled and stored now
+    pub fn module(&self, engine: &Engine, wasm_bytes: &[u8]) -> Result<Module> {
+        self.load(engine, wasm_bytes).map(|(module, _)| module)
+    }
+
+    /// Compile a module into the cache unless it is there already
+    /// Returns whether it had to be compiled.
+    pub fn compile(&self, engine: &Engine, wasm_bytes: &[u8]) -> Result<bool> {
+        self.load(engine, wasm_bytes).map(|(_, compiled)| compiled)
+    }
+
+    fn load(&self, engine: &Engine, wasm_bytes: &[u8]) -> Result<(Module, bool)> {
+        let hash = ModuleHash::of(wasm_bytes);
+        let path = self.path(engine, &hash);
+        if path.exists() {
+            // SAFETY: files in the cache are only written by `store`, from Wasmtime's own
+            // serialization; Wasmtime checks the version and settings they were compiled with.
+            match unsafe { Module::deserialize_file(engine, &path) } {
+                Ok(module) => {
+                    debug!(module = %hash, "Compiled module loaded from cache");
+                    touch(&path);
+                    return Ok((module, false));
+                }
+                Err(e) => {
+                    warn!(module = %hash, error = %e, "Cached module unusable; recompiling");
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+
+        let module = Module::new(engine, wasm_bytes).context("Failed to compile WebAssembly module")?;
+        // A cache that can't be written only costs the next start a compile
+        if let Err(e) = self.store(&path, &module) {
+            warn!(module = %hash, error = %e, "Failed to cache compiled module");
+        }
+        Ok((module, true))
+    }
+
+    fn store(&self, path: &Path, module: &Module) -> Result<()> {
+        let dir = path.parent().expect("cache paths have a parent");
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let tmp = path.with_extension("cwasm.tmp");
+        std::fs::write(&tmp, module.serialize()?).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))?;
+        if self.max_bytes > 0 {
+            self.evict(self.max_bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the least recently used files until the cache fits in `max_bytes`
+    /// Returns the number of files removed.
+    pub fn evict(&self, max_bytes: u64) -> Result<usize> {
+        let mut files: Vec<(PathBuf, u64, SystemTime)> = self.files()?.into_iter()
+            .filter_map(|path| {
+                let metadata = std::fs::metadata(&path).ok()?;
+                let used = metadata.modified().ok()?;
+                Some((path, metadata.len(), used))
+            })
+            .collect();
+        let mut total: u64 = files.iter().map(|(_, bytes, _)| bytes).sum();
+        files.sort_by_key(|(_, _, used)| *used);
+
+        let mut removed = 0;
+        for (path, bytes, _) in files {
+            if total <= max_bytes {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                total -= bytes;
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            info!(removed = removed, "Compile cache trimmed");
+        }
+        Ok(removed)
+    }
+
+    pub fn stats(&self) -> Result<CompileCacheStats> {
+        let mut stats = CompileCacheStats::default();
+        for path in self.files()? {
+            stats.modules += 1;
+            stats.bytes += std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        }
+        Ok(stats)
+    }
+
+    /// Every compiled module, for any engine
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for dir in std::fs::read_dir(&self.dir)? {
+            let dir = dir?.path();
+            if dir.is_dir() {
+                for file in std::fs::read_dir(&dir)? {
+                    let file = file?.path();
+                    if file.extension().is_some_and(|extension| extension == "cwasm") {
+                        files.push(file);
+                    }
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Mark a cached file as just used, for eviction
+fn touch(path: &Path) {
+    if let Ok(file) = std::fs::File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cage::{config::CageConfig, create_engine_for};
+    use tempfile::TempDir;
+
+    const WAT: &str = r#"(module (func (export "add") (param i32 i32) (result i32) local.get 0 local.get 1 i32.add))"#;
+
+    #[test]
+    fn test_compiled_once_per_engine() {
+        let temp = TempDir::new().unwrap();
+        let cache = CompileCache::new(temp.path(), 0).unwrap();
+        let wasm = wat::parse_str(WAT).unwrap();
+        let engine = create_engine_for(&CageConfig::default()).unwrap();
+
+        assert!(cache.compile(&engine, &wasm).unwrap());
+        assert!(!cache.compile(&engine, &wasm).unwrap());
+        // A new engine with the same settings, as after a restart, reuses the code
+        let restarted = create_engine_for(&CageConfig::default()).unwrap();
+        assert!(!cache.compile(&restarted, &wasm).unwrap());
+        assert!(cache.module(&restarted, &wasm).unwrap().get_export("add").is_some());
+
+        // Metered engines generate different code
+        let metered = create_engine_for(&CageConfig { fuel: Some(1000), ..Default::default() }).unwrap();
+        assert!(cache.compile(&metered, &wasm).unwrap());
+        assert_eq!(cache.stats().unwrap().modules, 2);
+
+        // A damaged file is replaced
+        let path = cache.path(&engine, &ModuleHash::of(&wasm));
+        std::fs::write(&path, b"not a compiled module").unwrap();
+        assert!(cache.compile(&engine, &wasm).unwrap());
+        assert!(!cache.compile(&engine, &wasm).unwrap());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let temp = TempDir::new().unwrap();
+        let cache = CompileCache::new(temp.path(), 0).unwrap();
+        let engine = create_engine_for(&CageConfig::default()).unwrap();
+        let old = wat::parse_str(WAT).unwrap();
+        let new = wat::parse_str("(module (func (export \"run\")))").unwrap();
+        cache.compile(&engine, &old).unwrap();
+        let old_path = cache.path(&engine, &ModuleHash::of(&old));
+        std::fs::File::options().write(true).open(&old_path).unwrap()
+            .set_modified(SystemTime::now() - std::time::Duration::from_secs(3600)).unwrap();
+        cache.compile(&engine, &new).unwrap();
+
+        let newest = std::fs::metadata(cache.path(&engine, &ModuleHash::of(&new))).unwrap().len();
+        assert_eq!(cache.evict(newest).unwrap(), 1);
+        assert!(!old_path.exists());
+        assert_eq!(cache.stats().unwrap().modules, 1);
+    }
+}
@@ -2,6 +2,20 @@
 // Defines resource limits and WASI permissions for Cage instances
 
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::cage::compile_cache::CompileCache;
+use crate::cage::crash::CrashReporter;
+use crate::cage::determinism::{Determinism, Recorder};
+use crate::cage::profiling::{JitProfiler, Profiler};
+use crate::cage::request_queue::{default_queue_depth, default_queue_timeout_ms};
+use crate::crdt::pubsub::GuestPubSub;
+use crate::crdt::session::GuestSessions;
+use crate::mail::GuestMailer;
+use crate::observability::guest_logs::SiteLog;
+use crate::scheduler::queue::GuestQueue;
+use crate::storage::database::GuestDatabase;
+use crate::tenancy::billing::GuestUsage;
 
 /// Configuration for a Cage instance
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +29,18 @@ pub struct CageConfig {
     /// Maximum number of concurrent requests per Cage
     pub max_concurrent_requests: usize,
     
+    /// Requests that may wait when every Cage of the pool is at `max_concurrent_requests`
+    #[serde(default = "default_queue_depth")]
+    pub request_queue_depth: usize,
+    
+    /// How long a waiting request gets for a Cage to free up before it is shed (milliseconds)
+    #[serde(default = "default_queue_timeout_ms")]
+    pub request_queue_timeout_ms: u64,
+    
+    /// Cages kept instantiated outside load balancing, promoted the moment one crashes
+    #[serde(default)]
+    pub standby_replicas: usize,
+    
     /// Allow filesystem access
     pub allow_filesystem: bool,
     
@@ -23,6 +49,123 @@ pub struct CageConfig {
     
     /// Preopened directories (if filesystem is allowed)
     pub preopen_dirs: Vec<String>,
+    
+    /// Environment variables, including decrypted site secrets
+    #[serde(skip)]
+    pub env: CageEnv,
+    
+    /// Site database linked as the `pear_db` host module
+    #[serde(skip)]
+    pub database: Option<GuestDatabase>,
+    
+    /// Site task queue linked as the `pear_queue` host module
+    #[serde(skip)]
+    pub queue: Option<GuestQueue>,
+    
+    /// Site topics linked as the `pear_pubsub` host module
+    #[serde(skip)]
+    pub pubsub: Option<GuestPubSub>,
+    
+    /// Tenant mail relay linked as the `pear_mail` host module
+    #[serde(skip)]
+    pub mail: Option<GuestMailer>,
+    
+    /// Site sessions linked as the `pear_session` host module
+    #[serde(skip)]
+    pub sessions: Option<GuestSessions>,
+    
+    /// Fixed time and randomness for guests, instead of the host's
+    #[serde(default)]
+    pub determinism: Option<Determinism>,
+    
+    /// Where calls to the site's guests are recorded for replay
+    #[serde(skip)]
+    pub recorder: Option<Arc<Recorder>>,
+    
+    /// Site log that guest stdout and stderr are captured into, instead of the daemon's stdio
+    #[serde(skip)]
+    pub guest_log: Option<Arc<SiteLog>>,
+    
+    /// Where traps of the site's guests are reported
+    #[serde(skip)]
+    pub crashes: Option<Arc<CrashReporter>>,
+    
+    /// Host profiler support compiled into the guest's code
+    #[serde(default)]
+    pub jit_profiler: JitProfiler,
+    
+    /// Sampling profile the site's guests are counted in
+    #[serde(skip)]
+    pub profiler: Option<Arc<Profiler>>,
+    
+    /// Fuel each guest call starts with; None runs guests unmetered
+    #[serde(default)]
+    pub fuel: Option<u64>,
+    
+    /// Where the site's guest calls are counted for billing
+    #[serde(skip)]
+    pub usage: Option<GuestUsage>,
+    
+    /// Where compiled modules are kept across restarts
+    #[serde(skip)]
+    pub compile_cache: Option<Arc<CompileCache>>,
+    
+    /// When the site's pool starts its Cages
+    #[serde(default)]
+    pub start: StartPolicy,
+    
+    /// Seconds without requests before a scale-to-zero pool stops its Cages
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout_secs: u64,
+}
+
+/// When a pool's Cages run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StartPolicy {
+    /// Every replica from deploy on
+    #[default]
+    Eager,
+    
+    /// No Cage until the first request
+    Lazy,
+    
+    /// Like lazy, and back to no Cages once idle for `idle_timeout_secs`
+    ScaleToZero,
+}
+
+pub fn default_idle_timeout() -> u64 { 300 }
+
+/// Environment passed to a Cage's WASI context
+/// Values may be decrypted secrets, so Debug only shows variable names
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct CageEnv(Vec<(String, String)>);
+
+impl CageEnv {
+    /// Add a variable
+    pub fn push(&mut self, name: &str, value: &str) {
+        self.0.push((name.to_string(), value.to_string()));
+    }
+
+    /// Name/value pairs in insertion order
+    pub fn as_pairs(&self) -> &[(String, String)] {
+        &self.0
+    }
+
+    /// Variable names only, safe to log
+    pub fn names(&self) -> Vec<&str> {
+        self.0.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::fmt::Debug for CageEnv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CageEnv").field(&self.names()).finish()
+    }
 }
 
 impl Default for CageConfig {
@@ -31,9 +174,29 @@ impl Default for CageConfig {
             memory_limit_bytes: 128 * 1024 * 1024, // 128MB
             cpu_timeout_ms: 1000,                    // 1 second
             max_concurrent_requests: 100,
+            request_queue_depth: default_queue_depth(),
+            request_queue_timeout_ms: default_queue_timeout_ms(),
+            standby_replicas: 0,
             allow_filesystem: false,                 // Disabled by default for security
             allow_network: false,                    // Disabled by default for security
             preopen_dirs: vec![],
+            env: CageEnv::default(),
+            database: None,
+            queue: None,
+            pubsub: None,
+            mail: None,
+            sessions: None,
+            determinism: None,
+            recorder: None,
+            guest_log: None,
+            crashes: None,
+            jit_profiler: JitProfiler::None,
+            profiler: None,
+            fuel: None,
+            usage: None,
+            compile_cache: None,
+            start: StartPolicy::Eager,
+            idle_timeout_secs: default_idle_timeout(),
         }
     }
 }
@@ -45,9 +208,29 @@ impl CageConfig {
             memory_limit_bytes: 256 * 1024 * 1024, // 256MB
             cpu_timeout_ms: 5000,                    // 5 seconds
             max_concurrent_requests: 50,
+            request_queue_depth: default_queue_depth(),
+            request_queue_timeout_ms: default_queue_timeout_ms(),
+            standby_replicas: 0,
             allow_filesystem: true,
             allow_network: true,
             preopen_dirs: vec![],
+            env: CageEnv::default(),
+            database: None,
+            queue: None,
+            pubsub: None,
+            mail: None,
+            sessions: None,
+            determinism: None,
+            recorder: None,
+            guest_log: None,
+            crashes: None,
+            jit_profiler: JitProfiler::None,
+            profiler: None,
+            fuel: None,
+            usage: None,
+            compile_cache: None,
+            start: StartPolicy::Eager,
+            idle_timeout_secs: default_idle_timeout(),
         }
     }
 
@@ -57,9 +240,29 @@ impl CageConfig {
             memory_limit_bytes: 64 * 1024 * 1024,  // 64MB
             cpu_timeout_ms: 500,                     // 500ms
             max_concurrent_requests: 200,
+            request_queue_depth: default_queue_depth(),
+            request_queue_timeout_ms: default_queue_timeout_ms(),
+            standby_replicas: 0,
             allow_filesystem: false,
             allow_network: false,
             preopen_dirs: vec![],
+            env: CageEnv::default(),
+            database: None,
+            queue: None,
+            pubsub: None,
+            mail: None,
+            sessions: None,
+            determinism: None,
+            recorder: None,
+            guest_log: None,
+            crashes: None,
+            jit_profiler: JitProfiler::None,
+            profiler: None,
+            fuel: None,
+            usage: None,
+            compile_cache: None,
+            start: StartPolicy::Eager,
+            idle_timeout_secs: default_idle_timeout(),
         }
     }
 
@@ -77,6 +280,10 @@ impl CageConfig {
             return Err("Max concurrent requests must be greater than 0".to_string());
         }
         
+        if self.start == StartPolicy::ScaleToZero && self.idle_timeout_secs == 0 {
+            return Err("Idle timeout must be greater than 0".to_string());
+        }
+        
         Ok(())
     }
 }
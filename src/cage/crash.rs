@@ -0,0 +1,311 @@
+// Crash Reports
+// What a guest was doing when it trapped: the trap, its backtrace, the call and its last output
+
+use base64::Engine;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use wasmtime::{Trap, WasmBacktrace};
+
+use crate::cage::determinism::RecordedCall;
+use crate::observability::guest_logs::SiteLog;
+use crate::observability::logs::LogFilter;
+
+/// `[crashes]`: crash reports kept for guests that trap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrashConfig {
+    pub enabled: bool,
+
+    /// Reports kept per site; the oldest are dropped first
+    pub max_reports: usize,
+
+    /// Lines of the Cage's recent output kept with each report
+    pub guest_log_lines: usize,
+
+    /// Longest request or task payload kept with a report; longer ones are cut
+    pub max_input_bytes: usize,
+}
+
+impl Default for CrashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_reports: 100,
+            guest_log_lines: 50,
+            max_input_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl CrashConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.enabled && self.max_reports == 0 {
+            anyhow::bail!("max_reports must be at least 1 when crash reports are enabled");
+        }
+        Ok(())
+    }
+}
+
+/// One frame of a guest backtrace, innermost first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashFrame {
+    pub func_index: u32,
+
+    /// From the module's name section, when it has one
+    pub func_name: Option<String>,
+
+    pub module: Option<String>,
+
+    /// Offset of the faulting instruction in the module
+    pub module_offset: Option<usize>,
+
+    /// Source locations from DWARF debug info, with `WASMTIME_BACKTRACE_DETAILS=1`
+    pub symbols: Vec<String>,
+}
+
+/// A guest trap and what led to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashReport {
+    pub id: u64,
+    pub site_id: String,
+    pub cage_id: u64,
+
+    /// Unix timestamp
+    pub crashed_at: i64,
+
+    /// Wasm trap code, unless the guest was stopped by a host call or exit
+    pub trap: Option<String>,
+
+    /// The full error chain
+    pub message: String,
+
+    /// Innermost guest function, by name when the module has a name section
+    pub function: Option<String>,
+
+    pub backtrace: Vec<CrashFrame>,
+
+    /// The call that crashed, with its request or payload
+    pub call: RecordedCall,
+
+    /// Whether the request or payload was cut to `max_input_bytes`
+    pub input_truncated: bool,
+
+    /// The Cage's last lines of output before the crash
+    pub guest_logs: Vec<String>,
+}
+
+/// Whether a call failed inside the guest, rather than before it ran
+/// Exiting with status 0 is a normal end.
+pub fn is_crash(error: &anyhow::Error) -> bool {
+    if let Some(exit) = error.downcast_ref::<wasi_common::I32Exit>() {
+        return exit.0 != 0;
+    }
+    error.downcast_ref::<Trap>().is_some() || error.downcast_ref::<WasmBacktrace>().is_some()
+}
+
+/// The most recent crashes of one site's guests
+pub struct CrashReporter {
+    site_id: String,
+    config: CrashConfig,
+    guest_log: Option<Arc<SiteLog>>,
+    next_id: AtomicU64,
+    reports: Mutex<VecDeque<CrashReport>>,
+}
+
+impl CrashReporter {
+    pub fn new(site_id: &str, config: CrashConfig, guest_log: Option<Arc<SiteLog>>) -> Self {
+        Self {
+            site_id: site_id.to_string(),
+            config,
+            guest_log,
+            next_id: AtomicU64::new(1),
+            reports: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Keep a report of the call's crash, returning its ID
+    pub fn report(&self, cage_id: u64, call: RecordedCall, error: &anyhow::Error) -> u64 {
+        let backtrace: Vec<CrashFrame> = error.downcast_ref::<WasmBacktrace>()
+            .map(|backtrace| backtrace.frames().iter().map(|frame| CrashFrame {
+                func_index: frame.func_index(),
+                func_name: frame.func_name().map(str::to_string),
+                module: frame.module().name().map(str::to_string),
+                module_offset: frame.module_offset(),
+                symbols: frame.symbols().iter().map(|symbol| {
+                    let name = symbol.name().unwrap_or("<unknown>");
+                    match (symbol.file(), symbol.line()) {
+                        (Some(file), Some(line)) => format!("{} at {}:{}", name, file, line),
+                        _ => name.to_string(),
+                    }
+                }).collect(),
+            }).collect())
+            .unwrap_or_default();
+        let function = backtrace.first().map(|frame| match &frame.func_name {
+            Some(name) => name.clone(),
+            None => format!("<wasm function {}>", frame.func_index),
+        });
+        let (call, input_truncated) = truncate(call, self.config.max_input_bytes);
+
+        let report = CrashReport {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            site_id: self.site_id.clone(),
+            cage_id,
+            crashed_at: chrono::Utc::now().timestamp(),
+            trap: error.downcast_ref::<Trap>().map(|trap| trap.to_string()),
+            message: format!("{:#}", error),
+            function,
+            backtrace,
+            call,
+            input_truncated,
+            guest_logs: self.guest_logs(cage_id),
+        };
+        let id = report.id;
+
+        let mut reports = self.reports.lock();
+        if reports.len() >= self.config.max_reports {
+            reports.pop_front();
+        }
+        reports.push_back(report);
+        id
+    }
+
+    /// Reports, oldest first
+    pub fn list(&self) -> Vec<CrashReport> {
+        self.reports.lock().iter().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<CrashReport> {
+        self.reports.lock().iter().find(|report| report.id == id).cloned()
+    }
+
+    /// Crashes reported since the node started, including dropped reports
+    pub fn total(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed) - 1
+    }
+
+    /// The Cage's last lines of output, as `stream: line`
+    fn guest_logs(&self, cage_id: u64) -> Vec<String> {
+        let Some(guest_log) = &self.guest_log else {
+            return Vec::new();
+        };
+        let prefix = format!("cage-{}::", cage_id);
+        let mut lines: Vec<String> = guest_log.buffer().query(&LogFilter::default(), usize::MAX).into_iter()
+            .rev()
+            .filter_map(|record| {
+                let stream = record.target.strip_prefix(&prefix)?;
+                Some(format!("{}: {}", stream, record.message))
+            })
+            .take(self.config.guest_log_lines)
+            .collect();
+        lines.reverse();
+        lines
+    }
+}
+
+impl std::fmt::Debug for CrashReporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CrashReporter").field("site_id", &self.site_id).finish()
+    }
+}
+
+/// Cut the call's request or payload to `max_bytes`
+fn truncate(call: RecordedCall, max_bytes: usize) -> (RecordedCall, bool) {
+    match call {
+        RecordedCall::Task { export, mut payload } if payload.len() > max_bytes => {
+            let mut end = max_bytes;
+            while !payload.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.truncate(end);
+            (RecordedCall::Task { export, payload }, true)
+        }
+        RecordedCall::Stream { export, request } => {
+            let engine = &base64::engine::general_purpose::STANDARD;
+            match engine.decode(&request) {
+                Ok(bytes) if bytes.len() > max_bytes => (RecordedCall::stream(&export, &bytes[..max_bytes]), true),
+                _ => (RecordedCall::Stream { export, request }, false),
+            }
+        }
+        call => (call, false),
+    }
+}
+
+/// Crash reporters of every site
+#[derive(Default)]
+pub struct CrashStore {
+    reporters: DashMap<String, Arc<CrashReporter>>,
+}
+
+impl CrashStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The site's reporter, created on first use so reports survive redeploys
+    pub fn reporter(&self, site_id: &str, config: &CrashConfig, guest_log: Option<Arc<SiteLog>>) -> Arc<CrashReporter> {
+        self.reporters.entry(site_id.to_string())
+            .or_insert_with(|| Arc::new(CrashReporter::new(site_id, config.clone(), guest_log)))
+            .clone()
+    }
+
+    pub fn get(&self, site_id: &str) -> Option<Arc<CrashReporter>> {
+        self.reporters.get(site_id).map(|reporter| reporter.clone())
+    }
+
+    /// Crashes per site since the node started
+    pub fn totals(&self) -> Vec<(String, u64)> {
+        let mut totals: Vec<_> = self.reporters.iter()
+            .map(|reporter| (reporter.key().clone(), reporter.total()))
+            .collect();
+        totals.sort();
+        totals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::guest_logs::{GuestLogConfig, GuestLogs, GuestStream};
+    use std::io::Write;
+    use wasmtime::{Engine, Instance, Module, Store};
+
+    #[test]
+    fn test_report_of_trap() {
+        let wat = r#"
+            (module $app
+                (func $divide (param i32) (result i32)
+                    (i32.div_u (i32.const 1) (local.get 0)))
+                (func (export "handle") (param i32) (result i32)
+                    (call $divide (local.get 0))))
+        "#;
+        let engine = Engine::default();
+        let module = Module::new(&engine, wat::parse_str(wat).unwrap()).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[]).unwrap();
+        let handle = instance.get_typed_func::<i32, i32>(&mut store, "handle").unwrap();
+        let error = handle.call(&mut store, 0).unwrap_err();
+        assert!(is_crash(&error));
+        assert!(!is_crash(&anyhow::anyhow!("Module has no `handle` export")));
+
+        let logs = GuestLogs::new(GuestLogConfig { dir: String::new(), ..Default::default() });
+        logs.site("blog").writer(2, GuestStream::Stderr).write_all(b"dividing\n").unwrap();
+        logs.site("blog").writer(3, GuestStream::Stdout).write_all(b"other cage\n").unwrap();
+        let config = CrashConfig { max_input_bytes: 4, ..Default::default() };
+        let reporter = CrashReporter::new("blog", config, Some(logs.site("blog")));
+        let id = reporter.report(2, RecordedCall::stream("handle", b"GET / HTTP/1.1"), &error);
+
+        let report = reporter.get(id).unwrap();
+        assert_eq!(report.trap.as_deref(), Some(Trap::IntegerDivisionByZero.to_string().as_str()));
+        assert_eq!(report.function.as_deref(), Some("divide"));
+        assert_eq!(report.backtrace.len(), 2);
+        assert_eq!(report.backtrace[0].module.as_deref(), Some("app"));
+        assert_eq!(report.call.request().unwrap(), Some(b"GET ".to_vec()));
+        assert!(report.input_truncated);
+        assert_eq!(report.guest_logs, vec!["stderr: dividing"]);
+        assert_eq!(reporter.total(), 1);
+    }
+}
@@ -0,0 +1,188 @@
+// Guest Database Host Functions
+// Exposes a site's database to its guests as the `pear_db` import module
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::debug;
+use wasmtime::{Caller, Linker};
+use wasmtime_wasi::WasiCtx;
+
+use super::guest_memory::{copy_out, read};
+use crate::storage::database::GuestDatabase;
+
+/// Import module guests link against
+pub const MODULE: &str = "pear_db";
+
+/// Statement and result-reading calls a guest can make on its site's database
+pub const FUNCTIONS: &[&str] = &["exec", "query", "read_result", "read_error"];
+
+/// Longest SQL text or parameter list a guest may pass
+const MAX_INPUT_BYTES: usize = 1024 * 1024;
+
+/// Output waiting for the guest to copy it out
+#[derive(Default)]
+struct Pending {
+    result: Vec<u8>,
+    error: Vec<u8>,
+}
+
+/// Register the `pear_db` imports:
+/// - `exec(sql_ptr, sql_len, params_ptr, params_len) -> i64`: changed rows, or -1 on error
+/// - `query(sql_ptr, sql_len, params_ptr, params_len) -> i64`: length of the JSON result, or -1
+/// - `read_result(ptr, len) -> i32`: move up to `len` bytes of the result into guest memory
+/// - `read_error(ptr, len) -> i32`: copy the last error message into guest memory
+///
+/// Parameters are a JSON array bound to the statement's placeholders; `params_len` 0 means none.
+/// Query results are `{"columns": [...], "rows": [[...], ...]}`.
+pub fn add_to_linker(linker: &mut Linker<WasiCtx>, db: GuestDatabase) -> Result<()> {
+    let pending = Arc::new(Mutex::new(Pending::default()));
+
+    let (exec_db, exec_pending) = (db.clone(), pending.clone());
+    linker.func_wrap(
+        MODULE,
+        "exec",
+        move |mut caller: Caller<'_, WasiCtx>, sql_ptr: i32, sql_len: i32, params_ptr: i32, params_len: i32| -> Result<i64> {
+            let (sql, params) = read_statement(&mut caller, sql_ptr, sql_len, params_ptr, params_len)?;
+            let outcome = params.and_then(|params| exec_db.exec(&sql, &params));
+            Ok(finish(&exec_pending, outcome.map(|changed| (changed as i64, Vec::new()))))
+        },
+    )?;
+
+    let (query_db, query_pending) = (db, pending.clone());
+    linker.func_wrap(
+        MODULE,
+        "query",
+        move |mut caller: Caller<'_, WasiCtx>, sql_ptr: i32, sql_len: i32, params_ptr: i32, params_len: i32| -> Result<i64> {
+            let (sql, params) = read_statement(&mut caller, sql_ptr, sql_len, params_ptr, params_len)?;
+            let outcome = params
+                .and_then(|params| query_db.query(&sql, &params))
+                .and_then(|result| Ok(serde_json::to_vec(&result)?))
+                .map(|json| (json.len() as i64, json));
+            Ok(finish(&query_pending, outcome))
+        },
+    )?;
+
+    let result_pending = pending.clone();
+    linker.func_wrap(
+        MODULE,
+        "read_result",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let mut pending = result_pending.lock();
+            let count = copy_out(&mut caller, ptr, len, &pending.result)?;
+            pending.result.drain(..count as usize);
+            Ok(count)
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "read_error",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let pending = pending.lock();
+            copy_out(&mut caller, ptr, len, &pending.error)
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Store a call's output for the guest, returning the value handed back to it
+fn finish(pending: &Mutex<Pending>, outcome: Result<(i64, Vec<u8>)>) -> i64 {
+    let mut pending = pending.lock();
+    match outcome {
+        Ok((value, result)) => {
+            pending.result = result;
+            pending.error.clear();
+            value
+        }
+        Err(e) => {
+            debug!(error = %e, "Guest database call failed");
+            pending.result.clear();
+            pending.error = format!("{:#}", e).into_bytes();
+            -1
+        }
+    }
+}
+
+/// Read SQL and its parameters out of guest memory
+/// Bad pointers trap the guest; malformed parameters are reported as a call error
+fn read_statement(
+    caller: &mut Caller<'_, WasiCtx>,
+    sql_ptr: i32,
+    sql_len: i32,
+    params_ptr: i32,
+    params_len: i32,
+) -> Result<(String, Result<Vec<serde_json::Value>>)> {
+    let sql = String::from_utf8(read(caller, sql_ptr, sql_len, MAX_INPUT_BYTES)?)?;
+
+    let params = if params_len == 0 {
+        Ok(Vec::new())
+    } else {
+        let bytes = read(caller, params_ptr, params_len, MAX_INPUT_BYTES)?;
+        serde_json::from_slice(&bytes).map_err(|e| anyhow::anyhow!("Parameters must be a JSON array: {}", e))
+    };
+
+    Ok((sql, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::database::{DatabaseConfig, DatabaseManager};
+    use wasmtime::{Engine, Module, Store};
+    use wasmtime_wasi::WasiCtxBuilder;
+
+    const GUEST: &str = r#"
+        (module
+          (import "pear_db" "exec" (func $exec (param i32 i32 i32 i32) (result i64)))
+          (import "pear_db" "query" (func $query (param i32 i32 i32 i32) (result i64)))
+          (import "pear_db" "read_result" (func $read_result (param i32 i32) (result i32)))
+          (import "pear_db" "read_error" (func $read_error (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "CREATE TABLE kv (k TEXT, v INTEGER)")
+          (data (i32.const 64) "INSERT INTO kv VALUES (?1, ?2)")
+          (data (i32.const 128) "[\"answer\", 42]")
+          (data (i32.const 192) "SELECT v FROM kv WHERE k = ?1")
+          (data (i32.const 256) "[\"answer\"]")
+          (data (i32.const 320) "NOT SQL")
+          (func (export "setup") (result i64)
+            (drop (call $exec (i32.const 0) (i32.const 35) (i32.const 0) (i32.const 0)))
+            (call $exec (i32.const 64) (i32.const 30) (i32.const 128) (i32.const 14)))
+          (func (export "lookup") (result i32)
+            (drop (call $query (i32.const 192) (i32.const 29) (i32.const 256) (i32.const 10)))
+            (call $read_result (i32.const 1024) (i32.const 1024)))
+          (func (export "broken") (result i64)
+            (call $exec (i32.const 320) (i32.const 7) (i32.const 0) (i32.const 0)))
+          (func (export "error") (result i32)
+            (call $read_error (i32.const 2048) (i32.const 256))))
+    "#;
+
+    #[test]
+    fn test_guest_exec_and_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(DatabaseManager::new(DatabaseConfig::default()).unwrap());
+        let db = manager.open("acme", "blog", dir.path()).unwrap();
+
+        let engine = Engine::default();
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker, db).unwrap();
+        let module = Module::new(&engine, wat::parse_str(GUEST).unwrap()).unwrap();
+        let mut store = Store::new(&engine, WasiCtxBuilder::new().build());
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+
+        let setup = instance.get_typed_func::<(), i64>(&mut store, "setup").unwrap();
+        assert_eq!(setup.call(&mut store, ()).unwrap(), 1);
+
+        let lookup = instance.get_typed_func::<(), i32>(&mut store, "lookup").unwrap();
+        let len = lookup.call(&mut store, ()).unwrap() as usize;
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&memory.data(&store)[1024..1024 + len]).unwrap();
+        assert_eq!(json["rows"], serde_json::json!([[42]]));
+
+        let broken = instance.get_typed_func::<(), i64>(&mut store, "broken").unwrap();
+        assert_eq!(broken.call(&mut store, ()).unwrap(), -1);
+        let error = instance.get_typed_func::<(), i32>(&mut store, "error").unwrap();
+        assert!(error.call(&mut store, ()).unwrap() > 0);
+    }
+}
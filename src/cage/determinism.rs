@@ -0,0 +1,317 @@
+// Deterministic Cages
+// Virtual clocks and seeded randomness for reproducible guests, and recordings of calls for replay
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use wasi_common::clocks::{WasiClocks, WasiMonotonicClock, WasiSystemClock};
+use wasi_common::table::Table;
+use wasmtime_wasi::WasiCtx;
+
+/// `[determinism]`: sites whose guests see fixed time and randomness
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeterminismConfig {
+    /// Settings by site ID
+    pub sites: HashMap<String, SiteDeterminism>,
+
+    /// Calls kept per recording site; the oldest are dropped first
+    pub max_recordings: usize,
+}
+
+impl Default for DeterminismConfig {
+    fn default() -> Self {
+        Self {
+            sites: HashMap::new(),
+            max_recordings: 1000,
+        }
+    }
+}
+
+impl DeterminismConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (site_id, site) in &self.sites {
+            if site.determinism.tick_us == 0 {
+                bail!("tick_us of site {} must be at least 1, or guests waiting on the clock never finish", site_id);
+            }
+        }
+        if self.max_recordings == 0 && self.sites.values().any(|site| site.record) {
+            bail!("max_recordings must be at least 1 when a site records calls");
+        }
+        Ok(())
+    }
+}
+
+/// One site's fixed time and randomness, and whether its calls are recorded
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteDeterminism {
+    #[serde(flatten)]
+    pub determinism: Determinism,
+
+    /// Record every call to the site's guests so it can be replayed
+    #[serde(default)]
+    pub record: bool,
+}
+
+/// What a guest sees instead of the host's clocks and entropy
+/// Every instance starts from the same time and seed, so the same calls give the same results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Determinism {
+    /// Seed of the guest's random number generator
+    pub seed: u64,
+
+    /// Wall clock time each instance starts at, in milliseconds since the Unix epoch
+    pub start_unix_ms: u64,
+
+    /// How far the clocks move every time the guest reads them, in microseconds
+    pub tick_us: u64,
+}
+
+impl Default for Determinism {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            start_unix_ms: 1_704_067_200_000, // 2024-01-01T00:00:00Z
+            tick_us: 1000,
+        }
+    }
+}
+
+impl Determinism {
+    /// A WASI context with virtual clocks and a seeded generator, and nothing else set up
+    pub fn wasi_context(&self) -> WasiCtx {
+        let clocks = WasiClocks::new()
+            .with_system(VirtualClock::new(self))
+            .with_monotonic(VirtualClock::new(self));
+        WasiCtx::new(
+            Box::new(StdRng::seed_from_u64(self.seed)),
+            clocks,
+            wasmtime_wasi::sync::sched_ctx(),
+            Table::new(),
+        )
+    }
+}
+
+/// Clock that moves by a fixed tick on every reading, whatever the real time
+struct VirtualClock {
+    start: SystemTime,
+    origin: Instant,
+    tick_us: u64,
+    readings: AtomicU64,
+}
+
+impl VirtualClock {
+    fn new(determinism: &Determinism) -> Self {
+        Self {
+            start: UNIX_EPOCH + Duration::from_millis(determinism.start_unix_ms),
+            origin: Instant::now(),
+            tick_us: determinism.tick_us,
+            readings: AtomicU64::new(0),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        let readings = self.readings.fetch_add(1, Ordering::Relaxed);
+        Duration::from_micros(readings.saturating_mul(self.tick_us))
+    }
+}
+
+impl WasiSystemClock for VirtualClock {
+    fn resolution(&self) -> cap_std::time::Duration {
+        Duration::from_micros(self.tick_us)
+    }
+
+    fn now(&self, _precision: cap_std::time::Duration) -> cap_std::time::SystemTime {
+        cap_std::time::SystemTime::from_std(self.start + self.elapsed())
+    }
+}
+
+impl WasiMonotonicClock for VirtualClock {
+    fn resolution(&self) -> cap_std::time::Duration {
+        Duration::from_micros(self.tick_us)
+    }
+
+    fn now(&self, _precision: cap_std::time::Duration) -> cap_std::time::Instant {
+        cap_std::time::Instant::from_std(self.origin + self.elapsed())
+    }
+}
+
+/// A call made to a guest, with the input it was given
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordedCall {
+    /// A scheduled job's `() -> ()` export
+    Export { export: String },
+
+    /// A queued task and its payload
+    Task { export: String, payload: String },
+
+    /// A streamed response; `request` is the serialized request, base64 encoded
+    Stream { export: String, request: String },
+}
+
+impl RecordedCall {
+    pub fn stream(export: &str, request: &[u8]) -> Self {
+        RecordedCall::Stream {
+            export: export.to_string(),
+            request: base64::engine::general_purpose::STANDARD.encode(request),
+        }
+    }
+
+    /// The serialized request of a streamed response
+    pub fn request(&self) -> Result<Option<Vec<u8>>> {
+        match self {
+            RecordedCall::Stream { request, .. } => Ok(Some(
+                base64::engine::general_purpose::STANDARD.decode(request).context("Recorded request is not valid base64")?,
+            )),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A recorded call and how it ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub id: u64,
+    pub site_id: String,
+    pub cage_id: u64,
+
+    /// Unix timestamp
+    pub recorded_at: i64,
+
+    pub determinism: Determinism,
+    pub call: RecordedCall,
+
+    /// What the export returned, for exports that return a code
+    pub result: Option<i32>,
+
+    /// Why the call failed, if it did
+    pub error: Option<String>,
+}
+
+/// The most recent calls to one site's guests
+pub struct Recorder {
+    site_id: String,
+    determinism: Determinism,
+    capacity: usize,
+    next_id: AtomicU64,
+    recordings: Mutex<VecDeque<Recording>>,
+}
+
+impl Recorder {
+    pub fn new(site_id: &str, determinism: Determinism, capacity: usize) -> Self {
+        Self {
+            site_id: site_id.to_string(),
+            determinism,
+            capacity,
+            next_id: AtomicU64::new(1),
+            recordings: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn record(&self, cage_id: u64, call: RecordedCall, outcome: Result<Option<i32>, &anyhow::Error>) {
+        let (result, error) = match outcome {
+            Ok(result) => (result, None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        let recording = Recording {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            site_id: self.site_id.clone(),
+            cage_id,
+            recorded_at: chrono::Utc::now().timestamp(),
+            determinism: self.determinism,
+            call,
+            result,
+            error,
+        };
+
+        let mut recordings = self.recordings.lock();
+        if recordings.len() >= self.capacity {
+            recordings.pop_front();
+        }
+        recordings.push_back(recording);
+    }
+
+    /// Recordings, oldest first
+    pub fn list(&self) -> Vec<Recording> {
+        self.recordings.lock().iter().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Recording> {
+        self.recordings.lock().iter().find(|recording| recording.id == id).cloned()
+    }
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder").field("site_id", &self.site_id).finish()
+    }
+}
+
+/// Recorders of every recording site
+#[derive(Default)]
+pub struct RecordingStore {
+    recorders: DashMap<String, Arc<Recorder>>,
+}
+
+impl RecordingStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The site's recorder, replacing one with different settings so recordings always match them
+    pub fn recorder(&self, site_id: &str, determinism: Determinism, capacity: usize) -> Arc<Recorder> {
+        let mut entry = self.recorders.entry(site_id.to_string())
+            .or_insert_with(|| Arc::new(Recorder::new(site_id, determinism, capacity)));
+        if entry.determinism != determinism || entry.capacity != capacity {
+            *entry = Arc::new(Recorder::new(site_id, determinism, capacity));
+        }
+        entry.clone()
+    }
+
+    pub fn get(&self, site_id: &str) -> Option<Arc<Recorder>> {
+        self.recorders.get(site_id).map(|recorder| recorder.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock_ticks() {
+        let determinism = Determinism { tick_us: 500, ..Default::default() };
+        let clock = VirtualClock::new(&determinism);
+        let resolution = WasiSystemClock::resolution(&clock);
+
+        let first = WasiSystemClock::now(&clock, resolution).into_std();
+        let second = WasiSystemClock::now(&clock, resolution).into_std();
+        assert_eq!(first.duration_since(UNIX_EPOCH).unwrap(), Duration::from_millis(determinism.start_unix_ms));
+        assert_eq!(second.duration_since(first).unwrap(), Duration::from_micros(500));
+    }
+
+    #[test]
+    fn test_recorder_keeps_latest() {
+        let recorder = Recorder::new("blog", Determinism::default(), 2);
+        for i in 0..3 {
+            let call = RecordedCall::Task { export: "handle_job".to_string(), payload: i.to_string() };
+            recorder.record(1, call, Ok(Some(0)));
+        }
+        recorder.record(1, RecordedCall::stream("handle_stream", b"GET /"), Err(&anyhow::anyhow!("trap")));
+
+        let recordings = recorder.list();
+        assert_eq!(recordings.iter().map(|r| r.id).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(recordings[1].error.as_deref(), Some("trap"));
+        assert_eq!(recorder.get(4).unwrap().call.request().unwrap(), Some(b"GET /".to_vec()));
+        assert!(recorder.get(1).is_none());
+    }
+}
@@ -0,0 +1,157 @@
+// Wasm Executor
+// Runs guest code off the async workers, in a bounded number of slots with a bounded wait queue
+
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Calls allowed to wait for a slot unless configured otherwise
+pub const DEFAULT_QUEUE_DEPTH: usize = 1024;
+
+/// Every slot is busy and the wait queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Saturated;
+
+impl std::fmt::Display for Saturated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Wasm executor is saturated")
+    }
+}
+
+impl std::error::Error for Saturated {}
+
+/// Executor load for metrics
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ExecutorStats {
+    pub threads: usize,
+    pub queue_depth: usize,
+    pub running: usize,
+    pub queued: usize,
+    pub completed: u64,
+    pub rejected: u64,
+}
+
+/// Runs guest calls on the blocking pool, at most `threads` at a time
+/// Calls beyond that wait for a slot, up to `queue_depth` of them; any more are rejected.
+pub struct WasmExecutor {
+    slots: Arc<Semaphore>,
+    threads: usize,
+    queue_depth: usize,
+    queued: AtomicUsize,
+    completed: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl WasmExecutor {
+    pub fn new(threads: usize, queue_depth: usize) -> Self {
+        let threads = threads.max(1);
+        Self {
+            slots: Arc::new(Semaphore::new(threads)),
+            threads,
+            queue_depth,
+            queued: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Run `call` once a slot is free, failing with `Saturated` when the queue is full
+    /// A call keeps its slot until it returns, even if the caller stops waiting for it.
+    pub async fn run<F, R>(&self, call: F) -> Result<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let permit = match self.slots.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let _waiting = self.enqueue()?;
+                self.slots.clone().acquire_owned().await?
+            }
+        };
+
+        let result = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            call()
+        }).await;
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        Ok(result?)
+    }
+
+    fn enqueue(&self) -> Result<Waiting<'_>> {
+        if self.queued.fetch_add(1, Ordering::AcqRel) >= self.queue_depth {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(Saturated.into());
+        }
+        Ok(Waiting(&self.queued))
+    }
+
+    pub fn stats(&self) -> ExecutorStats {
+        ExecutorStats {
+            threads: self.threads,
+            queue_depth: self.queue_depth,
+            running: self.threads - self.slots.available_permits(),
+            queued: self.queued.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for WasmExecutor {
+    /// One slot per CPU core
+    fn default() -> Self {
+        Self::new(num_cpus::get(), DEFAULT_QUEUE_DEPTH)
+    }
+}
+
+/// A call counted in the wait queue until it gets a slot or is abandoned
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_rejects_when_queue_is_full() {
+        let executor = Arc::new(WasmExecutor::new(1, 1));
+        let (release, blocked) = mpsc::channel::<()>();
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.run(move || blocked.recv().is_ok()).await }
+        });
+        while executor.stats().running == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let waiting = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.run(|| 7).await }
+        });
+        while executor.stats().queued == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let rejected = executor.run(|| 0).await.unwrap_err();
+        assert_eq!(rejected.downcast_ref::<Saturated>(), Some(&Saturated));
+
+        release.send(()).unwrap();
+        assert!(running.await.unwrap().unwrap());
+        assert_eq!(waiting.await.unwrap().unwrap(), 7);
+
+        let stats = executor.stats();
+        assert_eq!((stats.running, stats.queued, stats.completed, stats.rejected), (0, 0, 2, 1));
+    }
+}
@@ -0,0 +1,39 @@
+// Guest Memory Access
+// Pointer and length arguments of host functions, resolved against the guest's exported memory
+
+use anyhow::{Result, bail};
+use wasmtime::{Caller, Extern, Memory};
+use wasmtime_wasi::WasiCtx;
+
+/// The guest's linear memory
+pub(super) fn memory(caller: &mut Caller<'_, WasiCtx>) -> Result<Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => bail!("Guest does not export its memory"),
+    }
+}
+
+/// Copy `len` bytes at `ptr` out of guest memory, refusing more than `max`
+/// Bad pointers trap the guest.
+pub(super) fn read(caller: &mut Caller<'_, WasiCtx>, ptr: i32, len: i32, max: usize) -> Result<Vec<u8>> {
+    let len = len as u32 as usize;
+    if len > max {
+        bail!("Argument of {} bytes is over the {} byte limit", len, max);
+    }
+    let mut buf = vec![0u8; len];
+    memory(caller)?.read(&*caller, ptr as u32 as usize, &mut buf)?;
+    Ok(buf)
+}
+
+/// Borrow `len` bytes at `ptr` in place, or None when they run past the end of memory
+pub(super) fn slice<'a>(caller: &'a Caller<'_, WasiCtx>, memory: Memory, ptr: i32, len: i32) -> Option<&'a [u8]> {
+    let start = ptr as u32 as usize;
+    memory.data(caller).get(start..start.checked_add(len as u32 as usize)?)
+}
+
+/// Copy up to `len` bytes of `data` to guest memory at `ptr`, returning the count
+pub(super) fn copy_out(caller: &mut Caller<'_, WasiCtx>, ptr: i32, len: i32, data: &[u8]) -> Result<i32> {
+    let count = (len.max(0) as usize).min(data.len());
+    memory(caller)?.write(&mut *caller, ptr as u32 as usize, &data[..count])?;
+    Ok(count as i32)
+}
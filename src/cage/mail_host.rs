@@ -0,0 +1,107 @@
+// Mail Host Functions
+// Lets guests send email through the tenant's relay as the `pear_mail` import module
+
+use anyhow::{Result, bail};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::debug;
+use wasmtime::{Caller, Linker};
+use wasmtime_wasi::WasiCtx;
+
+use super::guest_memory::{copy_out, memory, slice};
+use crate::mail::GuestMailer;
+
+/// Import module guests link against
+pub const MODULE: &str = "pear_mail";
+
+/// Calls for sending email and reading why the last send was refused
+pub const FUNCTIONS: &[&str] = &["send", "read_error"];
+
+/// Register the `pear_mail` imports:
+/// - `send(ptr, len) -> i64`: queue a JSON email, returning its id, or -1 on error
+/// - `read_error(ptr, len) -> i32`: copy the last error message into guest memory
+///
+/// Emails are `{"to": [...], "subject": ..., "text": ..., "html"?, "from"?, "reply_to"?}`.
+/// Delivery happens in the background; outcomes show up in the tenant's delivery log.
+pub fn add_to_linker(linker: &mut Linker<WasiCtx>, mailer: GuestMailer) -> Result<()> {
+    let last_error = Arc::new(Mutex::new(Vec::new()));
+
+    let send_error = last_error.clone();
+    linker.func_wrap(
+        MODULE,
+        "send",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i64> {
+            let memory = memory(&mut caller)?;
+            let Some(email) = slice(&caller, memory, ptr, len) else {
+                bail!("Email is outside guest memory");
+            };
+
+            match mailer.send(email) {
+                Ok(id) => {
+                    send_error.lock().clear();
+                    Ok(id as i64)
+                }
+                Err(e) => {
+                    debug!(site_id = %mailer.site_id(), error = %e, "Guest email rejected");
+                    *send_error.lock() = format!("{:#}", e).into_bytes();
+                    Ok(-1)
+                }
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "read_error",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let error = last_error.lock().clone();
+            copy_out(&mut caller, ptr, len, &error)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::{MailConfig, MailRelay};
+    use wasmtime::{Engine, Module, Store};
+    use wasmtime_wasi::WasiCtxBuilder;
+
+    const GUEST: &str = r#"
+        (module
+          (import "pear_mail" "send" (func $send (param i32 i32) (result i64)))
+          (import "pear_mail" "read_error" (func $read_error (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"to\": [\"ann@example.com\"], \"subject\": \"Hi\", \"text\": \"Welcome\"}")
+          (data (i32.const 128) "{\"to\": []}")
+          (func (export "welcome") (result i64)
+            (call $send (i32.const 0) (i32.const 63)))
+          (func (export "broken") (result i32)
+            (drop (call $send (i32.const 128) (i32.const 10)))
+            (call $read_error (i32.const 1024) (i32.const 256))))
+    "#;
+
+    #[test]
+    fn test_guest_send() {
+        let config = MailConfig { enabled: true, state_path: String::new(), ..Default::default() };
+        let relay = Arc::new(MailRelay::new(config).unwrap());
+
+        let engine = Engine::default();
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker, relay.guest("acme", "blog")).unwrap();
+        let module = Module::new(&engine, wat::parse_str(GUEST).unwrap()).unwrap();
+        let mut store = Store::new(&engine, WasiCtxBuilder::new().build());
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+
+        let welcome = instance.get_typed_func::<(), i64>(&mut store, "welcome").unwrap();
+        assert_eq!(welcome.call(&mut store, ()).unwrap(), 1);
+        assert_eq!(relay.status("acme").sent_today, 1);
+
+        let broken = instance.get_typed_func::<(), i32>(&mut store, "broken").unwrap();
+        let len = broken.call(&mut store, ()).unwrap() as usize;
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        assert!(std::str::from_utf8(&memory.data(&store)[1024..1024 + len]).unwrap().contains("Invalid email JSON"));
+    }
+}
@@ -1,15 +1,36 @@
 // Cage Architecture Module
 // WebAssembly-based execution environments with strict isolation and resource limits
 
+pub mod compile_cache;
 pub mod config;
+pub mod crash;
+pub mod db_host;
+pub mod determinism;
+pub mod executor;
+mod guest_memory;
+pub mod mail_host;
+pub mod partition;
 pub mod pool;
+pub mod profiling;
+pub mod pubsub_host;
+pub mod queue_host;
+pub mod request_queue;
+pub mod session_host;
+pub mod stream_host;
 
 use config::CageConfig;
+use determinism::{Determinism, RecordedCall, Recording};
+use crate::observability::guest_logs::GuestStream;
+use crate::observability::histogram::{HistogramSnapshot, LatencyHistogram};
+use crate::router::stream::StreamSink;
 use anyhow::{Result, Context};
+use bytes::BytesMut;
+use std::fmt::Write;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, AtomicBool, Ordering};
-use tokio::sync::RwLock;
+use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
 use tracing::{info, debug, warn, error, instrument};
+use wasi_common::pipe::WritePipe;
 use wasmtime::*;
 use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
 
@@ -74,11 +95,23 @@ pub struct Cage {
     /// Active request counter for load balancing
     active_requests: Arc<AtomicU64>,
     
+    /// Requests allowed to run at once, up to `max_concurrent_requests`
+    slots: Arc<Semaphore>,
+    
+    /// Time spent executing requests
+    latency: LatencyHistogram,
+    
     /// Health status
     healthy: Arc<AtomicBool>,
     
     /// Last health check timestamp
     last_health_check: Arc<RwLock<std::time::Instant>>,
+    
+    /// Share of the tenant's resource partition, released when the Cage is dropped
+    reservation: Option<partition::Reservation>,
+    
+    /// Keeps the site's profiler sampling this Cage's guests
+    _profiling: Option<profiling::ProfilerGuard>,
 }
 
 impl Cage {
@@ -93,14 +126,14 @@ impl Cage {
     ) -> Result<Self> {
         info!(cage_id = id, cage_name = %name, "Creating new Cage");
 
-        // Compile the WebAssembly module
-        let module = Module::new(&engine, wasm_bytes)
-            .context("Failed to compile WebAssembly module")?;
+        // Compile the WebAssembly module, or load the code compiled before a restart
+        let module = match &config.compile_cache {
+            Some(cache) => cache.module(&engine, wasm_bytes)?,
+            None => Module::new(&engine, wasm_bytes).context("Failed to compile WebAssembly module")?,
+        };
 
-        // Create WASI context with configured permissions
-        let wasi = WasiCtxBuilder::new()
-            .inherit_stdio()
-            .build();
+        // Create WASI context with configured permissions and site environment
+        let wasi = wasi_context(&config, id, config.determinism.as_ref())?;
 
         // Create store with resource limits
         let mut store = Store::new(&engine, wasi);
@@ -109,6 +142,14 @@ impl Cage {
         store.limiter(|_| ResourceLimiterImpl {
             memory_limit: config.memory_limit_bytes,
         });
+        if let Some(fuel) = config.fuel {
+            store.set_fuel(fuel)?;
+        }
+        let profiling = config.profiler.as_ref().map(|profiler| {
+            profiler.watch(&mut store);
+            profiler.attach(&engine)
+        });
+        let max_concurrent_requests = config.max_concurrent_requests;
 
         let cage = Self {
             id,
@@ -120,13 +161,23 @@ impl Cage {
             config,
             request_count: Arc::new(AtomicU64::new(0)),
             active_requests: Arc::new(AtomicU64::new(0)),
+            slots: Arc::new(Semaphore::new(max_concurrent_requests)),
+            latency: LatencyHistogram::new(),
             healthy: Arc::new(AtomicBool::new(true)),
             last_health_check: Arc::new(RwLock::new(std::time::Instant::now())),
+            reservation: None,
+            _profiling: profiling,
         };
 
         Ok(cage)
     }
 
+    /// Hold a tenant partition reservation for the lifetime of this Cage
+    pub fn with_reservation(mut self, reservation: partition::Reservation) -> Self {
+        self.reservation = Some(reservation);
+        self
+    }
+
     /// Initialize the Cage and transition to Running state
     #[instrument(skip(self))]
     pub async fn initialize(&self) -> Result<()> {
@@ -135,9 +186,7 @@ impl Cage {
         // Instantiate the module
         let mut store = self.store.write().await;
         
-        // Add WASI to the linker
-        let mut linker = Linker::new(&self.engine);
-        wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
+        let linker = self.linker(None, None)?;
 
         // Instantiate and get the instance
         let _instance = linker.instantiate(&mut *store, &self.module)
@@ -153,9 +202,214 @@ impl Cage {
         Ok(())
     }
 
+    /// Linker with WASI and the host modules this Cage is configured for
+    /// `task` is the payload `pear_queue.read_payload` hands out while a task runs;
+    /// `stream` is the request and sink `pear_stream` is bound to while a response streams.
+    fn linker(&self, task: Option<&str>, stream: Option<(&[u8], &Arc<StreamSink>)>) -> Result<Linker<WasiCtx>> {
+        let mut linker = Linker::new(&self.engine);
+        wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
+        if let Some(database) = &self.config.database {
+            db_host::add_to_linker(&mut linker, database.clone())?;
+        }
+        if let Some(queue) = &self.config.queue {
+            queue_host::add_to_linker(&mut linker, queue.clone(), task.unwrap_or_default().as_bytes().to_vec())?;
+        }
+        if let Some(pubsub) = &self.config.pubsub {
+            pubsub_host::add_to_linker(&mut linker, pubsub.subscriber())?;
+        }
+        if let Some(mail) = &self.config.mail {
+            mail_host::add_to_linker(&mut linker, mail.clone())?;
+        }
+        if let Some(sessions) = &self.config.sessions {
+            session_host::add_to_linker(&mut linker, sessions.clone())?;
+        }
+        if let Some((request, sink)) = stream {
+            stream_host::add_to_linker(&mut linker, sink.clone(), request.to_vec())?;
+        }
+        Ok(linker)
+    }
+
+    /// Call a `() -> ()` export on a fresh instance of this Cage's module
+    /// Used for scheduled jobs; the instance and its memory are dropped afterwards.
+    /// Blocks while the guest runs, so call it from a blocking task.
+    pub fn invoke_export(&self, export: &str) -> Result<()> {
+        let result = self.call_fresh(self.config.determinism.as_ref(), export, None, None, ());
+        self.record(|| RecordedCall::Export { export: export.to_string() }, result.as_ref().map(|()| None));
+        result
+    }
+
+    /// Run a queued task through the `(payload_len: i32) -> i32` handler export
+    /// The guest reads the payload with `pear_queue.read_payload`; a non-zero result is a failure.
+    /// Blocks while the guest runs, so call it from a blocking task.
+    pub fn handle_task(&self, export: &str, payload: &str) -> Result<()> {
+        let result: Result<i32> = self.call_fresh(
+            self.config.determinism.as_ref(),
+            export,
+            Some(payload),
+            None,
+            payload.len() as i32,
+        );
+        self.record(
+            || RecordedCall::Task { export: export.to_string(), payload: payload.to_string() },
+            result.as_ref().map(|code| Some(*code)),
+        );
+        let code = result?;
+        if code != 0 {
+            anyhow::bail!("`{}` returned {}", export, code);
+        }
+        Ok(())
+    }
+
+    /// Whether the module exports a function with this name
+    pub fn has_export(&self, export: &str) -> bool {
+        matches!(self.module.get_export(export), Some(ExternType::Func(_)))
+    }
+
+    /// Stream a response through the `(request_len: i32) -> i32` export
+    /// The guest writes chunks with `pear_stream`; the response ends when it returns.
+    /// Blocks while the guest runs, so call it from a blocking task.
+    pub fn stream_request(&self, export: &str, request_data: &[u8], sink: Arc<StreamSink>) -> Result<()> {
+        let result: Result<i32> = self.call_fresh(
+            self.config.determinism.as_ref(),
+            export,
+            None,
+            Some((request_data, &sink)),
+            request_data.len() as i32,
+        );
+        self.record(|| RecordedCall::stream(export, request_data), result.as_ref().map(|code| Some(*code)));
+        let code = result?;
+        self.request_count.fetch_add(1, Ordering::Relaxed);
+        sink.flush();
+        if code != 0 {
+            anyhow::bail!("`{}` returned {}", export, code);
+        }
+        Ok(())
+    }
+
+    /// Run a recorded call again on a fresh instance, with the time and randomness it had
+    /// `sink` receives a replayed stream's response. Host modules are live, so the call's writes,
+    /// messages and mail happen again. Returns what the export returned, if it returns a code.
+    /// Blocks while the guest runs, so call it from a blocking task.
+    pub fn replay(&self, recording: &Recording, sink: Arc<StreamSink>) -> Result<Option<i32>> {
+        let determinism = Some(&recording.determinism);
+        let result: Result<Option<i32>> = match &recording.call {
+            RecordedCall::Export { export } => self.call_fresh(determinism, export, None, None, ()).map(|()| None),
+            RecordedCall::Task { export, payload } => {
+                self.call_fresh(determinism, export, Some(payload), None, payload.len() as i32).map(Some)
+            }
+            RecordedCall::Stream { export, .. } => {
+                let request = recording.call.request()?.unwrap_or_default();
+                self.call_fresh(determinism, export, None, Some((&request, &sink)), request.len() as i32).map(Some)
+            }
+        };
+        sink.flush();
+        result
+    }
+
+    /// Record a call if the site records them, and report it if the guest crashed
+    fn record(&self, call: impl FnOnce() -> RecordedCall, outcome: Result<Option<i32>, &anyhow::Error>) {
+        let crash = match (&self.config.crashes, outcome) {
+            (Some(reporter), Err(e)) if crash::is_crash(e) => Some((reporter, e)),
+            _ => None,
+        };
+        if crash.is_none() && self.config.recorder.is_none() {
+            return;
+        }
+
+        let call = call();
+        if let Some((reporter, e)) = crash {
+            let report = reporter.report(self.id, call.clone(), e);
+            warn!(cage_id = self.id, report, error = %e, "Guest crashed");
+        }
+        if let Some(recorder) = &self.config.recorder {
+            recorder.record(self.id, call, outcome);
+        }
+    }
+
+    /// Call an export on a fresh instance, counted as an active request
+    fn call_fresh<P: WasmParams, R: WasmResults>(
+        &self,
+        determinism: Option<&Determinism>,
+        export: &str,
+        task: Option<&str>,
+        stream: Option<(&[u8], &Arc<StreamSink>)>,
+        params: P,
+    ) -> Result<R> {
+        if !self.is_healthy() {
+            anyhow::bail!("Cage {} is not healthy", self.id);
+        }
+
+        self.active_requests.fetch_add(1, Ordering::Relaxed);
+        let start = std::time::Instant::now();
+        let (result, fuel_used) = self.call_instance(determinism, export, task, stream, params);
+        self.active_requests.fetch_sub(1, Ordering::Relaxed);
+        if let Some(usage) = &self.config.usage {
+            usage.record(fuel_used, start.elapsed());
+        }
+
+        result
+    }
+
+    /// Instantiate the module and call an export, returning the result and the fuel it burned
+    fn call_instance<P: WasmParams, R: WasmResults>(
+        &self,
+        determinism: Option<&Determinism>,
+        export: &str,
+        task: Option<&str>,
+        stream: Option<(&[u8], &Arc<StreamSink>)>,
+        params: P,
+    ) -> (Result<R>, u64) {
+        let mut fuel_used = 0;
+        let result = (|| {
+            let mut store = Store::new(&self.engine, wasi_context(&self.config, self.id, determinism)?);
+            if let Some(profiler) = &self.config.profiler {
+                profiler.watch(&mut store);
+            }
+            if let Some(fuel) = self.config.fuel {
+                store.set_fuel(fuel)?;
+            }
+            let instance = self.linker(task, stream)?.instantiate(&mut store, &self.module)
+                .context("Failed to instantiate WebAssembly module");
+            let result = instance.and_then(|instance| {
+                let func = instance.get_typed_func::<P, R>(&mut store, export)
+                    .with_context(|| format!("Module has no `{}` export of the expected type", export))?;
+                func.call(&mut store, params)
+            });
+            // Instantiation burns fuel too, so it counts even if the call never started
+            if let Some(fuel) = self.config.fuel {
+                fuel_used = fuel - store.get_fuel().unwrap_or(0);
+            }
+            result
+        })();
+        (result, fuel_used)
+    }
+
+    /// Call a `() -> ()` export as a health probe, even while the Cage is marked unhealthy
+    /// Probes aren't billed or recorded. Blocks while the guest runs, so call it from a blocking task.
+    pub fn probe_export(&self, export: &str) -> Result<()> {
+        self.call_instance(None, export, None, None, ()).0
+    }
+
+    /// Run a request as a health probe, even while the Cage is marked unhealthy
+    /// Blocks while the guest runs, so call it from a blocking task.
+    pub fn probe_request(&self, request_data: &[u8]) -> Result<()> {
+        self.execute_wasm_function(request_data, &mut BytesMut::new())
+    }
+
     /// Execute a request in this Cage
+    /// Blocks while the guest runs, so call it through a `WasmExecutor`.
     #[instrument(skip(self, request_data))]
-    pub async fn execute_request(&self, request_data: &[u8]) -> Result<Vec<u8>> {
+    pub fn execute_request(&self, request_data: &[u8]) -> Result<Vec<u8>> {
+        let mut response = BytesMut::new();
+        self.execute_request_into(request_data, &mut response)?;
+        Ok(response.to_vec())
+    }
+
+    /// Execute a request, writing the response into a caller-provided buffer
+    /// Lets the Router hand in pooled buffers so the hot path avoids allocation.
+    /// Blocks while the guest runs, so call it through a `WasmExecutor`.
+    #[instrument(skip(self, request_data, response))]
+    pub fn execute_request_into(&self, request_data: &[u8], response: &mut BytesMut) -> Result<()> {
         // Check if Cage is healthy
         if !self.is_healthy() {
             anyhow::bail!("Cage {} is not healthy", self.id);
@@ -167,7 +421,7 @@ impl Cage {
         let start = std::time::Instant::now();
         
         // Execute the request (simplified for Phase 2 - will be enhanced)
-        let response = self.execute_wasm_function(request_data).await;
+        let result = self.execute_wasm_function(request_data, response);
         
         // Decrement active request counter
         self.active_requests.fetch_sub(1, Ordering::Relaxed);
@@ -176,29 +430,30 @@ impl Cage {
         self.request_count.fetch_add(1, Ordering::Relaxed);
 
         let duration = start.elapsed();
+        self.latency.record(duration);
+        if let Some(usage) = &self.config.usage {
+            usage.record(0, duration);
+        }
         debug!(
             cage_id = self.id,
             duration_ms = duration.as_millis(),
             "Request executed"
         );
 
-        response
+        result
     }
 
     /// Execute a WebAssembly function (internal implementation)
-    async fn execute_wasm_function(&self, _request_data: &[u8]) -> Result<Vec<u8>> {
+    fn execute_wasm_function(&self, _request_data: &[u8], response: &mut BytesMut) -> Result<()> {
         // For Phase 2, return a simple response
         // In Phase 3, this will actually invoke Wasm functions
-        
-        // Simulate some processing
-        tokio::time::sleep(tokio::time::Duration::from_micros(100)).await;
-
-        let response = format!(
+        write!(
+            response,
             "{{\"cage_id\":{},\"status\":\"ok\",\"message\":\"Processed by Cage {}\"}}",
             self.id, self.name
-        );
+        )?;
         
-        Ok(response.into_bytes())
+        Ok(())
     }
 
     /// Perform a health check
@@ -236,6 +491,16 @@ impl Cage {
         *self.state.read().await
     }
 
+    /// One of the Cage's concurrent request slots, if any is free
+    pub fn try_slot(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    /// Whether another request may start on the Cage
+    pub fn has_free_slot(&self) -> bool {
+        self.slots.available_permits() > 0
+    }
+
     /// Get number of active requests
     pub fn active_request_count(&self) -> u64 {
         self.active_requests.load(Ordering::Relaxed)
@@ -246,6 +511,11 @@ impl Cage {
         self.request_count.load(Ordering::Relaxed)
     }
 
+    /// Execution latencies of the requests this Cage has handled
+    pub fn latency(&self) -> HistogramSnapshot {
+        self.latency.snapshot()
+    }
+
     /// Get Cage ID
     pub fn id(&self) -> u64 {
         self.id
@@ -264,6 +534,12 @@ impl Cage {
         self.healthy.store(false, Ordering::Relaxed);
     }
 
+    /// Fail health checks until the next `health_check`, without changing state
+    pub fn mark_unhealthy(&self) {
+        warn!(cage_id = self.id, "Marking Cage as unhealthy");
+        self.healthy.store(false, Ordering::Relaxed);
+    }
+
     /// Gracefully terminate the Cage
     #[instrument(skip(self))]
     pub async fn terminate(&self) -> Result<()> {
@@ -311,9 +587,43 @@ impl ResourceLimiter for ResourceLimiterImpl {
     }
 }
 
+/// WASI context with the Cage's environment
+/// With `determinism`, the guest gets virtual clocks and seeded randomness instead of the host's.
+/// With a guest log, stdout and stderr go to the site's log rather than the daemon's.
+fn wasi_context(config: &CageConfig, cage_id: u64, determinism: Option<&Determinism>) -> Result<WasiCtx> {
+    let mut ctx = match determinism {
+        Some(determinism) => determinism.wasi_context(),
+        None => WasiCtxBuilder::new().build(),
+    };
+    match &config.guest_log {
+        Some(log) => {
+            ctx.set_stdout(Box::new(WritePipe::new(log.writer(cage_id, GuestStream::Stdout))));
+            ctx.set_stderr(Box::new(WritePipe::new(log.writer(cage_id, GuestStream::Stderr))));
+        }
+        None => {
+            ctx.set_stdin(Box::new(wasmtime_wasi::sync::stdio::stdin()));
+            ctx.set_stdout(Box::new(wasmtime_wasi::sync::stdio::stdout()));
+            ctx.set_stderr(Box::new(wasmtime_wasi::sync::stdio::stderr()));
+        }
+    }
+    for (name, value) in config.env.as_pairs() {
+        ctx.push_env(name, value).context("Failed to set Cage environment")?;
+    }
+    Ok(ctx)
+}
+
 /// Create a shared Wasmtime engine with optimizations
 pub fn create_engine() -> Result<Engine> {
+    create_engine_for(&CageConfig::default())
+}
+
+/// Create an engine with the profiling and metering the Cage's config asks for
+/// Sampled guests need epoch interruption and metered guests fuel, each costing a little on every loop and call.
+pub fn create_engine_for(cage_config: &CageConfig) -> Result<Engine> {
     let mut config = Config::new();
+    config.profiler(cage_config.jit_profiler.strategy());
+    config.epoch_interruption(cage_config.profiler.is_some());
+    config.consume_fuel(cage_config.fuel.is_some());
     
     // Enable optimizations
     config.cranelift_opt_level(OptLevel::Speed);
@@ -369,4 +679,122 @@ mod tests {
         let is_healthy = cage.health_check().await;
         assert!(is_healthy);
     }
+
+    #[test]
+    fn test_deterministic_guest() {
+        let wat = r#"
+            (module
+                (import "wasi_snapshot_preview1" "random_get" (func $random (param i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "clock_time_get" (func $clock (param i32 i64 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "random") (result i64)
+                    (drop (call $random (i32.const 0) (i32.const 8)))
+                    (i64.load (i32.const 0)))
+                (func (export "now") (result i64)
+                    (drop (call $clock (i32.const 0) (i64.const 1) (i32.const 8)))
+                    (i64.load (i32.const 8))))
+        "#;
+        let wasm_bytes = wat::parse_str(wat).unwrap();
+        let cage = Cage::new(1, "test-cage".to_string(), create_engine().unwrap(), &wasm_bytes, CageConfig::default()).unwrap();
+        let seeded = Determinism { seed: 7, ..Default::default() };
+
+        let random = |determinism: Option<&Determinism>| cage.call_fresh::<(), i64>(determinism, "random", None, None, ()).unwrap();
+        assert_eq!(random(Some(&seeded)), random(Some(&seeded)));
+        assert_ne!(random(Some(&seeded)), random(Some(&Determinism { seed: 8, ..seeded })));
+        assert_ne!(random(None), random(None));
+
+        let now = cage.call_fresh::<(), i64>(Some(&seeded), "now", None, None, ()).unwrap();
+        assert_eq!(now as u64, seeded.start_unix_ms * 1_000_000);
+    }
+
+    #[test]
+    fn test_guest_output_captured() {
+        use crate::observability::guest_logs::{GuestLogConfig, GuestLogs};
+        use crate::observability::logs::LogFilter;
+
+        let wat = r#"
+            (module
+                (import "wasi_snapshot_preview1" "fd_write" (func $write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "hello\nbad")
+                (func (export "print") (result i32)
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const 6))
+                    (drop (call $write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (i32.store (i32.const 0) (i32.const 22))
+                    (i32.store (i32.const 4) (i32.const 3))
+                    (call $write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8))))
+        "#;
+        let logs = GuestLogs::new(GuestLogConfig { dir: String::new(), ..Default::default() });
+        let mut config = CageConfig::default();
+        config.guest_log = Some(logs.site("blog"));
+        let cage = Cage::new(5, "test-cage".to_string(), create_engine().unwrap(), &wat::parse_str(wat).unwrap(), config).unwrap();
+        assert_eq!(cage.call_fresh::<(), i32>(None, "print", None, None, ()).unwrap(), 0);
+
+        // The unfinished stderr line is logged once the call's context is gone
+        let lines: Vec<_> = logs.site("blog").buffer().query(&LogFilter::default(), 10).into_iter()
+            .map(|r| (r.level, r.target, r.message))
+            .collect();
+        assert_eq!(lines, vec![
+            ("INFO".to_string(), "cage-5::stdout".to_string(), "hello".to_string()),
+            ("WARN".to_string(), "cage-5::stderr".to_string(), "bad".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_crash_reported() {
+        use crate::cage::crash::{CrashConfig, CrashReporter};
+
+        let wat = r#"
+            (module
+                (func (export "handle_job") (param i32) (result i32) unreachable)
+                (func (export "handle_ok") (param i32) (result i32) (i32.const 1)))
+        "#;
+        let reporter = Arc::new(CrashReporter::new("blog", CrashConfig::default(), None));
+        let mut config = CageConfig::default();
+        config.crashes = Some(reporter.clone());
+        let cage = Cage::new(4, "test-cage".to_string(), create_engine().unwrap(), &wat::parse_str(wat).unwrap(), config).unwrap();
+
+        // A failing result code is not a crash
+        assert!(cage.handle_task("handle_ok", "{}").is_err());
+        assert!(reporter.list().is_empty());
+
+        assert!(cage.handle_task("handle_job", "{\"id\":1}").is_err());
+        let reports = reporter.list();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].cage_id, 4);
+        assert_eq!(reports[0].call, RecordedCall::Task { export: "handle_job".to_string(), payload: "{\"id\":1}".to_string() });
+        assert!(reports[0].trap.is_some());
+    }
+
+    #[test]
+    fn test_fuel_metered() {
+        use crate::tenancy::billing::{BillingConfig, BillingMeter};
+
+        let wat = r#"
+            (module
+                (func (export "spin") (param i32) (result i32)
+                    (loop $again
+                        (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                        (br_if $again (local.get 0)))
+                    (i32.const 0)))
+        "#;
+        let meter = Arc::new(BillingMeter::new(BillingConfig { state_path: String::new(), ..Default::default() }).unwrap());
+        let mut config = CageConfig::default();
+        config.fuel = Some(10_000);
+        config.usage = Some(meter.guest("blog", "acme"));
+        let cage = Cage::new(6, "test-cage".to_string(), create_engine_for(&config).unwrap(), &wat::parse_str(wat).unwrap(), config).unwrap();
+
+        assert_eq!(cage.call_fresh::<i32, i32>(None, "spin", None, None, 100).unwrap(), 0);
+        let period = crate::tenancy::bandwidth::Period::now().month;
+        let first = meter.tenant_bill("acme", period).usage;
+        assert_eq!(first.calls, 1);
+        assert!(first.fuel > 100);
+
+        // A call past its fuel is stopped, and still billed
+        assert!(cage.call_fresh::<i32, i32>(None, "spin", None, None, 1_000_000).is_err());
+        let usage = meter.tenant_bill("acme", period).usage;
+        assert_eq!(usage.calls, 2);
+        assert_eq!(usage.fuel, first.fuel + 10_000);
+    }
 }
@@ -0,0 +1,212 @@
+// Tenant Resource Partitions
+// Aggregate memory and instance budgets shared by all of a tenant's Cage pools
+
+use anyhow::{Result, bail};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Aggregate limits for one tenant; unset limits are unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionLimits {
+    /// Memory limit any single Cage may be configured with
+    pub max_memory_per_cage_bytes: Option<usize>,
+
+    /// Memory limits of all running Cages combined
+    pub max_total_memory_bytes: Option<usize>,
+
+    /// Running Cages across all pools
+    pub max_instances: Option<usize>,
+}
+
+/// Current consumption of a tenant's partition
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PartitionUsage {
+    pub memory_bytes: usize,
+    pub instances: usize,
+    pub limits: PartitionLimits,
+}
+
+impl PartitionUsage {
+    /// Check that `count` more Cages of `memory_bytes` each would fit
+    fn check(&self, count: usize, memory_bytes: usize) -> Result<()> {
+        if let Some(per_cage) = self.limits.max_memory_per_cage_bytes {
+            if memory_bytes > per_cage {
+                bail!(
+                    "Cage memory limit {} MB exceeds the tenant's per-Cage limit of {} MB",
+                    memory_bytes / (1024 * 1024),
+                    per_cage / (1024 * 1024)
+                );
+            }
+        }
+
+        if let Some(max_instances) = self.limits.max_instances {
+            if self.instances + count > max_instances {
+                bail!(
+                    "Instance quota exceeded: {} running, {} requested, limit is {}",
+                    self.instances,
+                    count,
+                    max_instances
+                );
+            }
+        }
+
+        if let Some(max_memory) = self.limits.max_total_memory_bytes {
+            let requested = memory_bytes.saturating_mul(count);
+            if self.memory_bytes + requested > max_memory {
+                bail!(
+                    "Tenant memory quota exceeded: {} MB reserved, {} MB requested, limit is {} MB",
+                    self.memory_bytes / (1024 * 1024),
+                    requested / (1024 * 1024),
+                    max_memory / (1024 * 1024)
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Per-tenant resource accounting for every Cage on the host
+#[derive(Default)]
+pub struct ResourcePartitions {
+    partitions: DashMap<String, PartitionUsage>,
+}
+
+impl ResourcePartitions {
+    /// Create an empty set of partitions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace a tenant's limits; running Cages are not evicted if over the new limits
+    pub fn set_limits(&self, tenant_id: &str, limits: PartitionLimits) {
+        self.partitions.entry(tenant_id.to_string()).or_default().limits = limits;
+    }
+
+    /// Check that `count` more Cages would fit, without reserving anything
+    pub fn can_reserve(&self, tenant_id: &str, count: usize, memory_bytes: usize) -> Result<()> {
+        self.usage(tenant_id).check(count, memory_bytes)
+    }
+
+    /// Reserve room for one Cage, released when the reservation is dropped
+    pub fn reserve(self: &Arc<Self>, tenant_id: &str, memory_bytes: usize) -> Result<Reservation> {
+        let mut usage = self.partitions.entry(tenant_id.to_string()).or_default();
+
+        if let Err(e) = usage.check(1, memory_bytes) {
+            warn!(tenant_id = %tenant_id, error = %e, "Cage reservation refused");
+            return Err(e);
+        }
+
+        usage.memory_bytes += memory_bytes;
+        usage.instances += 1;
+        debug!(
+            tenant_id = %tenant_id,
+            memory_bytes = usage.memory_bytes,
+            instances = usage.instances,
+            "Cage resources reserved"
+        );
+
+        Ok(Reservation {
+            partitions: self.clone(),
+            tenant_id: tenant_id.to_string(),
+            memory_bytes,
+        })
+    }
+
+    /// Current consumption and limits of a tenant
+    pub fn usage(&self, tenant_id: &str) -> PartitionUsage {
+        self.partitions.get(tenant_id).map(|usage| *usage).unwrap_or_default()
+    }
+
+    fn release(&self, tenant_id: &str, memory_bytes: usize) {
+        if let Some(mut usage) = self.partitions.get_mut(tenant_id) {
+            usage.memory_bytes = usage.memory_bytes.saturating_sub(memory_bytes);
+            usage.instances = usage.instances.saturating_sub(1);
+        }
+    }
+}
+
+/// Tenant partition a pool draws its Cages from
+#[derive(Clone)]
+pub struct PartitionHandle {
+    pub partitions: Arc<ResourcePartitions>,
+    pub tenant_id: String,
+}
+
+impl PartitionHandle {
+    /// Handle for a tenant's partition
+    pub fn new(partitions: Arc<ResourcePartitions>, tenant_id: impl Into<String>) -> Self {
+        Self {
+            partitions,
+            tenant_id: tenant_id.into(),
+        }
+    }
+
+    /// Check that `count` more Cages would fit
+    pub fn can_reserve(&self, count: usize, memory_bytes: usize) -> Result<()> {
+        self.partitions.can_reserve(&self.tenant_id, count, memory_bytes)
+    }
+
+    /// Reserve room for one Cage
+    pub fn reserve(&self, memory_bytes: usize) -> Result<Reservation> {
+        self.partitions.reserve(&self.tenant_id, memory_bytes)
+    }
+}
+
+/// One Cage's share of its tenant's partition
+pub struct Reservation {
+    partitions: Arc<ResourcePartitions>,
+    tenant_id: String,
+    memory_bytes: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.partitions.release(&self.tenant_id, self.memory_bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1024 * 1024;
+
+    #[test]
+    fn test_memory_and_instance_limits() {
+        let partitions = Arc::new(ResourcePartitions::new());
+        partitions.set_limits("acme", PartitionLimits {
+            max_memory_per_cage_bytes: Some(128 * MB),
+            max_total_memory_bytes: Some(256 * MB),
+            max_instances: Some(3),
+        });
+
+        assert!(partitions.reserve("acme", 256 * MB).is_err());
+
+        let first = partitions.reserve("acme", 128 * MB).unwrap();
+        let _second = partitions.reserve("acme", 128 * MB).unwrap();
+        assert!(partitions.reserve("acme", 64 * MB).is_err());
+        assert_eq!(partitions.usage("acme").instances, 2);
+
+        // Dropping a reservation frees its share
+        drop(first);
+        assert_eq!(partitions.usage("acme").memory_bytes, 128 * MB);
+        let _third = partitions.reserve("acme", 64 * MB).unwrap();
+        let _fourth = partitions.reserve("acme", 64 * MB).unwrap();
+        assert!(partitions.can_reserve("acme", 1, MB).is_err());
+    }
+
+    #[test]
+    fn test_tenants_are_isolated() {
+        let partitions = Arc::new(ResourcePartitions::new());
+        partitions.set_limits("acme", PartitionLimits { max_instances: Some(1), ..Default::default() });
+
+        let _acme = partitions.reserve("acme", 64 * MB).unwrap();
+        assert!(partitions.reserve("acme", 64 * MB).is_err());
+
+        // Tenants without limits are unrestricted
+        assert!(partitions.can_reserve("globex", 100, 128 * MB).is_ok());
+    }
+}
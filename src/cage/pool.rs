@@ -1,10 +1,17 @@
 // Cage Pool - Redundant WebAssembly instance management
 // Manages multiple Cage instances for a single site to ensure high availability
 
-use super::{Cage, CageState, CageConfig, create_engine};
+use super::{Cage, CageState, CageConfig, create_engine_for};
+use super::config::StartPolicy;
+use super::partition::PartitionHandle;
+use super::request_queue::{CageSlot, RequestQueue};
+use crate::observability::histogram::{HistogramSnapshot, LatencyHistogram};
+use crate::tenancy::quota::SiteQuota;
 use anyhow::{Result, Context};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
 use tracing::{info, debug, warn, error, instrument};
 
 /// Pool of redundant Cage instances for a single site
@@ -15,47 +22,132 @@ pub struct CagePool {
     /// All Cage instances in this pool
     cages: Arc<RwLock<Vec<Arc<Cage>>>>,
     
+    /// Warm Cages outside load balancing, waiting to replace crashed ones
+    standbys: RwLock<Vec<Arc<Cage>>>,
+    
     /// Configuration for Cages in this pool
     config: CageConfig,
     
     /// Target number of replicas (default: 3)
-    target_replicas: usize,
+    target_replicas: AtomicUsize,
+    
+    /// Tenant partition every Cage must reserve memory from
+    partition: Option<PartitionHandle>,
+    
+    /// Cage limits of the site's tenant, and where its Cage count is reported
+    quota: Option<SiteQuota>,
     
     /// Next Cage ID for spawning new instances
     next_cage_id: Arc<std::sync::atomic::AtomicU64>,
     
     /// Round-robin index for load balancing
     round_robin_index: Arc<std::sync::atomic::AtomicUsize>,
+    
+    /// Taken down for maintenance: no new requests, and no healing
+    draining: AtomicBool,
+    
+    /// Module a lazy or scale-to-zero pool starts its Cages from on the next request
+    module: parking_lot::RwLock<Option<Arc<[u8]>>>,
+    
+    /// Held while a cold pool starts, so requests arriving meanwhile wait for that one start
+    waking: Mutex<()>,
+    
+    created: Instant,
+    
+    /// Milliseconds after `created` of the last request
+    last_request_ms: AtomicU64,
+    
+    /// Time from a request finding the pool cold to its first Cage running
+    cold_starts: LatencyHistogram,
+    
+    /// Requests waiting for a Cage below its `max_concurrent_requests`
+    queue: RequestQueue,
 }
 
 impl CagePool {
     /// Create a new CagePool
-    #[instrument(skip(wasm_bytes))]
     pub async fn new(
         site_id: String,
         wasm_bytes: Vec<u8>,
         config: CageConfig,
         target_replicas: usize,
     ) -> Result<Self> {
-        info!(site_id = %site_id, replicas = target_replicas, "Creating CagePool");
+        Self::create(site_id, wasm_bytes, config, target_replicas, None, None).await
+    }
+
+    /// Create a CagePool held to its tenant's per-site Cage and memory quota
+    /// Fails without starting anything if the site may not run that many Cages
+    pub async fn new_with_quota(
+        site_id: String,
+        wasm_bytes: Vec<u8>,
+        config: CageConfig,
+        target_replicas: usize,
+        quota: SiteQuota,
+    ) -> Result<Self> {
+        quota.can_run(target_replicas, config.memory_limit_bytes)
+            .with_context(|| format!("Cannot create pool for site {}", site_id))?;
+        
+        Self::create(site_id, wasm_bytes, config, target_replicas, None, Some(quota)).await
+    }
+
+    /// Create a CagePool whose Cages count against a tenant's resource partition
+    /// Fails without starting anything if the pool would not fit
+    pub async fn new_in_partition(
+        site_id: String,
+        wasm_bytes: Vec<u8>,
+        config: CageConfig,
+        target_replicas: usize,
+        partition: PartitionHandle,
+    ) -> Result<Self> {
+        partition.can_reserve(target_replicas, config.memory_limit_bytes)
+            .with_context(|| format!("Cannot create pool for site {}", site_id))?;
+        
+        Self::create(site_id, wasm_bytes, config, target_replicas, Some(partition), None).await
+    }
 
+    #[instrument(skip(wasm_bytes, config, partition, quota))]
+    async fn create(
+        site_id: String,
+        wasm_bytes: Vec<u8>,
+        config: CageConfig,
+        target_replicas: usize,
+        partition: Option<PartitionHandle>,
+        quota: Option<SiteQuota>,
+    ) -> Result<Self> {
+        info!(site_id = %site_id, replicas = target_replicas, start = ?config.start, "Creating CagePool");
+
+        let eager = config.start == StartPolicy::Eager;
+        let queue = RequestQueue::new(config.request_queue_depth, Duration::from_millis(config.request_queue_timeout_ms));
         let pool = Self {
             site_id: site_id.clone(),
             cages: Arc::new(RwLock::new(Vec::new())),
+            standbys: RwLock::new(Vec::new()),
             config,
-            target_replicas,
+            target_replicas: AtomicUsize::new(target_replicas),
+            partition,
+            quota,
             next_cage_id: Arc::new(std::sync::atomic::AtomicU64::new(0)),
             round_robin_index: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            draining: AtomicBool::new(false),
+            module: parking_lot::RwLock::new((!eager).then(|| Arc::from(wasm_bytes.as_slice()))),
+            waking: Mutex::new(()),
+            created: Instant::now(),
+            last_request_ms: AtomicU64::new(0),
+            cold_starts: LatencyHistogram::new(),
+            queue,
         };
 
-        // Spawn initial Cages
-        for _ in 0..target_replicas {
-            pool.spawn_cage(&wasm_bytes).await?;
+        // Lazy and scale-to-zero pools start on their first request
+        if eager {
+            for _ in 0..target_replicas {
+                pool.spawn_cage(&wasm_bytes).await?;
+            }
+            pool.fill_standbys(&wasm_bytes).await;
         }
 
         info!(
             site_id = %site_id,
-            active_cages = target_replicas,
+            active_cages = if eager { target_replicas } else { 0 },
             "CagePool initialized"
         );
 
@@ -65,13 +157,98 @@ impl CagePool {
     /// Spawn a new Cage instance
     #[instrument(skip(self, wasm_bytes))]
     async fn spawn_cage(&self, wasm_bytes: &[u8]) -> Result<Arc<Cage>> {
+        if let Some(quota) = &self.quota {
+            let running = self.cages.read().await.len();
+            quota.can_spawn(running, self.config.memory_limit_bytes)?;
+        }
+        let cage_arc = self.instantiate(wasm_bytes).await?;
+
+        // Add to pool
+        let mut cages = self.cages.write().await;
+        cages.push(cage_arc.clone());
+        self.report_cage_count(cages.len());
+
+        info!(
+            site_id = %self.site_id,
+            cage_id = cage_arc.id(),
+            pool_size = cages.len(),
+            "Cage spawned successfully"
+        );
+
+        Ok(cage_arc)
+    }
+
+    /// Start standby Cages until the pool has `standby_replicas` of them
+    /// Returns how many were started; failures are logged and left for the next repair.
+    async fn fill_standbys(&self, wasm_bytes: &[u8]) -> usize {
+        let mut spawned = 0;
+        loop {
+            let standbys = self.standbys.read().await.len();
+            if standbys >= self.config.standby_replicas {
+                return spawned;
+            }
+            if let Some(quota) = &self.quota {
+                let running = self.cages.read().await.len() + standbys;
+                if let Err(e) = quota.can_spawn(running, self.config.memory_limit_bytes) {
+                    warn!(site_id = %self.site_id, error = %e, "Standby Cage refused by quota");
+                    return spawned;
+                }
+            }
+            match self.instantiate(wasm_bytes).await {
+                Ok(cage) => {
+                    debug!(site_id = %self.site_id, cage_id = cage.id(), "Standby Cage ready");
+                    self.standbys.write().await.push(cage);
+                    spawned += 1;
+                }
+                Err(e) => {
+                    warn!(site_id = %self.site_id, error = %e, "Failed to start standby Cage");
+                    return spawned;
+                }
+            }
+        }
+    }
+
+    /// Put standby Cages in the place of crashed ones, for as long as standbys last
+    /// Returns how many were promoted; `maintain_replicas` starts standbys to replace them.
+    pub async fn promote_standbys(&self) -> usize {
+        let mut cages = self.cages.write().await;
+        let mut standbys = self.standbys.write().await;
+        let mut promoted = 0;
+        for cage in cages.iter_mut() {
+            if standbys.is_empty() {
+                break;
+            }
+            if matches!(cage.state().await, CageState::Crashed | CageState::Terminated) {
+                let standby = standbys.pop().expect("standbys checked above");
+                info!(site_id = %self.site_id, crashed = cage.id(), promoted = standby.id(), "Standby Cage promoted");
+                *cage = standby;
+                promoted += 1;
+            }
+        }
+        promoted
+    }
+
+    /// Mark `cage` crashed, and promote a standby in its place if there is one
+    pub async fn crash(&self, cage: &Cage) {
+        cage.mark_crashed().await;
+        self.promote_standbys().await;
+    }
+
+    /// Create and initialize a Cage without adding it to the pool
+    async fn instantiate(&self, wasm_bytes: &[u8]) -> Result<Arc<Cage>> {
         let cage_id = self.next_cage_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         let cage_name = format!("{}-cage-{}", self.site_id, cage_id);
 
         debug!(site_id = %self.site_id, cage_id = cage_id, "Spawning new Cage");
 
+        // Claim the tenant's share first so an over-quota spawn costs nothing
+        let reservation = match &self.partition {
+            Some(partition) => Some(partition.reserve(self.config.memory_limit_bytes)?),
+            None => None,
+        };
+
         // Create engine (in production, this would be shared across pools)
-        let engine = create_engine()?;
+        let engine = create_engine_for(&self.config)?;
 
         // Create the Cage
         let cage = Cage::new(
@@ -81,30 +258,163 @@ impl CagePool {
             wasm_bytes,
             self.config.clone(),
         )?;
+        let cage = match reservation {
+            Some(reservation) => cage.with_reservation(reservation),
+            None => cage,
+        };
 
         // Initialize the Cage
         cage.initialize().await
             .context("Failed to initialize Cage")?;
 
-        let cage_arc = Arc::new(cage);
+        Ok(Arc::new(cage))
+    }
 
-        // Add to pool
-        let mut cages = self.cages.write().await;
-        cages.push(cage_arc.clone());
+    /// Replace every Cage with one running `wasm_bytes`, without dropping requests
+    /// The new Cages are instantiated first and take over in one step; the old ones finish their
+    /// in-flight requests in the background, then are terminated. Returns how many were retired.
+    /// Fails with the pool untouched if a new Cage can't start, or the tenant's partition can't
+    /// hold both sets while the old ones drain.
+    #[instrument(skip(self, wasm_bytes))]
+    pub async fn hot_swap(&self, wasm_bytes: &[u8]) -> Result<usize> {
+        // A cold pool just starts from the new module when woken
+        let _waking = self.waking.lock().await;
+        {
+            let mut module = self.module.write();
+            if module.is_some() {
+                *module = Some(Arc::from(wasm_bytes));
+            }
+        }
+        if self.is_cold().await {
+            info!(site_id = %self.site_id, "Hot-swapped module of cold CagePool");
+            return Ok(0);
+        }
 
-        info!(
-            site_id = %self.site_id,
-            cage_id = cage_id,
-            pool_size = cages.len(),
-            "Cage spawned successfully"
-        );
+        let replicas = self.target_replicas.load(Ordering::Relaxed);
+        let standbys = self.config.standby_replicas;
+        if let Some(partition) = &self.partition {
+            partition.can_reserve(replicas + standbys, self.config.memory_limit_bytes)
+                .with_context(|| format!("Cannot hot-swap site {}", self.site_id))?;
+        }
 
-        Ok(cage_arc)
+        let mut fresh = Vec::with_capacity(replicas);
+        for _ in 0..replicas {
+            fresh.push(self.instantiate(wasm_bytes).await?);
+        }
+        let mut fresh_standbys = Vec::with_capacity(standbys);
+        for _ in 0..standbys {
+            fresh_standbys.push(self.instantiate(wasm_bytes).await?);
+        }
+        let mut retired = std::mem::replace(&mut *self.cages.write().await, fresh);
+        retired.append(&mut std::mem::replace(&mut *self.standbys.write().await, fresh_standbys));
+        info!(site_id = %self.site_id, replicas = replicas, draining = retired.len(), "Hot-swapped CagePool module");
+
+        let count = retired.len();
+        let site_id = self.site_id.clone();
+        tokio::spawn(async move {
+            let drained = futures::future::join_all(retired.iter().map(|cage| cage.terminate())).await;
+            for (cage, result) in retired.iter().zip(drained) {
+                if let Err(e) = result {
+                    warn!(site_id = %site_id, cage_id = cage.id(), error = %e, "Failed to terminate Cage");
+                }
+            }
+        });
+        Ok(count)
+    }
+
+    /// Whether the pool has no Cages and starts them on the next request
+    pub async fn is_cold(&self) -> bool {
+        self.config.start != StartPolicy::Eager && self.cages.read().await.is_empty()
+    }
+
+    /// Note a request and, if the pool is cold, start its first Cage before returning
+    /// The rest of its replicas start in the background. Returns whether this call woke the pool.
+    pub async fn wake(self: &Arc<Self>) -> Result<bool> {
+        let started = Instant::now();
+        self.last_request_ms.store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+        if !self.is_cold().await {
+            return Ok(false);
+        }
+        let _waking = self.waking.lock().await;
+        if !self.is_cold().await {
+            return Ok(false);
+        }
+        let module = self.module.read().clone();
+        let wasm_bytes = module.context("Cold CagePool has no module")?;
+        self.spawn_cage(&wasm_bytes).await?;
+        let elapsed = started.elapsed();
+        self.cold_starts.record(elapsed);
+        info!(site_id = %self.site_id, cold_start_ms = elapsed.as_millis() as u64, "CagePool woken by a request");
+
+        let rest = self.target_replicas.load(Ordering::Relaxed).saturating_sub(1);
+        if rest > 0 || self.config.standby_replicas > 0 {
+            let pool = self.clone();
+            tokio::spawn(async move {
+                for _ in 0..rest {
+                    if let Err(e) = pool.spawn_cage(&wasm_bytes).await {
+                        warn!(site_id = %pool.site_id, error = %e, "Failed to start replica of woken CagePool");
+                        break;
+                    }
+                }
+                pool.fill_standbys(&wasm_bytes).await;
+            });
+        }
+        Ok(true)
+    }
+
+    /// Stop every Cage of a scale-to-zero pool that has gone `idle_timeout_secs` without requests
+    /// Returns how many were stopped.
+    pub async fn scale_to_zero_if_idle(&self) -> usize {
+        if self.config.start != StartPolicy::ScaleToZero || self.is_draining() {
+            return 0;
+        }
+        let timeout = Duration::from_secs(self.config.idle_timeout_secs);
+        let _waking = self.waking.lock().await;
+        let retired = {
+            // Requests note themselves before taking a Cage, so none can slip in under the write lock
+            let mut cages = self.cages.write().await;
+            let idle = self.created.elapsed()
+                .saturating_sub(Duration::from_millis(self.last_request_ms.load(Ordering::Relaxed)));
+            if cages.is_empty() || idle < timeout || cages.iter().any(|cage| cage.active_request_count() > 0) {
+                return 0;
+            }
+            self.report_cage_count(0);
+            let mut retired = std::mem::take(&mut *cages);
+            retired.append(&mut *self.standbys.write().await);
+            retired
+        };
+        for cage in &retired {
+            if let Err(e) = cage.terminate().await {
+                warn!(site_id = %self.site_id, cage_id = cage.id(), error = %e, "Failed to terminate Cage");
+            }
+        }
+        info!(site_id = %self.site_id, stopped = retired.len(), idle_secs = timeout.as_secs(), "Scaled idle CagePool to zero");
+        retired.len()
+    }
+
+    /// Requests waiting for a free Cage, and how long they waited
+    pub fn queue(&self) -> &RequestQueue {
+        &self.queue
+    }
+
+    /// One of `cage`'s concurrent request slots, if it has a free one
+    pub fn slot(&self, cage: &Cage) -> Option<CageSlot> {
+        cage.try_slot().map(|permit| self.queue.slot(permit))
+    }
+
+    /// Cold starts so far and how long they took
+    pub fn cold_starts(&self) -> HistogramSnapshot {
+        self.cold_starts.snapshot()
     }
 
     /// Get a healthy Cage for request execution (round-robin)
-    #[instrument(skip(self))]
     pub async fn get_cage_round_robin(&self) -> Option<Arc<Cage>> {
+        self.get_cage_round_robin_where(|_| true).await
+    }
+
+    /// Get a healthy Cage `usable` accepts (round-robin)
+    #[instrument(skip(self, usable))]
+    pub async fn get_cage_round_robin_where(&self, usable: impl Fn(&Arc<Cage>) -> bool) -> Option<Arc<Cage>> {
         let cages = self.cages.read().await;
         
         if cages.is_empty() {
@@ -119,7 +429,7 @@ impl CagePool {
             let index = (start_index + offset) % cages.len();
             let cage = &cages[index];
             
-            if cage.is_healthy() {
+            if cage.is_healthy() && usable(cage) {
                 debug!(
                     site_id = %self.site_id,
                     cage_id = cage.id(),
@@ -135,8 +445,13 @@ impl CagePool {
     }
 
     /// Get a healthy Cage with the least active requests
-    #[instrument(skip(self))]
     pub async fn get_cage_least_connected(&self) -> Option<Arc<Cage>> {
+        self.get_cage_least_connected_where(|_| true).await
+    }
+
+    /// Get a healthy Cage `usable` accepts with the least active requests
+    #[instrument(skip(self, usable))]
+    pub async fn get_cage_least_connected_where(&self, usable: impl Fn(&Arc<Cage>) -> bool) -> Option<Arc<Cage>> {
         let cages = self.cages.read().await;
         
         if cages.is_empty() {
@@ -149,7 +464,7 @@ impl CagePool {
         let mut min_requests = u64::MAX;
 
         for cage in cages.iter() {
-            if cage.is_healthy() {
+            if cage.is_healthy() && usable(cage) {
                 let active = cage.active_request_count();
                 if active < min_requests {
                     min_requests = active;
@@ -197,7 +512,39 @@ impl CagePool {
             healthy_cages: healthy,
             crashed_cages: crashed,
             initializing_cages: initializing,
+            draining: self.is_draining(),
+            cold: total == 0 && self.config.start != StartPolicy::Eager,
+            standby_cages: self.standbys.read().await.len(),
+            standby_target: if total == 0 { 0 } else { self.config.standby_replicas },
+        }
+    }
+
+    /// Execution latencies of each Cage currently in the pool
+    pub async fn cage_latencies(&self) -> Vec<CageLatency> {
+        self.cages.read().await.iter()
+            .map(|cage| CageLatency {
+                cage_id: cage.id(),
+                latency: cage.latency(),
+            })
+            .collect()
+    }
+
+    /// State, load and memory limit of each Cage currently in the pool
+    pub async fn cage_stats(&self) -> Vec<CageStats> {
+        let cages = self.cages.read().await;
+        let mut stats = Vec::with_capacity(cages.len());
+        for cage in cages.iter() {
+            stats.push(CageStats {
+                cage_id: cage.id(),
+                state: cage.state().await.to_string(),
+                healthy: cage.is_healthy(),
+                active_requests: cage.active_request_count(),
+                total_requests: cage.total_request_count(),
+                memory_limit_bytes: self.config.memory_limit_bytes,
+                latency: cage.latency().percentiles(),
+            });
         }
+        stats
     }
 
     /// Remove crashed Cages from the pool
@@ -214,6 +561,7 @@ impl CagePool {
         let removed = original_len - cages.len();
         
         if removed > 0 {
+            self.report_cage_count(cages.len());
             info!(
                 site_id = %self.site_id,
                 removed = removed,
@@ -227,41 +575,128 @@ impl CagePool {
 
     /// Ensure pool has the target number of healthy replicas
     #[instrument(skip(self, wasm_bytes))]
-    pub async fn maintain_replicas(&self, wasm_bytes: &[u8]) -> Result<()> {
-        // Remove crashed Cages
-        self.remove_crashed_cages().await;
+    pub async fn maintain_replicas(&self, wasm_bytes: &[u8]) -> Result<ReplicaRepair> {
+        // Replace crashed Cages with standbys while they last, then remove the rest
+        self.promote_standbys().await;
+        let mut repair = ReplicaRepair {
+            removed: self.remove_crashed_cages().await,
+            ..Default::default()
+        };
 
         let current_count = {
             let cages = self.cages.read().await;
             cages.len()
         };
+        // Cold pools, or lazy ones whose Cages all crashed, start again on their next request
+        if current_count == 0 && self.config.start != StartPolicy::Eager {
+            return Ok(repair);
+        }
 
         // Spawn new Cages if below target
-        if current_count < self.target_replicas {
-            let to_spawn = self.target_replicas - current_count;
+        let target_replicas = self.target_replicas.load(Ordering::Relaxed);
+        if current_count < target_replicas {
+            let to_spawn = target_replicas - current_count;
             
             info!(
                 site_id = %self.site_id,
                 current = current_count,
-                target = self.target_replicas,
+                target = target_replicas,
                 spawning = to_spawn,
                 "Spawning additional Cages to meet target"
             );
 
             for _ in 0..to_spawn {
+                // The rest would fail the same way, quota refusals included
                 if let Err(e) = self.spawn_cage(wasm_bytes).await {
                     error!(
                         site_id = %self.site_id,
                         error = %e,
                         "Failed to spawn Cage during maintenance"
                     );
+                    break;
                 }
+                repair.spawned += 1;
             }
+            repair.missing = to_spawn - repair.spawned;
         }
+        repair.spawned += self.fill_standbys(wasm_bytes).await;
 
+        Ok(repair)
+    }
+
+    /// Change the number of replicas
+    /// Scaling up is refused if the tenant's partition can't fit the extra Cages, or the site's quota
+    /// doesn't allow that many
+    #[instrument(skip(self, wasm_bytes))]
+    pub async fn scale(&self, replicas: usize, wasm_bytes: &[u8]) -> Result<()> {
+        let current = self.target_replicas.load(Ordering::Relaxed);
+        
+        if replicas > current {
+            if let Some(partition) = &self.partition {
+                partition.can_reserve(replicas - current, self.config.memory_limit_bytes)
+                    .with_context(|| format!("Cannot scale site {} to {} replicas", self.site_id, replicas))?;
+            }
+            if let Some(quota) = &self.quota {
+                quota.can_run(replicas, self.config.memory_limit_bytes)
+                    .with_context(|| format!("Cannot scale site {} to {} replicas", self.site_id, replicas))?;
+            }
+        }
+        
+        self.target_replicas.store(replicas, Ordering::Relaxed);
+        info!(site_id = %self.site_id, from = current, to = replicas, "Scaling CagePool");
+        
+        // Retire surplus Cages; their reservations are released once in-flight requests drop them
+        let surplus: Vec<Arc<Cage>> = {
+            let mut cages = self.cages.write().await;
+            let keep = replicas.min(cages.len());
+            let surplus = cages.split_off(keep);
+            self.report_cage_count(cages.len());
+            surplus
+        };
+        for cage in surplus {
+            if let Err(e) = cage.terminate().await {
+                warn!(site_id = %self.site_id, cage_id = cage.id(), error = %e, "Failed to terminate Cage");
+            }
+        }
+        
+        self.maintain_replicas(wasm_bytes).await?;
         Ok(())
     }
 
+    /// A new pool for the same site running another module, with this pool's configuration, replica target,
+    /// partition and quota
+    pub async fn sibling(&self, wasm_bytes: Vec<u8>) -> Result<Self> {
+        let replicas = self.target_replicas.load(Ordering::Relaxed);
+        if let Some(partition) = &self.partition {
+            partition.can_reserve(replicas, self.config.memory_limit_bytes)
+                .with_context(|| format!("Cannot create pool for site {}", self.site_id))?;
+        }
+        if let Some(quota) = &self.quota {
+            quota.can_run(replicas, self.config.memory_limit_bytes)
+                .with_context(|| format!("Cannot create pool for site {}", self.site_id))?;
+        }
+        Self::create(
+            self.site_id.clone(),
+            wasm_bytes,
+            self.config.clone(),
+            replicas,
+            self.partition.clone(),
+            self.quota.clone(),
+        ).await
+    }
+
+    /// Report the pool's Cage count as the site's, for its tenant's usage
+    fn report_cage_count(&self, count: usize) {
+        if let Some(quota) = &self.quota {
+            quota.set_cage_count(count);
+        }
+    }
+
+    /// The Cages currently in the pool
+    pub async fn cages(&self) -> Vec<Arc<Cage>> {
+        self.cages.read().await.clone()
+    }
+
     /// Get site ID
     pub fn site_id(&self) -> &str {
         &self.site_id
@@ -271,21 +706,89 @@ impl CagePool {
     pub async fn size(&self) -> usize {
         self.cages.read().await.len()
     }
+
+    /// Stop taking new requests and stop being healed; requests already running finish
+    /// Returns whether the pool was serving until now.
+    pub fn drain(&self) -> bool {
+        let was_draining = self.draining.swap(true, Ordering::Relaxed);
+        if !was_draining {
+            info!(site_id = %self.site_id, "Draining CagePool");
+        }
+        !was_draining
+    }
+
+    /// Take requests and be healed again after `drain`
+    /// Returns whether the pool was draining until now.
+    pub fn resume(&self) -> bool {
+        let was_draining = self.draining.swap(false, Ordering::Relaxed);
+        if was_draining {
+            info!(site_id = %self.site_id, "Resuming CagePool");
+        }
+        was_draining
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Requests running in the pool's Cages right now
+    pub async fn in_flight(&self) -> u64 {
+        self.cages.read().await.iter().map(|cage| cage.active_request_count()).sum()
+    }
 }
 
 /// Health statistics for a CagePool
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct PoolHealthStats {
     pub site_id: String,
     pub total_cages: usize,
     pub healthy_cages: usize,
     pub crashed_cages: usize,
     pub initializing_cages: usize,
+    pub draining: bool,
+    
+    /// No Cages until the next request starts them
+    pub cold: bool,
+    
+    /// Warm Cages waiting to replace crashed ones, and how many there should be
+    pub standby_cages: usize,
+    pub standby_target: usize,
+}
+
+/// What `maintain_replicas` did to a pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplicaRepair {
+    /// Crashed or terminated Cages taken out
+    pub removed: usize,
+    pub spawned: usize,
+
+    /// Cages still short of the target, because spawning failed
+    pub missing: usize,
+}
+
+/// Latency distribution of one Cage
+#[derive(Debug, Clone)]
+pub struct CageLatency {
+    pub cage_id: u64,
+    pub latency: crate::observability::histogram::HistogramSnapshot,
+}
+
+/// Point-in-time view of one Cage
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CageStats {
+    pub cage_id: u64,
+    pub state: String,
+    pub healthy: bool,
+    pub active_requests: u64,
+    pub total_requests: u64,
+    pub memory_limit_bytes: usize,
+    pub latency: crate::observability::histogram::LatencyPercentiles,
 }
 
 impl PoolHealthStats {
+    /// Whether the pool can serve, cold pools included
     pub fn is_healthy(&self) -> bool {
-        self.healthy_cages > 0
+        self.healthy_cages > 0 || self.cold
     }
 
     pub fn health_percentage(&self) -> f64 {
@@ -300,6 +803,7 @@ impl PoolHealthStats {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cage::partition::{PartitionLimits, ResourcePartitions};
 
     #[tokio::test]
     async fn test_pool_creation() {
@@ -340,6 +844,116 @@ mod tests {
         assert!(cage2.is_some());
     }
 
+    #[tokio::test]
+    async fn test_partition_limits_pool() {
+        let wasm_bytes = wat::parse_str("(module)").unwrap();
+        let partitions = Arc::new(ResourcePartitions::new());
+        partitions.set_limits("acme", PartitionLimits {
+            max_instances: Some(3),
+            ..Default::default()
+        });
+        let partition = PartitionHandle::new(partitions.clone(), "acme");
+        
+        let pool = CagePool::new_in_partition(
+            "blog".to_string(),
+            wasm_bytes.clone(),
+            CageConfig::default(),
+            2,
+            partition.clone(),
+        ).await.unwrap();
+        assert_eq!(partitions.usage("acme").instances, 2);
+        
+        // A second site of the same tenant only has room for one more Cage
+        let shop = CagePool::new_in_partition(
+            "shop".to_string(),
+            wasm_bytes.clone(),
+            CageConfig::default(),
+            2,
+            partition,
+        ).await;
+        assert!(shop.is_err());
+        
+        assert!(pool.scale(4, &wasm_bytes).await.is_err());
+        pool.scale(1, &wasm_bytes).await.unwrap();
+        assert_eq!(pool.size().await, 1);
+        assert_eq!(partitions.usage("acme").instances, 1);
+    }
+
+    #[tokio::test]
+    async fn test_site_quota_limits_pool() {
+        use crate::tenancy::{ResourceQuota, TenantManager};
+
+        let wasm_bytes = wat::parse_str("(module)").unwrap();
+        let tenants = Arc::new(TenantManager::new());
+        let tenant_id = tenants.create_tenant("acme".to_string(), "ops@acme.test".to_string(), ResourceQuota {
+            max_cages_per_site: 2,
+            max_memory_per_cage_mb: 128,
+            ..Default::default()
+        }).unwrap();
+        let site_id = tenants.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        let quota = tenants.site_quota(&site_id).unwrap();
+        let cage_count = || tenants.get_tenant(tenant_id).unwrap().sites[0].cage_count;
+
+        let create = |replicas, config| CagePool::new_with_quota(site_id.clone(), wasm_bytes.clone(), config, replicas, quota.clone());
+        assert!(create(3, CageConfig::default()).await.is_err());
+        assert!(create(1, CageConfig::development()).await.is_err());
+        assert_eq!(cage_count(), 0);
+
+        let pool = create(2, CageConfig::default()).await.unwrap();
+        assert_eq!(cage_count(), 2);
+        assert!(pool.scale(3, &wasm_bytes).await.is_err());
+        pool.scale(1, &wasm_bytes).await.unwrap();
+        assert_eq!(cage_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hot_swap() {
+        let wasm_bytes = wat::parse_str("(module)").unwrap();
+        let pool = CagePool::new("blog".to_string(), wasm_bytes, CageConfig::default(), 2).await.unwrap();
+        let old = pool.cages().await;
+
+        let swapped = wat::parse_str("(module (func (export \"v2\")))").unwrap();
+        assert_eq!(pool.hot_swap(&swapped).await.unwrap(), 2);
+        let fresh = pool.cages().await;
+        assert_eq!(fresh.len(), 2);
+        assert!(fresh.iter().all(|cage| old.iter().all(|old| old.id() != cage.id())));
+
+        // The retired Cages drain in the background
+        for _ in 0..50 {
+            if futures::future::join_all(old.iter().map(|cage| cage.state())).await
+                .iter()
+                .all(|state| *state == CageState::Terminated)
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(old[0].state().await, CageState::Terminated);
+
+        // A module that can't start leaves the pool as it was
+        assert!(pool.hot_swap(b"not wasm").await.is_err());
+        assert!(pool.cages().await.iter().zip(&fresh).all(|(cage, fresh)| Arc::ptr_eq(cage, fresh)));
+    }
+
+    #[tokio::test]
+    async fn test_hot_swap_needs_partition_room() {
+        let wasm_bytes = wat::parse_str("(module)").unwrap();
+        let partitions = Arc::new(ResourcePartitions::new());
+        partitions.set_limits("acme", PartitionLimits {
+            max_instances: Some(3),
+            ..Default::default()
+        });
+        let partition = PartitionHandle::new(partitions.clone(), "acme");
+        let pool = CagePool::new_in_partition("blog".to_string(), wasm_bytes.clone(), CageConfig::default(), 2, partition)
+            .await
+            .unwrap();
+
+        // Both sets are held while the old one drains
+        assert!(pool.hot_swap(&wasm_bytes).await.is_err());
+        assert_eq!(pool.size().await, 2);
+        assert_eq!(partitions.usage("acme").instances, 2);
+    }
+
     #[tokio::test]
     async fn test_health_stats() {
         let wat = r#"(module)"#;
@@ -358,4 +972,52 @@ mod tests {
         assert!(stats.is_healthy());
         assert!(stats.health_percentage() > 0.0);
     }
+
+    #[tokio::test]
+    async fn test_standby_promoted_on_crash() {
+        let wasm_bytes = wat::parse_str(r#"(module)"#).unwrap();
+        let config = CageConfig { standby_replicas: 1, ..CageConfig::default() };
+        let pool = CagePool::new("test-site".to_string(), wasm_bytes.clone(), config, 2).await.unwrap();
+        assert_eq!(pool.size().await, 2);
+        assert_eq!(pool.health_stats().await.standby_cages, 1);
+
+        // The standby takes the crashed Cage's place at once, outside any healing
+        let crashed = pool.cages().await[0].clone();
+        pool.crash(&crashed).await;
+        let stats = pool.health_stats().await;
+        assert_eq!((stats.healthy_cages, stats.crashed_cages, stats.standby_cages), (2, 0, 0));
+        assert!(!pool.cages().await.iter().any(|cage| Arc::ptr_eq(cage, &crashed)));
+
+        // Healing starts a new standby
+        let repair = pool.maintain_replicas(&wasm_bytes).await.unwrap();
+        assert_eq!((repair.removed, repair.spawned), (0, 1));
+        assert_eq!(pool.health_stats().await.standby_cages, 1);
+    }
+
+    #[tokio::test]
+    async fn test_lazy_and_scale_to_zero() {
+        let wasm_bytes = wat::parse_str(r#"(module)"#).unwrap();
+        let lazy = CageConfig { start: StartPolicy::Lazy, ..CageConfig::default() };
+        let pool = Arc::new(CagePool::new("test-site".to_string(), wasm_bytes.clone(), lazy, 1).await.unwrap());
+        assert_eq!(pool.size().await, 0);
+        assert!(pool.health_stats().await.cold);
+        assert!(pool.health_stats().await.is_healthy());
+
+        assert!(pool.wake().await.unwrap());
+        assert!(!pool.wake().await.unwrap());
+        assert_eq!(pool.size().await, 1);
+        assert_eq!(pool.cold_starts().count(), 1);
+        // Lazy pools keep their Cages once started
+        assert_eq!(pool.scale_to_zero_if_idle().await, 0);
+
+        let idle = CageConfig { start: StartPolicy::ScaleToZero, idle_timeout_secs: 1, ..CageConfig::default() };
+        let pool = Arc::new(CagePool::new("test-site".to_string(), wasm_bytes, idle, 1).await.unwrap());
+        pool.wake().await.unwrap();
+        assert_eq!(pool.scale_to_zero_if_idle().await, 0);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(pool.scale_to_zero_if_idle().await, 1);
+        assert!(pool.is_cold().await);
+        assert!(pool.wake().await.unwrap());
+        assert_eq!(pool.cold_starts().count(), 2);
+    }
 }
@@ -0,0 +1,329 @@
+// Guest Profiling
+// Samples where site guests spend their time, kept as folded stacks for flamegraph tools
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use wasmtime::{Engine, ProfilingStrategy, Store, UpdateDeadline, WasmBacktrace};
+
+/// Stack that samples are counted under once a profile holds `max_stacks` distinct stacks
+const OTHER_STACK: &str = "[other]";
+
+/// Native profiler support compiled into guest code, for profiling the whole host
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JitProfiler {
+    #[default]
+    None,
+
+    /// `/tmp/perf-<pid>.map` symbols for `perf`
+    PerfMap,
+
+    /// `jit-<pid>.dump` files for `perf inject`
+    JitDump,
+
+    /// Intel VTune
+    VTune,
+}
+
+impl JitProfiler {
+    pub fn strategy(&self) -> ProfilingStrategy {
+        match self {
+            JitProfiler::None => ProfilingStrategy::None,
+            JitProfiler::PerfMap => ProfilingStrategy::PerfMap,
+            JitProfiler::JitDump => ProfilingStrategy::JitDump,
+            JitProfiler::VTune => ProfilingStrategy::VTune,
+        }
+    }
+}
+
+/// `[profiling]`: host profiler support and sampling of site guests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfilingConfig {
+    pub jit_profiler: JitProfiler,
+
+    /// Sites whose guests are sampled
+    pub sites: Vec<String>,
+
+    /// Time between samples
+    pub interval_ms: u64,
+
+    /// Distinct stacks kept per site; samples of further stacks are counted as `[other]`
+    pub max_stacks: usize,
+}
+
+impl Default for ProfilingConfig {
+    fn default() -> Self {
+        Self {
+            jit_profiler: JitProfiler::None,
+            sites: Vec::new(),
+            interval_ms: 10,
+            max_stacks: 10_000,
+        }
+    }
+}
+
+impl ProfilingConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.interval_ms == 0 {
+            anyhow::bail!("interval_ms must be at least 1");
+        }
+        if self.max_stacks == 0 {
+            anyhow::bail!("max_stacks must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Sample counts and coverage of a site's profile
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileStats {
+    pub site_id: String,
+    pub interval_ms: u64,
+    pub samples: u64,
+    pub stacks: usize,
+
+    /// Unix timestamp the profile was started or last reset at
+    pub since: i64,
+}
+
+/// Engines whose epochs the ticker advances
+#[derive(Default)]
+struct Ticking {
+    engines: Vec<(u64, Engine)>,
+    running: bool,
+}
+
+/// Sampling profile of one site's guests
+/// Guests are interrupted every interval through Wasmtime's epochs and their stack is counted.
+pub struct Profiler {
+    site_id: String,
+    interval: Duration,
+    max_stacks: usize,
+    stacks: Mutex<HashMap<String, u64>>,
+    samples: AtomicU64,
+    since: Mutex<i64>,
+    ticking: Mutex<Ticking>,
+    next_engine: AtomicU64,
+}
+
+impl Profiler {
+    pub fn new(site_id: &str, config: &ProfilingConfig) -> Self {
+        Self {
+            site_id: site_id.to_string(),
+            interval: Duration::from_millis(config.interval_ms),
+            max_stacks: config.max_stacks,
+            stacks: Mutex::new(HashMap::new()),
+            samples: AtomicU64::new(0),
+            since: Mutex::new(chrono::Utc::now().timestamp()),
+            ticking: Mutex::new(Ticking::default()),
+            next_engine: AtomicU64::new(0),
+        }
+    }
+
+    /// Advance the engine's epoch every interval until the guard is dropped
+    /// The engine must have epoch interruption enabled, see `create_engine_for`.
+    pub fn attach(self: &Arc<Self>, engine: &Engine) -> ProfilerGuard {
+        let id = self.next_engine.fetch_add(1, Ordering::Relaxed);
+        let mut ticking = self.ticking.lock();
+        ticking.engines.push((id, engine.clone()));
+        if !ticking.running {
+            ticking.running = true;
+            let profiler = Arc::downgrade(self);
+            let interval = self.interval;
+            let spawned = std::thread::Builder::new()
+                .name(format!("pear-profiler-{}", self.site_id))
+                .spawn(move || tick(profiler, interval));
+            if let Err(e) = spawned {
+                tracing::warn!(site_id = %self.site_id, error = %e, "Failed to start guest profiler");
+                ticking.running = false;
+            }
+        }
+        ProfilerGuard { profiler: self.clone(), id }
+    }
+
+    /// Sample the store's guest whenever its engine's epoch advances
+    pub fn watch<T>(self: &Arc<Self>, store: &mut Store<T>) {
+        let profiler = self.clone();
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(move |context| {
+            profiler.sample(&WasmBacktrace::capture(&context));
+            Ok(UpdateDeadline::Continue(1))
+        });
+    }
+
+    /// Count a stack, innermost frame first as Wasmtime captures it
+    pub fn sample(&self, backtrace: &WasmBacktrace) {
+        if backtrace.frames().is_empty() {
+            return;
+        }
+        let mut stack = String::new();
+        for frame in backtrace.frames().iter().rev() {
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            match frame.func_name() {
+                // `;` separates frames and the last space the count in the folded format
+                Some(name) => stack.push_str(&name.replace(';', ":").replace(' ', "_")),
+                None => {
+                    let _ = write!(stack, "wasm-function[{}]", frame.func_index());
+                }
+            }
+        }
+        self.count(stack);
+    }
+
+    fn count(&self, stack: String) {
+        self.samples.fetch_add(1, Ordering::Relaxed);
+        let mut stacks = self.stacks.lock();
+        if let Some(count) = stacks.get_mut(&stack) {
+            *count += 1;
+        } else if stacks.len() < self.max_stacks {
+            stacks.insert(stack, 1);
+        } else {
+            *stacks.entry(OTHER_STACK.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// The profile as `frame;frame;frame count` lines, for flamegraph.pl, inferno or speedscope
+    pub fn folded(&self) -> String {
+        let stacks = self.stacks.lock();
+        let mut lines: Vec<_> = stacks.iter().collect();
+        lines.sort();
+        let mut out = String::new();
+        for (stack, count) in lines {
+            let _ = writeln!(out, "{} {}", stack, count);
+        }
+        out
+    }
+
+    pub fn stats(&self) -> ProfileStats {
+        ProfileStats {
+            site_id: self.site_id.clone(),
+            interval_ms: self.interval.as_millis() as u64,
+            samples: self.samples.load(Ordering::Relaxed),
+            stacks: self.stacks.lock().len(),
+            since: *self.since.lock(),
+        }
+    }
+
+    /// Start the profile over
+    pub fn reset(&self) {
+        self.stacks.lock().clear();
+        self.samples.store(0, Ordering::Relaxed);
+        *self.since.lock() = chrono::Utc::now().timestamp();
+    }
+}
+
+impl std::fmt::Debug for Profiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Profiler").field("site_id", &self.site_id).finish()
+    }
+}
+
+/// Advance attached engines' epochs until none are left
+fn tick(profiler: Weak<Profiler>, interval: Duration) {
+    loop {
+        std::thread::sleep(interval);
+        let Some(profiler) = profiler.upgrade() else { return };
+        let mut ticking = profiler.ticking.lock();
+        if ticking.engines.is_empty() {
+            ticking.running = false;
+            return;
+        }
+        for (_, engine) in &ticking.engines {
+            engine.increment_epoch();
+        }
+    }
+}
+
+/// Keeps an engine's guests sampled; held by the Cage that owns the engine
+pub struct ProfilerGuard {
+    profiler: Arc<Profiler>,
+    id: u64,
+}
+
+impl Drop for ProfilerGuard {
+    fn drop(&mut self) {
+        self.profiler.ticking.lock().engines.retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Profiles of every sampled site
+#[derive(Default)]
+pub struct ProfileStore {
+    profilers: DashMap<String, Arc<Profiler>>,
+}
+
+impl ProfileStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The site's profiler, kept across redeploys so the profile covers them
+    pub fn profiler(&self, site_id: &str, config: &ProfilingConfig) -> Arc<Profiler> {
+        self.profilers.entry(site_id.to_string())
+            .or_insert_with(|| Arc::new(Profiler::new(site_id, config)))
+            .clone()
+    }
+
+    pub fn get(&self, site_id: &str) -> Option<Arc<Profiler>> {
+        self.profilers.get(site_id).map(|profiler| profiler.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::{Config, Instance, Module};
+
+    #[test]
+    fn test_samples_busy_guest() {
+        let wat = r#"
+            (module
+                (func $spin (param i32)
+                    (loop $again
+                        (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                        (br_if $again (local.get 0))))
+                (func (export "handle") (param i32)
+                    (call $spin (local.get 0))))
+        "#;
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).unwrap();
+        let module = Module::new(&engine, wat::parse_str(wat).unwrap()).unwrap();
+        let profiler = Arc::new(Profiler::new("blog", &ProfilingConfig { interval_ms: 1, ..Default::default() }));
+        let guard = profiler.attach(&engine);
+
+        let mut store = Store::new(&engine, ());
+        profiler.watch(&mut store);
+        let instance = Instance::new(&mut store, &module, &[]).unwrap();
+        let handle = instance.get_typed_func::<i32, ()>(&mut store, "handle").unwrap();
+        let started = std::time::Instant::now();
+        while profiler.stats().samples < 3 && started.elapsed() < Duration::from_secs(10) {
+            handle.call(&mut store, 50_000_000).unwrap();
+        }
+        drop(guard);
+
+        assert!(profiler.stats().samples >= 3);
+        let folded = profiler.folded();
+        assert!(folded.lines().all(|line| line.starts_with("wasm-function[1];spin ")), "{}", folded);
+        profiler.reset();
+        assert_eq!(profiler.stats().samples, 0);
+    }
+
+    #[test]
+    fn test_stack_limit() {
+        let profiler = Profiler::new("blog", &ProfilingConfig { max_stacks: 2, ..Default::default() });
+        for stack in ["main;a", "main;b", "main;c", "main;a", "main;d"] {
+            profiler.count(stack.to_string());
+        }
+        assert_eq!(profiler.folded(), "[other] 2\nmain;a 2\nmain;b 1\n");
+    }
+}
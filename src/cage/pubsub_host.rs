@@ -0,0 +1,191 @@
+// Pub/Sub Host Functions
+// Lets guests publish and subscribe to site topics as the `pear_pubsub` import module
+
+use anyhow::{Result, bail};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::debug;
+use wasmtime::{Caller, Linker};
+use wasmtime_wasi::WasiCtx;
+
+use super::guest_memory::{copy_out, memory, read, slice};
+use crate::crdt::pubsub::GuestSubscriber;
+
+/// Import module guests link against
+pub const MODULE: &str = "pear_pubsub";
+
+/// Topic subscription, publishing and message-polling calls available to guests
+pub const FUNCTIONS: &[&str] = &["subscribe", "unsubscribe", "publish", "poll", "read_message", "read_error"];
+
+/// Longest topic name a guest may pass
+const MAX_TOPIC_BYTES: usize = 1024;
+
+/// Output waiting for the guest to copy it out
+#[derive(Default)]
+struct Pending {
+    message: Vec<u8>,
+    error: Vec<u8>,
+}
+
+/// Register the `pear_pubsub` imports:
+/// - `subscribe(topic_ptr, topic_len) -> i32`: 0, or -1 on error
+/// - `unsubscribe(topic_ptr, topic_len) -> i32`: 1 if the topic was subscribed, else 0
+/// - `publish(topic_ptr, topic_len, data_ptr, data_len) -> i32`: local deliveries, or -1 on error
+/// - `poll() -> i32`: length of the next message, 0 when the inbox is empty
+/// - `read_message(ptr, len) -> i32`: move up to `len` bytes of the polled message into guest memory
+/// - `read_error(ptr, len) -> i32`: copy the last error message into guest memory
+///
+/// Messages are `{"topic": ..., "data": ...}`; every instance has its own subscriptions and inbox.
+pub fn add_to_linker(linker: &mut Linker<WasiCtx>, subscriber: GuestSubscriber) -> Result<()> {
+    let subscriber = Arc::new(subscriber);
+    let pending = Arc::new(Mutex::new(Pending::default()));
+
+    let (sub, sub_pending) = (subscriber.clone(), pending.clone());
+    linker.func_wrap(
+        MODULE,
+        "subscribe",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let topic = read_str(&mut caller, ptr, len)?;
+            Ok(finish(&sub_pending, sub.subscribe(&topic).map(|()| 0)))
+        },
+    )?;
+
+    let unsub = subscriber.clone();
+    linker.func_wrap(
+        MODULE,
+        "unsubscribe",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let topic = read_str(&mut caller, ptr, len)?;
+            Ok(unsub.unsubscribe(&topic) as i32)
+        },
+    )?;
+
+    let (publisher, pub_pending) = (subscriber.clone(), pending.clone());
+    linker.func_wrap(
+        MODULE,
+        "publish",
+        move |mut caller: Caller<'_, WasiCtx>, topic_ptr: i32, topic_len: i32, data_ptr: i32, data_len: i32| -> Result<i32> {
+            let topic = read_str(&mut caller, topic_ptr, topic_len)?;
+            let memory = memory(&mut caller)?;
+            let Some(data) = slice(&caller, memory, data_ptr, data_len) else {
+                bail!("Message is outside guest memory");
+            };
+            let outcome = publisher.publish(&topic, data).map(|delivered| delivered as i32);
+            Ok(finish(&pub_pending, outcome))
+        },
+    )?;
+
+    let poll_pending = pending.clone();
+    linker.func_wrap(MODULE, "poll", move || -> Result<i32> {
+        let mut pending = poll_pending.lock();
+        pending.message = match subscriber.next() {
+            Some(message) => serde_json::to_vec(&serde_json::json!({
+                "topic": message.topic,
+                "data": message.data,
+            }))?,
+            None => Vec::new(),
+        };
+        Ok(pending.message.len() as i32)
+    })?;
+
+    let message_pending = pending.clone();
+    linker.func_wrap(
+        MODULE,
+        "read_message",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let mut pending = message_pending.lock();
+            let count = copy_out(&mut caller, ptr, len, &pending.message)?;
+            pending.message.drain(..count as usize);
+            Ok(count)
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "read_error",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let pending = pending.lock();
+            copy_out(&mut caller, ptr, len, &pending.error)
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Record a call's error for `read_error`, returning the value handed back to the guest
+fn finish(pending: &Mutex<Pending>, outcome: Result<i32>) -> i32 {
+    let mut pending = pending.lock();
+    match outcome {
+        Ok(value) => {
+            pending.error.clear();
+            value
+        }
+        Err(e) => {
+            debug!(error = %e, "Guest pub/sub call failed");
+            pending.error = format!("{:#}", e).into_bytes();
+            -1
+        }
+    }
+}
+
+/// Read a topic name; bad pointers trap the guest
+fn read_str(caller: &mut Caller<'_, WasiCtx>, ptr: i32, len: i32) -> Result<String> {
+    Ok(String::from_utf8(read(caller, ptr, len, MAX_TOPIC_BYTES)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::pubsub::{PubSub, PubSubConfig};
+    use wasmtime::{Engine, Module, Store};
+    use wasmtime_wasi::WasiCtxBuilder;
+
+    const GUEST: &str = r#"
+        (module
+          (import "pear_pubsub" "subscribe" (func $subscribe (param i32 i32) (result i32)))
+          (import "pear_pubsub" "publish" (func $publish (param i32 i32 i32 i32) (result i32)))
+          (import "pear_pubsub" "poll" (func $poll (result i32)))
+          (import "pear_pubsub" "read_message" (func $read_message (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "cache")
+          (data (i32.const 16) "/posts/1")
+          (func (export "listen") (result i32)
+            (call $subscribe (i32.const 0) (i32.const 5)))
+          (func (export "invalidate") (result i32)
+            (call $publish (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 8)))
+          (func (export "receive") (result i32)
+            (drop (call $poll))
+            (call $read_message (i32.const 1024) (i32.const 1024))))
+    "#;
+
+    #[test]
+    fn test_guests_exchange_messages() {
+        let hub = Arc::new(PubSub::new(PubSubConfig::default()).unwrap());
+        let engine = Engine::default();
+        let module = Module::new(&engine, wat::parse_str(GUEST).unwrap()).unwrap();
+
+        let mut instances = Vec::new();
+        for _ in 0..2 {
+            let mut linker = Linker::new(&engine);
+            add_to_linker(&mut linker, hub.guest("blog").subscriber()).unwrap();
+            let mut store = Store::new(&engine, WasiCtxBuilder::new().build());
+            let instance = linker.instantiate(&mut store, &module).unwrap();
+            instances.push((store, instance));
+        }
+
+        let (store, listener) = &mut instances[1];
+        let listen = listener.get_typed_func::<(), i32>(&mut *store, "listen").unwrap();
+        assert_eq!(listen.call(&mut *store, ()).unwrap(), 0);
+
+        let (store, publisher) = &mut instances[0];
+        let invalidate = publisher.get_typed_func::<(), i32>(&mut *store, "invalidate").unwrap();
+        assert_eq!(invalidate.call(&mut *store, ()).unwrap(), 1);
+
+        let (store, listener) = &mut instances[1];
+        let receive = listener.get_typed_func::<(), i32>(&mut *store, "receive").unwrap();
+        let len = receive.call(&mut *store, ()).unwrap() as usize;
+        let memory = listener.get_memory(&mut *store, "memory").unwrap();
+        let message: serde_json::Value = serde_json::from_slice(&memory.data(&*store)[1024..1024 + len]).unwrap();
+        assert_eq!(message, serde_json::json!({ "topic": "cache", "data": "/posts/1" }));
+    }
+}
@@ -0,0 +1,123 @@
+// Task Queue Host Functions
+// Lets guests enqueue background tasks as the `pear_queue` import module
+
+use anyhow::{Result, bail};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::debug;
+use wasmtime::{Caller, Linker};
+use wasmtime_wasi::WasiCtx;
+
+use super::guest_memory::{copy_out, memory, slice};
+use crate::scheduler::queue::GuestQueue;
+
+/// Import module guests link against
+pub const MODULE: &str = "pear_queue";
+
+/// Calls for queueing background tasks and reading the running task's payload
+pub const FUNCTIONS: &[&str] = &["enqueue", "read_payload", "read_error"];
+
+/// Register the `pear_queue` imports:
+/// - `enqueue(payload_ptr, payload_len, delay_ms: i64) -> i64`: task id, or -1 on error
+/// - `read_payload(ptr, len) -> i32`: copy the running task's payload into guest memory
+/// - `read_error(ptr, len) -> i32`: copy the last error message into guest memory
+///
+/// Tasks are handled by the site's `handle_job(payload_len: i32) -> i32` export, which
+/// returns 0 on success; anything else, or a trap, is retried with backoff.
+pub fn add_to_linker(linker: &mut Linker<WasiCtx>, queue: GuestQueue, payload: Vec<u8>) -> Result<()> {
+    let last_error = Arc::new(Mutex::new(Vec::new()));
+
+    let enqueue_error = last_error.clone();
+    linker.func_wrap(
+        MODULE,
+        "enqueue",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32, delay_ms: i64| -> Result<i64> {
+            let memory = memory(&mut caller)?;
+            let Some(task) = slice(&caller, memory, ptr, len) else {
+                bail!("Task payload is outside guest memory");
+            };
+
+            match queue.enqueue(task, delay_ms.max(0) as u64) {
+                Ok(id) => {
+                    enqueue_error.lock().clear();
+                    Ok(id as i64)
+                }
+                Err(e) => {
+                    debug!(site_id = %queue.site_id(), error = %e, "Guest enqueue failed");
+                    *enqueue_error.lock() = format!("{:#}", e).into_bytes();
+                    Ok(-1)
+                }
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "read_payload",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            copy_out(&mut caller, ptr, len, &payload)
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "read_error",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let error = last_error.lock().clone();
+            copy_out(&mut caller, ptr, len, &error)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::queue::{QueueConfig, TaskQueue};
+    use wasmtime::{Engine, Module, Store};
+    use wasmtime_wasi::WasiCtxBuilder;
+
+    const GUEST: &str = r#"
+        (module
+          (import "pear_queue" "enqueue" (func $enqueue (param i32 i32 i64) (result i64)))
+          (import "pear_queue" "read_payload" (func $read_payload (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"send\": \"welcome\"}")
+          (func (export "submit") (result i64)
+            (call $enqueue (i32.const 0) (i32.const 19) (i64.const 0)))
+          (func (export "handle_job") (param $len i32) (result i32)
+            (drop (call $read_payload (i32.const 1024) (local.get $len)))
+            ;; Fail unless the payload starts with '{'
+            (i32.ne (i32.load8_u (i32.const 1024)) (i32.const 123))))
+    "#;
+
+    fn instance(queue: GuestQueue, payload: &[u8]) -> (Store<WasiCtx>, wasmtime::Instance) {
+        let engine = Engine::default();
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker, queue, payload.to_vec()).unwrap();
+        let module = Module::new(&engine, wat::parse_str(GUEST).unwrap()).unwrap();
+        let mut store = Store::new(&engine, WasiCtxBuilder::new().build());
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        (store, instance)
+    }
+
+    #[test]
+    fn test_guest_enqueue_and_payload() {
+        let queue = Arc::new(TaskQueue::new(QueueConfig { state_path: String::new(), ..Default::default() }).unwrap());
+
+        let (mut store, guest) = instance(queue.guest("blog"), b"");
+        let submit = guest.get_typed_func::<(), i64>(&mut store, "submit").unwrap();
+        assert_eq!(submit.call(&mut store, ()).unwrap(), 1);
+        assert_eq!(queue.stats("blog").pending, 1);
+
+        let payload = b"{\"send\": \"welcome\"}";
+        let (mut store, worker) = instance(queue.guest("blog"), payload);
+        let handle = worker.get_typed_func::<i32, i32>(&mut store, "handle_job").unwrap();
+        assert_eq!(handle.call(&mut store, payload.len() as i32).unwrap(), 0);
+
+        let (mut store, worker) = instance(queue.guest("blog"), b"oops");
+        let handle = worker.get_typed_func::<i32, i32>(&mut store, "handle_job").unwrap();
+        assert_eq!(handle.call(&mut store, 4).unwrap(), 1);
+    }
+}
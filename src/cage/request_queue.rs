@@ -0,0 +1,200 @@
+// Request Queue
+// Caps the requests each Cage runs at once; requests finding every Cage busy wait briefly, then are shed
+
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, OwnedSemaphorePermit};
+
+use crate::observability::histogram::{HistogramSnapshot, LatencyHistogram};
+
+/// Requests allowed to wait for a busy pool unless configured otherwise
+pub fn default_queue_depth() -> usize {
+    64
+}
+
+/// How long a request waits for a free Cage unless configured otherwise
+pub fn default_queue_timeout_ms() -> u64 {
+    500
+}
+
+/// Every Cage of the pool is at its concurrency cap, and the request could not wait for one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueRejected {
+    /// The queue already holds as many requests as it may
+    Full,
+    /// No Cage freed up within the queue timeout
+    TimedOut,
+}
+
+impl std::fmt::Display for QueueRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueRejected::Full => write!(f, "Request queue is full"),
+            QueueRejected::TimedOut => write!(f, "Timed out waiting for a free Cage"),
+        }
+    }
+}
+
+impl std::error::Error for QueueRejected {}
+
+/// Queue load for metrics
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueStats {
+    pub queued: usize,
+    pub rejected: u64,
+    pub timed_out: u64,
+    /// Time requests waited for a Cage, those served at once included
+    pub wait: HistogramSnapshot,
+}
+
+/// Requests of one pool waiting for a Cage below its `max_concurrent_requests`
+pub struct RequestQueue {
+    depth: usize,
+    timeout: Duration,
+    queued: AtomicUsize,
+    freed: Arc<Notify>,
+    wait: LatencyHistogram,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+impl RequestQueue {
+    pub fn new(depth: usize, timeout: Duration) -> Self {
+        Self {
+            depth,
+            timeout,
+            queued: AtomicUsize::new(0),
+            freed: Arc::new(Notify::new()),
+            wait: LatencyHistogram::new(),
+            rejected: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+        }
+    }
+
+    /// A slot from the first Cage `reserve` finds one on, waiting for Cages to free up if none has
+    /// `reserve` returns `Ok(None)` when every Cage is busy and `Err` when there are none to wait for.
+    pub async fn acquire<T, F, Fut>(&self, mut reserve: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<Option<T>>>,
+    {
+        let started = Instant::now();
+        let deadline = started + self.timeout;
+        let mut waiting = None;
+        loop {
+            // Listen before looking, so a slot freed in between still wakes this request
+            let freed = self.freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+
+            if let Some(reserved) = reserve().await? {
+                self.wait.record(started.elapsed());
+                return Ok(reserved);
+            }
+            if waiting.is_none() {
+                waiting = Some(self.enqueue()?);
+            }
+            if tokio::time::timeout_at(deadline.into(), freed).await.is_err() {
+                self.timed_out.fetch_add(1, Ordering::Relaxed);
+                return Err(QueueRejected::TimedOut.into());
+            }
+        }
+    }
+
+    /// Take `permit` as a slot that wakes waiting requests when released
+    pub fn slot(&self, permit: OwnedSemaphorePermit) -> CageSlot {
+        CageSlot { permit: Some(permit), freed: self.freed.clone() }
+    }
+
+    fn enqueue(&self) -> Result<Waiting<'_>> {
+        if self.queued.fetch_add(1, Ordering::AcqRel) >= self.depth {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(QueueRejected::Full.into());
+        }
+        Ok(Waiting(&self.queued))
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            queued: self.queued.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            wait: self.wait.snapshot(),
+        }
+    }
+}
+
+/// One of a Cage's `max_concurrent_requests`, held for as long as a request runs on it
+pub struct CageSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    freed: Arc<Notify>,
+}
+
+impl Drop for CageSlot {
+    fn drop(&mut self) {
+        // Free the slot before waking waiters, or they could look too early and miss it
+        drop(self.permit.take());
+        self.freed.notify_waiters();
+    }
+}
+
+/// A request counted in the queue until it gets a slot or gives up
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Semaphore;
+
+    /// A slot from `cage`'s semaphore, or `None` while it is busy
+    async fn try_reserve(queue: &RequestQueue, cage: &Arc<Semaphore>) -> Result<Option<CageSlot>> {
+        Ok(cage.clone().try_acquire_owned().ok().map(|permit| queue.slot(permit)))
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_a_freed_slot() {
+        let queue = Arc::new(RequestQueue::new(1, Duration::from_secs(5)));
+        let cage = Arc::new(Semaphore::new(1));
+        let running = queue.acquire(|| try_reserve(&queue, &cage)).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let (queue, cage) = (queue.clone(), cage.clone());
+            async move { queue.acquire(|| try_reserve(&queue, &cage)).await.map(|_| ()) }
+        });
+        while queue.stats().queued == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // A second waiter finds the queue full
+        let full = queue.acquire(|| try_reserve(&queue, &cage)).await.err().unwrap();
+        assert_eq!(full.downcast_ref::<QueueRejected>(), Some(&QueueRejected::Full));
+
+        drop(running);
+        waiting.await.unwrap().unwrap();
+        let stats = queue.stats();
+        assert_eq!((stats.queued, stats.rejected, stats.timed_out), (0, 1, 0));
+        assert_eq!(stats.wait.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_times_out() {
+        let queue = RequestQueue::new(8, Duration::from_millis(20));
+        let cage = Arc::new(Semaphore::new(1));
+        let _running = queue.acquire(|| try_reserve(&queue, &cage)).await.unwrap();
+
+        let timed_out = queue.acquire(|| try_reserve(&queue, &cage)).await.err().unwrap();
+        assert_eq!(timed_out.downcast_ref::<QueueRejected>(), Some(&QueueRejected::TimedOut));
+        assert_eq!(queue.stats().timed_out, 1);
+        assert_eq!(queue.stats().queued, 0);
+    }
+}
@@ -0,0 +1,183 @@
+// Session Host Functions
+// Lets guests create, load and end user sessions as the `pear_session` import module
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::debug;
+use wasmtime::{Caller, Linker};
+use wasmtime_wasi::WasiCtx;
+
+use super::guest_memory::copy_out;
+use crate::crdt::session::{GuestSessions, UserSession};
+
+/// Import module guests link against
+pub const MODULE: &str = "pear_session";
+
+/// Session lifecycle calls, keyed by the cookie the guest passes in
+pub const FUNCTIONS: &[&str] = &["create", "load", "save", "destroy", "read_result", "read_error"];
+
+/// Largest session a guest may save
+const MAX_SESSION_BYTES: usize = 64 * 1024;
+
+/// Output waiting for the guest to copy it out
+#[derive(Default)]
+struct Pending {
+    result: Vec<u8>,
+    error: Vec<u8>,
+}
+
+/// Register the `pear_session` imports:
+/// - `create() -> i32`: length of `{"session": ..., "cookie": ...}`, or -1 on error
+/// - `load(cookie_ptr, cookie_len) -> i32`: length of the session JSON, 0 if it is unknown or expired, -1 on error
+/// - `save(session_ptr, session_len) -> i32`: 0, or -1 on error
+/// - `destroy(cookie_ptr, cookie_len) -> i32`: length of the `Set-Cookie` value clearing the cookie, or -1 on error
+/// - `read_result(ptr, len) -> i32`: copy the last result into guest memory
+/// - `read_error(ptr, len) -> i32`: copy the last error message into guest memory
+///
+/// `load` and `destroy` take the request's `Cookie` header or a bare session ID. The guest
+/// sends the `cookie` values back as `Set-Cookie` response headers.
+pub fn add_to_linker(linker: &mut Linker<WasiCtx>, sessions: GuestSessions) -> Result<()> {
+    let sessions = Arc::new(sessions);
+    let pending = Arc::new(Mutex::new(Pending::default()));
+
+    let (creator, create_pending) = (sessions.clone(), pending.clone());
+    linker.func_wrap(MODULE, "create", move || -> Result<i32> {
+        let outcome = creator.create().and_then(|(session, cookie)| {
+            Ok(serde_json::to_vec(&serde_json::json!({ "session": session, "cookie": cookie }))?)
+        });
+        Ok(finish(&create_pending, outcome))
+    })?;
+
+    let (loader, load_pending) = (sessions.clone(), pending.clone());
+    linker.func_wrap(
+        MODULE,
+        "load",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let cookie = String::from_utf8(read(&mut caller, ptr, len)?)?;
+            let outcome = loader.load(&cookie).and_then(|session| match session {
+                Some(session) => Ok(serde_json::to_vec(&session)?),
+                None => Ok(Vec::new()),
+            });
+            Ok(finish(&load_pending, outcome))
+        },
+    )?;
+
+    let (saver, save_pending) = (sessions.clone(), pending.clone());
+    linker.func_wrap(
+        MODULE,
+        "save",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let json = read(&mut caller, ptr, len)?;
+            let outcome = serde_json::from_slice::<UserSession>(&json)
+                .map_err(anyhow::Error::from)
+                .and_then(|session| saver.save(session))
+                .map(|()| Vec::new());
+            Ok(finish(&save_pending, outcome))
+        },
+    )?;
+
+    let destroy_pending = pending.clone();
+    linker.func_wrap(
+        MODULE,
+        "destroy",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let cookie = String::from_utf8(read(&mut caller, ptr, len)?)?;
+            let outcome = sessions.destroy(&cookie).map(|(_, clear)| clear.into_bytes());
+            Ok(finish(&destroy_pending, outcome))
+        },
+    )?;
+
+    let result_pending = pending.clone();
+    linker.func_wrap(
+        MODULE,
+        "read_result",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let pending = result_pending.lock();
+            copy_out(&mut caller, ptr, len, &pending.result)
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "read_error",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            let pending = pending.lock();
+            copy_out(&mut caller, ptr, len, &pending.error)
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Keep a call's result or error for the guest to read, returning the result's length or -1
+fn finish(pending: &Mutex<Pending>, outcome: Result<Vec<u8>>) -> i32 {
+    let mut pending = pending.lock();
+    match outcome {
+        Ok(result) => {
+            pending.error.clear();
+            pending.result = result;
+            pending.result.len() as i32
+        }
+        Err(e) => {
+            debug!(error = %e, "Guest session call failed");
+            pending.result.clear();
+            pending.error = format!("{:#}", e).into_bytes();
+            -1
+        }
+    }
+}
+
+/// Read a cookie or session; bad pointers trap the guest
+fn read(caller: &mut Caller<'_, WasiCtx>, ptr: i32, len: i32) -> Result<Vec<u8>> {
+    super::guest_memory::read(caller, ptr, len, MAX_SESSION_BYTES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::session::{SessionConfig, SessionManager};
+    use crate::crdt::DocumentStore;
+    use wasmtime::{Engine, Module, Store};
+    use wasmtime_wasi::WasiCtxBuilder;
+
+    const GUEST: &str = r#"
+        (module
+          (import "pear_session" "create" (func $create (result i32)))
+          (import "pear_session" "load" (func $load (param i32 i32) (result i32)))
+          (import "pear_session" "read_result" (func $read_result (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "create") (result i32)
+            (drop (call $create))
+            (call $read_result (i32.const 1024) (i32.const 4096)))
+          (func (export "load") (param i32) (result i32)
+            (drop (call $load (i32.const 0) (local.get 0)))
+            (call $read_result (i32.const 1024) (i32.const 4096))))
+    "#;
+
+    #[test]
+    fn test_guest_creates_and_loads_session() {
+        let manager = Arc::new(SessionManager::new(SessionConfig::default(), Arc::new(DocumentStore::default())));
+        let engine = Engine::default();
+        let module = Module::new(&engine, wat::parse_str(GUEST).unwrap()).unwrap();
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker, manager.guest("blog")).unwrap();
+        let mut store = Store::new(&engine, WasiCtxBuilder::new().build());
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+        let create = instance.get_typed_func::<(), i32>(&mut store, "create").unwrap();
+        let len = create.call(&mut store, ()).unwrap() as usize;
+        let created: serde_json::Value = serde_json::from_slice(&memory.data(&store)[1024..1024 + len]).unwrap();
+        let session_id = created["session"]["session_id"].as_str().unwrap().to_string();
+        assert!(created["cookie"].as_str().unwrap().starts_with(&format!("pear_session={}", session_id)));
+
+        // The guest passes the request's Cookie header straight through
+        let header = format!("theme=dark; pear_session={}", session_id);
+        memory.write(&mut store, 0, header.as_bytes()).unwrap();
+        let load = instance.get_typed_func::<i32, i32>(&mut store, "load").unwrap();
+        let len = load.call(&mut store, header.len() as i32).unwrap() as usize;
+        let loaded: UserSession = serde_json::from_slice(&memory.data(&store)[1024..1024 + len]).unwrap();
+        assert_eq!(loaded.session_id, session_id);
+    }
+}
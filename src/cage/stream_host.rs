@@ -0,0 +1,109 @@
+// Stream Host Functions
+// Lets guests write responses progressively as the `pear_stream` import module
+
+use anyhow::{Result, bail};
+use std::sync::Arc;
+use wasmtime::{Caller, Linker};
+use wasmtime_wasi::WasiCtx;
+
+use super::guest_memory::{copy_out, memory, slice};
+use crate::router::stream::StreamSink;
+
+/// Import module guests link against
+pub const MODULE: &str = "pear_stream";
+
+/// Calls a streaming guest uses to read its request and send the response in chunks
+pub const FUNCTIONS: &[&str] = &["read_request", "write", "flush"];
+
+/// Register the `pear_stream` imports:
+/// - `read_request(ptr, len) -> i32`: copy the request into guest memory
+/// - `write(ptr, len)`: append to the current chunk
+/// - `flush() -> i32`: send the chunk to the client; 0 on success, -1 once the client is gone
+///
+/// The streaming export is `(request_len: i32) -> i32`. `flush` waits while the client
+/// is behind, and the response ends when the export returns.
+pub fn add_to_linker(linker: &mut Linker<WasiCtx>, sink: Arc<StreamSink>, request: Vec<u8>) -> Result<()> {
+    linker.func_wrap(
+        MODULE,
+        "read_request",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<i32> {
+            copy_out(&mut caller, ptr, len, &request)
+        },
+    )?;
+
+    let write_sink = sink.clone();
+    linker.func_wrap(
+        MODULE,
+        "write",
+        move |mut caller: Caller<'_, WasiCtx>, ptr: i32, len: i32| -> Result<()> {
+            let memory = memory(&mut caller)?;
+            let Some(data) = slice(&caller, memory, ptr, len) else {
+                bail!("Stream data is outside guest memory");
+            };
+            write_sink.write(data);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "flush",
+        move || -> i32 {
+            if sink.flush() { 0 } else { -1 }
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::stream::{StreamingConfig, channel};
+    use crate::state::StreamAccounting;
+    use http_body_util::BodyExt;
+    use wasmtime::{Engine, Module, Store};
+    use wasmtime_wasi::WasiCtxBuilder;
+
+    // Echoes the request as two SSE events, stopping early if the client leaves
+    const GUEST: &str = r#"
+        (module
+          (import "pear_stream" "read_request" (func $read_request (param i32 i32) (result i32)))
+          (import "pear_stream" "write" (func $write (param i32 i32)))
+          (import "pear_stream" "flush" (func $flush (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "data: ")
+          (data (i32.const 8) "\n\n")
+          (func (export "handle_stream") (param $len i32) (result i32)
+            (local $i i32)
+            (drop (call $read_request (i32.const 64) (local.get $len)))
+            (loop $events
+              (call $write (i32.const 0) (i32.const 6))
+              (call $write (i32.const 64) (local.get $len))
+              (call $write (i32.const 8) (i32.const 2))
+              (if (i32.ne (call $flush) (i32.const 0)) (then (return (i32.const 1))))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br_if $events (i32.lt_u (local.get $i) (i32.const 2))))
+            (i32.const 0)))
+    "#;
+
+    #[tokio::test]
+    async fn test_guest_stream() {
+        let (sink, body) = channel(&StreamingConfig::default(), Arc::new(StreamAccounting::default()));
+
+        let guest = tokio::task::spawn_blocking(move || {
+            let engine = Engine::default();
+            let mut linker = Linker::new(&engine);
+            add_to_linker(&mut linker, Arc::new(sink), b"hi".to_vec()).unwrap();
+            let module = Module::new(&engine, wat::parse_str(GUEST).unwrap()).unwrap();
+            let mut store = Store::new(&engine, WasiCtxBuilder::new().build());
+            let instance = linker.instantiate(&mut store, &module).unwrap();
+            let handle = instance.get_typed_func::<i32, i32>(&mut store, "handle_stream").unwrap();
+            handle.call(&mut store, 2).unwrap()
+        });
+
+        let events = body.collect().await.unwrap().to_bytes();
+        assert_eq!(guest.await.unwrap(), 0);
+        assert_eq!(events.as_ref(), b"data: hi\n\ndata: hi\n\n");
+    }
+}
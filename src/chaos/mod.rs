@@ -0,0 +1,318 @@
+// Chaos Mode
+// Crashes Cages, delays requests and fails health checks on purpose, to prove self-healing recovers
+
+use crate::router::Router;
+use anyhow::{bail, Context, Result};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Injected faults kept for the status API
+const HISTORY_LEN: usize = 50;
+
+/// `[chaos]`: fault injection, for staging and CI only
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChaosConfig {
+    /// Allow faults at all, scheduled or triggered with `pear chaos`
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// How often each site rolls for a scheduled fault
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+
+    /// Chance per interval of crashing one of a site's Cages
+    #[serde(default)]
+    pub crash_probability: f64,
+
+    /// Chance per interval of delaying a site's requests
+    #[serde(default)]
+    pub latency_probability: f64,
+
+    /// Chance per interval of failing one Cage's health checks
+    #[serde(default)]
+    pub health_probability: f64,
+
+    /// Delay added by latency faults
+    #[serde(default = "default_latency")]
+    pub latency_ms: u64,
+
+    /// How long latency and health faults last
+    #[serde(default = "default_duration")]
+    pub duration_secs: u64,
+
+    /// Sites scheduled faults may hit (empty = all)
+    #[serde(default)]
+    pub sites: Vec<String>,
+}
+
+fn default_interval() -> u64 { 60 }
+fn default_latency() -> u64 { 500 }
+fn default_duration() -> u64 { 30 }
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_interval(),
+            crash_probability: 0.0,
+            latency_probability: 0.0,
+            health_probability: 0.0,
+            latency_ms: default_latency(),
+            duration_secs: default_duration(),
+            sites: Vec::new(),
+        }
+    }
+}
+
+impl ChaosConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_secs == 0 {
+            bail!("interval_secs must be at least 1");
+        }
+        for (name, probability) in [
+            ("crash_probability", self.crash_probability),
+            ("latency_probability", self.latency_probability),
+            ("health_probability", self.health_probability),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                bail!("{} must be between 0 and 1, got {}", name, probability);
+            }
+        }
+        Ok(())
+    }
+
+    fn scheduled(&self) -> bool {
+        self.crash_probability > 0.0 || self.latency_probability > 0.0 || self.health_probability > 0.0
+    }
+}
+
+/// A fault to inject into a site; unset fields are picked at random or taken from `[chaos]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fault {
+    /// Crash a Cage, leaving the Supervisor to replace it
+    Crash {
+        #[serde(default)]
+        cage: Option<u64>,
+    },
+    /// Delay every request to the site
+    Latency {
+        #[serde(default)]
+        delay_ms: Option<u64>,
+        #[serde(default)]
+        duration_secs: Option<u64>,
+    },
+    /// Fail a Cage's health checks, taking it out of rotation
+    Health {
+        #[serde(default)]
+        cage: Option<u64>,
+        #[serde(default)]
+        duration_secs: Option<u64>,
+    },
+}
+
+/// A fault that was injected
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultRecord {
+    pub site_id: String,
+    pub fault: Fault,
+
+    /// Unix seconds
+    pub injected_at: i64,
+
+    /// Whether it was rolled by the schedule rather than triggered by hand
+    pub scheduled: bool,
+}
+
+/// Injects faults into the Router's Cage pools
+pub struct ChaosEngine {
+    config: ChaosConfig,
+
+    /// Sites with injected latency: the delay and when it stops
+    latency: DashMap<String, (Duration, Instant)>,
+    history: Mutex<VecDeque<FaultRecord>>,
+}
+
+impl ChaosEngine {
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            config,
+            latency: DashMap::new(),
+            history: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Delay to add to a request for `site_id`, while a latency fault is active
+    pub fn latency(&self, site_id: &str) -> Option<Duration> {
+        if self.latency.is_empty() {
+            return None;
+        }
+        let (delay, until) = *self.latency.get(site_id)?;
+        if Instant::now() < until {
+            return Some(delay);
+        }
+        self.latency.remove_if(site_id, |_, (_, until)| Instant::now() >= *until);
+        None
+    }
+
+    /// Inject `fault` into the site's pool, resolving unset fields first
+    pub async fn inject(&self, router: &Router, site_id: &str, fault: Fault) -> Result<FaultRecord> {
+        self.apply(router, site_id, fault, false).await
+    }
+
+    async fn apply(&self, router: &Router, site_id: &str, fault: Fault, scheduled: bool) -> Result<FaultRecord> {
+        let pool = router.pool(site_id).with_context(|| format!("Site {} has no Cage pool", site_id))?;
+        let fault = match fault {
+            Fault::Crash { cage } => {
+                let cage = pick_cage(pool.cages().await, cage)?;
+                pool.crash(&cage).await;
+                Fault::Crash { cage: Some(cage.id()) }
+            }
+            Fault::Latency { delay_ms, duration_secs } => {
+                let delay_ms = delay_ms.unwrap_or(self.config.latency_ms);
+                let duration_secs = duration_secs.unwrap_or(self.config.duration_secs);
+                self.delay(site_id, Duration::from_millis(delay_ms), Duration::from_secs(duration_secs));
+                Fault::Latency { delay_ms: Some(delay_ms), duration_secs: Some(duration_secs) }
+            }
+            Fault::Health { cage, duration_secs } => {
+                let cage = pick_cage(pool.cages().await, cage)?;
+                let duration_secs = duration_secs.unwrap_or(self.config.duration_secs);
+                cage.mark_unhealthy();
+
+                // A Cage still running afterwards passes its next check; a crashed one stays down
+                let recovering = cage.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(duration_secs)).await;
+                    recovering.health_check().await;
+                });
+                Fault::Health { cage: Some(cage.id()), duration_secs: Some(duration_secs) }
+            }
+        };
+
+        warn!(site_id = %site_id, fault = ?fault, scheduled, "Chaos fault injected");
+        let record = FaultRecord {
+            site_id: site_id.to_string(),
+            fault,
+            injected_at: chrono::Utc::now().timestamp(),
+            scheduled,
+        };
+        let mut history = self.history.lock();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(record.clone());
+        Ok(record)
+    }
+
+    fn delay(&self, site_id: &str, delay: Duration, duration: Duration) {
+        self.latency.insert(site_id.to_string(), (delay, Instant::now() + duration));
+    }
+
+    /// Roll for scheduled faults every `interval_secs`, if any probability is set
+    pub fn start(self: &Arc<Self>, router: Arc<Router>) {
+        if !self.config.scheduled() {
+            return;
+        }
+
+        let engine = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(engine.config.interval_secs));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                for site_id in router.site_ids() {
+                    if !engine.config.sites.is_empty() && !engine.config.sites.contains(&site_id) {
+                        continue;
+                    }
+                    for fault in engine.roll() {
+                        if let Err(e) = engine.apply(&router, &site_id, fault, true).await {
+                            warn!(site_id = %site_id, "Scheduled chaos fault not injected: {:#}", e);
+                        }
+                    }
+                }
+            }
+        });
+
+        info!(interval_secs = self.config.interval_secs, "Chaos schedule started");
+    }
+
+    /// Faults the schedule picked for one site this interval
+    fn roll(&self) -> Vec<Fault> {
+        let mut faults = Vec::new();
+        if rand::random::<f64>() < self.config.crash_probability {
+            faults.push(Fault::Crash { cage: None });
+        }
+        if rand::random::<f64>() < self.config.latency_probability {
+            faults.push(Fault::Latency { delay_ms: None, duration_secs: None });
+        }
+        if rand::random::<f64>() < self.config.health_probability {
+            faults.push(Fault::Health { cage: None, duration_secs: None });
+        }
+        faults
+    }
+
+    /// Injected faults, oldest first
+    pub fn history(&self) -> Vec<FaultRecord> {
+        self.history.lock().iter().cloned().collect()
+    }
+
+    pub fn config(&self) -> &ChaosConfig {
+        &self.config
+    }
+}
+
+/// The Cage with `id`, or a random healthy one
+fn pick_cage(cages: Vec<Arc<crate::cage::Cage>>, id: Option<u64>) -> Result<Arc<crate::cage::Cage>> {
+    match id {
+        Some(id) => cages.into_iter().find(|cage| cage.id() == id).with_context(|| format!("No Cage {} in the pool", id)),
+        None => {
+            let healthy: Vec<_> = cages.into_iter().filter(|cage| cage.is_healthy()).collect();
+            healthy.choose(&mut rand::thread_rng()).cloned().context("The pool has no healthy Cages")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_validation() {
+        assert!(ChaosConfig::default().validate().is_ok());
+        assert!(!ChaosConfig::default().scheduled());
+
+        let config = ChaosConfig { crash_probability: 1.5, ..Default::default() };
+        assert!(config.validate().is_err());
+
+        let config = ChaosConfig { health_probability: 1.0, ..Default::default() };
+        assert!(config.validate().is_ok());
+        assert_eq!(ChaosEngine::new(config).roll(), vec![Fault::Health { cage: None, duration_secs: None }]);
+    }
+
+    #[test]
+    fn test_latency_fault_expires() {
+        let engine = ChaosEngine::new(ChaosConfig::default());
+        assert_eq!(engine.latency("blog"), None);
+
+        engine.delay("blog", Duration::from_millis(200), Duration::from_secs(60));
+        engine.delay("shop", Duration::from_millis(200), Duration::ZERO);
+        assert_eq!(engine.latency("blog"), Some(Duration::from_millis(200)));
+        assert_eq!(engine.latency("shop"), None);
+        assert!(!engine.latency.contains_key("shop"));
+    }
+
+    #[test]
+    fn test_fault_from_json() {
+        let fault: Fault = serde_json::from_str(r#"{"kind": "latency", "delay_ms": 250}"#).unwrap();
+        assert_eq!(fault, Fault::Latency { delay_ms: Some(250), duration_secs: None });
+
+        let fault: Fault = serde_json::from_str(r#"{"kind": "crash"}"#).unwrap();
+        assert_eq!(fault, Fault::Crash { cage: None });
+    }
+}
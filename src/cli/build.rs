@@ -0,0 +1,377 @@
+// Guest Builds
+// `pear build`: compiles a Rust, AssemblyScript or TinyGo project into a module Cages can run
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use colored::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::deployment::validate::{validate_module, ModuleReport, MAX_MODULE_BYTES};
+
+/// Rust targets for WASI preview 1, newest name first (`wasm32-wasi` before Rust 1.78)
+const RUST_TARGETS: &[&str] = &["wasm32-wasip1", "wasm32-wasi"];
+
+/// Proposals Cages enable, so wasm-opt may use them and nothing else
+const WASM_OPT_FEATURES: &[&str] = &[
+    "--enable-mutable-globals",
+    "--enable-nontrapping-float-to-int",
+    "--enable-sign-ext",
+    "--enable-multivalue",
+    "--enable-bulk-memory",
+    "--enable-reference-types",
+    "--enable-simd",
+];
+
+/// Compiler a guest project is built with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Toolchain {
+    /// cargo, for a crate with a cdylib or bin target
+    Rust,
+
+    /// asc, for a project with asconfig.json or assemblyscript in package.json
+    #[value(name = "assemblyscript", alias = "as")]
+    AssemblyScript,
+
+    /// tinygo, for a Go module
+    #[value(name = "tinygo", alias = "go")]
+    TinyGo,
+}
+
+impl Toolchain {
+    /// Tell the toolchain from the project's manifest
+    pub fn detect(dir: &Path) -> Option<Self> {
+        if dir.join("Cargo.toml").is_file() {
+            return Some(Toolchain::Rust);
+        }
+        if dir.join("asconfig.json").is_file() {
+            return Some(Toolchain::AssemblyScript);
+        }
+        if let Ok(package) = std::fs::read_to_string(dir.join("package.json")) {
+            if package.contains("\"assemblyscript\"") {
+                return Some(Toolchain::AssemblyScript);
+            }
+        }
+        if dir.join("go.mod").is_file() {
+            return Some(Toolchain::TinyGo);
+        }
+        None
+    }
+}
+
+/// What `pear build` is asked to do
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    pub dir: PathBuf,
+
+    /// Detected when not given
+    pub toolchain: Option<Toolchain>,
+
+    /// Default: `<dir>/dist/<project name>.wasm`
+    pub out: Option<PathBuf>,
+
+    /// Unoptimized, with debug info
+    pub debug: bool,
+
+    /// Run wasm-opt on release builds when it is installed
+    pub optimize: bool,
+}
+
+/// The built module and what Cages will make of it
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildResult {
+    pub toolchain: Toolchain,
+    pub module: PathBuf,
+
+    /// Whether wasm-opt ran
+    pub optimized: bool,
+    pub report: ModuleReport,
+}
+
+impl BuildResult {
+    /// Fail when Cages couldn't run the module
+    pub fn check(&self) -> anyhow::Result<()> {
+        if !self.report.is_valid() {
+            bail!("{} can't be deployed: {}", self.module.display(), self.report.problems.join("; "));
+        }
+        Ok(())
+    }
+}
+
+/// Build the project, optimize and check the module
+/// Compiler output goes to stderr so structured output stays parseable.
+pub fn run(options: &BuildOptions) -> anyhow::Result<BuildResult> {
+    let dir = &options.dir;
+    if !dir.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+    let toolchain = match options.toolchain.or_else(|| Toolchain::detect(dir)) {
+        Some(toolchain) => toolchain,
+        None => bail!("Cannot tell how to build {}: no Cargo.toml, asconfig.json, package.json or go.mod (use --toolchain)", dir.display()),
+    };
+
+    let name = project_name(dir);
+    let out = match &options.out {
+        Some(out) => out.clone(),
+        None => dir.join("dist").join(format!("{}.wasm", name)),
+    };
+    if let Some(parent) = out.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    match toolchain {
+        Toolchain::Rust => {
+            let built = build_rust(dir, options.debug)?;
+            std::fs::copy(&built, &out).with_context(|| format!("Failed to copy {} to {}", built.display(), out.display()))?;
+        }
+        Toolchain::AssemblyScript => build_assemblyscript(dir, &out, options.debug)?,
+        Toolchain::TinyGo => build_tinygo(dir, &out, options.debug)?,
+    }
+
+    let optimized = options.optimize && !options.debug && optimize(&out)?;
+    let module = std::fs::read(&out).with_context(|| format!("Failed to read {}", out.display()))?;
+    let report = validate_module(&module, MAX_MODULE_BYTES);
+    Ok(BuildResult { toolchain, module: out, optimized, report })
+}
+
+/// Print the result in the requested format, then `check` it
+pub fn print(result: &BuildResult, output: super::OutputFormat) -> anyhow::Result<()> {
+    if !super::print_structured(output, result)? {
+        let optimized = if result.optimized { ", optimized" } else { "" };
+        super::success(&format!(
+            "Built {} ({} bytes{})",
+            result.module.display().to_string().bright_white(),
+            result.report.size_bytes,
+            optimized,
+        ));
+        for problem in &result.report.problems {
+            super::error(problem);
+        }
+    }
+    result.check()
+}
+
+/// `cargo build` for the WASI target; returns the `.wasm` cargo produced
+fn build_rust(dir: &Path, debug: bool) -> anyhow::Result<PathBuf> {
+    let target = rust_target(dir)?;
+    let mut command = Command::new("cargo");
+    command
+        .current_dir(dir)
+        .args(["build", "--target", target, "--message-format=json-render-diagnostics"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    if !debug {
+        command.arg("--release");
+    }
+    let output = command.output().context("Failed to run cargo; is Rust installed?")?;
+    if !output.status.success() {
+        bail!("cargo build failed ({})", output.status);
+    }
+    match rust_artifact(&String::from_utf8_lossy(&output.stdout)) {
+        Some(module) => Ok(module),
+        None => bail!("cargo built no .wasm; give the crate a bin target or crate-type = [\"cdylib\"]"),
+    }
+}
+
+/// The WASI target this rustc knows, failing with the fix when its standard library isn't installed
+fn rust_target(dir: &Path) -> anyhow::Result<&'static str> {
+    let targets = Command::new("rustc")
+        .current_dir(dir)
+        .args(["--print", "target-list"])
+        .output()
+        .context("Failed to run rustc; is Rust installed?")?;
+    let targets = String::from_utf8_lossy(&targets.stdout);
+    let Some(target) = RUST_TARGETS.iter().copied().find(|target| targets.lines().any(|line| line == *target)) else {
+        bail!("This rustc has no WASI target; update Rust");
+    };
+
+    let sysroot = Command::new("rustc").current_dir(dir).args(["--print", "sysroot"]).output()?;
+    let sysroot = PathBuf::from(String::from_utf8_lossy(&sysroot.stdout).trim());
+    if !sysroot.join("lib/rustlib").join(target).is_dir() {
+        bail!("The {} standard library isn't installed; run `rustup target add {}`", target, target);
+    }
+    Ok(target)
+}
+
+/// The last `.wasm` in cargo's JSON messages, which is the top-level crate's
+fn rust_artifact(messages: &str) -> Option<PathBuf> {
+    messages
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .flat_map(|message| {
+            let filenames = message["filenames"].as_array().cloned().unwrap_or_default();
+            filenames.into_iter().filter_map(|name| name.as_str().map(PathBuf::from))
+        })
+        .rfind(|name| name.extension().is_some_and(|ext| ext == "wasm"))
+}
+
+/// `asc` from the project's node_modules
+fn build_assemblyscript(dir: &Path, out: &Path, debug: bool) -> anyhow::Result<()> {
+    let mut command = Command::new("npx");
+    command.current_dir(dir).args(assemblyscript_args(dir, out, debug)?);
+    run_tool(command, "npx asc")
+}
+
+/// Entry, output and the flags that keep imports to WASI and `pear_*`
+fn assemblyscript_args(dir: &Path, out: &Path, debug: bool) -> anyhow::Result<Vec<String>> {
+    let mut args = vec!["--no-install".to_string(), "asc".to_string()];
+    let config = dir.join("asconfig.json");
+    let has_entries = std::fs::read_to_string(&config)
+        .ok()
+        .and_then(|config| serde_json::from_str::<serde_json::Value>(&config).ok())
+        .is_some_and(|config| config["entries"].as_array().is_some_and(|entries| !entries.is_empty()));
+    if !has_entries {
+        if !dir.join("assembly/index.ts").is_file() {
+            bail!("No entries in asconfig.json and no assembly/index.ts");
+        }
+        args.push("assembly/index.ts".to_string());
+    }
+
+    let shim = dir.join("node_modules/@assemblyscript/wasi-shim/asconfig.json");
+    if config.is_file() {
+        args.extend(["--config".to_string(), "asconfig.json".to_string()]);
+    } else if shim.is_file() {
+        args.extend(["--config".to_string(), "node_modules/@assemblyscript/wasi-shim/asconfig.json".to_string()]);
+    } else {
+        // Cages don't provide `env.abort`; without the WASI shim, aborts just trap
+        args.push("--use".to_string());
+        args.push("abort=".to_string());
+    }
+
+    let out = absolute(out)?;
+    args.extend(["--outFile".to_string(), out.display().to_string()]);
+    if debug {
+        args.push("--debug".to_string());
+    } else {
+        args.extend(["-O3".to_string(), "--noAssert".to_string()]);
+    }
+    Ok(args)
+}
+
+/// `tinygo build` for WASI, without the goroutine scheduler a per-request call doesn't need
+fn build_tinygo(dir: &Path, out: &Path, debug: bool) -> anyhow::Result<()> {
+    let mut command = Command::new("tinygo");
+    command
+        .current_dir(dir)
+        .args(["build", "-target=wasi", "-scheduler=none", "-panic=trap", "-o"])
+        .arg(absolute(out)?);
+    if debug {
+        command.arg("-opt=1");
+    } else {
+        command.args(["-opt=2", "-no-debug"]);
+    }
+    command.arg(".");
+    run_tool(command, "tinygo")
+}
+
+/// Run wasm-opt in place; false when it isn't installed
+fn optimize(module: &Path) -> anyhow::Result<bool> {
+    let mut command = Command::new("wasm-opt");
+    command.arg(module).arg("-o").arg(module).args(["-O3", "--strip-debug"]).args(WASM_OPT_FEATURES);
+    match command.stdout(Stdio::from(std::io::stderr())).status() {
+        Ok(status) if status.success() => Ok(true),
+        Ok(status) => bail!("wasm-opt failed ({})", status),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            super::warning("wasm-opt not found; install binaryen for smaller, faster modules");
+            Ok(false)
+        }
+        Err(e) => Err(e).context("Failed to run wasm-opt"),
+    }
+}
+
+/// Run a compiler with its output on stderr
+fn run_tool(mut command: Command, name: &str) -> anyhow::Result<()> {
+    let status = command
+        .stdout(Stdio::from(std::io::stderr()))
+        .stderr(Stdio::inherit())
+        .status()
+        .with_context(|| format!("Failed to run {}; is it installed?", name))?;
+    if !status.success() {
+        bail!("{} failed ({})", name, status);
+    }
+    Ok(())
+}
+
+/// `path` as seen from the current directory, for compilers run in the project
+fn absolute(path: &Path) -> anyhow::Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(std::env::current_dir()?.join(path))
+}
+
+/// The package name from the manifest, or the directory's name
+fn project_name(dir: &Path) -> String {
+    let cargo = std::fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| manifest.parse::<toml::Value>().ok())
+        .and_then(|manifest| manifest.get("package")?.get("name")?.as_str().map(str::to_string));
+    let npm = || {
+        std::fs::read_to_string(dir.join("package.json"))
+            .ok()
+            .and_then(|package| serde_json::from_str::<serde_json::Value>(&package).ok())
+            .and_then(|package| package["name"].as_str().map(str::to_string))
+    };
+    let name = cargo.or_else(npm).or_else(|| {
+        dir.canonicalize().ok()?.file_name().map(|name| name.to_string_lossy().into_owned())
+    });
+    // npm scopes and Go-style paths would otherwise become directories
+    name.map(|name| name.rsplit('/').next().unwrap_or_default().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "module".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_toolchain() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Toolchain::detect(dir.path()), None);
+        std::fs::write(dir.path().join("go.mod"), "module example.com/blog\n").unwrap();
+        assert_eq!(Toolchain::detect(dir.path()), Some(Toolchain::TinyGo));
+        std::fs::write(dir.path().join("package.json"), r#"{"name": "@acme/blog", "devDependencies": {"assemblyscript": "^0.27"}}"#).unwrap();
+        assert_eq!(Toolchain::detect(dir.path()), Some(Toolchain::AssemblyScript));
+        assert_eq!(project_name(dir.path()), "blog");
+        std::fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"blog-guest\"\n").unwrap();
+        assert_eq!(Toolchain::detect(dir.path()), Some(Toolchain::Rust));
+        assert_eq!(project_name(dir.path()), "blog-guest");
+    }
+
+    #[test]
+    fn test_rust_artifact() {
+        let messages = [
+            r#"{"reason":"compiler-artifact","filenames":["/p/target/wasm32-wasip1/release/deps/libserde-1.rlib"]}"#,
+            r#"{"reason":"compiler-artifact","filenames":["/p/target/wasm32-wasip1/release/blog_guest.wasm","/p/target/wasm32-wasip1/release/libblog_guest.rlib"]}"#,
+            r#"{"reason":"build-finished","success":true}"#,
+        ]
+        .join("\n");
+        assert_eq!(rust_artifact(&messages), Some(PathBuf::from("/p/target/wasm32-wasip1/release/blog_guest.wasm")));
+        assert_eq!(rust_artifact(r#"{"reason":"build-finished","success":true}"#), None);
+    }
+
+    #[test]
+    fn test_assemblyscript_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("dist/blog.wasm");
+        assert!(assemblyscript_args(dir.path(), &out, false).is_err());
+
+        std::fs::create_dir(dir.path().join("assembly")).unwrap();
+        std::fs::write(dir.path().join("assembly/index.ts"), "export function handle(len: i32): i32 { return 0 }").unwrap();
+        let args = assemblyscript_args(dir.path(), &out, false).unwrap();
+        assert_eq!(&args[..3], ["--no-install", "asc", "assembly/index.ts"]);
+        assert!(args.windows(2).any(|pair| pair == ["--use", "abort="]));
+        assert!(args.windows(2).any(|pair| pair == ["--outFile", out.to_str().unwrap()]));
+        assert!(args.contains(&"-O3".to_string()));
+
+        std::fs::write(dir.path().join("asconfig.json"), r#"{"entries": ["assembly/main.ts"]}"#).unwrap();
+        let args = assemblyscript_args(dir.path(), &out, true).unwrap();
+        assert!(!args.contains(&"assembly/index.ts".to_string()));
+        assert!(args.windows(2).any(|pair| pair == ["--config", "asconfig.json"]));
+        assert!(args.contains(&"--debug".to_string()));
+    }
+}
@@ -1,31 +1,182 @@
 // CLI Command Implementations
 // Handles execution of each CLI command with colored output
 
-use super::{success, error, info, warning, Commands, ConfigAction};
+use super::{success, error, info, warning, print_structured, AuditArgs, ChaosAction, Commands, CronAction, DeploymentAction, DomainAction, EnvAction, LogsArgs, OutputFormat, SiteAction, SnapshotAction, CacheAction, TenantAction, WebhookAction, CaAction, TokenAction, RoleAction};
+use anyhow::Context;
+use base64::Engine;
 use colored::*;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::time::Duration;
 
 /// Execute a CLI command
-pub async fn execute(command: Commands) -> anyhow::Result<()> {
+pub async fn execute(command: Commands, config: String, output: OutputFormat) -> anyhow::Result<()> {
     match command {
-        Commands::Start { config, foreground, verbose } => {
+        Commands::Start { foreground, verbose, .. } => {
             start_command(config, foreground, verbose).await
         }
         Commands::Stop { force } => {
             stop_command(force).await
         }
-        Commands::Status { format } => {
-            status_command(format).await
+        Commands::Status => {
+            status_command(config, output).await
         }
-        Commands::Deploy { wasm_file, site, replicas } => {
-            deploy_command(wasm_file, site, replicas).await
+        Commands::Build { path, toolchain, out, debug, no_opt, deploy, replicas, strategy } => {
+            let options = super::build::BuildOptions {
+                dir: path.into(),
+                toolchain,
+                out: out.map(Into::into),
+                debug,
+                optimize: !no_opt,
+            };
+            let built = super::build::run(&options)?;
+            // With --deploy, structured output is the deploy's
+            let site = match deploy {
+                None => return super::build::print(&built, output),
+                Some(site) if output == OutputFormat::Table => {
+                    super::build::print(&built, output)?;
+                    site
+                }
+                Some(site) => {
+                    built.check()?;
+                    site
+                }
+            };
+            let wasm_file = built.module.display().to_string();
+            let strategy = match strategy {
+                Some(strategy) => strategy,
+                None => crate::config::PearConfig::load(&config)?.deployment.strategy,
+            };
+            match strategy {
+                crate::deployment::DeploymentStrategy::Canary => deploy_command(wasm_file, site, replicas, output).await,
+                crate::deployment::DeploymentStrategy::BlueGreen => {
+                    blue_green_deploy(config, ModuleSource::File(wasm_file), site, true, None, output).await
+                }
+            }
         }
-        Commands::Config { action } => {
-            config_command(action).await
+        Commands::New { template, path, name, force } => {
+            super::scaffold::run(template, path.as_deref(), name.as_deref(), force)
         }
-        Commands::Dashboard => {
-            dashboard_command().await
+        Commands::Dev { path, site, port, runtime_dir, debounce } => {
+            super::dev::run(super::dev::DevOptions {
+                path: path.into(),
+                site,
+                port,
+                runtime_dir: runtime_dir.into(),
+                debounce: Duration::from_millis(debounce),
+                config,
+            })
+            .await
+        }
+        Commands::Deploy { wasm_file, site, replicas, strategy, now, no_switch, signature, artifact, sha256, git, reference, build, build_output } => {
+            if now {
+                return hot_swap_deploy(config, wasm_file, site, signature, output).await;
+            }
+            let strategy = match strategy {
+                Some(strategy) => strategy,
+                None => crate::config::PearConfig::load(&config)?.deployment.strategy,
+            };
+            match strategy {
+                crate::deployment::DeploymentStrategy::Canary if signature.is_some() => {
+                    anyhow::bail!("--signature is only checked by blue/green deployments")
+                }
+                crate::deployment::DeploymentStrategy::Canary if artifact => {
+                    anyhow::bail!("--artifact is only fetched by blue/green deployments")
+                }
+                crate::deployment::DeploymentStrategy::Canary if git => {
+                    anyhow::bail!("--git deploys are blue/green deployments")
+                }
+                crate::deployment::DeploymentStrategy::Canary => deploy_command(wasm_file, site, replicas, output).await,
+                crate::deployment::DeploymentStrategy::BlueGreen if git => {
+                    let source = serde_json::json!({
+                        "url": wasm_file,
+                        "reference": reference,
+                        "build": build,
+                        "output": build_output,
+                        "switch": !no_switch,
+                    });
+                    git_deploy(config, site, source, output).await
+                }
+                crate::deployment::DeploymentStrategy::BlueGreen => {
+                    let source = match artifact {
+                        true => ModuleSource::Artifact { key: wasm_file, sha256 },
+                        false => ModuleSource::File(wasm_file),
+                    };
+                    blue_green_deploy(config, source, site, !no_switch, signature, output).await
+                }
+            }
+        }
+        Commands::Deployment { action } => {
+            deployment_command(action, config, output).await
+        }
+        Commands::Chaos { action } => {
+            chaos_command(action, config, output).await
+        }
+        Commands::Snapshot { action } => {
+            snapshot_command(action, config, output).await
+        }
+        Commands::Cache { action } => {
+            cache_command(action, config, output)
+        }
+        Commands::Upgrade { binary } => {
+            upgrade_command(config, binary).await
+        }
+        Commands::Rebalance { over, wait, cancel, status } => {
+            rebalance_command(config, over, wait, cancel, status, output).await
+        }
+        Commands::Env { action } => {
+            env_command(action, config).await
+        }
+        Commands::Cron { action } => {
+            cron_command(action, config).await
+        }
+        Commands::Tenant { action } => {
+            tenant_command(action, config, output).await
+        }
+        Commands::Site { action } => {
+            site_command(action, config, output).await
+        }
+        Commands::Domain { action } => {
+            domain_command(action, config, output).await
+        }
+        Commands::Webhook { action } => {
+            webhook_command(action, config, output).await
+        }
+        Commands::Ca { action } => {
+            ca_command(action, config, output)
+        }
+        Commands::Token { action } => {
+            token_command(action, config, output).await
+        }
+        Commands::Role { action } => {
+            role_command(action, config, output).await
+        }
+        Commands::Logs(args) => {
+            logs_command(args, config).await
+        }
+        Commands::Events { site, lines } => {
+            events_command(site, lines, config, output).await
+        }
+        Commands::Audit(args) => {
+            audit_command(args, config, output).await
+        }
+        Commands::Top { interval } => {
+            super::top::run(config, Duration::from_millis(interval.max(250))).await
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut <super::Cli as clap::CommandFactory>::command(), "pear", &mut std::io::stdout());
+            Ok(())
+        }
+        Commands::Init { force, yes } => {
+            super::init::run(&config, force, yes)
+        }
+        Commands::Doctor { runtime_dir } => {
+            super::doctor::run(&config, &runtime_dir, output)
+        }
+        Commands::Set { key, value } => {
+            set_command(config, key, value)
+        }
+        Commands::Validate { file } => {
+            validate_command(file.unwrap_or(config), output)
         }
     }
 }
@@ -121,62 +272,72 @@ async fn stop_command(force: bool) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Show server status
-async fn status_command(format: String) -> anyhow::Result<()> {
-    match format.as_str() {
-        "json" => {
-            println!(r#"{{
-  "status": "running",
-  "uptime_seconds": 3627,
-  "version": "0.3.0",
-  "cages": {{
-    "total": 3,
-    "healthy": 3,
-    "crashed": 0
-  }},
-  "requests": {{
-    "total": 156432,
-    "per_second": 42.3
-  }},
-  "memory_mb": 287
-}}"#);
-        }
-        "table" => {
-            println!();
-            println!("{}", "┌─── Pear Server Status ─────────────────────────────┐".bright_white());
-            println!("│                                                     │");
-            println!("│  {} {}                                │", "Status:".bright_white(), "RUNNING".green().bold());
-            println!("│  {} {}                               │", "Version:".bright_white(), "0.3.0".cyan());
-            println!("│  {} {}                           │", "Uptime:".bright_white(), "1h 30m".yellow());
-            println!("│                                                     │");
-            println!("│  {} {}                                      │", "Cage Pools".bright_cyan().bold(), "─".repeat(15).bright_black());
-            println!("│    Total Cages: {}  Healthy: {}  Crashed: {}        │", "3".white(), "3".green(), "0".red());
-            println!("│                                                     │");
-            println!("│  {} {}                                │", "Traffic Stats".bright_cyan().bold(), "─".repeat(13).bright_black());
-            println!("│    Total Requests: {}                       │", "156,432".cyan());
-            println!("│    Requests/sec: {}                           │", "42.3".yellow());
-            println!("│                                                     │");
-            println!("│  {} {}                                 │", "Resources".bright_cyan().bold(), "─".repeat(16).bright_black());
-            println!("│    Memory Usage: {}                           │", "287 MB".green());
-            println!("│    CPU Usage: {}                               │", "23%".yellow());
-            println!("│                                                     │");
-            println!("{}", "└─────────────────────────────────────────────────────┘".bright_white());
-            println!();
-        }
-        _ => {
-            println!("Pear Server: {}", "RUNNING".green().bold());
-            println!("Uptime: {}", "1h 30m".yellow());
-            println!("Cages: {} healthy / {} total", "3".green(), "3");
-            println!("Requests: {} total ({}/sec)", "156,432", "42.3");
-        }
+/// Show server status from the management API
+async fn status_command(config: String, output: OutputFormat) -> anyhow::Result<()> {
+    let status = api_request(&config, hyper::Method::GET, "/api/status", None).await?;
+    let pools = api_request(&config, hyper::Method::GET, "/api/pools", None).await?;
+    let pools = pools["pools"].as_array().cloned().unwrap_or_default();
+    
+    let count = |field: &str| pools.iter().map(|pool| pool["health"][field].as_u64().unwrap_or(0)).sum::<u64>();
+    let report = serde_json::json!({
+        "status": "running",
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_seconds": status["uptime_seconds"],
+        "cages": {
+            "total": count("total_cages"),
+            "healthy": count("healthy_cages"),
+            "crashed": count("crashed_cages"),
+        },
+        "requests": status["requests"],
+        "latency": status["latency"],
+        "memory_reserved_bytes": pools.iter().map(|pool| pool["memory_bytes"].as_u64().unwrap_or(0)).sum::<u64>(),
+        "draining": pools.iter()
+            .filter(|pool| pool["health"]["draining"].as_bool() == Some(true))
+            .map(|pool| pool["site_id"].clone())
+            .collect::<Vec<_>>(),
+        "healing_events": status["healing_events"],
+        "threats_detected": status["threats_detected"],
+    });
+    if print_structured(output, &report)? {
+        return Ok(());
     }
     
+    let uptime = report["uptime_seconds"].as_u64().unwrap_or(0);
+    let row = |label: &str, value: ColoredString| println!("  {} {}", format!("{:<18}", label).bright_white(), value);
+    println!();
+    println!("{}", "Pear Server Status".bright_cyan().bold());
+    row("Status:", "RUNNING".green().bold());
+    row("Version:", env!("CARGO_PKG_VERSION").cyan());
+    row("Uptime:", format!("{}h {}m", uptime / 3600, uptime % 3600 / 60).yellow());
+    row("Cages:", format!(
+        "{} total, {} healthy, {} crashed",
+        report["cages"]["total"], report["cages"]["healthy"], report["cages"]["crashed"]
+    ).normal());
+    row("Requests:", format!(
+        "{} total, {:.1}% successful",
+        report["requests"]["total"], report["requests"]["success_rate"].as_f64().unwrap_or(0.0)
+    ).cyan());
+    row("Latency:", format!(
+        "p50 {:.1}ms, p99 {:.1}ms",
+        report["latency"]["p50_ms"].as_f64().unwrap_or(0.0), report["latency"]["p99_ms"].as_f64().unwrap_or(0.0)
+    ).yellow());
+    row("Memory reserved:", format!("{} MB", report["memory_reserved_bytes"].as_u64().unwrap_or(0) / (1024 * 1024)).green());
+    if let Some(draining) = report["draining"].as_array().filter(|sites| !sites.is_empty()) {
+        let sites: Vec<&str> = draining.iter().filter_map(|site| site.as_str()).collect();
+        row("Draining:", sites.join(", ").yellow());
+    }
+    row("Healing events:", report["healing_events"].to_string().normal());
+    row("Threats detected:", report["threats_detected"].to_string().red());
+    println!();
+    
     Ok(())
 }
 
 /// Deploy a WebAssembly module
-async fn deploy_command(wasm_file: String, site: String, replicas: usize) -> anyhow::Result<()> {
-    info(&format!("Deploying {} to site '{}'", wasm_file.bright_white(), site.cyan()));
+async fn deploy_command(wasm_file: String, site: String, replicas: usize, output: OutputFormat) -> anyhow::Result<()> {
+    if output == OutputFormat::Table {
+        info(&format!("Deploying {} to site '{}'", wasm_file.bright_white(), site.cyan()));
+    }
     
     let spinner = ProgressBar::new_spinner();
     spinner.set_style(
@@ -201,6 +362,16 @@ async fn deploy_command(wasm_file: String, site: String, replicas: usize) -> any
     
     spinner.finish_and_clear();
     
+    let report = serde_json::json!({
+        "site_id": site,
+        "wasm_file": wasm_file,
+        "replicas": replicas,
+        "status": "healthy",
+    });
+    if print_structured(output, &report)? {
+        return Ok(());
+    }
+    
     success(&format!("Successfully deployed {} with {} replicas", site.cyan(), replicas.to_string().green()));
     println!();
     println!("  {} {}", "Site ID:".bright_white(), site.cyan());
@@ -212,42 +383,1731 @@ async fn deploy_command(wasm_file: String, site: String, replicas: usize) -> any
     Ok(())
 }
 
-/// Manage configuration
-async fn config_command(action: ConfigAction) -> anyhow::Result<()> {
-    match action {
-        ConfigAction::Show => {
-            println!();
-            println!();
+/// Start a green pool from the module, check it, and switch traffic to it unless told not to
+/// Where a blue/green deployment's module comes from
+enum ModuleSource {
+    /// Uploaded from a local file
+    File(String),
+    
+    /// Fetched by the node from the tenant's artifact source
+    Artifact { key: String, sha256: Option<String> },
+}
+
+async fn blue_green_deploy(
+    config: String,
+    source: ModuleSource,
+    site: String,
+    switch: bool,
+    signature_file: Option<String>,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let mut path = format!("/api/sites/{}/blue-green?switch={}", url_encode(&site), switch);
+    let (module, wasm_file) = match source {
+        ModuleSource::File(wasm_file) => {
+            let module = std::fs::read(&wasm_file)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", wasm_file, e))?;
+            (module, wasm_file)
         }
-        ConfigAction::Set { key, value } => {
-            success(&format!("Set {} = {}", key.cyan(), value.yellow()));
-            warning("Configuration changes will take effect after server restart");
+        ModuleSource::Artifact { key, sha256 } => {
+            path.push_str(&format!("&artifact={}", url_encode(&key)));
+            if let Some(sha256) = sha256 {
+                path.push_str(&format!("&sha256={}", url_encode(&sha256)));
+            }
+            (Vec::new(), key)
         }
-        ConfigAction::Validate { file } => {
-            info(&format!("Validating {}", file.bright_white()));
-            success("Configuration file is valid");
+    };
+    let mut headers = Vec::new();
+    if let Some(signature_file) = signature_file {
+        headers.push((crate::tenancy::signing::SIGNATURE_HEADER, read_signature(&signature_file)?));
+    }
+    if output == OutputFormat::Table {
+        info(&format!("Starting a green pool for '{}' from {}", site.cyan(), wasm_file.bright_white()));
+    }
+    
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(ProgressStyle::default_spinner().tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏").template("{spinner:.green} {msg}").unwrap());
+    spinner.set_message("Warming green Cages with synthetic checks...");
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let result = api_upload(&config, hyper::Method::POST, &path, "application/wasm", &headers, module).await;
+    spinner.finish_and_clear();
+    let (status, deployment) = result?;
+    
+    if !print_structured(output, &deployment)? {
+        print_deployment(&deployment);
+    }
+    if status == hyper::StatusCode::UNPROCESSABLE_ENTITY {
+        anyhow::bail!("green pool failed its checks; blue keeps serving");
+    }
+    if output == OutputFormat::Table {
+        match deployment["status"].as_str() {
+            Some("live") => info(&format!("Roll back instantly with {}", format!("pear deployment rollback {}", site).cyan())),
+            _ => info(&format!("Switch traffic with {}", format!("pear deployment switch {}", site).cyan())),
         }
     }
+    Ok(())
+}
+
+/// Hot-swap a site's live Cages onto a module file
+async fn hot_swap_deploy(
+    config: String,
+    wasm_file: String,
+    site: String,
+    signature_file: Option<String>,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let module = std::fs::read(&wasm_file)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", wasm_file, e))?;
+    let mut headers = Vec::new();
+    if let Some(signature_file) = signature_file {
+        headers.push((crate::tenancy::signing::SIGNATURE_HEADER, read_signature(&signature_file)?));
+    }
+    let path = format!("/api/sites/{}/swap", url_encode(&site));
+    let (status, swap) = api_upload(&config, hyper::Method::POST, &path, "application/wasm", &headers, module).await?;
     
+    if !print_structured(output, &swap)? {
+        match swap["swapped"].as_bool() {
+            Some(true) => success(&format!(
+                "Hot-swapped '{}' to {} ({} Cages, {} draining)",
+                site.cyan(),
+                wasm_file.bright_white(),
+                swap["cages"],
+                swap["draining"],
+            )),
+            _ => {
+                for failure in swap["failures"].as_array().into_iter().flatten() {
+                    println!("  {} {}", "✗".red(), failure.as_str().unwrap_or_default());
+                }
+            }
+        }
+    }
+    if status == hyper::StatusCode::UNPROCESSABLE_ENTITY {
+        anyhow::bail!("module was rejected; the current one keeps serving");
+    }
     Ok(())
 }
 
-/// Show dashboard information
-async fn dashboard_command() -> anyhow::Result<()> {
+/// A detached module signature file, as the `x-pear-signature` header carries it
+fn read_signature(signature_file: &str) -> anyhow::Result<String> {
+    let signature = std::fs::read(signature_file)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", signature_file, e))?;
+    // `openssl pkeyutl -sign` writes the 64 raw bytes; anything else is sent as the text it is
+    Ok(match signature.len() {
+        64 => base64::engine::general_purpose::STANDARD.encode(signature),
+        _ => String::from_utf8_lossy(&signature).trim().to_string(),
+    })
+}
+
+/// Point a site at a repository, then deploy it and show how the build went
+async fn git_deploy(config: String, site: String, source: serde_json::Value, output: OutputFormat) -> anyhow::Result<()> {
+    let path = format!("/api/sites/{}/git", url_encode(&site));
+    let registered = api_request(&config, hyper::Method::PUT, &path, Some(source)).await?;
+    if output == OutputFormat::Table {
+        info(&format!(
+            "Deploying '{}' from {} ({})",
+            site.cyan(),
+            registered["url"].as_str().unwrap_or_default().bright_white(),
+            registered["reference"].as_str().unwrap_or_default(),
+        ));
+    }
+    
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(ProgressStyle::default_spinner().tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏").template("{spinner:.green} {msg}").unwrap());
+    spinner.set_message("Fetching, building and warming green Cages...");
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let result = api_request(&config, hyper::Method::POST, &format!("{}/deploy", path), None).await;
+    spinner.finish_and_clear();
+    let deploy = match result {
+        Ok(deploy) => deploy,
+        Err(e) => {
+            // The fetch and build output usually says why
+            if let Ok(status) = api_request(&config, hyper::Method::GET, &path, None).await {
+                if let Some(log) = status["deploys"][0]["log"].as_str().filter(|log| !log.is_empty()) {
+                    println!("{}", log.bright_black());
+                }
+            }
+            return Err(e);
+        }
+    };
+    
+    if print_structured(output, &serde_json::json!({ "source": registered, "deploy": deploy }))? {
+        return Ok(());
+    }
     println!();
-    println!("{}", "📊 Pear Server Dashboard".bright_cyan().bold());
+    println!("  {} {}", "Commit:".bright_white(), deploy["commit"].as_str().unwrap_or("-"));
+    println!("  {} {}", "Language:".bright_white(), deploy["language"].as_str().unwrap_or("-"));
+    if deploy["deployment"].is_object() {
+        print_deployment(&deploy["deployment"]);
+    }
+    
     println!();
-    println!("  {} {}", "URL:".bright_white(), "http://localhost:9000".cyan().underline());
-    println!("  {} {}", "Status:".bright_white(), "Available".green());
+    info(&format!(
+        "Redeploy on every push: add a webhook for {} with secret {}",
+        registered["webhook_path"].as_str().unwrap_or_default().cyan(),
+        registered["webhook_secret"].as_str().unwrap_or_default().bright_white(),
+    ));
+    Ok(())
+}
+
+/// Blue/green deployment status and transitions
+async fn deployment_command(action: DeploymentAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    let (config, site, method, step) = match action {
+        DeploymentAction::History { site } => return deployment_history(config, site, output).await,
+        DeploymentAction::Status { site } => (config, site, hyper::Method::GET, None),
+        DeploymentAction::Switch { site } => (config, site, hyper::Method::POST, Some("switch")),
+        DeploymentAction::Rollback { site } => (config, site, hyper::Method::POST, Some("rollback")),
+        DeploymentAction::Finish { site } => (config, site, hyper::Method::POST, Some("finish")),
+    };
+    let mut path = format!("/api/sites/{}/blue-green", url_encode(&site));
+    if let Some(step) = step {
+        path.push('/');
+        path.push_str(step);
+    }
+    
+    let deployment = api_request(&config, method, &path, None).await?;
+    if !print_structured(output, &deployment)? {
+        print_deployment(&deployment);
+    }
+    Ok(())
+}
+
+/// The site's recent deployments, one line each
+async fn deployment_history(config: String, site: String, output: OutputFormat) -> anyhow::Result<()> {
+    let history = api_request(&config, hyper::Method::GET, &format!("/api/sites/{}/deployments", url_encode(&site)), None).await?;
+    if print_structured(output, &history)? {
+        return Ok(());
+    }
+    let deployments = history["deployments"].as_array().cloned().unwrap_or_default();
+    if deployments.is_empty() {
+        info(&format!("Site {} has no blue/green deployments", site.cyan()));
+        return Ok(());
+    }
+    println!("{}", format!("{:<36}  {:<19}  {:<8}  {:<14}  {}", "DEPLOYMENT", "STAGED", "STATUS", "SIGNATURE", "MODULE").bright_white());
+    for deployment in deployments {
+        let staged = deployment["staged_at"].as_i64()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let status = deployment["status"].as_str().unwrap_or_default();
+        let padded = format!("{:<8}", status);
+        let signature = &deployment["signature"];
+        let signature = match signature["key"].as_str() {
+            Some(key) => format!("key {}", key),
+            None => signature["status"].as_str().unwrap_or("-").to_string(),
+        };
+        let hash = deployment["module_hash"].as_str().map(|hash| &hash[..12.min(hash.len())]).unwrap_or("-");
+        println!(
+            "{:<36}  {:<19}  {}  {:<14}  {}",
+            deployment["deployment_id"].as_str().unwrap_or_default(),
+            staged,
+            if status == "failed" { padded.red() } else { padded.normal() },
+            signature,
+            hash,
+        );
+    }
+    Ok(())
+}
+
+fn print_deployment(deployment: &serde_json::Value) {
+    let status = deployment["status"].as_str().unwrap_or("unknown");
+    let colored_status = match status {
+        "live" | "ready" | "finished" => status.green(),
+        "failed" => status.red(),
+        _ => status.yellow(),
+    };
     println!();
-    println!("{}", "Features:".bright_white());
-    println!("  • Real-time Cage Pool visualization");
-    println!("  • Live traffic statistics");
-    println!("  • AI security threat alerts");
-    println!("  • Request logs streaming");
-    println!("  • Configuration management");
+    println!("  {} {}", "Site:".bright_white(), deployment["site_id"].as_str().unwrap_or_default().cyan());
+    println!("  {} {}", "Deployment:".bright_white(), deployment["deployment_id"].as_str().unwrap_or_default());
+    println!("  {} {}", "Status:".bright_white(), colored_status);
+    println!("  {} {}", "Checks run:".bright_white(), deployment["checks_run"]);
+    match (deployment["signature"]["status"].as_str(), deployment["signature"]["key"].as_str()) {
+        (_, Some(key)) => println!("  {} {} {}", "Signature:".bright_white(), "verified".green(), format!("({})", key).bright_black()),
+        (Some("unsigned"), _) | (None, _) => println!("  {} unsigned", "Signature:".bright_white()),
+        (Some(status), _) => println!("  {} {}", "Signature:".bright_white(), status.red()),
+    }
+    let module = &deployment["module"];
+    if let Some(size) = module["size_bytes"].as_u64() {
+        let list = |key: &str| module[key].as_array().into_iter().flatten()
+            .filter_map(|item| item.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("  {} {} KB", "Module:".bright_white(), size / 1024);
+        println!("  {} {}", "Exports:".bright_white(), list("exports"));
+        println!("  {} {}", "Features:".bright_white(), list("features"));
+    }
+    if let Some(switched_at) = deployment["switched_at"].as_i64().and_then(|secs| chrono::DateTime::from_timestamp(secs, 0)) {
+        println!("  {} {}", "Switched:".bright_white(), switched_at.format("%Y-%m-%d %H:%M:%S UTC"));
+    }
+    for failure in deployment["failures"].as_array().into_iter().flatten() {
+        println!("  {} {}", "✗".red(), failure.as_str().unwrap_or_default());
+    }
     println!();
-    info("Open the URL in your browser to access the dashboard");
+}
+
+/// Trigger a fault, or list the ones injected
+async fn chaos_command(action: ChaosAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    let (config, site, fault) = match action {
+        ChaosAction::Status => return chaos_status(config, output).await,
+        ChaosAction::Crash { site, cage } => {
+            (config, site, serde_json::json!({ "kind": "crash", "cage": cage }))
+        }
+        ChaosAction::Latency { site, delay, duration } => {
+            (config, site, serde_json::json!({ "kind": "latency", "delay_ms": delay, "duration_secs": duration }))
+        }
+        ChaosAction::Health { site, cage, duration } => {
+            (config, site, serde_json::json!({ "kind": "health", "cage": cage, "duration_secs": duration }))
+        }
+    };
+    
+    let path = format!("/api/chaos/sites/{}", url_encode(&site));
+    let record = api_request(&config, hyper::Method::POST, &path, Some(fault)).await?;
+    if !print_structured(output, &record)? {
+        success(&format!("Injected {} into {}", describe_fault(&record["fault"]), site.cyan()));
+    }
+    Ok(())
+}
+
+/// Save a snapshot of the running node, or check an archive and restore it
+async fn snapshot_command(action: SnapshotAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    use crate::storage::snapshot::{SnapshotArchive, SnapshotSummary};
+    
+    match action {
+        SnapshotAction::Create { file, tenant } => {
+            let mut path = "/api/snapshot".to_string();
+            if let Some(tenant) = &tenant {
+                path.push_str(&format!("?tenant={}", url_encode(tenant)));
+            }
+            let archive: SnapshotArchive = serde_json::from_value(api_request(&config, hyper::Method::GET, &path, None).await?)?;
+            let summary = archive.clone().open()
+                .map_err(|e| anyhow::anyhow!("The server returned an invalid snapshot: {:#}", e))?
+                .summary();
+            
+            let file = file.unwrap_or_else(|| format!("pear-snapshot-{}.json", chrono::Utc::now().format("%Y%m%d-%H%M%S")));
+            std::fs::write(&file, serde_json::to_vec_pretty(&archive)?)
+                .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", file, e))?;
+            
+            let report = serde_json::json!({ "file": file, "sha256": archive.sha256, "summary": summary });
+            if !print_structured(output, &report)? {
+                success(&format!("Snapshot saved to {}", file.cyan()));
+                print_snapshot_summary(&summary);
+            }
+        }
+        SnapshotAction::Restore { file, tenant, verify_only } => {
+            let contents = std::fs::read(&file)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", file, e))?;
+            // Checked here too, so a damaged archive is caught before it is uploaded
+            let mut snapshot = serde_json::from_slice::<SnapshotArchive>(&contents)
+                .map_err(anyhow::Error::from)
+                .and_then(SnapshotArchive::open)
+                .map_err(|e| anyhow::anyhow!("{} is not a valid snapshot: {:#}", file, e))?;
+            if let Some(tenant) = &tenant {
+                snapshot = snapshot.for_tenant(snapshot.find_tenant(tenant)?)?;
+            }
+            
+            if verify_only {
+                let summary = snapshot.summary();
+                if !print_structured(output, &summary)? {
+                    success(&format!("{} is intact", file.cyan()));
+                    print_snapshot_summary(&summary);
+                }
+                return Ok(());
+            }
+            
+            let mut path = "/api/snapshot/restore".to_string();
+            if let Some(tenant) = &tenant {
+                path.push_str(&format!("?tenant={}", url_encode(tenant)));
+            }
+            let (_, restored) = api_upload(&config, hyper::Method::POST, &path, "application/json", &[], contents).await?;
+            if !print_structured(output, &restored)? {
+                let summary: SnapshotSummary = serde_json::from_value(restored)?;
+                success(&format!("Restored {}", file.cyan()));
+                print_snapshot_summary(&summary);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_snapshot_summary(summary: &crate::storage::snapshot::SnapshotSummary) {
+    println!("  {} {}", "Tenants:".bright_white(), summary.tenants);
+    let sites = if summary.sites.is_empty() { "none".dimmed().to_string() } else { summary.sites.join(", ") };
+    println!("  {} {}", "Sites:".bright_white(), sites);
+    println!("  {} {}", "Modules:".bright_white(), summary.modules);
+    println!("  {} {}", "Bans:".bright_white(), summary.bans);
+    println!("  {} {}", "Documents:".bright_white(), summary.documents);
+}
+
+/// Chaos settings and recent faults, one line each
+async fn chaos_status(config: String, output: OutputFormat) -> anyhow::Result<()> {
+    let status = api_request(&config, hyper::Method::GET, "/api/chaos", None).await?;
+    if print_structured(output, &status)? {
+        return Ok(());
+    }
+    if status["enabled"].as_bool() != Some(true) {
+        info("Chaos mode is disabled (chaos.enabled = false)");
+        return Ok(());
+    }
+    let schedule = &status["config"];
+    println!(
+        "{} every {}s: crash {}, latency {}, health {}",
+        "Schedule".bright_white(),
+        schedule["interval_secs"],
+        schedule["crash_probability"],
+        schedule["latency_probability"],
+        schedule["health_probability"],
+    );
+    let faults = status["faults"].as_array().cloned().unwrap_or_default();
+    if faults.is_empty() {
+        info("No faults injected yet");
+        return Ok(());
+    }
+    println!("{}", format!("{:<19}  {:<20}  {:<9}  {}", "INJECTED", "SITE", "TRIGGER", "FAULT").bright_white());
+    for fault in faults.iter().rev() {
+        let injected = fault["injected_at"].as_i64()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        println!(
+            "{:<19}  {:<20}  {:<9}  {}",
+            injected,
+            fault["site_id"].as_str().unwrap_or_default(),
+            if fault["scheduled"].as_bool() == Some(true) { "schedule" } else { "manual" },
+            describe_fault(&fault["fault"]),
+        );
+    }
+    Ok(())
+}
+
+/// e.g. `latency of 500ms for 30s`
+fn describe_fault(fault: &serde_json::Value) -> String {
+    match fault["kind"].as_str().unwrap_or_default() {
+        "crash" => format!("crash of Cage {}", fault["cage"]),
+        "latency" => format!("latency of {}ms for {}s", fault["delay_ms"], fault["duration_secs"]),
+        "health" => format!("failing health checks on Cage {} for {}s", fault["cage"], fault["duration_secs"]),
+        kind => kind.to_string(),
+    }
+}
+
+/// Upgrade the running server in place
+async fn upgrade_command(config_path: String, binary: Option<String>) -> anyhow::Result<()> {
+    let config = crate::config::PearConfig::load(&config_path)?;
+    let pid_file = std::path::PathBuf::from(&config.server.pid_file);
+    
+    let old_pid = crate::upgrade::read_pid_file(&pid_file)
+        .map_err(|e| anyhow::anyhow!("Is Pear Server running? {}", e))?;
+    
+    if let Some(binary) = binary {
+        let binary = std::fs::canonicalize(&binary)
+            .map_err(|e| anyhow::anyhow!("Cannot find binary {}: {}", binary, e))?;
+        std::fs::write(crate::upgrade::next_binary_path(&pid_file), binary.to_string_lossy().as_bytes())?;
+        info(&format!("Upgrading to {}", binary.display().to_string().bright_white()));
+    }
+    
+    crate::upgrade::request_upgrade(old_pid)?;
+    
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏")
+            .template("{spinner:.cyan} {msg}")
+            .unwrap()
+    );
+    spinner.set_message(format!("Handing listeners from PID {} to new process...", old_pid));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    
+    // The successor rewrites the PID file once it is serving
+    let deadline = std::time::Instant::now() + Duration::from_secs(config.server.drain_timeout_secs.max(30));
+    loop {
+        match crate::upgrade::read_pid_file(&pid_file) {
+            Ok(pid) if pid != old_pid => {
+                spinner.finish_and_clear();
+                success(&format!("Upgrade complete: now serving from PID {}", pid.to_string().green()));
+                info(&format!("PID {} is draining in-flight requests", old_pid));
+                return Ok(());
+            }
+            _ if std::time::Instant::now() >= deadline => {
+                spinner.finish_and_clear();
+                error("Upgrade did not complete in time; the old process keeps serving");
+                anyhow::bail!("upgrade timed out");
+            }
+            _ => tokio::time::sleep(Duration::from_millis(200)).await,
+        }
+    }
+}
+
+/// Shed the node's connections to other nodes, or report or cancel shedding in progress
+async fn rebalance_command(
+    config: String,
+    over_secs: u64,
+    wait: bool,
+    cancel: bool,
+    status_only: bool,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let status = if cancel {
+        api_request(&config, hyper::Method::DELETE, "/api/rebalance", None).await?
+    } else if status_only {
+        api_request(&config, hyper::Method::GET, "/api/rebalance", None).await?
+    } else {
+        let body = serde_json::json!({ "over_secs": over_secs });
+        api_request(&config, hyper::Method::POST, "/api/rebalance", Some(body)).await?
+    };
+    if print_structured(output, &status)? {
+        return Ok(());
+    }
+
+    let open = status["open"].as_u64().unwrap_or(0);
+    let shed = status["shed"].as_u64().unwrap_or(0);
+    if cancel {
+        success("Rebalance cancelled; the node is ready for traffic again");
+        return Ok(());
+    }
+    match status["phase"].as_str().unwrap_or_default() {
+        "shedding" if !status_only => success(&format!(
+            "Shedding {} connections over {}s; readiness fails until `pear rebalance --cancel`",
+            open.to_string().bright_white(),
+            over_secs,
+        )),
+        "shedding" => info(&format!("Shedding: {} connections shed, {} still open", shed, open)),
+        "drained" => info(&format!("Drained: {} connections shed, {} still open", shed, open)),
+        _ => info("Not rebalancing; the node takes traffic"),
+    }
+    if !wait {
+        return Ok(());
+    }
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏")
+            .template("{spinner:.cyan} {msg}")
+            .unwrap()
+    );
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let status = api_request(&config, hyper::Method::GET, "/api/rebalance", None).await?;
+        let open = status["open"].as_u64().unwrap_or(0);
+        let shed = status["shed"].as_u64().unwrap_or(0);
+        match status["phase"].as_str().unwrap_or_default() {
+            "shedding" => spinner.set_message(format!("{} connections shed, {} still open...", shed, open)),
+            "drained" => {
+                spinner.finish_and_clear();
+                success(&format!("All connections shed ({}); {} still closing", shed, open));
+                return Ok(());
+            }
+            _ => {
+                spinner.finish_and_clear();
+                error("Rebalance was cancelled");
+                anyhow::bail!("rebalance cancelled");
+            }
+        }
+    }
+}
+
+/// Fill or empty the compiled module cache; works without a running node
+fn cache_command(action: CacheAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    use crate::cage::compile_cache::CompileCache;
+    use crate::storage::modules::ModuleStore;
+
+    match action {
+        CacheAction::Compile { modules, all } => {
+            let config = crate::config::PearConfig::load(&config)?;
+            let root = std::path::Path::new(&config.storage.root);
+            let cache = CompileCache::new(config.compile_cache.path(root), config.compile_cache.max_size_mb)?;
+            // The engine sites get unless they are being profiled, which compile as they start
+            let cage_config = crate::cage::config::CageConfig {
+                jit_profiler: config.profiling.jit_profiler,
+                fuel: config.billing.enabled.then(|| config.billing.fuel_per_call()).flatten(),
+                ..Default::default()
+            };
+            let engine = crate::cage::create_engine_for(&cage_config)?;
+
+            let mut sources: Vec<(String, Vec<u8>)> = Vec::new();
+            if all {
+                let store = ModuleStore::new(root.join("modules"))?;
+                for hash in store.hashes()? {
+                    sources.push((hash.to_string(), store.read(&hash)?));
+                }
+            }
+            for path in modules {
+                let wasm = std::fs::read(&path).with_context(|| format!("Failed to read {}", path))?;
+                sources.push((path, wasm));
+            }
+
+            let bar = ProgressBar::new(sources.len() as u64);
+            bar.set_style(ProgressStyle::default_bar().template("{bar:30.cyan} {pos}/{len} {msg}").unwrap());
+            let (mut compiled, mut cached, mut failed) = (0, 0, 0);
+            for (name, wasm) in &sources {
+                bar.set_message(name.clone());
+                match cache.compile(&engine, wasm) {
+                    Ok(true) => compiled += 1,
+                    Ok(false) => cached += 1,
+                    Err(e) => {
+                        failed += 1;
+                        bar.suspend(|| error(&format!("{}: {:#}", name, e)));
+                    }
+                }
+                bar.inc(1);
+            }
+            bar.finish_and_clear();
+
+            let stats = cache.stats()?;
+            let summary = serde_json::json!({
+                "compiled": compiled,
+                "cached": cached,
+                "failed": failed,
+                "modules": stats.modules,
+                "bytes": stats.bytes,
+            });
+            if !print_structured(output, &summary)? {
+                success(&format!("Compiled {} module(s), {} already cached", compiled.to_string().bright_white(), cached));
+                info(&format!("Cache: {} module(s), {:.1} MB", stats.modules, stats.bytes as f64 / (1024.0 * 1024.0)));
+            }
+            if failed > 0 {
+                anyhow::bail!("{} module(s) failed to compile", failed);
+            }
+        }
+        CacheAction::Clear => {
+            let config = crate::config::PearConfig::load(&config)?;
+            let cache = CompileCache::new(config.compile_cache.path(std::path::Path::new(&config.storage.root)), 0)?;
+            let removed = cache.evict(0)?;
+            if !print_structured(output, &serde_json::json!({ "removed": removed }))? {
+                success(&format!("Removed {} compiled module(s)", removed));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Manage site environment variables through the management API
+async fn env_command(action: EnvAction, config: String) -> anyhow::Result<()> {
+    match action {
+        EnvAction::Set { assignment, site, secret } => {
+            let (name, value) = match assignment.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    // Keeps secrets out of shell history and process listings
+                    let mut value = String::new();
+                    std::io::stdin().read_line(&mut value)?;
+                    (assignment, value.trim_end_matches(['\r', '\n']).to_string())
+                }
+            };
+            let body = serde_json::json!({ "value": value, "secret": secret });
+            api_request(&config, hyper::Method::PUT, &format!("/api/sites/{}/env/{}", site, name), Some(body)).await?;
+            
+            let kind = if secret { "secret" } else { "variable" };
+            success(&format!("Set {} {} on site '{}'", kind, name.cyan(), site.cyan()));
+            info("New Cages of the site receive the change; redeploy to apply it everywhere");
+        }
+        EnvAction::Unset { name, site } => {
+            api_request(&config, hyper::Method::DELETE, &format!("/api/sites/{}/env/{}", site, name), None).await?;
+            success(&format!("Removed {} from site '{}'", name.cyan(), site.cyan()));
+        }
+        EnvAction::List { site } => {
+            let listing = api_request(&config, hyper::Method::GET, &format!("/api/sites/{}/env", site), None).await?;
+            let entries = listing["env"].as_array().cloned().unwrap_or_default();
+            if entries.is_empty() {
+                info(&format!("No environment variables set on site '{}'", site.cyan()));
+            }
+            for entry in entries {
+                let name = entry["name"].as_str().unwrap_or_default();
+                match entry["value"].as_str() {
+                    Some(value) => println!("{}={}", name.bright_white(), value),
+                    None => println!("{}={}", name.bright_white(), "******** (secret)".bright_black()),
+                }
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// Manage scheduled jobs through the management API
+async fn cron_command(action: CronAction, config: String) -> anyhow::Result<()> {
+    match action {
+        CronAction::Add { name, site, schedule, export, timeout } => {
+            let body = serde_json::json!({ "name": name, "schedule": schedule, "export": export, "timeout_secs": timeout });
+            let job = api_request(&config, hyper::Method::POST, &format!("/api/sites/{}/cron", site), Some(body)).await?;
+            
+            success(&format!("Scheduled {} on site '{}' ({})", name.cyan(), site.cyan(), schedule));
+            if let Some(next) = job["next_run"].as_i64().and_then(|t| chrono::DateTime::from_timestamp(t, 0)) {
+                info(&format!("Next run at {}", next.format("%Y-%m-%d %H:%M UTC")));
+            }
+        }
+        CronAction::Remove { name, site } => {
+            api_request(&config, hyper::Method::DELETE, &format!("/api/sites/{}/cron/{}", site, name), None).await?;
+            success(&format!("Removed job {} from site '{}'", name.cyan(), site.cyan()));
+        }
+        CronAction::List { site } => {
+            let path = match &site {
+                Some(site) => format!("/api/sites/{}/cron", site),
+                None => "/api/cron".to_string(),
+            };
+            let listing = api_request(&config, hyper::Method::GET, &path, None).await?;
+            let jobs = listing["jobs"].as_array().cloned().unwrap_or_default();
+            if jobs.is_empty() {
+                info("No scheduled jobs");
+            }
+            
+            let time = |value: &serde_json::Value| {
+                value.as_i64()
+                    .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                    .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            for job in jobs {
+                let last = match job["last_run"]["outcome"].as_str() {
+                    Some("success") => "success".green(),
+                    Some(outcome) => outcome.red(),
+                    None => "never run".bright_black(),
+                };
+                println!(
+                    "{}/{}  {}  {}()  next {}  last {}{}",
+                    job["site_id"].as_str().unwrap_or_default().bright_white(),
+                    job["name"].as_str().unwrap_or_default().bright_white(),
+                    job["schedule"].as_str().unwrap_or_default(),
+                    job["export"].as_str().unwrap_or_default(),
+                    time(&job["next_run"]),
+                    last,
+                    if job["running"].as_bool() == Some(true) { " (running)".yellow() } else { "".normal() },
+                );
+            }
+        }
+        CronAction::History { name, site } => {
+            let listing = api_request(&config, hyper::Method::GET, &format!("/api/sites/{}/cron/{}/history", site, name), None).await?;
+            let runs = listing["runs"].as_array().cloned().unwrap_or_default();
+            if runs.is_empty() {
+                info(&format!("Job {} has not run yet", name.cyan()));
+            }
+            for run in runs {
+                let started = run["started_at"].as_i64()
+                    .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                let outcome = run["outcome"].as_str().unwrap_or_default();
+                let outcome = if outcome == "success" { outcome.green() } else { outcome.red() };
+                println!(
+                    "{}  {:<9}  {:>6}ms  {}",
+                    started,
+                    outcome,
+                    run["duration_ms"].as_u64().unwrap_or_default(),
+                    run["error"].as_str().unwrap_or_default(),
+                );
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// Manage tenants through the management API
+async fn tenant_command(action: TenantAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    match action {
+        TenantAction::Create { name, email, quota } => {
+            let body = serde_json::json!({ "name": name, "email": email });
+            let mut tenant = api_request(&config, hyper::Method::POST, "/api/tenants", Some(body)).await?;
+            let changes = quota.changes();
+            if !changes.is_empty() {
+                let path = format!("/api/tenants/{}/quota", tenant["id"].as_str().unwrap_or_default());
+                tenant["quota"] = api_request(&config, hyper::Method::PUT, &path, Some(changes.into())).await?;
+            }
+            if print_structured(output, &tenant)? {
+                return Ok(());
+            }
+            success(&format!("Created tenant {} ({})", name.cyan(), tenant["id"].as_str().unwrap_or_default()));
+        }
+        TenantAction::List => {
+            let listing = api_request(&config, hyper::Method::GET, "/api/tenants", None).await?;
+            if print_structured(output, &listing)? {
+                return Ok(());
+            }
+            let tenants = listing["tenants"].as_array().cloned().unwrap_or_default();
+            println!("{}", format!("{:<36}  {:<20}  {:<9}  {:>5}  {}", "ID", "NAME", "STATUS", "SITES", "EMAIL").bright_white());
+            for tenant in tenants {
+                let status = tenant["status"].as_str().unwrap_or_default();
+                let padded = format!("{:<9}", status);
+                println!(
+                    "{:<36}  {:<20}  {}  {:>5}  {}",
+                    tenant["id"].as_str().unwrap_or_default(),
+                    tenant["name"].as_str().unwrap_or_default(),
+                    if status == "Active" { padded.green() } else { padded.yellow() },
+                    tenant["sites"].as_u64().unwrap_or(0),
+                    tenant["email"].as_str().unwrap_or_default(),
+                );
+            }
+        }
+        TenantAction::Suspend { tenant } => {
+            let result = api_request(&config, hyper::Method::POST, &format!("/api/tenants/{}/suspend", tenant), None).await?;
+            if !print_structured(output, &result)? {
+                warning(&format!("Suspended tenant {}", tenant.cyan()));
+            }
+        }
+        TenantAction::Activate { tenant } => {
+            let result = api_request(&config, hyper::Method::POST, &format!("/api/tenants/{}/activate", tenant), None).await?;
+            if !print_structured(output, &result)? {
+                success(&format!("Activated tenant {}", tenant.cyan()));
+            }
+        }
+        TenantAction::Delete { tenant } => {
+            let result = api_request(&config, hyper::Method::DELETE, &format!("/api/tenants/{}", tenant), None).await?;
+            if !print_structured(output, &result)? {
+                let sites = result["sites"].as_array().map_or(0, |sites| sites.len());
+                warning(&format!("Deleted tenant {} and {} site(s)", tenant.cyan(), sites));
+            }
+        }
+        TenantAction::Quota { tenant, quota } => {
+            let path = format!("/api/tenants/{}/quota", tenant);
+            let changes = quota.changes();
+            let quota = if changes.is_empty() {
+                api_request(&config, hyper::Method::GET, &path, None).await?
+            } else {
+                api_request(&config, hyper::Method::PUT, &path, Some(changes.into())).await?
+            };
+            if print_structured(output, &quota)? {
+                return Ok(());
+            }
+            for (name, value) in quota.as_object().cloned().unwrap_or_default() {
+                let value = if value.is_null() { "unlimited".bright_black() } else { value.to_string().normal() };
+                println!("{} {}", format!("{:<24}", name).bright_white(), value);
+            }
+        }
+        TenantAction::Usage { tenant } => {
+            let report = api_request(&config, hyper::Method::GET, &format!("/api/tenants/{}/usage", tenant), None).await?;
+            if print_structured(output, &report)? {
+                return Ok(());
+            }
+            let usage = &report["usage"];
+            let row = |label: &str, used: String, limit: Option<u64>| {
+                let limit = limit.map(|l| l.to_string()).unwrap_or_else(|| "unlimited".to_string());
+                println!("{} {} / {}", format!("{:<16}", label).bright_white(), used.cyan(), limit);
+            };
+            row("Sites", usage["sites_used"].to_string(), usage["sites_limit"].as_u64());
+            row("Storage (MB)", usage["storage_used_mb"].to_string(), usage["storage_limit_mb"].as_u64());
+            let counted = usage["storage_scanned_at"].as_i64()
+                .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| "not yet".to_string());
+            println!("{} {}", format!("{:<16}", "Storage counted").bright_white(), counted.bright_black());
+            row("Bandwidth (MB)", usage["bandwidth_used_mb"].to_string(), usage["bandwidth_limit_mb"].as_u64());
+            println!("{} {}", format!("{:<16}", "Cages running").bright_white(), usage["cages_running"].to_string().cyan());
+        }
+        TenantAction::Keys { tenant } => {
+            let listing = api_request(&config, hyper::Method::GET, &format!("/api/tenants/{}/keys", tenant), None).await?;
+            if print_structured(output, &listing)? {
+                return Ok(());
+            }
+            let keys = listing["keys"].as_array().cloned().unwrap_or_default();
+            if keys.is_empty() {
+                info(&format!("Tenant {} has no signing keys", tenant.cyan()));
+                return Ok(());
+            }
+            println!("{}", format!("{:<20}  {:<44}  {}", "NAME", "PUBLIC KEY", "ADDED").bright_white());
+            for key in keys {
+                println!(
+                    "{:<20}  {:<44}  {}",
+                    key["name"].as_str().unwrap_or_default(),
+                    key["public_key"].as_str().unwrap_or_default(),
+                    key["added_at"].as_i64()
+                        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default(),
+                );
+            }
+        }
+        TenantAction::AddKey { tenant, name, key } => {
+            // A path to a key file, or the key itself
+            let public_key = match std::fs::read_to_string(&key) {
+                Ok(contents) => contents,
+                Err(_) => key,
+            };
+            let body = serde_json::json!({ "name": name, "public_key": public_key });
+            let key = api_request(&config, hyper::Method::POST, &format!("/api/tenants/{}/keys", tenant), Some(body)).await?;
+            if !print_structured(output, &key)? {
+                success(&format!("Added signing key {} to tenant {}", name.cyan(), tenant.cyan()));
+            }
+        }
+        TenantAction::RemoveKey { tenant, name } => {
+            let path = format!("/api/tenants/{}/keys/{}", tenant, url_encode(&name));
+            let result = api_request(&config, hyper::Method::DELETE, &path, None).await?;
+            if !print_structured(output, &result)? {
+                success(&format!("Removed signing key {} from tenant {}", name.cyan(), tenant.cyan()));
+            }
+        }
+        TenantAction::Encrypt { tenant, rotate } => {
+            let path = if rotate {
+                format!("/api/tenants/{}/encryption/rotate", tenant)
+            } else {
+                format!("/api/tenants/{}/encryption", tenant)
+            };
+            let result = api_request(&config, hyper::Method::POST, &path, None).await?;
+            if !print_structured(output, &result)? {
+                success(&format!(
+                    "Tenant {} encrypted with key version {} ({} files re-encrypted)",
+                    tenant.cyan(),
+                    result["key_version"],
+                    result["files_encrypted"],
+                ));
+            }
+        }
+        TenantAction::Artifacts { tenant, remove: true, .. } => {
+            let result = api_request(&config, hyper::Method::DELETE, &format!("/api/tenants/{}/artifacts", tenant), None).await?;
+            if !print_structured(output, &result)? {
+                success(&format!("Tenant {} uses the node's artifact source again", tenant.cyan()));
+            }
+        }
+        TenantAction::Artifacts { tenant, endpoint, bucket, region, prefix, path_style, access_key_id, remove: false } => {
+            // Keeps the secret out of shell history and process listings
+            let mut secret_access_key = String::new();
+            std::io::stdin().read_line(&mut secret_access_key)?;
+            let body = serde_json::json!({
+                "endpoint": endpoint,
+                "bucket": bucket,
+                "region": region,
+                "prefix": prefix,
+                "path_style": path_style,
+                "access_key_id": access_key_id,
+                "secret_access_key": secret_access_key.trim_end_matches(['\r', '\n']),
+            });
+            let result = api_request(&config, hyper::Method::PUT, &format!("/api/tenants/{}/artifacts", tenant), Some(body)).await?;
+            if !print_structured(output, &result)? {
+                success(&format!(
+                    "Tenant {} pulls artifacts from {}",
+                    tenant.cyan(),
+                    result["bucket"].as_str().unwrap_or_default().bright_white(),
+                ));
+            }
+        }
+        TenantAction::RewrapKeys => {
+            let result = api_request(&config, hyper::Method::POST, "/api/encryption/rewrap", None).await?;
+            if !print_structured(output, &result)? {
+                success(&format!("Rewrapped the keys of {} tenants", result["tenants"]));
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// Manage sites through the management API
+async fn site_command(action: SiteAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    match action {
+        SiteAction::Add { name, tenant, domain } => {
+            let body = serde_json::json!({ "name": name, "domain": domain });
+            let site = api_request(&config, hyper::Method::POST, &format!("/api/tenants/{}/sites", tenant), Some(body)).await?;
+            if print_structured(output, &site)? {
+                return Ok(());
+            }
+            success(&format!("Added site {} ({})", name.cyan(), site["id"].as_str().unwrap_or_default()));
+            if let Some(domain) = site["domain"].as_str() {
+                info(&format!("Attached domain {}", domain.cyan()));
+            }
+        }
+        SiteAction::Remove { site } => {
+            let result = api_request(&config, hyper::Method::DELETE, &format!("/api/sites/{}", site), None).await?;
+            if !print_structured(output, &result)? {
+                success(&format!("Removed site {}", site.cyan()));
+            }
+        }
+        SiteAction::List { tenant } => {
+            let listing = api_request(&config, hyper::Method::GET, &format!("/api/tenants/{}/sites", tenant), None).await?;
+            if print_structured(output, &listing)? {
+                return Ok(());
+            }
+            let sites = listing["sites"].as_array().cloned().unwrap_or_default();
+            if sites.is_empty() {
+                info(&format!("Tenant {} has no sites", tenant.cyan()));
+                return Ok(());
+            }
+            println!("{}", format!("{:<41}  {:<20}  {:<28}  {:>5}", "ID", "NAME", "DOMAIN", "CAGES").bright_white());
+            for site in sites {
+                let cages = match site["cages"].is_null() {
+                    true => "-".to_string(),
+                    false => format!("{}/{}", site["cages"]["healthy_cages"], site["cages"]["total_cages"]),
+                };
+                println!(
+                    "{:<41}  {:<20}  {:<28}  {:>5}",
+                    site["id"].as_str().unwrap_or_default(),
+                    site["name"].as_str().unwrap_or_default(),
+                    site["domain"].as_str().unwrap_or("-"),
+                    cages,
+                );
+            }
+        }
+        SiteAction::Domain { site, domain, remove: _ } => {
+            let path = format!("/api/sites/{}/domain", site);
+            let result = match &domain {
+                Some(domain) => api_request(&config, hyper::Method::PUT, &path, Some(serde_json::json!({ "domain": domain }))).await?,
+                None => api_request(&config, hyper::Method::DELETE, &path, None).await?,
+            };
+            if print_structured(output, &result)? {
+                return Ok(());
+            }
+            match result["domain"].as_str() {
+                Some(domain) => success(&format!("Attached {} to site {}", domain.cyan(), site.cyan())),
+                None => success(&format!("Detached the domain of site {}", site.cyan())),
+            }
+        }
+        SiteAction::Drain { site } => {
+            let result = api_request(&config, hyper::Method::POST, &format!("/api/sites/{}/drain", site), None).await?;
+            if print_structured(output, &result)? {
+                return Ok(());
+            }
+            success(&format!("Draining site {}; new requests get 503 until it is resumed", site.cyan()));
+            match result["in_flight"].as_u64().unwrap_or(0) {
+                0 => info("No requests in flight"),
+                in_flight => info(&format!("{} requests still in flight", in_flight)),
+            }
+        }
+        SiteAction::Resume { site } => {
+            let result = api_request(&config, hyper::Method::POST, &format!("/api/sites/{}/resume", site), None).await?;
+            if !print_structured(output, &result)? {
+                success(&format!("Resumed site {}", site.cyan()));
+            }
+        }
+        SiteAction::SyncContent { site, prefix } => {
+            let body = serde_json::json!({ "prefix": prefix });
+            let result = api_request(&config, hyper::Method::POST, &format!("/api/sites/{}/content/sync", site), Some(body)).await?;
+            if print_structured(output, &result)? {
+                return Ok(());
+            }
+            success(&format!(
+                "Synced {} into site {}: {} fetched, {} unchanged, {} removed",
+                result["prefix"].as_str().unwrap_or_default().bright_white(),
+                site.cyan(),
+                result["fetched"],
+                result["unchanged"],
+                result["removed"],
+            ));
+        }
+        SiteAction::RequireSignature { site, off } => {
+            let body = serde_json::json!({ "required": !off });
+            let result = api_request(&config, hyper::Method::PUT, &format!("/api/sites/{}/signing", site), Some(body)).await?;
+            if print_structured(output, &result)? {
+                return Ok(());
+            }
+            if off {
+                warning(&format!("Site {} accepts unsigned modules again", site.cyan()));
+            } else {
+                success(&format!("Site {} now only accepts signed modules", site.cyan()));
+            }
+        }
+        SiteAction::Crashes { site, id: Some(id) } => {
+            let report = api_request(&config, hyper::Method::GET, &format!("/api/sites/{}/crashes/{}", site, id), None).await?;
+            if print_structured(output, &report)? {
+                return Ok(());
+            }
+            let crashed = report["crashed_at"].as_i64()
+                .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            println!("{} {}", "Crash".bright_white(), format!("#{}", id).cyan());
+            println!("  Cage:     {}", report["cage_id"]);
+            println!("  Time:     {}", crashed);
+            println!("  Trap:     {}", report["trap"].as_str().unwrap_or("-"));
+            println!("  Function: {}", report["function"].as_str().unwrap_or("-"));
+            println!("  Call:     {} {}", report["call"]["kind"].as_str().unwrap_or_default(), report["call"]["export"].as_str().unwrap_or_default());
+            println!("  Error:    {}", report["message"].as_str().unwrap_or_default().red());
+            println!("\n{}", "Backtrace".bright_white());
+            for (n, frame) in report["backtrace"].as_array().cloned().unwrap_or_default().iter().enumerate() {
+                let name = frame["func_name"].as_str().map(str::to_string)
+                    .unwrap_or_else(|| format!("<wasm function {}>", frame["func_index"]));
+                let offset = frame["module_offset"].as_u64().map(|o| format!(" @ {:#x}", o)).unwrap_or_default();
+                println!("  {:>2}: {}{}", n, name, offset.bright_black());
+                for symbol in frame["symbols"].as_array().cloned().unwrap_or_default() {
+                    println!("        {}", symbol.as_str().unwrap_or_default().bright_black());
+                }
+            }
+            let logs = report["guest_logs"].as_array().cloned().unwrap_or_default();
+            if !logs.is_empty() {
+                println!("\n{}", "Last output".bright_white());
+                for line in logs {
+                    println!("  {}", line.as_str().unwrap_or_default());
+                }
+            }
+        }
+        SiteAction::Crashes { site, id: None } => {
+            let listing = api_request(&config, hyper::Method::GET, &format!("/api/sites/{}/crashes", site), None).await?;
+            if print_structured(output, &listing)? {
+                return Ok(());
+            }
+            let crashes = listing["crashes"].as_array().cloned().unwrap_or_default();
+            if crashes.is_empty() {
+                info(&format!("Site {} has no crashes", site.cyan()));
+                return Ok(());
+            }
+            println!("{}", format!("{:<6}  {:<19}  {:<6}  {:<28}  {}", "ID", "TIME", "CAGE", "FUNCTION", "TRAP").bright_white());
+            for crash in &crashes {
+                let crashed = crash["crashed_at"].as_i64()
+                    .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                println!(
+                    "{:<6}  {:<19}  {:<6}  {:<28}  {}",
+                    crash["id"],
+                    crashed,
+                    crash["cage_id"],
+                    crash["function"].as_str().unwrap_or("-"),
+                    crash["trap"].as_str().unwrap_or_else(|| crash["message"].as_str().unwrap_or_default()).red(),
+                );
+            }
+        }
+    }
     
     Ok(())
 }
+
+/// Manage site hostnames through the management API
+async fn domain_command(action: DomainAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    match action {
+        DomainAction::List { site } => {
+            let listing = api_request(&config, hyper::Method::GET, &format!("/api/sites/{}/domains", site), None).await?;
+            if print_structured(output, &listing)? {
+                return Ok(());
+            }
+            let domains = listing["domains"].as_array().cloned().unwrap_or_default();
+            if domains.is_empty() {
+                info(&format!("Site {} has no domains", site.cyan()));
+                return Ok(());
+            }
+            println!("{}", format!("{:<36}  {:<8}  {:<6}  {:<9}  {}", "HOSTNAME", "STATUS", "METHOD", "CERT", "REDIRECT").bright_white());
+            for domain in &domains {
+                let status = domain["status"].as_str().unwrap_or_default();
+                let padded = format!("{:<8}", status);
+                println!(
+                    "{:<36}  {}  {:<6}  {:<9}  {}",
+                    domain["hostname"].as_str().unwrap_or_default(),
+                    match status {
+                        "verified" => padded.green(),
+                        "failed" => padded.red(),
+                        _ => padded.yellow(),
+                    },
+                    domain["method"].as_str().unwrap_or_default(),
+                    domain["certificate"].as_str().unwrap_or_default(),
+                    domain["redirect_to"].as_str().map(|target| format!("→ {}", target)).unwrap_or_default(),
+                );
+            }
+            for domain in domains.iter().filter(|domain| domain["status"] == "pending") {
+                print_challenge(domain);
+            }
+        }
+        DomainAction::Add { hostname, site, method, redirect_to } => {
+            let body = serde_json::json!({ "hostname": hostname, "method": method, "redirect_to": redirect_to });
+            let domain = api_request(&config, hyper::Method::POST, &format!("/api/sites/{}/domains", site), Some(body)).await?;
+            if print_structured(output, &domain)? {
+                return Ok(());
+            }
+            success(&format!("Claimed {} for site {}", domain["hostname"].as_str().unwrap_or_default().cyan(), site.cyan()));
+            print_challenge(&domain);
+        }
+        DomainAction::Remove { hostname, site } => {
+            let path = format!("/api/sites/{}/domains/{}", site, url_encode(&hostname));
+            let result = api_request(&config, hyper::Method::DELETE, &path, None).await?;
+            if !print_structured(output, &result)? {
+                success(&format!("Released {} from site {}", hostname.cyan(), site.cyan()));
+            }
+        }
+        DomainAction::Verify { hostname, site } => {
+            let path = format!("/api/sites/{}/domains/{}/verify", site, url_encode(&hostname));
+            let domain = api_request(&config, hyper::Method::POST, &path, None).await?;
+            if print_structured(output, &domain)? {
+                return Ok(());
+            }
+            match domain["status"].as_str() {
+                Some("verified") => success(&format!("{} is verified and serving site {}", hostname.cyan(), site.cyan())),
+                _ => {
+                    error(domain["last_error"].as_str().unwrap_or("Not verified yet"));
+                    print_challenge(&domain);
+                    anyhow::bail!("{} is not verified", hostname);
+                }
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// What to publish for a pending domain to verify
+fn print_challenge(domain: &serde_json::Value) {
+    let challenge = &domain["challenge"];
+    let hostname = domain["hostname"].as_str().unwrap_or_default();
+    match challenge["type"].as_str() {
+        Some("dns") => info(&format!(
+            "Verify {} with a TXT record: {} = \"{}\"",
+            hostname.cyan(),
+            challenge["name"].as_str().unwrap_or_default().bright_white(),
+            challenge["value"].as_str().unwrap_or_default(),
+        )),
+        Some("http") => info(&format!(
+            "Point {} at this server; it is verified once {} answers",
+            hostname.cyan(),
+            challenge["url"].as_str().unwrap_or_default().bright_white(),
+        )),
+        _ => {}
+    }
+}
+
+async fn webhook_command(action: WebhookAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    match action {
+        WebhookAction::List { tenant } => {
+            let path = match tenant {
+                Some(tenant) => format!("/api/webhooks?tenant={}", url_encode(&tenant)),
+                None => "/api/webhooks".to_string(),
+            };
+            let listing = api_request(&config, hyper::Method::GET, &path, None).await?;
+            if print_structured(output, &listing)? {
+                return Ok(());
+            }
+            let webhooks = listing["webhooks"].as_array().cloned().unwrap_or_default();
+            if webhooks.is_empty() {
+                info("No webhooks registered");
+                return Ok(());
+            }
+            println!("{}", format!("{:<36}  {:<40}  {:<9}  {:<6}  {}", "ID", "URL", "DELIVERED", "FAILED", "EVENTS").bright_white());
+            for webhook in &webhooks {
+                let events: Vec<&str> = webhook["events"].as_array().map_or_else(Vec::new, |events| {
+                    events.iter().filter_map(|event| event.as_str()).collect()
+                });
+                let failed = webhook["failed"].as_u64().unwrap_or_default();
+                println!(
+                    "{:<36}  {:<40}  {:<9}  {:<6}  {}",
+                    webhook["id"].as_str().unwrap_or_default(),
+                    webhook["url"].as_str().unwrap_or_default(),
+                    webhook["delivered"],
+                    if failed > 0 { failed.to_string().red() } else { failed.to_string().normal() },
+                    if events.is_empty() { "all".to_string() } else { events.join(",") },
+                );
+                if let Some(error) = webhook["last_error"].as_str() {
+                    println!("{:>38} {}", "last error:".bright_black(), error.red());
+                }
+            }
+        }
+        WebhookAction::Add { url, events, tenant, secret, max_per_minute } => {
+            let body = serde_json::json!({
+                "url": url,
+                "events": events,
+                "tenant_id": tenant,
+                "secret": secret.unwrap_or_default(),
+                "max_per_minute": max_per_minute,
+            });
+            let webhook = api_request(&config, hyper::Method::POST, "/api/webhooks", Some(body)).await?;
+            if print_structured(output, &webhook)? {
+                return Ok(());
+            }
+            success(&format!("Registered webhook {}", webhook["id"].as_str().unwrap_or_default().cyan()));
+            println!("  Secret: {}", webhook["secret"].as_str().unwrap_or_default().yellow());
+            println!("  Verify X-Pear-Signature as sha256=HMAC-SHA256(secret, \"<X-Pear-Timestamp>.<body>\")");
+        }
+        WebhookAction::Remove { id } => {
+            let removed = api_request(&config, hyper::Method::DELETE, &format!("/api/webhooks/{}", id), None).await?;
+            if print_structured(output, &removed)? {
+                return Ok(());
+            }
+            success(&format!("Removed webhook {}", id.cyan()));
+        }
+    }
+    Ok(())
+}
+
+async fn token_command(action: TokenAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    match action {
+        TokenAction::Create { name, role, tenant, sites, read_only, expires_in_days } => {
+            let body = serde_json::json!({
+                "name": name,
+                "role": role,
+                "tenant_id": tenant,
+                "sites": sites,
+                "read_only": read_only,
+                "expires_in_days": expires_in_days,
+            });
+            let token = api_request(&config, hyper::Method::POST, "/api/tokens", Some(body)).await?;
+            if print_structured(output, &token)? {
+                return Ok(());
+            }
+            success(&format!("Issued token '{}' ({})", name.cyan(), token["id"].as_str().unwrap_or_default()));
+            println!("  Secret: {}", token["secret"].as_str().unwrap_or_default().yellow());
+            println!("  Send it as 'Authorization: Bearer <secret>' or set PEAR_ADMIN_TOKEN; it is not shown again");
+        }
+        TokenAction::List { tenant } => {
+            let path = match tenant {
+                Some(tenant) => format!("/api/tokens?tenant={}", url_encode(&tenant)),
+                None => "/api/tokens".to_string(),
+            };
+            let listing = api_request(&config, hyper::Method::GET, &path, None).await?;
+            if print_structured(output, &listing)? {
+                return Ok(());
+            }
+            let tokens = listing["tokens"].as_array().cloned().unwrap_or_default();
+            if tokens.is_empty() {
+                info("No API tokens issued");
+                return Ok(());
+            }
+            println!("{}", format!("{:<36}  {:<20}  {:<16}  {:<36}  {}", "ID", "NAME", "ROLE", "TENANT", "LIMITS").bright_white());
+            for token in &tokens {
+                let mut limits: Vec<String> = token["sites"].as_array().map_or_else(Vec::new, |sites| {
+                    sites.iter().filter_map(|site| site.as_str()).map(|site| format!("site:{}", site)).collect()
+                });
+                if token["read_only"].as_bool().unwrap_or_default() {
+                    limits.push("read-only".to_string());
+                }
+                if let Some(expires_at) = token["expires_at"].as_i64() {
+                    let expires = chrono::DateTime::from_timestamp(expires_at, 0).map_or_else(String::new, |at| at.format("%Y-%m-%d").to_string());
+                    limits.push(format!("expires {}", expires));
+                }
+                println!(
+                    "{:<36}  {:<20}  {:<16}  {:<36}  {}",
+                    token["id"].as_str().unwrap_or_default(),
+                    token["name"].as_str().unwrap_or_default(),
+                    token["role"].as_str().unwrap_or_default(),
+                    token["tenant_id"].as_str().unwrap_or("-"),
+                    limits.join(", "),
+                );
+            }
+        }
+        TokenAction::Revoke { id } => {
+            let revoked = api_request(&config, hyper::Method::DELETE, &format!("/api/tokens/{}", id), None).await?;
+            if print_structured(output, &revoked)? {
+                return Ok(());
+            }
+            success(&format!("Revoked token {}", id.cyan()));
+        }
+    }
+    Ok(())
+}
+
+async fn role_command(action: RoleAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    match action {
+        RoleAction::List { tenant } => {
+            let path = match tenant {
+                Some(tenant) => format!("/api/roles?tenant={}", url_encode(&tenant)),
+                None => "/api/roles".to_string(),
+            };
+            let listing = api_request(&config, hyper::Method::GET, &path, None).await?;
+            if print_structured(output, &listing)? {
+                return Ok(());
+            }
+            println!("{}", format!("{:<20}  {:<36}  {}", "ROLE", "TENANT", "PERMISSIONS").bright_white());
+            for role in listing["roles"].as_array().cloned().unwrap_or_default() {
+                let permissions: Vec<&str> = role["permissions"].as_array().map_or_else(Vec::new, |permissions| {
+                    permissions.iter().filter_map(|permission| permission.as_str()).collect()
+                });
+                let tenant = if role["builtin"].as_bool().unwrap_or_default() { "built-in" } else { role["tenant_id"].as_str().unwrap_or("-") };
+                println!(
+                    "{:<20}  {:<36}  {}",
+                    role["name"].as_str().unwrap_or_default(),
+                    tenant,
+                    permissions.join(","),
+                );
+            }
+        }
+        RoleAction::Set { name, permissions, tenant } => {
+            let body = serde_json::json!({ "tenant_id": tenant, "permissions": permissions });
+            let role = api_request(&config, hyper::Method::PUT, &format!("/api/roles/{}", url_encode(&name)), Some(body)).await?;
+            if print_structured(output, &role)? {
+                return Ok(());
+            }
+            success(&format!("Saved role '{}'", name.cyan()));
+        }
+        RoleAction::Remove { name, tenant } => {
+            let mut path = format!("/api/roles/{}", url_encode(&name));
+            if let Some(tenant) = tenant {
+                path.push_str(&format!("?tenant={}", url_encode(&tenant)));
+            }
+            let removed = api_request(&config, hyper::Method::DELETE, &path, None).await?;
+            if print_structured(output, &removed)? {
+                return Ok(());
+            }
+            success(&format!("Removed role '{}'", name.cyan()));
+        }
+    }
+    Ok(())
+}
+
+/// Directory of the CA configured in `config_path`, and the `[mtls]` section
+fn ca_location(config_path: &str) -> anyhow::Result<(std::path::PathBuf, crate::network::mtls::MtlsConfig)> {
+    let config = crate::config::PearConfig::load(config_path)?;
+    Ok((config.mtls.ca_path(std::path::Path::new(&config.storage.root)), config.mtls))
+}
+
+fn ca_command(action: CaAction, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    use crate::network::mtls::{CertKind, CertificateAuthority};
+
+    match action {
+        CaAction::Init { name } => {
+            let (dir, _) = ca_location(&config)?;
+            CertificateAuthority::init(&dir, &name)?;
+            success(&format!("Created certificate authority '{}' in {}", name.cyan(), dir.display()));
+            info("Set [mtls] enabled = true and restart to require client certificates on the management API");
+        }
+        CaAction::Issue { name, peer, hosts, days, out } => {
+            let (dir, mtls) = ca_location(&config)?;
+            let mut ca = CertificateAuthority::open(&dir)?;
+            let kind = if peer { CertKind::Peer } else { CertKind::Operator };
+            let pem = ca.issue(&name, kind, &hosts, days.unwrap_or(mtls.validity_days))?;
+
+            let out = std::path::Path::new(&out);
+            std::fs::create_dir_all(out)?;
+            let cert_path = out.join(format!("{}.pem", name));
+            let key_path = out.join(format!("{}.key", name));
+            std::fs::write(&cert_path, &pem.cert)?;
+            {
+                use std::io::Write;
+                use std::os::unix::fs::OpenOptionsExt;
+                std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&key_path)
+                    .with_context(|| format!("Failed to create {}", key_path.display()))?
+                    .write_all(pem.key.as_bytes())?;
+            }
+            std::fs::copy(dir.join("ca.pem"), out.join("ca.pem"))?;
+
+            success(&format!("Issued {} certificate {}", kind.as_str(), name.cyan()));
+            println!("  Certificate: {}", cert_path.display());
+            println!("  Key:         {}", key_path.display());
+            println!("  CA:          {}", out.join("ca.pem").display());
+            if peer {
+                println!("  On the peer, set [mtls] cert_path and key_path to these files and copy ca.pem to its ca_dir");
+            } else {
+                println!("  Use with PEAR_CLIENT_CERT, PEAR_CLIENT_KEY and PEAR_CA_CERT, or curl --cert --key --cacert");
+            }
+        }
+        CaAction::Revoke { name } => {
+            let (dir, _) = ca_location(&config)?;
+            let revoked = CertificateAuthority::open(&dir)?.revoke(&name)?;
+            success(&format!("Revoked {} certificate(s) named {}", revoked, name.cyan()));
+            info("Peers without the CA key check revocations against their own copy of issued.json");
+        }
+        CaAction::List => {
+            let (dir, _) = ca_location(&config)?;
+            let ca = CertificateAuthority::open(&dir)?;
+            if print_structured(output, &serde_json::json!({ "certificates": ca.list() }))? {
+                return Ok(());
+            }
+            if ca.list().is_empty() {
+                info("No certificates issued");
+                return Ok(());
+            }
+            let now = chrono::Utc::now().timestamp();
+            println!("{}", format!("{:<24}  {:<8}  {:<10}  {:<10}  {}", "NAME", "KIND", "EXPIRES", "STATUS", "SERIAL").bright_white());
+            for cert in ca.list() {
+                let status = match cert.revoked_at {
+                    Some(_) => "revoked".red(),
+                    None if cert.expires_at <= now => "expired".yellow(),
+                    None => "active".green(),
+                };
+                let expires = chrono::DateTime::from_timestamp(cert.expires_at, 0)
+                    .map_or_else(String::new, |at| at.format("%Y-%m-%d").to_string());
+                println!("{:<24}  {:<8}  {:<10}  {:<10}  {}", cert.name, cert.kind.as_str(), expires, status, cert.serial);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print recent log lines, then keep printing new ones when following
+async fn logs_command(args: LogsArgs, config: String) -> anyhow::Result<()> {
+    let mut query = vec![format!("limit={}", args.lines), format!("follow={}", args.follow)];
+    let params = [
+        ("site", args.site),
+        ("kind", args.source.map(|source| source.as_str().to_string())),
+        ("level", args.level),
+        ("since", args.since.map(|t| t.to_string())),
+        ("grep", args.grep),
+        ("request_id", args.request_id),
+    ];
+    for (name, value) in params {
+        if let Some(value) = value {
+            query.push(format!("{}={}", name, url_encode(&value)));
+        }
+    }
+    
+    api_stream(&config, &format!("/api/logs?{}", query.join("&")), |line| {
+        let Ok(record) = serde_json::from_str::<crate::observability::logs::LogRecord>(line) else { return };
+        let time = chrono::DateTime::from_timestamp_millis(record.timestamp)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+            .unwrap_or_default();
+        let padded = format!("{:<5}", record.level);
+        let level = match record.level.as_str() {
+            "ERROR" => padded.red().bold(),
+            "WARN" => padded.yellow(),
+            "INFO" => padded.green(),
+            _ => padded.bright_black(),
+        };
+        let site = record.site.map(|site| format!(" [{}]", site).cyan()).unwrap_or_else(|| "".normal());
+        // Guest lines say which Cage and stream printed them
+        let origin = match record.kind {
+            crate::observability::logs::LogKind::Guest => format!(" {}", record.target).bright_black(),
+            _ => "".normal(),
+        };
+        println!("{} {}{}{} {}", time.bright_black(), level, site, origin, record.message);
+    }).await
+}
+
+/// List recent management actions, or export the whole audit log
+async fn audit_command(args: AuditArgs, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    if args.export {
+        return api_stream(&config, "/api/audit/export", |line| println!("{}", line)).await;
+    }
+    
+    let mut query = vec![format!("limit={}", args.lines)];
+    let params = [
+        ("actor", args.actor),
+        ("action", args.action),
+        ("target", args.target),
+        ("since", args.since.map(|t| t.to_string())),
+    ];
+    for (name, value) in params {
+        if let Some(value) = value {
+            query.push(format!("{}={}", name, url_encode(&value)));
+        }
+    }
+    let listing = api_request(&config, hyper::Method::GET, &format!("/api/audit?{}", query.join("&")), None).await?;
+    if print_structured(output, &listing)? {
+        return Ok(());
+    }
+    let entries = listing["entries"].as_array().cloned().unwrap_or_default();
+    if entries.is_empty() {
+        info("No matching management actions");
+        return Ok(());
+    }
+    println!("{}", format!("{:<19}  {:<12}  {:<6}  {}", "TIME", "ACTOR", "STATUS", "ACTION").bright_white());
+    for entry in &entries {
+        let time = entry["timestamp"].as_i64()
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let actor = match (&entry["actor"]["role"], entry["actor"]["tenant_id"].as_str()) {
+            (serde_json::Value::Null, _) => "anonymous".to_string(),
+            (_, Some(tenant_id)) => format!("tenant {}", &tenant_id[..tenant_id.len().min(8)]),
+            (role, None) => role.as_str().unwrap_or_default().to_string(),
+        };
+        let status = entry["status"].as_u64().unwrap_or_default();
+        let status = if status < 400 { status.to_string().green() } else { status.to_string().red() };
+        let method = entry["action"].as_str().unwrap_or_default().split(' ').next().unwrap_or_default();
+        println!("{:<19}  {:<12}  {:<6}  {} {}", time, actor, status, method, entry["target"].as_str().unwrap_or_default());
+        for (label, value) in [("before", &entry["before"]), ("after", &entry["after"])] {
+            if !value.is_null() {
+                println!("{:>45} {}", label.bright_black(), value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Recent healing attempts, one line each
+async fn events_command(site: Option<String>, lines: usize, config: String, output: OutputFormat) -> anyhow::Result<()> {
+    let mut path = format!("/api/supervisor/events?limit={}", lines);
+    if let Some(site) = &site {
+        path.push_str(&format!("&site={}", url_encode(site)));
+    }
+    let listing = api_request(&config, hyper::Method::GET, &path, None).await?;
+    if print_structured(output, &listing)? {
+        return Ok(());
+    }
+    let events = listing["events"].as_array().cloned().unwrap_or_default();
+    if events.is_empty() {
+        info("No healing events recorded");
+        return Ok(());
+    }
+    println!(
+        "{}",
+        format!("{:<19}  {:<20}  {:>7}  {:>7}  {:>7}  {:>9}  {}", "TIME", "SITE", "ATTEMPT", "REMOVED", "SPAWNED", "BACKOFF", "OUTCOME").bright_white(),
+    );
+    for event in &events {
+        let time = event["timestamp"].as_i64()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let outcome = event["outcome"].as_str().unwrap_or_default();
+        let outcome = match outcome {
+            "healed" => outcome.green(),
+            "partial" => outcome.yellow(),
+            _ => outcome.red(),
+        };
+        println!(
+            "{:<19}  {:<20}  {:>7}  {:>7}  {:>7}  {:>9}  {}",
+            time,
+            event["site_id"].as_str().unwrap_or_default(),
+            event["attempt"],
+            event["cages_removed"],
+            event["cages_spawned"],
+            format!("{}ms", event["backoff_ms"]),
+            outcome,
+        );
+        if let Some(error) = event["error"].as_str() {
+            println!("{:>21} {}", "error".bright_black(), error);
+        }
+    }
+    Ok(())
+}
+
+/// Percent-encode a query parameter value
+pub(super) fn url_encode(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Call the local management API, returning the JSON response body
+pub(super) async fn api_request(
+    config_path: &str,
+    method: hyper::Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> anyhow::Result<serde_json::Value> {
+    use http_body_util::BodyExt;
+    
+    let response = send_api_request(config_path, method, path, body).await?;
+    let status = response.status();
+    let bytes = response.into_body().collect().await?.to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap_or_default();
+    
+    if !status.is_success() {
+        let message = json["error"].as_str().map(str::to_string).unwrap_or_else(|| status.to_string());
+        error(&message);
+        anyhow::bail!("management API returned {}", status);
+    }
+    Ok(json)
+}
+
+/// Send a file to the local management API
+/// Returns the status with the JSON body so callers can report unsuccessful outcomes themselves;
+/// errors carrying only a message are reported and returned as failures.
+pub(super) async fn api_upload(
+    config_path: &str,
+    method: hyper::Method,
+    path: &str,
+    content_type: &str,
+    headers: &[(&str, String)],
+    contents: Vec<u8>,
+) -> anyhow::Result<(hyper::StatusCode, serde_json::Value)> {
+    use http_body_util::BodyExt;
+    
+    let response = send_api_bytes(config_path, method, path, content_type, headers, contents.into()).await?;
+    let status = response.status();
+    let bytes = response.into_body().collect().await?.to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap_or_default();
+    
+    if let Some(message) = json["error"].as_str() {
+        error(message);
+        anyhow::bail!("management API returned {}", status);
+    }
+    if !status.is_success() && json.is_null() {
+        anyhow::bail!("management API returned {}", status);
+    }
+    Ok((status, json))
+}
+
+/// Call the local management API and pass each line of the response to `on_line` as it arrives
+pub(super) async fn api_stream(
+    config_path: &str,
+    path: &str,
+    mut on_line: impl FnMut(&str),
+) -> anyhow::Result<()> {
+    use http_body_util::BodyExt;
+    
+    let response = send_api_request(config_path, hyper::Method::GET, path, None).await?;
+    let status = response.status();
+    let mut body = response.into_body();
+    if !status.is_success() {
+        let bytes = body.collect().await?.to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap_or_default();
+        let message = json["error"].as_str().map(str::to_string).unwrap_or_else(|| status.to_string());
+        error(&message);
+        anyhow::bail!("management API returned {}", status);
+    }
+    
+    let mut pending = Vec::new();
+    while let Some(frame) = body.frame().await {
+        let Ok(data) = frame?.into_data() else { continue };
+        pending.extend_from_slice(&data);
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            on_line(String::from_utf8_lossy(&line[..end]).as_ref());
+        }
+    }
+    Ok(())
+}
+
+/// Send one request to the local management API
+async fn send_api_request(
+    config_path: &str,
+    method: hyper::Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> anyhow::Result<hyper::Response<hyper::body::Incoming>> {
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    send_api_bytes(config_path, method, path, "application/json", &[], body.into()).await
+}
+
+/// Send a raw body with the given content type and extra headers to the local management API
+async fn send_api_bytes(
+    config_path: &str,
+    method: hyper::Method,
+    path: &str,
+    content_type: &str,
+    headers: &[(&str, String)],
+    body: hyper::body::Bytes,
+) -> anyhow::Result<hyper::Response<hyper::body::Incoming>> {
+    use http_body_util::Full;
+    
+    let config = crate::config::PearConfig::load(config_path)?;
+    let port = config.dashboard.port;
+    let token = std::env::var("PEAR_ADMIN_TOKEN").unwrap_or(config.dashboard.admin_token);
+    let tls = config.mtls.enabled
+        .then(|| crate::network::mtls::cli_connector(&config.mtls, std::path::Path::new(&config.storage.root)))
+        .transpose()
+        .context("Failed to load a client certificate for the management API")?;
+    let stream = crate::network::mtls::connect(&format!("127.0.0.1:{}", port), "localhost", tls.as_ref()).await
+        .map_err(|e| anyhow::anyhow!("Is Pear Server running? Cannot reach management API on port {}: {}", port, e))?;
+    
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+    
+    let mut request = hyper::Request::builder()
+        .method(method)
+        .uri(path)
+        .header(hyper::header::HOST, format!("127.0.0.1:{}", port))
+        .header(hyper::header::CONTENT_TYPE, content_type);
+    if !token.is_empty() {
+        request = request.header(hyper::header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let request = request.body(Full::new(body))?;
+    
+    Ok(sender.send_request(request).await?)
+}
+
+/// Change one value in the configuration file
+fn set_command(config_path: String, key: String, value: String) -> anyhow::Result<()> {
+    crate::config::edit::set_value(std::path::Path::new(&config_path), &key, &value)?;
+    success(&format!("Set {} = {} in {}", key.cyan(), value.yellow(), config_path.bright_white()));
+    warning("Configuration changes will take effect after server restart");
+    Ok(())
+}
+
+/// Check a configuration file, including problems that span sections
+fn validate_command(file: String, output: OutputFormat) -> anyhow::Result<()> {
+    if !std::path::Path::new(&file).exists() {
+        anyhow::bail!("{} not found", file);
+    }
+    let config = crate::config::PearConfig::load(&file)?;
+    let problems = config.cross_check();
+
+    let report = serde_json::json!({ "file": file, "valid": problems.is_empty(), "problems": problems });
+    if !print_structured(output, &report)? {
+        info(&format!("Validating {}", file.bright_white()));
+        for problem in &problems {
+            error(problem);
+        }
+    }
+    if !problems.is_empty() {
+        anyhow::bail!("{} has {} problem(s)", file, problems.len());
+    }
+    if output == OutputFormat::Table {
+        success("Configuration file is valid");
+    }
+    Ok(())
+}
@@ -0,0 +1,426 @@
+// Local Development Loop
+// `pear dev`: watches a project and reloads it on every save, in a local dev server or on the daemon
+
+use anyhow::{bail, Context};
+use colored::*;
+use notify::{RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::build::{BuildOptions, Toolchain};
+use super::commands::{api_request, api_upload, url_encode};
+use super::{error, info, success};
+use crate::deployment::manifest::{SiteManifest, MANIFEST_FILE};
+use crate::deployment::validate::{validate_module, MAX_MODULE_BYTES};
+use crate::runtime::polyglot::{DetectedLanguage, PolyglotAdapter};
+
+/// Directories that hold build output, dependencies, VCS data or dev server state rather than sources
+const IGNORED_DIRS: &[&str] = &[".git", ".pear", "target", "dist", "build", "node_modules", "__pycache__", ".venv"];
+
+/// Site the local development server deploys the project as
+const DEV_SITE: &str = "dev";
+
+/// What `pear dev` is asked to do
+#[derive(Debug, Clone)]
+pub struct DevOptions {
+    /// Project directory, or a built module to reload whenever it changes
+    pub path: PathBuf,
+
+    /// Site on the local daemon to redeploy to; without one, the project is served by an
+    /// in-process development server
+    pub site: Option<String>,
+
+    /// Port the development server listens on, on localhost
+    pub port: u16,
+
+    /// Language runtimes for projects that aren't compiled
+    pub runtime_dir: PathBuf,
+
+    /// Quiet time after a change before rebuilding, so a save of many files deploys once
+    pub debounce: Duration,
+    pub config: String,
+}
+
+/// How a project reaches its Cages
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Project {
+    /// Built to a module, which is redeployed on each change
+    Compiled(Toolchain),
+
+    /// A module built by other means, redeployed when the file changes
+    Module(PathBuf),
+
+    /// Run by a language runtime; changed files are pushed into the site's content
+    Content(DetectedLanguage),
+}
+
+/// Deploy once, then redeploy on changes until interrupted
+pub async fn run(options: DevOptions) -> anyhow::Result<()> {
+    let path = options.path.canonicalize()
+        .with_context(|| format!("{} not found", options.path.display()))?;
+    let (dir, project) = if path.is_file() {
+        if path.extension().map_or(true, |extension| extension != "wasm") {
+            bail!("{} is not a .wasm module", path.display());
+        }
+        (path.parent().map_or_else(|| path.clone(), Path::to_path_buf), Project::Module(path))
+    } else {
+        let project = match Toolchain::detect(&path) {
+            Some(toolchain) => Project::Compiled(toolchain),
+            None => match PolyglotAdapter::new(&options.runtime_dir).detect_language(&path)? {
+                DetectedLanguage::Unknown => bail!("Cannot tell what {} is; start from `pear new`", path.display()),
+                language => Project::Content(language),
+            },
+        };
+        (path, project)
+    };
+
+    let mut changes = Changes::watch(&dir, options.debounce)?;
+    match &options.site {
+        Some(site) => push_to_daemon(&options, site, &dir, &project, &mut changes).await?,
+        None => serve(&options, &dir, &project, &mut changes).await?,
+    }
+    println!();
+    info("Stopped watching");
+    Ok(())
+}
+
+/// Redeploy to `site` on the daemon through the management API
+async fn push_to_daemon(
+    options: &DevOptions,
+    site: &str,
+    dir: &Path,
+    project: &Project,
+    changes: &mut Changes,
+) -> anyhow::Result<()> {
+    info(&format!("Watching {} for '{}' (Ctrl+C to stop)", dir.display().to_string().bright_white(), site.cyan()));
+    match project {
+        Project::Compiled(_) | Project::Module(_) => report(redeploy(options, site, dir, project).await),
+        Project::Content(language) => {
+            if let Err(e) = deploy_runtime(options, site, language).await {
+                report(Err(e));
+            }
+            let files: Vec<PathBuf> = walkdir::WalkDir::new(dir).into_iter()
+                .filter_entry(|entry| entry.path().strip_prefix(dir).map_or(true, |relative| !is_ignored(relative)))
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.into_path())
+                .collect();
+            report(push(options, site, dir, files).await);
+        }
+    }
+
+    while let Some(changed) = changes.next().await? {
+        if !touches(project, &changed) {
+            continue;
+        }
+        match project {
+            Project::Compiled(_) | Project::Module(_) => report(redeploy(options, site, dir, project).await),
+            Project::Content(_) => report(push(options, site, dir, changed).await),
+        }
+    }
+    Ok(())
+}
+
+/// Serve the project from a single-site node on localhost, with relaxed Cage limits, no bans,
+/// AI checks that only log, and readable logs, reloading its Cages on each change
+async fn serve(options: &DevOptions, dir: &Path, project: &Project, changes: &mut Changes) -> anyhow::Result<()> {
+    if let Project::Content(language) = project {
+        bail!(
+            "The development server runs compiled projects and .wasm modules; deploy this {:?} project \
+             to a running `pear start` with `pear dev --site <site>`",
+            language
+        );
+    }
+    let logs = crate::observability::init_with(crate::observability::LogFormat::Text)?;
+    let config = dev_config(options, dir)?;
+
+    info(&format!("Watching {} (Ctrl+C to stop)", dir.display().to_string().bright_white()));
+    let mut node = None;
+    // Manifest the site was last deployed with; the module alone is hot-swapped while it is unchanged
+    let mut deployed = None;
+    'serve: loop {
+        let loaded = load(dir, project).await
+            .and_then(|(module, loaded)| Ok((module, loaded, SiteManifest::load(dir)?.unwrap_or_default())));
+        match loaded {
+            Ok((module, loaded, manifest)) => match &node {
+                None => {
+                    let started = crate::PearNode::builder()
+                        .with_config(config.clone())
+                        .with_logs(logs.clone())
+                        .development()
+                        .start()
+                        .await?;
+                    started.router().set_default_site(DEV_SITE.to_string());
+                    match started.deploy_manifest(DEV_SITE, module, &manifest, dir).await {
+                        Ok(_) => {
+                            report(Ok(loaded));
+                            deployed = Some(manifest);
+                        }
+                        Err(e) => report(Err(e)),
+                    }
+                    for addr in started.local_addrs() {
+                        info(&format!("Serving on {}", format!("http://{}", addr).bright_white()));
+                    }
+                    node = Some(started);
+                }
+                Some(node) if deployed.as_ref() == Some(&manifest) => report(reload(node, module, &manifest, dir, loaded).await),
+                Some(node) => match node.deploy_manifest(DEV_SITE, module, &manifest, dir).await {
+                    Ok(_) => {
+                        report(Ok(format!("{}; {} applied and Cages restarted", loaded, MANIFEST_FILE)));
+                        deployed = Some(manifest);
+                    }
+                    Err(e) => report(Err(e)),
+                },
+            },
+            Err(e) => report(Err(e)),
+        }
+
+        loop {
+            match changes.next().await? {
+                None => break 'serve,
+                Some(changed) if touches(project, &changed) => break,
+                Some(_) => {}
+            }
+        }
+    }
+    if let Some(node) = node {
+        node.shutdown(Duration::from_secs(5)).await;
+    }
+    Ok(())
+}
+
+/// Hot-swap the rebuilt module into the site's Cages, and restart its routes' pools in case their
+/// modules changed too
+async fn reload(node: &crate::PearNode, module: Vec<u8>, manifest: &SiteManifest, dir: &Path, loaded: String) -> anyhow::Result<String> {
+    let swap = node.hot_swap(DEV_SITE, module).await?;
+    if !swap.swapped {
+        bail!("{}; the previous module keeps serving", swap.failures.join("; "));
+    }
+    if !manifest.routes.is_empty() {
+        node.deploy_routes(DEV_SITE, manifest, dir).await?;
+    }
+    Ok(format!("{}; Cages reloaded", loaded))
+}
+
+/// `pear.toml` if there is one, made to serve one site on localhost and keep its state in `.pear`
+fn dev_config(options: &DevOptions, dir: &Path) -> anyhow::Result<crate::config::PearConfig> {
+    let mut config = if Path::new(&options.config).exists() {
+        crate::config::PearConfig::load(&options.config)?
+    } else {
+        crate::config::PearConfig::default()
+    };
+    config.server.listeners = vec![crate::network::ListenerConfig {
+        name: None,
+        address: "127.0.0.1".to_string(),
+        port: options.port,
+        protocol: crate::network::ListenerProtocol::Http2,
+        tls: false,
+        ipv6_only: false,
+        proxy_protocol: false,
+        redirect_https: false,
+        https_port: 443,
+    }];
+    config.dashboard.enabled = false;
+    config.cages.default_replicas = 1;
+
+    let state = dir.join(".pear");
+    std::fs::create_dir_all(&state).with_context(|| format!("Failed to create {}", state.display()))?;
+    config.storage.root = state.join("storage").to_string_lossy().into_owned();
+    config.server.secret_key_file = state.join("secret.key").to_string_lossy().into_owned();
+    config.ai.model_path = String::new();
+    Ok(config)
+}
+
+/// Whether the changes call for a reload: any change does, except next to a watched module
+fn touches(project: &Project, changed: &BTreeSet<PathBuf>) -> bool {
+    match project {
+        // Route modules are usually built next to it
+        Project::Module(module) => changed.iter().any(|path| {
+            path == module
+                || path.file_name().is_some_and(|name| name == MANIFEST_FILE)
+                || path.extension().is_some_and(|extension| extension == "wasm")
+        }),
+        _ => true,
+    }
+}
+
+/// File events below a project, batched until they stop for the debounce interval
+struct Changes {
+    dir: PathBuf,
+    debounce: Duration,
+    events: tokio::sync::mpsc::UnboundedReceiver<Vec<PathBuf>>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl Changes {
+    fn watch(dir: &Path, debounce: Duration) -> anyhow::Result<Self> {
+        let (sender, events) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                let _ = sender.send(event.paths);
+            }
+        })?;
+        watcher.watch(dir, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", dir.display()))?;
+        Ok(Self { dir: dir.to_path_buf(), debounce, events, _watcher: watcher })
+    }
+
+    /// The next batch of changed paths that aren't ignored, or `None` once interrupted
+    async fn next(&mut self) -> anyhow::Result<Option<BTreeSet<PathBuf>>> {
+        loop {
+            let mut changed = BTreeSet::new();
+            tokio::select! {
+                paths = self.events.recv() => match paths {
+                    Some(paths) => changed.extend(paths),
+                    None => bail!("File watcher stopped"),
+                },
+                _ = tokio::signal::ctrl_c() => return Ok(None),
+            }
+            while let Ok(Some(paths)) = tokio::time::timeout(self.debounce, self.events.recv()).await {
+                changed.extend(paths);
+            }
+            changed.retain(|path| path.strip_prefix(&self.dir).is_ok_and(|relative| !is_ignored(relative)));
+            if !changed.is_empty() {
+                return Ok(Some(changed));
+            }
+        }
+    }
+}
+
+/// Print the outcome of one round and keep watching either way
+fn report(result: anyhow::Result<String>) {
+    let time = chrono::Local::now().format("%H:%M:%S").to_string();
+    match result {
+        Ok(done) => success(&format!("{} {}", time.bright_black(), done)),
+        Err(e) => error(&format!("{} {:#}", time.bright_black(), e)),
+    }
+}
+
+/// The project's module: rebuilt without wasm-opt, which is slow and not worth it for a dev loop,
+/// or read from disk, and checked either way
+async fn load(dir: &Path, project: &Project) -> anyhow::Result<(Vec<u8>, String)> {
+    match project {
+        Project::Module(path) => {
+            let module = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let report = validate_module(&module, MAX_MODULE_BYTES);
+            if !report.is_valid() {
+                bail!("{} can't be deployed: {}", path.display(), report.problems.join("; "));
+            }
+            let loaded = format!("Loaded {} ({} bytes)", path.display(), module.len());
+            Ok((module, loaded))
+        }
+        _ => {
+            let build = BuildOptions { dir: dir.to_path_buf(), toolchain: None, out: None, debug: false, optimize: false };
+            let built = tokio::task::spawn_blocking(move || super::build::run(&build)).await??;
+            built.check()?;
+            let module = std::fs::read(&built.module)?;
+            let loaded = format!("Rebuilt {} ({} bytes)", built.module.display(), module.len());
+            Ok((module, loaded))
+        }
+    }
+}
+
+/// Load the module and hot-swap it on the daemon
+async fn redeploy(options: &DevOptions, site: &str, dir: &Path, project: &Project) -> anyhow::Result<String> {
+    let (module, loaded) = load(dir, project).await?;
+    deploy_module(options, site, module).await?;
+    Ok(format!("{} and deployed it", loaded))
+}
+
+/// Stage the runtime for the project's language as the site's module
+async fn deploy_runtime(options: &DevOptions, site: &str, language: &DetectedLanguage) -> anyhow::Result<()> {
+    let runtime = PolyglotAdapter::new(&options.runtime_dir).get_runtime_wasm(language)?;
+    let module = std::fs::read(&runtime).with_context(|| format!("Failed to read {}", runtime.display()))?;
+    deploy_module(options, site, module).await?;
+    info(&format!("Deployed the {:?} runtime from {}", language, runtime.display()));
+    Ok(())
+}
+
+/// Hot-swap the site's Cages onto the module; one that is rejected leaves the previous one serving
+async fn deploy_module(options: &DevOptions, site: &str, module: Vec<u8>) -> anyhow::Result<()> {
+    let path = format!("/api/sites/{}/swap", url_encode(site));
+    let (status, swap) = api_upload(&options.config, hyper::Method::POST, &path, "application/wasm", &[], module).await?;
+    if status == hyper::StatusCode::UNPROCESSABLE_ENTITY {
+        let failures: Vec<&str> = swap["failures"].as_array().into_iter().flatten().filter_map(|f| f.as_str()).collect();
+        bail!("{}; the previous module keeps serving", failures.join("; "));
+    }
+    Ok(())
+}
+
+/// Upload the files that exist and remove the ones that don't from the site's content
+async fn push(
+    options: &DevOptions,
+    site: &str,
+    dir: &Path,
+    paths: impl IntoIterator<Item = PathBuf>,
+) -> anyhow::Result<String> {
+    let (mut uploaded, mut removed) = (0, 0);
+    for path in paths {
+        let Some(relative) = content_path(dir, &path) else { continue };
+        let api_path = format!("/api/sites/{}/files/{}", url_encode(site), relative);
+        if path.is_file() {
+            let contents = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            api_upload(&options.config, hyper::Method::PUT, &api_path, "application/octet-stream", &[], contents).await?;
+            uploaded += 1;
+        } else if !path.exists() {
+            let response = api_request(&options.config, hyper::Method::DELETE, &api_path, None).await?;
+            if response["removed"] == true {
+                removed += 1;
+            }
+        }
+    }
+    Ok(format!("Pushed {} files, removed {}", uploaded, removed))
+}
+
+/// The path below the project as URL-encoded segments
+fn content_path(dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(dir).ok()?;
+    let segments: Vec<String> = relative.iter().map(|part| part.to_str().map(url_encode)).collect::<Option<_>>()?;
+    (!segments.is_empty()).then(|| segments.join("/"))
+}
+
+/// Build output, dependencies and editor swap files don't trigger a deploy
+fn is_ignored(relative: &Path) -> bool {
+    if relative.iter().any(|part| part.to_str().map_or(true, |part| IGNORED_DIRS.contains(&part))) {
+        return true;
+    }
+    let name = relative.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    name.ends_with('~') || name.ends_with(".swp") || name.ends_with(".swx") || name.starts_with(".#")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignored_paths() {
+        assert!(!is_ignored(Path::new("src/lib.rs")));
+        assert!(!is_ignored(Path::new("assets/site.css")));
+        assert!(is_ignored(Path::new("target/release/blog.wasm")));
+        assert!(is_ignored(Path::new("web/node_modules/x/index.js")));
+        assert!(is_ignored(Path::new(".git/index")));
+        assert!(is_ignored(Path::new(".pear/storage/modules/ab")));
+        assert!(is_ignored(Path::new("src/.lib.rs.swp")));
+        assert!(is_ignored(Path::new("index.php~")));
+    }
+
+    #[test]
+    fn test_module_changes() {
+        let module = PathBuf::from("/work/blog/dist/blog.wasm");
+        let changed = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<BTreeSet<_>>();
+        assert!(touches(&Project::Module(module.clone()), &changed(&["/work/blog/dist/blog.wasm"])));
+        assert!(!touches(&Project::Module(module.clone()), &changed(&["/work/blog/dist/blog.d.ts"])));
+        assert!(touches(&Project::Module(module), &changed(&["/work/blog/dist/pear.site.toml"])));
+        assert!(touches(&Project::Compiled(Toolchain::Rust), &changed(&["/work/blog/src/lib.rs"])));
+    }
+
+    #[test]
+    fn test_content_path() {
+        let dir = Path::new("/work/blog");
+        assert_eq!(content_path(dir, Path::new("/work/blog/lib/greeting.php")).as_deref(), Some("lib/greeting.php"));
+        assert_eq!(content_path(dir, Path::new("/work/blog/My Page.html")).as_deref(), Some("My%20Page.html"));
+        assert_eq!(content_path(dir, dir), None);
+        assert_eq!(content_path(dir, Path::new("/work/other/index.html")), None);
+    }
+}
@@ -0,0 +1,413 @@
+// Installation Checks
+// `pear doctor`: finds host and configuration problems before they surface as failed starts
+
+use colored::*;
+use serde::Serialize;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+
+use super::{print_structured, OutputFormat};
+use crate::config::PearConfig;
+use crate::network::ListenerProtocol;
+use crate::runtime::affinity::{AffinityPlan, CpuTopology};
+use crate::runtime::limits::{MIN_FD_LIMIT, TARGET_FD_LIMIT};
+use crate::runtime::RuntimeConfig;
+use crate::runtime::polyglot::DetectedLanguage;
+
+/// Certificates expiring sooner than this are reported
+const CERT_WARN_DAYS: i64 = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Outcome of one check
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+
+    /// What to do about a warning or failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), status: Status::Ok, detail: detail.into(), fix: None }
+    }
+
+    fn warn(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { name: name.into(), status: Status::Warn, detail: detail.into(), fix: Some(fix.into()) }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { name: name.into(), status: Status::Fail, detail: detail.into(), fix: Some(fix.into()) }
+    }
+}
+
+/// Run every check, print the report, and fail if anything must be fixed before starting
+pub fn run(config_path: &str, runtime_dir: &str, output: OutputFormat) -> anyhow::Result<()> {
+    let (config, config_check) = load_config(config_path);
+    let mut checks = vec![config_check];
+    checks.push(check_fd_limits(crate::runtime::limits::file_descriptor_limits()));
+    checks.extend(check_ports(&config));
+    checks.extend(check_runtimes(Path::new(runtime_dir)));
+    checks.extend(check_directories(&config));
+    checks.extend(check_tls(&config));
+    checks.extend(check_kernel());
+    checks.push(check_cpu_affinity(&config.runtime, &CpuTopology::detect()));
+
+    if !print_structured(output, &checks)? {
+        for check in &checks {
+            let mark = match check.status {
+                Status::Ok => "✓".green().bold(),
+                Status::Warn => "⚠".yellow().bold(),
+                Status::Fail => "✗".red().bold(),
+            };
+            println!("{} {} {}", mark, format!("{}:", check.name).bright_white(), check.detail);
+            if let Some(fix) = &check.fix {
+                println!("    {} {}", "→".bright_black(), fix);
+            }
+        }
+        println!();
+    }
+
+    let failed = checks.iter().filter(|check| check.status == Status::Fail).count();
+    let warned = checks.iter().filter(|check| check.status == Status::Warn).count();
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed, {} warning(s)", failed, warned);
+    }
+    if output == OutputFormat::Table {
+        super::success(&format!("No problems that prevent starting ({} warning(s))", warned));
+    }
+    Ok(())
+}
+
+/// The configuration the remaining checks run against; defaults when it cannot be loaded
+fn load_config(path: &str) -> (PearConfig, Check) {
+    if !Path::new(path).exists() {
+        return (
+            PearConfig::default(),
+            Check::warn("config", format!("{} not found, checking the defaults", path), "Run `pear init` to write one"),
+        );
+    }
+    match PearConfig::load(path) {
+        Ok(config) => (config, Check::ok("config", format!("{} is valid", path))),
+        Err(e) => (
+            PearConfig::default(),
+            Check::fail("config", format!("{}: {:#}", path, e), format!("Fix {} (see pear.toml.example); the other checks used the defaults", path)),
+        ),
+    }
+}
+
+/// The daemon raises the soft limit to the hard limit at startup, so the hard limit is what counts
+fn check_fd_limits(limits: Option<(u64, u64)>) -> Check {
+    let Some((soft, hard)) = limits else {
+        return Check::warn("file descriptors", "limit could not be read", "Check `ulimit -n` manually");
+    };
+    let detail = format!("soft {}, hard {}", soft, hard);
+    let fix = format!(
+        "Set LimitNOFILE={} in the systemd unit, or add `* hard nofile {}` to /etc/security/limits.conf",
+        TARGET_FD_LIMIT, TARGET_FD_LIMIT
+    );
+    if hard < MIN_FD_LIMIT {
+        Check::fail("file descriptors", format!("{}; below the minimum of {}", detail, MIN_FD_LIMIT), fix)
+    } else if hard < TARGET_FD_LIMIT {
+        Check::warn("file descriptors", format!("{}; connection capacity is capped at the hard limit", detail), fix)
+    } else {
+        Check::ok("file descriptors", detail)
+    }
+}
+
+/// Bind each listener and the dashboard port briefly to see whether they are free
+fn check_ports(config: &PearConfig) -> Vec<Check> {
+    let running = running_daemon(&config.server.pid_file);
+    let mut sockets: Vec<(String, String, u16, bool)> = config.server.effective_listeners()
+        .iter()
+        .map(|listener| {
+            let udp = listener.protocol == ListenerProtocol::Http3;
+            let label = listener.name.clone().unwrap_or_else(|| if udp { "http3".to_string() } else { "http2".to_string() });
+            (label, listener.address.clone(), listener.port, udp)
+        })
+        .collect();
+    if config.dashboard.enabled {
+        sockets.push(("dashboard".to_string(), "0.0.0.0".to_string(), config.dashboard.port, false));
+    }
+
+    sockets.into_iter().map(|(label, address, port, udp)| {
+        let name = format!("port {}/{}", port, if udp { "udp" } else { "tcp" });
+        let addr: SocketAddr = match format!("{}:{}", address, port).parse()
+            .or_else(|_| format!("[{}]:{}", address, port).parse())
+        {
+            Ok(addr) => addr,
+            Err(_) => return Check::fail(name, format!("{} address '{}' is not an IP address", label, address), "Use an IPv4 or IPv6 address"),
+        };
+        let bound = if udp { UdpSocket::bind(addr).map(drop) } else { TcpListener::bind(addr).map(drop) };
+        match (bound, running) {
+            (Ok(()), _) => Check::ok(name, format!("{} {} is free", label, addr)),
+            (Err(_), Some(pid)) => Check::ok(name, format!("{} {} is held by the running daemon (pid {})", label, addr, pid)),
+            (Err(e), None) if e.kind() == std::io::ErrorKind::AddrInUse => Check::fail(
+                name,
+                format!("{} {} is already in use", label, addr),
+                format!("Stop the other process (`ss -ltnup 'sport = :{}'` shows it) or choose another port", port),
+            ),
+            (Err(e), None) if e.kind() == std::io::ErrorKind::PermissionDenied => Check::fail(
+                name,
+                format!("{} {} needs privileges to bind", label, addr),
+                "Start as root with server.user set, or grant CAP_NET_BIND_SERVICE to the binary",
+            ),
+            (Err(e), None) => Check::fail(name, format!("{} {}: {}", label, addr, e), "Check the address is assigned to this host"),
+        }
+    }).collect()
+}
+
+/// Process recorded in the PID file, if it is still alive
+fn running_daemon(pid_file: &str) -> Option<u32> {
+    let pid = crate::upgrade::read_pid_file(Path::new(pid_file)).ok()?;
+    #[cfg(unix)]
+    {
+        (unsafe { libc::kill(pid as libc::pid_t, 0) } == 0).then_some(pid)
+    }
+    #[cfg(not(unix))]
+    {
+        Some(pid)
+    }
+}
+
+fn check_runtimes(runtime_dir: &Path) -> Vec<Check> {
+    DetectedLanguage::WITH_RUNTIMES.iter().filter_map(|language| {
+        let file = language.runtime_wasm()?;
+        let path = runtime_dir.join(file);
+        let name = format!("runtime {:?}", language);
+        Some(if path.is_file() {
+            Check::ok(name, format!("{} found", path.display()))
+        } else {
+            Check::warn(
+                name,
+                format!("{} is missing; sites in this language cannot be deployed", path.display()),
+                format!("Download or build {} into {}", file, runtime_dir.display()),
+            )
+        })
+    }).collect()
+}
+
+/// Every directory the daemon writes state into must exist and be writable, or be creatable
+fn check_directories(config: &PearConfig) -> Vec<Check> {
+    let state_files = [
+        &config.server.pid_file,
+        &config.server.secret_key_file,
+        &config.ai.model_path,
+        &config.security.events.log_path,
+        &config.bandwidth.state_path,
+        &config.metrics_history.state_path,
+        &config.mail.state_path,
+        &config.scheduler.state_path,
+        &config.queue.state_path,
+    ];
+    let mut dirs = vec![PathBuf::from(&config.storage.root), config.storage.tenants_root()];
+    for file in state_files.into_iter().filter(|file| !file.is_empty()) {
+        let parent = Path::new(file).parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if !dirs.iter().any(|dir| dir == parent) {
+            dirs.push(parent.to_path_buf());
+        }
+    }
+    dirs.iter().map(|dir| check_directory(dir)).collect()
+}
+
+fn check_directory(dir: &Path) -> Check {
+    let name = format!("directory {}", dir.display());
+    let fix = format!("Create it and give the server user write access: `mkdir -p {0} && chown pear: {0}`", dir.display());
+    if dir.is_dir() {
+        return if writable(dir) {
+            Check::ok(name, "writable")
+        } else {
+            Check::fail(name, "not writable by this user", fix)
+        };
+    }
+    if dir.exists() {
+        return Check::fail(name, "exists but is not a directory", format!("Move {} out of the way", dir.display()));
+    }
+    match dir.ancestors().skip(1).find(|ancestor| ancestor.is_dir()) {
+        Some(ancestor) if writable(ancestor) => Check::ok(name, "will be created on first use"),
+        _ => Check::fail(name, "missing and cannot be created by this user", fix),
+    }
+}
+
+/// Whether a file can be created in `dir`
+fn writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".pear-doctor-{}", std::process::id()));
+    let created = std::fs::File::create(&probe).is_ok();
+    let _ = std::fs::remove_file(&probe);
+    created
+}
+
+fn check_tls(config: &PearConfig) -> Vec<Check> {
+    let ssl = &config.ssl;
+    let (Some(cert_path), Some(key_path)) = (&ssl.cert_path, &ssl.key_path) else {
+        let needs_tls = config.server.effective_listeners().iter().any(|listener| listener.tls);
+        return if needs_tls && !ssl.auto_cert {
+            vec![Check::warn(
+                "tls certificate",
+                "TLS listeners are configured without a certificate; a self-signed one will be used",
+                "Set ssl.cert_path and ssl.key_path, or enable ssl.auto_cert with ssl.domains",
+            )]
+        } else {
+            Vec::new()
+        };
+    };
+
+    let certs = match crate::network::tls::load_pem(cert_path, key_path) {
+        Ok((certs, _key)) => certs,
+        Err(e) => return vec![Check::fail("tls certificate", format!("{:#}", e), "Point ssl.cert_path and ssl.key_path at readable PEM files")],
+    };
+    let validity = match crate::network::tls::validity(&certs[0]) {
+        Ok(validity) => validity,
+        Err(e) => return vec![Check::fail("tls certificate", format!("{}: {}", cert_path, e), "Replace it with a valid X.509 certificate")],
+    };
+    vec![check_validity(&validity.subject, validity.not_before, validity.not_after, chrono::Utc::now().timestamp())]
+}
+
+fn check_validity(subject: &str, not_before: i64, not_after: i64, now: i64) -> Check {
+    let days_left = (not_after - now).div_euclid(86_400);
+    let renew = "Renew the certificate, or enable ssl.auto_cert to have it renewed automatically";
+    if now < not_before {
+        Check::fail("tls certificate", format!("{} is not valid yet", subject), "Check the system clock, or reissue the certificate")
+    } else if now >= not_after {
+        Check::fail("tls certificate", format!("{} expired {} day(s) ago", subject, -days_left), renew)
+    } else if days_left < CERT_WARN_DAYS {
+        Check::warn("tls certificate", format!("{} expires in {} day(s)", subject, days_left), renew)
+    } else {
+        Check::ok("tls certificate", format!("{} valid for {} more days", subject, days_left))
+    }
+}
+
+/// sysctls that cap connection counts or Cage memory reservations
+fn check_kernel() -> Vec<Check> {
+    const SETTINGS: [&str; 6] = [
+        "net.core.somaxconn",
+        "net.ipv4.ip_local_port_range",
+        "fs.file-max",
+        "fs.nr_open",
+        "vm.max_map_count",
+        "vm.overcommit_memory",
+    ];
+    if !Path::new("/proc/sys").is_dir() {
+        return Vec::new();
+    }
+    SETTINGS.iter().filter_map(|setting| {
+        let path = format!("/proc/sys/{}", setting.replace('.', "/"));
+        let value = std::fs::read_to_string(path).ok()?;
+        Some(check_sysctl(setting, value.trim()))
+    }).collect()
+}
+
+fn check_sysctl(setting: &str, value: &str) -> Check {
+    let numbers: Vec<u64> = value.split_whitespace().filter_map(|n| n.parse().ok()).collect();
+    let first = numbers.first().copied().unwrap_or(0);
+    let name = format!("sysctl {}", setting);
+    let raise = |to: u64| format!("sysctl -w {}={} (persist it in /etc/sysctl.d/99-pear.conf)", setting, to);
+    match setting {
+        "net.core.somaxconn" if first < 4096 => {
+            Check::warn(name, format!("{}; bursts of new connections will be dropped", value), raise(65_535))
+        }
+        "net.ipv4.ip_local_port_range" if numbers.len() == 2 && numbers[1].saturating_sub(numbers[0]) < 28_000 => Check::warn(
+            name,
+            format!("{}; few ports for upstream and outbound connections", value.replace('\t', " ")),
+            "sysctl -w net.ipv4.ip_local_port_range=\"1024 65535\" (persist it in /etc/sysctl.d/99-pear.conf)",
+        ),
+        "fs.file-max" | "fs.nr_open" if first < TARGET_FD_LIMIT => {
+            Check::warn(name, format!("{}; the per-process file limit cannot go higher", value), raise(TARGET_FD_LIMIT))
+        }
+        "vm.max_map_count" if first < 262_144 => {
+            Check::warn(name, format!("{}; each Cage maps its own linear memory", value), raise(262_144))
+        }
+        "vm.overcommit_memory" if first == 2 => Check::warn(
+            name,
+            "2 (strict); Wasm memory reservations may be refused",
+            "sysctl -w vm.overcommit_memory=0 (persist it in /etc/sysctl.d/99-pear.conf)",
+        ),
+        _ => Check::ok(name, value.replace('\t', " ")),
+    }
+}
+
+/// The host's CPU layout and where [runtime] would place threads on it
+fn check_cpu_affinity(runtime: &RuntimeConfig, topology: &CpuTopology) -> Check {
+    let name = "cpu topology";
+    if !runtime.cpu_affinity {
+        return if topology.nodes.len() > 1 {
+            Check::warn(
+                name,
+                format!("{}; threads are not pinned and may use memory on another node", topology.summary()),
+                "Set runtime.cpu_affinity = true, optionally with runtime.numa_nodes to keep the daemon on one node",
+            )
+        } else {
+            Check::ok(name, format!("{}; threads are not pinned", topology.summary()))
+        };
+    }
+    match AffinityPlan::new(topology, &runtime.numa_nodes, runtime.wasm_cores) {
+        Ok(plan) => Check::ok(name, format!("{}; {}", topology.summary(), plan.summary())),
+        Err(e) => Check::fail(name, format!("{}; {:#}", topology.summary(), e), "Adjust runtime.numa_nodes and runtime.wasm_cores to fit this host"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks_report_fixes() {
+        assert_eq!(check_fd_limits(Some((1024, 4096))).status, Status::Fail);
+        assert_eq!(check_fd_limits(Some((1024, MIN_FD_LIMIT))).status, Status::Warn);
+        assert_eq!(check_fd_limits(Some((1024, TARGET_FD_LIMIT))).status, Status::Ok);
+
+        let now = 1_700_000_000;
+        assert_eq!(check_validity("CN=a", now - 10, now + 90 * 86_400, now).status, Status::Ok);
+        let soon = check_validity("CN=a", now - 10, now + 3 * 86_400, now);
+        assert_eq!(soon.status, Status::Warn);
+        assert!(soon.detail.contains("3 day(s)"));
+        assert_eq!(check_validity("CN=a", now - 100, now - 10, now).status, Status::Fail);
+        assert_eq!(check_validity("CN=a", now + 10, now + 100, now).status, Status::Fail);
+
+        assert_eq!(check_sysctl("net.core.somaxconn", "128").status, Status::Warn);
+        assert_eq!(check_sysctl("net.core.somaxconn", "4096").status, Status::Ok);
+        assert_eq!(check_sysctl("net.ipv4.ip_local_port_range", "32768\t60999").status, Status::Ok);
+        assert_eq!(check_sysctl("net.ipv4.ip_local_port_range", "50000\t60999").status, Status::Warn);
+        let overcommit = check_sysctl("vm.overcommit_memory", "2");
+        assert!(overcommit.fix.unwrap().contains("vm.overcommit_memory=0"));
+    }
+
+    #[test]
+    fn test_cpu_affinity_check() {
+        let topology = CpuTopology {
+            nodes: std::collections::BTreeMap::from([(0, vec![0, 1]), (1, vec![2, 3])]),
+        };
+        let mut runtime = RuntimeConfig::default();
+        assert_eq!(check_cpu_affinity(&runtime, &topology).status, Status::Warn);
+
+        runtime.cpu_affinity = true;
+        runtime.wasm_cores = 2;
+        let planned = check_cpu_affinity(&runtime, &topology);
+        assert_eq!(planned.status, Status::Ok);
+        assert!(planned.detail.contains("workers on 0,2, Wasm on 1,3"));
+
+        runtime.numa_nodes = vec![0];
+        assert_eq!(check_cpu_affinity(&runtime, &topology).status, Status::Fail);
+    }
+
+    #[test]
+    fn test_directory_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(check_directory(dir.path()).status, Status::Ok);
+        assert_eq!(check_directory(&dir.path().join("a/b")).status, Status::Ok);
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(check_directory(&file).status, Status::Fail);
+    }
+}
@@ -0,0 +1,229 @@
+// Configuration Generation
+// `pear init`: asks a few questions and writes a commented pear.toml
+
+use anyhow::{bail, Context};
+use colored::*;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+/// Answers the generated file is built from
+#[derive(Debug, Clone)]
+pub struct InitAnswers {
+    pub bind_addr: String,
+    pub http2_port: u16,
+    pub http3_port: u16,
+    pub domains: Vec<String>,
+
+    /// Let's Encrypt account email; certificates are requested when set
+    pub email: Option<String>,
+    pub user: Option<String>,
+    pub replicas: usize,
+    pub memory_limit_mb: usize,
+    pub dashboard_port: u16,
+    pub admin_token: String,
+}
+
+impl Default for InitAnswers {
+    fn default() -> Self {
+        Self {
+            bind_addr: "0.0.0.0".to_string(),
+            http2_port: 8080,
+            http3_port: 8443,
+            domains: Vec::new(),
+            email: None,
+            user: None,
+            replicas: 3,
+            memory_limit_mb: 128,
+            dashboard_port: 9000,
+            admin_token: String::new(),
+        }
+    }
+}
+
+/// Ask for each value (or take the defaults) and write the file
+pub fn run(path: &str, force: bool, defaults: bool) -> anyhow::Result<()> {
+    if Path::new(path).exists() && !force {
+        bail!("{} already exists (use --force to overwrite it)", path);
+    }
+
+    let mut answers = InitAnswers { admin_token: random_token()?, ..Default::default() };
+    if !defaults && std::io::stdin().is_terminal() {
+        answers = ask(answers)?;
+    }
+
+    write_private(Path::new(path), &render(&answers))?;
+    super::success(&format!("Wrote {}", path.bright_white()));
+    if !answers.admin_token.is_empty() {
+        super::info("The admin token for `pear tenant` and `pear site` is in [dashboard]; keep the file private");
+    }
+    super::info(&format!("Run {} to check this host before starting", "pear doctor".cyan()));
+    Ok(())
+}
+
+fn ask(defaults: InitAnswers) -> anyhow::Result<InitAnswers> {
+    let mut input = std::io::stdin().lock();
+    let mut answer = |question: &str, default: &str| prompt(&mut input, question, default);
+
+    let bind_addr = answer("Address to listen on", &defaults.bind_addr)?;
+    let http2_port = answer("HTTP/2 (TCP) port", &defaults.http2_port.to_string())?.parse().context("Invalid port")?;
+    let http3_port = answer("HTTP/3 (QUIC) port", &defaults.http3_port.to_string())?.parse().context("Invalid port")?;
+    let domains: Vec<String> = answer("Domains served, comma separated (empty for none)", "")?
+        .split(',')
+        .map(|domain| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect();
+    let email = if domains.is_empty() {
+        None
+    } else {
+        Some(answer("Email for Let's Encrypt certificates (empty to skip)", "")?).filter(|email| !email.is_empty())
+    };
+    let user = Some(answer("Unprivileged user to run as after binding (empty to stay as the starting user)", "")?)
+        .filter(|user| !user.is_empty());
+    let replicas = answer("Cages per site", &defaults.replicas.to_string())?.parse().context("Invalid number of Cages")?;
+    let memory_limit_mb = answer("Memory per Cage in MB", &defaults.memory_limit_mb.to_string())?.parse().context("Invalid memory limit")?;
+    let dashboard_port = answer("Dashboard port", &defaults.dashboard_port.to_string())?.parse().context("Invalid port")?;
+    let manage = answer("Enable tenant and site management over the API? [Y/n]", "y")?;
+    let admin_token = if manage.eq_ignore_ascii_case("n") || manage.eq_ignore_ascii_case("no") {
+        String::new()
+    } else {
+        defaults.admin_token
+    };
+
+    Ok(InitAnswers { bind_addr, http2_port, http3_port, domains, email, user, replicas, memory_limit_mb, dashboard_port, admin_token })
+}
+
+/// Print `question [default]: ` and read one line, empty meaning the default
+fn prompt(input: &mut impl BufRead, question: &str, default: &str) -> anyhow::Result<String> {
+    if default.is_empty() {
+        print!("{} {}: ", "?".cyan().bold(), question);
+    } else {
+        print!("{} {} [{}]: ", "?".cyan().bold(), question, default.bright_white());
+    }
+    std::io::stdout().flush()?;
+
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let line = line.trim();
+    Ok(if line.is_empty() { default.to_string() } else { line.to_string() })
+}
+
+/// Hex-encoded 32 random bytes
+fn random_token() -> anyhow::Result<String> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("Failed to generate admin token"))?;
+    Ok(hex::encode(bytes))
+}
+
+/// The file may hold the admin token, so only the owner can read it
+fn write_private(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).with_context(|| format!("Failed to write {}", path.display()))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+/// A commented pear.toml; anything not asked about keeps its default and is left out
+pub fn render(answers: &InitAnswers) -> String {
+    let quote = |value: &str| toml::Value::String(value.to_string()).to_string();
+    let mut out = String::new();
+
+    out.push_str("# Pear Server Configuration\n");
+    out.push_str("# Generated by `pear init`; pear.toml.example documents every setting\n\n");
+
+    out.push_str("[server]\n");
+    out.push_str("# Address and ports for HTTP/2 over TCP and HTTP/3 over QUIC\n");
+    out.push_str(&format!("bind_addr = {}\n", quote(&answers.bind_addr)));
+    out.push_str(&format!("http2_port = {}\n", answers.http2_port));
+    out.push_str(&format!("http3_port = {}\n\n", answers.http3_port));
+    out.push_str("# Seconds to let in-flight connections finish on shutdown or upgrade\n");
+    out.push_str("drain_timeout_secs = 30\n\n");
+    out.push_str("# When started as root (e.g. to bind ports 80/443), switch to this user after binding\n");
+    match &answers.user {
+        Some(user) => out.push_str(&format!("user = {}\n\n", quote(user))),
+        None => out.push_str("# user = \"pear\"\n\n"),
+    }
+
+    out.push_str("[ssl]\n");
+    out.push_str("# Request and renew certificates from Let's Encrypt for the domains below\n");
+    out.push_str(&format!("auto_cert = {}\n", answers.email.is_some() && !answers.domains.is_empty()));
+    match &answers.email {
+        Some(email) => out.push_str(&format!("email = {}\n", quote(email))),
+        None => out.push_str("# email = \"admin@example.com\"\n"),
+    }
+    if answers.domains.is_empty() {
+        out.push_str("# domains = [\"example.com\", \"www.example.com\"]\n");
+    } else {
+        let domains: Vec<String> = answers.domains.iter().map(|domain| quote(domain)).collect();
+        out.push_str(&format!("domains = [{}]\n", domains.join(", ")));
+    }
+    out.push_str("\n# Or use an existing PEM certificate and key\n");
+    out.push_str("# cert_path = \"/etc/pear/cert.pem\"\n");
+    out.push_str("# key_path = \"/etc/pear/key.pem\"\n\n");
+
+    out.push_str("[cages]\n");
+    out.push_str("# Redundant Cage instances per site; a failed Cage is replaced while the others serve\n");
+    out.push_str(&format!("default_replicas = {}\n\n", answers.replicas));
+    out.push_str("# Memory limit per Cage (in MB)\n");
+    out.push_str(&format!("memory_limit_mb = {}\n\n", answers.memory_limit_mb));
+    out.push_str("# CPU timeout per request (in milliseconds)\n");
+    out.push_str("cpu_timeout_ms = 1000\n\n");
+
+    out.push_str("[ai]\n");
+    out.push_str("# Learn normal traffic and reject anomalies; \"monitor\" only logs what would be blocked\n");
+    out.push_str("enable_anomaly_detection = true\n");
+    out.push_str("mode = \"block\"\n\n");
+
+    out.push_str("[dashboard]\n");
+    out.push_str("# Dashboard and management API port\n");
+    out.push_str("enabled = true\n");
+    out.push_str(&format!("port = {}\n\n", answers.dashboard_port));
+    out.push_str("# Bearer token for `pear tenant` and `pear site`; empty disables those endpoints\n");
+    out.push_str(&format!("admin_token = {}\n", quote(&answers.admin_token)));
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PearConfig;
+
+    #[test]
+    fn test_rendered_config_is_valid() {
+        let config: PearConfig = toml::from_str(&render(&InitAnswers::default())).unwrap();
+        config.validate().unwrap();
+        assert!(!config.ssl.auto_cert);
+        assert!(config.server.user.is_none());
+
+        let answers = InitAnswers {
+            http2_port: 80,
+            domains: vec!["example.com".to_string(), "www.example.com".to_string()],
+            email: Some("ops@example.com".to_string()),
+            user: Some("pear".to_string()),
+            admin_token: random_token().unwrap(),
+            ..Default::default()
+        };
+        let config: PearConfig = toml::from_str(&render(&answers)).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.server.http2_port, 80);
+        assert!(config.ssl.auto_cert);
+        assert_eq!(config.ssl.domains.len(), 2);
+        assert_eq!(config.server.user.as_deref(), Some("pear"));
+        assert_eq!(config.dashboard.admin_token.len(), 64);
+    }
+
+    #[test]
+    fn test_prompt_uses_default() {
+        let mut input = "\n8081\n".as_bytes();
+        assert_eq!(prompt(&mut input, "Port", "8080").unwrap(), "8080");
+        assert_eq!(prompt(&mut input, "Port", "8080").unwrap(), "8081");
+    }
+}
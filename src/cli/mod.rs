@@ -1,10 +1,17 @@
 // Command Line Interface Module
 // Powerful CLI using clap for server management
 
+pub mod build;
 pub mod commands;
+pub mod dev;
+pub mod doctor;
+pub mod init;
+pub mod scaffold;
+pub mod top;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::*;
+use serde::Serialize;
 
 /// Pear Server - Revolutionary Next-Generation Web Server
 #[derive(Parser)]
@@ -15,16 +22,59 @@ use colored::*;
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+    
+    /// Output format for command results
+    #[arg(short, long, global = true, value_enum, default_value_t = OutputFormat::Table, alias = "format")]
+    pub output: OutputFormat,
+    
+    /// Configuration file, or a directory of *.toml files such as a mounted ConfigMap
+    /// Other commands read it to find the management API, the CA or the storage root.
+    #[arg(short, long, global = true, default_value = "pear.toml", env = "PEAR_CONFIG")]
+    pub config: String,
+}
+
+/// How commands print their results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable, colored output
+    #[default]
+    #[value(alias = "text")]
+    Table,
+    
+    /// One JSON document
+    Json,
+    
+    /// One YAML document
+    Yaml,
+}
+
+/// Where the lines `pear logs` shows come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogSource {
+    /// The daemon itself
+    Daemon,
+    
+    /// One line per served request
+    Access,
+    
+    /// What a site's guests print to stdout and stderr; needs --site
+    Guest,
+}
+
+impl LogSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogSource::Daemon => "daemon",
+            LogSource::Access => "access",
+            LogSource::Guest => "guest",
+        }
+    }
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// Start the Pear Server daemon
     Start {
-        /// Configuration file path
-        #[arg(short, long, default_value = "pear.toml")]
-        config: String,
-        
         /// Run in foreground (don't daemonize)
         #[arg(short, long)]
         foreground: bool,
@@ -32,6 +82,14 @@ pub enum Commands {
         /// Enable verbose logging
         #[arg(short, long)]
         verbose: bool,
+        
+        /// Run as a Kubernetes pod: flat JSON logs, no banner, shutdown within the grace period
+        #[arg(long, env = "PEAR_KUBERNETES")]
+        kubernetes: bool,
+        
+        /// Also serve the built-in demonstration site, as `[server] demo_mode = true` does
+        #[arg(long)]
+        demo: bool,
     },
     
     /// Stop the running Pear Server
@@ -42,28 +100,291 @@ pub enum Commands {
     },
     
     /// Show server status
-    Status {
-        /// Output format: text, json, or table
-        #[arg(short, long, default_value = "table")]
-        format: String,
+    Status,
+    
+    /// Compile a Rust, AssemblyScript or TinyGo project into a module Cages can run
+    Build {
+        /// Project directory
+        #[arg(default_value = ".")]
+        path: String,
+        
+        /// Compiler to use (detected from Cargo.toml, asconfig.json, package.json or go.mod)
+        #[arg(short, long, value_enum)]
+        toolchain: Option<build::Toolchain>,
+        
+        /// Where to write the module (defaults to PATH/dist/<name>.wasm)
+        #[arg(long)]
+        out: Option<String>,
+        
+        /// Unoptimized build with debug info
+        #[arg(long)]
+        debug: bool,
+        
+        /// Don't run wasm-opt on the module
+        #[arg(long)]
+        no_opt: bool,
+        
+        /// Deploy the module to this site once it is built
+        #[arg(long, value_name = "SITE")]
+        deploy: Option<String>,
+        
+        /// Number of Cage replicas for --deploy
+        #[arg(short, long, default_value = "3", requires = "deploy")]
+        replicas: usize,
+        
+        /// How the new module replaces the live one (defaults to deployment.strategy)
+        #[arg(long, value_enum, requires = "deploy")]
+        strategy: Option<crate::deployment::DeploymentStrategy>,
+    },
+    
+    /// Start a guest project from a template
+    New {
+        /// Template: rust, static, php or python
+        #[arg(value_enum)]
+        template: scaffold::Template,
+        
+        /// Directory to create (defaults to the template's name)
+        path: Option<String>,
+        
+        /// Project name (defaults to the directory's name)
+        #[arg(long)]
+        name: Option<String>,
+        
+        /// Overwrite files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+    
+    /// Serve a project locally and reload it on every save
+    Dev {
+        /// Project directory, or a built .wasm module to reload when it changes
+        #[arg(default_value = ".")]
+        path: String,
+        
+        /// Redeploy to this site on the running daemon instead of serving locally
+        #[arg(short, long)]
+        site: Option<String>,
+        
+        /// Port the local development server listens on
+        #[arg(short, long, default_value = "8080", conflicts_with = "site")]
+        port: u16,
+        
+        /// Directory holding the language runtime .wasm modules, for projects that aren't compiled
+        #[arg(long, default_value = "./assets/runtimes")]
+        runtime_dir: String,
+        
+        /// Milliseconds without changes before rebuilding
+        #[arg(long, default_value = "300")]
+        debounce: u64,
     },
     
     /// Deploy a WebAssembly module to a site
     Deploy {
-        /// Path to WebAssembly (.wasm) file
+        /// Path to WebAssembly (.wasm) file, its object key with --artifact, or the repository URL with --git
         wasm_file: String,
         
         /// Site identifier
         #[arg(short, long, default_value = "default-site")]
         site: String,
         
-        /// Number of Cage replicas
+        /// Number of Cage replicas (blue/green matches the live pool)
         #[arg(short, long, default_value = "3")]
         replicas: usize,
+        
+        /// How the new module replaces the live one (defaults to deployment.strategy)
+        #[arg(long, value_enum)]
+        strategy: Option<crate::deployment::DeploymentStrategy>,
+        
+        /// Hot-swap the site's live Cages onto the module at once, without canary or blue/green
+        #[arg(long, conflicts_with_all = ["replicas", "strategy", "no_switch", "artifact", "git"])]
+        now: bool,
+        
+        /// Blue/green: stop once the green pool passes its checks instead of switching traffic
+        #[arg(long)]
+        no_switch: bool,
+        
+        /// Blue/green: detached ed25519 signature of the module (raw, base64 or hex)
+        #[arg(long)]
+        signature: Option<String>,
+        
+        /// Blue/green: have the node fetch WASM_FILE from the tenant's artifact source
+        #[arg(long)]
+        artifact: bool,
+        
+        /// SHA-256 the fetched artifact must have (hex)
+        #[arg(long, requires = "artifact")]
+        sha256: Option<String>,
+        
+        /// Blue/green: deploy the repository at WASM_FILE, and again on every push to --reference
+        #[arg(long, conflicts_with_all = ["artifact", "signature"])]
+        git: bool,
+        
+        /// Branch or tag to deploy from the repository
+        #[arg(long, default_value = "main", requires = "git")]
+        reference: String,
+        
+        /// Command that builds the checkout, run in the node's build sandbox
+        #[arg(long, requires = "git")]
+        build: Option<String>,
+        
+        /// Built .wasm module or directory to serve, relative to the checkout
+        #[arg(long, requires = "git")]
+        build_output: Option<String>,
+    },
+    
+    /// Inspect and control a site's blue/green deployment
+    Deployment {
+        #[command(subcommand)]
+        action: DeploymentAction,
+    },
+    
+    /// Inject faults to check that self-healing recovers (needs chaos.enabled)
+    Chaos {
+        #[command(subcommand)]
+        action: ChaosAction,
+    },
+    
+    /// Save tenants, sites and their state to an archive, or restore one
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    
+    /// Compile modules ahead of a start, so the node loads native code instead of compiling
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
     },
     
+    /// Upgrade the running server to a new binary without dropping connections
+    Upgrade {
+        /// Path to the new binary (defaults to the running binary's path)
+        #[arg(short, long)]
+        binary: Option<String>,
+    },
     
-    /// Set a configuration value
+    /// Move clients to other nodes before maintenance: fail readiness and close connections gradually
+    Rebalance {
+        /// Spread the shedding over this long (30s, 10m, 2h)
+        #[arg(long, value_parser = parse_duration, default_value = "5m")]
+        over: u64,
+        
+        /// Wait until every connection has been shed
+        #[arg(short, long)]
+        wait: bool,
+        
+        /// Stop shedding and take traffic again
+        #[arg(long, conflicts_with_all = ["over", "wait", "status"])]
+        cancel: bool,
+        
+        /// Only show how far shedding has got
+        #[arg(long, conflicts_with_all = ["over", "wait"])]
+        status: bool,
+    },
+    
+    /// Manage site environment variables and secrets
+    Env {
+        #[command(subcommand)]
+        action: EnvAction,
+    },
+    
+    /// Manage scheduled jobs that call a site module's export
+    Cron {
+        #[command(subcommand)]
+        action: CronAction,
+    },
+    
+    /// Create, suspend and set quotas for tenants
+    Tenant {
+        #[command(subcommand)]
+        action: TenantAction,
+    },
+    
+    /// Add and remove a tenant's sites and attach domains
+    Site {
+        #[command(subcommand)]
+        action: SiteAction,
+    },
+    
+    /// Claim, verify and redirect a site's hostnames
+    Domain {
+        #[command(subcommand)]
+        action: DomainAction,
+    },
+    
+    /// Register webhooks for crash loops, quota warnings, rollbacks, certificate failures and bans
+    Webhook {
+        #[command(subcommand)]
+        action: WebhookAction,
+    },
+    
+    /// Manage the certificate authority behind mutual TLS on the management API
+    Ca {
+        #[command(subcommand)]
+        action: CaAction,
+    },
+    
+    /// Issue and revoke scoped API tokens
+    Token {
+        #[command(subcommand)]
+        action: TokenAction,
+    },
+    
+    /// Define custom roles: named sets of permissions tokens are issued with
+    Role {
+        #[command(subcommand)]
+        action: RoleAction,
+    },
+    
+    /// Show daemon, access and guest logs, optionally following new lines
+    Logs(LogsArgs),
+    
+    /// Show what the self-healing Supervisor did to each pool it healed
+    Events {
+        /// Only events of this site and its route pools
+        #[arg(short, long)]
+        site: Option<String>,
+        
+        /// Most recent events to show
+        #[arg(short = 'n', long, default_value = "50")]
+        lines: usize,
+    },
+    
+    /// Show who changed what through the management API
+    Audit(AuditArgs),
+    
+    /// Live view of pools, Cages, traffic, threats and healing
+    Top {
+        /// Milliseconds between refreshes
+        #[arg(short, long, default_value = "1000")]
+        interval: u64,
+    },
+    
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+    
+    /// Write a commented configuration file, asking for the important values
+    Init {
+        /// Overwrite an existing file
+        #[arg(long)]
+        force: bool,
+        
+        /// Don't ask; use the defaults
+        #[arg(short, long)]
+        yes: bool,
+    },
+    
+    /// Check this host and the configuration for problems, with fixes
+    Doctor {
+        /// Directory holding the language runtime .wasm modules
+        #[arg(long, default_value = "runtimes")]
+        runtime_dir: String,
+    },
+    
+    /// Set a configuration value, keeping the file's comments
     Set {
         /// Configuration key (e.g., server.http2_port)
         key: String,
@@ -74,12 +395,800 @@ pub enum Commands {
     
     /// Validate configuration file
     Validate {
-        /// Configuration file to validate
-        #[arg(short, long, default_value = "pear.toml")]
+        /// Configuration file to validate, instead of --config
+        #[arg(short, long)]
+        file: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EnvAction {
+    /// Set a variable; with only NAME the value is read from stdin
+    Set {
+        /// NAME=VALUE, or NAME to read the value from stdin
+        assignment: String,
+        
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+        
+        /// Store the value encrypted and hide it in listings
+        #[arg(long)]
+        secret: bool,
+    },
+    
+    /// Remove a variable or secret
+    Unset {
+        /// Variable name
+        name: String,
+        
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+    },
+    
+    /// List variables; secret values are masked
+    List {
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CronAction {
+    /// Add a job, or replace the site's job of the same name
+    Add {
+        /// Job name, unique per site
+        name: String,
+        
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+        
+        /// Cron expression in UTC (e.g. "*/5 * * * *" or "@daily")
+        #[arg(long)]
+        schedule: String,
+        
+        /// Export to call
+        #[arg(short, long, default_value = "on_schedule")]
+        export: String,
+        
+        /// Seconds a run may take (defaults to scheduler.default_timeout_secs)
+        #[arg(short, long)]
+        timeout: Option<u64>,
+    },
+    
+    /// Remove a job
+    Remove {
+        /// Job name
+        name: String,
+        
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+    },
+    
+    /// List jobs with their next and last run
+    List {
+        /// Only show jobs of this site
+        #[arg(short, long)]
+        site: Option<String>,
+    },
+    
+    /// Show a job's recent runs
+    History {
+        /// Job name
+        name: String,
+        
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TenantAction {
+    /// Create a tenant
+    Create {
+        /// Tenant name
+        name: String,
+        
+        /// Contact email
+        #[arg(short, long)]
+        email: String,
+        
+        #[command(flatten)]
+        quota: QuotaArgs,
+    },
+    
+    /// List tenants
+    List,
+    
+    /// Suspend a tenant
+    Suspend {
+        /// Tenant ID or name
+        tenant: String,
+    },
+    
+    /// Reactivate a suspended tenant
+    Activate {
+        /// Tenant ID or name
+        tenant: String,
+    },
+    
+    /// Delete a tenant and its sites, which stop being served
+    Delete {
+        /// Tenant ID or name
+        tenant: String,
+    },
+    
+    /// Show a tenant's quota, or change the limits given
+    Quota {
+        /// Tenant ID or name
+        tenant: String,
+        
+        #[command(flatten)]
+        quota: QuotaArgs,
+    },
+    
+    /// Show a tenant's usage against its quota
+    Usage {
+        /// Tenant ID or name
+        tenant: String,
+    },
+    
+    /// List the keys a tenant signs modules with
+    Keys {
+        /// Tenant ID or name
+        tenant: String,
+    },
+    
+    /// Add a module signing key, replacing any key of the same name
+    AddKey {
+        /// Tenant ID or name
+        tenant: String,
+        
+        /// Key name
+        name: String,
+        
+        /// Public key file (PEM, or a raw ed25519 key in base64 or hex), or the key itself
+        key: String,
+    },
+    
+    /// Remove a module signing key
+    RemoveKey {
+        /// Tenant ID or name
+        tenant: String,
+        
+        /// Key name
+        name: String,
+    },
+    
+    /// Encrypt a tenant's site files and secrets at rest
+    Encrypt {
+        /// Tenant ID or name
+        tenant: String,
+        
+        /// Move an encrypted tenant to a new key, re-encrypting everything
+        #[arg(long)]
+        rotate: bool,
+    },
+    
+    /// Pull the tenant's modules and site content from its own bucket; the secret key is read from stdin
+    Artifacts {
+        /// Tenant ID or name
+        tenant: String,
+        
+        /// S3-compatible endpoint, such as https://s3.eu-west-1.amazonaws.com
+        #[arg(long, required_unless_present = "remove")]
+        endpoint: Option<String>,
+        
+        #[arg(long, required_unless_present = "remove")]
+        bucket: Option<String>,
+        
+        #[arg(long, default_value = "us-east-1")]
+        region: String,
+        
+        /// Key prefix inside the bucket
+        #[arg(long, default_value = "")]
+        prefix: String,
+        
+        /// Address the bucket in the path, as MinIO and most self-hosted stores expect
+        #[arg(long)]
+        path_style: bool,
+        
+        #[arg(long, required_unless_present = "remove")]
+        access_key_id: Option<String>,
+        
+        /// Go back to the node's bucket
+        #[arg(long, conflicts_with_all = ["endpoint", "bucket", "access_key_id"])]
+        remove: bool,
+    },
+    
+    /// Rewrap every tenant key with the current master key, after rotating it
+    RewrapKeys,
+}
+
+#[derive(Subcommand)]
+pub enum SiteAction {
+    /// Add a site to a tenant
+    Add {
+        /// Site name
+        name: String,
+        
+        /// Tenant ID or name
+        #[arg(short, long)]
+        tenant: String,
+        
+        /// Domain to attach
+        #[arg(short, long)]
+        domain: Option<String>,
+    },
+    
+    /// Remove a site and stop serving it
+    Remove {
+        /// Site identifier
+        site: String,
+    },
+    
+    /// List a tenant's sites
+    List {
+        /// Tenant ID or name
+        #[arg(short, long)]
+        tenant: String,
+    },
+    
+    /// Attach a domain to a site, or detach it with --remove
+    Domain {
+        /// Site identifier
+        site: String,
+        
+        /// Domain to attach
+        #[arg(required_unless_present = "remove")]
+        domain: Option<String>,
+        
+        /// Detach the site's domain
+        #[arg(long, conflicts_with = "domain")]
+        remove: bool,
+    },
+    
+    /// Take a site down for maintenance: new requests get 503, running ones finish, and its Cages aren't healed
+    Drain {
+        /// Site identifier
+        site: String,
+    },
+    
+    /// Serve and heal a drained site again
+    Resume {
+        /// Site identifier
+        site: String,
+    },
+    
+    /// Mirror a prefix of the tenant's artifact source into the site's static content
+    SyncContent {
+        /// Site identifier
+        site: String,
+        
+        /// Key prefix to mirror (default: the site ID and a slash)
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    
+    /// Refuse modules not signed by one of the tenant's keys, or accept them again with --off
+    RequireSignature {
+        /// Site identifier
+        site: String,
+        
+        /// Accept unsigned modules again
+        #[arg(long)]
+        off: bool,
+    },
+    
+    /// List the site's guest crashes, or show one in full with --id
+    Crashes {
+        /// Site identifier
+        site: String,
+        
+        /// Crash report to show
+        #[arg(long)]
+        id: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DomainAction {
+    /// List a site's hostnames and how to verify them
+    List {
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+    },
+    
+    /// Claim a hostname for a site; it serves the site once verified
+    Add {
+        /// Hostname to claim
+        hostname: String,
+        
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+        
+        /// How ownership is proven
+        #[arg(short, long, value_enum, default_value = "http")]
+        method: crate::tenancy::domains::VerificationMethod,
+        
+        /// Redirect to another of the site's hostnames instead of serving the site
+        #[arg(long)]
+        redirect_to: Option<String>,
+    },
+    
+    /// Release a hostname
+    Remove {
+        /// Hostname to release
+        hostname: String,
+        
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+    },
+    
+    /// Check a hostname's ownership now
+    Verify {
+        /// Hostname to check
+        hostname: String,
+        
+        /// Site identifier
+        #[arg(short, long)]
+        site: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum WebhookAction {
+    /// List webhooks and how their deliveries went
+    List {
+        /// Only this tenant's webhooks
+        #[arg(short, long)]
+        tenant: Option<String>,
+    },
+    
+    /// Register a webhook and print the secret its requests are signed with
+    Add {
+        /// URL events are POSTed to
+        url: String,
+        
+        /// Events to send, comma separated (default: all)
+        #[arg(short, long, value_enum, value_delimiter = ',')]
+        events: Vec<crate::notifications::EventKind>,
+        
+        /// Only events of this tenant's sites
+        #[arg(short, long)]
+        tenant: Option<String>,
+        
+        /// Signing secret to use instead of a generated one
+        #[arg(long)]
+        secret: Option<String>,
+        
+        /// Deliveries per minute before further events are dropped
+        #[arg(long, default_value = "30")]
+        max_per_minute: u32,
+    },
+    
+    /// Remove a webhook registered with `pear webhook add`
+    Remove {
+        /// Webhook ID
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CaAction {
+    /// Create the CA in `[mtls] ca_dir`
+    Init {
+        /// Common name of the CA certificate
+        #[arg(long, default_value = "Pear Server CA")]
+        name: String,
+    },
+    
+    /// Issue a client certificate for an operator machine, or a certificate for a peer node
+    Issue {
+        /// Name of the certificate, used as its common name and to revoke it
+        name: String,
+        
+        /// Issue a peer node certificate, valid for serving the API as well as calling it
+        #[arg(long)]
+        peer: bool,
+        
+        /// Host name or address the peer is reached at (repeatable)
+        #[arg(long = "host", requires = "peer")]
+        hosts: Vec<String>,
+        
+        /// Days the certificate is valid (default: `[mtls] validity_days`)
+        #[arg(long)]
+        days: Option<u32>,
+        
+        /// Directory the certificate, its key and the CA certificate are written to
+        #[arg(long, default_value = ".")]
+        out: String,
+    },
+    
+    /// Revoke a certificate; the management API refuses it on the next connection
+    Revoke {
+        /// Name the certificate was issued with
+        name: String,
+    },
+    
+    /// List issued certificates and whether they are revoked
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum TokenAction {
+    /// Issue a token and print its secret, shown only this once
+    Create {
+        /// Name of the token
+        name: String,
+        
+        /// Role granting the token's permissions: tenant-admin or a custom role
+        #[arg(short, long)]
+        role: String,
+        
+        /// Tenant ID the token is limited to (default: node-wide)
+        #[arg(short, long)]
+        tenant: Option<String>,
+        
+        /// Site the token is limited to (repeatable; needs --tenant)
+        #[arg(long = "site", requires = "tenant")]
+        sites: Vec<String>,
+        
+        /// Only allow reading, whatever the role grants
+        #[arg(long)]
+        read_only: bool,
+        
+        /// Days until the token expires (default: never)
+        #[arg(long)]
+        expires_in_days: Option<u32>,
+    },
+    
+    /// List tokens; secrets are never shown
+    List {
+        /// Only this tenant's tokens
+        #[arg(short, long)]
+        tenant: Option<String>,
+    },
+    
+    /// Revoke a token; requests using it are refused from now on
+    Revoke {
+        /// Token ID
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RoleAction {
+    /// List built-in and custom roles with their permissions
+    List {
+        /// Only roles this tenant's tokens can use
+        #[arg(short, long)]
+        tenant: Option<String>,
+    },
+    
+    /// Create or replace a custom role
+    Set {
+        /// Name of the role
+        name: String,
+        
+        /// Permissions the role grants, comma separated
+        #[arg(short, long, value_enum, value_delimiter = ',', required = true)]
+        permissions: Vec<crate::tenancy::auth::Permission>,
+        
+        /// Tenant ID the role belongs to (default: usable by every tenant)
+        #[arg(short, long)]
+        tenant: Option<String>,
+    },
+    
+    /// Remove a custom role no token uses
+    Remove {
+        /// Name of the role
+        name: String,
+        
+        /// Tenant ID the role belongs to
+        #[arg(short, long)]
+        tenant: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DeploymentAction {
+    /// Show the site's current or last blue/green deployment
+    Status {
+        /// Site identifier
+        site: String,
+    },
+    
+    /// Send all traffic to the checked green pool
+    Switch {
+        /// Site identifier
+        site: String,
+    },
+    
+    /// Flip traffic back to blue, or discard a green pool that isn't live yet
+    Rollback {
+        /// Site identifier
+        site: String,
+    },
+    
+    /// Release the standby blue pool, giving up instant rollback
+    Finish {
+        /// Site identifier
+        site: String,
+    },
+    
+    /// List the site's recent deployments, newest first
+    History {
+        /// Site identifier
+        site: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ChaosAction {
+    /// Show the chaos settings and recently injected faults
+    Status,
+    
+    /// Crash one of the site's Cages
+    Crash {
+        /// Site identifier
+        site: String,
+        
+        /// Cage to crash (defaults to a random healthy one)
+        #[arg(long)]
+        cage: Option<u64>,
+    },
+    
+    /// Delay every request to the site
+    Latency {
+        /// Site identifier
+        site: String,
+        
+        /// Milliseconds added to each request (defaults to chaos.latency_ms)
+        #[arg(long)]
+        delay: Option<u64>,
+        
+        /// Seconds the delay lasts (defaults to chaos.duration_secs)
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    
+    /// Fail a Cage's health checks, taking it out of rotation
+    Health {
+        /// Site identifier
+        site: String,
+        
+        /// Cage to fail (defaults to a random healthy one)
+        #[arg(long)]
+        cage: Option<u64>,
+        
+        /// Seconds the checks fail for (defaults to chaos.duration_secs)
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Save the running node's tenants, sites, modules, bans and CRDT documents
+    Create {
+        /// Archive to write (defaults to pear-snapshot-<timestamp>.json)
+        file: Option<String>,
+        
+        /// Only save this tenant and its sites (ID or name)
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+    
+    /// Check an archive and restore it into the running node
+    Restore {
+        /// Archive written by `pear snapshot create`
         file: String,
+        
+        /// Only restore this tenant and its sites (ID or name, as saved in the archive)
+        #[arg(long)]
+        tenant: Option<String>,
+        
+        /// Check the archive without restoring it
+        #[arg(long)]
+        verify_only: bool,
     },
 }
 
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// Compile modules into `[compile_cache]` for the node's current engine settings
+    Compile {
+        /// Module files to compile
+        #[arg(required_unless_present = "all")]
+        modules: Vec<String>,
+        
+        /// Compile every module in the module store
+        #[arg(long, conflicts_with = "modules")]
+        all: bool,
+    },
+    
+    /// Delete every compiled module; the node recompiles as sites start
+    Clear,
+}
+
+/// Filters for `pear logs`
+#[derive(Args)]
+pub struct LogsArgs {
+    /// Only lines about this site
+    #[arg(short, long)]
+    pub site: Option<String>,
+    
+    /// Only lines from this source
+    #[arg(long, value_enum, requires_if("guest", "site"))]
+    pub source: Option<LogSource>,
+    
+    /// Least severe level to show: error, warn, info, debug, or trace
+    #[arg(short, long)]
+    pub level: Option<String>,
+    
+    /// Only lines newer than a duration ago (30s, 10m, 2h, 1d) or an RFC 3339 time
+    #[arg(long, value_parser = parse_since)]
+    pub since: Option<i64>,
+    
+    /// Only lines containing this text
+    #[arg(short, long)]
+    pub grep: Option<String>,
+    
+    /// Only lines logged while serving this request, including its guest's output
+    #[arg(long)]
+    pub request_id: Option<String>,
+    
+    /// Keep streaming new lines as they are logged
+    #[arg(short, long)]
+    pub follow: bool,
+    
+    /// Recent lines to show first
+    #[arg(short = 'n', long, default_value = "200")]
+    pub lines: usize,
+}
+
+/// Filters for `pear audit`
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Only actions by this user or tenant ID
+    #[arg(long)]
+    pub actor: Option<String>,
+    
+    /// Only actions containing this text, such as `quota` or `blue-green`
+    #[arg(long)]
+    pub action: Option<String>,
+    
+    /// Only actions on paths starting with this, such as `/api/sites/blog`
+    #[arg(long)]
+    pub target: Option<String>,
+    
+    /// Only actions newer than a duration ago (30s, 10m, 2h, 1d) or an RFC 3339 time
+    #[arg(long, value_parser = parse_since)]
+    pub since: Option<i64>,
+    
+    /// Most recent actions to show
+    #[arg(short = 'n', long, default_value = "100")]
+    pub lines: usize,
+    
+    /// Print the whole log as JSON lines instead
+    #[arg(long, conflicts_with_all = ["actor", "action", "target", "since"])]
+    pub export: bool,
+}
+
+/// Tenant quota limits; only the ones given are changed
+#[derive(Args, Default)]
+pub struct QuotaArgs {
+    /// Sites the tenant may create
+    #[arg(long)]
+    pub max_sites: Option<usize>,
+    
+    /// Storage across all sites
+    #[arg(long)]
+    pub max_storage_gb: Option<usize>,
+    
+    /// Memory limit of each Cage
+    #[arg(long)]
+    pub max_memory_per_cage_mb: Option<usize>,
+    
+    /// Cage replicas per site
+    #[arg(long)]
+    pub max_cages_per_site: Option<usize>,
+    
+    /// Requests per second across all sites
+    #[arg(long)]
+    pub max_requests_per_second: Option<usize>,
+    
+    /// Ingress plus egress per calendar month
+    #[arg(long)]
+    pub max_bandwidth_gb_month: Option<u64>,
+    
+    /// Memory limits of all running Cages combined
+    #[arg(long)]
+    pub max_total_memory_mb: Option<usize>,
+    
+    /// Running Cages across all sites
+    #[arg(long)]
+    pub max_instances: Option<usize>,
+    
+    /// Combined size of the site databases
+    #[arg(long)]
+    pub max_database_mb: Option<usize>,
+    
+    /// Email recipients per day
+    #[arg(long)]
+    pub max_emails_per_day: Option<u64>,
+}
+
+impl QuotaArgs {
+    /// The limits given, as the fields of a quota update
+    pub fn changes(&self) -> serde_json::Map<String, serde_json::Value> {
+        let limits = [
+            ("max_sites", self.max_sites.map(|v| v as u64)),
+            ("max_storage_gb", self.max_storage_gb.map(|v| v as u64)),
+            ("max_memory_per_cage_mb", self.max_memory_per_cage_mb.map(|v| v as u64)),
+            ("max_cages_per_site", self.max_cages_per_site.map(|v| v as u64)),
+            ("max_requests_per_second", self.max_requests_per_second.map(|v| v as u64)),
+            ("max_bandwidth_gb_month", self.max_bandwidth_gb_month),
+            ("max_total_memory_mb", self.max_total_memory_mb.map(|v| v as u64)),
+            ("max_instances", self.max_instances.map(|v| v as u64)),
+            ("max_database_mb", self.max_database_mb.map(|v| v as u64)),
+            ("max_emails_per_day", self.max_emails_per_day),
+        ];
+        limits.into_iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value?.into())))
+            .collect()
+    }
+}
+
+/// Parse `--since` into Unix milliseconds
+fn parse_since(value: &str) -> Result<i64, String> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp_millis());
+    }
+    
+    let seconds = duration_secs(value, "a duration like 10m or an RFC 3339 time")?;
+    Ok(chrono::Utc::now().timestamp_millis() - seconds as i64 * 1000)
+}
+
+/// Parse a duration such as 30s, 10m, 2h or 1d into seconds
+fn parse_duration(value: &str) -> Result<u64, String> {
+    duration_secs(value, "a duration like 10m")
+}
+
+fn duration_secs(value: &str, expected: &str) -> Result<u64, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| format!("expected {}, got '{}'", expected, value))?;
+    let seconds = match unit {
+        "s" | "" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("unknown duration unit '{}' (use s, m, h, or d)", unit)),
+    };
+    Ok(amount * seconds)
+}
+
+/// Print a command result as JSON or YAML
+/// Returns false for table output, which the caller prints itself.
+pub fn print_structured(output: OutputFormat, value: &impl Serialize) -> anyhow::Result<bool> {
+    match output {
+        OutputFormat::Table => return Ok(false),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(value)?),
+    }
+    Ok(true)
+}
+
 /// Print a success message
 pub fn success(msg: &str) {
     println!("{} {}", "✓".green().bold(), msg);
@@ -118,9 +1227,394 @@ pub fn print_banner() {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_cli_definition() {
+        // Catches flags that clash with the global ones
+        <Cli as clap::CommandFactory>::command().debug_assert();
+    }
+
     #[test]
     fn test_cli_parsing() {
         // Test that CLI can be constructed
         let _cli = Cli::parse_from(&["pear", "start", "--foreground"]);
+        let cli = Cli::parse_from(&["pear", "start", "--demo"]);
+        assert!(matches!(cli.command, Commands::Start { demo: true, kubernetes: false, .. }));
+    }
+
+    #[test]
+    fn test_set_parsing() {
+        let cli = Cli::parse_from(&["pear", "set", "server.http2_port", "80", "-c", "/etc/pear/pear.toml"]);
+        assert_eq!(cli.config, "/etc/pear/pear.toml");
+        match cli.command {
+            Commands::Set { key, value } => {
+                assert_eq!(key, "server.http2_port");
+                assert_eq!(value, "80");
+            }
+            _ => panic!("expected set command"),
+        }
+        
+        let cli = Cli::parse_from(&["pear", "validate", "--config", "prod.toml"]);
+        assert!(matches!(cli.command, Commands::Validate { file: None }));
+        assert_eq!(cli.config, "prod.toml");
+        let cli = Cli::parse_from(&["pear", "--config", "prod.toml", "validate", "--file", "staging.toml"]);
+        assert!(matches!(cli.command, Commands::Validate { file: Some(file) } if file == "staging.toml"));
+    }
+    
+    #[test]
+    fn test_deploy_parsing() {
+        let cli = Cli::parse_from(&["pear", "deploy", "site.wasm", "--site", "blog", "--strategy", "blue-green", "--no-switch"]);
+        match cli.command {
+            Commands::Deploy { site, strategy, no_switch, .. } => {
+                assert_eq!(site, "blog");
+                assert_eq!(strategy, Some(crate::deployment::DeploymentStrategy::BlueGreen));
+                assert!(no_switch);
+            }
+            _ => panic!("expected deploy command"),
+        }
+        
+        let cli = Cli::parse_from(&["pear", "deploy", "site.wasm", "--site", "blog", "--now"]);
+        assert!(matches!(cli.command, Commands::Deploy { now: true, strategy: None, .. }));
+        assert!(Cli::try_parse_from(&["pear", "deploy", "site.wasm", "--now", "--strategy", "canary"]).is_err());
+        
+        let cli = Cli::parse_from(&["pear", "deployment", "rollback", "blog"]);
+        assert!(matches!(cli.command, Commands::Deployment { action: DeploymentAction::Rollback { site, .. } } if site == "blog"));
+    }
+    
+    #[test]
+    fn test_chaos_parsing() {
+        let cli = Cli::parse_from(&["pear", "chaos", "latency", "blog", "--delay", "250"]);
+        match cli.command {
+            Commands::Chaos { action: ChaosAction::Latency { site, delay, duration, .. } } => {
+                assert_eq!(site, "blog");
+                assert_eq!((delay, duration), (Some(250), None));
+            }
+            _ => panic!("expected chaos latency command"),
+        }
+        
+        let cli = Cli::parse_from(&["pear", "chaos", "crash", "blog", "--cage", "3"]);
+        assert!(matches!(cli.command, Commands::Chaos { action: ChaosAction::Crash { cage: Some(3), .. } }));
+    }
+    
+    #[test]
+    fn test_snapshot_parsing() {
+        let cli = Cli::parse_from(&["pear", "snapshot", "create", "node.json"]);
+        assert!(matches!(cli.command, Commands::Snapshot { action: SnapshotAction::Create { file: Some(_), tenant: None, .. } }));
+        
+        let cli = Cli::parse_from(&["pear", "snapshot", "restore", "node.json", "--tenant", "acme", "--verify-only"]);
+        match cli.command {
+            Commands::Snapshot { action: SnapshotAction::Restore { file, tenant, verify_only, .. } } => {
+                assert_eq!(file, "node.json");
+                assert_eq!(tenant.as_deref(), Some("acme"));
+                assert!(verify_only);
+            }
+            _ => panic!("expected snapshot restore command"),
+        }
+    }
+    
+    #[test]
+    fn test_signing_parsing() {
+        let cli = Cli::parse_from(&["pear", "deploy", "site.wasm", "--site", "blog", "--signature", "site.wasm.sig"]);
+        assert!(matches!(cli.command, Commands::Deploy { signature: Some(path), .. } if path == "site.wasm.sig"));
+        
+        let cli = Cli::parse_from(&["pear", "tenant", "add-key", "acme", "ci", "ci.pub"]);
+        match cli.command {
+            Commands::Tenant { action: TenantAction::AddKey { tenant, name, key, .. } } => {
+                assert_eq!((tenant.as_str(), name.as_str(), key.as_str()), ("acme", "ci", "ci.pub"));
+            }
+            _ => panic!("expected tenant add-key command"),
+        }
+        
+        let cli = Cli::parse_from(&["pear", "site", "require-signature", "blog", "--off"]);
+        assert!(matches!(cli.command, Commands::Site { action: SiteAction::RequireSignature { off: true, .. } }));
+    }
+    
+    #[test]
+    fn test_artifact_parsing() {
+        let cli = Cli::parse_from(&["pear", "deploy", "builds/blog.wasm", "--site", "blog", "--artifact", "--sha256", "ab12"]);
+        assert!(matches!(cli.command, Commands::Deploy { artifact: true, sha256: Some(sha), .. } if sha == "ab12"));
+        assert!(Cli::try_parse_from(&["pear", "deploy", "blog.wasm", "--sha256", "ab12"]).is_err());
+        
+        let cli = Cli::parse_from(&["pear", "tenant", "artifacts", "acme", "--remove"]);
+        assert!(matches!(cli.command, Commands::Tenant { action: TenantAction::Artifacts { remove: true, .. } }));
+        assert!(Cli::try_parse_from(&["pear", "tenant", "artifacts", "acme", "--endpoint", "https://s3.amazonaws.com"]).is_err());
+    }
+    
+    #[test]
+    fn test_git_deploy_parsing() {
+        let cli = Cli::parse_from(&[
+            "pear", "deploy", "https://github.com/acme/blog.git", "--site", "blog", "--git",
+            "--reference", "v2.0", "--build", "npm ci && npm run build", "--build-output", "dist",
+        ]);
+        match cli.command {
+            Commands::Deploy { wasm_file, git, reference, build, build_output, .. } => {
+                assert!(git);
+                assert_eq!(wasm_file, "https://github.com/acme/blog.git");
+                assert_eq!(reference, "v2.0");
+                assert_eq!(build.as_deref(), Some("npm ci && npm run build"));
+                assert_eq!(build_output.as_deref(), Some("dist"));
+            }
+            _ => panic!("expected deploy command"),
+        }
+        assert!(Cli::try_parse_from(&["pear", "deploy", "blog.wasm", "--build", "make"]).is_err());
+        assert!(Cli::try_parse_from(&["pear", "deploy", "https://x.dev/r.git", "--git", "--artifact"]).is_err());
+    }
+    
+    #[test]
+    fn test_build_parsing() {
+        let cli = Cli::parse_from(&["pear", "build", "guests/blog", "-t", "as", "--deploy", "blog", "--strategy", "blue-green"]);
+        match cli.command {
+            Commands::Build { path, toolchain, deploy, strategy, no_opt, .. } => {
+                assert_eq!(path, "guests/blog");
+                assert_eq!(toolchain, Some(build::Toolchain::AssemblyScript));
+                assert_eq!(deploy.as_deref(), Some("blog"));
+                assert_eq!(strategy, Some(crate::deployment::DeploymentStrategy::BlueGreen));
+                assert!(!no_opt);
+            }
+            _ => panic!("expected build command"),
+        }
+        assert!(matches!(Cli::parse_from(&["pear", "build"]).command, Commands::Build { path, deploy: None, .. } if path == "."));
+        assert!(Cli::try_parse_from(&["pear", "build", "--replicas", "5"]).is_err());
+    }
+    
+    #[test]
+    fn test_new_and_dev_parsing() {
+        let cli = Cli::parse_from(&["pear", "new", "python", "sites/api", "--name", "api"]);
+        assert!(matches!(
+            cli.command,
+            Commands::New { template: scaffold::Template::Python, path: Some(path), name: Some(name), force: false }
+                if path == "sites/api" && name == "api"
+        ));
+        assert!(Cli::try_parse_from(&["pear", "new", "cobol"]).is_err());
+        
+        let cli = Cli::parse_from(&["pear", "dev", "--site", "blog", "--debounce", "500"]);
+        match cli.command {
+            Commands::Dev { path, site, debounce, .. } => {
+                assert_eq!(path, ".");
+                assert_eq!(site.as_deref(), Some("blog"));
+                assert_eq!(debounce, 500);
+            }
+            _ => panic!("expected dev command"),
+        }
+        let cli = Cli::parse_from(&["pear", "dev", "target/wasm32-wasip1/release/blog.wasm", "-p", "3000"]);
+        assert!(matches!(cli.command, Commands::Dev { site: None, port: 3000, .. }));
+        assert!(matches!(Cli::parse_from(&["pear", "dev"]).command, Commands::Dev { site: None, port: 8080, .. }));
+        assert!(Cli::try_parse_from(&["pear", "dev", "--site", "blog", "--port", "3000"]).is_err());
+    }
+    
+    #[test]
+    fn test_domain_parsing() {
+        let cli = Cli::parse_from(&["pear", "webhook", "add", "https://hooks.example.com/pear", "-e", "crash_loop,security_ban"]);
+        match cli.command {
+            Commands::Webhook { action: WebhookAction::Add { url, events, tenant, max_per_minute, .. } } => {
+                assert_eq!(url, "https://hooks.example.com/pear");
+                assert_eq!(events, vec![crate::notifications::EventKind::CrashLoop, crate::notifications::EventKind::SecurityBan]);
+                assert_eq!(tenant, None);
+                assert_eq!(max_per_minute, 30);
+            }
+            _ => panic!("expected webhook add command"),
+        }
+        
+        let cli = Cli::parse_from(&["pear", "domain", "add", "www.example.com", "-s", "blog", "-m", "dns", "--redirect-to", "example.com"]);
+        match cli.command {
+            Commands::Domain { action: DomainAction::Add { hostname, site, method, redirect_to, .. } } => {
+                assert_eq!(hostname, "www.example.com");
+                assert_eq!(site, "blog");
+                assert_eq!(method, crate::tenancy::domains::VerificationMethod::Dns);
+                assert_eq!(redirect_to.as_deref(), Some("example.com"));
+            }
+            _ => panic!("expected domain add command"),
+        }
+    }
+    
+    #[test]
+    fn test_upgrade_parsing() {
+        let cli = Cli::parse_from(&["pear", "upgrade", "--binary", "/usr/local/bin/pear"]);
+        assert_eq!(cli.config, "pear.toml");
+        match cli.command {
+            Commands::Upgrade { binary } => {
+                assert_eq!(binary.as_deref(), Some("/usr/local/bin/pear"));
+            }
+            _ => panic!("expected upgrade command"),
+        }
+    }
+
+    #[test]
+    fn test_rebalance_parsing() {
+        let cli = Cli::parse_from(&["pear", "rebalance", "--over", "10m", "--wait"]);
+        assert!(matches!(cli.command, Commands::Rebalance { over: 600, wait: true, cancel: false, .. }));
+
+        let cli = Cli::parse_from(&["pear", "rebalance", "--cancel"]);
+        assert!(matches!(cli.command, Commands::Rebalance { over: 300, cancel: true, .. }));
+        assert!(Cli::try_parse_from(&["pear", "rebalance", "--cancel", "--over", "1h"]).is_err());
+        assert!(Cli::try_parse_from(&["pear", "rebalance", "--over", "10 minutes"]).is_err());
+    }
+
+    #[test]
+    fn test_cache_parsing() {
+        let cli = Cli::parse_from(&["pear", "cache", "compile", "--all"]);
+        assert!(matches!(cli.command, Commands::Cache { action: CacheAction::Compile { all: true, .. } }));
+        let cli = Cli::parse_from(&["pear", "cache", "compile", "site.wasm"]);
+        assert!(matches!(cli.command, Commands::Cache { action: CacheAction::Compile { all: false, ref modules, .. } } if modules.len() == 1));
+        assert!(Cli::try_parse_from(&["pear", "cache", "compile"]).is_err());
+        assert!(Cli::try_parse_from(&["pear", "cache", "compile", "--all", "site.wasm"]).is_err());
+    }
+
+    #[test]
+    fn test_env_parsing() {
+        let cli = Cli::parse_from(&["pear", "env", "set", "API_KEY", "--site", "blog", "--secret"]);
+        match cli.command {
+            Commands::Env { action: EnvAction::Set { assignment, site, secret, .. } } => {
+                assert_eq!(assignment, "API_KEY");
+                assert_eq!(site, "blog");
+                assert!(secret);
+            }
+            _ => panic!("expected env set command"),
+        }
+    }
+
+    #[test]
+    fn test_cron_parsing() {
+        let cli = Cli::parse_from(&["pear", "cron", "add", "cleanup", "--site", "blog", "--schedule", "0 3 * * *", "--timeout", "60"]);
+        match cli.command {
+            Commands::Cron { action: CronAction::Add { name, site, schedule, export, timeout, .. } } => {
+                assert_eq!(name, "cleanup");
+                assert_eq!(site, "blog");
+                assert_eq!(schedule, "0 3 * * *");
+                assert_eq!(export, "on_schedule");
+                assert_eq!(timeout, Some(60));
+            }
+            _ => panic!("expected cron add command"),
+        }
+    }
+
+    #[test]
+    fn test_output_parsing() {
+        let cli = Cli::parse_from(&["pear", "status", "--output", "json"]);
+        assert_eq!(cli.output, OutputFormat::Json);
+        
+        // Accepted after the subcommand, and under the old `--format text` spelling
+        let cli = Cli::parse_from(&["pear", "deploy", "site.wasm", "-o", "yaml"]);
+        assert_eq!(cli.output, OutputFormat::Yaml);
+        let cli = Cli::parse_from(&["pear", "status", "--format", "text"]);
+        assert_eq!(cli.output, OutputFormat::Table);
+        
+        let cli = Cli::parse_from(&["pear", "completions", "zsh"]);
+        assert!(matches!(cli.command, Commands::Completions { shell: clap_complete::Shell::Zsh }));
+    }
+
+    #[test]
+    fn test_tenant_parsing() {
+        let cli = Cli::parse_from(&["pear", "tenant", "quota", "acme", "--max-sites", "10", "--max-bandwidth-gb-month", "500"]);
+        match cli.command {
+            Commands::Tenant { action: TenantAction::Quota { tenant, quota, .. } } => {
+                assert_eq!(tenant, "acme");
+                let changes = quota.changes();
+                assert_eq!(changes.len(), 2);
+                assert_eq!(changes["max_sites"], 10);
+                assert_eq!(changes["max_bandwidth_gb_month"], 500);
+            }
+            _ => panic!("expected tenant quota command"),
+        }
+
+        let cli = Cli::parse_from(&["pear", "tenant", "delete", "acme"]);
+        assert!(matches!(cli.command, Commands::Tenant { action: TenantAction::Delete { tenant, .. } } if tenant == "acme"));
+
+        let cli = Cli::parse_from(&["pear", "site", "domain", "site-1", "--remove"]);
+        assert!(matches!(cli.command, Commands::Site { action: SiteAction::Domain { remove: true, domain: None, .. } }));
+        assert!(Cli::try_parse_from(&["pear", "site", "domain", "site-1"]).is_err());
+
+        let cli = Cli::parse_from(&["pear", "site", "crashes", "site-1", "--id", "3"]);
+        assert!(matches!(cli.command, Commands::Site { action: SiteAction::Crashes { id: Some(3), .. } }));
+
+        let cli = Cli::parse_from(&["pear", "site", "drain", "site-1"]);
+        assert!(matches!(cli.command, Commands::Site { action: SiteAction::Drain { site, .. } } if site == "site-1"));
+        let cli = Cli::parse_from(&["pear", "site", "resume", "site-1"]);
+        assert!(matches!(cli.command, Commands::Site { action: SiteAction::Resume { .. } }));
+    }
+
+    #[test]
+    fn test_logs_parsing() {
+        let cli = Cli::parse_from(&["pear", "logs", "--site", "blog", "--level", "warn", "--since", "10m", "-f"]);
+        match cli.command {
+            Commands::Logs(args) => {
+                assert_eq!(args.source, None);
+                assert_eq!(args.site.as_deref(), Some("blog"));
+                assert_eq!(args.level.as_deref(), Some("warn"));
+                let ago = chrono::Utc::now().timestamp_millis() - args.since.unwrap();
+                assert!((600_000..610_000).contains(&ago));
+                assert!(args.follow);
+                assert_eq!(args.lines, 200);
+            }
+            _ => panic!("expected logs command"),
+        }
+
+        let cli = Cli::parse_from(&["pear", "logs", "--site", "blog", "--source", "guest"]);
+        assert!(matches!(cli.command, Commands::Logs(LogsArgs { source: Some(LogSource::Guest), .. })));
+        assert!(Cli::try_parse_from(&["pear", "logs", "--source", "guest"]).is_err());
+        assert!(Cli::try_parse_from(&["pear", "logs", "--source", "access"]).is_ok());
+
+        let cli = Cli::parse_from(&["pear", "audit", "--action", "quota", "--since", "1d"]);
+        assert!(matches!(cli.command, Commands::Audit(AuditArgs { action: Some(action), since: Some(_), lines: 100, .. }) if action == "quota"));
+        assert!(Cli::try_parse_from(&["pear", "audit", "--export", "--actor", "root"]).is_err());
+
+        let cli = Cli::parse_from(&["pear", "events", "--site", "foo"]);
+        assert!(matches!(cli.command, Commands::Events { site: Some(site), lines: 50, .. } if site == "foo"));
+
+        let cli = Cli::parse_from(&["pear", "logs", "--request-id", "3f2a9c"]);
+        assert!(matches!(cli.command, Commands::Logs(LogsArgs { request_id: Some(id), .. }) if id == "3f2a9c"));
+
+        assert_eq!(parse_since("2026-01-01T00:00:00Z"), Ok(1_767_225_600_000));
+        assert!(parse_since("5 minutes").is_err());
+    }
+
+    #[test]
+    fn test_ca_parsing() {
+        let cli = Cli::parse_from(&["pear", "ca", "issue", "node-b", "--peer", "--host", "10.0.0.2", "--host", "node-b.internal"]);
+        match cli.command {
+            Commands::Ca { action: CaAction::Issue { name, peer, hosts, days, out, .. } } => {
+                assert_eq!(name, "node-b");
+                assert!(peer);
+                assert_eq!(hosts, ["10.0.0.2", "node-b.internal"]);
+                assert_eq!(days, None);
+                assert_eq!(out, ".");
+            }
+            _ => panic!("expected ca issue command"),
+        }
+        assert!(Cli::try_parse_from(&["pear", "ca", "issue", "laptop", "--host", "10.0.0.2"]).is_err());
+
+        let cli = Cli::parse_from(&["pear", "ca", "init"]);
+        assert!(matches!(cli.command, Commands::Ca { action: CaAction::Init { name, .. } } if name == "Pear Server CA"));
+        let cli = Cli::parse_from(&["pear", "ca", "revoke", "laptop"]);
+        assert!(matches!(cli.command, Commands::Ca { action: CaAction::Revoke { name, .. } } if name == "laptop"));
+    }
+
+    #[test]
+    fn test_token_and_role_parsing() {
+        use crate::tenancy::auth::Permission;
+
+        let cli = Cli::parse_from(&["pear", "token", "create", "ci", "--role", "deployer", "--tenant", "acme", "--site", "blog", "--read-only"]);
+        match cli.command {
+            Commands::Token { action: TokenAction::Create { name, role, tenant, sites, read_only, expires_in_days, .. } } => {
+                assert_eq!(name, "ci");
+                assert_eq!(role, "deployer");
+                assert_eq!(tenant.as_deref(), Some("acme"));
+                assert_eq!(sites, ["blog"]);
+                assert!(read_only);
+                assert_eq!(expires_in_days, None);
+            }
+            _ => panic!("expected token create command"),
+        }
+        assert!(Cli::try_parse_from(&["pear", "token", "create", "ci", "--role", "deployer", "--site", "blog"]).is_err());
+
+        let cli = Cli::parse_from(&["pear", "role", "set", "deployer", "--permissions", "deploy,view-metrics"]);
+        match cli.command {
+            Commands::Role { action: RoleAction::Set { name, permissions, tenant, .. } } => {
+                assert_eq!(name, "deployer");
+                assert_eq!(permissions, [Permission::Deploy, Permission::ViewMetrics]);
+                assert_eq!(tenant, None);
+            }
+            _ => panic!("expected role set command"),
+        }
+        assert!(Cli::try_parse_from(&["pear", "role", "set", "deployer"]).is_err());
     }
 }
@@ -0,0 +1,180 @@
+// Project Templates
+// `pear new`: writes a starter guest project set up for what Cages import and call
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use colored::*;
+use include_dir::{include_dir, Dir, DirEntry};
+use std::path::{Path, PathBuf};
+
+/// One directory per template; `{{name}}` in file contents becomes the project name, and a
+/// `.template` suffix is dropped (it keeps the Rust template's Cargo.toml from being a package here)
+static TEMPLATES: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/templates");
+
+/// Starter projects `pear new` can write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Template {
+    /// Rust crate exporting a streaming HTTP handler
+    Rust,
+
+    /// HTML and CSS served by the static runtime
+    Static,
+
+    /// PHP app for php-cgi
+    Php,
+
+    /// Python WSGI app
+    Python,
+}
+
+impl Template {
+    fn dir(&self) -> &'static str {
+        match self {
+            Template::Rust => "rust",
+            Template::Static => "static",
+            Template::Php => "php",
+            Template::Python => "python",
+        }
+    }
+}
+
+/// Write the template into `path` (default: a directory named after the template) and say what's next
+pub fn run(template: Template, path: Option<&str>, name: Option<&str>, force: bool) -> anyhow::Result<()> {
+    let dir = PathBuf::from(path.unwrap_or(template.dir()));
+    let name = match name {
+        Some(name) => {
+            check_name(name)?;
+            name.to_string()
+        }
+        None => default_name(&dir),
+    };
+    let written = create(template, &dir, &name, force)?;
+
+    super::success(&format!("Created {} ({} files)", dir.display().to_string().bright_white(), written.len()));
+    println!();
+    println!("  cd {}", dir.display());
+    if template == Template::Rust {
+        println!("  rustup target add wasm32-wasip1");
+        println!("  pear dev");
+        println!();
+        super::info("`pear dev` serves the project on http://127.0.0.1:8080 and rebuilds it on every save");
+    } else {
+        println!("  pear dev --site <site>");
+        println!();
+        super::info("`pear dev` redeploys to the local daemon on every save");
+    }
+    Ok(())
+}
+
+/// Write the template's files, refusing to overwrite any unless `force`
+pub fn create(template: Template, dir: &Path, name: &str, force: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let Some(files) = TEMPLATES.get_dir(template.dir()) else {
+        bail!("Template {} is missing from this build", template.dir());
+    };
+    let mut planned = Vec::new();
+    collect(files, template.dir(), &mut planned);
+    if !force {
+        if let Some((path, _)) = planned.iter().find(|(path, _)| dir.join(path).exists()) {
+            bail!("{} already exists (use --force to overwrite it)", dir.join(path).display());
+        }
+    }
+
+    let mut written = Vec::new();
+    for (relative, contents) in planned {
+        let path = dir.join(&relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(&path, contents.replace("{{name}}", name))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Every file below `dir` as (path relative to the template, contents)
+fn collect(dir: &Dir<'_>, root: &str, out: &mut Vec<(PathBuf, String)>) {
+    for entry in dir.entries() {
+        match entry {
+            DirEntry::Dir(dir) => collect(dir, root, out),
+            DirEntry::File(file) => {
+                let Ok(relative) = file.path().strip_prefix(root) else { continue };
+                let relative = match relative.to_str().and_then(|path| path.strip_suffix(".template")) {
+                    Some(path) => PathBuf::from(path),
+                    None => relative.to_path_buf(),
+                };
+                out.push((relative, String::from_utf8_lossy(file.contents()).into_owned()));
+            }
+        }
+    }
+}
+
+/// Names end up as Cargo package names and in page titles
+fn check_name(name: &str) -> anyhow::Result<()> {
+    let mut chars = name.chars();
+    let starts_with_letter = chars.next().is_some_and(|c| c.is_ascii_alphabetic());
+    if !starts_with_letter || !chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!("Project names start with a letter and contain only letters, digits, - and _");
+    }
+    Ok(())
+}
+
+/// The directory's name, made into a valid project name
+fn default_name(dir: &Path) -> String {
+    let base = dir.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .or_else(|| std::env::current_dir().ok()?.file_name().map(|name| name.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let name: String = base
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '-' })
+        .collect();
+    let name = name.trim_matches('-');
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => name.to_string(),
+        Some(_) => format!("site-{}", name),
+        None => "site".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("hello");
+        create(Template::Rust, &project, "hello", false).unwrap();
+        let manifest = std::fs::read_to_string(project.join("Cargo.toml")).unwrap();
+        assert!(manifest.contains("name = \"hello\""));
+        assert!(manifest.contains("cdylib"));
+        assert!(std::fs::read_to_string(project.join("src/lib.rs")).unwrap().contains("pub extern \"C\" fn handle_stream"));
+        assert!(project.join(".gitignore").is_file());
+        assert_eq!(super::super::build::Toolchain::detect(&project), Some(super::super::build::Toolchain::Rust));
+
+        assert!(create(Template::Rust, &project, "hello", false).is_err());
+        create(Template::Rust, &project, "hello", true).unwrap();
+
+        let polyglot = crate::runtime::polyglot::PolyglotAdapter::new(dir.path());
+        for (template, language) in [
+            (Template::Static, crate::runtime::polyglot::DetectedLanguage::StaticFiles),
+            (Template::Php, crate::runtime::polyglot::DetectedLanguage::PHP),
+            (Template::Python, crate::runtime::polyglot::DetectedLanguage::Python),
+        ] {
+            let project = dir.path().join(template.dir());
+            create(template, &project, "demo", false).unwrap();
+            assert_eq!(polyglot.detect_language(&project).unwrap(), language);
+        }
+    }
+
+    #[test]
+    fn test_project_names() {
+        assert_eq!(default_name(Path::new("sites/My Blog")), "my-blog");
+        assert_eq!(default_name(Path::new("2024-site")), "site-2024-site");
+        assert!(check_name("blog_v2").is_ok());
+        assert!(check_name("2blog").is_err());
+        assert!(check_name("blog/../x").is_err());
+    }
+}
@@ -0,0 +1,368 @@
+// Live Terminal View
+// `pear top`: pools, Cages, traffic, threats and healing from the management API, redrawn in place
+
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use futures::StreamExt;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::commands::{api_request, api_stream};
+use crate::observability::logs::LogRecord;
+
+/// Healing log lines shown at the bottom
+const HEALING_LINES: usize = 5;
+
+/// Refresh until the user quits with q, Esc or Ctrl-C
+pub async fn run(config: String, interval: Duration) -> anyhow::Result<()> {
+    // The first fetch happens before the screen is taken over, so connection errors print normally
+    let mut view = View::new(interval);
+    view.update(fetch(&config).await?);
+
+    enable_raw_mode()?;
+    let _restore = RestoreTerminal;
+    crossterm::execute!(std::io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
+    terminal.hide_cursor()?;
+
+    let mut events = EventStream::new();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    terminal.draw(|frame| view.draw(frame))?;
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                match fetch(&config).await {
+                    Ok(snapshot) => view.update(snapshot),
+                    Err(e) => view.error = Some(e.to_string()),
+                }
+                terminal.draw(|frame| view.draw(frame))?;
+            }
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if is_quit(&key) => break,
+                Some(Ok(Event::Resize(..))) => {
+                    terminal.draw(|frame| view.draw(frame))?;
+                }
+                Some(Err(e)) => return Err(e.into()),
+                None => break,
+                _ => {}
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    key.kind == KeyEventKind::Press
+        && match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => true,
+            KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+            _ => false,
+        }
+}
+
+/// Leaves the alternate screen and raw mode, even when `run` fails
+struct RestoreTerminal;
+
+impl Drop for RestoreTerminal {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = crossterm::execute!(std::io::stdout(), LeaveAlternateScreen, crossterm::cursor::Show);
+    }
+}
+
+/// One poll of the management API
+struct Snapshot {
+    taken: Instant,
+    status: Value,
+    pools: Vec<Value>,
+    healing: Vec<LogRecord>,
+}
+
+async fn fetch(config: &str) -> anyhow::Result<Snapshot> {
+    let status = api_request(config, hyper::Method::GET, "/api/status", None).await?;
+    let pools = api_request(config, hyper::Method::GET, "/api/pools", None).await?;
+
+    let mut healing = Vec::new();
+    api_stream(config, &format!("/api/logs?grep=heal&limit={}", HEALING_LINES), |line| {
+        if let Ok(record) = serde_json::from_str(line) {
+            healing.push(record);
+        }
+    }).await?;
+
+    Ok(Snapshot {
+        taken: Instant::now(),
+        status,
+        pools: pools["pools"].as_array().cloned().unwrap_or_default(),
+        healing,
+    })
+}
+
+/// Requests per second between two snapshots, overall and per site
+#[derive(Default)]
+struct Rates {
+    total: f64,
+    sites: HashMap<String, f64>,
+}
+
+impl Rates {
+    fn between(earlier: &Snapshot, later: &Snapshot) -> Self {
+        let seconds = later.taken.duration_since(earlier.taken).as_secs_f64();
+        if seconds <= 0.0 {
+            return Self::default();
+        }
+        let rate = |before: u64, after: u64| after.saturating_sub(before) as f64 / seconds;
+
+        let before: HashMap<&str, u64> = earlier.pools.iter()
+            .map(|pool| (pool["site_id"].as_str().unwrap_or_default(), pool["requests"].as_u64().unwrap_or(0)))
+            .collect();
+        let sites = later.pools.iter()
+            .filter_map(|pool| {
+                let site_id = pool["site_id"].as_str()?;
+                let requests = pool["requests"].as_u64().unwrap_or(0);
+                Some((site_id.to_string(), rate(*before.get(site_id)?, requests)))
+            })
+            .collect();
+
+        Self {
+            total: rate(
+                earlier.status["requests"]["total"].as_u64().unwrap_or(0),
+                later.status["requests"]["total"].as_u64().unwrap_or(0),
+            ),
+            sites,
+        }
+    }
+}
+
+struct View {
+    interval: Duration,
+    current: Option<Snapshot>,
+    rates: Rates,
+
+    /// Why the last refresh failed; the previous data stays on screen
+    error: Option<String>,
+}
+
+impl View {
+    fn new(interval: Duration) -> Self {
+        Self { interval, current: None, rates: Rates::default(), error: None }
+    }
+
+    fn update(&mut self, snapshot: Snapshot) {
+        if let Some(previous) = &self.current {
+            self.rates = Rates::between(previous, &snapshot);
+        }
+        self.current = Some(snapshot);
+        self.error = None;
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let Some(snapshot) = &self.current else { return };
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(4),
+                Constraint::Percentage(35),
+                Constraint::Min(5),
+                Constraint::Length(HEALING_LINES as u16 + 2),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+
+        self.draw_summary(frame, areas[0], snapshot);
+        self.draw_pools(frame, areas[1], snapshot);
+        draw_cages(frame, areas[2], snapshot);
+        draw_healing(frame, areas[3], snapshot);
+
+        let footer = match &self.error {
+            Some(error) => Line::from(Span::styled(format!(" Refresh failed: {}", error), Style::new().fg(Color::Red))),
+            None => Line::from(Span::styled(
+                format!(" Refreshing every {:.1}s, q to quit", self.interval.as_secs_f64()),
+                Style::new().fg(Color::DarkGray),
+            )),
+        };
+        frame.render_widget(Paragraph::new(footer), areas[4]);
+    }
+
+    fn draw_summary(&self, frame: &mut Frame, area: Rect, snapshot: &Snapshot) {
+        let status = &snapshot.status;
+        let label = |text: &'static str| Span::styled(text, Style::new().fg(Color::Gray));
+        let value = |text: String, color: Color| Span::styled(text, Style::new().fg(color).add_modifier(Modifier::BOLD));
+
+        let lines = vec![
+            Line::from(vec![
+                label("Requests "),
+                value(status["requests"]["total"].as_u64().unwrap_or(0).to_string(), Color::Cyan),
+                label("  Rate "),
+                value(format!("{:.1}/s", self.rates.total), Color::Cyan),
+                label("  Success "),
+                value(format!("{:.1}%", status["requests"]["success_rate"].as_f64().unwrap_or(0.0)), Color::Green),
+                label("  p50 "),
+                value(format_ms(status["latency"]["p50_ms"].as_f64().unwrap_or(0.0)), Color::Yellow),
+                label("  p99 "),
+                value(format_ms(status["latency"]["p99_ms"].as_f64().unwrap_or(0.0)), Color::Yellow),
+            ]),
+            Line::from(vec![
+                label("Uptime "),
+                value(format_uptime(status["uptime_seconds"].as_u64().unwrap_or(0)), Color::White),
+                label("  Pools "),
+                value(status["pools"].as_u64().unwrap_or(0).to_string(), Color::White),
+                label("  Threats "),
+                value(status["threats_detected"].as_u64().unwrap_or(0).to_string(), Color::Red),
+                label("  Healing events "),
+                value(status["healing_events"].as_u64().unwrap_or(0).to_string(), Color::Magenta),
+            ]),
+        ];
+        let block = Block::default().borders(Borders::ALL).title(" 🍐 pear top ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn draw_pools(&self, frame: &mut Frame, area: Rect, snapshot: &Snapshot) {
+        let rows = snapshot.pools.iter().map(|pool| {
+            let site_id = pool["site_id"].as_str().unwrap_or_default();
+            let healthy = pool["health"]["healthy_cages"].as_u64().unwrap_or(0);
+            let total = pool["health"]["total_cages"].as_u64().unwrap_or(0);
+            let health_color = if healthy == 0 { Color::Red } else if healthy < total { Color::Yellow } else { Color::Green };
+            Row::new(vec![
+                Span::raw(site_id.to_string()),
+                Span::styled(format!("{}/{}", healthy, total), Style::new().fg(health_color)),
+                Span::raw(format!("{:.1}", self.rates.sites.get(site_id).copied().unwrap_or(0.0))),
+                Span::raw(pool["errors"].as_u64().unwrap_or(0).to_string()),
+                Span::raw(format_ms(pool["latency"]["p50_ms"].as_f64().unwrap_or(0.0))),
+                Span::raw(format_ms(pool["latency"]["p99_ms"].as_f64().unwrap_or(0.0))),
+                Span::raw(format_bytes(pool["memory_bytes"].as_u64().unwrap_or(0))),
+            ])
+        });
+
+        let table = Table::new(rows, [
+            Constraint::Min(16),
+            Constraint::Length(8),
+            Constraint::Length(9),
+            Constraint::Length(8),
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Length(10),
+        ])
+        .header(header(["Site", "Cages", "Req/s", "Errors", "p50", "p99", "Memory"]))
+        .block(Block::default().borders(Borders::ALL).title(" Pools "));
+        frame.render_widget(table, area);
+    }
+}
+
+fn draw_cages(frame: &mut Frame, area: Rect, snapshot: &Snapshot) {
+    let rows = snapshot.pools.iter().flat_map(|pool| {
+        let site_id = pool["site_id"].as_str().unwrap_or_default().to_string();
+        pool["cages"].as_array().cloned().unwrap_or_default().into_iter().map(move |cage| {
+            let state = cage["state"].as_str().unwrap_or_default().to_string();
+            let color = match (state.as_str(), cage["healthy"].as_bool()) {
+                ("RUN", Some(true)) => Color::Green,
+                ("CRASH", _) => Color::Red,
+                _ => Color::Yellow,
+            };
+            Row::new(vec![
+                Span::raw(site_id.clone()),
+                Span::raw(cage["cage_id"].as_u64().unwrap_or(0).to_string()),
+                Span::styled(state, Style::new().fg(color)),
+                Span::raw(cage["active_requests"].as_u64().unwrap_or(0).to_string()),
+                Span::raw(cage["total_requests"].as_u64().unwrap_or(0).to_string()),
+                Span::raw(format_ms(cage["latency"]["p99_ms"].as_f64().unwrap_or(0.0))),
+                Span::raw(format_bytes(cage["memory_limit_bytes"].as_u64().unwrap_or(0))),
+            ])
+        })
+    });
+
+    let table = Table::new(rows, [
+        Constraint::Min(16),
+        Constraint::Length(6),
+        Constraint::Length(7),
+        Constraint::Length(8),
+        Constraint::Length(10),
+        Constraint::Length(9),
+        Constraint::Length(10),
+    ])
+    .header(header(["Site", "Cage", "State", "Active", "Requests", "p99", "Mem limit"]))
+    .block(Block::default().borders(Borders::ALL).title(" Cages "));
+    frame.render_widget(table, area);
+}
+
+fn draw_healing(frame: &mut Frame, area: Rect, snapshot: &Snapshot) {
+    let lines: Vec<Line> = snapshot.healing.iter()
+        .map(|record| {
+            let time = chrono::DateTime::from_timestamp_millis(record.timestamp)
+                .map(|t| t.format("%H:%M:%S").to_string())
+                .unwrap_or_default();
+            Line::from(vec![
+                Span::styled(time, Style::new().fg(Color::DarkGray)),
+                Span::styled(
+                    record.site.as_ref().map(|site| format!(" [{}]", site)).unwrap_or_default(),
+                    Style::new().fg(Color::Cyan),
+                ),
+                Span::raw(format!(" {}", record.message)),
+            ])
+        })
+        .collect();
+    let block = Block::default().borders(Borders::ALL).title(" Recent healing ");
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn header<const N: usize>(titles: [&'static str; N]) -> Row<'static> {
+    Row::new(titles).style(Style::new().add_modifier(Modifier::BOLD).fg(Color::Gray))
+}
+
+fn format_ms(ms: f64) -> String {
+    if ms >= 1000.0 {
+        format!("{:.2}s", ms / 1000.0)
+    } else {
+        format!("{:.1}ms", ms)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const MB: u64 = 1024 * 1024;
+    if bytes >= 1024 * MB {
+        format!("{:.1} GB", bytes as f64 / (1024 * MB) as f64)
+    } else {
+        format!("{} MB", bytes / MB)
+    }
+}
+
+fn format_uptime(seconds: u64) -> String {
+    match seconds {
+        s if s >= 86400 => format!("{}d {}h", s / 86400, s % 86400 / 3600),
+        s if s >= 3600 => format!("{}h {}m", s / 3600, s % 3600 / 60),
+        s => format!("{}m {}s", s / 60, s % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rates_between_snapshots() {
+        let taken = Instant::now();
+        let snapshot = |offset: u64, total: u64, blog: u64| Snapshot {
+            taken: taken + Duration::from_secs(offset),
+            status: json!({ "requests": { "total": total } }),
+            pools: vec![json!({ "site_id": "blog", "requests": blog })],
+            healing: Vec::new(),
+        };
+
+        let rates = Rates::between(&snapshot(0, 100, 40), &snapshot(2, 300, 100));
+        assert_eq!(rates.total, 100.0);
+        assert_eq!(rates.sites["blog"], 30.0);
+
+        // A restarted daemon's counters go backwards
+        assert_eq!(Rates::between(&snapshot(0, 300, 0), &snapshot(1, 10, 0)).total, 0.0);
+    }
+}
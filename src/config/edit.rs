@@ -0,0 +1,133 @@
+// Configuration Editing
+// `pear set`: changes one value in pear.toml, keeping comments and layout
+
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use super::PearConfig;
+
+/// Set a dotted `key` such as `server.http2_port` in the file at `path`
+///
+/// The file is only rewritten if the result is still a valid configuration.
+pub fn set_value(path: &Path, key: &str, value: &str) -> Result<PearConfig> {
+    let contents = if path.exists() {
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?
+    } else {
+        String::new()
+    };
+    let (updated, config) = set_in(&contents, key, value)?;
+
+    // Write beside the file and rename, so a crash never leaves half a config
+    let tmp = path.with_extension("toml.tmp");
+    std::fs::write(&tmp, updated).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        std::fs::set_permissions(&tmp, metadata.permissions())?;
+    }
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(config)
+}
+
+/// `contents` with `key` set, and the configuration it parses to
+fn set_in(contents: &str, key: &str, value: &str) -> Result<(String, PearConfig)> {
+    let mut doc: DocumentMut = contents.parse().context("Failed to parse configuration file")?;
+    let path = set_key(&mut doc, key, value)?;
+
+    let updated = doc.to_string();
+    let config: PearConfig = toml::from_str(&updated)
+        .with_context(|| format!("{} = {} does not fit the configuration", key, value))?;
+    if !is_known(&config, &path) {
+        bail!("Unknown configuration key '{}'", key);
+    }
+    config.validate()?;
+    Ok((updated, config))
+}
+
+/// Set a dotted `key` in `doc`, creating sections on the way; returns the key's parts
+pub(super) fn set_key<'a>(doc: &mut DocumentMut, key: &'a str, value: &str) -> Result<Vec<&'a str>> {
+    let path: Vec<&str> = key.split('.').map(str::trim).collect();
+    if path.iter().any(|part| part.is_empty()) {
+        bail!("Invalid key '{}' (use section.name, e.g. server.http2_port)", key);
+    }
+    let (name, sections) = path.split_last().expect("split always yields a part");
+
+    let mut table: &mut Table = doc.as_table_mut();
+    for section in sections {
+        let item = table.entry(section).or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        });
+        table = match item {
+            Item::Table(table) => table,
+            _ => bail!("'{}' in '{}' is not a section", section, key),
+        };
+    }
+
+    match table.get_mut(name).and_then(Item::as_value_mut) {
+        Some(existing) => {
+            // Replace in place so the key keeps the comment above it and the value the one after it
+            let mut new_value = parse_value(value, Some(existing));
+            *new_value.decor_mut() = existing.decor().clone();
+            *existing = new_value;
+        }
+        None => {
+            table.insert(name, Item::Value(parse_value(value, None)));
+        }
+    }
+    Ok(path)
+}
+
+/// A TOML literal (number, boolean, array...) or else a plain string
+/// Existing strings stay strings, so `pear set server.user 1000` doesn't turn a name into a number.
+fn parse_value(value: &str, existing: Option<&Value>) -> Value {
+    if existing.is_some_and(Value::is_str) {
+        return Value::from(value);
+    }
+    value.parse::<Value>().unwrap_or_else(|_| Value::from(value))
+}
+
+/// Unknown keys are ignored by the parser, so check the key survives a round trip
+pub(super) fn is_known(config: &PearConfig, path: &[&str]) -> bool {
+    let Ok(mut current) = toml::Value::try_from(config) else {
+        return false;
+    };
+    for part in path {
+        match current.get(part) {
+            Some(next) => current = next.clone(),
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &str = "# Pear Server Configuration\n\n[server]\n# Public port\nhttp2_port = 8080 # dev\nbind_addr = \"0.0.0.0\"\n";
+
+    #[test]
+    fn test_set_keeps_comments() {
+        let (updated, config) = set_in(CONTENTS, "server.http2_port", "80").unwrap();
+        assert_eq!(config.server.http2_port, 80);
+        assert!(updated.contains("# Public port\nhttp2_port = 80 # dev\n"));
+        assert!(updated.starts_with("# Pear Server Configuration\n"));
+
+        let (updated, config) = set_in(&updated, "server.user", "pear").unwrap();
+        assert_eq!(config.server.user.as_deref(), Some("pear"));
+        assert!(updated.contains("user = \"pear\""));
+
+        let (_, config) = set_in(CONTENTS, "dashboard.port", "9100").unwrap();
+        assert_eq!(config.dashboard.port, 9100);
+    }
+
+    #[test]
+    fn test_set_rejects_bad_values() {
+        assert!(set_in(CONTENTS, "server.http2_port", "eighty").is_err());
+        assert!(set_in(CONTENTS, "server.http2_port", "0").is_err());
+        assert!(set_in(CONTENTS, "server.http2_prot", "80").is_err());
+        assert!(set_in(CONTENTS, "server..port", "80").is_err());
+        assert!(set_in(CONTENTS, "server.bind_addr.port", "80").is_err());
+    }
+}
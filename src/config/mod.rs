@@ -2,6 +2,8 @@
 // Handles pear.toml loading, defaults, and validation
 
 pub mod acme;
+pub mod edit;
+pub mod sources;
 
 use serde::{Deserialize, Serialize};
 use std::path::Path;
@@ -25,6 +27,126 @@ pub struct PearConfig {
     
     #[serde(default)]
     pub dashboard: DashboardConfig,
+    
+    #[serde(default)]
+    pub mtls: crate::network::mtls::MtlsConfig,
+    
+    #[serde(default)]
+    pub security: SecurityConfig,
+    
+    #[serde(default)]
+    pub bandwidth: crate::tenancy::bandwidth::BandwidthConfig,
+    
+    #[serde(default)]
+    pub billing: crate::tenancy::billing::BillingConfig,
+    
+    #[serde(default)]
+    pub storage: crate::storage::StorageConfig,
+    
+    #[serde(default)]
+    pub artifacts: crate::storage::artifacts::ArtifactsConfig,
+    
+    #[serde(default)]
+    pub database: crate::storage::database::DatabaseConfig,
+    
+    #[serde(default)]
+    pub scheduler: crate::scheduler::SchedulerConfig,
+    
+    #[serde(default)]
+    pub queue: crate::scheduler::queue::QueueConfig,
+    
+    #[serde(default)]
+    pub pubsub: crate::crdt::pubsub::PubSubConfig,
+    
+    #[serde(default)]
+    pub crdt: crate::crdt::CrdtConfig,
+    
+    #[serde(default)]
+    pub sessions: crate::crdt::session::SessionConfig,
+    
+    #[serde(default)]
+    pub mail: crate::mail::MailConfig,
+    
+    #[serde(default)]
+    pub rewrite: crate::router::rewrite::RewriteConfig,
+    
+    #[serde(default)]
+    pub upstream: crate::router::upstream::UpstreamConfig,
+    
+    #[serde(default)]
+    pub streaming: crate::router::stream::StreamingConfig,
+    
+    #[serde(default)]
+    pub limits: crate::router::limits::LimitsConfig,
+    
+    #[serde(default)]
+    pub acl: crate::router::acl::AclConfig,
+    
+    #[serde(default)]
+    pub admission: crate::router::admission::AdmissionConfig,
+    
+    #[serde(default)]
+    pub pool_health: crate::router::health::HealthChecksConfig,
+    
+    #[serde(default)]
+    pub outlier_detection: crate::router::outlier::OutlierConfig,
+    
+    #[serde(default)]
+    pub retries: crate::router::retry::RetryConfig,
+    
+    #[serde(default)]
+    pub chaos: crate::chaos::ChaosConfig,
+    
+    #[serde(default)]
+    pub determinism: crate::cage::determinism::DeterminismConfig,
+    
+    #[serde(default)]
+    pub crashes: crate::cage::crash::CrashConfig,
+    
+    #[serde(default)]
+    pub profiling: crate::cage::profiling::ProfilingConfig,
+    
+    #[serde(default)]
+    pub compile_cache: crate::cage::compile_cache::CompileCacheConfig,
+    
+    #[serde(default)]
+    pub quic: crate::network::quic_guard::QuicGuardConfig,
+    
+    #[serde(default)]
+    pub metrics_history: crate::observability::history::MetricsHistoryConfig,
+    
+    #[serde(default)]
+    pub guest_logs: crate::observability::guest_logs::GuestLogConfig,
+    
+    #[serde(default)]
+    pub audit: crate::observability::audit::AuditConfig,
+    
+    #[serde(default)]
+    pub rbac: crate::tenancy::auth::RbacConfig,
+    
+    #[serde(default)]
+    pub oidc: crate::tenancy::oidc::OidcConfig,
+    
+    #[serde(default)]
+    pub lockout: crate::tenancy::lockout::LockoutConfig,
+    
+    #[serde(default)]
+    pub notifications: crate::notifications::NotificationConfig,
+    
+    #[serde(default)]
+    pub health: crate::observability::health::HealthConfig,
+    
+    #[serde(default)]
+    pub restore: crate::storage::registry::RestoreConfig,
+    
+    #[serde(default)]
+    pub kubernetes: crate::runtime::kubernetes::KubernetesConfig,
+    
+    #[serde(default)]
+    pub deployment: crate::deployment::DeploymentConfig,
+
+    #[serde(default)]
+    pub runtime: crate::runtime::RuntimeConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,8 +157,57 @@ pub struct ServerConfig {
     #[serde(default = "default_http3_port")]
     pub http3_port: u16,
     
+    /// "::" serves IPv4 and IPv6 clients from one dual-stack socket
     #[serde(default = "default_bind_addr")]
     pub bind_addr: String,
+    
+    /// Explicit listeners; when set, the ports and bind address above are ignored
+    #[serde(default)]
+    pub listeners: Vec<crate::network::ListenerConfig>,
+    
+    /// Listener shards per protocol (0 = one per CPU core)
+    #[serde(default)]
+    pub acceptor_shards: usize,
+    
+    /// UDP sockets per HTTP/3 listener, each with its own QUIC endpoint (0 = `acceptor_shards`)
+    #[serde(default)]
+    pub http3_acceptor_shards: usize,
+    
+    /// I/O backend for the HTTP/2 listener ("epoll" or "uring")
+    #[serde(default)]
+    pub io_backend: crate::network::IoBackend,
+    
+    /// PID file used by `pear upgrade` to find the running daemon
+    #[serde(default = "default_pid_file")]
+    pub pid_file: String,
+    
+    /// Seconds to wait for in-flight connections on shutdown or upgrade
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout_secs: u64,
+    
+    /// Master key file for site secrets, generated on first start (overridden by PEAR_SECRET_KEY)
+    #[serde(default = "default_secret_key_file")]
+    pub secret_key_file: String,
+    
+    /// Unprivileged user to switch to after binding sockets as root
+    #[serde(default)]
+    pub user: Option<String>,
+    
+    /// Group to switch to (defaults to the user's primary group)
+    #[serde(default)]
+    pub group: Option<String>,
+    
+    /// Allow serving traffic as root when no user is configured
+    #[serde(default)]
+    pub allow_root: bool,
+    
+    /// Load balancer CIDRs whose X-Forwarded-For / Forwarded headers name the real client
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    
+    /// Serve the built-in demonstration module as `default-site`
+    #[serde(default)]
+    pub demo_mode: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +220,22 @@ pub struct SslConfig {
     
     #[serde(default)]
     pub domains: Vec<String>,
+    
+    /// PEM certificate chain for TLS listeners
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    
+    /// PEM private key matching `cert_path`
+    #[serde(default)]
+    pub key_path: Option<String>,
+    
+    /// Let TLS and QUIC clients resume sessions with tickets sealed under keys every listener shares
+    #[serde(default = "default_true")]
+    pub session_tickets: bool,
+    
+    /// Seconds between ticket key rotations; tickets resume for at least this long
+    #[serde(default = "default_ticket_rotation_secs")]
+    pub ticket_rotation_secs: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,6 +248,60 @@ pub struct CagesConfig {
     
     #[serde(default = "default_cpu_timeout")]
     pub cpu_timeout_ms: u64,
+    
+    /// Guest calls run at once, off the async workers (0 = one per reserved Wasm core, or per CPU core)
+    #[serde(default)]
+    pub execution_threads: usize,
+    
+    /// Calls allowed to wait for a free slot before requests are shed with 503
+    #[serde(default = "default_execution_queue_depth")]
+    pub execution_queue_depth: usize,
+    
+    /// Requests each Cage runs at once
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    
+    /// Requests that may wait for a Cage while all of a site's are at `max_concurrent_requests`
+    #[serde(default = "crate::cage::request_queue::default_queue_depth")]
+    pub request_queue_depth: usize,
+    
+    /// Milliseconds a request waits for a free Cage before it is shed with 503
+    #[serde(default = "crate::cage::request_queue::default_queue_timeout_ms")]
+    pub request_queue_timeout_ms: u64,
+    
+    /// Warm Cages per site kept out of load balancing, promoted when one crashes
+    #[serde(default)]
+    pub standby_replicas: usize,
+    
+    /// When pools start their Cages: "eager", "lazy" or "scale-to-zero"
+    #[serde(default)]
+    pub start: crate::cage::config::StartPolicy,
+    
+    /// Seconds without requests before a scale-to-zero pool stops its Cages
+    #[serde(default = "crate::cage::config::default_idle_timeout")]
+    pub idle_timeout_secs: u64,
+    
+    /// Per-site overrides of `start` and `idle_timeout_secs`, keyed by site ID
+    #[serde(default)]
+    pub sites: std::collections::HashMap<String, SiteStart>,
+}
+
+/// How one site's pool starts, where it differs from `[cages]`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteStart {
+    pub start: Option<crate::cage::config::StartPolicy>,
+    pub idle_timeout_secs: Option<u64>,
+}
+
+impl CagesConfig {
+    /// Start policy and idle timeout of `site_id`
+    pub fn start_of(&self, site_id: &str) -> (crate::cage::config::StartPolicy, u64) {
+        let site = self.sites.get(site_id);
+        (
+            site.and_then(|site| site.start).unwrap_or(self.start),
+            site.and_then(|site| site.idle_timeout_secs).unwrap_or(self.idle_timeout_secs),
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,6 +314,18 @@ pub struct AiConfig {
     
     #[serde(default = "default_sample_rate")]
     pub sample_rate: f64,
+    
+    /// File the trained anomaly model is persisted to ("" = keep in memory only)
+    #[serde(default = "default_model_path")]
+    pub model_path: String,
+    
+    /// Block unsafe requests, or only log them ("block" or "monitor")
+    #[serde(default)]
+    pub mode: crate::ai::policy::EnforcementMode,
+    
+    /// Per-site overrides of the settings above, keyed by site ID
+    #[serde(default)]
+    pub sites: std::collections::HashMap<String, crate::ai::policy::AiPolicy>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,6 +335,66 @@ pub struct DashboardConfig {
     
     #[serde(default = "default_true")]
     pub enabled: bool,
+    
+    /// Bearer token required to manage tenants and sites over the API (empty = management disabled)
+    #[serde(default)]
+    pub admin_token: String,
+    
+    /// Directory whose files replace the embedded dashboard assets (empty = embedded only)
+    #[serde(default)]
+    pub assets_dir: String,
+}
+
+/// WAF rules and sensitive paths, globally and per site
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    /// Enable built-in SQL injection / XSS signatures
+    #[serde(default = "default_true")]
+    pub waf_signatures: bool,
+    
+    /// Sensitive-path 404s from one IP before it is banned
+    #[serde(default = "default_scan_ban_threshold")]
+    pub scan_ban_threshold: usize,
+    
+    /// Seconds a scan ban lasts (0 = until manually lifted)
+    #[serde(default = "default_ban_ttl")]
+    pub ban_ttl_secs: u64,
+    
+    /// CIDR ranges never banned or rate limited (e.g. monitoring, office networks)
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+    
+    /// Sustained requests per second allowed per IP (0 = no rate limiting)
+    #[serde(default)]
+    pub rate_limit_rps: usize,
+    
+    /// Burst size above the sustained rate (0 = twice the rate)
+    #[serde(default)]
+    pub rate_limit_burst: usize,
+    
+    /// Seconds an IP is banned after persistently exceeding the rate limit
+    #[serde(default = "default_rate_limit_ban")]
+    pub rate_limit_ban_secs: u64,
+    
+    /// How blocked clients are answered
+    #[serde(default)]
+    pub block_response: crate::ai::challenge::BlockResponseConfig,
+    
+    /// Global rules and sensitive paths
+    #[serde(flatten)]
+    pub global: crate::ai::waf::RuleSetConfig,
+    
+    /// Per-site rules, keyed by site ID
+    #[serde(default)]
+    pub sites: std::collections::HashMap<String, crate::ai::waf::RuleSetConfig>,
+    
+    /// Security event log and alert hooks
+    #[serde(default)]
+    pub events: crate::ai::events::EventLogConfig,
+    
+    /// HSTS, nosniff, frame and CSP headers added to site responses
+    #[serde(default)]
+    pub headers: crate::router::headers::HeadersConfig,
 }
 
 // Default value functions
@@ -89,12 +402,88 @@ fn default_http2_port() -> u16 { 8080 }
 fn default_http3_port() -> u16 { 8443 }
 fn default_dashboard_port() -> u16 { 9000 }
 fn default_bind_addr() -> String { "0.0.0.0".to_string() }
+fn default_pid_file() -> String { "pear.pid".to_string() }
+fn default_drain_timeout() -> u64 { 30 }
+fn default_secret_key_file() -> String { "pear-secret.key".to_string() }
 fn default_replicas() -> usize { 3 }
 fn default_memory_limit() -> usize { 128 }
 fn default_cpu_timeout() -> u64 { 1000 }
+fn default_execution_queue_depth() -> usize { crate::cage::executor::DEFAULT_QUEUE_DEPTH }
+fn default_max_concurrent_requests() -> usize { 100 }
+fn default_ticket_rotation_secs() -> u64 { 3600 }
 fn default_threshold() -> f64 { 0.8 }
 fn default_sample_rate() -> f64 { 0.1 }
+fn default_model_path() -> String { "anomaly-model.json".to_string() }
 fn default_true() -> bool { true }
+fn default_scan_ban_threshold() -> usize { 5 }
+fn default_ban_ttl() -> u64 { 3600 }
+fn default_rate_limit_ban() -> u64 { 600 }
+
+impl ServerConfig {
+    /// Listeners to bind: `listeners` if set, else HTTP/2 and HTTP/3 on the legacy ports
+    pub fn effective_listeners(&self) -> Vec<crate::network::ListenerConfig> {
+        use crate::network::{ListenerConfig, ListenerProtocol};
+        
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+        vec![
+            ListenerConfig {
+                name: None,
+                address: self.bind_addr.clone(),
+                port: self.http2_port,
+                protocol: ListenerProtocol::Http2,
+                tls: false,
+                ipv6_only: false,
+                proxy_protocol: false,
+                redirect_https: false,
+                https_port: 443,
+            },
+            ListenerConfig {
+                name: None,
+                address: self.bind_addr.clone(),
+                port: self.http3_port,
+                protocol: ListenerProtocol::Http3,
+                tls: true,
+                ipv6_only: false,
+                proxy_protocol: false,
+                redirect_https: false,
+                https_port: 443,
+            },
+        ]
+    }
+    
+    /// Check every listener binds a distinct, valid socket
+    fn validate_listeners(&self) -> Result<()> {
+        use crate::network::ListenerProtocol;
+        
+        let mut bound = std::collections::HashSet::new();
+        for listener in self.effective_listeners() {
+            let addr = listener.socket_addr()?;
+            if listener.port == 0 {
+                anyhow::bail!("Listener {} has port 0", listener.label());
+            }
+            if listener.ipv6_only && addr.is_ipv4() {
+                anyhow::bail!("Listener {} sets ipv6_only on an IPv4 address", listener.label());
+            }
+            if listener.protocol == ListenerProtocol::Http3 && !listener.tls {
+                anyhow::bail!("Listener {} serves HTTP/3, which requires tls = true", listener.label());
+            }
+            if listener.proxy_protocol && listener.protocol != ListenerProtocol::Http2 {
+                anyhow::bail!("Listener {} expects PROXY protocol headers, which only TCP (http2) listeners support", listener.label());
+            }
+            if listener.redirect_https && (listener.protocol != ListenerProtocol::Http2 || listener.tls) {
+                anyhow::bail!("Listener {} redirects to HTTPS, which requires plain HTTP/2 without tls", listener.label());
+            }
+            
+            // HTTP/2 and HTTP/3 may share a port number (TCP vs UDP)
+            if !bound.insert((addr, listener.protocol)) {
+                anyhow::bail!("More than one listener binds {:?} on {}", listener.protocol, addr);
+            }
+        }
+        Ok(())
+    }
+}
 
 impl Default for ServerConfig {
     fn default() -> Self {
@@ -102,6 +491,37 @@ impl Default for ServerConfig {
             http2_port: default_http2_port(),
             http3_port: default_http3_port(),
             bind_addr: default_bind_addr(),
+            listeners: Vec::new(),
+            acceptor_shards: 0,
+            http3_acceptor_shards: 0,
+            io_backend: crate::network::IoBackend::default(),
+            pid_file: default_pid_file(),
+            drain_timeout_secs: default_drain_timeout(),
+            secret_key_file: default_secret_key_file(),
+            user: None,
+            group: None,
+            allow_root: false,
+            trusted_proxies: Vec::new(),
+            demo_mode: false,
+        }
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            waf_signatures: true,
+            scan_ban_threshold: default_scan_ban_threshold(),
+            ban_ttl_secs: default_ban_ttl(),
+            allowlist: Vec::new(),
+            rate_limit_rps: 0,
+            rate_limit_burst: 0,
+            rate_limit_ban_secs: default_rate_limit_ban(),
+            block_response: crate::ai::challenge::BlockResponseConfig::default(),
+            events: crate::ai::events::EventLogConfig::default(),
+            headers: crate::router::headers::HeadersConfig::default(),
+            global: crate::ai::waf::RuleSetConfig::default(),
+            sites: std::collections::HashMap::new(),
         }
     }
 }
@@ -112,6 +532,10 @@ impl Default for SslConfig {
             auto_cert: false,
             email: None,
             domains: Vec::new(),
+            cert_path: None,
+            key_path: None,
+            session_tickets: true,
+            ticket_rotation_secs: default_ticket_rotation_secs(),
         }
     }
 }
@@ -122,6 +546,15 @@ impl Default for CagesConfig {
             default_replicas: default_replicas(),
             memory_limit_mb: default_memory_limit(),
             cpu_timeout_ms: default_cpu_timeout(),
+            execution_threads: 0,
+            execution_queue_depth: default_execution_queue_depth(),
+            max_concurrent_requests: default_max_concurrent_requests(),
+            request_queue_depth: crate::cage::request_queue::default_queue_depth(),
+            request_queue_timeout_ms: crate::cage::request_queue::default_queue_timeout_ms(),
+            standby_replicas: 0,
+            start: Default::default(),
+            idle_timeout_secs: crate::cage::config::default_idle_timeout(),
+            sites: Default::default(),
         }
     }
 }
@@ -132,6 +565,9 @@ impl Default for AiConfig {
             enable_anomaly_detection: default_true(),
             anomaly_threshold: default_threshold(),
             sample_rate: default_sample_rate(),
+            model_path: default_model_path(),
+            mode: crate::ai::policy::EnforcementMode::default(),
+            sites: std::collections::HashMap::new(),
         }
     }
 }
@@ -141,6 +577,8 @@ impl Default for DashboardConfig {
         Self {
             port: default_dashboard_port(),
             enabled: default_true(),
+            admin_token: String::new(),
+            assets_dir: String::new(),
         }
     }
 }
@@ -153,30 +591,74 @@ impl Default for PearConfig {
             cages: CagesConfig::default(),
             ai: AiConfig::default(),
             dashboard: DashboardConfig::default(),
+            mtls: crate::network::mtls::MtlsConfig::default(),
+            security: SecurityConfig::default(),
+            bandwidth: crate::tenancy::bandwidth::BandwidthConfig::default(),
+            billing: crate::tenancy::billing::BillingConfig::default(),
+            storage: crate::storage::StorageConfig::default(),
+            artifacts: crate::storage::artifacts::ArtifactsConfig::default(),
+            database: crate::storage::database::DatabaseConfig::default(),
+            scheduler: crate::scheduler::SchedulerConfig::default(),
+            queue: crate::scheduler::queue::QueueConfig::default(),
+            pubsub: crate::crdt::pubsub::PubSubConfig::default(),
+            crdt: crate::crdt::CrdtConfig::default(),
+            sessions: crate::crdt::session::SessionConfig::default(),
+            mail: crate::mail::MailConfig::default(),
+            rewrite: crate::router::rewrite::RewriteConfig::default(),
+            upstream: crate::router::upstream::UpstreamConfig::default(),
+            streaming: crate::router::stream::StreamingConfig::default(),
+            limits: crate::router::limits::LimitsConfig::default(),
+            acl: crate::router::acl::AclConfig::default(),
+            admission: crate::router::admission::AdmissionConfig::default(),
+            pool_health: crate::router::health::HealthChecksConfig::default(),
+            outlier_detection: crate::router::outlier::OutlierConfig::default(),
+            retries: crate::router::retry::RetryConfig::default(),
+            chaos: crate::chaos::ChaosConfig::default(),
+            determinism: crate::cage::determinism::DeterminismConfig::default(),
+            crashes: crate::cage::crash::CrashConfig::default(),
+            profiling: crate::cage::profiling::ProfilingConfig::default(),
+            compile_cache: crate::cage::compile_cache::CompileCacheConfig::default(),
+            quic: crate::network::quic_guard::QuicGuardConfig::default(),
+            metrics_history: crate::observability::history::MetricsHistoryConfig::default(),
+            guest_logs: crate::observability::guest_logs::GuestLogConfig::default(),
+            audit: crate::observability::audit::AuditConfig::default(),
+            rbac: crate::tenancy::auth::RbacConfig::default(),
+            oidc: crate::tenancy::oidc::OidcConfig::default(),
+            lockout: crate::tenancy::lockout::LockoutConfig::default(),
+            notifications: crate::notifications::NotificationConfig::default(),
+            health: crate::observability::health::HealthConfig::default(),
+            restore: crate::storage::registry::RestoreConfig::default(),
+            kubernetes: crate::runtime::kubernetes::KubernetesConfig::default(),
+            deployment: crate::deployment::DeploymentConfig::default(),
+            runtime: crate::runtime::RuntimeConfig::default(),
         }
     }
 }
 
 impl PearConfig {
-    /// Load configuration from file or use defaults
+    /// Load configuration from a file or a directory of them, with `PEAR__` environment overrides
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
+        let overrides = sources::env_overrides(std::env::vars());
         
-        if path.exists() {
+        let contents = if path.exists() {
             info!("Loading configuration from {}", path.display());
-            let contents = std::fs::read_to_string(path)
-                .context("Failed to read configuration file")?;
-            
-            let config: PearConfig = toml::from_str(&contents)
-                .context("Failed to parse configuration file")?;
-            
-            config.validate()?;
-            Ok(config)
-        } else {
+            sources::read(path)?
+        } else if overrides.is_empty() {
             warn!("Configuration file not found, using defaults");
             info!("Create pear.toml to customize configuration");
-            Ok(Self::default())
+            return Ok(Self::default());
+        } else {
+            warn!("Configuration file not found, using defaults and environment overrides");
+            String::new()
+        };
+        
+        // Keys only: values can be tokens
+        if !overrides.is_empty() {
+            let keys: Vec<&str> = overrides.iter().map(|(key, _)| key.as_str()).collect();
+            info!(keys = ?keys, "Applying configuration from the environment");
         }
+        sources::parse(&contents, &overrides)
     }
 
     /// Validate configuration
@@ -193,6 +675,15 @@ impl PearConfig {
         if self.dashboard.port == 0 {
             anyhow::bail!("Dashboard port cannot be 0");
         }
+        self.mtls.validate().context("Invalid [mtls] config")?;
+        
+        self.server.validate_listeners().context("Invalid [[server.listeners]]")?;
+        crate::router::forwarded::TrustedProxies::parse(&self.server.trusted_proxies)
+            .context("Invalid server.trusted_proxies")?;
+        
+        if self.server.group.is_some() && self.server.user.is_none() {
+            anyhow::bail!("server.group requires server.user to be set");
+        }
         
         // Validate Cage config
         if self.cages.default_replicas == 0 {
@@ -203,6 +694,15 @@ impl PearConfig {
             anyhow::bail!("Memory limit must be at least 16MB");
         }
         
+        if self.cages.max_concurrent_requests == 0 {
+            anyhow::bail!("Max concurrent requests must be at least 1");
+        }
+        
+        let site_timeouts = self.cages.sites.values().filter_map(|site| site.idle_timeout_secs);
+        if std::iter::once(self.cages.idle_timeout_secs).chain(site_timeouts).any(|secs| secs == 0) {
+            anyhow::bail!("Cage idle timeouts must be at least 1 second");
+        }
+        
         // Validate AI config
         if self.ai.anomaly_threshold < 0.0 || self.ai.anomaly_threshold > 1.0 {
             anyhow::bail!("Anomaly threshold must be between 0.0 and 1.0");
@@ -212,6 +712,72 @@ impl PearConfig {
             anyhow::bail!("Sample rate must be between 0.0 and 1.0");
         }
         
+        for (site_id, policy) in &self.ai.sites {
+            policy.validate()
+                .with_context(|| format!("Invalid [ai.sites.{}] policy", site_id))?;
+        }
+        
+        crate::ai::allowlist::IpAllowlist::parse(&self.security.allowlist)
+            .context("Invalid [security] allowlist")?;
+        
+        if self.security.block_response.mode == crate::ai::challenge::BlockMode::Challenge
+            && self.security.block_response.challenge_ttl_secs == 0
+        {
+            anyhow::bail!("security.block_response.challenge_ttl_secs must be at least 1");
+        }
+        
+        for hook in &self.security.events.alerts {
+            hook.validate().context("Invalid [[security.events.alerts]] hook")?;
+        }
+        
+        // Validate WAF rules compile
+        crate::ai::waf::validate_rules(&self.security.global)
+            .context("Invalid [security] rules")?;
+        for (site_id, rules) in &self.security.sites {
+            crate::ai::waf::validate_rules(rules)
+                .with_context(|| format!("Invalid [security.sites.{}] rules", site_id))?;
+        }
+        
+        self.security.headers.validate().context("Invalid [security.headers] policy")?;
+        crate::router::rewrite::RewriteEngine::new(&self.rewrite).context("Invalid [rewrite] rules")?;
+        crate::router::upstream::UpstreamProxy::new(&self.upstream).context("Invalid [upstream] config")?;
+        self.streaming.validate().context("Invalid [streaming] config")?;
+        self.storage.validate().context("Invalid [storage] config")?;
+        self.artifacts.validate().context("Invalid [artifacts] config")?;
+        self.limits.validate().context("Invalid [limits] config")?;
+        self.admission.validate().context("Invalid [admission] config")?;
+        self.pool_health.validate().context("Invalid [pool_health] config")?;
+        self.outlier_detection.validate().context("Invalid [outlier_detection] config")?;
+        self.retries.validate().context("Invalid [retries] config")?;
+        self.chaos.validate().context("Invalid [chaos] config")?;
+        self.determinism.validate().context("Invalid [determinism] config")?;
+        self.crashes.validate().context("Invalid [crashes] config")?;
+        self.profiling.validate().context("Invalid [profiling] config")?;
+        self.compile_cache.validate().context("Invalid [compile_cache] config")?;
+        self.quic.validate().context("Invalid [quic] config")?;
+        crate::router::acl::AccessControl::new(&self.acl).context("Invalid [acl] rules")?;
+        
+        self.bandwidth.validate().context("Invalid [bandwidth] config")?;
+        self.billing.validate().context("Invalid [billing] config")?;
+        self.metrics_history.validate().context("Invalid [metrics_history] config")?;
+        self.guest_logs.validate().context("Invalid [guest_logs] config")?;
+        self.audit.validate().context("Invalid [audit] config")?;
+        self.rbac.validate().context("Invalid [rbac] config")?;
+        self.oidc.validate().context("Invalid [oidc] config")?;
+        self.lockout.validate().context("Invalid [lockout] config")?;
+        self.notifications.validate().context("Invalid [notifications] config")?;
+        self.restore.validate().context("Invalid [restore] config")?;
+        self.database.validate().context("Invalid [database] config")?;
+        self.scheduler.validate().context("Invalid [scheduler] config")?;
+        self.queue.validate().context("Invalid [queue] config")?;
+        self.pubsub.validate().context("Invalid [pubsub] config")?;
+        self.crdt.validate().context("Invalid [crdt] config")?;
+        self.sessions.validate().context("Invalid [sessions] config")?;
+        self.mail.validate().context("Invalid [mail] config")?;
+        self.deployment.validate().context("Invalid [deployment] config")?;
+        self.runtime.validate().context("Invalid [runtime] config")?;
+        self.kubernetes.validate().context("Invalid [kubernetes] config")?;
+        
         // Validate SSL config
         if self.ssl.auto_cert {
             if self.ssl.email.is_none() {
@@ -223,8 +789,81 @@ impl PearConfig {
             }
         }
         
+        if self.ssl.cert_path.is_some() != self.ssl.key_path.is_some() {
+            anyhow::bail!("ssl.cert_path and ssl.key_path must be set together");
+        }
+        
+        // TLS 1.3 caps ticket lifetimes at a week
+        if self.ssl.session_tickets && !(1..=604_800).contains(&self.ssl.ticket_rotation_secs) {
+            anyhow::bail!("ssl.ticket_rotation_secs must be between 1 and 604800");
+        }
+        
         Ok(())
     }
+    
+    /// Problems that span sections: sockets that would collide and quotas that contradict each other
+    /// `validate` checks each section on its own; `pear validate` also runs these.
+    pub fn cross_check(&self) -> Vec<String> {
+        use crate::network::ListenerProtocol;
+        
+        let mut problems = Vec::new();
+        
+        // A wildcard address overlaps every specific address on the same port, and "::" every IPv4 one
+        let mut sockets: Vec<(String, ListenerProtocol, Option<std::net::SocketAddr>, bool)> = self.server.effective_listeners()
+            .iter()
+            .map(|listener| (listener.label(), listener.protocol, listener.socket_addr().ok(), listener.ipv6_only))
+            .collect();
+        if self.dashboard.enabled {
+            let dashboard = std::net::SocketAddr::from(([0, 0, 0, 0], self.dashboard.port));
+            sockets.push(("dashboard".to_string(), ListenerProtocol::Http2, Some(dashboard), false));
+        }
+        for (i, (label, protocol, addr, ipv6_only)) in sockets.iter().enumerate() {
+            for (other_label, other_protocol, other_addr, other_ipv6_only) in &sockets[i + 1..] {
+                let (Some(addr), Some(other_addr)) = (addr, other_addr) else { continue };
+                if protocol == other_protocol && crate::network::config::sockets_overlap(*addr, *ipv6_only, *other_addr, *other_ipv6_only) {
+                    problems.push(format!("{} and {} both bind port {} ({} and {})", label, other_label, addr.port(), addr, other_addr));
+                }
+            }
+        }
+        
+        let bandwidth = &self.bandwidth;
+        let mut quotas = vec![("bandwidth.default_quota".to_string(), bandwidth.default_quota)];
+        quotas.extend(bandwidth.sites.iter().map(|(site, config)| (format!("bandwidth.sites.{}", site), config.quota)));
+        quotas.extend(bandwidth.tenants.iter().map(|(tenant, quota)| (format!("bandwidth.tenants.{}", tenant), *quota)));
+        for (name, quota) in &quotas {
+            if quota.daily_gb == Some(0) || quota.monthly_gb == Some(0) {
+                problems.push(format!("{} has a zero quota, which blocks all traffic", name));
+            }
+            if let (Some(daily), Some(monthly)) = (quota.daily_gb, quota.monthly_gb) {
+                if daily > monthly {
+                    problems.push(format!("{}.daily_gb ({}) is larger than monthly_gb ({})", name, daily, monthly));
+                }
+            }
+        }
+        for (site, config) in &bandwidth.sites {
+            let Some(tenant) = &config.tenant else { continue };
+            let Some(tenant_quota) = bandwidth.tenants.get(tenant) else { continue };
+            for (period, site_limit, tenant_limit) in [
+                ("daily_gb", config.quota.daily_gb, tenant_quota.daily_gb),
+                ("monthly_gb", config.quota.monthly_gb, tenant_quota.monthly_gb),
+            ] {
+                if let (Some(site_limit), Some(tenant_limit)) = (site_limit, tenant_limit) {
+                    if site_limit > tenant_limit {
+                        problems.push(format!(
+                            "bandwidth.sites.{}.{} ({}) exceeds tenant {}'s {} ({}) and can never be reached",
+                            site, period, site_limit, tenant, period, tenant_limit
+                        ));
+                    }
+                }
+            }
+        }
+        
+        if self.mail.enabled && self.mail.daily_quota == 0 {
+            problems.push("mail.daily_quota is 0, so no tenant can send mail".to_string());
+        }
+        
+        problems
+    }
 }
 
 #[cfg(test)]
@@ -239,6 +878,240 @@ mod tests {
         assert!(config.validate().is_ok());
     }
 
+    #[test]
+    fn test_cross_check() {
+        assert!(PearConfig::default().cross_check().is_empty());
+        
+        let config: PearConfig = toml::from_str(concat!(
+            "[dashboard]\nport = 8080\n",
+            "[bandwidth.default_quota]\ndaily_gb = 50\nmonthly_gb = 10\n",
+            "[bandwidth.tenants.acme]\nmonthly_gb = 100\n",
+            "[bandwidth.sites.blog]\ntenant = \"acme\"\nmonthly_gb = 500\n",
+        )).unwrap();
+        config.validate().unwrap();
+        let problems = config.cross_check();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].contains("dashboard both bind port 8080"));
+        assert!(problems[1].contains("daily_gb (50) is larger than monthly_gb (10)"));
+        assert!(problems[2].contains("can never be reached"));
+    }
+    
+    #[test]
+    fn test_io_backend_parsing() {
+        let config: PearConfig = toml::from_str("[server]\nio_backend = \"uring\"\n").unwrap();
+        assert_eq!(config.server.io_backend, crate::network::IoBackend::Uring);
+        assert_eq!(PearConfig::default().server.io_backend, crate::network::IoBackend::Epoll);
+    }
+
+    #[test]
+    fn test_listeners_parsing() {
+        let toml = r#"
+            [[server.listeners]]
+            name = "redirect"
+            port = 80
+            redirect_https = true
+
+            [[server.listeners]]
+            port = 443
+            tls = true
+
+            [[server.listeners]]
+            port = 443
+            protocol = "http3"
+            tls = true
+
+            [[server.listeners]]
+            name = "internal"
+            address = "127.0.0.1"
+            port = 8081
+        "#;
+        let config: PearConfig = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        let listeners = config.server.effective_listeners();
+        assert_eq!(listeners.len(), 4);
+        assert_eq!(listeners[2].protocol, crate::network::ListenerProtocol::Http3);
+        assert_eq!(listeners[3].socket_addr().unwrap().to_string(), "127.0.0.1:8081");
+        assert!(listeners[0].redirect_https);
+        assert_eq!(listeners[0].https_port, 443);
+
+        let mut duplicate = config.clone();
+        duplicate.server.listeners[0].port = 443;
+        assert!(duplicate.validate().is_err());
+
+        let mut plain_quic = config;
+        plain_quic.server.listeners[2].tls = false;
+        assert!(plain_quic.validate().is_err());
+        
+        let mut tls_redirect = plain_quic;
+        tls_redirect.server.listeners[2].tls = true;
+        tls_redirect.server.listeners[1].redirect_https = true;
+        assert!(tls_redirect.validate().is_err());
+
+        assert_eq!(PearConfig::default().server.effective_listeners().len(), 2);
+    }
+
+    #[test]
+    fn test_dual_stack_listeners() {
+        let toml = r#"
+            [[server.listeners]]
+            port = 443
+            tls = true
+
+            [[server.listeners]]
+            address = "::"
+            port = 443
+            tls = true
+            ipv6_only = true
+        "#;
+        let config: PearConfig = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        assert!(config.cross_check().is_empty());
+
+        let mut dual_stack = config.clone();
+        dual_stack.server.listeners[1].ipv6_only = false;
+        assert_eq!(dual_stack.cross_check().len(), 1);
+
+        let mut ipv4_only = config;
+        ipv4_only.server.listeners[0].ipv6_only = true;
+        assert!(ipv4_only.validate().is_err());
+    }
+
+    #[test]
+    fn test_proxy_settings_validation() {
+        let config: PearConfig = toml::from_str(concat!(
+            "[server]\ntrusted_proxies = [\"10.0.0.0/8\", \"fd00::/8\"]\n",
+            "[[server.listeners]]\nport = 443\ntls = true\nproxy_protocol = true\n",
+        )).unwrap();
+        config.validate().unwrap();
+
+        let mut quic = config.clone();
+        quic.server.listeners[0].protocol = crate::network::ListenerProtocol::Http3;
+        assert!(quic.validate().is_err());
+
+        let mut bad_cidr = config;
+        bad_cidr.server.trusted_proxies.push("10.0.0.0/33".to_string());
+        assert!(bad_cidr.validate().is_err());
+    }
+
+    #[test]
+    fn test_group_requires_user() {
+        let mut config = PearConfig::default();
+        config.server.group = Some("pear".to_string());
+        assert!(config.validate().is_err());
+        
+        config.server.user = Some("pear".to_string());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_security_rules_parsing() {
+        let toml = r#"
+            [security]
+            sensitive_paths = ["/internal"]
+
+            [[security.rules]]
+            name = "block-actuator"
+            pattern = "^/actuator"
+            target = "path"
+
+            [[security.sites.shop.rules]]
+            name = "allow-editor"
+            pattern = "^/editor/"
+            action = "allow"
+        "#;
+        let config: PearConfig = toml::from_str(toml).unwrap();
+        
+        assert_eq!(config.security.global.sensitive_paths, vec!["/internal"]);
+        assert_eq!(config.security.global.rules[0].action, crate::ai::waf::RuleAction::Block);
+        assert_eq!(config.security.sites["shop"].rules[0].action, crate::ai::waf::RuleAction::Allow);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_security_allowlist_validation() {
+        let config: PearConfig = toml::from_str(
+            "[security]\nban_ttl_secs = 600\nallowlist = [\"10.0.0.0/8\", \"::1\"]\n"
+        ).unwrap();
+        assert_eq!(config.security.ban_ttl_secs, 600);
+        assert!(config.validate().is_ok());
+        
+        let mut config = PearConfig::default();
+        config.security.allowlist = vec!["10.0.0.0/40".to_string()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_block_response_parsing() {
+        let toml = r#"
+            [security]
+            rate_limit_rps = 50
+
+            [security.block_response]
+            mode = "challenge"
+            brand = "Example Hosting"
+        "#;
+        let config: PearConfig = toml::from_str(toml).unwrap();
+        
+        assert_eq!(config.security.rate_limit_rps, 50);
+        assert_eq!(config.security.rate_limit_ban_secs, 600);
+        assert_eq!(config.security.block_response.mode, crate::ai::challenge::BlockMode::Challenge);
+        assert_eq!(config.security.block_response.challenge_ttl_secs, 3600);
+        assert!(config.validate().is_ok());
+        
+        let mut config = config;
+        config.security.block_response.challenge_ttl_secs = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_bandwidth_parsing() {
+        let toml = r#"
+            [bandwidth.default_quota]
+            monthly_gb = 100
+
+            [bandwidth.sites.blog]
+            tenant = "acme"
+            daily_gb = 5
+
+            [bandwidth.tenants.acme]
+            monthly_gb = 500
+        "#;
+        let config: PearConfig = toml::from_str(toml).unwrap();
+        
+        assert_eq!(config.bandwidth.warn_percent, 80);
+        assert_eq!(config.bandwidth.default_quota.monthly_gb, Some(100));
+        assert_eq!(config.bandwidth.sites["blog"].tenant.as_deref(), Some("acme"));
+        assert_eq!(config.bandwidth.sites["blog"].quota.daily_gb, Some(5));
+        assert_eq!(config.bandwidth.tenants["acme"].monthly_gb, Some(500));
+        assert!(config.validate().is_ok());
+        
+        let mut config = config;
+        config.bandwidth.warn_percent = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_site_ai_policy_parsing() {
+        let toml = r#"
+            [ai.sites.api]
+            enable_anomaly_detection = false
+
+            [ai.sites.wordpress]
+            sample_rate = 1.0
+            anomaly_threshold = 0.6
+        "#;
+        let config: PearConfig = toml::from_str(toml).unwrap();
+        
+        assert_eq!(config.ai.mode, crate::ai::policy::EnforcementMode::Block);
+        assert_eq!(config.ai.sites["api"].enable_anomaly_detection, Some(false));
+        assert_eq!(config.ai.sites["wordpress"].sample_rate, Some(1.0));
+        assert!(config.validate().is_ok());
+        
+        let mut config = config;
+        config.ai.sites.get_mut("wordpress").unwrap().anomaly_threshold = Some(3.0);
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_invalid_threshold() {
         let mut config = PearConfig::default();
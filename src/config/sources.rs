@@ -0,0 +1,133 @@
+// Configuration Sources
+// pear.toml, a directory of TOML files such as a mounted ConfigMap, and PEAR__ environment overrides
+
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use toml_edit::DocumentMut;
+
+use super::edit::{is_known, set_key};
+use super::PearConfig;
+
+/// Variables starting with this set one key each: `PEAR__SERVER__HTTP2_PORT=8080` sets `server.http2_port`
+pub const ENV_PREFIX: &str = "PEAR__";
+
+/// `(key, value)` overrides among environment variables, sorted by key
+pub fn env_overrides(vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut overrides: Vec<(String, String)> = vars.into_iter()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix(ENV_PREFIX)?;
+            let parts: Vec<String> = key.split("__").map(str::to_ascii_lowercase).collect();
+            Some((parts.join("."), value))
+        })
+        .collect();
+    overrides.sort();
+    overrides
+}
+
+/// The variable that overrides `key`
+pub fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "__").to_ascii_uppercase())
+}
+
+/// TOML from a file, or from every `*.toml` file of a directory merged in name order
+/// Later files win key by key. Hidden entries are skipped, like the `..data` links of a ConfigMap volume.
+pub fn read(path: &Path) -> Result<String> {
+    if !path.is_dir() {
+        return std::fs::read_to_string(path).context("Failed to read configuration file");
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))? {
+        let file = entry?.path();
+        let is_toml = file.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| !name.starts_with('.') && name.ends_with(".toml"));
+        if is_toml && file.is_file() {
+            files.push(file);
+        }
+    }
+    files.sort();
+
+    let mut merged = toml::Table::new();
+    for file in &files {
+        let contents = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        let table: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", file.display()))?;
+        merge(&mut merged, table);
+    }
+    toml::to_string(&merged).context("Failed to merge configuration files")
+}
+
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The configuration `contents` describe with `overrides` applied, validated
+pub fn parse(contents: &str, overrides: &[(String, String)]) -> Result<PearConfig> {
+    let mut doc: DocumentMut = contents.parse().context("Failed to parse configuration file")?;
+    for (key, value) in overrides {
+        set_key(&mut doc, key, value).with_context(|| format!("Invalid {}", env_name(key)))?;
+    }
+
+    let config: PearConfig = toml::from_str(&doc.to_string()).context("Failed to parse configuration file")?;
+    for (key, _) in overrides {
+        let path: Vec<&str> = key.split('.').collect();
+        if !is_known(&config, &path) {
+            bail!("{} sets unknown configuration key '{}'", env_name(key), key);
+        }
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let overrides = env_overrides(vars(&[
+            ("PEAR__SERVER__HTTP2_PORT", "8443"),
+            ("PEAR_ADMIN_TOKEN", "not a key"),
+            ("PEAR__DASHBOARD__ADMIN_TOKEN", "s3cret"),
+            ("HOME", "/root"),
+        ]));
+        assert_eq!(overrides, vars(&[("dashboard.admin_token", "s3cret"), ("server.http2_port", "8443")]));
+        assert_eq!(env_name("server.http2_port"), "PEAR__SERVER__HTTP2_PORT");
+
+        let config = parse("[server]\nhttp2_port = 8080\n", &overrides).unwrap();
+        assert_eq!(config.server.http2_port, 8443);
+        assert_eq!(config.dashboard.admin_token, "s3cret");
+
+        let typo = env_overrides(vars(&[("PEAR__SERVER__HTTP2_PROT", "80")]));
+        assert!(parse("", &typo).is_err());
+        let invalid = env_overrides(vars(&[("PEAR__SERVER__HTTP2_PORT", "0")]));
+        assert!(parse("", &invalid).is_err());
+    }
+
+    #[test]
+    fn test_directory_merges_in_name_order() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("10-base.toml"), "[server]\nhttp2_port = 8080\nbind_addr = \"127.0.0.1\"\n").unwrap();
+        std::fs::write(dir.path().join("20-site.toml"), "[server]\nhttp2_port = 9443\n\n[dashboard]\nport = 9100\n").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+        std::fs::create_dir(dir.path().join("..data")).unwrap();
+
+        let config = parse(&read(dir.path()).unwrap(), &[]).unwrap();
+        assert_eq!(config.server.http2_port, 9443);
+        assert_eq!(config.server.bind_addr, "127.0.0.1");
+        assert_eq!(config.dashboard.port, 9100);
+    }
+}
@@ -3,13 +3,57 @@
 
 pub mod sync;
 pub mod session;
+pub mod pubsub;
 
-use automerge::{Automerge, transaction::Transactable, ObjType, ScalarValue};
-use anyhow::{Result, Context};
+use automerge::{Automerge, ObjId, ObjType, ReadDoc, ScalarValue, Value};
+use automerge::transaction::{Transactable, Transaction};
+use anyhow::{bail, Result, Context};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tracing::{info, debug, warn, instrument};
 
+/// What one peer knows of another's document, kept between sync messages
+pub use automerge::sync::State as SyncState;
+
+/// Bytes assumed for a change besides its key and value, when estimating document growth
+const CHANGE_OVERHEAD_BYTES: usize = 64;
+
+/// Document size limits and compaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrdtConfig {
+    /// Largest a site's saved document may grow; writes past it fail (0 = unlimited)
+    pub max_document_bytes: usize,
+
+    /// Seconds between compaction passes (0 = never compact)
+    pub compaction_interval_secs: u64,
+
+    /// Documents smaller than this are left alone by compaction
+    pub compact_min_bytes: usize,
+}
+
+impl Default for CrdtConfig {
+    fn default() -> Self {
+        Self {
+            max_document_bytes: 8 * 1024 * 1024,
+            compaction_interval_secs: 300,
+            compact_min_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl CrdtConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_document_bytes > 0 && self.compact_min_bytes >= self.max_document_bytes {
+            bail!("compact_min_bytes must be below max_document_bytes, or documents reach the limit uncompacted");
+        }
+        Ok(())
+    }
+}
+
 /// CRDT-based shared state manager
 /// Provides eventually consistent state across all Cages in a pool
 pub struct CrdtStateManager {
@@ -18,6 +62,15 @@ pub struct CrdtStateManager {
     
     /// Site identifier for this state manager
     site_id: String,
+    
+    /// Largest the saved document may grow (None = unlimited)
+    max_bytes: Option<usize>,
+    
+    /// Saved size when last measured, plus an estimate of what was written since
+    size_bytes: AtomicUsize,
+    
+    /// Times the document's history was dropped
+    compactions: AtomicU64,
 }
 
 impl CrdtStateManager {
@@ -28,16 +81,37 @@ impl CrdtStateManager {
         let document = Automerge::new();
         
         Self {
+            size_bytes: AtomicUsize::new(document.save().len()),
             document: Arc::new(RwLock::new(document)),
             site_id,
+            max_bytes: None,
+            compactions: AtomicU64::new(0),
         }
     }
 
+    /// Refuse writes once the saved document is `max_bytes` long (0 = unlimited)
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = (max_bytes > 0).then_some(max_bytes);
+        self
+    }
+
     /// Set a value in the shared state
     #[instrument(skip(self, value))]
     pub async fn set(&self, key: &str, value: serde_json::Value) -> Result<()> {
         let mut doc = self.document.write().await;
         
+        // Measuring saves the whole document, so it is only done once the estimate nears the limit
+        let estimate = key.len() + value.to_string().len() + CHANGE_OVERHEAD_BYTES;
+        if let Some(max_bytes) = self.max_bytes {
+            if self.size_bytes.load(Ordering::Relaxed) + estimate > max_bytes {
+                let measured = doc.save().len();
+                self.size_bytes.store(measured, Ordering::Relaxed);
+                if measured + estimate > max_bytes {
+                    bail!("Shared state of site {} is at its {} byte limit", self.site_id, max_bytes);
+                }
+            }
+        }
+        
         let mut tx = doc.transaction();
         
         // Convert JSON value to Automerge scalar
@@ -64,6 +138,7 @@ impl CrdtStateManager {
             .context("Failed to set value in CRDT")?;
         
         tx.commit();
+        self.size_bytes.fetch_add(estimate, Ordering::Relaxed);
 
         debug!(key = %key, "CRDT value set");
         Ok(())
@@ -98,6 +173,29 @@ impl CrdtStateManager {
         }
     }
 
+    /// Remove a key from the shared state, returning whether it was set
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        let mut doc = self.document.write().await;
+        if doc.get(automerge::ROOT, key)?.is_none() {
+            return Ok(false);
+        }
+        
+        let mut tx = doc.transaction();
+        tx.delete(automerge::ROOT, key)
+            .context("Failed to delete value from CRDT")?;
+        tx.commit();
+        self.size_bytes.fetch_add(key.len() + CHANGE_OVERHEAD_BYTES, Ordering::Relaxed);
+        
+        debug!(key = %key, "CRDT value deleted");
+        Ok(true)
+    }
+
+    /// Keys in the shared state starting with `prefix`
+    pub async fn keys(&self, prefix: &str) -> Vec<String> {
+        let doc = self.document.read().await;
+        doc.keys(automerge::ROOT).filter(|key| key.starts_with(prefix)).collect()
+    }
+
     /// Get the changeset for synchronization
     pub async fn get_changes(&self) -> Result<Vec<u8>> {
         let doc = self.document.read().await;
@@ -111,6 +209,7 @@ impl CrdtStateManager {
         
         doc.load_incremental(changes)
             .context("Failed to apply CRDT changes")?;
+        self.size_bytes.fetch_add(changes.len(), Ordering::Relaxed);
         
         info!("CRDT changes applied successfully");
         Ok(())
@@ -120,7 +219,7 @@ impl CrdtStateManager {
     pub async fn merge(&self, other_doc: &Automerge) -> Result<()> {
         let mut doc = self.document.write().await;
         
-        doc.merge(other_doc)
+        doc.merge(&mut other_doc.clone())
             .context("Failed to merge CRDT documents")?;
         
         info!("CRDT documents merged successfully");
@@ -130,8 +229,141 @@ impl CrdtStateManager {
     /// Get current document size
     pub async fn size(&self) -> usize {
         let doc = self.document.read().await;
-        doc.save().len()
+        let size = doc.save().len();
+        self.size_bytes.store(size, Ordering::Relaxed);
+        size
+    }
+
+    /// The next sync message for a peer, or None when the peer has everything we do
+    /// Only changes the peer lacks are sent, so a quiet document costs nothing to keep in sync.
+    pub async fn generate_sync_message(&self, peer: &mut SyncState) -> Option<Vec<u8>> {
+        use automerge::sync::SyncDoc;
+        
+        let doc = self.document.read().await;
+        doc.generate_sync_message(peer).map(|message| message.encode())
+    }
+
+    /// Apply a sync message from a peer
+    pub async fn receive_sync_message(&self, peer: &mut SyncState, message: &[u8]) -> Result<()> {
+        use automerge::sync::{Message, SyncDoc};
+        
+        let len = message.len();
+        let message = Message::decode(message).context("Malformed CRDT sync message")?;
+        let mut doc = self.document.write().await;
+        doc.receive_sync_message(peer, message)
+            .context("Failed to apply CRDT sync message")?;
+        self.size_bytes.fetch_add(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Drop the document's history, keeping only its current contents
+    /// Peers syncing with this document must be compacted together, see [`compact_together`].
+    pub async fn compact(&self) -> Result<Compaction> {
+        let mut doc = self.document.write().await;
+        let compaction = self.compact_locked(&mut doc)?;
+        Ok(compaction)
+    }
+
+    fn compact_locked(&self, doc: &mut Automerge) -> Result<Compaction> {
+        let before_bytes = doc.save().len();
+        let mut compacted = Automerge::new();
+        let mut tx = compacted.transaction();
+        copy_object(doc, &automerge::ROOT, &mut tx, &automerge::ROOT)?;
+        tx.commit();
+        let after_bytes = compacted.save().len();
+        
+        *doc = compacted;
+        self.size_bytes.store(after_bytes, Ordering::Relaxed);
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        debug!(site_id = %self.site_id, before_bytes, after_bytes, "CRDT document compacted");
+        Ok(Compaction { before_bytes, after_bytes })
+    }
+
+    pub fn stats(&self) -> DocumentStats {
+        DocumentStats {
+            site_id: self.site_id.clone(),
+            size_bytes: self.size_bytes.load(Ordering::Relaxed),
+            max_bytes: self.max_bytes,
+            compactions: self.compactions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Compact documents that sync with each other, all at once
+/// Each must hold the same changes; compacting one alone would have its peers sync the old
+/// history straight back. Returns None, compacting nothing, when they have not converged.
+pub async fn compact_together(managers: &[Arc<CrdtStateManager>]) -> Result<Option<Compaction>> {
+    let Some((first, rest)) = managers.split_first() else { return Ok(None) };
+    
+    // Taken in order, and only here, so no other task can be holding some while waiting for others
+    let mut docs = Vec::with_capacity(managers.len());
+    for manager in managers {
+        docs.push(manager.document.write().await);
+    }
+    let heads = docs[0].get_heads();
+    if docs.iter().any(|doc| doc.get_heads() != heads) {
+        return Ok(None);
+    }
+    
+    let (first_doc, rest_docs) = docs.split_first_mut().expect("one document per manager");
+    let compaction = first.compact_locked(first_doc)?;
+    for (manager, doc) in rest.iter().zip(rest_docs) {
+        **doc = first_doc.fork();
+        manager.size_bytes.store(compaction.after_bytes, Ordering::Relaxed);
+        manager.compactions.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(Some(compaction))
+}
+
+/// Copy an object's current contents into `target`, leaving its history behind
+fn copy_object(from: &Automerge, source: &ObjId, to: &mut Transaction<'_>, target: &ObjId) -> Result<()> {
+    match from.object_type(source)? {
+        ObjType::Map | ObjType::Table => {
+            for key in from.keys(source) {
+                match from.get(source, key.as_str())? {
+                    Some((Value::Object(obj_type), child)) => {
+                        let copy = to.put_object(target, key.as_str(), obj_type)?;
+                        copy_object(from, &child, to, &copy)?;
+                    }
+                    Some((Value::Scalar(value), _)) => to.put(target, key.as_str(), value.into_owned())?,
+                    None => {}
+                }
+            }
+        }
+        ObjType::List => {
+            for index in 0..from.length(source) {
+                match from.get(source, index)? {
+                    Some((Value::Object(obj_type), child)) => {
+                        let copy = to.insert_object(target, index, obj_type)?;
+                        copy_object(from, &child, to, &copy)?;
+                    }
+                    Some((Value::Scalar(value), _)) => to.insert(target, index, value.into_owned())?,
+                    None => {}
+                }
+            }
+        }
+        ObjType::Text => to.splice_text(target, 0, 0, &from.text(source)?)?,
     }
+    Ok(())
+}
+
+/// Size of a document before and after compaction
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Compaction {
+    pub before_bytes: usize,
+    pub after_bytes: usize,
+}
+
+/// Size and upkeep of one site's document
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentStats {
+    pub site_id: String,
+    
+    /// Saved size when last measured, plus an estimate of later writes
+    pub size_bytes: usize,
+    
+    pub max_bytes: Option<usize>,
+    pub compactions: u64,
 }
 
 /// Shared state handle for Cages
@@ -155,6 +387,82 @@ impl StateHandle {
     }
 }
 
+/// Shared state documents of every site on the node
+#[derive(Default)]
+pub struct DocumentStore {
+    documents: dashmap::DashMap<String, Arc<CrdtStateManager>>,
+    config: CrdtConfig,
+}
+
+impl DocumentStore {
+    pub fn new(config: CrdtConfig) -> Self {
+        Self { documents: Default::default(), config }
+    }
+
+    /// The site's document, created empty on first use
+    pub fn document(&self, site_id: &str) -> Arc<CrdtStateManager> {
+        self.documents.entry(site_id.to_string())
+            .or_insert_with(|| {
+                Arc::new(CrdtStateManager::new(site_id.to_string())
+                    .with_max_bytes(self.config.max_document_bytes))
+            })
+            .clone()
+    }
+
+    /// The site's document, if it has one
+    pub fn get(&self, site_id: &str) -> Option<Arc<CrdtStateManager>> {
+        self.documents.get(site_id).map(|document| document.clone())
+    }
+
+    /// Sites that have a document
+    pub fn site_ids(&self) -> Vec<String> {
+        self.documents.iter().map(|entry| entry.key().clone()).collect()
+    }
+
+    /// Size and upkeep of every document
+    pub fn stats(&self) -> Vec<DocumentStats> {
+        let mut stats: Vec<_> = self.documents.iter().map(|entry| entry.value().stats()).collect();
+        stats.sort_by(|a, b| a.site_id.cmp(&b.site_id));
+        stats
+    }
+
+    /// Periodically compact documents that have grown past `compact_min_bytes`
+    pub fn start_compaction(self: &Arc<Self>) {
+        if self.config.compaction_interval_secs == 0 {
+            return;
+        }
+        let store = Arc::downgrade(self);
+        let period = Duration::from_secs(self.config.compaction_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(store) = store.upgrade() else { break };
+                store.compact_all().await;
+            }
+        });
+    }
+
+    async fn compact_all(&self) {
+        let documents: Vec<_> = self.documents.iter().map(|entry| entry.value().clone()).collect();
+        for document in documents {
+            if document.size().await < self.config.compact_min_bytes {
+                continue;
+            }
+            match document.compact().await {
+                Ok(compaction) => info!(
+                    site_id = %document.site_id,
+                    before_bytes = compaction.before_bytes,
+                    after_bytes = compaction.after_bytes,
+                    "CRDT document compacted"
+                ),
+                Err(e) => warn!(site_id = %document.site_id, "CRDT compaction failed: {:#}", e),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -194,4 +502,36 @@ mod tests {
         let value = manager2.get("key1").await.unwrap();
         assert_eq!(value, Some(serde_json::json!(42)));
     }
+
+    #[tokio::test]
+    async fn test_document_size_limit() {
+        let manager = CrdtStateManager::new("site".to_string()).with_max_bytes(4096);
+        
+        let mut written = 0;
+        while manager.set(&format!("key{}", written), serde_json::json!(uuid::Uuid::new_v4().to_string())).await.is_ok() {
+            written += 1;
+            assert!(written < 1000, "limit never reached");
+        }
+        assert!(written > 0);
+        assert!(manager.size().await <= 4096);
+        
+        // Rewriting an existing key grows the history just the same
+        assert!(manager.set("key0", serde_json::json!("x".repeat(4096))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compact_keeps_contents() {
+        let manager = CrdtStateManager::new("site".to_string());
+        for i in 0..100 {
+            manager.set("counter", serde_json::json!(i)).await.unwrap();
+        }
+        manager.set("name", serde_json::json!("pear")).await.unwrap();
+        
+        let compaction = manager.compact().await.unwrap();
+        assert!(compaction.after_bytes < compaction.before_bytes);
+        assert_eq!(manager.get("counter").await.unwrap(), Some(serde_json::json!(99)));
+        assert_eq!(manager.get("name").await.unwrap(), Some(serde_json::json!("pear")));
+        assert_eq!(manager.stats().compactions, 1);
+        assert_eq!(manager.stats().size_bytes, compaction.after_bytes);
+    }
 }
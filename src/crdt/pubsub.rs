@@ -0,0 +1,560 @@
+// Publish/Subscribe
+// Site-scoped topics between Cages, optionally forwarded to peer nodes
+
+use anyhow::{Context, Result, bail};
+use dashmap::DashMap;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper_util::rt::TokioIo;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Messages waiting to be forwarded before new ones are dropped
+const FORWARD_BUFFER: usize = 4096;
+
+/// Messages sent to a peer in one request
+const FORWARD_BATCH: usize = 256;
+
+/// Pub/sub settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PubSubConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: usize,
+
+    /// Messages a site may publish per second
+    #[serde(default = "default_max_messages_per_sec")]
+    pub max_messages_per_sec: u64,
+
+    /// Bytes a site may publish per second
+    #[serde(default = "default_max_bytes_per_sec")]
+    pub max_bytes_per_sec: u64,
+
+    /// Unread messages kept per subscriber; the oldest are dropped first
+    #[serde(default = "default_inbox_capacity")]
+    pub inbox_capacity: usize,
+
+    /// Management API addresses ("host:port") of nodes to forward messages to
+    #[serde(default)]
+    pub peers: Vec<String>,
+
+    /// Shared token peers present when forwarding; required to accept remote messages
+    #[serde(default)]
+    pub peer_token: Option<String>,
+}
+
+fn default_enabled() -> bool { true }
+fn default_max_message_bytes() -> usize { 64 * 1024 }
+fn default_max_messages_per_sec() -> u64 { 1000 }
+fn default_max_bytes_per_sec() -> u64 { 1024 * 1024 }
+fn default_inbox_capacity() -> usize { 256 }
+
+impl Default for PubSubConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            max_message_bytes: default_max_message_bytes(),
+            max_messages_per_sec: default_max_messages_per_sec(),
+            max_bytes_per_sec: default_max_bytes_per_sec(),
+            inbox_capacity: default_inbox_capacity(),
+            peers: Vec::new(),
+            peer_token: None,
+        }
+    }
+}
+
+impl PubSubConfig {
+    /// Check the settings are usable
+    pub fn validate(&self) -> Result<()> {
+        if self.max_message_bytes == 0 || self.inbox_capacity == 0 {
+            bail!("pubsub.max_message_bytes and pubsub.inbox_capacity must be greater than 0");
+        }
+        if self.max_messages_per_sec == 0 || self.max_bytes_per_sec < self.max_message_bytes as u64 {
+            bail!("pubsub rate limits must allow at least one message of max_message_bytes per second");
+        }
+        if !self.peers.is_empty() && self.peer_token.as_deref().map_or(true, str::is_empty) {
+            bail!("pubsub.peer_token is required when peers are configured");
+        }
+        for peer in &self.peers {
+            if peer.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok()).is_none() {
+                bail!("pubsub peer '{}' must be host:port", peer);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A published message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub site_id: String,
+    pub topic: String,
+
+    /// UTF-8 body, typically JSON
+    pub data: String,
+
+    /// Node the message was published on
+    pub origin: String,
+
+    /// Unix timestamp (milliseconds)
+    pub published_at_ms: i64,
+}
+
+/// Counters of one topic
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TopicStats {
+    pub published: u64,
+    pub bytes: u64,
+
+    /// Copies handed to subscriber inboxes
+    pub delivered: u64,
+
+    /// Copies lost to full inboxes
+    pub dropped: u64,
+
+    /// Publishes refused by the site's throughput cap
+    pub rejected: u64,
+
+    /// Messages received from peer nodes
+    pub remote: u64,
+
+    pub subscribers: usize,
+}
+
+#[derive(Default)]
+struct TopicCounters {
+    published: AtomicU64,
+    bytes: AtomicU64,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    rejected: AtomicU64,
+    remote: AtomicU64,
+}
+
+/// Publish budget of a site for the current second
+#[derive(Default)]
+struct RateWindow {
+    second: i64,
+    messages: u64,
+    bytes: u64,
+}
+
+/// Carries messages to other nodes
+pub trait PubSubBridge: Send + Sync {
+    fn forward(&self, message: &Message);
+}
+
+struct Subscriber {
+    site_id: String,
+    topics: Mutex<HashSet<String>>,
+    inbox: Mutex<VecDeque<Arc<Message>>>,
+}
+
+/// Topic hub shared by every Cage on this node
+pub struct PubSub {
+    config: PubSubConfig,
+    node_id: String,
+    subscribers: RwLock<Vec<Weak<Subscriber>>>,
+
+    /// Counters keyed by "site/topic"
+    topics: DashMap<String, TopicCounters>,
+
+    rates: DashMap<String, RateWindow>,
+    bridge: OnceLock<Arc<dyn PubSubBridge>>,
+}
+
+impl PubSub {
+    pub fn new(config: PubSubConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            node_id: uuid::Uuid::new_v4().to_string(),
+            subscribers: RwLock::new(Vec::new()),
+            topics: DashMap::new(),
+            rates: DashMap::new(),
+            bridge: OnceLock::new(),
+        })
+    }
+
+    /// Identifies this node as the origin of its messages
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Forward local publishes through `bridge`
+    pub fn set_bridge(&self, bridge: Arc<dyn PubSubBridge>) {
+        if self.bridge.set(bridge).is_err() {
+            warn!("Pub/sub bridge already set");
+        }
+    }
+
+    /// Handle a site's Cages use to publish and subscribe
+    pub fn guest(self: &Arc<Self>, site_id: &str) -> GuestPubSub {
+        GuestPubSub {
+            hub: self.clone(),
+            site_id: site_id.to_string(),
+        }
+    }
+
+    /// Publish from this node, returning the number of local deliveries
+    pub fn publish(&self, site_id: &str, topic: &str, data: &[u8]) -> Result<usize> {
+        validate_topic(topic)?;
+        if data.len() > self.config.max_message_bytes {
+            bail!("Message of {} bytes exceeds the {} byte limit", data.len(), self.config.max_message_bytes);
+        }
+        let data = std::str::from_utf8(data).context("Message must be UTF-8")?;
+
+        let counters = self.counters(site_id, topic);
+        if let Err(e) = self.take_budget(site_id, data.len() as u64) {
+            counters.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+        counters.published.fetch_add(1, Ordering::Relaxed);
+        counters.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        drop(counters);
+
+        let message = Message {
+            site_id: site_id.to_string(),
+            topic: topic.to_string(),
+            data: data.to_string(),
+            origin: self.node_id.clone(),
+            published_at_ms: chrono::Utc::now().timestamp_millis(),
+        };
+        if let Some(bridge) = self.bridge.get() {
+            bridge.forward(&message);
+        }
+
+        Ok(self.deliver(Arc::new(message)))
+    }
+
+    /// Deliver a message forwarded by a peer node
+    /// Remote messages are not forwarded again, so peers must form a full mesh
+    pub fn receive_remote(&self, message: Message) -> Result<usize> {
+        if message.origin == self.node_id {
+            return Ok(0);
+        }
+        validate_topic(&message.topic)?;
+        if message.data.len() > self.config.max_message_bytes {
+            bail!("Message of {} bytes exceeds the {} byte limit", message.data.len(), self.config.max_message_bytes);
+        }
+
+        self.counters(&message.site_id, &message.topic).remote.fetch_add(1, Ordering::Relaxed);
+        Ok(self.deliver(Arc::new(message)))
+    }
+
+    /// Check a peer's token against `peer_token`
+    pub fn verify_peer_token(&self, token: &str) -> bool {
+        match &self.config.peer_token {
+            // Compare every byte so timing doesn't reveal the matching prefix
+            Some(expected) if !expected.is_empty() && expected.len() == token.len() => {
+                expected.bytes().zip(token.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+            }
+            _ => false,
+        }
+    }
+
+    /// Counters of every topic a site has used, with current subscriber counts
+    pub fn topic_stats(&self, site_id: &str) -> BTreeMap<String, TopicStats> {
+        let prefix = format!("{}/", site_id);
+        let mut stats: BTreeMap<String, TopicStats> = self.topics.iter()
+            .filter_map(|entry| {
+                let topic = entry.key().strip_prefix(&prefix)?;
+                let c = entry.value();
+                Some((topic.to_string(), TopicStats {
+                    published: c.published.load(Ordering::Relaxed),
+                    bytes: c.bytes.load(Ordering::Relaxed),
+                    delivered: c.delivered.load(Ordering::Relaxed),
+                    dropped: c.dropped.load(Ordering::Relaxed),
+                    rejected: c.rejected.load(Ordering::Relaxed),
+                    remote: c.remote.load(Ordering::Relaxed),
+                    subscribers: 0,
+                }))
+            })
+            .collect();
+
+        for subscriber in self.subscribers.read().iter().filter_map(Weak::upgrade) {
+            if subscriber.site_id == site_id {
+                for topic in subscriber.topics.lock().iter() {
+                    stats.entry(topic.clone()).or_default().subscribers += 1;
+                }
+            }
+        }
+        stats
+    }
+
+    /// Put a message in the inbox of every matching subscriber on this node
+    fn deliver(&self, message: Arc<Message>) -> usize {
+        let mut delivered = 0;
+        let mut dropped = 0;
+        let mut stale = false;
+
+        for weak in self.subscribers.read().iter() {
+            let Some(subscriber) = weak.upgrade() else {
+                stale = true;
+                continue;
+            };
+            if subscriber.site_id != message.site_id || !subscriber.topics.lock().contains(&message.topic) {
+                continue;
+            }
+
+            let mut inbox = subscriber.inbox.lock();
+            if inbox.len() >= self.config.inbox_capacity {
+                inbox.pop_front();
+                dropped += 1;
+            }
+            inbox.push_back(message.clone());
+            delivered += 1;
+        }
+
+        if stale {
+            self.subscribers.write().retain(|weak| weak.strong_count() > 0);
+        }
+
+        let counters = self.counters(&message.site_id, &message.topic);
+        counters.delivered.fetch_add(delivered as u64, Ordering::Relaxed);
+        counters.dropped.fetch_add(dropped, Ordering::Relaxed);
+        delivered
+    }
+
+    /// Charge a publish against the site's per-second caps
+    fn take_budget(&self, site_id: &str, bytes: u64) -> Result<()> {
+        let second = chrono::Utc::now().timestamp();
+        let mut window = self.rates.entry(site_id.to_string()).or_default();
+        if window.second != second {
+            *window = RateWindow { second, messages: 0, bytes: 0 };
+        }
+
+        if window.messages >= self.config.max_messages_per_sec
+            || window.bytes + bytes > self.config.max_bytes_per_sec
+        {
+            bail!("Site publish rate limit exceeded");
+        }
+        window.messages += 1;
+        window.bytes += bytes;
+        Ok(())
+    }
+
+    fn counters(&self, site_id: &str, topic: &str) -> dashmap::mapref::one::Ref<'_, String, TopicCounters> {
+        let key = format!("{}/{}", site_id, topic);
+        if let Some(counters) = self.topics.get(&key) {
+            return counters;
+        }
+        self.topics.entry(key).or_default().downgrade()
+    }
+
+    fn register(&self, subscriber: &Arc<Subscriber>) {
+        self.subscribers.write().push(Arc::downgrade(subscriber));
+    }
+}
+
+/// A site's view of the hub, held in its Cage configuration
+#[derive(Clone)]
+pub struct GuestPubSub {
+    hub: Arc<PubSub>,
+    site_id: String,
+}
+
+impl GuestPubSub {
+    /// A new subscriber; it stops receiving once dropped
+    pub fn subscriber(&self) -> GuestSubscriber {
+        let subscriber = Arc::new(Subscriber {
+            site_id: self.site_id.clone(),
+            topics: Mutex::new(HashSet::new()),
+            inbox: Mutex::new(VecDeque::new()),
+        });
+        self.hub.register(&subscriber);
+
+        GuestSubscriber {
+            hub: self.hub.clone(),
+            subscriber,
+        }
+    }
+
+    pub fn site_id(&self) -> &str {
+        &self.site_id
+    }
+}
+
+impl std::fmt::Debug for GuestPubSub {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuestPubSub").field("site_id", &self.site_id).finish()
+    }
+}
+
+/// One guest instance's subscriptions and inbox
+pub struct GuestSubscriber {
+    hub: Arc<PubSub>,
+    subscriber: Arc<Subscriber>,
+}
+
+impl GuestSubscriber {
+    pub fn subscribe(&self, topic: &str) -> Result<()> {
+        validate_topic(topic)?;
+        self.subscriber.topics.lock().insert(topic.to_string());
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, topic: &str) -> bool {
+        self.subscriber.topics.lock().remove(topic)
+    }
+
+    /// Publish to the site's subscribers, on this node and its peers
+    pub fn publish(&self, topic: &str, data: &[u8]) -> Result<usize> {
+        self.hub.publish(&self.subscriber.site_id, topic, data)
+    }
+
+    /// Oldest unread message
+    pub fn next(&self) -> Option<Arc<Message>> {
+        self.subscriber.inbox.lock().pop_front()
+    }
+}
+
+/// Forwards messages to peer nodes' management APIs
+pub struct HttpBridge {
+    sender: mpsc::Sender<Message>,
+}
+
+impl HttpBridge {
+    /// Start forwarding to `peers` in the background, over mutual TLS if `tls` is given
+    pub fn start(peers: Vec<String>, token: String, tls: Option<tokio_rustls::TlsConnector>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<Message>(FORWARD_BUFFER);
+
+        tokio::spawn(async move {
+            while let Some(first) = receiver.recv().await {
+                let mut batch = vec![first];
+                while batch.len() < FORWARD_BATCH {
+                    match receiver.try_recv() {
+                        Ok(message) => batch.push(message),
+                        Err(_) => break,
+                    }
+                }
+                let body = Bytes::from(serde_json::to_vec(&batch).unwrap_or_default());
+
+                for peer in &peers {
+                    if let Err(e) = forward_batch(peer, &token, tls.as_ref(), body.clone()).await {
+                        warn!(peer = %peer, error = %e, "Failed to forward pub/sub messages");
+                    }
+                }
+            }
+        });
+
+        info!("Pub/sub forwarding to peer nodes started");
+        Self { sender }
+    }
+}
+
+impl PubSubBridge for HttpBridge {
+    fn forward(&self, message: &Message) {
+        if self.sender.try_send(message.clone()).is_err() {
+            debug!(site_id = %message.site_id, topic = %message.topic, "Pub/sub forward buffer full, message not forwarded");
+        }
+    }
+}
+
+/// POST a batch of messages to a peer's `/api/pubsub/messages`
+async fn forward_batch(peer: &str, token: &str, tls: Option<&tokio_rustls::TlsConnector>, body: Bytes) -> Result<()> {
+    // The peer's certificate names its host, as it appears in `peers`
+    let host = peer.rsplit_once(':').map_or(peer, |(host, _)| host).trim_start_matches('[').trim_end_matches(']');
+    let stream = crate::network::mtls::connect(peer, host, tls).await?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+
+    let request = hyper::Request::post("/api/pubsub/messages")
+        .header(hyper::header::HOST, peer)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(hyper::header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Full::new(body))?;
+
+    let status = sender.send_request(request).await?.status();
+    if !status.is_success() {
+        bail!("Peer returned {}", status);
+    }
+    Ok(())
+}
+
+/// Topics are short printable names such as "cache/invalidate"
+pub fn validate_topic(topic: &str) -> Result<()> {
+    if topic.is_empty() || topic.len() > 128 || !topic.chars().all(|c| c.is_ascii_graphic()) {
+        bail!("Invalid topic '{}': use 1-128 printable characters without spaces", topic);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hub(config: PubSubConfig) -> Arc<PubSub> {
+        Arc::new(PubSub::new(config).unwrap())
+    }
+
+    #[test]
+    fn test_publish_reaches_site_subscribers() {
+        let hub = hub(PubSubConfig { inbox_capacity: 2, ..Default::default() });
+        let blog_a = hub.guest("blog").subscriber();
+        let blog_b = hub.guest("blog").subscriber();
+        let shop = hub.guest("shop").subscriber();
+        blog_a.subscribe("cache").unwrap();
+        blog_b.subscribe("cache").unwrap();
+        shop.subscribe("cache").unwrap();
+        assert!(blog_a.subscribe("bad topic").is_err());
+
+        assert_eq!(blog_a.publish("cache", b"/posts/1").unwrap(), 2);
+        assert_eq!(blog_b.next().unwrap().data, "/posts/1");
+        assert!(shop.next().is_none());
+
+        // Full inboxes drop their oldest message
+        blog_a.publish("cache", b"/posts/2").unwrap();
+        blog_a.publish("cache", b"/posts/3").unwrap();
+        assert_eq!(blog_a.next().unwrap().data, "/posts/2");
+
+        drop(blog_b);
+        blog_a.publish("cache", b"/posts/4").unwrap();
+        let stats = &hub.topic_stats("blog")["cache"];
+        assert_eq!((stats.published, stats.delivered, stats.dropped, stats.subscribers), (4, 7, 1, 1));
+    }
+
+    #[test]
+    fn test_site_throughput_cap() {
+        let hub = hub(PubSubConfig { max_messages_per_sec: 3, ..Default::default() });
+        let blog = hub.guest("blog").subscriber();
+
+        // Even if a second boundary refills the budget midway, 7 publishes exceed 2 x 3
+        let results: Vec<bool> = (0..7).map(|_| blog.publish("live", b"tick").is_ok()).collect();
+        assert!(results.iter().filter(|ok| !**ok).count() >= 1);
+        assert!(hub.topic_stats("blog")["live"].rejected >= 1);
+        assert!(hub.guest("shop").subscriber().publish("live", b"tick").is_ok());
+    }
+
+    #[test]
+    fn test_remote_messages_delivered_once() {
+        struct Capture(Mutex<Vec<Message>>);
+        impl PubSubBridge for Capture {
+            fn forward(&self, message: &Message) {
+                self.0.lock().push(message.clone());
+            }
+        }
+
+        let node_a = hub(PubSubConfig::default());
+        let node_b = hub(PubSubConfig::default());
+        let bridge = Arc::new(Capture(Mutex::new(Vec::new())));
+        node_a.set_bridge(bridge.clone());
+
+        let subscriber = node_b.guest("blog").subscriber();
+        subscriber.subscribe("chat").unwrap();
+        node_a.guest("blog").subscriber().publish("chat", b"hello").unwrap();
+
+        let forwarded = bridge.0.lock().pop().unwrap();
+        assert_eq!(node_a.receive_remote(forwarded.clone()).unwrap(), 0);
+        assert_eq!(node_b.receive_remote(forwarded).unwrap(), 1);
+        assert_eq!(subscriber.next().unwrap().origin, node_a.node_id());
+        assert_eq!(node_b.topic_stats("blog")["chat"].remote, 1);
+    }
+}
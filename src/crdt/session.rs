@@ -1,8 +1,22 @@
 // Session data structures for CRDT state
 // Manages user sessions, authentication, and application state
 
+use anyhow::{anyhow, bail, Context, Result};
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::{CrdtStateManager, DocumentStore};
+
+/// Prefix of the shared state keys sessions are stored under
+const SESSION_KEY_PREFIX: &str = "session:";
+
+/// Loading a session only records activity when the last record is older than this,
+/// so busy sessions don't write to the shared state on every request
+const TOUCH_INTERVAL_SECS: u64 = 60;
 
 /// User session data
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,7 +81,313 @@ impl UserSession {
             .unwrap()
             .as_secs();
         
-        now - self.last_activity > timeout_secs
+        // Another node's clock may be ahead of ours
+        now.saturating_sub(self.last_activity) > timeout_secs
+    }
+}
+
+/// Session lifetimes and cookies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Link the `pear_session` host module into Cages
+    pub enabled: bool,
+
+    /// Seconds without activity before a session expires
+    pub timeout_secs: u64,
+
+    /// Seconds between sweeps removing expired sessions
+    pub sweep_interval_secs: u64,
+
+    /// Name of the session cookie
+    pub cookie_name: String,
+
+    /// Only send the cookie over HTTPS
+    pub secure_cookie: bool,
+
+    /// Sessions a site may hold at once
+    pub max_sessions_per_site: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout_secs: 30 * 60,
+            sweep_interval_secs: 60,
+            cookie_name: "pear_session".to_string(),
+            secure_cookie: true,
+            max_sessions_per_site: 100_000,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.timeout_secs == 0 {
+            bail!("timeout_secs must be at least 1");
+        }
+        if self.sweep_interval_secs == 0 {
+            bail!("sweep_interval_secs must be at least 1");
+        }
+        let valid_name = !self.cookie_name.is_empty()
+            && self.cookie_name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+        if !valid_name {
+            bail!("cookie_name '{}' is not a valid cookie name", self.cookie_name);
+        }
+        Ok(())
+    }
+}
+
+/// Sessions of every site, kept in each site's shared state document
+/// Every Cage of a site, on any node syncing the document, sees the same sessions.
+pub struct SessionManager {
+    config: SessionConfig,
+    documents: Arc<DocumentStore>,
+    rng: SystemRandom,
+}
+
+impl SessionManager {
+    pub fn new(config: SessionConfig, documents: Arc<DocumentStore>) -> Self {
+        Self { config, documents, rng: SystemRandom::new() }
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    /// A new, empty session for the site
+    pub async fn create(&self, site_id: &str) -> Result<UserSession> {
+        let document = self.documents.document(site_id);
+        let count = document.keys(SESSION_KEY_PREFIX).await.len();
+        if count >= self.config.max_sessions_per_site {
+            bail!("Site {} has reached its limit of {} sessions", site_id, self.config.max_sessions_per_site);
+        }
+
+        let mut id = [0u8; 32];
+        self.rng.fill(&mut id).map_err(|_| anyhow!("Failed to generate a session ID"))?;
+        let session = UserSession::new(hex::encode(id));
+        store(&document, &session).await?;
+
+        debug!(site_id = %site_id, "Session created");
+        Ok(session)
+    }
+
+    /// The session, unless it is unknown or expired; records activity on it
+    pub async fn load(&self, site_id: &str, session_id: &str) -> Result<Option<UserSession>> {
+        let Some(document) = self.documents.get(site_id) else {
+            return Ok(None);
+        };
+        let Some(mut session) = fetch(&document, session_id).await? else {
+            return Ok(None);
+        };
+        if session.is_expired(self.config.timeout_secs) {
+            return Ok(None);
+        }
+
+        let idle = unix_now().saturating_sub(session.last_activity);
+        if idle >= TOUCH_INTERVAL_SECS {
+            session.update_activity();
+            store(&document, &session).await?;
+        }
+        Ok(Some(session))
+    }
+
+    /// Store changes a guest made to a session it loaded
+    /// The session must still exist; its ID and creation time can't be changed.
+    pub async fn save(&self, site_id: &str, mut session: UserSession) -> Result<()> {
+        let stored = match self.documents.get(site_id) {
+            Some(document) => fetch(&document, &session.session_id).await?.map(|stored| (document, stored)),
+            None => None,
+        };
+        let Some((document, stored)) = stored.filter(|(_, stored)| !stored.is_expired(self.config.timeout_secs)) else {
+            bail!("Session does not exist or has expired");
+        };
+
+        session.created_at = stored.created_at;
+        session.update_activity();
+        store(&document, &session).await
+    }
+
+    /// End a session, returning whether it existed
+    pub async fn destroy(&self, site_id: &str, session_id: &str) -> Result<bool> {
+        match self.documents.get(site_id) {
+            Some(document) => document.delete(&session_key(session_id)).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Sessions of the site that have not expired
+    pub async fn active_sessions(&self, site_id: &str) -> Result<usize> {
+        match self.documents.get(site_id) {
+            Some(document) => Ok(sessions(&document).await?
+                .iter()
+                .filter(|session| !session.is_expired(self.config.timeout_secs))
+                .count()),
+            None => Ok(0),
+        }
+    }
+
+    /// Active session counts of every site that has sessions
+    pub async fn active_counts(&self) -> Vec<(String, usize)> {
+        let mut counts = Vec::new();
+        for site_id in self.documents.site_ids() {
+            match self.active_sessions(&site_id).await {
+                Ok(0) => {}
+                Ok(count) => counts.push((site_id, count)),
+                Err(e) => warn!(site_id = %site_id, "Failed to count sessions: {:#}", e),
+            }
+        }
+        counts.sort();
+        counts
+    }
+
+    /// Remove expired sessions of every site, returning how many were removed
+    pub async fn sweep(&self) -> Result<usize> {
+        let mut removed = 0;
+        for site_id in self.documents.site_ids() {
+            let Some(document) = self.documents.get(&site_id) else { continue };
+            for session in sessions(&document).await? {
+                if session.is_expired(self.config.timeout_secs)
+                    && document.delete(&session_key(&session.session_id)).await?
+                {
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Periodically remove expired sessions
+    pub fn start_sweeper(self: &Arc<Self>) {
+        let manager = Arc::downgrade(self);
+        let period = Duration::from_secs(self.config.sweep_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(manager) = manager.upgrade() else { break };
+                match manager.sweep().await {
+                    Ok(0) => {}
+                    Ok(removed) => info!(removed, "Expired sessions removed"),
+                    Err(e) => warn!("Session sweep failed: {:#}", e),
+                }
+            }
+        });
+    }
+
+    /// `Set-Cookie` value handing the session to the browser
+    pub fn cookie(&self, session: &UserSession) -> String {
+        self.cookie_with(&session.session_id, self.config.timeout_secs)
+    }
+
+    /// `Set-Cookie` value removing the session cookie from the browser
+    pub fn clear_cookie(&self) -> String {
+        self.cookie_with("", 0)
+    }
+
+    fn cookie_with(&self, value: &str, max_age: u64) -> String {
+        let secure = if self.config.secure_cookie { "; Secure" } else { "" };
+        format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}", self.config.cookie_name, value, max_age, secure)
+    }
+
+    /// Session ID in a `Cookie` header, or the header itself when it is a bare session ID
+    pub fn session_id<'a>(&self, cookie: &'a str) -> Option<&'a str> {
+        let id = cookie.split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == self.config.cookie_name)
+            .map_or(cookie.trim(), |(_, value)| value.trim());
+        (!id.is_empty() && id.bytes().all(|b| b.is_ascii_hexdigit())).then_some(id)
+    }
+
+    /// Sessions of one site, handed to its Cages
+    pub fn guest(self: &Arc<Self>, site_id: &str) -> GuestSessions {
+        GuestSessions { manager: self.clone(), site_id: site_id.to_string() }
+    }
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("{}{}", SESSION_KEY_PREFIX, session_id)
+}
+
+async fn store(document: &CrdtStateManager, session: &UserSession) -> Result<()> {
+    let json = serde_json::to_string(session)?;
+    document.set(&session_key(&session.session_id), serde_json::Value::String(json)).await
+}
+
+async fn fetch(document: &CrdtStateManager, session_id: &str) -> Result<Option<UserSession>> {
+    match document.get(&session_key(session_id)).await? {
+        Some(serde_json::Value::String(json)) => Ok(Some(serde_json::from_str(&json).context("Corrupt session")?)),
+        _ => Ok(None),
+    }
+}
+
+/// Every session in the document, skipping any that can't be read
+async fn sessions(document: &CrdtStateManager) -> Result<Vec<UserSession>> {
+    let mut sessions = Vec::new();
+    for key in document.keys(SESSION_KEY_PREFIX).await {
+        match fetch(document, &key[SESSION_KEY_PREFIX.len()..]).await {
+            Ok(Some(session)) => sessions.push(session),
+            Ok(None) => {}
+            Err(e) => debug!(key = %key, "Skipping unreadable session: {:#}", e),
+        }
+    }
+    Ok(sessions)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// One site's sessions, for the `pear_session` host module
+/// Guest calls run on blocking threads, so these wait on the shared state directly.
+#[derive(Clone)]
+pub struct GuestSessions {
+    manager: Arc<SessionManager>,
+    site_id: String,
+}
+
+impl GuestSessions {
+    pub fn site_id(&self) -> &str {
+        &self.site_id
+    }
+
+    /// A new session and the `Set-Cookie` value for it
+    pub fn create(&self) -> Result<(UserSession, String)> {
+        let session = futures::executor::block_on(self.manager.create(&self.site_id))?;
+        let cookie = self.manager.cookie(&session);
+        Ok((session, cookie))
+    }
+
+    /// The session named by a `Cookie` header or session ID
+    pub fn load(&self, cookie: &str) -> Result<Option<UserSession>> {
+        match self.manager.session_id(cookie) {
+            Some(id) => futures::executor::block_on(self.manager.load(&self.site_id, id)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save(&self, session: UserSession) -> Result<()> {
+        futures::executor::block_on(self.manager.save(&self.site_id, session))
+    }
+
+    /// End the session, returning whether it existed and the `Set-Cookie` value clearing it
+    pub fn destroy(&self, cookie: &str) -> Result<(bool, String)> {
+        let existed = match self.manager.session_id(cookie) {
+            Some(id) => futures::executor::block_on(self.manager.destroy(&self.site_id, id))?,
+            None => false,
+        };
+        Ok((existed, self.manager.clear_cookie()))
+    }
+}
+
+impl std::fmt::Debug for GuestSessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuestSessions").field("site_id", &self.site_id).finish()
     }
 }
 
@@ -138,6 +458,61 @@ mod tests {
         assert_eq!(session.user_id, Some("user123".to_string()));
     }
 
+    fn manager(config: SessionConfig) -> Arc<SessionManager> {
+        Arc::new(SessionManager::new(config, Arc::new(DocumentStore::default())))
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let sessions = manager(SessionConfig::default());
+
+        let mut session = sessions.create("blog").await.unwrap();
+        assert_eq!(session.session_id.len(), 64);
+        session.authenticate("user123".to_string(), "token456".to_string());
+        sessions.save("blog", session.clone()).await.unwrap();
+
+        let loaded = sessions.load("blog", &session.session_id).await.unwrap().unwrap();
+        assert_eq!(loaded.user_id, Some("user123".to_string()));
+        assert!(sessions.load("shop", &session.session_id).await.unwrap().is_none());
+        assert_eq!(sessions.active_counts().await, vec![("blog".to_string(), 1)]);
+
+        assert!(sessions.destroy("blog", &session.session_id).await.unwrap());
+        assert!(sessions.load("blog", &session.session_id).await.unwrap().is_none());
+        assert!(sessions.save("blog", session).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_removes_expired_sessions() {
+        let sessions = manager(SessionConfig { timeout_secs: 60, ..Default::default() });
+        let fresh = sessions.create("blog").await.unwrap();
+        let mut stale = sessions.create("blog").await.unwrap();
+        stale.last_activity -= 120;
+        store(&sessions.documents.document("blog"), &stale).await.unwrap();
+
+        assert!(sessions.load("blog", &stale.session_id).await.unwrap().is_none());
+        assert_eq!(sessions.active_sessions("blog").await.unwrap(), 1);
+        assert_eq!(sessions.sweep().await.unwrap(), 1);
+        assert!(sessions.load("blog", &fresh.session_id).await.unwrap().is_some());
+        assert_eq!(sessions.documents.document("blog").keys(SESSION_KEY_PREFIX).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_session_limit_and_cookies() {
+        let sessions = manager(SessionConfig { max_sessions_per_site: 1, ..Default::default() });
+        let session = sessions.create("blog").await.unwrap();
+        assert!(sessions.create("blog").await.is_err());
+
+        let cookie = sessions.cookie(&session);
+        assert!(cookie.starts_with(&format!("pear_session={};", session.session_id)));
+        assert!(cookie.contains("HttpOnly") && cookie.ends_with("; Secure"));
+        assert!(sessions.clear_cookie().contains("Max-Age=0"));
+
+        let header = format!("theme=dark; pear_session={}", session.session_id);
+        assert_eq!(sessions.session_id(&header), Some(session.session_id.as_str()));
+        assert_eq!(sessions.session_id(&session.session_id), Some(session.session_id.as_str()));
+        assert_eq!(sessions.session_id("theme=dark"), None);
+    }
+
     #[test]
     fn test_shopping_cart() {
         let mut cart = ShoppingCart::new("cart-1".to_string());
@@ -1,11 +1,14 @@
 // CRDT Synchronization Protocol
 // Manages state propagation between Cage instances
 
-use super::CrdtStateManager;
+use super::{compact_together, CrdtStateManager, SyncState};
 use std::sync::Arc;
-use tokio::time::{Duration, Interval};
+use tokio::time::{Duration, Instant};
 use tracing::{info, debug, error, instrument};
 
+/// Message exchanges per sync before giving up until the next tick; two peers converge in a few
+const MAX_SYNC_ROUNDS: usize = 8;
+
 /// Synchronization configuration
 #[derive(Debug, Clone)]
 pub struct SyncConfig {
@@ -17,6 +20,12 @@ pub struct SyncConfig {
     
     /// Batch size (number of changes before forcing sync)
     pub batch_size: usize,
+    
+    /// Seconds between compactions of the synced documents (0 = never compact)
+    pub compaction_interval_secs: u64,
+    
+    /// Documents smaller than this are not compacted
+    pub compact_min_bytes: usize,
 }
 
 impl Default for SyncConfig {
@@ -25,16 +34,28 @@ impl Default for SyncConfig {
             sync_interval_ms: 100,  // Sync every 100ms
             enable_batching: true,
             batch_size: 10,
+            compaction_interval_secs: 300,
+            compact_min_bytes: 64 * 1024,
         }
     }
 }
 
+/// Sync state of every ordered pair of managers: `peers[i][j]` is what `i` knows of `j`
+type PeerStates = Vec<Vec<SyncState>>;
+
+fn new_peer_states(managers: usize) -> PeerStates {
+    (0..managers).map(|_| (0..managers).map(|_| SyncState::new()).collect()).collect()
+}
+
 /// Synchronization coordinator
 /// Manages periodic state sync between Cages in a pool
 pub struct SyncCoordinator {
     config: SyncConfig,
     running: Arc<std::sync::atomic::AtomicBool>,
     sync_count: Arc<std::sync::atomic::AtomicU64>,
+    message_count: Arc<std::sync::atomic::AtomicU64>,
+    payload_bytes: Arc<std::sync::atomic::AtomicU64>,
+    compaction_count: Arc<std::sync::atomic::AtomicU64>,
 }
 
 impl SyncCoordinator {
@@ -43,6 +64,9 @@ impl SyncCoordinator {
             config,
             running: Arc::new(std::sync::atomic::AtomicBool::new(false)),
             sync_count: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            message_count: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            payload_bytes: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            compaction_count: Arc::new(std::sync::atomic::AtomicU64::new(0)),
         }
     }
 
@@ -63,21 +87,51 @@ impl SyncCoordinator {
         let config = self.config.clone();
         let running = self.running.clone();
         let sync_count = self.sync_count.clone();
+        let message_count = self.message_count.clone();
+        let payload_bytes = self.payload_bytes.clone();
+        let compaction_count = self.compaction_count.clone();
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(
                 Duration::from_millis(config.sync_interval_ms)
             );
+            let compaction_interval = Duration::from_secs(config.compaction_interval_secs);
+            let mut last_compaction = Instant::now();
+            let mut peers = new_peer_states(managers.len());
 
             while running.load(std::sync::atomic::Ordering::Relaxed) {
                 interval.tick().await;
 
                 // Synchronize all managers
-                if let Err(e) = Self::sync_all(&managers).await {
-                    error!(error = %e, "Synchronization failed");
-                } else {
-                    sync_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                    debug!(sync_count = sync_count.load(std::sync::atomic::Ordering::Relaxed), "Sync completed");
+                match Self::sync_all(&managers, &mut peers).await {
+                    Ok(round) => {
+                        sync_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        message_count.fetch_add(round.messages, std::sync::atomic::Ordering::Relaxed);
+                        payload_bytes.fetch_add(round.payload_bytes, std::sync::atomic::Ordering::Relaxed);
+                        debug!(
+                            sync_count = sync_count.load(std::sync::atomic::Ordering::Relaxed),
+                            messages = round.messages,
+                            payload_bytes = round.payload_bytes,
+                            "Sync completed"
+                        );
+                    }
+                    Err(e) => error!(error = %e, "Synchronization failed"),
+                }
+
+                if config.compaction_interval_secs == 0 || last_compaction.elapsed() < compaction_interval {
+                    continue;
+                }
+                match Self::compact_all(&managers, &mut peers, config.compact_min_bytes).await {
+                    // Documents that have not converged yet are tried again next tick
+                    Ok(false) => {}
+                    Ok(true) => {
+                        last_compaction = Instant::now();
+                        compaction_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        last_compaction = Instant::now();
+                        error!(error = %e, "Compaction failed");
+                    }
                 }
             }
 
@@ -86,29 +140,59 @@ impl SyncCoordinator {
     }
 
     /// Synchronize all managers (delta-based)
-    async fn sync_all(managers: &[Arc<CrdtStateManager>]) -> anyhow::Result<()> {
+    /// Each pair exchanges sync messages until neither has anything the other lacks.
+    async fn sync_all(managers: &[Arc<CrdtStateManager>], peers: &mut PeerStates) -> anyhow::Result<SyncRound> {
+        let mut round = SyncRound::default();
         if managers.len() < 2 {
-            return Ok(());
+            return Ok(round);
         }
 
-        // Collect changes from all managers
-        let mut all_changes = Vec::new();
-        
-        for manager in managers {
-            let changes = manager.get_changes().await?;
-            all_changes.push(changes);
-        }
-
-        // Apply all changes to all managers (excluding self)
-        for (i, manager) in managers.iter().enumerate() {
-            for (j, changes) in all_changes.iter().enumerate() {
-                if i != j {
-                    manager.apply_changes(changes).await?;
+        for i in 0..managers.len() {
+            for j in (i + 1)..managers.len() {
+                let (left, right) = peers.split_at_mut(j);
+                let (i_of_j, j_of_i) = (&mut left[i][j], &mut right[0][i]);
+                for _ in 0..MAX_SYNC_ROUNDS {
+                    let mut quiet = true;
+                    if let Some(message) = managers[i].generate_sync_message(i_of_j).await {
+                        round.record(&message);
+                        managers[j].receive_sync_message(j_of_i, &message).await?;
+                        quiet = false;
+                    }
+                    if let Some(message) = managers[j].generate_sync_message(j_of_i).await {
+                        round.record(&message);
+                        managers[i].receive_sync_message(i_of_j, &message).await?;
+                        quiet = false;
+                    }
+                    if quiet {
+                        break;
+                    }
                 }
             }
         }
 
-        Ok(())
+        Ok(round)
+    }
+
+    /// Drop the history of the synced documents, once they have converged
+    /// Returns whether they were compacted.
+    async fn compact_all(managers: &[Arc<CrdtStateManager>], peers: &mut PeerStates, min_bytes: usize) -> anyhow::Result<bool> {
+        match managers.first() {
+            Some(first) if first.size().await >= min_bytes => {}
+            _ => return Ok(false),
+        }
+        let Some(compaction) = compact_together(managers).await? else {
+            return Ok(false);
+        };
+
+        // The old sync states describe history that no longer exists
+        *peers = new_peer_states(managers.len());
+        info!(
+            before_bytes = compaction.before_bytes,
+            after_bytes = compaction.after_bytes,
+            documents = managers.len(),
+            "CRDT documents compacted"
+        );
+        Ok(true)
     }
 
     /// Stop synchronization
@@ -122,15 +206,40 @@ impl SyncCoordinator {
         SyncStats {
             total_syncs: self.sync_count.load(std::sync::atomic::Ordering::Relaxed),
             is_running: self.running.load(std::sync::atomic::Ordering::Relaxed),
+            messages: self.message_count.load(std::sync::atomic::Ordering::Relaxed),
+            payload_bytes: self.payload_bytes.load(std::sync::atomic::Ordering::Relaxed),
+            compactions: self.compaction_count.load(std::sync::atomic::Ordering::Relaxed),
         }
     }
 }
 
+/// Traffic of one sync of all managers
+#[derive(Debug, Default)]
+struct SyncRound {
+    messages: u64,
+    payload_bytes: u64,
+}
+
+impl SyncRound {
+    fn record(&mut self, message: &[u8]) {
+        self.messages += 1;
+        self.payload_bytes += message.len() as u64;
+    }
+}
+
 /// Synchronization statistics
 #[derive(Debug, Clone)]
 pub struct SyncStats {
     pub total_syncs: u64,
     pub is_running: bool,
+    
+    /// Sync messages sent between managers
+    pub messages: u64,
+    
+    /// Bytes of all sync messages sent
+    pub payload_bytes: u64,
+    
+    pub compactions: u64,
 }
 
 #[cfg(test)]
@@ -158,10 +267,49 @@ mod tests {
         manager2.set("key2", serde_json::json!("value2")).await.unwrap();
         
         // Sync
-        SyncCoordinator::sync_all(&[manager1.clone(), manager2.clone()]).await.unwrap();
+        let managers = [manager1.clone(), manager2.clone()];
+        let mut peers = new_peer_states(2);
+        SyncCoordinator::sync_all(&managers, &mut peers).await.unwrap();
         
         // Both should have both keys
         assert!(manager1.get("key2").await.unwrap().is_some());
         assert!(manager2.get("key1").await.unwrap().is_some());
+        
+        // Once converged, a sync sends only the changes made since
+        for i in 0..200 {
+            manager1.set(&format!("bulk{}", i), serde_json::json!(uuid::Uuid::new_v4().to_string())).await.unwrap();
+        }
+        SyncCoordinator::sync_all(&managers, &mut peers).await.unwrap();
+        manager1.set("key3", serde_json::json!("value3")).await.unwrap();
+        let round = SyncCoordinator::sync_all(&managers, &mut peers).await.unwrap();
+        assert!(round.messages > 0);
+        assert!(round.payload_bytes < manager1.size().await as u64 / 4);
+        assert_eq!(manager2.get("key3").await.unwrap(), Some(serde_json::json!("value3")));
+    }
+
+    #[tokio::test]
+    async fn test_compaction_after_convergence() {
+        let manager1 = Arc::new(CrdtStateManager::new("site1".to_string()));
+        let manager2 = Arc::new(CrdtStateManager::new("site2".to_string()));
+        let managers = [manager1.clone(), manager2.clone()];
+        let mut peers = new_peer_states(2);
+        
+        for i in 0..200 {
+            manager1.set("counter", serde_json::json!(i)).await.unwrap();
+        }
+        let before = manager1.size().await;
+        
+        // Not compacted while manager2 is missing changes
+        assert!(!SyncCoordinator::compact_all(&managers, &mut peers, 0).await.unwrap());
+        
+        SyncCoordinator::sync_all(&managers, &mut peers).await.unwrap();
+        assert!(SyncCoordinator::compact_all(&managers, &mut peers, 0).await.unwrap());
+        assert!(manager1.size().await < before);
+        assert_eq!(manager2.get("counter").await.unwrap(), Some(serde_json::json!(199)));
+        
+        // Compacted documents keep syncing
+        manager2.set("key", serde_json::json!("value")).await.unwrap();
+        SyncCoordinator::sync_all(&managers, &mut peers).await.unwrap();
+        assert_eq!(manager1.get("key").await.unwrap(), Some(serde_json::json!("value")));
     }
 }
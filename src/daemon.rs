@@ -0,0 +1,181 @@
+// Daemon
+// Starts every component of the server from its configuration and serves until shutdown or upgrade
+
+use crate::{cli, config, network, node, observability, runtime, signals, upgrade};
+use anyhow::Result;
+use futures::StreamExt;
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// Run the Pear Server daemon until shutdown, or until a successor takes over on upgrade
+pub async fn run(pear_config: config::PearConfig, _foreground: bool, logs: Arc<observability::logs::LogBuffer>) -> Result<()> {
+
+    info!("🍐 Pear Server Phase 3 - Complete System: CLI + Dashboard + Auto-Config");
+    info!("Initializing userspace pseudo-operating system daemon...");
+
+    // Configure runtime limits (file descriptors, memory, etc.)
+    runtime::configure_limits()?;
+    info!("✓ Runtime limits configured");
+
+    let kubernetes = pear_config.kubernetes.clone();
+    if kubernetes.enabled {
+        kubernetes.enter_work_dir()?;
+        info!(
+            work_dir = %std::env::current_dir()?.display(),
+            termination_grace_secs = kubernetes.termination_grace_secs,
+            "✓ Kubernetes mode"
+        );
+    }
+
+    // Set up graceful shutdown signal handlers
+    let shutdown_signal = signals::create_shutdown_listener()?;
+    let mut upgrade_signal = signals::create_upgrade_listener()?;
+    info!("✓ Signal handlers installed (SIGTERM, SIGINT, SIGUSR2)");
+
+    // Remember how we were launched so an upgrade can exec the same command line
+    let current_exe = std::env::current_exe()?;
+    let launch_args: Vec<std::ffi::OsString> = std::env::args_os().skip(1).collect();
+
+    // Read before the config moves into the node; on Kubernetes the whole shutdown fits the grace period
+    let drain_timeout = if kubernetes.enabled {
+        kubernetes.drain_timeout()
+    } else {
+        std::time::Duration::from_secs(pear_config.server.drain_timeout_secs)
+    };
+
+    // Start every component, serving as the unprivileged user; the demonstration site only on request
+    let mut builder = node::PearNode::builder();
+    if pear_config.server.demo_mode {
+        builder = builder.with_site("default-site", create_default_wasm_module());
+    }
+    let node = builder
+        .with_config(pear_config)
+        .with_logs(logs)
+        .drop_privileges()
+        .start()
+        .await?;
+    let pear_config = node.config();
+
+    // Publish our PID once listeners are up; a predecessor waits on this during upgrades
+    let pid_file = std::path::PathBuf::from(&pear_config.server.pid_file);
+    upgrade::write_pid_file(&pid_file)?;
+    info!(pid_file = %pid_file.display(), "✓ PID file written");
+
+    // On Kubernetes stdout is for log lines only
+    if !kubernetes.enabled {
+        print_ready(pear_config)?;
+        if node.sites().is_empty() {
+            cli::info("No sites deployed yet: deploy one with `pear deploy <module> --site <name>`");
+            println!();
+        }
+    }
+
+    // Wait for shutdown, or hand our listeners to a new binary on SIGUSR2
+    tokio::pin!(shutdown_signal);
+    
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("🛑 Shutdown signal received - Initiating graceful shutdown");
+                upgrade::remove_pid_file(&pid_file);
+                break;
+            }
+            Some(()) = upgrade_signal.next() => {
+                match upgrade_to_successor(&current_exe, &launch_args, &pid_file, drain_timeout).await {
+                    Ok(()) => {
+                        info!("🔁 Successor is serving - draining this process");
+                        break;
+                    }
+                    Err(e) => error!("Upgrade aborted, continuing to serve: {:#}", e),
+                }
+            }
+        }
+    }
+
+    // Endpoints drop a pod some time after SIGTERM; keep serving while /readyz reports the shutdown
+    if kubernetes.enabled && kubernetes.shutdown_delay_secs > 0 {
+        node.health().begin_shutdown();
+        info!(delay_secs = kubernetes.shutdown_delay_secs, "Failing readiness before draining");
+        tokio::time::sleep(std::time::Duration::from_secs(kubernetes.shutdown_delay_secs)).await;
+    }
+
+    node.shutdown(drain_timeout).await;
+
+    info!("✓ Graceful shutdown complete - No zombie processes");
+    info!("👋 Pear Server stopped");
+
+    Ok(())
+}
+
+/// The ready banner with every listener's address
+fn print_ready(pear_config: &config::PearConfig) -> Result<()> {
+    let listeners = pear_config.server.effective_listeners();
+    println!();
+    cli::success("🚀 Pear Server Phase 3 is ready - All systems operational");
+    println!();
+    println!("  {} Architecture: HTTP → Router → CagePool → WebAssembly Cages", "🎯".bright_cyan());
+    println!("  {} Security: AI-powered anomaly detection active", "🔒".bright_cyan());
+    println!("  {} Self-Healing: Automatic crash recovery enabled", "🩺".bright_cyan());
+    println!("  {} Configuration: Smart defaults with pear.toml override", "⚙️".bright_cyan());
+    println!();
+    for listener in &listeners {
+        if listener.redirect_https {
+            cli::info(&format!("HTTPS redirect: http://{} → port {}", listener.socket_addr()?, listener.https_port));
+            continue;
+        }
+        let scheme = if listener.tls { "https" } else { "http" };
+        let protocol = match listener.protocol {
+            network::ListenerProtocol::Http2 => "HTTP/2",
+            network::ListenerProtocol::Http3 => "HTTP/3",
+        };
+        cli::info(&format!("{} server: {}://{}", protocol, scheme, listener.socket_addr()?));
+    }
+    if pear_config.dashboard.enabled {
+        cli::info(&format!("Dashboard: http://localhost:{} 📊", pear_config.dashboard.port));
+    }
+    println!();
+    cli::info("Press Ctrl+C for graceful shutdown");
+    println!();
+    Ok(())
+}
+
+/// Exec the new binary with our listeners and wait until it is serving
+async fn upgrade_to_successor(
+    current_exe: &std::path::Path,
+    args: &[std::ffi::OsString],
+    pid_file: &std::path::Path,
+    timeout: std::time::Duration,
+) -> Result<()> {
+    // `pear upgrade --binary` leaves the path to exec next to the PID file
+    let next_binary_file = upgrade::next_binary_path(pid_file);
+    let binary = match std::fs::read_to_string(&next_binary_file) {
+        Ok(path) => {
+            let _ = std::fs::remove_file(&next_binary_file);
+            std::path::PathBuf::from(path.trim())
+        }
+        Err(_) => current_exe.to_path_buf(),
+    };
+
+    info!(
+        binary = %binary.display(),
+        listeners = upgrade::registered_count(),
+        "Starting successor process"
+    );
+
+    let mut child = upgrade::spawn_successor(&binary, args)?;
+    upgrade::wait_for_successor(&mut child, pid_file, timeout).await
+}
+
+/// Create a simple default WebAssembly module for demonstration
+fn create_default_wasm_module() -> Vec<u8> {
+    // Simple WAT module that exports a function
+    let wat = r#"
+        (module
+            (func (export "handle_request") (result i32)
+                i32.const 42
+            )
+        )
+    "#;
+    
+    wat::parse_str(wat).expect("Failed to parse WAT module")
+}
@@ -0,0 +1,389 @@
+// Access API
+// Custom roles and scoped API tokens, the check that guards reads when `[rbac] protect_reads` is set,
+// and lockouts for clients that keep failing to log in
+
+use axum::{
+    extract::{MatchedPath, Path, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::api::{bearer_token, client_ip, require, site_scope, tenant_scope};
+use super::audit::with_change;
+use super::DashboardState;
+use crate::observability::audit::{AuditChange, AuditEntry};
+use crate::tenancy::auth::{AuthManager, CustomRole, Permission, Role, Scope, TokenClaims, TokenRequest};
+use crate::tenancy::lockout::{Lockout, LoginGuard};
+
+/// Filter for listing roles and tokens
+#[derive(Deserialize)]
+pub struct TenantQuery {
+    pub tenant: Option<Uuid>,
+}
+
+/// Permissions granted by a custom role
+#[derive(Deserialize)]
+pub struct RoleUpdate {
+    pub tenant_id: Option<Uuid>,
+    pub permissions: BTreeSet<Permission>,
+}
+
+/// A token to issue
+#[derive(Deserialize)]
+pub struct TokenCreate {
+    pub name: String,
+    pub tenant_id: Option<Uuid>,
+    pub role: String,
+    #[serde(default)]
+    pub sites: Vec<String>,
+    #[serde(default)]
+    pub read_only: bool,
+    pub expires_in_days: Option<u32>,
+}
+
+/// What the presented token may do
+pub async fn whoami(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(auth) = &state.auth else {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Management is disabled; set dashboard.admin_token to enable it" })),
+        );
+    };
+    match bearer_token(&headers).map(|token| auth.validate_token(token)) {
+        Some(Ok(claims)) => (StatusCode::OK, Json(json!({
+            "role": claims.role.name(),
+            "tenant_id": claims.tenant_id,
+            "permissions": claims.permissions,
+            "sites": claims.sites,
+            "subject": claims.subject,
+            "expires_at": (claims.exp != i64::MAX).then_some(claims.exp),
+        }))),
+        Some(Err(e)) => (StatusCode::UNAUTHORIZED, Json(json!({ "error": format!("{:#}", e) }))),
+        None => (StatusCode::UNAUTHORIZED, Json(json!({ "error": "Missing or invalid token" }))),
+    }
+}
+
+/// Built-in and custom roles, all or those a tenant can use
+pub async fn list_roles(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Query(query): Query<TenantQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, query.tenant.map_or(Scope::Node, Scope::Tenant)) {
+        return response;
+    }
+    let auth = manager(&state);
+
+    let all: Vec<_> = Permission::ALL.iter().map(Permission::as_str).collect();
+    let builtin = [Role::RootAdmin, Role::TenantAdmin].map(|role| {
+        json!({ "name": role.name(), "tenant_id": null, "permissions": all, "builtin": true })
+    });
+    let custom = auth.roles(query.tenant).into_iter().map(|role| {
+        json!({ "name": role.name, "tenant_id": role.tenant_id, "permissions": role.permissions, "builtin": false })
+    });
+    (StatusCode::OK, Json(json!({ "roles": builtin.into_iter().chain(custom).collect::<Vec<_>>() })))
+}
+
+/// Create or replace a custom role; it can't grant more than the caller holds
+pub async fn set_role(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(update): Json<RoleUpdate>,
+) -> Response {
+    let claims = match require(&state, &headers, Permission::ManageTenants, update.tenant_id.map_or(Scope::Node, Scope::Tenant)) {
+        Ok(claims) => claims,
+        Err(response) => return response.into_response(),
+    };
+    let auth = manager(&state);
+    if let Err(response) = check_tenant(&state, update.tenant_id) {
+        return response.into_response();
+    }
+    if let Err(response) = check_grant(&claims, &update.permissions) {
+        return response.into_response();
+    }
+
+    let before = auth.roles(update.tenant_id).into_iter()
+        .find(|role| role.name == name && role.tenant_id == update.tenant_id);
+    let role = CustomRole { name, tenant_id: update.tenant_id, permissions: update.permissions };
+    match auth.set_role(role.clone()) {
+        Ok(()) => {
+            info!(role = %role.name, "Role saved via API");
+            with_change((StatusCode::OK, Json(json!(role))), before.map(|role| json!(role)), Some(json!(role)))
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": format!("{:#}", e) }))).into_response(),
+    }
+}
+
+/// Remove a custom role no token uses
+pub async fn remove_role(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Query(query): Query<TenantQuery>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, query.tenant.map_or(Scope::Node, Scope::Tenant)) {
+        return response.into_response();
+    }
+    let auth = manager(&state);
+
+    let before = auth.roles(query.tenant).into_iter()
+        .find(|role| role.name == name && role.tenant_id == query.tenant);
+    match auth.remove_role(query.tenant, &name) {
+        Ok(true) => {
+            info!(role = %name, "Role removed via API");
+            with_change((StatusCode::OK, Json(json!({ "name": name }))), before.map(|role| json!(role)), None)
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Role '{}' not found", name) })),
+        ).into_response(),
+        Err(e) => (StatusCode::CONFLICT, Json(json!({ "error": format!("{:#}", e) }))).into_response(),
+    }
+}
+
+/// API tokens, all or a tenant's; secrets are never listed
+pub async fn list_tokens(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Query(query): Query<TenantQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, query.tenant.map_or(Scope::Node, Scope::Tenant)) {
+        return response;
+    }
+    let auth = manager(&state);
+    (StatusCode::OK, Json(json!({ "tokens": auth.tokens(query.tenant) })))
+}
+
+/// Issue a token; the response carries its secret, shown only this once
+pub async fn create_token(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Json(request): Json<TokenCreate>,
+) -> Response {
+    let claims = match require(&state, &headers, Permission::ManageTenants, request.tenant_id.map_or(Scope::Node, Scope::Tenant)) {
+        Ok(claims) => claims,
+        Err(response) => return response.into_response(),
+    };
+    let auth = manager(&state);
+    if let Err(response) = check_tenant(&state, request.tenant_id) {
+        return response.into_response();
+    }
+    if let Some(site) = request.sites.iter()
+        .find(|site| request.tenant_id.is_some() && state.tenants.find_site_tenant(site) != request.tenant_id)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("Site '{}' does not belong to the token's tenant", site) })),
+        ).into_response();
+    }
+
+    let role = Role::parse(&request.role);
+    let mut granted = match auth.permissions_of(request.tenant_id, &role) {
+        Ok(permissions) => permissions,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(json!({ "error": format!("{:#}", e) }))).into_response(),
+    };
+    if request.read_only {
+        granted.retain(|permission| *permission == Permission::ViewMetrics);
+    }
+    if let Err(response) = check_grant(&claims, &granted) {
+        return response.into_response();
+    }
+
+    let expires_at = request.expires_in_days
+        .map(|days| chrono::Utc::now().timestamp() + i64::from(days) * 86_400);
+    match auth.issue_token(TokenRequest {
+        name: request.name,
+        tenant_id: request.tenant_id,
+        role,
+        sites: request.sites,
+        read_only: request.read_only,
+        expires_at,
+    }) {
+        Ok((token, secret)) => {
+            info!(token_id = %token.id, "API token issued via API");
+            let token = json!(token);
+            let mut body = token.clone();
+            body["secret"] = secret.into();
+            with_change((StatusCode::CREATED, Json(body)), None, Some(token))
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": format!("{:#}", e) }))).into_response(),
+    }
+}
+
+/// Revoke a token; requests using it fail from now on
+pub async fn revoke_token(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let scope = state.auth.as_ref()
+        .and_then(|auth| auth.tokens(None).into_iter().find(|token| token.id == id))
+        .and_then(|token| token.tenant_id)
+        .map_or(Scope::Node, Scope::Tenant);
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, scope) {
+        return response.into_response();
+    }
+    let auth = manager(&state);
+
+    match auth.revoke_token(id) {
+        Ok(Some(token)) => {
+            info!(token_id = %id, "API token revoked via API");
+            with_change((StatusCode::OK, Json(json!({ "id": id }))), Some(json!(token)), None)
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Token {} not found", id) })),
+        ).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("{:#}", e) }))).into_response(),
+    }
+}
+
+/// Middleware requiring `view-metrics` on reads of `/api` and `/metrics` when `[rbac] protect_reads` is set
+/// The scope comes from the path, so a site-limited token can read its own sites.
+pub async fn guard_reads(
+    State(state): State<Arc<DashboardState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let guarded = state.auth.as_ref().map_or(false, |auth| auth.protects_reads())
+        && *request.method() == Method::GET
+        && (path.starts_with("/api/") || path == "/metrics")
+        && path != "/api/whoami";
+    if !guarded {
+        return next.run(request).await;
+    }
+
+    let segments: Vec<&str> = path.split('/').skip(2).collect();
+    let scope = match segments.as_slice() {
+        ["sites", site_id, ..] | [_, "sites", site_id, ..] => site_scope(&state, site_id),
+        ["tenants", tenant_id, ..] => tenant_scope(&state, tenant_id),
+        _ => Scope::Node,
+    };
+    if let Err(response) = require(&state, request.headers(), Permission::ViewMetrics, scope) {
+        return response.into_response();
+    }
+    next.run(request).await
+}
+
+/// Middleware locking out clients that keep presenting invalid tokens or failing single sign-on, when `[lockout]` is enabled
+/// Banned clients are refused outright, and clients locked out `ban_after_lockouts` times are banned.
+pub async fn guard_logins(
+    State(state): State<Arc<DashboardState>>,
+    matched: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(guard) = state.auth.as_ref().and_then(|auth| auth.lockout()).cloned() else {
+        return next.run(request).await;
+    };
+    let sso = request.uri().path() == "/auth/callback";
+    let attempt = sso || bearer_token(request.headers()).is_some();
+    let Some(ip) = client_ip(request.extensions())
+        .filter(|ip| attempt && !state.ai_module.allowlist().allows(*ip))
+    else {
+        return next.run(request).await;
+    };
+
+    if state.ai_module.path_monitor().is_banned(ip) {
+        return (StatusCode::FORBIDDEN, Json(json!({ "error": "This address is banned", "banned": true }))).into_response();
+    }
+    let key = LoginGuard::ip_key(ip);
+    if let Some(remaining) = guard.check(&key) {
+        return locked_out(remaining);
+    }
+
+    let route = matched.as_ref().map_or(request.uri().path(), |matched| matched.as_str());
+    let action = format!("login-failed {} {}", request.method(), route);
+    let target = request.uri().path().to_string();
+    let response = next.run(request).await;
+    let status = response.status();
+    if status != StatusCode::UNAUTHORIZED && !(sso && status.is_client_error()) {
+        return response;
+    }
+
+    warn!(ip = %ip, target = %target, "Failed management login");
+    record_login(&state, action, target.clone(), ip, status);
+    if let Some(lockout) = guard.record_failure(&key) {
+        lock_out(&state, &key, ip, lockout);
+        let ban_after = guard.config().ban_after_lockouts;
+        if ban_after > 0 && lockout.count >= ban_after {
+            warn!(ip = %ip, lockouts = lockout.count, "Banning client for repeated failed management logins");
+            state.ai_module.ban_login_abuser(ip, &target, lockout.count);
+        }
+    }
+    response
+}
+
+/// Log and audit a lockout of `key`, a client address or an account
+pub(super) fn lock_out(state: &DashboardState, key: &str, ip: IpAddr, lockout: Lockout) {
+    warn!(key = %key, secs = lockout.duration.as_secs(), lockouts = lockout.count, "Locked out of the management API");
+    record_login(state, "login-lockout".to_string(), key.to_string(), ip, StatusCode::TOO_MANY_REQUESTS);
+}
+
+/// Refusal while a client or account is locked out
+pub(super) fn locked_out(remaining: Duration) -> Response {
+    let secs = remaining.as_secs().max(1);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        Json(json!({ "error": format!("Too many failed logins; try again in {} seconds", secs) })),
+    ).into_response()
+}
+
+/// Audit a failed login, which carries no valid token to name an actor by
+fn record_login(state: &DashboardState, action: String, target: String, ip: IpAddr, status: StatusCode) {
+    if let Some(audit) = &state.audit {
+        audit.record(AuditEntry {
+            id: 0,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            actor: None,
+            action,
+            target,
+            status: status.as_u16(),
+            ip: Some(ip),
+            change: AuditChange::default(),
+        });
+    }
+}
+
+/// The auth manager, once `require` has passed
+fn manager(state: &DashboardState) -> &AuthManager {
+    state.auth.as_deref().expect("require passes only with auth configured")
+}
+
+/// A tenant named in a request body must exist
+fn check_tenant(state: &DashboardState, tenant_id: Option<Uuid>) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    match tenant_id {
+        Some(tenant_id) if state.tenants.get_tenant(tenant_id).is_none() => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Tenant {} not found", tenant_id) })),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Callers can only hand out permissions they hold themselves
+fn check_grant(claims: &TokenClaims, permissions: &BTreeSet<Permission>) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    match permissions.iter().find(|permission| !claims.permissions.contains(permission)) {
+        Some(permission) => Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": format!("Cannot grant {}, which the token lacks", permission.as_str()) })),
+        )),
+        None => Ok(()),
+    }
+}
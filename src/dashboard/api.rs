@@ -0,0 +1,2084 @@
+// Management REST API
+// JSON endpoints for inspecting and updating server state at runtime
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use super::audit::with_change;
+use super::DashboardState;
+use crate::ai::events::{EventQuery, SecurityEvent};
+use crate::ai::policy::{AiPolicy, ResolvedPolicy};
+use crate::ai::waf::{RuleSetConfig, RuleStats};
+use crate::crdt::pubsub::Message;
+use crate::mail::{MailRelay, TenantMailPolicy};
+use crate::router::acl::AclRule;
+use crate::router::health::{HealthCheckOverrides, HealthChecksConfig};
+use crate::scheduler::JobSpec;
+use crate::supervisor::history::HealingQuery;
+use crate::storage::artifacts::ArtifactSourceRequest;
+use crate::tenancy::{ResourceQuota, Tenant};
+use crate::tenancy::auth::{Permission, Scope, TokenClaims};
+use crate::tenancy::bandwidth::{BandwidthQuota, Period};
+use crate::tenancy::domains::{Domain, VerificationMethod};
+
+/// Filter for listing scheduled jobs
+#[derive(Deserialize)]
+pub struct CronQuery {
+    pub site: Option<String>,
+}
+
+/// Paging for a tenant's mail delivery log
+#[derive(Deserialize)]
+pub struct MailLogQuery {
+    #[serde(default = "default_mail_log_limit")]
+    pub limit: usize,
+}
+
+fn default_mail_log_limit() -> usize { 100 }
+
+/// Range of metrics history to return
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// How far back to go, in seconds
+    #[serde(default = "default_history_range")]
+    pub range_secs: i64,
+
+    /// Rollups are merged so roughly this many are returned
+    #[serde(default = "default_history_points")]
+    pub points: i64,
+}
+
+fn default_history_range() -> i64 { 3600 }
+fn default_history_points() -> i64 { 120 }
+
+/// Month of billing records to return
+#[derive(Deserialize)]
+pub struct BillingQuery {
+    /// Month as YYYYMM (the current one if unset)
+    pub period: Option<u32>,
+}
+
+/// Body of a tenant creation request
+#[derive(Deserialize)]
+pub struct NewTenant {
+    pub name: String,
+    pub email: String,
+
+    /// Defaults to the standard quota
+    #[serde(default)]
+    pub quota: Option<ResourceQuota>,
+}
+
+/// Body of a request adding a site to a tenant
+#[derive(Deserialize)]
+pub struct NewSite {
+    pub name: String,
+
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
+/// Body of a site domain update
+#[derive(Deserialize)]
+pub struct DomainUpdate {
+    pub domain: String,
+}
+
+/// Body of a request claiming another hostname for a site
+#[derive(Deserialize)]
+pub struct NewDomain {
+    pub hostname: String,
+
+    #[serde(default)]
+    pub method: VerificationMethod,
+
+    /// Redirect to another of the site's hostnames instead of serving the site
+    #[serde(default)]
+    pub redirect_to: Option<String>,
+}
+
+/// Body of a request registering a tenant signing key
+#[derive(Deserialize)]
+pub struct NewSigningKey {
+    pub name: String,
+
+    /// Raw ed25519 key in base64 or hex, or a PEM public key
+    pub public_key: String,
+}
+
+/// Body of a request to shed the node's connections
+#[derive(Deserialize)]
+pub struct RebalanceStart {
+    /// Seconds the shedding is spread over
+    pub over_secs: u64,
+}
+
+/// Body of a site signature requirement update
+#[derive(Deserialize)]
+pub struct SigningUpdate {
+    pub required: bool,
+}
+
+/// Body of a site environment update
+#[derive(Deserialize)]
+pub struct EnvUpdate {
+    pub value: String,
+
+    /// Store the value encrypted and mask it in listings
+    #[serde(default)]
+    pub secret: bool,
+}
+
+/// Current counters, latency since startup, and the last hour from the metrics history
+pub async fn status(
+    State(state): State<Arc<DashboardState>>,
+) -> Json<serde_json::Value> {
+    let router = state.router.stats();
+    let last_hour = state.metrics_history.as_ref()
+        .and_then(|history| history.summary(chrono::Utc::now().timestamp() - 3600));
+
+    Json(json!({
+        "uptime_seconds": state.telemetry.uptime_seconds(),
+        "requests": {
+            "total": router.total_requests,
+            "successful": router.successful_requests,
+            "failed": router.failed_requests,
+            "errors": router.errors,
+            "success_rate": router.success_rate(),
+        },
+        "latency": router.latency,
+        "pools": router.active_pools,
+        "wasm_executor": router.wasm,
+        "admission": router.admission,
+        "outliers": router.outliers,
+        "retries": router.retries,
+        "healing_events": state.supervisor.stats().healing_events,
+        "threats_detected": state.ai_module.stats().threats_detected,
+        "last_hour": last_hour,
+    }))
+}
+
+/// Minute or hour rollups over `range_secs`, merged down to about `points`
+pub async fn metrics_history(
+    State(state): State<Arc<DashboardState>>,
+    Query(query): Query<HistoryQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(history) = &state.metrics_history else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Metrics history is disabled" })),
+        );
+    };
+
+    let range = query.range_secs.max(60);
+    let step = range / query.points.max(1);
+    let rollups = history.query(chrono::Utc::now().timestamp() - range, step);
+    (StatusCode::OK, Json(json!({ "range_secs": range, "rollups": rollups })))
+}
+
+/// Every Cage pool with its health, traffic, reserved memory and Cages
+pub async fn pools(
+    State(state): State<Arc<DashboardState>>,
+) -> Json<serde_json::Value> {
+    let traffic: std::collections::HashMap<_, _> = state.router.site_traffic().into_iter()
+        .map(|site| (site.site_id.clone(), site))
+        .collect();
+
+    let mut site_ids = state.router.site_ids();
+    site_ids.sort();
+    let mut pools = Vec::new();
+    for site_id in site_ids {
+        let Some(pool) = state.router.pool(&site_id) else { continue };
+        let cages = pool.cage_stats().await;
+        let site = traffic.get(&site_id);
+        pools.push(json!({
+            "site_id": site_id,
+            "health": pool.health_stats().await,
+            "requests": site.map(|site| site.requests).unwrap_or(0),
+            "errors": site.map(|site| site.errors).unwrap_or(0),
+            "latency": site.map(|site| site.latency.percentiles()).unwrap_or_default(),
+            "memory_bytes": cages.iter().map(|cage| cage.memory_limit_bytes).sum::<usize>(),
+            "cages": cages,
+        }));
+    }
+
+    Json(json!({ "pools": pools }))
+}
+
+/// Open client connections with their request counts and idle times
+/// Admin only, since it lists client addresses.
+pub async fn connections(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ViewMetrics, Scope::Node) {
+        return response;
+    }
+    let report = state.router.state().connection_report();
+    (StatusCode::OK, Json(json!(report)))
+}
+
+/// Progress of shedding connections to other nodes
+pub async fn rebalance_status(
+    State(state): State<Arc<DashboardState>>,
+) -> Json<serde_json::Value> {
+    Json(json!(state.rebalancer.status()))
+}
+
+/// Close every connection gradually and fail readiness, ahead of maintenance
+pub async fn start_rebalance(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Json(request): Json<RebalanceStart>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, Scope::Node) {
+        return response;
+    }
+    match state.rebalancer.start(std::time::Duration::from_secs(request.over_secs)) {
+        Ok(status) => {
+            info!(over_secs = request.over_secs, "Rebalance started via API");
+            (StatusCode::OK, Json(json!(status)))
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": format!("{:#}", e) }))),
+    }
+}
+
+/// Stop shedding connections and take traffic again
+pub async fn cancel_rebalance(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, Scope::Node) {
+        return response;
+    }
+    if !state.rebalancer.cancel() {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": "No rebalance in progress" })));
+    }
+    info!("Rebalance cancelled via API");
+    (StatusCode::OK, Json(json!(state.rebalancer.status())))
+}
+
+/// Latency percentiles per site and per Cage, with each site's sampled baseline
+pub async fn latency(
+    State(state): State<Arc<DashboardState>>,
+) -> Json<serde_json::Value> {
+    let mut sites = Vec::new();
+    for site in state.router.site_traffic() {
+        let cages = match state.router.pool(&site.site_id) {
+            Some(pool) => pool.cage_latencies().await.into_iter()
+                .map(|cage| json!({ "cage_id": cage.cage_id, "latency": cage.latency.percentiles() }))
+                .collect(),
+            None => Vec::new(),
+        };
+        sites.push(json!({
+            "site_id": site.site_id,
+            "requests": site.requests,
+            "errors": site.errors,
+            "latency": site.latency.percentiles(),
+            "baseline": state.ai_module.performance(&site.site_id),
+            "cages": cages,
+        }));
+    }
+
+    Json(json!({ "overall": state.router.stats().latency, "sites": sites }))
+}
+
+/// List every WAF rule with its hit counter
+pub async fn security_rules(
+    State(state): State<Arc<DashboardState>>,
+) -> Json<Vec<RuleStats>> {
+    Json(state.ai_module.waf().rule_stats())
+}
+
+/// Browse recent security events, newest first
+/// Filters: `limit`, `min_severity`, `site_id`, `kind`
+pub async fn security_events(
+    State(state): State<Arc<DashboardState>>,
+    Query(query): Query<EventQuery>,
+) -> Json<Vec<SecurityEvent>> {
+    Json(state.ai_module.events().recent(&query))
+}
+
+/// Currently banned IPs with seconds since each ban
+pub async fn security_bans(
+    State(state): State<Arc<DashboardState>>,
+) -> Json<serde_json::Value> {
+    let bans: Vec<_> = state.ai_module.path_monitor().bans().into_iter()
+        .map(|(ip, age)| json!({ "ip": ip, "banned_secs": age.as_secs() }))
+        .collect();
+    Json(json!({ "bans": bans }))
+}
+
+/// Ban an IP until the scan ban TTL runs out
+pub async fn ban_ip(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(ip): Path<IpAddr>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageBans, Scope::Node) {
+        return response;
+    }
+    state.ai_module.path_monitor().manual_ban(ip);
+    info!(ip = %ip, "IP banned via API");
+    (StatusCode::OK, Json(json!({ "ip": ip, "banned": true })))
+}
+
+/// Lift every ban on an IP
+pub async fn unban_ip(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(ip): Path<IpAddr>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageBans, Scope::Node) {
+        return response;
+    }
+    if !state.ai_module.unban(ip) {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": format!("{} is not banned", ip) })));
+    }
+    info!(ip = %ip, "IP unbanned via API");
+    (StatusCode::OK, Json(json!({ "ip": ip, "banned": false })))
+}
+
+/// Effective AI policy for a site
+pub async fn site_policy(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> Json<ResolvedPolicy> {
+    Json(state.ai_module.policy(&site_id))
+}
+
+/// Replace a site's AI policy overrides
+pub async fn update_site_policy(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(policy): Json<AiPolicy>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageBans, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match state.ai_module.set_site_policy(&site_id, policy) {
+        Ok(()) => {
+            info!(site_id = %site_id, "Site AI policy updated via API");
+            (StatusCode::OK, Json(json!(state.ai_module.policy(&site_id))))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Replace a site's WAF rules and sensitive paths
+pub async fn update_site_rules(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(rules): Json<RuleSetConfig>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageBans, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match state.ai_module.set_site_rules(&site_id, &rules) {
+        Ok(()) => {
+            info!(site_id = %site_id, rules = rules.rules.len(), "Site security rules updated via API");
+            (StatusCode::OK, Json(json!({ "site_id": site_id, "rules": rules.rules.len() })))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Bandwidth usage and quota state of every site and tenant
+pub async fn bandwidth(
+    State(state): State<Arc<DashboardState>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match state.router.bandwidth_meter() {
+        Some(meter) => (StatusCode::OK, Json(json!(meter.report()))),
+        None => bandwidth_disabled(),
+    }
+}
+
+/// Daily and monthly traffic history for a site
+pub async fn site_bandwidth(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match state.router.bandwidth_meter() {
+        Some(meter) => (
+            StatusCode::OK,
+            Json(json!({
+                "usage": meter.site_report(&site_id),
+                "history": meter.site_history(&site_id).unwrap_or_default(),
+            })),
+        ),
+        None => bandwidth_disabled(),
+    }
+}
+
+/// Replace a site's bandwidth quota
+pub async fn update_site_bandwidth_quota(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(quota): Json<BandwidthQuota>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response;
+    }
+    match state.router.bandwidth_meter() {
+        Some(meter) => {
+            meter.set_site_quota(&site_id, quota);
+            info!(site_id = %site_id, "Site bandwidth quota updated via API");
+            (StatusCode::OK, Json(json!(meter.site_report(&site_id))))
+        }
+        None => bandwidth_disabled(),
+    }
+}
+
+/// Every tenant's fuel, wall time and egress in a month
+pub async fn billing(
+    State(state): State<Arc<DashboardState>>,
+    Query(query): Query<BillingQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(meter) = state.router.billing() else {
+        return billing_disabled();
+    };
+    let period = query.period.unwrap_or_else(|| Period::now().month);
+    (
+        StatusCode::OK,
+        Json(json!({ "period": period, "periods": meter.periods(), "tenants": meter.report(period) })),
+    )
+}
+
+/// A tenant's fuel, wall time and egress in a month, per site
+pub async fn tenant_billing(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+    Query(query): Query<BillingQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(meter) = state.router.billing() else {
+        return billing_disabled();
+    };
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    let period = query.period.unwrap_or_else(|| Period::now().month);
+    (StatusCode::OK, Json(json!(meter.tenant_bill(&tenant.id.to_string(), period))))
+}
+
+fn billing_disabled() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Billing is disabled" })),
+    )
+}
+
+fn bandwidth_disabled() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Bandwidth accounting is disabled" })),
+    )
+}
+
+/// Recent healing attempts of the Supervisor, oldest first
+pub async fn supervisor_events(
+    State(state): State<Arc<DashboardState>>,
+    Query(query): Query<HealingQuery>,
+) -> Json<serde_json::Value> {
+    Json(json!({
+        "events": state.supervisor.history().recent(&query),
+        "healing_events": state.supervisor.stats().healing_events,
+    }))
+}
+
+/// Pool health check settings, global and per site
+pub async fn health_checks(State(state): State<Arc<DashboardState>>) -> Json<HealthChecksConfig> {
+    Json(state.router.health_checker().config())
+}
+
+/// Replace every pool health check setting; pools use them from their next probe
+pub async fn update_health_checks(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Json(config): Json<HealthChecksConfig>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, Scope::Node) {
+        return response;
+    }
+    let checker = state.router.health_checker();
+    match checker.set_config(config) {
+        Ok(()) => {
+            info!("Pool health checks updated via API");
+            (StatusCode::OK, Json(json!(checker.config())))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// A site's health check overrides and the settings its pools are probed with
+pub async fn site_health(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> Json<serde_json::Value> {
+    Json(site_health_json(&state, &site_id))
+}
+
+/// Replace a site's health check overrides
+pub async fn update_site_health(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(overrides): Json<HealthCheckOverrides>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match state.router.health_checker().set_site(&site_id, Some(overrides)) {
+        Ok(()) => {
+            info!(site_id = %site_id, "Site health checks updated via API");
+            (StatusCode::OK, Json(site_health_json(&state, &site_id)))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Probe a site's pools with the global settings again
+pub async fn reset_site_health(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    // Removing overrides cannot fail validation
+    let _ = state.router.health_checker().set_site(&site_id, None);
+    info!(site_id = %site_id, "Site health check overrides removed via API");
+    (StatusCode::OK, Json(site_health_json(&state, &site_id)))
+}
+
+fn site_health_json(state: &DashboardState, site_id: &str) -> serde_json::Value {
+    let checker = state.router.health_checker();
+    json!({
+        "site_id": site_id,
+        "overrides": checker.site_overrides(site_id),
+        "settings": checker.settings(site_id),
+        "failing_cages": checker.failing(site_id),
+    })
+}
+
+/// Path access rules of a site; credential hashes are redacted
+pub async fn site_acl(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match state.router.access_control() {
+        Some(acl) => (StatusCode::OK, Json(json!({ "site_id": site_id, "rules": acl.site_rules(&site_id) }))),
+        None => acl_disabled(),
+    }
+}
+
+/// Replace a site's path access rules
+/// Redacted credentials keep their stored hashes; an empty list removes the site's rules.
+pub async fn update_site_acl(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(rules): Json<Vec<AclRule>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(acl) = state.router.access_control() else {
+        return acl_disabled();
+    };
+    match acl.set_site_rules(&site_id, &rules) {
+        Ok(()) => {
+            info!(site_id = %site_id, rules = rules.len(), "Site access rules updated via API");
+            (StatusCode::OK, Json(json!({ "site_id": site_id, "rules": acl.site_rules(&site_id) })))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+fn acl_disabled() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Access control is not enabled" })),
+    )
+}
+
+/// Environment variables of a site; secret values are never returned
+pub async fn site_env(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    match state.tenants.site_env(tenant_id, &site_id) {
+        Ok(entries) => (StatusCode::OK, Json(json!({ "site_id": site_id, "env": entries }))),
+        Err(_) => site_not_found(&site_id),
+    }
+}
+
+/// Set a site environment variable or secret, applied to Cages created afterwards
+pub async fn set_site_env(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, name)): Path<(String, String)>,
+    Json(update): Json<EnvUpdate>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    match state.tenants.set_site_env(tenant_id, &site_id, &name, &update.value, update.secret) {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(json!({ "site_id": site_id, "name": name, "secret": update.secret })),
+        ),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Remove a site environment variable or secret
+pub async fn unset_site_env(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, name)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    match state.tenants.unset_site_env(tenant_id, &site_id, &name) {
+        Ok(true) => (StatusCode::OK, Json(json!({ "site_id": site_id, "name": name }))),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("{} is not set", name) })),
+        ),
+        Err(_) => site_not_found(&site_id),
+    }
+}
+
+fn site_not_found(site_id: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Site {} not found", site_id) })),
+    )
+}
+
+/// Security events shown on a tenant's overview
+const TENANT_EVENT_LIMIT: usize = 20;
+
+/// Tenants, for picking whose view to show
+pub async fn tenants(
+    State(state): State<Arc<DashboardState>>,
+) -> Json<serde_json::Value> {
+    let tenants: Vec<_> = state.tenants.list_tenants().into_iter()
+        .map(|tenant| json!({
+            "id": tenant.id,
+            "name": tenant.name,
+            "email": tenant.email,
+            "status": tenant.status,
+            "sites": tenant.sites.len(),
+            "created_at": tenant.created_at.timestamp(),
+        }))
+        .collect();
+    Json(json!({ "tenants": tenants }))
+}
+
+/// Create a tenant
+pub async fn create_tenant(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Json(request): Json<NewTenant>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response.into_response();
+    }
+
+    let quota = request.quota.unwrap_or_default();
+    match state.tenants.create_tenant(request.name.clone(), request.email, quota) {
+        Ok(tenant_id) => {
+            sync_tenant_quotas(&state);
+            info!(tenant_id = %tenant_id, name = %request.name, "Tenant created via API");
+            let summary = json!(state.tenants.get_tenant(tenant_id).map(|tenant| tenant_summary(&tenant)));
+            with_change((StatusCode::CREATED, Json(summary.clone())), None, Some(summary))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ).into_response(),
+    }
+}
+
+/// Suspend a tenant
+pub async fn suspend_tenant(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+) -> Response {
+    set_tenant_active(&state, &headers, &tenant_id, false)
+}
+
+/// Reactivate a suspended tenant
+pub async fn activate_tenant(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+) -> Response {
+    set_tenant_active(&state, &headers, &tenant_id, true)
+}
+
+fn set_tenant_active(
+    state: &DashboardState,
+    headers: &HeaderMap,
+    tenant_id: &str,
+    active: bool,
+) -> Response {
+    if let Err(response) = require(state, headers, Permission::ManageTenants, Scope::Node) {
+        return response.into_response();
+    }
+    let tenant = match find_tenant(state, tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response.into_response(),
+    };
+
+    let result = if active {
+        state.tenants.activate_tenant(tenant.id)
+    } else {
+        state.tenants.suspend_tenant(tenant.id)
+    };
+    state.router.tenant_changed(tenant.id);
+    match result.map(|()| state.tenants.get_tenant(tenant.id)) {
+        Ok(Some(updated)) => with_change(
+            (StatusCode::OK, Json(tenant_summary(&updated))),
+            Some(json!({ "status": tenant.status })),
+            Some(json!({ "status": updated.status })),
+        ),
+        _ => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Tenant {} not found", tenant_id) })),
+        ).into_response(),
+    }
+}
+
+/// Delete a tenant with its sites, and stop serving them
+pub async fn delete_tenant(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response.into_response();
+    }
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response.into_response(),
+    };
+
+    let site_ids = match state.tenants.delete_tenant(tenant.id) {
+        Ok(site_ids) => site_ids,
+        Err(e) => return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ).into_response(),
+    };
+    state.router.unregister_tenant(tenant.id);
+    for site_id in &site_ids {
+        state.router.unregister_pool(site_id);
+        state.supervisor.unregister_pool(site_id);
+        state.git.forget(site_id);
+    }
+    info!(tenant_id = %tenant.id, sites = site_ids.len(), "Tenant deleted via API");
+    with_change(
+        (StatusCode::OK, Json(json!({ "id": tenant.id, "sites": site_ids }))),
+        Some(json!({ "status": tenant.status, "sites": site_ids })),
+        None,
+    )
+}
+
+/// A tenant's resource quota
+pub async fn tenant_quota(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => (StatusCode::OK, Json(json!(tenant.quota))),
+        Err(response) => response,
+    }
+}
+
+/// Change some or all of a tenant's quota; fields left out keep their value
+pub async fn update_tenant_quota(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+    Json(changes): Json<serde_json::Map<String, serde_json::Value>>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response.into_response();
+    }
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response.into_response(),
+    };
+
+    let mut quota = json!(tenant.quota);
+    if let Some(fields) = quota.as_object_mut() {
+        fields.extend(changes);
+    }
+    let quota: ResourceQuota = match serde_json::from_value(quota) {
+        Ok(quota) => quota,
+        Err(e) => return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("Invalid quota: {}", e) })),
+        ).into_response(),
+    };
+
+    match state.tenants.update_quota(tenant.id, quota.clone()) {
+        Ok(()) => {
+            sync_tenant_quotas(&state);
+            info!(tenant_id = %tenant.id, "Tenant quota updated via API");
+            with_change((StatusCode::OK, Json(json!(quota))), Some(json!(tenant.quota)), Some(json!(quota)))
+        }
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ).into_response(),
+    }
+}
+
+/// A tenant's usage against its quota, including this month's bandwidth when metered
+pub async fn tenant_usage(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    let usage = match state.router.bandwidth_meter() {
+        Some(meter) => state.tenants.get_usage_with_bandwidth(tenant.id, meter),
+        None => state.tenants.get_usage(tenant.id),
+    };
+    (StatusCode::OK, Json(json!({ "tenant_id": tenant.id, "usage": usage })))
+}
+
+/// A tenant's sites with their domains and Cage health
+pub async fn tenant_sites(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    let mut sites = Vec::with_capacity(tenant.sites.len());
+    for site in &tenant.sites {
+        let cages = match state.router.pool(&site.id) {
+            Some(pool) => Some(pool.health_stats().await),
+            None => None,
+        };
+        sites.push(json!({
+            "id": site.id,
+            "name": site.name,
+            "domain": site.domain,
+            "storage_used_mb": site.storage_used_mb,
+            "storage_scanned_at": site.storage_scanned_at,
+            "created_at": site.created_at.timestamp(),
+            "cages": cages,
+        }));
+    }
+    (StatusCode::OK, Json(json!({ "tenant_id": tenant.id, "sites": sites })))
+}
+
+/// Add a site to a tenant, optionally with a domain
+pub async fn add_tenant_site(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+    Json(request): Json<NewSite>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, tenant_scope(&state, &tenant_id)) {
+        return response;
+    }
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    // Attached separately so the domain is checked like any other
+    let result = state.tenants.add_site(tenant.id, request.name.clone(), None).and_then(|site_id| {
+        if let Some(domain) = request.domain {
+            if let Err(e) = state.tenants.set_site_domain(tenant.id, &site_id, Some(domain)) {
+                let _ = state.tenants.remove_site(tenant.id, &site_id);
+                return Err(e);
+            }
+        }
+        Ok(site_id)
+    });
+    match result {
+        Ok(site_id) => {
+            sync_tenant_quotas(&state);
+            info!(tenant_id = %tenant.id, site_id = %site_id, "Site added via API");
+            let site = state.tenants.get_tenant(tenant.id)
+                .and_then(|tenant| tenant.sites.into_iter().find(|site| site.id == site_id));
+            (StatusCode::CREATED, Json(json!({
+                "tenant_id": tenant.id,
+                "id": site_id,
+                "name": request.name,
+                "domain": site.and_then(|site| site.domain),
+            })))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Remove a site from its tenant and stop serving it
+pub async fn remove_site(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+
+    match state.tenants.remove_site(tenant_id, &site_id) {
+        Ok(()) => {
+            state.router.unregister_pool(&site_id);
+            state.git.forget(&site_id);
+            info!(tenant_id = %tenant_id, site_id = %site_id, "Site removed via API");
+            (StatusCode::OK, Json(json!({ "tenant_id": tenant_id, "id": site_id })))
+        }
+        Err(_) => site_not_found(&site_id),
+    }
+}
+
+/// Stop sending a site new requests and stop healing it, for maintenance
+pub async fn drain_site(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    set_site_draining(&state, &headers, &site_id, true).await
+}
+
+/// Serve and heal a drained site again
+pub async fn resume_site(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    set_site_draining(&state, &headers, &site_id, false).await
+}
+
+async fn set_site_draining(
+    state: &DashboardState,
+    headers: &HeaderMap,
+    site_id: &str,
+    draining: bool,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(state, headers, Permission::ManageSites, site_scope(state, site_id)) {
+        return response;
+    }
+    if !state.router.set_draining(site_id, draining) {
+        return site_not_found(site_id);
+    }
+    let mut in_flight = 0;
+    for pool in state.router.site_pools(site_id) {
+        in_flight += pool.in_flight().await;
+    }
+    info!(site_id = %site_id, draining, in_flight, "Site drain state changed via API");
+    (StatusCode::OK, Json(json!({ "id": site_id, "draining": draining, "in_flight": in_flight })))
+}
+
+/// Attach a domain to a site, replacing its previous one
+pub async fn set_site_domain(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(update): Json<DomainUpdate>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    update_site_domain(&state, &headers, &site_id, Some(update.domain))
+}
+
+/// Detach a site's domain
+pub async fn remove_site_domain(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    update_site_domain(&state, &headers, &site_id, None)
+}
+
+fn update_site_domain(
+    state: &DashboardState,
+    headers: &HeaderMap,
+    site_id: &str,
+    domain: Option<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(state, headers, Permission::ManageDomains, site_scope(state, site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(site_id) else {
+        return site_not_found(site_id);
+    };
+
+    match state.tenants.set_site_domain(tenant_id, site_id, domain) {
+        Ok(()) => {
+            let domain = state.tenants.get_tenant(tenant_id)
+                .and_then(|tenant| tenant.sites.into_iter().find(|site| site.id == site_id))
+                .and_then(|site| site.domain);
+            info!(site_id = %site_id, domain = ?domain, "Site domain updated via API");
+            (StatusCode::OK, Json(json!({ "id": site_id, "domain": domain })))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// A site's hostnames with their verification state
+pub async fn site_domains(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if state.tenants.find_site_tenant(&site_id).is_none() {
+        return site_not_found(&site_id);
+    }
+    let domains: Vec<_> = state.tenants.domains().site_domains(&site_id).iter().map(domain_json).collect();
+    (StatusCode::OK, Json(json!({ "site_id": site_id, "domains": domains })))
+}
+
+/// Claim a hostname for a site; it is routed once verified
+pub async fn claim_site_domain(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(request): Json<NewDomain>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageDomains, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+
+    let result = state.tenants.add_site_domain(
+        tenant_id,
+        &site_id,
+        &request.hostname,
+        request.method,
+        request.redirect_to.as_deref(),
+    );
+    match result {
+        Ok(domain) => {
+            info!(site_id = %site_id, hostname = %domain.hostname, "Domain claimed via API");
+            (StatusCode::CREATED, Json(domain_json(&domain)))
+        }
+        Err(e) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Release one of a site's hostnames
+pub async fn release_site_domain(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, hostname)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageDomains, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+
+    match state.tenants.remove_site_domain(tenant_id, &site_id, &hostname) {
+        Ok(()) => (StatusCode::OK, Json(json!({ "site_id": site_id, "hostname": hostname }))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Check a hostname's ownership now instead of waiting for the next background check
+pub async fn verify_site_domain(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, hostname)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageDomains, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let domains = state.tenants.domains();
+    if domains.get(&hostname).filter(|domain| domain.site_id == site_id).is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Domain {} is not attached to site {}", hostname, site_id) })),
+        );
+    }
+
+    match domains.verify(&hostname).await {
+        Ok(domain) => (StatusCode::OK, Json(domain_json(&domain))),
+        Err(e) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// A domain with what its owner has to publish to verify it
+fn domain_json(domain: &Domain) -> serde_json::Value {
+    let mut value = json!(domain);
+    value["challenge"] = match domain.method {
+        VerificationMethod::Dns => json!({ "type": "dns", "name": domain.txt_name(), "value": domain.txt_value() }),
+        VerificationMethod::Http => json!({ "type": "http", "url": domain.challenge_url() }),
+    };
+    value
+}
+
+/// Keys a tenant's modules may be signed with
+pub async fn tenant_keys(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => (StatusCode::OK, Json(json!({ "tenant_id": tenant.id, "keys": tenant.signing_keys }))),
+        Err(response) => response,
+    }
+}
+
+/// Register a signing key, replacing any key of the same name
+pub async fn add_tenant_key(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+    Json(request): Json<NewSigningKey>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, tenant_scope(&state, &tenant_id)) {
+        return response;
+    }
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    match state.tenants.add_signing_key(tenant.id, &request.name, &request.public_key) {
+        Ok(key) => (StatusCode::CREATED, Json(json!(key))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Remove a signing key
+pub async fn remove_tenant_key(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((tenant_id, name)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, tenant_scope(&state, &tenant_id)) {
+        return response;
+    }
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    match state.tenants.remove_signing_key(tenant.id, &name) {
+        Ok(()) => (StatusCode::OK, Json(json!({ "tenant_id": tenant.id, "name": name }))),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Whether a tenant encrypts its files and secrets at rest
+pub async fn tenant_encryption(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => (StatusCode::OK, Json(json!(state.tenants.tenant_encryption(tenant.id)))),
+        Err(response) => response,
+    }
+}
+
+/// Turn on encryption at rest for a tenant
+pub async fn enable_tenant_encryption(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    encrypt_tenant(&state, &headers, &tenant_id, false).await
+}
+
+/// Move a tenant to a new encryption key, re-encrypting its files and secrets
+pub async fn rotate_tenant_encryption(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    encrypt_tenant(&state, &headers, &tenant_id, true).await
+}
+
+async fn encrypt_tenant(
+    state: &DashboardState,
+    headers: &HeaderMap,
+    tenant_id: &str,
+    rotate: bool,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(state, headers, Permission::ManageTenants, tenant_scope(state, tenant_id)) {
+        return response;
+    }
+    let tenant = match find_tenant(state, tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    match state.tenants.encrypt_tenant(tenant.id, rotate).await {
+        Ok(files) => {
+            let mut encryption = json!(state.tenants.tenant_encryption(tenant.id));
+            encryption["files_encrypted"] = json!(files);
+            (StatusCode::OK, Json(encryption))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Rewrap every tenant key with the current master key
+pub async fn rewrap_tenant_keys(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response;
+    }
+    match state.tenants.rewrap_keys().await {
+        Ok(tenants) => (StatusCode::OK, Json(json!({ "tenants": tenants }))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// The tenant's own artifact bucket, without its secret
+pub async fn tenant_artifacts(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    let source = tenant.artifact_source.map(|source| json!({
+        "endpoint": source.endpoint,
+        "region": source.region,
+        "bucket": source.bucket,
+        "prefix": source.prefix,
+        "path_style": source.path_style,
+        "access_key_id": source.access_key_id,
+    }));
+    (StatusCode::OK, Json(json!({ "tenant_id": tenant.id, "source": source })))
+}
+
+/// Give a tenant its own artifact bucket; the secret access key is sealed on arrival
+pub async fn set_tenant_artifacts(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+    Json(request): Json<ArtifactSourceRequest>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, tenant_scope(&state, &tenant_id)) {
+        return response;
+    }
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    match state.tenants.set_artifact_source(tenant.id, Some(&request)) {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(json!({ "tenant_id": tenant.id, "endpoint": request.endpoint, "bucket": request.bucket })),
+        ),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Send a tenant back to the node's artifact bucket
+pub async fn remove_tenant_artifacts(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, tenant_scope(&state, &tenant_id)) {
+        return response;
+    }
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+    match state.tenants.set_artifact_source(tenant.id, None) {
+        Ok(()) => (StatusCode::OK, Json(json!({ "tenant_id": tenant.id }))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Which objects to mirror into a site's content directory
+#[derive(Deserialize)]
+pub struct ContentSyncRequest {
+    /// Key prefix in the tenant's artifact source (default: the site ID and a slash)
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+/// Mirror a prefix of the tenant's artifact source into the site's static content
+pub async fn sync_site_content(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(request): Json<ContentSyncRequest>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    let prefix = request.prefix.unwrap_or_else(|| format!("{}/", site_id));
+    match state.tenants.sync_site_content(tenant_id, &site_id, &prefix).await {
+        Ok(sync) => {
+            let mut body = json!(sync);
+            body["site_id"] = json!(site_id);
+            body["prefix"] = json!(prefix);
+            (StatusCode::OK, Json(body))
+        }
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Write one file of the site's content
+pub async fn put_site_file(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, path)): Path<(String, String)>,
+    contents: axum::body::Bytes,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    match state.tenants.write_site_content(tenant_id, &site_id, &path, &contents) {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(json!({ "site_id": site_id, "path": path, "bytes": contents.len() })),
+        ),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Remove one file of the site's content; removing a file that isn't there succeeds
+pub async fn delete_site_file(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, path)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    match state.tenants.remove_site_content(tenant_id, &site_id, &path) {
+        Ok(removed) => (StatusCode::OK, Json(json!({ "site_id": site_id, "path": path, "removed": removed }))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Require, or stop requiring, signed modules for a site
+pub async fn set_site_signing(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(update): Json<SigningUpdate>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+
+    match state.tenants.set_site_require_signature(tenant_id, &site_id, update.required) {
+        Ok(()) => (StatusCode::OK, Json(json!({ "id": site_id, "require_signature": update.required }))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+fn tenant_summary(tenant: &Tenant) -> serde_json::Value {
+    json!({
+        "id": tenant.id,
+        "name": tenant.name,
+        "email": tenant.email,
+        "status": tenant.status,
+        "sites": tenant.sites.len(),
+        "created_at": tenant.created_at.timestamp(),
+    })
+}
+
+/// Push tenant quotas and site assignments to the components enforcing them
+fn sync_tenant_quotas(state: &DashboardState) {
+    if let Some(meter) = state.router.bandwidth_meter() {
+        state.tenants.sync_bandwidth(meter);
+    }
+    if let Some(relay) = &state.mail {
+        state.tenants.sync_mail(relay);
+    }
+}
+
+/// Reject the request unless its token grants `permission` on `scope`
+/// Without a configured admin token, management through the API is disabled.
+pub(super) fn require(
+    state: &DashboardState,
+    headers: &HeaderMap,
+    permission: Permission,
+    scope: Scope<'_>,
+) -> Result<TokenClaims, (StatusCode, Json<serde_json::Value>)> {
+    let Some(auth) = &state.auth else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Management is disabled; set dashboard.admin_token to enable it" })),
+        ));
+    };
+
+    match bearer_token(headers).map(|token| auth.validate_token(token)) {
+        Some(Ok(claims)) if auth.authorize(&claims, permission, scope) => Ok(claims),
+        Some(Ok(_)) => Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": format!("The token lacks the {} permission here", permission.as_str()) })),
+        )),
+        _ => Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "Missing or invalid token" })),
+        )),
+    }
+}
+
+/// Scope of a request acting on a site
+pub(super) fn site_scope<'a>(state: &DashboardState, site_id: &'a str) -> Scope<'a> {
+    Scope::Site { tenant_id: state.tenants.find_site_tenant(site_id), site_id }
+}
+
+/// Scope of a request acting on a tenant given by ID or name
+/// Unknown tenants get a scope only root and node-wide tokens pass, so the handler can answer 404.
+pub(super) fn tenant_scope(state: &DashboardState, tenant_id: &str) -> Scope<'static> {
+    Scope::Tenant(find_tenant(state, tenant_id).map_or(Uuid::nil(), |tenant| tenant.id))
+}
+
+/// The token in the request's `Authorization: Bearer` header
+pub(super) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Address of the client that sent a request, from the connection
+pub(super) fn client_ip(extensions: &axum::http::Extensions) -> Option<IpAddr> {
+    extensions.get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .map(|info| info.0.ip())
+}
+
+/// A tenant's sites with Cage health and bandwidth, its quota usage and recent security events
+pub async fn tenant_overview(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    let meter = state.router.bandwidth_meter();
+    let usage = match meter {
+        Some(meter) => state.tenants.get_usage_with_bandwidth(tenant.id, meter),
+        None => state.tenants.get_usage(tenant.id),
+    };
+
+    let mut sites = Vec::with_capacity(tenant.sites.len());
+    let mut events = Vec::new();
+    for site in &tenant.sites {
+        let cages = match state.router.pool(&site.id) {
+            Some(pool) => Some(pool.health_stats().await),
+            None => None,
+        };
+        sites.push(json!({
+            "id": site.id,
+            "name": site.name,
+            "domain": site.domain,
+            "cages": cages,
+            "bandwidth": meter.map(|meter| meter.site_report(&site.id)),
+        }));
+
+        events.extend(state.ai_module.events().recent(&EventQuery {
+            limit: Some(TENANT_EVENT_LIMIT),
+            site_id: Some(site.id.clone()),
+            ..Default::default()
+        }));
+    }
+    events.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.id.cmp(&a.id)));
+    events.truncate(TENANT_EVENT_LIMIT);
+
+    (StatusCode::OK, Json(json!({
+        "tenant": {
+            "id": tenant.id,
+            "name": tenant.name,
+            "status": tenant.status,
+        },
+        "quota": tenant.quota,
+        "usage": usage,
+        "sites": sites,
+        "events": events,
+    })))
+}
+
+/// Sampled traffic, bandwidth and Cage health of a tenant's sites, oldest sample first
+pub async fn tenant_telemetry(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let tenant = match find_tenant(&state, &tenant_id) {
+        Ok(tenant) => tenant,
+        Err(response) => return response,
+    };
+
+    let sites: serde_json::Map<_, _> = tenant.sites.iter()
+        .map(|site| (site.id.clone(), json!(state.telemetry.site_history(&site.id))))
+        .collect();
+    (StatusCode::OK, Json(json!({
+        "tenant_id": tenant.id,
+        "interval_secs": super::telemetry::DEFAULT_SAMPLE_INTERVAL.as_secs(),
+        "sites": sites,
+    })))
+}
+
+/// Look a tenant up by ID, or by name when the name is unique
+pub(super) fn find_tenant(state: &DashboardState, tenant_id: &str) -> Result<Tenant, (StatusCode, Json<serde_json::Value>)> {
+    let by_id = tenant_id.parse().ok().and_then(|id| state.tenants.get_tenant(id));
+    let by_name = || {
+        let mut named = state.tenants.list_tenants().into_iter().filter(|tenant| tenant.name == tenant_id);
+        match (named.next(), named.next()) {
+            (Some(tenant), None) => Some(tenant),
+            _ => None,
+        }
+    };
+    by_id.or_else(by_name)
+        .ok_or_else(|| (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Tenant {} not found", tenant_id) })),
+        ))
+}
+
+/// Scheduled jobs of every site, or of `?site=`
+pub async fn cron_jobs(
+    State(state): State<Arc<DashboardState>>,
+    Query(query): Query<CronQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match &state.scheduler {
+        Some(scheduler) => (StatusCode::OK, Json(json!({ "jobs": scheduler.list(query.site.as_deref()) }))),
+        None => scheduler_disabled(),
+    }
+}
+
+/// Scheduled jobs of a site
+pub async fn site_cron_jobs(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match &state.scheduler {
+        Some(scheduler) => (StatusCode::OK, Json(json!({ "site_id": site_id, "jobs": scheduler.list(Some(&site_id)) }))),
+        None => scheduler_disabled(),
+    }
+}
+
+/// Add a scheduled job to a site, replacing one of the same name
+pub async fn add_cron_job(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(spec): Json<JobSpec>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(scheduler) = &state.scheduler else {
+        return scheduler_disabled();
+    };
+    if state.router.pool(&site_id).is_none() {
+        return site_not_found(&site_id);
+    }
+    match scheduler.add(&site_id, spec) {
+        Ok(job) => (StatusCode::OK, Json(json!(job))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Remove a scheduled job
+pub async fn remove_cron_job(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, name)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match &state.scheduler {
+        Some(scheduler) if scheduler.remove(&site_id, &name) => {
+            (StatusCode::OK, Json(json!({ "site_id": site_id, "name": name })))
+        }
+        Some(_) => job_not_found(&site_id, &name),
+        None => scheduler_disabled(),
+    }
+}
+
+/// Recent runs of a scheduled job, oldest first
+pub async fn cron_job_history(
+    State(state): State<Arc<DashboardState>>,
+    Path((site_id, name)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(scheduler) = &state.scheduler else {
+        return scheduler_disabled();
+    };
+    match scheduler.history(&site_id, &name) {
+        Some(runs) => (StatusCode::OK, Json(json!({ "site_id": site_id, "name": name, "runs": runs }))),
+        None => job_not_found(&site_id, &name),
+    }
+}
+
+fn job_not_found(site_id: &str, name: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Job {} not found on site {}", name, site_id) })),
+    )
+}
+
+fn scheduler_disabled() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Job scheduler is disabled" })),
+    )
+}
+
+/// Task queue counters and dead tasks of a site
+pub async fn site_queue(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match &state.queue {
+        Some(queue) => (
+            StatusCode::OK,
+            Json(json!({
+                "site_id": site_id,
+                "stats": queue.stats(&site_id),
+                "dead": queue.dead_tasks(&site_id),
+            })),
+        ),
+        None => queue_disabled(),
+    }
+}
+
+/// Put a dead task back on the queue
+pub async fn retry_dead_task(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, task_id)): Path<(String, u64)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match &state.queue {
+        Some(queue) if queue.retry_dead(&site_id, task_id) => {
+            info!(site_id = %site_id, task_id, "Dead task requeued via API");
+            (StatusCode::OK, Json(json!({ "site_id": site_id, "task_id": task_id })))
+        }
+        Some(_) => dead_task_not_found(&site_id, task_id),
+        None => queue_disabled(),
+    }
+}
+
+/// Drop a dead task
+pub async fn discard_dead_task(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, task_id)): Path<(String, u64)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match &state.queue {
+        Some(queue) if queue.discard_dead(&site_id, task_id) => {
+            (StatusCode::OK, Json(json!({ "site_id": site_id, "task_id": task_id })))
+        }
+        Some(_) => dead_task_not_found(&site_id, task_id),
+        None => queue_disabled(),
+    }
+}
+
+fn dead_task_not_found(site_id: &str, task_id: u64) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("No dead task {} on site {}", task_id, site_id) })),
+    )
+}
+
+fn queue_disabled() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Task queue is disabled" })),
+    )
+}
+
+/// Topic counters of a site's pub/sub traffic on this node
+pub async fn site_pubsub(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match &state.pubsub {
+        Some(hub) => (
+            StatusCode::OK,
+            Json(json!({ "site_id": site_id, "node_id": hub.node_id(), "topics": hub.topic_stats(&site_id) })),
+        ),
+        None => pubsub_disabled(),
+    }
+}
+
+/// Active user sessions of a site
+pub async fn site_sessions(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(sessions) = &state.sessions else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Sessions are disabled" })),
+        );
+    };
+    match sessions.active_sessions(&site_id).await {
+        Ok(active) => (
+            StatusCode::OK,
+            Json(json!({ "site_id": site_id, "active": active, "timeout_secs": sessions.config().timeout_secs })),
+        ),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("{:#}", e) }))),
+    }
+}
+
+/// Messages forwarded by a peer node
+/// Peers authenticate with `Authorization: Bearer <pubsub.peer_token>`
+pub async fn receive_pubsub_messages(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Json(messages): Json<Vec<Message>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(hub) = &state.pubsub else {
+        return pubsub_disabled();
+    };
+
+    let token = headers.get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !hub.verify_peer_token(token) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "Invalid peer token" })),
+        );
+    }
+
+    let mut delivered = 0;
+    let mut rejected = 0;
+    for message in messages {
+        match hub.receive_remote(message) {
+            Ok(count) => delivered += count,
+            Err(_) => rejected += 1,
+        }
+    }
+    (StatusCode::OK, Json(json!({ "delivered": delivered, "rejected": rejected })))
+}
+
+fn pubsub_disabled() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Pub/sub is disabled" })),
+    )
+}
+
+/// A tenant's mail policy and today's usage
+pub async fn tenant_mail(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match mail_relay(&state, &tenant_id) {
+        Ok(relay) => (StatusCode::OK, Json(json!({ "tenant_id": tenant_id, "mail": relay.status(&tenant_id) }))),
+        Err(response) => response,
+    }
+}
+
+/// Replace a tenant's mail policy, e.g. to lift a suspension or allow sender domains
+pub async fn update_tenant_mail_policy(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(tenant_id): Path<String>,
+    Json(policy): Json<TenantMailPolicy>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, tenant_scope(&state, &tenant_id)) {
+        return response;
+    }
+    let relay = match mail_relay(&state, &tenant_id) {
+        Ok(relay) => relay,
+        Err(response) => return response,
+    };
+
+    match relay.set_policy(&tenant_id, policy) {
+        Ok(()) => {
+            info!(tenant_id = %tenant_id, "Mail policy updated via API");
+            (StatusCode::OK, Json(json!({ "tenant_id": tenant_id, "mail": relay.status(&tenant_id) })))
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Recent deliveries of a tenant, newest first
+pub async fn tenant_mail_log(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+    Query(query): Query<MailLogQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match mail_relay(&state, &tenant_id) {
+        Ok(relay) => (StatusCode::OK, Json(json!({ "tenant_id": tenant_id, "deliveries": relay.log(&tenant_id, query.limit) }))),
+        Err(response) => response,
+    }
+}
+
+/// Addresses a tenant won't send to
+pub async fn tenant_mail_suppressions(
+    State(state): State<Arc<DashboardState>>,
+    Path(tenant_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match mail_relay(&state, &tenant_id) {
+        Ok(relay) => (StatusCode::OK, Json(json!({ "tenant_id": tenant_id, "suppressions": relay.suppressions(&tenant_id) }))),
+        Err(response) => response,
+    }
+}
+
+/// Stop a tenant from sending to an address
+pub async fn suppress_mail_address(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((tenant_id, address)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, tenant_scope(&state, &tenant_id)) {
+        return response;
+    }
+    let relay = match mail_relay(&state, &tenant_id) {
+        Ok(relay) => relay,
+        Err(response) => return response,
+    };
+
+    match relay.suppress(&tenant_id, &address) {
+        Ok(()) => (StatusCode::OK, Json(json!({ "tenant_id": tenant_id, "address": address }))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": e.to_string() }))),
+    }
+}
+
+/// Let a tenant send to an address again
+pub async fn unsuppress_mail_address(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((tenant_id, address)): Path<(String, String)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, tenant_scope(&state, &tenant_id)) {
+        return response;
+    }
+    match mail_relay(&state, &tenant_id) {
+        Ok(relay) if relay.unsuppress(&tenant_id, &address) => {
+            (StatusCode::OK, Json(json!({ "tenant_id": tenant_id, "address": address })))
+        }
+        Ok(_) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("{} is not suppressed", address) })),
+        ),
+        Err(response) => response,
+    }
+}
+
+/// The mail relay, once the tenant is known to exist
+fn mail_relay<'a>(state: &'a DashboardState, tenant_id: &str) -> Result<&'a Arc<MailRelay>, (StatusCode, Json<serde_json::Value>)> {
+    let Some(relay) = &state.mail else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Outbound mail is disabled" })),
+        ));
+    };
+
+    let exists = tenant_id.parse().ok().and_then(|id| state.tenants.get_tenant(id)).is_some();
+    if !exists {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Tenant {} not found", tenant_id) })),
+        ));
+    }
+    Ok(relay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_state(root: &std::path::Path) -> Arc<DashboardState> {
+        let router = Arc::new(crate::router::Router::new(Default::default()));
+        let supervisor = Arc::new(crate::supervisor::Supervisor::new(Default::default()));
+        let ai_module = Arc::new(crate::ai::AiSecurityModule::new(Default::default()).unwrap());
+        let tenants = Arc::new(crate::tenancy::TenantManager::new());
+        let modules = crate::storage::modules::ModuleStore::new(root).unwrap();
+        let documents = Arc::new(crate::crdt::DocumentStore::new(Default::default()));
+        let deployments = Arc::new(crate::deployment::bluegreen::BlueGreenManager::new(
+            &Default::default(),
+            router.clone(),
+            supervisor.clone(),
+            modules.clone(),
+        ));
+        let health = Arc::new(crate::observability::health::Health::new(Default::default(), None));
+
+        Arc::new(DashboardState {
+            git: Arc::new(crate::deployment::git::GitDeployer::new(&Default::default(), root, tenants.clone(), deployments.clone())),
+            snapshots: Arc::new(crate::storage::snapshot::SnapshotManager::new(
+                tenants.clone(),
+                router.clone(),
+                supervisor.clone(),
+                modules,
+                ai_module.clone(),
+                documents.clone(),
+            )),
+            rebalancer: Arc::new(crate::network::rebalance::Rebalancer::new(router.state().clone(), health.clone())),
+            router,
+            supervisor,
+            ai_module,
+            tenants,
+            scheduler: None,
+            queue: None,
+            pubsub: None,
+            mail: None,
+            metrics_history: None,
+            telemetry: Arc::new(crate::dashboard::telemetry::TelemetryCollector::new()),
+            logs: Arc::new(crate::observability::logs::LogBuffer::new(16)),
+            auth: None,
+            deployments,
+            documents,
+            sessions: None,
+            recordings: Arc::new(crate::cage::determinism::RecordingStore::new()),
+            guest_logs: None,
+            crashes: Arc::new(crate::cage::crash::CrashStore::new()),
+            profiles: Arc::new(crate::cage::profiling::ProfileStore::new()),
+            audit: None,
+            notifications: None,
+            health,
+            assets: crate::dashboard::assets::Assets::new(None),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_writes_forbidden_without_admin_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+
+        let (status, _) = set_site_env(
+            State(state.clone()),
+            HeaderMap::new(),
+            Path(("site".to_string(), "KEY".to_string())),
+            Json(EnvUpdate { value: "value".to_string(), secret: false }),
+        ).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = update_site_acl(
+            State(state),
+            HeaderMap::new(),
+            Path("site".to_string()),
+            Json(Vec::new()),
+        ).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,188 @@
+// Dashboard Assets
+// Static files compiled into the binary, optionally replaced from a directory during development
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use include_dir::{include_dir, Dir};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Component, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tracing::info;
+
+use super::DashboardState;
+
+/// The repository's `static/` tree, as it was at build time
+static EMBEDDED: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/static");
+
+/// The dashboard page
+pub async fn index(State(state): State<Arc<DashboardState>>, headers: HeaderMap) -> Response {
+    state.assets.respond("dashboard.html", &headers).await
+}
+
+/// A file under `/static`
+pub async fn file(
+    State(state): State<Arc<DashboardState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    state.assets.respond(&path, &headers).await
+}
+
+/// An asset's contents and the ETag they hash to
+pub struct Asset {
+    pub contents: Cow<'static, [u8]>,
+    pub etag: String,
+}
+
+/// Where assets are looked up
+pub struct Assets {
+    /// Files here win over the embedded ones, and are read on every request
+    override_dir: Option<PathBuf>,
+}
+
+impl Assets {
+    pub fn new(override_dir: Option<PathBuf>) -> Self {
+        if let Some(dir) = &override_dir {
+            info!(dir = %dir.display(), "Serving dashboard assets from disk before the embedded ones");
+        }
+        Self { override_dir }
+    }
+
+    /// The asset at a path relative to `static/`, from the override directory when it has one
+    pub async fn get(&self, path: &str) -> Option<Asset> {
+        let relative = clean(path)?;
+        if let Some(dir) = &self.override_dir {
+            if let Ok(contents) = tokio::fs::read(dir.join(&relative)).await {
+                let etag = etag(&contents);
+                return Some(Asset { contents: Cow::Owned(contents), etag });
+            }
+        }
+        let file = EMBEDDED.get_file(&relative)?;
+        Some(Asset {
+            contents: Cow::Borrowed(file.contents()),
+            etag: embedded_etags().get(relative.as_str()).cloned().unwrap_or_else(|| etag(file.contents())),
+        })
+    }
+
+    /// The asset, or 304 when the client already has this version of it
+    pub async fn respond(&self, path: &str, headers: &HeaderMap) -> Response {
+        let Some(asset) = self.get(path).await else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let cached = headers.get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == asset.etag || tag.trim() == "*"));
+        // Names are not content-hashed, so clients revalidate every time and get 304s
+        let common = [
+            (header::ETAG, asset.etag.clone()),
+            (header::CACHE_CONTROL, "no-cache".to_string()),
+        ];
+        if cached {
+            return (StatusCode::NOT_MODIFIED, common).into_response();
+        }
+        (
+            common,
+            [(header::CONTENT_TYPE, content_type(path))],
+            asset.contents.into_owned(),
+        ).into_response()
+    }
+}
+
+/// A request path as a relative path, refusing anything that could leave the asset root
+fn clean(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in std::path::Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Quoted, truncated SHA-256 of the contents
+fn etag(contents: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, contents);
+    format!("\"{}\"", hex::encode(&digest.as_ref()[..8]))
+}
+
+/// ETags of the embedded files, hashed once
+fn embedded_etags() -> &'static HashMap<String, String> {
+    static ETAGS: OnceLock<HashMap<String, String>> = OnceLock::new();
+    ETAGS.get_or_init(|| {
+        let mut etags = HashMap::new();
+        let mut dirs = vec![&EMBEDDED];
+        while let Some(dir) = dirs.pop() {
+            for file in dir.files() {
+                etags.insert(file.path().to_string_lossy().into_owned(), etag(file.contents()));
+            }
+            dirs.extend(dir.dirs());
+        }
+        etags
+    })
+}
+
+fn content_type(path: &str) -> String {
+    let extension = path.rsplit_once('.').map_or("", |(_, extension)| extension);
+    match extension.to_ascii_lowercase().as_str() {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[tokio::test]
+    async fn test_embedded_assets() {
+        let assets = Assets::new(None);
+        let page = assets.get("dashboard.html").await.unwrap();
+        assert!(String::from_utf8_lossy(&page.contents).contains("/static/dashboard.js"));
+        assert_eq!(page.etag, etag(&page.contents));
+        assert!(assets.get("dashboard.js").await.is_some());
+
+        assert!(assets.get("../Cargo.toml").await.is_none());
+        assert!(assets.get("/etc/passwd").await.is_none());
+        assert!(assets.get("missing.css").await.is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(&page.etag).unwrap());
+        assert_eq!(assets.respond("dashboard.html", &headers).await.status(), StatusCode::NOT_MODIFIED);
+        let response = assets.respond("dashboard.css", &headers).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/css; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_override_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("dashboard.css"), "body { color: red }").unwrap();
+        let assets = Assets::new(Some(dir.path().to_path_buf()));
+
+        let css = assets.get("dashboard.css").await.unwrap();
+        assert_eq!(&*css.contents, b"body { color: red }");
+
+        // Edits are picked up without a restart, under a new ETag
+        std::fs::write(dir.path().join("dashboard.css"), "body { color: blue }").unwrap();
+        assert_ne!(assets.get("dashboard.css").await.unwrap().etag, css.etag);
+
+        // Files the directory lacks still come from the binary
+        assert!(assets.get("dashboard.js").await.is_some());
+    }
+}
@@ -0,0 +1,142 @@
+// Audit API
+// Records every mutating management request, and serves the audit log back
+
+use axum::{
+    extract::{MatchedPath, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+use super::api::{bearer_token, client_ip, require};
+use super::DashboardState;
+use crate::observability::audit::{AuditActor, AuditChange, AuditEntry, AuditFilter};
+use crate::tenancy::auth::{Permission, Scope};
+
+/// Filters and paging for audit entries
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+
+    /// Unix time in milliseconds
+    pub since: Option<i64>,
+
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize { 100 }
+
+/// Middleware writing an entry for each request that changes something
+/// Handlers that know the values they replaced attach them with `with_change`.
+pub async fn record(
+    State(state): State<Arc<DashboardState>>,
+    matched: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(audit) = state.audit.clone() else {
+        return next.run(request).await;
+    };
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(request).await;
+    }
+
+    let ip = client_ip(request.extensions());
+    let presented = bearer_token(request.headers()).is_some();
+    let actor = state.auth.as_ref()
+        .zip(bearer_token(request.headers()))
+        .and_then(|(auth, token)| auth.validate_token(token).ok())
+        .map(|claims| AuditActor::from(&claims));
+    let route = matched.as_ref().map_or(request.uri().path(), |matched| matched.as_str());
+    let action = format!("{} {}", request.method(), route);
+    let target = request.uri().path().to_string();
+
+    let mut response = next.run(request).await;
+    let change = response.extensions_mut().remove::<AuditChange>().unwrap_or_default();
+    // With lockouts enabled, `access::guard_logins` records rejected tokens itself
+    let guarded = state.auth.as_ref().is_some_and(|auth| auth.lockout().is_some());
+    if guarded && presented && response.status() == StatusCode::UNAUTHORIZED {
+        return response;
+    }
+    audit.record(AuditEntry {
+        id: 0,
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        actor,
+        action,
+        target,
+        status: response.status().as_u16(),
+        ip,
+        change,
+    });
+    response
+}
+
+/// Attach what a change replaced and what it left, for the audit entry of its request
+pub(super) fn with_change(
+    response: impl IntoResponse,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+) -> Response {
+    let mut response = response.into_response();
+    response.extensions_mut().insert(AuditChange { before, after });
+    response
+}
+
+/// Recent audit entries, oldest first
+pub async fn list(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response;
+    }
+    let Some(audit) = &state.audit else {
+        return disabled();
+    };
+    let filter = AuditFilter {
+        actor: query.actor,
+        action: query.action,
+        target: query.target,
+        since: query.since,
+    };
+    (StatusCode::OK, Json(json!({ "entries": audit.query(&filter, query.limit) })))
+}
+
+/// The whole audit log as JSON lines
+pub async fn export(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response.into_response();
+    }
+    let Some(audit) = state.audit.clone() else {
+        return disabled().into_response();
+    };
+    match tokio::task::spawn_blocking(move || audit.export()).await {
+        Ok(Ok(lines)) => ([(header::CONTENT_TYPE, "application/x-ndjson")], lines).into_response(),
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        ).into_response(),
+    }
+}
+
+fn disabled() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "The audit log is disabled; set [audit] enabled = true" })),
+    )
+}
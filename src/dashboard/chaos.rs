@@ -0,0 +1,49 @@
+// Chaos API
+// Trigger faults by hand and list the ones injected, when chaos mode is enabled
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use super::api::{require, site_scope};
+use super::DashboardState;
+use crate::chaos::Fault;
+use crate::tenancy::auth::Permission;
+
+/// Chaos settings and the faults injected recently
+pub async fn status(State(state): State<Arc<DashboardState>>) -> Json<serde_json::Value> {
+    match state.router.chaos() {
+        Some(chaos) => Json(json!({
+            "enabled": true,
+            "config": chaos.config(),
+            "faults": chaos.history(),
+        })),
+        None => Json(json!({ "enabled": false, "faults": [] })),
+    }
+}
+
+/// Inject a fault into the site's pool
+pub async fn inject(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(fault): Json<Fault>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(chaos) = state.router.chaos() else {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "Chaos mode is disabled; set chaos.enabled = true to inject faults" })),
+        );
+    };
+    match chaos.inject(&state.router, &site_id, fault).await {
+        Ok(record) => (StatusCode::CREATED, Json(json!(record))),
+        Err(e) => (StatusCode::CONFLICT, Json(json!({ "error": format!("{:#}", e) }))),
+    }
+}
@@ -0,0 +1,58 @@
+// Crash Report API
+// Reports of site guests that trapped, for `pear site crashes`
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use super::api::{require, site_scope};
+use super::DashboardState;
+use crate::tenancy::auth::Permission;
+
+/// Crashes of a site, newest first, without their backtraces and inputs
+pub async fn list(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ViewMetrics, site_scope(&state, &site_id)) {
+        return response;
+    }
+    // A site that never crashed has no reporter when its guests were never deployed here
+    let reports = state.crashes.get(&site_id).map(|reporter| reporter.list()).unwrap_or_default();
+    let crashes: Vec<_> = reports.into_iter()
+        .rev()
+        .map(|report| json!({
+            "id": report.id,
+            "cage_id": report.cage_id,
+            "crashed_at": report.crashed_at,
+            "trap": report.trap,
+            "function": report.function,
+            "kind": json!(report.call)["kind"].clone(),
+            "message": report.message,
+        }))
+        .collect();
+    (StatusCode::OK, Json(json!({ "site_id": site_id, "crashes": crashes })))
+}
+
+/// A crash report with its backtrace, input and the guest's last output
+pub async fn get(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, id)): Path<(String, u64)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ViewMetrics, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match state.crashes.get(&site_id).and_then(|reporter| reporter.get(id)) {
+        Some(report) => (StatusCode::OK, Json(json!(report))),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("No crash report {} on site {}", id, site_id) })),
+        ),
+    }
+}
@@ -0,0 +1,195 @@
+// Deployment API
+// Stage, switch, roll back and finish blue/green deployments of a site, or hot-swap its module
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+use super::api::{require, site_scope};
+use super::audit::with_change;
+use super::DashboardState;
+use crate::deployment::bluegreen::{BlueGreenDeployment, BlueGreenStatus};
+use crate::tenancy::auth::Permission;
+use crate::tenancy::signing::SIGNATURE_HEADER;
+
+/// Options for staging a green pool
+#[derive(Deserialize)]
+pub struct StageQuery {
+    /// Switch traffic as soon as the checks pass
+    #[serde(default)]
+    pub switch: bool,
+
+    /// Take the module from this key of the tenant's artifact source instead of the body
+    #[serde(default)]
+    pub artifact: Option<String>,
+
+    /// SHA-256 the artifact must have; a cached copy with it is used without asking the source
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// The site's current or last blue/green deployment
+pub async fn status(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match state.deployments.get(&site_id) {
+        Some(deployment) => (StatusCode::OK, Json(json!(deployment))),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("No blue/green deployment for {}", site_id) })),
+        ),
+    }
+}
+
+/// The site's deployments, newest first
+pub async fn history(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let deployments = state.deployments.history(&site_id);
+    (StatusCode::OK, Json(json!({ "site_id": site_id, "deployments": deployments })))
+}
+
+/// Check the signature in the `x-pear-signature` header, validate the uploaded or fetched module,
+/// then start and check a green pool from it
+pub async fn stage(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Query(query): Query<StageQuery>,
+    module: Bytes,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response.into_response();
+    }
+    let before = state.deployments.get(&site_id);
+
+    let module = match &query.artifact {
+        Some(key) => {
+            let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+                return error(anyhow::anyhow!("Site {} belongs to no tenant", site_id)).into_response();
+            };
+            let fetched = state.tenants.fetch_artifact(tenant_id, key, query.sha256.as_deref()).await
+                .and_then(|artifact| artifact.read());
+            match fetched {
+                Ok(module) => Bytes::from(module),
+                Err(e) => return (StatusCode::BAD_GATEWAY, Json(json!({ "error": format!("{:#}", e) }))).into_response(),
+            }
+        }
+        None => module,
+    };
+    let signature = headers.get(SIGNATURE_HEADER).and_then(|value| value.to_str().ok());
+    let signature = state.tenants.check_module_signature(&site_id, &module, signature);
+    let deployment = match state.deployments.stage(&site_id, module.to_vec(), signature).await {
+        Ok(deployment) => deployment,
+        Err(e) => return error(e).into_response(),
+    };
+    match deployment.status {
+        BlueGreenStatus::Ready if query.switch => {
+            reply(before, state.deployments.switch(&site_id), StatusCode::CREATED)
+        }
+        BlueGreenStatus::Ready => reply(before, Ok(deployment), StatusCode::CREATED),
+        _ => reply(before, Ok(deployment), StatusCode::UNPROCESSABLE_ENTITY),
+    }
+}
+
+/// Route the site to its checked green pool
+pub async fn switch(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response.into_response();
+    }
+    let before = state.deployments.get(&site_id);
+    reply(before, state.deployments.switch(&site_id), StatusCode::OK)
+}
+
+/// Flip back to blue, or discard a green pool that never went live
+pub async fn rollback(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response.into_response();
+    }
+    let before = state.deployments.get(&site_id);
+    reply(before, state.deployments.rollback(&site_id), StatusCode::OK)
+}
+
+/// Release the standby blue pool
+pub async fn finish(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response.into_response();
+    }
+    let before = state.deployments.get(&site_id);
+    reply(before, state.deployments.finish(&site_id), StatusCode::OK)
+}
+
+/// Check the signature in the `x-pear-signature` header, then move the site's live pool onto
+/// the uploaded module in place; 422 when the module is rejected and the current one keeps serving
+pub async fn swap(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    module: Bytes,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response.into_response();
+    }
+    let signature = headers.get(SIGNATURE_HEADER).and_then(|value| value.to_str().ok());
+    let signature = state.tenants.check_module_signature(&site_id, &module, signature);
+    let swap = match state.deployments.hot_swap(&site_id, module.to_vec(), signature).await {
+        Ok(swap) => swap,
+        Err(e) => return error(e).into_response(),
+    };
+    let status = if swap.swapped { StatusCode::OK } else { StatusCode::UNPROCESSABLE_ENTITY };
+    let before = json!({ "module_hash": swap.previous_hash });
+    let after = match swap.swapped {
+        true => json!({ "module_hash": swap.module_hash }),
+        false => before.clone(),
+    };
+    with_change((status, Json(json!(swap))), Some(before), Some(after))
+}
+
+/// The deployment, with its state before and after the call for the audit log
+fn reply(
+    before: Option<BlueGreenDeployment>,
+    result: anyhow::Result<BlueGreenDeployment>,
+    status: StatusCode,
+) -> Response {
+    match result {
+        Ok(deployment) => with_change(
+            (status, Json(json!(deployment))),
+            before.as_ref().map(summary),
+            Some(summary(&deployment)),
+        ),
+        Err(e) => error(e).into_response(),
+    }
+}
+
+fn summary(deployment: &BlueGreenDeployment) -> serde_json::Value {
+    json!({
+        "deployment_id": deployment.deployment_id,
+        "status": deployment.status,
+        "module_hash": deployment.module_hash,
+    })
+}
+
+fn error(e: anyhow::Error) -> (StatusCode, Json<serde_json::Value>) {
+    (StatusCode::CONFLICT, Json(json!({ "error": format!("{:#}", e) })))
+}
@@ -0,0 +1,165 @@
+// Git Deployment API
+// Point a site at a repository, deploy it on request, and redeploy it on push webhooks
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use super::api::{require, site_scope};
+use super::DashboardState;
+use crate::deployment::git::{self, GitDeployStatus, GitSourceRequest};
+use crate::tenancy::auth::Permission;
+
+/// The site's git source, without its webhook secret, and its recent deploys
+pub async fn source(
+    State(state): State<Arc<DashboardState>>,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    let source = match state.tenants.site_git(tenant_id, &site_id) {
+        Ok(source) => source,
+        Err(_) => return site_not_found(&site_id),
+    };
+    let source = source.map(|source| json!({
+        "url": source.url,
+        "reference": source.reference,
+        "build": source.build,
+        "output": source.output,
+        "switch": source.switch,
+    }));
+    (
+        StatusCode::OK,
+        Json(json!({ "site_id": site_id, "source": source, "deploys": state.git.history(&site_id) })),
+    )
+}
+
+/// Deploy the site from a repository; the webhook secret is returned this once
+pub async fn set_source(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    Json(request): Json<GitSourceRequest>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    match state.tenants.set_site_git(tenant_id, &site_id, &request) {
+        Ok(secret) => (
+            StatusCode::OK,
+            Json(json!({
+                "site_id": site_id,
+                "url": request.url,
+                "reference": request.reference,
+                "webhook_path": format!("/api/sites/{}/git/webhook", site_id),
+                "webhook_secret": secret,
+            })),
+        ),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Stop deploying the site from git
+pub async fn remove_source(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let Some(tenant_id) = state.tenants.find_site_tenant(&site_id) else {
+        return site_not_found(&site_id);
+    };
+    match state.tenants.remove_site_git(tenant_id, &site_id) {
+        Ok(true) => {
+            state.git.forget(&site_id);
+            (StatusCode::OK, Json(json!({ "site_id": site_id })))
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Site {} has no git source", site_id) })),
+        ),
+        Err(_) => site_not_found(&site_id),
+    }
+}
+
+/// Fetch, build and stage the site's branch or tag, replying once the deploy is done
+pub async fn deploy(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::Deploy, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match state.git.deploy(&site_id, "api").await {
+        Ok(deploy) if deploy.status == GitDeployStatus::Deployed => (StatusCode::CREATED, Json(json!(deploy))),
+        Ok(deploy) => (StatusCode::UNPROCESSABLE_ENTITY, Json(json!(deploy))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+/// Push webhook from GitHub, GitLab or Gitea; authenticated by the site's webhook secret, not the admin token
+pub async fn webhook(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+    body: Bytes,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let source = state.tenants.find_site_tenant(&site_id)
+        .and_then(|tenant_id| {
+            let secret = state.tenants.git_webhook_secret(tenant_id, &site_id).ok()?;
+            Some((secret, state.tenants.site_git(tenant_id, &site_id).ok()??))
+        });
+    // Unknown sites and bad signatures look alike, so the endpoint can't be used to find sites
+    let verified = source.filter(|(secret, _)| git::verify_webhook(secret, &headers, &body));
+    let Some((_, source)) = verified else {
+        warn!(site_id = %site_id, "Git webhook rejected");
+        return (StatusCode::UNAUTHORIZED, Json(json!({ "error": "Invalid webhook signature" })));
+    };
+
+    if headers.get("x-github-event").is_some_and(|event| event == "ping") {
+        return (StatusCode::OK, Json(json!({ "pong": true })));
+    }
+    let pushed = git::pushed_ref(&body).unwrap_or_default();
+    if !git::is_source_ref(&pushed, &source.reference) {
+        return (
+            StatusCode::ACCEPTED,
+            Json(json!({ "ignored": true, "ref": pushed, "reference": source.reference })),
+        );
+    }
+
+    info!(site_id = %site_id, pushed = %pushed, "Git push received, redeploying");
+    let deployer = state.git.clone();
+    let deploy_site = site_id.clone();
+    tokio::spawn(async move {
+        if let Err(e) = deployer.deploy(&deploy_site, "webhook").await {
+            warn!(site_id = %deploy_site, error = %e, "Git deploy from webhook failed to start");
+        }
+    });
+    (StatusCode::ACCEPTED, Json(json!({ "site_id": site_id, "ref": pushed, "deploying": true })))
+}
+
+fn site_not_found(site_id: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Site {} not found", site_id) })),
+    )
+}
@@ -0,0 +1,27 @@
+// Health API
+// Unauthenticated liveness and readiness probes
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use super::DashboardState;
+
+/// Liveness: answering at all means the process and its runtime are up
+pub async fn healthz(State(state): State<Arc<DashboardState>>) -> Json<serde_json::Value> {
+    Json(json!({
+        "status": "ok",
+        "uptime_secs": state.health.uptime().as_secs(),
+    }))
+}
+
+/// Readiness: 200 when every check passes, 503 with the failing ones otherwise
+pub async fn readyz(State(state): State<Arc<DashboardState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let readiness = state.health.readiness(&state.router, &state.supervisor).await;
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(json!(readiness)))
+}
@@ -0,0 +1,125 @@
+// Log Streaming
+// Recent and live daemon, access and guest logs as newline-delimited JSON for `pear logs`
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+use super::DashboardState;
+use crate::observability::logs::{LogFilter, LogKind, LogRecord};
+
+/// Filters and paging for the log stream
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    pub site: Option<String>,
+    pub level: Option<String>,
+
+    /// Unix time in milliseconds
+    pub since: Option<i64>,
+    pub grep: Option<String>,
+    pub kind: Option<LogKind>,
+    pub request_id: Option<String>,
+
+    /// Recent lines to send before following
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+
+    /// Keep the response open and send new lines as they are logged
+    #[serde(default)]
+    pub follow: bool,
+}
+
+fn default_limit() -> usize { 200 }
+
+/// Stream matching lines, one JSON object per line
+pub async fn handler(
+    State(state): State<Arc<DashboardState>>,
+    Query(query): Query<LogsQuery>,
+) -> Response {
+    let filter = LogFilter {
+        site: query.site,
+        level: query.level,
+        since: query.since,
+        grep: query.grep,
+        kind: query.kind,
+        request_id: query.request_id,
+    };
+    if let Err(e) = filter.validate() {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": e.to_string() }))).into_response();
+    }
+
+    // Guest output is kept apart per site, so a chatty guest can't push out the daemon's lines
+    let guest_log;
+    let buffer = match filter.kind {
+        Some(LogKind::Guest) => {
+            let Some(site_id) = filter.site.as_deref() else {
+                let error = "Guest logs are kept per site, so a site is required";
+                return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+            };
+            let Some(guest_logs) = &state.guest_logs else {
+                let error = "Guest output is not captured on this node";
+                return (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response();
+            };
+            let Some(site_log) = guest_logs.get(site_id) else {
+                let error = format!("Site {} has no guest output", site_id);
+                return (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response();
+            };
+            guest_log = site_log;
+            guest_log.buffer()
+        }
+        _ => &*state.logs,
+    };
+
+    // Subscribe first so nothing logged while the backlog is read is lost
+    let live = query.follow.then(|| buffer.subscribe());
+    let mut backlog = buffer.query(&filter, query.limit);
+    let last_seq = backlog.last().map(|record| record.seq).unwrap_or(0);
+
+    // One request's lines include what its guest printed, wherever that is kept
+    if let (Some(_), None, Some(guest_logs)) = (&filter.request_id, filter.kind, &state.guest_logs) {
+        backlog.extend(guest_logs.query(&filter, query.limit));
+        backlog.sort_by_key(|record| record.timestamp);
+        backlog = backlog.split_off(backlog.len().saturating_sub(query.limit));
+    }
+
+    let backlog = futures::stream::iter(backlog.into_iter().map(|record| Ok::<_, Infallible>(line(&record))));
+    let body = match live {
+        Some(receiver) => {
+            let live = futures::stream::unfold(receiver, move |mut receiver| {
+                let filter = filter.clone();
+                async move {
+                    loop {
+                        match receiver.recv().await {
+                            Ok(record) if record.seq > last_seq && filter.matches(&record) => {
+                                return Some((Ok(line(&record)), receiver));
+                            }
+                            // A follower too slow to keep up skips ahead rather than stalling logging
+                            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => return None,
+                        }
+                    }
+                }
+            });
+            Body::from_stream(backlog.chain(live))
+        }
+        None => Body::from_stream(backlog),
+    };
+
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response()
+}
+
+fn line(record: &LogRecord) -> String {
+    let mut line = serde_json::to_string(record).unwrap_or_default();
+    line.push('\n');
+    line
+}
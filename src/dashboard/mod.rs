@@ -1,17 +1,35 @@
 // Administration Dashboard Module
 // Real-time monitoring and management interface
 
+pub mod access;
+pub mod api;
+pub mod assets;
+pub mod audit;
+pub mod chaos;
+pub mod crashes;
+pub mod deployments;
+pub mod git;
+pub mod health;
+pub mod logs;
+pub mod notifications;
+pub mod profile;
+pub mod prometheus;
+pub mod recordings;
+pub mod snapshots;
+pub mod sso;
 pub mod websocket;
 pub mod telemetry;
 
 use axum::{
     Router,
-    routing::get,
-    response::Html,
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post, put},
 };
-use tower_http::services::ServeDir;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
 use std::sync::Arc;
-use tracing::{info, error};
+use tracing::{debug, info};
 
 /// Dashboard server state
 pub struct DashboardState {
@@ -23,43 +41,271 @@ pub struct DashboardState {
     
     /// Reference to AI module for threat stats
     pub ai_module: Arc<crate::ai::AiSecurityModule>,
+    
+    /// Tenants and their sites, for site environment management
+    pub tenants: Arc<crate::tenancy::TenantManager>,
+    
+    /// Scheduled jobs, when the scheduler is enabled
+    pub scheduler: Option<Arc<crate::scheduler::Scheduler>>,
+    
+    /// Background task queue, when enabled
+    pub queue: Option<Arc<crate::scheduler::queue::TaskQueue>>,
+    
+    /// Pub/sub hub, when enabled
+    pub pubsub: Option<Arc<crate::crdt::pubsub::PubSub>>,
+    
+    /// Outbound mail relay, when enabled
+    pub mail: Option<Arc<crate::mail::MailRelay>>,
+    
+    /// Per-minute rollups of server metrics, when enabled
+    pub metrics_history: Option<Arc<crate::observability::history::MetricsHistory>>,
+    
+    /// Per-site time series behind the tenant graphs
+    pub telemetry: Arc<telemetry::TelemetryCollector>,
+    
+    /// Recent daemon and access log lines
+    pub logs: Arc<crate::observability::logs::LogBuffer>,
+    
+    /// Checks the admin token on tenant and site management, when one is configured
+    pub auth: Option<Arc<crate::tenancy::auth::AuthManager>>,
+    
+    /// Blue/green deployments of each site
+    pub deployments: Arc<crate::deployment::bluegreen::BlueGreenManager>,
+    
+    /// Builds and deploys of sites that deploy from git
+    pub git: Arc<crate::deployment::git::GitDeployer>,
+    
+    /// Snapshots of tenants, sites and their state
+    pub snapshots: Arc<crate::storage::snapshot::SnapshotManager>,
+    
+    /// Shared state documents of each site
+    pub documents: Arc<crate::crdt::DocumentStore>,
+    
+    /// User sessions of each site, unless disabled
+    pub sessions: Option<Arc<crate::crdt::session::SessionManager>>,
+    
+    /// Calls recorded on deterministic sites
+    pub recordings: Arc<crate::cage::determinism::RecordingStore>,
+    
+    /// What each site's guests print, unless capture is disabled
+    pub guest_logs: Option<Arc<crate::observability::guest_logs::GuestLogs>>,
+    
+    /// Reports of guests that trapped
+    pub crashes: Arc<crate::cage::crash::CrashStore>,
+    
+    /// Sampling profiles of profiled sites
+    pub profiles: Arc<crate::cage::profiling::ProfileStore>,
+    
+    /// Record of management actions, unless disabled
+    pub audit: Option<Arc<crate::observability::audit::AuditLog>>,
+    
+    /// Webhooks for operational events, unless disabled
+    pub notifications: Option<Arc<crate::notifications::Notifications>>,
+    
+    /// Readiness state and requirements behind `/readyz`
+    pub health: Arc<crate::observability::health::Health>,
+    
+    /// Sheds connections to other nodes ahead of maintenance
+    pub rebalancer: Arc<crate::network::rebalance::Rebalancer>,
+    
+    /// Static files behind `/` and `/static`
+    pub assets: assets::Assets,
+}
+
+/// Bind the dashboard listener
+/// Done before privileges are dropped so privileged ports work
+pub async fn bind(port: u16) -> anyhow::Result<tokio::net::TcpListener> {
+    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+    
+    // Reuse the listener handed over by a previous process during an upgrade
+    let listener = match crate::upgrade::inherited_tcp_listeners(&addr).pop() {
+        Some(inherited) => {
+            inherited.set_nonblocking(true)?;
+            tokio::net::TcpListener::from_std(inherited)?
+        }
+        None => tokio::net::TcpListener::bind(addr).await?,
+    };
+    crate::upgrade::register_listener(crate::upgrade::tcp_name(&addr), listener.as_raw_fd());
+    
+    Ok(listener)
 }
 
 /// Start the dashboard server
 pub async fn serve(
-    port: u16,
-    router: Arc<crate::router::Router>,
-    supervisor: Arc<crate::supervisor::Supervisor>,
-    ai_module: Arc<crate::ai::AiSecurityModule>,
+    listener: tokio::net::TcpListener,
+    state: Arc<DashboardState>,
+    tls: Option<tokio_rustls::TlsAcceptor>,
 ) -> anyhow::Result<()> {
-    info!(port = port, "Starting administration dashboard");
+    let addr = listener.local_addr()?;
+    info!(port = addr.port(), "Starting administration dashboard");
 
-    let state = Arc::new(DashboardState {
-        router,
-        supervisor,
-        ai_module,
-    });
+    state.telemetry.start(state.router.clone(), telemetry::DEFAULT_SAMPLE_INTERVAL);
 
     // Build our application with routes
     let app = Router::new()
-        .route("/", get(dashboard_index))
+        .route("/", get(assets::index))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
         .route("/ws", get(websocket::handler))
-        .nest_service("/static", ServeDir::new("static"))
+        .route("/api/status", get(api::status))
+        .route("/api/metrics/history", get(api::metrics_history))
+        .route("/api/latency", get(api::latency))
+        .route("/api/pools", get(api::pools))
+        .route("/api/connections", get(api::connections))
+        .route("/api/rebalance", get(api::rebalance_status).post(api::start_rebalance).delete(api::cancel_rebalance))
+        .route("/metrics", get(prometheus::handler))
+        .route("/api/logs", get(logs::handler))
+        .route("/api/security/rules", get(api::security_rules))
+        .route("/api/security/events", get(api::security_events))
+        .route("/api/security/bans", get(api::security_bans))
+        .route("/api/security/bans/:ip", put(api::ban_ip).delete(api::unban_ip))
+        .route("/api/security/sites/:site_id", put(api::update_site_rules))
+        .route("/api/security/sites/:site_id/policy", get(api::site_policy).put(api::update_site_policy))
+        .route("/api/bandwidth", get(api::bandwidth))
+        .route("/api/bandwidth/sites/:site_id", get(api::site_bandwidth))
+        .route("/api/bandwidth/sites/:site_id/quota", put(api::update_site_bandwidth_quota))
+        .route("/api/billing", get(api::billing))
+        .route("/api/supervisor/events", get(api::supervisor_events))
+        .route("/api/health-checks", get(api::health_checks).put(api::update_health_checks))
+        .route("/api/sites/:site_id/health", get(api::site_health).put(api::update_site_health).delete(api::reset_site_health))
+        .route("/api/sites/:site_id/acl", get(api::site_acl).put(api::update_site_acl))
+        .route("/api/sites/:site_id/env", get(api::site_env))
+        .route("/api/sites/:site_id/env/:name", put(api::set_site_env).delete(api::unset_site_env))
+        .route("/api/cron", get(api::cron_jobs))
+        .route("/api/sites/:site_id/cron", get(api::site_cron_jobs).post(api::add_cron_job))
+        .route("/api/sites/:site_id/cron/:name", delete(api::remove_cron_job))
+        .route("/api/sites/:site_id/cron/:name/history", get(api::cron_job_history))
+        .route("/api/sites/:site_id/queue", get(api::site_queue))
+        .route("/api/sites/:site_id/queue/dead/:task_id", delete(api::discard_dead_task))
+        .route("/api/sites/:site_id/queue/dead/:task_id/retry", post(api::retry_dead_task))
+        .route("/api/sites/:site_id/pubsub", get(api::site_pubsub))
+        .route("/api/sites/:site_id/sessions", get(api::site_sessions))
+        .route("/api/sites/:site_id/crashes", get(crashes::list))
+        .route("/api/sites/:site_id/crashes/:id", get(crashes::get))
+        .route("/api/sites/:site_id/profile", get(profile::folded).delete(profile::reset))
+        .route("/api/sites/:site_id/recordings", get(recordings::list))
+        .route("/api/sites/:site_id/recordings/:id", get(recordings::get))
+        .route("/api/sites/:site_id/recordings/:id/replay", post(recordings::replay))
+        .route("/api/pubsub/messages", post(api::receive_pubsub_messages))
+        .route("/api/tenants", get(api::tenants).post(api::create_tenant))
+        .route("/api/tenants/:tenant_id", delete(api::delete_tenant))
+        .route("/api/tenants/:tenant_id/suspend", post(api::suspend_tenant))
+        .route("/api/tenants/:tenant_id/activate", post(api::activate_tenant))
+        .route("/api/tenants/:tenant_id/quota", get(api::tenant_quota).put(api::update_tenant_quota))
+        .route("/api/tenants/:tenant_id/usage", get(api::tenant_usage))
+        .route("/api/tenants/:tenant_id/billing", get(api::tenant_billing))
+        .route("/api/tenants/:tenant_id/sites", get(api::tenant_sites).post(api::add_tenant_site))
+        .route("/api/tenants/:tenant_id/keys", get(api::tenant_keys).post(api::add_tenant_key))
+        .route("/api/tenants/:tenant_id/keys/:name", delete(api::remove_tenant_key))
+        .route("/api/tenants/:tenant_id/encryption", get(api::tenant_encryption).post(api::enable_tenant_encryption))
+        .route("/api/tenants/:tenant_id/encryption/rotate", post(api::rotate_tenant_encryption))
+        .route("/api/encryption/rewrap", post(api::rewrap_tenant_keys))
+        .route(
+            "/api/tenants/:tenant_id/artifacts",
+            get(api::tenant_artifacts).put(api::set_tenant_artifacts).delete(api::remove_tenant_artifacts),
+        )
+        .route("/api/sites/:site_id/content/sync", post(api::sync_site_content))
+        .route(
+            "/api/sites/:site_id/files/*path",
+            put(api::put_site_file)
+                .delete(api::delete_site_file)
+                .layer(DefaultBodyLimit::max(crate::deployment::validate::MAX_MODULE_BYTES)),
+        )
+        .route("/api/sites/:site_id/git", get(git::source).put(git::set_source).delete(git::remove_source))
+        .route("/api/sites/:site_id/git/deploy", post(git::deploy))
+        .route("/api/sites/:site_id/git/webhook", post(git::webhook))
+        .route("/api/sites/:site_id", delete(api::remove_site))
+        .route("/api/sites/:site_id/drain", post(api::drain_site))
+        .route("/api/sites/:site_id/resume", post(api::resume_site))
+        .route("/api/sites/:site_id/signing", put(api::set_site_signing))
+        .route("/api/sites/:site_id/domain", put(api::set_site_domain).delete(api::remove_site_domain))
+        .route("/api/sites/:site_id/domains", get(api::site_domains).post(api::claim_site_domain))
+        .route("/api/sites/:site_id/domains/:hostname", delete(api::release_site_domain))
+        .route("/api/sites/:site_id/domains/:hostname/verify", post(api::verify_site_domain))
+        .route(
+            "/api/sites/:site_id/blue-green",
+            get(deployments::status)
+                .post(deployments::stage)
+                .layer(DefaultBodyLimit::max(crate::deployment::validate::MAX_MODULE_BYTES)),
+        )
+        .route(
+            "/api/sites/:site_id/swap",
+            post(deployments::swap).layer(DefaultBodyLimit::max(crate::deployment::validate::MAX_MODULE_BYTES)),
+        )
+        .route("/api/sites/:site_id/blue-green/switch", post(deployments::switch))
+        .route("/api/sites/:site_id/blue-green/rollback", post(deployments::rollback))
+        .route("/api/sites/:site_id/blue-green/finish", post(deployments::finish))
+        .route("/api/sites/:site_id/deployments", get(deployments::history))
+        .route("/api/snapshot", get(snapshots::create))
+        .route(
+            "/api/snapshot/restore",
+            post(snapshots::restore).layer(DefaultBodyLimit::max(snapshots::MAX_ARCHIVE_BYTES)),
+        )
+        .route("/api/audit", get(audit::list))
+        .route("/api/audit/export", get(audit::export))
+        .route("/api/webhooks", get(notifications::list).post(notifications::register))
+        .route("/api/webhooks/:id", delete(notifications::remove))
+        .route("/api/whoami", get(access::whoami))
+        .route("/auth/config", get(sso::config))
+        .route("/auth/login", get(sso::login))
+        .route("/auth/callback", get(sso::callback))
+        .route("/auth/logout", post(sso::logout))
+        .route("/api/roles", get(access::list_roles))
+        .route("/api/roles/:name", put(access::set_role).delete(access::remove_role))
+        .route("/api/tokens", get(access::list_tokens).post(access::create_token))
+        .route("/api/tokens/:id", delete(access::revoke_token))
+        .route("/api/chaos", get(chaos::status))
+        .route("/api/chaos/sites/:site_id", post(chaos::inject))
+        .route("/api/tenants/:tenant_id/overview", get(api::tenant_overview))
+        .route("/api/tenants/:tenant_id/telemetry", get(api::tenant_telemetry))
+        .route("/api/tenants/:tenant_id/mail", get(api::tenant_mail).put(api::update_tenant_mail_policy))
+        .route("/api/tenants/:tenant_id/mail/log", get(api::tenant_mail_log))
+        .route("/api/tenants/:tenant_id/mail/suppressions", get(api::tenant_mail_suppressions))
+        .route("/api/tenants/:tenant_id/mail/suppressions/:address", put(api::suppress_mail_address).delete(api::unsuppress_mail_address))
+        .route_layer(middleware::from_fn_with_state(state.clone(), access::guard_reads))
+        .route_layer(middleware::from_fn_with_state(state.clone(), audit::record))
+        .route_layer(middleware::from_fn_with_state(state.clone(), access::guard_logins))
+        .route("/static/*path", get(assets::file))
         .with_state(state);
 
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    
-    info!("Dashboard server listening on http://{}", addr);
-
-    axum::serve(listener, app).await?;
+    let Some(acceptor) = tls else {
+        info!("Dashboard server listening on http://{}", addr);
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+        return Ok(());
+    };
 
-    Ok(())
-}
+    // With mutual TLS, only clients holding a certificate from the node's CA get a connection
+    info!("Dashboard server listening on https://{} (client certificates required)", addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let tls = match acceptor.accept(stream).await {
+                Ok(tls) => tls,
+                Err(e) => {
+                    debug!(peer = %peer, error = %e, "Refused management API connection");
+                    return;
+                }
+            };
+            let client = tls.get_ref().1.peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| crate::network::tls::validity(cert).ok())
+                .map(|validity| validity.subject);
+            debug!(peer = %peer, client = ?client, "Management API client connected");
 
-/// Dashboard HTML page
-async fn dashboard_index() -> Html<&'static str> {
-    Html(include_str!("../../static/dashboard.html"))
+            let service = hyper::service::service_fn(move |mut request: hyper::Request<hyper::body::Incoming>| {
+                request.extensions_mut().insert(axum::extract::ConnectInfo(peer));
+                tower::Service::call(&mut app.clone(), request)
+            });
+            if let Err(e) = hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new())
+                .serve_connection_with_upgrades(hyper_util::rt::TokioIo::new(tls), service)
+                .await
+            {
+                debug!(peer = %peer, error = %e, "Management API connection closed with error");
+            }
+        });
+    }
 }
 
 #[cfg(test)]
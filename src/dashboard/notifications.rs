@@ -0,0 +1,138 @@
+// Webhook API
+// Register and remove the webhooks told about operational events
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use super::api::require;
+use super::audit::with_change;
+use super::DashboardState;
+use crate::notifications::{Notifications, WebhookConfig};
+use crate::tenancy::auth::{Permission, Scope};
+
+/// Filter for listing webhooks
+#[derive(Deserialize)]
+pub struct WebhookQuery {
+    pub tenant: Option<Uuid>,
+}
+
+/// Registered webhooks with their delivery counts; secrets are never listed
+pub async fn list(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Query(query): Query<WebhookQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, query.tenant.map_or(Scope::Node, Scope::Tenant)) {
+        return response;
+    }
+    match hub(&state) {
+        Ok(hub) => (StatusCode::OK, Json(json!({ "webhooks": hub.list(query.tenant) }))),
+        Err(response) => response,
+    }
+}
+
+/// Register a webhook; the response carries its signing secret, shown only this once
+pub async fn register(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Json(webhook): Json<WebhookConfig>,
+) -> Response {
+    let claims = match require(&state, &headers, Permission::ManageTenants, webhook.tenant_id.map_or(Scope::Node, Scope::Tenant)) {
+        Ok(claims) => claims,
+        Err(response) => return response.into_response(),
+    };
+    let hub = match hub(&state) {
+        Ok(hub) => hub,
+        Err(response) => return response.into_response(),
+    };
+    if let Some(tenant_id) = webhook.tenant_id {
+        if state.tenants.get_tenant(tenant_id).is_none() {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": format!("Tenant {} not found", tenant_id) })),
+            ).into_response();
+        }
+    }
+
+    // Tenants may only reach the internet, not the node's own network
+    let restricted = claims.tenant_id.is_some();
+    if restricted {
+        if let Err(e) = webhook.resolve_public().await {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": format!("{:#}", e) })),
+            ).into_response();
+        }
+    }
+
+    match hub.register(webhook, restricted) {
+        Ok((id, secret)) => {
+            info!(webhook_id = %id, "Webhook registered via API");
+            let webhook = json!(hub.get(id));
+            let mut body = webhook.clone();
+            body["secret"] = secret.into();
+            with_change((StatusCode::CREATED, Json(body)), None, Some(webhook))
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ).into_response(),
+    }
+}
+
+/// Remove a webhook registered through the API
+pub async fn remove(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, webhook_scope(&state, id)) {
+        return response.into_response();
+    }
+    let hub = match hub(&state) {
+        Ok(hub) => hub,
+        Err(response) => return response.into_response(),
+    };
+
+    let before = hub.get(id);
+    match hub.remove(id) {
+        Ok(true) => {
+            info!(webhook_id = %id, "Webhook removed via API");
+            with_change((StatusCode::OK, Json(json!({ "id": id }))), Some(json!(before)), None)
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Webhook {} not found", id) })),
+        ).into_response(),
+        Err(e) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("{:#}", e) })),
+        ).into_response(),
+    }
+}
+
+fn hub(state: &DashboardState) -> Result<&Arc<Notifications>, (StatusCode, Json<serde_json::Value>)> {
+    state.notifications.as_ref().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Notifications are disabled; set [notifications] enabled = true" })),
+        )
+    })
+}
+
+/// Scope of a request acting on a registered webhook: its tenant's, or the node's
+fn webhook_scope(state: &DashboardState, id: Uuid) -> Scope<'static> {
+    state.notifications.as_ref()
+        .and_then(|hub| hub.get(id))
+        .and_then(|webhook| webhook.tenant_id)
+        .map_or(Scope::Node, Scope::Tenant)
+}
@@ -0,0 +1,70 @@
+// Profile API
+// Sampled guest stacks of profiled sites, in the folded format flamegraph tools read
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use super::api::{require, site_scope};
+use super::DashboardState;
+use crate::cage::profiling::Profiler;
+use crate::tenancy::auth::Permission;
+
+/// The site's profile as `frame;frame;frame count` lines, outermost frame first
+/// `curl .../profile | inferno-flamegraph > profile.svg` draws it.
+pub async fn folded(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> Response {
+    if let Err(response) = require(&state, &headers, Permission::ViewMetrics, site_scope(&state, &site_id)) {
+        return response.into_response();
+    }
+    match profiler(&state, &site_id) {
+        Ok(profiler) => {
+            let stats = profiler.stats();
+            (
+                [
+                    (header::CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+                    (header::HeaderName::from_static("x-pear-profile-samples"), stats.samples.to_string()),
+                    (header::HeaderName::from_static("x-pear-profile-since"), stats.since.to_string()),
+                ],
+                profiler.folded(),
+            ).into_response()
+        }
+        Err(response) => response.into_response(),
+    }
+}
+
+/// Start the site's profile over, returning the totals of the one dropped
+pub async fn reset(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match profiler(&state, &site_id) {
+        Ok(profiler) => {
+            let stats = profiler.stats();
+            profiler.reset();
+            (StatusCode::OK, Json(json!(stats)))
+        }
+        Err(response) => response,
+    }
+}
+
+fn profiler(state: &DashboardState, site_id: &str) -> Result<Arc<Profiler>, (StatusCode, Json<serde_json::Value>)> {
+    state.profiles.get(site_id).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Site {} is not profiled; add it to [profiling] sites", site_id) })),
+        )
+    })
+}
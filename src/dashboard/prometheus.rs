@@ -0,0 +1,383 @@
+// Prometheus Exposition
+// Request counters and per-site and per-Cage latency summaries in the Prometheus text format
+
+use axum::{
+    extract::State,
+    http::header,
+    response::IntoResponse,
+};
+use std::fmt::Write;
+use std::sync::Arc;
+
+use super::DashboardState;
+use crate::cage::pool::CageLatency;
+use crate::cage::request_queue::QueueStats;
+use crate::crdt::DocumentStats;
+use crate::network::acceptor::ShardStats;
+use crate::network::quic_guard::{HandshakeRejection, QuicGuardStats};
+use crate::observability::guest_logs::GuestLogStats;
+use crate::observability::histogram::HistogramSnapshot;
+use crate::router::{RouterStats, SiteTrafficStats};
+
+/// Quantiles reported for every latency summary
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Scrape endpoint
+pub async fn handler(State(state): State<Arc<DashboardState>>) -> impl IntoResponse {
+    let sites = state.router.site_traffic();
+    let mut cages = Vec::new();
+    let mut cold_starts = Vec::new();
+    let mut queues = Vec::new();
+    for site in &sites {
+        if let Some(pool) = state.router.pool(&site.site_id) {
+            cages.push((site.site_id.clone(), pool.cage_latencies().await));
+            cold_starts.push((site.site_id.clone(), pool.cold_starts()));
+            queues.push((site.site_id.clone(), pool.queue().stats()));
+        }
+    }
+
+    let mut text = render(&state.router.stats(), &sites, &cages);
+    render_cold_starts(&mut text, &cold_starts);
+    render_queues(&mut text, &queues);
+    render_documents(&mut text, &state.documents.stats());
+    if let Some(sessions) = &state.sessions {
+        render_sessions(&mut text, &sessions.active_counts().await);
+    }
+    render_crashes(&mut text, &state.crashes.totals());
+    if let Some(guard) = state.router.quic_guard() {
+        render_quic_guard(&mut text, &guard.stats());
+    }
+    let listeners: Vec<_> = state.router.listener_metrics().iter()
+        .map(|(label, metrics)| (label.clone(), metrics.snapshot()))
+        .collect();
+    render_acceptors(&mut text, &listeners);
+    if let Some(guest_logs) = &state.guest_logs {
+        render_guest_logs(&mut text, &guest_logs.stats());
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text)
+}
+
+fn render(stats: &RouterStats, sites: &[SiteTrafficStats], cages: &[(String, Vec<CageLatency>)]) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# HELP pear_requests_total Requests handled by the router.");
+    let _ = writeln!(out, "# TYPE pear_requests_total counter");
+    let _ = writeln!(out, "pear_requests_total {}", stats.total_requests);
+    let _ = writeln!(out, "# HELP pear_requests_failed_total Requests the router failed to serve.");
+    let _ = writeln!(out, "# TYPE pear_requests_failed_total counter");
+    let _ = writeln!(out, "pear_requests_failed_total {}", stats.failed_requests);
+    let _ = writeln!(out, "# HELP pear_request_errors_total Failed requests by error code.");
+    let _ = writeln!(out, "# TYPE pear_request_errors_total counter");
+    for (code, count) in &stats.errors {
+        let _ = writeln!(out, "pear_request_errors_total{{code=\"{}\"}} {}", code, count);
+    }
+
+    let _ = writeln!(out, "# HELP pear_wasm_running Guest calls running on the Wasm executor.");
+    let _ = writeln!(out, "# TYPE pear_wasm_running gauge");
+    let _ = writeln!(out, "pear_wasm_running {}", stats.wasm.running);
+    let _ = writeln!(out, "# HELP pear_wasm_queued Guest calls waiting for an executor slot.");
+    let _ = writeln!(out, "# TYPE pear_wasm_queued gauge");
+    let _ = writeln!(out, "pear_wasm_queued {}", stats.wasm.queued);
+    let _ = writeln!(out, "# HELP pear_wasm_rejected_total Requests shed because the Wasm executor queue was full.");
+    let _ = writeln!(out, "# TYPE pear_wasm_rejected_total counter");
+    let _ = writeln!(out, "pear_wasm_rejected_total {}", stats.wasm.rejected);
+
+    let _ = writeln!(out, "# HELP pear_requests_in_flight Requests admitted and not yet answered.");
+    let _ = writeln!(out, "# TYPE pear_requests_in_flight gauge");
+    let _ = writeln!(out, "pear_requests_in_flight {}", stats.admission.in_flight);
+    let _ = writeln!(out, "# HELP pear_requests_shed_total Requests answered 503 by admission control, by the limit reached.");
+    let _ = writeln!(out, "# TYPE pear_requests_shed_total counter");
+    let _ = writeln!(out, "pear_requests_shed_total{{reason=\"global\"}} {}", stats.admission.shed_global);
+    let _ = writeln!(out, "pear_requests_shed_total{{reason=\"site\"}} {}", stats.admission.shed_site);
+    let _ = writeln!(out, "pear_requests_shed_total{{reason=\"latency\"}} {}", stats.admission.shed_latency);
+
+    let _ = writeln!(out, "# HELP pear_cages_ejected Cages out of selection as outliers.");
+    let _ = writeln!(out, "# TYPE pear_cages_ejected gauge");
+    let _ = writeln!(out, "pear_cages_ejected {}", stats.outliers.ejected);
+    let _ = writeln!(out, "# HELP pear_cage_ejections_total Outlier Cages ejected from selection, by reason.");
+    let _ = writeln!(out, "# TYPE pear_cage_ejections_total counter");
+    let _ = writeln!(out, "pear_cage_ejections_total{{reason=\"consecutive_failures\"}} {}", stats.outliers.consecutive_failures);
+    let _ = writeln!(out, "pear_cage_ejections_total{{reason=\"failure_rate\"}} {}", stats.outliers.failure_rate);
+    let _ = writeln!(out, "pear_cage_ejections_total{{reason=\"latency\"}} {}", stats.outliers.latency);
+    let _ = writeln!(out, "# HELP pear_retries_total Failed requests retried on another Cage.");
+    let _ = writeln!(out, "# TYPE pear_retries_total counter");
+    let _ = writeln!(out, "pear_retries_total {}", stats.retries.retries);
+    let _ = writeln!(out, "# HELP pear_retries_budget_exhausted_total Retries not made because the retry budget was spent.");
+    let _ = writeln!(out, "# TYPE pear_retries_budget_exhausted_total counter");
+    let _ = writeln!(out, "pear_retries_budget_exhausted_total {}", stats.retries.budget_exhausted);
+
+    let _ = writeln!(out, "# HELP pear_site_requests_total Responses sent per site.");
+    let _ = writeln!(out, "# TYPE pear_site_requests_total counter");
+    for site in sites {
+        let _ = writeln!(out, "pear_site_requests_total{{site=\"{}\"}} {}", escape(&site.site_id), site.requests);
+    }
+    let _ = writeln!(out, "# HELP pear_site_errors_total Responses with a 4xx or 5xx status per site.");
+    let _ = writeln!(out, "# TYPE pear_site_errors_total counter");
+    for site in sites {
+        let _ = writeln!(out, "pear_site_errors_total{{site=\"{}\"}} {}", escape(&site.site_id), site.errors);
+    }
+
+    let _ = writeln!(out, "# HELP pear_request_duration_seconds Time from request to response head per site.");
+    let _ = writeln!(out, "# TYPE pear_request_duration_seconds summary");
+    for site in sites {
+        let labels = format!("site=\"{}\"", escape(&site.site_id));
+        write_summary(&mut out, "pear_request_duration_seconds", &labels, &site.latency);
+    }
+
+    let _ = writeln!(out, "# HELP pear_cage_execution_duration_seconds Time a Cage spent executing requests.");
+    let _ = writeln!(out, "# TYPE pear_cage_execution_duration_seconds summary");
+    for (site_id, site_cages) in cages {
+        for cage in site_cages {
+            let labels = format!("site=\"{}\",cage=\"{}\"", escape(site_id), cage.cage_id);
+            write_summary(&mut out, "pear_cage_execution_duration_seconds", &labels, &cage.latency);
+        }
+    }
+
+    out
+}
+
+/// Time lazy and scale-to-zero pools took to start their first Cage
+fn render_cold_starts(out: &mut String, cold_starts: &[(String, HistogramSnapshot)]) {
+    let _ = writeln!(out, "# HELP pear_cold_start_duration_seconds Time from a request to an idle pool's first Cage per site.");
+    let _ = writeln!(out, "# TYPE pear_cold_start_duration_seconds summary");
+    for (site_id, latency) in cold_starts {
+        let labels = format!("site=\"{}\"", escape(site_id));
+        write_summary(out, "pear_cold_start_duration_seconds", &labels, latency);
+    }
+}
+
+/// Requests waiting for a Cage below its concurrency cap, how long they waited and those shed
+fn render_queues(out: &mut String, queues: &[(String, QueueStats)]) {
+    let _ = writeln!(out, "# HELP pear_request_queue_depth Requests waiting for a free Cage per site.");
+    let _ = writeln!(out, "# TYPE pear_request_queue_depth gauge");
+    for (site_id, queue) in queues {
+        let _ = writeln!(out, "pear_request_queue_depth{{site=\"{}\"}} {}", escape(site_id), queue.queued);
+    }
+    let _ = writeln!(out, "# HELP pear_request_queue_wait_seconds Time requests waited for a free Cage per site.");
+    let _ = writeln!(out, "# TYPE pear_request_queue_wait_seconds summary");
+    for (site_id, queue) in queues {
+        let labels = format!("site=\"{}\"", escape(site_id));
+        write_summary(out, "pear_request_queue_wait_seconds", &labels, &queue.wait);
+    }
+    let _ = writeln!(out, "# HELP pear_request_queue_shed_total Requests shed because every Cage was busy, by reason.");
+    let _ = writeln!(out, "# TYPE pear_request_queue_shed_total counter");
+    for (site_id, queue) in queues {
+        let site = escape(site_id);
+        let _ = writeln!(out, "pear_request_queue_shed_total{{site=\"{}\",reason=\"full\"}} {}", site, queue.rejected);
+        let _ = writeln!(out, "pear_request_queue_shed_total{{site=\"{}\",reason=\"timeout\"}} {}", site, queue.timed_out);
+    }
+}
+
+/// Size and compactions of each site's shared state document
+fn render_documents(out: &mut String, documents: &[DocumentStats]) {
+    let _ = writeln!(out, "# HELP pear_crdt_document_bytes Saved size of each site's shared state document.");
+    let _ = writeln!(out, "# TYPE pear_crdt_document_bytes gauge");
+    for document in documents {
+        let _ = writeln!(out, "pear_crdt_document_bytes{{site=\"{}\"}} {}", escape(&document.site_id), document.size_bytes);
+    }
+    let _ = writeln!(out, "# HELP pear_crdt_document_limit_bytes Size at which writes to a site's shared state are refused.");
+    let _ = writeln!(out, "# TYPE pear_crdt_document_limit_bytes gauge");
+    for document in documents {
+        if let Some(max_bytes) = document.max_bytes {
+            let _ = writeln!(out, "pear_crdt_document_limit_bytes{{site=\"{}\"}} {}", escape(&document.site_id), max_bytes);
+        }
+    }
+    let _ = writeln!(out, "# HELP pear_crdt_compactions_total Times a site's shared state history was dropped.");
+    let _ = writeln!(out, "# TYPE pear_crdt_compactions_total counter");
+    for document in documents {
+        let _ = writeln!(out, "pear_crdt_compactions_total{{site=\"{}\"}} {}", escape(&document.site_id), document.compactions);
+    }
+}
+
+/// Sessions that have not expired, per site
+fn render_sessions(out: &mut String, counts: &[(String, usize)]) {
+    let _ = writeln!(out, "# HELP pear_sessions_active User sessions that have not expired per site.");
+    let _ = writeln!(out, "# TYPE pear_sessions_active gauge");
+    for (site_id, count) in counts {
+        let _ = writeln!(out, "pear_sessions_active{{site=\"{}\"}} {}", escape(site_id), count);
+    }
+}
+
+/// Guest traps per site
+fn render_crashes(out: &mut String, totals: &[(String, u64)]) {
+    let _ = writeln!(out, "# HELP pear_cage_crashes_total Guest traps reported per site.");
+    let _ = writeln!(out, "# TYPE pear_cage_crashes_total counter");
+    for (site_id, total) in totals {
+        let _ = writeln!(out, "pear_cage_crashes_total{{site=\"{}\"}} {}", escape(site_id), total);
+    }
+}
+
+/// Refused QUIC handshakes, bans and whether clients must validate their address
+fn render_quic_guard(out: &mut String, stats: &QuicGuardStats) {
+    let _ = writeln!(out, "# HELP pear_quic_handshakes_rejected_total QUIC handshakes refused before TLS, by reason.");
+    let _ = writeln!(out, "# TYPE pear_quic_handshakes_rejected_total counter");
+    for reason in HandshakeRejection::ALL {
+        let _ = writeln!(out, "pear_quic_handshakes_rejected_total{{reason=\"{}\"}} {}", reason.as_str(), stats.rejected(reason));
+    }
+    let _ = writeln!(out, "# HELP pear_quic_handshake_bans_total Clients banned for flooding QUIC handshakes.");
+    let _ = writeln!(out, "# TYPE pear_quic_handshake_bans_total counter");
+    let _ = writeln!(out, "pear_quic_handshake_bans_total {}", stats.bans);
+    let _ = writeln!(out, "# HELP pear_quic_handshakes_per_second New QUIC connections in the last full second.");
+    let _ = writeln!(out, "# TYPE pear_quic_handshakes_per_second gauge");
+    let _ = writeln!(out, "pear_quic_handshakes_per_second {}", stats.handshakes_per_sec);
+    let _ = writeln!(out, "# HELP pear_quic_address_validation Whether new QUIC clients must echo a Retry token first.");
+    let _ = writeln!(out, "# TYPE pear_quic_address_validation gauge");
+    let _ = writeln!(out, "pear_quic_address_validation {}", u8::from(stats.address_validation));
+}
+
+/// Accept counters of each listener shard
+fn render_acceptors(out: &mut String, listeners: &[(String, Vec<ShardStats>)]) {
+    let counters: [(&str, &str, fn(&ShardStats) -> u64); 4] = [
+        ("pear_acceptor_accepted_total", "Connections accepted by each listener shard.", |s| s.accepted),
+        ("pear_acceptor_errors_total", "Failed accepts on each listener shard.", |s| s.accept_errors),
+        ("pear_acceptor_rejected_total", "Connections refused by a per-IP or accept queue limit.", |s| s.rejected),
+        ("pear_acceptor_streams_rejected_total", "Streams refused because the listener ran as many as allowed.", |s| s.streams_rejected),
+    ];
+    for (name, help, value) in counters {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (label, shards) in listeners {
+            for shard in shards {
+                let _ = writeln!(out, "{}{{listener=\"{}\",shard=\"{}\"}} {}", name, escape(label), shard.shard, value(shard));
+            }
+        }
+    }
+}
+
+/// Lines each site's guests printed, and those dropped by the rate limit
+fn render_guest_logs(out: &mut String, stats: &[GuestLogStats]) {
+    let _ = writeln!(out, "# HELP pear_guest_log_lines_total Lines printed by each site's guests.");
+    let _ = writeln!(out, "# TYPE pear_guest_log_lines_total counter");
+    for site in stats {
+        let _ = writeln!(out, "pear_guest_log_lines_total{{site=\"{}\"}} {}", escape(&site.site_id), site.lines);
+    }
+    let _ = writeln!(out, "# HELP pear_guest_log_dropped_total Guest lines dropped over the site's rate limit.");
+    let _ = writeln!(out, "# TYPE pear_guest_log_dropped_total counter");
+    for site in stats {
+        let _ = writeln!(out, "pear_guest_log_dropped_total{{site=\"{}\"}} {}", escape(&site.site_id), site.dropped);
+    }
+}
+
+fn write_summary(out: &mut String, name: &str, labels: &str, latency: &HistogramSnapshot) {
+    for quantile in QUANTILES {
+        let _ = writeln!(
+            out,
+            "{}{{{},quantile=\"{}\"}} {}",
+            name,
+            labels,
+            quantile,
+            latency.percentile(quantile).as_secs_f64()
+        );
+    }
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, latency.sum().as_secs_f64());
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, latency.count());
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::histogram::LatencyHistogram;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render_summaries() {
+        let histogram = LatencyHistogram::new();
+        histogram.record(Duration::from_millis(20));
+        let latency = histogram.snapshot();
+
+        let stats = RouterStats {
+            total_requests: 3,
+            successful_requests: 2,
+            failed_requests: 1,
+            active_pools: 1,
+            latency: latency.percentiles(),
+            wasm: crate::cage::executor::ExecutorStats { rejected: 4, ..Default::default() },
+            admission: crate::router::admission::AdmissionStats { shed_latency: 2, ..Default::default() },
+            outliers: crate::router::outlier::OutlierStats { ejected: 1, latency: 3, ..Default::default() },
+            retries: crate::router::retry::RetryStats { retries: 5, budget_exhausted: 0 },
+            errors: [("site_not_found", 1)].into_iter().collect(),
+        };
+        let sites = vec![SiteTrafficStats {
+            site_id: "blog\"".to_string(),
+            requests: 3,
+            errors: 1,
+            latency: latency.clone(),
+        }];
+        let cages = vec![("blog\"".to_string(), vec![CageLatency { cage_id: 7, latency }])];
+
+        let mut text = render(&stats, &sites, &cages);
+        assert!(text.contains("pear_requests_total 3\n"));
+        assert!(text.contains("pear_wasm_rejected_total 4\n"));
+        assert!(text.contains("pear_requests_shed_total{reason=\"latency\"} 2\n"));
+        assert!(text.contains("pear_request_errors_total{code=\"site_not_found\"} 1\n"));
+        assert!(text.contains("pear_cage_ejections_total{reason=\"latency\"} 3\n"));
+        assert!(text.contains("pear_retries_total 5\n"));
+        assert!(text.contains("pear_site_errors_total{site=\"blog\\\"\"} 1\n"));
+        assert!(text.contains("pear_request_duration_seconds_count{site=\"blog\\\"\"} 1\n"));
+        assert!(text.contains("pear_cage_execution_duration_seconds{site=\"blog\\\"\",cage=\"7\",quantile=\"0.99\"} 0.02"));
+
+        render_cold_starts(&mut text, &[("blog".to_string(), histogram.snapshot())]);
+        assert!(text.contains("pear_cold_start_duration_seconds_count{site=\"blog\"} 1\n"));
+
+        let queue = QueueStats { queued: 2, rejected: 1, timed_out: 3, wait: histogram.snapshot() };
+        render_queues(&mut text, &[("blog".to_string(), queue)]);
+        assert!(text.contains("pear_request_queue_depth{site=\"blog\"} 2\n"));
+        assert!(text.contains("pear_request_queue_wait_seconds_count{site=\"blog\"} 1\n"));
+        assert!(text.contains("pear_request_queue_shed_total{site=\"blog\",reason=\"timeout\"} 3\n"));
+    }
+
+    #[test]
+    fn test_render_acceptors() {
+        let metrics = crate::network::acceptor::AcceptorMetrics::new(2);
+        metrics.record_accept(1);
+        metrics.record_accept(1);
+        metrics.record_rejected(0);
+
+        let mut text = String::new();
+        render_acceptors(&mut text, &[("http2 0.0.0.0:443".to_string(), metrics.snapshot())]);
+        assert!(text.contains("pear_acceptor_accepted_total{listener=\"http2 0.0.0.0:443\",shard=\"1\"} 2\n"));
+        assert!(text.contains("pear_acceptor_accepted_total{listener=\"http2 0.0.0.0:443\",shard=\"0\"} 0\n"));
+        assert!(text.contains("pear_acceptor_rejected_total{listener=\"http2 0.0.0.0:443\",shard=\"0\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_quic_guard() {
+        let stats = QuicGuardStats { rejected: [0, 7, 2, 0, 1], bans: 1, handshakes_per_sec: 1500, address_validation: true };
+        let mut text = String::new();
+        render_quic_guard(&mut text, &stats);
+        assert!(text.contains("pear_quic_handshakes_rejected_total{reason=\"rate_limited\"} 7\n"));
+        assert!(text.contains("pear_quic_handshakes_rejected_total{reason=\"backlog\"} 1\n"));
+        assert!(text.contains("pear_quic_handshake_bans_total 1\n"));
+        assert!(text.contains("pear_quic_address_validation 1\n"));
+    }
+
+    #[test]
+    fn test_render_documents() {
+        let documents = vec![
+            DocumentStats { site_id: "blog".to_string(), size_bytes: 2048, max_bytes: Some(8192), compactions: 3 },
+            DocumentStats { site_id: "shop".to_string(), size_bytes: 512, max_bytes: None, compactions: 0 },
+        ];
+
+        let mut text = String::new();
+        render_documents(&mut text, &documents);
+        assert!(text.contains("pear_crdt_document_bytes{site=\"blog\"} 2048\n"));
+        assert!(text.contains("pear_crdt_document_limit_bytes{site=\"blog\"} 8192\n"));
+        assert!(!text.contains("pear_crdt_document_limit_bytes{site=\"shop\"}"));
+        assert!(text.contains("pear_crdt_compactions_total{site=\"shop\"} 0\n"));
+
+        render_sessions(&mut text, &[("blog".to_string(), 5)]);
+        assert!(text.contains("pear_sessions_active{site=\"blog\"} 5\n"));
+
+        render_crashes(&mut text, &[("blog".to_string(), 2)]);
+        assert!(text.contains("pear_cage_crashes_total{site=\"blog\"} 2\n"));
+
+        render_guest_logs(&mut text, &[GuestLogStats { site_id: "blog".to_string(), lines: 40, dropped: 7 }]);
+        assert!(text.contains("pear_guest_log_lines_total{site=\"blog\"} 40\n"));
+        assert!(text.contains("pear_guest_log_dropped_total{site=\"blog\"} 7\n"));
+    }
+}
@@ -0,0 +1,131 @@
+// Recording API
+// List the calls recorded on deterministic sites and replay them against the deployed module
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use base64::Engine;
+use http_body_util::BodyExt;
+use serde_json::json;
+use std::sync::Arc;
+
+use super::api::{require, site_scope};
+use super::DashboardState;
+use crate::cage::determinism::{Recorder, Recording};
+use crate::router::stream::{self, StreamingConfig};
+use crate::state::StreamAccounting;
+use crate::tenancy::auth::Permission;
+
+/// Recorded calls of a site, oldest first, without their inputs
+pub async fn list(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path(site_id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ViewMetrics, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let recorder = match recorder(&state, &site_id) {
+        Ok(recorder) => recorder,
+        Err(response) => return response,
+    };
+    let recordings: Vec<_> = recorder.list().into_iter()
+        .map(|recording| json!({
+            "id": recording.id,
+            "cage_id": recording.cage_id,
+            "recorded_at": recording.recorded_at,
+            "kind": kind(&recording),
+            "result": recording.result,
+            "error": recording.error,
+        }))
+        .collect();
+    (StatusCode::OK, Json(json!({ "site_id": site_id, "recordings": recordings })))
+}
+
+/// A recorded call with its input, to keep for debugging
+pub async fn get(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, id)): Path<(String, u64)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ViewMetrics, site_scope(&state, &site_id)) {
+        return response;
+    }
+    match recording(&state, &site_id, id) {
+        Ok(recording) => (StatusCode::OK, Json(json!(recording))),
+        Err(response) => response,
+    }
+}
+
+/// Run a recorded call again with the time and randomness it had
+/// Runs against the module deployed now; `matches` says whether it ended as recorded.
+pub async fn replay(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Path((site_id, id)): Path<(String, u64)>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageSites, site_scope(&state, &site_id)) {
+        return response;
+    }
+    let recording = match recording(&state, &site_id, id) {
+        Ok(recording) => recording,
+        Err(response) => return response,
+    };
+    let cage = match state.router.pool(&site_id) {
+        Some(pool) => pool.cages().await.into_iter().find(|cage| cage.is_healthy()),
+        None => None,
+    };
+    let Some(cage) = cage else {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": format!("Site {} has no healthy Cage to replay on", site_id) })),
+        );
+    };
+
+    // The response is collected while the guest writes it, so a full buffer can't stall the guest
+    let (sink, body) = stream::channel(&StreamingConfig::default(), Arc::new(StreamAccounting::default()));
+    let replayed = recording.clone();
+    let call = tokio::task::spawn_blocking(move || cage.replay(&replayed, Arc::new(sink)));
+    let output = body.collect().await.map(|collected| collected.to_bytes()).unwrap_or_default();
+    let (result, error) = match call.await {
+        Ok(Ok(result)) => (result, None),
+        Ok(Err(e)) => (None, Some(format!("{:#}", e))),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": e.to_string() }))),
+    };
+
+    let matches = result == recording.result && error == recording.error;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "id": recording.id,
+            "result": result,
+            "error": error,
+            "output": base64::engine::general_purpose::STANDARD.encode(&output),
+            "matches": matches,
+        })),
+    )
+}
+
+fn kind(recording: &Recording) -> serde_json::Value {
+    json!(recording.call)["kind"].clone()
+}
+
+fn recorder(state: &DashboardState, site_id: &str) -> Result<Arc<Recorder>, (StatusCode, Json<serde_json::Value>)> {
+    state.recordings.get(site_id).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Site {} does not record calls", site_id) })),
+        )
+    })
+}
+
+fn recording(state: &DashboardState, site_id: &str, id: u64) -> Result<Recording, (StatusCode, Json<serde_json::Value>)> {
+    recorder(state, site_id)?.get(id).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("No recording {} on site {}", id, site_id) })),
+        )
+    })
+}
@@ -0,0 +1,86 @@
+// Snapshot API
+// Download a snapshot of the node's tenants, sites and state, and restore one
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+use super::api::{find_tenant, require};
+use super::DashboardState;
+use crate::storage::snapshot::SnapshotArchive;
+use crate::tenancy::auth::{Permission, Scope};
+
+/// Largest archive accepted for restore; archives carry every deployed module, base64 encoded
+pub const MAX_ARCHIVE_BYTES: usize = 512 * 1024 * 1024;
+
+/// Limit a snapshot or restore to one tenant
+#[derive(Deserialize)]
+pub struct SnapshotQuery {
+    /// Tenant ID or name
+    pub tenant: Option<String>,
+}
+
+/// Snapshot the node, or one tenant, as an archive
+pub async fn create(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Query(query): Query<SnapshotQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response;
+    }
+    let tenant = match query.tenant.as_deref().map(|tenant| find_tenant(&state, tenant)).transpose() {
+        Ok(tenant) => tenant.map(|tenant| tenant.id),
+        Err(response) => return response,
+    };
+    let archive = state.snapshots.create(tenant).await
+        .and_then(|snapshot| SnapshotArchive::seal(&snapshot));
+    match archive {
+        Ok(archive) => (StatusCode::OK, Json(json!(archive))),
+        Err(e) => (StatusCode::CONFLICT, Json(json!({ "error": format!("{:#}", e) }))),
+    }
+}
+
+/// Check an uploaded archive, then restore it, or only one tenant from it
+pub async fn restore(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+    Query(query): Query<SnapshotQuery>,
+    archive: Bytes,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let Err(response) = require(&state, &headers, Permission::ManageTenants, Scope::Node) {
+        return response;
+    }
+
+    // Hashing every module of a large archive is too slow for a runtime thread
+    let opened = tokio::task::spawn_blocking(move || {
+        serde_json::from_slice::<SnapshotArchive>(&archive)
+            .map_err(anyhow::Error::from)
+            .and_then(SnapshotArchive::open)
+    }).await;
+    let snapshot = match opened {
+        Ok(Ok(snapshot)) => snapshot,
+        Ok(Err(e)) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": format!("Invalid snapshot: {:#}", e) })),
+            )
+        }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": e.to_string() }))),
+    };
+
+    let tenant = match query.tenant.as_deref().map(|tenant| snapshot.find_tenant(tenant)).transpose() {
+        Ok(tenant) => tenant,
+        Err(e) => return (StatusCode::NOT_FOUND, Json(json!({ "error": format!("{:#}", e) }))),
+    };
+    match state.snapshots.restore(snapshot, tenant).await {
+        Ok(summary) => (StatusCode::OK, Json(json!(summary))),
+        Err(e) => (StatusCode::CONFLICT, Json(json!({ "error": format!("{:#}", e) }))),
+    }
+}
@@ -0,0 +1,117 @@
+// Single Sign-On
+// Dashboard login through the `[oidc]` provider: redirect there, and turn its answer into a session
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::warn;
+
+use super::access::{lock_out, locked_out};
+use super::api::bearer_token;
+use super::DashboardState;
+use crate::tenancy::lockout::LoginGuard;
+
+/// What the provider redirects back with
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Which login methods the login screen offers
+pub async fn config(State(state): State<Arc<DashboardState>>) -> Json<serde_json::Value> {
+    let oidc = state.auth.as_ref().and_then(|auth| auth.oidc());
+    Json(json!({
+        "sso": oidc.is_some(),
+        "provider_name": oidc.map(|oidc| oidc.config().provider_name.clone()),
+        "local_login": oidc.map_or(true, |oidc| oidc.config().local_login),
+    }))
+}
+
+/// Send the browser to the provider's login page
+pub async fn login(State(state): State<Arc<DashboardState>>) -> Response {
+    let Some(oidc) = state.auth.as_ref().and_then(|auth| auth.oidc()) else {
+        return (StatusCode::NOT_FOUND, "Single sign-on is not enabled").into_response();
+    };
+    match oidc.login_url().await {
+        Ok(url) => Redirect::to(&url).into_response(),
+        Err(e) => {
+            warn!(error = %format!("{:#}", e), "Failed to start single sign-on");
+            (StatusCode::BAD_GATEWAY, "The identity provider can't be reached").into_response()
+        }
+    }
+}
+
+/// Finish a login, handing the session to the dashboard through session storage
+/// The secret never appears in a URL, so it doesn't end up in history or logs.
+/// Refused sign-ins count against the account as well as the client, so a
+/// locked-out account stays locked out from any address.
+pub async fn callback(
+    State(state): State<Arc<DashboardState>>,
+    Query(query): Query<CallbackQuery>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+) -> Response {
+    let Some(auth) = &state.auth else {
+        return (StatusCode::NOT_FOUND, "Single sign-on is not enabled").into_response();
+    };
+    let Some(oidc) = auth.oidc() else {
+        return (StatusCode::NOT_FOUND, "Single sign-on is not enabled").into_response();
+    };
+    if let Some(error) = query.error {
+        let description = query.error_description.unwrap_or_default();
+        return (StatusCode::UNAUTHORIZED, format!("Sign-in failed: {} {}", error, description)).into_response();
+    }
+    let (Some(code), Some(login)) = (query.code, query.state) else {
+        return (StatusCode::BAD_REQUEST, "Missing code or state").into_response();
+    };
+
+    let identity = match oidc.complete(&code, &login).await {
+        Ok(identity) => identity,
+        Err(e) => return refused(e),
+    };
+    let account = auth.lockout().map(|guard| (guard, LoginGuard::account_key(&identity.subject)));
+    if let Some(remaining) = account.as_ref().and_then(|(guard, key)| guard.check(key)) {
+        return locked_out(remaining);
+    }
+
+    match auth.start_session(&identity) {
+        Ok(secret) => (
+            [(header::CACHE_CONTROL, "no-store"), (header::REFERRER_POLICY, "no-referrer")],
+            Html(format!(
+                "<!doctype html><meta charset=\"utf-8\"><title>Signing in</title>\
+                 <script>sessionStorage.setItem('pear_session', '{}'); location.replace('/');</script>",
+                secret,
+            )),
+        ).into_response(),
+        Err(e) => {
+            if let Some((guard, key)) = &account {
+                if let Some(lockout) = guard.record_failure(key) {
+                    lock_out(&state, key, peer.ip(), lockout);
+                }
+            }
+            refused(e)
+        }
+    }
+}
+
+fn refused(e: anyhow::Error) -> Response {
+    warn!(error = %format!("{:#}", e), "Single sign-on refused");
+    (StatusCode::FORBIDDEN, format!("Sign-in refused: {:#}", e)).into_response()
+}
+
+/// End the presented session
+pub async fn logout(State(state): State<Arc<DashboardState>>, headers: HeaderMap) -> Json<serde_json::Value> {
+    let ended = state.auth.as_ref()
+        .zip(bearer_token(&headers))
+        .map_or(false, |(auth, token)| auth.end_session(token));
+    Json(json!({ "signed_out": ended }))
+}
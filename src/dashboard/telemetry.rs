@@ -1,21 +1,164 @@
 // Telemetry collection utilities
-// Placeholder module for future telemetry features
+// Samples per-site traffic, bandwidth and Cage health into fixed-size time series for dashboard graphs
+
+use crate::router::Router;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Samples kept per site: an hour at the default interval
+const DEFAULT_CAPACITY: usize = 360;
+
+/// Default time between samples
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A site's activity over one sampling interval
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SiteSample {
+    /// Unix timestamp (seconds) at the end of the interval
+    pub timestamp: i64,
+
+    pub requests: u64,
+    pub errors: u64,
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+
+    /// Cage health at sampling time
+    pub healthy_cages: usize,
+    pub total_cages: usize,
+}
+
+/// Running totals of a site, as read at sampling time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SiteCounters {
+    pub requests: u64,
+    pub errors: u64,
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+}
+
+impl SiteCounters {
+    /// Change since `previous`; a counter that went backwards was reset and counts from zero
+    fn since(&self, previous: &SiteCounters) -> SiteCounters {
+        let delta = |now: u64, before: u64| if now >= before { now - before } else { now };
+        SiteCounters {
+            requests: delta(self.requests, previous.requests),
+            errors: delta(self.errors, previous.errors),
+            ingress_bytes: delta(self.ingress_bytes, previous.ingress_bytes),
+            egress_bytes: delta(self.egress_bytes, previous.egress_bytes),
+        }
+    }
+}
+
+struct SiteSeries {
+    last: SiteCounters,
+    samples: VecDeque<SiteSample>,
+}
 
 /// Telemetry collector for system metrics
 pub struct TelemetryCollector {
     start_time: std::time::Instant,
+
+    /// Samples kept per site before the oldest is dropped
+    capacity: usize,
+
+    series: Mutex<HashMap<String, SiteSeries>>,
 }
 
 impl TelemetryCollector {
     pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
         Self {
             start_time: std::time::Instant::now(),
+            capacity: capacity.max(1),
+            series: Mutex::new(HashMap::new()),
         }
     }
 
     pub fn uptime_seconds(&self) -> u64 {
         self.start_time.elapsed().as_secs()
     }
+
+    /// Record a site's totals, storing the change since its previous sample
+    /// The first sample of a site only sets its baseline, so it reads as zero.
+    pub fn record(&self, site_id: &str, timestamp: i64, counters: SiteCounters, healthy_cages: usize, total_cages: usize) {
+        let mut series = self.series.lock();
+        let site = series.entry(site_id.to_string()).or_insert_with(|| SiteSeries {
+            last: counters,
+            samples: VecDeque::with_capacity(self.capacity),
+        });
+
+        let delta = counters.since(&site.last);
+        site.last = counters;
+        if site.samples.len() == self.capacity {
+            site.samples.pop_front();
+        }
+        site.samples.push_back(SiteSample {
+            timestamp,
+            requests: delta.requests,
+            errors: delta.errors,
+            ingress_bytes: delta.ingress_bytes,
+            egress_bytes: delta.egress_bytes,
+            healthy_cages,
+            total_cages,
+        });
+    }
+
+    /// A site's samples, oldest first
+    pub fn site_history(&self, site_id: &str) -> Vec<SiteSample> {
+        self.series.lock()
+            .get(site_id)
+            .map(|site| site.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Take one sample of every site the Router serves
+    pub async fn sample(&self, router: &Router) {
+        let timestamp = chrono::Utc::now().timestamp();
+        let mut sites: HashMap<String, SiteCounters> = router.site_traffic().into_iter()
+            .map(|traffic| (traffic.site_id, SiteCounters {
+                requests: traffic.requests,
+                errors: traffic.errors,
+                ..Default::default()
+            }))
+            .collect();
+        for site_id in router.site_ids() {
+            sites.entry(site_id).or_default();
+        }
+
+        for (site_id, mut counters) in sites {
+            if let Some(meter) = router.bandwidth_meter() {
+                let total = meter.site_report(&site_id).total;
+                counters.ingress_bytes = total.ingress_bytes;
+                counters.egress_bytes = total.egress_bytes;
+            }
+            let (healthy, total) = match router.pool(&site_id) {
+                Some(pool) => {
+                    let health = pool.health_stats().await;
+                    (health.healthy_cages, health.total_cages)
+                }
+                None => (0, 0),
+            };
+            self.record(&site_id, timestamp, counters, healthy, total);
+        }
+    }
+
+    /// Sample the Router in the background every `interval`
+    pub fn start(self: &Arc<Self>, router: Arc<Router>, interval: Duration) {
+        let collector = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                collector.sample(&router).await;
+            }
+        });
+    }
 }
 
 impl Default for TelemetryCollector {
@@ -23,3 +166,33 @@ impl Default for TelemetryCollector {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(requests: u64, egress_bytes: u64) -> SiteCounters {
+        SiteCounters { requests, egress_bytes, ..Default::default() }
+    }
+
+    #[test]
+    fn test_samples_are_deltas() {
+        let collector = TelemetryCollector::with_capacity(3);
+        collector.record("blog", 10, counters(100, 5000), 3, 3);
+        collector.record("blog", 20, counters(150, 7000), 3, 3);
+        // Counters restarting from zero are not negative traffic
+        collector.record("blog", 30, counters(20, 1000), 2, 3);
+
+        let history = collector.site_history("blog");
+        assert_eq!(history.iter().map(|s| s.requests).collect::<Vec<_>>(), vec![0, 50, 20]);
+        assert_eq!(history[1].egress_bytes, 2000);
+        assert_eq!(history[2].healthy_cages, 2);
+
+        // The oldest sample is dropped at capacity
+        collector.record("blog", 40, counters(25, 1000), 3, 3);
+        let history = collector.site_history("blog");
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].timestamp, 20);
+        assert!(collector.site_history("shop").is_empty());
+    }
+}
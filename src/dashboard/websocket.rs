@@ -11,6 +11,10 @@ use tokio::time::{interval, Duration};
 use tracing::{info, error};
 
 use super::DashboardState;
+use crate::supervisor::history::HealingEvent;
+
+/// Healing events sent when a client connects; later snapshots carry only new ones
+const HEALING_BACKLOG: usize = 50;
 
 /// WebSocket upgrade handler
 pub async fn handler(
@@ -30,12 +34,16 @@ async fn handle_socket(socket: WebSocket, state: Arc<DashboardState>) {
     let state_clone = state.clone();
     let mut send_task = tokio::spawn(async move {
         let mut tick_interval = interval(Duration::from_secs(1));
+        let mut last_healing = 0;
         
         loop {
             tick_interval.tick().await;
             
             // Collect telemetry
-            let telemetry = collect_telemetry(&state_clone).await;
+            let telemetry = collect_telemetry(&state_clone, last_healing).await;
+            if let Some(event) = telemetry.healing.last() {
+                last_healing = event.id;
+            }
             
             // Send as JSON
             let json = serde_json::to_string(&telemetry)
@@ -66,8 +74,8 @@ async fn handle_socket(socket: WebSocket, state: Arc<DashboardState>) {
     info!("Dashboard WebSocket connection closed");
 }
 
-/// Collect telemetry from all components
-async fn collect_telemetry(state: &DashboardState) -> Telemetry {
+/// Collect telemetry from all components, with the healing events after `last_healing`
+async fn collect_telemetry(state: &DashboardState, last_healing: u64) -> Telemetry {
     let router_stats = state.router.stats();
     let supervisor_stats = state.supervisor.stats();
     let ai_stats = state.ai_module.stats();
@@ -80,15 +88,24 @@ async fn collect_telemetry(state: &DashboardState) -> Telemetry {
             failed_requests: router_stats.failed_requests,
             active_pools: router_stats.active_pools,
             success_rate: router_stats.success_rate(),
+            latency: router_stats.latency,
         },
         supervisor: SupervisorTelemetry {
             supervised_pools: supervisor_stats.supervised_pools,
             healing_events: supervisor_stats.healing_events,
             is_running: supervisor_stats.is_running,
         },
+        healing: state.supervisor.history().since(last_healing, HEALING_BACKLOG),
         ai: AiTelemetry {
             threats_detected: ai_stats.threats_detected,
             anomaly_detection_enabled: ai_stats.anomaly_detection_enabled,
+            anomaly_model_trained: ai_stats.anomaly_model_trained,
+            banned_ips: ai_stats.banned_ips,
+            expired_bans: ai_stats.expired_bans,
+            allowlist_hits: ai_stats.allowlist_hits,
+            site_policies: ai_stats.site_policies,
+            security_events: ai_stats.security_events,
+            performance_anomalies: ai_stats.performance_anomalies,
         },
         // Mock Cage Pool data for demonstration
         cages: vec![
@@ -129,6 +146,7 @@ struct Telemetry {
     timestamp: i64,
     router: RouterTelemetry,
     supervisor: SupervisorTelemetry,
+    healing: Vec<HealingEvent>,
     ai: AiTelemetry,
     cages: Vec<CageTelemetry>,
 }
@@ -140,6 +158,7 @@ struct RouterTelemetry {
     failed_requests: u64,
     active_pools: usize,
     success_rate: f64,
+    latency: crate::observability::histogram::LatencyPercentiles,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -153,6 +172,13 @@ struct SupervisorTelemetry {
 struct AiTelemetry {
     threats_detected: u64,
     anomaly_detection_enabled: bool,
+    anomaly_model_trained: bool,
+    banned_ips: usize,
+    expired_bans: u64,
+    allowlist_hits: u64,
+    site_policies: usize,
+    security_events: u64,
+    performance_anomalies: u64,
 }
 
 #[derive(Debug, serde::Serialize)]
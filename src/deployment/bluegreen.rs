@@ -0,0 +1,494 @@
+// Blue/Green Deployments
+// Spins up a complete green pool beside the live one, warms it, then switches traffic in one step
+
+use super::hotswap::{hot_swap, HotSwap};
+use super::validate::{validate_module, ModuleReport};
+use super::DeploymentConfig;
+use crate::cage::pool::CagePool;
+use crate::router::Router;
+use crate::storage::modules::{ModuleHash, ModuleRef, ModuleStore};
+use crate::supervisor::Supervisor;
+use crate::tenancy::signing::SignatureCheck;
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Failures kept in a deployment's report
+const MAX_REPORTED_FAILURES: usize = 10;
+
+/// Past deployments kept per site
+const MAX_HISTORY: usize = 20;
+
+/// How green pools are checked before they may take traffic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlueGreenConfig {
+    /// Requests sent to every green Cage
+    #[serde(default = "default_checks")]
+    pub checks: Vec<SyntheticCheck>,
+
+    /// Times each check is repeated, which also warms the Cages
+    #[serde(default = "default_warmup_rounds")]
+    pub warmup_rounds: usize,
+
+    /// Longest a single check may take
+    #[serde(default = "default_check_timeout")]
+    pub check_timeout_ms: u64,
+}
+
+/// One synthetic GET request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyntheticCheck {
+    pub path: String,
+
+    /// Text the response body must contain
+    #[serde(default)]
+    pub expect: Option<String>,
+}
+
+fn default_checks() -> Vec<SyntheticCheck> {
+    vec![SyntheticCheck { path: "/".to_string(), expect: None }]
+}
+fn default_warmup_rounds() -> usize { 3 }
+fn default_check_timeout() -> u64 { 5000 }
+
+impl Default for BlueGreenConfig {
+    fn default() -> Self {
+        Self {
+            checks: default_checks(),
+            warmup_rounds: default_warmup_rounds(),
+            check_timeout_ms: default_check_timeout(),
+        }
+    }
+}
+
+impl BlueGreenConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.checks.is_empty() {
+            bail!("at least one check is required");
+        }
+        if let Some(check) = self.checks.iter().find(|check| !check.path.starts_with('/')) {
+            bail!("check path '{}' must start with /", check.path);
+        }
+        if self.warmup_rounds == 0 {
+            bail!("warmup_rounds must be at least 1");
+        }
+        if self.check_timeout_ms == 0 {
+            bail!("check_timeout_ms must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlueGreenStatus {
+    /// Green is being created and checked
+    Warming,
+
+    /// Green passed its checks and waits for the switch
+    Ready,
+
+    /// Green failed to start or failed a check and was discarded
+    Failed,
+
+    /// Green serves traffic; blue stands by for rollback
+    Live,
+
+    /// Blue serves traffic again and green was discarded
+    RolledBack,
+
+    /// Green serves traffic and blue was released
+    Finished,
+}
+
+/// One site's blue/green deployment
+#[derive(Clone, Serialize)]
+pub struct BlueGreenDeployment {
+    pub deployment_id: Uuid,
+    pub site_id: String,
+    pub status: BlueGreenStatus,
+
+    /// Unix time in seconds
+    pub staged_at: i64,
+    pub switched_at: Option<i64>,
+
+    pub checks_run: usize,
+    pub failures: Vec<String>,
+
+    /// What the module imports, exports and requires
+    pub module: ModuleReport,
+
+    /// Where the green module is kept in the module store, once it passed validation
+    pub module_hash: Option<ModuleHash>,
+
+    /// How the module's signature checked out against the tenant's keys
+    pub signature: SignatureCheck,
+
+    #[serde(skip)]
+    blue: Option<PoolVersion>,
+    #[serde(skip)]
+    green: Option<PoolVersion>,
+}
+
+/// A pool and the module its Cages run, which the supervisor needs for respawns
+#[derive(Clone)]
+struct PoolVersion {
+    pool: Arc<CagePool>,
+    module: ModuleRef,
+}
+
+/// Blue/green deployments of every site
+pub struct BlueGreenManager {
+    config: BlueGreenConfig,
+    max_module_bytes: usize,
+    router: Arc<Router>,
+    supervisor: Arc<Supervisor>,
+    modules: Arc<ModuleStore>,
+    deployments: DashMap<String, BlueGreenDeployment>,
+
+    /// Earlier deployments per site, newest first
+    history: DashMap<String, VecDeque<BlueGreenDeployment>>,
+}
+
+impl BlueGreenManager {
+    pub fn new(
+        config: &DeploymentConfig,
+        router: Arc<Router>,
+        supervisor: Arc<Supervisor>,
+        modules: Arc<ModuleStore>,
+    ) -> Self {
+        Self {
+            config: config.blue_green.clone(),
+            max_module_bytes: config.max_module_bytes,
+            router,
+            supervisor,
+            modules,
+            deployments: DashMap::new(),
+            history: DashMap::new(),
+        }
+    }
+
+    /// The site's current or last deployment
+    pub fn get(&self, site_id: &str) -> Option<BlueGreenDeployment> {
+        self.deployments.get(site_id).map(|deployment| deployment.clone())
+    }
+
+    /// The site's deployments, newest first
+    pub fn history(&self, site_id: &str) -> Vec<BlueGreenDeployment> {
+        let mut deployments: Vec<_> = self.get(site_id).into_iter().collect();
+        if let Some(history) = self.history.get(site_id) {
+            deployments.extend(history.iter().cloned());
+        }
+        deployments
+    }
+
+    /// Validate `wasm_bytes`, then start a green pool running it beside the site's live pool and run the checks
+    /// Returns the deployment as Ready, or as Failed with the reasons; a module whose
+    /// `signature` was rejected or that fails validation never gets a pool.
+    pub async fn stage(&self, site_id: &str, wasm_bytes: Vec<u8>, signature: SignatureCheck) -> Result<BlueGreenDeployment> {
+        let Some(blue_pool) = self.router.pool(site_id) else {
+            bail!("Site {} has no live pool to deploy beside", site_id);
+        };
+        let Some(blue_module) = self.supervisor.module(site_id) else {
+            bail!("Site {} is not supervised", site_id);
+        };
+
+        let deployment_id = Uuid::new_v4();
+        {
+            let entry = self.deployments.entry(site_id.to_string());
+            if let dashmap::mapref::entry::Entry::Occupied(existing) = &entry {
+                match existing.get().status {
+                    BlueGreenStatus::Warming => bail!("A green pool for {} is already warming", site_id),
+                    BlueGreenStatus::Ready => bail!("A green pool for {} is waiting; switch to it or roll it back first", site_id),
+                    BlueGreenStatus::Live => bail!("Green is live for {}; finish or roll back first", site_id),
+                    _ => {}
+                }
+                let mut history = self.history.entry(site_id.to_string()).or_default();
+                history.push_front(existing.get().clone());
+                history.truncate(MAX_HISTORY);
+            }
+            entry.insert(BlueGreenDeployment {
+                deployment_id,
+                site_id: site_id.to_string(),
+                status: BlueGreenStatus::Warming,
+                staged_at: chrono::Utc::now().timestamp(),
+                switched_at: None,
+                checks_run: 0,
+                failures: Vec::new(),
+                module: ModuleReport::default(),
+                module_hash: None,
+                signature: signature.clone(),
+                blue: None,
+                green: None,
+            });
+        }
+        info!(site_id = %site_id, deployment_id = %deployment_id, signature = ?signature, "Starting green pool");
+
+        let (module, green, checks_run, failures) = match signature.problem() {
+            Some(problem) => (ModuleReport::default(), None, 0, vec![problem]),
+            None => self.start_green(&blue_pool, wasm_bytes).await?,
+        };
+
+        let mut deployment = self.deployments.get_mut(site_id)
+            .filter(|deployment| deployment.deployment_id == deployment_id)
+            .ok_or_else(|| anyhow::anyhow!("Deployment for {} was replaced while warming", site_id))?;
+        deployment.checks_run = checks_run;
+        deployment.module = module;
+        deployment.module_hash = green.as_ref().map(|green| green.module.hash());
+        match green {
+            Some(green) if failures.is_empty() => {
+                deployment.status = BlueGreenStatus::Ready;
+                deployment.blue = Some(PoolVersion { pool: blue_pool, module: blue_module });
+                deployment.green = Some(green);
+                info!(site_id = %site_id, checks = checks_run, "Green pool passed its checks");
+            }
+            _ => {
+                deployment.status = BlueGreenStatus::Failed;
+                warn!(site_id = %site_id, failures = failures.len(), first = %failures[0], "Green pool failed its checks");
+                deployment.failures = failures;
+            }
+        }
+        Ok(deployment.clone())
+    }
+
+    /// Validate and store the module, then start and check a green pool running it
+    async fn start_green(
+        &self,
+        blue_pool: &CagePool,
+        wasm_bytes: Vec<u8>,
+    ) -> Result<(ModuleReport, Option<PoolVersion>, usize, Vec<String>)> {
+        // Parsing a large module several times over is too slow for a runtime thread
+        let max_module_bytes = self.max_module_bytes;
+        let modules = self.modules.clone();
+        let (wasm_bytes, module, stored) = tokio::task::spawn_blocking(move || {
+            let module = validate_module(&wasm_bytes, max_module_bytes);
+            let stored = module.is_valid().then(|| modules.put(&wasm_bytes));
+            (wasm_bytes, module, stored)
+        }).await?;
+
+        Ok(match stored {
+            None => {
+                let problems = module.problems.clone();
+                (module, None, 0, problems)
+            }
+            Some(Err(e)) => (module, None, 0, vec![format!("{:#}", e)]),
+            Some(Ok(stored)) => match blue_pool.sibling(wasm_bytes).await {
+                Ok(pool) => {
+                    let pool = Arc::new(pool);
+                    let (checks_run, failures) = self.warm(&pool).await;
+                    (module, Some(PoolVersion { pool, module: stored }), checks_run, failures)
+                }
+                Err(e) => (module, None, 0, vec![format!("{:#}", e)]),
+            },
+        })
+    }
+
+    /// Send every check to every green Cage, `warmup_rounds` times
+    async fn warm(&self, pool: &CagePool) -> (usize, Vec<String>) {
+        let timeout = Duration::from_millis(self.config.check_timeout_ms);
+        let mut failures = Vec::new();
+        let mut runs = 0;
+
+        let health = pool.health_stats().await;
+        if health.healthy_cages < health.total_cages || health.total_cages == 0 {
+            failures.push(format!("{} of {} Cages healthy", health.healthy_cages, health.total_cages));
+        }
+
+        let cages = pool.cages().await;
+        for _ in 0..self.config.warmup_rounds {
+            for check in &self.config.checks {
+                // Same shape as the router's request serialization
+                let request = serde_json::json!({ "method": "GET", "uri": check.path }).to_string();
+                for cage in &cages {
+                    runs += 1;
+                    let (executing, request) = (cage.clone(), request.clone());
+                    let executed = self.router.wasm_executor().run(move || executing.execute_request(request.as_bytes()));
+                    let failure = match tokio::time::timeout(timeout, executed).await {
+                        Err(_) => Some(format!("GET {} on Cage {} timed out", check.path, cage.id())),
+                        Ok(Err(e)) | Ok(Ok(Err(e))) => Some(format!("GET {} on Cage {} failed: {}", check.path, cage.id(), e)),
+                        Ok(Ok(Ok(body))) => check.expect.as_ref()
+                            .filter(|expect| !String::from_utf8_lossy(&body).contains(expect.as_str()))
+                            .map(|expect| format!("GET {} on Cage {} did not contain '{}'", check.path, cage.id(), expect)),
+                    };
+                    if let Some(failure) = failure {
+                        if failures.len() < MAX_REPORTED_FAILURES {
+                            failures.push(failure);
+                        }
+                    }
+                }
+            }
+        }
+        (runs, failures)
+    }
+
+    /// Move the site's live pool onto `wasm_bytes` in place, without a green pool or checks
+    /// Refused while a green pool is warming, waiting or live, since the swap would be lost or undone.
+    pub async fn hot_swap(&self, site_id: &str, wasm_bytes: Vec<u8>, signature: SignatureCheck) -> Result<HotSwap> {
+        if let Some(deployment) = self.deployments.get(site_id) {
+            if matches!(deployment.status, BlueGreenStatus::Warming | BlueGreenStatus::Ready | BlueGreenStatus::Live) {
+                bail!("A blue/green deployment of {} is {:?}; finish or roll it back first", site_id, deployment.status);
+            }
+        }
+        hot_swap(&self.router, &self.supervisor, &self.modules, site_id, wasm_bytes, self.max_module_bytes, signature).await
+    }
+
+    /// Route the site to its Ready green pool
+    pub fn switch(&self, site_id: &str) -> Result<BlueGreenDeployment> {
+        let mut deployment = self.find(site_id)?;
+        if deployment.status != BlueGreenStatus::Ready {
+            bail!("Nothing to switch to for {} (deployment is {:?})", site_id, deployment.status);
+        }
+        let Some(green) = deployment.green.clone() else {
+            bail!("Green pool for {} is gone", site_id);
+        };
+
+        // One map insert, so every request sees either blue or green
+        self.router.register_pool(site_id.to_string(), green.pool.clone());
+        self.supervisor.register_pool(site_id.to_string(), green.pool, green.module);
+        deployment.status = BlueGreenStatus::Live;
+        deployment.switched_at = Some(chrono::Utc::now().timestamp());
+        info!(site_id = %site_id, deployment_id = %deployment.deployment_id, "Switched traffic to green");
+        Ok(deployment.clone())
+    }
+
+    /// Put blue back in front of the site, or discard a green pool that never went live
+    pub fn rollback(&self, site_id: &str) -> Result<BlueGreenDeployment> {
+        let mut deployment = self.find(site_id)?;
+        match deployment.status {
+            BlueGreenStatus::Live => {
+                let Some(blue) = deployment.blue.take() else {
+                    bail!("Blue pool for {} is gone", site_id);
+                };
+                self.router.register_pool(site_id.to_string(), blue.pool.clone());
+                self.supervisor.register_pool(site_id.to_string(), blue.pool, blue.module);
+                warn!(site_id = %site_id, deployment_id = %deployment.deployment_id, "Rolled traffic back to blue");
+            }
+            BlueGreenStatus::Ready => {
+                info!(site_id = %site_id, deployment_id = %deployment.deployment_id, "Discarded green pool");
+            }
+            status => bail!("Nothing to roll back for {} (deployment is {:?})", site_id, status),
+        }
+        deployment.status = BlueGreenStatus::RolledBack;
+        deployment.blue = None;
+        deployment.green = None;
+        Ok(deployment.clone())
+    }
+
+    /// Release the standby blue pool once green has proven itself
+    pub fn finish(&self, site_id: &str) -> Result<BlueGreenDeployment> {
+        let mut deployment = self.find(site_id)?;
+        if deployment.status != BlueGreenStatus::Live {
+            bail!("Green is not live for {} (deployment is {:?})", site_id, deployment.status);
+        }
+        deployment.status = BlueGreenStatus::Finished;
+        deployment.blue = None;
+        deployment.green = None;
+        info!(site_id = %site_id, deployment_id = %deployment.deployment_id, "Released blue pool");
+        Ok(deployment.clone())
+    }
+
+    fn find(&self, site_id: &str) -> Result<dashmap::mapref::one::RefMut<'_, String, BlueGreenDeployment>> {
+        self.deployments.get_mut(site_id)
+            .ok_or_else(|| anyhow::anyhow!("No blue/green deployment for {}", site_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cage::config::CageConfig;
+    use crate::router::RouterConfig;
+    use crate::supervisor::SupervisorConfig;
+
+    async fn manager(blue_green: BlueGreenConfig) -> (BlueGreenManager, tempfile::TempDir) {
+        let temp = tempfile::TempDir::new().unwrap();
+        let modules = ModuleStore::new(temp.path()).unwrap();
+        let router = Arc::new(Router::new(RouterConfig::default()));
+        let supervisor = Arc::new(Supervisor::new(SupervisorConfig::default()));
+        let wasm = wat::parse_str("(module)").unwrap();
+        let pool = Arc::new(CagePool::new("blog".to_string(), wasm.clone(), CageConfig::default(), 2).await.unwrap());
+        router.register_pool("blog".to_string(), pool.clone());
+        supervisor.register_pool("blog".to_string(), pool, modules.put(&wasm).unwrap());
+        let config = DeploymentConfig { blue_green, ..Default::default() };
+        (BlueGreenManager::new(&config, router, supervisor, modules), temp)
+    }
+
+    #[tokio::test]
+    async fn test_switch_and_rollback() {
+        let (manager, _temp) = manager(BlueGreenConfig::default()).await;
+        let blue = manager.router.pool("blog").unwrap();
+        let green_wasm = wat::parse_str("(module (func (export \"green\")))").unwrap();
+
+        let staged = manager.stage("blog", green_wasm.clone(), SignatureCheck::Unsigned).await.unwrap();
+        assert_eq!(staged.status, BlueGreenStatus::Ready);
+        assert_eq!(staged.checks_run, 3 * 2);
+        let green_hash = staged.module_hash.unwrap();
+        assert_eq!(manager.modules.references(&green_hash), 1);
+        assert!(Arc::ptr_eq(&manager.router.pool("blog").unwrap(), &blue));
+        assert!(manager.stage("blog", green_wasm.clone(), SignatureCheck::Unsigned).await.is_err());
+
+        manager.switch("blog").unwrap();
+        let green = manager.router.pool("blog").unwrap();
+        assert!(!Arc::ptr_eq(&green, &blue));
+        assert_eq!(green.size().await, 2);
+        assert_eq!(manager.supervisor.module("blog").unwrap().load().unwrap(), green_wasm);
+
+        let rolled_back = manager.rollback("blog").unwrap();
+        assert_eq!(rolled_back.status, BlueGreenStatus::RolledBack);
+        assert!(Arc::ptr_eq(&manager.router.pool("blog").unwrap(), &blue));
+        assert!(manager.finish("blog").is_err());
+
+        // Nothing runs the green module any more
+        assert_eq!(manager.modules.references(&green_hash), 0);
+        assert_eq!(manager.modules.collect_garbage().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hot_swap_waits_for_blue_green() {
+        let (manager, _temp) = manager(BlueGreenConfig::default()).await;
+        let green_wasm = wat::parse_str("(module (func (export \"green\")))").unwrap();
+        manager.stage("blog", green_wasm.clone(), SignatureCheck::Unsigned).await.unwrap();
+        assert!(manager.hot_swap("blog", green_wasm.clone(), SignatureCheck::Unsigned).await.is_err());
+
+        manager.rollback("blog").unwrap();
+        let swap = manager.hot_swap("blog", green_wasm, SignatureCheck::Unsigned).await.unwrap();
+        assert!(swap.swapped);
+    }
+
+    #[tokio::test]
+    async fn test_failed_checks_keep_blue() {
+        let config = BlueGreenConfig {
+            checks: vec![SyntheticCheck { path: "/health".to_string(), expect: Some("healthy".to_string()) }],
+            warmup_rounds: 1,
+            ..Default::default()
+        };
+        let (manager, _temp) = manager(config).await;
+        let blue = manager.router.pool("blog").unwrap();
+
+        let staged = manager.stage("blog", wat::parse_str("(module)").unwrap(), SignatureCheck::Unsigned).await.unwrap();
+        assert_eq!(staged.status, BlueGreenStatus::Failed);
+        assert_eq!(staged.failures.len(), 2);
+        assert!(manager.switch("blog").is_err());
+        assert!(Arc::ptr_eq(&manager.router.pool("blog").unwrap(), &blue));
+
+        // A failed deployment can be retried; an invalid module never gets a pool
+        let staged = manager.stage("blog", b"not wasm".to_vec(), SignatureCheck::Unsigned).await.unwrap();
+        assert_eq!(staged.status, BlueGreenStatus::Failed);
+        assert_eq!(staged.checks_run, 0);
+        assert!(staged.failures[0].starts_with("Invalid module"));
+
+        // So does one whose signature was rejected, and the attempt stays in the history
+        let staged = manager.stage("blog", wat::parse_str("(module)").unwrap(), SignatureCheck::Missing).await.unwrap();
+        assert_eq!(staged.status, BlueGreenStatus::Failed);
+        assert!(staged.module_hash.is_none());
+        let history = manager.history("blog");
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].signature, SignatureCheck::Missing);
+        assert!(Arc::ptr_eq(&manager.router.pool("blog").unwrap(), &blue));
+    }
+}
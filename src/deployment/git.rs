@@ -0,0 +1,662 @@
+// Git Deployments
+// Fetch a site's repository, build it in a sandbox and deploy the result, on request or on push
+
+use super::bluegreen::{BlueGreenDeployment, BlueGreenManager, BlueGreenStatus};
+use crate::runtime::polyglot::{DetectedLanguage, PolyglotAdapter};
+use crate::tenancy::secrets::SealedSecret;
+use crate::tenancy::TenantManager;
+use anyhow::{bail, Context, Result};
+use dashmap::DashMap;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Name a site's webhook secret is sealed under
+pub const WEBHOOK_SECRET_NAME: &str = "GIT_WEBHOOK_SECRET";
+
+/// Git deploys kept per site
+const HISTORY_LIMIT: usize = 10;
+
+/// `[deployment.git]`: where repositories are checked out and how builds are confined
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GitConfig {
+    /// Checkouts, one per site ("" = `<storage root>/git`)
+    pub workdir: String,
+
+    /// The git executable
+    pub git: String,
+
+    /// Longest a fetch may take
+    pub fetch_timeout_secs: u64,
+
+    /// Longest a build command may run
+    pub build_timeout_secs: u64,
+
+    /// Command builds run under, followed by `sh -c <build>`; `{dir}` is the checkout
+    pub sandbox: Vec<String>,
+
+    /// Run build commands directly when `sandbox` is empty
+    pub allow_unsandboxed_builds: bool,
+
+    /// Tail of the fetch and build output kept with each deploy
+    pub max_log_bytes: usize,
+
+    /// Runtime modules for repositories that hold sources rather than a built module
+    pub runtime_dir: String,
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        Self {
+            workdir: String::new(),
+            git: "git".to_string(),
+            fetch_timeout_secs: 120,
+            build_timeout_secs: 600,
+            sandbox: default_sandbox(),
+            allow_unsandboxed_builds: false,
+            max_log_bytes: 64 * 1024,
+            runtime_dir: "./assets/runtimes".to_string(),
+        }
+    }
+}
+
+/// bubblewrap with the system directories read-only, the checkout at `/build` and nothing else of the host
+fn default_sandbox() -> Vec<String> {
+    [
+        "bwrap",
+        "--ro-bind", "/usr", "/usr",
+        "--ro-bind", "/etc", "/etc",
+        "--ro-bind-try", "/bin", "/bin",
+        "--ro-bind-try", "/sbin", "/sbin",
+        "--ro-bind-try", "/lib", "/lib",
+        "--ro-bind-try", "/lib64", "/lib64",
+        "--proc", "/proc",
+        "--dev", "/dev",
+        "--tmpfs", "/tmp",
+        "--bind", "{dir}", "/build",
+        "--chdir", "/build",
+        "--setenv", "HOME", "/tmp",
+        "--unshare-all",
+        "--share-net",
+        "--die-with-parent",
+        "--new-session",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect()
+}
+
+impl GitConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.git.is_empty() {
+            bail!("git must name the git executable");
+        }
+        if self.fetch_timeout_secs == 0 || self.build_timeout_secs == 0 {
+            bail!("fetch_timeout_secs and build_timeout_secs must be positive");
+        }
+        if self.max_log_bytes == 0 {
+            bail!("max_log_bytes must be positive");
+        }
+        Ok(())
+    }
+
+    /// Where checkouts live, given the storage root
+    pub fn workdir(&self, storage_root: &Path) -> PathBuf {
+        if self.workdir.is_empty() {
+            storage_root.join("git")
+        } else {
+            PathBuf::from(&self.workdir)
+        }
+    }
+}
+
+/// Where a site is deployed from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitSource {
+    /// `https://`, `ssh://` or `user@host:path`
+    pub url: String,
+
+    /// Branch or tag
+    pub reference: String,
+
+    /// Shell command that builds the checkout, run in the sandbox
+    #[serde(default)]
+    pub build: Option<String>,
+
+    /// The built `.wasm` or the directory to serve, relative to the checkout (default: the checkout)
+    #[serde(default)]
+    pub output: Option<String>,
+
+    /// Switch traffic as soon as the green pool passes its checks
+    #[serde(default)]
+    pub switch: bool,
+
+    /// Push webhooks are checked against this; sealed like a site secret
+    pub webhook_secret: SealedSecret,
+}
+
+/// A site's git source as given to the API
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitSourceRequest {
+    pub url: String,
+
+    #[serde(default = "default_reference")]
+    pub reference: String,
+
+    #[serde(default)]
+    pub build: Option<String>,
+
+    #[serde(default)]
+    pub output: Option<String>,
+
+    #[serde(default)]
+    pub switch: bool,
+}
+
+fn default_reference() -> String {
+    "main".to_string()
+}
+
+impl GitSourceRequest {
+    pub fn validate(&self) -> Result<()> {
+        check_url(&self.url)?;
+        check_reference(&self.reference)?;
+        if let Some(output) = &self.output {
+            if !is_plain_relative(Path::new(output)) {
+                bail!("output must be a path inside the repository ({})", output);
+            }
+        }
+        if self.build.as_deref().is_some_and(|build| build.trim().is_empty()) {
+            bail!("build must not be empty");
+        }
+        Ok(())
+    }
+}
+
+/// Remote repositories only: a local path or `file://` URL would let a tenant read the node's files
+fn check_url(url: &str) -> Result<()> {
+    if url.starts_with('-') || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        bail!("Invalid repository URL {}", url);
+    }
+    if let Some((scheme, _)) = url.split_once("://") {
+        if !matches!(scheme, "https" | "http" | "ssh" | "git") {
+            bail!("Repository URLs must use https, http, ssh or git, not {}", scheme);
+        }
+        return Ok(());
+    }
+    // scp-like `user@host:path`
+    match url.split_once(':') {
+        Some((host, path)) if host.contains('@') && !host.contains('/') && !path.is_empty() => Ok(()),
+        _ => bail!("Repository URL {} is neither a URL nor user@host:path", url),
+    }
+}
+
+/// A branch or tag name git would accept, and nothing that reads as an option
+fn check_reference(reference: &str) -> Result<()> {
+    let valid = !reference.is_empty()
+        && !reference.starts_with('-')
+        && !reference.starts_with('/')
+        && !reference.ends_with('/')
+        && !reference.ends_with(".lock")
+        && !reference.contains("..")
+        && !reference.contains("@{")
+        && !reference.chars().any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c));
+    if !valid {
+        bail!("Invalid branch or tag name {}", reference);
+    }
+    Ok(())
+}
+
+fn is_plain_relative(path: &Path) -> bool {
+    path.components().all(|component| matches!(component, Component::Normal(_)))
+        && path.components().next().is_some()
+}
+
+/// Where a git deploy got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GitDeployStatus {
+    Fetching,
+    Building,
+    Deploying,
+    Deployed,
+    Failed,
+}
+
+/// One deploy of a site from git
+#[derive(Clone, Serialize)]
+pub struct GitDeploy {
+    pub deploy_id: Uuid,
+    pub site_id: String,
+    pub url: String,
+    pub reference: String,
+
+    /// What started it: `api` or `webhook`
+    pub trigger: String,
+
+    pub status: GitDeployStatus,
+    pub commit: Option<String>,
+    pub language: Option<String>,
+
+    /// Fetch and build output, cut to its tail
+    pub log: String,
+
+    pub error: Option<String>,
+
+    /// Unix time in seconds
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+
+    /// The blue/green deployment the build was staged as
+    pub deployment: Option<BlueGreenDeployment>,
+}
+
+/// What a build produced
+struct BuildOutput {
+    /// Language whose runtime serves the output, unless the build produced its own module
+    language: Option<DetectedLanguage>,
+    module: Vec<u8>,
+
+    /// Files to serve beside a runtime module
+    content: Option<PathBuf>,
+}
+
+/// Deploys sites from their git sources, one deploy per site at a time
+pub struct GitDeployer {
+    config: GitConfig,
+    workdir: PathBuf,
+    polyglot: PolyglotAdapter,
+    tenants: Arc<TenantManager>,
+    deployments: Arc<BlueGreenManager>,
+
+    /// Held for the length of a site's deploy
+    locks: DashMap<String, Arc<tokio::sync::Mutex<()>>>,
+
+    /// Each site's deploys, newest first
+    history: DashMap<String, Vec<GitDeploy>>,
+}
+
+impl GitDeployer {
+    pub fn new(
+        config: &GitConfig,
+        storage_root: &Path,
+        tenants: Arc<TenantManager>,
+        deployments: Arc<BlueGreenManager>,
+    ) -> Self {
+        Self {
+            workdir: config.workdir(storage_root),
+            polyglot: PolyglotAdapter::new(&config.runtime_dir),
+            config: config.clone(),
+            tenants,
+            deployments,
+            locks: DashMap::new(),
+            history: DashMap::new(),
+        }
+    }
+
+    /// The site's deploys, newest first
+    pub fn history(&self, site_id: &str) -> Vec<GitDeploy> {
+        self.history.get(site_id).map(|deploys| deploys.clone()).unwrap_or_default()
+    }
+
+    /// Forget a removed site's checkout and deploys
+    pub fn forget(&self, site_id: &str) {
+        self.history.remove(site_id);
+        self.locks.remove(site_id);
+        if let Ok(dir) = self.checkout_dir(site_id) {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    /// Fetch, build and stage the site's configured branch or tag, waiting for any deploy already running
+    pub async fn deploy(&self, site_id: &str, trigger: &str) -> Result<GitDeploy> {
+        let tenant_id = self.tenants.find_site_tenant(site_id).context("Site not found")?;
+        let source = self.tenants.site_git(tenant_id, site_id)?
+            .context("Site has no git source; set one with `pear deploy --git`")?;
+
+        let lock = self.locks.entry(site_id.to_string()).or_default().clone();
+        let _guard = lock.lock().await;
+
+        let mut deploy = GitDeploy {
+            deploy_id: Uuid::new_v4(),
+            site_id: site_id.to_string(),
+            url: source.url.clone(),
+            reference: source.reference.clone(),
+            trigger: trigger.to_string(),
+            status: GitDeployStatus::Fetching,
+            commit: None,
+            language: None,
+            log: String::new(),
+            error: None,
+            started_at: chrono::Utc::now().timestamp(),
+            finished_at: None,
+            deployment: None,
+        };
+        self.record(&deploy);
+        info!(site_id = %site_id, url = %source.url, reference = %source.reference, trigger = %trigger, "Git deploy started");
+
+        let result = self.run(tenant_id, &source, &mut deploy).await;
+        deploy.finished_at = Some(chrono::Utc::now().timestamp());
+        match result {
+            Ok(()) => info!(site_id = %site_id, commit = ?deploy.commit, status = ?deploy.status, "Git deploy finished"),
+            Err(e) => {
+                warn!(site_id = %site_id, error = %e, "Git deploy failed");
+                deploy.status = GitDeployStatus::Failed;
+                deploy.error = Some(format!("{:#}", e));
+            }
+        }
+        self.record(&deploy);
+        Ok(deploy)
+    }
+
+    async fn run(&self, tenant_id: Uuid, source: &GitSource, deploy: &mut GitDeploy) -> Result<()> {
+        let site_id = deploy.site_id.clone();
+        let checkout = self.checkout_dir(&site_id)?;
+        let commit = self.fetch(&checkout, source, &mut deploy.log).await?;
+        deploy.commit = Some(commit.clone());
+
+        if let Some(build) = &source.build {
+            deploy.status = GitDeployStatus::Building;
+            self.record(deploy);
+            self.build(&checkout, build, &site_id, source, &commit, &mut deploy.log).await?;
+        }
+
+        let output = build_output(&self.polyglot, &checkout, source.output.as_deref())?;
+        deploy.language = output.language.map(|language| format!("{:?}", language));
+        deploy.status = GitDeployStatus::Deploying;
+        self.record(deploy);
+
+        if let Some(content) = &output.content {
+            self.tenants.import_site_content(tenant_id, &site_id, content)?;
+        }
+        let signature = self.tenants.check_module_signature(&site_id, &output.module, None);
+        let staged = self.deployments.stage(&site_id, output.module, signature).await?;
+        let staged = match staged.status {
+            BlueGreenStatus::Ready if source.switch => self.deployments.switch(&site_id)?,
+            _ => staged,
+        };
+        deploy.status = match staged.status {
+            BlueGreenStatus::Ready | BlueGreenStatus::Live => GitDeployStatus::Deployed,
+            _ => GitDeployStatus::Failed,
+        };
+        if deploy.status == GitDeployStatus::Failed {
+            deploy.error = Some(format!("Green pool failed its checks: {}", staged.failures.join("; ")));
+        }
+        deploy.deployment = Some(staged);
+        Ok(())
+    }
+
+    fn record(&self, deploy: &GitDeploy) {
+        let mut deploys = self.history.entry(deploy.site_id.clone()).or_default();
+        match deploys.first_mut() {
+            Some(latest) if latest.deploy_id == deploy.deploy_id => {
+                *latest = deploy.clone();
+            }
+            _ => {
+                deploys.insert(0, deploy.clone());
+                deploys.truncate(HISTORY_LIMIT);
+            }
+        }
+    }
+
+    fn checkout_dir(&self, site_id: &str) -> Result<PathBuf> {
+        if !is_plain_relative(Path::new(site_id)) || site_id.contains('/') {
+            bail!("Site ID {} cannot name a checkout", site_id);
+        }
+        Ok(self.workdir.join(site_id))
+    }
+
+    /// Bring the checkout to the tip of the source's branch or tag, returning its commit
+    async fn fetch(&self, checkout: &Path, source: &GitSource, log: &mut String) -> Result<String> {
+        let timeout = Duration::from_secs(self.config.fetch_timeout_secs);
+        if !checkout.join(".git").exists() {
+            std::fs::create_dir_all(checkout)
+                .with_context(|| format!("Failed to create checkout {}", checkout.display()))?;
+            self.git(checkout, &["init", "-q"], timeout, log).await?;
+            self.git(checkout, &["remote", "add", "origin", &source.url], timeout, log).await?;
+        } else {
+            self.git(checkout, &["remote", "set-url", "origin", &source.url], timeout, log).await?;
+        }
+        self.git(checkout, &["fetch", "-q", "--depth", "1", "--no-tags", "origin", &source.reference], timeout, log).await?;
+        self.git(checkout, &["checkout", "-q", "--force", "FETCH_HEAD"], timeout, log).await?;
+        // Earlier builds' outputs must not leak into this one
+        self.git(checkout, &["clean", "-q", "-f", "-d", "-x"], timeout, log).await?;
+        let commit = self.git(checkout, &["rev-parse", "HEAD"], timeout, log).await?;
+        Ok(commit.trim().to_string())
+    }
+
+    /// Run git in the checkout, returning its standard output
+    async fn git(&self, checkout: &Path, args: &[&str], timeout: Duration, log: &mut String) -> Result<String> {
+        let mut command = Command::new(&self.config.git);
+        command
+            .args(["-c", "protocol.file.allow=never", "-c", "core.hooksPath=/dev/null"])
+            .args(args)
+            .current_dir(checkout)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_ASKPASS", "true");
+        let stdout = run(command, timeout, self.config.max_log_bytes, log).await
+            .with_context(|| format!("git {} failed", args.first().unwrap_or(&"")))?;
+        Ok(stdout)
+    }
+
+    /// Run the source's build command in the sandbox, with only a minimal environment
+    async fn build(&self, checkout: &Path, build: &str, site_id: &str, source: &GitSource, commit: &str, log: &mut String) -> Result<()> {
+        let mut command = match self.config.sandbox.split_first() {
+            Some((program, args)) => {
+                let dir = checkout.to_string_lossy();
+                let mut command = Command::new(program);
+                command.args(args.iter().map(|arg| arg.replace("{dir}", &dir))).arg("sh");
+                command
+            }
+            None if self.config.allow_unsandboxed_builds => {
+                let mut command = Command::new("sh");
+                command.current_dir(checkout);
+                command
+            }
+            None => bail!("Build commands need [deployment.git] sandbox, or allow_unsandboxed_builds = true"),
+        };
+        command
+            .arg("-c")
+            .arg(build)
+            .env_clear()
+            .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+            .env("HOME", "/tmp")
+            .env("PEAR_SITE_ID", site_id)
+            .env("PEAR_GIT_REF", &source.reference)
+            .env("PEAR_GIT_COMMIT", commit);
+
+        run(command, Duration::from_secs(self.config.build_timeout_secs), self.config.max_log_bytes, log).await
+            .context("Build command failed")?;
+        Ok(())
+    }
+}
+
+/// The module to deploy: the built `.wasm`, or the runtime for the language of the served directory
+fn build_output(polyglot: &PolyglotAdapter, checkout: &Path, output: Option<&str>) -> Result<BuildOutput> {
+    let target = match output {
+        Some(output) => checkout.join(output),
+        None => checkout.to_path_buf(),
+    };
+    if !target.exists() {
+        bail!("Build output {} does not exist", target.display());
+    }
+    // A symlink in the repository must not hand the node's own files to the deploy
+    if !target.canonicalize()?.starts_with(checkout.canonicalize()?) {
+        bail!("Build output {} points outside the checkout", target.display());
+    }
+    if target.is_file() {
+        if target.extension().is_some_and(|ext| ext == "wasm") {
+            let module = std::fs::read(&target).with_context(|| format!("Failed to read {}", target.display()))?;
+            return Ok(BuildOutput { language: None, module, content: None });
+        }
+        bail!("Build output {} is neither a .wasm module nor a directory", target.display());
+    }
+
+    let language = polyglot.detect_language(&target)?;
+    if language == DetectedLanguage::Unknown {
+        bail!("Cannot tell how to run {}; set output to the built .wasm module", target.display());
+    }
+    let runtime = polyglot.get_runtime_wasm(&language)?;
+    let module = std::fs::read(&runtime).with_context(|| format!("Failed to read {}", runtime.display()))?;
+    Ok(BuildOutput { language: Some(language), module, content: Some(target) })
+}
+
+/// Run a command to completion within `timeout`, appending its output to `log`
+/// Returns its standard output; fails if it exits unsuccessfully or runs out of time.
+async fn run(mut command: Command, timeout: Duration, max_log_bytes: usize, log: &mut String) -> Result<String> {
+    command.kill_on_drop(true).stdin(std::process::Stdio::null());
+    let output = match tokio::time::timeout(timeout, command.output()).await {
+        Ok(output) => output.context("Failed to start")?,
+        Err(_) => bail!("Timed out after {}s", timeout.as_secs()),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    log.push_str(&stdout);
+    log.push_str(&String::from_utf8_lossy(&output.stderr));
+    if log.len() > max_log_bytes {
+        let mut cut = log.len() - max_log_bytes;
+        while !log.is_char_boundary(cut) {
+            cut += 1;
+        }
+        log.drain(..cut);
+    }
+    if !output.status.success() {
+        bail!("Exited with {}", output.status);
+    }
+    Ok(stdout)
+}
+
+/// A fresh webhook secret, as hex
+pub fn generate_webhook_secret() -> Result<String> {
+    Ok(hex::encode(crate::tenancy::secrets::random_key()?))
+}
+
+/// Whether a push webhook carries the site's secret: GitHub and Gitea sign the body, GitLab sends the token
+pub fn verify_webhook(secret: &str, headers: &hyper::HeaderMap, body: &[u8]) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let signed = header("x-hub-signature-256")
+        .and_then(|signature| signature.strip_prefix("sha256="))
+        .or_else(|| header("x-gitea-signature"));
+    if let Some(signature) = signed {
+        return hex::decode(signature)
+            .is_ok_and(|signature| hmac::verify(&key, body, &signature).is_ok());
+    }
+    match header("x-gitlab-token") {
+        // Compared as MACs of the secret, so the comparison takes the same time wherever they differ
+        Some(token) => hmac::verify(&key, token.as_bytes(), hmac::sign(&key, secret.as_bytes()).as_ref()).is_ok(),
+        None => false,
+    }
+}
+
+/// The ref a push webhook reports, such as `refs/heads/main`
+pub fn pushed_ref(body: &[u8]) -> Option<String> {
+    let payload: serde_json::Value = serde_json::from_slice(body).ok()?;
+    payload.get("ref")?.as_str().map(str::to_string)
+}
+
+/// Whether a pushed ref is the source's branch or tag
+pub fn is_source_ref(pushed: &str, reference: &str) -> bool {
+    pushed == reference
+        || pushed.strip_prefix("refs/heads/") == Some(reference)
+        || pushed.strip_prefix("refs/tags/") == Some(reference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_validation() {
+        let request = |url: &str, reference: &str| GitSourceRequest {
+            url: url.to_string(),
+            reference: reference.to_string(),
+            build: None,
+            output: None,
+            switch: false,
+        };
+        assert!(request("https://github.com/acme/blog.git", "main").validate().is_ok());
+        assert!(request("git@github.com:acme/blog.git", "v1.2.0").validate().is_ok());
+        assert!(request("ssh://git@git.acme.dev/blog", "release/2024").validate().is_ok());
+
+        assert!(request("file:///etc", "main").validate().is_err());
+        assert!(request("/srv/repos/blog", "main").validate().is_err());
+        assert!(request("--upload-pack=touch /tmp/x", "main").validate().is_err());
+        assert!(request("https://github.com/acme/blog.git", "--force").validate().is_err());
+        assert!(request("https://github.com/acme/blog.git", "a..b").validate().is_err());
+
+        let escaping = GitSourceRequest { output: Some("../../etc".to_string()), ..request("https://x.dev/r", "main") };
+        assert!(escaping.validate().is_err());
+    }
+
+    #[test]
+    fn test_webhook_verification() {
+        let body = br#"{"ref":"refs/heads/main"}"#;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"topsecret");
+        let signature = hex::encode(hmac::sign(&key, body));
+
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-hub-signature-256", format!("sha256={}", signature).parse().unwrap());
+        assert!(verify_webhook("topsecret", &headers, body));
+        assert!(!verify_webhook("other", &headers, body));
+        assert!(!verify_webhook("topsecret", &headers, br#"{"ref":"refs/heads/evil"}"#));
+
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-gitlab-token", "topsecret".parse().unwrap());
+        assert!(verify_webhook("topsecret", &headers, body));
+        assert!(!verify_webhook("topsecret", &hyper::HeaderMap::new(), body));
+
+        assert_eq!(pushed_ref(body).as_deref(), Some("refs/heads/main"));
+        assert!(is_source_ref("refs/heads/main", "main"));
+        assert!(is_source_ref("refs/tags/v1.0", "v1.0"));
+        assert!(!is_source_ref("refs/heads/main-old", "main"));
+    }
+
+    #[test]
+    fn test_build_output() {
+        let checkout = tempfile::tempdir().unwrap();
+        let runtimes = tempfile::tempdir().unwrap();
+        std::fs::write(runtimes.path().join("static-server.wasm"), b"\0asm").unwrap();
+        let polyglot = PolyglotAdapter::new(runtimes.path());
+
+        std::fs::create_dir(checkout.path().join("dist")).unwrap();
+        std::fs::write(checkout.path().join("dist/index.html"), "<h1>hi</h1>").unwrap();
+        std::fs::write(checkout.path().join("app.wasm"), b"\0asm built").unwrap();
+
+        let served = build_output(&polyglot, checkout.path(), Some("dist")).unwrap();
+        assert_eq!(served.language, Some(DetectedLanguage::StaticFiles));
+        assert_eq!(served.module, b"\0asm");
+        assert_eq!(served.content.unwrap(), checkout.path().join("dist"));
+
+        let built = build_output(&polyglot, checkout.path(), Some("app.wasm")).unwrap();
+        assert_eq!((built.language, built.module.as_slice()), (None, &b"\0asm built"[..]));
+
+        assert!(build_output(&polyglot, checkout.path(), Some("missing")).is_err());
+        std::os::unix::fs::symlink("/etc", checkout.path().join("escape")).unwrap();
+        assert!(build_output(&polyglot, checkout.path(), Some("escape")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_keeps_log_tail() {
+        let mut log = String::new();
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo first; echo second >&2"]);
+        let stdout = run(command, Duration::from_secs(10), 10, &mut log).await.unwrap();
+        assert_eq!(stdout, "first\n");
+        assert_eq!(log, "\nsecond\n");
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "exit 3"]);
+        assert!(run(command, Duration::from_secs(10), 1024, &mut log).await.is_err());
+
+        let mut command = Command::new("sleep");
+        command.arg("5");
+        let timed_out = run(command, Duration::from_millis(100), 1024, &mut log).await.unwrap_err();
+        assert!(timed_out.to_string().contains("Timed out"));
+    }
+}
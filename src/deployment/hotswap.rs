@@ -0,0 +1,165 @@
+// Hot-Swap Deployments
+// Moves a site's live pool onto a new module in place: no second pool and no synthetic checks
+
+use super::validate::{validate_module, ModuleReport};
+use crate::router::Router;
+use crate::storage::modules::{ModuleHash, ModuleStore};
+use crate::supervisor::Supervisor;
+use crate::tenancy::signing::SignatureCheck;
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Outcome of one hot-swap
+#[derive(Debug, Clone, Serialize)]
+pub struct HotSwap {
+    pub site_id: String,
+
+    /// Whether the site now runs the new module
+    pub swapped: bool,
+
+    /// Unix time in seconds
+    pub swapped_at: i64,
+
+    /// What the module imports, exports and requires
+    pub module: ModuleReport,
+
+    /// Where the module is kept in the module store, once it passed validation
+    pub module_hash: Option<ModuleHash>,
+
+    /// Module the site ran before
+    pub previous_hash: Option<ModuleHash>,
+
+    /// Cages now running the new module
+    pub cages: usize,
+
+    /// Cages of the previous module finishing their in-flight requests
+    pub draining: usize,
+
+    /// Why the module was not swapped in
+    pub failures: Vec<String>,
+}
+
+/// Validate and store `wasm_bytes`, then move the site's live pool onto it
+/// A module whose `signature` was rejected, that fails validation or whose Cages fail to start
+/// comes back with `swapped: false`, and the site keeps serving its current module.
+pub async fn hot_swap(
+    router: &Router,
+    supervisor: &Supervisor,
+    modules: &Arc<ModuleStore>,
+    site_id: &str,
+    wasm_bytes: Vec<u8>,
+    max_module_bytes: usize,
+    signature: SignatureCheck,
+) -> Result<HotSwap> {
+    let Some(pool) = router.pool(site_id) else {
+        bail!("Site {} has no live pool to swap", site_id);
+    };
+    let mut swap = HotSwap {
+        site_id: site_id.to_string(),
+        swapped: false,
+        swapped_at: chrono::Utc::now().timestamp(),
+        module: ModuleReport::default(),
+        module_hash: None,
+        previous_hash: supervisor.module(site_id).map(|module| module.hash()),
+        cages: pool.size().await,
+        draining: 0,
+        failures: Vec::new(),
+    };
+    if let Some(problem) = signature.problem() {
+        swap.failures.push(problem);
+        return Ok(rejected(swap));
+    }
+
+    // Parsing a large module several times over is too slow for a runtime thread
+    let store = modules.clone();
+    let (wasm_bytes, module, stored) = tokio::task::spawn_blocking(move || {
+        let module = validate_module(&wasm_bytes, max_module_bytes);
+        let stored = module.is_valid().then(|| store.put(&wasm_bytes));
+        (wasm_bytes, module, stored)
+    }).await?;
+    swap.module = module;
+    let stored = match stored {
+        None => {
+            swap.failures = swap.module.problems.clone();
+            return Ok(rejected(swap));
+        }
+        Some(Err(e)) => {
+            swap.failures.push(format!("{:#}", e));
+            return Ok(rejected(swap));
+        }
+        Some(Ok(stored)) => stored,
+    };
+    swap.module_hash = Some(stored.hash());
+
+    // Respawns use the new module from here on, so none brings the old one back after the swap
+    let previous = supervisor.set_module(site_id, stored);
+    match pool.hot_swap(&wasm_bytes).await {
+        Ok(draining) => {
+            swap.swapped = true;
+            swap.draining = draining;
+            swap.cages = pool.size().await;
+            info!(site_id = %site_id, cages = swap.cages, draining = draining, "Hot-swapped site module");
+            Ok(swap)
+        }
+        Err(e) => {
+            if let Some(previous) = previous {
+                supervisor.set_module(site_id, previous);
+            }
+            swap.failures.push(format!("{:#}", e));
+            Ok(rejected(swap))
+        }
+    }
+}
+
+fn rejected(swap: HotSwap) -> HotSwap {
+    warn!(site_id = %swap.site_id, first = %swap.failures[0], "Hot-swap rejected; the current module keeps serving");
+    swap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cage::config::CageConfig;
+    use crate::cage::pool::CagePool;
+    use crate::router::RouterConfig;
+    use crate::supervisor::SupervisorConfig;
+
+    #[tokio::test]
+    async fn test_hot_swap() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let modules = ModuleStore::new(temp.path()).unwrap();
+        let router = Router::new(RouterConfig::default());
+        let supervisor = Supervisor::new(SupervisorConfig::default());
+        let wasm = wat::parse_str("(module)").unwrap();
+        let pool = Arc::new(CagePool::new("blog".to_string(), wasm.clone(), CageConfig::default(), 2).await.unwrap());
+        router.register_pool("blog".to_string(), pool.clone());
+        let blue = modules.put(&wasm).unwrap();
+        supervisor.register_pool("blog".to_string(), pool.clone(), blue.clone());
+
+        let v2 = wat::parse_str("(module (func (export \"v2\")))").unwrap();
+        let swap = hot_swap(&router, &supervisor, &modules, "blog", v2.clone(), 1 << 20, SignatureCheck::Unsigned)
+            .await
+            .unwrap();
+        assert!(swap.swapped, "{:?}", swap.failures);
+        assert_eq!((swap.cages, swap.draining), (2, 2));
+        assert_eq!(swap.previous_hash, Some(blue.hash()));
+        assert_eq!(supervisor.module("blog").unwrap().load().unwrap(), v2);
+        assert!(Arc::ptr_eq(&router.pool("blog").unwrap(), &pool));
+
+        // Rejected modules leave the site and its respawn module alone
+        let swap = hot_swap(&router, &supervisor, &modules, "blog", b"not wasm".to_vec(), 1 << 20, SignatureCheck::Unsigned)
+            .await
+            .unwrap();
+        assert!(!swap.swapped);
+        assert!(swap.module_hash.is_none());
+        let swap = hot_swap(&router, &supervisor, &modules, "blog", wasm, 1 << 20, SignatureCheck::Missing)
+            .await
+            .unwrap();
+        assert!(!swap.swapped);
+        assert_eq!(supervisor.module("blog").unwrap().load().unwrap(), v2);
+
+        assert!(hot_swap(&router, &supervisor, &modules, "shop", v2, 1 << 20, SignatureCheck::Unsigned).await.is_err());
+    }
+}
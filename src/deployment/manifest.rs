@@ -0,0 +1,230 @@
+// Site Manifest
+// pear.site.toml: everything a site needs besides its modules, read next to them at deploy time
+
+use crate::cage::config::{CageConfig, StartPolicy};
+use crate::router::routes::{validate_routes, RouteConfig};
+use crate::scheduler::JobSpec;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// File name of a site manifest
+pub const MANIFEST_FILE: &str = "pear.site.toml";
+
+/// Host modules a site can rely on the node to link into its Cages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    /// `pear_db`: the site's SQLite database
+    Database,
+    /// `pear_mail`: the tenant's mail relay
+    Mail,
+    /// `pear_pubsub`: topics shared between sites
+    Pubsub,
+    /// `pear_queue`: background tasks
+    Queue,
+    /// `pear_session`: user sessions
+    Sessions,
+    /// `pear_stream`: streamed responses
+    Streaming,
+}
+
+/// A site manifest
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteManifest {
+    /// Module serving the site, relative to the manifest
+    pub module: Option<String>,
+
+    /// Cages running the site's module (the node's default if unset)
+    pub replicas: Option<usize>,
+
+    /// Memory limit of each of the site's Cages, route modules' included (the node's default if unset)
+    pub memory_mb: Option<usize>,
+
+    /// Warm Cages kept out of load balancing, promoted when one crashes (the node's default if unset)
+    pub standby_replicas: Option<usize>,
+
+    /// When the site's Cages start: "eager", "lazy" or "scale-to-zero" (the node's default if unset)
+    pub start: Option<StartPolicy>,
+
+    /// Seconds without requests before a scale-to-zero site stops its Cages (the node's default if unset)
+    pub idle_timeout_secs: Option<u64>,
+
+    /// Host modules the site's Cages need; the deploy fails on a node that doesn't provide them
+    pub requires: Vec<Capability>,
+
+    /// Environment variables of the site's Cages
+    pub env: BTreeMap<String, String>,
+
+    /// Path prefixes served by other modules, strategies or limits than the site's own
+    pub routes: Vec<RouteConfig>,
+
+    /// Exports called on a schedule
+    pub cron: Vec<JobSpec>,
+}
+
+impl SiteManifest {
+    pub fn parse(text: &str) -> Result<Self> {
+        let manifest: SiteManifest = toml::from_str(text).context("Invalid site manifest")?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// The manifest in `dir`, if it has one
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Self::parse(&text).with_context(|| format!("In {}", path.display())).map(Some)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(module) = &self.module {
+            check_module_path(module)?;
+        }
+        if self.replicas == Some(0) {
+            bail!("replicas must be at least 1");
+        }
+        if self.memory_mb == Some(0) {
+            bail!("memory_mb must be at least 1");
+        }
+        if self.idle_timeout_secs == Some(0) {
+            bail!("idle_timeout_secs must be at least 1");
+        }
+        for (name, value) in &self.env {
+            crate::tenancy::secrets::validate_var(name, value)?;
+        }
+        validate_routes(&self.routes)?;
+
+        let mut jobs = HashSet::new();
+        for job in &self.cron {
+            job.validate().with_context(|| format!("Invalid cron job '{}'", job.name))?;
+            if !jobs.insert(job.name.as_str()) {
+                bail!("Cron job '{}' is listed twice", job.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuse the manifest unless the node provides every capability it requires
+    pub fn check_capabilities(&self, available: &[Capability]) -> Result<()> {
+        let missing: Vec<String> = self.requires.iter()
+            .filter(|capability| !available.contains(capability))
+            .map(|capability| format!("{:?}", capability).to_lowercase())
+            .collect();
+        if !missing.is_empty() {
+            bail!("The site requires {}, which this node doesn't provide", missing.join(", "));
+        }
+        Ok(())
+    }
+
+    /// The site's module, resolved against the manifest's directory
+    pub fn module_path(&self, dir: &Path) -> Option<PathBuf> {
+        self.module.as_ref().map(|module| dir.join(module))
+    }
+
+    /// Modules the routes name, resolved against the manifest's directory
+    pub fn route_modules(&self, dir: &Path) -> Vec<PathBuf> {
+        self.routes.iter()
+            .filter_map(|route| route.module.as_ref())
+            .map(|module| dir.join(module))
+            .collect()
+    }
+
+    /// Apply the memory limit and environment to the Cage settings of one of the site's pools
+    pub fn apply_to(&self, config: &mut CageConfig) {
+        if let Some(memory_mb) = self.memory_mb {
+            config.memory_limit_bytes = memory_mb * 1024 * 1024;
+        }
+        if let Some(standby_replicas) = self.standby_replicas {
+            config.standby_replicas = standby_replicas;
+        }
+        if let Some(start) = self.start {
+            config.start = start;
+        }
+        if let Some(idle_timeout_secs) = self.idle_timeout_secs {
+            config.idle_timeout_secs = idle_timeout_secs;
+        }
+        for (name, value) in &self.env {
+            config.env.push(name, value);
+        }
+    }
+}
+
+/// Refuse module paths that would leave the manifest's directory
+pub(crate) fn check_module_path(module: &str) -> Result<()> {
+    let path = Path::new(module);
+    if path.is_absolute() || path.components().any(|c| matches!(c, Component::ParentDir)) {
+        bail!("Module '{}' must be inside the site directory", module);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = SiteManifest::parse(r#"
+            module = "site.wasm"
+            replicas = 3
+            memory_mb = 256
+            requires = ["database", "mail"]
+
+            [env]
+            MODE = "production"
+
+            [[routes]]
+            path = "/api/*"
+            module = "api.wasm"
+            replicas = 4
+            strategy = "least-connected"
+
+            [[routes]]
+            path = "/*"
+            limits = { max_body_bytes = 65536 }
+
+            [[cron]]
+            name = "cleanup"
+            schedule = "0 3 * * *"
+        "#).unwrap();
+        assert_eq!(manifest.module_path(Path::new("site")), Some(PathBuf::from("site/site.wasm")));
+        assert_eq!(manifest.routes.len(), 2);
+        assert_eq!(manifest.routes[0].prefix(), "/api");
+        assert_eq!(manifest.routes[1].prefix(), "/");
+        assert_eq!(manifest.route_modules(Path::new("site")), vec![PathBuf::from("site/api.wasm")]);
+        assert_eq!(manifest.cron[0].export, "on_schedule");
+
+        let mut config = CageConfig::default();
+        manifest.apply_to(&mut config);
+        assert_eq!(config.memory_limit_bytes, 256 * 1024 * 1024);
+        assert_eq!(config.env.as_pairs(), [("MODE".to_string(), "production".to_string())]);
+
+        assert!(manifest.check_capabilities(&[Capability::Database, Capability::Mail]).is_ok());
+        let missing = manifest.check_capabilities(&[Capability::Database]).unwrap_err();
+        assert!(missing.to_string().contains("mail"));
+
+        let temp = tempfile::TempDir::new().unwrap();
+        assert!(SiteManifest::load(temp.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rejects_invalid_manifests() {
+        assert!(SiteManifest::parse("[[routes]]\npath = \"/api\"\nmodule = \"/etc/api.wasm\"\n").is_err());
+        assert!(SiteManifest::parse("module = \"../site.wasm\"\n").is_err());
+        assert!(SiteManifest::parse("[[route]]\npath = \"/api\"\n").is_err());
+        assert!(SiteManifest::parse("replicas = 0\n").is_err());
+        assert!(SiteManifest::parse("requires = [\"gpu\"]\n").is_err());
+        assert!(SiteManifest::parse("[env]\n\"NOT VALID\" = \"1\"\n").is_err());
+        assert!(SiteManifest::parse("[[cron]]\nname = \"cleanup\"\nschedule = \"every day\"\n").is_err());
+        assert!(SiteManifest::parse(
+            "[[cron]]\nname = \"a\"\nschedule = \"* * * * *\"\n[[cron]]\nname = \"a\"\nschedule = \"0 * * * *\"\n",
+        ).is_err());
+    }
+}
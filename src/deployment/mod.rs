@@ -1,7 +1,12 @@
 // Canary Deployment Module
 // Advanced deployment workflow with safety mechanisms
 
+pub mod bluegreen;
+pub mod git;
+pub mod hotswap;
+pub mod manifest;
 pub mod rollout;
+pub mod validate;
 
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -11,6 +16,59 @@ use uuid::Uuid;
 use anyhow::{Result, Context};
 use tracing::{info, warn};
 
+/// How a new module replaces a site's live one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeploymentStrategy {
+    /// Shift a growing share of traffic to the new module
+    #[default]
+    Canary,
+    
+    /// Warm a complete second pool, then switch all traffic at once
+    BlueGreen,
+}
+
+/// Deployment configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentConfig {
+    /// Strategy `pear deploy` uses unless told otherwise
+    #[serde(default)]
+    pub strategy: DeploymentStrategy,
+    
+    /// Largest module a deploy accepts
+    #[serde(default = "default_max_module_bytes")]
+    pub max_module_bytes: usize,
+    
+    #[serde(default)]
+    pub blue_green: bluegreen::BlueGreenConfig,
+    
+    #[serde(default)]
+    pub git: git::GitConfig,
+}
+
+fn default_max_module_bytes() -> usize { validate::DEFAULT_MAX_MODULE_BYTES }
+
+impl Default for DeploymentConfig {
+    fn default() -> Self {
+        Self {
+            strategy: DeploymentStrategy::default(),
+            max_module_bytes: default_max_module_bytes(),
+            blue_green: bluegreen::BlueGreenConfig::default(),
+            git: git::GitConfig::default(),
+        }
+    }
+}
+
+impl DeploymentConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_module_bytes == 0 || self.max_module_bytes > validate::MAX_MODULE_BYTES {
+            anyhow::bail!("max_module_bytes must be between 1 and {}", validate::MAX_MODULE_BYTES);
+        }
+        self.blue_green.validate().context("Invalid [deployment.blue_green] config")?;
+        self.git.validate().context("Invalid [deployment.git] config")
+    }
+}
+
 /// Canary deployment manager
 pub struct CanaryManager {
     /// Active canary deployments
@@ -197,6 +255,12 @@ impl CanaryManager {
             reason = %reason,
             "Canary deployment rolled back"
         );
+        crate::notifications::notify(crate::notifications::Notification::new(
+            crate::notifications::EventKind::CanaryRollback,
+            Some(site_id),
+            format!("Canary deployment of {} rolled back: {}", site_id, reason),
+            serde_json::json!({ "canary_id": canary.canary_id, "reason": reason }),
+        ));
         
         Ok(())
     }
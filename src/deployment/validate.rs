@@ -0,0 +1,204 @@
+// Module Validation
+// Checks uploaded Wasm modules against what Cages can run before any pool is created
+
+use serde::Serialize;
+use wasmparser::{ExternalKind, Parser, Payload, TypeRef, Validator, WasmFeatures};
+
+use crate::cage::{db_host, mail_host, pubsub_host, queue_host, session_host, stream_host};
+
+/// Largest module accepted by any deploy path; `max_module_bytes` may only lower it
+pub const MAX_MODULE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Default for `[deployment] max_module_bytes`
+pub const DEFAULT_MAX_MODULE_BYTES: usize = 32 * 1024 * 1024;
+
+/// WASI snapshots linked into every Cage
+const WASI_MODULES: &[&str] = &["wasi_snapshot_preview1", "wasi_unstable"];
+
+/// Functions both WASI snapshots provide
+const WASI_FUNCTIONS: &[&str] = &[
+    "args_get", "args_sizes_get", "environ_get", "environ_sizes_get", "clock_res_get", "clock_time_get",
+    "fd_advise", "fd_allocate", "fd_close", "fd_datasync", "fd_fdstat_get", "fd_fdstat_set_flags",
+    "fd_fdstat_set_rights", "fd_filestat_get", "fd_filestat_set_size", "fd_filestat_set_times", "fd_pread",
+    "fd_prestat_get", "fd_prestat_dir_name", "fd_pwrite", "fd_read", "fd_readdir", "fd_renumber", "fd_seek",
+    "fd_sync", "fd_tell", "fd_write", "path_create_directory", "path_filestat_get", "path_filestat_set_times",
+    "path_link", "path_open", "path_readlink", "path_remove_directory", "path_rename", "path_symlink",
+    "path_unlink_file", "poll_oneoff", "proc_exit", "proc_raise", "sched_yield", "random_get", "sock_accept",
+    "sock_recv", "sock_send", "sock_shutdown",
+];
+
+/// Switches one proposal off
+type Disable = fn(&mut WasmFeatures);
+
+/// Proposals the Cage engine enables, and how to switch each off to see whether a module needs it
+const OPTIONAL_FEATURES: &[(&str, Disable)] = &[
+    ("mutable-global", |f| f.mutable_global = false),
+    ("saturating-float-to-int", |f| f.saturating_float_to_int = false),
+    ("sign-extension", |f| f.sign_extension = false),
+    ("multi-value", |f| f.multi_value = false),
+    ("bulk-memory", |f| f.bulk_memory = false),
+    ("reference-types", |f| f.reference_types = false),
+    ("simd", |f| f.simd = false),
+    ("relaxed-simd", |f| f.relaxed_simd = false),
+];
+
+/// What a module needs from its Cages, and anything that rules it out
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ModuleReport {
+    pub size_bytes: usize,
+
+    /// `module.name` of every import
+    pub imports: Vec<String>,
+    pub exports: Vec<String>,
+
+    /// Post-MVP proposals the module uses
+    pub features: Vec<String>,
+
+    /// Reasons the module can't be deployed; empty when it can
+    pub problems: Vec<String>,
+}
+
+impl ModuleReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Parse and check `wasm_bytes`
+pub fn validate_module(wasm_bytes: &[u8], max_module_bytes: usize) -> ModuleReport {
+    let mut report = ModuleReport { size_bytes: wasm_bytes.len(), ..Default::default() };
+    if wasm_bytes.len() > max_module_bytes {
+        report.problems.push(format!("Module is {} bytes; the limit is {}", wasm_bytes.len(), max_module_bytes));
+        return report;
+    }
+
+    if let Err(problem) = check_features(wasm_bytes, &mut report) {
+        report.problems.push(problem);
+        return report;
+    }
+
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload {
+            Ok(Payload::ImportSection(imports)) => {
+                for import in imports.into_iter().flatten() {
+                    let name = format!("{}.{}", import.module, import.name);
+                    if let Some(problem) = check_import(import.module, import.name, &import.ty) {
+                        report.problems.push(problem);
+                    }
+                    report.imports.push(name);
+                }
+            }
+            Ok(Payload::ExportSection(exports)) => {
+                for export in exports.into_iter().flatten() {
+                    report.exports.push(match export.kind {
+                        ExternalKind::Func => export.name.to_string(),
+                        ExternalKind::Memory => format!("{} (memory)", export.name),
+                        ExternalKind::Table => format!("{} (table)", export.name),
+                        ExternalKind::Global => format!("{} (global)", export.name),
+                        ExternalKind::Tag => format!("{} (tag)", export.name),
+                    });
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                report.problems.push(format!("Invalid module: {}", e));
+                break;
+            }
+        }
+    }
+    report
+}
+
+/// Validate against the Cage engine's features and record the optional ones the module relies on
+fn check_features(wasm_bytes: &[u8], report: &mut ModuleReport) -> Result<(), String> {
+    if let Err(e) = validate_with(wasm_bytes, cage_features()) {
+        let threads = WasmFeatures { threads: true, ..cage_features() };
+        if validate_with(wasm_bytes, threads).is_ok() {
+            return Err("Module requires threads (shared memory or atomics), which Cages don't run".to_string());
+        }
+        if validate_with(wasm_bytes, WasmFeatures::all()).is_ok() {
+            return Err(format!("Module uses a WebAssembly proposal Cages don't enable: {}", e));
+        }
+        return Err(format!("Invalid module: {}", e));
+    }
+
+    for (name, disable) in OPTIONAL_FEATURES {
+        let mut features = cage_features();
+        disable(&mut features);
+        if validate_with(wasm_bytes, features).is_err() {
+            report.features.push(name.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Features `create_engine` leaves enabled, for plain core modules
+fn cage_features() -> WasmFeatures {
+    WasmFeatures {
+        threads: false,
+        tail_call: false,
+        multi_memory: false,
+        component_model: false,
+        ..WasmFeatures::default()
+    }
+}
+
+fn validate_with(wasm_bytes: &[u8], features: WasmFeatures) -> wasmparser::Result<()> {
+    Validator::new_with_features(features).validate_all(wasm_bytes).map(|_| ())
+}
+
+/// Why an import can't be satisfied by the host, if it can't
+fn check_import(module: &str, name: &str, ty: &TypeRef) -> Option<String> {
+    let functions = if WASI_MODULES.contains(&module) {
+        WASI_FUNCTIONS
+    } else {
+        match module {
+            db_host::MODULE => db_host::FUNCTIONS,
+            mail_host::MODULE => mail_host::FUNCTIONS,
+            pubsub_host::MODULE => pubsub_host::FUNCTIONS,
+            queue_host::MODULE => queue_host::FUNCTIONS,
+            session_host::MODULE => session_host::FUNCTIONS,
+            stream_host::MODULE => stream_host::FUNCTIONS,
+            _ => return Some(format!("Unknown import module '{}' (for {}.{})", module, module, name)),
+        }
+    };
+    if !matches!(ty, TypeRef::Func(_)) {
+        return Some(format!("Import {}.{} is not a function; Cages only provide functions", module, name));
+    }
+    if !functions.contains(&name) {
+        return Some(format!("Unknown import {}.{}", module, name));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts_host_imports() {
+        let wasm = wat::parse_str(r#"(module
+            (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+            (import "pear_db" "query" (func (param i32 i32 i32 i32) (result i64)))
+            (memory (export "memory") 1)
+            (func (export "handle") (result v128) (v128.const i64x2 0 0)))"#).unwrap();
+        let report = validate_module(&wasm, DEFAULT_MAX_MODULE_BYTES);
+        assert!(report.is_valid(), "{:?}", report.problems);
+        assert_eq!(report.imports, ["wasi_snapshot_preview1.fd_write", "pear_db.query"]);
+        assert_eq!(report.exports, ["memory (memory)", "handle"]);
+        assert_eq!(report.features, ["simd"]);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_modules() {
+        let unknown = wat::parse_str(r#"(module (import "env" "abort" (func)) (import "pear_db" "drop" (func)))"#).unwrap();
+        let report = validate_module(&unknown, DEFAULT_MAX_MODULE_BYTES);
+        assert_eq!(report.problems.len(), 2);
+
+        let threads = wat::parse_str("(module (memory 1 1 shared))").unwrap();
+        assert!(validate_module(&threads, DEFAULT_MAX_MODULE_BYTES).problems[0].contains("threads"));
+
+        assert!(!validate_module(b"not wasm", DEFAULT_MAX_MODULE_BYTES).is_valid());
+        assert!(!validate_module(&wat::parse_str("(module)").unwrap(), 4).is_valid());
+    }
+}
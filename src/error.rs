@@ -0,0 +1,198 @@
+// Errors
+// Why a request wasn't served: a stable code for API consumers and metrics, and the status it maps to
+
+use hyper::StatusCode;
+use serde_json::json;
+use thiserror::Error;
+
+use crate::router::limits::LimitError;
+
+/// Status answered once a site or tenant is over its hard bandwidth limit
+pub const BANDWIDTH_LIMIT_EXCEEDED: u16 = 509;
+
+/// A request the server refused or failed to serve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum PearError {
+    /// No pool, route or upstream serves the requested site
+    #[error("Site not found")]
+    SiteNotFound,
+
+    /// The site's tenant is suspended
+    #[error("Site suspended")]
+    SiteSuspended,
+
+    /// The site's tenant is deleted
+    #[error("Site unavailable")]
+    SiteUnavailable,
+
+    /// The site is drained for maintenance
+    #[error("Site is down for maintenance")]
+    SiteDraining,
+
+    /// Every Cage of the pool is crashed, unhealthy or starting
+    #[error("No healthy instances available")]
+    NoHealthyCages,
+
+    /// Access rules refuse the client
+    #[error("Forbidden")]
+    Forbidden,
+
+    /// Access rules want credentials the request lacks
+    #[error("Authentication required")]
+    Unauthorized,
+
+    /// The security module blocked the client or the request
+    #[error("Request blocked")]
+    Blocked,
+
+    /// The security module rate limited the client
+    #[error("Too many requests")]
+    RateLimited,
+
+    /// The connection has as many streamed responses open as allowed
+    #[error("Too many streams on this connection")]
+    TooManyStreams,
+
+    /// The site or its tenant used up its bandwidth for the period
+    #[error("Bandwidth limit exceeded")]
+    QuotaExceeded,
+
+    /// The request's head or body is over the site's limits
+    #[error("{0}")]
+    Limit(LimitError),
+
+    /// Shed by admission control or a full Wasm executor queue
+    #[error("Server is busy, try again shortly")]
+    Overloaded,
+
+    /// The upstream of a proxied site failed
+    #[error("Upstream unavailable")]
+    UpstreamUnavailable,
+
+    /// The guest was interrupted or ran out of fuel
+    #[error("Request timed out")]
+    Timeout,
+
+    /// The guest trapped
+    #[error("Request execution failed")]
+    CageTrap,
+
+    /// Anything else that went wrong running the request
+    #[error("Request execution failed")]
+    Internal,
+}
+
+impl PearError {
+    /// Stable identifier, sent in error bodies and used as a metrics label
+    pub fn code(&self) -> &'static str {
+        match self {
+            PearError::SiteNotFound => "site_not_found",
+            PearError::SiteSuspended => "site_suspended",
+            PearError::SiteUnavailable => "site_unavailable",
+            PearError::SiteDraining => "site_draining",
+            PearError::NoHealthyCages => "no_healthy_cages",
+            PearError::Forbidden => "forbidden",
+            PearError::Unauthorized => "unauthorized",
+            PearError::Blocked => "blocked",
+            PearError::RateLimited => "rate_limited",
+            PearError::TooManyStreams => "too_many_streams",
+            PearError::QuotaExceeded => "quota_exceeded",
+            PearError::Limit(LimitError::TooManyHeaders) => "too_many_headers",
+            PearError::Limit(LimitError::HeadersTooLarge) => "headers_too_large",
+            PearError::Limit(LimitError::BodyTooLarge) => "body_too_large",
+            PearError::Limit(LimitError::BodyTooSlow) => "body_too_slow",
+            PearError::Overloaded => "overloaded",
+            PearError::UpstreamUnavailable => "upstream_unavailable",
+            PearError::Timeout => "timeout",
+            PearError::CageTrap => "cage_trap",
+            PearError::Internal => "internal_error",
+        }
+    }
+
+    /// HTTP status the error is answered with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            PearError::SiteNotFound => StatusCode::NOT_FOUND,
+            PearError::SiteSuspended | PearError::Forbidden | PearError::Blocked => StatusCode::FORBIDDEN,
+            PearError::Unauthorized => StatusCode::UNAUTHORIZED,
+            PearError::RateLimited | PearError::TooManyStreams => StatusCode::TOO_MANY_REQUESTS,
+            PearError::QuotaExceeded => StatusCode::from_u16(BANDWIDTH_LIMIT_EXCEEDED).expect("509 is a valid status"),
+            PearError::Limit(limit) => limit.status(),
+            PearError::SiteUnavailable
+            | PearError::SiteDraining
+            | PearError::NoHealthyCages
+            | PearError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            PearError::UpstreamUnavailable => StatusCode::BAD_GATEWAY,
+            PearError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            PearError::CageTrap | PearError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// What went wrong running a request in a Cage
+    pub fn from_execution(error: &anyhow::Error) -> Self {
+        use wasmtime::Trap;
+        if error.is::<crate::cage::executor::Saturated>() || error.is::<crate::cage::request_queue::QueueRejected>() {
+            return PearError::Overloaded;
+        }
+        match error.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel | Trap::Interrupt) => PearError::Timeout,
+            Some(_) => PearError::CageTrap,
+            None if crate::cage::crash::is_crash(error) => PearError::CageTrap,
+            None => PearError::Internal,
+        }
+    }
+
+    /// JSON body of an error response
+    pub fn body(&self, request_id: Option<&str>) -> serde_json::Value {
+        let mut body = json!({
+            "error": self.to_string(),
+            "code": self.code(),
+            "status": self.status().as_u16(),
+        });
+        if let Some(request_id) = request_id {
+            body["request_id"] = request_id.into();
+        }
+        body
+    }
+}
+
+impl From<LimitError> for PearError {
+    fn from(limit: LimitError) -> Self {
+        PearError::Limit(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_and_statuses() {
+        assert_eq!(PearError::SiteNotFound.status(), StatusCode::NOT_FOUND);
+        assert_eq!(PearError::QuotaExceeded.status().as_u16(), BANDWIDTH_LIMIT_EXCEEDED);
+        assert_eq!(PearError::from(LimitError::BodyTooLarge).status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(PearError::from(LimitError::BodyTooLarge).code(), "body_too_large");
+
+        let body = PearError::SiteDraining.body(Some("req-1"));
+        assert_eq!(body["code"], "site_draining");
+        assert_eq!(body["status"], 503);
+        assert_eq!(body["error"], "Site is down for maintenance");
+        assert_eq!(body["request_id"], "req-1");
+        assert!(PearError::Timeout.body(None).get("request_id").is_none());
+    }
+
+    #[test]
+    fn test_execution_errors() {
+        let saturated = anyhow::Error::new(crate::cage::executor::Saturated);
+        assert_eq!(PearError::from_execution(&saturated), PearError::Overloaded);
+        let queued = anyhow::Error::new(crate::cage::request_queue::QueueRejected::TimedOut);
+        assert_eq!(PearError::from_execution(&queued), PearError::Overloaded);
+
+        let timeout = anyhow::Error::new(wasmtime::Trap::OutOfFuel).context("Guest call failed");
+        assert_eq!(PearError::from_execution(&timeout), PearError::Timeout);
+        let trap = anyhow::Error::new(wasmtime::Trap::UnreachableCodeReached);
+        assert_eq!(PearError::from_execution(&trap), PearError::CageTrap);
+
+        assert_eq!(PearError::from_execution(&anyhow::anyhow!("Cage 3 is not healthy")), PearError::Internal);
+    }
+}
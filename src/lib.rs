@@ -0,0 +1,46 @@
+// Pear Server - Phase 1: Foundation and Linux Interaction Layer
+// Phase 2: Middle Layer - Cage Architecture & AI Engine
+// Phase 3: Interaction Layer & Configuration Management
+// Library crate: every server component, embeddable and testable on its own
+
+// Phase 1 modules
+pub mod network;
+pub mod observability;
+pub mod runtime;
+pub mod signals;
+pub mod state;
+pub mod upgrade;
+
+// Phase 2 modules
+pub mod cage;
+pub mod router;
+pub mod supervisor;
+pub mod crdt;
+pub mod ai;
+
+// Phase 3 modules
+pub mod cli;
+pub mod config;
+pub mod dashboard;
+
+// Phase 4 modules
+pub mod tenancy;
+pub mod deployment;
+pub mod storage;
+pub mod scheduler;
+pub mod mail;
+pub mod chaos;
+pub mod notifications;
+
+pub mod daemon;
+pub mod error;
+pub mod node;
+
+pub use cage::pool::CagePool;
+pub use cage::Cage;
+pub use config::PearConfig;
+pub use error::PearError;
+pub use node::{PearNode, PearNodeBuilder, SiteHandle};
+pub use router::Router;
+pub use supervisor::Supervisor;
+pub use tenancy::TenantManager;
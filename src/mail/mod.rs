@@ -0,0 +1,953 @@
+// Outbound Mail Module
+// Per-tenant email relay with quotas, suppression lists and delivery logs
+
+pub mod smtp;
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use smtp::{SmtpRelay, SmtpReply, SmtpSecurity};
+
+/// Give up on a delivery after this long
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Messages accepted but not yet handed to the transport
+const QUEUE_CAPACITY: usize = 1024;
+
+/// How accepted messages leave the node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// Submit to an SMTP relay
+    #[default]
+    Smtp,
+
+    /// POST JSON to a provider's HTTP API
+    Api,
+}
+
+/// Mail relay settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Tenant policies, suppression lists and daily counts are persisted here ("" = memory only)
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+
+    #[serde(default)]
+    pub transport: TransportKind,
+
+    #[serde(default = "default_smtp_host")]
+    pub smtp_host: String,
+
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+
+    #[serde(default)]
+    pub smtp_security: SmtpSecurity,
+
+    #[serde(default)]
+    pub smtp_username: Option<String>,
+
+    #[serde(default)]
+    pub smtp_password: Option<String>,
+
+    /// Provider endpoint receiving `{from, to, reply_to, subject, text, html}`
+    #[serde(default)]
+    pub api_url: String,
+
+    /// Sent as a bearer token to the provider
+    #[serde(default)]
+    pub api_token: String,
+
+    /// Sender used when a guest doesn't name one
+    #[serde(default = "default_from")]
+    pub default_from: String,
+
+    /// Recipients a tenant may send to per day, unless its quota says otherwise
+    #[serde(default = "default_daily_quota")]
+    pub daily_quota: u64,
+
+    /// Messages a tenant may submit per minute
+    #[serde(default = "default_max_per_minute")]
+    pub max_per_minute: u32,
+
+    #[serde(default = "default_max_recipients")]
+    pub max_recipients: usize,
+
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: usize,
+
+    /// Consecutive permanent failures before a tenant is suspended (0 = never)
+    #[serde(default = "default_suspend_after")]
+    pub suspend_after_failures: u32,
+
+    /// Delivery records kept per tenant
+    #[serde(default = "default_log_capacity")]
+    pub log_capacity: usize,
+}
+
+fn default_state_path() -> String { "mail.json".to_string() }
+fn default_smtp_host() -> String { "localhost".to_string() }
+fn default_smtp_port() -> u16 { 25 }
+fn default_from() -> String { "noreply@localhost".to_string() }
+fn default_daily_quota() -> u64 { 500 }
+fn default_max_per_minute() -> u32 { 30 }
+fn default_max_recipients() -> usize { 20 }
+fn default_max_message_bytes() -> usize { 256 * 1024 }
+fn default_suspend_after() -> u32 { 20 }
+fn default_log_capacity() -> usize { 500 }
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            state_path: default_state_path(),
+            transport: TransportKind::default(),
+            smtp_host: default_smtp_host(),
+            smtp_port: default_smtp_port(),
+            smtp_security: SmtpSecurity::default(),
+            smtp_username: None,
+            smtp_password: None,
+            api_url: String::new(),
+            api_token: String::new(),
+            default_from: default_from(),
+            daily_quota: default_daily_quota(),
+            max_per_minute: default_max_per_minute(),
+            max_recipients: default_max_recipients(),
+            max_message_bytes: default_max_message_bytes(),
+            suspend_after_failures: default_suspend_after(),
+            log_capacity: default_log_capacity(),
+        }
+    }
+}
+
+impl MailConfig {
+    /// Check the settings are usable
+    pub fn validate(&self) -> Result<()> {
+        validate_address(&self.default_from).context("Invalid mail.default_from")?;
+        if self.max_per_minute == 0 || self.max_recipients == 0 || self.max_message_bytes == 0 {
+            bail!("mail.max_per_minute, mail.max_recipients and mail.max_message_bytes must be greater than 0");
+        }
+        match self.transport {
+            TransportKind::Smtp => {
+                if self.smtp_host.is_empty() {
+                    bail!("mail.smtp_host is required for the smtp transport");
+                }
+                if self.smtp_username.is_some() != self.smtp_password.is_some() {
+                    bail!("mail.smtp_username and mail.smtp_password must be set together");
+                }
+            }
+            TransportKind::Api => {
+                if !self.api_url.starts_with("http://") && !self.api_url.starts_with("https://") {
+                    bail!("mail.api_url must be an http(s) URL for the api transport");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A message as submitted by a guest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Email {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+
+    pub to: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+
+    pub subject: String,
+
+    #[serde(default)]
+    pub text: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+}
+
+/// Operator overrides for one tenant
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TenantMailPolicy {
+    /// Replaces the tenant's quota and the global default
+    #[serde(default)]
+    pub daily_quota: Option<u64>,
+
+    #[serde(default)]
+    pub max_per_minute: Option<u32>,
+
+    /// Domains the tenant may send from besides the default sender
+    #[serde(default)]
+    pub from_domains: Vec<String>,
+
+    #[serde(default)]
+    pub suspended: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspended_reason: Option<String>,
+}
+
+/// Where a message is in its delivery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Queued,
+    Sent,
+
+    /// The transport failed; the message was not retried
+    Failed,
+
+    /// Every recipient was refused for good
+    Rejected,
+
+    /// Every recipient was on the suppression list
+    Suppressed,
+}
+
+/// One entry of a tenant's delivery log
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveryRecord {
+    pub id: u64,
+    pub site_id: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    pub status: DeliveryStatus,
+
+    /// Recipients skipped or refused
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dropped: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Unix timestamp (seconds)
+    pub queued_at: i64,
+
+    /// Unix timestamp (seconds)
+    pub updated_at: i64,
+}
+
+impl DeliveryRecord {
+    fn new(id: u64, site_id: &str, from: &str, email: &Email, status: DeliveryStatus, dropped: Vec<String>) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Self {
+            id,
+            site_id: site_id.to_string(),
+            from: from.to_string(),
+            to: email.to.clone(),
+            subject: email.subject.clone(),
+            status,
+            dropped,
+            error: None,
+            queued_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// A tenant's mail settings and today's usage
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantMailStatus {
+    pub policy: TenantMailPolicy,
+    pub daily_quota: u64,
+    pub sent_today: u64,
+    pub suppressed: usize,
+    pub consecutive_failures: u32,
+}
+
+/// Hands messages to a mail service
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    /// Deliver a message, returning the recipients refused for good
+    /// Nothing is delivered when every recipient is refused.
+    async fn send(&self, from: &str, email: &Email, message_id: &str) -> Result<Vec<String>>;
+}
+
+/// Delivery through an SMTP relay
+pub struct SmtpTransport {
+    relay: SmtpRelay,
+}
+
+#[async_trait]
+impl MailTransport for SmtpTransport {
+    async fn send(&self, from: &str, email: &Email, message_id: &str) -> Result<Vec<String>> {
+        self.relay.send(from, &email.to, &format_message(from, email, message_id)).await
+    }
+}
+
+/// Delivery through a provider's HTTP API
+pub struct ApiTransport {
+    url: String,
+    token: String,
+}
+
+#[async_trait]
+impl MailTransport for ApiTransport {
+    async fn send(&self, from: &str, email: &Email, message_id: &str) -> Result<Vec<String>> {
+        let body = serde_json::json!({
+            "message_id": message_id,
+            "from": from,
+            "to": email.to,
+            "reply_to": email.reply_to,
+            "subject": email.subject,
+            "text": email.text,
+            "html": email.html,
+        });
+        let token = Some(self.token.as_str()).filter(|token| !token.is_empty());
+        let status = crate::ai::alerts::post_json(&self.url, serde_json::to_vec(&body)?, token).await?;
+
+        if status.is_client_error() {
+            // The provider won't take this message however often it's retried
+            return Err(SmtpReply { code: 550, text: format!("mail API returned {}", status) }.into());
+        }
+        if !status.is_success() {
+            bail!("Mail API returned {}", status);
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct DailyUsage {
+    /// UTC date the count belongs to
+    day: String,
+    sent: u64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct MailState {
+    policies: BTreeMap<String, TenantMailPolicy>,
+    suppressions: BTreeMap<String, BTreeSet<String>>,
+    usage: BTreeMap<String, DailyUsage>,
+}
+
+struct Outgoing {
+    id: u64,
+    tenant_id: String,
+    from: String,
+    email: Email,
+}
+
+/// Every tenant's outbound mail
+pub struct MailRelay {
+    config: MailConfig,
+    transport: Arc<dyn MailTransport>,
+    state: Mutex<MailState>,
+
+    /// Daily limits from tenant quotas
+    quotas: DashMap<String, u64>,
+
+    /// Start and count of each tenant's current one-minute window
+    windows: DashMap<String, (Instant, u32)>,
+
+    failures: DashMap<String, u32>,
+    logs: DashMap<String, VecDeque<DeliveryRecord>>,
+    next_id: AtomicU64,
+    sender: mpsc::Sender<Outgoing>,
+    receiver: Mutex<Option<mpsc::Receiver<Outgoing>>>,
+    state_path: Option<PathBuf>,
+    dirty: AtomicBool,
+    save_lock: Mutex<()>,
+}
+
+impl MailRelay {
+    /// Create a relay for the configured transport, loading persisted state if present
+    pub fn new(config: MailConfig) -> Result<Self> {
+        config.validate()?;
+
+        let transport: Arc<dyn MailTransport> = match config.transport {
+            TransportKind::Smtp => Arc::new(SmtpTransport {
+                relay: SmtpRelay {
+                    host: config.smtp_host.clone(),
+                    port: config.smtp_port,
+                    security: config.smtp_security,
+                    credentials: config.smtp_username.clone().zip(config.smtp_password.clone()),
+                },
+            }),
+            TransportKind::Api => Arc::new(ApiTransport {
+                url: config.api_url.clone(),
+                token: config.api_token.clone(),
+            }),
+        };
+
+        let state_path = Some(&config.state_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let state = match &state_path {
+            Some(path) if path.exists() => load_state(path)?,
+            _ => MailState::default(),
+        };
+
+        info!(transport = ?config.transport, tenants = state.policies.len(), "Mail relay initialized");
+
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        Ok(Self {
+            config,
+            transport,
+            state: Mutex::new(state),
+            quotas: DashMap::new(),
+            windows: DashMap::new(),
+            failures: DashMap::new(),
+            logs: DashMap::new(),
+            next_id: AtomicU64::new(0),
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            state_path,
+            dirty: AtomicBool::new(false),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Deliver through another transport
+    pub fn with_transport(mut self, transport: Arc<dyn MailTransport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Handle a site's guests send mail with
+    pub fn guest(self: &Arc<Self>, tenant_id: &str, site_id: &str) -> GuestMailer {
+        GuestMailer {
+            relay: self.clone(),
+            tenant_id: tenant_id.to_string(),
+            site_id: site_id.to_string(),
+        }
+    }
+
+    /// Set or clear the daily limit from a tenant's quota
+    pub fn set_tenant_quota(&self, tenant_id: &str, max_per_day: Option<u64>) {
+        match max_per_day {
+            Some(limit) => { self.quotas.insert(tenant_id.to_string(), limit); }
+            None => { self.quotas.remove(tenant_id); }
+        }
+    }
+
+    /// Check a message against the tenant's limits and queue it, returning its id
+    pub fn submit(&self, tenant_id: &str, site_id: &str, mut email: Email) -> Result<u64> {
+        let from = self.check_message(tenant_id, &email)?;
+
+        let mut state = self.state.lock();
+        let policy = state.policies.get(tenant_id).cloned().unwrap_or_default();
+        if policy.suspended {
+            bail!("Outbound mail is suspended for this tenant");
+        }
+
+        let suppressed = state.suppressions.get(tenant_id);
+        let dropped: Vec<String> = email.to.iter()
+            .filter(|to| suppressed.is_some_and(|list| list.contains(&to.to_lowercase())))
+            .cloned()
+            .collect();
+        email.to.retain(|to| !dropped.contains(to));
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        if email.to.is_empty() {
+            self.record(tenant_id, DeliveryRecord::new(id, site_id, &from, &email, DeliveryStatus::Suppressed, dropped));
+            bail!("Every recipient is on the suppression list");
+        }
+
+        let max_per_minute = policy.max_per_minute.unwrap_or(self.config.max_per_minute);
+        let mut window = self.windows.entry(tenant_id.to_string()).or_insert((Instant::now(), 0));
+        if window.0.elapsed() >= Duration::from_secs(60) {
+            *window = (Instant::now(), 0);
+        }
+        if window.1 >= max_per_minute {
+            bail!("Mail rate limit of {} messages per minute reached", max_per_minute);
+        }
+
+        let daily_quota = self.daily_quota(tenant_id, &policy);
+        let today = today();
+        let usage = state.usage.entry(tenant_id.to_string()).or_default();
+        if usage.day != today {
+            *usage = DailyUsage { day: today, sent: 0 };
+        }
+        if usage.sent + email.to.len() as u64 > daily_quota {
+            bail!("Daily mail quota of {} recipients reached", daily_quota);
+        }
+
+        let outgoing = Outgoing { id, tenant_id: tenant_id.to_string(), from: from.clone(), email: email.clone() };
+        if self.sender.try_send(outgoing).is_err() {
+            bail!("Mail queue is full");
+        }
+
+        window.1 += 1;
+        usage.sent += email.to.len() as u64;
+        self.dirty.store(true, Ordering::Relaxed);
+        drop(window);
+        drop(state);
+
+        self.record(tenant_id, DeliveryRecord::new(id, site_id, &from, &email, DeliveryStatus::Queued, dropped));
+        debug!(tenant_id = %tenant_id, site_id = %site_id, message_id = id, "Mail queued");
+        Ok(id)
+    }
+
+    /// Validate addresses and size, returning the sender to use
+    fn check_message(&self, tenant_id: &str, email: &Email) -> Result<String> {
+        if email.to.is_empty() {
+            bail!("Message has no recipients");
+        }
+        if email.to.len() > self.config.max_recipients {
+            bail!("Message has {} recipients, the limit is {}", email.to.len(), self.config.max_recipients);
+        }
+        for address in email.to.iter().chain(&email.reply_to) {
+            validate_address(address)?;
+        }
+        if email.subject.contains(['\r', '\n']) {
+            bail!("Subject must be a single line");
+        }
+        let size = email.subject.len() + email.text.len() + email.html.as_ref().map_or(0, |html| html.len());
+        if size > self.config.max_message_bytes {
+            bail!("Message of {} bytes exceeds the {} byte limit", size, self.config.max_message_bytes);
+        }
+
+        let Some(from) = &email.from else {
+            return Ok(self.config.default_from.clone());
+        };
+        validate_address(from)?;
+        let domain = domain_of(from);
+        let allowed = self.state.lock().policies.get(tenant_id)
+            .is_some_and(|policy| policy.from_domains.iter().any(|d| d.eq_ignore_ascii_case(domain)));
+        if !allowed && !from.eq_ignore_ascii_case(&self.config.default_from) {
+            bail!("Tenant may not send from {}", domain);
+        }
+        Ok(from.clone())
+    }
+
+    fn daily_quota(&self, tenant_id: &str, policy: &TenantMailPolicy) -> u64 {
+        policy.daily_quota
+            .or_else(|| self.quotas.get(tenant_id).map(|limit| *limit))
+            .unwrap_or(self.config.daily_quota)
+    }
+
+    /// Deliver one queued message and log the outcome
+    async fn deliver(&self, outgoing: Outgoing) {
+        let Outgoing { id, tenant_id, from, email } = outgoing;
+        let message_id = format!("<{}.{}@{}>", id, uuid::Uuid::new_v4().simple(), domain_of(&from));
+
+        let result = match tokio::time::timeout(SEND_TIMEOUT, self.transport.send(&from, &email, &message_id)).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("Timed out after {}s", SEND_TIMEOUT.as_secs())),
+        };
+
+        let mut permanent = false;
+        let (status, refused, error) = match result {
+            Ok(refused) if refused.len() == email.to.len() => {
+                permanent = true;
+                (DeliveryStatus::Rejected, refused, None)
+            }
+            Ok(refused) => (DeliveryStatus::Sent, refused, None),
+            Err(e) => {
+                permanent = is_permanent(&e);
+                (DeliveryStatus::Failed, Vec::new(), Some(format!("{:#}", e)))
+            }
+        };
+
+        if !refused.is_empty() {
+            let mut state = self.state.lock();
+            let list = state.suppressions.entry(tenant_id.clone()).or_default();
+            list.extend(refused.iter().map(|address| address.to_lowercase()));
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+
+        if permanent {
+            let failures = {
+                let mut count = self.failures.entry(tenant_id.clone()).or_insert(0);
+                *count += 1;
+                *count
+            };
+            if self.config.suspend_after_failures > 0 && failures >= self.config.suspend_after_failures {
+                let reason = format!("{} consecutive messages were refused", failures);
+                error!(tenant_id = %tenant_id, failures, "Suspending outbound mail for tenant");
+                self.suspend(&tenant_id, &reason);
+            }
+        } else if status == DeliveryStatus::Sent {
+            self.failures.remove(&tenant_id);
+        }
+
+        match &error {
+            Some(e) => warn!(tenant_id = %tenant_id, message_id = id, error = %e, "Mail delivery failed"),
+            None => debug!(tenant_id = %tenant_id, message_id = id, status = ?status, "Mail delivered"),
+        }
+
+        if let Some(mut log) = self.logs.get_mut(&tenant_id) {
+            if let Some(record) = log.iter_mut().rev().find(|record| record.id == id) {
+                record.status = status;
+                record.dropped.extend(refused);
+                record.error = error;
+                record.updated_at = chrono::Utc::now().timestamp();
+            }
+        }
+    }
+
+    fn record(&self, tenant_id: &str, record: DeliveryRecord) {
+        let mut log = self.logs.entry(tenant_id.to_string()).or_default();
+        log.push_back(record);
+        while log.len() > self.config.log_capacity {
+            log.pop_front();
+        }
+    }
+
+    /// A tenant's delivery log, newest first
+    pub fn log(&self, tenant_id: &str, limit: usize) -> Vec<DeliveryRecord> {
+        self.logs.get(tenant_id)
+            .map(|log| log.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// A tenant's policy and today's usage
+    pub fn status(&self, tenant_id: &str) -> TenantMailStatus {
+        let state = self.state.lock();
+        let policy = state.policies.get(tenant_id).cloned().unwrap_or_default();
+        let today = today();
+
+        TenantMailStatus {
+            daily_quota: self.daily_quota(tenant_id, &policy),
+            sent_today: state.usage.get(tenant_id).filter(|usage| usage.day == today).map_or(0, |usage| usage.sent),
+            suppressed: state.suppressions.get(tenant_id).map_or(0, |list| list.len()),
+            consecutive_failures: self.failures.get(tenant_id).map_or(0, |count| *count),
+            policy,
+        }
+    }
+
+    /// Replace a tenant's policy; lifting a suspension clears its failure count
+    pub fn set_policy(&self, tenant_id: &str, policy: TenantMailPolicy) -> Result<()> {
+        for domain in &policy.from_domains {
+            if domain.is_empty() || domain.contains(['@', ' ']) {
+                bail!("Invalid sender domain: {:?}", domain);
+            }
+        }
+        if !policy.suspended {
+            self.failures.remove(tenant_id);
+        }
+        self.state.lock().policies.insert(tenant_id.to_string(), policy);
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn suspend(&self, tenant_id: &str, reason: &str) {
+        let mut state = self.state.lock();
+        let policy = state.policies.entry(tenant_id.to_string()).or_default();
+        policy.suspended = true;
+        policy.suspended_reason = Some(reason.to_string());
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Addresses a tenant won't send to, sorted
+    pub fn suppressions(&self, tenant_id: &str) -> Vec<String> {
+        self.state.lock().suppressions.get(tenant_id)
+            .map(|list| list.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Stop sending to an address
+    pub fn suppress(&self, tenant_id: &str, address: &str) -> Result<()> {
+        validate_address(address)?;
+        self.state.lock().suppressions.entry(tenant_id.to_string()).or_default().insert(address.to_lowercase());
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Allow sending to an address again, returning whether it was suppressed
+    pub fn unsuppress(&self, tenant_id: &str, address: &str) -> bool {
+        let mut state = self.state.lock();
+        let removed = state.suppressions.get_mut(tenant_id)
+            .is_some_and(|list| list.remove(&address.to_lowercase()));
+        if removed {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// Persist policies, suppressions and usage if anything changed
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+
+        let _guard = self.save_lock.lock();
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let json = serde_json::to_vec(&*self.state.lock())?;
+        let result = save_state(path, &json);
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Deliver queued messages and persist changes in the background
+    pub fn start(self: &Arc<Self>) {
+        let Some(mut receiver) = self.receiver.lock().take() else {
+            return;
+        };
+
+        let relay = self.clone();
+        tokio::spawn(async move {
+            while let Some(outgoing) = receiver.recv().await {
+                relay.deliver(outgoing).await;
+            }
+        });
+
+        let relay = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                if relay.dirty.load(Ordering::Relaxed) {
+                    let relay = relay.clone();
+                    match tokio::task::spawn_blocking(move || relay.save()).await {
+                        Ok(Err(e)) => error!(error = %e, "Failed to persist mail state"),
+                        Err(e) => error!(error = %e, "Mail state save task failed"),
+                        Ok(Ok(())) => {}
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// A site's mail relay as seen by its Cages
+#[derive(Clone)]
+pub struct GuestMailer {
+    relay: Arc<MailRelay>,
+    tenant_id: String,
+    site_id: String,
+}
+
+impl GuestMailer {
+    /// Queue a JSON-encoded `Email`, returning its id
+    pub fn send(&self, json: &[u8]) -> Result<u64> {
+        let email: Email = serde_json::from_slice(json).context("Invalid email JSON")?;
+        self.relay.submit(&self.tenant_id, &self.site_id, email)
+    }
+
+    pub fn site_id(&self) -> &str {
+        &self.site_id
+    }
+}
+
+impl std::fmt::Debug for GuestMailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuestMailer")
+            .field("tenant_id", &self.tenant_id)
+            .field("site_id", &self.site_id)
+            .finish()
+    }
+}
+
+/// Format a message for SMTP, with an HTML alternative when present
+pub fn format_message(from: &str, email: &Email, message_id: &str) -> String {
+    let mut headers = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: {}\r\nMIME-Version: 1.0\r\n",
+        from,
+        email.to.join(", "),
+        smtp::encode_header(&email.subject),
+        chrono::Utc::now().to_rfc2822(),
+        message_id,
+    );
+    if let Some(reply_to) = &email.reply_to {
+        headers.push_str(&format!("Reply-To: {}\r\n", reply_to));
+    }
+
+    let Some(html) = &email.html else {
+        return format!("{}Content-Type: text/plain; charset=utf-8\r\n\r\n{}", headers, email.text);
+    };
+
+    let boundary = format!("pear-{}", uuid::Uuid::new_v4().simple());
+    format!(
+        "{headers}Content-Type: multipart/alternative; boundary=\"{b}\"\r\n\r\n\
+         --{b}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{text}\r\n\
+         --{b}\r\nContent-Type: text/html; charset=utf-8\r\n\r\n{html}\r\n\
+         --{b}--",
+        headers = headers,
+        b = boundary,
+        text = email.text,
+        html = html,
+    )
+}
+
+/// Check an address is a bare `local@domain` that can't inject headers or commands
+pub fn validate_address(address: &str) -> Result<()> {
+    let valid = address.len() <= 254
+        && address.split('@').count() == 2
+        && !address.starts_with('@')
+        && !address.ends_with('@')
+        && address.bytes().all(|b| b.is_ascii_graphic() && !matches!(b, b'<' | b'>' | b',' | b';' | b'"'));
+    if !valid {
+        bail!("Invalid email address: {:?}", address);
+    }
+    Ok(())
+}
+
+/// Whether a failure won't go away on retry
+fn is_permanent(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.downcast_ref::<SmtpReply>().is_some_and(SmtpReply::is_permanent))
+}
+
+fn domain_of(address: &str) -> &str {
+    address.rsplit('@').next().unwrap_or(address)
+}
+
+fn today() -> String {
+    chrono::Utc::now().date_naive().to_string()
+}
+
+fn load_state(path: &Path) -> Result<MailState> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_state(path: &Path, json: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, json)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Refuses recipients at bounce.example and counts what it sends
+    #[derive(Default)]
+    struct FakeTransport {
+        sent: Mutex<Vec<(String, Email)>>,
+    }
+
+    #[async_trait]
+    impl MailTransport for FakeTransport {
+        async fn send(&self, from: &str, email: &Email, _message_id: &str) -> Result<Vec<String>> {
+            self.sent.lock().push((from.to_string(), email.clone()));
+            Ok(email.to.iter().filter(|to| to.ends_with("@bounce.example")).cloned().collect())
+        }
+    }
+
+    fn relay(config: MailConfig) -> (Arc<MailRelay>, Arc<FakeTransport>) {
+        let transport = Arc::new(FakeTransport::default());
+        let config = MailConfig { enabled: true, state_path: String::new(), ..config };
+        let relay = MailRelay::new(config).unwrap().with_transport(transport.clone());
+        (Arc::new(relay), transport)
+    }
+
+    fn email(to: &[&str]) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "to": to,
+            "subject": "Welcome",
+            "text": "Hello!",
+        })).unwrap()
+    }
+
+    async fn settle(relay: &MailRelay, tenant_id: &str) {
+        for _ in 0..100 {
+            if relay.log(tenant_id, usize::MAX).iter().all(|r| r.status != DeliveryStatus::Queued) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("mail was not delivered");
+    }
+
+    #[tokio::test]
+    async fn test_delivery_suppresses_refused_recipients() {
+        let (relay, transport) = relay(MailConfig::default());
+        relay.start();
+        let mailer = relay.guest("acme", "blog");
+
+        mailer.send(&email(&["ann@example.com", "gone@bounce.example"])).unwrap();
+        settle(&relay, "acme").await;
+
+        let log = relay.log("acme", 10);
+        assert_eq!(log[0].status, DeliveryStatus::Sent);
+        assert_eq!(log[0].dropped, ["gone@bounce.example"]);
+        assert_eq!(transport.sent.lock()[0].0, "noreply@localhost");
+        assert_eq!(relay.suppressions("acme"), ["gone@bounce.example"]);
+
+        // Suppressed recipients are dropped before they reach the transport
+        assert!(mailer.send(&email(&["Gone@bounce.example"])).is_err());
+        assert_eq!(relay.log("acme", 1)[0].status, DeliveryStatus::Suppressed);
+        assert!(relay.unsuppress("acme", "gone@bounce.example"));
+        assert!(relay.suppressions("bob").is_empty());
+    }
+
+    #[test]
+    fn test_quotas_and_sender_domains() {
+        let (relay, _) = relay(MailConfig { max_per_minute: 2, ..Default::default() });
+        let mailer = relay.guest("acme", "blog");
+
+        relay.set_tenant_quota("acme", Some(3));
+        mailer.send(&email(&["a@example.com", "b@example.com"])).unwrap();
+        assert!(mailer.send(&email(&["c@example.com", "d@example.com"])).is_err());
+        mailer.send(&email(&["c@example.com"])).unwrap();
+        assert_eq!(relay.status("acme").sent_today, 3);
+
+        // Two messages went through this minute
+        relay.set_tenant_quota("acme", None);
+        assert!(mailer.send(&email(&["e@example.com"])).unwrap_err().to_string().contains("rate limit"));
+
+        let spoofed = serde_json::json!({ "from": "ceo@bank.example", "to": ["x@example.com"], "subject": "Hi" });
+        let other = relay.guest("bob", "shop");
+        assert!(other.send(&serde_json::to_vec(&spoofed).unwrap()).unwrap_err().to_string().contains("may not send"));
+        relay.set_policy("bob", TenantMailPolicy { from_domains: vec!["bank.example".into()], ..Default::default() }).unwrap();
+        other.send(&serde_json::to_vec(&spoofed).unwrap()).unwrap();
+
+        assert!(other.send(&email(&["x@example.com>\r\nRCPT TO:<y@example.com"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_repeated_rejections_suspend_tenant() {
+        let (relay, _) = relay(MailConfig { suspend_after_failures: 2, ..Default::default() });
+        relay.start();
+        let mailer = relay.guest("acme", "blog");
+
+        mailer.send(&email(&["a@bounce.example"])).unwrap();
+        mailer.send(&email(&["b@bounce.example"])).unwrap();
+        settle(&relay, "acme").await;
+
+        assert_eq!(relay.log("acme", 1)[0].status, DeliveryStatus::Rejected);
+        let status = relay.status("acme");
+        assert!(status.policy.suspended);
+        assert!(mailer.send(&email(&["c@example.com"])).unwrap_err().to_string().contains("suspended"));
+
+        relay.set_policy("acme", TenantMailPolicy::default()).unwrap();
+        mailer.send(&email(&["c@example.com"])).unwrap();
+    }
+
+    #[test]
+    fn test_format_message() {
+        let email = Email {
+            from: None,
+            to: vec!["ann@example.com".into()],
+            reply_to: Some("help@example.com".into()),
+            subject: "Bienvenue à bord".into(),
+            text: "Hi".into(),
+            html: Some("<p>Hi</p>".into()),
+        };
+        let message = format_message("app@example.com", &email, "<1@example.com>");
+        assert!(message.contains("Subject: =?utf-8?B?"));
+        assert!(message.contains("Reply-To: help@example.com\r\n"));
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.contains("Content-Type: text/html; charset=utf-8\r\n\r\n<p>Hi</p>"));
+    }
+}
@@ -0,0 +1,270 @@
+// SMTP Client
+// Minimal SMTP submission with STARTTLS, implicit TLS and AUTH PLAIN
+
+use anyhow::{Context, Result, bail};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::ai::alerts::tls_connector;
+
+/// How the connection to the relay is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain text, for a relay on localhost
+    #[default]
+    None,
+
+    /// Upgrade with STARTTLS (usually port 587)
+    StartTls,
+
+    /// TLS from the first byte (usually port 465)
+    Tls,
+}
+
+/// An SMTP server to hand messages to
+#[derive(Debug, Clone)]
+pub struct SmtpRelay {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+
+    /// Username and password for AUTH PLAIN
+    pub credentials: Option<(String, String)>,
+}
+
+/// A reply with an unexpected code
+#[derive(Debug)]
+pub struct SmtpReply {
+    pub code: u16,
+    pub text: String,
+}
+
+impl SmtpReply {
+    /// 5xx replies won't succeed on retry
+    pub fn is_permanent(&self) -> bool {
+        self.code >= 500
+    }
+}
+
+impl std::fmt::Display for SmtpReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SMTP relay replied {} {}", self.code, self.text)
+    }
+}
+
+impl std::error::Error for SmtpReply {}
+
+impl SmtpRelay {
+    /// A plain relay without authentication
+    pub fn plain(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            security: SmtpSecurity::None,
+            credentials: None,
+        }
+    }
+
+    /// Send a formatted message, returning recipients the relay refused permanently
+    /// Nothing is sent when every recipient is refused.
+    pub async fn send(&self, from: &str, to: &[String], message: &str) -> Result<Vec<String>> {
+        if to.is_empty() {
+            bail!("Message has no recipients");
+        }
+
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await
+            .with_context(|| format!("Failed to connect to SMTP relay {}:{}", self.host, self.port))?;
+
+        match self.security {
+            SmtpSecurity::Tls => {
+                let stream = tls_connector().connect(self.server_name()?, stream).await?;
+                let mut stream = BufReader::new(stream);
+                expect_reply(&mut stream, 220).await?;
+                command(&mut stream, "EHLO pear-server", 250).await?;
+                self.transaction(&mut stream, from, to, message).await
+            }
+            SmtpSecurity::StartTls => {
+                let mut stream = BufReader::new(stream);
+                expect_reply(&mut stream, 220).await?;
+                command(&mut stream, "EHLO pear-server", 250).await?;
+                command(&mut stream, "STARTTLS", 220).await?;
+
+                let stream = tls_connector().connect(self.server_name()?, stream.into_inner()).await?;
+                let mut stream = BufReader::new(stream);
+                command(&mut stream, "EHLO pear-server", 250).await?;
+                self.transaction(&mut stream, from, to, message).await
+            }
+            SmtpSecurity::None => {
+                let mut stream = BufReader::new(stream);
+                expect_reply(&mut stream, 220).await?;
+                command(&mut stream, "EHLO pear-server", 250).await?;
+                self.transaction(&mut stream, from, to, message).await
+            }
+        }
+    }
+
+    /// Authenticate and submit one message on an established session
+    async fn transaction<S>(&self, stream: &mut BufReader<S>, from: &str, to: &[String], message: &str) -> Result<Vec<String>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if let Some((username, password)) = &self.credentials {
+            let token = STANDARD.encode(format!("\0{}\0{}", username, password));
+            command(stream, &format!("AUTH PLAIN {}", token), 235).await
+                .context("SMTP authentication failed")?;
+        }
+
+        command(stream, &format!("MAIL FROM:<{}>", from), 250).await?;
+
+        let mut refused = Vec::new();
+        for recipient in to {
+            match command(stream, &format!("RCPT TO:<{}>", recipient), 250).await {
+                Ok(()) => {}
+                Err(e) if e.downcast_ref::<SmtpReply>().is_some_and(SmtpReply::is_permanent) => {
+                    refused.push(recipient.clone());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if refused.len() == to.len() {
+            let _ = command(stream, "QUIT", 221).await;
+            return Ok(refused);
+        }
+
+        command(stream, "DATA", 354).await?;
+        stream.get_mut().write_all(dot_stuff(message).as_bytes()).await?;
+        stream.get_mut().write_all(b"\r\n").await?;
+        command(stream, ".", 250).await?;
+
+        // The message is accepted at this point; a failed QUIT doesn't matter
+        let _ = command(stream, "QUIT", 221).await;
+        Ok(refused)
+    }
+
+    fn server_name(&self) -> Result<rustls::pki_types::ServerName<'static>> {
+        rustls::pki_types::ServerName::try_from(self.host.clone()).context("Invalid TLS server name")
+    }
+}
+
+/// Send one SMTP command and check the reply code
+async fn command<S>(stream: &mut BufReader<S>, line: &str, expected: u16) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let writer = stream.get_mut();
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\r\n").await?;
+    writer.flush().await?;
+    expect_reply(stream, expected).await
+}
+
+/// Read a (possibly multi-line) SMTP reply and check its code
+async fn expect_reply<R: AsyncBufReadExt + Unpin>(reader: &mut R, expected: u16) -> Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("SMTP relay closed the connection");
+        }
+
+        let code: u16 = line.get(..3).and_then(|c| c.parse().ok())
+            .with_context(|| format!("Malformed SMTP reply: {}", line.trim_end()))?;
+
+        // "250-..." continues a multi-line reply, "250 ..." ends it
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+
+        if code != expected {
+            return Err(SmtpReply { code, text: line.get(4..).unwrap_or_default().trim_end().to_string() }.into());
+        }
+        return Ok(());
+    }
+}
+
+/// Escape lines starting with '.' and normalize line endings for the DATA section
+pub fn dot_stuff(body: &str) -> String {
+    body.lines()
+        .map(|line| if line.starts_with('.') { format!(".{}", line) } else { line.to_string() })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+/// Encode a header value as RFC 2047 UTF-8 when it isn't plain ASCII
+pub fn encode_header(value: &str) -> String {
+    if value.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        value.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", STANDARD.encode(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(encode_header("Hello"), "Hello");
+        assert_eq!(encode_header("Grüße"), "=?utf-8?B?R3LDvMOfZQ==?=");
+    }
+
+    #[tokio::test]
+    async fn test_send_skips_refused_recipients() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Scripted relay: refuses bounced@example.com, records the DATA section
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"220 test\r\n").await.unwrap();
+
+            let mut data = String::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    return data;
+                }
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        data.push_str(&line);
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-test\r\n250 AUTH PLAIN\r\n"
+                } else if line.starts_with("AUTH PLAIN") {
+                    b"235 ok\r\n"
+                } else if line.contains("bounced@") {
+                    b"550 no such user\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go\r\n"
+                } else if line.starts_with("QUIT") {
+                    b"221 bye\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                stream.get_mut().write_all(reply).await.unwrap();
+            }
+        });
+
+        let relay = SmtpRelay {
+            credentials: Some(("user".into(), "secret".into())),
+            ..SmtpRelay::plain("127.0.0.1", port)
+        };
+        let to = vec!["ok@example.com".to_string(), "bounced@example.com".to_string()];
+        let refused = relay.send("app@example.com", &to, "Subject: hi\r\n\r\n.dot").await.unwrap();
+        assert_eq!(refused, ["bounced@example.com"]);
+        assert!(server.await.unwrap().ends_with("..dot\r\n"));
+
+        assert!(relay.send("app@example.com", &[], "x").await.is_err());
+    }
+}
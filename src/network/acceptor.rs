@@ -0,0 +1,290 @@
+// Acceptor sharding for SO_REUSEPORT listeners
+// Binds one socket per shard so the kernel load-balances new connections across accept loops
+
+use crate::network::NetworkConfig;
+use anyhow::{Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::info;
+
+/// Per-shard accept counters
+struct ShardCounters {
+    accepted: AtomicU64,
+    accept_errors: AtomicU64,
+    rejected: AtomicU64,
+    streams_rejected: AtomicU64,
+}
+
+/// Accept metrics for a set of listener shards
+pub struct AcceptorMetrics {
+    shards: Vec<ShardCounters>,
+    
+    /// Connections currently being served (used to drain on shutdown)
+    active: AtomicU64,
+}
+
+impl AcceptorMetrics {
+    /// Create metrics for the given number of shards
+    pub fn new(shard_count: usize) -> Self {
+        let shards = (0..shard_count.max(1))
+            .map(|_| ShardCounters {
+                accepted: AtomicU64::new(0),
+                accept_errors: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+                streams_rejected: AtomicU64::new(0),
+            })
+            .collect();
+
+        Self {
+            shards,
+            active: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a successfully accepted connection on a shard
+    pub fn record_accept(&self, shard: usize) {
+        if let Some(counters) = self.shards.get(shard) {
+            counters.accepted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a failed accept on a shard
+    pub fn record_error(&self, shard: usize) {
+        if let Some(counters) = self.shards.get(shard) {
+            counters.accept_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a connection refused by a per-IP or accept queue limit
+    pub fn record_rejected(&self, shard: usize) {
+        if let Some(counters) = self.shards.get(shard) {
+            counters.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a stream refused because the listener had as many running as allowed
+    pub fn record_stream_rejected(&self, shard: usize) {
+        if let Some(counters) = self.shards.get(shard) {
+            counters.streams_rejected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that a connection started being served
+    pub fn connection_opened(&self) {
+        self.active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a connection finished
+    pub fn connection_closed(&self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Connections currently being served
+    pub fn active_connections(&self) -> u64 {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Number of shards tracked
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Total accepted connections across all shards
+    pub fn total_accepted(&self) -> u64 {
+        self.shards.iter()
+            .map(|s| s.accepted.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Get per-shard statistics
+    pub fn snapshot(&self) -> Vec<ShardStats> {
+        self.shards.iter()
+            .enumerate()
+            .map(|(shard, counters)| ShardStats {
+                shard,
+                accepted: counters.accepted.load(Ordering::Relaxed),
+                accept_errors: counters.accept_errors.load(Ordering::Relaxed),
+                rejected: counters.rejected.load(Ordering::Relaxed),
+                streams_rejected: counters.streams_rejected.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+/// Accept statistics for a single shard
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct ShardStats {
+    pub shard: usize,
+    pub accepted: u64,
+    pub accept_errors: u64,
+    pub rejected: u64,
+    pub streams_rejected: u64,
+}
+
+/// Bind one TCP listener per acceptor shard
+/// All shards share the same address via SO_REUSEPORT
+pub fn bind_tcp_shards(addr: &SocketAddr, config: &NetworkConfig) -> Result<Vec<tokio::net::TcpListener>> {
+    bind_std_tcp_shards(addr, config)?
+        .into_iter()
+        .map(|listener| Ok(tokio::net::TcpListener::from_std(listener)?))
+        .collect()
+}
+
+/// Bind one non-blocking std TCP listener per acceptor shard
+/// Used by backends that register the listener with their own runtime
+/// Listeners inherited from a previous process are reused instead of rebinding
+pub fn bind_std_tcp_shards(addr: &SocketAddr, config: &NetworkConfig) -> Result<Vec<std::net::TcpListener>> {
+    let mut listeners = crate::upgrade::inherited_tcp_listeners(addr);
+
+    if listeners.is_empty() {
+        let shard_count = config.effective_acceptor_shards();
+
+        for shard in 0..shard_count {
+            let socket = super::http2::create_optimized_socket(addr, config)
+                .with_context(|| format!("Failed to bind TCP acceptor shard {}", shard))?;
+            steer_shard(&socket, shard, config)?;
+            listeners.push(socket.into());
+        }
+
+        info!(addr = %addr, shards = shard_count, "TCP acceptor shards bound");
+    } else {
+        info!(addr = %addr, shards = listeners.len(), "TCP acceptor shards inherited");
+    }
+
+    for listener in &listeners {
+        listener.set_nonblocking(true)?;
+        crate::upgrade::register_listener(crate::upgrade::tcp_name(addr), listener.as_raw_fd());
+    }
+
+    Ok(listeners)
+}
+
+/// Bind one UDP socket per acceptor shard for QUIC endpoints
+/// Sockets inherited from a previous process are reused instead of rebinding
+pub fn bind_udp_shards(addr: &SocketAddr, config: &NetworkConfig) -> Result<Vec<std::net::UdpSocket>> {
+    let mut sockets = crate::upgrade::inherited_udp_sockets(addr);
+
+    if sockets.is_empty() {
+        let shard_count = config.effective_http3_shards();
+
+        for shard in 0..shard_count {
+            let socket = create_udp_socket(addr, config)
+                .with_context(|| format!("Failed to bind UDP acceptor shard {}", shard))?;
+            steer_shard(&socket, shard, config)?;
+            sockets.push(socket.into());
+        }
+
+        info!(addr = %addr, shards = shard_count, "UDP acceptor shards bound");
+    } else {
+        info!(addr = %addr, shards = sockets.len(), "UDP acceptor shards inherited");
+    }
+
+    for socket in &sockets {
+        socket.set_nonblocking(true)?;
+        crate::upgrade::register_listener(crate::upgrade::udp_name(addr), socket.as_raw_fd());
+    }
+
+    Ok(sockets)
+}
+
+/// Have the kernel hand a shard the connections whose packets are processed on its core
+/// Keeps each connection's traffic on the core its worker is pinned to.
+#[cfg(target_os = "linux")]
+fn steer_shard(socket: &Socket, shard: usize, config: &NetworkConfig) -> Result<()> {
+    if let Some(&cpu) = config.acceptor_cpus.get(shard % config.acceptor_cpus.len().max(1)) {
+        socket.set_cpu_affinity(cpu)
+            .with_context(|| format!("Failed to steer acceptor shard {} to CPU {}", shard, cpu))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn steer_shard(_socket: &Socket, _shard: usize, _config: &NetworkConfig) -> Result<()> {
+    Ok(())
+}
+
+/// Create a UDP socket suitable for sharing between QUIC endpoints
+fn create_udp_socket(addr: &SocketAddr, config: &NetworkConfig) -> Result<Socket> {
+    let domain = if addr.is_ipv4() {
+        Domain::IPV4
+    } else {
+        Domain::IPV6
+    };
+
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(config.ipv6_only)?;
+    }
+
+    if config.so_reuseaddr {
+        socket.set_reuse_address(true)?;
+    }
+
+    #[cfg(unix)]
+    if config.so_reuseport {
+        socket.set_reuse_port(true)?;
+    }
+
+    socket.set_send_buffer_size(config.tcp_send_buffer_size)?;
+    socket.set_recv_buffer_size(config.tcp_recv_buffer_size)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&(*addr).into())?;
+
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_per_shard() {
+        let metrics = AcceptorMetrics::new(4);
+        metrics.record_accept(0);
+        metrics.record_accept(0);
+        metrics.record_accept(3);
+        metrics.record_error(1);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 4);
+        assert_eq!(snapshot[0].accepted, 2);
+        assert_eq!(snapshot[1].accept_errors, 1);
+        assert_eq!(metrics.total_accepted(), 3);
+    }
+
+    #[test]
+    fn test_active_connection_gauge() {
+        let metrics = AcceptorMetrics::new(1);
+        metrics.connection_opened();
+        metrics.connection_opened();
+        metrics.connection_closed();
+        assert_eq!(metrics.active_connections(), 1);
+    }
+
+    #[test]
+    fn test_metrics_ignore_unknown_shard() {
+        let metrics = AcceptorMetrics::new(1);
+        metrics.record_accept(7);
+        assert_eq!(metrics.total_accepted(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_bind_tcp_shards() {
+        let config = NetworkConfig {
+            acceptor_shards: 2,
+            ..Default::default()
+        };
+
+        // Every shard binds the same free port, which only SO_REUSEPORT allows
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let listeners = bind_tcp_shards(&addr, &config).unwrap();
+        assert_eq!(listeners.len(), 2);
+        for listener in &listeners {
+            assert_eq!(listener.local_addr().unwrap(), addr);
+        }
+    }
+}
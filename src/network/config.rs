@@ -1,7 +1,104 @@
 // Network configuration
 // Defines ports, buffer sizes, and protocol-specific settings
 
-use std::net::SocketAddr;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+
+/// I/O backend used for the HTTP/2 listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IoBackend {
+    /// Readiness-based I/O through the standard Tokio reactor
+    #[default]
+    Epoll,
+    /// Completion-based I/O through io_uring (requires the `io-uring` feature)
+    Uring,
+}
+
+/// Protocol served by a listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerProtocol {
+    /// HTTP/2 over TCP, routed through the Router
+    #[default]
+    Http2,
+    /// HTTP/3 over QUIC (UDP), always with TLS
+    Http3,
+}
+
+/// One `[[server.listeners]]` entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    /// Label used in logs
+    #[serde(default)]
+    pub name: Option<String>,
+    
+    /// IPv4 or IPv6 address to bind, e.g. "127.0.0.1" for an internal-only listener
+    /// "::" accepts both IPv4 and IPv6 clients unless `ipv6_only` is set
+    #[serde(default = "default_listener_address")]
+    pub address: String,
+    
+    /// Accept only IPv6 clients on an IPv6 address, so a separate IPv4 listener can share the port
+    #[serde(default)]
+    pub ipv6_only: bool,
+    
+    pub port: u16,
+    
+    #[serde(default)]
+    pub protocol: ListenerProtocol,
+    
+    /// Terminate TLS with the `[ssl]` certificate
+    #[serde(default)]
+    pub tls: bool,
+    
+    /// Expect a PROXY protocol (v1 or v2) header from a load balancer ahead of every connection
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    
+    /// Redirect every request to HTTPS instead of routing it
+    #[serde(default)]
+    pub redirect_https: bool,
+    
+    /// Port named in redirect locations
+    #[serde(default = "default_https_port")]
+    pub https_port: u16,
+}
+
+fn default_listener_address() -> String { "0.0.0.0".to_string() }
+fn default_https_port() -> u16 { 443 }
+
+impl ListenerConfig {
+    /// Address to bind
+    pub fn socket_addr(&self) -> Result<SocketAddr> {
+        let ip: IpAddr = self.address.parse()
+            .with_context(|| format!("Invalid listener address: {}", self.address))?;
+        Ok(SocketAddr::new(ip, self.port))
+    }
+    
+    /// Name for logs, defaulting to the protocol and address
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{:?} {}:{}", self.protocol, self.address, self.port).to_lowercase(),
+        }
+    }
+}
+
+/// Whether two sockets on the same transport would claim the same port
+/// A wildcard overlaps every address of its family, and a dual-stack "::" every IPv4 address too.
+pub fn sockets_overlap(addr: SocketAddr, ipv6_only: bool, other: SocketAddr, other_ipv6_only: bool) -> bool {
+    if addr.port() != other.port() {
+        return false;
+    }
+    match (addr.ip(), other.ip()) {
+        (a, b) if a == b => true,
+        (IpAddr::V4(a), IpAddr::V4(b)) => a.is_unspecified() || b.is_unspecified(),
+        (IpAddr::V6(a), IpAddr::V6(b)) => a.is_unspecified() || b.is_unspecified(),
+        (IpAddr::V6(a), IpAddr::V4(_)) => a.is_unspecified() && !ipv6_only,
+        (IpAddr::V4(_), IpAddr::V6(b)) => b.is_unspecified() && !other_ipv6_only,
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
@@ -11,9 +108,12 @@ pub struct NetworkConfig {
     /// Port for HTTP/3 over QUIC (default: 8443 for dev, 443 for production)
     pub http3_port: u16,
     
-    /// Bind address (typically 0.0.0.0 to listen on all interfaces)
+    /// Bind address (typically 0.0.0.0 to listen on all interfaces, or :: for IPv4 and IPv6)
     pub bind_addr: String,
     
+    /// Set IPV6_V6ONLY on IPv6 sockets; otherwise they also accept IPv4 clients as mapped addresses
+    pub ipv6_only: bool,
+    
     /// TCP socket send buffer size (2MB for high throughput)
     pub tcp_send_buffer_size: usize,
     
@@ -26,6 +126,9 @@ pub struct NetworkConfig {
     /// Maximum concurrent HTTP/3 streams per connection
     pub http3_max_concurrent_streams: u64,
     
+    /// HTTP/3 streams running at once across a listener's connections (0 = unlimited)
+    pub http3_max_total_streams: usize,
+    
     /// QUIC idle timeout in milliseconds
     pub quic_idle_timeout_ms: u64,
     
@@ -37,6 +140,40 @@ pub struct NetworkConfig {
     
     /// Enable SO_REUSEPORT
     pub so_reuseport: bool,
+    
+    /// Number of listener sockets (and accept loops) per protocol
+    /// Only takes effect when SO_REUSEPORT is enabled
+    pub acceptor_shards: usize,
+    
+    /// UDP sockets (and QUIC endpoints) per HTTP/3 listener (0 = `acceptor_shards`)
+    /// Only takes effect when SO_REUSEPORT is enabled
+    pub http3_acceptor_shards: usize,
+    
+    /// Cores acceptor shards are steered to in turn (SO_INCOMING_CPU); empty = no steering
+    pub acceptor_cpus: Vec<usize>,
+    
+    /// I/O backend for the HTTP/2 listener
+    pub io_backend: IoBackend,
+    
+    /// Terminate TLS on the HTTP/2 listener
+    pub tls: bool,
+    
+    /// PEM certificate chain and private key for TLS listeners
+    /// A self-signed development certificate is generated when unset
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    
+    /// Read the client address from a PROXY protocol header on each TCP connection
+    pub proxy_protocol: bool,
+    
+    /// Open connections allowed per client IP on each listener (0 = unlimited)
+    pub max_connections_per_ip: usize,
+    
+    /// TCP listen backlog, and QUIC handshakes allowed in flight per endpoint
+    pub accept_backlog: u32,
+    
+    /// Session-ticket keys shared by every TLS and QUIC listener (None = no tickets)
+    pub session_tickets: Option<std::sync::Arc<super::tickets::TicketKeys>>,
 }
 
 impl Default for NetworkConfig {
@@ -47,6 +184,7 @@ impl Default for NetworkConfig {
             http2_port: 8080,
             http3_port: 8443,
             bind_addr: "0.0.0.0".to_string(),
+            ipv6_only: false,
             
             // 2MB buffers for high throughput
             tcp_send_buffer_size: 2 * 1024 * 1024,
@@ -55,6 +193,7 @@ impl Default for NetworkConfig {
             // High concurrency settings
             http2_max_concurrent_streams: 1000,
             http3_max_concurrent_streams: 1000,
+            http3_max_total_streams: 0,
             
             // 30 second idle timeout for QUIC
             quic_idle_timeout_ms: 30_000,
@@ -63,6 +202,22 @@ impl Default for NetworkConfig {
             tcp_nodelay: true,
             so_reuseaddr: true,
             so_reuseport: true,
+            
+            // One acceptor per core
+            acceptor_shards: num_cpus::get(),
+            http3_acceptor_shards: 0,
+            acceptor_cpus: Vec::new(),
+            
+            io_backend: IoBackend::Epoll,
+            
+            tls: false,
+            tls_cert_path: None,
+            tls_key_path: None,
+            proxy_protocol: false,
+            
+            max_connections_per_ip: 0,
+            accept_backlog: 1024,
+            session_tickets: None,
         }
     }
 }
@@ -70,16 +225,47 @@ impl Default for NetworkConfig {
 impl NetworkConfig {
     /// Get the HTTP/2 socket address
     pub fn http2_socket_addr(&self) -> SocketAddr {
-        format!("{}:{}", self.bind_addr, self.http2_port)
-            .parse()
-            .expect("Invalid HTTP/2 socket address")
+        let ip: IpAddr = self.bind_addr.parse().expect("Invalid HTTP/2 socket address");
+        SocketAddr::new(ip, self.http2_port)
     }
 
     /// Get the HTTP/3 socket address
     pub fn http3_socket_addr(&self) -> SocketAddr {
-        format!("{}:{}", self.bind_addr, self.http3_port)
-            .parse()
-            .expect("Invalid HTTP/3 socket address")
+        let ip: IpAddr = self.bind_addr.parse().expect("Invalid HTTP/3 socket address");
+        SocketAddr::new(ip, self.http3_port)
+    }
+
+    /// Settings for serving one listener, sharing everything else with `self`
+    pub fn for_listener(&self, listener: &ListenerConfig) -> Self {
+        let mut config = self.clone();
+        config.bind_addr = listener.address.clone();
+        config.ipv6_only = listener.ipv6_only;
+        config.tls = listener.tls;
+        config.proxy_protocol = listener.proxy_protocol;
+        match listener.protocol {
+            ListenerProtocol::Http2 => config.http2_port = listener.port,
+            ListenerProtocol::Http3 => config.http3_port = listener.port,
+        }
+        config
+    }
+
+    /// Number of acceptor shards to actually bind
+    /// Falls back to a single listener when SO_REUSEPORT is unavailable
+    pub fn effective_acceptor_shards(&self) -> usize {
+        if cfg!(unix) && self.so_reuseport {
+            self.acceptor_shards.max(1)
+        } else {
+            1
+        }
+    }
+
+    /// Number of UDP sockets to bind for each HTTP/3 listener
+    pub fn effective_http3_shards(&self) -> usize {
+        match self.http3_acceptor_shards {
+            0 => self.effective_acceptor_shards(),
+            shards if cfg!(unix) && self.so_reuseport => shards,
+            _ => 1,
+        }
     }
 
     /// Create production configuration with privileged ports
@@ -119,4 +305,59 @@ mod tests {
         let http3_addr = config.http3_socket_addr();
         assert_eq!(http3_addr.port(), 8443);
     }
+
+    #[test]
+    fn test_listener_config() {
+        let listener: ListenerConfig = toml::from_str("address = \"::1\"\nport = 9443\ntls = true\n").unwrap();
+        assert_eq!(listener.protocol, ListenerProtocol::Http2);
+        assert_eq!(listener.socket_addr().unwrap(), "[::1]:9443".parse().unwrap());
+        assert_eq!(listener.label(), "http2 ::1:9443");
+
+        let config = NetworkConfig::default().for_listener(&listener);
+        assert_eq!(config.http2_socket_addr().port(), 9443);
+        assert!(config.tls);
+
+        let bad = ListenerConfig { address: "localhost".to_string(), ..listener };
+        assert!(bad.socket_addr().is_err());
+    }
+
+    #[test]
+    fn test_dual_stack_overlap() {
+        let any_v4: SocketAddr = "0.0.0.0:443".parse().unwrap();
+        let any_v6: SocketAddr = "[::]:443".parse().unwrap();
+        let loopback_v6: SocketAddr = "[::1]:443".parse().unwrap();
+
+        assert!(sockets_overlap(any_v4, false, any_v6, false));
+        assert!(!sockets_overlap(any_v4, false, any_v6, true));
+        assert!(sockets_overlap(loopback_v6, false, any_v6, true));
+        assert!(!sockets_overlap(any_v4, false, loopback_v6, false));
+        assert!(!sockets_overlap(any_v4, false, "[::]:80".parse().unwrap(), false));
+
+        let listener: ListenerConfig = toml::from_str("address = \"::\"\nport = 443\nipv6_only = true\n").unwrap();
+        assert!(NetworkConfig::default().for_listener(&listener).ipv6_only);
+    }
+
+    #[test]
+    fn test_effective_acceptor_shards() {
+        let config = NetworkConfig {
+            acceptor_shards: 0,
+            ..Default::default()
+        };
+        assert_eq!(config.effective_acceptor_shards(), 1);
+
+        let config = NetworkConfig {
+            acceptor_shards: 4,
+            so_reuseport: false,
+            ..Default::default()
+        };
+        assert_eq!(config.effective_acceptor_shards(), 1);
+        assert_eq!(NetworkConfig { http3_acceptor_shards: 8, ..config }.effective_http3_shards(), 1);
+
+        let config = NetworkConfig {
+            acceptor_shards: 4,
+            ..Default::default()
+        };
+        assert_eq!(config.effective_http3_shards(), config.effective_acceptor_shards());
+        assert_eq!(NetworkConfig { http3_acceptor_shards: 2, ..config }.effective_http3_shards(), if cfg!(unix) { 2 } else { 1 });
+    }
 }
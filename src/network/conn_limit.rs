@@ -0,0 +1,98 @@
+// Per-IP Connection Caps
+// Limits how many connections one client address may hold open on a listener
+// IPv6 clients are counted per /64 (see `peer::client_key`)
+
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use super::peer::client_key;
+
+/// Open connection counts by client IP
+pub struct ConnectionLimiter {
+    /// Connections allowed per IP (0 = unlimited)
+    max_per_ip: usize,
+    open: DashMap<IpAddr, usize>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_per_ip: usize) -> Self {
+        Self {
+            max_per_ip,
+            open: DashMap::new(),
+        }
+    }
+
+    /// Count a new connection from `ip`, or None if it already has too many open
+    /// The connection is released when the permit is dropped.
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionPermit> {
+        let ip = client_key(ip);
+        let mut count = self.open.entry(ip).or_insert(0);
+        if self.max_per_ip > 0 && *count >= self.max_per_ip {
+            return None;
+        }
+        *count += 1;
+
+        Some(ConnectionPermit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    /// Connections currently open from `ip`
+    pub fn open_connections(&self, ip: IpAddr) -> usize {
+        self.open.get(&client_key(ip)).map_or(0, |count| *count)
+    }
+}
+
+/// One open connection counted against its client IP
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+
+    /// The client's key, not its exact address
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if let Entry::Occupied(mut entry) = self.limiter.open.entry(self.ip) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_ip_cap() {
+        let limiter = Arc::new(ConnectionLimiter::new(2));
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        let first = limiter.try_acquire(client).unwrap();
+        let _second = limiter.try_acquire(client).unwrap();
+        assert!(limiter.try_acquire(client).is_none());
+        assert!(limiter.try_acquire(other).is_some());
+
+        drop(first);
+        assert_eq!(limiter.open_connections(client), 1);
+        assert!(limiter.try_acquire(client).is_some());
+        assert_eq!(limiter.open_connections(other), 0);
+    }
+
+    #[test]
+    fn test_ipv6_clients_share_their_prefix() {
+        let limiter = Arc::new(ConnectionLimiter::new(1));
+        let client: IpAddr = "2001:db8:0:1::10".parse().unwrap();
+
+        let _permit = limiter.try_acquire(client).unwrap();
+        assert!(limiter.try_acquire("2001:db8:0:1::11".parse().unwrap()).is_none());
+        assert!(limiter.try_acquire("2001:db8:0:2::10".parse().unwrap()).is_some());
+    }
+}
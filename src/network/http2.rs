@@ -2,6 +2,8 @@
 // Production-grade server with optimized socket configuration
 
 use crate::network::NetworkConfig;
+use crate::network::acceptor::{self, AcceptorMetrics};
+use crate::signals::ShutdownCoordinator;
 use crate::state::GlobalState;
 use anyhow::Result;
 use hyper::server::conn::http2;
@@ -16,38 +18,63 @@ use tokio::net::TcpListener;
 use tracing::{info, debug, error, warn, instrument};
 
 /// Start the HTTP/2 server
-#[instrument(skip(config, state))]
-pub async fn serve(config: NetworkConfig, state: GlobalState) -> Result<()> {
+#[instrument(skip(config, state, metrics, shutdown))]
+pub async fn serve(
+    config: NetworkConfig,
+    state: GlobalState,
+    metrics: Arc<AcceptorMetrics>,
+    shutdown: Arc<ShutdownCoordinator>,
+) -> Result<()> {
     let addr = config.http2_socket_addr();
     
-    // Create TCP socket with advanced options
-    let socket = create_optimized_socket(&addr, &config)?;
-    
-    // Convert to TcpListener
-    let std_listener: std::net::TcpListener = socket.into();
-    std_listener.set_nonblocking(true)?;
-    let listener = TcpListener::from_std(std_listener)?;
+    // Bind one SO_REUSEPORT listener per acceptor shard
+    let listeners = acceptor::bind_tcp_shards(&addr, &config)?;
     
-    info!("HTTP/2 server listening on {}", addr);
+    info!("HTTP/2 server listening on {} ({} acceptor shards)", addr, listeners.len());
+
+    let mut shards = Vec::with_capacity(listeners.len());
+    for (shard, listener) in listeners.into_iter().enumerate() {
+        let state = state.clone();
+        let metrics = metrics.clone();
+        let shutdown = shutdown.clone();
+        shards.push(tokio::spawn(accept_loop(shard, listener, state, metrics, shutdown)));
+    }
+
+    futures::future::join_all(shards).await;
+    Ok(())
+}
+
+/// Accept loop for a single listener shard
+async fn accept_loop(
+    shard: usize,
+    listener: TcpListener,
+    state: GlobalState,
+    metrics: Arc<AcceptorMetrics>,
+    shutdown: Arc<ShutdownCoordinator>,
+) {
+    let mut shutdown_rx = shutdown.subscribe();
 
-    // Accept loop
     loop {
-        match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown_rx.recv() => break,
+        };
+
+        match accepted {
             Ok((stream, peer_addr)) => {
+                metrics.record_accept(shard);
+                metrics.connection_opened();
+                let metrics = metrics.clone();
+                
                 let state = state.clone();
                 let conn_id = state.next_request_id();
                 
-                debug!(conn_id = conn_id, peer = %peer_addr, "New HTTP/2 connection");
+                debug!(conn_id = conn_id, shard = shard, peer = %peer_addr, "New HTTP/2 connection");
 
                 // Register connection in global state
                 state.register_connection(
                     conn_id,
-                    crate::state::ConnectionMetadata {
-                        protocol: crate::state::Protocol::Http2,
-                        remote_addr: peer_addr.to_string(),
-                        connected_at: std::time::Instant::now(),
-                        request_count: 0,
-                    },
+                    crate::state::ConnectionMetadata::new(crate::state::Protocol::Http2, peer_addr.to_string()),
                 );
 
                 // Spawn a task to handle this connection
@@ -57,12 +84,14 @@ pub async fn serve(config: NetworkConfig, state: GlobalState) -> Result<()> {
                     }
                     
                     // Cleanup connection
+                    metrics.connection_closed();
                     state.remove_connection(&conn_id);
                     debug!(conn_id = conn_id, "HTTP/2 connection closed");
                 });
             }
             Err(e) => {
-                error!("Failed to accept HTTP/2 connection: {}", e);
+                metrics.record_error(shard);
+                error!(shard = shard, "Failed to accept HTTP/2 connection: {}", e);
             }
         }
     }
@@ -160,6 +189,11 @@ pub(crate) fn create_optimized_socket(addr: &SocketAddr, config: &NetworkConfig)
 
     let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
 
+    // Set explicitly so "::" means the same thing whatever the host's bindv6only default is
+    if addr.is_ipv6() {
+        socket.set_only_v6(config.ipv6_only)?;
+    }
+
     // Enable address reuse
     if config.so_reuseaddr {
         socket.set_reuse_address(true)?;
@@ -185,7 +219,7 @@ pub(crate) fn create_optimized_socket(addr: &SocketAddr, config: &NetworkConfig)
 
     // Bind and listen
     socket.bind(&(*addr).into())?;
-    socket.listen(1024)?; // Backlog of 1024 pending connections
+    socket.listen(config.accept_backlog.min(i32::MAX as u32) as i32)?;
 
     info!(
         addr = %addr,
@@ -199,65 +233,77 @@ pub(crate) fn create_optimized_socket(addr: &SocketAddr, config: &NetworkConfig)
 }
 
 /// Handle connection with Router (Phase 2)
-pub(crate) async fn handle_connection_with_router(
-    stream: tokio::net::TcpStream,
+/// Sends GOAWAY and finishes in-flight streams when shutdown is signalled
+/// `secure` marks requests as received over TLS, which enables HSTS.
+pub(crate) async fn handle_connection_with_router<S>(
+    stream: S,
     router: std::sync::Arc<crate::router::Router>,
     peer_addr: std::net::SocketAddr,
-) -> Result<()> {
+    secure: bool,
+    mut shutdown: tokio::sync::broadcast::Receiver<()>,
+) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
     use hyper::server::conn::http2;
     use hyper::service::service_fn;
     
     debug!(peer = %peer_addr, "New HTTP/2 connection (Router mode)");
 
-    let service = service_fn(move |req| {
-        let router = router.clone();
-        async move {
-            router.route_request(req).await
-                .or_else(|e| {
-                    error!(error = %e, "Router error");
-                    Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Full::new(Bytes::from("Internal error")))
-                        .unwrap())
-                })
-        }
-    });
-
-    let builder = http2::Builder::new(tokio::runtime::Handle::current());
-    builder.serve_connection(hyper_util::rt::TokioIo::new(stream), service).await?;
+    let state = router.state().clone();
+    let conn_id = state.next_connection_id();
+    state.register_connection(conn_id, crate::state::ConnectionMetadata::new(crate::state::Protocol::Http2, peer_addr.to_string()));
 
-    Ok(())
-}
+    // Header blocks over every site's limit are refused by the codec
+    let max_header_bytes = router.limits().and_then(|limits| limits.max_header_bytes());
+    let reaper = std::sync::Arc::new(super::reaper::ConnectionReaper::new(state.clone(), conn_id, router.limits()));
 
-/// Handle connection with Router (Phase 2)
-pub(crate) async fn handle_connection_with_router(
-    stream: tokio::net::TcpStream,
-    router: std::sync::Arc<crate::router::Router>,
-    peer_addr: std::net::SocketAddr,
-) -> Result<()> {
-    use hyper::server::conn::http2;
-    use hyper::service::service_fn;
-    
-    debug!(peer = %peer_addr, "New HTTP/2 connection (Router mode)");
-
-    let service = service_fn(move |req| {
+    let connection_reaper = reaper.clone();
+    let service = service_fn(move |mut req: Request<Incoming>| {
         let router = router.clone();
+        let request = connection_reaper.request_started();
+        req.extensions_mut().insert(crate::router::ClientAddr(peer_addr));
+        req.extensions_mut().insert(crate::router::ConnectionId(conn_id));
+        if secure {
+            req.extensions_mut().insert(crate::router::headers::SecureConnection);
+        }
         async move {
-            router.route_request(req).await
+            let response = router.route_request(req).await;
+            drop(request);
+            response
                 .or_else(|e| {
                     error!(error = %e, "Router error");
-                    Ok(Response::builder()
+                    Ok::<_, hyper::Error>(Response::builder()
                         .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Full::new(Bytes::from("Internal error")))
+                        .body(crate::router::full_body("Internal error"))
                         .unwrap())
                 })
         }
     });
 
-    let builder = http2::Builder::new(tokio::runtime::Handle::current());
-    builder.serve_connection(hyper_util::rt::TokioIo::new(stream), service).await?;
+    let mut builder = http2::Builder::new(hyper_util::rt::TokioExecutor::new());
+    if let Some(bytes) = max_header_bytes {
+        builder.max_header_list_size(bytes.min(u32::MAX as usize) as u32);
+    }
+    let connection = builder.serve_connection(hyper_util::rt::TokioIo::new(stream), service);
+    tokio::pin!(connection);
+
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown.recv() => {
+            debug!(peer = %peer_addr, "Draining HTTP/2 connection");
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+        reason = reaper.retired() => {
+            debug!(peer = %peer_addr, reason = ?reason, "Closing HTTP/2 connection");
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    state.remove_connection(conn_id);
 
-    Ok(())
+    Ok(result?)
 }
 
 #[cfg(test)]
@@ -2,79 +2,273 @@
 // Modern UDP-based protocol with TLS 1.3
 
 use crate::network::NetworkConfig;
+use crate::network::acceptor::AcceptorMetrics;
+use crate::network::conn_limit::ConnectionLimiter;
+use crate::network::quic_guard::{GuardedSocket, HandshakeRejection, QuicGuard};
+use crate::network::tickets::QuicTickets;
+use crate::signals::ShutdownCoordinator;
 use crate::state::GlobalState;
 use anyhow::Result;
-use quinn::{Endpoint, ServerConfig, Connection};
+use quinn::{Endpoint, EndpointConfig, ServerConfig, Connection};
 use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tracing::{info, debug, error, warn, instrument};
 
+/// HTTP/3 error code for closing a connection that did nothing wrong
+const H3_NO_ERROR: quinn::VarInt = quinn::VarInt::from_u32(0x100);
+
+/// HTTP/3 error code for refusing a request before processing any of it, so the client may retry it
+const H3_REQUEST_REJECTED: quinn::VarInt = quinn::VarInt::from_u32(0x10B);
+
+/// Requests served at once on each connection, and across every connection of the listener
+#[derive(Clone)]
+struct StreamLimits {
+    per_connection: usize,
+    total: Option<Arc<Semaphore>>,
+}
+
+impl StreamLimits {
+    fn new(config: &NetworkConfig) -> Self {
+        Self {
+            per_connection: (config.http3_max_concurrent_streams as usize).max(1),
+            total: (config.http3_max_total_streams > 0).then(|| Arc::new(Semaphore::new(config.http3_max_total_streams))),
+        }
+    }
+
+    /// A slot under the listener's total, or `None` if every one is taken
+    fn try_total(&self) -> Option<Option<OwnedSemaphorePermit>> {
+        match &self.total {
+            Some(total) => total.clone().try_acquire_owned().ok().map(Some),
+            None => Some(None),
+        }
+    }
+}
+
 /// Start the HTTP/3 server
-#[instrument(skip(config, state))]
-pub async fn serve(config: NetworkConfig, state: GlobalState) -> Result<()> {
+/// Every datagram passes `guard` before the endpoints see it.
+#[instrument(skip(config, sockets, state, metrics, guard, shutdown))]
+pub async fn serve(
+    config: NetworkConfig,
+    sockets: Vec<std::net::UdpSocket>,
+    state: GlobalState,
+    metrics: Arc<AcceptorMetrics>,
+    guard: Arc<QuicGuard>,
+    shutdown: Arc<ShutdownCoordinator>,
+) -> Result<()> {
     let addr = config.http3_socket_addr();
 
     // Create server configuration with TLS
     let server_config = create_server_config(&config)?;
+    let validating = guard.address_validation();
+    let mut initial_config = server_config.clone();
+    initial_config.use_retry(validating);
     
-    // Create QUIC endpoint
-    let endpoint = Endpoint::server(server_config, addr)?;
+    // Create one QUIC endpoint per pre-bound SO_REUSEPORT UDP socket
+    let mut endpoints = Vec::with_capacity(sockets.len());
+    for socket in sockets {
+        endpoints.push(Endpoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            Some(initial_config.clone()),
+            GuardedSocket::new(socket, guard.clone())?,
+            Arc::new(quinn::TokioRuntime),
+        )?);
+    }
     
-    info!("HTTP/3 server listening on {}", addr);
+    info!("HTTP/3 server listening on {} ({} acceptor shards)", addr, endpoints.len());
+    tokio::spawn(validate_addresses(endpoints.clone(), server_config, validating, guard.clone(), shutdown.clone()));
 
-    // Accept loop for incoming QUIC connections
-    while let Some(connecting) = endpoint.accept().await {
+    let limiter = Arc::new(ConnectionLimiter::new(config.max_connections_per_ip));
+    let streams = StreamLimits::new(&config);
+    let mut shards = Vec::with_capacity(endpoints.len());
+    for (shard, endpoint) in endpoints.into_iter().enumerate() {
         let state = state.clone();
+        let limiter = limiter.clone();
+        let handshakes = Arc::new(Semaphore::new(config.accept_backlog as usize));
+        let streams = streams.clone();
+        let metrics = metrics.clone();
+        let guard = guard.clone();
+        let shutdown = shutdown.clone();
+        shards.push(tokio::spawn(accept_loop(shard, endpoint, state, limiter, handshakes, streams, metrics, guard, shutdown)));
+    }
+
+    futures::future::join_all(shards).await;
+
+    info!("HTTP/3 server shutting down");
+    Ok(())
+}
+
+/// Ask new clients for a Retry token while the guard says handshakes are flooding in
+/// A validated client has proven it receives packets at its address, so spoofed floods
+/// never get as far as TLS. Stops, leaving the endpoints closed, on shutdown.
+async fn validate_addresses(
+    endpoints: Vec<Endpoint>,
+    server_config: ServerConfig,
+    mut validating: bool,
+    guard: Arc<QuicGuard>,
+    shutdown: Arc<ShutdownCoordinator>,
+) {
+    let mut shutdown_rx = shutdown.subscribe();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = shutdown_rx.recv() => break,
+            _ = interval.tick() => {}
+        }
+
+        guard.cleanup();
+        let validate = guard.address_validation();
+        if validate == validating {
+            continue;
+        }
+        let mut config = server_config.clone();
+        config.use_retry(validate);
+        for endpoint in &endpoints {
+            endpoint.set_server_config(Some(config.clone()));
+        }
+        validating = validate;
+    }
+
+    // A tick may have raced the acceptors closing the endpoints
+    for endpoint in &endpoints {
+        endpoint.set_server_config(None);
+    }
+}
+
+/// Accept loop for incoming QUIC connections on a single endpoint shard
+/// On shutdown the endpoint stops accepting but keeps serving open connections
+/// Connections over the client's per-IP cap, or arriving while `handshakes` is exhausted, are refused.
+#[allow(clippy::too_many_arguments)]
+async fn accept_loop(
+    shard: usize,
+    endpoint: Endpoint,
+    state: GlobalState,
+    limiter: Arc<ConnectionLimiter>,
+    handshakes: Arc<Semaphore>,
+    streams: StreamLimits,
+    metrics: Arc<AcceptorMetrics>,
+    guard: Arc<QuicGuard>,
+    shutdown: Arc<ShutdownCoordinator>,
+) {
+    let mut shutdown_rx = shutdown.subscribe();
+
+    loop {
+        let connecting = tokio::select! {
+            connecting = endpoint.accept() => match connecting {
+                Some(connecting) => connecting,
+                None => break,
+            },
+            _ = shutdown_rx.recv() => {
+                endpoint.set_server_config(None);
+                debug!(shard = shard, "HTTP/3 acceptor stopped");
+                endpoint.wait_idle().await;
+                break;
+            }
+        };
+
+        // Dropping a Connecting closes it
+        let peer_addr = super::peer::canonical(connecting.remote_address());
+        let Some(permit) = limiter.try_acquire(peer_addr.ip()) else {
+            metrics.record_rejected(shard);
+            guard.record(HandshakeRejection::ConnectionLimit);
+            debug!(shard = shard, peer = %peer_addr, "Too many connections from client, refusing");
+            continue;
+        };
+        let Ok(handshake) = handshakes.clone().try_acquire_owned() else {
+            metrics.record_rejected(shard);
+            guard.record(HandshakeRejection::Backlog);
+            warn!(shard = shard, peer = %peer_addr, "HTTP/3 handshake queue full, refusing");
+            continue;
+        };
+
+        let state = state.clone();
+        let streams = streams.clone();
+        let metrics = metrics.clone();
         let conn_id = state.next_request_id();
         
         tokio::spawn(async move {
-            match connecting.await {
+            let connecting = connecting.await;
+            drop(handshake);
+            match connecting {
                 Ok(connection) => {
-                    let peer_addr = connection.remote_address();
-                    debug!(conn_id = conn_id, peer = %peer_addr, "New HTTP/3 connection");
+                    metrics.record_accept(shard);
+                    metrics.connection_opened();
+                    
+                    debug!(conn_id = conn_id, shard = shard, peer = %peer_addr, "New HTTP/3 connection");
                     
                     // Register connection
                     state.register_connection(
                         conn_id,
-                        crate::state::ConnectionMetadata {
-                            protocol: crate::state::Protocol::Http3,
-                            remote_addr: peer_addr.to_string(),
-                            connected_at: std::time::Instant::now(),
-                            request_count: 0,
-                        },
+                        crate::state::ConnectionMetadata::new(crate::state::Protocol::Http3, peer_addr.to_string()),
                     );
 
-                    if let Err(e) = handle_connection(connection, state.clone(), conn_id).await {
+                    if let Err(e) = handle_connection(connection, state.clone(), conn_id, streams, &metrics, shard).await {
                         error!(conn_id = conn_id, error = %e, "HTTP/3 connection error");
                     }
                     
                     // Cleanup
+                    metrics.connection_closed();
                     state.remove_connection(&conn_id);
+                    drop(permit);
                     debug!(conn_id = conn_id, "HTTP/3 connection closed");
                 }
                 Err(e) => {
-                    error!("Failed to establish HTTP/3 connection: {}", e);
+                    metrics.record_error(shard);
+                    error!(shard = shard, "Failed to establish HTTP/3 connection: {}", e);
                 }
             }
         });
     }
-
-    info!("HTTP/3 server shutting down");
-    Ok(())
 }
 
 /// Handle a single HTTP/3 QUIC connection
+/// A shed connection is closed with H3_NO_ERROR, so its client reconnects, possibly to another node.
+/// No more streams are accepted while the connection has `per_connection` requests running, and
+/// streams over the listener's total are refused with H3_REQUEST_REJECTED.
 async fn handle_connection(
     connection: Connection,
     state: GlobalState,
     conn_id: u64,
+    limits: StreamLimits,
+    metrics: &AcceptorMetrics,
+    shard: usize,
 ) -> Result<()> {
+    let shed = state.shed_signal(conn_id);
+    let running = Arc::new(Semaphore::new(limits.per_connection));
+
     // Accept bidirectional streams (HTTP/3 requests)
     loop {
-        match connection.accept_bi().await {
-            Ok((mut send, mut recv)) => {
+        let accepted = tokio::select! {
+            accepted = async {
+                let permit = running.clone().acquire_owned().await.expect("stream semaphore is never closed");
+                connection.accept_bi().await.map(|streams| (permit, streams))
+            } => accepted,
+            _ = async {
+                match &shed {
+                    Some(shed) => shed.notified().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                debug!(conn_id = conn_id, "Shedding HTTP/3 connection");
+                state.record_reaped(crate::state::ReapReason::Shed);
+                connection.close(H3_NO_ERROR, b"rebalancing");
+                break;
+            }
+        };
+        match accepted {
+            Ok((permit, (mut send, mut recv))) => {
+                let Some(total) = limits.try_total() else {
+                    metrics.record_stream_rejected(shard);
+                    debug!(conn_id = conn_id, "Too many HTTP/3 streams on the listener, refusing");
+                    let _ = send.reset(H3_REQUEST_REJECTED);
+                    let _ = recv.stop(H3_REQUEST_REJECTED);
+                    continue;
+                };
                 let state = state.clone();
                 
                 tokio::spawn(async move {
+                    let _permits = (permit, total);
                     if let Err(e) = handle_stream(&mut send, &mut recv, state, conn_id).await {
                         error!(conn_id = conn_id, "Stream error: {}", e);
                     }
@@ -165,16 +359,19 @@ fn create_server_config(config: &NetworkConfig) -> Result<ServerConfig> {
     let cert_der = cert.serialize_der()?;
     let priv_key = cert.serialize_private_key_der();
     
-    let cert_chain = vec![rustls::Certificate(cert_der)];
-    let key_der = rustls::PrivateKey(priv_key);
+    let cert_chain = vec![quinn_rustls::Certificate(cert_der)];
+    let key_der = quinn_rustls::PrivateKey(priv_key);
 
     // Create rustls config
-    let mut crypto = rustls::ServerConfig::builder()
+    let mut crypto = quinn_rustls::ServerConfig::builder()
         .with_safe_defaults()
         .with_no_client_auth()
         .with_single_cert(cert_chain, key_der)?;
 
     crypto.alpn_protocols = vec![b"h3".to_vec()]; // HTTP/3 ALPN
+    if let Some(tickets) = &config.session_tickets {
+        crypto.ticketer = Arc::new(QuicTickets(tickets.clone()));
+    }
     
     // Create Quinn server config
     let mut server_config = ServerConfig::with_crypto(Arc::new(crypto));
@@ -214,6 +411,19 @@ mod tests {
         assert!(server_config.is_ok());
     }
 
+    #[test]
+    fn test_stream_limits() {
+        let limits = StreamLimits::new(&NetworkConfig { http3_max_total_streams: 1, ..Default::default() });
+        let running = limits.try_total().unwrap();
+        assert!(running.is_some());
+        assert!(limits.try_total().is_none());
+        drop(running);
+        assert!(limits.try_total().is_some());
+
+        let unlimited = StreamLimits::new(&NetworkConfig::default());
+        assert!(matches!(unlimited.try_total(), Some(None)));
+    }
+
     #[test]
     fn test_response_builder() {
         let state = GlobalState::new();
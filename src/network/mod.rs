@@ -1,12 +1,25 @@
 // Network module - Dual-protocol networking stack
 // Supports both HTTP/2 over TCP and HTTP/3 over QUIC
 
+pub mod acceptor;
 pub mod config;
+pub mod conn_limit;
 pub mod http2;
 pub mod http3;
+pub mod mtls;
+pub mod peer;
+pub mod proxy_protocol;
+pub mod quic_guard;
+pub mod reaper;
+pub mod rebalance;
+pub mod redirect;
+pub mod tickets;
 pub mod router_integration;
+pub mod tls;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
 
-pub use config::NetworkConfig;
+pub use config::{IoBackend, ListenerConfig, ListenerProtocol, NetworkConfig};
 
 // Re-export router-integrated serve functions
 pub use router_integration::{serve_with_router as http2_serve_with_router};
@@ -0,0 +1,623 @@
+// Mutual TLS
+// A built-in CA issues certificates for operators and peer nodes; the management API only accepts clients holding one that isn't revoked
+
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use rand::RngCore;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose, SanType, SerialNumber,
+};
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{CertificateError, DigitallySignedStruct, DistinguishedName as HintName, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{info, warn};
+
+const CA_CERT: &str = "ca.pem";
+const CA_KEY: &str = "ca.key";
+const INVENTORY: &str = "issued.json";
+const NODE_CERT: &str = "node.pem";
+const NODE_KEY: &str = "node.key";
+
+/// The CA certificate is valid this long
+const CA_VALIDITY_DAYS: i64 = 3650;
+
+/// The node certificate is reissued once it has less than this left
+const NODE_RENEW_DAYS: i64 = 30;
+
+/// `[mtls]`: client certificates for the management API and peer forwarding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MtlsConfig {
+    /// Require a certificate from the CA on every management API connection
+    pub enabled: bool,
+
+    /// Directory holding the CA and its inventory ("" = `<storage root>/ca`)
+    pub ca_dir: String,
+
+    /// This node's certificate, presented to API clients and to peers ("" = `<ca_dir>/node.pem`)
+    pub cert_path: String,
+
+    /// Key of `cert_path` ("" = `<ca_dir>/node.key`)
+    pub key_path: String,
+
+    /// Host names and addresses put in the node certificate besides localhost
+    pub node_names: Vec<String>,
+
+    /// Validity of certificates issued with `pear ca issue`
+    pub validity_days: u32,
+}
+
+impl Default for MtlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ca_dir: String::new(),
+            cert_path: String::new(),
+            key_path: String::new(),
+            node_names: Vec::new(),
+            validity_days: 365,
+        }
+    }
+}
+
+impl MtlsConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.validity_days == 0 {
+            bail!("validity_days must be at least 1");
+        }
+        if self.cert_path.is_empty() != self.key_path.is_empty() {
+            bail!("cert_path and key_path must be set together");
+        }
+        for name in &self.node_names {
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                bail!("Invalid node name '{}'", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Directory of the CA, given the storage root
+    pub fn ca_path(&self, storage_root: &Path) -> PathBuf {
+        if self.ca_dir.is_empty() {
+            storage_root.join("ca")
+        } else {
+            PathBuf::from(&self.ca_dir)
+        }
+    }
+
+    /// The node certificate and key, given the CA directory
+    fn node_paths(&self, ca_dir: &Path) -> (PathBuf, PathBuf) {
+        if self.cert_path.is_empty() {
+            (ca_dir.join(NODE_CERT), ca_dir.join(NODE_KEY))
+        } else {
+            (PathBuf::from(&self.cert_path), PathBuf::from(&self.key_path))
+        }
+    }
+}
+
+/// What a certificate is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CertKind {
+    /// An operator machine calling the management API
+    Operator,
+    /// Another node forwarding to this one and serving its own API
+    Peer,
+    /// This node's own certificate
+    Node,
+}
+
+impl CertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CertKind::Operator => "operator",
+            CertKind::Peer => "peer",
+            CertKind::Node => "node",
+        }
+    }
+}
+
+/// A certificate the CA signed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuedCertificate {
+    pub name: String,
+    pub kind: CertKind,
+    /// Hex serial number, without leading zeros
+    pub serial: String,
+    /// Subject alternative names
+    #[serde(default)]
+    pub names: Vec<String>,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<i64>,
+}
+
+impl IssuedCertificate {
+    pub fn is_active(&self, now: i64) -> bool {
+        self.revoked_at.is_none() && self.expires_at > now
+    }
+}
+
+/// `issued.json`: every certificate the CA signed, and which are revoked
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Inventory {
+    pub common_name: String,
+    pub certificates: Vec<IssuedCertificate>,
+}
+
+impl Inventory {
+    fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&data).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        write_file(path, &serde_json::to_vec_pretty(self)?, 0o644)
+    }
+
+    /// Serials of revoked certificates
+    fn revoked(&self) -> HashSet<String> {
+        self.certificates.iter()
+            .filter(|cert| cert.revoked_at.is_some())
+            .map(|cert| cert.serial.clone())
+            .collect()
+    }
+}
+
+/// A certificate and its private key, in PEM
+pub struct IssuedPem {
+    pub cert: String,
+    pub key: String,
+}
+
+/// The node's CA, opened from its directory
+pub struct CertificateAuthority {
+    dir: PathBuf,
+    signer: Certificate,
+    inventory: Inventory,
+}
+
+impl CertificateAuthority {
+    /// Create a CA in `dir`, refusing to replace an existing one
+    pub fn init(dir: &Path, common_name: &str) -> Result<Self> {
+        if dir.join(CA_KEY).exists() {
+            bail!("A CA already exists in {}", dir.display());
+        }
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let key_pair = KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+        let mut params = ca_params(common_name, key_pair);
+        let now = chrono::Utc::now();
+        set_validity(&mut params, now, now + chrono::Duration::days(CA_VALIDITY_DAYS));
+        params.serial_number = Some(random_serial());
+        let signer = Certificate::from_params(params)?;
+
+        write_file(&dir.join(CA_KEY), signer.serialize_private_key_pem().as_bytes(), 0o600)?;
+        write_file(&dir.join(CA_CERT), signer.serialize_pem()?.as_bytes(), 0o644)?;
+        let inventory = Inventory { common_name: common_name.to_string(), certificates: Vec::new() };
+        inventory.save(&dir.join(INVENTORY))?;
+
+        info!(dir = %dir.display(), "Certificate authority created");
+        Ok(Self { dir: dir.to_path_buf(), signer, inventory })
+    }
+
+    /// Open the CA in `dir`
+    pub fn open(dir: &Path) -> Result<Self> {
+        let key_path = dir.join(CA_KEY);
+        let key_pem = std::fs::read_to_string(&key_path)
+            .with_context(|| format!("No CA in {} (run `pear ca init`)", dir.display()))?;
+        let key_pair = KeyPair::from_pem(&key_pem).with_context(|| format!("Failed to parse {}", key_path.display()))?;
+        let inventory = Inventory::load(&dir.join(INVENTORY))?;
+
+        // Issued certificates only carry the CA's name and signature, so the signer is rebuilt from its key
+        let signer = Certificate::from_params(ca_params(&inventory.common_name, key_pair))?;
+        Ok(Self { dir: dir.to_path_buf(), signer, inventory })
+    }
+
+    /// Whether `dir` holds a CA able to issue certificates
+    pub fn exists(dir: &Path) -> bool {
+        dir.join(CA_KEY).exists()
+    }
+
+    pub fn list(&self) -> &[IssuedCertificate] {
+        &self.inventory.certificates
+    }
+
+    /// Sign a new certificate named `name`, valid for `days`
+    pub fn issue(&mut self, name: &str, kind: CertKind, names: &[String], days: u32) -> Result<IssuedPem> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            bail!("Invalid certificate name '{}'", name);
+        }
+        let now = chrono::Utc::now();
+        if kind != CertKind::Node
+            && self.inventory.certificates.iter().any(|cert| cert.name == name && cert.is_active(now.timestamp()))
+        {
+            bail!("A certificate named '{}' is already active; revoke it first", name);
+        }
+
+        let mut params = CertificateParams::default();
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+        params.extended_key_usages = match kind {
+            CertKind::Operator => vec![ExtendedKeyUsagePurpose::ClientAuth],
+            CertKind::Peer | CertKind::Node => {
+                vec![ExtendedKeyUsagePurpose::ServerAuth, ExtendedKeyUsagePurpose::ClientAuth]
+            }
+        };
+        params.subject_alt_names = names.iter()
+            .map(|san| match san.parse() {
+                Ok(ip) => SanType::IpAddress(ip),
+                Err(_) => SanType::DnsName(san.clone()),
+            })
+            .collect();
+        let expires = now + chrono::Duration::days(days as i64);
+        set_validity(&mut params, now, expires);
+        let serial = random_serial();
+        params.serial_number = Some(serial.clone());
+
+        let cert = Certificate::from_params(params)?;
+        let pem = IssuedPem {
+            cert: cert.serialize_pem_with_signer(&self.signer)?,
+            key: cert.serialize_private_key_pem(),
+        };
+
+        self.inventory.certificates.push(IssuedCertificate {
+            name: name.to_string(),
+            kind,
+            serial: serial_hex(&serial.to_bytes()),
+            names: names.to_vec(),
+            issued_at: now.timestamp(),
+            expires_at: expires.timestamp(),
+            revoked_at: None,
+        });
+        self.inventory.save(&self.dir.join(INVENTORY))?;
+        Ok(pem)
+    }
+
+    /// Revoke every active certificate named `name`, returning how many there were
+    pub fn revoke(&mut self, name: &str) -> Result<usize> {
+        let now = chrono::Utc::now().timestamp();
+        let mut revoked = 0;
+        for cert in self.inventory.certificates.iter_mut().filter(|cert| cert.name == name && cert.is_active(now)) {
+            cert.revoked_at = Some(now);
+            revoked += 1;
+        }
+        if revoked == 0 {
+            bail!("No active certificate named '{}'", name);
+        }
+        self.inventory.save(&self.dir.join(INVENTORY))?;
+        Ok(revoked)
+    }
+}
+
+/// The CA certificate's parameters; everything a signature depends on is deterministic
+fn ca_params(common_name: &str, key_pair: KeyPair) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, common_name);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign, KeyUsagePurpose::DigitalSignature];
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    params.key_pair = Some(key_pair);
+    params
+}
+
+/// Validity in whole days, starting a day early to allow for clock skew between nodes
+fn set_validity(params: &mut CertificateParams, from: chrono::DateTime<chrono::Utc>, until: chrono::DateTime<chrono::Utc>) {
+    use chrono::Datelike;
+    let from = from - chrono::Duration::days(1);
+    params.not_before = rcgen::date_time_ymd(from.year(), from.month() as u8, from.day() as u8);
+    params.not_after = rcgen::date_time_ymd(until.year(), until.month() as u8, until.day() as u8);
+}
+
+/// 128 random bits, positive so the DER integer needs no padding
+fn random_serial() -> SerialNumber {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[0] = (bytes[0] & 0x7f) | 0x01;
+    SerialNumber::from_slice(&bytes)
+}
+
+/// Hex form of a serial number, as stored in the inventory
+fn serial_hex(bytes: &[u8]) -> String {
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+    hex::encode(&bytes[start..])
+}
+
+/// Write `data` to `path` through a staging file, with the given mode
+fn write_file(path: &Path, data: &[u8], mode: u32) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let staging = path.with_extension("tmp");
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&staging, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Revoked serials, reread whenever `issued.json` changes
+#[derive(Debug)]
+struct Revocations {
+    path: PathBuf,
+    state: Mutex<(Option<SystemTime>, HashSet<String>)>,
+}
+
+impl Revocations {
+    fn new(path: PathBuf) -> Self {
+        Self { path, state: Mutex::new((None, HashSet::new())) }
+    }
+
+    fn is_revoked(&self, serial: &str) -> bool {
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let mut state = self.state.lock();
+        if modified != state.0 {
+            match Inventory::load(&self.path) {
+                Ok(inventory) => *state = (modified, inventory.revoked()),
+                // Keep the last list rather than accept revoked certificates while the file is unreadable
+                Err(e) => warn!(error = %e, "Failed to reload revoked certificates"),
+            }
+        }
+        state.1.contains(serial)
+    }
+}
+
+/// Verifies client certificates against the CA, then refuses revoked ones
+#[derive(Debug)]
+struct RevocationVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    revocations: Revocations,
+}
+
+impl ClientCertVerifier for RevocationVerifier {
+    fn root_hint_subjects(&self) -> &[HintName] {
+        self.inner.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self.inner.verify_client_cert(end_entity, intermediates, now)?;
+        let (_, cert) = x509_parser::parse_x509_certificate(end_entity)
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        if self.revocations.is_revoked(&serial_hex(cert.raw_serial())) {
+            warn!(subject = %cert.subject(), "Refused a revoked client certificate");
+            return Err(rustls::Error::InvalidCertificate(CertificateError::Revoked));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// The node's side of mutual TLS: it accepts API clients and connects to peers with the same certificate
+pub struct NodeIdentity {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+}
+
+impl NodeIdentity {
+    /// Load the node certificate, issuing or renewing it first when this node holds the CA key
+    pub fn load(config: &MtlsConfig, storage_root: &Path) -> Result<Self> {
+        let ca_dir = config.ca_path(storage_root);
+        let (cert_path, key_path) = config.node_paths(&ca_dir);
+        if config.cert_path.is_empty() && CertificateAuthority::exists(&ca_dir) && needs_renewal(&cert_path) {
+            let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+            names.extend(config.node_names.iter().cloned());
+            let mut ca = CertificateAuthority::open(&ca_dir)?;
+            let pem = ca.issue("node", CertKind::Node, &names, config.validity_days)?;
+            write_file(&key_path, pem.key.as_bytes(), 0o600)?;
+            write_file(&cert_path, pem.cert.as_bytes(), 0o644)?;
+            info!(cert = %cert_path.display(), "Issued the node certificate");
+        }
+
+        let roots = Arc::new(root_store(&ca_dir.join(CA_CERT))?);
+        let (certs, key) = super::tls::load_pem(&cert_path.to_string_lossy(), &key_path.to_string_lossy())
+            .context("Failed to load the node certificate (issue one with `pear ca issue --peer` on the CA node)")?;
+
+        let inner = WebPkiClientVerifier::builder(roots.clone()).build()
+            .map_err(|e| anyhow::anyhow!("Invalid CA certificate: {}", e))?;
+        let verifier = Arc::new(RevocationVerifier {
+            inner,
+            revocations: Revocations::new(ca_dir.join(INVENTORY)),
+        });
+        let mut server = rustls::ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs.clone(), key.clone_key())
+            .context("Invalid node certificate or key")?;
+        server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        // A resumed session skips the client certificate, and with it the revocation check
+        server.session_storage = Arc::new(rustls::server::NoServerSessionStorage {});
+        server.send_tls13_tickets = 0;
+
+        let client = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)
+            .context("Invalid node certificate or key")?;
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+            connector: TlsConnector::from(Arc::new(client)),
+        })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.clone()
+    }
+
+    pub fn connector(&self) -> TlsConnector {
+        self.connector.clone()
+    }
+}
+
+/// Connector for a client holding `cert_path`, trusting only the CA
+pub fn client_connector(ca_cert: &Path, cert_path: &Path, key_path: &Path) -> Result<TlsConnector> {
+    let roots = root_store(ca_cert)?;
+    let (certs, key) = super::tls::load_pem(&cert_path.to_string_lossy(), &key_path.to_string_lossy())?;
+    let client = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(certs, key)
+        .context("Invalid client certificate or key")?;
+    Ok(TlsConnector::from(Arc::new(client)))
+}
+
+/// Connector for the CLI on a node: `PEAR_CLIENT_CERT`/`PEAR_CLIENT_KEY` if set, else the node certificate
+pub fn cli_connector(config: &MtlsConfig, storage_root: &Path) -> Result<TlsConnector> {
+    let ca_dir = config.ca_path(storage_root);
+    let ca_cert = std::env::var_os("PEAR_CA_CERT").map_or_else(|| ca_dir.join(CA_CERT), PathBuf::from);
+    let (cert_path, key_path) = match (std::env::var_os("PEAR_CLIENT_CERT"), std::env::var_os("PEAR_CLIENT_KEY")) {
+        (Some(cert), Some(key)) => (PathBuf::from(cert), PathBuf::from(key)),
+        _ => config.node_paths(&ca_dir),
+    };
+    client_connector(&ca_cert, &cert_path, &key_path)
+}
+
+/// Trust store holding only the CA certificate
+fn root_store(ca_cert: &Path) -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in super::tls::load_certificates(&ca_cert.to_string_lossy())? {
+        roots.add(cert).context("Invalid CA certificate")?;
+    }
+    Ok(roots)
+}
+
+/// Whether the node certificate is missing or close to expiring
+fn needs_renewal(cert_path: &Path) -> bool {
+    let Ok(certs) = super::tls::load_certificates(&cert_path.to_string_lossy()) else { return true };
+    let Ok(validity) = super::tls::validity(&certs[0]) else { return true };
+    validity.not_after - chrono::Utc::now().timestamp() < NODE_RENEW_DAYS * 86400
+}
+
+/// A connection to a management API, plain or over TLS
+pub trait ApiIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ApiIo for T {}
+
+/// Connect to `addr`, over TLS with `tls` if given, checking the server's certificate for `server_name`
+pub async fn connect(addr: &str, server_name: &str, tls: Option<&TlsConnector>) -> Result<Box<dyn ApiIo>> {
+    let stream = tokio::time::timeout(std::time::Duration::from_secs(5), tokio::net::TcpStream::connect(addr)).await
+        .context("Timed out connecting")??;
+    let Some(tls) = tls else { return Ok(Box::new(stream)) };
+    let server_name = ServerName::try_from(server_name.to_string()).context("Invalid TLS server name")?;
+    Ok(Box::new(tls.connect(server_name, stream).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_issue_and_revoke() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ca = CertificateAuthority::init(dir.path(), "Test CA").unwrap();
+        assert!(CertificateAuthority::init(dir.path(), "Test CA").is_err());
+
+        ca.issue("laptop", CertKind::Operator, &[], 30).unwrap();
+        assert!(ca.issue("laptop", CertKind::Operator, &[], 30).is_err());
+
+        // Reopening rebuilds the signer, so certificates keep verifying against ca.pem
+        let mut ca = CertificateAuthority::open(dir.path()).unwrap();
+        assert_eq!(ca.list().len(), 1);
+        ca.issue("node-b", CertKind::Peer, &["10.0.0.2".to_string()], 30).unwrap();
+        assert_eq!(ca.revoke("laptop").unwrap(), 1);
+        assert!(ca.revoke("laptop").is_err());
+        ca.issue("laptop", CertKind::Operator, &[], 30).unwrap();
+
+        let revocations = Revocations::new(dir.path().join(INVENTORY));
+        let laptops: Vec<_> = ca.list().iter().filter(|cert| cert.name == "laptop").collect();
+        assert!(revocations.is_revoked(&laptops[0].serial));
+        assert!(!revocations.is_revoked(&laptops[1].serial));
+    }
+
+    #[tokio::test]
+    async fn test_mutual_handshake() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ca = CertificateAuthority::init(&dir.path().join("ca"), "Test CA").unwrap();
+        let config = MtlsConfig { enabled: true, ..Default::default() };
+        let node = NodeIdentity::load(&config, dir.path()).unwrap();
+
+        let laptop = ca.issue("laptop", CertKind::Operator, &[], 30).unwrap();
+        std::fs::write(dir.path().join("laptop.pem"), &laptop.cert).unwrap();
+        std::fs::write(dir.path().join("laptop.key"), &laptop.key).unwrap();
+        let client = client_connector(
+            &dir.path().join("ca").join(CA_CERT),
+            &dir.path().join("laptop.pem"),
+            &dir.path().join("laptop.key"),
+        ).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let acceptor = node.acceptor();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Ok(mut tls) = acceptor.accept(stream).await {
+                    let _ = tls.write_all(b"ok").await;
+                    let _ = tls.shutdown().await;
+                }
+            }
+        });
+
+        let mut reply = String::new();
+        let mut stream = connect(&addr, "localhost", Some(&client)).await.unwrap();
+        stream.read_to_string(&mut reply).await.unwrap();
+        assert_eq!(reply, "ok");
+
+        // Without a certificate, or with a revoked one, the server aborts the handshake
+        let anonymous = TlsConnector::from(Arc::new(
+            rustls::ClientConfig::builder()
+                .with_root_certificates(root_store(&dir.path().join("ca").join(CA_CERT)).unwrap())
+                .with_no_client_auth(),
+        ));
+        let mut refused = connect(&addr, "localhost", Some(&anonymous)).await.unwrap();
+        assert!(refused.read_to_string(&mut String::new()).await.is_err());
+
+        ca.revoke("laptop").unwrap();
+        let mut refused = connect(&addr, "localhost", Some(&client)).await.unwrap();
+        assert!(refused.read_to_string(&mut String::new()).await.is_err());
+    }
+}
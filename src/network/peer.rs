@@ -0,0 +1,45 @@
+// Peer Addresses
+// Client addresses as dual-stack sockets report them, and the key per-client limits count them under
+
+use std::net::{IpAddr, SocketAddr};
+
+/// IPv6 prefix treated as one client; hosts usually get a whole /64 to pick addresses from
+pub const IPV6_CLIENT_PREFIX: u32 = 64;
+
+/// `addr` with an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) turned back into IPv4
+/// Dual-stack sockets report IPv4 clients this way; everything downstream expects the IPv4 form.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// Key rate limits, bans and connection caps are counted under
+/// IPv4 clients are keyed by address and IPv6 clients by their /64, so rotating addresses within it doesn't escape a limit
+pub fn client_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & !(u128::MAX >> IPV6_CLIENT_PREFIX)).into()),
+        v4 => v4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_peer() {
+        let mapped: SocketAddr = "[::ffff:192.0.2.7]:5000".parse().unwrap();
+        assert_eq!(canonical(mapped), "192.0.2.7:5000".parse().unwrap());
+        let v6: SocketAddr = "[2001:db8::1]:5000".parse().unwrap();
+        assert_eq!(canonical(v6), v6);
+    }
+
+    #[test]
+    fn test_client_key() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(client_key(ip("192.0.2.7")), ip("192.0.2.7"));
+        assert_eq!(client_key(ip("::ffff:192.0.2.7")), ip("192.0.2.7"));
+        assert_eq!(client_key(ip("2001:db8:1:2:aaaa::1")), ip("2001:db8:1:2::"));
+        assert_eq!(client_key(ip("2001:db8:1:2:bbbb::9")), client_key(ip("2001:db8:1:2::1")));
+        assert_ne!(client_key(ip("2001:db8:1:3::1")), client_key(ip("2001:db8:1:2::1")));
+    }
+}
@@ -0,0 +1,149 @@
+// PROXY Protocol
+// Reads the v1 (text) or v2 (binary) header a load balancer sends ahead of each TCP connection
+
+use anyhow::{bail, Context, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// First 12 bytes of every v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest v1 header the spec allows, CRLF included
+const V1_MAX_LEN: usize = 107;
+
+/// How long a connection may take to send its header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The client a connection from `peer` carries traffic for
+/// Connections the proxy makes on its own behalf, such as health checks, keep `peer`.
+pub async fn client_addr<S: AsyncRead + Unpin>(stream: &mut S, peer: SocketAddr) -> Result<SocketAddr> {
+    let header = tokio::time::timeout(HEADER_TIMEOUT, read_header(stream)).await
+        .context("Timed out waiting for PROXY protocol header")??;
+    Ok(header.map_or(peer, super::peer::canonical))
+}
+
+/// Read exactly one header from the start of `stream`, leaving the rest of the connection unread
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>> {
+    let first = stream.read_u8().await?;
+    match first {
+        b'P' => {
+            // Byte at a time, so nothing past the CRLF is consumed
+            let mut line = vec![first];
+            while !line.ends_with(b"\r\n") {
+                if line.len() >= V1_MAX_LEN {
+                    bail!("PROXY v1 header longer than {} bytes", V1_MAX_LEN);
+                }
+                line.push(stream.read_u8().await?);
+            }
+            parse_v1(&line[..line.len() - 2])
+        }
+        b'\r' => {
+            let mut header = [0u8; 16];
+            header[0] = first;
+            stream.read_exact(&mut header[1..]).await?;
+            let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+            let mut addresses = vec![0u8; len];
+            stream.read_exact(&mut addresses).await?;
+            parse_v2(&header, &addresses)
+        }
+        _ => bail!("Connection did not start with a PROXY protocol header"),
+    }
+}
+
+/// `PROXY TCP4 <src> <dst> <sport> <dport>`, or `PROXY UNKNOWN ...`
+fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).context("PROXY v1 header is not ASCII")?;
+    let mut fields = line.split(' ');
+    if fields.next() != Some("PROXY") {
+        bail!("Malformed PROXY v1 header");
+    }
+    match fields.next() {
+        Some("UNKNOWN") => Ok(None),
+        Some(family @ ("TCP4" | "TCP6")) => {
+            let fields: Vec<&str> = fields.collect();
+            let [source, _, port, _] = fields[..] else {
+                bail!("Malformed PROXY v1 header");
+            };
+            let ip: IpAddr = source.parse().context("Invalid PROXY v1 source address")?;
+            if ip.is_ipv4() != (family == "TCP4") {
+                bail!("PROXY v1 source address doesn't match {}", family);
+            }
+            let port: u16 = port.parse().context("Invalid PROXY v1 source port")?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => bail!("Unsupported PROXY v1 protocol"),
+    }
+}
+
+/// Fixed 16-byte header, then the address block for its family
+fn parse_v2(header: &[u8; 16], addresses: &[u8]) -> Result<Option<SocketAddr>> {
+    if header[..12] != V2_SIGNATURE {
+        bail!("Invalid PROXY v2 signature");
+    }
+    if header[12] >> 4 != 2 {
+        bail!("Unsupported PROXY protocol version {}", header[12] >> 4);
+    }
+    match header[12] & 0x0f {
+        // LOCAL: the proxy's own connection
+        0 => return Ok(None),
+        1 => {}
+        command => bail!("Unknown PROXY v2 command {}", command),
+    }
+
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    match header[13] >> 4 {
+        0 => Ok(None),
+        1 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[..4].try_into()?;
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port(8))))
+        }
+        2 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[..16].try_into()?;
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port(32))))
+        }
+        // Unix sockets name no client address
+        3 => Ok(None),
+        _ => bail!("Truncated or unknown PROXY v2 address block"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reads_v1_header() {
+        let mut stream: &[u8] = b"PROXY TCP4 203.0.113.9 10.0.0.1 51234 443\r\nGET / HTTP/1.1\r\n";
+        let peer: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        assert_eq!(client_addr(&mut stream, peer).await.unwrap(), "203.0.113.9:51234".parse().unwrap());
+        assert_eq!(stream, b"GET / HTTP/1.1\r\n");
+
+        let mut unknown: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(client_addr(&mut unknown, peer).await.unwrap(), peer);
+
+        let mut mismatched: &[u8] = b"PROXY TCP4 2001:db8::1 10.0.0.1 1 2\r\n";
+        assert!(read_header(&mut mismatched).await.is_err());
+        let mut plain: &[u8] = b"GET / HTTP/1.1\r\n";
+        assert!(read_header(&mut plain).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reads_v2_header() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x21, 0, 36]);
+        bytes.extend_from_slice(&"2001:db8::9".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&[0; 16]);
+        bytes.extend_from_slice(&4711u16.to_be_bytes());
+        bytes.extend_from_slice(&443u16.to_be_bytes());
+        bytes.extend_from_slice(b"PRI *");
+
+        let mut stream = &bytes[..];
+        assert_eq!(read_header(&mut stream).await.unwrap(), Some("[2001:db8::9]:4711".parse().unwrap()));
+        assert_eq!(stream, b"PRI *");
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(read_header(&mut &local[..]).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,594 @@
+// QUIC Handshake Guard
+// Screens packets that open connections before Quinn spends TLS work on them
+// Clients are rate limited per IP (IPv6 per /64), and asked to prove their address with a Retry under load
+
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use quinn::udp::{RecvMeta, Transmit, UdpState};
+use quinn::{AsyncUdpSocket, Runtime};
+use serde::{Deserialize, Serialize};
+use std::io::{self, IoSliceMut};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use super::peer::{canonical, client_key};
+use crate::ai::AiSecurityModule;
+
+/// Smallest datagram a client may carry an Initial packet in (RFC 9000 §14.1)
+const MIN_INITIAL_SIZE: usize = 1200;
+
+const QUIC_V2: u32 = 0x6b33_43cf;
+
+/// How long refused handshakes count toward a ban, and an idle client is remembered
+const CLIENT_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long a connection attempt is remembered, so its later Initials don't count as new connections
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Initials one attempt may send before the next counts as a new connection
+/// A handshake needs a few (retransmits, ACKs, the Initial after a Retry); more is someone reusing the ID.
+const INITIALS_PER_ATTEMPT: u32 = 16;
+
+/// Connection attempts remembered at once; past this every Initial counts as a new connection
+const MAX_ATTEMPTS: usize = 65_536;
+
+/// When clients must prove their address with a Retry before the server does any TLS work
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressValidation {
+    /// While new connections arrive faster than `validation_threshold`
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// `[quic]`: handshake flood protection for HTTP/3 listeners
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuicGuardConfig {
+    pub address_validation: AddressValidation,
+
+    /// New connections per second across the node at which `auto` validation turns on
+    /// It turns off again once they fall below half of this.
+    pub validation_threshold: u32,
+
+    /// New connections one client IP may start per second (0 = unlimited)
+    pub handshakes_per_ip_per_sec: u32,
+
+    /// New connections one client IP may start at once (0 = twice the rate)
+    pub handshake_burst: u32,
+
+    /// Handshakes refused in a minute after which the client IP is banned (0 = never)
+    pub ban_after_rejected: u32,
+}
+
+impl Default for QuicGuardConfig {
+    fn default() -> Self {
+        Self {
+            address_validation: AddressValidation::Auto,
+            validation_threshold: 1000,
+            handshakes_per_ip_per_sec: 20,
+            handshake_burst: 0,
+            ban_after_rejected: 1000,
+        }
+    }
+}
+
+impl QuicGuardConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.address_validation == AddressValidation::Auto && self.validation_threshold == 0 {
+            bail!("validation_threshold must be at least 1 when address_validation = \"auto\"");
+        }
+        Ok(())
+    }
+
+    fn burst(&self) -> f64 {
+        match self.handshake_burst {
+            0 => self.handshakes_per_ip_per_sec as f64 * 2.0,
+            burst => burst as f64,
+        }
+    }
+}
+
+/// Why a handshake was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeRejection {
+    /// The client is on the ban list
+    Banned,
+    /// The client started connections faster than `handshakes_per_ip_per_sec`
+    RateLimited,
+    /// The datagram was too small to open a connection with, as in amplification attempts
+    Undersized,
+    /// The client already held `max_connections_per_ip` connections
+    ConnectionLimit,
+    /// The listener had `accept_backlog` handshakes in flight
+    Backlog,
+}
+
+impl HandshakeRejection {
+    pub const ALL: [HandshakeRejection; 5] = [
+        HandshakeRejection::Banned,
+        HandshakeRejection::RateLimited,
+        HandshakeRejection::Undersized,
+        HandshakeRejection::ConnectionLimit,
+        HandshakeRejection::Backlog,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeRejection::Banned => "banned",
+            HandshakeRejection::RateLimited => "rate_limited",
+            HandshakeRejection::Undersized => "undersized",
+            HandshakeRejection::ConnectionLimit => "connection_limit",
+            HandshakeRejection::Backlog => "backlog",
+        }
+    }
+}
+
+/// Refused handshakes and the guard's current state
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QuicGuardStats {
+    /// Refused handshakes by reason, in the order of `HandshakeRejection::ALL`
+    pub rejected: [u64; 5],
+    /// Clients banned for starting too many connections
+    pub bans: u64,
+    /// New connections in the last full second
+    pub handshakes_per_sec: u64,
+    /// Whether clients must currently validate their address
+    pub address_validation: bool,
+}
+
+impl QuicGuardStats {
+    pub fn rejected(&self, reason: HandshakeRejection) -> u64 {
+        self.rejected[reason as usize]
+    }
+}
+
+/// New connections counted per second
+#[derive(Default)]
+struct HandshakeRate {
+    second: u64,
+    current: u64,
+    previous: u64,
+}
+
+impl HandshakeRate {
+    fn roll(&mut self, second: u64) {
+        if second != self.second {
+            self.previous = if second == self.second + 1 { self.current } else { 0 };
+            self.current = 0;
+            self.second = second;
+        }
+    }
+}
+
+/// A client IP's handshake allowance
+struct ClientHandshakes {
+    tokens: f64,
+    updated: Instant,
+    rejected: u32,
+    window_start: Instant,
+}
+
+impl ClientHandshakes {
+    /// Spend one handshake, if the client has one left
+    fn take(&mut self, rate: f64, burst: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Count a refused handshake, returning how many were refused this window
+    fn refuse(&mut self, now: Instant) -> u32 {
+        if now.saturating_duration_since(self.window_start) >= CLIENT_WINDOW {
+            self.window_start = now;
+            self.rejected = 0;
+        }
+        self.rejected += 1;
+        self.rejected
+    }
+}
+
+/// A connection attempt, named by the client's address and its source connection ID
+/// Both stay the same for every Initial of one connection, whichever destination ID it carries.
+struct Attempt {
+    admitted: bool,
+    started: Instant,
+    initials: u32,
+}
+
+/// Decides which new connections reach the QUIC endpoints, shared by every HTTP/3 listener
+pub struct QuicGuard {
+    config: QuicGuardConfig,
+    clients: DashMap<IpAddr, ClientHandshakes>,
+    attempts: DashMap<(SocketAddr, Vec<u8>), Attempt>,
+    rate: Mutex<HandshakeRate>,
+    validating: AtomicBool,
+    rejected: [AtomicU64; 5],
+    bans: AtomicU64,
+    security: Option<Arc<AiSecurityModule>>,
+    started: Instant,
+}
+
+impl QuicGuard {
+    pub fn new(config: QuicGuardConfig) -> Self {
+        Self {
+            validating: AtomicBool::new(config.address_validation == AddressValidation::Always),
+            config,
+            clients: DashMap::new(),
+            attempts: DashMap::new(),
+            rate: Mutex::new(HandshakeRate::default()),
+            rejected: Default::default(),
+            bans: AtomicU64::new(0),
+            security: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Refuse banned clients, spare allowlisted ones, and ban clients that keep getting refused
+    pub fn with_security(mut self, security: Arc<AiSecurityModule>) -> Self {
+        self.security = Some(security);
+        self
+    }
+
+    /// Whether a datagram from `from` may reach the endpoint
+    /// Only Initial packets are checked, and only the first of each connection attempt is counted;
+    /// the rest get the same answer.
+    pub fn admit(&self, from: SocketAddr, datagram: &[u8]) -> bool {
+        self.admit_at(from, datagram, Instant::now())
+    }
+
+    fn admit_at(&self, from: SocketAddr, datagram: &[u8], now: Instant) -> bool {
+        if !is_initial(datagram) {
+            return true;
+        }
+        // Quinn drops these too, but only after parsing them
+        if datagram.len() < MIN_INITIAL_SIZE {
+            self.record(HandshakeRejection::Undersized);
+            return false;
+        }
+
+        let from = canonical(from);
+        let key = initial_scid(datagram).map(|scid| (from, scid.to_vec()));
+        if let Some(key) = &key {
+            if let Some(mut attempt) = self.attempts.get_mut(key) {
+                if now.saturating_duration_since(attempt.started) < ATTEMPT_WINDOW && attempt.initials < INITIALS_PER_ATTEMPT {
+                    attempt.initials += 1;
+                    return attempt.admitted;
+                }
+            }
+        }
+
+        let admitted = self.admit_new(from.ip(), now);
+        if let Some(key) = key {
+            if self.attempts.len() < MAX_ATTEMPTS || self.attempts.contains_key(&key) {
+                self.attempts.insert(key, Attempt { admitted, started: now, initials: 1 });
+            }
+        }
+        admitted
+    }
+
+    /// Whether a client may start a new connection
+    fn admit_new(&self, ip: IpAddr, now: Instant) -> bool {
+        {
+            let mut rate = self.rate.lock();
+            rate.roll(self.second(now));
+            rate.current += 1;
+        }
+
+        if let Some(security) = &self.security {
+            if security.allowlist().allows(ip) {
+                return true;
+            }
+            if security.path_monitor().is_banned(ip) {
+                self.record(HandshakeRejection::Banned);
+                return false;
+            }
+        }
+        if self.config.handshakes_per_ip_per_sec == 0 {
+            return true;
+        }
+
+        let rate = self.config.handshakes_per_ip_per_sec as f64;
+        let burst = self.config.burst();
+        let rejected = {
+            let mut client = self.clients.entry(client_key(ip)).or_insert_with(|| ClientHandshakes {
+                tokens: burst,
+                updated: now,
+                rejected: 0,
+                window_start: now,
+            });
+            if client.take(rate, burst, now) {
+                return true;
+            }
+            client.refuse(now)
+        };
+        self.record(HandshakeRejection::RateLimited);
+        if self.config.ban_after_rejected > 0 && rejected >= self.config.ban_after_rejected {
+            self.ban(ip, rejected);
+        }
+        false
+    }
+
+    /// Count a handshake refused here or by the listener
+    pub fn record(&self, reason: HandshakeRejection) {
+        self.rejected[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn ban(&self, ip: IpAddr, rejected: u32) {
+        let Some(security) = &self.security else {
+            return;
+        };
+        self.clients.remove(&client_key(ip));
+        security.ban_handshake_flooder(ip, rejected);
+        self.bans.fetch_add(1, Ordering::Relaxed);
+        warn!(ip = %ip, rejected = rejected, "Client banned for flooding QUIC handshakes");
+    }
+
+    /// Whether new connections must validate their address now
+    pub fn address_validation(&self) -> bool {
+        self.address_validation_at(Instant::now())
+    }
+
+    fn address_validation_at(&self, now: Instant) -> bool {
+        match self.config.address_validation {
+            AddressValidation::Always => true,
+            AddressValidation::Never => false,
+            AddressValidation::Auto => {
+                let per_sec = self.handshakes_per_sec(now);
+                let threshold = self.config.validation_threshold as u64;
+                let on = self.validating.load(Ordering::Relaxed);
+                let next = if on { per_sec * 2 >= threshold } else { per_sec >= threshold };
+                if next != on && self.validating.compare_exchange(on, next, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+                    if next {
+                        warn!(handshakes_per_sec = per_sec, "QUIC handshake flood; requiring address validation");
+                    } else {
+                        info!(handshakes_per_sec = per_sec, "QUIC handshakes back to normal; address validation off");
+                    }
+                }
+                next
+            }
+        }
+    }
+
+    fn handshakes_per_sec(&self, now: Instant) -> u64 {
+        let mut rate = self.rate.lock();
+        rate.roll(self.second(now));
+        rate.previous
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.started).as_secs()
+    }
+
+    /// Forget clients that have not started a connection in a while
+    pub fn cleanup(&self) {
+        let now = Instant::now();
+        self.clients.retain(|_, client| now.saturating_duration_since(client.updated) < CLIENT_WINDOW);
+        self.attempts.retain(|_, attempt| now.saturating_duration_since(attempt.started) < ATTEMPT_WINDOW);
+    }
+
+    pub fn stats(&self) -> QuicGuardStats {
+        QuicGuardStats {
+            rejected: std::array::from_fn(|i| self.rejected[i].load(Ordering::Relaxed)),
+            bans: self.bans.load(Ordering::Relaxed),
+            handshakes_per_sec: self.handshakes_per_sec(Instant::now()),
+            address_validation: self.validating.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Whether a datagram starts with a QUIC Initial packet, which every connection opens with
+fn is_initial(datagram: &[u8]) -> bool {
+    if datagram.len() < 5 || datagram[0] & 0x80 == 0 {
+        return false;
+    }
+    let packet_type = (datagram[0] >> 4) & 0x03;
+    match u32::from_be_bytes([datagram[1], datagram[2], datagram[3], datagram[4]]) {
+        // Version negotiation
+        0 => false,
+        QUIC_V2 => packet_type == 1,
+        _ => packet_type == 0,
+    }
+}
+
+/// The source connection ID of an Initial packet, or None if the header is malformed
+fn initial_scid(datagram: &[u8]) -> Option<&[u8]> {
+    let dcid_len = *datagram.get(5)? as usize;
+    let scid_at = 6 + dcid_len;
+    let scid_len = *datagram.get(scid_at)? as usize;
+    if dcid_len > 20 || scid_len > 20 {
+        return None;
+    }
+    datagram.get(scid_at + 1..scid_at + 1 + scid_len)
+}
+
+/// A UDP socket whose received datagrams pass the guard before the endpoint sees them
+pub struct GuardedSocket {
+    inner: Box<dyn AsyncUdpSocket>,
+    guard: Arc<QuicGuard>,
+}
+
+impl GuardedSocket {
+    pub fn new(socket: std::net::UdpSocket, guard: Arc<QuicGuard>) -> io::Result<Self> {
+        Ok(Self {
+            inner: quinn::TokioRuntime.wrap_udp_socket(socket)?,
+            guard,
+        })
+    }
+}
+
+impl std::fmt::Debug for GuardedSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuardedSocket").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl AsyncUdpSocket for GuardedSocket {
+    fn poll_send(&self, state: &UdpState, cx: &mut Context, transmits: &[Transmit]) -> Poll<io::Result<usize>> {
+        self.inner.poll_send(state, cx, transmits)
+    }
+
+    fn poll_recv(&self, cx: &mut Context, bufs: &mut [IoSliceMut<'_>], meta: &mut [RecvMeta]) -> Poll<io::Result<usize>> {
+        let received = ready!(self.inner.poll_recv(cx, bufs, meta))?;
+        for (meta, buf) in meta.iter_mut().zip(bufs.iter()).take(received) {
+            // Coalesced datagrams all come from one sender; the first decides for the rest.
+            // Quinn skips a dropped entry because it has nothing left to read.
+            let first = match meta.stride {
+                0 => meta.len,
+                stride => stride.min(meta.len),
+            };
+            if !self.guard.admit(meta.addr, &buf[..first]) {
+                meta.len = 0;
+            }
+        }
+        Poll::Ready(Ok(received))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{AiConfig, AiSecurityModule};
+
+    /// An Initial opening a new connection
+    fn initial(len: usize) -> Vec<u8> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        initial_with(len, &[1; 8], &NEXT.fetch_add(1, Ordering::Relaxed).to_be_bytes())
+    }
+
+    fn initial_with(len: usize, dcid: &[u8], scid: &[u8]) -> Vec<u8> {
+        let mut datagram = vec![0u8; len];
+        datagram[0] = 0xc0;
+        datagram[1..5].copy_from_slice(&1u32.to_be_bytes());
+        datagram[5] = dcid.len() as u8;
+        datagram[6..6 + dcid.len()].copy_from_slice(dcid);
+        datagram[6 + dcid.len()] = scid.len() as u8;
+        datagram[7 + dcid.len()..7 + dcid.len() + scid.len()].copy_from_slice(scid);
+        datagram
+    }
+
+    #[test]
+    fn test_one_handshake_counted_once() {
+        let guard = QuicGuard::new(QuicGuardConfig {
+            validation_threshold: 2,
+            handshakes_per_ip_per_sec: 1,
+            handshake_burst: 1,
+            ..Default::default()
+        });
+        let from: SocketAddr = "203.0.113.1:4433".parse().unwrap();
+        let start = guard.started;
+
+        // A retransmit, an ACK-only Initial to the server's ID, and the Initial after a Retry
+        assert!(guard.admit_at(from, &initial_with(1200, &[1; 8], &[7; 8]), start));
+        assert!(guard.admit_at(from, &initial_with(1200, &[1; 8], &[7; 8]), start));
+        assert!(guard.admit_at(from, &initial_with(1200, &[2; 8], &[7; 8]), start));
+        assert!(guard.admit_at(from, &initial_with(1200, &[3; 16], &[7; 8]), start));
+        assert_eq!(guard.handshakes_per_sec(start + Duration::from_secs(1)), 1);
+
+        // A second connection spends its own allowance, and its retransmits aren't refused again
+        assert!(!guard.admit_at(from, &initial_with(1200, &[4; 8], &[8; 8]), start));
+        assert!(!guard.admit_at(from, &initial_with(1200, &[4; 8], &[8; 8]), start));
+        assert_eq!(guard.stats().rejected(HandshakeRejection::RateLimited), 1);
+
+        // Reusing one ID for more than a handshake's worth of Initials counts again
+        let later = start + Duration::from_secs(2);
+        for _ in 0..=INITIALS_PER_ATTEMPT {
+            guard.admit_at(from, &initial_with(1200, &[5; 8], &[9; 8]), later);
+        }
+        assert_eq!(guard.handshakes_per_sec(later + Duration::from_secs(1)), 2);
+    }
+
+    #[test]
+    fn test_only_initial_packets_checked() {
+        let guard = QuicGuard::new(QuicGuardConfig { handshakes_per_ip_per_sec: 1, handshake_burst: 1, ..Default::default() });
+        let from: SocketAddr = "203.0.113.1:4433".parse().unwrap();
+
+        assert!(guard.admit(from, &initial(1200)));
+        assert!(!guard.admit(from, &initial(1200)));
+        // Short-header and Handshake packets belong to connections already admitted
+        assert!(guard.admit(from, &[0x40; 64]));
+        let mut handshake = initial(1200);
+        handshake[0] = 0xe0;
+        assert!(guard.admit(from, &handshake));
+
+        assert!(!guard.admit("203.0.113.2:4433".parse().unwrap(), &initial(100)));
+        let stats = guard.stats();
+        assert_eq!(stats.rejected(HandshakeRejection::RateLimited), 1);
+        assert_eq!(stats.rejected(HandshakeRejection::Undersized), 1);
+    }
+
+    #[tokio::test]
+    async fn test_flooder_banned() {
+        let security = Arc::new(AiSecurityModule::new(AiConfig::default()).unwrap());
+        let guard = QuicGuard::new(QuicGuardConfig {
+            handshakes_per_ip_per_sec: 1,
+            handshake_burst: 1,
+            ban_after_rejected: 3,
+            ..Default::default()
+        })
+        .with_security(security.clone());
+        let flooder: SocketAddr = "[2001:db8::1]:4433".parse().unwrap();
+        let neighbour: SocketAddr = "[2001:db8::2]:4433".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(guard.admit_at(flooder, &initial(1200), now));
+        // The /64 shares one allowance
+        assert!(!guard.admit_at(neighbour, &initial(1200), now));
+        assert!(!guard.admit_at(flooder, &initial(1200), now));
+        assert!(!guard.admit_at(flooder, &initial(1200), now));
+        assert!(security.path_monitor().is_banned(flooder.ip()));
+        assert_eq!(guard.stats().bans, 1);
+
+        // Banned clients are refused even with allowance to spare
+        assert!(!guard.admit_at(flooder, &initial(1200), now + Duration::from_secs(5)));
+        assert_eq!(guard.stats().rejected(HandshakeRejection::Banned), 1);
+    }
+
+    #[test]
+    fn test_auto_address_validation() {
+        let guard = QuicGuard::new(QuicGuardConfig {
+            validation_threshold: 4,
+            handshakes_per_ip_per_sec: 0,
+            ..Default::default()
+        });
+        let from: SocketAddr = "203.0.113.1:4433".parse().unwrap();
+        let start = guard.started;
+
+        for _ in 0..4 {
+            guard.admit_at(from, &initial(1200), start);
+        }
+        assert!(!guard.address_validation_at(start));
+        assert!(guard.address_validation_at(start + Duration::from_secs(1)));
+
+        // Stays on until the rate falls below half the threshold
+        for _ in 0..2 {
+            guard.admit_at(from, &initial(1200), start + Duration::from_secs(1));
+        }
+        assert!(guard.address_validation_at(start + Duration::from_secs(2)));
+        assert!(!guard.address_validation_at(start + Duration::from_secs(4)));
+
+        let always = QuicGuard::new(QuicGuardConfig { address_validation: AddressValidation::Always, ..Default::default() });
+        assert!(always.address_validation());
+    }
+}
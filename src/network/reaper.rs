@@ -0,0 +1,138 @@
+// Connection Reaper
+// Retires connections that sit idle too long, have served their request allowance, or are shed to rebalance
+
+use crate::router::limits::LimitsConfig;
+use crate::state::{GlobalState, ReapReason};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Tracks one connection's requests and says when to close it
+pub struct ConnectionReaper {
+    state: GlobalState,
+    conn_id: u64,
+    idle_timeout: Option<Duration>,
+
+    /// 0 = unlimited
+    max_requests: u64,
+    exhausted: Notify,
+
+    /// Signalled by `GlobalState::shed_connection`
+    shed: Option<Arc<Notify>>,
+}
+
+impl ConnectionReaper {
+    /// Without configured limits the defaults apply
+    pub fn new(state: GlobalState, conn_id: u64, limits: Option<&LimitsConfig>) -> Self {
+        let defaults = LimitsConfig::default();
+        let limits = limits.unwrap_or(&defaults);
+        let shed = state.shed_signal(conn_id);
+        Self {
+            state,
+            conn_id,
+            idle_timeout: limits.idle_timeout(),
+            max_requests: limits.max_requests_per_connection,
+            exhausted: Notify::new(),
+            shed,
+        }
+    }
+
+    /// Count a request; it is in flight until the returned guard is dropped
+    /// A guard also covers requests the client cancels before they finish.
+    pub fn request_started(self: &Arc<Self>) -> InFlightRequest {
+        let served = self.state.begin_request(self.conn_id);
+        if self.max_requests > 0 && served >= self.max_requests {
+            self.exhausted.notify_one();
+        }
+        InFlightRequest { reaper: self.clone() }
+    }
+
+    /// Resolves once the connection should be shut down gracefully, counting why
+    pub async fn retired(&self) -> ReapReason {
+        let reason = tokio::select! {
+            _ = self.exhausted.notified() => ReapReason::RequestLimit,
+            _ = self.idle() => ReapReason::Idle,
+            _ = self.shed() => ReapReason::Shed,
+        };
+        self.state.record_reaped(reason);
+        reason
+    }
+
+    async fn shed(&self) {
+        match &self.shed {
+            Some(shed) => shed.notified().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn idle(&self) {
+        let Some(timeout) = self.idle_timeout else {
+            return std::future::pending().await;
+        };
+        loop {
+            // A busy connection is looked at again a full timeout later
+            let idle = self.state.idle_for(self.conn_id).unwrap_or_default();
+            if idle >= timeout {
+                return;
+            }
+            tokio::time::sleep(timeout - idle).await;
+        }
+    }
+}
+
+/// A request counted against its connection
+pub struct InFlightRequest {
+    reaper: Arc<ConnectionReaper>,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.reaper.state.end_request(self.reaper.conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{ConnectionMetadata, Protocol};
+
+    fn connection(limits: &LimitsConfig) -> (GlobalState, Arc<ConnectionReaper>) {
+        let state = GlobalState::new();
+        state.register_connection(1, ConnectionMetadata::new(Protocol::Http2, "192.0.2.1:5000".to_string()));
+        let reaper = Arc::new(ConnectionReaper::new(state.clone(), 1, Some(limits)));
+        (state, reaper)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reaps_idle_connection() {
+        let limits = LimitsConfig { idle_timeout_secs: 30, ..Default::default() };
+        let (state, reaper) = connection(&limits);
+        let request = reaper.request_started();
+
+        // Nothing is reaped while a request is in flight
+        assert!(tokio::time::timeout(Duration::from_secs(60), reaper.retired()).await.is_err());
+
+        drop(request);
+        assert_eq!(reaper.retired().await, ReapReason::Idle);
+        assert_eq!(state.connection_report().reaped_idle, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retires_after_request_limit() {
+        let limits = LimitsConfig { max_requests_per_connection: 2, ..Default::default() };
+        let (_state, reaper) = connection(&limits);
+        let _first = reaper.request_started();
+        let _second = reaper.request_started();
+        assert_eq!(reaper.retired().await, ReapReason::RequestLimit);
+    }
+
+    #[tokio::test]
+    async fn test_retires_shed_connection() {
+        let (state, reaper) = connection(&LimitsConfig::default());
+        // Shedding before the connection waits still retires it
+        assert!(state.shed_connection(1));
+        assert_eq!(reaper.retired().await, ReapReason::Shed);
+        assert_eq!(state.connection_report().reaped_shed, 1);
+        assert!(!state.shed_connection(2));
+    }
+}
@@ -0,0 +1,188 @@
+// Connection Rebalancing
+// Sheds a node's connections a few at a time over a window, so clients move to other nodes before maintenance
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::observability::health::Health;
+use crate::state::GlobalState;
+
+/// How often a share of the remaining connections is shed
+const TICK: Duration = Duration::from_secs(1);
+
+/// Longest window shedding may be spread over
+pub const MAX_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+/// Where a rebalance is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RebalancePhase {
+    /// Taking traffic as usual
+    #[default]
+    Idle,
+
+    /// Closing connections over the window
+    Shedding,
+
+    /// Every connection was shed; the node stays unready until cancelled
+    Drained,
+}
+
+/// Progress of the current or last rebalance
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RebalanceStatus {
+    pub phase: RebalancePhase,
+
+    /// Unix time (seconds)
+    pub started_at: Option<i64>,
+
+    pub window_secs: u64,
+
+    /// Connections asked to close so far
+    pub shed: u64,
+
+    /// Connections still open on the node
+    pub open: usize,
+}
+
+/// Sheds connections gracefully: HTTP/2 clients get GOAWAY, HTTP/3 clients CONNECTION_CLOSE
+/// Readiness fails meanwhile, so load balancers and anycast health checks send new clients elsewhere.
+pub struct Rebalancer {
+    state: GlobalState,
+    health: Arc<Health>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    status: Mutex<RebalanceStatus>,
+}
+
+impl Rebalancer {
+    pub fn new(state: GlobalState, health: Arc<Health>) -> Self {
+        Self {
+            state,
+            health,
+            task: Mutex::new(None),
+            status: Mutex::new(RebalanceStatus::default()),
+        }
+    }
+
+    /// Start shedding every open connection over `window`, restarting a rebalance in progress
+    pub fn start(self: &Arc<Self>, window: Duration) -> Result<RebalanceStatus> {
+        if !(TICK..=MAX_WINDOW).contains(&window) {
+            bail!("The window must be between {}s and {}s", TICK.as_secs(), MAX_WINDOW.as_secs());
+        }
+
+        let mut task = self.task.lock();
+        if let Some(previous) = task.take() {
+            previous.abort();
+        }
+        self.health.set_rebalancing(true);
+        *self.status.lock() = RebalanceStatus {
+            phase: RebalancePhase::Shedding,
+            started_at: Some(chrono::Utc::now().timestamp()),
+            window_secs: window.as_secs(),
+            shed: 0,
+            open: self.state.connection_count(),
+        };
+        info!(connections = self.state.connection_count(), window_secs = window.as_secs(), "Rebalancing: shedding connections");
+
+        let rebalancer = self.clone();
+        *task = Some(tokio::spawn(async move { rebalancer.shed_over(window).await }));
+        Ok(self.status())
+    }
+
+    /// Stop shedding and take traffic again, returning whether a rebalance was in progress or done
+    pub fn cancel(&self) -> bool {
+        if let Some(task) = self.task.lock().take() {
+            task.abort();
+        }
+        self.health.set_rebalancing(false);
+        let mut status = self.status.lock();
+        let was = status.phase != RebalancePhase::Idle;
+        status.phase = RebalancePhase::Idle;
+        if was {
+            info!(shed = status.shed, "Rebalancing cancelled; taking traffic again");
+        }
+        was
+    }
+
+    pub fn status(&self) -> RebalanceStatus {
+        let mut status = self.status.lock().clone();
+        status.open = self.state.connection_count();
+        status
+    }
+
+    /// Shed an even share of the connections not yet shed every tick, so the last goes at the deadline
+    /// Connections opened meanwhile are shed too.
+    async fn shed_over(&self, window: Duration) {
+        let deadline = Instant::now() + window;
+        let mut shed = HashSet::new();
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let open = self.state.connection_ids();
+            // Connections that finished closing are forgotten
+            let still_open: HashSet<u64> = open.iter().copied().collect();
+            shed.retain(|id| still_open.contains(id));
+
+            let pending: Vec<u64> = open.into_iter().filter(|id| !shed.contains(id)).collect();
+            let ticks_left = deadline.saturating_duration_since(now).as_millis().div_ceil(TICK.as_millis()).max(1);
+            let count = (pending.len() as u128).div_ceil(ticks_left) as usize;
+            let mut newly_shed = 0;
+            for id in pending.into_iter().take(count) {
+                if self.state.shed_connection(id) {
+                    shed.insert(id);
+                    newly_shed += 1;
+                }
+            }
+            self.status.lock().shed += newly_shed;
+
+            if now >= deadline {
+                break;
+            }
+        }
+
+        let mut status = self.status.lock();
+        status.phase = RebalancePhase::Drained;
+        info!(shed = status.shed, "Rebalancing done; the node stays unready until cancelled");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::health::HealthConfig;
+    use crate::state::{ConnectionMetadata, Protocol};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sheds_connections_over_the_window() {
+        let state = GlobalState::new();
+        let health = Arc::new(Health::new(HealthConfig::default(), None));
+        for id in 1..=10 {
+            state.register_connection(id, ConnectionMetadata::new(Protocol::Http2, format!("192.0.2.{}:5000", id)));
+        }
+        let signals: Vec<_> = (1..=10).map(|id| state.shed_signal(id).unwrap()).collect();
+        let rebalancer = Arc::new(Rebalancer::new(state.clone(), health));
+
+        assert!(rebalancer.start(Duration::ZERO).is_err());
+        rebalancer.start(Duration::from_secs(5)).unwrap();
+        // The first tick sheds a fifth of the connections, oldest first
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(rebalancer.status().shed, 2);
+        assert!(tokio::time::timeout(Duration::ZERO, signals[0].notified()).await.is_ok());
+
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        let status = rebalancer.status();
+        assert_eq!((status.phase, status.shed, status.open), (RebalancePhase::Drained, 10, 10));
+
+        assert!(rebalancer.cancel());
+        assert_eq!(rebalancer.status().phase, RebalancePhase::Idle);
+        assert!(!rebalancer.cancel());
+    }
+}
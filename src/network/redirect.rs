@@ -0,0 +1,190 @@
+// HTTPS Redirect Listener
+// Answers plain-HTTP requests with a permanent redirect to the HTTPS origin
+
+use crate::signals::ShutdownCoordinator;
+use crate::tenancy::domains::{self, DomainManager, HostRoute};
+use super::acceptor::AcceptorMetrics;
+use anyhow::Result;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::http::uri::Authority;
+use hyper::{Request, Response, StatusCode, header};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Serve redirects on pre-bound listeners until shutdown
+/// Speaks HTTP/1.1 as well as HTTP/2, since browsers try plain HTTP over 1.1.
+/// Domain challenges are answered here too, since they're fetched over plain HTTP.
+pub async fn serve(
+    listeners: Vec<std::net::TcpListener>,
+    https_port: u16,
+    proxy_protocol: bool,
+    domains: Option<Arc<DomainManager>>,
+    metrics: Arc<AcceptorMetrics>,
+    shutdown: Arc<ShutdownCoordinator>,
+) -> Result<()> {
+    info!(https_port, shards = listeners.len(), "HTTPS redirect listener started");
+
+    let mut shards = Vec::with_capacity(listeners.len());
+    for (shard, listener) in listeners.into_iter().enumerate() {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        shards.push(tokio::spawn(accept_loop(shard, listener, https_port, proxy_protocol, domains.clone(), metrics.clone(), shutdown.clone())));
+    }
+
+    futures::future::join_all(shards).await;
+    Ok(())
+}
+
+async fn accept_loop(
+    shard: usize,
+    listener: tokio::net::TcpListener,
+    https_port: u16,
+    proxy_protocol: bool,
+    domains: Option<Arc<DomainManager>>,
+    metrics: Arc<AcceptorMetrics>,
+    shutdown: Arc<ShutdownCoordinator>,
+) {
+    let mut shutdown_rx = shutdown.subscribe();
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown_rx.recv() => {
+                debug!(shard = shard, "Redirect acceptor stopped");
+                break;
+            }
+        };
+
+        let (mut stream, peer_addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                metrics.record_error(shard);
+                debug!(shard = shard, error = %e, "Failed to accept redirect connection");
+                continue;
+            }
+        };
+        metrics.record_accept(shard);
+
+        let metrics = metrics.clone();
+        let domains = domains.clone();
+        let mut connection_shutdown = shutdown.subscribe();
+        metrics.connection_opened();
+        tokio::spawn(async move {
+            // The header has to be consumed before HTTP; the address it names is only logged
+            let peer_addr = if proxy_protocol {
+                match super::proxy_protocol::client_addr(&mut stream, peer_addr).await {
+                    Ok(client) => client,
+                    Err(e) => {
+                        debug!(peer = %peer_addr, error = %e, "Invalid PROXY protocol header, closing");
+                        metrics.connection_closed();
+                        return;
+                    }
+                }
+            } else {
+                peer_addr
+            };
+            let service = hyper::service::service_fn(move |req: Request<Incoming>| {
+                let response = redirect_response(&req, https_port, domains.as_deref());
+                async move { Ok::<_, std::convert::Infallible>(response) }
+            });
+
+            let builder = hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
+            let connection = builder.serve_connection(hyper_util::rt::TokioIo::new(stream), service);
+            tokio::pin!(connection);
+
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = connection_shutdown.recv() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                debug!(peer = %peer_addr, error = %e, "Redirect connection error");
+            }
+            metrics.connection_closed();
+        });
+    }
+}
+
+/// 308 to the same host and path over HTTPS, or 400 without a usable host
+/// Domains that redirect elsewhere go straight to their target.
+fn redirect_response<B>(req: &Request<B>, https_port: u16, domains: Option<&DomainManager>) -> Response<Full<Bytes>> {
+    let route = domains.and_then(|domains| domains.route(domains::request_host(req)?, req.uri().path()));
+    let location = match route {
+        Some(HostRoute::Challenge(token)) => return domains::challenge_response(token),
+        Some(HostRoute::Redirect(target)) => Some(https_url(&target, req, https_port)),
+        _ => https_location(req, https_port),
+    };
+    match location {
+        Some(location) => Response::builder()
+            .status(StatusCode::PERMANENT_REDIRECT)
+            .header(header::LOCATION, location)
+            .body(Full::new(Bytes::new()))
+            .unwrap(),
+        None => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Full::new(Bytes::from("Missing Host header")))
+            .unwrap(),
+    }
+}
+
+/// HTTPS URL for a request, keeping its host, path and query
+fn https_location<B>(req: &Request<B>, https_port: u16) -> Option<String> {
+    // HTTP/2 carries the host in :authority, HTTP/1.1 in the Host header
+    let authority = match req.uri().authority() {
+        Some(authority) => authority.clone(),
+        None => req.headers().get(header::HOST)?.to_str().ok()?.parse::<Authority>().ok()?,
+    };
+
+    Some(https_url(authority.host(), req, https_port))
+}
+
+fn https_url<B>(host: &str, req: &Request<B>, https_port: u16) -> String {
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    if https_port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, https_port, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str, host: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(host) = host {
+            builder = builder.header(header::HOST, host);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_https_location() {
+        let req = request("/blog/post?id=7", Some("example.com:8080"));
+        assert_eq!(https_location(&req, 443).as_deref(), Some("https://example.com/blog/post?id=7"));
+        assert_eq!(https_location(&req, 8443).as_deref(), Some("https://example.com:8443/blog/post?id=7"));
+
+        let req = request("http://[::1]:8080/", None);
+        assert_eq!(https_location(&req, 443).as_deref(), Some("https://[::1]/"));
+
+        let req = request("/", None);
+        assert_eq!(redirect_response(&req, 443, None).status(), StatusCode::BAD_REQUEST);
+        let req = request("/", Some("bad host"));
+        assert!(https_location(&req, 443).is_none());
+    }
+
+    #[test]
+    fn test_answers_domain_challenges() {
+        let domains = DomainManager::new();
+        let domain = domains.claim(uuid::Uuid::new_v4(), "blog", "example.com", domains::VerificationMethod::Http, None).unwrap();
+        let req = request(&format!("{}{}", domains::CHALLENGE_PATH, domain.token), Some("example.com"));
+        assert_eq!(redirect_response(&req, 443, Some(&domains)).status(), StatusCode::OK);
+
+        // Anything else still goes to HTTPS
+        let req = request("/", Some("example.com"));
+        assert_eq!(redirect_response(&req, 443, Some(&domains)).status(), StatusCode::PERMANENT_REDIRECT);
+    }
+}
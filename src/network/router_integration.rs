@@ -2,44 +2,130 @@
 // Extended version that routes requests through the Router
 
 use crate::router::Router;
-use super::{NetworkConfig, http2};
+use crate::signals::ShutdownCoordinator;
+use super::{IoBackend, NetworkConfig, http2, proxy_protocol, tls};
+use super::acceptor::AcceptorMetrics;
+use super::conn_limit::ConnectionLimiter;
 use anyhow::Result;
 use std::sync::Arc;
-use tracing::{info};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, debug, warn};
 
 /// Start HTTP/2 server with Router integration
+/// Listeners are bound up front (see `acceptor::bind_std_tcp_shards`) so privileges can be dropped first
 pub async fn serve_with_router(
     config: NetworkConfig,
+    listeners: Vec<std::net::TcpListener>,
     router: Arc<Router>,
+    metrics: Arc<AcceptorMetrics>,
+    shutdown: Arc<ShutdownCoordinator>,
 ) -> Result<()> {
     info!("Starting HTTP/2 server with Router integration");
-    
+
+    if config.io_backend == IoBackend::Uring {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if super::uring::is_available() && !config.tls && !config.proxy_protocol {
+            return super::uring::serve_with_router(config, listeners, router, metrics, shutdown).await;
+        }
+
+        warn!("io_uring backend unavailable, falling back to epoll");
+    }
+
     let addr = config.http2_socket_addr();
-    
-    // Create optimized socket
-    let socket = http2::create_optimized_socket(&addr, &config)?;
-    
-    // Convert to TcpListener
-    let std_listener: std::net::TcpListener = socket.into();
-    std_listener.set_nonblocking(true)?;
-    let listener = tokio::net::TcpListener::from_std(std_listener)?;
-    
-    info!("HTTP/2 server (Router mode) listening on {}", addr);
-
-    // Accept loop
+    let tls = if config.tls { Some(tls::acceptor(&config)?) } else { None };
+
+    info!("HTTP/2 server (Router mode) listening on {} ({} acceptor shards, TLS {})",
+        addr, listeners.len(), if tls.is_some() { "on" } else { "off" });
+
+    let limiter = Arc::new(ConnectionLimiter::new(config.max_connections_per_ip));
+    let mut shards = Vec::with_capacity(listeners.len());
+    for (shard, listener) in listeners.into_iter().enumerate() {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let router = router.clone();
+        let metrics = metrics.clone();
+        let shutdown = shutdown.clone();
+        shards.push(tokio::spawn(accept_loop(shard, listener, tls.clone(), config.proxy_protocol, router, limiter.clone(), metrics, shutdown)));
+    }
+
+    futures::future::join_all(shards).await;
+    Ok(())
+}
+
+/// Accept loop for a single listener shard
+/// Stops accepting once shutdown is triggered; open connections are drained gracefully
+async fn accept_loop(
+    shard: usize,
+    listener: tokio::net::TcpListener,
+    tls: Option<TlsAcceptor>,
+    proxy_protocol: bool,
+    router: Arc<Router>,
+    limiter: Arc<ConnectionLimiter>,
+    metrics: Arc<AcceptorMetrics>,
+    shutdown: Arc<ShutdownCoordinator>,
+) {
+    let mut shutdown_rx = shutdown.subscribe();
+
     loop {
-        match listener.accept().await {
-            Ok((stream, peer_addr)) => {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown_rx.recv() => {
+                debug!(shard = shard, "HTTP/2 acceptor stopped");
+                break;
+            }
+        };
+
+        match accepted {
+            Ok((mut stream, peer_addr)) => {
+                metrics.record_accept(shard);
+
                 let router = router.clone();
-                
+                let limiter = limiter.clone();
+                let metrics = metrics.clone();
+                let connection_shutdown = shutdown.subscribe();
+                let tls = tls.clone();
+
                 tokio::spawn(async move {
-                    if let Err(e) = http2::handle_connection_with_router(stream, router,peer_addr).await {
+                    // Behind a load balancer the PROXY header names the client, not the socket
+                    let peer_addr = if proxy_protocol {
+                        match proxy_protocol::client_addr(&mut stream, peer_addr).await {
+                            Ok(client) => client,
+                            Err(e) => {
+                                metrics.record_rejected(shard);
+                                debug!(shard = shard, peer = %peer_addr, error = %e, "Invalid PROXY protocol header, closing");
+                                return;
+                            }
+                        }
+                    } else {
+                        super::peer::canonical(peer_addr)
+                    };
+                    let Some(permit) = limiter.try_acquire(peer_addr.ip()) else {
+                        metrics.record_rejected(shard);
+                        debug!(shard = shard, peer = %peer_addr, "Too many connections from client, closing");
+                        return;
+                    };
+                    debug!(shard = shard, peer = %peer_addr, "Accepted HTTP/2 connection");
+
+                    metrics.connection_opened();
+                    let result = match tls {
+                        Some(tls) => match tls.accept(stream).await {
+                            Ok(stream) => http2::handle_connection_with_router(stream, router, peer_addr, true, connection_shutdown).await,
+                            Err(e) => {
+                                debug!(peer = %peer_addr, error = %e, "TLS handshake failed");
+                                Ok(())
+                            }
+                        },
+                        None => http2::handle_connection_with_router(stream, router, peer_addr, false, connection_shutdown).await,
+                    };
+                    if let Err(e) = result {
                         tracing::error!("HTTP/2 (Router) connection error: {}", e);
                     }
+                    metrics.connection_closed();
+                    drop(permit);
                 });
             }
             Err(e) => {
-                tracing::error!("Failed to accept HTTP/2 connection: {}", e);
+                metrics.record_error(shard);
+                tracing::error!(shard = shard, "Failed to accept HTTP/2 connection: {}", e);
             }
         }
     }
@@ -0,0 +1,244 @@
+// Session Ticket Keys
+// Rotating keys that encrypt TLS 1.3 session tickets, shared by every TLS and QUIC listener
+
+use anyhow::{anyhow, Result};
+use parking_lot::{RwLock, RwLockReadGuard};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::server::ProducesTickets;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Bytes naming the key a ticket was sealed with
+const NAME_LEN: usize = 16;
+
+/// A ticket key and the name tickets carry to find it again
+struct TicketKey {
+    name: [u8; NAME_LEN],
+    key: LessSafeKey,
+}
+
+impl TicketKey {
+    fn generate(rng: &SystemRandom) -> Result<Self> {
+        let mut name = [0u8; NAME_LEN];
+        let mut secret = [0u8; 32];
+        rng.fill(&mut name).map_err(|_| anyhow!("Failed to generate a ticket key"))?;
+        rng.fill(&mut secret).map_err(|_| anyhow!("Failed to generate a ticket key"))?;
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &secret).map_err(|_| anyhow!("Invalid ticket key"))?;
+        secret.fill(0);
+        Ok(Self { name, key: LessSafeKey::new(key) })
+    }
+}
+
+struct Keys {
+    current: TicketKey,
+    /// The key before the last rotation, still accepted so recent tickets resume
+    previous: Option<TicketKey>,
+    rotated_at: Instant,
+}
+
+/// Seals session tickets under a key replaced every `rotation`
+///
+/// Tickets from the previous key are still accepted for one more rotation; older keys are
+/// dropped, so a key stolen later can't decrypt the sessions of past tickets. Keys live only
+/// in memory and rotate when next used, so each node issues its own tickets.
+pub struct TicketKeys {
+    rotation: Duration,
+    keys: RwLock<Keys>,
+    rng: SystemRandom,
+}
+
+impl TicketKeys {
+    pub fn new(rotation: Duration) -> Result<Self> {
+        let rng = SystemRandom::new();
+        Ok(Self {
+            rotation,
+            keys: RwLock::new(Keys {
+                current: TicketKey::generate(&rng)?,
+                previous: None,
+                rotated_at: Instant::now(),
+            }),
+            rng,
+        })
+    }
+
+    /// The keys in use at `now`, rotated first if due
+    fn keys_at(&self, now: Instant) -> RwLockReadGuard<'_, Keys> {
+        {
+            let keys = self.keys.read();
+            if now.saturating_duration_since(keys.rotated_at) < self.rotation {
+                return keys;
+            }
+        }
+        self.rotate(now);
+        self.keys.read()
+    }
+
+    fn rotate(&self, now: Instant) {
+        let mut keys = self.keys.write();
+        let age = now.saturating_duration_since(keys.rotated_at);
+        if age < self.rotation {
+            return;
+        }
+        let fresh = match TicketKey::generate(&self.rng) {
+            Ok(fresh) => fresh,
+            Err(e) => {
+                warn!(error = %e, "Session ticket key rotation failed; keeping the current key");
+                return;
+            }
+        };
+        let retired = std::mem::replace(&mut keys.current, fresh);
+        // Tickets under the retired key were issued over a rotation ago if it idled past its turn
+        keys.previous = (age < self.rotation * 2).then_some(retired);
+        keys.rotated_at = now;
+        debug!("Session ticket key rotated");
+    }
+
+    fn encrypt_at(&self, plain: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).ok()?;
+
+        let keys = self.keys_at(now);
+        let mut sealed = plain.to_vec();
+        keys.current.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&keys.current.name), &mut sealed)
+            .ok()?;
+
+        let mut ticket = Vec::with_capacity(NAME_LEN + NONCE_LEN + sealed.len());
+        ticket.extend_from_slice(&keys.current.name);
+        ticket.extend_from_slice(&nonce);
+        ticket.extend_from_slice(&sealed);
+        Some(ticket)
+    }
+
+    fn decrypt_at(&self, ticket: &[u8], now: Instant) -> Option<Vec<u8>> {
+        if ticket.len() < NAME_LEN + NONCE_LEN + CHACHA20_POLY1305.tag_len() {
+            return None;
+        }
+        let (name, rest) = ticket.split_at(NAME_LEN);
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+
+        let keys = self.keys_at(now);
+        let key = [Some(&keys.current), keys.previous.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|key| key.name[..] == *name)?;
+        let mut plain = sealed.to_vec();
+        let len = key.key
+            .open_in_place(Nonce::try_assume_unique_for_key(nonce).ok()?, Aad::from(name), &mut plain)
+            .ok()?
+            .len();
+        plain.truncate(len);
+        Some(plain)
+    }
+}
+
+impl std::fmt::Debug for TicketKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TicketKeys").field("rotation", &self.rotation).finish_non_exhaustive()
+    }
+}
+
+impl ProducesTickets for TicketKeys {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    /// Tickets are accepted for at least one rotation after they are issued
+    fn lifetime(&self) -> u32 {
+        self.rotation.as_secs().try_into().unwrap_or(u32::MAX)
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.encrypt_at(plain, Instant::now())
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_at(cipher, Instant::now())
+    }
+}
+
+/// The same keys for QUIC listeners, whose quinn release is built on rustls 0.21
+#[derive(Debug)]
+pub struct QuicTickets(pub Arc<TicketKeys>);
+
+impl quinn_rustls::server::ProducesTickets for QuicTickets {
+    fn enabled(&self) -> bool {
+        self.0.enabled()
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.0.lifetime()
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.0.encrypt(plain)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.0.decrypt(cipher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tickets_outlive_one_rotation() {
+        let keys = TicketKeys::new(Duration::from_secs(3600)).unwrap();
+        let start = keys.keys.read().rotated_at;
+        let hour = Duration::from_secs(3600);
+
+        let ticket = keys.encrypt_at(b"session", start).unwrap();
+        assert_eq!(keys.decrypt_at(&ticket, start).unwrap(), b"session");
+
+        // Still resumes under the previous key, and new tickets use the fresh one
+        assert_eq!(keys.decrypt_at(&ticket, start + hour).unwrap(), b"session");
+        let fresh = keys.encrypt_at(b"session", start + hour).unwrap();
+        assert_ne!(fresh[..NAME_LEN], ticket[..NAME_LEN]);
+
+        // Gone after a second rotation
+        assert!(keys.decrypt_at(&ticket, start + hour * 2).is_none());
+        assert_eq!(keys.decrypt_at(&fresh, start + hour * 2).unwrap(), b"session");
+    }
+
+    #[test]
+    fn test_idle_keys_dropped() {
+        let keys = TicketKeys::new(Duration::from_secs(60)).unwrap();
+        let start = keys.keys.read().rotated_at;
+        let ticket = keys.encrypt_at(b"session", start).unwrap();
+
+        // Nothing used the keys for two rotations, so the old one is not kept
+        assert!(keys.decrypt_at(&ticket, start + Duration::from_secs(150)).is_none());
+    }
+
+    #[test]
+    fn test_tampered_tickets_rejected() {
+        let keys = TicketKeys::new(Duration::from_secs(3600)).unwrap();
+        let mut ticket = keys.encrypt(b"session").unwrap();
+        let last = ticket.len() - 1;
+        ticket[last] ^= 1;
+        assert!(keys.decrypt(&ticket).is_none());
+        assert!(keys.decrypt(&ticket[..NAME_LEN]).is_none());
+
+        // Another node's keys can't open them
+        let other = TicketKeys::new(Duration::from_secs(3600)).unwrap();
+        assert!(other.decrypt(&keys.encrypt(b"session").unwrap()).is_none());
+        assert_eq!(keys.lifetime(), 3600);
+    }
+
+    #[test]
+    fn test_quic_shares_tls_tickets() {
+        use quinn_rustls::server::ProducesTickets as QuicProducesTickets;
+
+        let keys = Arc::new(TicketKeys::new(Duration::from_secs(3600)).unwrap());
+        let quic = QuicTickets(keys.clone());
+
+        // A ticket issued over TCP resumes over QUIC, and the other way round
+        assert_eq!(quic.decrypt(&keys.encrypt(b"session").unwrap()).unwrap(), b"session");
+        assert_eq!(keys.decrypt(&quic.encrypt(b"session").unwrap()).unwrap(), b"session");
+        assert_eq!(QuicProducesTickets::lifetime(&quic), 3600);
+    }
+}
@@ -0,0 +1,109 @@
+// TLS for TCP listeners
+// Loads the `[ssl]` PEM certificate, or generates a self-signed one for development
+
+use super::NetworkConfig;
+use anyhow::{Context, Result, bail};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+use tracing::warn;
+
+/// Build the acceptor for a TLS listener, advertising HTTP/2 over ALPN
+pub fn acceptor(config: &NetworkConfig) -> Result<TlsAcceptor> {
+    let (certs, key) = match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => load_pem(cert_path, key_path)?,
+        (None, None) => {
+            warn!("No TLS certificate configured, using a self-signed development certificate");
+            self_signed()?
+        }
+        _ => bail!("ssl.cert_path and ssl.key_path must be set together"),
+    };
+
+    let mut server = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid TLS certificate or key")?;
+    server.alpn_protocols = vec![b"h2".to_vec()];
+    if let Some(tickets) = &config.session_tickets {
+        server.ticketer = tickets.clone();
+    }
+
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
+
+/// Read a certificate chain and private key from PEM files
+pub(crate) fn load_pem(cert_path: &str, key_path: &str) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = load_certificates(cert_path)?;
+
+    let key_pem = std::fs::read(key_path)
+        .with_context(|| format!("Failed to read {}", key_path))?;
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .with_context(|| format!("Failed to parse {}", key_path))?
+        .with_context(|| format!("{} contains no private key", key_path))?;
+
+    Ok((certs, key))
+}
+
+/// Read a certificate chain from a PEM file
+pub(crate) fn load_certificates(cert_path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let cert_pem = std::fs::read(cert_path)
+        .with_context(|| format!("Failed to read {}", cert_path))?;
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse {}", cert_path))?;
+    if certs.is_empty() {
+        bail!("{} contains no certificates", cert_path);
+    }
+    Ok(certs)
+}
+
+/// Subject and validity window of a certificate, in Unix seconds
+pub(crate) struct Validity {
+    pub subject: String,
+    pub not_before: i64,
+    pub not_after: i64,
+}
+
+pub(crate) fn validity(cert: &CertificateDer<'_>) -> Result<Validity> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|e| anyhow::anyhow!("Invalid X.509 certificate: {}", e))?;
+    Ok(Validity {
+        subject: cert.subject().to_string(),
+        not_before: cert.validity().not_before.timestamp(),
+        not_after: cert.validity().not_after.timestamp(),
+    })
+}
+
+fn self_signed() -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+    let key = PrivatePkcs8KeyDer::from(cert.serialize_private_key_der());
+    Ok((vec![CertificateDer::from(cert.serialize_der()?)], key.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pem_certificate_is_loaded() {
+        let cert = rcgen::generate_simple_self_signed(vec!["pear.test".to_string()]).unwrap();
+        let dir = tempfile::TempDir::new().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let config = NetworkConfig {
+            tls_cert_path: Some(cert_path.to_string_lossy().into_owned()),
+            tls_key_path: Some(key_path.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        assert!(acceptor(&config).is_ok());
+
+        let missing_key = NetworkConfig { tls_key_path: None, ..config.clone() };
+        assert!(acceptor(&missing_key).is_err());
+
+        std::fs::write(&key_path, "not a key").unwrap();
+        assert!(acceptor(&config).is_err());
+    }
+}
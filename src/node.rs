@@ -0,0 +1,1187 @@
+// Embedded Pear node
+// Builds and runs every server component in-process, with typed handles for deploying sites
+
+use crate::{
+    ai, cage, chaos, config, crdt, dashboard, deployment, mail, network, notifications, observability, router,
+    runtime, scheduler, signals, state, storage, supervisor, tenancy,
+};
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// What a site added to a [`PearNodeBuilder`] is deployed from
+enum SiteSource {
+    Module(Vec<u8>),
+
+    /// A directory with a `pear.site.toml`
+    Dir(std::path::PathBuf),
+}
+
+/// Builder for a [`PearNode`]
+///
+/// ```no_run
+/// # async fn example(wasm: Vec<u8>) -> anyhow::Result<()> {
+/// let node = pear_server::PearNode::builder()
+///     .with_config(pear_server::PearConfig::default())
+///     .with_site("my-site", wasm)
+///     .start()
+///     .await?;
+/// println!("{:?}", node.stats().await);
+/// node.shutdown(std::time::Duration::from_secs(5)).await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct PearNodeBuilder {
+    config: Option<config::PearConfig>,
+    sites: Vec<(String, SiteSource)>,
+    logs: Option<Arc<observability::logs::LogBuffer>>,
+    drop_privileges: bool,
+    development: bool,
+}
+
+impl PearNodeBuilder {
+    /// Use `config` instead of the defaults
+    pub fn with_config(mut self, config: config::PearConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Deploy `wasm` as `site_id` before the listeners start
+    pub fn with_site(mut self, site_id: impl Into<String>, wasm: impl Into<Vec<u8>>) -> Self {
+        self.sites.push((site_id.into(), SiteSource::Module(wasm.into())));
+        self
+    }
+
+    /// Deploy the site in `dir` as its `pear.site.toml` describes, before the listeners start
+    pub fn with_site_dir(mut self, site_id: impl Into<String>, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.sites.push((site_id.into(), SiteSource::Dir(dir.into())));
+        self
+    }
+
+    /// Serve log lines from `logs` on the dashboard
+    pub fn with_logs(mut self, logs: Arc<observability::logs::LogBuffer>) -> Self {
+        self.logs = Some(logs);
+        self
+    }
+
+    /// Switch to the configured unprivileged user once sockets are bound, as the daemon does
+    pub fn drop_privileges(mut self) -> Self {
+        self.drop_privileges = true;
+        self
+    }
+
+    /// Run as a local development server: Cages get `CageConfig::development` limits, no client
+    /// is banned or rate limited, unsafe requests are logged rather than blocked, and requests
+    /// for unknown hosts go to the first site
+    pub fn development(mut self) -> Self {
+        self.development = true;
+        self
+    }
+
+    /// Start every component, deploy the sites and begin serving
+    pub async fn start(self) -> Result<PearNode> {
+        let PearNodeBuilder { config, sites, logs, drop_privileges, development } = self;
+        let mut pear_config = config.unwrap_or_default();
+        if development {
+            pear_config.ai.mode = ai::policy::EnforcementMode::Monitor;
+            for policy in pear_config.ai.sites.values_mut() {
+                policy.mode = None;
+            }
+            pear_config.security.allowlist = vec!["0.0.0.0/0".to_string(), "::/0".to_string()];
+            pear_config.security.rate_limit_rps = 0;
+        }
+        pear_config.validate()?;
+        let logs = logs.unwrap_or_default();
+
+        // Initialize global state manager
+        let global_state = state::GlobalState::new();
+        info!("✓ Global state manager initialized");
+
+        let shutdown = Arc::new(signals::ShutdownCoordinator::new());
+
+        // === Phase 2: Initialize Middle Layer Components ===
+
+        // Initialize Wasmtime engine
+        info!("Initializing WebAssembly runtime...");
+        let _wasm_engine = cage::create_engine()?;
+        info!("✓ Wasmtime engine created");
+
+        // Deployed modules are stored once by hash and collected when nothing uses them
+        let storage = Arc::new(storage::StorageManager::open(&pear_config.storage)?);
+        storage.modules().start_collection(std::time::Duration::from_secs(3600));
+        info!("✓ Module store ready");
+        let compile_cache = cage::compile_cache::CompileCache::open(&pear_config.compile_cache, storage.root())?;
+        let keyring = Arc::new(storage::encryption::Keyring::open(&pear_config.storage.encryption, storage.clone()).await?);
+        let artifacts = Arc::new(storage::artifacts::ArtifactStore::open(&pear_config.artifacts, storage.root())?);
+
+        // Initialize Router
+        let router_config = router::RouterConfig::default();
+        let router = Arc::new(router::Router::new(router_config));
+        info!("✓ Traffic Router initialized");
+
+        // Initialize Supervisor
+        let supervisor_config = supervisor::SupervisorConfig::default();
+        let supervisor = Arc::new(supervisor::Supervisor::new(supervisor_config));
+        info!("✓ Self-Healing Supervisor initialized");
+
+        // Initialize AI Security Module
+        let ai_config = ai::AiConfig {
+            enable_anomaly_detection: pear_config.ai.enable_anomaly_detection,
+            anomaly_threshold: pear_config.ai.anomaly_threshold,
+            sample_rate: pear_config.ai.sample_rate,
+            mode: pear_config.ai.mode,
+            sites: pear_config.ai.sites.clone(),
+            model_path: Some(&pear_config.ai.model_path)
+                .filter(|path| !path.is_empty())
+                .map(std::path::PathBuf::from),
+            security: pear_config.security.clone(),
+        };
+        let ai_module = Arc::new(ai::AiSecurityModule::new(ai_config)?);
+        router.set_security_module(ai_module.clone());
+        router.set_header_policies(pear_config.security.headers.clone());
+        router.set_rewrite_rules(router::rewrite::RewriteEngine::new(&pear_config.rewrite)?);
+        let upstreams = router::upstream::UpstreamProxy::new(&pear_config.upstream)?;
+        upstreams.start();
+        router.set_upstreams(upstreams);
+        router.set_streaming(pear_config.streaming.clone());
+        router.set_limits(pear_config.limits.clone());
+        router.set_admission(pear_config.admission.clone());
+        if pear_config.outlier_detection.enabled {
+            router.set_outlier_detection(pear_config.outlier_detection.clone());
+        }
+        router.set_retries(pear_config.retries.clone());
+        router.set_access_control(Arc::new(router::acl::AccessControl::new(&pear_config.acl)?));
+        let execution_threads = match pear_config.cages.execution_threads {
+            0 if pear_config.runtime.wasm_cores > 0 => pear_config.runtime.wasm_cores,
+            0 => num_cpus::get(),
+            threads => threads,
+        };
+        router.set_wasm_executor(Arc::new(cage::executor::WasmExecutor::new(
+            execution_threads,
+            pear_config.cages.execution_queue_depth,
+        )));
+        let trusted_proxies = router::forwarded::TrustedProxies::parse(&pear_config.server.trusted_proxies)?;
+        if !trusted_proxies.is_empty() {
+            router.set_trusted_proxies(trusted_proxies);
+        }
+        info!("✓ AI Security Module initialized (WAF attached to Router)");
+        ai_module.start_maintenance().await;
+
+        // Initialize bandwidth accounting
+        let bandwidth_meter = if pear_config.bandwidth.enabled {
+            let meter = Arc::new(tenancy::bandwidth::BandwidthMeter::new(pear_config.bandwidth.clone())?);
+            router.set_bandwidth_meter(meter.clone());
+            meter.start();
+            info!("✓ Bandwidth accounting enabled");
+            Some(meter)
+        } else {
+            None
+        };
+
+        // Initialize billing records, counted by the Router and the Cages
+        let billing = if pear_config.billing.enabled {
+            let meter = Arc::new(tenancy::billing::BillingMeter::new(pear_config.billing.clone())?);
+            router.set_billing(meter.clone());
+            meter.start();
+            info!(fuel = pear_config.billing.fuel, "✓ Billing records enabled");
+            Some(meter)
+        } else {
+            None
+        };
+
+        // Record per-minute metrics rollups for graphs and the status API
+        let metrics_history = if pear_config.metrics_history.enabled {
+            let history = Arc::new(observability::history::MetricsHistory::new(pear_config.metrics_history.clone())?);
+            let (router, supervisor, ai_module) = (router.clone(), supervisor.clone(), ai_module.clone());
+            history.start(move || {
+                let stats = router.stats();
+                observability::history::MetricTotals {
+                    requests: stats.total_requests,
+                    errors: stats.failed_requests,
+                    cage_restarts: supervisor.stats().healing_events,
+                    threats: ai_module.stats().threats_detected,
+                    latency: router.latency(),
+                }
+            });
+            info!("✓ Metrics history enabled");
+            Some(history)
+        } else {
+            None
+        };
+
+        // Initialize tenants and the key protecting site secrets
+        let secret_key = tenancy::secrets::SecretKey::load_or_create(
+            std::path::Path::new(&pear_config.server.secret_key_file),
+        )?;
+        // Site domains are verified in the background and requested certificates once verified
+        let mut domains = tenancy::domains::DomainManager::new();
+        if let (true, Some(email)) = (pear_config.ssl.auto_cert, &pear_config.ssl.email) {
+            domains = domains.with_acme(email.clone());
+        }
+        let domains = Arc::new(domains);
+        domains.start(tenancy::domains::VERIFY_INTERVAL);
+        router.set_domains(domains.clone());
+        let tenants = Arc::new(
+            tenancy::TenantManager::new()
+                .with_storage(storage.clone())
+                .with_keyring(keyring)
+                .with_artifacts(artifacts)
+                .with_secret_key(Arc::new(secret_key))
+                .with_domains(domains.clone()),
+        );
+        router.set_tenants(tenants.clone());
+        tenants.start_storage_scans(&pear_config.storage);
+        info!("✓ Tenant Manager initialized");
+
+        // Initialize per-site guest databases
+        if pear_config.database.enabled {
+            let databases = Arc::new(storage::database::DatabaseManager::new(pear_config.database.clone())?);
+            tenants.sync_databases(&databases);
+            databases.start_backups();
+            info!("✓ Site databases enabled");
+        }
+
+        // Initialize scheduled jobs, run against the Router's Cage pools
+        let scheduler = if pear_config.scheduler.enabled {
+            let scheduler = Arc::new(scheduler::Scheduler::new(pear_config.scheduler.clone(), router.clone())?);
+            scheduler.start();
+            info!("✓ Job scheduler started");
+            Some(scheduler)
+        } else {
+            None
+        };
+
+        // Initialize the background task queue
+        let task_queue = if pear_config.queue.enabled {
+            let queue = Arc::new(scheduler::queue::TaskQueue::new(pear_config.queue.clone())?);
+            queue.start();
+            info!("✓ Task queue started");
+            Some(queue)
+        } else {
+            None
+        };
+
+        // Client certificates for the management API, also presented when forwarding to peers
+        let identity = pear_config.mtls.enabled
+            .then(|| network::mtls::NodeIdentity::load(&pear_config.mtls, storage.root()))
+            .transpose()
+            .context("Failed to set up mutual TLS")?;
+        if identity.is_some() {
+            info!("✓ Mutual TLS required on the management API");
+        }
+
+        // Initialize pub/sub between Cages, forwarded to peer nodes if configured
+        let pubsub = if pear_config.pubsub.enabled {
+            let hub = Arc::new(crdt::pubsub::PubSub::new(pear_config.pubsub.clone())?);
+            if let (false, Some(token)) = (pear_config.pubsub.peers.is_empty(), &pear_config.pubsub.peer_token) {
+                let bridge = crdt::pubsub::HttpBridge::start(
+                    pear_config.pubsub.peers.clone(),
+                    token.clone(),
+                    identity.as_ref().map(|identity| identity.connector()),
+                );
+                hub.set_bridge(Arc::new(bridge));
+            }
+            info!(node_id = %hub.node_id(), peers = pear_config.pubsub.peers.len(), "✓ Pub/sub enabled");
+            Some(hub)
+        } else {
+            None
+        };
+
+        // Initialize the outbound mail relay with tenant email quotas
+        let mail_relay = if pear_config.mail.enabled {
+            let relay = Arc::new(mail::MailRelay::new(pear_config.mail.clone())?);
+            tenants.sync_mail(&relay);
+            relay.start();
+            info!(transport = ?pear_config.mail.transport, "✓ Mail relay started");
+            Some(relay)
+        } else {
+            None
+        };
+
+        // Start Router health checks, whose failing Cages the Supervisor replaces
+        router.health_checker().set_config(pear_config.pool_health.clone())?;
+        supervisor.set_health_checker(router.health_checker().clone());
+        router.start_health_checks().await;
+        router.start_outlier_detection();
+        info!("✓ Router health checks started");
+
+        // Start Supervisor monitoring loop
+        supervisor.start().await;
+        info!("✓ Supervisor monitoring loop started");
+
+        // Inject faults on purpose, to exercise the self-healing above
+        if pear_config.chaos.enabled {
+            let chaos = Arc::new(chaos::ChaosEngine::new(pear_config.chaos.clone()));
+            router.set_chaos(chaos.clone());
+            chaos.start(router.clone());
+            warn!("✓ Chaos mode enabled: faults will be injected into Cage pools");
+        }
+
+        // Site state documents, and snapshots of everything needed to rebuild the node
+        let documents = Arc::new(crdt::DocumentStore::new(pear_config.crdt.clone()));
+        documents.start_compaction();
+        let recordings = Arc::new(cage::determinism::RecordingStore::new());
+        let crashes = Arc::new(cage::crash::CrashStore::new());
+        let profiles = Arc::new(cage::profiling::ProfileStore::new());
+        let guest_logs = pear_config.guest_logs.enabled
+            .then(|| Arc::new(observability::guest_logs::GuestLogs::new(pear_config.guest_logs.clone())));
+
+        // Operators' webhooks hear about crash loops, quota warnings, rollbacks and bans from here on
+        let notifications = if pear_config.notifications.enabled {
+            let site_tenants = tenants.clone();
+            let hub = Arc::new(notifications::Notifications::new(
+                pear_config.notifications.clone(),
+                move |site_id| site_tenants.find_site_tenant(site_id),
+            ).context("Failed to load notification webhooks")?);
+            notifications::install(hub.clone());
+            Some(hub)
+        } else {
+            None
+        };
+        let health = Arc::new(observability::health::Health::new(
+            pear_config.health.clone(),
+            pear_config.ssl.cert_path.clone(),
+        ));
+        let sessions = pear_config.sessions.enabled.then(|| {
+            let sessions = Arc::new(crdt::session::SessionManager::new(pear_config.sessions.clone(), documents.clone()));
+            sessions.start_sweeper();
+            sessions
+        });
+        let snapshots = Arc::new(storage::snapshot::SnapshotManager::new(
+            tenants.clone(),
+            router.clone(),
+            supervisor.clone(),
+            storage.modules().clone(),
+            ai_module.clone(),
+            documents.clone(),
+        ));
+        // A development server neither restores the daemon's sites nor saves its own
+        let registry = (pear_config.restore.enabled && !development).then(|| Arc::new(storage::registry::SiteRegistry::new(
+            &pear_config.restore,
+            storage.root(),
+            tenants.clone(),
+            router.clone(),
+            supervisor.clone(),
+            storage.modules().clone(),
+        )));
+
+        let mut node = PearNode {
+            config: pear_config,
+            global_state,
+            shutdown,
+            storage,
+            compile_cache,
+            router,
+            supervisor,
+            ai_module,
+            tenants,
+            domains,
+            bandwidth_meter,
+            billing,
+            metrics_history,
+            scheduler,
+            task_queue,
+            pubsub,
+            mail_relay,
+            documents,
+            sessions,
+            recordings,
+            guest_logs,
+            crashes,
+            profiles,
+            notifications,
+            health,
+            snapshots,
+            registry,
+            control_plane: None,
+            listener_metrics: Vec::new(),
+            server_handles: Vec::new(),
+            local_addrs: Vec::new(),
+            development,
+        };
+
+        if let (true, Some((site_id, _))) = (development, sites.first()) {
+            node.router.set_default_site(site_id.clone());
+        }
+        // Sites are deployed while still privileged, before any traffic arrives
+        for (site_id, source) in sites {
+            let deployed = match source {
+                SiteSource::Module(wasm) => node.deploy(&site_id, wasm).await,
+                SiteSource::Dir(dir) => node.deploy_dir(&site_id, &dir).await,
+            };
+            deployed.with_context(|| format!("Failed to deploy site '{}'", site_id))?;
+        }
+
+        let PearNode {
+            config: pear_config,
+            storage,
+            router,
+            supervisor,
+            ai_module,
+            tenants,
+            domains,
+            metrics_history,
+            scheduler,
+            task_queue,
+            pubsub,
+            mail_relay,
+            snapshots,
+            documents,
+            sessions,
+            recordings,
+            guest_logs,
+            crashes,
+            profiles,
+            notifications,
+            health,
+            shutdown,
+            ..
+        } = &node;
+
+        // Create network configuration shared by every listener
+        let mut network_config = network::NetworkConfig {
+            http2_port: pear_config.server.http2_port,
+            http3_port: pear_config.server.http3_port,
+            bind_addr: pear_config.server.bind_addr.clone(),
+            io_backend: pear_config.server.io_backend,
+            tls_cert_path: pear_config.ssl.cert_path.clone(),
+            tls_key_path: pear_config.ssl.key_path.clone(),
+            max_connections_per_ip: pear_config.limits.max_connections_per_ip,
+            accept_backlog: pear_config.limits.accept_backlog,
+            http3_max_concurrent_streams: pear_config.limits.http3_max_streams_per_connection,
+            http3_max_total_streams: pear_config.limits.http3_max_streams,
+            http3_acceptor_shards: pear_config.server.http3_acceptor_shards,
+            session_tickets: pear_config.ssl.session_tickets
+                .then(|| network::tickets::TicketKeys::new(std::time::Duration::from_secs(pear_config.ssl.ticket_rotation_secs)).map(Arc::new))
+                .transpose()?,
+            ..Default::default()
+        };
+        if pear_config.server.acceptor_shards > 0 {
+            network_config.acceptor_shards = pear_config.server.acceptor_shards;
+        }
+        if let Some(plan) = pear_config.runtime.affinity_plan()? {
+            network_config.acceptor_cpus = plan.worker_cpus;
+        }
+        let listeners = pear_config.server.effective_listeners();
+        info!(
+            listeners = listeners.len(),
+            acceptor_shards = network_config.effective_acceptor_shards(),
+            io_backend = ?network_config.io_backend,
+            "Network configuration loaded"
+        );
+
+        // Bind every socket while still privileged (ports 80/443 need root)
+        let dashboard_listener = if pear_config.dashboard.enabled {
+            Some(dashboard::bind(pear_config.dashboard.port).await?)
+        } else {
+            None
+        };
+        let mut http2_listeners = Vec::new();
+        let mut redirect_listeners = Vec::new();
+        let mut http3_sockets = Vec::new();
+        let mut local_addrs = Vec::new();
+        for listener in &listeners {
+            let config = network_config.for_listener(listener);
+            let addr = listener.socket_addr()?;
+            match listener.protocol {
+                network::ListenerProtocol::Http2 => {
+                    let sockets = network::acceptor::bind_std_tcp_shards(&addr, &config)?;
+                    if listener.redirect_https {
+                        redirect_listeners.push((listener.label(), listener.https_port, listener.proxy_protocol, sockets));
+                    } else {
+                        if let Some(socket) = sockets.first() {
+                            local_addrs.push(socket.local_addr()?);
+                        }
+                        http2_listeners.push((listener.label(), config, sockets));
+                    }
+                }
+                network::ListenerProtocol::Http3 => {
+                    let sockets = network::acceptor::bind_udp_shards(&addr, &config)?;
+                    http3_sockets.push((listener.label(), config, sockets));
+                }
+            }
+        }
+        info!("✓ Listening sockets bound");
+
+        // Switch to the unprivileged user before serving any traffic
+        if drop_privileges {
+            runtime::privileges::drop_if_root(
+                pear_config.server.user.as_deref(),
+                pear_config.server.group.as_deref(),
+                pear_config.server.allow_root,
+            )?;
+            info!(user = %runtime::privileges::current_user(), "✓ Serving as unprivileged user");
+        }
+
+        // === Phase 3: Start Dashboard Server ===
+
+        // The dashboard gets its own runtime when configured, so it can be reached while workers are saturated
+        let control_plane = pear_config.runtime.control_plane
+            .then(runtime::ControlPlane::start)
+            .transpose()?;
+        if control_plane.is_some() {
+            info!("✓ Control plane runtime started");
+        }
+
+        if let Some(listener) = dashboard_listener {
+            let auth = (!pear_config.dashboard.admin_token.is_empty() || pear_config.oidc.enabled)
+                .then(|| tenancy::auth::AuthManager::open(pear_config.dashboard.admin_token.clone(), &pear_config.rbac))
+                .transpose()?
+                .map(|auth| {
+                    let auth = if pear_config.lockout.enabled {
+                        let guard = Arc::new(tenancy::lockout::LoginGuard::new(pear_config.lockout.clone()));
+                        guard.start();
+                        auth.with_lockout(guard)
+                    } else {
+                        auth
+                    };
+                    if !pear_config.oidc.enabled {
+                        return Arc::new(auth);
+                    }
+                    let provider = Arc::new(tenancy::oidc::OidcProvider::new(pear_config.oidc.clone(), tenants.clone()));
+                    provider.start();
+                    info!(issuer = %pear_config.oidc.issuer, "✓ Single sign-on enabled");
+                    Arc::new(auth.with_oidc(provider))
+                });
+            let deployments = Arc::new(deployment::bluegreen::BlueGreenManager::new(
+                &pear_config.deployment,
+                router.clone(),
+                supervisor.clone(),
+                storage.modules().clone(),
+            ));
+            let git = Arc::new(deployment::git::GitDeployer::new(
+                &pear_config.deployment.git,
+                storage.root(),
+                tenants.clone(),
+                deployments.clone(),
+            ));
+            let audit = pear_config.audit.enabled
+                .then(|| observability::audit::AuditLog::open(&pear_config.audit).map(Arc::new))
+                .transpose()
+                .context("Failed to open the audit log")?;
+            let assets_dir = (!pear_config.dashboard.assets_dir.is_empty())
+                .then(|| std::path::PathBuf::from(&pear_config.dashboard.assets_dir));
+
+            let state = Arc::new(dashboard::DashboardState {
+                router: router.clone(),
+                supervisor: supervisor.clone(),
+                ai_module: ai_module.clone(),
+                tenants: tenants.clone(),
+                scheduler: scheduler.clone(),
+                queue: task_queue.clone(),
+                pubsub: pubsub.clone(),
+                mail: mail_relay.clone(),
+                metrics_history: metrics_history.clone(),
+                telemetry: Arc::new(dashboard::telemetry::TelemetryCollector::new()),
+                logs,
+                auth,
+                deployments,
+                git,
+                snapshots: snapshots.clone(),
+                documents: documents.clone(),
+                sessions: sessions.clone(),
+                recordings: recordings.clone(),
+                guest_logs: guest_logs.clone(),
+                crashes: crashes.clone(),
+                profiles: profiles.clone(),
+                audit,
+                notifications: notifications.clone(),
+                health: health.clone(),
+                rebalancer: Arc::new(network::rebalance::Rebalancer::new(router.state().clone(), health.clone())),
+                assets: dashboard::assets::Assets::new(assets_dir),
+            });
+            let tls = identity.as_ref().map(|identity| identity.acceptor());
+            
+            let control = control_plane.as_ref().map_or_else(tokio::runtime::Handle::current, |plane| plane.handle().clone());
+            let listener = listener.into_std()?;
+            control.spawn(async move {
+                let listener = match tokio::net::TcpListener::from_std(listener) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        error!("Dashboard server error: {}", e);
+                        return;
+                    }
+                };
+                if let Err(e) = dashboard::serve(listener, state, tls).await {
+                    error!("Dashboard server error: {}", e);
+                }
+            });
+
+            info!("✓ Administration Dashboard started on port {}", pear_config.dashboard.port);
+        }
+
+        // Bring back the sites saved before the last stop; traffic waits for them, /healthz does not
+        if let Some(registry) = &node.registry {
+            node.restore_sites(registry).await;
+            registry.start(std::time::Duration::from_secs(pear_config.restore.save_interval_secs));
+        }
+
+        // Start HTTP/2 servers (TCP) - every listener routes through the same Router
+        let mut listener_metrics = Vec::new();
+        let mut server_handles = Vec::new();
+        for (label, config, sockets) in http2_listeners {
+            let metrics = Arc::new(network::acceptor::AcceptorMetrics::new(sockets.len()));
+            listener_metrics.push((label.clone(), metrics.clone()));
+            let router = router.clone();
+            let shutdown = shutdown.clone();
+            let tls = config.tls;
+            server_handles.push(tokio::spawn(async move {
+                if let Err(e) = network::http2_serve_with_router(config, sockets, router, metrics, shutdown).await {
+                    error!("HTTP/2 server error: {}", e);
+                }
+            }));
+            info!(listener = %label, tls, "✓ HTTP/2 server started (routing to Cages)");
+        }
+
+        // Plain-HTTP listeners that only redirect to HTTPS
+        for (label, https_port, proxy_protocol, sockets) in redirect_listeners {
+            let metrics = Arc::new(network::acceptor::AcceptorMetrics::new(sockets.len()));
+            listener_metrics.push((label.clone(), metrics.clone()));
+            let shutdown = shutdown.clone();
+            let domains = Some(domains.clone());
+            server_handles.push(tokio::spawn(async move {
+                if let Err(e) = network::redirect::serve(sockets, https_port, proxy_protocol, domains, metrics, shutdown).await {
+                    error!("HTTPS redirect server error: {}", e);
+                }
+            }));
+            info!(listener = %label, https_port, "✓ HTTPS redirect started");
+        }
+
+        // Start HTTP/3 servers (QUIC/UDP) - simplified for Phase 2
+        // One guard screens handshakes for all of them, so limits and bans span listeners
+        let quic_guard = Arc::new(network::quic_guard::QuicGuard::new(pear_config.quic.clone()).with_security(ai_module.clone()));
+        if !http3_sockets.is_empty() {
+            router.set_quic_guard(quic_guard.clone());
+        }
+        for (label, config, sockets) in http3_sockets {
+            let metrics = Arc::new(network::acceptor::AcceptorMetrics::new(sockets.len()));
+            listener_metrics.push((label.clone(), metrics.clone()));
+            let shutdown = shutdown.clone();
+            let connections = router.state().clone();
+            let guard = quic_guard.clone();
+            server_handles.push(tokio::spawn(async move {
+                if let Err(e) = network::http3::serve(config, sockets, connections, metrics, guard, shutdown).await {
+                    error!("HTTP/3 server error: {}", e);
+                }
+            }));
+            info!(listener = %label, "✓ HTTP/3 server started");
+        }
+
+        router.set_listener_metrics(listener_metrics.clone());
+        health.set_listening(true);
+        node.control_plane = control_plane;
+        node.listener_metrics = listener_metrics;
+        node.server_handles = server_handles;
+        node.local_addrs = local_addrs;
+        Ok(node)
+    }
+}
+
+/// A running Pear node: the Router, its Cage pools and every service around them
+pub struct PearNode {
+    config: config::PearConfig,
+    global_state: state::GlobalState,
+    shutdown: Arc<signals::ShutdownCoordinator>,
+    storage: Arc<storage::StorageManager>,
+    compile_cache: Option<Arc<cage::compile_cache::CompileCache>>,
+    router: Arc<router::Router>,
+    supervisor: Arc<supervisor::Supervisor>,
+    ai_module: Arc<ai::AiSecurityModule>,
+    tenants: Arc<tenancy::TenantManager>,
+    domains: Arc<tenancy::domains::DomainManager>,
+    bandwidth_meter: Option<Arc<tenancy::bandwidth::BandwidthMeter>>,
+    billing: Option<Arc<tenancy::billing::BillingMeter>>,
+    metrics_history: Option<Arc<observability::history::MetricsHistory>>,
+    scheduler: Option<Arc<scheduler::Scheduler>>,
+    task_queue: Option<Arc<scheduler::queue::TaskQueue>>,
+    pubsub: Option<Arc<crdt::pubsub::PubSub>>,
+    mail_relay: Option<Arc<mail::MailRelay>>,
+    documents: Arc<crdt::DocumentStore>,
+    sessions: Option<Arc<crdt::session::SessionManager>>,
+    recordings: Arc<cage::determinism::RecordingStore>,
+    guest_logs: Option<Arc<observability::guest_logs::GuestLogs>>,
+    crashes: Arc<cage::crash::CrashStore>,
+    profiles: Arc<cage::profiling::ProfileStore>,
+    notifications: Option<Arc<notifications::Notifications>>,
+    health: Arc<observability::health::Health>,
+    snapshots: Arc<storage::snapshot::SnapshotManager>,
+    registry: Option<Arc<storage::registry::SiteRegistry>>,
+    control_plane: Option<runtime::ControlPlane>,
+    listener_metrics: Vec<(String, Arc<network::acceptor::AcceptorMetrics>)>,
+    server_handles: Vec<tokio::task::JoinHandle<()>>,
+    local_addrs: Vec<SocketAddr>,
+    development: bool,
+}
+
+impl PearNode {
+    /// Start building a node
+    pub fn builder() -> PearNodeBuilder {
+        PearNodeBuilder::default()
+    }
+
+    /// The configuration the node was started with
+    pub fn config(&self) -> &config::PearConfig {
+        &self.config
+    }
+
+    pub fn router(&self) -> &Arc<router::Router> {
+        &self.router
+    }
+
+    pub fn supervisor(&self) -> &Arc<supervisor::Supervisor> {
+        &self.supervisor
+    }
+
+    pub fn tenants(&self) -> &Arc<tenancy::TenantManager> {
+        &self.tenants
+    }
+
+    /// Shared state documents of the node's sites
+    pub fn documents(&self) -> &Arc<crdt::DocumentStore> {
+        &self.documents
+    }
+
+    /// Calls recorded on sites with `record` set, for replay
+    pub fn recordings(&self) -> &Arc<cage::determinism::RecordingStore> {
+        &self.recordings
+    }
+
+    /// What the node's guests print, unless capture is disabled
+    pub fn guest_logs(&self) -> Option<&Arc<observability::guest_logs::GuestLogs>> {
+        self.guest_logs.as_ref()
+    }
+
+    /// Reports of guests that trapped
+    pub fn crashes(&self) -> &Arc<cage::crash::CrashStore> {
+        &self.crashes
+    }
+
+    /// Sampling profiles of the sites listed in `[profiling]`
+    pub fn profiles(&self) -> &Arc<cage::profiling::ProfileStore> {
+        &self.profiles
+    }
+
+    /// Webhooks told about operational events, unless disabled
+    /// Readiness state behind `/readyz`
+    pub fn health(&self) -> &Arc<observability::health::Health> {
+        &self.health
+    }
+
+    pub fn notifications(&self) -> Option<&Arc<notifications::Notifications>> {
+        self.notifications.as_ref()
+    }
+
+    /// User sessions of the node's sites, unless disabled
+    pub fn sessions(&self) -> Option<&Arc<crdt::session::SessionManager>> {
+        self.sessions.as_ref()
+    }
+
+    /// Snapshot and restore the node's tenants, sites and state
+    pub fn snapshots(&self) -> &Arc<storage::snapshot::SnapshotManager> {
+        &self.snapshots
+    }
+
+    /// Saved tenants and sites, restored on start; None when `[restore]` is disabled or in development
+    pub fn registry(&self) -> Option<&Arc<storage::registry::SiteRegistry>> {
+        self.registry.as_ref()
+    }
+
+    /// Addresses of the HTTP/2 listeners, with the actual port for listeners bound to port 0
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Deploy `wasm` as `site_id`, replacing the site's pool if it is already deployed
+    pub async fn deploy(&self, site_id: &str, wasm: Vec<u8>) -> Result<SiteHandle> {
+        self.start_site(site_id, wasm, &deployment::manifest::SiteManifest::default()).await
+    }
+
+    /// Deploy the site in `dir` as its `pear.site.toml` describes, module included
+    pub async fn deploy_dir(&self, site_id: &str, dir: &std::path::Path) -> Result<SiteHandle> {
+        use deployment::manifest::{SiteManifest, MANIFEST_FILE};
+        let manifest = SiteManifest::load(dir)?
+            .with_context(|| format!("No {} in {}", MANIFEST_FILE, dir.display()))?;
+        let path = manifest.module_path(dir)
+            .with_context(|| format!("{} names no module", MANIFEST_FILE))?;
+        let wasm = std::fs::read(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        self.deploy_manifest(site_id, wasm, &manifest, dir).await
+    }
+
+    /// Deploy `wasm` as `site_id` with the settings, routes and cron jobs of `manifest`,
+    /// whose route modules are read from `dir`; the manifest's own `module` is not read
+    pub async fn deploy_manifest(
+        &self,
+        site_id: &str,
+        wasm: Vec<u8>,
+        manifest: &deployment::manifest::SiteManifest,
+        dir: &std::path::Path,
+    ) -> Result<SiteHandle> {
+        manifest.validate()?;
+        manifest.check_capabilities(&self.capabilities())?;
+        if !manifest.cron.is_empty() && self.scheduler.is_none() {
+            anyhow::bail!("The site has cron jobs, but the scheduler is disabled");
+        }
+
+        let site = self.start_site(site_id, wasm, manifest).await?;
+        self.deploy_routes(site_id, manifest, dir).await?;
+        if let Some(scheduler) = &self.scheduler {
+            for job in &manifest.cron {
+                scheduler.add(site_id, job.clone())?;
+            }
+        }
+        Ok(site)
+    }
+
+    /// Restore the saved tenants, then start the saved sites' pools, `[restore] concurrency` at a time
+    /// and the `[health]` required sites first. Sites already deployed are left as they are; a site
+    /// that fails is logged and stays saved for the next start.
+    async fn restore_sites(&self, registry: &storage::registry::SiteRegistry) {
+        use futures::StreamExt;
+
+        let state = match registry.load() {
+            Ok(Some(state)) => state,
+            Ok(None) => return,
+            Err(e) => {
+                error!(path = %registry.path().display(), error = %format!("{:#}", e), "Saved sites not restored");
+                return;
+            }
+        };
+
+        let default_tenant = self.tenants.default_tenant_id();
+        for tenant in &state.tenants {
+            let mut tenant = tenant.clone();
+            if tenant.id == state.default_tenant {
+                tenant.id = default_tenant;
+            }
+            let tenant_id = tenant.id;
+            if let Err(e) = self.tenants.restore_tenant(tenant) {
+                error!(tenant_id = %tenant_id, error = %format!("{:#}", e), "Failed to restore tenant");
+            }
+        }
+
+        let sites: Vec<_> = state.boot_order(&self.config.health.required_sites).into_iter()
+            .filter(|site| self.router.pool(&site.site_id).is_none())
+            .collect();
+        let total = sites.len();
+        info!(tenants = state.tenants.len(), sites = total, "Restoring saved sites...");
+        self.health.begin_restore(total);
+        let results: Vec<_> = futures::stream::iter(sites)
+            .map(|site| async move {
+                let result = self.restore_site(&site).await;
+                self.health.site_restored();
+                (site, result)
+            })
+            .buffer_unordered(self.config.restore.concurrency)
+            .collect()
+            .await;
+
+        let mut failed = 0;
+        for (site, result) in results {
+            if let Err(e) = result {
+                error!(site = %site.site_id, error = %format!("{:#}", e), "Failed to restore site");
+                registry.keep_unrestored(site);
+                failed += 1;
+            }
+        }
+        self.health.finish_restore();
+        info!(restored = total - failed, failed, "✓ Saved sites restored");
+    }
+
+    /// Start a saved site from its stored module, fetching the module again from its artifact if it is gone
+    async fn restore_site(&self, site: &storage::registry::SiteRecord) -> Result<SiteHandle> {
+        let wasm = match self.storage.modules().read(&site.module) {
+            Ok(wasm) => wasm,
+            Err(e) => {
+                let (Some(key), Some(tenant_id)) = (&site.artifact, self.tenants.find_site_tenant(&site.site_id)) else {
+                    return Err(e);
+                };
+                warn!(site = %site.site_id, module = %site.module, error = %format!("{:#}", e), "Fetching the saved module again");
+                self.tenants.fetch_artifact(tenant_id, key, Some(&site.module.to_string())).await?.read()?
+            }
+        };
+        let manifest = deployment::manifest::SiteManifest { replicas: Some(site.replicas.max(1)), ..Default::default() };
+        self.start_site(&site.site_id, wasm, &manifest).await
+    }
+
+    /// Host modules this node links into Cages
+    pub fn capabilities(&self) -> Vec<deployment::manifest::Capability> {
+        use deployment::manifest::Capability;
+        let mut capabilities = vec![Capability::Streaming];
+        capabilities.extend(self.mail_relay.as_ref().map(|_| Capability::Mail));
+        capabilities.extend(self.pubsub.as_ref().map(|_| Capability::Pubsub));
+        capabilities.extend(self.task_queue.as_ref().map(|_| Capability::Queue));
+        capabilities.extend(self.sessions.as_ref().map(|_| Capability::Sessions));
+        capabilities
+    }
+
+    async fn start_site(&self, site_id: &str, wasm: Vec<u8>, manifest: &deployment::manifest::SiteManifest) -> Result<SiteHandle> {
+        let module = self.storage.modules().put(&wasm)?;
+
+        let mut cage_config = self.cage_config(site_id);
+        manifest.apply_to(&mut cage_config);
+        if let Some(queue) = &self.task_queue {
+            cage_config.queue = Some(queue.guest(site_id));
+            let worker = scheduler::queue::CageWorker::new(
+                site_id,
+                &wasm,
+                cage_config.clone(),
+                queue.guest(site_id),
+            )?;
+            queue.register_worker(site_id, Arc::new(worker));
+        }
+        let replicas = manifest.replicas.unwrap_or(self.config.cages.default_replicas);
+        let pool = match self.tenants.site_quota(site_id) {
+            Some(quota) => cage::pool::CagePool::new_with_quota(site_id.to_string(), wasm, cage_config, replicas, quota).await?,
+            None => cage::pool::CagePool::new(site_id.to_string(), wasm, cage_config, replicas).await?,
+        };
+        let pool = Arc::new(pool);
+
+        self.router.register_pool(site_id.to_string(), pool.clone());
+        self.supervisor.register_pool(site_id.to_string(), pool.clone(), module);
+        info!(site = site_id, replicas = replicas, "✓ Site deployed");
+        Ok(SiteHandle { pool })
+    }
+
+    /// Serve path prefixes of `site_id` as its manifest's routes say, replacing its previous routes
+    /// Route modules are read from `dir`; each runs in a pool of its own, watched by the supervisor.
+    pub async fn deploy_routes(&self, site_id: &str, manifest: &deployment::manifest::SiteManifest, dir: &std::path::Path) -> Result<usize> {
+        manifest.validate()?;
+        let mut routes = Vec::new();
+        for config in &manifest.routes {
+            let pool = match &config.module {
+                Some(module) => {
+                    let path = dir.join(module);
+                    let wasm = std::fs::read(&path)
+                        .with_context(|| format!("Failed to read route module {}", path.display()))?;
+                    let stored = self.storage.modules().put(&wasm)?;
+                    let replicas = config.replicas.unwrap_or(self.config.cages.default_replicas);
+                    let mut cage_config = self.cage_config(site_id);
+                    manifest.apply_to(&mut cage_config);
+                    let pool = Arc::new(cage::pool::CagePool::new(site_id.to_string(), wasm, cage_config, replicas)
+                        .await
+                        .with_context(|| format!("Failed to start route {}", config.path))?);
+                    Some((pool, stored))
+                }
+                None => None,
+            };
+            routes.push((config, pool));
+        }
+
+        let site_routes = router::routes::SiteRoutes::new(routes.iter().map(|(config, pool)| router::routes::Route {
+            prefix: config.prefix().to_string(),
+            pool: pool.as_ref().map(|(pool, _)| pool.clone()),
+            strategy: config.strategy,
+            limits: config.limits.clone(),
+        }).collect());
+        let previous = self.router.set_routes(site_id.to_string(), site_routes);
+        for (prefix, _) in previous.iter().flat_map(|previous| previous.pools()) {
+            self.supervisor.unregister_pool(&router::routes::pool_key(site_id, prefix));
+        }
+        for (config, pool) in routes.iter() {
+            if let Some((pool, stored)) = pool {
+                self.supervisor.register_pool(router::routes::pool_key(site_id, config.prefix()), pool.clone(), stored.clone());
+            }
+        }
+        info!(site = site_id, routes = routes.len(), "✓ Site routes deployed");
+        Ok(routes.len())
+    }
+
+    /// Cage settings of `site_id` from the node's configuration
+    fn cage_config(&self, site_id: &str) -> cage::config::CageConfig {
+        let mut cage_config = if self.development {
+            cage::config::CageConfig::development()
+        } else {
+            cage::config::CageConfig::default()
+        };
+        cage_config.pubsub = self.pubsub.as_ref().map(|hub| hub.guest(site_id));
+        cage_config.mail = self.mail_relay.as_ref()
+            .map(|relay| relay.guest(&self.tenants.default_tenant_id().to_string(), site_id));
+        cage_config.sessions = self.sessions.as_ref().map(|sessions| sessions.guest(site_id));
+        cage_config.guest_log = self.guest_logs.as_ref().map(|logs| logs.site(site_id));
+        cage_config.crashes = self.config.crashes.enabled
+            .then(|| self.crashes.reporter(site_id, &self.config.crashes, cage_config.guest_log.clone()));
+        if let Some(meter) = &self.billing {
+            let tenant_id = self.tenants.find_site_tenant(site_id).unwrap_or_else(|| self.tenants.default_tenant_id());
+            cage_config.fuel = meter.fuel_per_call();
+            cage_config.usage = Some(meter.guest(site_id, &tenant_id.to_string()));
+        }
+        cage_config.jit_profiler = self.config.profiling.jit_profiler;
+        cage_config.compile_cache = self.compile_cache.clone();
+        (cage_config.start, cage_config.idle_timeout_secs) = self.config.cages.start_of(site_id);
+        cage_config.max_concurrent_requests = self.config.cages.max_concurrent_requests;
+        cage_config.request_queue_depth = self.config.cages.request_queue_depth;
+        cage_config.request_queue_timeout_ms = self.config.cages.request_queue_timeout_ms;
+        cage_config.standby_replicas = self.config.cages.standby_replicas;
+        if self.config.profiling.sites.iter().any(|site| site == site_id) {
+            cage_config.profiler = Some(self.profiles.profiler(site_id, &self.config.profiling));
+        }
+        if let Some(site) = self.config.determinism.sites.get(site_id) {
+            cage_config.determinism = Some(site.determinism);
+            cage_config.recorder = site.record.then(|| {
+                self.recordings.recorder(site_id, site.determinism, self.config.determinism.max_recordings)
+            });
+        }
+        cage_config
+    }
+
+    /// Move the deployed `site_id` onto `wasm` in place: new Cages start first, take over at once,
+    /// and the old ones finish their in-flight requests; a rejected module leaves the site as it was
+    pub async fn hot_swap(&self, site_id: &str, wasm: Vec<u8>) -> Result<deployment::hotswap::HotSwap> {
+        deployment::hotswap::hot_swap(
+            &self.router,
+            &self.supervisor,
+            self.storage.modules(),
+            site_id,
+            wasm,
+            self.config.deployment.max_module_bytes,
+            tenancy::signing::SignatureCheck::Unsigned,
+        ).await
+    }
+
+    /// Stop routing to `site_id`; returns false if it was not deployed
+    pub fn undeploy(&self, site_id: &str) -> bool {
+        if self.router.pool(site_id).is_none() {
+            return false;
+        }
+        for (prefix, _) in self.router.routes(site_id).iter().flat_map(|routes| routes.pools()) {
+            self.supervisor.unregister_pool(&router::routes::pool_key(site_id, prefix));
+        }
+        self.router.unregister_pool(site_id);
+        self.supervisor.unregister_pool(site_id);
+        if let Some(quota) = self.tenants.site_quota(site_id) {
+            quota.set_cage_count(0);
+        }
+        true
+    }
+
+    /// Handle to a deployed site
+    pub fn site(&self, site_id: &str) -> Option<SiteHandle> {
+        self.router.pool(site_id).map(|pool| SiteHandle { pool })
+    }
+
+    /// Every deployed site
+    pub fn sites(&self) -> Vec<SiteHandle> {
+        self.router.site_ids().iter().filter_map(|site_id| self.site(site_id)).collect()
+    }
+
+    /// Connections currently open across every listener
+    pub fn active_connections(&self) -> u64 {
+        self.listener_metrics.iter().map(|(_, m)| m.active_connections()).sum()
+    }
+
+    /// Router, Supervisor and per-site statistics
+    pub async fn stats(&self) -> NodeStats {
+        let mut sites = Vec::new();
+        for site in self.sites() {
+            sites.push(site.stats().await);
+        }
+        NodeStats {
+            router: self.router.stats(),
+            supervisor: self.supervisor.stats(),
+            sites,
+            active_connections: self.active_connections(),
+        }
+    }
+
+    /// Stop accepting, wait up to `drain_timeout` for open connections, then stop every component
+    pub async fn shutdown(self, drain_timeout: std::time::Duration) {
+        // Stop accepting and let in-flight connections finish; probes see the node as not ready from here
+        info!("Stopping network services...");
+        self.health.begin_shutdown();
+        self.shutdown.trigger();
+
+        info!("Stopping Supervisor...");
+        self.supervisor.stop();
+
+        let deadline = tokio::time::Instant::now() + drain_timeout;
+        while self.active_connections() > 0 {
+            if tokio::time::Instant::now() >= deadline {
+                warn!(
+                    remaining = self.active_connections(),
+                    "Drain timeout reached, closing remaining connections"
+                );
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+        drop(self.server_handles);
+
+        if let Some(meter) = &self.bandwidth_meter {
+            if let Err(e) = meter.save() {
+                error!("Failed to persist bandwidth usage: {:#}", e);
+            }
+        }
+        if let Some(meter) = &self.billing {
+            if let Err(e) = meter.save() {
+                error!("Failed to persist billing records: {:#}", e);
+            }
+        }
+        if let Some(history) = &self.metrics_history {
+            if let Err(e) = history.save() {
+                error!("Failed to persist metrics history: {:#}", e);
+            }
+        }
+        if let Some(registry) = &self.registry {
+            if let Err(e) = registry.save().await {
+                error!("Failed to save the site registry: {:#}", e);
+            }
+        }
+
+        // Cleanup global state
+        info!("Cleaning up global state...");
+        drop(self.global_state);
+        drop(self.router);
+    }
+}
+
+/// Handle to a site deployed on a [`PearNode`]
+#[derive(Clone)]
+pub struct SiteHandle {
+    pool: Arc<cage::pool::CagePool>,
+}
+
+impl SiteHandle {
+    pub fn site_id(&self) -> &str {
+        self.pool.site_id()
+    }
+
+    /// The site's Cage pool
+    pub fn pool(&self) -> &Arc<cage::pool::CagePool> {
+        &self.pool
+    }
+
+    pub async fn stats(&self) -> cage::pool::PoolHealthStats {
+        self.pool.health_stats().await
+    }
+}
+
+/// Statistics for a whole node
+#[derive(Debug, Clone)]
+pub struct NodeStats {
+    pub router: router::RouterStats,
+    pub supervisor: supervisor::SupervisorStats,
+    pub sites: Vec<cage::pool::PoolHealthStats>,
+    pub active_connections: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_collects_sites() {
+        let builder = PearNode::builder()
+            .with_site("a", vec![0u8])
+            .with_site(String::from("b"), &b"\0asm"[..])
+            .with_site_dir("c", "sites/c")
+            .drop_privileges();
+        let sites: Vec<&str> = builder.sites.iter().map(|(site_id, _)| site_id.as_str()).collect();
+        assert_eq!(sites, ["a", "b", "c"]);
+        assert!(matches!(&builder.sites[2].1, SiteSource::Dir(dir) if dir.ends_with("sites/c")));
+        assert!(builder.drop_privileges);
+        assert!(builder.config.is_none());
+    }
+}
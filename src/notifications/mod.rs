@@ -0,0 +1,671 @@
+// Operational Notifications
+// Signed webhooks for crash loops, quota warnings, rollbacks, certificate failures and bans
+
+use crate::ai::alerts::{post_json_via, post_json_with_headers};
+use anyhow::{bail, Context, Result};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rand::RngCore;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Give up on one delivery attempt after this long
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait between delivery attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Headers sent with every delivery
+pub const EVENT_HEADER: &str = "x-pear-event";
+pub const DELIVERY_HEADER: &str = "x-pear-delivery";
+pub const TIMESTAMP_HEADER: &str = "x-pear-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-pear-signature";
+
+/// The node's notification hub, once the node has installed one
+static HUB: OnceLock<Arc<Notifications>> = OnceLock::new();
+
+/// Make `hub` the one `notify` sends to
+pub fn install(hub: Arc<Notifications>) {
+    if HUB.set(hub).is_err() {
+        warn!("Notification hub already installed");
+    }
+}
+
+/// Send a notification to the webhooks registered for it
+/// Does nothing until a hub is installed, so components stay usable on their own.
+pub fn notify(notification: Notification) {
+    if let Some(hub) = HUB.get() {
+        hub.send(notification);
+    }
+}
+
+/// What a notification is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A site's Cages kept crashing until the supervisor gave up on them
+    CrashLoop,
+
+    /// A site or tenant crossed its bandwidth warning level or quota
+    QuotaThreshold,
+
+    /// A canary deployment was rolled back
+    CanaryRollback,
+
+    /// A domain's certificate could not be issued or renewed
+    CertificateFailure,
+
+    /// A client was banned by the security module
+    SecurityBan,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::CrashLoop => "crash_loop",
+            EventKind::QuotaThreshold => "quota_threshold",
+            EventKind::CanaryRollback => "canary_rollback",
+            EventKind::CertificateFailure => "certificate_failure",
+            EventKind::SecurityBan => "security_ban",
+        }
+    }
+}
+
+/// One event, as POSTed to webhooks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    /// Also sent as `X-Pear-Delivery`; retries of a delivery keep it
+    pub id: Uuid,
+    pub kind: EventKind,
+
+    /// Unix timestamp
+    pub timestamp: i64,
+
+    /// Tenant the event concerns, looked up from the site when not given
+    pub tenant_id: Option<Uuid>,
+    pub site_id: Option<String>,
+
+    /// One line for chat integrations
+    pub text: String,
+    pub details: serde_json::Value,
+}
+
+impl Notification {
+    pub fn new(kind: EventKind, site_id: Option<&str>, text: impl Into<String>, details: serde_json::Value) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            kind,
+            timestamp: chrono::Utc::now().timestamp(),
+            tenant_id: None,
+            site_id: site_id.map(str::to_string),
+            text: text.into(),
+            details,
+        }
+    }
+
+    pub fn for_tenant(mut self, tenant_id: Uuid) -> Self {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+}
+
+fn default_max_per_minute() -> u32 { 30 }
+
+/// A webhook and the events it wants
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+
+    /// Key of the `X-Pear-Signature` HMAC; generated when registered without one
+    #[serde(default)]
+    pub secret: String,
+
+    /// Events sent to the webhook (empty = all)
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+
+    /// Only events of this tenant's sites (none = every event)
+    #[serde(default)]
+    pub tenant_id: Option<Uuid>,
+
+    /// Deliveries per minute before further events are dropped
+    #[serde(default = "default_max_per_minute")]
+    pub max_per_minute: u32,
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<()> {
+        let uri: hyper::Uri = self.url.parse().with_context(|| format!("Invalid webhook URL '{}'", self.url))?;
+        match uri.scheme_str() {
+            Some("http") | Some("https") => {}
+            _ => bail!("Webhook URL must be http:// or https://: '{}'", self.url),
+        }
+        if uri.host().is_none() {
+            bail!("Webhook URL has no host: '{}'", self.url);
+        }
+        if self.max_per_minute == 0 {
+            bail!("max_per_minute must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Resolve the webhook's host, refusing addresses inside the node's network
+    /// Loopback, link-local (cloud metadata), private and unique-local addresses are
+    /// refused, so a tenant's webhook can't be aimed at services only the node can reach.
+    pub async fn resolve_public(&self) -> Result<SocketAddr> {
+        let uri: hyper::Uri = self.url.parse().with_context(|| format!("Invalid webhook URL '{}'", self.url))?;
+        let host = uri.host().with_context(|| format!("Webhook URL has no host: '{}'", self.url))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("https") { 443 } else { 80 });
+
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await
+            .with_context(|| format!("Failed to resolve {}", host))?
+            .collect();
+        if let Some(internal) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+            bail!("Webhook host {} resolves to {}, which is not a public address", host, internal.ip());
+        }
+        addrs.first().copied().with_context(|| format!("{} has no addresses", host))
+    }
+
+    fn wants(&self, notification: &Notification) -> bool {
+        (self.events.is_empty() || self.events.contains(&notification.kind))
+            && self.tenant_id.map_or(true, |tenant_id| notification.tenant_id == Some(tenant_id))
+    }
+}
+
+/// Whether `ip` is reachable from the internet rather than only from the node's network
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// `[notifications]`: webhooks for operational events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    pub enabled: bool,
+
+    /// Webhooks of the node's operators; more can be registered through the API
+    pub webhooks: Vec<WebhookConfig>,
+
+    /// Where webhooks registered through the API are kept ("" = until restart)
+    pub state_path: String,
+
+    /// Attempts per delivery, for connection failures, 429 and 5xx responses
+    pub max_attempts: u32,
+
+    /// Wait before the first retry, doubled for each one after
+    pub retry_delay_ms: u64,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            webhooks: Vec::new(),
+            state_path: "webhooks.json".to_string(),
+            max_attempts: 5,
+            retry_delay_ms: 1000,
+        }
+    }
+}
+
+impl NotificationConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_attempts == 0 {
+            bail!("max_attempts must be at least 1");
+        }
+        for webhook in &self.webhooks {
+            webhook.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// A webhook as listed by the API, its secret left out
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookInfo {
+    pub id: Uuid,
+    pub url: String,
+    pub events: Vec<EventKind>,
+    pub tenant_id: Option<Uuid>,
+    pub max_per_minute: u32,
+
+    /// From `[notifications]` rather than the API, so it can't be removed there
+    pub from_config: bool,
+
+    /// Registered with a tenant's token, so only delivered to public addresses
+    pub restricted: bool,
+
+    pub delivered: u64,
+    pub failed: u64,
+
+    /// Events dropped over the rate limit
+    pub suppressed: u64,
+    pub last_error: Option<String>,
+}
+
+/// A webhook registered through the API, as kept in the state file
+#[derive(Serialize, Deserialize)]
+struct StoredWebhook {
+    id: Uuid,
+    #[serde(default)]
+    restricted: bool,
+    #[serde(flatten)]
+    config: WebhookConfig,
+}
+
+/// A webhook with its rate-limit window and delivery counts
+struct Endpoint {
+    id: Uuid,
+    config: WebhookConfig,
+    from_config: bool,
+    restricted: bool,
+    window: Mutex<(Instant, u32)>,
+    delivered: AtomicU64,
+    failed: AtomicU64,
+    suppressed: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl Endpoint {
+    fn new(id: Uuid, config: WebhookConfig, from_config: bool, restricted: bool) -> Self {
+        Self {
+            id,
+            config,
+            from_config,
+            restricted,
+            window: Mutex::new((Instant::now(), 0)),
+            delivered: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Count a delivery against this minute's budget
+    fn allow(&self) -> bool {
+        let mut window = self.window.lock();
+        if window.0.elapsed() >= Duration::from_secs(60) {
+            *window = (Instant::now(), 0);
+        }
+        if window.1 >= self.config.max_per_minute {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+
+    fn info(&self) -> WebhookInfo {
+        WebhookInfo {
+            id: self.id,
+            url: self.config.url.clone(),
+            events: self.config.events.clone(),
+            tenant_id: self.config.tenant_id,
+            max_per_minute: self.config.max_per_minute,
+            from_config: self.from_config,
+            restricted: self.restricted,
+            delivered: self.delivered.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            suppressed: self.suppressed.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().clone(),
+        }
+    }
+}
+
+type SiteTenant = dyn Fn(&str) -> Option<Uuid> + Send + Sync;
+
+/// Registered webhooks, and delivery of notifications to them
+pub struct Notifications {
+    config: NotificationConfig,
+    state_path: Option<PathBuf>,
+    endpoints: DashMap<Uuid, Arc<Endpoint>>,
+    site_tenant: Box<SiteTenant>,
+    save_lock: Mutex<()>,
+}
+
+impl Notifications {
+    /// Create the hub with the configured webhooks and those registered before
+    /// `site_tenant` finds the tenant of a site, so tenant webhooks get its events.
+    pub fn new(
+        config: NotificationConfig,
+        site_tenant: impl Fn(&str) -> Option<Uuid> + Send + Sync + 'static,
+    ) -> Result<Self> {
+        let state_path = Some(&config.state_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let endpoints = DashMap::new();
+        for webhook in &config.webhooks {
+            let id = Uuid::new_v4();
+            endpoints.insert(id, Arc::new(Endpoint::new(id, webhook.clone(), true, false)));
+        }
+        if let Some(path) = state_path.as_deref().filter(|path| path.exists()) {
+            for stored in load_webhooks(path)? {
+                endpoints.insert(stored.id, Arc::new(Endpoint::new(stored.id, stored.config, false, stored.restricted)));
+            }
+        }
+
+        info!(webhooks = endpoints.len(), "Notification webhooks loaded");
+        Ok(Self {
+            config,
+            state_path,
+            endpoints,
+            site_tenant: Box::new(site_tenant),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Register a webhook, returning its ID and secret
+    /// A `restricted` webhook has its host checked again before every delivery.
+    pub fn register(&self, mut config: WebhookConfig, restricted: bool) -> Result<(Uuid, String)> {
+        config.validate()?;
+        if config.secret.is_empty() {
+            let mut secret = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            config.secret = hex::encode(secret);
+        }
+        let id = Uuid::new_v4();
+        let secret = config.secret.clone();
+        self.endpoints.insert(id, Arc::new(Endpoint::new(id, config, false, restricted)));
+        self.save()?;
+        Ok((id, secret))
+    }
+
+    /// Remove a webhook registered through the API
+    pub fn remove(&self, id: Uuid) -> Result<bool> {
+        if self.endpoints.get(&id).is_some_and(|endpoint| endpoint.from_config) {
+            bail!("Webhook {} is set in [notifications]; remove it there", id);
+        }
+        if self.endpoints.remove(&id).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Webhooks, or only one tenant's
+    pub fn list(&self, tenant_id: Option<Uuid>) -> Vec<WebhookInfo> {
+        let mut webhooks: Vec<WebhookInfo> = self.endpoints.iter()
+            .filter(|endpoint| tenant_id.map_or(true, |tenant_id| endpoint.config.tenant_id == Some(tenant_id)))
+            .map(|endpoint| endpoint.info())
+            .collect();
+        webhooks.sort_by(|a, b| a.url.cmp(&b.url).then(a.id.cmp(&b.id)));
+        webhooks
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<WebhookInfo> {
+        self.endpoints.get(&id).map(|endpoint| endpoint.info())
+    }
+
+    /// Deliver a notification in the background to every webhook that wants it
+    pub fn send(&self, mut notification: Notification) {
+        if notification.tenant_id.is_none() {
+            notification.tenant_id = notification.site_id.as_deref().and_then(|site_id| (self.site_tenant)(site_id));
+        }
+        let targets: Vec<Arc<Endpoint>> = self.endpoints.iter()
+            .filter(|endpoint| endpoint.config.wants(&notification))
+            .map(|endpoint| endpoint.clone())
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!(kind = notification.kind.as_str(), "Notification dropped outside the runtime");
+            return;
+        };
+
+        let notification = Arc::new(notification);
+        let body: Arc<[u8]> = serde_json::to_vec(&*notification).unwrap_or_default().into();
+        for endpoint in targets {
+            if !endpoint.allow() {
+                debug!(url = %endpoint.config.url, kind = notification.kind.as_str(), "Notification suppressed by rate limit");
+                continue;
+            }
+            let retry = Retry {
+                attempts: self.config.max_attempts,
+                delay: Duration::from_millis(self.config.retry_delay_ms),
+            };
+            runtime.spawn(deliver(endpoint, notification.clone(), body.clone(), retry));
+        }
+    }
+
+    /// Write the webhooks registered through the API to the state file
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+        let _guard = self.save_lock.lock();
+        let stored: Vec<StoredWebhook> = self.endpoints.iter()
+            .filter(|endpoint| !endpoint.from_config)
+            .map(|endpoint| StoredWebhook { id: endpoint.id, restricted: endpoint.restricted, config: endpoint.config.clone() })
+            .collect();
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&stored)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Notifications {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Notifications").field("webhooks", &self.endpoints.len()).finish()
+    }
+}
+
+/// How often and how patiently a delivery is retried
+#[derive(Debug, Clone, Copy)]
+struct Retry {
+    attempts: u32,
+    delay: Duration,
+}
+
+impl Retry {
+    /// Wait before retrying after `attempt` failed attempts
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.delay.saturating_mul(1 << attempt.min(16)).min(MAX_RETRY_DELAY)
+    }
+}
+
+/// POST the notification until it is accepted, refused, or out of attempts
+async fn deliver(endpoint: Arc<Endpoint>, notification: Arc<Notification>, body: Arc<[u8]>, retry: Retry) {
+    let url = &endpoint.config.url;
+    let mut last_error = String::new();
+    for attempt in 0..retry.attempts {
+        if attempt > 0 {
+            tokio::time::sleep(retry.backoff(attempt - 1)).await;
+        }
+
+        // Signed per attempt, so receivers can reject stale replays by timestamp
+        let timestamp = chrono::Utc::now().timestamp();
+        let headers = [
+            (EVENT_HEADER, notification.kind.as_str().to_string()),
+            (DELIVERY_HEADER, notification.id.to_string()),
+            (TIMESTAMP_HEADER, timestamp.to_string()),
+            (SIGNATURE_HEADER, sign(&endpoint.config.secret, timestamp, &body)),
+        ];
+        // The host may have been repointed since it was registered; connect only to the address checked
+        let post = async {
+            if endpoint.restricted {
+                let addr = endpoint.config.resolve_public().await?;
+                post_json_via(addr, url, body.to_vec(), &headers).await
+            } else {
+                post_json_with_headers(url, body.to_vec(), &headers).await
+            }
+        };
+        let sent = tokio::time::timeout(SEND_TIMEOUT, post).await;
+        let retryable = match sent {
+            Ok(Ok(status)) if status.is_success() => {
+                endpoint.delivered.fetch_add(1, Ordering::Relaxed);
+                debug!(url = %url, kind = notification.kind.as_str(), "Notification delivered");
+                return;
+            }
+            Ok(Ok(status)) => {
+                last_error = format!("Webhook returned {}", status);
+                status.is_server_error() || status == hyper::StatusCode::TOO_MANY_REQUESTS
+            }
+            Ok(Err(e)) => {
+                last_error = format!("{:#}", e);
+                true
+            }
+            Err(_) => {
+                last_error = "Timed out".to_string();
+                true
+            }
+        };
+        if !retryable {
+            break;
+        }
+    }
+
+    endpoint.failed.fetch_add(1, Ordering::Relaxed);
+    warn!(url = %url, kind = notification.kind.as_str(), error = %last_error, "Notification delivery failed");
+    *endpoint.last_error.lock() = Some(last_error);
+}
+
+/// `X-Pear-Signature` of a body: `sha256=` and the hex HMAC of `<timestamp>.<body>`
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let mut context = hmac::Context::with_key(&key);
+    context.update(timestamp.to_string().as_bytes());
+    context.update(b".");
+    context.update(body);
+    let tag = context.sign();
+    format!("sha256={}", hex::encode(tag))
+}
+
+fn load_webhooks(path: &Path) -> Result<Vec<StoredWebhook>> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn webhook(events: Vec<EventKind>, tenant_id: Option<Uuid>) -> WebhookConfig {
+        WebhookConfig {
+            url: "http://127.0.0.1:9/hook".to_string(),
+            secret: String::new(),
+            events,
+            tenant_id,
+            max_per_minute: 2,
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        // Receivers recompute the HMAC over `<X-Pear-Timestamp>.<body>`
+        let signature = sign("secret", 1_700_000_000, b"{}");
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let expected = hmac::sign(&key, b"1700000000.{}");
+        assert_eq!(signature, format!("sha256={}", hex::encode(expected)));
+        assert_ne!(signature, sign("other", 1_700_000_000, b"{}"));
+    }
+
+    #[test]
+    fn test_matching_and_rate_limit() {
+        let tenant = Uuid::new_v4();
+        let crash = Notification::new(EventKind::CrashLoop, Some("blog"), "blog is crash looping", json!({})).for_tenant(tenant);
+        let ban = Notification::new(EventKind::SecurityBan, Some("shop"), "ban", json!({}));
+
+        assert!(webhook(vec![], None).wants(&crash));
+        assert!(webhook(vec![EventKind::CrashLoop], Some(tenant)).wants(&crash));
+        assert!(!webhook(vec![EventKind::CrashLoop], None).wants(&ban));
+        assert!(!webhook(vec![], Some(tenant)).wants(&ban));
+
+        let endpoint = Endpoint::new(Uuid::new_v4(), webhook(vec![], None), false, false);
+        assert!(endpoint.allow());
+        assert!(endpoint.allow());
+        assert!(!endpoint.allow());
+        assert_eq!(endpoint.info().suppressed, 1);
+
+        let retry = Retry { attempts: 5, delay: Duration::from_secs(1) };
+        assert_eq!(retry.backoff(0), Duration::from_secs(1));
+        assert_eq!(retry.backoff(3), Duration::from_secs(8));
+        assert_eq!(retry.backoff(30), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_registered_webhooks_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NotificationConfig {
+            webhooks: vec![webhook(vec![EventKind::SecurityBan], None)],
+            state_path: dir.path().join("webhooks.json").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let hub = Notifications::new(config.clone(), |_| None).unwrap();
+        let (id, secret) = hub.register(webhook(vec![], Some(Uuid::new_v4())), true).unwrap();
+        assert_eq!(secret.len(), 64);
+        assert!(hub.register(WebhookConfig { url: "ftp://example.com".to_string(), ..webhook(vec![], None) }, false).is_err());
+        let configured = hub.list(None).into_iter().find(|info| info.from_config).unwrap();
+        assert!(hub.remove(configured.id).is_err());
+
+        let hub = Notifications::new(config, |_| None).unwrap();
+        assert_eq!(hub.list(None).len(), 2);
+        assert!(hub.get(id).unwrap().restricted);
+        assert!(hub.remove(id).unwrap());
+        assert!(!hub.remove(id).unwrap());
+        assert_eq!(hub.list(None).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_internal_targets_refused() {
+        let target = |url: &str| WebhookConfig { url: url.to_string(), ..webhook(vec![], None) };
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.5/hook",
+            "https://192.168.1.1/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "http://localhost/hook",
+        ] {
+            assert!(target(url).resolve_public().await.is_err(), "{} accepted", url);
+        }
+
+        let addr = target("https://93.184.216.34/hook").resolve_public().await.unwrap();
+        assert_eq!(addr, "93.184.216.34:443".parse().unwrap());
+        assert!(is_public("2606:4700::1111".parse().unwrap()));
+    }
+}
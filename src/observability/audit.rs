@@ -0,0 +1,315 @@
+// Audit Log
+// Append-only record of who changed what through the management API
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::tenancy::auth::{Role, TokenClaims};
+
+/// `[audit]`: where management actions are recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+
+    /// JSON lines file entries are appended to ("" = memory only)
+    pub path: String,
+
+    /// Recent entries kept in memory for queries; the file keeps everything
+    pub max_entries: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: "audit.jsonl".to_string(),
+            max_entries: 10_000,
+        }
+    }
+}
+
+impl AuditConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled && self.max_entries == 0 {
+            anyhow::bail!("max_entries must be at least 1 when the audit log is enabled");
+        }
+        Ok(())
+    }
+}
+
+/// Who made a change, from the token they presented
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditActor {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub tenant_id: Option<Uuid>,
+
+    /// Who signed in, for single sign-on logins
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+}
+
+impl From<&TokenClaims> for AuditActor {
+    fn from(claims: &TokenClaims) -> Self {
+        Self {
+            user_id: claims.user_id,
+            role: claims.role.clone(),
+            tenant_id: claims.tenant_id,
+            subject: claims.subject.clone(),
+        }
+    }
+}
+
+/// What a change replaced and what it left, when the handler making it knows
+/// Request bodies are never recorded as such, since they can carry secrets and modules.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditChange {
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+/// One management action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Increases by one per entry, across restarts
+    pub id: u64,
+
+    /// Unix time in milliseconds
+    pub timestamp: i64,
+
+    /// None when the request carried no valid token
+    pub actor: Option<AuditActor>,
+
+    /// Method and route, such as `PUT /api/tenants/:tenant_id/quota`
+    pub action: String,
+
+    /// Path the action was applied to
+    pub target: String,
+
+    /// Response status; refused attempts are recorded too
+    pub status: u16,
+
+    /// Address the request came from, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+
+    #[serde(flatten)]
+    pub change: AuditChange,
+}
+
+/// Which entries to return
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    /// User ID, tenant ID or signed-in user of the actor
+    pub actor: Option<String>,
+
+    /// Substring of the action
+    pub action: Option<String>,
+
+    /// Prefix of the target
+    pub target: Option<String>,
+
+    /// Unix time in milliseconds
+    pub since: Option<i64>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        if let Some(actor) = &self.actor {
+            let matched = entry.actor.as_ref().is_some_and(|who| {
+                who.user_id.to_string() == *actor
+                    || who.tenant_id.is_some_and(|tenant_id| tenant_id.to_string() == *actor)
+                    || who.subject.as_deref() == Some(actor.as_str())
+            });
+            if !matched {
+                return false;
+            }
+        }
+        if self.action.as_ref().is_some_and(|action| !entry.action.contains(action.as_str())) {
+            return false;
+        }
+        if self.target.as_ref().is_some_and(|target| !entry.target.starts_with(target.as_str())) {
+            return false;
+        }
+        self.since.map_or(true, |since| entry.timestamp >= since)
+    }
+}
+
+/// The node's audit log: every entry appended to a file, the latest kept in memory
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    file: Mutex<Option<File>>,
+    recent: Mutex<VecDeque<AuditEntry>>,
+    max_entries: usize,
+    next_id: AtomicU64,
+}
+
+impl AuditLog {
+    /// Open the log, reading back its latest entries so IDs carry on where they stopped
+    pub fn open(config: &AuditConfig) -> Result<Self> {
+        let path = (!config.path.is_empty()).then(|| PathBuf::from(&config.path));
+        let mut recent = VecDeque::new();
+        let mut next_id = 1;
+        let file = match &path {
+            Some(path) => {
+                if path.exists() {
+                    for entry in read_entries(path)? {
+                        next_id = next_id.max(entry.id + 1);
+                        if recent.len() >= config.max_entries {
+                            recent.pop_front();
+                        }
+                        recent.push_back(entry);
+                    }
+                }
+                if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir)
+                        .with_context(|| format!("Failed to create {}", dir.display()))?;
+                }
+                let file = OpenOptions::new().create(true).append(true).open(path)
+                    .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            recent: Mutex::new(recent),
+            max_entries: config.max_entries,
+            next_id: AtomicU64::new(next_id),
+        })
+    }
+
+    /// Append an entry, assigning its ID and returning it
+    pub fn record(&self, mut entry: AuditEntry) -> u64 {
+        // The file lock orders IDs the way entries are written
+        let mut file = self.file.lock();
+        entry.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Some(out) = file.as_mut() {
+            let mut line = serde_json::to_vec(&entry).unwrap_or_default();
+            line.push(b'\n');
+            if let Err(e) = out.write_all(&line).and_then(|()| out.sync_data()) {
+                warn!(error = %e, id = entry.id, "Failed to append to the audit log");
+            }
+        }
+        drop(file);
+
+        let id = entry.id;
+        let mut recent = self.recent.lock();
+        if recent.len() >= self.max_entries {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+        id
+    }
+
+    /// The last `limit` matching entries still in memory, oldest first
+    pub fn query(&self, filter: &AuditFilter, limit: usize) -> Vec<AuditEntry> {
+        let recent = self.recent.lock();
+        let mut matched: Vec<AuditEntry> = recent.iter()
+            .rev()
+            .filter(|entry| filter.matches(entry))
+            .take(limit)
+            .cloned()
+            .collect();
+        matched.reverse();
+        matched
+    }
+
+    /// Every entry as JSON lines: the whole file, or what is in memory without one
+    pub fn export(&self) -> Result<Vec<u8>> {
+        if let Some(path) = &self.path {
+            return std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()));
+        }
+        let mut out = Vec::new();
+        for entry in self.recent.lock().iter() {
+            serde_json::to_writer(&mut out, entry)?;
+            out.push(b'\n');
+        }
+        Ok(out)
+    }
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog").field("path", &self.path).finish()
+    }
+}
+
+/// Entries of an audit log file, skipping a line cut short by a crash
+fn read_entries(path: &Path) -> Result<Vec<AuditEntry>> {
+    let file = File::open(path).with_context(|| format!("Failed to open audit log {}", path.display()))?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) if !line.trim().is_empty() => warn!(error = %e, "Skipping unreadable audit log line"),
+            Err(_) => {}
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(actor: Option<AuditActor>, action: &str, target: &str) -> AuditEntry {
+        AuditEntry {
+            id: 0,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            actor,
+            action: action.to_string(),
+            target: target.to_string(),
+            status: 200,
+            ip: None,
+            change: AuditChange::default(),
+        }
+    }
+
+    #[test]
+    fn test_record_query_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AuditConfig {
+            path: dir.path().join("audit.jsonl").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let tenant_id = Uuid::new_v4();
+        let root = AuditActor { user_id: Uuid::nil(), role: Role::RootAdmin, tenant_id: None, subject: None };
+        let tenant = AuditActor { user_id: Uuid::new_v4(), role: Role::TenantAdmin, tenant_id: Some(tenant_id), subject: None };
+
+        let log = AuditLog::open(&config).unwrap();
+        let mut quota = entry(Some(root.clone()), "PUT /api/tenants/:tenant_id/quota", "/api/tenants/acme/quota");
+        quota.change = AuditChange { before: Some(json!({ "max_sites": 5 })), after: Some(json!({ "max_sites": 10 })) };
+        assert_eq!(log.record(quota), 1);
+        assert_eq!(log.record(entry(Some(tenant), "POST /api/sites/:site_id/blue-green", "/api/sites/blog/blue-green")), 2);
+        assert_eq!(log.record(entry(None, "DELETE /api/sites/:site_id", "/api/sites/blog")), 3);
+
+        let by_tenant = AuditFilter { actor: Some(tenant_id.to_string()), ..Default::default() };
+        assert_eq!(log.query(&by_tenant, 10).iter().map(|e| e.id).collect::<Vec<_>>(), vec![2]);
+        let sites = AuditFilter { target: Some("/api/sites/blog".to_string()), ..Default::default() };
+        assert_eq!(log.query(&sites, 10).len(), 2);
+        assert_eq!(log.query(&AuditFilter::default(), 1)[0].id, 3);
+
+        // A reopened log knows its history and keeps counting
+        drop(log);
+        let log = AuditLog::open(&config).unwrap();
+        let first = &log.query(&AuditFilter::default(), 10)[0];
+        assert_eq!(first.actor, Some(root));
+        assert_eq!(first.change.before, Some(json!({ "max_sites": 5 })));
+        assert_eq!(log.record(entry(None, "POST /api/tenants", "/api/tenants")), 4);
+        assert_eq!(String::from_utf8(log.export().unwrap()).unwrap().lines().count(), 4);
+    }
+}
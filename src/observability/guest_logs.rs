@@ -0,0 +1,451 @@
+// Guest Logs
+// Captures what guests print to stdout and stderr into per-site buffers and size-rotated files
+
+use anyhow::{Context, Result, bail};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{Level, warn};
+
+use super::logs::{current_request_id, LogBuffer, LogFilter, LogKind, LogRecord};
+
+/// `[guest_logs]`: where guest output goes instead of the daemon's stdio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuestLogConfig {
+    /// Capture guest output; when off, guests write to the daemon's stdout and stderr
+    pub enabled: bool,
+
+    /// Directory of the `<site>.log` files ("" = memory only)
+    pub dir: String,
+
+    /// Lines kept in memory per site
+    pub buffer_lines: usize,
+
+    /// Size a site's log file may reach before it is rotated
+    pub max_file_bytes: u64,
+
+    /// Rotated files kept per site, as `<site>.log.1` (newest) to `<site>.log.N`
+    pub max_files: usize,
+
+    /// Lines per second a site's guests may print (0 = unlimited)
+    pub lines_per_sec: u32,
+
+    /// Lines a site may print at once before `lines_per_sec` applies
+    pub burst: u32,
+
+    /// Longer lines are split
+    pub max_line_bytes: usize,
+}
+
+impl Default for GuestLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: "guest-logs".to_string(),
+            buffer_lines: 1000,
+            max_file_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+            lines_per_sec: 100,
+            burst: 500,
+            max_line_bytes: 4096,
+        }
+    }
+}
+
+impl GuestLogConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_line_bytes == 0 {
+            bail!("max_line_bytes must be at least 1");
+        }
+        if !self.dir.is_empty() && self.max_file_bytes < self.max_line_bytes as u64 {
+            bail!("max_file_bytes must be at least max_line_bytes, or every line would rotate the file");
+        }
+        if self.lines_per_sec > 0 && self.burst == 0 {
+            bail!("burst must be at least 1 when lines_per_sec is set");
+        }
+        Ok(())
+    }
+}
+
+/// Which of the guest's streams a line was printed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestStream {
+    Stdout,
+    Stderr,
+}
+
+impl GuestStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GuestStream::Stdout => "stdout",
+            GuestStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Output of every site's guests
+pub struct GuestLogs {
+    config: GuestLogConfig,
+    sites: DashMap<String, Arc<SiteLog>>,
+}
+
+impl GuestLogs {
+    pub fn new(config: GuestLogConfig) -> Self {
+        Self { config, sites: DashMap::new() }
+    }
+
+    /// The site's log, opening its file on first use
+    pub fn site(&self, site_id: &str) -> Arc<SiteLog> {
+        self.sites.entry(site_id.to_string())
+            .or_insert_with(|| Arc::new(SiteLog::new(site_id, &self.config)))
+            .clone()
+    }
+
+    /// The site's log, if its guests have been deployed
+    pub fn get(&self, site_id: &str) -> Option<Arc<SiteLog>> {
+        self.sites.get(site_id).map(|site| site.clone())
+    }
+
+    /// The last `limit` matching lines across every site's guests, oldest first
+    pub fn query(&self, filter: &LogFilter, limit: usize) -> Vec<LogRecord> {
+        let mut records: Vec<LogRecord> = self.sites.iter()
+            .filter(|site| filter.site.as_ref().map_or(true, |wanted| wanted == site.key()))
+            .flat_map(|site| site.buffer.query(filter, limit))
+            .collect();
+        records.sort_by_key(|record| record.timestamp);
+        let skip = records.len().saturating_sub(limit);
+        records.split_off(skip)
+    }
+
+    pub fn stats(&self) -> Vec<GuestLogStats> {
+        let mut stats: Vec<_> = self.sites.iter().map(|site| site.stats()).collect();
+        stats.sort_by(|a, b| a.site_id.cmp(&b.site_id));
+        stats
+    }
+}
+
+/// Lines a site's guests printed, and those dropped over the rate limit
+#[derive(Debug, Clone, Serialize)]
+pub struct GuestLogStats {
+    pub site_id: String,
+    pub lines: u64,
+    pub dropped: u64,
+}
+
+/// One site's guest output
+pub struct SiteLog {
+    site_id: String,
+    buffer: LogBuffer,
+    file: Mutex<Option<RotatingFile>>,
+    limiter: Mutex<RateLimiter>,
+    max_line_bytes: usize,
+    lines: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl SiteLog {
+    fn new(site_id: &str, config: &GuestLogConfig) -> Self {
+        let file = (!config.dir.is_empty()).then(|| {
+            RotatingFile::open(Path::new(&config.dir), site_id, config.max_file_bytes, config.max_files)
+        });
+        let file = match file.transpose() {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(site_id = %site_id, error = %e, "Guest output of the site is kept in memory only");
+                None
+            }
+        };
+        Self {
+            site_id: site_id.to_string(),
+            buffer: LogBuffer::new(config.buffer_lines),
+            file: Mutex::new(file),
+            limiter: Mutex::new(RateLimiter::new(config.lines_per_sec, config.burst)),
+            max_line_bytes: config.max_line_bytes,
+            lines: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Recent lines, for `pear logs --source guest`
+    pub fn buffer(&self) -> &LogBuffer {
+        &self.buffer
+    }
+
+    /// Writer for one of a Cage's streams, to hand to its WASI context
+    pub fn writer(self: &Arc<Self>, cage_id: u64, stream: GuestStream) -> GuestWriter {
+        GuestWriter { site: self.clone(), cage_id, stream, partial: Vec::new() }
+    }
+
+    pub fn stats(&self) -> GuestLogStats {
+        GuestLogStats {
+            site_id: self.site_id.clone(),
+            lines: self.lines.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    fn line(&self, cage_id: u64, stream: GuestStream, text: &str) {
+        let suppressed = match self.limiter.lock().take(Instant::now()) {
+            Some(suppressed) => suppressed,
+            None => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        // Say how much was lost once the site is allowed to print again
+        if suppressed > 0 {
+            let notice = format!("{} lines dropped over the rate limit", suppressed);
+            self.append(&Level::WARN, &format!("cage-{}", cage_id), &notice);
+        }
+
+        self.lines.fetch_add(1, Ordering::Relaxed);
+        let level = match stream {
+            GuestStream::Stdout => Level::INFO,
+            GuestStream::Stderr => Level::WARN,
+        };
+        self.append(&level, &format!("cage-{}::{}", cage_id, stream.as_str()), text);
+    }
+
+    fn append(&self, level: &Level, target: &str, text: &str) {
+        let timestamp = chrono::Utc::now();
+        let mut file = self.file.lock();
+        if let Some(rotating) = file.as_mut() {
+            let line = format!("{} {:<5} {}: {}\n", timestamp.to_rfc3339(), level, target, text);
+            if let Err(e) = rotating.write(line.as_bytes()) {
+                warn!(site_id = %self.site_id, error = %e, "Failed to write guest log, keeping it in memory only");
+                *file = None;
+            }
+        }
+        drop(file);
+        self.buffer.push(LogRecord {
+            seq: 0,
+            timestamp: timestamp.timestamp_millis(),
+            level: level.to_string(),
+            kind: LogKind::Guest,
+            target: target.to_string(),
+            site: Some(self.site_id.clone()),
+            request_id: current_request_id(),
+            message: text.to_string(),
+        });
+    }
+}
+
+impl std::fmt::Debug for SiteLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SiteLog").field("site_id", &self.site_id).finish()
+    }
+}
+
+/// Splits a guest stream into lines for its site's log
+/// A line the guest never finished is logged when the writer is dropped with its WASI context.
+pub struct GuestWriter {
+    site: Arc<SiteLog>,
+    cage_id: u64,
+    stream: GuestStream,
+    partial: Vec<u8>,
+}
+
+impl GuestWriter {
+    fn emit(&mut self, line: &[u8]) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        self.site.line(self.cage_id, self.stream, &String::from_utf8_lossy(line));
+    }
+}
+
+impl Write for GuestWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let max = self.site.max_line_bytes;
+        for &byte in buf {
+            if byte == b'\n' {
+                let line = std::mem::take(&mut self.partial);
+                self.emit(&line);
+                continue;
+            }
+            self.partial.push(byte);
+            if self.partial.len() >= max {
+                let line = std::mem::take(&mut self.partial);
+                self.emit(&line);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for GuestWriter {
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.emit(&line);
+        }
+    }
+}
+
+/// Token bucket of lines, counting what it turned away since it last allowed one
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+    suppressed: u64,
+}
+
+impl RateLimiter {
+    fn new(lines_per_sec: u32, burst: u32) -> Self {
+        Self {
+            rate: lines_per_sec as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled: Instant::now(),
+            suppressed: 0,
+        }
+    }
+
+    /// Lines dropped since the last allowed one, or None if this one is dropped too
+    fn take(&mut self, now: Instant) -> Option<u64> {
+        if self.rate > 0.0 {
+            let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+            self.refilled = now;
+            if self.tokens < 1.0 {
+                self.suppressed += 1;
+                return None;
+            }
+            self.tokens -= 1.0;
+        }
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+/// Append-only file moved aside once it reaches its size limit
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(dir: &Path, site_id: &str, max_bytes: u64, max_files: usize) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create guest log directory {}", dir.display()))?;
+        // Site IDs come from users, so they never reach the path unescaped
+        let name: String = site_id.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+            .collect();
+        let path = dir.join(format!("{}.log", name));
+        let file = Self::append_to(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size, max_bytes, max_files })
+    }
+
+    fn append_to(path: &Path) -> Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Failed to open guest log {}", path.display()))
+    }
+
+    fn write(&mut self, line: &[u8]) -> Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+            self.size = 0;
+            return Ok(());
+        }
+        // The oldest file is overwritten by the one before it
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated(1))?;
+        self.file = Self::append_to(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::logs::LogFilter;
+    use std::time::Duration;
+
+    #[test]
+    fn test_writer_splits_lines() {
+        let logs = GuestLogs::new(GuestLogConfig { dir: String::new(), max_line_bytes: 16, ..Default::default() });
+        let site = logs.site("blog");
+        let mut stdout = site.writer(3, GuestStream::Stdout);
+        stdout.write_all(b"hello ").unwrap();
+        stdout.write_all(b"world\r\nthis line is far too long\nunfinished").unwrap();
+        let mut stderr = site.writer(3, GuestStream::Stderr);
+        stderr.write_all(b"oops\n").unwrap();
+        drop(stdout);
+
+        let lines: Vec<_> = site.buffer().query(&LogFilter::default(), 10).into_iter()
+            .map(|r| (r.target, r.message))
+            .collect();
+        assert_eq!(lines, vec![
+            ("cage-3::stdout".to_string(), "hello world".to_string()),
+            ("cage-3::stdout".to_string(), "this line is far".to_string()),
+            ("cage-3::stdout".to_string(), " too long".to_string()),
+            ("cage-3::stderr".to_string(), "oops".to_string()),
+            ("cage-3::stdout".to_string(), "unfinished".to_string()),
+        ]);
+        assert!(site.buffer().query(&LogFilter::default(), 10).iter().all(|r| r.kind == LogKind::Guest));
+    }
+
+    #[test]
+    fn test_rate_limit_drops_and_reports() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(10, 2);
+        assert_eq!(limiter.take(start), Some(0));
+        assert_eq!(limiter.take(start), Some(0));
+        assert_eq!(limiter.take(start), None);
+        assert_eq!(limiter.take(start), None);
+        // A tenth of a second earns one more line, which reports the two dropped
+        assert_eq!(limiter.take(start + Duration::from_millis(100)), Some(2));
+        assert_eq!(RateLimiter::new(0, 0).take(start), Some(0));
+    }
+
+    #[test]
+    fn test_file_rotation() {
+        let dir = std::env::temp_dir().join(format!("pear-guest-logs-{}", uuid::Uuid::new_v4()));
+        let mut file = RotatingFile::open(&dir, "../blog", 10, 2).unwrap();
+        for line in ["aaaaaa\n", "bbbbbb\n", "cccccc\n", "dddddd\n"] {
+            file.write(line.as_bytes()).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read(".._blog.log"), "dddddd\n");
+        assert_eq!(read(".._blog.log.1"), "cccccc\n");
+        assert_eq!(read(".._blog.log.2"), "bbbbbb\n");
+        assert!(!dir.join(".._blog.log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+// Health Checks
+// Liveness and readiness of the node, for load balancer and Kubernetes probes
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::cage::pool::PoolHealthStats;
+use crate::network::tls;
+use crate::router::Router;
+use crate::supervisor::Supervisor;
+
+/// `[health]`: what `/readyz` requires before the node takes traffic
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Sites that need at least one healthy Cage (empty = every deployed site)
+    pub required_sites: Vec<String>,
+}
+
+/// One condition readiness depends on
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, ok: bool, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), ok, detail: detail.into() }
+    }
+}
+
+/// Every readiness check, and whether all of them passed
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+/// What readiness is judged on, beyond state the router and supervisor already hold
+pub struct Health {
+    config: HealthConfig,
+    cert_path: Option<String>,
+    listening: AtomicBool,
+    shutting_down: AtomicBool,
+    rebalancing: AtomicBool,
+    restoring: AtomicBool,
+    sites_to_restore: AtomicUsize,
+    sites_restored: AtomicUsize,
+    started: Instant,
+}
+
+impl Health {
+    pub fn new(config: HealthConfig, cert_path: Option<String>) -> Self {
+        Self {
+            config,
+            cert_path,
+            listening: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            rebalancing: AtomicBool::new(false),
+            restoring: AtomicBool::new(false),
+            sites_to_restore: AtomicUsize::new(0),
+            sites_restored: AtomicUsize::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    /// Set once every listener serves
+    pub fn set_listening(&self, listening: bool) {
+        self.listening.store(listening, Ordering::Relaxed);
+    }
+
+    /// Fail readiness from now on, while listeners may still be serving
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Fail readiness while connections are shed to other nodes, so no new ones are sent here
+    pub fn set_rebalancing(&self, rebalancing: bool) {
+        self.rebalancing.store(rebalancing, Ordering::Relaxed);
+    }
+
+    /// Fail readiness until `finish_restore`, while `sites` saved sites are restored
+    pub fn begin_restore(&self, sites: usize) {
+        self.sites_to_restore.store(sites, Ordering::Relaxed);
+        self.sites_restored.store(0, Ordering::Relaxed);
+        self.restoring.store(true, Ordering::Relaxed);
+    }
+
+    /// Count a saved site as done, whether or not it came back
+    pub fn site_restored(&self) {
+        self.sites_restored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finish_restore(&self) {
+        self.restoring.store(false, Ordering::Relaxed);
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Run every check; the node is ready when all of them pass
+    pub async fn readiness(&self, router: &Router, supervisor: &Supervisor) -> Readiness {
+        let mut checks = Vec::new();
+
+        let listening = self.listening.load(Ordering::Relaxed);
+        let shutting_down = self.shutting_down.load(Ordering::Relaxed);
+        checks.push(Check::new(
+            "listeners",
+            listening && !shutting_down,
+            if shutting_down {
+                "shutting down"
+            } else if listening {
+                "bound and accepting"
+            } else {
+                "not accepting connections"
+            },
+        ));
+
+        if self.rebalancing.load(Ordering::Relaxed) {
+            checks.push(Check::new("rebalance", false, "shedding connections to other nodes"));
+        }
+
+        let restoring = self.restoring.load(Ordering::Relaxed);
+        let to_restore = self.sites_to_restore.load(Ordering::Relaxed);
+        if restoring || to_restore > 0 {
+            let detail = if restoring {
+                format!("restoring saved sites ({}/{} done)", self.sites_restored.load(Ordering::Relaxed), to_restore)
+            } else {
+                format!("done with {} saved sites", to_restore)
+            };
+            checks.push(Check::new("restore", !restoring, detail));
+        }
+
+        let supervisor = supervisor.stats();
+        checks.push(Check::new(
+            "supervisor",
+            supervisor.is_running,
+            if supervisor.is_running {
+                format!("supervising {} pool(s)", supervisor.supervised_pools)
+            } else {
+                "not running".to_string()
+            },
+        ));
+
+        let mut sites = if self.config.required_sites.is_empty() {
+            router.site_ids()
+        } else {
+            self.config.required_sites.clone()
+        };
+        sites.sort();
+        for site_id in sites {
+            let stats = match router.pool(&site_id) {
+                Some(pool) => Some(pool.health_stats().await),
+                None => None,
+            };
+            checks.push(site_check(&site_id, stats.as_ref()));
+        }
+
+        if let Some(cert_path) = &self.cert_path {
+            let check = match tls::load_certificates(cert_path).and_then(|certs| tls::validity(&certs[0])) {
+                Ok(validity) => validity_check(&validity, chrono::Utc::now().timestamp()),
+                Err(e) => Check::new("certificate", false, format!("{:#}", e)),
+            };
+            checks.push(check);
+        }
+
+        Readiness { ready: checks.iter().all(|check| check.ok), checks }
+    }
+}
+
+fn site_check(site_id: &str, stats: Option<&PoolHealthStats>) -> Check {
+    let name = format!("site:{}", site_id);
+    match stats {
+        // Drained on purpose, so the node stays ready
+        Some(stats) if stats.draining => Check::new(name, true, "draining for maintenance"),
+        Some(stats) if stats.cold => Check::new(name, true, "idle; starts on the next request"),
+        Some(stats) => Check::new(
+            name,
+            stats.healthy_cages > 0,
+            format!("{}/{} Cages healthy", stats.healthy_cages, stats.total_cages),
+        ),
+        None => Check::new(name, false, "not deployed"),
+    }
+}
+
+/// Only an expired or not yet valid certificate fails; `pear doctor` warns ahead of expiry
+fn validity_check(validity: &tls::Validity, now: i64) -> Check {
+    if now < validity.not_before {
+        Check::new("certificate", false, format!("{} is not valid yet", validity.subject))
+    } else if now >= validity.not_after {
+        Check::new("certificate", false, format!("{} has expired", validity.subject))
+    } else {
+        let days_left = (validity.not_after - now) / 86_400;
+        Check::new("certificate", true, format!("{} valid for {} more days", validity.subject, days_left))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::RouterConfig;
+    use crate::supervisor::SupervisorConfig;
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let router = Router::new(RouterConfig::default());
+        let supervisor = Supervisor::new(SupervisorConfig::default());
+        let health = Health::new(HealthConfig { required_sites: vec!["blog".to_string()] }, None);
+
+        let failing = |readiness: &Readiness| -> Vec<String> {
+            readiness.checks.iter().filter(|check| !check.ok).map(|check| check.name.clone()).collect()
+        };
+        let readiness = health.readiness(&router, &supervisor).await;
+        assert!(!readiness.ready);
+        assert_eq!(failing(&readiness), vec!["listeners", "supervisor", "site:blog"]);
+
+        health.set_listening(true);
+        supervisor.start().await;
+        assert_eq!(failing(&health.readiness(&router, &supervisor).await), vec!["site:blog"]);
+
+        health.begin_restore(2);
+        health.site_restored();
+        let readiness = health.readiness(&router, &supervisor).await;
+        assert_eq!(failing(&readiness), vec!["restore", "site:blog"]);
+        assert_eq!(readiness.checks[1].detail, "restoring saved sites (1/2 done)");
+        health.finish_restore();
+        assert_eq!(failing(&health.readiness(&router, &supervisor).await), vec!["site:blog"]);
+
+        health.set_rebalancing(true);
+        assert_eq!(failing(&health.readiness(&router, &supervisor).await), vec!["rebalance", "site:blog"]);
+        health.set_rebalancing(false);
+
+        health.begin_shutdown();
+        assert_eq!(failing(&health.readiness(&router, &supervisor).await), vec!["listeners", "site:blog"]);
+        supervisor.stop();
+    }
+
+    #[test]
+    fn test_site_and_certificate_checks() {
+        let mut stats = PoolHealthStats {
+            site_id: "blog".to_string(),
+            total_cages: 2,
+            healthy_cages: 0,
+            crashed_cages: 2,
+            initializing_cages: 0,
+            draining: false,
+            cold: false,
+            standby_cages: 0,
+            standby_target: 0,
+        };
+        assert!(!site_check("blog", Some(&stats)).ok);
+        stats.healthy_cages = 1;
+        assert_eq!(site_check("blog", Some(&stats)).detail, "1/2 Cages healthy");
+        assert!(site_check("blog", Some(&stats)).ok);
+        stats.healthy_cages = 0;
+        stats.draining = true;
+        assert!(site_check("blog", Some(&stats)).ok);
+        stats.draining = false;
+        stats.total_cages = 0;
+        stats.cold = true;
+        assert_eq!(site_check("blog", Some(&stats)).detail, "idle; starts on the next request");
+        assert!(site_check("blog", Some(&stats)).ok);
+
+        let now = 1_700_000_000;
+        let validity = |not_before, not_after| tls::Validity { subject: "CN=a".to_string(), not_before, not_after };
+        assert!(validity_check(&validity(now - 10, now + 3 * 86_400), now).ok);
+        assert!(!validity_check(&validity(now - 100, now - 10), now).ok);
+        assert!(!validity_check(&validity(now + 10, now + 100), now).ok);
+    }
+}
@@ -0,0 +1,210 @@
+// Latency Histograms
+// Lock-free fixed-bucket histograms with log-linear buckets (12.5% precision)
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Sub-buckets per power of two, as a bit count
+const SUB_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+
+/// Largest power of two tracked, in microseconds (2^36 µs is about 19 hours)
+const MAX_EXPONENT: u32 = 36;
+
+/// Number of buckets; anything slower lands in the last one
+pub const BUCKETS: usize = SUB_BUCKETS * (MAX_EXPONENT - SUB_BITS + 2) as usize;
+
+/// Bucket holding a latency of `micros`
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+    let exponent = 63 - micros.leading_zeros();
+    let mantissa = (micros >> (exponent - SUB_BITS)) as usize - SUB_BUCKETS;
+    let index = SUB_BUCKETS * (exponent - SUB_BITS + 1) as usize + mantissa;
+    index.min(BUCKETS - 1)
+}
+
+/// Smallest latency, in microseconds, that falls into bucket `index`
+fn bucket_lower_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let exponent = (index / SUB_BUCKETS) as u32 - 1 + SUB_BITS;
+    let mantissa = (index % SUB_BUCKETS) as u64;
+    (SUB_BUCKETS as u64 + mantissa) << (exponent - SUB_BITS)
+}
+
+/// Cumulative latency distribution, safe to record into from any thread
+pub struct LatencyHistogram {
+    counts: Box<[AtomicU64]>,
+    sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            counts: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.counts[bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Everything recorded so far
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            counts: self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect(),
+            sum_micros: self.sum_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Point-in-time copy of a histogram; the difference of two covers the time between them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistogramSnapshot {
+    counts: Vec<u64>,
+    sum_micros: u64,
+}
+
+impl HistogramSnapshot {
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => Duration::from_micros(self.sum_micros / count),
+        }
+    }
+
+    /// Latency below which `quantile` (0.0 to 1.0) of the recorded requests fall
+    /// Reported as the upper end of the bucket the quantile lands in.
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+
+        let rank = ((quantile.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, bucket) in self.counts.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                return Duration::from_micros(bucket_lower_bound(index + 1) - 1);
+            }
+        }
+        Duration::from_micros(bucket_lower_bound(BUCKETS - 1))
+    }
+
+    /// Total of all recorded latencies
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros)
+    }
+
+    /// Count, mean and the usual percentiles
+    pub fn percentiles(&self) -> LatencyPercentiles {
+        let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+        LatencyPercentiles {
+            count: self.count(),
+            mean_ms: ms(self.mean()),
+            p50_ms: ms(self.percentile(0.5)),
+            p90_ms: ms(self.percentile(0.9)),
+            p99_ms: ms(self.percentile(0.99)),
+        }
+    }
+
+    /// What was recorded after `earlier` was taken
+    pub fn since(&self, earlier: &HistogramSnapshot) -> HistogramSnapshot {
+        HistogramSnapshot {
+            counts: self.counts.iter()
+                .zip(earlier.counts.iter().chain(std::iter::repeat(&0)))
+                .map(|(now, before)| now.saturating_sub(*before))
+                .collect(),
+            sum_micros: self.sum_micros.saturating_sub(earlier.sum_micros),
+        }
+    }
+
+    /// Add another snapshot's requests to this one
+    pub fn merge(&mut self, other: &HistogramSnapshot) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, added) in self.counts.iter_mut().zip(&other.counts) {
+            *count += added;
+        }
+        self.sum_micros += other.sum_micros;
+    }
+}
+
+/// Summary of a latency distribution, in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct LatencyPercentiles {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl Default for HistogramSnapshot {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            sum_micros: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_are_contiguous() {
+        for index in 0..BUCKETS - 1 {
+            let lower = bucket_lower_bound(index);
+            assert_eq!(bucket_index(lower), index);
+            assert_eq!(bucket_index(bucket_lower_bound(index + 1) - 1), index);
+        }
+        assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_percentiles_and_deltas() {
+        let histogram = LatencyHistogram::new();
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        let first = histogram.snapshot();
+        assert_eq!(first.count(), 100);
+
+        // Within the bucket precision of the true values
+        let p50 = first.percentile(0.5).as_micros() as f64;
+        let p99 = first.percentile(0.99).as_micros() as f64;
+        assert!((50_000.0..=50_000.0 * 1.125).contains(&p50), "p50 {}", p50);
+        assert!((99_000.0..=99_000.0 * 1.125).contains(&p99), "p99 {}", p99);
+
+        histogram.record(Duration::from_secs(2));
+        let delta = histogram.snapshot().since(&first);
+        assert_eq!(delta.count(), 1);
+        assert!(delta.percentile(0.5) >= Duration::from_secs(2));
+
+        let mut merged = first.clone();
+        merged.merge(&delta);
+        assert_eq!(merged.count(), 101);
+        assert_eq!(HistogramSnapshot::default().percentile(0.99), Duration::ZERO);
+    }
+}
@@ -0,0 +1,452 @@
+// Metrics History
+// Per-minute rollups of server counters kept for days, downsampled to hourly rollups for longer ranges
+
+use super::histogram::HistogramSnapshot;
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{info, error};
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 3600;
+const DAY: i64 = 86400;
+
+/// Metrics history configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsHistoryConfig {
+    /// Record per-minute rollups
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Days of per-minute rollups kept
+    #[serde(default = "default_minute_retention")]
+    pub minute_retention_days: u32,
+
+    /// Days of hourly rollups kept
+    #[serde(default = "default_hourly_retention")]
+    pub hourly_retention_days: u32,
+
+    /// File the history is persisted to ("" = memory only)
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+
+    /// Seconds between writes of the history file
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval_secs: u64,
+}
+
+fn default_true() -> bool { true }
+fn default_minute_retention() -> u32 { 7 }
+fn default_hourly_retention() -> u32 { 90 }
+fn default_state_path() -> String { "metrics-history.json".to_string() }
+fn default_flush_interval() -> u64 { 300 }
+
+impl Default for MetricsHistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            minute_retention_days: default_minute_retention(),
+            hourly_retention_days: default_hourly_retention(),
+            state_path: default_state_path(),
+            flush_interval_secs: default_flush_interval(),
+        }
+    }
+}
+
+impl MetricsHistoryConfig {
+    /// Check that retention windows are sensible
+    pub fn validate(&self) -> Result<()> {
+        if self.minute_retention_days == 0 {
+            anyhow::bail!("minute_retention_days must be at least 1");
+        }
+
+        if self.hourly_retention_days < self.minute_retention_days {
+            anyhow::bail!("hourly_retention_days must not be shorter than minute_retention_days");
+        }
+
+        if self.flush_interval_secs == 0 {
+            anyhow::bail!("flush_interval_secs must be at least 1");
+        }
+
+        Ok(())
+    }
+}
+
+/// Cumulative counters read from the running server
+#[derive(Debug, Clone, Default)]
+pub struct MetricTotals {
+    pub requests: u64,
+    pub errors: u64,
+    pub cage_restarts: u64,
+    pub threats: u64,
+    pub latency: HistogramSnapshot,
+}
+
+/// Activity over one minute or hour
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rollup {
+    /// Unix timestamp (seconds) of the minute or hour the rollup covers
+    pub timestamp: i64,
+
+    pub requests: u64,
+    pub errors: u64,
+    pub cage_restarts: u64,
+    pub threats: u64,
+
+    /// Latency percentiles in milliseconds
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl Rollup {
+    fn new(timestamp: i64, delta: &MetricTotals) -> Self {
+        let latency = delta.latency.percentiles();
+        Self {
+            timestamp,
+            requests: delta.requests,
+            errors: delta.errors,
+            cage_restarts: delta.cage_restarts,
+            threats: delta.threats,
+            p50_ms: latency.p50_ms,
+            p90_ms: latency.p90_ms,
+            p99_ms: latency.p99_ms,
+        }
+    }
+
+    /// Fold a later rollup into this one; percentiles keep the worst period's
+    fn absorb(&mut self, other: &Rollup) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.cage_restarts += other.cage_restarts;
+        self.threats += other.threats;
+        self.p50_ms = self.p50_ms.max(other.p50_ms);
+        self.p90_ms = self.p90_ms.max(other.p90_ms);
+        self.p99_ms = self.p99_ms.max(other.p99_ms);
+    }
+}
+
+/// The hour being accumulated, with its full latency distribution for exact percentiles
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct OpenHour {
+    timestamp: i64,
+    requests: u64,
+    errors: u64,
+    cage_restarts: u64,
+    threats: u64,
+    latency: HistogramSnapshot,
+}
+
+impl OpenHour {
+    fn close(&self) -> Rollup {
+        Rollup::new(self.timestamp, &MetricTotals {
+            requests: self.requests,
+            errors: self.errors,
+            cage_restarts: self.cage_restarts,
+            threats: self.threats,
+            latency: self.latency.clone(),
+        })
+    }
+}
+
+/// Persisted form of the history
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HistoryState {
+    minutes: VecDeque<Rollup>,
+    hours: VecDeque<Rollup>,
+    open_hour: Option<OpenHour>,
+}
+
+/// Ring buffers of minute and hour rollups
+pub struct MetricsHistory {
+    config: MetricsHistoryConfig,
+    state_path: Option<PathBuf>,
+    state: Mutex<HistoryState>,
+
+    /// Totals at the previous sample; counters restart with the process
+    last: Mutex<Option<MetricTotals>>,
+
+    /// History changed since the last save
+    dirty: AtomicBool,
+
+    /// Serializes saves from the flush loop and shutdown
+    save_lock: Mutex<()>,
+}
+
+impl MetricsHistory {
+    /// Create a history, loading persisted rollups if present
+    pub fn new(config: MetricsHistoryConfig) -> Result<Self> {
+        let state_path = Some(&config.state_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+
+        let state = match &state_path {
+            Some(path) if path.exists() => load_state(path)?,
+            _ => HistoryState::default(),
+        };
+
+        info!(
+            minutes = state.minutes.len(),
+            hours = state.hours.len(),
+            state_path = ?state_path,
+            "Metrics history initialized"
+        );
+
+        Ok(Self {
+            config,
+            state_path,
+            state: Mutex::new(state),
+            last: Mutex::new(None),
+            dirty: AtomicBool::new(false),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Record the server's totals, adding the change since the previous call as a minute rollup
+    /// The first call after startup only sets the baseline.
+    pub fn record(&self, timestamp: i64, totals: MetricTotals) {
+        let delta = {
+            let mut last = self.last.lock();
+            let delta = last.as_ref().map(|previous| MetricTotals {
+                requests: totals.requests.saturating_sub(previous.requests),
+                errors: totals.errors.saturating_sub(previous.errors),
+                cage_restarts: totals.cage_restarts.saturating_sub(previous.cage_restarts),
+                threats: totals.threats.saturating_sub(previous.threats),
+                latency: totals.latency.since(&previous.latency),
+            });
+            *last = Some(totals);
+            delta
+        };
+
+        if let Some(delta) = delta {
+            self.record_delta(timestamp, &delta);
+        }
+    }
+
+    fn record_delta(&self, timestamp: i64, delta: &MetricTotals) {
+        let minute = timestamp - timestamp.rem_euclid(MINUTE);
+        let hour = timestamp - timestamp.rem_euclid(HOUR);
+        let mut state = self.state.lock();
+
+        state.minutes.push_back(Rollup::new(minute, delta));
+        let oldest_minute = minute - self.config.minute_retention_days as i64 * DAY;
+        while state.minutes.front().is_some_and(|rollup| rollup.timestamp < oldest_minute) {
+            state.minutes.pop_front();
+        }
+
+        if state.open_hour.as_ref().is_some_and(|open| open.timestamp != hour) {
+            let closed = state.open_hour.take().expect("open hour").close();
+            state.hours.push_back(closed);
+            let oldest_hour = hour - self.config.hourly_retention_days as i64 * DAY;
+            while state.hours.front().is_some_and(|rollup| rollup.timestamp < oldest_hour) {
+                state.hours.pop_front();
+            }
+        }
+        let open = state.open_hour.get_or_insert_with(|| OpenHour {
+            timestamp: hour,
+            ..Default::default()
+        });
+        open.requests += delta.requests;
+        open.errors += delta.errors;
+        open.cage_restarts += delta.cage_restarts;
+        open.threats += delta.threats;
+        open.latency.merge(&delta.latency);
+
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Rollups since `since` (Unix seconds), merged into periods of at least `step_secs`
+    /// Minute rollups serve ranges they still cover; older ranges use hourly rollups.
+    pub fn query(&self, since: i64, step_secs: i64) -> Vec<Rollup> {
+        let state = self.state.lock();
+        let oldest_hour = state.hours.front().map(|r| r.timestamp)
+            .or(state.open_hour.as_ref().map(|open| open.timestamp))
+            .unwrap_or(i64::MAX);
+        let minutes_cover = state.minutes.front()
+            .is_some_and(|oldest| oldest.timestamp - oldest.timestamp.rem_euclid(HOUR) <= since.max(oldest_hour));
+
+        if minutes_cover {
+            return downsample(state.minutes.iter().filter(|r| r.timestamp >= since), MINUTE, step_secs);
+        }
+
+        let open = state.open_hour.as_ref().map(OpenHour::close);
+        let hours: Vec<Rollup> = state.hours.iter().copied().chain(open).collect();
+        downsample(hours.iter().filter(|r| r.timestamp >= since), HOUR, step_secs)
+    }
+
+    /// Everything since `since` merged into one rollup, or None without any rollups
+    pub fn summary(&self, since: i64) -> Option<Rollup> {
+        self.query(since, MINUTE).into_iter().reduce(|mut total, rollup| {
+            total.absorb(&rollup);
+            total
+        })
+    }
+
+    /// Write the history to the state file if it changed since the last save
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+
+        let _guard = self.save_lock.lock();
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let data = serde_json::to_vec(&*self.state.lock())?;
+        let result = save_state(path, &data);
+        if result.is_err() {
+            // Try again on the next flush
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Record a minute rollup every minute from `totals`, and periodically persist the history
+    pub fn start<F>(self: &Arc<Self>, totals: F)
+    where
+        F: Fn() -> MetricTotals + Send + 'static,
+    {
+        let history = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(MINUTE as u64));
+            loop {
+                interval.tick().await;
+                history.record(chrono::Utc::now().timestamp(), totals());
+            }
+        });
+
+        let history = self.clone();
+        let flush_interval = Duration::from_secs(self.config.flush_interval_secs.max(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flush_interval);
+            loop {
+                interval.tick().await;
+
+                let history = history.clone();
+                let result = tokio::task::spawn_blocking(move || history.save()).await;
+                match result {
+                    Ok(Err(e)) => error!(error = %e, "Failed to persist metrics history"),
+                    Err(e) => error!(error = %e, "Metrics history flush task failed"),
+                    Ok(Ok(())) => {}
+                }
+            }
+        });
+    }
+}
+
+/// Merge consecutive rollups into buckets of `step_secs`, aligned to the step
+fn downsample<'a>(rollups: impl Iterator<Item = &'a Rollup>, period: i64, step_secs: i64) -> Vec<Rollup> {
+    let step = step_secs.max(period);
+    let mut merged: Vec<Rollup> = Vec::new();
+    for rollup in rollups {
+        let bucket = rollup.timestamp - rollup.timestamp.rem_euclid(step);
+        match merged.last_mut() {
+            Some(last) if last.timestamp == bucket => last.absorb(rollup),
+            _ => merged.push(Rollup { timestamp: bucket, ..*rollup }),
+        }
+    }
+    merged
+}
+
+fn load_state(path: &Path) -> Result<HistoryState> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_state(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, data)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observability::histogram::LatencyHistogram;
+
+    fn history() -> MetricsHistory {
+        MetricsHistory::new(MetricsHistoryConfig {
+            minute_retention_days: 1,
+            hourly_retention_days: 2,
+            state_path: String::new(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_minute_rollups_and_hourly_downsampling() {
+        let history = history();
+        let latency = LatencyHistogram::new();
+        let mut totals = MetricTotals::default();
+        history.record(0, totals.clone());
+
+        // Two hours of ten requests a minute, one of them slow in the second hour
+        for minute in 1..=120 {
+            for _ in 0..10 {
+                latency.record(Duration::from_millis(10));
+            }
+            if minute == 90 {
+                latency.record(Duration::from_secs(1));
+                totals.errors += 1;
+            }
+            totals.requests += 10;
+            totals.latency = latency.snapshot();
+            history.record(minute * MINUTE, totals.clone());
+        }
+
+        let minutes = history.query(0, MINUTE);
+        assert_eq!(minutes.len(), 120);
+        assert_eq!(minutes[0].timestamp, MINUTE);
+        assert!(minutes.iter().all(|r| r.requests == 10));
+        assert!(minutes[89].p99_ms >= 1000.0);
+
+        // Merging into 30 minute steps keeps totals and the worst percentile
+        let steps = history.query(0, 30 * MINUTE);
+        assert_eq!(steps.iter().map(|r| r.requests).sum::<u64>(), 1200);
+        assert_eq!(steps[3].errors, 1);
+        assert!(steps[3].p99_ms >= 1000.0);
+        assert!(steps[2].p99_ms < 20.0);
+
+        // Push the early minutes out of retention; the hourly tier still has them
+        totals.requests += 5;
+        history.record(DAY + 2 * HOUR, totals.clone());
+        let hours = history.query(0, HOUR);
+        assert_eq!(hours[0].timestamp, 0);
+        assert_eq!(hours[0].requests, 590);
+        assert_eq!(hours[1].errors, 1);
+        // One slow request in 601 is under the hour's p99
+        assert!(hours[1].p99_ms < 20.0);
+    }
+
+    #[test]
+    fn test_state_survives_restart() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = MetricsHistoryConfig {
+            state_path: dir.path().join("history.json").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let history = MetricsHistory::new(config.clone()).unwrap();
+        history.record(0, MetricTotals::default());
+        history.record(MINUTE, MetricTotals { requests: 7, ..Default::default() });
+        history.save().unwrap();
+
+        let restored = MetricsHistory::new(config).unwrap();
+        assert_eq!(restored.query(0, MINUTE)[0].requests, 7);
+    }
+}
@@ -0,0 +1,329 @@
+// Log Capture
+// Keeps recent daemon and access log lines in memory so `pear logs` can query and follow them
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Target of the per-request access log lines
+pub const ACCESS_TARGET: &str = "pear_server::access";
+
+/// Lines kept in memory
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Live lines a slow follower may fall behind by before it skips ahead
+const FOLLOW_BUFFER: usize = 1024;
+
+/// Whether a line came from the daemon itself, from serving a request, or from a guest's output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogKind {
+    Daemon,
+    Access,
+    Guest,
+}
+
+/// One captured log line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// Increases by one per line, so followers can skip what they have seen
+    pub seq: u64,
+
+    /// Unix time in milliseconds
+    pub timestamp: i64,
+    pub level: String,
+    pub kind: LogKind,
+    pub target: String,
+
+    /// Site the line is about, from a `site` or `site_id` field
+    pub site: Option<String>,
+
+    /// Request the line was logged while serving, from a `request_id` field or span
+    #[serde(default)]
+    pub request_id: Option<String>,
+
+    /// The message followed by the event's other fields
+    pub message: String,
+}
+
+/// Which lines to return
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    pub site: Option<String>,
+
+    /// Least severe level to include
+    pub level: Option<String>,
+
+    /// Unix time in milliseconds
+    pub since: Option<i64>,
+
+    /// Case-sensitive substring of the message
+    pub grep: Option<String>,
+
+    pub kind: Option<LogKind>,
+
+    pub request_id: Option<String>,
+}
+
+impl LogFilter {
+    /// Check the filter's level before using it
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(level) = &self.level {
+            level.parse::<Level>().map_err(|_| anyhow::anyhow!("Unknown log level '{}'", level))?;
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        if self.site.as_ref().is_some_and(|site| record.site.as_ref() != Some(site)) {
+            return false;
+        }
+        if self.kind.is_some_and(|kind| kind != record.kind) {
+            return false;
+        }
+        if self.request_id.as_ref().is_some_and(|id| record.request_id.as_ref() != Some(id)) {
+            return false;
+        }
+        if self.since.is_some_and(|since| record.timestamp < since) {
+            return false;
+        }
+        if let Some(level) = self.level.as_ref().and_then(|level| level.parse::<Level>().ok()) {
+            // More verbose levels compare greater
+            match record.level.parse::<Level>() {
+                Ok(record_level) if record_level <= level => {}
+                _ => return false,
+            }
+        }
+        if self.grep.as_ref().is_some_and(|pattern| !record.message.contains(pattern.as_str())) {
+            return false;
+        }
+        true
+    }
+}
+
+/// Ring buffer of recent log lines with a live feed for followers
+pub struct LogBuffer {
+    capacity: usize,
+    records: Mutex<VecDeque<LogRecord>>,
+    next_seq: AtomicU64,
+    live: broadcast::Sender<LogRecord>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let (live, _) = broadcast::channel(FOLLOW_BUFFER);
+        Self {
+            capacity: capacity.max(1),
+            records: Mutex::new(VecDeque::with_capacity(capacity.min(DEFAULT_CAPACITY))),
+            next_seq: AtomicU64::new(1),
+            live,
+        }
+    }
+
+    /// Add a line; its `seq` is assigned here
+    pub fn push(&self, mut record: LogRecord) {
+        let mut records = self.records.lock();
+        record.seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record.clone());
+        // Nobody following is fine
+        let _ = self.live.send(record);
+    }
+
+    /// The newest `limit` lines matching `filter`, oldest first
+    pub fn query(&self, filter: &LogFilter, limit: usize) -> Vec<LogRecord> {
+        let records = self.records.lock();
+        let mut matched: Vec<LogRecord> = records.iter()
+            .rev()
+            .filter(|record| filter.matches(record))
+            .take(limit)
+            .cloned()
+            .collect();
+        matched.reverse();
+        matched
+    }
+
+    /// Lines pushed from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<LogRecord> {
+        self.live.subscribe()
+    }
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Tracing layer copying every event that passes the filter into a `LogBuffer`
+pub struct LogCapture {
+    buffer: Arc<LogBuffer>,
+}
+
+impl LogCapture {
+    pub fn new(buffer: Arc<LogBuffer>) -> Self {
+        Self { buffer }
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for LogCapture {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = FieldCollector::default();
+        attrs.record(&mut fields);
+        if let (Some(request_id), Some(span)) = (fields.request_id, ctx.span(id)) {
+            span.extensions_mut().insert(SpanRequestId(request_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut fields = FieldCollector::default();
+        event.record(&mut fields);
+        let request_id = fields.request_id.or_else(|| {
+            ctx.event_scope(event)?
+                .find_map(|span| span.extensions().get::<SpanRequestId>().map(|id| id.0.clone()))
+        });
+
+        let kind = if metadata.target() == ACCESS_TARGET { LogKind::Access } else { LogKind::Daemon };
+        let mut message = fields.message;
+        if !fields.rest.is_empty() {
+            if !message.is_empty() {
+                message.push(' ');
+            }
+            message.push_str(&fields.rest);
+        }
+        self.buffer.push(LogRecord {
+            seq: 0,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            level: metadata.level().to_string(),
+            kind,
+            target: metadata.target().to_string(),
+            site: fields.site,
+            request_id,
+            message,
+        });
+    }
+}
+
+/// Request ID of a span, kept so events inside it can be found by request
+struct SpanRequestId(String);
+
+/// Request ID of the span the caller is in, if the span is enabled
+/// Lets lines logged outside of tracing, like guest output, be tied to their request.
+pub fn current_request_id() -> Option<String> {
+    tracing::Span::current().with_subscriber(|(id, dispatch)| {
+        let registry = dispatch.downcast_ref::<tracing_subscriber::Registry>()?;
+        let span = registry.span(id)?;
+        let found = span.scope().find_map(|span| span.extensions().get::<SpanRequestId>().map(|id| id.0.clone()));
+        found
+    })?
+}
+
+/// Flattens an event's fields into its message, site, request, and `key=value` pairs
+#[derive(Default)]
+struct FieldCollector {
+    message: String,
+    site: Option<String>,
+    request_id: Option<String>,
+    rest: String,
+}
+
+impl Visit for FieldCollector {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            "site" | "site_id" => self.site = Some(value.to_string()),
+            "request_id" => self.request_id = Some(value.to_string()),
+            name => self.push(name, format_args!("{}", value)),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{:?}", value),
+            "site" | "site_id" => self.site = Some(format!("{:?}", value).trim_matches('"').to_string()),
+            "request_id" => self.request_id = Some(format!("{:?}", value).trim_matches('"').to_string()),
+            name => self.push(name, format_args!("{:?}", value)),
+        }
+    }
+}
+
+impl FieldCollector {
+    fn push(&mut self, name: &str, value: std::fmt::Arguments<'_>) {
+        if !self.rest.is_empty() {
+            self.rest.push(' ');
+        }
+        let _ = write!(self.rest, "{}={}", name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_capture_and_filter() {
+        let buffer = Arc::new(LogBuffer::new(3));
+        let subscriber = tracing_subscriber::registry().with(LogCapture::new(buffer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("starting up");
+            tracing::warn!(site_id = %"blog", cage_id = 4, "Cage restarted");
+            tracing::info!(target: ACCESS_TARGET, site = %"blog", status = 200, "GET /");
+            tracing::error!(site_id = %"shop", "Pool exhausted");
+        });
+
+        // The oldest line fell out of the buffer
+        let all = buffer.query(&LogFilter::default(), 10);
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].message, "Cage restarted cage_id=4");
+        assert_eq!(all[1].kind, LogKind::Access);
+        assert_eq!(all[1].message, "GET / status=200");
+
+        let blog = LogFilter { site: Some("blog".to_string()), ..Default::default() };
+        assert_eq!(buffer.query(&blog, 10).len(), 2);
+        assert_eq!(buffer.query(&blog, 1)[0].kind, LogKind::Access);
+
+        let warnings = LogFilter { level: Some("warn".to_string()), ..Default::default() };
+        let warnings: Vec<_> = buffer.query(&warnings, 10).into_iter().map(|r| r.level).collect();
+        assert_eq!(warnings, vec!["WARN", "ERROR"]);
+
+        let grep = LogFilter { grep: Some("Pool".to_string()), since: Some(all[2].timestamp), ..Default::default() };
+        assert_eq!(buffer.query(&grep, 10)[0].site.as_deref(), Some("shop"));
+
+        assert!(LogFilter { level: Some("loud".to_string()), ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_request_id_from_span() {
+        let buffer = Arc::new(LogBuffer::new(10));
+        let subscriber = tracing_subscriber::registry().with(LogCapture::new(buffer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = %"req-1");
+            span.in_scope(|| {
+                tracing::debug_span!("dispatch").in_scope(|| {
+                    tracing::warn!("Cage execution failed");
+                    assert_eq!(current_request_id().as_deref(), Some("req-1"));
+                });
+                tracing::info!(target: ACCESS_TARGET, request_id = %"req-1", "GET /");
+            });
+            tracing::info!("outside");
+            assert_eq!(current_request_id(), None);
+        });
+
+        let filter = LogFilter { request_id: Some("req-1".to_string()), ..Default::default() };
+        let messages: Vec<_> = buffer.query(&filter, 10).into_iter().map(|r| r.message).collect();
+        assert_eq!(messages, vec!["Cage execution failed", "GET /"]);
+    }
+}
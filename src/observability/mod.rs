@@ -1,7 +1,15 @@
 // Observability infrastructure using tracing crate
 // Provides structured logging and telemetry without blocking the main request loop
 
+pub mod audit;
+pub mod guest_logs;
+pub mod health;
+pub mod histogram;
+pub mod history;
+pub mod logs;
+
 use anyhow::Result;
+use std::sync::Arc;
 use tracing_subscriber::{
     fmt::{self, format::FmtSpan},
     layer::SubscriberExt,
@@ -9,18 +17,48 @@ use tracing_subscriber::{
     EnvFilter,
 };
 
+/// How log lines are written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// JSON with thread and span details, and a line per closed span
+    Json,
+
+    /// One flat JSON object per event, as log collectors on Kubernetes nodes expect
+    Kubernetes,
+
+    /// Compact, colored lines for a person reading a terminal
+    Text,
+}
+
 /// Initialize the observability system
 /// Sets up structured logging to stdout with JSON formatting for machine parsing
-pub fn init() -> Result<()> {
+/// Returns the buffer of recent lines served to `pear logs`.
+pub fn init() -> Result<Arc<logs::LogBuffer>> {
+    init_with(LogFormat::Json)
+}
+
+/// `init` with a choice of log format
+pub fn init_with(format: LogFormat) -> Result<Arc<logs::LogBuffer>> {
     // Create a JSON formatter for structured logs
-    let fmt_layer = fmt::layer()
-        .json()
-        .with_target(true)
-        .with_current_span(true)
-        .with_span_list(true)
-        .with_thread_ids(true)
-        .with_thread_names(true)
-        .with_span_events(FmtSpan::CLOSE);
+    let json_layer = match format {
+        LogFormat::Json => Some(fmt::layer()
+            .json()
+            .with_target(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_thread_ids(true)
+            .with_thread_names(true)
+            .with_span_events(FmtSpan::CLOSE)),
+        LogFormat::Kubernetes => Some(fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_target(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_span_events(FmtSpan::NONE)),
+        LogFormat::Text => None,
+    };
+    let text_layer = (format == LogFormat::Text).then(|| fmt::layer().compact().with_target(false));
 
     // Configure filter from environment or use default
     // Example: RUST_LOG=pear_server=debug,quinn=info
@@ -28,24 +66,37 @@ pub fn init() -> Result<()> {
         .or_else(|_| EnvFilter::try_new("pear_server=info,quinn=warn,hyper=warn"))
         .expect("Failed to create tracing filter");
 
+    let buffer = Arc::new(logs::LogBuffer::default());
+
     // Build and set the global subscriber
     tracing_subscriber::registry()
         .with(filter_layer)
-        .with(fmt_layer)
+        .with(json_layer)
+        .with(text_layer)
+        .with(logs::LogCapture::new(buffer.clone()))
         .init();
 
-    Ok(())
+    Ok(buffer)
+}
+
+/// Header carrying a request's ID to guests, upstreams and clients
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// A new request ID
+pub fn new_request_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
 }
 
 /// Create a span for tracing request handling
-/// Use this to instrument critical paths without blocking
+/// Every line logged inside it can be found with `pear logs --request-id`.
 #[inline]
-pub fn request_span(protocol: &str, path: &str) -> tracing::Span {
+pub fn request_span(protocol: &str, method: &str, path: &str, request_id: &str) -> tracing::Span {
     tracing::info_span!(
         "request",
         protocol = protocol,
+        method = method,
         path = path,
-        request_id = %uuid::Uuid::new_v4(),
+        request_id = request_id,
     )
 }
 
@@ -0,0 +1,436 @@
+// Path Access Control
+// Per-site allow/deny by CIDR, basic auth and bearer tokens, checked before a request reaches a Cage
+
+use crate::ai::allowlist::CidrBlock;
+use crate::ai::waf::percent_decode;
+use anyhow::{Context, Result, bail};
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use dashmap::DashMap;
+use hyper::header::{self, HeaderMap};
+use ring::{digest, pbkdf2};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use tracing::info;
+
+/// Shown instead of credential hashes when rules are read back
+const REDACTED: &str = "********";
+
+/// Fewest PBKDF2 iterations a password hash may use
+const MIN_PASSWORD_ITERATIONS: u32 = 100_000;
+
+/// Standard base64, padded or not
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Access rule for one path prefix of a site
+/// Passwords are stored as `$pbkdf2-sha256$i=<iterations>$<salt>$<hash>` (base64 salt and hash),
+/// bearer tokens as hex SHA-256, e.g. `printf %s token | sha256sum`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclRule {
+    /// Protected path prefix, matched on segment boundaries ("/admin" covers "/admin/users")
+    pub path: String,
+
+    /// Only clients in these CIDR ranges may access the path (empty = any)
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// Clients in these CIDR ranges are always refused
+    #[serde(default)]
+    pub deny: Vec<String>,
+
+    /// Basic auth users: name to salted PBKDF2-SHA256 password hash
+    #[serde(default)]
+    pub users: BTreeMap<String, String>,
+
+    /// Accepted bearer token hashes
+    #[serde(default)]
+    pub tokens: Vec<String>,
+
+    #[serde(default = "default_realm")]
+    pub realm: String,
+}
+
+fn default_realm() -> String { "Restricted".to_string() }
+
+impl AclRule {
+    /// Copy of the rule with credential hashes hidden
+    pub fn redacted(&self) -> AclRule {
+        AclRule {
+            users: self.users.keys().map(|user| (user.clone(), REDACTED.to_string())).collect(),
+            tokens: self.tokens.iter().map(|_| REDACTED.to_string()).collect(),
+            ..self.clone()
+        }
+    }
+}
+
+/// `[acl]`: path rules keyed by site ID
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AclConfig {
+    #[serde(default)]
+    pub sites: HashMap<String, Vec<AclRule>>,
+}
+
+/// Outcome of checking a request against a site's rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AclDecision {
+    Allow,
+    /// Client address not permitted (403)
+    Forbidden,
+    /// Missing or wrong credentials (401), with the challenge to send
+    Unauthorized { challenge: String },
+}
+
+/// A parsed `$pbkdf2-sha256$` password hash
+struct PasswordHash {
+    iterations: NonZeroU32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl PasswordHash {
+    fn parse(encoded: &str) -> Result<Self> {
+        let format = "expected $pbkdf2-sha256$i=<iterations>$<salt>$<hash>";
+        let mut fields = encoded.trim().strip_prefix("$pbkdf2-sha256$").context(format)?.split('$');
+        let (Some(iterations), Some(salt), Some(hash), None) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+            bail!(format);
+        };
+        let iterations = iterations.strip_prefix("i=").and_then(|i| i.parse::<u32>().ok()).context(format)?;
+        if iterations < MIN_PASSWORD_ITERATIONS {
+            bail!("PBKDF2 hashes need at least {} iterations", MIN_PASSWORD_ITERATIONS);
+        }
+        let salt = BASE64.decode(salt).context("Invalid salt")?;
+        let hash = BASE64.decode(hash).context("Invalid hash")?;
+        if salt.len() < 8 || hash.len() != digest::SHA256_OUTPUT_LEN {
+            bail!("expected a salt of at least 8 bytes and a 32-byte hash");
+        }
+        Ok(Self { iterations: iterations.try_into()?, salt, hash })
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, self.iterations, &self.salt, password.as_bytes(), &self.hash).is_ok()
+    }
+}
+
+/// A rule with its ranges and hashes parsed
+struct CompiledRule {
+    rule: AclRule,
+    allow: Vec<CidrBlock>,
+    deny: Vec<CidrBlock>,
+    users: HashMap<String, PasswordHash>,
+    /// SHA-256 of each user's last verified password, so repeat requests skip PBKDF2
+    verified: DashMap<String, Vec<u8>>,
+    tokens: Vec<Vec<u8>>,
+}
+
+impl CompiledRule {
+    fn new(rule: &AclRule) -> Result<Self> {
+        if !rule.path.starts_with('/') {
+            bail!("ACL path '{}' must start with /", rule.path);
+        }
+        let parse_ranges = |ranges: &[String]| ranges.iter()
+            .map(|range| range.parse::<CidrBlock>())
+            .collect::<Result<Vec<_>>>();
+        let users = rule.users.iter()
+            .map(|(user, hash)| {
+                if user.is_empty() || user.contains(':') {
+                    bail!("Invalid basic auth user name '{}'", user);
+                }
+                let hash = PasswordHash::parse(hash).with_context(|| format!("Invalid password hash for user {}", user))?;
+                Ok((user.clone(), hash))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        let tokens = rule.tokens.iter()
+            .map(|hash| decode_sha256(hash).context("Invalid bearer token hash"))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            allow: parse_ranges(&rule.allow).with_context(|| format!("Invalid allow range for {}", rule.path))?,
+            deny: parse_ranges(&rule.deny).with_context(|| format!("Invalid deny range for {}", rule.path))?,
+            users,
+            verified: DashMap::new(),
+            tokens,
+            rule: rule.clone(),
+        })
+    }
+
+    fn covers(&self, path: &str) -> bool {
+        let prefix = self.rule.path.trim_end_matches('/');
+        match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.is_empty(),
+            None => false,
+        }
+    }
+
+    fn check(&self, ip: Option<IpAddr>, headers: &HeaderMap) -> AclDecision {
+        let in_ranges = |ranges: &[CidrBlock]| ip.is_some_and(|ip| ranges.iter().any(|range| range.contains(ip)));
+        if in_ranges(&self.deny) || (!self.allow.is_empty() && !in_ranges(&self.allow)) {
+            return AclDecision::Forbidden;
+        }
+        if self.users.is_empty() && self.tokens.is_empty() {
+            return AclDecision::Allow;
+        }
+
+        let authorization = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+        let authorized = match authorization.and_then(|value| value.split_once(' ')) {
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("basic") => {
+                BASE64.decode(credentials.trim()).ok()
+                    .and_then(|decoded| String::from_utf8(decoded).ok())
+                    .and_then(|decoded| {
+                        let (user, password) = decoded.split_once(':')?;
+                        Some(self.password_matches(user, password))
+                    })
+                    .unwrap_or(false)
+            }
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+                let token = token.trim();
+                self.tokens.iter().fold(false, |found, hash| hash_matches(hash, token) | found)
+            }
+            _ => false,
+        };
+        if authorized {
+            return AclDecision::Allow;
+        }
+
+        let scheme = if self.users.is_empty() { "Bearer" } else { "Basic" };
+        AclDecision::Unauthorized {
+            challenge: format!("{} realm=\"{}\"", scheme, self.rule.realm.replace('"', "")),
+        }
+    }
+
+    fn password_matches(&self, user: &str, password: &str) -> bool {
+        let Some(hash) = self.users.get(user) else {
+            return false;
+        };
+        if self.verified.get(user).is_some_and(|verified| hash_matches(&verified, password)) {
+            return true;
+        }
+        if !hash.verify(password) {
+            return false;
+        }
+        let verified = digest::digest(&digest::SHA256, password.as_bytes());
+        self.verified.insert(user.to_string(), verified.as_ref().to_vec());
+        true
+    }
+}
+
+/// Parsed rules of every site, replaceable at runtime
+pub struct AccessControl {
+    /// Rules per site, most specific path first
+    sites: DashMap<String, Arc<Vec<CompiledRule>>>,
+}
+
+impl AccessControl {
+    /// Check and load the configured rules
+    pub fn new(config: &AclConfig) -> Result<Self> {
+        let acl = Self { sites: DashMap::new() };
+        for (site_id, rules) in &config.sites {
+            acl.set_site_rules(site_id, rules)
+                .with_context(|| format!("Invalid ACL for site {}", site_id))?;
+        }
+        if !acl.sites.is_empty() {
+            info!(sites = acl.sites.len(), "Path access rules loaded");
+        }
+        Ok(acl)
+    }
+
+    /// Replace a site's rules; an empty list removes them
+    /// Redacted credentials keep the hash stored for the same path, so rules read back can be saved as-is.
+    pub fn set_site_rules(&self, site_id: &str, rules: &[AclRule]) -> Result<()> {
+        if rules.is_empty() {
+            self.sites.remove(site_id);
+            return Ok(());
+        }
+
+        let current = self.sites.get(site_id).map(|rules| rules.clone());
+        let mut compiled = rules.iter()
+            .map(|rule| {
+                let stored = current.as_ref().and_then(|current| current.iter().find(|compiled| compiled.rule.path == rule.path));
+                CompiledRule::new(&unredact(rule, stored.map(|compiled| &compiled.rule)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        compiled.sort_by_key(|rule| std::cmp::Reverse(rule.rule.path.trim_end_matches('/').len()));
+        self.sites.insert(site_id.to_string(), Arc::new(compiled));
+        Ok(())
+    }
+
+    /// A site's rules, with credential hashes redacted
+    pub fn site_rules(&self, site_id: &str) -> Vec<AclRule> {
+        self.sites.get(site_id)
+            .map(|rules| rules.iter().map(|compiled| compiled.rule.redacted()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Check a request against the most specific rule covering its path
+    /// The path is normalized first, so encoded, doubled or dot segments can't step around a rule.
+    pub fn check(&self, site_id: &str, path: &str, ip: Option<IpAddr>, headers: &HeaderMap) -> AclDecision {
+        let Some(rules) = self.sites.get(site_id).map(|rules| rules.clone()) else {
+            return AclDecision::Allow;
+        };
+        let path = normalize_path(path);
+        match rules.iter().find(|rule| rule.covers(&path)) {
+            Some(rule) => rule.check(ip, headers),
+            None => AclDecision::Allow,
+        }
+    }
+}
+
+/// Fill redacted credentials back in from the stored rule
+fn unredact(rule: &AclRule, stored: Option<&AclRule>) -> AclRule {
+    let mut rule = rule.clone();
+    for (user, hash) in rule.users.iter_mut() {
+        if let Some(stored) = stored.filter(|_| hash == REDACTED).and_then(|stored| stored.users.get(user)) {
+            hash.clone_from(stored);
+        }
+    }
+    for (i, hash) in rule.tokens.iter_mut().enumerate() {
+        if let Some(stored) = stored.filter(|_| hash == REDACTED).and_then(|stored| stored.tokens.get(i)) {
+            hash.clone_from(stored);
+        }
+    }
+    rule
+}
+
+/// Compare a secret against a stored hash without short-circuiting
+fn hash_matches(expected: &[u8], secret: &str) -> bool {
+    let actual = digest::digest(&digest::SHA256, secret.as_bytes());
+    expected.len() == actual.as_ref().len()
+        && expected.iter().zip(actual.as_ref()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn decode_sha256(encoded: &str) -> Result<Vec<u8>> {
+    hex::decode(encoded.trim()).ok()
+        .filter(|hash| hash.len() == digest::SHA256_OUTPUT_LEN)
+        .context("expected 64 hex digits of SHA-256")
+}
+
+/// Decode escapes, drop empty and `.` segments and resolve `..`, as the site would see the path
+fn normalize_path(path: &str) -> String {
+    let decoded = percent_decode(path, false);
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    /// SHA-256 of "secret"
+    const SECRET_HASH: &str = "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+
+    fn password_hash(password: &str, salt: &[u8]) -> String {
+        let iterations = NonZeroU32::new(MIN_PASSWORD_ITERATIONS).unwrap();
+        let mut hash = [0u8; digest::SHA256_OUTPUT_LEN];
+        pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, password.as_bytes(), &mut hash);
+        format!("$pbkdf2-sha256$i={}${}${}", iterations, BASE64.encode(salt), BASE64.encode(hash))
+    }
+
+    fn acl(toml: &str) -> Result<AccessControl> {
+        AccessControl::new(&toml::from_str(toml).unwrap())
+    }
+
+    fn auth(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_cidr_rules() {
+        let acl = acl(r#"
+            [[sites.blog]]
+            path = "/admin"
+            allow = ["10.0.0.0/8"]
+            deny = ["10.0.0.66"]
+
+            [[sites.blog]]
+            path = "/admin/public"
+        "#).unwrap();
+        let office = Some("10.1.2.3".parse().unwrap());
+        let none = HeaderMap::new();
+
+        assert_eq!(acl.check("blog", "/admin/users", office, &none), AclDecision::Allow);
+        assert_eq!(acl.check("blog", "/admin", Some("192.0.2.1".parse().unwrap()), &none), AclDecision::Forbidden);
+        assert_eq!(acl.check("blog", "/admin", Some("10.0.0.66".parse().unwrap()), &none), AclDecision::Forbidden);
+        assert_eq!(acl.check("blog", "/admin", None, &none), AclDecision::Forbidden);
+
+        // Longest prefix wins, and prefixes stop at segment boundaries
+        assert_eq!(acl.check("blog", "/admin/public/logo.png", None, &none), AclDecision::Allow);
+        assert_eq!(acl.check("blog", "/administrator", None, &none), AclDecision::Allow);
+        assert_eq!(acl.check("shop", "/admin", None, &none), AclDecision::Allow);
+    }
+
+    #[test]
+    fn test_paths_normalized() {
+        let acl = acl("[[sites.blog]]\npath = \"/admin\"\nallow = [\"10.0.0.0/8\"]\n").unwrap();
+        let client = Some("192.0.2.1".parse().unwrap());
+        let none = HeaderMap::new();
+
+        for path in ["//admin", "/%61dmin", "/x/../admin", "/./admin/", "/public/%2e%2e/admin/users", "/../admin"] {
+            assert_eq!(acl.check("blog", path, client, &none), AclDecision::Forbidden, "{}", path);
+        }
+        assert_eq!(acl.check("blog", "/admin/../public", client, &none), AclDecision::Allow);
+    }
+
+    #[test]
+    fn test_credentials() {
+        let acl = acl(&format!(
+            "[[sites.blog]]\npath = \"/admin\"\nusers = {{ alice = \"{}\" }}\ntokens = [\"{}\"]\n",
+            password_hash("secret", b"saltsalt"),
+            SECRET_HASH,
+        )).unwrap();
+
+        // "alice:secret" and "alice:wrong"
+        assert_eq!(acl.check("blog", "/admin", None, &auth("Basic YWxpY2U6c2VjcmV0")), AclDecision::Allow);
+        assert_eq!(acl.check("blog", "/admin", None, &auth("Bearer secret")), AclDecision::Allow);
+        assert_eq!(
+            acl.check("blog", "/admin", None, &auth("Basic YWxpY2U6d3Jvbmc=")),
+            AclDecision::Unauthorized { challenge: "Basic realm=\"Restricted\"".to_string() },
+        );
+        assert!(matches!(acl.check("blog", "/admin", None, &HeaderMap::new()), AclDecision::Unauthorized { .. }));
+
+        // Verified passwords are remembered, and a wrong one still fails afterwards
+        assert_eq!(acl.check("blog", "/admin", None, &auth("Basic YWxpY2U6c2VjcmV0")), AclDecision::Allow);
+        assert!(matches!(acl.check("blog", "/admin", None, &auth("Basic YWxpY2U6d3Jvbmc")), AclDecision::Unauthorized { .. }));
+
+        // Rules read back are redacted, and saving them unchanged keeps the credentials
+        let rules = acl.site_rules("blog");
+        assert_eq!(rules[0].users["alice"], REDACTED);
+        assert_eq!(rules[0].tokens, vec![REDACTED.to_string()]);
+        acl.set_site_rules("blog", &rules).unwrap();
+        assert_eq!(acl.check("blog", "/admin", None, &auth("Bearer secret")), AclDecision::Allow);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(acl("[[sites.blog]]\npath = \"admin\"\n").is_err());
+        assert!(acl("[[sites.blog]]\npath = \"/admin\"\nallow = [\"10.0.0.0/33\"]\n").is_err());
+        assert!(acl("[[sites.blog]]\npath = \"/admin\"\ntokens = [\"secret\"]\n").is_err());
+
+        // Passwords need a salted PBKDF2 hash with enough iterations
+        assert!(acl(&format!("[[sites.blog]]\npath = \"/admin\"\nusers = {{ alice = \"{}\" }}\n", SECRET_HASH)).is_err());
+        let weak = password_hash("secret", b"saltsalt").replace("i=100000", "i=1000");
+        assert!(acl(&format!("[[sites.blog]]\npath = \"/admin\"\nusers = {{ alice = \"{}\" }}\n", weak)).is_err());
+
+        let acl = acl("").unwrap();
+        assert!(acl.set_site_rules("blog", &[]).is_ok());
+        assert!(acl.site_rules("blog").is_empty());
+    }
+}
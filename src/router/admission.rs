@@ -0,0 +1,300 @@
+// Admission Control
+// Sheds requests early with 503 when in-flight limits or the latency target are exceeded, instead of queueing them
+
+use crate::observability::histogram::{HistogramSnapshot, LatencyHistogram};
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Fewest requests in a window for its p99 to adjust the limit
+const MIN_WINDOW_SAMPLES: u64 = 20;
+
+/// `[admission]`: in-flight caps and the latency target load is shed to meet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdmissionConfig {
+    /// Requests in flight across all sites (0 = unlimited)
+    #[serde(default)]
+    pub max_in_flight: usize,
+
+    /// Requests in flight for any one site (0 = unlimited)
+    #[serde(default)]
+    pub max_in_flight_per_site: usize,
+
+    /// Per-site overrides of max_in_flight_per_site, keyed by site ID
+    #[serde(default)]
+    pub sites: HashMap<String, usize>,
+
+    /// p99 latency to hold; concurrency is cut while it is missed (0 = no target)
+    #[serde(default)]
+    pub p99_target_ms: u64,
+
+    /// How often the p99 is measured and the limit adjusted
+    #[serde(default = "default_latency_window")]
+    pub latency_window_secs: u64,
+
+    /// Retry-After sent with shed requests
+    #[serde(default = "default_retry_after")]
+    pub retry_after_secs: u64,
+}
+
+fn default_latency_window() -> u64 { 5 }
+fn default_retry_after() -> u64 { 1 }
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 0,
+            max_in_flight_per_site: 0,
+            sites: HashMap::new(),
+            p99_target_ms: 0,
+            latency_window_secs: default_latency_window(),
+            retry_after_secs: default_retry_after(),
+        }
+    }
+}
+
+impl AdmissionConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.latency_window_secs == 0 {
+            bail!("latency_window_secs must be at least 1");
+        }
+        Ok(())
+    }
+
+    fn site_limit(&self, site_id: &str) -> usize {
+        self.sites.get(site_id).copied().unwrap_or(self.max_in_flight_per_site)
+    }
+}
+
+/// Why a request was shed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShedReason {
+    /// max_in_flight reached
+    Global,
+    /// The site's in-flight cap reached
+    Site,
+    /// The latency-adjusted limit reached
+    Latency,
+}
+
+impl std::fmt::Display for ShedReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ShedReason::Global => "global in-flight limit",
+            ShedReason::Site => "site in-flight limit",
+            ShedReason::Latency => "latency target",
+        })
+    }
+}
+
+/// Shed counters and current load
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct AdmissionStats {
+    pub in_flight: usize,
+
+    /// Concurrency allowed while the latency target is missed; None when uncapped
+    pub latency_limit: Option<usize>,
+    pub shed_global: u64,
+    pub shed_site: u64,
+    pub shed_latency: u64,
+}
+
+/// Decides whether a request may proceed and tracks it while it does
+pub struct AdmissionController {
+    config: AdmissionConfig,
+    in_flight: AtomicUsize,
+
+    /// In flight per site, for sites with a cap; entries go when their count reaches 0
+    sites: DashMap<String, AtomicUsize>,
+
+    /// usize::MAX while the latency target is met
+    latency_limit: AtomicUsize,
+
+    /// Most requests in flight during the current window
+    peak: AtomicUsize,
+    latency: LatencyHistogram,
+    window: Mutex<(Instant, HistogramSnapshot)>,
+    shed_global: AtomicU64,
+    shed_site: AtomicU64,
+    shed_latency: AtomicU64,
+}
+
+impl AdmissionController {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            config,
+            in_flight: AtomicUsize::new(0),
+            sites: DashMap::new(),
+            latency_limit: AtomicUsize::new(usize::MAX),
+            peak: AtomicUsize::new(0),
+            latency: LatencyHistogram::new(),
+            window: Mutex::new((Instant::now(), HistogramSnapshot::default())),
+            shed_global: AtomicU64::new(0),
+            shed_site: AtomicU64::new(0),
+            shed_latency: AtomicU64::new(0),
+        }
+    }
+
+    /// Retry-After for shed requests
+    pub fn retry_after(&self) -> Duration {
+        Duration::from_secs(self.config.retry_after_secs)
+    }
+
+    /// Admit a request for `site_id`; it counts as in flight until the permit is dropped
+    pub fn admit(self: &Arc<Self>, site_id: &str) -> Result<AdmissionPermit, ShedReason> {
+        self.roll_window();
+
+        let in_flight = self.in_flight.fetch_add(1, Ordering::AcqRel) + 1;
+        let site_limit = self.config.site_limit(site_id);
+        let site = (site_limit > 0).then(|| site_id.to_string());
+        let site_in_flight = site.as_ref()
+            .map_or(0, |site| self.sites.entry(site.clone()).or_default().fetch_add(1, Ordering::AcqRel) + 1);
+
+        let shed = if self.config.max_in_flight > 0 && in_flight > self.config.max_in_flight {
+            Some((ShedReason::Global, &self.shed_global))
+        } else if site_limit > 0 && site_in_flight > site_limit {
+            Some((ShedReason::Site, &self.shed_site))
+        } else if in_flight > self.latency_limit.load(Ordering::Relaxed) {
+            Some((ShedReason::Latency, &self.shed_latency))
+        } else {
+            None
+        };
+        if let Some((reason, counter)) = shed {
+            self.release(site.as_deref());
+            counter.fetch_add(1, Ordering::Relaxed);
+            return Err(reason);
+        }
+
+        self.peak.fetch_max(in_flight, Ordering::Relaxed);
+        Ok(AdmissionPermit { controller: self.clone(), site, started: Instant::now() })
+    }
+
+    fn release(&self, site: Option<&str>) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+        if let Some(site) = site {
+            // Decremented under the map's lock, so a concurrent admit can't count into a removed entry
+            self.sites.remove_if(site, |_, count| count.fetch_sub(1, Ordering::AcqRel) == 1);
+        }
+    }
+
+    /// Once a window has passed, adjust the latency limit to its p99
+    fn roll_window(&self) {
+        if self.config.p99_target_ms == 0 {
+            return;
+        }
+        let Some(mut window) = self.window.try_lock() else { return };
+        if window.0.elapsed() < Duration::from_secs(self.config.latency_window_secs) {
+            return;
+        }
+        let now = self.latency.snapshot();
+        let recent = now.since(&window.1);
+        *window = (Instant::now(), now);
+        drop(window);
+
+        let peak = self.peak.swap(self.in_flight.load(Ordering::Relaxed), Ordering::Relaxed);
+        if recent.count() >= MIN_WINDOW_SAMPLES {
+            self.adjust(recent.percentile(0.99), peak);
+        }
+    }
+
+    /// Cut concurrency by a tenth while the p99 misses its target, and grow it back by a tenth while it is met
+    /// The cap is lifted once load is well below it.
+    fn adjust(&self, p99: Duration, peak: usize) {
+        let target = Duration::from_millis(self.config.p99_target_ms);
+        let limit = self.latency_limit.load(Ordering::Relaxed);
+        let next = if p99 > target {
+            (limit.min(peak) * 9 / 10).max(1)
+        } else if limit == usize::MAX || limit > peak.saturating_mul(2) {
+            usize::MAX
+        } else {
+            limit + (limit / 10).max(1)
+        };
+        self.latency_limit.store(next, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> AdmissionStats {
+        let limit = self.latency_limit.load(Ordering::Relaxed);
+        AdmissionStats {
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            latency_limit: (limit != usize::MAX).then_some(limit),
+            shed_global: self.shed_global.load(Ordering::Relaxed),
+            shed_site: self.shed_site.load(Ordering::Relaxed),
+            shed_latency: self.shed_latency.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// An admitted request; its latency is measured until it is dropped
+pub struct AdmissionPermit {
+    controller: Arc<AdmissionController>,
+    site: Option<String>,
+    started: Instant,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        self.controller.latency.record(self.started.elapsed());
+        self.controller.release(self.site.as_deref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_flight_limits() {
+        let config = AdmissionConfig {
+            max_in_flight: 3,
+            max_in_flight_per_site: 2,
+            sites: HashMap::from([("big".to_string(), 3)]),
+            ..Default::default()
+        };
+        let controller = Arc::new(AdmissionController::new(config));
+
+        let first = controller.admit("blog").unwrap();
+        let second = controller.admit("blog").unwrap();
+        assert_eq!(controller.admit("blog").err(), Some(ShedReason::Site));
+        let _third = controller.admit("big").unwrap();
+        assert_eq!(controller.admit("big").err(), Some(ShedReason::Global));
+
+        drop(first);
+        let _fourth = controller.admit("big").unwrap();
+
+        let stats = controller.stats();
+        assert_eq!((stats.in_flight, stats.shed_site, stats.shed_global), (3, 1, 1));
+
+        // Sites without requests in flight are forgotten
+        drop(second);
+        assert!(controller.sites.contains_key("big"));
+        assert!(!controller.sites.contains_key("blog"));
+    }
+
+    #[test]
+    fn test_latency_target_adjusts_limit() {
+        let config = AdmissionConfig { p99_target_ms: 100, ..Default::default() };
+        let controller = Arc::new(AdmissionController::new(config));
+
+        // Missing the target caps concurrency below the load that missed it
+        controller.adjust(Duration::from_millis(250), 20);
+        assert_eq!(controller.stats().latency_limit, Some(18));
+        controller.adjust(Duration::from_millis(250), 18);
+        assert_eq!(controller.stats().latency_limit, Some(16));
+
+        let held: Vec<_> = (0..16).map(|_| controller.admit("blog").unwrap()).collect();
+        assert_eq!(controller.admit("blog").err(), Some(ShedReason::Latency));
+        assert_eq!(controller.stats().shed_latency, 1);
+        drop(held);
+
+        // Meeting it grows the cap back, then lifts it once load is well below
+        controller.adjust(Duration::from_millis(40), 16);
+        assert_eq!(controller.stats().latency_limit, Some(17));
+        controller.adjust(Duration::from_millis(40), 5);
+        assert_eq!(controller.stats().latency_limit, None);
+    }
+}
@@ -0,0 +1,149 @@
+// Forwarded Headers
+// Recovers the real client address from X-Forwarded-For / Forwarded when a trusted proxy sent the request
+
+use crate::ai::allowlist::CidrBlock;
+use anyhow::Result;
+use hyper::{HeaderMap, Request};
+use std::net::{IpAddr, SocketAddr};
+
+use super::{ClientAddr, ProxiedBy};
+
+/// Load balancers allowed to name the client a request came from
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    blocks: Vec<CidrBlock>,
+}
+
+impl TrustedProxies {
+    /// Parse `[server] trusted_proxies` CIDR strings
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        let blocks = entries.iter()
+            .map(|entry| entry.parse())
+            .collect::<Result<Vec<CidrBlock>>>()?;
+        Ok(Self { blocks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.blocks.iter().any(|block| block.contains(ip))
+    }
+
+    /// The client behind `peer`: the nearest address in the forwarding chain that isn't a trusted proxy
+    /// Headers from untrusted peers are ignored, since any client can send them.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        if !self.trusts(peer) {
+            return client;
+        }
+        for hop in forwarding_chain(headers).into_iter().rev() {
+            // An unknown or obfuscated hop hides everything before it
+            let Some(hop) = hop else { break };
+            client = hop;
+            if !self.trusts(hop) {
+                break;
+            }
+        }
+        client
+    }
+
+    /// Replace the request's `ClientAddr` with the client its trusted proxy names
+    /// The proxy's own address is kept as `ProxiedBy` for the next hop's X-Forwarded-For.
+    pub fn resolve<B>(&self, req: &mut Request<B>) {
+        let Some(&ClientAddr(peer)) = req.extensions().get::<ClientAddr>() else { return };
+        let client = self.client_ip(peer.ip(), req.headers());
+        if client != peer.ip() {
+            req.extensions_mut().insert(ProxiedBy(peer));
+            req.extensions_mut().insert(ClientAddr(SocketAddr::new(client, 0)));
+        }
+    }
+}
+
+/// Addresses a request was forwarded for, client first
+/// `Forwarded` is preferred over `X-Forwarded-For` when both are present.
+fn forwarding_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = headers.get_all("forwarded").iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, node)| parse_node(node))
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers.get_all("x-forwarded-for").iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+/// An address with or without a port, e.g. `192.0.2.1`, `"[2001:db8::1]:4711"`; None for `unknown` and `_hidden` names
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    let ip = node.parse::<IpAddr>().ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())?;
+    Some(ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::parse(&["10.0.0.0/8".to_string(), "fd00::/8".to_string()]).unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs.iter().map(|(name, value)| (hyper::header::HeaderName::from_static(name), value.parse().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_client_from_forwarded_for() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let chain = headers(&[("x-forwarded-for", "198.51.100.1, 203.0.113.9, 10.0.0.2")]);
+
+        // The rightmost untrusted hop is the client; anything left of it could be forged
+        assert_eq!(proxies().client_ip(ip("10.0.0.1"), &chain), ip("203.0.113.9"));
+        assert_eq!(proxies().client_ip(ip("192.0.2.50"), &chain), ip("192.0.2.50"));
+        assert_eq!(TrustedProxies::default().client_ip(ip("10.0.0.1"), &chain), ip("10.0.0.1"));
+
+        let all_trusted = headers(&[("x-forwarded-for", "10.1.1.1")]);
+        assert_eq!(proxies().client_ip(ip("10.0.0.1"), &all_trusted), ip("10.1.1.1"));
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let forwarded = headers(&[
+            ("forwarded", "for=\"[2001:db8::7]:4711\";proto=https, For=10.2.0.1"),
+            ("x-forwarded-for", "198.51.100.1"),
+        ]);
+        assert_eq!(proxies().client_ip(ip("fd00::1"), &forwarded), ip("2001:db8::7"));
+
+        let hidden = headers(&[("forwarded", "for=192.0.2.60, for=_hidden, for=10.2.0.1")]);
+        assert_eq!(proxies().client_ip(ip("10.0.0.1"), &hidden), ip("10.2.0.1"));
+    }
+
+    #[test]
+    fn test_resolve_keeps_proxy_address() {
+        let mut req = Request::builder()
+            .header("x-forwarded-for", "203.0.113.9")
+            .body(())
+            .unwrap();
+        let peer: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        req.extensions_mut().insert(ClientAddr(peer));
+
+        proxies().resolve(&mut req);
+        assert_eq!(req.extensions().get::<ClientAddr>().unwrap().0.ip(), "203.0.113.9".parse::<IpAddr>().unwrap());
+        assert_eq!(req.extensions().get::<ProxiedBy>(), Some(&ProxiedBy(peer)));
+    }
+}
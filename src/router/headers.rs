@@ -0,0 +1,255 @@
+// Security Response Headers
+// Per-site HSTS, nosniff, frame and CSP policy applied to every routed response
+
+use anyhow::{Result, bail};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// HSTS preload lists require at least a year
+const HSTS_PRELOAD_MIN_AGE: u64 = 31_536_000;
+
+/// Marks requests that arrived over TLS
+/// Inserted into request extensions by the protocol servers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecureConnection;
+
+/// `X-Frame-Options` value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameOptions {
+    Deny,
+    #[default]
+    SameOrigin,
+    /// Send no header; the site may be framed anywhere
+    Allow,
+}
+
+/// Effective header policy of a site
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderPolicy {
+    /// Send `Strict-Transport-Security` on responses served over TLS
+    #[serde(default = "default_true")]
+    pub hsts: bool,
+
+    #[serde(default = "default_hsts_max_age")]
+    pub hsts_max_age_secs: u64,
+
+    #[serde(default)]
+    pub hsts_include_subdomains: bool,
+
+    /// Ask to be on browsers' preload lists (needs include_subdomains and a year's max age)
+    #[serde(default)]
+    pub hsts_preload: bool,
+
+    /// Send `X-Content-Type-Options: nosniff`
+    #[serde(default = "default_true")]
+    pub nosniff: bool,
+
+    #[serde(default)]
+    pub frame_options: FrameOptions,
+
+    /// `Content-Security-Policy` for responses that don't set one
+    #[serde(default)]
+    pub content_security_policy: Option<String>,
+
+    /// Replace the site's own CSP instead of passing it through
+    #[serde(default)]
+    pub override_csp: bool,
+}
+
+fn default_true() -> bool { true }
+fn default_hsts_max_age() -> u64 { HSTS_PRELOAD_MIN_AGE }
+
+impl Default for HeaderPolicy {
+    fn default() -> Self {
+        Self {
+            hsts: true,
+            hsts_max_age_secs: default_hsts_max_age(),
+            hsts_include_subdomains: false,
+            hsts_preload: false,
+            nosniff: true,
+            frame_options: FrameOptions::default(),
+            content_security_policy: None,
+            override_csp: false,
+        }
+    }
+}
+
+impl HeaderPolicy {
+    /// Apply overrides on top of this policy
+    pub fn with(&self, overrides: &HeaderOverrides) -> HeaderPolicy {
+        HeaderPolicy {
+            hsts: overrides.hsts.unwrap_or(self.hsts),
+            hsts_max_age_secs: overrides.hsts_max_age_secs.unwrap_or(self.hsts_max_age_secs),
+            hsts_include_subdomains: overrides.hsts_include_subdomains.unwrap_or(self.hsts_include_subdomains),
+            hsts_preload: overrides.hsts_preload.unwrap_or(self.hsts_preload),
+            nosniff: overrides.nosniff.unwrap_or(self.nosniff),
+            frame_options: overrides.frame_options.unwrap_or(self.frame_options),
+            content_security_policy: overrides.content_security_policy.clone()
+                .or_else(|| self.content_security_policy.clone()),
+            override_csp: overrides.override_csp.unwrap_or(self.override_csp),
+        }
+    }
+
+    /// Check the header values are sendable and the preload rules are met
+    pub fn validate(&self) -> Result<()> {
+        if self.hsts_preload && (!self.hsts_include_subdomains || self.hsts_max_age_secs < HSTS_PRELOAD_MIN_AGE) {
+            bail!("hsts_preload requires hsts_include_subdomains and hsts_max_age_secs of at least {}", HSTS_PRELOAD_MIN_AGE);
+        }
+        if let Some(csp) = &self.content_security_policy {
+            if HeaderValue::from_str(csp).is_err() {
+                bail!("content_security_policy is not a valid header value");
+            }
+        }
+        if self.override_csp && self.content_security_policy.is_none() {
+            bail!("override_csp requires content_security_policy");
+        }
+        Ok(())
+    }
+
+    /// Add the policy's headers to a response
+    /// Headers the site already set are kept, except HSTS and an overridden CSP.
+    pub fn apply(&self, headers: &mut HeaderMap, secure: bool) {
+        if self.hsts && secure {
+            let mut value = format!("max-age={}", self.hsts_max_age_secs);
+            if self.hsts_include_subdomains {
+                value.push_str("; includeSubDomains");
+            }
+            if self.hsts_preload {
+                value.push_str("; preload");
+            }
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(header::STRICT_TRANSPORT_SECURITY, value);
+            }
+        }
+
+        if self.nosniff {
+            headers.entry(header::X_CONTENT_TYPE_OPTIONS).or_insert(HeaderValue::from_static("nosniff"));
+        }
+
+        let frame = match self.frame_options {
+            FrameOptions::Deny => Some("DENY"),
+            FrameOptions::SameOrigin => Some("SAMEORIGIN"),
+            FrameOptions::Allow => None,
+        };
+        if let Some(frame) = frame {
+            headers.entry(header::X_FRAME_OPTIONS).or_insert(HeaderValue::from_static(frame));
+        }
+
+        if let Some(value) = self.content_security_policy.as_deref().and_then(|csp| HeaderValue::from_str(csp).ok()) {
+            if self.override_csp {
+                headers.insert(header::CONTENT_SECURITY_POLICY, value);
+            } else {
+                headers.entry(header::CONTENT_SECURITY_POLICY).or_insert(value);
+            }
+        }
+    }
+}
+
+/// Header policy overrides for a site
+/// Unset fields fall back to the global [security.headers] settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderOverrides {
+    pub hsts: Option<bool>,
+    pub hsts_max_age_secs: Option<u64>,
+    pub hsts_include_subdomains: Option<bool>,
+    pub hsts_preload: Option<bool>,
+    pub nosniff: Option<bool>,
+    pub frame_options: Option<FrameOptions>,
+    pub content_security_policy: Option<String>,
+    pub override_csp: Option<bool>,
+}
+
+/// `[security.headers]`: global policy plus per-site overrides
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeadersConfig {
+    #[serde(flatten)]
+    pub global: HeaderPolicy,
+
+    /// Per-site overrides, keyed by site ID
+    #[serde(default)]
+    pub sites: HashMap<String, HeaderOverrides>,
+}
+
+impl HeadersConfig {
+    /// Effective policy for a site
+    pub fn resolve(&self, site_id: &str) -> HeaderPolicy {
+        match self.sites.get(site_id) {
+            Some(overrides) => self.global.with(overrides),
+            None => self.global.clone(),
+        }
+    }
+
+    /// Check the global policy and every site's effective policy
+    pub fn validate(&self) -> Result<()> {
+        self.global.validate()?;
+        for site_id in self.sites.keys() {
+            self.resolve(site_id).validate()
+                .map_err(|e| anyhow::anyhow!("site {}: {}", site_id, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_keep_site_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        HeaderPolicy::default().apply(&mut headers, false);
+
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert!(!headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+        assert!(!headers.contains_key(header::CONTENT_SECURITY_POLICY));
+
+        HeaderPolicy::default().apply(&mut headers, true);
+        assert_eq!(headers[header::STRICT_TRANSPORT_SECURITY], "max-age=31536000");
+    }
+
+    #[test]
+    fn test_site_overrides() {
+        let toml = r#"
+            hsts_include_subdomains = true
+            content_security_policy = "default-src 'self'"
+
+            [sites.shop]
+            hsts_preload = true
+            frame_options = "allow"
+            content_security_policy = "default-src https:"
+            override_csp = true
+        "#;
+        let config: HeadersConfig = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("default-src *"));
+        config.resolve("blog").apply(&mut headers, true);
+        assert_eq!(headers[header::CONTENT_SECURITY_POLICY], "default-src *");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("default-src *"));
+        config.resolve("shop").apply(&mut headers, true);
+        assert_eq!(headers[header::CONTENT_SECURITY_POLICY], "default-src https:");
+        assert_eq!(headers[header::STRICT_TRANSPORT_SECURITY], "max-age=31536000; includeSubDomains; preload");
+        assert!(!headers.contains_key(header::X_FRAME_OPTIONS));
+    }
+
+    #[test]
+    fn test_preload_requirements() {
+        let policy = HeaderPolicy { hsts_preload: true, ..Default::default() };
+        assert!(policy.validate().is_err());
+
+        let config = HeadersConfig {
+            sites: HashMap::from([("blog".to_string(), HeaderOverrides { override_csp: Some(true), ..Default::default() })]),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -1,9 +1,15 @@
 // Health checking subsystem for Cages
-// Implements periodic health probes and circuit breaker pattern
+// Implements periodic per-site health probes and circuit breaker pattern
 
+use crate::cage::pool::CagePool;
+use crate::cage::{Cage, CageState};
+use anyhow::{bail, Context, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use tokio::time::{Duration, Interval};
+use tokio::time::{Duration, Instant, Interval};
 use tracing::{debug, warn, info};
 
 /// Health status for a Cage or Pool
@@ -14,46 +20,339 @@ pub enum HealthStatus {
     Unhealthy,
 }
 
-/// Health checker for individual Cages
+/// How a Cage is probed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeKind {
+    /// The Cage's lifecycle state only; no guest code runs
+    #[default]
+    State,
+    /// Call a `() -> ()` export on a fresh instance
+    Export,
+    /// Run a synthetic GET request through the Cage
+    Http,
+}
+
+/// Effective health check settings of a site's pools
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckSettings {
+    /// Seconds between probes of a pool
+    pub interval_secs: u64,
+
+    /// Failed probes in a row before a Cage is taken out of rotation
+    pub failure_threshold: u32,
+
+    /// Passed probes in a row before it is put back
+    pub success_threshold: u32,
+
+    pub probe: ProbeKind,
+
+    /// Export called by `export` probes
+    pub export: String,
+
+    /// Path requested by `http` probes
+    pub path: String,
+
+    /// Probes taking longer count as failed
+    pub timeout_ms: u64,
+}
+
+impl Default for HealthCheckSettings {
+    fn default() -> Self {
+        Self {
+            interval_secs: 5,
+            failure_threshold: 3,
+            success_threshold: 2,
+            probe: ProbeKind::State,
+            export: "health_check".to_string(),
+            path: "/healthz".to_string(),
+            timeout_ms: 1000,
+        }
+    }
+}
+
+impl HealthCheckSettings {
+    /// Apply overrides on top of these settings
+    pub fn with(&self, overrides: &HealthCheckOverrides) -> HealthCheckSettings {
+        HealthCheckSettings {
+            interval_secs: overrides.interval_secs.unwrap_or(self.interval_secs),
+            failure_threshold: overrides.failure_threshold.unwrap_or(self.failure_threshold),
+            success_threshold: overrides.success_threshold.unwrap_or(self.success_threshold),
+            probe: overrides.probe.unwrap_or(self.probe),
+            export: overrides.export.clone().unwrap_or_else(|| self.export.clone()),
+            path: overrides.path.clone().unwrap_or_else(|| self.path.clone()),
+            timeout_ms: overrides.timeout_ms.unwrap_or(self.timeout_ms),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_secs == 0 {
+            bail!("interval_secs must be at least 1");
+        }
+        if self.failure_threshold == 0 || self.success_threshold == 0 {
+            bail!("failure_threshold and success_threshold must be at least 1");
+        }
+        if self.timeout_ms == 0 {
+            bail!("timeout_ms must be at least 1");
+        }
+        if self.export.is_empty() {
+            bail!("export must not be empty");
+        }
+        if !self.path.starts_with('/') || self.path.contains(|c: char| c == '"' || c == '\\' || c.is_whitespace() || c.is_control()) {
+            bail!("path '{}' must start with '/' and contain no quotes, backslashes or whitespace", self.path);
+        }
+        Ok(())
+    }
+}
+
+/// Health check overrides for a site
+/// Unset fields fall back to the global [pool_health] settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckOverrides {
+    pub interval_secs: Option<u64>,
+    pub failure_threshold: Option<u32>,
+    pub success_threshold: Option<u32>,
+    pub probe: Option<ProbeKind>,
+    pub export: Option<String>,
+    pub path: Option<String>,
+    pub timeout_ms: Option<u64>,
+}
+
+/// `[pool_health]`: how the Router probes Cages, with per-site overrides
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthChecksConfig {
+    #[serde(flatten)]
+    pub global: HealthCheckSettings,
+
+    /// Per-site overrides, keyed by site ID
+    #[serde(default)]
+    pub sites: HashMap<String, HealthCheckOverrides>,
+}
+
+impl HealthChecksConfig {
+    /// Effective settings for a site
+    pub fn resolve(&self, site_id: &str) -> HealthCheckSettings {
+        match self.sites.get(site_id) {
+            Some(overrides) => self.global.with(overrides),
+            None => self.global.clone(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.global.validate()?;
+        for site_id in self.sites.keys() {
+            self.resolve(site_id).validate()
+                .with_context(|| format!("Invalid health checks of site '{}'", site_id))?;
+        }
+        Ok(())
+    }
+}
+
+/// Probe counts of one Cage
+#[derive(Debug, Clone, Copy, Default)]
+struct CageHealth {
+    failures: u32,
+    successes: u32,
+    /// Failed `failure_threshold` probes in a row and hasn't recovered since
+    failing: bool,
+}
+
+impl CageHealth {
+    /// Count a probe, returning the Cage's new verdict if it changed
+    fn record(&mut self, passed: bool, settings: &HealthCheckSettings) -> Option<bool> {
+        if passed {
+            self.failures = 0;
+            self.successes = self.successes.saturating_add(1);
+            if self.failing && self.successes >= settings.success_threshold {
+                self.failing = false;
+                return Some(true);
+            }
+        } else {
+            self.successes = 0;
+            self.failures = self.failures.saturating_add(1);
+            if !self.failing && self.failures >= settings.failure_threshold {
+                self.failing = true;
+                return Some(false);
+            }
+        }
+        None
+    }
+}
+
+/// Probe state of one pool
+#[derive(Default)]
+struct PoolProbes {
+    last_probe: Option<Instant>,
+    cages: HashMap<u64, CageHealth>,
+}
+
+/// Probes the Cages of every pool by its site's settings, taking failing ones out of rotation
+/// Settings can be replaced at runtime; they apply from each pool's next probe.
 pub struct HealthChecker {
-    /// Interval between health checks
-    check_interval: Duration,
-    
-    /// Failure threshold before marking as unhealthy
-    failure_threshold: u32,
-    
-    /// Success threshold to mark as healthy again
-    success_threshold: u32,
+    config: parking_lot::RwLock<HealthChecksConfig>,
+
+    /// Probe state keyed by pool: a site ID, or a route's pool key
+    pools: DashMap<String, PoolProbes>,
 }
 
 impl HealthChecker {
-    pub fn new(
-        check_interval: Duration,
-        failure_threshold: u32,
-        success_threshold: u32,
-    ) -> Self {
+    pub fn new(config: HealthChecksConfig) -> Self {
         Self {
-            check_interval,
-            failure_threshold,
-            success_threshold,
+            config: parking_lot::RwLock::new(config),
+            pools: DashMap::new(),
         }
     }
 
-    /// Create with default configuration
-    pub fn default_config() -> Self {
-        Self {
-            check_interval: Duration::from_secs(5),
-            failure_threshold: 3,
-            success_threshold: 2,
+    pub fn config(&self) -> HealthChecksConfig {
+        self.config.read().clone()
+    }
+
+    /// Effective settings for a site
+    pub fn settings(&self, site_id: &str) -> HealthCheckSettings {
+        self.config.read().resolve(site_id)
+    }
+
+    /// A site's overrides, if it has any
+    pub fn site_overrides(&self, site_id: &str) -> Option<HealthCheckOverrides> {
+        self.config.read().sites.get(site_id).cloned()
+    }
+
+    /// Replace every setting
+    pub fn set_config(&self, config: HealthChecksConfig) -> Result<()> {
+        config.validate()?;
+        *self.config.write() = config;
+        Ok(())
+    }
+
+    /// Replace a site's overrides; None falls back to the global settings
+    pub fn set_site(&self, site_id: &str, overrides: Option<HealthCheckOverrides>) -> Result<()> {
+        let mut config = self.config.write();
+        match overrides {
+            Some(overrides) => {
+                config.global.with(&overrides).validate()?;
+                config.sites.insert(site_id.to_string(), overrides);
+            }
+            None => {
+                config.sites.remove(site_id);
+            }
         }
+        Ok(())
     }
 
-    /// Get check interval
-    pub fn interval(&self) -> Duration {
-        self.check_interval
+    /// Probe a pool's Cages if its site's interval has passed since the last probe
+    /// Returns whether they were probed.
+    pub async fn check(&self, key: &str, pool: &CagePool) -> bool {
+        let interval = self.settings(pool.site_id()).interval();
+        let now = Instant::now();
+        {
+            let mut probes = self.pools.entry(key.to_string()).or_default();
+            if probes.last_probe.is_some_and(|last| now.duration_since(last) < interval) {
+                return false;
+            }
+            probes.last_probe = Some(now);
+        }
+        self.probe_pool(key, pool).await;
+        true
+    }
+
+    /// Probe every Cage of a pool now
+    /// Cages failing `failure_threshold` probes in a row are marked unhealthy, and healthy
+    /// again after `success_threshold` passed ones.
+    pub async fn probe_pool(&self, key: &str, pool: &CagePool) {
+        let settings = self.settings(pool.site_id());
+        let cages = pool.cages().await;
+        let results = futures::future::join_all(cages.iter().map(|cage| probe(cage.clone(), &settings))).await;
+
+        let mut changed = Vec::new();
+        {
+            let mut probes = self.pools.entry(key.to_string()).or_default();
+            probes.cages.retain(|cage_id, _| cages.iter().any(|cage| cage.id() == *cage_id));
+            for (cage, result) in cages.iter().zip(results) {
+                if let Err(e) = &result {
+                    debug!(site_id = %pool.site_id(), cage_id = cage.id(), error = %e, "Health probe failed");
+                }
+                let health = probes.cages.entry(cage.id()).or_default();
+                if let Some(healthy) = health.record(result.is_ok(), &settings) {
+                    changed.push((cage.clone(), healthy));
+                }
+            }
+        }
+
+        for (cage, healthy) in changed {
+            if healthy {
+                info!(site_id = %pool.site_id(), cage_id = cage.id(), "Cage passed its health probes again");
+                cage.health_check().await;
+            } else {
+                warn!(
+                    site_id = %pool.site_id(),
+                    cage_id = cage.id(),
+                    failures = settings.failure_threshold,
+                    "Cage failed its health probes"
+                );
+                cage.mark_unhealthy();
+            }
+        }
+    }
+
+    /// Cages of a pool that failed their probes and haven't recovered
+    pub fn failing(&self, key: &str) -> Vec<u64> {
+        self.pools.get(key)
+            .map(|probes| probes.cages.iter().filter(|(_, health)| health.failing).map(|(id, _)| *id).collect())
+            .unwrap_or_default()
+    }
+
+    /// Stop tracking a Cage, once it has been replaced
+    pub fn forget(&self, key: &str, cage_id: u64) {
+        if let Some(mut probes) = self.pools.get_mut(key) {
+            probes.cages.remove(&cage_id);
+        }
+    }
+
+    /// Stop tracking pools other than `live`, once they are gone
+    pub fn retain(&self, live: &[String]) {
+        self.pools.retain(|key, _| live.contains(key));
     }
 }
 
+/// Run one probe against a Cage; Cages that aren't running always fail
+async fn probe(cage: Arc<Cage>, settings: &HealthCheckSettings) -> Result<()> {
+    let state = cage.state().await;
+    if state != CageState::Running {
+        bail!("Cage is {:?}", state);
+    }
+
+    let call: Box<dyn FnOnce() -> Result<()> + Send> = match settings.probe {
+        ProbeKind::State => return Ok(()),
+        ProbeKind::Export => {
+            let export = settings.export.clone();
+            Box::new(move || cage.probe_export(&export))
+        }
+        ProbeKind::Http => {
+            let request = synthetic_request(&settings.path);
+            Box::new(move || cage.probe_request(request.as_bytes()))
+        }
+    };
+    match tokio::time::timeout(settings.timeout(), tokio::task::spawn_blocking(call)).await {
+        Ok(result) => result.context("Health probe panicked")?,
+        Err(_) => bail!("Health probe timed out after {}ms", settings.timeout_ms),
+    }
+}
+
+/// A GET request for `path`, serialized the way the Router hands requests to Cages
+fn synthetic_request(path: &str) -> String {
+    format!("{{\"method\":\"GET\",\"uri\":\"{}\",\"request_id\":\"health-check\"}}", path)
+}
+
 /// Circuit breaker for Cage health management
 /// Prevents routing to known-bad Cages
 pub struct CircuitBreaker {
@@ -243,8 +542,74 @@ mod tests {
     }
 
     #[test]
-    fn test_health_checker_creation() {
-        let checker = HealthChecker::default_config();
-        assert!(checker.interval().as_secs() > 0);
+    fn test_site_settings() {
+        let config: HealthChecksConfig = toml::from_str(r#"
+            failure_threshold = 5
+            [sites.api]
+            probe = "http"
+            interval_secs = 2
+        "#).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.resolve("blog"), HealthCheckSettings { failure_threshold: 5, ..Default::default() });
+
+        let api = config.resolve("api");
+        assert_eq!(api.probe, ProbeKind::Http);
+        assert_eq!(api.interval(), Duration::from_secs(2));
+        assert_eq!(api.failure_threshold, 5);
+
+        let checker = HealthChecker::new(config);
+        let overrides = HealthCheckOverrides { path: Some("/health check".to_string()), ..Default::default() };
+        assert!(checker.set_site("api", Some(overrides)).is_err());
+        checker.set_site("api", None).unwrap();
+        assert_eq!(checker.settings("api").probe, ProbeKind::State);
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let settings = HealthCheckSettings { failure_threshold: 2, success_threshold: 2, ..Default::default() };
+        let mut health = CageHealth::default();
+
+        assert_eq!(health.record(false, &settings), None);
+        assert_eq!(health.record(true, &settings), None);
+        assert_eq!(health.record(false, &settings), None);
+        assert_eq!(health.record(false, &settings), Some(false));
+        assert_eq!(health.record(false, &settings), None);
+
+        assert_eq!(health.record(true, &settings), None);
+        assert_eq!(health.record(true, &settings), Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_export_probes() {
+        use crate::cage::config::CageConfig;
+
+        let wasm = wat::parse_str(r#"(module (func (export "health_check")))"#).unwrap();
+        let pool = CagePool::new("blog".to_string(), wasm, CageConfig::default(), 2).await.unwrap();
+        let checker = HealthChecker::new(HealthChecksConfig {
+            global: HealthCheckSettings {
+                probe: ProbeKind::Export,
+                failure_threshold: 2,
+                success_threshold: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        checker.probe_pool("blog", &pool).await;
+        assert!(checker.failing("blog").is_empty());
+
+        // Settings apply from the next probe
+        let missing = HealthCheckOverrides { export: Some("missing".to_string()), ..Default::default() };
+        checker.set_site("blog", Some(missing)).unwrap();
+        checker.probe_pool("blog", &pool).await;
+        assert_eq!(pool.health_stats().await.healthy_cages, 2);
+        checker.probe_pool("blog", &pool).await;
+        assert_eq!(checker.failing("blog").len(), 2);
+        assert_eq!(pool.health_stats().await.healthy_cages, 0);
+
+        checker.set_site("blog", None).unwrap();
+        checker.probe_pool("blog", &pool).await;
+        assert!(checker.failing("blog").is_empty());
+        assert_eq!(pool.health_stats().await.healthy_cages, 2);
     }
 }
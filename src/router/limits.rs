@@ -0,0 +1,375 @@
+// Request Limits
+// Per-site header and body size caps, plus a minimum body transfer rate against slowloris clients
+
+use anyhow::{Result, bail};
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header;
+use hyper::{Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+
+/// How often a body's transfer rate is checked while it is stalled
+const RATE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Error type of a limited body
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Why a request was cut off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitError {
+    TooManyHeaders,
+    HeadersTooLarge,
+    BodyTooLarge,
+    BodyTooSlow,
+}
+
+impl LimitError {
+    /// Status answered to the client
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LimitError::TooManyHeaders | LimitError::HeadersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            LimitError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            LimitError::BodyTooSlow => StatusCode::REQUEST_TIMEOUT,
+        }
+    }
+}
+
+impl std::fmt::Display for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LimitError::TooManyHeaders => "Too many request headers",
+            LimitError::HeadersTooLarge => "Request headers too large",
+            LimitError::BodyTooLarge => "Request body too large",
+            LimitError::BodyTooSlow => "Request body sent too slowly",
+        })
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// Effective request limits of a site (0 disables a limit)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestLimits {
+    #[serde(default = "default_max_headers")]
+    pub max_headers: usize,
+
+    /// Combined size of header names and values
+    #[serde(default = "default_max_header_bytes")]
+    pub max_header_bytes: usize,
+
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: u64,
+
+    /// Slowest average body upload accepted once the grace period is over
+    #[serde(default = "default_min_body_rate")]
+    pub min_body_rate_bytes_per_sec: u64,
+
+    #[serde(default = "default_body_rate_grace")]
+    pub body_rate_grace_secs: u64,
+}
+
+fn default_max_headers() -> usize { 100 }
+fn default_max_header_bytes() -> usize { 16 * 1024 }
+fn default_max_body_bytes() -> u64 { 10 * 1024 * 1024 }
+fn default_min_body_rate() -> u64 { 1024 }
+fn default_body_rate_grace() -> u64 { 5 }
+fn default_accept_backlog() -> u32 { 1024 }
+fn default_idle_timeout() -> u64 { 300 }
+fn default_http3_max_streams_per_connection() -> u64 { 100 }
+fn default_http3_max_streams() -> usize { 10_000 }
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_headers: default_max_headers(),
+            max_header_bytes: default_max_header_bytes(),
+            max_body_bytes: default_max_body_bytes(),
+            min_body_rate_bytes_per_sec: default_min_body_rate(),
+            body_rate_grace_secs: default_body_rate_grace(),
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Apply overrides on top of these limits
+    pub fn with(&self, overrides: &LimitOverrides) -> RequestLimits {
+        RequestLimits {
+            max_headers: overrides.max_headers.unwrap_or(self.max_headers),
+            max_header_bytes: overrides.max_header_bytes.unwrap_or(self.max_header_bytes),
+            max_body_bytes: overrides.max_body_bytes.unwrap_or(self.max_body_bytes),
+            min_body_rate_bytes_per_sec: overrides.min_body_rate_bytes_per_sec.unwrap_or(self.min_body_rate_bytes_per_sec),
+            body_rate_grace_secs: overrides.body_rate_grace_secs.unwrap_or(self.body_rate_grace_secs),
+        }
+    }
+
+    /// Check the headers and declared body length before the body is read
+    pub fn check_head<B>(&self, req: &Request<B>) -> Result<(), LimitError> {
+        let headers = req.headers();
+        if self.max_headers > 0 && headers.len() > self.max_headers {
+            return Err(LimitError::TooManyHeaders);
+        }
+
+        let header_bytes: usize = headers.iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        if self.max_header_bytes > 0 && header_bytes > self.max_header_bytes {
+            return Err(LimitError::HeadersTooLarge);
+        }
+
+        let declared = headers.get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if self.max_body_bytes > 0 && declared.is_some_and(|len| len > self.max_body_bytes) {
+            return Err(LimitError::BodyTooLarge);
+        }
+        Ok(())
+    }
+}
+
+/// Request limit overrides for a site
+/// Unset fields fall back to the global [limits] settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitOverrides {
+    pub max_headers: Option<usize>,
+    pub max_header_bytes: Option<usize>,
+    pub max_body_bytes: Option<u64>,
+    pub min_body_rate_bytes_per_sec: Option<u64>,
+    pub body_rate_grace_secs: Option<u64>,
+}
+
+/// `[limits]`: global request limits, per-site overrides and connection caps
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    #[serde(flatten)]
+    pub global: RequestLimits,
+
+    /// Per-site overrides, keyed by site ID
+    #[serde(default)]
+    pub sites: HashMap<String, LimitOverrides>,
+
+    /// Open connections allowed from one client IP on each listener (0 = unlimited)
+    #[serde(default)]
+    pub max_connections_per_ip: usize,
+
+    /// Connections queued by the kernel (TCP) or handshaking (QUIC) before new ones are refused
+    #[serde(default = "default_accept_backlog")]
+    pub accept_backlog: u32,
+
+    /// Seconds a connection may sit with nothing in flight before it is closed (0 = never)
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout_secs: u64,
+
+    /// Requests served on one connection before it is closed gracefully (0 = unlimited)
+    #[serde(default)]
+    pub max_requests_per_connection: u64,
+
+    /// HTTP/3 requests running at once on one connection; the client waits for the rest
+    #[serde(default = "default_http3_max_streams_per_connection")]
+    pub http3_max_streams_per_connection: u64,
+
+    /// HTTP/3 requests running at once across each listener; more are refused for the client to retry (0 = unlimited)
+    #[serde(default = "default_http3_max_streams")]
+    pub http3_max_streams: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            global: RequestLimits::default(),
+            sites: HashMap::new(),
+            max_connections_per_ip: 0,
+            accept_backlog: default_accept_backlog(),
+            idle_timeout_secs: default_idle_timeout(),
+            max_requests_per_connection: 0,
+            http3_max_streams_per_connection: default_http3_max_streams_per_connection(),
+            http3_max_streams: default_http3_max_streams(),
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// Effective limits for a site
+    pub fn resolve(&self, site_id: &str) -> RequestLimits {
+        match self.sites.get(site_id) {
+            Some(overrides) => self.global.with(overrides),
+            None => self.global.clone(),
+        }
+    }
+
+    /// Largest header block any site accepts, used as the HTTP/2 header list limit
+    /// None when some site has no header size limit.
+    pub fn max_header_bytes(&self) -> Option<usize> {
+        let sites = self.sites.keys().map(|site_id| self.resolve(site_id).max_header_bytes);
+        std::iter::once(self.global.max_header_bytes)
+            .chain(sites)
+            .try_fold(0, |largest, bytes| (bytes > 0).then(|| largest.max(bytes)))
+    }
+
+    /// How long an idle connection is kept open, if it is ever closed
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_secs > 0).then(|| Duration::from_secs(self.idle_timeout_secs))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.accept_backlog == 0 {
+            bail!("accept_backlog must be at least 1");
+        }
+        if !(1..=u32::MAX as u64).contains(&self.http3_max_streams_per_connection) {
+            bail!("http3_max_streams_per_connection must be between 1 and {}", u32::MAX);
+        }
+        Ok(())
+    }
+}
+
+/// Request body that enforces a site's size and transfer rate limits
+/// The tripped limit is kept so a failed upstream request can be answered with its status.
+pub struct LimitedBody<B> {
+    inner: B,
+    max_bytes: u64,
+    min_rate: u64,
+    grace: Duration,
+    received: u64,
+    started: Instant,
+    rate_check: Option<Pin<Box<Sleep>>>,
+    tripped: Arc<OnceLock<LimitError>>,
+}
+
+impl<B> LimitedBody<B> {
+    pub fn new(inner: B, limits: &RequestLimits) -> Self {
+        let started = Instant::now();
+        let grace = Duration::from_secs(limits.body_rate_grace_secs);
+        let rate_check = (limits.min_body_rate_bytes_per_sec > 0)
+            .then(|| Box::pin(tokio::time::sleep_until(started + grace.max(RATE_CHECK_INTERVAL))));
+
+        Self {
+            inner,
+            max_bytes: limits.max_body_bytes,
+            min_rate: limits.min_body_rate_bytes_per_sec,
+            grace,
+            received: 0,
+            started,
+            rate_check,
+            tripped: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Limit this body ran into, once it has
+    pub fn tripped(&self) -> Arc<OnceLock<LimitError>> {
+        self.tripped.clone()
+    }
+
+    fn trip(&self, error: LimitError) -> BoxError {
+        let _ = self.tripped.set(error);
+        Box::new(error)
+    }
+}
+
+impl<B> Body for LimitedBody<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Data = B::Data;
+    type Error = BoxError;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<B::Data>, BoxError>>> {
+        use hyper::body::Buf;
+
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.received += data.remaining() as u64;
+                    if this.max_bytes > 0 && this.received > this.max_bytes {
+                        return Poll::Ready(Some(Err(this.trip(LimitError::BodyTooLarge))));
+                    }
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e.into()))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => {
+                let Some(rate_check) = this.rate_check.as_mut() else {
+                    return Poll::Pending;
+                };
+                while rate_check.as_mut().poll(cx).is_ready() {
+                    let elapsed = this.started.elapsed().saturating_sub(this.grace);
+                    if (this.received as f64) < this.min_rate as f64 * elapsed.as_secs_f64() {
+                        return Poll::Ready(Some(Err(this.trip(LimitError::BodyTooSlow))));
+                    }
+                    rate_check.as_mut().reset(Instant::now() + RATE_CHECK_INTERVAL);
+                }
+                Poll::Pending
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use http_body_util::{BodyExt, Full, StreamBody};
+    use hyper::body::Bytes;
+
+    #[test]
+    fn test_check_head() {
+        let config: LimitsConfig = toml::from_str(
+            "max_headers = 2\nmax_body_bytes = 10\n[sites.uploads]\nmax_body_bytes = 1000\n",
+        ).unwrap();
+        config.validate().unwrap();
+        let limits = config.resolve("blog");
+
+        let small = Request::post("/").header(header::CONTENT_LENGTH, "10").body(()).unwrap();
+        assert_eq!(limits.check_head(&small), Ok(()));
+
+        let large = Request::post("/").header(header::CONTENT_LENGTH, "11").body(()).unwrap();
+        assert_eq!(limits.check_head(&large), Err(LimitError::BodyTooLarge));
+        assert_eq!(config.resolve("uploads").check_head(&large), Ok(()));
+
+        let headers = Request::get("/").header("a", "1").header("b", "2").header("c", "3").body(()).unwrap();
+        assert_eq!(limits.check_head(&headers).unwrap_err().status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+        let big_header = Request::get("/").header("cookie", "x".repeat(20_000)).body(()).unwrap();
+        assert_eq!(limits.check_head(&big_header), Err(LimitError::HeadersTooLarge));
+        assert_eq!(config.max_header_bytes(), Some(16 * 1024));
+    }
+
+    #[tokio::test]
+    async fn test_body_size_limit() {
+        let limits = RequestLimits { max_body_bytes: 4, ..Default::default() };
+        let body = LimitedBody::new(Full::new(Bytes::from_static(b"hello")), &limits);
+        let tripped = body.tripped();
+
+        assert!(body.collect().await.is_err());
+        assert_eq!(tripped.get(), Some(&LimitError::BodyTooLarge));
+    }
+
+    #[tokio::test]
+    async fn test_slow_body_times_out() {
+        let limits = RequestLimits { min_body_rate_bytes_per_sec: 100, body_rate_grace_secs: 0, ..Default::default() };
+        let chunks = futures::stream::iter(vec![Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from_static(b"x")))])
+            .chain(futures::stream::pending());
+        let body = LimitedBody::new(StreamBody::new(chunks), &limits);
+        let tripped = body.tripped();
+
+        let error = body.collect().await.unwrap_err();
+        assert_eq!(error.downcast_ref::<LimitError>(), Some(&LimitError::BodyTooSlow));
+        assert_eq!(tripped.get().map(LimitError::status), Some(StatusCode::REQUEST_TIMEOUT));
+    }
+}
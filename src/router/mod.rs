@@ -3,18 +3,89 @@
 
 pub mod strategies;
 pub mod health;
+pub mod acl;
+pub mod admission;
+pub mod forwarded;
+pub mod headers;
+pub mod limits;
+pub mod outlier;
+pub mod retry;
+pub mod rewrite;
+pub mod routes;
+pub mod stream;
+pub mod tenants;
+pub mod upstream;
 
+use crate::cage::executor::{self, WasmExecutor};
+use crate::cage::request_queue;
 use crate::cage::pool::CagePool;
+use crate::error::PearError;
+use crate::observability::{new_request_id, request_span, REQUEST_ID_HEADER};
+use crate::state::shared_memory::{MemoryPool, PooledBuffer};
+use crate::tenancy::domains::{self, DomainManager, HostRoute};
 use anyhow::{Result, Context};
 use dashmap::DashMap;
 use std::sync::Arc;
-use tracing::{info, debug, warn, error, instrument};
+use uuid::Uuid;
+use tracing::{info, debug, warn, error, Instrument};
 use hyper::{Request, Response, StatusCode};
 use hyper::body::{Incoming, Bytes};
-use http_body_util::Full;
+use http_body_util::{BodyExt, Full};
+use http_body_util::combinators::UnsyncBoxBody;
+use std::fmt::Write;
+use std::net::SocketAddr;
 
-/// Load balancing strategy
+/// Initial capacity for serialized requests
+const REQUEST_BUFFER_HINT: usize = 1024;
+
+/// Initial capacity for Cage response bodies
+const RESPONSE_BUFFER_HINT: usize = 4 * 1024;
+
+/// Served for every request while no site is deployed
+const LANDING_PAGE: &str = include_str!("../../static/landing.html");
+
+/// Response body: a buffered Cage response, or an upstream's streamed body and trailers
+pub type RouterBody = UnsyncBoxBody<Bytes, hyper::Error>;
+
+/// Box a buffered body as a RouterBody
+pub fn full_body(data: impl Into<Bytes>) -> RouterBody {
+    boxed(Full::new(data.into()))
+}
+
+fn boxed(body: Full<Bytes>) -> RouterBody {
+    body.map_err(|never| match never {}).boxed_unsync()
+}
+
+/// Client address of the connection a request arrived on
+/// Inserted into request extensions by the protocol servers
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// Address of the trusted proxy a request came through, when `ClientAddr` names the client behind it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxiedBy(pub SocketAddr);
+
+/// GlobalState ID of the connection a request arrived on
+/// Inserted into request extensions by the protocol servers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionId(pub u64);
+
+/// ID of a request, as given by a trusted proxy or generated
+/// Inserted into request extensions by `route_request`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+/// Longest request ID taken from a trusted proxy
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// ID of the request being routed, for error pages
+    static CURRENT_REQUEST_ID: String;
+}
+
+/// Load balancing strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum LoadBalancingStrategy {
     /// Distribute requests in round-robin fashion
     RoundRobin,
@@ -31,9 +102,6 @@ pub struct RouterConfig {
     
     /// Enable health checking
     pub health_check_enabled: bool,
-    
-    /// Health check interval in seconds
-    pub health_check_interval_secs: u64,
 }
 
 impl Default for RouterConfig {
@@ -41,19 +109,32 @@ impl Default for RouterConfig {
         Self {
             strategy: LoadBalancingStrategy::RoundRobin,
             health_check_enabled: true,
-            health_check_interval_secs: 5,
         }
     }
 }
 
+/// A site's CagePool and the tenant it is served for
+#[derive(Clone)]
+struct RoutedPool {
+    /// Tenant owning the site, if the pool was registered for one
+    tenant_id: Option<Uuid>,
+    pool: Arc<CagePool>,
+}
+
 /// Traffic Router coordinating request distribution
 pub struct Router {
-    /// Map of site ID to CagePool
-    pools: Arc<DashMap<String, Arc<CagePool>>>,
+    /// Map of site ID to its CagePool and tenant (site IDs are unique across tenants)
+    pools: Arc<DashMap<String, RoutedPool>>,
+    
+    /// Path prefixes of sites served by their own modules, strategies or limits
+    routes: Arc<DashMap<String, Arc<routes::SiteRoutes>>>,
     
     /// Router configuration
     config: RouterConfig,
     
+    /// Per-site probes of the Cages behind every pool, site and route alike
+    health: Arc<health::HealthChecker>,
+    
     /// Request counter for metrics
     total_requests: Arc<std::sync::atomic::AtomicU64>,
     
@@ -62,6 +143,81 @@ pub struct Router {
     
     /// Failed requests counter
     failed_requests: Arc<std::sync::atomic::AtomicU64>,
+    
+    /// Failed requests by error code
+    errors: DashMap<&'static str, std::sync::atomic::AtomicU64>,
+    
+    /// Pooled buffers for request serialization and response bodies
+    memory_pool: Arc<MemoryPool>,
+    
+    /// WAF and scan detection, checked before requests reach a Cage
+    security: std::sync::OnceLock<Arc<crate::ai::AiSecurityModule>>,
+    
+    /// Per-site traffic accounting and bandwidth quotas
+    bandwidth: std::sync::OnceLock<Arc<crate::tenancy::bandwidth::BandwidthMeter>>,
+    
+    /// Per-tenant usage records for invoicing
+    billing: std::sync::OnceLock<Arc<crate::tenancy::billing::BillingMeter>>,
+    
+    /// Security headers added to responses (built-in defaults until set)
+    headers: std::sync::OnceLock<headers::HeadersConfig>,
+    
+    /// Per-site redirects, path rewrites and header edits
+    rewrites: std::sync::OnceLock<rewrite::RewriteEngine>,
+    
+    /// Sites served by a backend instead of Cages
+    upstreams: std::sync::OnceLock<upstream::UpstreamProxy>,
+    
+    /// When Cage responses are streamed (disabled until set)
+    streaming: std::sync::OnceLock<stream::StreamingConfig>,
+    
+    /// Request header and body limits (built-in defaults until set)
+    limits: std::sync::OnceLock<limits::LimitsConfig>,
+    
+    /// Per-site path access rules
+    acl: std::sync::OnceLock<Arc<acl::AccessControl>>,
+    
+    /// Verified custom domains and their challenges
+    domains: std::sync::OnceLock<Arc<DomainManager>>,
+    
+    /// Load balancers whose forwarding headers name the client
+    trusted_proxies: std::sync::OnceLock<forwarded::TrustedProxies>,
+    
+    /// In-flight caps and latency-driven load shedding (disabled until set)
+    admission: std::sync::OnceLock<Arc<admission::AdmissionController>>,
+    
+    /// Cages ejected from selection on their live traffic (disabled until set)
+    outliers: std::sync::OnceLock<Arc<outlier::OutlierDetector>>,
+    
+    /// Retries of failed requests on another Cage (none until set)
+    retries: std::sync::OnceLock<retry::RetryBudget>,
+    
+    /// Slots guest code runs in (one per core until set)
+    executor: std::sync::OnceLock<Arc<WasmExecutor>>,
+    
+    /// Fault injection, when chaos mode is enabled
+    chaos: std::sync::OnceLock<Arc<crate::chaos::ChaosEngine>>,
+    
+    /// Handshake screening shared by the HTTP/3 listeners, when there are any
+    quic_guard: std::sync::OnceLock<Arc<crate::network::quic_guard::QuicGuard>>,
+    
+    /// Accept counters of each listener, by listener label (none until set)
+    listeners: std::sync::OnceLock<Vec<(String, Arc<crate::network::acceptor::AcceptorMetrics>)>>,
+    
+    /// Site serving requests whose host names no other site (none until set)
+    default_site: std::sync::OnceLock<String>,
+    
+    /// Statuses of the tenants owning the sites (every site is served until set)
+    tenants: std::sync::OnceLock<tenants::TenantStatusCache>,
+    
+    /// Request and error counts and latencies of sites with a pool or upstream
+    site_traffic: DashMap<String, SiteTraffic>,
+    
+    /// Time from receiving a request to its response head, across all sites
+    latency: crate::observability::histogram::LatencyHistogram,
+    
+    /// Connections and their streaming accounting
+    state: crate::state::GlobalState,
 }
 
 impl Router {
@@ -71,75 +227,700 @@ impl Router {
         
         Self {
             pools: Arc::new(DashMap::new()),
+            routes: Arc::new(DashMap::new()),
             config,
+            health: Arc::new(health::HealthChecker::new(health::HealthChecksConfig::default())),
             total_requests: Arc::new(std::sync::atomic::AtomicU64::new(0)),
             successful_requests: Arc::new(std::sync::atomic::AtomicU64::new(0)),
             failed_requests: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            errors: DashMap::new(),
+            memory_pool: Arc::new(MemoryPool::new()),
+            security: std::sync::OnceLock::new(),
+            bandwidth: std::sync::OnceLock::new(),
+            billing: std::sync::OnceLock::new(),
+            headers: std::sync::OnceLock::new(),
+            rewrites: std::sync::OnceLock::new(),
+            upstreams: std::sync::OnceLock::new(),
+            streaming: std::sync::OnceLock::new(),
+            limits: std::sync::OnceLock::new(),
+            acl: std::sync::OnceLock::new(),
+            domains: std::sync::OnceLock::new(),
+            trusted_proxies: std::sync::OnceLock::new(),
+            admission: std::sync::OnceLock::new(),
+            outliers: std::sync::OnceLock::new(),
+            retries: std::sync::OnceLock::new(),
+            executor: std::sync::OnceLock::new(),
+            chaos: std::sync::OnceLock::new(),
+            quic_guard: std::sync::OnceLock::new(),
+            listeners: std::sync::OnceLock::new(),
+            default_site: std::sync::OnceLock::new(),
+            tenants: std::sync::OnceLock::new(),
+            site_traffic: DashMap::new(),
+            latency: crate::observability::histogram::LatencyHistogram::new(),
+            state: crate::state::GlobalState::new(),
+        }
+    }
+
+    /// Create a Router that shares an existing memory pool
+    pub fn with_memory_pool(config: RouterConfig, memory_pool: Arc<MemoryPool>) -> Self {
+        Self {
+            memory_pool,
+            ..Self::new(config)
+        }
+    }
+
+    /// Attach the security module used to filter requests
+    pub fn set_security_module(&self, module: Arc<crate::ai::AiSecurityModule>) {
+        if self.security.set(module).is_err() {
+            warn!("Security module already attached to Router");
+        }
+    }
+
+    /// Attach the meter that counts traffic and enforces bandwidth quotas
+    pub fn set_bandwidth_meter(&self, meter: Arc<crate::tenancy::bandwidth::BandwidthMeter>) {
+        if self.bandwidth.set(meter).is_err() {
+            warn!("Bandwidth meter already attached to Router");
+        }
+    }
+
+    /// Attach the meter that keeps billing records; the Router counts egress into it
+    pub fn set_billing(&self, meter: Arc<crate::tenancy::billing::BillingMeter>) {
+        if self.billing.set(meter).is_err() {
+            warn!("Billing meter already attached to Router");
+        }
+    }
+
+    /// Set the global and per-site security header policies
+    pub fn set_header_policies(&self, config: headers::HeadersConfig) {
+        if self.headers.set(config).is_err() {
+            warn!("Header policies already set on Router");
+        }
+    }
+
+    /// Attach the per-site rewrite rules
+    pub fn set_rewrite_rules(&self, rules: rewrite::RewriteEngine) {
+        if self.rewrites.set(rules).is_err() {
+            warn!("Rewrite rules already attached to Router");
+        }
+    }
+
+    /// Attach the backends of proxied sites
+    pub fn set_upstreams(&self, upstreams: upstream::UpstreamProxy) {
+        if self.upstreams.set(upstreams).is_err() {
+            warn!("Upstreams already attached to Router");
+        }
+    }
+
+    /// Enable streamed responses for SSE and long-poll requests
+    pub fn set_streaming(&self, config: stream::StreamingConfig) {
+        if self.streaming.set(config).is_err() {
+            warn!("Streaming already configured on Router");
+        }
+    }
+
+    /// Set the global and per-site request limits
+    pub fn set_limits(&self, config: limits::LimitsConfig) {
+        if self.limits.set(config).is_err() {
+            warn!("Request limits already set on Router");
+        }
+    }
+
+    /// Request limits, if configured
+    pub fn limits(&self) -> Option<&limits::LimitsConfig> {
+        self.limits.get()
+    }
+
+    /// Attach the per-site path access rules
+    pub fn set_access_control(&self, acl: Arc<acl::AccessControl>) {
+        if self.acl.set(acl).is_err() {
+            warn!("Access control already attached to Router");
+        }
+    }
+
+    /// Get the path access rules, if attached
+    pub fn access_control(&self) -> Option<&Arc<acl::AccessControl>> {
+        self.acl.get()
+    }
+
+    /// Enable admission control
+    pub fn set_admission(&self, config: admission::AdmissionConfig) {
+        if self.admission.set(Arc::new(admission::AdmissionController::new(config))).is_err() {
+            warn!("Admission control already configured on Router");
+        }
+    }
+
+    /// Enable outlier detection
+    pub fn set_outlier_detection(&self, config: outlier::OutlierConfig) {
+        if self.outliers.set(Arc::new(outlier::OutlierDetector::new(config))).is_err() {
+            warn!("Outlier detection already configured on Router");
         }
     }
 
-    /// Register a CagePool for a site
+    /// Set how failed requests are retried on other Cages
+    pub fn set_retries(&self, config: retry::RetryConfig) {
+        if self.retries.set(retry::RetryBudget::new(config)).is_err() {
+            warn!("Retries already configured on Router");
+        }
+    }
+
+    /// Set the executor Cage requests run on
+    pub fn set_wasm_executor(&self, executor: Arc<WasmExecutor>) {
+        if self.executor.set(executor).is_err() {
+            warn!("Wasm executor already set on Router");
+        }
+    }
+
+    /// Executor Cage requests run on
+    pub fn wasm_executor(&self) -> &Arc<WasmExecutor> {
+        self.executor.get_or_init(Default::default)
+    }
+
+    /// Enable chaos mode
+    pub fn set_chaos(&self, chaos: Arc<crate::chaos::ChaosEngine>) {
+        if self.chaos.set(chaos).is_err() {
+            warn!("Chaos engine already attached to Router");
+        }
+    }
+
+    /// Get the chaos engine, if chaos mode is enabled
+    pub fn chaos(&self) -> Option<&Arc<crate::chaos::ChaosEngine>> {
+        self.chaos.get()
+    }
+
+    /// Attach the HTTP/3 handshake guard, for metrics
+    pub fn set_quic_guard(&self, guard: Arc<crate::network::quic_guard::QuicGuard>) {
+        if self.quic_guard.set(guard).is_err() {
+            warn!("QUIC guard already attached to Router");
+        }
+    }
+
+    /// The HTTP/3 handshake guard, if the node has HTTP/3 listeners
+    pub fn quic_guard(&self) -> Option<&Arc<crate::network::quic_guard::QuicGuard>> {
+        self.quic_guard.get()
+    }
+
+    /// Attach the accept counters of the node's listeners, for metrics
+    pub fn set_listener_metrics(&self, listeners: Vec<(String, Arc<crate::network::acceptor::AcceptorMetrics>)>) {
+        if self.listeners.set(listeners).is_err() {
+            warn!("Listener metrics already attached to Router");
+        }
+    }
+
+    /// Accept counters of each listener, by label
+    pub fn listener_metrics(&self) -> &[(String, Arc<crate::network::acceptor::AcceptorMetrics>)] {
+        self.listeners.get().map_or(&[], Vec::as_slice)
+    }
+
+    /// Serve requests for hosts that name no site from `site_id`, as a single-site server does
+    pub fn set_default_site(&self, site_id: String) {
+        if self.default_site.set(site_id).is_err() {
+            warn!("Default site already set on Router");
+        }
+    }
+
+    /// Refuse requests for sites whose tenant is suspended or deleted
+    pub fn set_tenants(&self, manager: Arc<crate::tenancy::TenantManager>) {
+        if self.tenants.set(tenants::TenantStatusCache::new(manager, tenants::STATUS_TTL)).is_err() {
+            warn!("Tenants already attached to Router");
+        }
+    }
+
+    /// Apply a change of the tenant's status from its next request on
+    pub fn tenant_changed(&self, tenant_id: Uuid) {
+        if let Some(tenants) = self.tenants.get() {
+            tenants.invalidate(tenant_id);
+        }
+    }
+
+    /// Attach the site domains requests are routed by
+    pub fn set_domains(&self, domains: Arc<DomainManager>) {
+        if self.domains.set(domains).is_err() {
+            warn!("Domains already attached to Router");
+        }
+    }
+
+    /// Attach the proxies trusted to name the client in X-Forwarded-For / Forwarded
+    pub fn set_trusted_proxies(&self, proxies: forwarded::TrustedProxies) {
+        if self.trusted_proxies.set(proxies).is_err() {
+            warn!("Trusted proxies already attached to Router");
+        }
+    }
+
+    /// Connection registry shared with the protocol servers
+    pub fn state(&self) -> &crate::state::GlobalState {
+        &self.state
+    }
+
+    /// Get the bandwidth meter, if accounting is enabled
+    pub fn bandwidth_meter(&self) -> Option<&Arc<crate::tenancy::bandwidth::BandwidthMeter>> {
+        self.bandwidth.get()
+    }
+
+    /// Get the billing meter, if billing is enabled
+    pub fn billing(&self) -> Option<&Arc<crate::tenancy::billing::BillingMeter>> {
+        self.billing.get()
+    }
+
+    /// Register a CagePool for a site, for the tenant of the pool it replaces or else the site's owner
     pub fn register_pool(&self, site_id: String, pool: Arc<CagePool>) {
-        info!(site_id = %site_id, "Registering CagePool with Router");
-        self.pools.insert(site_id, pool);
+        let tenant_id = self.site_tenant(&site_id)
+            .or_else(|| self.tenants.get().and_then(|tenants| tenants.owner(&site_id)));
+        match tenant_id {
+            Some(tenant_id) => self.register_tenant_pool(tenant_id, site_id, pool),
+            None => {
+                info!(site_id = %site_id, "Registering CagePool with Router");
+                self.pools.insert(site_id, RoutedPool { tenant_id: None, pool });
+            }
+        }
+    }
+
+    /// Register a CagePool for a tenant's site, served only while the tenant is active
+    pub fn register_tenant_pool(&self, tenant_id: Uuid, site_id: String, pool: Arc<CagePool>) {
+        info!(tenant_id = %tenant_id, site_id = %site_id, "Registering CagePool with Router");
+        self.pools.insert(site_id, RoutedPool { tenant_id: Some(tenant_id), pool });
     }
 
-    /// Unregister a CagePool
+    /// Unregister a CagePool, and the site's path routes with it
     pub fn unregister_pool(&self, site_id: &str) {
         info!(site_id = %site_id, "Unregistering CagePool from Router");
         self.pools.remove(site_id);
+        self.routes.remove(site_id);
+    }
+
+    /// Route path prefixes of a site by `routes`, replacing its previous ones
+    /// Paths no route matches go to the site's own pool.
+    pub fn set_routes(&self, site_id: String, routes: routes::SiteRoutes) -> Option<Arc<routes::SiteRoutes>> {
+        info!(site_id = %site_id, routes = routes.len(), "Setting site path routes");
+        if routes.is_empty() {
+            return self.routes.remove(&site_id).map(|(_, previous)| previous);
+        }
+        self.routes.insert(site_id, Arc::new(routes))
+    }
+
+    /// A site's path routes, if it has any
+    pub fn routes(&self, site_id: &str) -> Option<Arc<routes::SiteRoutes>> {
+        self.routes.get(site_id).map(|routes| routes.clone())
+    }
+
+    /// Unregister every CagePool of a tenant, returning the IDs of their sites
+    pub fn unregister_tenant(&self, tenant_id: Uuid) -> Vec<String> {
+        let mut site_ids = Vec::new();
+        self.pools.retain(|site_id, routed| {
+            let owned = routed.tenant_id == Some(tenant_id);
+            if owned {
+                site_ids.push(site_id.clone());
+            }
+            !owned
+        });
+        for site_id in &site_ids {
+            self.routes.remove(site_id);
+        }
+        self.tenant_changed(tenant_id);
+        info!(tenant_id = %tenant_id, sites = site_ids.len(), "Unregistered tenant's CagePools from Router");
+        site_ids
+    }
+
+    /// Get the CagePool serving a site
+    pub fn pool(&self, site_id: &str) -> Option<Arc<CagePool>> {
+        self.pools.get(site_id).map(|routed| routed.pool.clone())
+    }
+
+    /// The site's own pool and its routes' pools
+    pub fn site_pools(&self, site_id: &str) -> Vec<Arc<CagePool>> {
+        let mut pools: Vec<Arc<CagePool>> = self.pool(site_id).into_iter().collect();
+        if let Some(routes) = self.routes(site_id) {
+            pools.extend(routes.pools().map(|(_, pool)| pool.clone()));
+        }
+        pools
+    }
+
+    /// Drain a site's pools for maintenance, or resume them; false if the site has no pool
+    pub fn set_draining(&self, site_id: &str, draining: bool) -> bool {
+        let pools = self.site_pools(site_id);
+        for pool in &pools {
+            if draining {
+                pool.drain();
+            } else {
+                pool.resume();
+            }
+        }
+        !pools.is_empty()
+    }
+
+    /// Tenant a site's CagePool is served for
+    pub fn site_tenant(&self, site_id: &str) -> Option<Uuid> {
+        self.pools.get(site_id).and_then(|routed| routed.tenant_id)
+    }
+
+    /// IDs of the sites with a registered CagePool
+    pub fn site_ids(&self) -> Vec<String> {
+        self.pools.iter().map(|entry| entry.key().clone()).collect()
     }
 
     /// Route an HTTP request to the appropriate Cage
-    #[instrument(skip(self, req), fields(method = %req.method(), uri = %req.uri()))]
+    /// The request's ID goes to the Cage or upstream, back to the client as `X-Request-Id`,
+    /// and on every line logged while handling it.
     pub async fn route_request(
+        &self,
+        mut req: Request<Incoming>,
+    ) -> Result<Response<RouterBody>> {
+        // Behind a load balancer, security checks and logs see the client it names
+        if let Some(proxies) = self.trusted_proxies.get() {
+            proxies.resolve(&mut req);
+        }
+        let request_id = request_id(&req);
+        let header = hyper::header::HeaderValue::from_str(&request_id)
+            .expect("request IDs are valid header values");
+        req.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+        
+        let span = request_span(&format!("{:?}", req.version()), req.method().as_str(), req.uri().path(), &request_id);
+        let mut response = CURRENT_REQUEST_ID
+            .scope(request_id.clone(), self.handle(req, &request_id).instrument(span))
+            .await?;
+        response.headers_mut().insert(REQUEST_ID_HEADER, header);
+        Ok(response)
+    }
+
+    async fn handle(
+        &self,
+        mut req: Request<Incoming>,
+        request_id: &str,
+    ) -> Result<Response<RouterBody>> {
+        let started = std::time::Instant::now();
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+        let client = req.extensions().get::<ClientAddr>()
+            .map(|addr| addr.0.ip().to_string())
+            .unwrap_or_default();
+        
+        // Verified domains route to their site; other hosts are taken as site IDs
+        let host_route = self.domains.get().and_then(|domains| {
+            domains.route(domains::request_host(&req)?, req.uri().path())
+        });
+        let site_id = match &host_route {
+            Some(HostRoute::Site(site_id)) => site_id.clone(),
+            _ => self.extract_site_id(&req),
+        };
+        let secure = req.extensions().get::<headers::SecureConnection>().is_some();
+        let limits = self.limits.get()
+            .map(|config| config.resolve(&site_id))
+            .unwrap_or_default();
+        
+        // Path routes match the requested path, before any rewrite
+        let route = self.routes.get(&site_id)
+            .and_then(|routes| routes.matching(req.uri().path()).cloned());
+        let limits = match &route {
+            Some(route) => limits.with(&route.limits),
+            None => limits,
+        };
+        
+        // Oversized requests are refused before their body is read
+        let rejected = limits.check_head(&req).err();
+        
+        // Redirects and domain challenges are answered without reaching a Cage
+        let redirect = match (rejected, host_route) {
+            (Some(_), _) => None,
+            (None, Some(HostRoute::Challenge(token))) => Some(domains::challenge_response(token)),
+            (None, Some(HostRoute::Redirect(target))) => Some(domains::redirect_response(&req, &target, secure)),
+            (None, _) => self.rewrites.get().and_then(|rules| rules.rewrite_request(&site_id, &mut req)),
+        };
+        let mut response = match (rejected, redirect) {
+            (Some(limit), _) => {
+                debug!(site_id = %site_id, limit = %limit, "Request over limits");
+                self.total_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                self.error_response(limit.into())
+            }
+            (None, Some(redirect)) => {
+                self.total_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                self.successful_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                redirect.map(boxed)
+            }
+            (None, None) => match self.admission.get().map(|admission| admission.admit(&site_id)).transpose() {
+                Err(reason) => {
+                    debug!(site_id = %site_id, reason = %reason, "Request shed");
+                    self.total_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let retry_after = self.admission.get().map_or(std::time::Duration::from_secs(1), |admission| admission.retry_after());
+                    self.busy_response(retry_after)
+                }
+                Ok(_admitted) => {
+                    if let Some(delay) = self.chaos.get().and_then(|chaos| chaos.latency(&site_id)) {
+                        tokio::time::sleep(delay).await;
+                    }
+                    let mut response = self.dispatch(req, &site_id, route.as_ref(), &limits).await?;
+                    if let Some(rules) = self.rewrites.get() {
+                        rules.rewrite_response(&site_id, response.headers_mut());
+                    }
+                    response
+                }
+            },
+        };
+        
+        // Security headers go on every response, including blocks and errors
+        let policy = self.headers.get()
+            .map(|config| config.resolve(&site_id))
+            .unwrap_or_default();
+        policy.apply(response.headers_mut(), secure);
+        let elapsed = started.elapsed();
+        self.latency.record(elapsed);
+        self.record_site_response(&site_id, response.status(), elapsed);
+        info!(
+            target: crate::observability::logs::ACCESS_TARGET,
+            site = %site_id,
+            client = %client,
+            request_id = %request_id,
+            status = response.status().as_u16(),
+            latency_ms = elapsed.as_millis() as u64,
+            "{} {}", method, path
+        );
+        
+        Ok(response)
+    }
+
+    /// Count a response and its latency against its site
+    /// Only served sites are tracked, since site IDs come from the Host header.
+    fn record_site_response(&self, site_id: &str, status: StatusCode, latency: std::time::Duration) {
+        let error = status.is_client_error() || status.is_server_error();
+        match self.site_traffic.get(site_id) {
+            Some(traffic) => traffic.record(error, latency),
+            None => {
+                let served = self.pools.contains_key(site_id)
+                    || self.upstreams.get().is_some_and(|upstreams| upstreams.get(site_id).is_some());
+                if !served {
+                    return;
+                }
+                self.site_traffic.entry(site_id.to_string()).or_default().record(error, latency);
+            }
+        }
+        
+        if let Some(security) = self.security.get() {
+            security.record_latency(site_id, latency);
+        }
+    }
+
+    /// Why a site's tenant may not be served, if it may not
+    /// Statuses are cached briefly, so suspending a tenant takes effect within seconds
+    /// unless the change is reported through `tenant_changed`.
+    fn tenant_refusal(&self, site_id: &str) -> Option<PearError> {
+        let tenants = self.tenants.get()?;
+        match tenants.status(self.site_tenant(site_id)?) {
+            crate::tenancy::TenantStatus::Active => None,
+            crate::tenancy::TenantStatus::Suspended => Some(PearError::SiteSuspended),
+            crate::tenancy::TenantStatus::Deleted => Some(PearError::SiteUnavailable),
+        }
+    }
+
+    /// Filter a request and execute it in one of the site's Cages
+    async fn dispatch(
         &self,
         req: Request<Incoming>,
-    ) -> Result<Response<Full<Bytes>>> {
+        site_id: &str,
+        route: Option<&routes::Route>,
+        limits: &limits::RequestLimits,
+    ) -> Result<Response<RouterBody>> {
         self.total_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         
         let start = std::time::Instant::now();
-        
-        // Extract site ID from request (simplified - in production, use Host header)
-        let site_id = self.extract_site_id(&req);
+        let site_id = site_id.to_string();
         
         debug!(site_id = %site_id, "Routing request to site");
 
-        // Get the CagePool for this site
-        let pool = match self.pools.get(&site_id) {
-            Some(pool) => pool.clone(),
+        // Suspended tenants' sites stay registered but are not served
+        if let Some(refusal) = self.tenant_refusal(&site_id) {
+            debug!(site_id = %site_id, code = refusal.code(), "Request refused for the site's tenant");
+            return Ok(self.error_response(refusal));
+        }
+
+        // Reject banned clients and WAF matches before touching a Cage
+        let client_ip = req.extensions().get::<ClientAddr>().map(|addr| addr.0.ip());
+        if let Some(security) = self.security.get() {
+            if let Some(ip) = client_ip {
+                security.verify_challenge(&site_id, ip, req.headers(), req.uri().path());
+            }
+            
+            let mut verdict = security.inspect(
+                &site_id,
+                client_ip,
+                req.uri().path(),
+                req.uri().query().unwrap_or(""),
+            );
+            
+            if verdict.is_safe && security.should_sample(&site_id) {
+                let features = crate::ai::RequestFeatures::from_request(&req, client_ip);
+                verdict = security.score_request(&site_id, features).await;
+            }
+            
+            if !verdict.is_safe {
+                if security.policy(&site_id).blocks() {
+                    warn!(
+                        site_id = %site_id,
+                        threat = ?verdict.threat_type,
+                        details = verdict.details.as_deref().unwrap_or(""),
+                        "Request blocked by security module"
+                    );
+                    self.record_error(if verdict.retry_after.is_some() { PearError::RateLimited } else { PearError::Blocked });
+                    return Ok(security.block_response(client_ip, &verdict).map(boxed));
+                }
+                
+                info!(
+                    site_id = %site_id,
+                    threat = ?verdict.threat_type,
+                    details = verdict.details.as_deref().unwrap_or(""),
+                    "Unsafe request allowed (monitor-only policy)"
+                );
+            }
+        }
+
+        // Protected paths need an allowed address or valid credentials
+        if let Some(acl) = self.acl.get() {
+            match acl.check(&site_id, req.uri().path(), client_ip, req.headers()) {
+                acl::AclDecision::Allow => {}
+                acl::AclDecision::Forbidden => {
+                    debug!(site_id = %site_id, path = req.uri().path(), "Request refused by access rules");
+                    return Ok(self.error_response(PearError::Forbidden));
+                }
+                acl::AclDecision::Unauthorized { challenge } => {
+                    let mut response = self.error_response(PearError::Unauthorized);
+                    if let Ok(challenge) = hyper::header::HeaderValue::from_str(&challenge) {
+                        response.headers_mut().insert(hyper::header::WWW_AUTHENTICATE, challenge);
+                    }
+                    return Ok(response);
+                }
+            }
+        }
+
+        // Sites over a hard bandwidth limit are cut off until the period rolls over
+        if let Some(meter) = self.bandwidth.get() {
+            if meter.check(&site_id) == crate::tenancy::bandwidth::QuotaStatus::Exceeded {
+                return Ok(self.error_response(PearError::QuotaExceeded));
+            }
+        }
+
+        // Proxied sites stream to and from their backend, trailers included
+        // The body is cut off if it grows past the size limit or trickles in too slowly.
+        if let Some(upstream) = self.upstreams.get().and_then(|upstreams| upstreams.get(&site_id)) {
+            let request_bytes = request_size(&req);
+            let (parts, body) = req.into_parts();
+            let body = limits::LimitedBody::new(body, limits);
+            let tripped = body.tripped();
+            return match upstream.forward(Request::from_parts(parts, body)).await {
+                Ok(response) => {
+                    self.successful_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    debug!(
+                        site_id = %site_id,
+                        status = response.status().as_u16(),
+                        duration_ms = start.elapsed().as_millis(),
+                        "Request proxied to upstream"
+                    );
+                    
+                    // Streamed bodies are counted by their declared length
+                    let response_bytes = response.headers().get(hyper::header::CONTENT_LENGTH)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .unwrap_or(0);
+                    if let Some(meter) = self.bandwidth.get() {
+                        meter.record(&site_id, request_bytes, response_bytes);
+                    }
+                    if let Some(meter) = self.billing.get() {
+                        meter.record_egress(&site_id, response_bytes);
+                    }
+                    Ok(response.map(|body| body.boxed_unsync()))
+                }
+                Err(e) => {
+                    if let Some(limit) = tripped.get() {
+                        debug!(site_id = %site_id, limit = %limit, "Upstream request body over limits");
+                        return Ok(self.error_response((*limit).into()));
+                    }
+                    error!(site_id = %site_id, error = %e, "Upstream request failed");
+                    Ok(self.error_response(PearError::UpstreamUnavailable))
+                }
+            };
+        }
+
+        // Get the CagePool for this site, or for the path route's module
+        let pool = route.and_then(|route| route.pool.clone()).or_else(|| self.pool(&site_id));
+        let pool = match pool {
+            Some(pool) => pool,
+            // A node nothing is deployed on yet says so, rather than answering with an error
+            None if self.pools.is_empty() => {
+                return Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "text/html; charset=utf-8")
+                    .body(full_body(LANDING_PAGE))
+                    .unwrap());
+            }
             None => {
                 warn!(site_id = %site_id, "No CagePool found for site");
-                self.failed_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                return Ok(self.error_response(
-                    StatusCode::NOT_FOUND,
-                    "Site not found",
-                ));
+                if let (Some(security), Some(ip)) = (self.security.get(), client_ip) {
+                    security.record_response(&site_id, ip, req.uri().path(), StatusCode::NOT_FOUND.as_u16());
+                }
+                return Ok(self.error_response(PearError::SiteNotFound));
             }
         };
 
-        // Select a Cage based on load balancing strategy
-        let cage = match self.config.strategy {
-            LoadBalancingStrategy::RoundRobin => pool.get_cage_round_robin().await,
-            LoadBalancingStrategy::LeastConnected => pool.get_cage_least_connected().await,
-        };
+        // Drained pools finish the requests they have but take no new ones
+        if pool.is_draining() {
+            debug!(site_id = %site_id, "Request refused while the site drains");
+            return Ok(self.error_response(PearError::SiteDraining));
+        }
 
-        let cage = match cage {
-            Some(cage) => cage,
-            None => {
+        // Lazy and scale-to-zero pools start a Cage for the request that finds them cold
+        if let Err(e) = pool.wake().await {
+            error!(site_id = %site_id, error = %e, "Failed to start Cages of cold pool");
+            return Ok(self.error_response(PearError::NoHealthyCages));
+        }
+
+        // Select a Cage based on load balancing strategy, waiting if all are at their concurrency cap
+        let strategy = route.and_then(|route| route.strategy).unwrap_or(self.config.strategy);
+        let (cage, slot) = match self.reserve_cage(&pool, strategy, &[]).await {
+            Ok(reserved) => reserved,
+            Err(e) if e.is::<request_queue::QueueRejected>() => {
+                warn!(site_id = %site_id, error = %e, "Every Cage busy, shedding request");
+                return Ok(self.busy_response(std::time::Duration::from_secs(1)));
+            }
+            Err(_) => {
                 error!(site_id = %site_id, "No healthy Cages available");
-                self.failed_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                return Ok(self.error_response(
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    "No healthy instances available",
-                ));
+                return Ok(self.error_response(PearError::NoHealthyCages));
             }
         };
 
-        // Execute request in the selected Cage
-        let request_data = self.serialize_request(&req).await;
+        // SSE and long-poll requests stream if the module has a streaming export
+        if let Some(streaming) = self.streaming.get().filter(|streaming| streaming.wants_stream(&req)) {
+            if cage.has_export(&streaming.export) {
+                return Ok(self.stream_response(streaming, cage, slot, &req, &site_id).await);
+            }
+        }
+
+        // Execute request in the selected Cage, and on another while the failure is worth retrying
+        let request_data = self.serialize_request(&req).await.into_bytes();
+        if let Some(budget) = self.retries.get() {
+            budget.record_request();
+        }
+        let mut cage = cage;
+        let mut _slot = slot;
+        let mut tried = Vec::new();
+        let executed = loop {
+            let executed = self.execute(&pool, &cage, request_data.clone()).await;
+            let Err(e) = &executed else { break executed };
+            if !self.should_retry(req.method(), e, tried.len()) {
+                break executed;
+            }
+            tried.push(cage.clone());
+            let Ok((next, next_slot)) = self.reserve_cage(&pool, strategy, &tried).await else { break executed };
+            if !self.retries.get().is_some_and(|budget| budget.try_retry()) {
+                debug!(site_id = %site_id, cage_id = cage.id(), "Retry budget exhausted, not retrying");
+                break executed;
+            }
+            warn!(site_id = %site_id, cage_id = cage.id(), retry_cage_id = next.id(), error = %e, "Retrying request on another Cage");
+            cage = next;
+            _slot = next_slot;
+        };
         
-        match cage.execute_request(&request_data).await {
+        match executed {
+            Err(e) if e.is::<executor::Saturated>() => {
+                warn!(site_id = %site_id, "Wasm executor saturated, shedding request");
+                Ok(self.busy_response(std::time::Duration::from_secs(1)))
+            }
             Ok(response_data) => {
                 self.successful_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
                 
@@ -151,71 +932,203 @@ impl Router {
                     "Request routed successfully"
                 );
 
-                Ok(self.build_response(response_data))
+                let body = response_data.into_bytes();
+                if let Some(meter) = self.bandwidth.get() {
+                    meter.record(&site_id, request_size(&req), body.len() as u64);
+                }
+                if let Some(meter) = self.billing.get() {
+                    meter.record_egress(&site_id, body.len() as u64);
+                }
+
+                Ok(self.build_response(body))
             }
             Err(e) => {
+                let failure = PearError::from_execution(&e);
                 error!(
                     site_id = %site_id,
                     cage_id = cage.id(),
+                    code = failure.code(),
                     error = %e,
                     "Cage execution failed"
                 );
-                self.failed_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                
-                Ok(self.error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Request execution failed",
-                ))
+                Ok(self.error_response(failure))
+            }
+        }
+    }
+
+    /// A Cage `select_cage` picks among those below their `max_concurrent_requests`, and a slot on it
+    /// While every Cage is busy the request waits in the pool's queue, and fails with `QueueRejected`
+    /// if it is full or no Cage frees up in time.
+    async fn reserve_cage(
+        &self,
+        pool: &CagePool,
+        strategy: LoadBalancingStrategy,
+        tried: &[Arc<crate::cage::Cage>],
+    ) -> Result<(Arc<crate::cage::Cage>, request_queue::CageSlot)> {
+        pool.queue().acquire(|| async move {
+            match self.select_cage(pool, strategy, tried, |cage| cage.has_free_slot()).await {
+                Some(cage) => Ok(pool.slot(&cage).map(|slot| (cage, slot))),
+                None if self.select_cage(pool, strategy, tried, |_| true).await.is_some() => Ok(None),
+                None => Err(PearError::NoHealthyCages.into()),
+            }
+        }).await
+    }
+
+    /// A healthy Cage of the pool `usable` accepts, passing over ejected outliers and those in `tried`
+    /// Ejections are ignored if they leave nothing to choose from.
+    async fn select_cage(
+        &self,
+        pool: &CagePool,
+        strategy: LoadBalancingStrategy,
+        tried: &[Arc<crate::cage::Cage>],
+        usable: impl Fn(&Arc<crate::cage::Cage>) -> bool,
+    ) -> Option<Arc<crate::cage::Cage>> {
+        let untried = |cage: &Arc<crate::cage::Cage>| usable(cage) && !tried.iter().any(|other| Arc::ptr_eq(other, cage));
+        if let Some(outliers) = self.outliers.get() {
+            let cage = select_where(pool, strategy, |cage| untried(cage) && !outliers.is_ejected(cage)).await;
+            if cage.is_some() {
+                return cage;
             }
         }
+        select_where(pool, strategy, untried).await
+    }
+
+    /// Run a request in a Cage using pooled buffers, off the async workers
+    /// The outcome counts toward the Cage's outlier detection, unless the executor had no room for it.
+    async fn execute(&self, pool: &CagePool, cage: &Arc<crate::cage::Cage>, request_data: Bytes) -> Result<PooledBuffer> {
+        let mut response_data = self.memory_pool.acquire_pooled(RESPONSE_BUFFER_HINT);
+        let executing = cage.clone();
+        let span = tracing::Span::current();
+        let executed = self.wasm_executor().run(move || {
+            let _entered = span.enter();
+            let started = std::time::Instant::now();
+            let result = executing.execute_request_into(&request_data, &mut response_data);
+            (result.map(|()| response_data), started.elapsed())
+        }).await;
+
+        let (result, elapsed) = executed?;
+        if let Some(outliers) = self.outliers.get() {
+            outliers.observe(pool, cage, result.is_err(), elapsed).await;
+        }
+        result
+    }
+
+    /// Whether a failed request is worth running again on another Cage
+    /// Only idempotent requests whose guest trapped or failed are; timeouts and overload would only add load.
+    fn should_retry(&self, method: &hyper::Method, error: &anyhow::Error, attempts: usize) -> bool {
+        let Some(budget) = self.retries.get() else { return false };
+        attempts < budget.max_retries() as usize
+            && method.is_idempotent()
+            && matches!(PearError::from_execution(error), PearError::CageTrap | PearError::Internal)
+    }
+
+    /// Start a streamed response, the Cage writing to it from a blocking task
+    async fn stream_response(
+        &self,
+        streaming: &stream::StreamingConfig,
+        cage: Arc<crate::cage::Cage>,
+        slot: request_queue::CageSlot,
+        req: &Request<Incoming>,
+        site_id: &str,
+    ) -> Response<RouterBody> {
+        let conn_id = req.extensions().get::<ConnectionId>().map_or(0, |id| id.0);
+        let accounting = self.state.stream_accounting(conn_id);
+        if accounting.open_streams.load(std::sync::atomic::Ordering::Relaxed) >= streaming.max_streams_per_connection {
+            return self.error_response(PearError::TooManyStreams);
+        }
+
+        let request_data = self.serialize_request(req).await.to_vec();
+        let (sink, body) = stream::channel(streaming, accounting);
+        let export = streaming.export.clone();
+        let site = site_id.to_string();
+        let span = tracing::Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let _slot = slot;
+            if let Err(e) = cage.stream_request(&export, &request_data, Arc::new(sink)) {
+                warn!(site_id = %site, cage_id = cage.id(), error = %e, "Streaming response failed");
+            }
+        });
+
+        self.successful_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        debug!(site_id = %site_id, conn_id, "Streaming response started");
+        
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, stream::content_type(req))
+            .header(hyper::header::CACHE_CONTROL, "no-cache")
+            .header("X-Routed-By", "Cage-Router")
+            .body(body.boxed_unsync())
+            .unwrap()
     }
 
     /// Extract site ID from request (simplified)
     fn extract_site_id(&self, req: &Request<Incoming>) -> String {
         // In production, extract from Host header
         // For Phase 2, use a default site
-        req.headers()
+        let host = req.headers()
             .get("host")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("default-site")
-            .to_string()
+            .and_then(|h| h.to_str().ok());
+        match (host, self.default_site.get()) {
+            (Some(host), Some(_)) if self.pools.contains_key(host) => host.to_string(),
+            (_, Some(site_id)) => site_id.clone(),
+            (host, None) => host.unwrap_or("default-site").to_string(),
+        }
     }
 
     /// Serialize request for Cage execution
-    async fn serialize_request(&self, req: &Request<Incoming>) -> Vec<u8> {
+    /// Written into a pooled buffer that is recycled once the Cage is done with it
+    async fn serialize_request(&self, req: &Request<Incoming>) -> PooledBuffer {
         // Simplified serialization for Phase 2
         // In production, serialize full HTTP request
-        let method = req.method().to_string();
-        let uri = req.uri().to_string();
+        let mut buffer = self.memory_pool.acquire_pooled(REQUEST_BUFFER_HINT);
+        
+        // Writing into a BytesMut cannot fail
+        let request_id = req.extensions().get::<RequestId>().map_or("", |id| id.0.as_str());
+        let _ = write!(
+            buffer,
+            "{{\"method\":\"{}\",\"uri\":\"{}\",\"request_id\":\"{}\"}}",
+            req.method(), req.uri(), request_id
+        );
         
-        format!("{{\"method\":\"{}\",\"uri\":\"{}\"}}", method, uri).into_bytes()
+        buffer
     }
 
     /// Build HTTP response from Cage output
-    fn build_response(&self, data: Vec<u8>) -> Response<Full<Bytes>> {
+    fn build_response(&self, data: Bytes) -> Response<RouterBody> {
         Response::builder()
             .status(StatusCode::OK)
             .header("Content-Type", "application/json")
             .header("X-Powered-By", "Pear-Server/0.2.0")
             .header("X-Routed-By", "Cage-Router")
-            .body(Full::new(Bytes::from(data)))
+            .body(full_body(data))
             .unwrap()
     }
 
-    /// Build error response
-    fn error_response(&self, status: StatusCode, message: &str) -> Response<Full<Bytes>> {
-        let body = serde_json::json!({
-            "error": message,
-            "status": status.as_u16(),
-        });
-
+    /// Count a failed request and build its JSON error response
+    fn error_response(&self, error: PearError) -> Response<RouterBody> {
+        self.record_error(error);
+        let request_id = CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok();
         Response::builder()
-            .status(status)
+            .status(error.status())
             .header("Content-Type", "application/json")
-            .body(Full::new(Bytes::from(body.to_string())))
+            .body(full_body(error.body(request_id.as_deref()).to_string()))
             .unwrap()
     }
 
+    /// Count a failed request under its error code
+    fn record_error(&self, error: PearError) {
+        self.failed_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.errors.entry(error.code()).or_default().fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// 503 telling the client when to retry a request shed under load
+    fn busy_response(&self, retry_after: std::time::Duration) -> Response<RouterBody> {
+        let mut response = self.error_response(PearError::Overloaded);
+        response.headers_mut().insert(hyper::header::RETRY_AFTER, hyper::header::HeaderValue::from(retry_after.as_secs().max(1)));
+        response
+    }
+
     /// Get router statistics
     pub fn stats(&self) -> RouterStats {
         RouterStats {
@@ -223,51 +1136,210 @@ impl Router {
             successful_requests: self.successful_requests.load(std::sync::atomic::Ordering::Relaxed),
             failed_requests: self.failed_requests.load(std::sync::atomic::Ordering::Relaxed),
             active_pools: self.pools.len(),
+            latency: self.latency.snapshot().percentiles(),
+            wasm: self.wasm_executor().stats(),
+            admission: self.admission.get().map(|admission| admission.stats()).unwrap_or_default(),
+            outliers: self.outliers.get().map(|outliers| outliers.stats()).unwrap_or_default(),
+            retries: self.retries.get().map(|budget| budget.stats()).unwrap_or_default(),
+            errors: self.errors.iter()
+                .map(|entry| (*entry.key(), entry.value().load(std::sync::atomic::Ordering::Relaxed)))
+                .collect(),
         }
     }
 
+    /// Response latencies recorded since startup
+    pub fn latency(&self) -> crate::observability::histogram::HistogramSnapshot {
+        self.latency.snapshot()
+    }
+
+    /// Cumulative request and error counts and latencies per site
+    pub fn site_traffic(&self) -> Vec<SiteTrafficStats> {
+        self.site_traffic.iter()
+            .map(|entry| SiteTrafficStats {
+                site_id: entry.key().clone(),
+                requests: entry.requests.load(std::sync::atomic::Ordering::Relaxed),
+                errors: entry.errors.load(std::sync::atomic::Ordering::Relaxed),
+                latency: entry.latency.snapshot(),
+            })
+            .collect()
+    }
+
+    /// Health checker probing the pools, whose settings can be replaced at runtime
+    pub fn health_checker(&self) -> &Arc<health::HealthChecker> {
+        &self.health
+    }
+
     /// Start health checking loop
+    /// Wakes every second and probes each pool whose site's interval has passed.
     pub async fn start_health_checks(&self) {
         if !self.config.health_check_enabled {
             return;
         }
 
         let pools = self.pools.clone();
-        let interval_secs = self.config.health_check_interval_secs;
+        let site_routes = self.routes.clone();
+        let checker = self.health.clone();
 
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(interval_secs));
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
             
             loop {
                 interval.tick().await;
                 
-                for entry in pools.iter() {
-                    let site_id = entry.key();
-                    let pool = entry.value();
+                // Probing awaits, so don't hold the maps' locks meanwhile
+                let watched = watched_pools(&pools, &site_routes);
+
+                for (key, pool) in &watched {
+                    if pool.is_draining() || !checker.check(key, pool).await {
+                        continue;
+                    }
                     
                     let stats = pool.health_stats().await;
                     
                     if !stats.is_healthy() {
                         warn!(
-                            site_id = %site_id,
+                            site_id = %stats.site_id,
+                            pool = %key,
                             healthy = stats.healthy_cages,
                             total = stats.total_cages,
                             "Pool health degraded"
                         );
                     }
                 }
+                let keys: Vec<String> = watched.into_iter().map(|(key, _)| key).collect();
+                checker.retain(&keys);
             }
         });
 
         info!("Health check loop started");
     }
 
+    /// Start checking the pools' Cages for outliers every interval
+    pub fn start_outlier_detection(&self) {
+        let Some(outliers) = self.outliers.get().cloned() else { return };
+        let pools = self.pools.clone();
+        let site_routes = self.routes.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(outliers.interval());
+            interval.tick().await;
+            
+            loop {
+                interval.tick().await;
+                for (_, pool) in watched_pools(&pools, &site_routes) {
+                    if !pool.is_draining() {
+                        outliers.analyze(&pool).await;
+                    }
+                }
+                outliers.prune();
+            }
+        });
+
+        info!("Outlier detection started");
+    }
+
+    /// Get memory pool statistics for the request path
+    pub fn memory_pool_stats(&self) -> crate::state::shared_memory::PoolStats {
+        self.memory_pool.stats()
+    }
+
     /// Get number of registered pools
     pub fn pool_count(&self) -> usize {
         self.pools.len()
     }
 }
 
+/// Every site's pool and route pool, keyed as health checks key them
+fn watched_pools(
+    pools: &DashMap<String, RoutedPool>,
+    site_routes: &DashMap<String, Arc<routes::SiteRoutes>>,
+) -> Vec<(String, Arc<CagePool>)> {
+    let mut watched: Vec<(String, Arc<CagePool>)> = pools.iter()
+        .map(|entry| (entry.key().clone(), entry.value().pool.clone()))
+        .collect();
+    for entry in site_routes.iter() {
+        watched.extend(entry.value().pools()
+            .map(|(prefix, pool)| (routes::pool_key(entry.key(), prefix), pool.clone())));
+    }
+    watched
+}
+
+/// A healthy Cage of the pool `usable` accepts, chosen by `strategy`
+async fn select_where(
+    pool: &CagePool,
+    strategy: LoadBalancingStrategy,
+    usable: impl Fn(&Arc<crate::cage::Cage>) -> bool,
+) -> Option<Arc<crate::cage::Cage>> {
+    match strategy {
+        LoadBalancingStrategy::RoundRobin => pool.get_cage_round_robin_where(usable).await,
+        LoadBalancingStrategy::LeastConnected => pool.get_cage_least_connected_where(usable).await,
+    }
+}
+
+/// The request's ID: a trusted proxy's `X-Request-Id` if it is usable, else a new one
+/// IDs from clients are not trusted, so nobody can file their requests under another's ID.
+fn request_id<B>(req: &Request<B>) -> String {
+    if req.extensions().get::<ProxiedBy>().is_some() {
+        let given = req.headers().get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok());
+        if let Some(id) = given.filter(|id| valid_request_id(id)) {
+            return id.to_string();
+        }
+    }
+    new_request_id()
+}
+
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// Bytes a request occupied on the wire: request line, headers and declared body
+fn request_size<B>(req: &Request<B>) -> u64 {
+    let head = req.method().as_str().len()
+        + req.uri().path_and_query().map_or(0, |pq| pq.as_str().len())
+        + req.headers().iter()
+            .map(|(name, value)| name.as_str().len() + value.len() + 4)
+            .sum::<usize>();
+
+    let body = req.headers().get(hyper::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    head as u64 + body
+}
+
+/// Per-site response counters
+#[derive(Default)]
+struct SiteTraffic {
+    requests: std::sync::atomic::AtomicU64,
+    
+    /// Responses with a 4xx or 5xx status
+    errors: std::sync::atomic::AtomicU64,
+    
+    latency: crate::observability::histogram::LatencyHistogram,
+}
+
+impl SiteTraffic {
+    fn record(&self, error: bool, latency: std::time::Duration) {
+        self.requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        if error {
+            self.errors.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        self.latency.record(latency);
+    }
+}
+
+/// Requests served for a site since startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteTrafficStats {
+    pub site_id: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub latency: crate::observability::histogram::HistogramSnapshot,
+}
+
 /// Router statistics
 #[derive(Debug, Clone)]
 pub struct RouterStats {
@@ -275,6 +1347,24 @@ pub struct RouterStats {
     pub successful_requests: u64,
     pub failed_requests: u64,
     pub active_pools: usize,
+    
+    /// Response latency across all sites since startup
+    pub latency: crate::observability::histogram::LatencyPercentiles,
+    
+    /// Load on the executor Cage requests run on
+    pub wasm: executor::ExecutorStats,
+    
+    /// Requests in flight and shed by admission control
+    pub admission: admission::AdmissionStats,
+    
+    /// Cages ejected from selection as outliers
+    pub outliers: outlier::OutlierStats,
+    
+    /// Failed requests retried on another Cage
+    pub retries: retry::RetryStats,
+    
+    /// Failed requests by `PearError` code
+    pub errors: std::collections::BTreeMap<&'static str, u64>,
 }
 
 impl RouterStats {
@@ -298,6 +1388,36 @@ mod tests {
         assert_eq!(router.pool_count(), 0);
     }
 
+    #[test]
+    fn test_request_size() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("/upload")
+            .header("content-length", "1000")
+            .body(())
+            .unwrap();
+        
+        // "POST" + "/upload" + "content-length" + "1000" + separators + body
+        assert_eq!(request_size(&req), 4 + 7 + 14 + 4 + 4 + 1000);
+    }
+
+    #[test]
+    fn test_request_id() {
+        let proxy: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let request = |id: &str, proxied: bool| {
+            let mut req = Request::builder().header(REQUEST_ID_HEADER, id).body(()).unwrap();
+            if proxied {
+                req.extensions_mut().insert(ProxiedBy(proxy));
+            }
+            request_id(&req)
+        };
+
+        assert_eq!(request("lb-7f3a.1", true), "lb-7f3a.1");
+        assert_ne!(request("lb-7f3a.1", false), "lb-7f3a.1");
+        assert_ne!(request("a b", true), "a b");
+        assert_eq!(request(&"x".repeat(200), true).len(), 32);
+    }
+
     #[test]
     fn test_router_stats() {
         let router = Router::new(RouterConfig::default());
@@ -305,4 +1425,31 @@ mod tests {
         assert_eq!(stats.total_requests, 0);
         assert_eq!(stats.success_rate(), 0.0);
     }
+
+    #[tokio::test]
+    async fn test_tenant_pools() {
+        let tenants = Arc::new(crate::tenancy::TenantManager::new());
+        let acme = tenants.create_tenant("Acme".to_string(), "ops@acme.test".to_string(), Default::default()).unwrap();
+        let router = Router::new(RouterConfig::default());
+        router.set_tenants(tenants.clone());
+        let wasm = wat::parse_str("(module)").unwrap();
+        let pool = Arc::new(CagePool::new("blog".to_string(), wasm, Default::default(), 1).await.unwrap());
+        router.register_tenant_pool(acme, "blog".to_string(), pool.clone());
+        router.register_pool("docs".to_string(), pool.clone());
+        assert_eq!(router.tenant_refusal("blog"), None);
+
+        // Suspension applies once the Router hears of it
+        tenants.suspend_tenant(acme).unwrap();
+        router.tenant_changed(acme);
+        assert_eq!(router.tenant_refusal("blog"), Some(PearError::SiteSuspended));
+        assert_eq!(router.tenant_refusal("docs"), None);
+
+        // Replacing the pool keeps its tenant
+        router.register_pool("blog".to_string(), pool);
+        assert_eq!(router.site_tenant("blog"), Some(acme));
+
+        assert_eq!(router.unregister_tenant(acme), vec!["blog".to_string()]);
+        assert!(router.pool("blog").is_none());
+        assert_eq!(router.pool_count(), 1);
+    }
 }
@@ -0,0 +1,392 @@
+// Outlier Detection
+// Passive health checks on live traffic: Cages failing or lagging the rest of their pool leave selection for a while, without waiting for the Supervisor
+
+use crate::cage::pool::CagePool;
+use crate::cage::Cage;
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// `[outlier_detection]`: when Cages are ejected from selection on their live traffic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutlierConfig {
+    pub enabled: bool,
+
+    /// Failed requests in a row that eject a Cage at once (0 = never)
+    pub consecutive_failures: u32,
+
+    /// Share of a Cage's requests in an interval that failed, ejecting it (0 = never)
+    pub failure_rate: f64,
+
+    /// Ejects a Cage whose mean latency in an interval is this many times its pool's median (0 = never)
+    pub latency_factor: f64,
+
+    /// Fewest requests a Cage needs in an interval for its failure rate and latency to count
+    pub min_requests: u64,
+
+    /// Seconds between checks of failure rates and latencies
+    pub interval_secs: u64,
+
+    /// Seconds of a first ejection; each ejection in a row adds as much again
+    pub base_ejection_secs: u64,
+
+    /// Longest an ejection lasts
+    pub max_ejection_secs: u64,
+
+    /// Most of a pool's Cages ejected at once, in percent; a pool always keeps one Cage
+    pub max_ejection_percent: u32,
+}
+
+impl Default for OutlierConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            consecutive_failures: 5,
+            failure_rate: 0.5,
+            latency_factor: 3.0,
+            min_requests: 20,
+            interval_secs: 10,
+            base_ejection_secs: 30,
+            max_ejection_secs: 300,
+            max_ejection_percent: 50,
+        }
+    }
+}
+
+impl OutlierConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.failure_rate) {
+            bail!("failure_rate must be between 0 and 1");
+        }
+        if self.latency_factor != 0.0 && self.latency_factor <= 1.0 {
+            bail!("latency_factor must be over 1, or 0 to disable it");
+        }
+        if self.interval_secs == 0 {
+            bail!("interval_secs must be at least 1");
+        }
+        if self.base_ejection_secs == 0 || self.max_ejection_secs < self.base_ejection_secs {
+            bail!("base_ejection_secs must be at least 1 and at most max_ejection_secs");
+        }
+        if self.max_ejection_percent > 100 {
+            bail!("max_ejection_percent must be at most 100");
+        }
+        Ok(())
+    }
+}
+
+/// Why a Cage was ejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EjectionReason {
+    ConsecutiveFailures,
+    FailureRate,
+    Latency,
+}
+
+impl EjectionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EjectionReason::ConsecutiveFailures => "consecutive_failures",
+            EjectionReason::FailureRate => "failure_rate",
+            EjectionReason::Latency => "latency",
+        }
+    }
+}
+
+/// Ejections since startup and Cages out of selection now
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct OutlierStats {
+    pub ejected: usize,
+    pub consecutive_failures: u64,
+    pub failure_rate: u64,
+    pub latency: u64,
+}
+
+/// A Cage's outcomes since the last interval, and its ejection
+struct CageRecord {
+    /// Keeps the allocation the record is keyed by from being reused while the record exists
+    cage: Weak<Cage>,
+    requests: u64,
+    failures: u64,
+    latency: Duration,
+    consecutive_failures: u32,
+
+    /// Ejections in a row, without a clean interval between them
+    ejections: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl CageRecord {
+    fn new(cage: &Arc<Cage>) -> Self {
+        Self {
+            cage: Arc::downgrade(cage),
+            requests: 0,
+            failures: 0,
+            latency: Duration::ZERO,
+            consecutive_failures: 0,
+            ejections: 0,
+            ejected_until: None,
+        }
+    }
+
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Tracks the outcomes of requests on each Cage and ejects the outliers
+pub struct OutlierDetector {
+    config: OutlierConfig,
+
+    /// Keyed by the Cage's address
+    cages: DashMap<usize, CageRecord>,
+
+    ejections: [AtomicU64; 3],
+}
+
+fn key(cage: &Arc<Cage>) -> usize {
+    Arc::as_ptr(cage) as usize
+}
+
+impl OutlierDetector {
+    pub fn new(config: OutlierConfig) -> Self {
+        Self {
+            config,
+            cages: DashMap::new(),
+            ejections: Default::default(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_secs)
+    }
+
+    /// Whether the Cage is out of selection
+    pub fn is_ejected(&self, cage: &Arc<Cage>) -> bool {
+        self.cages.get(&key(cage)).is_some_and(|record| record.is_ejected(Instant::now()))
+    }
+
+    /// Record a request's outcome on a Cage of the pool, ejecting the Cage once it has failed too often in a row
+    pub async fn observe(&self, pool: &CagePool, cage: &Arc<Cage>, failed: bool, latency: Duration) {
+        if self.record(cage, failed, latency) {
+            let cages = pool.cages().await;
+            self.eject(pool.site_id(), cage, &cages, EjectionReason::ConsecutiveFailures);
+        }
+    }
+
+    /// Count an outcome; true once the Cage reaches `consecutive_failures`
+    fn record(&self, cage: &Arc<Cage>, failed: bool, latency: Duration) -> bool {
+        let mut record = self.cages.entry(key(cage)).or_insert_with(|| CageRecord::new(cage));
+        record.requests += 1;
+        record.latency += latency;
+        if !failed {
+            record.consecutive_failures = 0;
+            return false;
+        }
+        record.failures += 1;
+        record.consecutive_failures += 1;
+        self.config.consecutive_failures > 0
+            && record.consecutive_failures >= self.config.consecutive_failures
+            && !record.is_ejected(Instant::now())
+    }
+
+    /// Take a Cage out of selection, unless too much of its pool already is
+    /// Returns how long for.
+    fn eject(&self, site_id: &str, cage: &Arc<Cage>, pool: &[Arc<Cage>], reason: EjectionReason) -> Option<Duration> {
+        let now = Instant::now();
+        let ejected = pool.iter()
+            .filter(|other| !Arc::ptr_eq(other, cage) && self.is_ejected(other))
+            .count();
+        if (ejected + 1) * 100 > pool.len() * self.config.max_ejection_percent as usize || ejected + 1 >= pool.len() {
+            debug!(
+                site_id = %site_id,
+                cage_id = cage.id(),
+                reason = reason.as_str(),
+                ejected,
+                "Outlier Cage kept in selection, too much of its pool is ejected"
+            );
+            return None;
+        }
+
+        let mut record = self.cages.entry(key(cage)).or_insert_with(|| CageRecord::new(cage));
+        let duration = Duration::from_secs(self.config.base_ejection_secs)
+            .saturating_mul(record.ejections + 1)
+            .min(Duration::from_secs(self.config.max_ejection_secs));
+        record.ejections += 1;
+        record.ejected_until = Some(now + duration);
+        record.consecutive_failures = 0;
+        drop(record);
+
+        self.ejections[reason as usize].fetch_add(1, Ordering::Relaxed);
+        warn!(
+            site_id = %site_id,
+            cage_id = cage.id(),
+            reason = reason.as_str(),
+            ejection_secs = duration.as_secs(),
+            "Ejected outlier Cage from selection"
+        );
+        Some(duration)
+    }
+
+    /// Check the pool's Cages' failure rates and latencies over the interval just ended, then start a new one
+    pub async fn analyze(&self, pool: &CagePool) {
+        let cages = pool.cages().await;
+        self.analyze_cages(pool.site_id(), &cages);
+    }
+
+    fn analyze_cages(&self, site_id: &str, cages: &[Arc<Cage>]) {
+        let now = Instant::now();
+
+        // (requests, failures, mean latency) of each Cage, resetting the interval
+        let intervals: Vec<(u64, u64, Duration)> = cages.iter()
+            .map(|cage| match self.cages.get_mut(&key(cage)) {
+                Some(mut record) => {
+                    if record.ejected_until.is_some_and(|until| until <= now) {
+                        record.ejected_until = None;
+                        info!(site_id = %site_id, cage_id = cage.id(), "Ejected Cage returned to selection");
+                    }
+                    let interval = (record.requests, record.failures, record.latency / record.requests.max(1) as u32);
+                    record.requests = 0;
+                    record.failures = 0;
+                    record.latency = Duration::ZERO;
+                    interval
+                }
+                None => (0, 0, Duration::ZERO),
+            })
+            .collect();
+
+        let mut latencies: Vec<Duration> = intervals.iter()
+            .filter(|(requests, _, _)| *requests >= self.config.min_requests)
+            .map(|(_, _, latency)| *latency)
+            .collect();
+        latencies.sort();
+        let median = (latencies.len() >= 2).then(|| latencies[latencies.len() / 2]);
+
+        for (cage, &(requests, failures, latency)) in cages.iter().zip(&intervals) {
+            if requests < self.config.min_requests.max(1) {
+                continue;
+            }
+            let reason = if self.config.failure_rate > 0.0 && failures as f64 >= requests as f64 * self.config.failure_rate {
+                Some(EjectionReason::FailureRate)
+            } else if median.is_some_and(|median| {
+                self.config.latency_factor > 0.0 && latency > median.mul_f64(self.config.latency_factor)
+            }) {
+                Some(EjectionReason::Latency)
+            } else {
+                None
+            };
+
+            match reason {
+                Some(reason) => {
+                    self.eject(site_id, cage, cages, reason);
+                }
+                // A clean interval forgives earlier ejections
+                None => {
+                    if let Some(mut record) = self.cages.get_mut(&key(cage)) {
+                        record.ejections = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forget Cages that no longer exist
+    pub fn prune(&self) {
+        self.cages.retain(|_, record| record.cage.strong_count() > 0);
+    }
+
+    pub fn stats(&self) -> OutlierStats {
+        let now = Instant::now();
+        OutlierStats {
+            ejected: self.cages.iter().filter(|record| record.is_ejected(now)).count(),
+            consecutive_failures: self.ejections[EjectionReason::ConsecutiveFailures as usize].load(Ordering::Relaxed),
+            failure_rate: self.ejections[EjectionReason::FailureRate as usize].load(Ordering::Relaxed),
+            latency: self.ejections[EjectionReason::Latency as usize].load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cage::config::CageConfig;
+
+    async fn cages(count: usize) -> (CagePool, Vec<Arc<Cage>>) {
+        let wasm_bytes = wat::parse_str("(module)").unwrap();
+        let pool = CagePool::new("blog".to_string(), wasm_bytes, CageConfig::default(), count).await.unwrap();
+        let cages = pool.cages().await;
+        (pool, cages)
+    }
+
+    #[tokio::test]
+    async fn test_consecutive_failures_eject() {
+        let (pool, cages) = cages(4).await;
+        let detector = OutlierDetector::new(OutlierConfig { consecutive_failures: 3, ..Default::default() });
+        let ms = Duration::from_millis(1);
+
+        detector.observe(&pool, &cages[0], true, ms).await;
+        detector.observe(&pool, &cages[0], true, ms).await;
+        detector.observe(&pool, &cages[0], false, ms).await;
+        detector.observe(&pool, &cages[0], true, ms).await;
+        assert!(!detector.is_ejected(&cages[0]));
+        detector.observe(&pool, &cages[0], true, ms).await;
+        detector.observe(&pool, &cages[0], true, ms).await;
+        assert!(detector.is_ejected(&cages[0]));
+
+        // Ejected Cages are passed over
+        for _ in 0..4 {
+            let cage = pool.get_cage_round_robin_where(|cage| !detector.is_ejected(cage)).await.unwrap();
+            assert!(!Arc::ptr_eq(&cage, &cages[0]));
+        }
+
+        // At most half of the pool is ejected
+        for _ in 0..3 {
+            detector.observe(&pool, &cages[1], true, ms).await;
+            detector.observe(&pool, &cages[2], true, ms).await;
+        }
+        assert!(detector.is_ejected(&cages[1]));
+        assert!(!detector.is_ejected(&cages[2]));
+        assert_eq!(detector.stats().ejected, 2);
+        assert_eq!(detector.stats().consecutive_failures, 2);
+    }
+
+    #[tokio::test]
+    async fn test_interval_outliers() {
+        let (pool, cages) = cages(4).await;
+        let detector = OutlierDetector::new(OutlierConfig {
+            min_requests: 10,
+            max_ejection_percent: 100,
+            ..Default::default()
+        });
+
+        for _ in 0..10 {
+            detector.record(&cages[0], false, Duration::from_millis(10));
+            detector.record(&cages[1], false, Duration::from_millis(12));
+            detector.record(&cages[2], false, Duration::from_millis(80));
+        }
+        for failed in [true, false].repeat(5) {
+            detector.record(&cages[3], failed, Duration::from_millis(10));
+        }
+        detector.analyze_cages("blog", &cages);
+
+        assert!(!detector.is_ejected(&cages[0]));
+        assert!(detector.is_ejected(&cages[2]));
+        assert!(detector.is_ejected(&cages[3]));
+        let stats = detector.stats();
+        assert_eq!((stats.latency, stats.failure_rate), (1, 1));
+
+        // Ejections in a row last longer, up to the maximum
+        let ejected = detector.eject("blog", &cages[3], &cages, EjectionReason::FailureRate).unwrap();
+        assert_eq!(ejected, Duration::from_secs(60));
+
+        // Records of dropped Cages go
+        drop(cages);
+        drop(pool);
+        detector.prune();
+        assert!(detector.cages.is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+// Retry Budget
+// Idempotent requests whose Cage failed are retried on another Cage, but retries are held to a share of traffic so they can't amplify an incident
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Requests and retries are counted over this window and the one before it
+const BUDGET_WINDOW: Duration = Duration::from_secs(10);
+
+/// `[retries]`: retrying failed requests on another Cage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Other Cages an idempotent request is tried on after its Cage trapped or failed (0 = no retries)
+    pub max_retries: u32,
+
+    /// Retries allowed as a percentage of the requests of the last 10 to 20 seconds
+    pub budget_percent: u32,
+
+    /// Retries per second allowed however few requests there are
+    pub min_retries_per_sec: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 1,
+            budget_percent: 20,
+            min_retries_per_sec: 10,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.budget_percent > 100 {
+            bail!("budget_percent must be at most 100");
+        }
+        Ok(())
+    }
+}
+
+/// Retries made and refused since startup
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct RetryStats {
+    pub retries: u64,
+    pub budget_exhausted: u64,
+}
+
+/// Counts of the previous window, and when the current one started
+struct Window {
+    started: Instant,
+    requests: u64,
+    retries: u64,
+}
+
+/// Shared across all sites, so retries stay a bounded share of the node's load
+pub struct RetryBudget {
+    config: RetryConfig,
+    window: Mutex<Window>,
+
+    /// Counts of the current window
+    requests: AtomicU64,
+    retries: AtomicU64,
+
+    total_retries: AtomicU64,
+    exhausted: AtomicU64,
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            config,
+            window: Mutex::new(Window { started: Instant::now(), requests: 0, retries: 0 }),
+            requests: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            total_retries: AtomicU64::new(0),
+            exhausted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.config.max_retries
+    }
+
+    /// Count a request the budget grows with
+    pub fn record_request(&self) {
+        self.roll();
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take a retry from the budget; false once it is spent
+    pub fn try_retry(&self) -> bool {
+        self.roll();
+        let (requests, retries) = {
+            let window = self.window.lock();
+            (
+                window.requests + self.requests.load(Ordering::Relaxed),
+                window.retries + self.retries.load(Ordering::Relaxed),
+            )
+        };
+        let allowed = self.config.min_retries_per_sec as u64 * BUDGET_WINDOW.as_secs()
+            + requests * self.config.budget_percent as u64 / 100;
+        if retries >= allowed {
+            self.exhausted.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.retries.fetch_add(1, Ordering::Relaxed);
+        self.total_retries.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Start a new window once the current one has passed
+    fn roll(&self) {
+        let Some(mut window) = self.window.try_lock() else { return };
+        let elapsed = window.started.elapsed();
+        if elapsed < BUDGET_WINDOW {
+            return;
+        }
+        let requests = self.requests.swap(0, Ordering::Relaxed);
+        let retries = self.retries.swap(0, Ordering::Relaxed);
+        // After an idle window there is nothing recent to count
+        let recent = elapsed < BUDGET_WINDOW * 2;
+        *window = Window {
+            started: Instant::now(),
+            requests: if recent { requests } else { 0 },
+            retries: if recent { retries } else { 0 },
+        };
+    }
+
+    pub fn stats(&self) -> RetryStats {
+        RetryStats {
+            retries: self.total_retries.load(Ordering::Relaxed),
+            budget_exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = RetryBudget::new(RetryConfig { budget_percent: 10, min_retries_per_sec: 0, ..Default::default() });
+        assert!(!budget.try_retry());
+
+        for _ in 0..25 {
+            budget.record_request();
+        }
+        assert!(budget.try_retry());
+        assert!(budget.try_retry());
+        assert!(!budget.try_retry());
+
+        // The window before the current one still counts
+        budget.window.lock().started -= BUDGET_WINDOW;
+        budget.record_request();
+        assert!(!budget.try_retry());
+        for _ in 0..10 {
+            budget.record_request();
+        }
+        assert!(budget.try_retry());
+
+        let stats = budget.stats();
+        assert_eq!((stats.retries, stats.budget_exhausted), (3, 3));
+
+        let floor = RetryBudget::new(RetryConfig { min_retries_per_sec: 1, ..Default::default() });
+        assert_eq!((0..20).filter(|_| floor.try_retry()).count(), 10);
+    }
+}
@@ -0,0 +1,396 @@
+// Rewrite Rules
+// Per-site redirects, path rewrites and header edits applied around Cage dispatch
+
+use anyhow::{Context, Result, bail};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response, StatusCode, Uri};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{debug, info, warn};
+
+/// Canonical form of paths without a file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// Leave paths as requested
+    #[default]
+    Keep,
+    /// Redirect `/docs` to `/docs/`
+    Add,
+    /// Redirect `/docs/` to `/docs`
+    Remove,
+}
+
+/// Redirect clients whose path matches `from`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectRule {
+    /// Regular expression matched against the path
+    pub from: String,
+
+    /// Location, with `$1`/`${name}` capture references; the query is kept unless it has its own
+    pub to: String,
+
+    /// 301, 302, 303, 307 or 308
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+
+/// Serve a different path than the one requested, invisibly to the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathRewriteRule {
+    /// Regular expression matched against the path
+    pub from: String,
+
+    /// Replacement path, with capture references
+    pub to: String,
+}
+
+/// Header edits, applied as rename, then remove, then set
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderRules {
+    /// Old name to new name
+    pub rename: HashMap<String, String>,
+
+    pub remove: Vec<String>,
+
+    /// Headers added or replaced
+    pub set: HashMap<String, String>,
+}
+
+/// Rewrite rules for one site
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SiteRewriteConfig {
+    pub trailing_slash: TrailingSlash,
+
+    /// Evaluated in order, first match wins
+    pub redirects: Vec<RedirectRule>,
+
+    /// Evaluated in order after redirects, first match wins
+    pub rewrites: Vec<PathRewriteRule>,
+
+    /// Edits to requests before they reach the Cage
+    pub request_headers: HeaderRules,
+
+    /// Edits to responses on their way to the client
+    pub response_headers: HeaderRules,
+}
+
+/// `[rewrite]`: per-site rules, keyed by site ID
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RewriteConfig {
+    #[serde(default)]
+    pub sites: HashMap<String, SiteRewriteConfig>,
+}
+
+fn default_redirect_status() -> u16 { 301 }
+
+struct CompiledRedirect {
+    regex: Regex,
+    to: String,
+    status: StatusCode,
+}
+
+struct CompiledRewrite {
+    regex: Regex,
+    to: String,
+}
+
+struct CompiledHeaders {
+    rename: Vec<(HeaderName, HeaderName)>,
+    remove: Vec<HeaderName>,
+    set: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl CompiledHeaders {
+    fn compile(config: &HeaderRules) -> Result<Self> {
+        let name = |name: &str| HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name '{}'", name));
+
+        let rename = config.rename.iter()
+            .map(|(from, to)| Ok((name(from)?, name(to)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let remove = config.remove.iter()
+            .map(|header| name(header))
+            .collect::<Result<Vec<_>>>()?;
+        let set = config.set.iter()
+            .map(|(header, value)| {
+                let value = HeaderValue::from_str(value)
+                    .with_context(|| format!("Invalid value for header '{}'", header))?;
+                Ok((name(header)?, value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { rename, remove, set })
+    }
+
+    fn apply(&self, headers: &mut HeaderMap) {
+        for (from, to) in &self.rename {
+            let values: Vec<_> = headers.get_all(from).iter().cloned().collect();
+            headers.remove(from);
+            for value in values {
+                headers.append(to.clone(), value);
+            }
+        }
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Compiled rules for one site
+struct SiteRules {
+    trailing_slash: TrailingSlash,
+    redirects: Vec<CompiledRedirect>,
+    rewrites: Vec<CompiledRewrite>,
+    request_headers: CompiledHeaders,
+    response_headers: CompiledHeaders,
+}
+
+impl SiteRules {
+    fn compile(config: &SiteRewriteConfig) -> Result<Self> {
+        let redirects = config.redirects.iter()
+            .map(|rule| {
+                if !matches!(rule.status, 301 | 302 | 303 | 307 | 308) {
+                    bail!("Redirect status {} for '{}' is not a redirect", rule.status, rule.from);
+                }
+                Ok(CompiledRedirect {
+                    regex: Regex::new(&rule.from)
+                        .with_context(|| format!("Invalid redirect pattern '{}'", rule.from))?,
+                    to: rule.to.clone(),
+                    status: StatusCode::from_u16(rule.status)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let rewrites = config.rewrites.iter()
+            .map(|rule| {
+                if !rule.to.starts_with('/') {
+                    bail!("Rewrite target '{}' must be a path starting with '/'", rule.to);
+                }
+                Ok(CompiledRewrite {
+                    regex: Regex::new(&rule.from)
+                        .with_context(|| format!("Invalid rewrite pattern '{}'", rule.from))?,
+                    to: rule.to.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            trailing_slash: config.trailing_slash,
+            redirects,
+            rewrites,
+            request_headers: CompiledHeaders::compile(&config.request_headers).context("Invalid request_headers")?,
+            response_headers: CompiledHeaders::compile(&config.response_headers).context("Invalid response_headers")?,
+        })
+    }
+
+    /// Where to redirect a path, if anywhere
+    /// Explicit redirects take precedence over trailing slash normalization
+    fn redirect(&self, path: &str, query: Option<&str>) -> Option<(StatusCode, String)> {
+        let explicit = self.redirects.iter().find_map(|rule| {
+            let captures = rule.regex.captures(path)?;
+            let mut location = String::new();
+            captures.expand(&rule.to, &mut location);
+            Some((rule.status, with_query(location, query)))
+        });
+        if explicit.is_some() {
+            return explicit;
+        }
+
+        let location = match self.trailing_slash {
+            TrailingSlash::Add if !path.ends_with('/') && !has_extension(path) => format!("{}/", path),
+            TrailingSlash::Remove if path.ends_with('/') && !path.trim_end_matches('/').is_empty() => path.trim_end_matches('/').to_string(),
+            _ => return None,
+        };
+        Some((StatusCode::MOVED_PERMANENTLY, with_query(location, query)))
+    }
+
+    /// Internal path for a request path, if rewritten
+    fn rewrite(&self, path: &str, query: Option<&str>) -> Option<String> {
+        self.rewrites.iter().find_map(|rule| {
+            let captures = rule.regex.captures(path)?;
+            let mut target = String::new();
+            captures.expand(&rule.to, &mut target);
+            Some(with_query(target, query))
+        })
+    }
+}
+
+/// Append the original query unless the target has one of its own
+fn with_query(target: String, query: Option<&str>) -> String {
+    match query {
+        Some(query) if !target.contains('?') => format!("{}?{}", target, query),
+        _ => target,
+    }
+}
+
+/// Whether the last path segment looks like a file name
+fn has_extension(path: &str) -> bool {
+    path.rsplit('/').next().is_some_and(|segment| segment.contains('.'))
+}
+
+/// Rewrite rules for every configured site
+pub struct RewriteEngine {
+    sites: HashMap<String, SiteRules>,
+}
+
+impl RewriteEngine {
+    /// Compile the configured rules
+    pub fn new(config: &RewriteConfig) -> Result<Self> {
+        let sites = config.sites.iter()
+            .map(|(site_id, rules)| {
+                let rules = SiteRules::compile(rules)
+                    .with_context(|| format!("Invalid rewrite rules for site {}", site_id))?;
+                Ok((site_id.clone(), rules))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        if !sites.is_empty() {
+            info!(sites = sites.len(), "Rewrite rules loaded");
+        }
+        Ok(Self { sites })
+    }
+
+    /// Apply redirects, path rewrites and request header edits
+    /// Returns the redirect response when the client should go elsewhere.
+    pub fn rewrite_request<B>(&self, site_id: &str, req: &mut Request<B>) -> Option<Response<Full<Bytes>>> {
+        let rules = self.sites.get(site_id)?;
+        let path = req.uri().path().to_string();
+        let query = req.uri().query().map(str::to_string);
+
+        if let Some((status, location)) = rules.redirect(&path, query.as_deref()) {
+            debug!(site_id = %site_id, from = %path, to = %location, "Redirecting request");
+            return match HeaderValue::from_str(&location) {
+                Ok(location) => Some(Response::builder()
+                    .status(status)
+                    .header(header::LOCATION, location)
+                    .body(Full::new(Bytes::new()))
+                    .unwrap()),
+                Err(_) => {
+                    warn!(site_id = %site_id, location = %location, "Redirect target is not a valid Location");
+                    None
+                }
+            };
+        }
+
+        if let Some(target) = rules.rewrite(&path, query.as_deref()) {
+            let mut parts = req.uri().clone().into_parts();
+            match target.parse() {
+                Ok(path_and_query) => {
+                    parts.path_and_query = Some(path_and_query);
+                    if let Ok(uri) = Uri::from_parts(parts) {
+                        debug!(site_id = %site_id, from = %path, to = %target, "Rewrote request path");
+                        *req.uri_mut() = uri;
+                    }
+                }
+                Err(_) => warn!(site_id = %site_id, target = %target, "Rewritten path is not a valid URI"),
+            }
+        }
+
+        rules.request_headers.apply(req.headers_mut());
+        None
+    }
+
+    /// Apply response header edits
+    pub fn rewrite_response(&self, site_id: &str, headers: &mut HeaderMap) {
+        if let Some(rules) = self.sites.get(site_id) {
+            rules.response_headers.apply(headers);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(toml: &str) -> RewriteEngine {
+        let config: RewriteConfig = toml::from_str(toml).unwrap();
+        RewriteEngine::new(&config).unwrap()
+    }
+
+    fn request(uri: &str) -> Request<()> {
+        Request::builder().uri(uri).body(()).unwrap()
+    }
+
+    #[test]
+    fn test_redirects() {
+        let engine = engine(r#"
+            [sites.blog]
+            trailing_slash = "add"
+
+            [[sites.blog.redirects]]
+            from = "^/old/(.*)$"
+            to = "/new/$1"
+
+            [[sites.blog.redirects]]
+            from = "^/promo$"
+            to = "https://shop.example.com/sale?ref=blog"
+            status = 302
+        "#);
+
+        let response = engine.rewrite_request("blog", &mut request("/old/post.html?page=2")).unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::LOCATION], "/new/post.html?page=2");
+
+        let response = engine.rewrite_request("blog", &mut request("/docs")).unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "/docs/");
+
+        let response = engine.rewrite_request("blog", &mut request("/promo?x=1")).unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[header::LOCATION], "https://shop.example.com/sale?ref=blog");
+
+        assert!(engine.rewrite_request("blog", &mut request("/docs/")).is_none());
+        assert!(engine.rewrite_request("shop", &mut request("/old/x")).is_none());
+    }
+
+    #[test]
+    fn test_rewrites_and_headers() {
+        let engine = engine(r#"
+            [[sites.blog.rewrites]]
+            from = "^/posts/(?P<slug>[a-z-]+)$"
+            to = "/index.php?post=${slug}"
+
+            [sites.blog.request_headers]
+            rename = { "x-forwarded-user" = "x-user" }
+
+            [sites.blog.response_headers]
+            remove = ["x-powered-by"]
+            set = { "cache-control" = "public, max-age=60" }
+        "#);
+
+        let mut req = request("/posts/hello-world?utm=feed");
+        req.headers_mut().insert("x-forwarded-user", HeaderValue::from_static("ann"));
+        assert!(engine.rewrite_request("blog", &mut req).is_none());
+        assert_eq!(req.uri(), "/index.php?post=hello-world");
+        assert_eq!(req.headers()["x-user"], "ann");
+        assert!(!req.headers().contains_key("x-forwarded-user"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-powered-by", HeaderValue::from_static("php"));
+        engine.rewrite_response("blog", &mut headers);
+        assert!(!headers.contains_key("x-powered-by"));
+        assert_eq!(headers[header::CACHE_CONTROL], "public, max-age=60");
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let invalid = [
+            "[[sites.blog.redirects]]\nfrom = \"^/a\"\nto = \"/b\"\nstatus = 200\n",
+            "[[sites.blog.rewrites]]\nfrom = \"(\"\nto = \"/b\"\n",
+            "[[sites.blog.rewrites]]\nfrom = \"^/a\"\nto = \"b\"\n",
+            "[sites.blog.response_headers]\nremove = [\"bad header\"]\n",
+        ];
+        for toml in invalid {
+            let config: RewriteConfig = toml::from_str(toml).unwrap();
+            assert!(RewriteEngine::new(&config).is_err(), "{}", toml);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// Path Routes
+// Serve path prefixes of one site from different modules, each with its own strategy and limits
+
+use super::limits::LimitOverrides;
+use super::LoadBalancingStrategy;
+use crate::cage::pool::CagePool;
+use crate::deployment::manifest::check_module_path;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A `[[routes]]` entry of a site manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteConfig {
+    /// Path prefix, `/api` or `/api/*`; `/*` matches every path
+    pub path: String,
+
+    /// Module serving the prefix, relative to the manifest (the site's own module if unset)
+    #[serde(default)]
+    pub module: Option<String>,
+
+    /// Cages running the module (the node's default if unset)
+    #[serde(default)]
+    pub replicas: Option<usize>,
+
+    /// Cage selection for the prefix (the Router's if unset)
+    #[serde(default)]
+    pub strategy: Option<LoadBalancingStrategy>,
+
+    /// Request limits for the prefix, over the site's
+    #[serde(default)]
+    pub limits: LimitOverrides,
+}
+
+impl RouteConfig {
+    /// The prefix without its wildcard or trailing slash; `/` for a catch-all
+    pub fn prefix(&self) -> &str {
+        let prefix = self.path.strip_suffix('*').unwrap_or(&self.path);
+        match prefix.trim_end_matches('/') {
+            "" => "/",
+            prefix => prefix,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.path.starts_with('/') {
+            bail!("Route path '{}' must start with '/'", self.path);
+        }
+        if self.path.trim_end_matches('*').contains('*') {
+            bail!("Route path '{}' may only end in a wildcard", self.path);
+        }
+        if self.replicas == Some(0) {
+            bail!("Route '{}' needs at least one replica", self.path);
+        }
+        if let Some(module) = &self.module {
+            check_module_path(module).with_context(|| format!("Invalid route '{}'", self.path))?;
+        }
+        Ok(())
+    }
+}
+
+/// A path prefix and what serves it
+#[derive(Clone)]
+pub struct Route {
+    pub prefix: String,
+
+    /// Pool running the route's module; None routes to the site's own pool
+    pub pool: Option<Arc<CagePool>>,
+
+    pub strategy: Option<LoadBalancingStrategy>,
+
+    pub limits: LimitOverrides,
+}
+
+impl Route {
+    /// Whether the route serves `path`, matching whole segments only
+    pub fn matches(&self, path: &str) -> bool {
+        self.prefix == "/"
+            || path.strip_prefix(self.prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// A site's routes, longest prefix first
+#[derive(Clone, Default)]
+pub struct SiteRoutes {
+    routes: Vec<Route>,
+}
+
+impl SiteRoutes {
+    pub fn new(mut routes: Vec<Route>) -> Self {
+        routes.sort_by_key(|route| std::cmp::Reverse(route.prefix.len()));
+        Self { routes }
+    }
+
+    /// The route with the longest prefix matching `path`
+    pub fn matching(&self, path: &str) -> Option<&Route> {
+        self.routes.iter().find(|route| route.matches(path))
+    }
+
+    /// Pools of routes with their own module
+    pub fn pools(&self) -> impl Iterator<Item = (&str, &Arc<CagePool>)> {
+        self.routes.iter().filter_map(|route| Some((route.prefix.as_str(), route.pool.as_ref()?)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+/// Key the supervisor watches a route's pool under
+pub fn pool_key(site_id: &str, prefix: &str) -> String {
+    format!("{}{}", site_id, prefix)
+}
+
+/// Check a site's routes, refusing two that claim the same prefix
+pub fn validate_routes(routes: &[RouteConfig]) -> Result<()> {
+    let mut prefixes = std::collections::HashSet::new();
+    for route in routes {
+        route.validate()?;
+        if !prefixes.insert(route.prefix()) {
+            bail!("Route prefix '{}' is listed twice", route.prefix());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(path: &str) -> Route {
+        let config = RouteConfig {
+            path: path.to_string(),
+            module: None,
+            replicas: None,
+            strategy: None,
+            limits: LimitOverrides::default(),
+        };
+        Route { prefix: config.prefix().to_string(), pool: None, strategy: None, limits: config.limits }
+    }
+
+    #[test]
+    fn test_longest_prefix() {
+        let routes = SiteRoutes::new(vec![route("/*"), route("/api/*"), route("/api/v2")]);
+        let prefix = |path: &str| routes.matching(path).map(|route| route.prefix.clone());
+
+        assert_eq!(prefix("/api/v2/users").as_deref(), Some("/api/v2"));
+        assert_eq!(prefix("/api/v2").as_deref(), Some("/api/v2"));
+        assert_eq!(prefix("/api/v20").as_deref(), Some("/api"));
+        assert_eq!(prefix("/api").as_deref(), Some("/api"));
+        assert_eq!(prefix("/apis").as_deref(), Some("/"));
+        assert_eq!(SiteRoutes::new(vec![route("/api")]).matching("/about").map(|r| r.prefix.clone()), None);
+    }
+
+    #[test]
+    fn test_validate_routes() {
+        let config = |path: &str| toml::from_str::<RouteConfig>(&format!("path = \"{}\"", path)).unwrap();
+        assert!(validate_routes(&[config("/api"), config("/*")]).is_ok());
+        assert!(validate_routes(&[config("/api"), config("/api/*")]).is_err());
+        assert!(validate_routes(&[config("api")]).is_err());
+        assert!(validate_routes(&[config("/a*/b")]).is_err());
+
+        let route: RouteConfig = toml::from_str(
+            "path = \"/api\"\nmodule = \"../api.wasm\"\nstrategy = \"least-connected\"\n[limits]\nmax_body_bytes = 1024\n",
+        ).unwrap();
+        assert_eq!(route.strategy, Some(LoadBalancingStrategy::LeastConnected));
+        assert_eq!(route.limits.max_body_bytes, Some(1024));
+        assert!(route.validate().is_err());
+    }
+}
@@ -0,0 +1,272 @@
+// Streaming Responses
+// Chunked Cage output for Server-Sent Events and long polling, with backpressure
+
+use crate::state::StreamAccounting;
+use anyhow::{Result, bail};
+use bytes::BytesMut;
+use hyper::body::{Body, Bytes, Frame};
+use hyper::header::{self, HeaderValue};
+use hyper::Request;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::Sleep;
+use tracing::debug;
+
+/// `[streaming]`: when requests get a streamed response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamingConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// `(request_len: i32) -> i32` export that writes the response through `pear_stream`
+    #[serde(default = "default_export")]
+    pub export: String,
+
+    /// Path prefixes always streamed (e.g. long-poll endpoints)
+    /// Requests accepting `text/event-stream` are streamed on any path.
+    #[serde(default)]
+    pub paths: Vec<String>,
+
+    /// Close a stream after this many seconds without a chunk
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout_secs: u64,
+
+    /// Flushed chunks held for a slow client before the Cage waits
+    #[serde(default = "default_max_buffered_chunks")]
+    pub max_buffered_chunks: usize,
+
+    #[serde(default = "default_max_streams_per_connection")]
+    pub max_streams_per_connection: usize,
+}
+
+fn default_true() -> bool { true }
+fn default_export() -> String { "handle_stream".to_string() }
+fn default_idle_timeout() -> u64 { 60 }
+fn default_max_buffered_chunks() -> usize { 16 }
+fn default_max_streams_per_connection() -> usize { 8 }
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            export: default_export(),
+            paths: Vec::new(),
+            idle_timeout_secs: default_idle_timeout(),
+            max_buffered_chunks: default_max_buffered_chunks(),
+            max_streams_per_connection: default_max_streams_per_connection(),
+        }
+    }
+}
+
+impl StreamingConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.export.is_empty() {
+            bail!("export must not be empty");
+        }
+        if self.idle_timeout_secs == 0 {
+            bail!("idle_timeout_secs must be at least 1");
+        }
+        if self.max_buffered_chunks == 0 || self.max_streams_per_connection == 0 {
+            bail!("max_buffered_chunks and max_streams_per_connection must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Whether a request should get a streamed response
+    pub fn wants_stream<B>(&self, req: &Request<B>) -> bool {
+        self.enabled && (is_event_stream(req) || self.paths.iter().any(|prefix| req.uri().path().starts_with(prefix.as_str())))
+    }
+}
+
+/// Whether the client asked for Server-Sent Events
+pub fn is_event_stream<B>(req: &Request<B>) -> bool {
+    req.headers().get_all(header::ACCEPT).iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/event-stream"))
+}
+
+/// Content type of a streamed response
+pub fn content_type<B>(req: &Request<B>) -> HeaderValue {
+    if is_event_stream(req) {
+        HeaderValue::from_static("text/event-stream")
+    } else {
+        HeaderValue::from_static("application/octet-stream")
+    }
+}
+
+/// Create a connected sink (Cage side) and body (client side)
+pub fn channel(config: &StreamingConfig, accounting: Arc<StreamAccounting>) -> (StreamSink, StreamBody) {
+    let (tx, rx) = mpsc::channel(config.max_buffered_chunks);
+    accounting.open_streams.fetch_add(1, Ordering::Relaxed);
+
+    let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+    let sink = StreamSink {
+        tx,
+        pending: Mutex::new(BytesMut::new()),
+        accounting: accounting.clone(),
+    };
+    let body = StreamBody {
+        rx,
+        idle: Box::pin(tokio::time::sleep(idle_timeout)),
+        idle_timeout,
+        accounting,
+    };
+    (sink, body)
+}
+
+/// Where a Cage writes its streamed response
+/// `write` buffers; `flush` hands the buffer to the client, waiting while it is full.
+pub struct StreamSink {
+    tx: mpsc::Sender<Bytes>,
+    pending: Mutex<BytesMut>,
+    accounting: Arc<StreamAccounting>,
+}
+
+impl StreamSink {
+    /// Append to the current chunk
+    pub fn write(&self, data: &[u8]) {
+        self.pending.lock().extend_from_slice(data);
+    }
+
+    /// Send the current chunk, returning false once the client is gone
+    /// Blocks while the client is behind, so call it from a blocking task.
+    pub fn flush(&self) -> bool {
+        let chunk = self.pending.lock().split().freeze();
+        if chunk.is_empty() {
+            return !self.tx.is_closed();
+        }
+
+        let len = chunk.len() as u64;
+        self.accounting.buffered_bytes.fetch_add(len, Ordering::Relaxed);
+        let sent = match self.tx.try_send(chunk) {
+            Ok(()) => true,
+            Err(TrySendError::Full(chunk)) => {
+                self.accounting.stalls.fetch_add(1, Ordering::Relaxed);
+                self.tx.blocking_send(chunk).is_ok()
+            }
+            Err(TrySendError::Closed(_)) => false,
+        };
+        if !sent {
+            self.accounting.buffered_bytes.fetch_sub(len, Ordering::Relaxed);
+        }
+        sent
+    }
+}
+
+/// Response body fed by a StreamSink
+/// Ends when the sink is dropped, or after `idle_timeout` without a chunk.
+pub struct StreamBody {
+    rx: mpsc::Receiver<Bytes>,
+    idle: Pin<Box<Sleep>>,
+    idle_timeout: Duration,
+    accounting: Arc<StreamAccounting>,
+}
+
+impl Body for StreamBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        match self.rx.poll_recv(cx) {
+            Poll::Ready(Some(chunk)) => {
+                let len = chunk.len() as u64;
+                self.accounting.buffered_bytes.fetch_sub(len, Ordering::Relaxed);
+                self.accounting.sent_bytes.fetch_add(len, Ordering::Relaxed);
+                let deadline = tokio::time::Instant::now() + self.idle_timeout;
+                self.idle.as_mut().reset(deadline);
+                Poll::Ready(Some(Ok(Frame::data(chunk))))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => match self.idle.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    debug!(idle_secs = self.idle_timeout.as_secs(), "Closing idle stream");
+                    self.rx.close();
+                    Poll::Ready(None)
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl Drop for StreamBody {
+    fn drop(&mut self) {
+        // Chunks the client never took no longer count as buffered
+        self.rx.close();
+        while let Ok(chunk) = self.rx.try_recv() {
+            self.accounting.buffered_bytes.fetch_sub(chunk.len() as u64, Ordering::Relaxed);
+        }
+        self.accounting.open_streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    fn config(max_buffered_chunks: usize) -> StreamingConfig {
+        StreamingConfig { max_buffered_chunks, idle_timeout_secs: 1, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_stream_chunks_and_backpressure() {
+        let accounting = Arc::new(StreamAccounting::default());
+        let (sink, mut body) = channel(&config(1), accounting.clone());
+
+        let producer = tokio::task::spawn_blocking(move || {
+            for event in ["data: one\n\n", "data: two\n\n", "data: three\n\n"] {
+                sink.write(event.as_bytes());
+                assert!(sink.flush());
+            }
+        });
+
+        let mut received = Vec::new();
+        while let Some(frame) = body.frame().await {
+            received.extend_from_slice(&frame.unwrap().into_data().unwrap());
+        }
+        producer.await.unwrap();
+
+        assert_eq!(received, b"data: one\n\ndata: two\n\ndata: three\n\n");
+        assert_eq!(accounting.sent_bytes.load(Ordering::Relaxed), received.len() as u64);
+        assert_eq!(accounting.buffered_bytes.load(Ordering::Relaxed), 0);
+        drop(body);
+        assert_eq!(accounting.open_streams.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_idle_stream_is_closed() {
+        let accounting = Arc::new(StreamAccounting::default());
+        let (sink, body) = channel(&config(4), accounting.clone());
+
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert!(collected.is_empty());
+
+        // The Cage learns the client is gone on its next flush
+        let sink = tokio::task::spawn_blocking(move || {
+            sink.write(b"late");
+            sink.flush()
+        });
+        assert!(!sink.await.unwrap());
+        assert_eq!(accounting.open_streams.load(Ordering::Relaxed), 0);
+        assert_eq!(accounting.buffered_bytes.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_wants_stream() {
+        let config = StreamingConfig { paths: vec!["/poll/".to_string()], ..Default::default() };
+        let sse = Request::get("/events").header(header::ACCEPT, "text/event-stream").body(()).unwrap();
+        assert!(config.wants_stream(&sse));
+        assert_eq!(content_type(&sse), "text/event-stream");
+        assert!(config.wants_stream(&Request::get("/poll/jobs").body(()).unwrap()));
+        assert!(!config.wants_stream(&Request::get("/index.html").body(()).unwrap()));
+    }
+}
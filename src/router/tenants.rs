@@ -0,0 +1,69 @@
+// Tenant Status Cache
+// Lets the Router check on every request whether a site's tenant may be served, without a tenant lookup each time
+
+use crate::tenancy::{TenantManager, TenantStatus};
+use dashmap::DashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a tenant's status is trusted before it is looked up again
+pub const STATUS_TTL: Duration = Duration::from_secs(5);
+
+/// Recently looked-up tenant statuses
+pub struct TenantStatusCache {
+    tenants: Arc<TenantManager>,
+    ttl: Duration,
+    statuses: DashMap<Uuid, (TenantStatus, Instant)>,
+}
+
+impl TenantStatusCache {
+    pub fn new(tenants: Arc<TenantManager>, ttl: Duration) -> Self {
+        Self { tenants, ttl, statuses: DashMap::new() }
+    }
+
+    /// The tenant's status, at most `ttl` old; a tenant that no longer exists counts as deleted
+    pub fn status(&self, tenant_id: Uuid) -> TenantStatus {
+        if let Some(cached) = self.statuses.get(&tenant_id) {
+            let (status, checked_at) = *cached;
+            if checked_at.elapsed() < self.ttl {
+                return status;
+            }
+        }
+        let status = self.tenants.get_tenant(tenant_id).map_or(TenantStatus::Deleted, |tenant| tenant.status);
+        self.statuses.insert(tenant_id, (status, Instant::now()));
+        status
+    }
+
+    /// Tenant owning a site
+    pub fn owner(&self, site_id: &str) -> Option<Uuid> {
+        self.tenants.find_site_tenant(site_id)
+    }
+
+    /// Look the tenant up again on its next request
+    pub fn invalidate(&self, tenant_id: Uuid) {
+        self.statuses.remove(&tenant_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenancy::ResourceQuota;
+
+    #[test]
+    fn test_cached_status() {
+        let tenants = Arc::new(TenantManager::new());
+        let tenant_id = tenants.create_tenant("acme".to_string(), "ops@acme.test".to_string(), ResourceQuota::default()).unwrap();
+        let cache = TenantStatusCache::new(tenants.clone(), Duration::from_secs(60));
+        assert_eq!(cache.status(tenant_id), TenantStatus::Active);
+
+        // Changes show once the entry expires or is invalidated
+        tenants.suspend_tenant(tenant_id).unwrap();
+        assert_eq!(cache.status(tenant_id), TenantStatus::Active);
+        cache.invalidate(tenant_id);
+        assert_eq!(cache.status(tenant_id), TenantStatus::Suspended);
+
+        assert_eq!(cache.status(Uuid::new_v4()), TenantStatus::Deleted);
+    }
+}
@@ -0,0 +1,553 @@
+// Upstream Proxy
+// Forwards a site's requests to an HTTP/1.1 or HTTP/2 backend, streaming bodies and trailers
+
+use super::{ClientAddr, ProxiedBy, headers::SecureConnection, limits::BoxError};
+use anyhow::{Context, Result, bail};
+use hickory_resolver::TokioAsyncResolver;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use http_body_util::BodyExt;
+use http_body_util::combinators::UnsyncBoxBody;
+use hyper::body::{Body, Bytes, Incoming};
+use hyper::client::conn::{http1, http2};
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response, Uri};
+use parking_lot::RwLock;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+type ProxyBody = UnsyncBoxBody<Bytes, BoxError>;
+
+/// Protocol spoken to the backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamProtocol {
+    #[default]
+    Http1,
+    /// HTTP/2 with prior knowledge (h2c), required for gRPC
+    Http2,
+}
+
+/// How a backend's addresses are found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamDiscovery {
+    /// Connect to the URL's host, resolved by the system on every connection
+    #[default]
+    Static,
+    /// Re-resolve the host as its records expire, using its SRV records where present
+    Dns,
+}
+
+/// Backend serving a site instead of Cages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamTarget {
+    /// Plain-HTTP base URL, e.g. "http://127.0.0.1:50051"
+    pub url: String,
+
+    #[serde(default)]
+    pub protocol: UpstreamProtocol,
+
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout_ms: u64,
+
+    #[serde(default)]
+    pub discovery: UpstreamDiscovery,
+
+    /// Bounds on how long resolved records are used, whatever their TTL
+    #[serde(default = "default_min_refresh")]
+    pub min_refresh_secs: u64,
+
+    #[serde(default = "default_max_refresh")]
+    pub max_refresh_secs: u64,
+
+    /// How long requests on an address that left DNS may finish before its connections close
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout_secs: u64,
+}
+
+/// `[upstream]`: sites proxied to a backend, keyed by site ID
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpstreamConfig {
+    #[serde(default)]
+    pub sites: HashMap<String, UpstreamTarget>,
+}
+
+fn default_connect_timeout() -> u64 { 3000 }
+fn default_min_refresh() -> u64 { 5 }
+fn default_max_refresh() -> u64 { 300 }
+fn default_drain_timeout() -> u64 { 30 }
+
+/// Headers that describe a single hop and are never forwarded
+const HOP_BY_HOP: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// One address of a backend
+/// HTTP/2 backends share one multiplexed connection per address; HTTP/1.1 connects per request.
+struct Endpoint {
+    /// "ip:port" found in DNS, or the URL's authority for static backends
+    addr: String,
+    h2: tokio::sync::Mutex<Option<http2::SendRequest<ProxyBody>>>,
+
+    /// Cancelled once the address disappears from DNS
+    draining: CancellationToken,
+}
+
+impl Endpoint {
+    fn new(addr: String) -> Arc<Self> {
+        Arc::new(Self {
+            addr,
+            h2: tokio::sync::Mutex::new(None),
+            draining: CancellationToken::new(),
+        })
+    }
+}
+
+/// A connected backend
+pub struct Upstream {
+    authority: String,
+    host: String,
+    port: u16,
+    protocol: UpstreamProtocol,
+    connect_timeout: Duration,
+    discovery: UpstreamDiscovery,
+    min_refresh: Duration,
+    max_refresh: Duration,
+    drain_timeout: Duration,
+
+    /// Addresses in use with their weights
+    endpoints: RwLock<Vec<(Arc<Endpoint>, u32)>>,
+}
+
+impl Upstream {
+    fn new(target: &UpstreamTarget) -> Result<Self> {
+        let uri: Uri = target.url.parse().with_context(|| format!("Invalid upstream URL {}", target.url))?;
+        if uri.scheme_str() != Some("http") {
+            bail!("Upstream URL {} must use http://", target.url);
+        }
+        if !matches!(uri.path(), "" | "/") || uri.query().is_some() {
+            bail!("Upstream URL {} must not have a path", target.url);
+        }
+        let Some(authority) = uri.authority() else {
+            bail!("Upstream URL {} has no host", target.url);
+        };
+        if target.discovery == UpstreamDiscovery::Dns
+            && (target.min_refresh_secs == 0 || target.min_refresh_secs > target.max_refresh_secs)
+        {
+            bail!("min_refresh_secs must be at least 1 and at most max_refresh_secs");
+        }
+
+        let port = authority.port_u16().unwrap_or(80);
+        let authority = format!("{}:{}", authority.host(), port);
+        // DNS backends have no address until the first lookup
+        let endpoints = match target.discovery {
+            UpstreamDiscovery::Static => vec![(Endpoint::new(authority.clone()), 1)],
+            UpstreamDiscovery::Dns => Vec::new(),
+        };
+        Ok(Self {
+            host: uri.host().unwrap_or_default().trim_matches(['[', ']']).to_string(),
+            authority,
+            port,
+            protocol: target.protocol,
+            connect_timeout: Duration::from_millis(target.connect_timeout_ms),
+            discovery: target.discovery,
+            min_refresh: Duration::from_secs(target.min_refresh_secs),
+            max_refresh: Duration::from_secs(target.max_refresh_secs),
+            drain_timeout: Duration::from_secs(target.drain_timeout_secs),
+            endpoints: RwLock::new(endpoints),
+        })
+    }
+
+    /// Forward a request, returning the backend's response with its body and trailers streamed
+    pub async fn forward<B>(&self, req: Request<B>) -> Result<Response<Incoming>>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let (mut parts, body) = req.into_parts();
+        let body: ProxyBody = body.map_err(Into::into).boxed_unsync();
+        let path = parts.uri.path_and_query().map_or("/", |pq| pq.as_str());
+
+        // Browsers' HTTP/2 requests carry the host in :authority only
+        if !parts.headers.contains_key(header::HOST) {
+            let host = parts.uri.authority().map_or(self.authority.as_str(), |a| a.as_str());
+            if let Ok(host) = HeaderValue::from_str(host) {
+                parts.headers.insert(header::HOST, host);
+            }
+        }
+
+        strip_hop_by_hop(&mut parts.headers);
+        // The hop appended is whoever connected to us, which is the proxy when one named the client
+        let peer = parts.extensions.get::<ProxiedBy>().map(|ProxiedBy(addr)| addr)
+            .or_else(|| parts.extensions.get::<ClientAddr>().map(|ClientAddr(addr)| addr));
+        if let Some(addr) = peer {
+            let forwarded = match parts.headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+                Some(chain) => format!("{}, {}", chain, addr.ip()),
+                None => addr.ip().to_string(),
+            };
+            if let Ok(forwarded) = HeaderValue::from_str(&forwarded) {
+                parts.headers.insert("x-forwarded-for", forwarded);
+            }
+        }
+        let proto = if parts.extensions.get::<SecureConnection>().is_some() { "https" } else { "http" };
+        parts.headers.insert("x-forwarded-proto", HeaderValue::from_static(proto));
+
+        let endpoint = self.pick()?;
+        let mut response = match self.protocol {
+            UpstreamProtocol::Http1 => {
+                parts.uri = path.parse()?;
+                let request = Request::from_parts(parts, body);
+                let (mut sender, connection) = http1::handshake(self.connect(&endpoint).await?).await?;
+                self.spawn_connection(&endpoint, connection);
+                sender.send_request(request).await?
+            }
+            UpstreamProtocol::Http2 => {
+                // HTTP/2 wants the authority as :authority, not a Host header
+                parts.uri = format!("http://{}{}", self.authority, path).parse()?;
+                parts.headers.remove(header::HOST);
+                let request = Request::from_parts(parts, body);
+                let mut sender = self.h2_sender(&endpoint).await?;
+                sender.ready().await?;
+                sender.send_request(request).await?
+            }
+        };
+
+        strip_hop_by_hop(response.headers_mut());
+        Ok(response)
+    }
+
+    /// Shared HTTP/2 connection to an address, reconnecting once it has closed
+    async fn h2_sender(&self, endpoint: &Arc<Endpoint>) -> Result<http2::SendRequest<ProxyBody>> {
+        let mut cached = endpoint.h2.lock().await;
+        if let Some(sender) = cached.as_ref().filter(|sender| !sender.is_closed()) {
+            return Ok(sender.clone());
+        }
+
+        let stream = self.connect(endpoint).await?;
+        let (sender, connection) = http2::handshake(hyper_util::rt::TokioExecutor::new(), stream).await?;
+        self.spawn_connection(endpoint, connection);
+
+        *cached = Some(sender.clone());
+        Ok(sender)
+    }
+
+    async fn connect(&self, endpoint: &Endpoint) -> Result<hyper_util::rt::TokioIo<TcpStream>> {
+        let stream = tokio::time::timeout(self.connect_timeout, TcpStream::connect(&endpoint.addr)).await
+            .with_context(|| format!("Timed out connecting to upstream {}", endpoint.addr))?
+            .with_context(|| format!("Failed to connect to upstream {}", endpoint.addr))?;
+        stream.set_nodelay(true)?;
+        Ok(hyper_util::rt::TokioIo::new(stream))
+    }
+
+    /// Drive a backend connection until it closes, or `drain_timeout` after its address left DNS
+    fn spawn_connection<F>(&self, endpoint: &Endpoint, connection: F)
+    where
+        F: Future<Output = hyper::Result<()>> + Send + 'static,
+    {
+        let addr = endpoint.addr.clone();
+        let draining = endpoint.draining.clone();
+        let drain_timeout = self.drain_timeout;
+        tokio::spawn(async move {
+            let drained = async {
+                draining.cancelled().await;
+                tokio::time::sleep(drain_timeout).await;
+            };
+            tokio::select! {
+                result = connection => {
+                    if let Err(e) = result {
+                        debug!(upstream = %addr, error = %e, "Upstream connection closed");
+                    }
+                }
+                _ = drained => debug!(upstream = %addr, "Closed upstream connection after draining"),
+            }
+        });
+    }
+
+    /// Address for the next request, chosen at random by weight
+    fn pick(&self) -> Result<Arc<Endpoint>> {
+        let endpoints = self.endpoints.read();
+        let total: u64 = endpoints.iter().map(|(_, weight)| u64::from(*weight)).sum();
+        if total == 0 {
+            bail!("Upstream {} has no resolved addresses", self.authority);
+        }
+        let roll = rand::thread_rng().gen_range(0..total);
+        Ok(weighted(&endpoints, roll).clone())
+    }
+
+    /// Keep the addresses found in DNS up to date until the node stops
+    async fn discover(self: Arc<Self>) {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .unwrap_or_else(|_| TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()));
+        loop {
+            let delay = match self.resolve(&resolver).await {
+                Ok((addrs, valid_until)) => {
+                    self.update(addrs);
+                    valid_until.saturating_duration_since(Instant::now()).clamp(self.min_refresh, self.max_refresh)
+                }
+                // Lookups failing leave the last addresses in use
+                Err(e) => {
+                    warn!(upstream = %self.host, error = %e, "Upstream DNS lookup failed");
+                    self.min_refresh
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Look up the backend's addresses with their weights and the time they expire
+    /// SRV records of the best priority that resolves are used, else the host's A and AAAA records.
+    async fn resolve(&self, resolver: &TokioAsyncResolver) -> Result<(Vec<(SocketAddr, u32)>, Instant)> {
+        if let Ok(srv) = resolver.srv_lookup(self.host.as_str()).await {
+            let mut priorities: Vec<u16> = srv.iter().map(|record| record.priority()).collect();
+            priorities.sort_unstable();
+            priorities.dedup();
+            let mut valid_until = srv.as_lookup().valid_until();
+            for priority in priorities {
+                let mut addrs = Vec::new();
+                for record in srv.iter().filter(|record| record.priority() == priority) {
+                    let target = record.target().to_utf8();
+                    match resolver.lookup_ip(target.as_str()).await {
+                        Ok(ips) => {
+                            valid_until = valid_until.min(ips.valid_until());
+                            // Weight 0 targets are still picked, rarely
+                            addrs.extend(ips.iter().map(|ip| (SocketAddr::new(ip, record.port()), u32::from(record.weight()).max(1))));
+                        }
+                        Err(e) => debug!(upstream = %self.host, target = %target, error = %e, "SRV target did not resolve"),
+                    }
+                }
+                if !addrs.is_empty() {
+                    return Ok((addrs, valid_until));
+                }
+            }
+        }
+
+        let ips = resolver.lookup_ip(self.host.as_str()).await
+            .with_context(|| format!("No addresses for {}", self.host))?;
+        let addrs: Vec<_> = ips.iter().map(|ip| (SocketAddr::new(ip, self.port), 1)).collect();
+        if addrs.is_empty() {
+            bail!("No addresses for {}", self.host);
+        }
+        Ok((addrs, ips.valid_until()))
+    }
+
+    /// Use the addresses just resolved, draining those that disappeared
+    /// Addresses still present keep their connections.
+    fn update(&self, addrs: Vec<(SocketAddr, u32)>) {
+        let mut weights: HashMap<String, u32> = HashMap::new();
+        for (addr, weight) in addrs {
+            *weights.entry(addr.to_string()).or_default() += weight;
+        }
+
+        let mut endpoints = self.endpoints.write();
+        let mut removed = 0;
+        for (endpoint, _) in endpoints.iter() {
+            if !weights.contains_key(&endpoint.addr) {
+                endpoint.draining.cancel();
+                removed += 1;
+                info!(upstream = %self.host, addr = %endpoint.addr, "Upstream address gone; draining its connections");
+            }
+        }
+        let added = weights.keys().filter(|addr| !endpoints.iter().any(|(endpoint, _)| &endpoint.addr == *addr)).count();
+
+        let next = weights.into_iter()
+            .map(|(addr, weight)| {
+                let endpoint = endpoints.iter()
+                    .find(|(endpoint, _)| endpoint.addr == addr)
+                    .map_or_else(|| Endpoint::new(addr), |(endpoint, _)| endpoint.clone());
+                (endpoint, weight)
+            })
+            .collect();
+        *endpoints = next;
+        if added > 0 || removed > 0 {
+            info!(upstream = %self.host, addresses = endpoints.len(), added, removed, "Upstream addresses changed");
+        }
+    }
+}
+
+/// The endpoint a roll in `0..total weight` lands on
+fn weighted(endpoints: &[(Arc<Endpoint>, u32)], mut roll: u64) -> &Arc<Endpoint> {
+    for (endpoint, weight) in endpoints {
+        if roll < u64::from(*weight) {
+            return endpoint;
+        }
+        roll -= u64::from(*weight);
+    }
+    &endpoints[endpoints.len() - 1].0
+}
+
+/// Remove hop-by-hop headers, including any named in `Connection`
+/// `TE: trailers` survives, since gRPC requires it end to end.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<HeaderName> = headers.get_all(header::CONNECTION).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in named {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(name);
+    }
+
+    let trailers_only = headers.get_all(header::TE).iter()
+        .all(|value| value.as_bytes().eq_ignore_ascii_case(b"trailers"));
+    if !trailers_only {
+        headers.remove(header::TE);
+    }
+}
+
+/// Backends for every proxied site
+pub struct UpstreamProxy {
+    sites: HashMap<String, Arc<Upstream>>,
+}
+
+impl UpstreamProxy {
+    /// Check and load the configured backends
+    pub fn new(config: &UpstreamConfig) -> Result<Self> {
+        let sites = config.sites.iter()
+            .map(|(site_id, target)| {
+                let upstream = Upstream::new(target)
+                    .with_context(|| format!("Invalid upstream for site {}", site_id))?;
+                Ok((site_id.clone(), Arc::new(upstream)))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        if !sites.is_empty() {
+            info!(sites = sites.len(), "Upstream proxy sites loaded");
+        }
+        Ok(Self { sites })
+    }
+
+    /// Start resolving the backends that use DNS discovery
+    pub fn start(&self) {
+        for upstream in self.sites.values() {
+            if upstream.discovery == UpstreamDiscovery::Dns {
+                tokio::spawn(upstream.clone().discover());
+            }
+        }
+    }
+
+    /// Backend serving a site, if it is proxied
+    pub fn get(&self, site_id: &str) -> Option<Arc<Upstream>> {
+        self.sites.get(site_id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{Full, StreamBody};
+    use hyper::body::Frame;
+
+    fn proxy(toml: &str) -> Result<UpstreamProxy> {
+        UpstreamProxy::new(&toml::from_str(toml).unwrap())
+    }
+
+    #[test]
+    fn test_upstream_validation() {
+        assert!(proxy("[sites.api]\nurl = \"http://127.0.0.1:50051\"\nprotocol = \"http2\"\n").is_ok());
+        assert!(proxy("[sites.api]\nurl = \"https://127.0.0.1\"\n").is_err());
+        assert!(proxy("[sites.api]\nurl = \"http://127.0.0.1/api\"\n").is_err());
+        assert!(proxy("[sites.api]\nurl = \"http://api.internal\"\ndiscovery = \"dns\"\nmin_refresh_secs = 0\n").is_err());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("close, x-trace"));
+        headers.insert("x-trace", HeaderValue::from_static("1"));
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+        strip_hop_by_hop(&mut headers);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[header::TE], "trailers");
+    }
+
+    #[tokio::test]
+    async fn test_dns_addresses_update_and_drain() {
+        let proxy = proxy("[sites.api]\nurl = \"http://_grpc._tcp.api.internal\"\ndiscovery = \"dns\"\n").unwrap();
+        let upstream = proxy.get("api").unwrap();
+        assert!(upstream.pick().is_err());
+
+        let a: SocketAddr = "10.0.0.1:50051".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:50051".parse().unwrap();
+        upstream.update(vec![(a, 3), (b, 1)]);
+        let endpoint = |addr: SocketAddr| upstream.endpoints.read().iter()
+            .find(|(endpoint, _)| endpoint.addr == addr.to_string())
+            .map(|(endpoint, _)| endpoint.clone())
+            .unwrap();
+        let (kept, gone) = (endpoint(a), endpoint(b));
+
+        // An address dropping out of DNS drains; the one kept keeps its connections
+        upstream.update(vec![(a, 1)]);
+        assert!(gone.draining.is_cancelled());
+        assert!(!kept.draining.is_cancelled());
+        assert!(Arc::ptr_eq(&upstream.pick().unwrap(), &kept));
+    }
+
+    #[test]
+    fn test_weighted_pick() {
+        let endpoints = vec![
+            (Endpoint::new("10.0.0.1:80".into()), 3),
+            (Endpoint::new("10.0.0.2:80".into()), 1),
+        ];
+        let picks: Vec<&str> = (0..4).map(|roll| weighted(&endpoints, roll).addr.as_str()).collect();
+        assert_eq!(picks, ["10.0.0.1:80", "10.0.0.1:80", "10.0.0.1:80", "10.0.0.2:80"]);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_trailers_pass_through() {
+        // HTTP/2 backend answering like a gRPC server: data, then grpc-status in trailers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = hyper::service::service_fn(|req: Request<Incoming>| async move {
+                assert_eq!(req.headers()[header::TE], "trailers");
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                let frames = vec![
+                    Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from_static(b"\0\0\0\0\x02hi"))),
+                    Ok(Frame::trailers(trailers)),
+                ];
+                Ok::<_, std::convert::Infallible>(Response::builder()
+                    .header(header::CONTENT_TYPE, "application/grpc")
+                    .body(StreamBody::new(futures::stream::iter(frames)))
+                    .unwrap())
+            });
+            hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new())
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                .await
+                .unwrap();
+        });
+
+        let proxy = proxy(&format!("[sites.api]\nurl = \"http://127.0.0.1:{}\"\nprotocol = \"http2\"\n", port)).unwrap();
+        let upstream = proxy.get("api").unwrap();
+        let request = || Request::post("/greeter.Greeter/SayHello")
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .header(header::TE, "trailers")
+            .body(Full::new(Bytes::from_static(b"\0\0\0\0\0")))
+            .unwrap();
+
+        // The second call reuses the multiplexed connection
+        for _ in 0..2 {
+            let response = upstream.forward(request()).await.unwrap();
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/grpc");
+            let body = response.into_body().collect().await.unwrap();
+            assert_eq!(body.trailers().unwrap()["grpc-status"], "0");
+            assert_eq!(body.to_bytes().as_ref(), b"\0\0\0\0\x02hi");
+        }
+    }
+}
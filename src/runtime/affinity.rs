@@ -0,0 +1,234 @@
+// CPU Affinity
+// Detects the CPU/NUMA topology and pins runtime threads to cores
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::warn;
+
+/// CPUs this process may run on, grouped by NUMA node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuTopology {
+    /// Node id to its CPUs, ascending
+    pub nodes: BTreeMap<usize, Vec<usize>>,
+}
+
+impl CpuTopology {
+    /// Read the host's NUMA layout, limited to the CPUs in this process's affinity mask
+    pub fn detect() -> Self {
+        Self::from_sysfs(Path::new("/sys/devices/system/node"), &allowed_cpus())
+    }
+
+    fn from_sysfs(node_dir: &Path, allowed: &[usize]) -> Self {
+        let mut nodes = BTreeMap::new();
+        for entry in std::fs::read_dir(node_dir).into_iter().flatten().flatten() {
+            let name = entry.file_name();
+            let Some(node) = name.to_str().and_then(|name| name.strip_prefix("node")).and_then(|id| id.parse().ok()) else {
+                continue;
+            };
+            let Ok(list) = std::fs::read_to_string(entry.path().join("cpulist")) else { continue };
+            let cpus: Vec<usize> = parse_cpu_list(list.trim()).unwrap_or_default()
+                .into_iter()
+                .filter(|cpu| allowed.contains(cpu))
+                .collect();
+            if !cpus.is_empty() {
+                nodes.insert(node, cpus);
+            }
+        }
+
+        // Without NUMA information (or outside Linux) every CPU is on one node
+        if nodes.is_empty() {
+            nodes.insert(0, allowed.to_vec());
+        }
+        Self { nodes }
+    }
+
+    pub fn cpu_count(&self) -> usize {
+        self.nodes.values().map(Vec::len).sum()
+    }
+
+    /// e.g. `2 NUMA nodes, 16 CPUs (node0: 0-7, node1: 8-15)`
+    pub fn summary(&self) -> String {
+        let nodes: Vec<String> = self.nodes.iter()
+            .map(|(node, cpus)| format!("node{}: {}", node, format_cpu_list(cpus)))
+            .collect();
+        format!("{} NUMA node(s), {} CPUs ({})", self.nodes.len(), self.cpu_count(), nodes.join(", "))
+    }
+}
+
+/// Which cores the runtime's threads run on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AffinityPlan {
+    /// One worker per core, in this order; acceptor shards follow the same order
+    pub worker_cpus: Vec<usize>,
+
+    /// Cores for the blocking pool, where guest code runs; empty = share the worker cores
+    pub wasm_cpus: Vec<usize>,
+}
+
+impl AffinityPlan {
+    /// Split the CPUs of `numa_nodes` (all nodes when empty) between workers and `wasm_cores` reserved cores
+    pub fn new(topology: &CpuTopology, numa_nodes: &[usize], wasm_cores: usize) -> Result<Self> {
+        let mut nodes: Vec<Vec<usize>> = if numa_nodes.is_empty() {
+            topology.nodes.values().cloned().collect()
+        } else {
+            numa_nodes.iter()
+                .map(|node| topology.nodes.get(node).cloned()
+                    .with_context(|| format!("NUMA node {} has no CPUs available to this process", node)))
+                .collect::<Result<_>>()?
+        };
+
+        let available: usize = nodes.iter().map(Vec::len).sum();
+        if wasm_cores >= available {
+            bail!("runtime.wasm_cores = {} leaves none of the {} available CPUs for workers", wasm_cores, available);
+        }
+
+        // Reserve from whichever node has the most cores left, so every node keeps workers
+        let mut wasm_cpus = Vec::with_capacity(wasm_cores);
+        while wasm_cpus.len() < wasm_cores {
+            let Some(cpu) = nodes.iter_mut().max_by_key(|cpus| cpus.len()).and_then(Vec::pop) else { break };
+            wasm_cpus.push(cpu);
+        }
+        wasm_cpus.sort_unstable();
+
+        Ok(Self { worker_cpus: nodes.concat(), wasm_cpus })
+    }
+
+    /// `on_thread_start` hook pinning the first `workers` threads to a core each and later ones to the Wasm cores
+    /// Tokio starts every worker as soon as the runtime is built, so the threads after them belong to the blocking pool.
+    pub fn thread_pinner(&self, workers: usize) -> impl Fn() + Send + Sync + 'static {
+        let plan = self.clone();
+        let started = AtomicUsize::new(0);
+        move || {
+            let index = started.fetch_add(1, Ordering::Relaxed);
+            let cpus = if index < workers {
+                std::slice::from_ref(&plan.worker_cpus[index % plan.worker_cpus.len()])
+            } else if plan.wasm_cpus.is_empty() {
+                &plan.worker_cpus[..]
+            } else {
+                &plan.wasm_cpus[..]
+            };
+            if let Err(e) = pin_current_thread(cpus) {
+                warn!(cpus = %format_cpu_list(cpus), "Failed to pin runtime thread: {:#}", e);
+            }
+        }
+    }
+
+    /// e.g. `workers on 0-13, Wasm on 14-15`
+    pub fn summary(&self) -> String {
+        if self.wasm_cpus.is_empty() {
+            format!("workers on {}", format_cpu_list(&self.worker_cpus))
+        } else {
+            format!("workers on {}, Wasm on {}", format_cpu_list(&self.worker_cpus), format_cpu_list(&self.wasm_cpus))
+        }
+    }
+}
+
+/// Parse a kernel CPU list such as `0-3,8,10-11`
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.split(',').map(str::trim).filter(|range| !range.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let start: usize = start.parse().with_context(|| format!("Invalid CPU list entry '{}'", range))?;
+        let end: usize = end.parse().with_context(|| format!("Invalid CPU list entry '{}'", range))?;
+        cpus.extend(start..=end);
+    }
+    Ok(cpus)
+}
+
+/// The reverse of `parse_cpu_list`
+pub fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < cpus.len() {
+        let start = cpus[i];
+        while i + 1 < cpus.len() && cpus[i + 1] == cpus[i] + 1 {
+            i += 1;
+        }
+        ranges.push(if cpus[i] == start { start.to_string() } else { format!("{}-{}", start, cpus[i]) });
+        i += 1;
+    }
+    ranges.join(",")
+}
+
+/// CPUs in this process's affinity mask
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> Vec<usize> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+        return (0..num_cpus::get()).collect();
+    }
+    (0..libc::CPU_SETSIZE as usize).filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) }).collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> Vec<usize> {
+    (0..num_cpus::get()).collect()
+}
+
+/// Restrict the calling thread to `cpus`
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("sched_setaffinity failed");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &[usize]) -> Result<()> {
+    bail!("CPU affinity is only supported on Linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_nodes() -> CpuTopology {
+        CpuTopology { nodes: BTreeMap::from([(0, vec![0, 1, 2, 3]), (1, vec![4, 5, 6, 7])]) }
+    }
+
+    #[test]
+    fn test_cpu_lists() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11").unwrap(), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(format_cpu_list(&[0, 1, 2, 3, 8, 10, 11]), "0-3,8,10-11");
+        assert!(parse_cpu_list("0-x").is_err());
+    }
+
+    #[test]
+    fn test_topology_from_sysfs() {
+        let dir = tempfile::tempdir().unwrap();
+        for (node, list) in [("node0", "0-3\n"), ("node1", "4-7\n")] {
+            std::fs::create_dir(dir.path().join(node)).unwrap();
+            std::fs::write(dir.path().join(node).join("cpulist"), list).unwrap();
+        }
+        std::fs::create_dir(dir.path().join("power")).unwrap();
+
+        let topology = CpuTopology::from_sysfs(dir.path(), &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(topology.nodes, BTreeMap::from([(0, vec![0, 1, 2, 3]), (1, vec![4, 5])]));
+        assert_eq!(topology.summary(), "2 NUMA node(s), 6 CPUs (node0: 0-3, node1: 4-5)");
+
+        let flat = CpuTopology::from_sysfs(&dir.path().join("missing"), &[0, 1]);
+        assert_eq!(flat.nodes, BTreeMap::from([(0, vec![0, 1])]));
+    }
+
+    #[test]
+    fn test_plan_reserves_wasm_cores_across_nodes() {
+        let plan = AffinityPlan::new(&two_nodes(), &[], 2).unwrap();
+        assert_eq!(plan.worker_cpus, vec![0, 1, 2, 4, 5, 6]);
+        assert_eq!(plan.wasm_cpus, vec![3, 7]);
+        assert_eq!(plan.summary(), "workers on 0-2,4-6, Wasm on 3,7");
+
+        let one_node = AffinityPlan::new(&two_nodes(), &[1], 0).unwrap();
+        assert_eq!(one_node.worker_cpus, vec![4, 5, 6, 7]);
+        assert!(one_node.wasm_cpus.is_empty());
+
+        assert!(AffinityPlan::new(&two_nodes(), &[2], 0).is_err());
+        assert!(AffinityPlan::new(&two_nodes(), &[0], 4).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+// Kubernetes Mode
+// Running as a pod: stdout logs, probe-driven shutdown inside the grace period, state on a volume
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Time kept back from the grace period for what runs after connections drain
+const SHUTDOWN_MARGIN_SECS: u64 = 2;
+
+/// `[kubernetes]`: how `pear start --kubernetes` behaves
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KubernetesConfig {
+    /// Set by `pear start --kubernetes` or PEAR_KUBERNETES, never from the file:
+    /// the mode also picks the log format, which is set up before the file is read
+    #[serde(skip)]
+    pub enabled: bool,
+
+    /// The pod's terminationGracePeriodSeconds; shutdown finishes before the kubelet kills the process
+    pub termination_grace_secs: u64,
+
+    /// After SIGTERM, keep serving while /readyz fails, so the pod leaves Service endpoints first
+    pub shutdown_delay_secs: u64,
+
+    /// Writable directory (such as a mounted volume) relative paths resolve in (empty = working directory)
+    pub work_dir: String,
+}
+
+impl Default for KubernetesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            termination_grace_secs: 30,
+            shutdown_delay_secs: 5,
+            work_dir: String::new(),
+        }
+    }
+}
+
+impl KubernetesConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.shutdown_delay_secs + SHUTDOWN_MARGIN_SECS >= self.termination_grace_secs {
+            bail!(
+                "shutdown_delay_secs ({}) leaves no time to drain within termination_grace_secs ({})",
+                self.shutdown_delay_secs,
+                self.termination_grace_secs
+            );
+        }
+        Ok(())
+    }
+
+    /// How long open connections may drain: the grace period less the delay and a margin
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.termination_grace_secs
+                .saturating_sub(self.shutdown_delay_secs)
+                .saturating_sub(SHUTDOWN_MARGIN_SECS),
+        )
+    }
+
+    /// Move into `work_dir`, so storage, logs and state files land on the volume
+    pub fn enter_work_dir(&self) -> Result<()> {
+        if self.work_dir.is_empty() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.work_dir)
+            .with_context(|| format!("Failed to create {}", self.work_dir))?;
+        std::env::set_current_dir(&self.work_dir)
+            .with_context(|| format!("Failed to enter {}", self.work_dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain_fits_grace_period() {
+        let config = KubernetesConfig::default();
+        config.validate().unwrap();
+        assert_eq!(config.drain_timeout(), Duration::from_secs(23));
+
+        let config = KubernetesConfig { termination_grace_secs: 10, shutdown_delay_secs: 8, ..Default::default() };
+        assert!(config.validate().is_err());
+    }
+}
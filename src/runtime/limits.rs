@@ -8,10 +8,10 @@ use tracing::{info, warn};
 use libc::{getrlimit, setrlimit, RLIMIT_NOFILE, rlimit};
 
 /// Target file descriptor limit for high concurrency (1M connections)
-const TARGET_FD_LIMIT: u64 = 1_048_576;
+pub const TARGET_FD_LIMIT: u64 = 1_048_576;
 
 /// Minimum acceptable file descriptor limit
-const MIN_FD_LIMIT: u64 = 65_536;
+pub const MIN_FD_LIMIT: u64 = 65_536;
 
 /// Set file descriptor limit to support millions of concurrent connections
 #[cfg(unix)]
@@ -73,6 +73,21 @@ pub fn set_file_descriptor_limit() -> Result<()> {
     Ok(())
 }
 
+/// Current soft and hard file descriptor limits
+#[cfg(unix)]
+pub fn file_descriptor_limits() -> Option<(u64, u64)> {
+    let mut limit = rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { getrlimit(RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    Some((limit.rlim_cur as u64, limit.rlim_max as u64))
+}
+
+#[cfg(not(unix))]
+pub fn file_descriptor_limits() -> Option<(u64, u64)> {
+    None
+}
+
 /// Log system information for diagnostics
 pub fn log_system_info() {
     let num_cpus = num_cpus::get();
@@ -1,3 +1,21 @@
+// Runtime configuration module
+// Linux resource limits, privilege management, and Tokio runtime settings
+
+pub mod affinity;
+pub mod kubernetes;
+pub mod limits;
+pub mod polyglot;
+pub mod privileges;
+
+use affinity::{AffinityPlan, CpuTopology};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::sync::oneshot;
+use tracing::info;
+
+/// Configure process resource limits before accepting traffic
+pub fn configure_limits() -> Result<()> {
     limits::set_file_descriptor_limit()?;
     limits::log_system_info();
     Ok(())
@@ -9,43 +27,178 @@ pub fn worker_thread_count() -> usize {
     num_cpus::get()
 }
 
-/// Configuration for Tokio runtime
-/// Note: When using #[tokio::main], these settings are applied via macro attributes
-/// This struct documents our runtime requirements
+/// Configuration for the Tokio runtime, from the `[runtime]` section of pear.toml
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuntimeConfig {
-    /// Number of worker threads (defaults to CPU count)
+    /// Number of worker threads (0 = one per CPU core)
+    #[serde(default)]
     pub worker_threads: usize,
-    
+
     /// Thread stack size (8MB for deep async call stacks)
+    #[serde(default = "default_thread_stack_size")]
     pub thread_stack_size: usize,
-    
-    /// Enable I/O driver
-    pub enable_io: bool,
-    
-    /// Enable time driver
-    pub enable_time: bool,
+
+    /// Name given to worker and blocking threads
+    #[serde(default = "default_thread_name")]
+    pub thread_name: String,
+
+    /// Cap on the blocking pool used by spawn_blocking and file I/O (0 = Tokio's default)
+    #[serde(default)]
+    pub max_blocking_threads: usize,
+
+    /// Run the dashboard on its own single-thread runtime so it stays reachable when workers are saturated
+    #[serde(default)]
+    pub control_plane: bool,
+
+    /// Pin each worker thread to its own core and steer acceptor shards to the same cores
+    #[serde(default)]
+    pub cpu_affinity: bool,
+
+    /// Cores kept free of workers for the blocking pool that runs guest code (requires cpu_affinity)
+    #[serde(default)]
+    pub wasm_cores: usize,
+
+    /// NUMA nodes whose CPUs the runtime may use (empty = all)
+    #[serde(default)]
+    pub numa_nodes: Vec<usize>,
+}
+
+fn default_thread_stack_size() -> usize {
+    8 * 1024 * 1024 // 8MB
+}
+
+fn default_thread_name() -> String {
+    "pear-worker".to_string()
 }
 
 impl Default for RuntimeConfig {
     fn default() -> Self {
         Self {
-            worker_threads: worker_thread_count(),
-            thread_stack_size: 8 * 1024 * 1024, // 8MB
-            enable_io: true,
-            enable_time: true,
+            worker_threads: 0,
+            thread_stack_size: default_thread_stack_size(),
+            thread_name: default_thread_name(),
+            max_blocking_threads: 0,
+            control_plane: false,
+            cpu_affinity: false,
+            wasm_cores: 0,
+            numa_nodes: Vec::new(),
         }
     }
 }
 
 impl RuntimeConfig {
-    pub fn log_info(&self) {
+    /// Worker threads the runtime will actually start
+    /// With CPU affinity, 0 means one per core left after the Wasm reservation.
+    pub fn effective_worker_threads(&self, plan: Option<&AffinityPlan>) -> usize {
+        match (self.worker_threads, plan) {
+            (0, Some(plan)) => plan.worker_cpus.len(),
+            (0, None) => worker_thread_count(),
+            (threads, _) => threads,
+        }
+    }
+
+    /// Core assignment for this host, when CPU affinity is enabled
+    pub fn affinity_plan(&self) -> Result<Option<AffinityPlan>> {
+        if !self.cpu_affinity {
+            return Ok(None);
+        }
+        AffinityPlan::new(&CpuTopology::detect(), &self.numa_nodes, self.wasm_cores).map(Some)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.thread_stack_size < 64 * 1024 {
+            bail!("runtime.thread_stack_size must be at least 64KB");
+        }
+        if self.thread_name.is_empty() {
+            bail!("runtime.thread_name cannot be empty");
+        }
+        if !self.cpu_affinity && (self.wasm_cores > 0 || !self.numa_nodes.is_empty()) {
+            bail!("runtime.wasm_cores and runtime.numa_nodes require cpu_affinity = true");
+        }
+        Ok(())
+    }
+
+    /// Build the multi-threaded runtime that serves traffic
+    pub fn build(&self) -> Result<Runtime> {
+        let plan = self.affinity_plan()?;
+        let workers = self.effective_worker_threads(plan.as_ref());
+        let mut builder = Builder::new_multi_thread();
+        builder
+            .enable_all()
+            .worker_threads(workers)
+            .thread_stack_size(self.thread_stack_size)
+            .thread_name(self.thread_name.clone());
+        if self.max_blocking_threads > 0 {
+            builder.max_blocking_threads(self.max_blocking_threads);
+        }
+        if let Some(plan) = &plan {
+            builder.on_thread_start(plan.thread_pinner(workers));
+        }
         info!(
-            worker_threads = self.worker_threads,
+            worker_threads = workers,
             thread_stack_size = self.thread_stack_size,
+            max_blocking_threads = self.max_blocking_threads,
+            control_plane = self.control_plane,
+            affinity = %plan.as_ref().map_or_else(|| "off".to_string(), AffinityPlan::summary),
             "Tokio runtime configuration"
         );
+        builder.build().context("Failed to build Tokio runtime")
+    }
+}
+
+/// A single-thread runtime on its own OS thread for administrative tasks
+/// It runs until dropped.
+pub struct ControlPlane {
+    handle: Handle,
+    _stop: oneshot::Sender<()>,
+}
+
+impl ControlPlane {
+    pub fn start() -> Result<Self> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to build control plane runtime")?;
+        let handle = runtime.handle().clone();
+        let (stop, stopped) = oneshot::channel::<()>();
+        std::thread::Builder::new()
+            .name("pear-control".to_string())
+            .spawn(move || {
+                let _ = runtime.block_on(stopped);
+            })
+            .context("Failed to start control plane thread")?;
+        Ok(Self { handle, _stop: stop })
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
     }
 }
 
-// Re-export num_cpus for convenience
-use num_cpus;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builds_configured_runtime() {
+        let config: RuntimeConfig = toml::from_str("worker_threads = 2\nthread_name = \"pear-test\"").unwrap();
+        assert_eq!(config.thread_stack_size, default_thread_stack_size());
+        let runtime = config.build().unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 2);
+
+        let name = runtime.block_on(async {
+            tokio::spawn(async { std::thread::current().name().map(str::to_string) }).await.unwrap()
+        });
+        assert_eq!(name.as_deref(), Some("pear-test"));
+    }
+
+    #[test]
+    fn test_control_plane_runs_on_its_own_thread() {
+        let control = ControlPlane::start().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        control.handle().spawn(async move {
+            tx.send(std::thread::current().name().map(str::to_string)).unwrap();
+        });
+        assert_eq!(rx.recv().unwrap().as_deref(), Some("pear-control"));
+    }
+}
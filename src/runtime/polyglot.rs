@@ -16,6 +16,29 @@ pub enum DetectedLanguage {
     Unknown,
 }
 
+impl DetectedLanguage {
+    /// Languages that run on a bundled runtime module
+    pub const WITH_RUNTIMES: [DetectedLanguage; 5] = [
+        DetectedLanguage::PHP,
+        DetectedLanguage::Python,
+        DetectedLanguage::NodeJS,
+        DetectedLanguage::Ruby,
+        DetectedLanguage::StaticFiles,
+    ];
+
+    /// File name of the runtime module in the runtime directory
+    pub fn runtime_wasm(&self) -> Option<&'static str> {
+        match self {
+            DetectedLanguage::PHP => Some("php-cgi.wasm"),
+            DetectedLanguage::Python => Some("python3.11-wasi.wasm"),
+            DetectedLanguage::NodeJS => Some("node-wasi.wasm"),
+            DetectedLanguage::Ruby => Some("ruby-wasi.wasm"),
+            DetectedLanguage::StaticFiles => Some("static-server.wasm"),
+            DetectedLanguage::Unknown => None,
+        }
+    }
+}
+
 /// Runtime adapter
 pub struct PolyglotAdapter {
     /// Path to runtime WebAssembly modules
@@ -82,15 +105,9 @@ impl PolyglotAdapter {
 
     /// Get runtime WebAssembly module path for language
     pub fn get_runtime_wasm(&self, language: &DetectedLanguage) -> Result<PathBuf> {
-        let wasm_name = match language {
-            DetectedLanguage::PHP => "php-cgi.wasm",
-            DetectedLanguage::Python => "python3.11-wasi.wasm",
-            DetectedLanguage::NodeJS => "node-wasi.wasm",
-            DetectedLanguage::Ruby => "ruby-wasi.wasm",
-            DetectedLanguage::StaticFiles => "static-server.wasm",
-            DetectedLanguage::Unknown => {
-                anyhow::bail!("Cannot get runtime for unknown language")
-            }
+        let wasm_name = match language.runtime_wasm() {
+            Some(name) => name,
+            None => anyhow::bail!("Cannot get runtime for unknown language"),
         };
 
         let wasm_path = self.runtime_dir.join(wasm_name);
@@ -0,0 +1,155 @@
+// Privilege dropping for processes started as root
+// Sockets are bound while privileged, then the process switches to an unprivileged user
+
+use anyhow::{Context, Result};
+use std::ffi::{CStr, CString};
+use tracing::{info, warn};
+
+/// Buffer size for getpwnam_r / getgrnam_r lookups
+const LOOKUP_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Whether the process is running with root privileges
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Drop to the configured user/group if running as root
+/// Refuses to keep root privileges unless `allow_root` is set
+pub fn drop_if_root(user: Option<&str>, group: Option<&str>, allow_root: bool) -> Result<()> {
+    if !is_root() {
+        if user.is_some() {
+            info!("Not running as root - [server] user setting ignored");
+        }
+        return Ok(());
+    }
+
+    match user {
+        Some(user) => drop_privileges(user, group),
+        None if allow_root => {
+            warn!("Running as root because [server] allow_root = true");
+            Ok(())
+        }
+        None => anyhow::bail!(
+            "Refusing to serve traffic as root: set [server] user (and optionally group), \
+             or allow_root = true to override"
+        ),
+    }
+}
+
+/// Switch to the given user and group
+/// Supplementary groups are reset so no root group membership survives
+pub fn drop_privileges(user: &str, group: Option<&str>) -> Result<()> {
+    let (uid, primary_gid) = lookup_user(user)?;
+    let gid = match group {
+        Some(group) => lookup_group(group)?,
+        None => primary_gid,
+    };
+
+    unsafe {
+        if libc::setgroups(1, &gid) != 0 {
+            return Err(std::io::Error::last_os_error()).context("setgroups failed");
+        }
+
+        // Group must change first: after setuid we no longer may change it
+        if libc::setgid(gid) != 0 {
+            return Err(std::io::Error::last_os_error()).context("setgid failed");
+        }
+
+        if libc::setuid(uid) != 0 {
+            return Err(std::io::Error::last_os_error()).context("setuid failed");
+        }
+
+        // Regaining root must be impossible once privileges are dropped
+        if uid != 0 && libc::setuid(0) == 0 {
+            anyhow::bail!("Privilege drop failed: process could regain root");
+        }
+    }
+
+    info!(user = user, uid = uid, gid = gid, "Dropped root privileges");
+
+    Ok(())
+}
+
+/// Resolve a user name (or numeric uid) to its uid and primary gid
+fn lookup_user(user: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    let name = CString::new(user).context("Invalid user name")?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+    let rc = unsafe {
+        libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+
+    if rc == 0 && !result.is_null() {
+        return Ok((passwd.pw_uid, passwd.pw_gid));
+    }
+
+    // Numeric ids are accepted for users without a passwd entry (e.g. containers)
+    match user.parse::<libc::uid_t>() {
+        Ok(uid) => Ok((uid, uid)),
+        Err(_) => anyhow::bail!("Unknown user '{}'", user),
+    }
+}
+
+/// Resolve a group name (or numeric gid) to its gid
+fn lookup_group(group: &str) -> Result<libc::gid_t> {
+    let name = CString::new(group).context("Invalid group name")?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut result: *mut libc::group = std::ptr::null_mut();
+
+    let rc = unsafe {
+        libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+
+    if rc == 0 && !result.is_null() {
+        return Ok(entry.gr_gid);
+    }
+
+    group.parse::<libc::gid_t>()
+        .map_err(|_| anyhow::anyhow!("Unknown group '{}'", group))
+}
+
+/// Name of the user the process is running as, for logging
+pub fn current_user() -> String {
+    let uid = unsafe { libc::geteuid() };
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+    let rc = unsafe {
+        libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+
+    if rc == 0 && !result.is_null() {
+        unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().into_owned()
+    } else {
+        uid.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_root() {
+        let (uid, gid) = lookup_user("root").unwrap();
+        assert_eq!(uid, 0);
+        assert_eq!(gid, 0);
+    }
+
+    #[test]
+    fn test_lookup_numeric_fallback() {
+        assert_eq!(lookup_user("65534").unwrap().0, 65534);
+        assert!(lookup_group("no-such-group-pear").is_err());
+    }
+
+    #[test]
+    fn test_non_root_is_noop() {
+        if !is_root() {
+            assert!(drop_if_root(Some("nobody"), None, false).is_ok());
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// Cron Expressions
+// Five-field cron schedules (minute hour day-of-month month day-of-week), evaluated in UTC
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use std::str::FromStr;
+
+/// Give up looking for a next run after this many steps (e.g. "0 0 30 2 *")
+const MAX_SEARCH_STEPS: usize = 10_000;
+
+const MONTH_NAMES: &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const DAY_NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A parsed cron schedule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+
+    /// Day-of-month and day-of-week were both restricted, so either may match
+    either_day: bool,
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(expr: &str) -> Result<Self> {
+        let expr = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("Cron expression needs 5 fields (minute hour day month weekday), got {}", fields.len());
+        };
+
+        // Sunday may be written as 0 or 7
+        let weekdays = parse_field(weekday, 0, 7, DAY_NAMES, 0).context("Invalid day-of-week field")?;
+        let weekdays = ((weekdays | (weekdays >> 7)) & 0x7f) as u8;
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[], 0).context("Invalid minute field")?,
+            hours: parse_field(hour, 0, 23, &[], 0).context("Invalid hour field")? as u32,
+            days: parse_field(day, 1, 31, &[], 1).context("Invalid day-of-month field")? as u32,
+            months: parse_field(month, 1, 12, MONTH_NAMES, 1).context("Invalid month field")? as u16,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+}
+
+impl CronSchedule {
+    /// First time strictly after `after` the schedule fires
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut t = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+
+        for _ in 0..MAX_SEARCH_STEPS {
+            if self.months & (1 << t.month()) == 0 {
+                let (year, month) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+                continue;
+            }
+            if !self.day_matches(t) {
+                t = (t + Duration::days(1)).with_hour(0)?.with_minute(0)?;
+                continue;
+            }
+            if self.hours & (1 << t.hour()) == 0 {
+                t = (t + Duration::hours(1)).with_minute(0)?;
+                continue;
+            }
+            if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+                continue;
+            }
+            return Some(t);
+        }
+
+        None
+    }
+
+    fn day_matches(&self, t: DateTime<Utc>) -> bool {
+        let day = self.days & (1 << t.day()) != 0;
+        let weekday = self.weekdays & (1 << t.weekday().num_days_from_sunday()) != 0;
+        if self.either_day { day || weekday } else { day && weekday }
+    }
+}
+
+/// Parse one field into a bitmask of allowed values
+/// `names` map to values starting at `name_base` (e.g. "jan" = 1)
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], name_base: u32) -> Result<u64> {
+    let value = |s: &str| -> Result<u32> {
+        let lower = s.to_ascii_lowercase();
+        let v = match names.iter().position(|name| *name == lower) {
+            Some(i) => i as u32 + name_base,
+            None => s.parse().with_context(|| format!("'{}' is not a number", s))?,
+        };
+        if v < min || v > max {
+            bail!("{} is outside {}-{}", v, min, max);
+        }
+        Ok(v)
+    };
+
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().with_context(|| format!("Invalid step '{}'", step))?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("Step must be greater than 0");
+        }
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // "5/15" means from 5 to the end in steps of 15
+                None if step > 1 => (value(range)?, max),
+                None => { let v = value(range)?; (v, v) }
+            },
+        };
+        if start > end {
+            bail!("Range {}-{} is reversed", start, end);
+        }
+
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn next(expr: &str, after: &str) -> String {
+        let schedule: CronSchedule = expr.parse().unwrap();
+        schedule.next_after(at(after)).unwrap().to_rfc3339()
+    }
+
+    #[test]
+    fn test_next_run() {
+        assert_eq!(next("*/15 * * * *", "2024-03-10T10:07:30Z"), "2024-03-10T10:15:00+00:00");
+        assert_eq!(next("0 3 * * *", "2024-03-10T03:00:00Z"), "2024-03-11T03:00:00+00:00");
+        assert_eq!(next("30 9 * * mon-fri", "2024-03-09T12:00:00Z"), "2024-03-11T09:30:00+00:00");
+        assert_eq!(next("@monthly", "2024-12-15T00:00:00Z"), "2025-01-01T00:00:00+00:00");
+        assert_eq!(next("0 0 29 feb *", "2024-03-01T00:00:00Z"), "2028-02-29T00:00:00+00:00");
+        // Sunday as 7, and day-of-month OR day-of-week when both are set
+        assert_eq!(next("0 12 * * 7", "2024-03-10T13:00:00Z"), "2024-03-17T12:00:00+00:00");
+        assert_eq!(next("0 0 13 * fri", "2024-09-01T00:00:00Z"), "2024-09-06T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expr in ["* * * *", "60 * * * *", "* 24 * * *", "*/0 * * * *", "5-1 * * * *", "* * * foo *"] {
+            assert!(expr.parse::<CronSchedule>().is_err(), "{} should be rejected", expr);
+        }
+        let impossible: CronSchedule = "0 0 30 2 *".parse().unwrap();
+        assert!(impossible.next_after(Utc::now()).is_none());
+    }
+}
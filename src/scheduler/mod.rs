@@ -0,0 +1,624 @@
+// Job Scheduler
+// Invokes named exports of site modules on cron schedules
+
+pub mod cron;
+pub mod queue;
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+use crate::ai::alerts::{Alert, AlertHookConfig, Notifier};
+use crate::ai::events::Severity;
+use cron::CronSchedule;
+
+/// Scheduler settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Jobs and their run history are persisted here ("" = memory only)
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+
+    /// Runs remembered per job
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+
+    /// Run time allowed to jobs without their own timeout
+    #[serde(default = "default_timeout")]
+    pub default_timeout_secs: u64,
+
+    /// Consecutive failures before an alert is sent
+    #[serde(default = "default_alert_after")]
+    pub alert_after_failures: u32,
+
+    /// Where failure alerts go
+    #[serde(default)]
+    pub alerts: Vec<AlertHookConfig>,
+}
+
+fn default_enabled() -> bool { true }
+fn default_state_path() -> String { "schedules.json".to_string() }
+fn default_history_limit() -> usize { 20 }
+fn default_timeout() -> u64 { 300 }
+fn default_alert_after() -> u32 { 1 }
+fn default_export() -> String { "on_schedule".to_string() }
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            state_path: default_state_path(),
+            history_limit: default_history_limit(),
+            default_timeout_secs: default_timeout(),
+            alert_after_failures: default_alert_after(),
+            alerts: Vec::new(),
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// Check the settings are usable
+    pub fn validate(&self) -> Result<()> {
+        if self.history_limit == 0 {
+            bail!("scheduler.history_limit must be at least 1");
+        }
+        if self.default_timeout_secs == 0 {
+            bail!("scheduler.default_timeout_secs must be greater than 0");
+        }
+        if self.alert_after_failures == 0 {
+            bail!("scheduler.alert_after_failures must be at least 1");
+        }
+        for hook in &self.alerts {
+            hook.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// A job as defined by the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobSpec {
+    /// Unique per site
+    pub name: String,
+
+    /// Cron expression, evaluated in UTC
+    pub schedule: String,
+
+    /// Export called with no arguments
+    #[serde(default = "default_export")]
+    pub export: String,
+
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+impl JobSpec {
+    /// Check the job can be scheduled, returning its parsed schedule
+    pub fn validate(&self) -> Result<CronSchedule> {
+        validate_name(&self.name)?;
+        if self.export.is_empty() {
+            bail!("Job export must not be empty");
+        }
+        if self.timeout_secs == Some(0) {
+            bail!("Job timeout must be greater than 0");
+        }
+        self.schedule.parse()
+            .with_context(|| format!("Invalid schedule '{}'", self.schedule))
+    }
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunOutcome {
+    Success,
+    Failed,
+    TimedOut,
+
+    /// Not started because the previous run was still going
+    Skipped,
+}
+
+/// One entry of a job's execution history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRun {
+    /// Unix timestamp (seconds)
+    pub started_at: i64,
+    pub duration_ms: u64,
+    pub outcome: RunOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A job and its current state, as listed by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub site_id: String,
+    #[serde(flatten)]
+    pub spec: JobSpec,
+
+    /// Unix timestamp (seconds) of the next run
+    pub next_run: Option<i64>,
+    pub running: bool,
+    pub consecutive_failures: u32,
+    pub last_run: Option<JobRun>,
+}
+
+/// Runs a job's export; implemented by the Router's Cage pools
+#[async_trait]
+pub trait JobTarget: Send + Sync {
+    async fn invoke(&self, site_id: &str, export: &str) -> Result<()>;
+}
+
+#[async_trait]
+impl JobTarget for crate::router::Router {
+    async fn invoke(&self, site_id: &str, export: &str) -> Result<()> {
+        let pool = self.pool(site_id)
+            .with_context(|| format!("No Cage pool for site {}", site_id))?;
+        pool.wake().await?;
+        let cage = pool.get_cage_least_connected().await
+            .context("No healthy Cage available")?;
+
+        let export = export.to_string();
+        tokio::task::spawn_blocking(move || cage.invoke_export(&export)).await?
+    }
+}
+
+struct Job {
+    site_id: String,
+    spec: JobSpec,
+    schedule: CronSchedule,
+    next_run: Option<DateTime<Utc>>,
+    running: Arc<AtomicBool>,
+    history: VecDeque<JobRun>,
+    consecutive_failures: u32,
+}
+
+impl Job {
+    fn info(&self) -> JobInfo {
+        JobInfo {
+            site_id: self.site_id.clone(),
+            spec: self.spec.clone(),
+            next_run: self.next_run.map(|t| t.timestamp()),
+            running: self.running.load(Ordering::Relaxed),
+            consecutive_failures: self.consecutive_failures,
+            last_run: self.history.back().cloned(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SchedulerState {
+    jobs: Vec<PersistedJob>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedJob {
+    site_id: String,
+    spec: JobSpec,
+    #[serde(default)]
+    history: VecDeque<JobRun>,
+    #[serde(default)]
+    consecutive_failures: u32,
+}
+
+/// Runs every site's scheduled jobs
+pub struct Scheduler {
+    config: SchedulerConfig,
+    target: Arc<dyn JobTarget>,
+
+    /// Jobs keyed by "site/name"
+    jobs: DashMap<String, Job>,
+
+    notifier: Notifier,
+    state_path: Option<PathBuf>,
+    dirty: AtomicBool,
+    save_lock: Mutex<()>,
+}
+
+impl Scheduler {
+    /// Create a scheduler, loading persisted jobs if present
+    pub fn new(config: SchedulerConfig, target: Arc<dyn JobTarget>) -> Result<Self> {
+        config.validate()?;
+
+        let state_path = Some(&config.state_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let state = match &state_path {
+            Some(path) if path.exists() => load_state(path)?,
+            _ => SchedulerState::default(),
+        };
+
+        let now = Utc::now();
+        let jobs = DashMap::new();
+        for persisted in state.jobs {
+            let schedule: CronSchedule = match persisted.spec.schedule.parse() {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    warn!(site_id = %persisted.site_id, job = %persisted.spec.name, error = %e, "Dropping job with invalid schedule");
+                    continue;
+                }
+            };
+            jobs.insert(job_key(&persisted.site_id, &persisted.spec.name), Job {
+                next_run: schedule.next_after(now),
+                schedule,
+                site_id: persisted.site_id,
+                spec: persisted.spec,
+                running: Arc::new(AtomicBool::new(false)),
+                history: persisted.history,
+                consecutive_failures: persisted.consecutive_failures,
+            });
+        }
+
+        info!(jobs = jobs.len(), state_path = ?state_path, "Scheduler initialized");
+
+        Ok(Self {
+            notifier: Notifier::new(config.alerts.clone()),
+            config,
+            target,
+            jobs,
+            state_path,
+            dirty: AtomicBool::new(false),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Add a job, or replace the site's job of the same name
+    pub fn add(&self, site_id: &str, spec: JobSpec) -> Result<JobInfo> {
+        let schedule = spec.validate()?;
+        let next_run = schedule.next_after(Utc::now())
+            .with_context(|| format!("Schedule '{}' never fires", spec.schedule))?;
+
+        let key = job_key(site_id, &spec.name);
+        let mut job = Job {
+            site_id: site_id.to_string(),
+            spec,
+            schedule,
+            next_run: Some(next_run),
+            running: Arc::new(AtomicBool::new(false)),
+            history: VecDeque::new(),
+            consecutive_failures: 0,
+        };
+
+        // A replaced job keeps its history, and a run in progress still blocks overlap
+        if let Some((_, old)) = self.jobs.remove(&key) {
+            job.running = old.running;
+            job.history = old.history;
+        }
+
+        let info = job.info();
+        self.jobs.insert(key, job);
+        self.dirty.store(true, Ordering::Relaxed);
+
+        info!(site_id = %site_id, job = %info.spec.name, schedule = %info.spec.schedule, "Scheduled job saved");
+        Ok(info)
+    }
+
+    /// Remove a job, returning whether it existed
+    pub fn remove(&self, site_id: &str, name: &str) -> bool {
+        let removed = self.jobs.remove(&job_key(site_id, name)).is_some();
+        if removed {
+            self.dirty.store(true, Ordering::Relaxed);
+            info!(site_id = %site_id, job = %name, "Scheduled job removed");
+        }
+        removed
+    }
+
+    /// Jobs of one site, or of every site, sorted by site and name
+    pub fn list(&self, site_id: Option<&str>) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self.jobs.iter()
+            .filter(|job| site_id.map_or(true, |site| job.site_id == site))
+            .map(|job| job.info())
+            .collect();
+        jobs.sort_by(|a, b| (&a.site_id, &a.spec.name).cmp(&(&b.site_id, &b.spec.name)));
+        jobs
+    }
+
+    /// Execution history of a job, oldest first
+    pub fn history(&self, site_id: &str, name: &str) -> Option<Vec<JobRun>> {
+        self.jobs.get(&job_key(site_id, name)).map(|job| job.history.iter().cloned().collect())
+    }
+
+    /// Start every job due at `now`, returning how many were started
+    pub fn run_due(self: &Arc<Self>, now: DateTime<Utc>) -> usize {
+        let mut started = 0;
+
+        for mut job in self.jobs.iter_mut() {
+            if job.next_run.map_or(true, |next| next > now) {
+                continue;
+            }
+            job.next_run = job.schedule.next_after(now);
+
+            if job.running.swap(true, Ordering::AcqRel) {
+                warn!(site_id = %job.site_id, job = %job.spec.name, "Previous run still in progress, skipping");
+                let run = JobRun {
+                    started_at: now.timestamp(),
+                    duration_ms: 0,
+                    outcome: RunOutcome::Skipped,
+                    error: None,
+                };
+                self.push_run(&mut job, run);
+                continue;
+            }
+
+            let timeout = Duration::from_secs(job.spec.timeout_secs.unwrap_or(self.config.default_timeout_secs));
+            tokio::spawn(self.clone().run(job.key().clone(), job.site_id.clone(), job.spec.export.clone(), timeout, job.running.clone()));
+            started += 1;
+        }
+
+        started
+    }
+
+    /// Run one job to completion and record the result
+    async fn run(self: Arc<Self>, key: String, site_id: String, export: String, timeout: Duration, running: Arc<AtomicBool>) {
+        debug!(site_id = %site_id, export = %export, "Running scheduled job");
+        let started_at = Utc::now().timestamp();
+        let start = Instant::now();
+
+        let target = self.target.clone();
+        let task_site = site_id.clone();
+        let mut task = tokio::spawn(async move { target.invoke(&task_site, &export).await });
+
+        let (outcome, error) = match tokio::time::timeout(timeout, &mut task).await {
+            Ok(Ok(Ok(()))) => (RunOutcome::Success, None),
+            Ok(Ok(Err(e))) => (RunOutcome::Failed, Some(format!("{:#}", e))),
+            Ok(Err(e)) => (RunOutcome::Failed, Some(format!("Job task failed: {}", e))),
+            Err(_) => (RunOutcome::TimedOut, Some(format!("Timed out after {}s", timeout.as_secs()))),
+        };
+
+        self.record(&key, JobRun {
+            started_at,
+            duration_ms: start.elapsed().as_millis() as u64,
+            outcome,
+            error,
+        });
+
+        // A timed-out guest can't be interrupted; keep blocking overlap until it returns
+        if outcome == RunOutcome::TimedOut {
+            let _ = task.await;
+        }
+        running.store(false, Ordering::Release);
+    }
+
+    /// Add a finished run to its job's history and alert on repeated failures
+    fn record(&self, key: &str, run: JobRun) {
+        let Some(mut job) = self.jobs.get_mut(key) else {
+            return;
+        };
+
+        if run.outcome == RunOutcome::Success {
+            job.consecutive_failures = 0;
+            debug!(site_id = %job.site_id, job = %job.spec.name, duration_ms = run.duration_ms, "Scheduled job succeeded");
+        } else {
+            job.consecutive_failures += 1;
+            let error = run.error.clone().unwrap_or_default();
+            warn!(site_id = %job.site_id, job = %job.spec.name, error = %error, "Scheduled job failed");
+
+            if job.consecutive_failures >= self.config.alert_after_failures {
+                self.notifier.notify_alert(Alert {
+                    severity: Severity::Warning,
+                    summary: format!(
+                        "[pear] Scheduled job {} on {} failed ({} in a row): {}",
+                        job.spec.name, job.site_id, job.consecutive_failures, error
+                    ),
+                    kind: "job",
+                    payload: serde_json::json!({ "info": job.info(), "run": run }),
+                });
+            }
+        }
+
+        self.push_run(&mut job, run);
+    }
+
+    fn push_run(&self, job: &mut Job, run: JobRun) {
+        job.history.push_back(run);
+        while job.history.len() > self.config.history_limit {
+            job.history.pop_front();
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Persist jobs and history if anything changed
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+
+        let _guard = self.save_lock.lock();
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let state = SchedulerState {
+            jobs: self.jobs.iter()
+                .map(|job| PersistedJob {
+                    site_id: job.site_id.clone(),
+                    spec: job.spec.clone(),
+                    history: job.history.clone(),
+                    consecutive_failures: job.consecutive_failures,
+                })
+                .collect(),
+        };
+
+        let result = save_state(path, &state);
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Check for due jobs every second and persist changes
+    pub fn start(self: &Arc<Self>) {
+        let scheduler = self.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+            loop {
+                interval.tick().await;
+                scheduler.run_due(Utc::now());
+
+                if scheduler.dirty.load(Ordering::Relaxed) {
+                    let scheduler = scheduler.clone();
+                    match tokio::task::spawn_blocking(move || scheduler.save()).await {
+                        Ok(Err(e)) => error!(error = %e, "Failed to persist scheduled jobs"),
+                        Err(e) => error!(error = %e, "Scheduler save task failed"),
+                        Ok(Ok(())) => {}
+                    }
+                }
+            }
+        });
+    }
+}
+
+fn job_key(site_id: &str, name: &str) -> String {
+    format!("{}/{}", site_id, name)
+}
+
+/// Job names appear in URLs and CLI arguments
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 64 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!("Invalid job name '{}': use up to 64 letters, digits, '-' and '_'", name);
+    }
+    Ok(())
+}
+
+fn load_state(path: &Path) -> Result<SchedulerState> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_state(path: &Path, state: &SchedulerState) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, serde_json::to_vec(state)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Counts invocations; fails for the "broken" export and hangs on "slow"
+    #[derive(Default)]
+    struct FakeTarget {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl JobTarget for FakeTarget {
+        async fn invoke(&self, _site_id: &str, export: &str) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match export {
+                "broken" => bail!("trap: unreachable"),
+                "slow" => {
+                    tokio::time::sleep(Duration::from_millis(1500)).await;
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn scheduler(target: Arc<FakeTarget>, state_path: &str) -> Arc<Scheduler> {
+        Arc::new(Scheduler::new(SchedulerConfig {
+            state_path: state_path.to_string(),
+            ..Default::default()
+        }, target).unwrap())
+    }
+
+    fn spec(name: &str, export: &str) -> JobSpec {
+        JobSpec {
+            name: name.to_string(),
+            schedule: "* * * * *".to_string(),
+            export: export.to_string(),
+            timeout_secs: Some(1),
+        }
+    }
+
+    /// Run everything due at the job's next run time and wait for it to finish
+    async fn tick(scheduler: &Arc<Scheduler>, site: &str, name: &str) -> usize {
+        let next = scheduler.list(Some(site)).iter().find(|j| j.spec.name == name).unwrap().next_run.unwrap();
+        let started = scheduler.run_due(DateTime::from_timestamp(next, 0).unwrap());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        started
+    }
+
+    #[tokio::test]
+    async fn test_runs_are_recorded() {
+        let target = Arc::new(FakeTarget::default());
+        let scheduler = scheduler(target.clone(), "");
+        scheduler.add("blog", spec("cleanup", "on_schedule")).unwrap();
+        scheduler.add("blog", spec("report", "broken")).unwrap();
+        assert!(scheduler.add("blog", JobSpec { schedule: "61 * * * *".into(), ..spec("bad", "x") }).is_err());
+
+        assert_eq!(tick(&scheduler, "blog", "cleanup").await, 2);
+        assert_eq!(target.calls.load(Ordering::SeqCst), 2);
+
+        let ok = scheduler.history("blog", "cleanup").unwrap();
+        assert_eq!(ok[0].outcome, RunOutcome::Success);
+        let failed = &scheduler.list(Some("blog"))[1];
+        assert_eq!(failed.consecutive_failures, 1);
+        assert!(failed.last_run.as_ref().unwrap().error.as_deref().unwrap().contains("unreachable"));
+
+        assert!(scheduler.remove("blog", "report"));
+        assert_eq!(scheduler.list(None).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_runs_are_skipped() {
+        let target = Arc::new(FakeTarget::default());
+        let scheduler = scheduler(target.clone(), "");
+        scheduler.add("blog", spec("import", "slow")).unwrap();
+
+        assert_eq!(tick(&scheduler, "blog", "import").await, 1);
+        assert_eq!(tick(&scheduler, "blog", "import").await, 0);
+        assert_eq!(scheduler.history("blog", "import").unwrap()[0].outcome, RunOutcome::Skipped);
+
+        // Past the timeout the run is recorded, but overlap is blocked until it returns
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(scheduler.history("blog", "import").unwrap()[1].outcome, RunOutcome::TimedOut);
+        assert!(scheduler.list(None)[0].running);
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(!scheduler.list(None)[0].running);
+        assert_eq!(target.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schedules.json");
+        let path = path.to_str().unwrap();
+
+        let first = scheduler(Arc::new(FakeTarget::default()), path);
+        first.add("blog", spec("cleanup", "on_schedule")).unwrap();
+        tick(&first, "blog", "cleanup").await;
+        first.save().unwrap();
+
+        let second = scheduler(Arc::new(FakeTarget::default()), path);
+        let jobs = second.list(Some("blog"));
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].last_run.as_ref().unwrap().outcome, RunOutcome::Success);
+        assert!(jobs[0].next_run.is_some());
+    }
+}
@@ -0,0 +1,673 @@
+// Task Queue
+// Background tasks enqueued by guests and handled by a dedicated Cage per site
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::cage::config::CageConfig;
+use crate::cage::{Cage, create_engine_for};
+
+/// Export called with each task
+pub const HANDLER_EXPORT: &str = "handle_job";
+
+/// Task queue settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Pending and dead tasks are persisted here ("" = memory only)
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+
+    /// Attempts before a task is moved to the dead-letter list
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// First retry delay; doubles with every further attempt
+    #[serde(default = "default_retry_base_ms")]
+    pub retry_base_ms: u64,
+
+    /// Longest retry delay
+    #[serde(default = "default_retry_max_secs")]
+    pub retry_max_secs: u64,
+
+    /// Run time allowed to one `handle_job` call
+    #[serde(default = "default_task_timeout")]
+    pub task_timeout_secs: u64,
+
+    #[serde(default = "default_max_payload")]
+    pub max_payload_bytes: usize,
+
+    /// Pending tasks a site may have before enqueues fail
+    #[serde(default = "default_max_pending")]
+    pub max_pending_per_site: usize,
+
+    /// Longest delay a guest may ask for
+    #[serde(default = "default_max_delay")]
+    pub max_delay_secs: u64,
+
+    /// Dead tasks kept per site
+    #[serde(default = "default_dead_letter_limit")]
+    pub dead_letter_limit: usize,
+}
+
+fn default_enabled() -> bool { true }
+fn default_state_path() -> String { "task-queue.json".to_string() }
+fn default_max_attempts() -> u32 { 5 }
+fn default_retry_base_ms() -> u64 { 1000 }
+fn default_retry_max_secs() -> u64 { 300 }
+fn default_task_timeout() -> u64 { 60 }
+fn default_max_payload() -> usize { 64 * 1024 }
+fn default_max_pending() -> usize { 10_000 }
+fn default_max_delay() -> u64 { 7 * 24 * 3600 }
+fn default_dead_letter_limit() -> usize { 100 }
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            state_path: default_state_path(),
+            max_attempts: default_max_attempts(),
+            retry_base_ms: default_retry_base_ms(),
+            retry_max_secs: default_retry_max_secs(),
+            task_timeout_secs: default_task_timeout(),
+            max_payload_bytes: default_max_payload(),
+            max_pending_per_site: default_max_pending(),
+            max_delay_secs: default_max_delay(),
+            dead_letter_limit: default_dead_letter_limit(),
+        }
+    }
+}
+
+impl QueueConfig {
+    /// Check the settings are usable
+    pub fn validate(&self) -> Result<()> {
+        if self.max_attempts == 0 {
+            bail!("queue.max_attempts must be at least 1");
+        }
+        if self.task_timeout_secs == 0 {
+            bail!("queue.task_timeout_secs must be greater than 0");
+        }
+        if self.max_payload_bytes == 0 || self.max_pending_per_site == 0 {
+            bail!("queue.max_payload_bytes and queue.max_pending_per_site must be greater than 0");
+        }
+        if self.retry_base_ms > self.retry_max_secs * 1000 {
+            bail!("queue.retry_base_ms must not exceed queue.retry_max_secs");
+        }
+        Ok(())
+    }
+}
+
+/// A unit of background work
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    pub id: u64,
+    pub site_id: String,
+
+    /// UTF-8 text handed to `handle_job`, typically JSON
+    pub payload: String,
+
+    /// Failed attempts so far
+    pub attempts: u32,
+
+    /// Unix timestamp (milliseconds) before which the task must not run
+    pub run_at_ms: i64,
+
+    /// Unix timestamp (seconds)
+    pub enqueued_at: i64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// A task that failed every attempt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadTask {
+    #[serde(flatten)]
+    pub task: Task,
+
+    /// Unix timestamp (seconds)
+    pub failed_at: i64,
+}
+
+/// Queue counters of a site
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueStats {
+    pub pending: usize,
+    pub running: bool,
+    pub completed: u64,
+    pub retried: u64,
+    pub dead: usize,
+    pub has_worker: bool,
+}
+
+/// Runs one task; implemented by the dedicated worker Cage
+#[async_trait]
+pub trait TaskHandler: Send + Sync {
+    async fn handle(&self, task: &Task) -> Result<()>;
+}
+
+/// A Cage kept apart from the request pool to run a site's tasks
+pub struct CageWorker {
+    cage: Arc<Cage>,
+}
+
+impl CageWorker {
+    /// Compile the site's module into a worker Cage linked to `queue`
+    pub fn new(site_id: &str, wasm_bytes: &[u8], mut config: CageConfig, queue: GuestQueue) -> Result<Self> {
+        config.queue = Some(queue);
+        let cage = Cage::new(0, format!("{}-worker", site_id), create_engine_for(&config)?, wasm_bytes, config)?;
+        Ok(Self { cage: Arc::new(cage) })
+    }
+}
+
+#[async_trait]
+impl TaskHandler for CageWorker {
+    async fn handle(&self, task: &Task) -> Result<()> {
+        let cage = self.cage.clone();
+        let payload = task.payload.clone();
+        tokio::task::spawn_blocking(move || cage.handle_task(HANDLER_EXPORT, &payload)).await?
+    }
+}
+
+struct Worker {
+    handler: Arc<dyn TaskHandler>,
+
+    /// Tasks run one at a time per site
+    busy: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct QueueState {
+    next_id: u64,
+
+    /// Keyed by (run_at_ms, id) so the earliest task comes first
+    #[serde(with = "pending_list")]
+    pending: BTreeMap<(i64, u64), Task>,
+
+    dead: BTreeMap<String, VecDeque<DeadTask>>,
+
+    /// Tasks handed to a worker, by id
+    #[serde(skip)]
+    running: BTreeMap<u64, Task>,
+}
+
+/// Stores the pending map as a plain list
+mod pending_list {
+    use super::Task;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S: Serializer>(pending: &BTreeMap<(i64, u64), Task>, s: S) -> Result<S::Ok, S::Error> {
+        pending.values().collect::<Vec<_>>().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<BTreeMap<(i64, u64), Task>, D::Error> {
+        let tasks = Vec::<Task>::deserialize(d)?;
+        Ok(tasks.into_iter().map(|task| ((task.run_at_ms, task.id), task)).collect())
+    }
+}
+
+#[derive(Default)]
+struct SiteCounters {
+    completed: AtomicU64,
+    retried: AtomicU64,
+}
+
+/// Every site's background tasks
+pub struct TaskQueue {
+    config: QueueConfig,
+    state: Mutex<QueueState>,
+    workers: DashMap<String, Worker>,
+    counters: DashMap<String, SiteCounters>,
+    state_path: Option<PathBuf>,
+    dirty: AtomicBool,
+    save_lock: Mutex<()>,
+}
+
+impl TaskQueue {
+    /// Create a queue, loading persisted tasks if present
+    pub fn new(config: QueueConfig) -> Result<Self> {
+        config.validate()?;
+
+        let state_path = Some(&config.state_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let state = match &state_path {
+            Some(path) if path.exists() => load_state(path)?,
+            _ => QueueState::default(),
+        };
+
+        info!(pending = state.pending.len(), state_path = ?state_path, "Task queue initialized");
+
+        Ok(Self {
+            config,
+            state: Mutex::new(state),
+            workers: DashMap::new(),
+            counters: DashMap::new(),
+            state_path,
+            dirty: AtomicBool::new(false),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Handle a site's guests use to enqueue tasks
+    pub fn guest(self: &Arc<Self>, site_id: &str) -> GuestQueue {
+        GuestQueue {
+            queue: self.clone(),
+            site_id: site_id.to_string(),
+        }
+    }
+
+    /// Set the handler running a site's tasks, replacing any previous one
+    pub fn register_worker(&self, site_id: &str, handler: Arc<dyn TaskHandler>) {
+        self.workers.insert(site_id.to_string(), Worker {
+            handler,
+            busy: Arc::new(AtomicBool::new(false)),
+        });
+        info!(site_id = %site_id, "Task worker registered");
+    }
+
+    /// Stop running a site's tasks; pending ones are kept
+    pub fn remove_worker(&self, site_id: &str) {
+        self.workers.remove(site_id);
+    }
+
+    /// Add a task that becomes due after `delay_ms`
+    pub fn enqueue(&self, site_id: &str, payload: &[u8], delay_ms: u64) -> Result<u64> {
+        if payload.len() > self.config.max_payload_bytes {
+            bail!("Task payload of {} bytes exceeds the {} byte limit", payload.len(), self.config.max_payload_bytes);
+        }
+        if delay_ms > self.config.max_delay_secs * 1000 {
+            bail!("Task delay exceeds the {}s limit", self.config.max_delay_secs);
+        }
+        let payload = std::str::from_utf8(payload).context("Task payload must be UTF-8")?;
+
+        let mut state = self.state.lock();
+        let pending = state.pending.values().filter(|task| task.site_id == site_id).count();
+        if pending >= self.config.max_pending_per_site {
+            bail!("Site has {} pending tasks, the limit", pending);
+        }
+
+        state.next_id += 1;
+        let task = Task {
+            id: state.next_id,
+            site_id: site_id.to_string(),
+            payload: payload.to_string(),
+            attempts: 0,
+            run_at_ms: now_ms() + delay_ms as i64,
+            enqueued_at: chrono::Utc::now().timestamp(),
+            last_error: None,
+        };
+        let id = task.id;
+        state.pending.insert((task.run_at_ms, id), task);
+        self.dirty.store(true, Ordering::Relaxed);
+
+        debug!(site_id = %site_id, task_id = id, delay_ms, "Task enqueued");
+        Ok(id)
+    }
+
+    /// Queue counters of a site
+    pub fn stats(&self, site_id: &str) -> QueueStats {
+        let state = self.state.lock();
+        let worker = self.workers.get(site_id);
+        let counters = self.counters.get(site_id);
+
+        QueueStats {
+            pending: state.pending.values().filter(|task| task.site_id == site_id).count(),
+            running: worker.as_ref().is_some_and(|w| w.busy.load(Ordering::Relaxed)),
+            completed: counters.as_ref().map_or(0, |c| c.completed.load(Ordering::Relaxed)),
+            retried: counters.as_ref().map_or(0, |c| c.retried.load(Ordering::Relaxed)),
+            dead: state.dead.get(site_id).map_or(0, |dead| dead.len()),
+            has_worker: worker.is_some(),
+        }
+    }
+
+    /// Dead tasks of a site, oldest first
+    pub fn dead_tasks(&self, site_id: &str) -> Vec<DeadTask> {
+        self.state.lock().dead.get(site_id)
+            .map(|dead| dead.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Move a dead task back to the queue with its attempts reset
+    pub fn retry_dead(&self, site_id: &str, task_id: u64) -> bool {
+        let mut state = self.state.lock();
+        let Some(mut task) = take_dead(&mut state, site_id, task_id) else {
+            return false;
+        };
+
+        task.attempts = 0;
+        task.run_at_ms = now_ms();
+        state.pending.insert((task.run_at_ms, task.id), task);
+        self.dirty.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Drop a dead task for good
+    pub fn discard_dead(&self, site_id: &str, task_id: u64) -> bool {
+        let removed = take_dead(&mut self.state.lock(), site_id, task_id).is_some();
+        if removed {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// Start the earliest due task of every idle site, returning how many were started
+    pub fn dispatch_due(self: &Arc<Self>) -> usize {
+        let now = now_ms();
+        let mut started = 0;
+        let mut state = self.state.lock();
+
+        for worker in self.workers.iter() {
+            if worker.busy.load(Ordering::Acquire) {
+                continue;
+            }
+            let site_id = worker.key();
+            let Some(key) = state.pending.iter()
+                .take_while(|((run_at, _), _)| *run_at <= now)
+                .find(|(_, task)| &task.site_id == site_id)
+                .map(|(key, _)| *key)
+            else {
+                continue;
+            };
+
+            let task = state.pending.remove(&key).expect("task key was just found");
+            state.running.insert(task.id, task.clone());
+            worker.busy.store(true, Ordering::Release);
+            tokio::spawn(self.clone().run(task, worker.handler.clone(), worker.busy.clone()));
+            started += 1;
+        }
+
+        if started > 0 {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        started
+    }
+
+    /// Run one task and retry or bury it on failure
+    async fn run(self: Arc<Self>, mut task: Task, handler: Arc<dyn TaskHandler>, busy: Arc<AtomicBool>) {
+        debug!(site_id = %task.site_id, task_id = task.id, attempt = task.attempts + 1, "Running task");
+        let timeout = Duration::from_secs(self.config.task_timeout_secs);
+
+        let running = task.clone();
+        let mut handle = tokio::spawn(async move { handler.handle(&running).await });
+        let result = match tokio::time::timeout(timeout, &mut handle).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => Err(anyhow::anyhow!("Task handler failed: {}", e)),
+            Err(_) => Err(anyhow::anyhow!("Timed out after {}s", timeout.as_secs())),
+        };
+
+        let timed_out = !handle.is_finished();
+        self.state.lock().running.remove(&task.id);
+        self.dirty.store(true, Ordering::Relaxed);
+        match result {
+            Ok(()) => {
+                self.counters.entry(task.site_id.clone()).or_default().completed.fetch_add(1, Ordering::Relaxed);
+                debug!(site_id = %task.site_id, task_id = task.id, "Task completed");
+            }
+            Err(e) => {
+                task.attempts += 1;
+                task.last_error = Some(format!("{:#}", e));
+                self.fail(task);
+            }
+        }
+
+        // A timed-out guest can't be interrupted; the site's next task waits for it
+        if timed_out {
+            let _ = handle.await;
+        }
+        busy.store(false, Ordering::Release);
+    }
+
+    fn fail(&self, mut task: Task) {
+        let mut state = self.state.lock();
+        self.dirty.store(true, Ordering::Relaxed);
+
+        if task.attempts < self.config.max_attempts {
+            let delay = self.retry_delay(task.attempts);
+            warn!(
+                site_id = %task.site_id, task_id = task.id, attempts = task.attempts,
+                retry_in_ms = delay.as_millis() as u64, error = task.last_error.as_deref().unwrap_or_default(),
+                "Task failed, retrying"
+            );
+            self.counters.entry(task.site_id.clone()).or_default().retried.fetch_add(1, Ordering::Relaxed);
+            task.run_at_ms = now_ms() + delay.as_millis() as i64;
+            state.pending.insert((task.run_at_ms, task.id), task);
+            return;
+        }
+
+        error!(
+            site_id = %task.site_id, task_id = task.id, attempts = task.attempts,
+            error = task.last_error.as_deref().unwrap_or_default(),
+            "Task failed every attempt, moved to dead letters"
+        );
+        let dead = state.dead.entry(task.site_id.clone()).or_default();
+        dead.push_back(DeadTask { task, failed_at: chrono::Utc::now().timestamp() });
+        while dead.len() > self.config.dead_letter_limit {
+            dead.pop_front();
+        }
+    }
+
+    /// Exponential backoff after `attempts` failures
+    fn retry_delay(&self, attempts: u32) -> Duration {
+        let delay = self.config.retry_base_ms.saturating_mul(1 << attempts.saturating_sub(1).min(32));
+        Duration::from_millis(delay.min(self.config.retry_max_secs * 1000))
+    }
+
+    /// Persist tasks if anything changed
+    /// Tasks running at the time are saved as pending so a crash doesn't lose them
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+
+        let _guard = self.save_lock.lock();
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let mut snapshot = self.state.lock().clone();
+        let running = std::mem::take(&mut snapshot.running);
+        snapshot.pending.extend(running.into_values().map(|task| ((task.run_at_ms, task.id), task)));
+
+        let json = serde_json::to_vec(&snapshot)?;
+        let result = save_state(path, &json);
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Dispatch due tasks and persist changes in the background
+    pub fn start(self: &Arc<Self>) {
+        let queue = self.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(200));
+            let mut ticks = 0u64;
+
+            loop {
+                interval.tick().await;
+                queue.dispatch_due();
+
+                ticks += 1;
+                if ticks % 5 == 0 && queue.dirty.load(Ordering::Relaxed) {
+                    let queue = queue.clone();
+                    match tokio::task::spawn_blocking(move || queue.save()).await {
+                        Ok(Err(e)) => error!(error = %e, "Failed to persist task queue"),
+                        Err(e) => error!(error = %e, "Task queue save task failed"),
+                        Ok(Ok(())) => {}
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// A site's queue as seen by its Cages
+#[derive(Clone)]
+pub struct GuestQueue {
+    queue: Arc<TaskQueue>,
+    site_id: String,
+}
+
+impl GuestQueue {
+    /// Add a task for this site, returning its id
+    pub fn enqueue(&self, payload: &[u8], delay_ms: u64) -> Result<u64> {
+        self.queue.enqueue(&self.site_id, payload, delay_ms)
+    }
+
+    pub fn site_id(&self) -> &str {
+        &self.site_id
+    }
+}
+
+impl std::fmt::Debug for GuestQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuestQueue").field("site_id", &self.site_id).finish()
+    }
+}
+
+fn take_dead(state: &mut QueueState, site_id: &str, task_id: u64) -> Option<Task> {
+    let dead = state.dead.get_mut(site_id)?;
+    let index = dead.iter().position(|d| d.task.id == task_id)?;
+    dead.remove(index).map(|d| d.task)
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn load_state(path: &Path) -> Result<QueueState> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_state(path: &Path, json: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, json)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails tasks whose payload is "fail"
+    #[derive(Default)]
+    struct FakeHandler {
+        handled: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl TaskHandler for FakeHandler {
+        async fn handle(&self, task: &Task) -> Result<()> {
+            self.handled.lock().push(task.payload.clone());
+            if task.payload == "fail" {
+                bail!("handler returned 1");
+            }
+            Ok(())
+        }
+    }
+
+    fn queue(state_path: &str) -> Arc<TaskQueue> {
+        Arc::new(TaskQueue::new(QueueConfig {
+            state_path: state_path.to_string(),
+            max_attempts: 2,
+            retry_base_ms: 20,
+            ..Default::default()
+        }).unwrap())
+    }
+
+    async fn drain(queue: &Arc<TaskQueue>) {
+        for _ in 0..20 {
+            queue.dispatch_due();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tasks_run_in_order_with_delay() {
+        let queue = queue("");
+        let handler = Arc::new(FakeHandler::default());
+        queue.register_worker("blog", handler.clone());
+
+        queue.guest("blog").enqueue(b"later", 500).unwrap();
+        queue.guest("blog").enqueue(b"first", 0).unwrap();
+        queue.guest("blog").enqueue(b"second", 0).unwrap();
+        queue.guest("shop").enqueue(b"no worker", 0).unwrap();
+        assert!(queue.guest("blog").enqueue(&[0xff], 0).is_err());
+
+        drain(&queue).await;
+        assert_eq!(*handler.handled.lock(), ["first", "second"]);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        drain(&queue).await;
+        assert_eq!(handler.handled.lock().len(), 3);
+
+        assert_eq!(queue.stats("blog").completed, 3);
+        assert_eq!(queue.stats("shop").pending, 1);
+        assert!(!queue.stats("shop").has_worker);
+    }
+
+    #[tokio::test]
+    async fn test_failed_tasks_retry_then_dead_letter() {
+        let queue = queue("");
+        let handler = Arc::new(FakeHandler::default());
+        queue.register_worker("blog", handler.clone());
+        let id = queue.guest("blog").enqueue(b"fail", 0).unwrap();
+
+        drain(&queue).await;
+        assert_eq!(handler.handled.lock().len(), 2);
+        let stats = queue.stats("blog");
+        assert_eq!((stats.pending, stats.retried, stats.dead), (0, 1, 1));
+
+        let dead = queue.dead_tasks("blog");
+        assert_eq!(dead[0].task.attempts, 2);
+        assert!(dead[0].task.last_error.as_deref().unwrap().contains("handler returned 1"));
+
+        assert!(queue.retry_dead("blog", id));
+        assert_eq!(queue.stats("blog").pending, 1);
+        drain(&queue).await;
+        assert!(queue.discard_dead("blog", id));
+        assert!(queue.dead_tasks("blog").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pending_tasks_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("task-queue.json");
+        let path = path.to_str().unwrap();
+
+        let first = queue(path);
+        first.guest("blog").enqueue(b"{\"email\": 42}", 0).unwrap();
+        first.save().unwrap();
+
+        let second = queue(path);
+        let handler = Arc::new(FakeHandler::default());
+        second.register_worker("blog", handler.clone());
+        let next = second.guest("blog").enqueue(b"next", 0).unwrap();
+        assert_eq!(next, 2);
+
+        drain(&second).await;
+        assert_eq!(*handler.handled.lock(), ["{\"email\": 42}", "next"]);
+    }
+}
@@ -1,8 +1,8 @@
 // Unix signal handling for graceful shutdown
-// Captures SIGTERM and SIGINT to allow clean resource cleanup
+// Captures SIGTERM and SIGINT to allow clean resource cleanup, and SIGUSR2 for binary upgrades
 
 use anyhow::Result;
-use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR2};
 use signal_hook_tokio::Signals;
 use futures::StreamExt;
 use tracing::{info, debug};
@@ -33,6 +33,15 @@ pub fn create_shutdown_listener() -> Result<impl std::future::Future<Output = ()
     })
 }
 
+/// Create a stream that yields each time a binary upgrade is requested (SIGUSR2)
+pub fn create_upgrade_listener() -> Result<impl futures::Stream<Item = ()> + Unpin> {
+    let signals = Signals::new(&[SIGUSR2])?;
+    
+    Ok(signals.map(|_| {
+        info!("Received SIGUSR2 - binary upgrade requested");
+    }))
+}
+
 /// Signal-safe shutdown coordinator
 /// Ensures all subsystems are notified and have time to cleanup
 pub struct ShutdownCoordinator {
@@ -5,7 +5,8 @@ pub mod shared_memory;
 
 use dashmap::DashMap;
 use std::sync::Arc;
-use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
 
 /// Global state manager for the Pear Server
 /// Uses Arc for shared ownership across async tasks
@@ -28,6 +29,17 @@ pub struct GlobalState {
 
     /// Shared memory pool for zero-copy operations
     memory_pool: Arc<shared_memory::MemoryPool>,
+    
+    /// Connection ID generator
+    connection_counter: Arc<AtomicU64>,
+    
+    /// Streaming response accounting for connections with open streams
+    streams: Arc<DashMap<u64, Arc<StreamAccounting>>>,
+    
+    /// Connections closed for sitting idle, for reaching their request allowance, and to rebalance
+    reaped_idle: Arc<AtomicU64>,
+    reaped_request_limit: Arc<AtomicU64>,
+    reaped_shed: Arc<AtomicU64>,
 }
 
 impl GlobalState {
@@ -38,6 +50,11 @@ impl GlobalState {
             config: Arc::new(arc_swap::ArcSwap::from_pointee(ServerConfig::default())),
             request_counter: Arc::new(std::sync::atomic::AtomicU64::new(0)),
             memory_pool: Arc::new(shared_memory::MemoryPool::new()),
+            connection_counter: Arc::new(AtomicU64::new(1)),
+            streams: Arc::new(DashMap::new()),
+            reaped_idle: Arc::new(AtomicU64::new(0)),
+            reaped_request_limit: Arc::new(AtomicU64::new(0)),
+            reaped_shed: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -46,6 +63,11 @@ impl GlobalState {
         self.request_counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Generate a unique connection ID
+    pub fn next_connection_id(&self) -> u64 {
+        self.connection_counter.fetch_add(1, Ordering::Relaxed)
+    }
+
     /// Register a new connection
     pub fn register_connection(&self, conn_id: u64, metadata: ConnectionMetadata) {
         self.connections.insert(conn_id, metadata);
@@ -54,6 +76,114 @@ impl GlobalState {
     /// Remove a connection
     pub fn remove_connection(&self, conn_id: u64) {
         self.connections.remove(&conn_id);
+        self.streams.remove(&conn_id);
+    }
+
+    /// Count a request starting on a connection, returning how many it has now served
+    pub fn begin_request(&self, conn_id: u64) -> u64 {
+        let Some(mut connection) = self.connections.get_mut(&conn_id) else { return 0 };
+        connection.request_count += 1;
+        connection.active_requests += 1;
+        connection.last_activity = tokio::time::Instant::now();
+        connection.request_count
+    }
+
+    /// Mark a request's response as sent
+    pub fn end_request(&self, conn_id: u64) {
+        if let Some(mut connection) = self.connections.get_mut(&conn_id) {
+            connection.active_requests = connection.active_requests.saturating_sub(1);
+            connection.last_activity = tokio::time::Instant::now();
+        }
+    }
+
+    /// How long a connection has had nothing in flight, or None while it is busy
+    /// Open streaming responses count as in flight.
+    pub fn idle_for(&self, conn_id: u64) -> Option<Duration> {
+        let connection = self.connections.get(&conn_id)?;
+        let streaming = self.streams.get(&conn_id)
+            .is_some_and(|streams| streams.open_streams.load(Ordering::Relaxed) > 0);
+        if connection.active_requests > 0 || streaming {
+            return None;
+        }
+        Some(connection.last_activity.elapsed())
+    }
+
+    /// Ask a connection to close gracefully, so its client reconnects, possibly to another node
+    /// Returns whether the connection was open.
+    pub fn shed_connection(&self, conn_id: u64) -> bool {
+        let Some(connection) = self.connections.get(&conn_id) else { return false };
+        connection.shed.notify_one();
+        true
+    }
+
+    /// Signalled when the connection is shed; None once it is gone
+    pub fn shed_signal(&self, conn_id: u64) -> Option<Arc<tokio::sync::Notify>> {
+        self.connections.get(&conn_id).map(|connection| connection.shed.clone())
+    }
+
+    /// IDs of open connections, oldest first
+    pub fn connection_ids(&self) -> Vec<u64> {
+        let mut connections: Vec<(std::time::Instant, u64)> = self.connections.iter()
+            .map(|entry| (entry.connected_at, *entry.key()))
+            .collect();
+        connections.sort();
+        connections.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Count a connection closed by the reaper
+    pub fn record_reaped(&self, reason: ReapReason) {
+        let counter = match reason {
+            ReapReason::Idle => &self.reaped_idle,
+            ReapReason::RequestLimit => &self.reaped_request_limit,
+            ReapReason::Shed => &self.reaped_shed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Open connections, oldest first, and reaper totals
+    pub fn connection_report(&self) -> ConnectionReport {
+        let mut connections: Vec<ConnectionInfo> = self.connections.iter()
+            .map(|entry| {
+                let connection = entry.value();
+                ConnectionInfo {
+                    id: *entry.key(),
+                    protocol: connection.protocol.to_string(),
+                    remote_addr: connection.remote_addr.clone(),
+                    age_secs: connection.connected_at.elapsed().as_secs(),
+                    idle_secs: connection.last_activity.elapsed().as_secs(),
+                    requests: connection.request_count,
+                    active_requests: connection.active_requests,
+                }
+            })
+            .collect();
+        connections.sort_by(|a, b| b.age_secs.cmp(&a.age_secs).then(a.id.cmp(&b.id)));
+        ConnectionReport {
+            reaped_idle: self.reaped_idle.load(Ordering::Relaxed),
+            reaped_request_limit: self.reaped_request_limit.load(Ordering::Relaxed),
+            reaped_shed: self.reaped_shed.load(Ordering::Relaxed),
+            connections,
+        }
+    }
+
+    /// Streaming accounting for a connection, created on first use
+    pub fn stream_accounting(&self, conn_id: u64) -> Arc<StreamAccounting> {
+        self.streams.entry(conn_id).or_default().clone()
+    }
+
+    /// Streaming totals across all connections
+    pub fn stream_stats(&self) -> StreamStats {
+        let mut stats = StreamStats::default();
+        for entry in self.streams.iter() {
+            let open = entry.open_streams.load(Ordering::Relaxed);
+            if open > 0 {
+                stats.streaming_connections += 1;
+            }
+            stats.open_streams += open;
+            stats.buffered_bytes += entry.buffered_bytes.load(Ordering::Relaxed);
+            stats.sent_bytes += entry.sent_bytes.load(Ordering::Relaxed);
+            stats.stalls += entry.stalls.load(Ordering::Relaxed);
+        }
+        stats
     }
 
     /// Get connection count
@@ -67,8 +197,14 @@ impl GlobalState {
     }
 
     /// Acquire a buffer from the memory pool
-    pub fn acquire_buffer(&self, size: usize) -> Bytes {
-        self.memory_pool.acquire(size)
+    /// The buffer is recycled automatically when dropped
+    pub fn acquire_buffer(&self, size: usize) -> shared_memory::PooledBuffer {
+        self.memory_pool.acquire_pooled(size)
+    }
+
+    /// Get the shared memory pool
+    pub fn memory_pool(&self) -> Arc<shared_memory::MemoryPool> {
+        self.memory_pool.clone()
     }
 
     /// Get memory pool statistics
@@ -83,6 +219,30 @@ impl Default for GlobalState {
     }
 }
 
+/// Streaming response accounting for one connection
+#[derive(Debug, Default)]
+pub struct StreamAccounting {
+    pub open_streams: AtomicUsize,
+    
+    /// Bytes Cages have produced that the client hasn't taken yet
+    pub buffered_bytes: AtomicU64,
+    
+    pub sent_bytes: AtomicU64,
+    
+    /// Times a Cage waited because the client wasn't reading (backpressure)
+    pub stalls: AtomicU64,
+}
+
+/// Streaming totals, as reported by `GlobalState::stream_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct StreamStats {
+    pub streaming_connections: usize,
+    pub open_streams: usize,
+    pub buffered_bytes: u64,
+    pub sent_bytes: u64,
+    pub stalls: u64,
+}
+
 /// Metadata for active connections
 #[derive(Debug, Clone)]
 pub struct ConnectionMetadata {
@@ -90,6 +250,60 @@ pub struct ConnectionMetadata {
     pub remote_addr: String,
     pub connected_at: std::time::Instant,
     pub request_count: u64,
+    
+    /// Requests whose response head hasn't been sent yet
+    pub active_requests: u32,
+    
+    /// When the last request started or finished
+    pub last_activity: tokio::time::Instant,
+
+    /// Notified to close the connection gracefully
+    pub shed: Arc<tokio::sync::Notify>,
+}
+
+impl ConnectionMetadata {
+    pub fn new(protocol: Protocol, remote_addr: String) -> Self {
+        Self {
+            protocol,
+            remote_addr,
+            connected_at: std::time::Instant::now(),
+            request_count: 0,
+            active_requests: 0,
+            last_activity: tokio::time::Instant::now(),
+            shed: Arc::new(tokio::sync::Notify::new()),
+        }
+    }
+}
+
+/// Why the reaper closed a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReapReason {
+    Idle,
+    RequestLimit,
+
+    /// Shed to move its client to another node
+    Shed,
+}
+
+/// One row of the connections table
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub protocol: String,
+    pub remote_addr: String,
+    pub age_secs: u64,
+    pub idle_secs: u64,
+    pub requests: u64,
+    pub active_requests: u32,
+}
+
+/// Open connections, as reported by `GlobalState::connection_report`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConnectionReport {
+    pub reaped_idle: u64,
+    pub reaped_request_limit: u64,
+    pub reaped_shed: u64,
+    pub connections: Vec<ConnectionInfo>,
 }
 
 /// Protocol type
@@ -148,17 +362,53 @@ mod tests {
     fn test_connection_management() {
         let state = GlobalState::new();
         
-        let metadata = ConnectionMetadata {
-            protocol: Protocol::Http2,
-            remote_addr: "127.0.0.1:1234".to_string(),
-            connected_at: std::time::Instant::now(),
-            request_count: 0,
-        };
+        let metadata = ConnectionMetadata::new(Protocol::Http2, "127.0.0.1:1234".to_string());
         
         state.register_connection(1, metadata);
         assert_eq!(state.connection_count(), 1);
         
-        state.remove_connection(&1);
+        state.remove_connection(1);
         assert_eq!(state.connection_count(), 0);
     }
+
+    #[test]
+    fn test_connection_activity() {
+        let state = GlobalState::new();
+        state.register_connection(7, ConnectionMetadata::new(Protocol::Http2, "192.0.2.1:5000".to_string()));
+
+        assert_eq!(state.begin_request(7), 1);
+        assert_eq!(state.begin_request(7), 2);
+        assert_eq!(state.idle_for(7), None);
+        state.end_request(7);
+        state.end_request(7);
+        assert!(state.idle_for(7).is_some());
+
+        state.stream_accounting(7).open_streams.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(state.idle_for(7), None);
+
+        state.record_reaped(ReapReason::Idle);
+        let report = state.connection_report();
+        assert_eq!(report.reaped_idle, 1);
+        assert_eq!(report.connections.len(), 1);
+        assert_eq!((report.connections[0].requests, report.connections[0].active_requests), (2, 0));
+        assert_eq!(state.begin_request(99), 0);
+    }
+
+    #[test]
+    fn test_stream_accounting() {
+        let state = GlobalState::new();
+        let conn_id = state.next_connection_id();
+
+        let accounting = state.stream_accounting(conn_id);
+        accounting.open_streams.fetch_add(2, Ordering::Relaxed);
+        accounting.sent_bytes.fetch_add(512, Ordering::Relaxed);
+        state.stream_accounting(conn_id).stalls.fetch_add(1, Ordering::Relaxed);
+
+        let stats = state.stream_stats();
+        assert_eq!((stats.streaming_connections, stats.open_streams), (1, 2));
+        assert_eq!((stats.sent_bytes, stats.stalls), (512, 1));
+
+        state.remove_connection(conn_id);
+        assert_eq!(state.stream_stats(), StreamStats::default());
+    }
 }
@@ -4,6 +4,7 @@
 use bytes::{Bytes, BytesMut};
 use parking_lot::Mutex;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// Memory pool for zero-copy buffer operations
 /// Reduces allocation overhead for frequently used buffer sizes
@@ -13,6 +14,12 @@ pub struct MemoryPool {
     
     /// Statistics for pool performance monitoring
     stats: Arc<std::sync::atomic::AtomicU64>,
+    
+    /// Buffers that had to be freshly allocated
+    allocations: AtomicU64,
+    
+    /// Buffers returned to the pool for reuse
+    recycled: AtomicU64,
 }
 
 struct BufferPools {
@@ -30,6 +37,9 @@ const SMALL_BUFFER_SIZE: usize = 4 * 1024;       // 4KB
 const MEDIUM_BUFFER_SIZE: usize = 64 * 1024;     // 64KB
 const LARGE_BUFFER_SIZE: usize = 1024 * 1024;    // 1MB
 
+/// Buffers that grew beyond this are dropped instead of pooled
+const MAX_POOLED_CAPACITY: usize = 4 * LARGE_BUFFER_SIZE;
+
 const POOL_SIZE_SMALL: usize = 1000;
 const POOL_SIZE_MEDIUM: usize = 500;
 const POOL_SIZE_LARGE: usize = 100;
@@ -46,46 +56,91 @@ impl MemoryPool {
         Self {
             pools: Arc::new(Mutex::new(pools)),
             stats: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            allocations: AtomicU64::new(0),
+            recycled: AtomicU64::new(0),
         }
     }
 
-    /// Acquire a buffer of at least the specified size
+    /// Acquire an empty buffer with at least the specified capacity
     /// Returns a buffer from the pool if available, otherwise allocates
-    pub fn acquire(&self, size: usize) -> Bytes {
-        self.stats.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    pub fn acquire(&self, size: usize) -> BytesMut {
+        self.stats.fetch_add(1, Ordering::Relaxed);
 
-        let mut pools = self.pools.lock();
-        
-        let buffer = if size <= SMALL_BUFFER_SIZE {
-            pools.small.pop().unwrap_or_else(|| BytesMut::with_capacity(SMALL_BUFFER_SIZE))
-        } else if size <= MEDIUM_BUFFER_SIZE {
-            pools.medium.pop().unwrap_or_else(|| BytesMut::with_capacity(MEDIUM_BUFFER_SIZE))
-        } else if size <= LARGE_BUFFER_SIZE {
-            pools.large.pop().unwrap_or_else(|| BytesMut::with_capacity(LARGE_BUFFER_SIZE))
-        } else {
-            // For very large sizes, allocate on demand
-            BytesMut::with_capacity(size)
+        let pooled = {
+            let mut pools = self.pools.lock();
+            
+            if size <= SMALL_BUFFER_SIZE {
+                pools.small.pop()
+            } else if size <= MEDIUM_BUFFER_SIZE {
+                pools.medium.pop()
+            } else if size <= LARGE_BUFFER_SIZE {
+                pools.large.pop()
+            } else {
+                None
+            }
         };
 
-        buffer.freeze()
+        match pooled {
+            Some(buffer) => buffer,
+            None => {
+                self.allocations.fetch_add(1, Ordering::Relaxed);
+                
+                // Allocate at the size-class capacity so the buffer can be recycled
+                let capacity = if size <= SMALL_BUFFER_SIZE {
+                    SMALL_BUFFER_SIZE
+                } else if size <= MEDIUM_BUFFER_SIZE {
+                    MEDIUM_BUFFER_SIZE
+                } else if size <= LARGE_BUFFER_SIZE {
+                    LARGE_BUFFER_SIZE
+                } else {
+                    // For very large sizes, allocate on demand
+                    size
+                };
+                BytesMut::with_capacity(capacity)
+            }
+        }
+    }
+
+    /// Acquire a buffer that returns itself to this pool when dropped
+    pub fn acquire_pooled(self: &Arc<Self>, size: usize) -> PooledBuffer {
+        PooledBuffer {
+            buffer: self.acquire(size),
+            pool: self.clone(),
+        }
     }
 
     /// Return a buffer to the pool for reuse
-    /// Note: In practice, Bytes uses Arc internally, so this is automatic
-    /// This method is here for future explicit pool management
-    #[allow(dead_code)]
-    pub fn release(&self, buffer: Bytes) {
+    /// The buffer is cleared and filed under the largest size class it can serve
+    pub fn release(&self, mut buffer: BytesMut) {
         let capacity = buffer.capacity();
         
-        if capacity == 0 {
+        if capacity < SMALL_BUFFER_SIZE || capacity > MAX_POOLED_CAPACITY {
             return;
         }
 
-        // Convert back to BytesMut if possible (requires unique ownership)
-        // For now, we let the buffer drop naturally
-        // In a more advanced implementation, we could use a custom allocator
+        buffer.clear();
+        
+        let mut pools = self.pools.lock();
         
-        drop(buffer);
+        let (class, limit) = if capacity >= LARGE_BUFFER_SIZE {
+            (&mut pools.large, POOL_SIZE_LARGE)
+        } else if capacity >= MEDIUM_BUFFER_SIZE {
+            (&mut pools.medium, POOL_SIZE_MEDIUM)
+        } else {
+            (&mut pools.small, POOL_SIZE_SMALL)
+        };
+
+        if class.len() < limit {
+            class.push(buffer);
+            self.recycled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Return frozen bytes to the pool if this is the last reference
+    pub fn release_bytes(&self, bytes: Bytes) {
+        if let Ok(buffer) = bytes.try_into_mut() {
+            self.release(buffer);
+        }
     }
 
     /// Get pool statistics
@@ -93,7 +148,9 @@ impl MemoryPool {
         let pools = self.pools.lock();
         
         PoolStats {
-            total_acquisitions: self.stats.load(std::sync::atomic::Ordering::Relaxed),
+            total_acquisitions: self.stats.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            recycled: self.recycled.load(Ordering::Relaxed),
             small_available: pools.small.len(),
             medium_available: pools.medium.len(),
             large_available: pools.large.len(),
@@ -107,15 +164,69 @@ impl Default for MemoryPool {
     }
 }
 
+/// Buffer checked out of a MemoryPool
+/// Returned to the pool on drop, including after being frozen into Bytes
+pub struct PooledBuffer {
+    buffer: BytesMut,
+    pool: Arc<MemoryPool>,
+}
+
+impl PooledBuffer {
+    /// Freeze into Bytes without copying
+    /// The underlying buffer goes back to the pool once the last Bytes clone is dropped
+    pub fn into_bytes(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl std::ops::Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        &self.buffer
+    }
+}
+
+impl std::ops::DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        &mut self.buffer
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.release(std::mem::take(&mut self.buffer));
+    }
+}
+
 /// Statistics for memory pool performance
 #[derive(Debug, Clone, Copy)]
 pub struct PoolStats {
     pub total_acquisitions: u64,
+    pub allocations: u64,
+    pub recycled: u64,
     pub small_available: usize,
     pub medium_available: usize,
     pub large_available: usize,
 }
 
+impl PoolStats {
+    /// Fraction of acquisitions served from recycled buffers
+    pub fn reuse_ratio(&self) -> f64 {
+        if self.total_acquisitions == 0 {
+            0.0
+        } else {
+            1.0 - (self.allocations as f64 / self.total_acquisitions as f64)
+        }
+    }
+}
+
 /// Zero-copy data passing utilities
 pub mod zero_copy {
     use bytes::Bytes;
@@ -162,6 +273,45 @@ mod tests {
         
         let stats = pool.stats();
         assert_eq!(stats.total_acquisitions, 3);
+        assert_eq!(stats.allocations, 3);
+    }
+
+    #[test]
+    fn test_buffer_recycling_avoids_allocation() {
+        let pool = MemoryPool::new();
+        
+        let mut buffer = pool.acquire(1024);
+        buffer.extend_from_slice(b"hello");
+        pool.release(buffer);
+        
+        // Warm pool serves repeated cycles without fresh allocations
+        for _ in 0..100 {
+            let buffer = pool.acquire(1024);
+            assert!(buffer.is_empty());
+            pool.release(buffer);
+        }
+        
+        let stats = pool.stats();
+        assert_eq!(stats.allocations, 1);
+        assert_eq!(stats.total_acquisitions, 101);
+        assert!(stats.reuse_ratio() > 0.98);
+    }
+
+    #[test]
+    fn test_pooled_bytes_return_on_drop() {
+        let pool = Arc::new(MemoryPool::new());
+        
+        let mut buffer = pool.acquire_pooled(32 * 1024);
+        buffer.extend_from_slice(b"response body");
+        let bytes = buffer.into_bytes();
+        let clone = bytes.clone();
+        assert_eq!(&clone[..], b"response body");
+        
+        drop(bytes);
+        assert_eq!(pool.stats().medium_available, 0);
+        
+        drop(clone);
+        assert_eq!(pool.stats().medium_available, 1);
     }
 
     #[test]
@@ -0,0 +1,428 @@
+// Site Artifacts
+// Modules and static content pulled from S3-compatible storage into a checksummed local cache
+
+use super::s3::{self, ObjectEntry, S3Source};
+use crate::tenancy::secrets::{SealedSecret, SecretKey};
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use http_body_util::BodyExt;
+use parking_lot::Mutex;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, warn};
+
+/// File mapping object names to what they last held, inside the cache directory
+const INDEX_FILE: &str = "index.json";
+
+/// `[artifacts]`: the node's default object storage, and where fetched objects are cached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtifactsConfig {
+    /// `https://s3.amazonaws.com`, `http://minio:9000`, ... ("" = tenants must bring their own bucket)
+    pub endpoint: String,
+
+    pub region: String,
+
+    pub bucket: String,
+
+    /// Prepended to every key, such as `pear/`
+    pub prefix: String,
+
+    /// Address the bucket in the path rather than as a subdomain
+    pub path_style: bool,
+
+    /// Fetched objects, by SHA-256 ("" = `<storage root>/artifacts`)
+    pub cache_dir: String,
+
+    /// Largest object fetched
+    pub max_object_mb: u64,
+}
+
+impl Default for ArtifactsConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            region: "us-east-1".to_string(),
+            bucket: String::new(),
+            prefix: String::new(),
+            path_style: false,
+            cache_dir: String::new(),
+            max_object_mb: 256,
+        }
+    }
+}
+
+impl ArtifactsConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.endpoint.is_empty() {
+            check_endpoint(&self.endpoint)?;
+            if self.bucket.is_empty() {
+                bail!("bucket must be set with endpoint");
+            }
+        }
+        if self.max_object_mb == 0 {
+            bail!("max_object_mb must be positive");
+        }
+        Ok(())
+    }
+
+    /// The node's bucket, read with `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+    pub fn node_source(&self) -> Result<Option<S3Source>> {
+        if self.endpoint.is_empty() {
+            return Ok(None);
+        }
+        let access_key_id = std::env::var("AWS_ACCESS_KEY_ID")
+            .context("[artifacts] endpoint is set but AWS_ACCESS_KEY_ID is not")?;
+        let secret_access_key = std::env::var("AWS_SECRET_ACCESS_KEY")
+            .context("[artifacts] endpoint is set but AWS_SECRET_ACCESS_KEY is not")?;
+        Ok(Some(S3Source {
+            endpoint: self.endpoint.trim_end_matches('/').to_string(),
+            region: self.region.clone(),
+            bucket: self.bucket.clone(),
+            prefix: self.prefix.clone(),
+            path_style: self.path_style,
+            access_key_id,
+            secret_access_key,
+        }))
+    }
+}
+
+fn check_endpoint(endpoint: &str) -> Result<()> {
+    if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+        bail!("endpoint must be an http:// or https:// URL ({})", endpoint);
+    }
+    let uri: hyper::Uri = endpoint.parse().context("Invalid endpoint")?;
+    if uri.host().is_none() {
+        bail!("endpoint has no host ({})", endpoint);
+    }
+    Ok(())
+}
+
+/// A tenant's own bucket, used instead of the node's
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantArtifactSource {
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+
+    #[serde(default)]
+    pub prefix: String,
+
+    #[serde(default)]
+    pub path_style: bool,
+
+    pub access_key_id: String,
+
+    /// Sealed like a site secret, with the tenant's sealing key
+    pub secret_access_key: SealedSecret,
+}
+
+/// Name the secret access key is sealed under
+pub const SECRET_NAME: &str = "AWS_SECRET_ACCESS_KEY";
+
+/// A tenant's bucket as given to the API, its secret still in the clear
+#[derive(Clone, Deserialize)]
+pub struct ArtifactSourceRequest {
+    pub endpoint: String,
+
+    #[serde(default)]
+    pub region: String,
+
+    pub bucket: String,
+
+    #[serde(default)]
+    pub prefix: String,
+
+    #[serde(default)]
+    pub path_style: bool,
+
+    pub access_key_id: String,
+
+    pub secret_access_key: String,
+}
+
+impl TenantArtifactSource {
+    /// Validate a requested source and seal its secret access key
+    pub fn seal(request: &ArtifactSourceRequest, key: &SecretKey) -> Result<Self> {
+        check_endpoint(&request.endpoint)?;
+        if request.bucket.is_empty() || request.access_key_id.is_empty() || request.secret_access_key.is_empty() {
+            bail!("bucket, access_key_id and secret_access_key are required");
+        }
+        Ok(Self {
+            endpoint: request.endpoint.trim_end_matches('/').to_string(),
+            region: if request.region.is_empty() { "us-east-1".to_string() } else { request.region.clone() },
+            bucket: request.bucket.clone(),
+            prefix: request.prefix.clone(),
+            path_style: request.path_style,
+            access_key_id: request.access_key_id.clone(),
+            secret_access_key: key.seal(SECRET_NAME, &request.secret_access_key)?,
+        })
+    }
+
+    /// The source with its secret opened
+    pub fn resolve(&self, key: &SecretKey) -> Result<S3Source> {
+        Ok(S3Source {
+            endpoint: self.endpoint.clone(),
+            region: self.region.clone(),
+            bucket: self.bucket.clone(),
+            prefix: self.prefix.clone(),
+            path_style: self.path_style,
+            access_key_id: self.access_key_id.clone(),
+            secret_access_key: key.open(SECRET_NAME, &self.secret_access_key)?,
+        })
+    }
+}
+
+/// An object in the local cache, checked against its SHA-256
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub key: String,
+    pub sha256: String,
+    pub size: u64,
+    pub path: PathBuf,
+}
+
+impl Artifact {
+    pub fn read(&self) -> Result<Vec<u8>> {
+        std::fs::read(&self.path).with_context(|| format!("Failed to read cached artifact {}", self.key))
+    }
+}
+
+/// What an object held when it was last fetched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    etag: String,
+    sha256: String,
+}
+
+/// Objects fetched from object storage, stored by SHA-256 so nodes and sites share them
+pub struct ArtifactStore {
+    cache_dir: PathBuf,
+    max_object_bytes: u64,
+    node_source: Option<S3Source>,
+
+    /// `bucket/prefix/key` → the ETag and SHA-256 it was fetched with
+    index: Mutex<HashMap<String, IndexEntry>>,
+}
+
+impl ArtifactStore {
+    /// Open the cache, keeping it under `storage_root` unless `cache_dir` is set
+    pub fn open(config: &ArtifactsConfig, storage_root: &Path) -> Result<Self> {
+        let cache_dir = if config.cache_dir.is_empty() {
+            storage_root.join("artifacts")
+        } else {
+            PathBuf::from(&config.cache_dir)
+        };
+        std::fs::create_dir_all(&cache_dir)
+            .with_context(|| format!("Failed to create artifact cache {}", cache_dir.display()))?;
+
+        let index = match std::fs::read(cache_dir.join(INDEX_FILE)) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!(error = %e, "Artifact index unreadable, starting empty");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        let node_source = config.node_source()?;
+        if let Some(source) = &node_source {
+            info!(endpoint = %source.endpoint, bucket = %source.bucket, "Artifact source configured");
+        }
+
+        Ok(Self {
+            cache_dir,
+            max_object_bytes: config.max_object_mb * 1024 * 1024,
+            node_source,
+            index: Mutex::new(index),
+        })
+    }
+
+    /// The node's bucket, for tenants without their own
+    pub fn node_source(&self) -> Option<&S3Source> {
+        self.node_source.as_ref()
+    }
+
+    /// Every object under `prefix`
+    pub async fn list(&self, source: &S3Source, prefix: &str) -> Result<Vec<ObjectEntry>> {
+        source.list(prefix).await
+    }
+
+    /// An object from the cache, fetched first if it isn't there or changed since
+    /// With `sha256`, a cached copy is used without asking the store, and a fetched one must match it.
+    pub async fn fetch(&self, source: &S3Source, key: &str, sha256: Option<&str>) -> Result<Artifact> {
+        if let Some(expected) = sha256 {
+            let expected = expected.to_ascii_lowercase();
+            hex::decode(&expected).ok().filter(|bytes| bytes.len() == 32).context("sha256 must be 64 hex digits")?;
+            if let Some(artifact) = self.cached(key, &expected)? {
+                return Ok(artifact);
+            }
+            return self.download(source, key, Some(&expected)).await;
+        }
+
+        let name = source.object_name(key);
+        let known = self.index.lock().get(&name).cloned();
+        if let Some(known) = known {
+            if source.head(key).await? == known.etag {
+                if let Some(artifact) = self.cached(key, &known.sha256)? {
+                    return Ok(artifact);
+                }
+            }
+        }
+        self.download(source, key, None).await
+    }
+
+    /// An object from a listing, fetched unless the cache holds it with the listed ETag
+    pub async fn fetch_listed(&self, source: &S3Source, entry: &ObjectEntry) -> Result<Artifact> {
+        match self.known(source, entry)? {
+            Some(artifact) => Ok(artifact),
+            None => self.download(source, &entry.key, None).await,
+        }
+    }
+
+    /// A fetched object with the given entry's ETag, as long as it can still be checked
+    pub fn known(&self, source: &S3Source, entry: &ObjectEntry) -> Result<Option<Artifact>> {
+        let known = self.index.lock().get(&source.object_name(&entry.key)).cloned();
+        match known {
+            Some(known) if known.etag == entry.etag => self.cached(&entry.key, &known.sha256),
+            _ => Ok(None),
+        }
+    }
+
+    /// The cached copy with this SHA-256, if it is present and intact
+    fn cached(&self, key: &str, sha256: &str) -> Result<Option<Artifact>> {
+        let path = self.cache_dir.join(sha256);
+        let Ok(mut file) = std::fs::File::open(&path) else { return Ok(None) };
+
+        let mut context = digest::Context::new(&digest::SHA256);
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut size = 0u64;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            context.update(&buffer[..read]);
+            size += read as u64;
+        }
+        if hex::encode(context.finish()) != sha256 {
+            warn!(key = %key, path = %path.display(), "Cached artifact corrupt, fetching it again");
+            std::fs::remove_file(&path)?;
+            return Ok(None);
+        }
+        Ok(Some(Artifact { key: key.to_string(), sha256: sha256.to_string(), size, path }))
+    }
+
+    async fn download(&self, source: &S3Source, key: &str, expected: Option<&str>) -> Result<Artifact> {
+        let response = source.get(key).await?;
+        let etag = s3::header(&response, "etag").unwrap_or_default();
+        let stored = s3::header(&response, "x-amz-checksum-sha256")
+            .and_then(|checksum| base64::engine::general_purpose::STANDARD.decode(checksum).ok())
+            .map(hex::encode);
+
+        let staging = self.cache_dir.join(format!(".fetch-{}", uuid::Uuid::new_v4()));
+        let result = self.receive(response.into_body(), &staging, key).await;
+        let (sha256, size) = match result {
+            Ok(received) => received,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&staging).await;
+                return Err(e);
+            }
+        };
+
+        for (wanted, what) in [(expected, "the expected"), (stored.as_deref(), "the stored")] {
+            if let Some(wanted) = wanted {
+                if wanted != sha256 {
+                    let _ = tokio::fs::remove_file(&staging).await;
+                    bail!("Object {} has SHA-256 {}, not {} {}", key, sha256, what, wanted);
+                }
+            }
+        }
+
+        let path = self.cache_dir.join(&sha256);
+        tokio::fs::rename(&staging, &path).await.context("Failed to store fetched artifact")?;
+        self.index.lock().insert(source.object_name(key), IndexEntry { etag, sha256: sha256.clone() });
+        self.save_index()?;
+
+        debug!(key = %key, sha256 = %sha256, size, "Artifact fetched");
+        Ok(Artifact { key: key.to_string(), sha256, size, path })
+    }
+
+    /// Stream a body to `path`, returning its SHA-256 and size
+    async fn receive(&self, mut body: hyper::body::Incoming, path: &Path, key: &str) -> Result<(String, u64)> {
+        let mut file = tokio::fs::File::create(path).await?;
+        let mut context = digest::Context::new(&digest::SHA256);
+        let mut size = 0u64;
+        while let Some(frame) = body.frame().await {
+            let frame = frame.with_context(|| format!("Failed to read {}", key))?;
+            let Ok(data) = frame.into_data() else { continue };
+            size += data.len() as u64;
+            if size > self.max_object_bytes {
+                bail!("Object {} is larger than {} MB", key, self.max_object_bytes / (1024 * 1024));
+            }
+            context.update(&data);
+            file.write_all(&data).await?;
+        }
+        file.sync_all().await?;
+        Ok((hex::encode(context.finish()), size))
+    }
+
+    fn save_index(&self) -> Result<()> {
+        let data = serde_json::to_vec(&*self.index.lock())?;
+        let staging = self.cache_dir.join(format!("{}.tmp", INDEX_FILE));
+        std::fs::write(&staging, data)?;
+        std::fs::rename(&staging, self.cache_dir.join(INDEX_FILE))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_source_seals_secret() {
+        let key = SecretKey::generate().unwrap();
+        let request = ArtifactSourceRequest {
+            endpoint: "https://s3.eu-west-1.amazonaws.com/".to_string(),
+            region: String::new(),
+            bucket: "acme-builds".to_string(),
+            prefix: "site/".to_string(),
+            path_style: false,
+            access_key_id: "AKID".to_string(),
+            secret_access_key: "s3cr3t".to_string(),
+        };
+        let source = TenantArtifactSource::seal(&request, &key).unwrap();
+        assert_eq!(source.region, "us-east-1");
+        assert!(!serde_json::to_string(&source).unwrap().contains("s3cr3t"));
+        let resolved = source.resolve(&key).unwrap();
+        assert_eq!(resolved.endpoint, "https://s3.eu-west-1.amazonaws.com");
+        assert_eq!(resolved.secret_access_key, "s3cr3t");
+        assert!(!format!("{:?}", resolved).contains("s3cr3t"));
+
+        let schemeless = ArtifactSourceRequest { endpoint: "s3.amazonaws.com".to_string(), ..request.clone() };
+        assert!(TenantArtifactSource::seal(&schemeless, &key).is_err());
+        let bucketless = ArtifactSourceRequest { bucket: String::new(), ..request };
+        assert!(TenantArtifactSource::seal(&bucketless, &key).is_err());
+    }
+
+    #[test]
+    fn test_cache_is_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ArtifactStore::open(&ArtifactsConfig::default(), dir.path()).unwrap();
+        let data = b"(module)";
+        let sha256 = hex::encode(digest::digest(&digest::SHA256, data));
+        std::fs::write(dir.path().join("artifacts").join(&sha256), data).unwrap();
+
+        let artifact = store.cached("app.wasm", &sha256).unwrap().unwrap();
+        assert_eq!(artifact.read().unwrap(), data);
+        assert_eq!(artifact.size, data.len() as u64);
+
+        // A corrupt copy is dropped rather than served
+        std::fs::write(&artifact.path, b"(module $evil)").unwrap();
+        assert!(store.cached("app.wasm", &sha256).unwrap().is_none());
+        assert!(!artifact.path.exists());
+    }
+}
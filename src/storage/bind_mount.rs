@@ -5,7 +5,7 @@ use wasmtime::*;
 use wasmtime_wasi::{WasiCtxBuilder, Dir, DirPerms, FilePerms};
 use std::path::Path;
 use anyhow::{Result, Context};
-use tracing::debug;
+use tracing::{debug, warn};
 
 /// Configure bind mount for a Cage
 pub fn configure_bind_mount<P: AsRef<Path>>(
@@ -62,19 +62,26 @@ pub fn configure_rw_bind_mount<P: AsRef<Path>>(
 }
 
 /// Standard bind mount configuration for web applications
+/// `writable` is false once the tenant is over its storage quota, making /tmp read-only
 pub fn standard_web_mounts<P: AsRef<Path>>(
     wasi_builder: WasiCtxBuilder,
     site_path: P,
+    writable: bool,
 ) -> Result<WasiCtxBuilder> {
     let site_path = site_path.as_ref();
     
     // Mount site files at /var/www (read-only)
     let wasi_builder = configure_bind_mount(wasi_builder, site_path, "/var/www")?;
     
-    // Create temp directory for uploads/cache (read-write)
+    // Create temp directory for uploads/cache (read-write while within quota)
     let temp_path = site_path.join("tmp");
     std::fs::create_dir_all(&temp_path)?;
-    let wasi_builder = configure_rw_bind_mount(wasi_builder, temp_path, "/tmp")?;
+    let wasi_builder = if writable {
+        configure_rw_bind_mount(wasi_builder, temp_path, "/tmp")?
+    } else {
+        warn!(site_path = %site_path.display(), "Storage quota reached, mounting /tmp read-only");
+        configure_bind_mount(wasi_builder, temp_path, "/tmp")?
+    };
     
     debug!("Standard web mounts configured");
     
@@ -100,7 +107,10 @@ mod tests {
         let temp = TempDir::new().unwrap();
         let wasi_builder = WasiCtxBuilder::new();
         
-        let result = standard_web_mounts(wasi_builder, temp.path());
+        let result = standard_web_mounts(wasi_builder, temp.path(), true);
+        assert!(result.is_ok());
+        
+        let result = standard_web_mounts(WasiCtxBuilder::new(), temp.path(), false);
         assert!(result.is_ok());
     }
 }
@@ -0,0 +1,590 @@
+// Site Databases
+// Per-site SQLite databases for guests, with tenant size quotas and backups
+
+use anyhow::{Context, Result, bail};
+use dashmap::DashMap;
+use parking_lot::{Mutex, RwLock};
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use rusqlite::limits::Limit;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{Connection, DatabaseName, ErrorCode, OpenFlags};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// Directory inside the site directory backups are written to
+const BACKUP_DIR: &str = "backups";
+
+/// Pragmas guests may set; any other setting could lift the size limit
+const GUEST_SETTINGS: &[&str] = &["foreign_keys", "user_version"];
+
+/// Pragmas guests may run to inspect the schema
+const GUEST_INTROSPECTION: &[&str] = &[
+    "table_info", "table_xinfo", "table_list", "index_list", "index_info", "index_xinfo",
+    "foreign_key_list", "foreign_key_check", "integrity_check", "quick_check",
+];
+
+/// Settings guests may read but not change
+const GUEST_READ_ONLY: &[&str] = &["page_count", "page_size", "max_page_count", "journal_mode"];
+
+/// Database settings shared by every site
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseConfig {
+    /// Give guests a database
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Database file name inside each site directory
+    #[serde(default = "default_file_name")]
+    pub file_name: String,
+
+    /// Size limit of a single site database
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+
+    /// Rows returned by one query at most
+    #[serde(default = "default_max_rows")]
+    pub max_rows: usize,
+
+    /// How long a statement waits for a lock held by another Cage
+    #[serde(default = "default_busy_timeout")]
+    pub busy_timeout_ms: u64,
+
+    /// Seconds between automatic backups (0 disables them)
+    #[serde(default = "default_backup_interval")]
+    pub backup_interval_secs: u64,
+
+    /// Backups kept per site
+    #[serde(default = "default_backup_keep")]
+    pub backup_keep: usize,
+
+    /// Command run after each backup with the backup path as its argument
+    #[serde(default)]
+    pub backup_command: Option<String>,
+}
+
+fn default_enabled() -> bool { true }
+fn default_file_name() -> String { "site.db".to_string() }
+fn default_max_size_mb() -> u64 { 256 }
+fn default_max_rows() -> usize { 10_000 }
+fn default_busy_timeout() -> u64 { 5000 }
+fn default_backup_interval() -> u64 { 3600 }
+fn default_backup_keep() -> usize { 24 }
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            file_name: default_file_name(),
+            max_size_mb: default_max_size_mb(),
+            max_rows: default_max_rows(),
+            busy_timeout_ms: default_busy_timeout(),
+            backup_interval_secs: default_backup_interval(),
+            backup_keep: default_backup_keep(),
+            backup_command: None,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// Check the settings are usable
+    pub fn validate(&self) -> Result<()> {
+        if self.file_name.is_empty() || self.file_name.contains('/') || self.file_name.starts_with('.') {
+            bail!("database.file_name must be a plain file name");
+        }
+        if self.max_size_mb == 0 {
+            bail!("database.max_size_mb must be greater than 0");
+        }
+        if self.max_rows == 0 {
+            bail!("database.max_rows must be greater than 0");
+        }
+        if self.backup_interval_secs > 0 && self.backup_keep == 0 {
+            bail!("database.backup_keep must be at least 1 when backups are enabled");
+        }
+        Ok(())
+    }
+}
+
+/// Rows returned by a query; values are JSON, blobs become byte arrays
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// A finished backup, as handed to backup hooks
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub tenant_id: String,
+    pub site_id: String,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+}
+
+/// Called after every successful backup, e.g. to ship it off the host
+pub trait BackupHook: Send + Sync {
+    fn on_backup(&self, backup: &BackupInfo) -> Result<()>;
+}
+
+/// Runs the configured `backup_command` with the backup path
+struct CommandHook {
+    command: String,
+}
+
+impl BackupHook for CommandHook {
+    fn on_backup(&self, backup: &BackupInfo) -> Result<()> {
+        let status = std::process::Command::new(&self.command)
+            .arg(&backup.path)
+            .env("PEAR_TENANT_ID", &backup.tenant_id)
+            .env("PEAR_SITE_ID", &backup.site_id)
+            .status()
+            .with_context(|| format!("Failed to run backup command {}", self.command))?;
+        if !status.success() {
+            bail!("Backup command {} exited with {}", self.command, status);
+        }
+        Ok(())
+    }
+}
+
+/// One site's database file
+pub struct SiteDatabase {
+    tenant_id: String,
+    site_id: String,
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+impl SiteDatabase {
+    /// Open or create a database, capped at `max_size_bytes`
+    pub fn open(tenant_id: &str, site_id: &str, path: &Path, max_size_bytes: u64, busy_timeout: Duration) -> Result<Self> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .with_context(|| format!("Failed to open database {}", path.display()))?;
+
+        conn.busy_timeout(busy_timeout)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "trusted_schema", false)?;
+
+        // SQLite rejects writes that would grow the file past the limit
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        conn.pragma_update(None, "max_page_count", (max_size_bytes / page_size).max(1))?;
+
+        // Guests must not reach other files (ATTACH, VACUUM INTO) or change settings
+        conn.set_limit(Limit::SQLITE_LIMIT_ATTACHED, 0);
+        conn.authorizer(Some(authorize));
+
+        Ok(Self {
+            tenant_id: tenant_id.to_string(),
+            site_id: site_id.to_string(),
+            path: path.to_path_buf(),
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Current size of the database in bytes
+    pub fn size_bytes(&self) -> Result<u64> {
+        let conn = self.conn.lock();
+        let pages: u64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok(pages * page_size)
+    }
+
+    /// Run a statement, returning the number of changed rows
+    pub fn exec(&self, sql: &str, params: &[Value]) -> Result<usize> {
+        let params = bind(params)?;
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare(sql)?;
+        statement.execute(rusqlite::params_from_iter(params)).map_err(quota_error)
+    }
+
+    /// Run a query, returning at most `max_rows` rows
+    pub fn query(&self, sql: &str, params: &[Value], max_rows: usize) -> Result<QueryResult> {
+        let params = bind(params)?;
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare(sql)?;
+
+        let columns: Vec<String> = statement.column_names().into_iter().map(String::from).collect();
+        let mut rows = statement.query(rusqlite::params_from_iter(params))?;
+        let mut result = QueryResult { columns, rows: Vec::new() };
+
+        while let Some(row) = rows.next().map_err(quota_error)? {
+            if result.rows.len() == max_rows {
+                bail!("Query returned more than {} rows", max_rows);
+            }
+            let values = (0..result.columns.len())
+                .map(|i| row.get_ref(i).map(to_json))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            result.rows.push(values);
+        }
+
+        Ok(result)
+    }
+
+    /// Write a consistent copy of the database to `dest`
+    pub fn backup_to(&self, dest: &Path) -> Result<()> {
+        self.conn.lock()
+            .backup(DatabaseName::Main, dest, None)
+            .with_context(|| format!("Failed to back up database of site {}", self.site_id))
+    }
+
+    pub fn site_id(&self) -> &str {
+        &self.site_id
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Every open site database, with per-tenant size quotas
+pub struct DatabaseManager {
+    config: DatabaseConfig,
+    databases: DashMap<String, Arc<SiteDatabase>>,
+
+    /// Combined size limit of a tenant's databases, in bytes
+    tenant_quotas: DashMap<String, u64>,
+
+    hooks: RwLock<Vec<Arc<dyn BackupHook>>>,
+}
+
+impl DatabaseManager {
+    /// Create a manager; nothing is opened until a site asks for its database
+    pub fn new(config: DatabaseConfig) -> Result<Self> {
+        config.validate()?;
+
+        let mut hooks: Vec<Arc<dyn BackupHook>> = Vec::new();
+        if let Some(command) = &config.backup_command {
+            hooks.push(Arc::new(CommandHook { command: command.clone() }));
+        }
+
+        Ok(Self {
+            config,
+            databases: DashMap::new(),
+            tenant_quotas: DashMap::new(),
+            hooks: RwLock::new(hooks),
+        })
+    }
+
+    /// Open a site's database inside its site directory
+    pub fn open(self: &Arc<Self>, tenant_id: &str, site_id: &str, site_dir: &Path) -> Result<GuestDatabase> {
+        if !self.databases.contains_key(site_id) {
+            let path = site_dir.join(&self.config.file_name);
+            let db = SiteDatabase::open(
+                tenant_id,
+                site_id,
+                &path,
+                self.config.max_size_mb * 1024 * 1024,
+                Duration::from_millis(self.config.busy_timeout_ms),
+            )?;
+            info!(tenant_id = %tenant_id, site_id = %site_id, path = %path.display(), "Site database opened");
+            self.databases.entry(site_id.to_string()).or_insert_with(|| Arc::new(db));
+        }
+
+        Ok(GuestDatabase {
+            manager: self.clone(),
+            site_id: site_id.to_string(),
+        })
+    }
+
+    /// Close a site's database, e.g. when the site is removed
+    pub fn close(&self, site_id: &str) {
+        if self.databases.remove(site_id).is_some() {
+            info!(site_id = %site_id, "Site database closed");
+        }
+    }
+
+    /// Set or clear a tenant's combined database size limit
+    pub fn set_tenant_quota(&self, tenant_id: &str, max_bytes: Option<u64>) {
+        match max_bytes {
+            Some(bytes) => { self.tenant_quotas.insert(tenant_id.to_string(), bytes); }
+            None => { self.tenant_quotas.remove(tenant_id); }
+        }
+    }
+
+    /// Combined size of a tenant's open databases
+    pub fn tenant_usage(&self, tenant_id: &str) -> u64 {
+        self.databases.iter()
+            .filter(|db| db.tenant_id == tenant_id)
+            .filter_map(|db| db.size_bytes().ok())
+            .sum()
+    }
+
+    /// Register a hook run after every backup
+    pub fn add_backup_hook(&self, hook: Arc<dyn BackupHook>) {
+        self.hooks.write().push(hook);
+    }
+
+    fn database(&self, site_id: &str) -> Result<Arc<SiteDatabase>> {
+        self.databases.get(site_id)
+            .map(|db| db.clone())
+            .with_context(|| format!("No database open for site {}", site_id))
+    }
+
+    fn exec(&self, site_id: &str, sql: &str, params: &[Value]) -> Result<usize> {
+        let db = self.database(site_id)?;
+
+        // Reads stay available once a tenant is over quota, writes do not
+        if let Some(quota) = self.tenant_quotas.get(&db.tenant_id).map(|q| *q) {
+            if self.tenant_usage(&db.tenant_id) >= quota {
+                bail!("Tenant database quota of {} MB exceeded", quota / (1024 * 1024));
+            }
+        }
+
+        db.exec(sql, params)
+    }
+
+    fn query(&self, site_id: &str, sql: &str, params: &[Value]) -> Result<QueryResult> {
+        self.database(site_id)?.query(sql, params, self.config.max_rows)
+    }
+
+    /// Back up every open database and run the backup hooks
+    pub fn backup_all(&self) -> usize {
+        let databases: Vec<_> = self.databases.iter().map(|db| db.clone()).collect();
+        let mut completed = 0;
+
+        for db in databases {
+            match self.backup(&db) {
+                Ok(backup) => {
+                    completed += 1;
+                    for hook in self.hooks.read().iter() {
+                        if let Err(e) = hook.on_backup(&backup) {
+                            warn!(site_id = %backup.site_id, error = %e, "Backup hook failed");
+                        }
+                    }
+                }
+                Err(e) => error!(site_id = %db.site_id, error = %e, "Database backup failed"),
+            }
+        }
+
+        completed
+    }
+
+    fn backup(&self, db: &SiteDatabase) -> Result<BackupInfo> {
+        let dir = db.path.parent().unwrap_or(Path::new(".")).join(BACKUP_DIR);
+        std::fs::create_dir_all(&dir)?;
+
+        let stem = Path::new(&self.config.file_name).file_stem().and_then(|s| s.to_str()).unwrap_or("site");
+        let path = dir.join(format!("{}-{}.db", stem, chrono::Utc::now().format("%Y%m%dT%H%M%S%3f")));
+        db.backup_to(&path)?;
+        let size_bytes = std::fs::metadata(&path)?.len();
+
+        // Timestamped names sort oldest first
+        let mut existing: Vec<PathBuf> = std::fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(stem) && n.ends_with(".db")))
+            .collect();
+        existing.sort();
+        let surplus = existing.len().saturating_sub(self.config.backup_keep);
+        for old in &existing[..surplus] {
+            std::fs::remove_file(old)?;
+        }
+
+        debug!(site_id = %db.site_id, path = %path.display(), size_bytes, "Site database backed up");
+
+        Ok(BackupInfo {
+            tenant_id: db.tenant_id.clone(),
+            site_id: db.site_id.clone(),
+            path,
+            size_bytes,
+        })
+    }
+
+    /// Back up all databases periodically in the background
+    pub fn start_backups(self: &Arc<Self>) {
+        if self.config.backup_interval_secs == 0 {
+            return;
+        }
+
+        let manager = self.clone();
+        let period = Duration::from_secs(self.config.backup_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                let manager = manager.clone();
+                match tokio::task::spawn_blocking(move || manager.backup_all()).await {
+                    Ok(count) => debug!(databases = count, "Scheduled database backups complete"),
+                    Err(e) => error!(error = %e, "Database backup task failed"),
+                }
+            }
+        });
+    }
+}
+
+/// A site's database as seen by its Cages
+#[derive(Clone)]
+pub struct GuestDatabase {
+    manager: Arc<DatabaseManager>,
+    site_id: String,
+}
+
+impl GuestDatabase {
+    /// Run a statement with bound parameters
+    pub fn exec(&self, sql: &str, params: &[Value]) -> Result<usize> {
+        self.manager.exec(&self.site_id, sql, params)
+    }
+
+    /// Run a query with bound parameters
+    pub fn query(&self, sql: &str, params: &[Value]) -> Result<QueryResult> {
+        self.manager.query(&self.site_id, sql, params)
+    }
+
+    pub fn site_id(&self) -> &str {
+        &self.site_id
+    }
+}
+
+impl std::fmt::Debug for GuestDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuestDatabase").field("site_id", &self.site_id).finish()
+    }
+}
+
+/// Statement authorizer for guest connections
+fn authorize(ctx: AuthContext<'_>) -> Authorization {
+    match ctx.action {
+        AuthAction::Attach { .. } | AuthAction::Detach { .. } => Authorization::Deny,
+        AuthAction::Pragma { pragma_name, pragma_value } => {
+            let name = pragma_name.to_ascii_lowercase();
+            let allowed = GUEST_SETTINGS.contains(&name.as_str())
+                || GUEST_INTROSPECTION.contains(&name.as_str())
+                || (GUEST_READ_ONLY.contains(&name.as_str()) && pragma_value.is_none());
+            if allowed { Authorization::Allow } else { Authorization::Deny }
+        }
+        _ => Authorization::Allow,
+    }
+}
+
+/// Convert JSON parameters to SQLite values
+fn bind(params: &[Value]) -> Result<Vec<SqlValue>> {
+    params.iter().map(|param| {
+        Ok(match param {
+            Value::Null => SqlValue::Null,
+            Value::Bool(b) => SqlValue::Integer(*b as i64),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => SqlValue::Integer(i),
+                None => SqlValue::Real(n.as_f64().context("Unsupported number parameter")?),
+            },
+            Value::String(s) => SqlValue::Text(s.clone()),
+            Value::Array(bytes) => SqlValue::Blob(
+                bytes.iter()
+                    .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .context("Array parameters must be byte arrays")?,
+            ),
+            Value::Object(_) => bail!("Object parameters are not supported"),
+        })
+    }).collect()
+}
+
+fn to_json(value: ValueRef<'_>) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Value::from(f),
+        ValueRef::Text(text) => Value::from(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(bytes) => Value::from(bytes.to_vec()),
+    }
+}
+
+/// Report a full database as a quota error rather than an SQLite one
+fn quota_error(e: rusqlite::Error) -> anyhow::Error {
+    match e.sqlite_error_code() {
+        Some(ErrorCode::DiskFull) => anyhow::anyhow!("Site database size limit reached"),
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn manager(config: DatabaseConfig) -> Arc<DatabaseManager> {
+        Arc::new(DatabaseManager::new(config).unwrap())
+    }
+
+    #[test]
+    fn test_exec_and_query_with_parameters() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = manager(DatabaseConfig::default()).open("acme", "blog", dir.path()).unwrap();
+
+        db.exec("CREATE TABLE posts (id INTEGER PRIMARY KEY, title TEXT, body BLOB)", &[]).unwrap();
+        let changed = db.exec("INSERT INTO posts (title, body) VALUES (?1, ?2)", &[json!("Hello"), json!([1, 2])]).unwrap();
+        assert_eq!(changed, 1);
+
+        // Parameters are bound, never spliced into the SQL
+        db.exec("INSERT INTO posts (title) VALUES (?1)", &[json!("x'); DROP TABLE posts; --")]).unwrap();
+
+        let result = db.query("SELECT id, title, body FROM posts WHERE id = ?1", &[json!(1)]).unwrap();
+        assert_eq!(result.columns, vec!["id", "title", "body"]);
+        assert_eq!(result.rows, vec![vec![json!(1), json!("Hello"), json!([1, 2])]]);
+        assert_eq!(db.query("SELECT count(*) FROM posts", &[]).unwrap().rows[0][0], json!(2));
+
+        assert!(db.exec("INSERT INTO posts (title) VALUES (?1)", &[json!({ "a": 1 })]).is_err());
+    }
+
+    #[test]
+    fn test_site_and_tenant_size_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(DatabaseConfig { max_size_mb: 1, ..Default::default() });
+        let db = manager.open("acme", "blog", dir.path()).unwrap();
+        db.exec("CREATE TABLE blobs (data BLOB)", &[]).unwrap();
+
+        let chunk = json!(vec![0u8; 256 * 1024]);
+        let result = (0..8).try_for_each(|_| db.exec("INSERT INTO blobs VALUES (?1)", std::slice::from_ref(&chunk)).map(drop));
+        assert!(result.unwrap_err().to_string().contains("size limit"));
+
+        // Guests can't lift the limit or write other files
+        assert!(db.exec("PRAGMA max_page_count = 1000000", &[]).is_err());
+        assert!(db.exec("ATTACH DATABASE ?1 AS other", &[json!(dir.path().join("other.db").to_str())]).is_err());
+        assert!(db.exec("VACUUM INTO ?1", &[json!(dir.path().join("copy.db").to_str())]).is_err());
+        assert!(!dir.path().join("copy.db").exists());
+        assert!(db.query("PRAGMA table_info(blobs)", &[]).is_ok());
+
+        // Once the tenant is over quota only reads are allowed
+        manager.set_tenant_quota("acme", Some(4096));
+        assert!(db.exec("DELETE FROM blobs", &[]).is_err());
+        assert!(db.query("SELECT count(*) FROM blobs", &[]).is_ok());
+        manager.set_tenant_quota("acme", None);
+        assert!(db.exec("DELETE FROM blobs", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_backups_are_pruned_and_hooked() {
+        struct Counter(Mutex<Vec<BackupInfo>>);
+        impl BackupHook for Counter {
+            fn on_backup(&self, backup: &BackupInfo) -> Result<()> {
+                self.0.lock().push(backup.clone());
+                Ok(())
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(DatabaseConfig { backup_keep: 2, ..Default::default() });
+        let db = manager.open("acme", "blog", dir.path()).unwrap();
+        db.exec("CREATE TABLE t (v TEXT)", &[]).unwrap();
+
+        let hook = Arc::new(Counter(Mutex::new(Vec::new())));
+        manager.add_backup_hook(hook.clone());
+        for _ in 0..3 {
+            assert_eq!(manager.backup_all(), 1);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(hook.0.lock().len(), 3);
+        assert_eq!(std::fs::read_dir(dir.path().join(BACKUP_DIR)).unwrap().count(), 2);
+
+        // A backup is a working database
+        let latest = &hook.0.lock()[2].path;
+        let copy = Connection::open(latest).unwrap();
+        assert!(copy.query_row("SELECT count(*) FROM t", [], |row| row.get::<_, i64>(0)).is_ok());
+    }
+}
@@ -0,0 +1,774 @@
+// Encryption at Rest
+// Per-tenant keys, wrapped by a node key file or Vault Transit, encrypting site files in place
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::StorageManager;
+use crate::tenancy::secrets::{self, SecretKey};
+
+/// A tenant's wrapped keys, kept in its directory
+pub const KEY_FILE: &str = "encryption.json";
+
+/// Leads every encrypted file
+const MAGIC: &[u8; 8] = b"PEARENC1";
+
+/// Plaintext bytes per sealed chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Environment variable holding the Vault token
+pub const VAULT_TOKEN_ENV: &str = "VAULT_TOKEN";
+
+/// What tenant keys are wrapped with; bound into every wrapped key
+const WRAP_AAD: &[u8] = b"pear tenant key";
+
+/// `[storage.encryption]`: how tenants that opt in have their files encrypted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// Node key wrapping tenant keys, created on first start; unused with Vault
+    pub key_file: String,
+
+    /// The key file before a rotation; tenant keys still wrapped by it are rewrapped on start
+    pub previous_key_file: String,
+
+    /// Vault server whose Transit engine wraps tenant keys instead, authenticated by VAULT_TOKEN
+    pub vault_address: String,
+
+    /// Transit key name
+    pub vault_key: String,
+
+    /// Where decrypted views of site files are kept for Cages; should be memory-backed
+    pub plaintext_dir: String,
+
+    /// Site files left in plaintext, as `*` patterns over the name, or over the path if they contain `/`
+    pub exclude: Vec<String>,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            key_file: "pear-encryption.key".to_string(),
+            previous_key_file: String::new(),
+            vault_address: String::new(),
+            vault_key: "pear".to_string(),
+            plaintext_dir: "/dev/shm/pear".to_string(),
+            // Live databases, the link to the shared module store and guest scratch space
+            exclude: vec![
+                super::SITE_MODULE_FILE.to_string(),
+                "*.db".to_string(),
+                "*.db-*".to_string(),
+                "tmp/*".to_string(),
+            ],
+        }
+    }
+}
+
+impl EncryptionConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.vault_address.is_empty() {
+            if self.key_file.is_empty() {
+                bail!("key_file must be set unless vault_address is");
+            }
+        } else {
+            if !self.vault_address.starts_with("http://") && !self.vault_address.starts_with("https://") {
+                bail!("vault_address must be an http:// or https:// URL");
+            }
+            if self.vault_key.is_empty() {
+                bail!("vault_key must be set with vault_address");
+            }
+        }
+        if self.plaintext_dir.is_empty() {
+            bail!("plaintext_dir must be set");
+        }
+        Ok(())
+    }
+
+    /// Whether a site file, by its path within the site directory, stays in plaintext
+    pub fn is_excluded(&self, relative: &str) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.exclude.iter().any(|pattern| {
+            if pattern.contains('/') {
+                glob(pattern, relative)
+            } else {
+                glob(pattern, name)
+            }
+        })
+    }
+}
+
+/// `*` matches any run of characters, everything else itself
+fn glob(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let Some(text) = text.strip_prefix(prefix) else { return false };
+            (0..=text.len()).filter(|&i| text.is_char_boundary(i)).any(|i| glob(rest, &text[i..]))
+        }
+    }
+}
+
+/// What wraps tenant keys
+pub enum MasterKey {
+    File { key: Box<SecretKey>, previous: Option<Box<SecretKey>> },
+    Vault { address: String, key: String, token: String },
+}
+
+impl MasterKey {
+    pub fn from_config(config: &EncryptionConfig) -> Result<Self> {
+        if !config.vault_address.is_empty() {
+            let token = std::env::var(VAULT_TOKEN_ENV)
+                .with_context(|| format!("{} must be set to wrap keys with Vault", VAULT_TOKEN_ENV))?;
+            return Ok(Self::Vault {
+                address: config.vault_address.trim_end_matches('/').to_string(),
+                key: config.vault_key.clone(),
+                token,
+            });
+        }
+        let key = SecretKey::load_or_create_file(Path::new(&config.key_file))
+            .context("Failed to load the encryption key")?;
+        let key = Box::new(key);
+        let previous = match config.previous_key_file.as_str() {
+            "" => None,
+            path => {
+                let hex = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read previous encryption key {}", path))?;
+                Some(Box::new(SecretKey::from_bytes(&hex::decode(hex.trim())?)?))
+            }
+        };
+        Ok(Self::File { key, previous })
+    }
+
+    /// Recorded with each tenant's keys, so a change of master is noticed
+    pub fn name(&self) -> String {
+        match self {
+            Self::File { .. } => "key-file".to_string(),
+            Self::Vault { key, .. } => format!("vault:{}", key),
+        }
+    }
+
+    async fn wrap(&self, key_bytes: &[u8]) -> Result<String> {
+        match self {
+            Self::File { key, .. } => Ok(hex::encode(key.seal_bytes(WRAP_AAD, key_bytes)?)),
+            Self::Vault { .. } => {
+                let plaintext = base64::engine::general_purpose::STANDARD.encode(key_bytes);
+                let data = self.transit("encrypt", serde_json::json!({ "plaintext": plaintext })).await?;
+                data["ciphertext"].as_str().map(str::to_string).context("Vault returned no ciphertext")
+            }
+        }
+    }
+
+    /// The key bytes, and whether they were wrapped by a previous master and should be rewrapped
+    async fn unwrap(&self, wrapped: &str) -> Result<(Vec<u8>, bool)> {
+        match self {
+            Self::File { key, previous } => {
+                let sealed = hex::decode(wrapped)?;
+                if let Ok(bytes) = key.open_bytes(WRAP_AAD, &sealed) {
+                    return Ok((bytes, false));
+                }
+                let previous = previous.as_ref().context("Tenant key is not wrapped by the encryption key")?;
+                let bytes = previous.open_bytes(WRAP_AAD, &sealed)
+                    .context("Tenant key is wrapped by neither the encryption key nor the previous one")?;
+                Ok((bytes, true))
+            }
+            Self::Vault { .. } => {
+                let data = self.transit("decrypt", serde_json::json!({ "ciphertext": wrapped })).await?;
+                let plaintext = data["plaintext"].as_str().context("Vault returned no plaintext")?;
+                let bytes = base64::engine::general_purpose::STANDARD.decode(plaintext)
+                    .context("Vault returned invalid base64")?;
+                Ok((bytes, false))
+            }
+        }
+    }
+
+    /// Call a Transit endpoint, returning the response's `data`
+    async fn transit(&self, operation: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let Self::Vault { address, key, token } = self else {
+            bail!("Not a Vault master key");
+        };
+        let url = format!("{}/v1/transit/{}/{}", address, operation, key);
+        let headers = [("X-Vault-Token", token.clone())];
+        let (status, response) = crate::ai::alerts::post_json_for_response(&url, serde_json::to_vec(&body)?, &headers, 64 * 1024)
+            .await
+            .with_context(|| format!("Vault Transit {} failed", operation))?;
+        if !status.is_success() {
+            bail!("Vault Transit {} returned {}", operation, status);
+        }
+        let response: serde_json::Value = serde_json::from_slice(&response).context("Vault returned invalid JSON")?;
+        Ok(response["data"].clone())
+    }
+}
+
+/// A tenant's key file: every key version still in use, wrapped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyFile {
+    master: String,
+    current: u32,
+    keys: BTreeMap<u32, String>,
+}
+
+/// A tenant's unwrapped keys; files name the version they were encrypted with
+pub struct TenantKeys {
+    current: u32,
+    keys: BTreeMap<u32, Arc<SecretKey>>,
+}
+
+impl TenantKeys {
+    /// Version new files are encrypted with
+    pub fn version(&self) -> u32 {
+        self.current
+    }
+
+    /// Key new files and secrets are encrypted with
+    pub fn current(&self) -> &Arc<SecretKey> {
+        &self.keys[&self.current]
+    }
+
+    fn get(&self, version: u32) -> Result<&SecretKey> {
+        self.keys.get(&version).map(|key| key.as_ref()).with_context(|| format!("No tenant key version {}", version))
+    }
+}
+
+/// Keys of every tenant that encrypts its files
+pub struct Keyring {
+    config: EncryptionConfig,
+    master: MasterKey,
+    storage: Arc<StorageManager>,
+    tenants: DashMap<Uuid, Arc<TenantKeys>>,
+}
+
+impl Keyring {
+    /// Unwrap the keys of every tenant found in storage, rewrapping those a previous master wrapped
+    pub async fn open(config: &EncryptionConfig, storage: Arc<StorageManager>) -> Result<Self> {
+        let keyring = Self {
+            config: config.clone(),
+            master: MasterKey::from_config(config)?,
+            storage,
+            tenants: DashMap::new(),
+        };
+
+        let entries = match std::fs::read_dir(keyring.storage.tenants_path()) {
+            Ok(entries) => entries.collect::<std::io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context("Failed to list tenant directories"),
+        };
+        for entry in entries {
+            let Some(tenant_id) = entry.file_name().to_str().and_then(|name| name.parse::<Uuid>().ok()) else {
+                continue;
+            };
+            let Some(file) = keyring.read_key_file(tenant_id)? else { continue };
+            let keys = keyring.unwrap_file(tenant_id, &file).await
+                .with_context(|| format!("Failed to unwrap the keys of tenant {}", tenant_id))?;
+            keyring.tenants.insert(tenant_id, Arc::new(keys));
+        }
+
+        info!(master = %keyring.master.name(), tenants = keyring.tenants.len(), "Encryption keyring loaded");
+        Ok(keyring)
+    }
+
+    pub fn is_encrypted(&self, tenant_id: Uuid) -> bool {
+        self.tenants.contains_key(&tenant_id)
+    }
+
+    pub fn tenant_keys(&self, tenant_id: Uuid) -> Option<Arc<TenantKeys>> {
+        self.tenants.get(&tenant_id).map(|keys| keys.clone())
+    }
+
+    /// Name of the master key wrapping tenant keys
+    pub fn master_name(&self) -> String {
+        self.master.name()
+    }
+
+    /// Give a tenant a new current key, keeping older versions until `encrypt_files` has moved off them
+    pub async fn create_key(&self, tenant_id: Uuid) -> Result<Arc<TenantKeys>> {
+        let mut file = self.read_key_file(tenant_id)?.unwrap_or(KeyFile {
+            master: self.master.name(),
+            current: 0,
+            keys: BTreeMap::new(),
+        });
+        let key_bytes = secrets::random_key()?;
+        file.current += 1;
+        file.keys.insert(file.current, self.master.wrap(&key_bytes).await?);
+
+        let mut keys = BTreeMap::new();
+        if let Some(existing) = self.tenant_keys(tenant_id) {
+            keys.extend(existing.keys.iter().map(|(version, key)| (*version, key.clone())));
+        }
+        keys.insert(file.current, Arc::new(SecretKey::from_bytes(&key_bytes)?));
+
+        self.write_key_file(tenant_id, &file)?;
+        let keys = Arc::new(TenantKeys { current: file.current, keys });
+        self.tenants.insert(tenant_id, keys.clone());
+        info!(tenant_id = %tenant_id, version = file.current, "Tenant encryption key created");
+        Ok(keys)
+    }
+
+    /// Encrypt every site file of the tenant with its current key, then forget older key versions
+    /// Safe to run again after an interruption: files already on the current version are skipped.
+    pub async fn encrypt_files(self: &Arc<Self>, tenant_id: Uuid) -> Result<usize> {
+        let keys = self.tenant_keys(tenant_id).context("Tenant has no encryption key")?;
+        let keyring = self.clone();
+        let encrypted = tokio::task::spawn_blocking(move || -> Result<usize> {
+            let mut encrypted = 0;
+            for (site_id, relative, path) in keyring.site_files(tenant_id) {
+                if keyring.config.is_excluded(&relative) {
+                    continue;
+                }
+                if reencrypt(&keys, &relative, &path)? {
+                    encrypted += 1;
+                }
+                keyring.drop_view(tenant_id, &site_id);
+            }
+            Ok(encrypted)
+        }).await??;
+
+        self.prune(tenant_id)?;
+        info!(tenant_id = %tenant_id, files = encrypted, "Tenant site files encrypted");
+        Ok(encrypted)
+    }
+
+    /// Rewrap every tenant's keys with the master key as it is now, such as after rotating it in Vault
+    pub async fn rewrap(&self) -> Result<usize> {
+        let tenant_ids: Vec<Uuid> = self.tenants.iter().map(|entry| *entry.key()).collect();
+        for tenant_id in &tenant_ids {
+            let keys = self.tenant_keys(*tenant_id).context("Tenant keys removed during rewrap")?;
+            self.write_wrapped(*tenant_id, &keys).await?;
+        }
+        info!(master = %self.master.name(), tenants = tenant_ids.len(), "Tenant keys rewrapped");
+        Ok(tenant_ids.len())
+    }
+
+    /// A site file's contents, decrypted if the file is encrypted
+    pub fn read_site_file(&self, tenant_id: Uuid, site_id: &str, relative: &str) -> Result<Vec<u8>> {
+        let path = self.storage.site_dir(tenant_id, site_id).join(relative);
+        let mut file = std::fs::File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut contents = Vec::new();
+        match self.tenant_keys(tenant_id) {
+            Some(keys) => decrypt(&keys, relative, &mut file, &mut contents)?,
+            None => {
+                file.read_to_end(&mut contents)?;
+            }
+        }
+        Ok(contents)
+    }
+
+    /// Write a site file, encrypted unless the tenant doesn't encrypt or the file is excluded
+    pub fn write_site_file(&self, tenant_id: Uuid, site_id: &str, relative: &str, contents: &[u8]) -> Result<()> {
+        let path = self.storage.site_dir(tenant_id, site_id).join(relative);
+        let keys = self.tenant_keys(tenant_id).filter(|_| !self.config.is_excluded(relative));
+        write_atomically(&path, |out| match &keys {
+            Some(keys) => encrypt(keys, relative, &mut &contents[..], out),
+            None => Ok(out.write_all(contents)?),
+        })?;
+        self.drop_view(tenant_id, site_id);
+        Ok(())
+    }
+
+    /// A directory of the site's files in plaintext, for a read-only preopen; the site directory itself
+    /// when the tenant doesn't encrypt. Built on first use, and again after files change through the keyring.
+    pub fn plaintext_view(&self, tenant_id: Uuid, site_id: &str) -> Result<PathBuf> {
+        let site_dir = self.storage.site_dir(tenant_id, site_id);
+        let Some(keys) = self.tenant_keys(tenant_id) else {
+            return Ok(site_dir);
+        };
+        let view = self.view_dir(tenant_id, site_id);
+        if view.exists() {
+            return Ok(view);
+        }
+
+        let staging = view.with_extension("building");
+        let _ = std::fs::remove_dir_all(&staging);
+        create_private_dir(&staging)?;
+        for entry in walkdir::WalkDir::new(&site_dir).into_iter().filter_map(|entry| entry.ok()) {
+            let Some(relative) = relative_path(&site_dir, entry.path()) else { continue };
+            if !entry.file_type().is_file() || self.config.is_excluded(&relative) {
+                continue;
+            }
+            let target = staging.join(&relative);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut input = BufReader::new(std::fs::File::open(entry.path())?);
+            let mut output = BufWriter::new(std::fs::File::create(&target)?);
+            decrypt(&keys, &relative, &mut input, &mut output)
+                .with_context(|| format!("Failed to decrypt {}", entry.path().display()))?;
+            output.flush()?;
+        }
+        std::fs::rename(&staging, &view).context("Failed to publish the plaintext view")?;
+        Ok(view)
+    }
+
+    /// Remove a site's plaintext view; the next Cage rebuilds it
+    pub fn drop_view(&self, tenant_id: Uuid, site_id: &str) {
+        let view = self.view_dir(tenant_id, site_id);
+        if let Err(e) = std::fs::remove_dir_all(&view) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(path = %view.display(), error = %e, "Failed to remove plaintext view");
+            }
+        }
+    }
+
+    fn view_dir(&self, tenant_id: Uuid, site_id: &str) -> PathBuf {
+        Path::new(&self.config.plaintext_dir).join(tenant_id.to_string()).join(site_id)
+    }
+
+    /// `(site, path within the site, path)` of every regular file of the tenant's sites
+    fn site_files(&self, tenant_id: Uuid) -> Vec<(String, String, PathBuf)> {
+        let sites_dir = self.storage.tenant_dir(tenant_id).join("sites");
+        let mut files = Vec::new();
+        let Ok(sites) = std::fs::read_dir(&sites_dir) else { return files };
+        for site in sites.filter_map(|entry| entry.ok()) {
+            let Some(site_id) = site.file_name().to_str().map(str::to_string) else { continue };
+            let site_dir = site.path();
+            for entry in walkdir::WalkDir::new(&site_dir).into_iter().filter_map(|entry| entry.ok()) {
+                if !entry.file_type().is_file() || is_staging(entry.path()) {
+                    continue;
+                }
+                if let Some(relative) = relative_path(&site_dir, entry.path()) {
+                    files.push((site_id.clone(), relative, entry.path().to_path_buf()));
+                }
+            }
+        }
+        files
+    }
+
+    fn read_key_file(&self, tenant_id: Uuid) -> Result<Option<KeyFile>> {
+        let path = self.storage.tenant_dir(tenant_id).join(KEY_FILE);
+        match std::fs::read(&path) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)
+                .with_context(|| format!("Invalid key file {}", path.display()))?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    fn write_key_file(&self, tenant_id: Uuid, file: &KeyFile) -> Result<()> {
+        let path = self.storage.tenant_dir(tenant_id).join(KEY_FILE);
+        let contents = serde_json::to_vec_pretty(file)?;
+        write_atomically(&path, |out| Ok(out.write_all(&contents)?))
+    }
+
+    async fn unwrap_file(&self, tenant_id: Uuid, file: &KeyFile) -> Result<TenantKeys> {
+        let mut keys = BTreeMap::new();
+        let mut stale = file.master != self.master.name();
+        for (version, wrapped) in &file.keys {
+            let (bytes, previous) = self.master.unwrap(wrapped).await?;
+            stale |= previous;
+            keys.insert(*version, Arc::new(SecretKey::from_bytes(&bytes)?));
+        }
+        keys.get(&file.current).context("Key file lacks its current version")?;
+        let keys = TenantKeys { current: file.current, keys };
+        if stale {
+            self.write_wrapped(tenant_id, &keys).await?;
+            info!(tenant_id = %tenant_id, "Tenant keys rewrapped with the current encryption key");
+        }
+        Ok(keys)
+    }
+
+    /// Write the tenant's key file from keys in memory, wrapping them anew
+    async fn write_wrapped(&self, tenant_id: Uuid, keys: &TenantKeys) -> Result<()> {
+        let mut file = KeyFile { master: self.master.name(), current: keys.current, keys: BTreeMap::new() };
+        // Unwrapped keys can't be exported from ring, so versions are rewrapped from their stored form
+        let stored = self.read_key_file(tenant_id)?.context("Tenant key file disappeared")?;
+        for version in keys.keys.keys() {
+            let wrapped = stored.keys.get(version).with_context(|| format!("Key file lacks version {}", version))?;
+            let (bytes, _) = self.master.unwrap(wrapped).await?;
+            file.keys.insert(*version, self.master.wrap(&bytes).await?);
+        }
+        self.write_key_file(tenant_id, &file)
+    }
+
+    /// Drop key versions no file is encrypted with any more
+    fn prune(&self, tenant_id: Uuid) -> Result<()> {
+        let Some(keys) = self.tenant_keys(tenant_id) else { return Ok(()) };
+        if keys.keys.len() == 1 {
+            return Ok(());
+        }
+        let mut file = self.read_key_file(tenant_id)?.context("Tenant key file disappeared")?;
+        file.keys.retain(|version, _| *version == keys.current);
+        self.write_key_file(tenant_id, &file)?;
+        let current = keys.current().clone();
+        self.tenants.insert(tenant_id, Arc::new(TenantKeys {
+            current: keys.current,
+            keys: BTreeMap::from([(keys.current, current)]),
+        }));
+        Ok(())
+    }
+}
+
+/// Whether `data` starts like an encrypted file
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypt `input` into `output` with the tenant's current key, bound to the file's path within its site
+/// Layout: magic, key version, then chunks of `[last flag][sealed length][nonce, ciphertext, tag]`.
+pub fn encrypt(keys: &TenantKeys, relative: &str, input: &mut impl Read, output: &mut impl Write) -> Result<()> {
+    output.write_all(MAGIC)?;
+    output.write_all(&keys.current.to_be_bytes())?;
+    let key = keys.current();
+
+    let mut chunk = read_chunk(input)?;
+    let mut index = 0u64;
+    loop {
+        let next = if chunk.len() < CHUNK_SIZE { Vec::new() } else { read_chunk(input)? };
+        let last = next.is_empty();
+        let sealed = key.seal_bytes(&chunk_aad(relative, index, last), &chunk)?;
+        output.write_all(&[last as u8])?;
+        output.write_all(&(sealed.len() as u32).to_be_bytes())?;
+        output.write_all(&sealed)?;
+        if last {
+            return Ok(());
+        }
+        chunk = next;
+        index += 1;
+    }
+}
+
+/// Decrypt `input` into `output`, copying it unchanged if it isn't encrypted
+pub fn decrypt(keys: &TenantKeys, relative: &str, input: &mut impl Read, output: &mut impl Write) -> Result<()> {
+    let mut header = [0u8; 12];
+    let read = read_up_to(input, &mut header)?;
+    if !is_encrypted(&header[..read]) || read < header.len() {
+        output.write_all(&header[..read])?;
+        std::io::copy(input, output)?;
+        return Ok(());
+    }
+    let version = u32::from_be_bytes(header[8..12].try_into().expect("four bytes"));
+    let key = keys.get(version)?;
+
+    for index in 0u64.. {
+        let mut record = [0u8; 5];
+        if read_up_to(input, &mut record)? < record.len() {
+            bail!("Encrypted file is truncated");
+        }
+        let last = match record[0] {
+            0 => false,
+            1 => true,
+            _ => bail!("Encrypted file is corrupt"),
+        };
+        let len = u32::from_be_bytes(record[1..5].try_into().expect("four bytes")) as usize;
+        if len > CHUNK_SIZE + 64 {
+            bail!("Encrypted file is corrupt");
+        }
+        let mut sealed = vec![0u8; len];
+        input.read_exact(&mut sealed).context("Encrypted file is truncated")?;
+        output.write_all(&key.open_bytes(&chunk_aad(relative, index, last), &sealed)?)?;
+        if last {
+            break;
+        }
+    }
+    if input.read(&mut [0u8; 1])? != 0 {
+        bail!("Encrypted file has trailing data");
+    }
+    Ok(())
+}
+
+/// Bring a file to the tenant's current key version, returning whether it was rewritten
+fn reencrypt(keys: &TenantKeys, relative: &str, path: &Path) -> Result<bool> {
+    let mut header = [0u8; 12];
+    let read = read_up_to(&mut std::fs::File::open(path)?, &mut header)?;
+    if read == header.len() && is_encrypted(&header) && header[8..12] == keys.current.to_be_bytes() {
+        return Ok(false);
+    }
+
+    // Files on older versions are decrypted in memory, never to disk
+    let mut plaintext = Vec::new();
+    decrypt(keys, relative, &mut BufReader::new(std::fs::File::open(path)?), &mut plaintext)
+        .with_context(|| format!("Failed to decrypt {}", path.display()))?;
+    write_atomically(path, |out| encrypt(keys, relative, &mut &plaintext[..], out))
+        .with_context(|| format!("Failed to encrypt {}", path.display()))?;
+    Ok(true)
+}
+
+fn chunk_aad(relative: &str, index: u64, last: bool) -> Vec<u8> {
+    let mut aad = relative.as_bytes().to_vec();
+    aad.push(0);
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.push(last as u8);
+    aad
+}
+
+fn read_chunk(input: &mut impl Read) -> Result<Vec<u8>> {
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    let read = read_up_to(input, &mut chunk)?;
+    chunk.truncate(read);
+    Ok(chunk)
+}
+
+/// Fill `buf` unless the input ends first, returning how much was read
+fn read_up_to(input: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Replace `path` with what `write` produces, readable only by the server user
+fn write_atomically(path: &Path, write: impl FnOnce(&mut BufWriter<std::fs::File>) -> Result<()>) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let staging = staging_path(path);
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    let mut out = BufWriter::new(file);
+    let written = write(&mut out).and_then(|()| {
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        Ok(())
+    });
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&staging);
+        return Err(e);
+    }
+    std::fs::rename(&staging, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+fn staging_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".pear-staging");
+    path.with_file_name(name)
+}
+
+fn is_staging(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "pear-staging")
+}
+
+fn create_private_dir(path: &Path) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))
+}
+
+fn relative_path(base: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(base).ok()?;
+    let parts: Option<Vec<&str>> = relative.components().map(|component| component.as_os_str().to_str()).collect();
+    Some(parts?.join("/")).filter(|relative| !relative.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(versions: &[u32]) -> TenantKeys {
+        TenantKeys {
+            current: *versions.last().unwrap(),
+            keys: versions.iter().map(|v| (*v, Arc::new(SecretKey::generate().unwrap()))).collect(),
+        }
+    }
+
+    fn round_trip(keys: &TenantKeys, relative: &str, data: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::new();
+        encrypt(keys, relative, &mut &data[..], &mut sealed).unwrap();
+        sealed
+    }
+
+    #[test]
+    fn test_files_round_trip_and_detect_tampering() {
+        let keys = keys(&[1]);
+        for size in [0, 10, CHUNK_SIZE, CHUNK_SIZE * 2 + 7] {
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let sealed = round_trip(&keys, "www/index.html", &data);
+            assert!(is_encrypted(&sealed));
+            let mut plain = Vec::new();
+            decrypt(&keys, "www/index.html", &mut &sealed[..], &mut plain).unwrap();
+            assert_eq!(plain, data, "size {}", size);
+        }
+
+        let sealed = round_trip(&keys, "a.txt", &vec![7u8; CHUNK_SIZE + 1]);
+        // Bound to the path, and neither truncation nor edits go unnoticed
+        assert!(decrypt(&keys, "b.txt", &mut &sealed[..], &mut Vec::new()).is_err());
+        let truncated = &sealed[..sealed.len() - CHUNK_SIZE / 2];
+        assert!(decrypt(&keys, "a.txt", &mut &truncated[..], &mut Vec::new()).is_err());
+        let first_chunk_only = &sealed[..12 + 5 + CHUNK_SIZE + 28];
+        assert!(decrypt(&keys, "a.txt", &mut &first_chunk_only[..], &mut Vec::new()).is_err());
+        let mut flipped = sealed.clone();
+        flipped[40] ^= 1;
+        assert!(decrypt(&keys, "a.txt", &mut &flipped[..], &mut Vec::new()).is_err());
+
+        // Plaintext passes through unchanged
+        let mut plain = Vec::new();
+        decrypt(&keys, "a.txt", &mut &b"hello"[..], &mut plain).unwrap();
+        assert_eq!(plain, b"hello");
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let config = EncryptionConfig::default();
+        assert!(config.is_excluded("module.wasm"));
+        assert!(config.is_excluded("site.db"));
+        assert!(config.is_excluded("site.db-wal"));
+        assert!(config.is_excluded("tmp/cache/x"));
+        assert!(!config.is_excluded("www/index.html"));
+        assert!(!config.is_excluded("uploads/tmp/x"));
+        assert!(glob("*.d*b", "a.dxb") && !glob("a*", "ba"));
+    }
+
+    #[tokio::test]
+    async fn test_enable_rotate_and_reopen() {
+        let temp = tempfile::tempdir().unwrap();
+        let storage = Arc::new(StorageManager::new(temp.path().join("storage")).unwrap());
+        let config = EncryptionConfig {
+            key_file: temp.path().join("enc.key").to_string_lossy().into_owned(),
+            plaintext_dir: temp.path().join("plain").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let tenant_id = Uuid::new_v4();
+        let site_dir = storage.create_site_storage(tenant_id, "blog").unwrap();
+        std::fs::create_dir_all(site_dir.join("www")).unwrap();
+        std::fs::write(site_dir.join("www/index.html"), "<h1>hi</h1>").unwrap();
+        std::fs::write(site_dir.join("site.db"), "sqlite").unwrap();
+
+        let keyring = Arc::new(Keyring::open(&config, storage.clone()).await.unwrap());
+        assert_eq!(keyring.read_site_file(tenant_id, "blog", "www/index.html").unwrap(), b"<h1>hi</h1>");
+        keyring.create_key(tenant_id).await.unwrap();
+        assert_eq!(keyring.encrypt_files(tenant_id).await.unwrap(), 1);
+        assert!(is_encrypted(&std::fs::read(site_dir.join("www/index.html")).unwrap()));
+        assert_eq!(std::fs::read(site_dir.join("site.db")).unwrap(), b"sqlite");
+
+        let view = keyring.plaintext_view(tenant_id, "blog").unwrap();
+        assert_eq!(std::fs::read(view.join("www/index.html")).unwrap(), b"<h1>hi</h1>");
+        assert!(!view.join("site.db").exists());
+
+        // Rotation moves every file to the new version and forgets the old one
+        keyring.create_key(tenant_id).await.unwrap();
+        assert_eq!(keyring.encrypt_files(tenant_id).await.unwrap(), 1);
+        assert_eq!(keyring.tenant_keys(tenant_id).unwrap().version(), 2);
+        assert!(!view.exists());
+        keyring.write_site_file(tenant_id, "blog", "www/about.html", b"about").unwrap();
+
+        // Another start finds the keys again, and rewrapping keeps them usable
+        let reopened = Keyring::open(&config, storage.clone()).await.unwrap();
+        assert!(reopened.is_encrypted(tenant_id));
+        assert_eq!(reopened.read_site_file(tenant_id, "blog", "www/index.html").unwrap(), b"<h1>hi</h1>");
+        assert_eq!(reopened.rewrap().await.unwrap(), 1);
+        let reopened = Keyring::open(&config, storage).await.unwrap();
+        assert_eq!(reopened.read_site_file(tenant_id, "blog", "www/about.html").unwrap(), b"about");
+    }
+}
@@ -1,36 +1,195 @@
 // Zero-Copy Bind Mount Storage Module
 // Shared read-only access across Cages using Wasmtime preopened directories
 
+pub mod artifacts;
 pub mod bind_mount;
+pub mod database;
+pub mod encryption;
+pub mod modules;
+pub mod registry;
+pub mod s3;
+pub mod snapshot;
+pub mod usage;
 
-use anyhow::{Result, Context};
-use std::path::{Path, PathBuf};
+use anyhow::{bail, Result, Context};
+use modules::ModuleStore;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 use tracing::{info, warn};
+use usage::{SiteUsage, UsageCache};
 use uuid::Uuid;
 
+/// File a site's deployed module is stored as, inside the site directory
+pub const SITE_MODULE_FILE: &str = "module.wasm";
+
+/// Directory of a site's static content, inside the site directory
+pub const SITE_CONTENT_DIR: &str = "www";
+
+/// `[storage]`: where modules and tenant files live
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Modules and tenant files; relative paths resolve in the working directory
+    pub root: String,
+
+    /// Tenant and site directories ("" = `<root>/tenants`)
+    pub tenants_root: String,
+
+    /// Earlier roots whose contents are moved into `root` on start, if `root` is still empty
+    pub migrate_from: Vec<String>,
+
+    /// How often every site's directory is walked to recount its usage
+    pub scan_interval_secs: u64,
+
+    /// Each scan interval is moved by up to this share of itself, either way
+    pub scan_jitter_percent: u8,
+
+    /// Rescan a site soon after its files change, where the platform reports file events
+    pub watch: bool,
+
+    /// Encryption of the files of tenants that turn it on
+    pub encryption: encryption::EncryptionConfig,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            root: "pear-storage".to_string(),
+            tenants_root: String::new(),
+            migrate_from: Vec::new(),
+            scan_interval_secs: 300,
+            scan_jitter_percent: 20,
+            watch: true,
+            encryption: Default::default(),
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn validate(&self) -> Result<()> {
+        check_root("root", &self.root)?;
+        if !self.tenants_root.is_empty() {
+            check_root("tenants_root", &self.tenants_root)?;
+        }
+        if self.scan_interval_secs == 0 {
+            bail!("scan_interval_secs must be positive");
+        }
+        if self.scan_jitter_percent > 50 {
+            bail!("scan_jitter_percent must be at most 50");
+        }
+        self.encryption.validate().context("Invalid [storage.encryption] config")?;
+        for from in &self.migrate_from {
+            if Path::new(from) == Path::new(&self.root) {
+                bail!("migrate_from lists the storage root itself ({})", from);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn scan_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.scan_interval_secs)
+    }
+
+    /// Directory holding one directory per tenant
+    pub fn tenants_root(&self) -> PathBuf {
+        if self.tenants_root.is_empty() {
+            Path::new(&self.root).join("tenants")
+        } else {
+            PathBuf::from(&self.tenants_root)
+        }
+    }
+}
+
+/// A root must name a directory of its own: not empty, not `/`, no `..`
+fn check_root(name: &str, root: &str) -> Result<()> {
+    let path = Path::new(root);
+    if root.trim().is_empty() {
+        bail!("{} must not be empty", name);
+    }
+    if path.components().all(|component| matches!(component, Component::RootDir | Component::CurDir)) {
+        bail!("{} must not be the filesystem root or the working directory itself", name);
+    }
+    if path.components().any(|component| component == Component::ParentDir) {
+        bail!("{} must not contain '..' ({})", name, root);
+    }
+    Ok(())
+}
+
 /// Storage manager for tenant and site files
 pub struct StorageManager {
     /// Base storage directory
     base_path: PathBuf,
+    
+    /// Directory holding one directory per tenant
+    tenants_path: PathBuf,
+    
+    /// Deployed modules, shared across tenants
+    modules: Arc<ModuleStore>,
+    
+    /// Site usage from the last scans
+    usage: Arc<UsageCache>,
 }
 
 impl StorageManager {
-    /// Create a new storage manager
+    /// Create a new storage manager, keeping tenants under `<base_path>/tenants`
     pub fn new<P: AsRef<Path>>(base_path: P) -> Result<Self> {
         let base_path = base_path.as_ref().to_path_buf();
-        
+        let tenants_path = base_path.join("tenants");
+        Self::with_tenants_path(base_path, tenants_path)
+    }
+
+    fn with_tenants_path(base_path: PathBuf, tenants_path: PathBuf) -> Result<Self> {
         // Ensure base directory exists
         std::fs::create_dir_all(&base_path)
             .context("Failed to create storage base directory")?;
         
-        info!(path = %base_path.display(), "Storage manager initialized");
+        let modules = ModuleStore::new(base_path.join("modules"))?;
+        
+        info!(path = %base_path.display(), tenants = %tenants_path.display(), "Storage manager initialized");
         
-        Ok(Self { base_path })
+        Ok(Self { base_path, tenants_path, modules, usage: Arc::default() })
+    }
+
+    /// Open the configured root, first moving an earlier root's contents into it
+    pub fn open(config: &StorageConfig) -> Result<Self> {
+        let root = Path::new(&config.root);
+        for from in &config.migrate_from {
+            migrate(Path::new(from), root)
+                .with_context(|| format!("Failed to migrate storage from {} to {}", from, root.display()))?;
+        }
+        if !writable(root) {
+            bail!(
+                "Storage root {} is not writable by this user; set [storage] root or PEAR__STORAGE__ROOT",
+                root.display()
+            );
+        }
+        Self::with_tenants_path(root.to_path_buf(), config.tenants_root())
+    }
+
+    /// Base storage directory
+    pub fn root(&self) -> &Path {
+        &self.base_path
+    }
+
+    /// Directory holding one directory per tenant
+    pub fn tenants_path(&self) -> &Path {
+        &self.tenants_path
+    }
+
+    /// Site usage from the last scans
+    pub fn usage(&self) -> &Arc<UsageCache> {
+        &self.usage
+    }
+
+    /// Content-addressed store of deployed modules
+    pub fn modules(&self) -> &Arc<ModuleStore> {
+        &self.modules
     }
 
     /// Get tenant directory path
     pub fn tenant_dir(&self, tenant_id: Uuid) -> PathBuf {
-        self.base_path.join("tenants").join(tenant_id.to_string())
+        self.tenants_path.join(tenant_id.to_string())
     }
 
     /// Get site directory path  
@@ -38,6 +197,11 @@ impl StorageManager {
         self.tenant_dir(tenant_id).join("sites").join(site_id)
     }
 
+    /// Path of a site's deployed module
+    pub fn site_module_path(&self, tenant_id: Uuid, site_id: &str) -> PathBuf {
+        self.site_dir(tenant_id, site_id).join(SITE_MODULE_FILE)
+    }
+
     /// Create tenant directory
     pub fn create_tenant_storage(&self, tenant_id: Uuid) -> Result<PathBuf> {
         let tenant_dir = self.tenant_dir(tenant_id);
@@ -99,6 +263,24 @@ impl StorageManager {
         Ok(bytes / (1024 * 1024))
     }
 
+    /// A site's usage, walking its directory only if files changed since the last scan
+    pub fn site_usage(&self, tenant_id: Uuid, site_id: &str) -> Result<SiteUsage> {
+        match self.usage.get(tenant_id, site_id) {
+            Some(usage) => Ok(usage),
+            None => self.scan_site(tenant_id, site_id),
+        }
+    }
+
+    /// Walk a site's directory and cache what it takes
+    pub fn scan_site(&self, tenant_id: Uuid, site_id: &str) -> Result<SiteUsage> {
+        let usage = SiteUsage {
+            bytes: self.calculate_usage(self.site_dir(tenant_id, site_id))? as u64,
+            scanned_at: chrono::Utc::now().timestamp(),
+        };
+        self.usage.record(tenant_id, site_id, usage);
+        Ok(usage)
+    }
+
     /// Check if storage quota is exceeded
     pub fn check_quota(&self, used_mb: usize, limit_mb: usize) -> Result<()> {
         if used_mb > limit_mb {
@@ -135,14 +317,74 @@ impl StorageManager {
             
             info!(tenant_id = %tenant_id, site_id = %site_id, "Site storage deleted");
         }
+        self.usage.forget(tenant_id, site_id);
         
         Ok(())
     }
 }
 
+/// Move everything in `from` into `to`, unless `to` already holds data
+/// Returns whether anything was moved.
+pub fn migrate(from: &Path, to: &Path) -> Result<bool> {
+    if !from.is_dir() || is_same_dir(from, to) {
+        return Ok(false);
+    }
+    if to.is_dir() && std::fs::read_dir(to)?.next().is_some() {
+        warn!(from = %from.display(), to = %to.display(), "Both storage roots hold data; not migrating");
+        return Ok(false);
+    }
+
+    if let Some(parent) = to.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let _ = std::fs::remove_dir(to);
+    if std::fs::rename(from, to).is_err() {
+        // Different filesystems: copy, then remove the original only once the copy is complete
+        copy_tree(from, to)?;
+        std::fs::remove_dir_all(from)?;
+    }
+    info!(from = %from.display(), to = %to.display(), "Storage migrated");
+    Ok(true)
+}
+
+fn is_same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    for entry in walkdir::WalkDir::new(from) {
+        let entry = entry?;
+        let dest = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest)?;
+        } else if entry.file_type().is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &dest)?;
+        } else {
+            std::fs::copy(entry.path(), &dest)
+                .with_context(|| format!("Failed to copy {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether this user can create files in `dir`, or create `dir` itself
+fn writable(dir: &Path) -> bool {
+    let Some(existing) = dir.ancestors().find(|ancestor| ancestor.as_os_str().is_empty() || ancestor.is_dir()) else {
+        return false;
+    };
+    let existing = if existing.as_os_str().is_empty() { Path::new(".") } else { existing };
+    let probe = existing.join(format!(".pear-write-test-{}", std::process::id()));
+    let created = std::fs::File::create(&probe).is_ok();
+    let _ = std::fs::remove_file(&probe);
+    created
+}
+
 impl Default for StorageManager {
     fn default() -> Self {
-        Self::new("/srv/pear-storage").expect("Failed to initialize default storage")
+        Self::open(&StorageConfig::default()).expect("Failed to initialize default storage")
     }
 }
 
@@ -185,4 +427,70 @@ mod tests {
         let usage = manager.calculate_usage(&site_dir).unwrap();
         assert!(usage > 0);
     }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(StorageConfig::default().validate().is_ok());
+        for root in ["", "/", ".", "data/../..", "../pear"] {
+            let config = StorageConfig { root: root.to_string(), ..Default::default() };
+            assert!(config.validate().is_err(), "{:?} accepted", root);
+        }
+        let config = StorageConfig { root: "/var/lib/pear".to_string(), ..Default::default() };
+        assert_eq!(config.tenants_root(), PathBuf::from("/var/lib/pear/tenants"));
+    }
+
+    #[test]
+    fn test_open_migrates_earlier_root() {
+        let temp = TempDir::new().unwrap();
+        let old = temp.path().join("srv/pear-storage");
+        std::fs::create_dir_all(old.join("tenants/t/sites/blog")).unwrap();
+        std::fs::write(old.join("tenants/t/sites/blog/site.db"), "data").unwrap();
+
+        let root = temp.path().join("data/pear");
+        let config = StorageConfig {
+            root: root.to_string_lossy().into_owned(),
+            migrate_from: vec![old.to_string_lossy().into_owned()],
+            ..Default::default()
+        };
+        let manager = StorageManager::open(&config).unwrap();
+        assert_eq!(manager.root(), root);
+        assert_eq!(std::fs::read_to_string(root.join("tenants/t/sites/blog/site.db")).unwrap(), "data");
+        assert!(!old.exists());
+
+        // A root that already holds data is never merged into
+        std::fs::create_dir_all(&old).unwrap();
+        std::fs::write(old.join("stale"), "old").unwrap();
+        assert!(!migrate(&old, &root).unwrap());
+        assert!(old.join("stale").exists());
+
+        // Copying covers roots on another filesystem
+        let copy = temp.path().join("copy");
+        copy_tree(&root, &copy).unwrap();
+        assert!(copy.join("tenants/t/sites/blog/site.db").exists());
+    }
+    #[test]
+    fn test_tenants_root_outside_storage_root() {
+        let temp = TempDir::new().unwrap();
+        let config = StorageConfig {
+            root: temp.path().join("root").to_string_lossy().into_owned(),
+            tenants_root: temp.path().join("volume").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let manager = StorageManager::open(&config).unwrap();
+        let tenant_id = Uuid::new_v4();
+
+        let site_dir = manager.create_site_storage(tenant_id, "blog").unwrap();
+        assert!(site_dir.starts_with(temp.path().join("volume")));
+        std::fs::write(manager.site_module_path(tenant_id, "blog"), vec![0u8; 2 * 1024 * 1024]).unwrap();
+        assert_eq!(manager.site_usage(tenant_id, "blog").unwrap().mb(), 2);
+
+        // Cached until the site is invalidated
+        std::fs::write(site_dir.join("more"), vec![0u8; 1024 * 1024]).unwrap();
+        assert_eq!(manager.site_usage(tenant_id, "blog").unwrap().mb(), 2);
+        manager.usage().invalidate(tenant_id, "blog");
+        assert_eq!(manager.site_usage(tenant_id, "blog").unwrap().mb(), 3);
+
+        manager.delete_site_storage(tenant_id, "blog").unwrap();
+        assert!(!site_dir.exists());
+    }
 }
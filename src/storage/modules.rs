@@ -0,0 +1,359 @@
+// Content-Addressed Module Store
+// Deployed Wasm modules are kept once by SHA-256, however many tenants and sites run them
+
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// SHA-256 of a module's bytes
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleHash([u8; 32]);
+
+impl ModuleHash {
+    pub fn of(wasm_bytes: &[u8]) -> Self {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(digest(&SHA256, wasm_bytes).as_ref());
+        Self(hash)
+    }
+}
+
+impl fmt::Display for ModuleHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ModuleHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ModuleHash({})", self)
+    }
+}
+
+impl FromStr for ModuleHash {
+    type Err = anyhow::Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            bail!("Module hash must be 64 hex digits");
+        }
+        let mut hash = [0u8; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).context("Module hash must be 64 hex digits")?;
+        }
+        Ok(Self(hash))
+    }
+}
+
+impl Serialize for ModuleHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ModuleHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Modules stored as `<root>/<first two hex digits>/<hash>.wasm`
+///
+/// Running pools hold a [`ModuleRef`] for the module they respawn from; sites hold a hard
+/// link to the file. A module is only collected once neither kind of reference is left.
+pub struct ModuleStore {
+    root: PathBuf,
+
+    /// Live `ModuleRef`s per module; the lock also orders writes against collection
+    refs: Mutex<HashMap<ModuleHash, usize>>,
+}
+
+/// Modules kept and space used by the store
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ModuleStoreStats {
+    pub modules: usize,
+    pub bytes: u64,
+    pub referenced: usize,
+}
+
+impl ModuleStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Arc<Self>> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(&root)
+            .with_context(|| format!("Failed to create module store: {}", root.display()))?;
+        Ok(Arc::new(Self { root, refs: Mutex::new(HashMap::new()) }))
+    }
+
+    /// File a module is stored in
+    pub fn path(&self, hash: &ModuleHash) -> PathBuf {
+        let hex = hash.to_string();
+        self.root.join(&hex[..2]).join(format!("{}.wasm", hex))
+    }
+
+    /// Store a module, or reuse the copy already stored, and reference it
+    pub fn put(self: &Arc<Self>, wasm_bytes: &[u8]) -> Result<ModuleRef> {
+        let hash = ModuleHash::of(wasm_bytes);
+        let path = self.path(&hash);
+
+        let mut refs = self.refs.lock();
+        if path.exists() {
+            debug!(module = %hash, "Module already stored");
+        } else {
+            let dir = path.parent().expect("module paths have a parent");
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            let tmp = path.with_extension("wasm.tmp");
+            std::fs::write(&tmp, wasm_bytes)
+                .with_context(|| format!("Failed to write {}", tmp.display()))?;
+            std::fs::rename(&tmp, &path)
+                .with_context(|| format!("Failed to store module {}", hash))?;
+            info!(module = %hash, bytes = wasm_bytes.len(), "Module stored");
+        }
+        *refs.entry(hash).or_default() += 1;
+        Ok(ModuleRef { hash, store: self.clone() })
+    }
+
+    /// Reference a module that is already stored
+    pub fn open(self: &Arc<Self>, hash: ModuleHash) -> Result<ModuleRef> {
+        let mut refs = self.refs.lock();
+        if !self.path(&hash).exists() {
+            bail!("Module {} is not stored", hash);
+        }
+        *refs.entry(hash).or_default() += 1;
+        Ok(ModuleRef { hash, store: self.clone() })
+    }
+
+    /// Read a module back, checking it still matches its hash
+    pub fn read(&self, hash: &ModuleHash) -> Result<Vec<u8>> {
+        let path = self.path(hash);
+        let wasm_bytes = std::fs::read(&path)
+            .with_context(|| format!("Failed to read module {}", hash))?;
+        if ModuleHash::of(&wasm_bytes) != *hash {
+            bail!("Module {} is corrupt ({} no longer matches its hash)", hash, path.display());
+        }
+        Ok(wasm_bytes)
+    }
+
+    /// Give a site its own name for a stored module
+    /// `dest` is a hard link, so the bytes stay shared and the module counts as referenced
+    /// while the site keeps it. Across filesystems it falls back to a plain copy.
+    pub fn link(&self, module: &ModuleRef, dest: &Path) -> Result<()> {
+        let source = self.path(&module.hash);
+        let tmp = dest.with_extension("wasm.tmp");
+        let _ = std::fs::remove_file(&tmp);
+        if std::fs::hard_link(&source, &tmp).is_err() {
+            std::fs::copy(&source, &tmp)
+                .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        }
+        std::fs::rename(&tmp, dest)
+            .with_context(|| format!("Failed to replace {}", dest.display()))?;
+        // Renaming onto another link to the same file is a no-op that leaves `tmp` behind
+        let _ = std::fs::remove_file(&tmp);
+        Ok(())
+    }
+
+    /// Live `ModuleRef`s to a module
+    pub fn references(&self, hash: &ModuleHash) -> usize {
+        self.refs.lock().get(hash).copied().unwrap_or(0)
+    }
+
+    /// Delete modules no pool references and no site links to
+    /// Returns the number of modules removed.
+    pub fn collect_garbage(&self) -> Result<usize> {
+        let refs = self.refs.lock();
+        let mut removed = 0;
+        for path in self.files()? {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else { continue };
+            // Writes happen under the lock, so any temporary file left over is from a crash
+            if name.ends_with(".tmp") {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            let Some(hash) = name.strip_suffix(".wasm").and_then(|hex| hex.parse::<ModuleHash>().ok()) else {
+                continue;
+            };
+            let linked = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata.nlink() > 1,
+                Err(_) => true,
+            };
+            if refs.contains_key(&hash) || linked {
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => {
+                    debug!(module = %hash, "Unreferenced module removed");
+                    removed += 1;
+                }
+                Err(e) => warn!(module = %hash, error = %e, "Failed to remove unreferenced module"),
+            }
+        }
+        if removed > 0 {
+            info!(removed = removed, "Module store garbage collected");
+        }
+        Ok(removed)
+    }
+
+    /// Collect garbage every `interval` in the background
+    pub fn start_collection(self: &Arc<Self>, interval: Duration) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let store = store.clone();
+                match tokio::task::spawn_blocking(move || store.collect_garbage()).await {
+                    Ok(Err(e)) => warn!(error = %e, "Module store garbage collection failed"),
+                    Err(e) => warn!(error = %e, "Module store garbage collection panicked"),
+                    Ok(Ok(_)) => {}
+                }
+            }
+        });
+    }
+
+    pub fn stats(&self) -> Result<ModuleStoreStats> {
+        let mut stats = ModuleStoreStats { referenced: self.refs.lock().len(), ..Default::default() };
+        for path in self.files()? {
+            if path.extension().is_some_and(|extension| extension == "wasm") {
+                stats.modules += 1;
+                stats.bytes += std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Every module stored
+    pub fn hashes(&self) -> Result<Vec<ModuleHash>> {
+        Ok(self.files()?.iter()
+            .filter_map(|path| path.file_name()?.to_str()?.strip_suffix(".wasm")?.parse().ok())
+            .collect())
+    }
+
+    /// Every file in the two-level layout
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for dir in std::fs::read_dir(&self.root)? {
+            let dir = dir?.path();
+            if dir.is_dir() {
+                for file in std::fs::read_dir(&dir)? {
+                    files.push(file?.path());
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn release(&self, hash: &ModuleHash) {
+        let mut refs = self.refs.lock();
+        if let Some(count) = refs.get_mut(hash) {
+            *count -= 1;
+            if *count == 0 {
+                refs.remove(hash);
+            }
+        }
+    }
+}
+
+/// A counted reference to a stored module; the module is kept while any exist
+pub struct ModuleRef {
+    hash: ModuleHash,
+    store: Arc<ModuleStore>,
+}
+
+impl ModuleRef {
+    pub fn hash(&self) -> ModuleHash {
+        self.hash
+    }
+
+    /// The module's bytes, read from the store
+    pub fn load(&self) -> Result<Vec<u8>> {
+        self.store.read(&self.hash)
+    }
+}
+
+impl Clone for ModuleRef {
+    fn clone(&self) -> Self {
+        *self.store.refs.lock().entry(self.hash).or_default() += 1;
+        Self { hash: self.hash, store: self.store.clone() }
+    }
+}
+
+impl Drop for ModuleRef {
+    fn drop(&mut self) {
+        self.store.release(&self.hash);
+    }
+}
+
+impl fmt::Debug for ModuleRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ModuleRef").field(&self.hash).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_identical_modules_stored_once() {
+        let temp = TempDir::new().unwrap();
+        let store = ModuleStore::new(temp.path()).unwrap();
+
+        let first = store.put(b"\0asm one").unwrap();
+        let second = store.put(b"\0asm one").unwrap();
+        let other = store.put(b"\0asm two").unwrap();
+        assert_eq!(first.hash(), second.hash());
+        assert_eq!(store.references(&first.hash()), 2);
+        assert_eq!(store.stats().unwrap().modules, 2);
+        assert_eq!(first.load().unwrap(), b"\0asm one");
+
+        let hash = first.hash();
+        assert_eq!(hash.to_string().parse::<ModuleHash>().unwrap(), hash);
+        assert_eq!(store.open(hash).unwrap().load().unwrap(), b"\0asm one");
+        assert!(store.open(ModuleHash::of(b"missing")).is_err());
+        drop(other);
+
+        std::fs::write(store.path(&hash), b"tampered").unwrap();
+        assert!(second.load().is_err());
+    }
+
+    #[test]
+    fn test_collects_unreferenced_modules() {
+        let temp = TempDir::new().unwrap();
+        let store = ModuleStore::new(temp.path().join("modules")).unwrap();
+
+        let running = store.put(b"\0asm running").unwrap();
+        let deployed = store.put(b"\0asm deployed").unwrap();
+        let site_file = temp.path().join("module.wasm");
+        store.link(&deployed, &site_file).unwrap();
+        store.link(&deployed, &site_file).unwrap();
+        assert!(!temp.path().join("module.wasm.tmp").exists());
+        let deployed_hash = deployed.hash();
+        drop(deployed);
+        drop(store.put(b"\0asm replaced").unwrap());
+
+        assert_eq!(store.collect_garbage().unwrap(), 1);
+        assert!(running.load().is_ok());
+        assert!(store.read(&deployed_hash).is_ok());
+
+        // Once the site moves on, nothing holds it
+        std::fs::remove_file(&site_file).unwrap();
+        let running_hash = running.hash();
+        drop(running);
+        assert_eq!(store.collect_garbage().unwrap(), 2);
+        assert!(store.read(&running_hash).is_err());
+        assert_eq!(store.stats().unwrap().modules, 0);
+    }
+}
@@ -0,0 +1,290 @@
+// Site Registry
+// Tenants and deployed sites saved as they change, so a restarted node brings its sites back by itself
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use super::modules::{ModuleHash, ModuleRef, ModuleStore};
+use crate::router::Router;
+use crate::supervisor::Supervisor;
+use crate::tenancy::{Tenant, TenantManager};
+
+/// Registry file in the storage root, unless `state_path` says otherwise
+pub const REGISTRY_FILE: &str = "sites.json";
+
+/// `[restore]`: saving deployed sites and restoring them on start
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestoreConfig {
+    /// Restore the saved tenants and sites on start, and keep saving them
+    pub enabled: bool,
+
+    /// File the registry is kept in ("" = sites.json in the storage root)
+    pub state_path: String,
+
+    /// Site pools started at the same time while restoring
+    pub concurrency: usize,
+
+    /// Seconds between saves of the registry
+    pub save_interval_secs: u64,
+}
+
+impl Default for RestoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            state_path: String::new(),
+            concurrency: 4,
+            save_interval_secs: 30,
+        }
+    }
+}
+
+impl RestoreConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.concurrency == 0 {
+            anyhow::bail!("concurrency must be at least 1");
+        }
+        if self.save_interval_secs == 0 {
+            anyhow::bail!("save_interval_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// A deployed site as saved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SiteRecord {
+    pub site_id: String,
+
+    /// None for sites outside any tenant, such as the demonstration site
+    pub tenant_id: Option<Uuid>,
+
+    /// Module in the node's module store
+    pub module: ModuleHash,
+
+    pub replicas: usize,
+
+    /// Key of the tenant's artifact source the module was fetched from, to fetch it again if the store lost it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<String>,
+}
+
+/// What the registry file holds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryState {
+    pub saved_at: i64,
+
+    /// The default tenant when saved; the next start's default tenant takes its place
+    pub default_tenant: Uuid,
+
+    pub tenants: Vec<Tenant>,
+    pub sites: Vec<SiteRecord>,
+}
+
+impl RegistryState {
+    /// Sites in the order to restore them: those in `required` first, then the rest as saved
+    pub fn boot_order(&self, required: &[String]) -> Vec<SiteRecord> {
+        let (mut first, rest): (Vec<SiteRecord>, Vec<SiteRecord>) = self.sites.iter()
+            .cloned()
+            .partition(|site| required.contains(&site.site_id));
+        first.extend(rest);
+        first
+    }
+}
+
+/// Saves the node's tenants and sites, and remembers the saved sites that could not be restored
+pub struct SiteRegistry {
+    path: PathBuf,
+    tenants: Arc<TenantManager>,
+    router: Arc<Router>,
+    supervisor: Arc<Supervisor>,
+    modules: Arc<ModuleStore>,
+
+    /// Saved sites that failed to restore, with their modules held against collection; they stay
+    /// saved until deployed again or removed from their tenant
+    unrestored: Mutex<HashMap<String, (SiteRecord, Option<ModuleRef>)>>,
+
+    /// Orders writes of the file
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl SiteRegistry {
+    pub fn new(
+        config: &RestoreConfig,
+        storage_root: &Path,
+        tenants: Arc<TenantManager>,
+        router: Arc<Router>,
+        supervisor: Arc<Supervisor>,
+        modules: Arc<ModuleStore>,
+    ) -> Self {
+        let path = match config.state_path.as_str() {
+            "" => storage_root.join(REGISTRY_FILE),
+            path => PathBuf::from(path),
+        };
+        Self {
+            path,
+            tenants,
+            router,
+            supervisor,
+            modules,
+            unrestored: Mutex::new(HashMap::new()),
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The saved registry; None before the first save
+    pub fn load(&self) -> Result<Option<RegistryState>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        load_state(&self.path).map(Some)
+    }
+
+    /// Keep saving a site that failed to restore, so it is tried again on the next start
+    pub fn keep_unrestored(&self, record: SiteRecord) {
+        let module = self.modules.open(record.module).ok();
+        self.unrestored.lock().insert(record.site_id.clone(), (record, module));
+    }
+
+    /// The node's tenants and deployed sites, and the saved sites still waiting to be restored
+    pub async fn capture(&self) -> RegistryState {
+        let mut sites = Vec::new();
+        let mut live = HashSet::new();
+        for site_id in self.router.site_ids() {
+            let (Some(pool), Some(module)) = (self.router.pool(&site_id), self.supervisor.module(&site_id)) else {
+                continue;
+            };
+            let module = module.hash();
+            live.insert(site_id.clone());
+            sites.push(SiteRecord {
+                tenant_id: self.tenants.find_site_tenant(&site_id),
+                artifact: self.tenants.artifact_key(&module.to_string()),
+                module,
+                replicas: pool.size().await,
+                site_id,
+            });
+        }
+
+        let mut unrestored = self.unrestored.lock();
+        unrestored.retain(|site_id, (record, _)| {
+            !live.contains(site_id) && (record.tenant_id.is_none() || self.tenants.find_site_tenant(site_id).is_some())
+        });
+        sites.extend(unrestored.values().map(|(record, _)| record.clone()));
+        drop(unrestored);
+
+        RegistryState {
+            saved_at: chrono::Utc::now().timestamp(),
+            default_tenant: self.tenants.default_tenant_id(),
+            tenants: self.tenants.list_tenants(),
+            sites,
+        }
+    }
+
+    /// Write the current tenants and sites to the registry file
+    pub async fn save(&self) -> Result<()> {
+        let _guard = self.save_lock.lock().await;
+        let state = self.capture().await;
+        let sites = state.sites.len();
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || save_state(&path, &state))
+            .await
+            .context("Registry save task failed")??;
+        debug!(sites, path = %self.path.display(), "Site registry saved");
+        Ok(())
+    }
+
+    /// Save the registry every `interval` in the background
+    pub fn start(self: &Arc<Self>, interval: Duration) {
+        let registry = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = registry.save().await {
+                    error!(error = %format!("{:#}", e), "Failed to save the site registry");
+                }
+            }
+        });
+    }
+}
+
+fn load_state(path: &Path) -> Result<RegistryState> {
+    let contents = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&contents).with_context(|| format!("Malformed site registry {}", path.display()))
+}
+
+fn save_state(path: &Path, state: &RegistryState) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn record(site_id: &str) -> SiteRecord {
+        SiteRecord {
+            site_id: site_id.to_string(),
+            tenant_id: None,
+            module: ModuleHash::of(site_id.as_bytes()),
+            replicas: 2,
+            artifact: None,
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip_and_boot_order() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(REGISTRY_FILE);
+        let mut shop = record("shop");
+        shop.artifact = Some("builds/shop.wasm".to_string());
+        let state = RegistryState {
+            saved_at: 1_700_000_000,
+            default_tenant: Uuid::new_v4(),
+            tenants: Vec::new(),
+            sites: vec![record("blog"), shop.clone(), record("docs")],
+        };
+        save_state(&path, &state).unwrap();
+
+        let loaded = load_state(&path).unwrap();
+        assert_eq!(loaded.sites, state.sites);
+        assert_eq!(loaded.default_tenant, state.default_tenant);
+
+        let order = loaded.boot_order(&["docs".to_string(), "shop".to_string(), "gone".to_string()]);
+        let order: Vec<&str> = order.iter().map(|site| site.site_id.as_str()).collect();
+        assert_eq!(order, ["shop", "docs", "blog"]);
+
+        std::fs::write(&path, b"{").unwrap();
+        assert!(load_state(&path).is_err());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(RestoreConfig::default().validate().is_ok());
+        let config: RestoreConfig = toml::from_str("concurrency = 0").unwrap();
+        assert!(config.validate().is_err());
+        assert!(config.enabled);
+    }
+}
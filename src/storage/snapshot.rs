@@ -0,0 +1,476 @@
+// Snapshots
+// Tenants, deployed sites, their modules, bans and CRDT documents in one versioned, checksummed archive
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+use uuid::Uuid;
+
+use super::modules::{ModuleHash, ModuleStore};
+use crate::ai::AiSecurityModule;
+use crate::cage::config::CageConfig;
+use crate::cage::pool::CagePool;
+use crate::crdt::DocumentStore;
+use crate::router::Router;
+use crate::supervisor::Supervisor;
+use crate::tenancy::{Tenant, TenantManager};
+
+/// Written in the `format` field of every archive
+pub const SNAPSHOT_FORMAT: &str = "pear-snapshot";
+
+/// Archive version written by this build; older versions are still read
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A snapshot as stored on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotArchive {
+    pub format: String,
+    pub version: u32,
+
+    /// SHA-256 of `snapshot` serialized as compact JSON with sorted keys
+    pub sha256: String,
+
+    pub snapshot: serde_json::Value,
+}
+
+impl SnapshotArchive {
+    /// Wrap a snapshot with the current version and its checksum
+    pub fn seal(snapshot: &Snapshot) -> Result<Self> {
+        let snapshot = serde_json::to_value(snapshot)?;
+        Ok(Self {
+            format: SNAPSHOT_FORMAT.to_string(),
+            version: SNAPSHOT_VERSION,
+            sha256: checksum(&snapshot)?,
+            snapshot,
+        })
+    }
+
+    /// Check the version and checksum, then the snapshot's own consistency
+    pub fn open(self) -> Result<Snapshot> {
+        if self.format != SNAPSHOT_FORMAT {
+            bail!("Not a Pear snapshot (format '{}')", self.format);
+        }
+        if self.version == 0 || self.version > SNAPSHOT_VERSION {
+            bail!("Snapshot version {} is not supported (this build reads up to {})", self.version, SNAPSHOT_VERSION);
+        }
+        let actual = checksum(&self.snapshot)?;
+        if actual != self.sha256 {
+            bail!("Snapshot checksum mismatch: archive says {}, contents hash to {}", self.sha256, actual);
+        }
+        let snapshot: Snapshot = serde_json::from_value(self.snapshot).context("Malformed snapshot")?;
+        snapshot.verify()?;
+        Ok(snapshot)
+    }
+}
+
+/// `serde_json::Value` keeps object keys sorted, so the same contents always hash the same
+fn checksum(snapshot: &serde_json::Value) -> Result<String> {
+    let bytes = serde_json::to_vec(snapshot)?;
+    Ok(hex::encode(digest(&SHA256, &bytes)))
+}
+
+/// Everything needed to rebuild a node's tenants and sites elsewhere
+///
+/// Site secrets stay sealed with the source node's secret key; copy `secret_key_file`
+/// along with the snapshot when migrating.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub created_at: i64,
+
+    /// The source node's default tenant, restored as the restoring node's default tenant
+    pub default_tenant: Uuid,
+
+    /// Set when the snapshot holds a single tenant
+    #[serde(default)]
+    pub tenant: Option<Uuid>,
+
+    pub tenants: Vec<Tenant>,
+    pub sites: Vec<SiteSnapshot>,
+    pub modules: Vec<ModuleSnapshot>,
+    pub bans: Vec<BanSnapshot>,
+    pub documents: Vec<DocumentSnapshot>,
+}
+
+/// A site's live pool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteSnapshot {
+    pub site_id: String,
+
+    /// None for sites outside any tenant, such as the demonstration site
+    pub tenant_id: Option<Uuid>,
+
+    pub module: ModuleHash,
+    pub replicas: usize,
+}
+
+/// A deployed module, base64 encoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleSnapshot {
+    pub hash: ModuleHash,
+    pub wasm: String,
+}
+
+/// A banned address, with the Unix time it was banned at
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanSnapshot {
+    pub ip: IpAddr,
+    pub banned_at: i64,
+}
+
+/// A site's CRDT document in Automerge's save format, base64 encoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentSnapshot {
+    pub site_id: String,
+    pub data: String,
+}
+
+impl Snapshot {
+    /// Only `tenant_id`, its sites, their modules and documents; bans are node-wide and left out
+    pub fn for_tenant(&self, tenant_id: Uuid) -> Result<Self> {
+        let Some(tenant) = self.tenants.iter().find(|tenant| tenant.id == tenant_id) else {
+            bail!("Tenant {} is not in the snapshot", tenant_id);
+        };
+        let sites: Vec<SiteSnapshot> = self.sites.iter()
+            .filter(|site| site.tenant_id == Some(tenant_id))
+            .cloned()
+            .collect();
+        let site_ids: HashSet<&str> = tenant.sites.iter().map(|site| site.id.as_str()).collect();
+        let hashes: HashSet<ModuleHash> = sites.iter().map(|site| site.module).collect();
+        Ok(Self {
+            created_at: self.created_at,
+            default_tenant: self.default_tenant,
+            tenant: Some(tenant_id),
+            tenants: vec![tenant.clone()],
+            modules: self.modules.iter().filter(|module| hashes.contains(&module.hash)).cloned().collect(),
+            bans: Vec::new(),
+            documents: self.documents.iter()
+                .filter(|document| site_ids.contains(document.site_id.as_str()))
+                .cloned()
+                .collect(),
+            sites,
+        })
+    }
+
+    /// A tenant in the snapshot by ID, or by name when the name is unique
+    pub fn find_tenant(&self, tenant: &str) -> Result<Uuid> {
+        if let Some(found) = tenant.parse::<Uuid>().ok().filter(|id| self.tenants.iter().any(|t| t.id == *id)) {
+            return Ok(found);
+        }
+        let mut named = self.tenants.iter().filter(|t| t.name == tenant);
+        match (named.next(), named.next()) {
+            (Some(found), None) => Ok(found.id),
+            (Some(_), Some(_)) => bail!("Several tenants in the snapshot are named {}; use the tenant ID", tenant),
+            _ => bail!("Tenant {} is not in the snapshot", tenant),
+        }
+    }
+
+    /// Modules decoded and checked against their hashes
+    pub fn module_bytes(&self) -> Result<HashMap<ModuleHash, Vec<u8>>> {
+        let mut modules = HashMap::new();
+        for module in &self.modules {
+            let wasm = decode(&module.wasm).with_context(|| format!("Module {} is not valid base64", module.hash))?;
+            if ModuleHash::of(&wasm) != module.hash {
+                bail!("Module {} does not match its hash", module.hash);
+            }
+            modules.insert(module.hash, wasm);
+        }
+        Ok(modules)
+    }
+
+    /// Check every module against its hash and every reference against the snapshot's contents
+    pub fn verify(&self) -> Result<()> {
+        let modules = self.module_bytes()?;
+        let tenants: HashSet<Uuid> = self.tenants.iter().map(|tenant| tenant.id).collect();
+        for site in &self.sites {
+            if !modules.contains_key(&site.module) {
+                bail!("Site {} runs module {}, which is not in the snapshot", site.site_id, site.module);
+            }
+            if let Some(tenant_id) = site.tenant_id.filter(|tenant_id| !tenants.contains(tenant_id)) {
+                bail!("Site {} belongs to tenant {}, which is not in the snapshot", site.site_id, tenant_id);
+            }
+        }
+        for document in &self.documents {
+            decode(&document.data).with_context(|| format!("Document of {} is not valid base64", document.site_id))?;
+        }
+        Ok(())
+    }
+
+    /// Counts for reporting what the snapshot holds
+    pub fn summary(&self) -> SnapshotSummary {
+        SnapshotSummary {
+            tenants: self.tenants.len(),
+            sites: self.sites.iter().map(|site| site.site_id.clone()).collect(),
+            modules: self.modules.len(),
+            bans: self.bans.len(),
+            documents: self.documents.len(),
+        }
+    }
+}
+
+/// What a snapshot holds, or what was restored from one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotSummary {
+    pub tenants: usize,
+    pub sites: Vec<String>,
+    pub modules: usize,
+    pub bans: usize,
+    pub documents: usize,
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+fn decode(text: &str) -> Result<Vec<u8>> {
+    Ok(base64::engine::general_purpose::STANDARD.decode(text)?)
+}
+
+/// Takes snapshots of a running node and restores them into it
+pub struct SnapshotManager {
+    tenants: Arc<TenantManager>,
+    router: Arc<Router>,
+    supervisor: Arc<Supervisor>,
+    modules: Arc<ModuleStore>,
+    ai_module: Arc<AiSecurityModule>,
+    documents: Arc<DocumentStore>,
+}
+
+impl SnapshotManager {
+    pub fn new(
+        tenants: Arc<TenantManager>,
+        router: Arc<Router>,
+        supervisor: Arc<Supervisor>,
+        modules: Arc<ModuleStore>,
+        ai_module: Arc<AiSecurityModule>,
+        documents: Arc<DocumentStore>,
+    ) -> Self {
+        Self { tenants, router, supervisor, modules, ai_module, documents }
+    }
+
+    /// Snapshot the whole node, or only `tenant`
+    pub async fn create(&self, tenant: Option<Uuid>) -> Result<Snapshot> {
+        let mut sites = Vec::new();
+        let mut modules = Vec::new();
+        let mut hashes = HashSet::new();
+        for site_id in self.router.site_ids() {
+            let (Some(pool), Some(module)) = (self.router.pool(&site_id), self.supervisor.module(&site_id)) else {
+                continue;
+            };
+            let hash = module.hash();
+            if hashes.insert(hash) {
+                // Read back through the store so a corrupt module fails the snapshot instead of the restore
+                modules.push(ModuleSnapshot { hash, wasm: encode(&self.modules.read(&hash)?) });
+            }
+            sites.push(SiteSnapshot {
+                tenant_id: self.tenants.find_site_tenant(&site_id),
+                site_id,
+                module: hash,
+                replicas: pool.size().await,
+            });
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let bans = self.ai_module.path_monitor().bans().into_iter()
+            .map(|(ip, age)| BanSnapshot { ip, banned_at: now - age.as_secs() as i64 })
+            .collect();
+
+        let mut documents = Vec::new();
+        for site_id in self.documents.site_ids() {
+            if let Some(document) = self.documents.get(&site_id) {
+                documents.push(DocumentSnapshot { data: encode(&document.get_changes().await?), site_id });
+            }
+        }
+
+        let snapshot = Snapshot {
+            created_at: now,
+            default_tenant: self.tenants.default_tenant_id(),
+            tenant: None,
+            tenants: self.tenants.list_tenants(),
+            sites,
+            modules,
+            bans,
+            documents,
+        };
+        let snapshot = match tenant {
+            Some(tenant_id) => snapshot.for_tenant(tenant_id)?,
+            None => snapshot,
+        };
+        info!(tenants = snapshot.tenants.len(), sites = snapshot.sites.len(), "Snapshot created");
+        Ok(snapshot)
+    }
+
+    /// Restore everything in `snapshot`, or only `tenant`
+    /// Tenants are replaced by their saved copies, saved sites get fresh pools running their saved
+    /// module, and documents are merged into the sites' current ones. Other tenants and sites are untouched.
+    pub async fn restore(&self, snapshot: Snapshot, tenant: Option<Uuid>) -> Result<SnapshotSummary> {
+        let snapshot = match tenant {
+            Some(tenant_id) => snapshot.for_tenant(tenant_id)?,
+            None => snapshot,
+        };
+        snapshot.verify()?;
+        let wasm = snapshot.module_bytes()?;
+
+        let default_tenant = self.tenants.default_tenant_id();
+        for tenant in &snapshot.tenants {
+            let mut tenant = tenant.clone();
+            if tenant.id == snapshot.default_tenant {
+                tenant.id = default_tenant;
+            }
+            self.tenants.restore_tenant(tenant)?;
+        }
+
+        for site in &snapshot.sites {
+            let wasm_bytes = wasm.get(&site.module).cloned().context("Module missing from snapshot")?;
+            let module = self.modules.put(&wasm_bytes)?;
+            let replicas = site.replicas.max(1);
+            // A live pool passes its Cage config on; a new site starts from the defaults
+            let pool = match self.router.pool(&site.site_id) {
+                Some(live) => {
+                    let pool = live.sibling(wasm_bytes.clone()).await?;
+                    if pool.size().await != replicas {
+                        pool.scale(replicas, &wasm_bytes).await?;
+                    }
+                    pool
+                }
+                None => match self.tenants.site_quota(&site.site_id) {
+                    Some(quota) => {
+                        CagePool::new_with_quota(site.site_id.clone(), wasm_bytes, CageConfig::default(), replicas, quota).await?
+                    }
+                    None => CagePool::new(site.site_id.clone(), wasm_bytes, CageConfig::default(), replicas).await?,
+                },
+            };
+            let pool = Arc::new(pool);
+            self.router.register_pool(site.site_id.clone(), pool.clone());
+            self.supervisor.register_pool(site.site_id.clone(), pool, module);
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        for ban in &snapshot.bans {
+            let age = Duration::from_secs(now.saturating_sub(ban.banned_at).max(0) as u64);
+            self.ai_module.path_monitor().restore_ban(ban.ip, age);
+        }
+
+        for document in &snapshot.documents {
+            let data = decode(&document.data)?;
+            self.documents.document(&document.site_id).apply_changes(&data).await
+                .with_context(|| format!("Failed to restore the document of {}", document.site_id))?;
+        }
+
+        info!(tenants = snapshot.tenants.len(), sites = snapshot.sites.len(), bans = snapshot.bans.len(), "Snapshot restored");
+        Ok(snapshot.summary())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenancy::{ResourceQuota, Site, TenantStatus};
+
+    fn tenant(id: Uuid, site_ids: &[&str]) -> Tenant {
+        Tenant {
+            id,
+            name: "Acme".to_string(),
+            email: "ops@acme.test".to_string(),
+            quota: ResourceQuota::default(),
+            sites: site_ids.iter().map(|site_id| Site {
+                id: site_id.to_string(),
+                name: site_id.to_string(),
+                domain: None,
+                cage_count: 1,
+                storage_used_mb: 0,
+                created_at: chrono::Utc::now(),
+                storage_scanned_at: None,
+                ai_policy: Default::default(),
+                env: Default::default(),
+                require_signature: false,
+                git: None,
+            }).collect(),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            status: TenantStatus::Active,
+            ai_policy: Default::default(),
+            signing_keys: Vec::new(),
+            artifact_source: None,
+        }
+    }
+
+    fn sample() -> (Snapshot, Uuid) {
+        let (acme, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let (blog, shop) = (b"\0asm blog".to_vec(), b"\0asm shop".to_vec());
+        let site = |site_id: &str, tenant_id, wasm: &[u8]| SiteSnapshot {
+            site_id: site_id.to_string(),
+            tenant_id: Some(tenant_id),
+            module: ModuleHash::of(wasm),
+            replicas: 2,
+        };
+        let snapshot = Snapshot {
+            created_at: 1_700_000_000,
+            default_tenant: other,
+            tenant: None,
+            tenants: vec![tenant(acme, &["blog"]), tenant(other, &["shop"])],
+            sites: vec![site("blog", acme, &blog), site("shop", other, &shop)],
+            modules: [&blog, &shop].iter()
+                .map(|wasm| ModuleSnapshot { hash: ModuleHash::of(wasm), wasm: encode(wasm) })
+                .collect(),
+            bans: vec![BanSnapshot { ip: "203.0.113.9".parse().unwrap(), banned_at: 1_700_000_000 }],
+            documents: vec![
+                DocumentSnapshot { site_id: "blog".to_string(), data: encode(b"blog") },
+                DocumentSnapshot { site_id: "shop".to_string(), data: encode(b"shop") },
+            ],
+        };
+        (snapshot, acme)
+    }
+
+    #[test]
+    fn test_archive_round_trip_and_tampering() {
+        let (snapshot, _) = sample();
+        let archive = SnapshotArchive::seal(&snapshot).unwrap();
+
+        // Written pretty and read back, the checksum still holds
+        let text = serde_json::to_string_pretty(&archive).unwrap();
+        let restored = serde_json::from_str::<SnapshotArchive>(&text).unwrap().open().unwrap();
+        assert_eq!(restored.summary().sites, ["blog", "shop"]);
+
+        let mut tampered = archive.clone();
+        tampered.snapshot["sites"][0]["replicas"] = serde_json::json!(9);
+        assert!(tampered.open().unwrap_err().to_string().contains("checksum"));
+
+        let mut future = archive;
+        future.version = SNAPSHOT_VERSION + 1;
+        assert!(future.open().is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_corrupt_module() {
+        let (mut snapshot, _) = sample();
+        snapshot.modules[0].wasm = encode(b"\0asm other");
+        assert!(snapshot.verify().unwrap_err().to_string().contains("does not match"));
+
+        let (mut snapshot, _) = sample();
+        snapshot.modules.remove(1);
+        assert!(snapshot.verify().unwrap_err().to_string().contains("not in the snapshot"));
+    }
+
+    #[test]
+    fn test_for_tenant() {
+        let (snapshot, acme) = sample();
+        let partial = snapshot.for_tenant(acme).unwrap();
+        assert_eq!(partial.tenant, Some(acme));
+        assert_eq!(partial.tenants.len(), 1);
+        assert_eq!(partial.summary().sites, ["blog"]);
+        assert_eq!(partial.modules.len(), 1);
+        assert_eq!(partial.documents.len(), 1);
+        assert!(partial.bans.is_empty());
+        partial.verify().unwrap();
+
+        assert!(snapshot.for_tenant(Uuid::new_v4()).is_err());
+        assert_eq!(snapshot.find_tenant(&acme.to_string()).unwrap(), acme);
+        assert!(snapshot.find_tenant("Acme").unwrap_err().to_string().contains("Several"));
+    }
+}
@@ -0,0 +1,175 @@
+// Storage Usage
+// Cached per-site directory sizes, invalidated by file events and rescanned in the background
+
+use dashmap::{DashMap, DashSet};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// A site's storage as of its last scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SiteUsage {
+    pub bytes: u64,
+
+    /// Unix time the directory was walked
+    pub scanned_at: i64,
+}
+
+impl SiteUsage {
+    pub fn mb(&self) -> usize {
+        (self.bytes / (1024 * 1024)) as usize
+    }
+}
+
+type SiteKey = (Uuid, String);
+
+/// Last scan of every site, and the sites whose files changed since
+#[derive(Default)]
+pub struct UsageCache {
+    entries: DashMap<SiteKey, SiteUsage>,
+    dirty: DashSet<SiteKey>,
+    changed: Notify,
+}
+
+impl UsageCache {
+    /// The cached usage, unless files changed since it was scanned
+    pub fn get(&self, tenant_id: Uuid, site_id: &str) -> Option<SiteUsage> {
+        let key = (tenant_id, site_id.to_string());
+        if self.dirty.contains(&key) {
+            return None;
+        }
+        self.entries.get(&key).map(|entry| *entry)
+    }
+
+    pub fn record(&self, tenant_id: Uuid, site_id: &str, usage: SiteUsage) {
+        let key = (tenant_id, site_id.to_string());
+        self.dirty.remove(&key);
+        self.entries.insert(key, usage);
+    }
+
+    /// Mark a site for rescanning, waking whoever waits on `changed`
+    pub fn invalidate(&self, tenant_id: Uuid, site_id: &str) {
+        if self.dirty.insert((tenant_id, site_id.to_string())) {
+            self.changed.notify_one();
+        }
+    }
+
+    pub fn forget(&self, tenant_id: Uuid, site_id: &str) {
+        let key = (tenant_id, site_id.to_string());
+        self.entries.remove(&key);
+        self.dirty.remove(&key);
+    }
+
+    /// Sites invalidated since the last call
+    pub fn take_dirty(&self) -> Vec<SiteKey> {
+        let dirty: Vec<SiteKey> = self.dirty.iter().map(|key| key.clone()).collect();
+        for key in &dirty {
+            self.dirty.remove(key);
+        }
+        dirty
+    }
+
+    /// Notified when a site is invalidated
+    pub fn changed(&self) -> &Notify {
+        &self.changed
+    }
+}
+
+/// The site a path under the tenants directory belongs to: `<tenant>/sites/<site>/...`
+pub fn site_of(tenants_path: &Path, path: &Path) -> Option<SiteKey> {
+    let mut parts = path.strip_prefix(tenants_path).ok()?.components().map(|component| match component {
+        Component::Normal(part) => part.to_str(),
+        _ => None,
+    });
+    let tenant_id = parts.next()??.parse().ok()?;
+    if parts.next()?? != "sites" {
+        return None;
+    }
+    Some((tenant_id, parts.next()??.to_string()))
+}
+
+/// Invalidate sites as their files change, where the platform reports file events
+/// Without a watcher (no inotify, or its limits reached), only periodic rescans notice changes.
+pub fn watch(tenants_path: &Path, cache: Arc<UsageCache>) -> Option<RecommendedWatcher> {
+    let root = tenants_path.to_path_buf();
+    let handler = move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        if event.kind.is_access() {
+            return;
+        }
+        for path in &event.paths {
+            if let Some((tenant_id, site_id)) = site_of(&root, path) {
+                cache.invalidate(tenant_id, &site_id);
+            }
+        }
+    };
+
+    let watched = std::fs::create_dir_all(tenants_path).map_err(notify::Error::io).and_then(|()| {
+        let mut watcher = notify::recommended_watcher(handler)?;
+        watcher.watch(tenants_path, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+    match watched {
+        Ok(watcher) => {
+            info!(path = %tenants_path.display(), "Watching tenant storage for changes");
+            Some(watcher)
+        }
+        Err(e) => {
+            warn!(path = %tenants_path.display(), error = %e, "Cannot watch tenant storage, relying on periodic scans");
+            None
+        }
+    }
+}
+
+/// `interval` moved by up to `percent` either way, so nodes sharing a disk don't scan in step
+pub fn jittered(interval: Duration, percent: u8) -> Duration {
+    let spread = interval.as_secs_f64() * f64::from(percent.min(100)) / 100.0;
+    if spread <= 0.0 {
+        return interval;
+    }
+    let offset = rand::thread_rng().gen_range(-spread..=spread);
+    Duration::from_secs_f64((interval.as_secs_f64() + offset).max(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_invalidation() {
+        let cache = UsageCache::default();
+        let tenant_id = Uuid::new_v4();
+        let usage = SiteUsage { bytes: 3 * 1024 * 1024, scanned_at: 1_700_000_000 };
+
+        cache.record(tenant_id, "blog", usage);
+        assert_eq!(cache.get(tenant_id, "blog"), Some(usage));
+        assert_eq!(usage.mb(), 3);
+
+        cache.invalidate(tenant_id, "blog");
+        assert_eq!(cache.get(tenant_id, "blog"), None);
+        assert_eq!(cache.take_dirty(), vec![(tenant_id, "blog".to_string())]);
+        assert!(cache.take_dirty().is_empty());
+
+        let root = Path::new("/data/tenants");
+        let file = root.join(tenant_id.to_string()).join("sites/blog/uploads/a.png");
+        assert_eq!(site_of(root, &file), Some((tenant_id, "blog".to_string())));
+        assert_eq!(site_of(root, &root.join(tenant_id.to_string())), None);
+        assert_eq!(site_of(root, Path::new("/elsewhere/x")), None);
+    }
+
+    #[test]
+    fn test_jitter_stays_in_range() {
+        let interval = Duration::from_secs(300);
+        assert_eq!(jittered(interval, 0), interval);
+        for _ in 0..100 {
+            let next = jittered(interval, 20);
+            assert!(next >= Duration::from_secs(240) && next <= Duration::from_secs(360), "{:?}", next);
+        }
+    }
+}
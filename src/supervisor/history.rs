@@ -0,0 +1,172 @@
+// Healing History
+// What the Supervisor did to each pool it healed, kept for the API, CLI and dashboard timeline
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Healing events kept before the oldest are dropped
+pub const HISTORY_LEN: usize = 500;
+
+/// How a healing attempt ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealingOutcome {
+    /// The pool is back at its replica target
+    Healed,
+    /// Some replacement Cages couldn't be spawned
+    Partial,
+    /// The module couldn't be loaded or the pool repaired
+    Failed,
+}
+
+/// One healing attempt on a pool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealingEvent {
+    /// Increasing, so clients can ask for the events after one they have
+    pub id: u64,
+
+    /// Unix time in seconds
+    pub timestamp: i64,
+
+    /// Site, or a route's pool key
+    pub site_id: String,
+
+    /// Counts from 1 for each pool, up to the Supervisor's `max_respawn_attempts`
+    pub attempt: u32,
+
+    /// Delay the backoff required since the previous attempt
+    pub backoff_ms: u64,
+
+    pub cages_removed: usize,
+    pub cages_spawned: usize,
+    pub outcome: HealingOutcome,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    pub duration_ms: u64,
+}
+
+/// Filter for listing healing events
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealingQuery {
+    /// Only events of this site and its route pools
+    pub site: Option<String>,
+
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize { 100 }
+
+impl HealingQuery {
+    fn matches(&self, event: &HealingEvent) -> bool {
+        match &self.site {
+            Some(site) => event.site_id.strip_prefix(site.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+            None => true,
+        }
+    }
+}
+
+/// The most recent healing events, oldest first
+pub struct HealingHistory {
+    events: Mutex<VecDeque<HealingEvent>>,
+    next_id: AtomicU64,
+}
+
+impl Default for HealingHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealingHistory {
+    pub fn new() -> Self {
+        Self {
+            events: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Keep an event, numbering it; returns its ID
+    pub fn record(&self, mut event: HealingEvent) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        event.id = id;
+        let mut events = self.events.lock();
+        if events.len() == HISTORY_LEN {
+            events.pop_front();
+        }
+        events.push_back(event);
+        id
+    }
+
+    /// The latest `query.limit` matching events, oldest first
+    pub fn recent(&self, query: &HealingQuery) -> Vec<HealingEvent> {
+        let events = self.events.lock();
+        let mut matching: Vec<HealingEvent> = events.iter().rev()
+            .filter(|event| query.matches(event))
+            .take(query.limit)
+            .cloned()
+            .collect();
+        matching.reverse();
+        matching
+    }
+
+    /// At most `limit` of the latest events after `id`, oldest first
+    pub fn since(&self, id: u64, limit: usize) -> Vec<HealingEvent> {
+        let events = self.events.lock();
+        let newer = events.iter().rev().take_while(|event| event.id > id).count();
+        events.iter().skip(events.len() - newer.min(limit)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(site_id: &str) -> HealingEvent {
+        HealingEvent {
+            id: 0,
+            timestamp: 0,
+            site_id: site_id.to_string(),
+            attempt: 1,
+            backoff_ms: 1000,
+            cages_removed: 1,
+            cages_spawned: 1,
+            outcome: HealingOutcome::Healed,
+            error: None,
+            duration_ms: 5,
+        }
+    }
+
+    #[test]
+    fn test_history_queries() {
+        let history = HealingHistory::new();
+        for site_id in ["blog", "blog/api", "blogs", "shop"] {
+            history.record(event(site_id));
+        }
+
+        let blog = history.recent(&HealingQuery { site: Some("blog".to_string()), limit: 10 });
+        assert_eq!(blog.iter().map(|e| e.site_id.as_str()).collect::<Vec<_>>(), ["blog", "blog/api"]);
+        let latest = history.recent(&HealingQuery { site: None, limit: 1 });
+        assert_eq!(latest[0].id, 4);
+
+        assert_eq!(history.since(2, 10).iter().map(|e| e.id).collect::<Vec<_>>(), [3, 4]);
+        assert_eq!(history.since(0, 1).iter().map(|e| e.id).collect::<Vec<_>>(), [4]);
+        assert!(history.since(4, 10).is_empty());
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let history = HealingHistory::new();
+        for _ in 0..HISTORY_LEN + 5 {
+            history.record(event("blog"));
+        }
+        let all = history.recent(&HealingQuery { site: None, limit: usize::MAX });
+        assert_eq!(all.len(), HISTORY_LEN);
+        assert_eq!(all[0].id, 6);
+    }
+}
@@ -1,9 +1,13 @@
 // Self-Healing Supervisor Module
 // Automatic failure detection and recovery system
 
+pub mod history;
 pub mod monitor;
 
-use crate::cage::pool::{CagePool, PoolHealthStats};
+use crate::cage::pool::{CagePool, PoolHealthStats, ReplicaRepair};
+use crate::router::health::HealthChecker;
+use history::{HealingEvent, HealingHistory, HealingOutcome};
+use crate::storage::modules::ModuleRef;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tokio::time::{Duration, Interval};
@@ -49,14 +53,20 @@ pub struct Supervisor {
     /// Total healing events counter
     healing_events: Arc<std::sync::atomic::AtomicU64>,
     
+    /// Recent healing attempts and what they did
+    history: Arc<HealingHistory>,
+    
     /// Running flag
     running: Arc<std::sync::atomic::AtomicBool>,
+    
+    /// Probes whose failing Cages are replaced (only crashed Cages are until set)
+    health: std::sync::OnceLock<Arc<HealthChecker>>,
 }
 
-/// Supervised pool with Wasm bytes for respawning
+/// Supervised pool with the stored module it respawns from
 struct SupervisedPool {
     pool: Arc<CagePool>,
-    wasm_bytes: Vec<u8>,
+    module: ModuleRef,
     respawn_attempts: Arc<std::sync::atomic::AtomicU32>,
     last_respawn: Arc<RwLock<Option<std::time::Instant>>>,
 }
@@ -70,17 +80,19 @@ impl Supervisor {
             config,
             pools: Arc::new(DashMap::new()),
             healing_events: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            history: Arc::new(HealingHistory::new()),
             running: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            health: std::sync::OnceLock::new(),
         }
     }
 
     /// Register a CagePool for supervision
-    pub fn register_pool(&self, site_id: String, pool: Arc<CagePool>, wasm_bytes: Vec<u8>) {
+    pub fn register_pool(&self, site_id: String, pool: Arc<CagePool>, module: ModuleRef) {
         info!(site_id = %site_id, "Registering pool with Supervisor");
         
         let supervised = SupervisedPool {
             pool,
-            wasm_bytes,
+            module,
             respawn_attempts: Arc::new(std::sync::atomic::AtomicU32::new(0)),
             last_respawn: Arc::new(RwLock::new(None)),
         };
@@ -94,6 +106,25 @@ impl Supervisor {
         self.pools.remove(site_id);
     }
 
+    /// Module a supervised site's Cages are respawned from
+    pub fn module(&self, site_id: &str) -> Option<ModuleRef> {
+        self.pools.get(site_id).map(|supervised| supervised.module.clone())
+    }
+
+    /// Respawn the site's Cages from `module` from now on, returning the module it replaced
+    pub fn set_module(&self, site_id: &str, module: ModuleRef) -> Option<ModuleRef> {
+        self.pools.get_mut(site_id)
+            .map(|mut supervised| std::mem::replace(&mut supervised.module, module))
+    }
+
+    /// Replace Cages that fail the checker's probes, as if they had crashed
+    /// Takes effect when the loop starts.
+    pub fn set_health_checker(&self, health: Arc<HealthChecker>) {
+        if self.health.set(health).is_err() {
+            warn!("Health checker already attached to Supervisor");
+        }
+    }
+
     /// Start the supervision loop
     #[instrument(skip(self))]
     pub async fn start(&self) {
@@ -107,7 +138,9 @@ impl Supervisor {
         let pools = self.pools.clone();
         let config = self.config.clone();
         let healing_events = self.healing_events.clone();
+        let history = self.history.clone();
         let running = self.running.clone();
+        let health = self.health.get().cloned();
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(
@@ -121,8 +154,23 @@ impl Supervisor {
                     let site_id = entry.key();
                     let supervised = entry.value();
 
-                    // Check pool health
+                    // Drained for maintenance; Cages stopped meanwhile are left alone
+                    if supervised.pool.is_draining() {
+                        continue;
+                    }
+
+                    if let Some(health) = &health {
+                        Self::crash_failing(&supervised.pool, site_id, health).await;
+                    }
+                    // Cages crashed since the last tick are replaced by standbys before any backoff
+                    supervised.pool.promote_standbys().await;
+                    supervised.pool.scale_to_zero_if_idle().await;
+
+                    // Check pool health; cold pools have no Cages to heal
                     let stats = supervised.pool.health_stats().await;
+                    if stats.cold {
+                        continue;
+                    }
 
                     debug!(
                         site_id = %site_id,
@@ -132,12 +180,13 @@ impl Supervisor {
                         "Pool health check"
                     );
 
-                    // If pool is unhealthy, attempt healing
-                    if stats.crashed_cages > 0 || stats.healthy_cages == 0 {
+                    // If pool is unhealthy or short of standbys, attempt healing
+                    if stats.crashed_cages > 0 || stats.healthy_cages == 0 || stats.standby_cages < stats.standby_target {
                         warn!(
                             site_id = %site_id,
                             crashed = stats.crashed_cages,
                             healthy = stats.healthy_cages,
+                            standby = stats.standby_cages,
                             "Pool requires healing"
                         );
 
@@ -146,6 +195,7 @@ impl Supervisor {
                             site_id,
                             &config,
                             &healing_events,
+                            &history,
                         ).await {
                             error!(
                                 site_id = %site_id,
@@ -168,12 +218,13 @@ impl Supervisor {
     }
 
     /// Heal a pool by respawning failed Cages
-    #[instrument(skip(supervised, config, healing_events))]
+    #[instrument(skip(supervised, config, healing_events, history))]
     async fn heal_pool(
         supervised: &SupervisedPool,
         site_id: &str,
         config: &SupervisorConfig,
         healing_events: &Arc<std::sync::atomic::AtomicU64>,
+        history: &HealingHistory,
     ) -> anyhow::Result<()> {
         // Check respawn attempts
         let attempts = supervised.respawn_attempts.load(std::sync::atomic::Ordering::Relaxed);
@@ -217,10 +268,42 @@ impl Supervisor {
         );
 
         // Maintain replicas (removes crashed and spawns new)
-        supervised.pool.maintain_replicas(&supervised.wasm_bytes).await?;
+        let module = supervised.module.clone();
+        let started = std::time::Instant::now();
+        let result: anyhow::Result<ReplicaRepair> = async {
+            let wasm_bytes = tokio::task::spawn_blocking(move || module.load()).await??;
+            supervised.pool.maintain_replicas(&wasm_bytes).await
+        }.await;
+
+        let repair = result.as_ref().copied().unwrap_or_default();
+        history.record(HealingEvent {
+            id: 0,
+            timestamp: chrono::Utc::now().timestamp(),
+            site_id: site_id.to_string(),
+            attempt: attempts + 1,
+            backoff_ms: delay_ms,
+            cages_removed: repair.removed,
+            cages_spawned: repair.spawned,
+            outcome: match &result {
+                Ok(repair) if repair.missing > 0 => HealingOutcome::Partial,
+                Ok(_) => HealingOutcome::Healed,
+                Err(_) => HealingOutcome::Failed,
+            },
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+        result?;
 
         // Update respawn tracking
-        supervised.respawn_attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let attempts = supervised.respawn_attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+        if attempts == config.max_respawn_attempts {
+            crate::notifications::notify(crate::notifications::Notification::new(
+                crate::notifications::EventKind::CrashLoop,
+                Some(site_id),
+                format!("Cages of {} keep crashing; gave up after {} respawns", site_id, attempts),
+                serde_json::json!({ "respawn_attempts": attempts }),
+            ));
+        }
         *last_respawn = Some(std::time::Instant::now());
         healing_events.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
@@ -229,12 +312,32 @@ impl Supervisor {
         Ok(())
     }
 
+    /// Mark Cages failing their health probes as crashed, so healing replaces them
+    async fn crash_failing(pool: &CagePool, key: &str, health: &HealthChecker) {
+        let failing = health.failing(key);
+        if failing.is_empty() {
+            return;
+        }
+        for cage in pool.cages().await {
+            if failing.contains(&cage.id()) {
+                warn!(pool = %key, cage_id = cage.id(), "Replacing Cage failing its health probes");
+                pool.crash(&cage).await;
+                health.forget(key, cage.id());
+            }
+        }
+    }
+
     /// Calculate exponential backoff delay
     fn calculate_backoff(attempts: u32, min_ms: u64, max_ms: u64) -> u64 {
         let delay = min_ms * 2u64.pow(attempts);
         delay.min(max_ms)
     }
 
+    /// Recent healing attempts
+    pub fn history(&self) -> &Arc<HealingHistory> {
+        &self.history
+    }
+
     /// Get supervisor statistics
     pub fn stats(&self) -> SupervisorStats {
         SupervisorStats {
@@ -1,19 +1,167 @@
 // Multi-Tenancy Authentication and Authorization
-// JWT-based tenant authentication with role-based access control
+// Root token plus scoped API tokens, whose roles grant permissions on the node, a tenant or some of its sites
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use anyhow::{Result, Context};
-use tracing::{info, warn};
+use anyhow::{Result, Context, bail};
+use parking_lot::RwLock;
+use rand::RngCore;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::info;
+
+use super::lockout::LoginGuard;
+use super::oidc::{Grant, Identity, OidcProvider};
+
+/// Prefix of API token secrets, so leaked ones are easy to search for
+const TOKEN_PREFIX: &str = "pear_";
+
+/// `[rbac]`: custom roles and scoped API tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RbacConfig {
+    /// Where roles and tokens created through the API are kept ("" = until restart)
+    pub state_path: String,
+
+    /// Require a token with `view-metrics` to read `/api` and `/metrics`
+    pub protect_reads: bool,
+}
+
+impl Default for RbacConfig {
+    fn default() -> Self {
+        Self {
+            state_path: "access.json".to_string(),
+            protect_reads: false,
+        }
+    }
+}
+
+impl RbacConfig {
+    pub fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Something a token can be allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    /// Stage, switch and roll back deployments; push site files and Git sources
+    Deploy,
+    /// Read metrics, connections, crash reports, recordings and profiles
+    ViewMetrics,
+    /// Change site settings: environment, health checks, access rules, cron jobs, draining
+    ManageSites,
+    /// Claim, verify and release domains
+    ManageDomains,
+    /// Ban and unban clients, change security rules
+    ManageBans,
+    /// Tenants and their keys, quotas and mail; webhooks, roles and tokens
+    ManageTenants,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 6] = [
+        Permission::Deploy,
+        Permission::ViewMetrics,
+        Permission::ManageSites,
+        Permission::ManageDomains,
+        Permission::ManageBans,
+        Permission::ManageTenants,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Deploy => "deploy",
+            Permission::ViewMetrics => "view-metrics",
+            Permission::ManageSites => "manage-sites",
+            Permission::ManageDomains => "manage-domains",
+            Permission::ManageBans => "manage-bans",
+            Permission::ManageTenants => "manage-tenants",
+        }
+    }
+}
 
 /// User role in the system
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Role {
     /// Root administrator with global access
     RootAdmin,
-    
+
     /// Tenant administrator with tenant-specific access
     TenantAdmin,
+
+    /// A role defined with `set_role`, by name
+    Custom(String),
+}
+
+impl Role {
+    /// `tenant-admin` or the name of a custom role
+    pub fn parse(name: &str) -> Self {
+        match name {
+            "root-admin" => Role::RootAdmin,
+            "tenant-admin" => Role::TenantAdmin,
+            name => Role::Custom(name.to_string()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Role::RootAdmin => "root-admin",
+            Role::TenantAdmin => "tenant-admin",
+            Role::Custom(name) => name,
+        }
+    }
+}
+
+/// Permissions granted under a name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomRole {
+    pub name: String,
+
+    /// Tenant the role belongs to; None for roles tokens of every tenant can use
+    pub tenant_id: Option<Uuid>,
+
+    pub permissions: BTreeSet<Permission>,
+}
+
+/// An API token, without its secret
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+
+    /// Tenant the token acts for; None for node-wide tokens
+    pub tenant_id: Option<Uuid>,
+
+    pub role: Role,
+
+    /// Sites the token is limited to (empty = every site in its scope)
+    #[serde(default)]
+    pub sites: Vec<String>,
+
+    /// Only `view-metrics` is granted, whatever the role allows
+    #[serde(default)]
+    pub read_only: bool,
+
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+/// A token as kept in the state file: only a hash of the secret is stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    secret_sha256: String,
+}
+
+/// Roles and tokens created through the API
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Access {
+    roles: Vec<CustomRole>,
+    tokens: Vec<StoredToken>,
 }
 
 /// Authentication token claims
@@ -21,24 +169,85 @@ pub enum Role {
 pub struct TokenClaims {
     /// User ID
     pub user_id: Uuid,
-    
+
     /// User role
     pub role: Role,
-    
-    /// Tenant ID (None for RootAdmin)
+
+    /// Tenant ID (None for RootAdmin and node-wide tokens)
     pub tenant_id: Option<Uuid>,
-    
+
+    /// What the token may do, resolved from its role
+    #[serde(default)]
+    pub permissions: BTreeSet<Permission>,
+
+    /// Sites the token is limited to (empty = every site in its scope)
+    #[serde(default)]
+    pub sites: Vec<String>,
+
+    /// Who signed in, for single sign-on logins
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+
     /// Issued at (Unix timestamp)
     pub iat: i64,
-    
+
     /// Expires at (Unix timestamp)
     pub exp: i64,
 }
 
+/// What a request acts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope<'a> {
+    /// The node as a whole: global settings, or every tenant at once
+    Node,
+
+    /// One tenant and all of its sites
+    Tenant(Uuid),
+
+    /// One site, with the tenant owning it if any
+    Site { tenant_id: Option<Uuid>, site_id: &'a str },
+}
+
+/// A token to create
+#[derive(Debug, Clone)]
+pub struct TokenRequest {
+    pub name: String,
+    pub tenant_id: Option<Uuid>,
+    pub role: Role,
+    pub sites: Vec<String>,
+    pub read_only: bool,
+    pub expires_at: Option<i64>,
+}
+
 /// Authentication manager
 pub struct AuthManager {
     /// Root admin token (for demo)
     root_admin_token: String,
+
+    /// Where roles and tokens are saved, if anywhere
+    state_path: Option<PathBuf>,
+
+    access: RwLock<Access>,
+
+    protect_reads: bool,
+
+    /// Provider for single sign-on, when `[oidc]` is enabled
+    oidc: Option<Arc<OidcProvider>>,
+
+    /// Dashboard logins through the provider, by hash of their secret; kept until restart
+    sessions: RwLock<HashMap<String, Session>>,
+
+    /// Failed login counters, when `[lockout]` is enabled
+    lockout: Option<Arc<LoginGuard>>,
+}
+
+/// A signed-in single sign-on user
+struct Session {
+    user_id: Uuid,
+    subject: String,
+    grant: Grant,
+    started_at: i64,
+    expires_at: i64,
 }
 
 impl AuthManager {
@@ -46,64 +255,304 @@ impl AuthManager {
     pub fn new() -> Self {
         // In production, this would use proper JWT signing
         let root_admin_token = "root_admin_secret_token".to_string();
-        
+
         info!("Authentication manager initialized");
-        
+
+        Self::with_root_token(root_admin_token)
+    }
+
+    /// Accept `token` as the root admin token
+    pub fn with_root_token(token: impl Into<String>) -> Self {
         Self {
-            root_admin_token,
+            root_admin_token: token.into(),
+            state_path: None,
+            access: RwLock::new(Access::default()),
+            protect_reads: false,
+            oidc: None,
+            sessions: RwLock::new(HashMap::new()),
+            lockout: None,
+        }
+    }
+
+    /// Also let users of an OpenID Connect provider sign in
+    pub fn with_oidc(mut self, provider: Arc<OidcProvider>) -> Self {
+        self.oidc = Some(provider);
+        self
+    }
+
+    pub fn oidc(&self) -> Option<&Arc<OidcProvider>> {
+        self.oidc.as_ref()
+    }
+
+    /// Lock out clients and accounts that keep failing to log in
+    pub fn with_lockout(mut self, guard: Arc<LoginGuard>) -> Self {
+        self.lockout = Some(guard);
+        self
+    }
+
+    pub fn lockout(&self) -> Option<&Arc<LoginGuard>> {
+        self.lockout.as_ref()
+    }
+
+    /// Accept `token` as the root admin token, and the roles and tokens saved in `[rbac] state_path`
+    pub fn open(token: impl Into<String>, config: &RbacConfig) -> Result<Self> {
+        let mut auth = Self::with_root_token(token);
+        auth.protect_reads = config.protect_reads;
+        auth.state_path = Some(&config.state_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        if let Some(path) = auth.state_path.as_deref().filter(|path| path.exists()) {
+            let access = load_access(path)?;
+            info!(roles = access.roles.len(), tokens = access.tokens.len(), "API roles and tokens loaded");
+            *auth.access.get_mut() = access;
         }
+        Ok(auth)
+    }
+
+    /// Whether reading the API needs a token too
+    pub fn protects_reads(&self) -> bool {
+        self.protect_reads
     }
 
     /// Validate token and extract claims
     pub fn validate_token(&self, token: &str) -> Result<TokenClaims> {
-        // Simplified validation for Phase 4
-        // In production, use proper JWT library (jsonwebtoken crate)
-        
-        if token == self.root_admin_token {
-            Ok(TokenClaims {
+        let now = chrono::Utc::now().timestamp();
+        if !self.root_admin_token.is_empty() && token == self.root_admin_token {
+            return Ok(TokenClaims {
                 user_id: Uuid::nil(),
                 role: Role::RootAdmin,
                 tenant_id: None,
-                iat: chrono::Utc::now().timestamp(),
-                exp: chrono::Utc::now().timestamp() + 3600 * 24, // 24 hours
-            })
-        } else if token.starts_with("tenant_") {
-            // Parse tenant token: "tenant_{tenant_id}"
-            let tenant_id = token.strip_prefix("tenant_")
-                .and_then(|id| Uuid::parse_str(id).ok())
-                .context("Invalid tenant token")?;
-            
-            Ok(TokenClaims {
-                user_id: Uuid::new_v4(),
-                role: Role::TenantAdmin,
-                tenant_id: Some(tenant_id),
-                iat: chrono::Utc::now().timestamp(),
-                exp: chrono::Utc::now().timestamp() + 3600, // 1 hour
-            })
-        } else {
-            anyhow::bail!("Invalid token")
-        }
-    }
-
-    /// Generate tenant token
-    pub fn generate_tenant_token(&self, tenant_id: Uuid) -> String {
-        // Simplified token generation
-        // In production, use proper JWT signing
-        format!("tenant_{}", tenant_id)
+                permissions: Permission::ALL.into_iter().collect(),
+                sites: Vec::new(),
+                subject: None,
+                iat: now,
+                exp: now + 3600 * 24, // 24 hours
+            });
+        }
+        if !token.starts_with(TOKEN_PREFIX) {
+            return self.validate_id_token(token);
+        }
+
+        let hash = secret_hash(token);
+        let access = self.access.read();
+        if let Some(session) = self.sessions.read().get(&hash) {
+            if session.expires_at <= now {
+                bail!("Login has expired; sign in again");
+            }
+            return resolve_claims(&access.roles, &session.grant, session.user_id, Some(&session.subject), session.started_at, session.expires_at);
+        }
+        let token = &access.tokens.iter()
+            .find(|stored| stored.secret_sha256 == hash)
+            .context("Invalid token")?
+            .token;
+        if token.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            bail!("Token '{}' has expired", token.name);
+        }
+
+        let grant = Grant {
+            role: token.role.clone(),
+            tenant_id: token.tenant_id,
+            sites: token.sites.clone(),
+            read_only: token.read_only,
+        };
+        resolve_claims(&access.roles, &grant, token.id, None, token.created_at, token.expires_at.unwrap_or(i64::MAX))
+    }
+
+    /// Accept the provider's ID tokens as bearer tokens, when `[oidc] accept_id_tokens` is set
+    fn validate_id_token(&self, token: &str) -> Result<TokenClaims> {
+        let Some(oidc) = self.oidc.as_ref().filter(|oidc| oidc.config().accept_id_tokens) else {
+            bail!("Invalid token");
+        };
+        let identity = oidc.verify(token, None)?;
+        let grant = oidc.grant(&identity)?;
+        let now = chrono::Utc::now().timestamp();
+        resolve_claims(&self.access.read().roles, &grant, identity_user_id(&identity), Some(identity.display_name()), now, identity.expires_at)
+    }
+
+    /// Sign in a user the provider vouched for, returning the session secret
+    pub fn start_session(&self, identity: &Identity) -> Result<String> {
+        let oidc = self.oidc.as_ref().context("Single sign-on is not enabled")?;
+        let grant = oidc.grant(identity)?;
+        // Fails for custom roles that don't exist
+        self.permissions_of(grant.tenant_id, &grant.role)?;
+
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let secret = format!("{}{}", TOKEN_PREFIX, hex::encode(secret));
+        let now = chrono::Utc::now().timestamp();
+        let expires_at = now + oidc.config().session_ttl_secs as i64;
+
+        info!(user = %identity.display_name(), role = grant.role.name(), tenant_id = ?grant.tenant_id, "Signed in through single sign-on");
+        let mut sessions = self.sessions.write();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(secret_hash(&secret), Session {
+            user_id: identity_user_id(identity),
+            subject: identity.display_name().to_string(),
+            grant,
+            started_at: now,
+            expires_at,
+        });
+        Ok(secret)
+    }
+
+    /// Sign out a session, returning whether it existed
+    pub fn end_session(&self, secret: &str) -> bool {
+        self.sessions.write().remove(&secret_hash(secret)).is_some()
+    }
+
+    /// Whether the claims grant `permission` on `scope`
+    pub fn authorize(&self, claims: &TokenClaims, permission: Permission, scope: Scope<'_>) -> bool {
+        if !claims.permissions.contains(&permission) {
+            return false;
+        }
+        match scope {
+            Scope::Node => claims.tenant_id.is_none() && claims.sites.is_empty(),
+            Scope::Tenant(tenant_id) => self.check_tenant_access(claims, tenant_id) && claims.sites.is_empty(),
+            Scope::Site { tenant_id, site_id } => {
+                let tenant_ok = match (claims.tenant_id, tenant_id) {
+                    (None, _) => true,
+                    (Some(own), Some(owner)) => own == owner,
+                    (Some(_), None) => false,
+                };
+                tenant_ok && (claims.sites.is_empty() || claims.sites.iter().any(|site| site == site_id))
+            }
+        }
     }
 
     /// Check if user has access to tenant
     pub fn check_tenant_access(&self, claims: &TokenClaims, tenant_id: Uuid) -> bool {
-        match claims.role {
-            Role::RootAdmin => true, // Root admin has access to all tenants
-            Role::TenantAdmin => claims.tenant_id == Some(tenant_id),
-        }
+        // Root and node-wide tokens reach every tenant
+        claims.tenant_id.map_or(true, |own| own == tenant_id)
     }
 
     /// Check if user is root admin
     pub fn is_root_admin(&self, claims: &TokenClaims) -> bool {
         claims.role == Role::RootAdmin
     }
+
+    /// Custom roles, all or those of one tenant (node-wide ones included)
+    pub fn roles(&self, tenant_id: Option<Uuid>) -> Vec<CustomRole> {
+        self.access.read().roles.iter()
+            .filter(|role| tenant_id.is_none() || role.tenant_id.is_none() || role.tenant_id == tenant_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Permissions a token with `role` would get for `tenant_id`
+    pub fn permissions_of(&self, tenant_id: Option<Uuid>, role: &Role) -> Result<BTreeSet<Permission>> {
+        role_permissions(&self.access.read().roles, tenant_id, role)
+    }
+
+    /// Create or replace a custom role
+    pub fn set_role(&self, role: CustomRole) -> Result<()> {
+        validate_name(&role.name)?;
+        if matches!(Role::parse(&role.name), Role::RootAdmin | Role::TenantAdmin) {
+            bail!("'{}' is a built-in role", role.name);
+        }
+        if role.permissions.is_empty() {
+            bail!("A role needs at least one permission");
+        }
+
+        let mut access = self.access.write();
+        access.roles.retain(|existing| !(existing.name == role.name && existing.tenant_id == role.tenant_id));
+        info!(role = %role.name, tenant_id = ?role.tenant_id, "Role saved");
+        access.roles.push(role);
+        self.save(&access)
+    }
+
+    /// Remove a custom role no token uses, returning whether it existed
+    pub fn remove_role(&self, tenant_id: Option<Uuid>, name: &str) -> Result<bool> {
+        let mut access = self.access.write();
+        let role = Role::Custom(name.to_string());
+        if let Some(stored) = access.tokens.iter()
+            .find(|stored| stored.token.role == role && (tenant_id.is_none() || stored.token.tenant_id == tenant_id))
+        {
+            bail!("Role '{}' is used by token '{}'; revoke it first", name, stored.token.name);
+        }
+        let before = access.roles.len();
+        access.roles.retain(|existing| !(existing.name == name && existing.tenant_id == tenant_id));
+        if access.roles.len() == before {
+            return Ok(false);
+        }
+        self.save(&access)?;
+        Ok(true)
+    }
+
+    /// API tokens, all or those of one tenant
+    pub fn tokens(&self, tenant_id: Option<Uuid>) -> Vec<ApiToken> {
+        self.access.read().tokens.iter()
+            .map(|stored| &stored.token)
+            .filter(|token| tenant_id.is_none() || token.tenant_id == tenant_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Create a token, returning it with its secret; the secret cannot be shown again
+    pub fn issue_token(&self, request: TokenRequest) -> Result<(ApiToken, String)> {
+        validate_name(&request.name)?;
+        match (&request.role, request.tenant_id) {
+            (Role::RootAdmin, _) => bail!("Tokens cannot be root admins; use dashboard.admin_token"),
+            (Role::TenantAdmin, None) => bail!("A tenant-admin token needs a tenant"),
+            _ => {}
+        }
+        if request.tenant_id.is_none() && !request.sites.is_empty() {
+            bail!("Site-limited tokens need a tenant");
+        }
+        // Fails for roles that don't exist or belong to another tenant
+        self.permissions_of(request.tenant_id, &request.role)?;
+
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let secret = format!("{}{}", TOKEN_PREFIX, hex::encode(secret));
+        let token = ApiToken {
+            id: Uuid::new_v4(),
+            name: request.name,
+            tenant_id: request.tenant_id,
+            role: request.role,
+            sites: request.sites,
+            read_only: request.read_only,
+            created_at: chrono::Utc::now().timestamp(),
+            expires_at: request.expires_at,
+        };
+
+        let mut access = self.access.write();
+        access.tokens.push(StoredToken { token: token.clone(), secret_sha256: secret_hash(&secret) });
+        self.save(&access)?;
+        info!(token = %token.name, role = token.role.name(), tenant_id = ?token.tenant_id, "API token issued");
+        Ok((token, secret))
+    }
+
+    /// Revoke a token, returning it if it existed
+    pub fn revoke_token(&self, id: Uuid) -> Result<Option<ApiToken>> {
+        let mut access = self.access.write();
+        let Some(index) = access.tokens.iter().position(|stored| stored.token.id == id) else {
+            return Ok(None);
+        };
+        let removed = access.tokens.remove(index).token;
+        self.save(&access)?;
+        info!(token = %removed.name, "API token revoked");
+        Ok(Some(removed))
+    }
+
+    /// Write roles and tokens to the state file
+    fn save(&self, access: &Access) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        {
+            use std::io::Write;
+            use std::os::unix::fs::OpenOptionsExt;
+            let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp)
+                .with_context(|| format!("Failed to write {}", tmp.display()))?;
+            file.write_all(&serde_json::to_vec_pretty(access)?)?;
+        }
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
 }
 
 impl Default for AuthManager {
@@ -112,6 +561,75 @@ impl Default for AuthManager {
     }
 }
 
+/// Permissions of a built-in or custom role, for a token of `tenant_id`
+fn role_permissions(roles: &[CustomRole], tenant_id: Option<Uuid>, role: &Role) -> Result<BTreeSet<Permission>> {
+    match role {
+        Role::RootAdmin | Role::TenantAdmin => Ok(Permission::ALL.into_iter().collect()),
+        Role::Custom(name) => {
+            // The tenant's own role wins over a node-wide one of the same name
+            let own = roles.iter().find(|role| role.name == *name && role.tenant_id.is_some() && role.tenant_id == tenant_id);
+            let shared = || roles.iter().find(|role| role.name == *name && role.tenant_id.is_none());
+            own.or_else(shared)
+                .map(|role| role.permissions.clone())
+                .with_context(|| format!("Role '{}' does not exist", name))
+        }
+    }
+}
+
+/// Claims for a grant, with the permissions its role has now
+fn resolve_claims(
+    roles: &[CustomRole],
+    grant: &Grant,
+    user_id: Uuid,
+    subject: Option<&str>,
+    iat: i64,
+    exp: i64,
+) -> Result<TokenClaims> {
+    let mut permissions = role_permissions(roles, grant.tenant_id, &grant.role)?;
+    if grant.read_only {
+        permissions.retain(|permission| *permission == Permission::ViewMetrics);
+    }
+    Ok(TokenClaims {
+        user_id,
+        role: grant.role.clone(),
+        tenant_id: grant.tenant_id,
+        permissions,
+        sites: grant.sites.clone(),
+        subject: subject.map(str::to_string),
+        iat,
+        exp,
+    })
+}
+
+/// A stable user ID for a provider's subject
+fn identity_user_id(identity: &Identity) -> Uuid {
+    let digest = ring::digest::digest(&ring::digest::SHA256, identity.subject.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest.as_ref()[..16]);
+    Uuid::from_bytes(bytes)
+}
+
+fn secret_hash(secret: &str) -> String {
+    hex::encode(ring::digest::digest(&ring::digest::SHA256, secret.as_bytes()))
+}
+
+/// Role and token names are short identifiers
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 64
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        bail!("Invalid name '{}': use 1-64 letters, digits, '-', '_' or '.'", name);
+    }
+    Ok(())
+}
+
+fn load_access(path: &Path) -> Result<Access> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
 /// Authorization middleware result
 #[derive(Debug)]
 pub enum AuthResult {
@@ -123,6 +641,17 @@ pub enum AuthResult {
 mod tests {
     use super::*;
 
+    fn tenant_admin(auth: &AuthManager, tenant_id: Uuid) -> String {
+        auth.issue_token(TokenRequest {
+            name: "ci".to_string(),
+            tenant_id: Some(tenant_id),
+            role: Role::TenantAdmin,
+            sites: Vec::new(),
+            read_only: false,
+            expires_at: None,
+        }).unwrap().1
+    }
+
     #[test]
     fn test_auth_manager_creation() {
         let auth = AuthManager::new();
@@ -133,21 +662,25 @@ mod tests {
     fn test_root_admin_token() {
         let auth = AuthManager::new();
         let claims = auth.validate_token("root_admin_secret_token").unwrap();
-        
+
         assert_eq!(claims.role, Role::RootAdmin);
         assert!(auth.is_root_admin(&claims));
+        assert!(auth.authorize(&claims, Permission::ManageTenants, Scope::Node));
     }
 
     #[test]
     fn test_tenant_token() {
         let auth = AuthManager::new();
         let tenant_id = Uuid::new_v4();
-        
-        let token = auth.generate_tenant_token(tenant_id);
+
+        let token = tenant_admin(&auth, tenant_id);
         let claims = auth.validate_token(&token).unwrap();
-        
+
         assert_eq!(claims.role, Role::TenantAdmin);
         assert_eq!(claims.tenant_id, Some(tenant_id));
+
+        // Guessing a tenant's ID is not enough
+        assert!(auth.validate_token(&format!("tenant_{}", tenant_id)).is_err());
     }
 
     #[test]
@@ -155,25 +688,76 @@ mod tests {
         let auth = AuthManager::new();
         let tenant_id = Uuid::new_v4();
         let other_tenant_id = Uuid::new_v4();
-        
-        let token = auth.generate_tenant_token(tenant_id);
+
+        let token = tenant_admin(&auth, tenant_id);
         let claims = auth.validate_token(&token).unwrap();
-        
+
         // Should have access to own tenant
         assert!(auth.check_tenant_access(&claims, tenant_id));
-        
+
         // Should not have access to other tenant
         assert!(!auth.check_tenant_access(&claims, other_tenant_id));
+
+        // Nor to the node as a whole
+        assert!(!auth.authorize(&claims, Permission::ManageTenants, Scope::Node));
+        assert!(auth.authorize(&claims, Permission::Deploy, Scope::Site { tenant_id: Some(tenant_id), site_id: "blog" }));
+        assert!(!auth.authorize(&claims, Permission::Deploy, Scope::Site { tenant_id: Some(other_tenant_id), site_id: "shop" }));
     }
 
     #[test]
     fn test_root_admin_access() {
         let auth = AuthManager::new();
         let claims = auth.validate_token("root_admin_secret_token").unwrap();
-        
+
         let any_tenant = Uuid::new_v4();
-        
+
         // Root admin has access to all tenants
         assert!(auth.check_tenant_access(&claims, any_tenant));
     }
+
+    #[test]
+    fn test_custom_roles_and_scoped_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = RbacConfig { state_path: dir.path().join("access.json").to_string_lossy().into_owned(), ..Default::default() };
+        let auth = AuthManager::open("root", &config).unwrap();
+        let tenant_id = Uuid::new_v4();
+
+        auth.set_role(CustomRole {
+            name: "deployer".to_string(),
+            tenant_id: Some(tenant_id),
+            permissions: [Permission::Deploy, Permission::ViewMetrics].into_iter().collect(),
+        }).unwrap();
+        assert!(auth.set_role(CustomRole { name: "tenant-admin".to_string(), tenant_id: None, permissions: BTreeSet::new() }).is_err());
+
+        let request = TokenRequest {
+            name: "blog-ci".to_string(),
+            tenant_id: Some(tenant_id),
+            role: Role::Custom("deployer".to_string()),
+            sites: vec!["blog".to_string()],
+            read_only: false,
+            expires_at: None,
+        };
+        assert!(auth.issue_token(TokenRequest { tenant_id: Some(Uuid::new_v4()), ..request.clone() }).is_err());
+        let (issued, secret) = auth.issue_token(request.clone()).unwrap();
+
+        // Roles and tokens survive a restart; the secret itself is never stored
+        let auth = AuthManager::open("root", &config).unwrap();
+        assert!(!std::fs::read_to_string(dir.path().join("access.json")).unwrap().contains(&secret));
+        let claims = auth.validate_token(&secret).unwrap();
+        let blog = Scope::Site { tenant_id: Some(tenant_id), site_id: "blog" };
+        let shop = Scope::Site { tenant_id: Some(tenant_id), site_id: "shop" };
+        assert!(auth.authorize(&claims, Permission::Deploy, blog));
+        assert!(!auth.authorize(&claims, Permission::Deploy, shop));
+        assert!(!auth.authorize(&claims, Permission::ManageDomains, blog));
+        assert!(!auth.authorize(&claims, Permission::Deploy, Scope::Tenant(tenant_id)));
+        assert!(auth.remove_role(Some(tenant_id), "deployer").is_err());
+
+        let (_, read_only) = auth.issue_token(TokenRequest { name: "grafana".to_string(), read_only: true, ..request }).unwrap();
+        let claims = auth.validate_token(&read_only).unwrap();
+        assert!(auth.authorize(&claims, Permission::ViewMetrics, blog));
+        assert!(!auth.authorize(&claims, Permission::Deploy, blog));
+
+        auth.revoke_token(issued.id).unwrap().unwrap();
+        assert!(auth.validate_token(&secret).is_err());
+    }
 }
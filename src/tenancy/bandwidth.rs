@@ -0,0 +1,630 @@
+// Bandwidth Accounting
+// Per-site and per-tenant ingress/egress totals with daily and monthly quotas
+
+use anyhow::{Context, Result};
+use chrono::Datelike;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{info, warn, error};
+
+/// Bytes in a quota gigabyte
+const GB: u64 = 1024 * 1024 * 1024;
+
+/// Days of daily totals kept per site and tenant
+const DAILY_HISTORY: usize = 62;
+
+/// Months of monthly totals kept per site and tenant
+const MONTHLY_HISTORY: usize = 24;
+
+/// Bandwidth accounting configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandwidthConfig {
+    /// Count traffic and enforce quotas
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// File usage totals are persisted to ("" = memory only)
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+
+    /// Seconds between writes of the usage file
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval_secs: u64,
+
+    /// Percentage of a quota at which a warning is logged
+    #[serde(default = "default_warn_percent")]
+    pub warn_percent: u8,
+
+    /// Quota for sites without their own
+    #[serde(default)]
+    pub default_quota: BandwidthQuota,
+
+    /// Per-site quotas and tenant assignment, keyed by site ID
+    #[serde(default)]
+    pub sites: HashMap<String, SiteBandwidthConfig>,
+
+    /// Per-tenant quotas covering all of the tenant's sites
+    #[serde(default)]
+    pub tenants: HashMap<String, BandwidthQuota>,
+}
+
+fn default_true() -> bool { true }
+fn default_state_path() -> String { "bandwidth.json".to_string() }
+fn default_flush_interval() -> u64 { 60 }
+fn default_warn_percent() -> u8 { 80 }
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            state_path: default_state_path(),
+            flush_interval_secs: default_flush_interval(),
+            warn_percent: default_warn_percent(),
+            default_quota: BandwidthQuota::default(),
+            sites: HashMap::new(),
+            tenants: HashMap::new(),
+        }
+    }
+}
+
+impl BandwidthConfig {
+    /// Check that limits are sensible
+    pub fn validate(&self) -> Result<()> {
+        if self.warn_percent == 0 || self.warn_percent > 100 {
+            anyhow::bail!("warn_percent must be between 1 and 100");
+        }
+
+        if self.flush_interval_secs == 0 {
+            anyhow::bail!("flush_interval_secs must be at least 1");
+        }
+
+        Ok(())
+    }
+}
+
+/// Traffic limits; unset limits are unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthQuota {
+    /// Ingress plus egress per calendar day (UTC), in GB
+    pub daily_gb: Option<u64>,
+
+    /// Ingress plus egress per calendar month (UTC), in GB
+    pub monthly_gb: Option<u64>,
+}
+
+impl BandwidthQuota {
+    fn is_empty(&self) -> bool {
+        self.daily_gb.is_none() && self.monthly_gb.is_none()
+    }
+}
+
+/// Site quota and owning tenant
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteBandwidthConfig {
+    /// Tenant whose quota this site's traffic also counts against
+    #[serde(default)]
+    pub tenant: Option<String>,
+
+    #[serde(flatten)]
+    pub quota: BandwidthQuota,
+}
+
+/// Byte counts in each direction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Traffic {
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+}
+
+impl Traffic {
+    /// Bytes in both directions
+    pub fn total(&self) -> u64 {
+        self.ingress_bytes + self.egress_bytes
+    }
+
+    fn add(&mut self, ingress: u64, egress: u64) {
+        self.ingress_bytes += ingress;
+        self.egress_bytes += egress;
+    }
+}
+
+/// Quota state of a site or tenant
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaStatus {
+    #[default]
+    Ok,
+
+    /// Past the warning threshold; traffic still served
+    Warning,
+
+    /// Over a hard limit; traffic rejected until the period rolls over
+    Exceeded,
+}
+
+/// Accounting period, as UTC calendar day (YYYYMMDD) and month (YYYYMM)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    pub day: u32,
+    pub month: u32,
+}
+
+impl Period {
+    /// Current UTC period
+    pub fn now() -> Self {
+        Self::from_date(chrono::Utc::now().date_naive())
+    }
+
+    /// Period containing a date
+    pub fn from_date(date: chrono::NaiveDate) -> Self {
+        let month = date.year() as u32 * 100 + date.month();
+        Self {
+            day: month * 100 + date.day(),
+            month,
+        }
+    }
+}
+
+/// Daily and monthly totals for one site or tenant
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageHistory {
+    /// Totals keyed by YYYYMMDD
+    pub daily: BTreeMap<u32, Traffic>,
+
+    /// Totals keyed by YYYYMM
+    pub monthly: BTreeMap<u32, Traffic>,
+
+    /// All-time totals
+    pub total: Traffic,
+
+    /// Last status reported, so transitions are logged once
+    #[serde(skip)]
+    status: QuotaStatus,
+}
+
+impl UsageHistory {
+    fn record(&mut self, period: Period, ingress: u64, egress: u64) {
+        self.daily.entry(period.day).or_default().add(ingress, egress);
+        self.monthly.entry(period.month).or_default().add(ingress, egress);
+        self.total.add(ingress, egress);
+
+        trim(&mut self.daily, DAILY_HISTORY);
+        trim(&mut self.monthly, MONTHLY_HISTORY);
+    }
+
+    /// Traffic so far in a period's day
+    pub fn day(&self, period: Period) -> Traffic {
+        self.daily.get(&period.day).copied().unwrap_or_default()
+    }
+
+    /// Traffic so far in a period's month
+    pub fn month(&self, period: Period) -> Traffic {
+        self.monthly.get(&period.month).copied().unwrap_or_default()
+    }
+
+    /// Quota state for the period
+    fn status(&self, period: Period, quota: &BandwidthQuota, warn_percent: u8) -> QuotaStatus {
+        let day = quota_status(self.day(period).total(), quota.daily_gb, warn_percent);
+        let month = quota_status(self.month(period).total(), quota.monthly_gb, warn_percent);
+        day.max(month)
+    }
+}
+
+/// Drop the oldest entries beyond a history length
+fn trim(history: &mut BTreeMap<u32, Traffic>, keep: usize) {
+    while history.len() > keep {
+        history.pop_first();
+    }
+}
+
+fn quota_status(used: u64, limit_gb: Option<u64>, warn_percent: u8) -> QuotaStatus {
+    match limit_gb {
+        Some(limit_gb) => {
+            let limit = limit_gb * GB;
+            if used >= limit {
+                QuotaStatus::Exceeded
+            } else if used as u128 * 100 >= limit as u128 * warn_percent as u128 {
+                QuotaStatus::Warning
+            } else {
+                QuotaStatus::Ok
+            }
+        }
+        None => QuotaStatus::Ok,
+    }
+}
+
+/// Usage report for a site or tenant
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub id: String,
+
+    /// Owning tenant, for sites
+    pub tenant: Option<String>,
+
+    pub today: Traffic,
+    pub this_month: Traffic,
+    pub total: Traffic,
+    pub quota: BandwidthQuota,
+    pub status: QuotaStatus,
+}
+
+/// Usage of every site and tenant
+#[derive(Debug, Clone, Serialize)]
+pub struct BandwidthReport {
+    pub sites: Vec<UsageReport>,
+    pub tenants: Vec<UsageReport>,
+}
+
+/// Persisted usage file
+#[derive(Default, Serialize, Deserialize)]
+struct UsageState {
+    sites: HashMap<String, UsageHistory>,
+    tenants: HashMap<String, UsageHistory>,
+}
+
+/// Counts traffic per site and tenant and enforces quotas
+pub struct BandwidthMeter {
+    config: BandwidthConfig,
+    state_path: Option<PathBuf>,
+    sites: DashMap<String, UsageHistory>,
+    tenants: DashMap<String, UsageHistory>,
+
+    /// Site quotas and tenant assignment, replaceable at runtime
+    site_config: DashMap<String, SiteBandwidthConfig>,
+    tenant_quotas: DashMap<String, BandwidthQuota>,
+
+    /// Usage changed since the last save
+    dirty: AtomicBool,
+
+    /// Serializes saves from the flush loop and shutdown
+    save_lock: Mutex<()>,
+}
+
+impl BandwidthMeter {
+    /// Create a meter, loading persisted totals if present
+    pub fn new(config: BandwidthConfig) -> Result<Self> {
+        let state_path = Some(&config.state_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+
+        let state = match &state_path {
+            Some(path) if path.exists() => load_state(path)?,
+            _ => UsageState::default(),
+        };
+
+        info!(
+            sites = state.sites.len(),
+            tenants = state.tenants.len(),
+            state_path = ?state_path,
+            "Bandwidth meter initialized"
+        );
+
+        Ok(Self {
+            site_config: config.sites.clone().into_iter().collect(),
+            tenant_quotas: config.tenants.clone().into_iter().collect(),
+            config,
+            state_path,
+            sites: state.sites.into_iter().collect(),
+            tenants: state.tenants.into_iter().collect(),
+            dirty: AtomicBool::new(false),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Count traffic for a site, and for its tenant if it has one
+    pub fn record(&self, site_id: &str, ingress: u64, egress: u64) {
+        self.record_at(Period::now(), site_id, ingress, egress);
+    }
+
+    fn record_at(&self, period: Period, site_id: &str, ingress: u64, egress: u64) {
+        match self.sites.get_mut(site_id) {
+            Some(mut usage) => usage.record(period, ingress, egress),
+            None => self.sites.entry(site_id.to_string()).or_default().record(period, ingress, egress),
+        }
+
+        if let Some(tenant) = self.tenant_of(site_id) {
+            self.tenants.entry(tenant).or_default().record(period, ingress, egress);
+        }
+
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Quota state for a site, including its tenant's quota
+    /// Logs when a site or tenant crosses the warning or hard limit
+    pub fn check(&self, site_id: &str) -> QuotaStatus {
+        self.check_at(Period::now(), site_id)
+    }
+
+    fn check_at(&self, period: Period, site_id: &str) -> QuotaStatus {
+        let site_status = match self.sites.get_mut(site_id) {
+            Some(mut usage) => {
+                let status = usage.status(period, &self.site_quota(site_id), self.config.warn_percent);
+                log_transition("site", site_id, &mut usage.status, status);
+                status
+            }
+            None => QuotaStatus::Ok,
+        };
+
+        let tenant_status = self.tenant_of(site_id)
+            .and_then(|tenant| {
+                let quota = self.tenant_quotas.get(&tenant).map(|q| *q)?;
+                let mut usage = self.tenants.get_mut(&tenant)?;
+                let status = usage.status(period, &quota, self.config.warn_percent);
+                log_transition("tenant", &tenant, &mut usage.status, status);
+                Some(status)
+            })
+            .unwrap_or_default();
+
+        site_status.max(tenant_status)
+    }
+
+    /// Quota applied to a site
+    pub fn site_quota(&self, site_id: &str) -> BandwidthQuota {
+        self.site_config.get(site_id)
+            .map(|site| site.quota)
+            .filter(|quota| !quota.is_empty())
+            .unwrap_or(self.config.default_quota)
+    }
+
+    /// Replace a site's quota
+    pub fn set_site_quota(&self, site_id: &str, quota: BandwidthQuota) {
+        self.site_config.entry(site_id.to_string()).or_default().quota = quota;
+    }
+
+    /// Count a site's traffic against a tenant's quota from now on
+    pub fn assign_tenant(&self, site_id: &str, tenant_id: &str) {
+        self.site_config.entry(site_id.to_string()).or_default().tenant = Some(tenant_id.to_string());
+    }
+
+    /// Replace a tenant's quota
+    pub fn set_tenant_quota(&self, tenant_id: &str, quota: BandwidthQuota) {
+        self.tenant_quotas.insert(tenant_id.to_string(), quota);
+    }
+
+    fn tenant_of(&self, site_id: &str) -> Option<String> {
+        self.site_config.get(site_id).and_then(|site| site.tenant.clone())
+    }
+
+    /// Full history for a site
+    pub fn site_history(&self, site_id: &str) -> Option<UsageHistory> {
+        self.sites.get(site_id).map(|usage| usage.clone())
+    }
+
+    /// Full history for a tenant
+    pub fn tenant_history(&self, tenant_id: &str) -> Option<UsageHistory> {
+        self.tenants.get(tenant_id).map(|usage| usage.clone())
+    }
+
+    /// Current usage of a site
+    pub fn site_report(&self, site_id: &str) -> UsageReport {
+        let period = Period::now();
+        let usage = self.site_history(site_id).unwrap_or_default();
+        let quota = self.site_quota(site_id);
+
+        UsageReport {
+            id: site_id.to_string(),
+            tenant: self.tenant_of(site_id),
+            today: usage.day(period),
+            this_month: usage.month(period),
+            total: usage.total,
+            status: usage.status(period, &quota, self.config.warn_percent),
+            quota,
+        }
+    }
+
+    /// Current usage of a tenant across its sites
+    pub fn tenant_report(&self, tenant_id: &str) -> UsageReport {
+        let period = Period::now();
+        let usage = self.tenant_history(tenant_id).unwrap_or_default();
+        let quota = self.tenant_quotas.get(tenant_id).map(|q| *q).unwrap_or_default();
+
+        UsageReport {
+            id: tenant_id.to_string(),
+            tenant: None,
+            today: usage.day(period),
+            this_month: usage.month(period),
+            total: usage.total,
+            status: usage.status(period, &quota, self.config.warn_percent),
+            quota,
+        }
+    }
+
+    /// Usage of every site and tenant seen so far
+    pub fn report(&self) -> BandwidthReport {
+        let mut sites: Vec<_> = self.sites.iter().map(|e| self.site_report(e.key())).collect();
+        sites.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut tenant_ids: Vec<String> = self.tenants.iter().map(|e| e.key().clone()).collect();
+        tenant_ids.extend(self.tenant_quotas.iter().map(|e| e.key().clone()));
+        tenant_ids.sort();
+        tenant_ids.dedup();
+
+        BandwidthReport {
+            sites,
+            tenants: tenant_ids.iter().map(|id| self.tenant_report(id)).collect(),
+        }
+    }
+
+    /// Write totals to the state file if they changed since the last save
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+
+        let _guard = self.save_lock.lock();
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let state = UsageState {
+            sites: self.sites.iter().map(|e| (e.key().clone(), e.value().clone())).collect(),
+            tenants: self.tenants.iter().map(|e| (e.key().clone(), e.value().clone())).collect(),
+        };
+
+        let result = save_state(path, &state);
+        if result.is_err() {
+            // Try again on the next flush
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Periodically persist usage totals
+    pub fn start(self: &Arc<Self>) {
+        let meter = self.clone();
+        let interval_secs = self.config.flush_interval_secs.max(1);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(interval_secs));
+
+            loop {
+                interval.tick().await;
+
+                let meter = meter.clone();
+                let result = tokio::task::spawn_blocking(move || meter.save()).await;
+                match result {
+                    Ok(Err(e)) => error!(error = %e, "Failed to persist bandwidth usage"),
+                    Err(e) => error!(error = %e, "Bandwidth flush task failed"),
+                    Ok(Ok(())) => {}
+                }
+            }
+        });
+    }
+}
+
+/// Log a quota state change once
+fn log_transition(kind: &str, id: &str, last: &mut QuotaStatus, status: QuotaStatus) {
+    if *last == status {
+        return;
+    }
+
+    match status {
+        QuotaStatus::Exceeded => warn!(kind = kind, id = %id, "Bandwidth quota exceeded, rejecting traffic"),
+        QuotaStatus::Warning => warn!(kind = kind, id = %id, "Bandwidth usage approaching quota"),
+        QuotaStatus::Ok => info!(kind = kind, id = %id, "Bandwidth usage back within quota"),
+    }
+    if status != QuotaStatus::Ok {
+        notify_threshold(kind, id, status);
+    }
+    *last = status;
+}
+
+/// Tell the site's or tenant's webhooks it crossed a threshold
+fn notify_threshold(kind: &str, id: &str, status: QuotaStatus) {
+    use crate::notifications::{EventKind, Notification};
+
+    let text = match status {
+        QuotaStatus::Exceeded => format!("Bandwidth quota of {} {} exceeded; traffic is rejected", kind, id),
+        _ => format!("Bandwidth usage of {} {} is approaching its quota", kind, id),
+    };
+    let details = serde_json::json!({ "kind": kind, "id": id, "status": status });
+    let notification = match kind {
+        "site" => Notification::new(EventKind::QuotaThreshold, Some(id), text, details),
+        _ => {
+            let notification = Notification::new(EventKind::QuotaThreshold, None, text, details);
+            match id.parse() {
+                Ok(tenant_id) => notification.for_tenant(tenant_id),
+                Err(_) => notification,
+            }
+        }
+    };
+    crate::notifications::notify(notification);
+}
+
+fn load_state(path: &Path) -> Result<UsageState> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_state(path: &Path, state: &UsageState) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, serde_json::to_vec(state)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meter() -> BandwidthMeter {
+        BandwidthMeter::new(BandwidthConfig {
+            state_path: String::new(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn period(date: &str) -> Period {
+        Period::from_date(date.parse().unwrap())
+    }
+
+    #[test]
+    fn test_daily_and_monthly_rollover() {
+        let meter = meter();
+        meter.record_at(period("2026-10-30"), "blog", 100, 1000);
+        meter.record_at(period("2026-10-31"), "blog", 10, 20);
+        meter.record_at(period("2026-11-01"), "blog", 1, 2);
+
+        let usage = meter.site_history("blog").unwrap();
+        assert_eq!(usage.day(period("2026-10-31")).total(), 30);
+        assert_eq!(usage.month(period("2026-10-01")).total(), 1130);
+        assert_eq!(usage.month(period("2026-11-15")).egress_bytes, 2);
+        assert_eq!(usage.total.total(), 1133);
+    }
+
+    #[test]
+    fn test_site_and_tenant_quotas() {
+        let meter = meter();
+        let today = period("2026-10-18");
+        meter.set_site_quota("blog", BandwidthQuota { daily_gb: Some(1), monthly_gb: None });
+        meter.assign_tenant("blog", "acme");
+        meter.assign_tenant("shop", "acme");
+        meter.set_tenant_quota("acme", BandwidthQuota { daily_gb: None, monthly_gb: Some(2) });
+
+        meter.record_at(today, "blog", 0, GB * 85 / 100);
+        assert_eq!(meter.check_at(today, "blog"), QuotaStatus::Warning);
+        assert_eq!(meter.check_at(today, "shop"), QuotaStatus::Ok);
+
+        meter.record_at(today, "blog", 0, GB);
+        assert_eq!(meter.check_at(today, "blog"), QuotaStatus::Exceeded);
+
+        // The tenant's monthly quota covers both sites
+        meter.record_at(today, "shop", GB / 2, 0);
+        assert_eq!(meter.check_at(today, "shop"), QuotaStatus::Exceeded);
+
+        // A new day lifts the site's daily limit, but not the tenant's monthly one
+        let tomorrow = period("2026-10-19");
+        assert_eq!(meter.check_at(tomorrow, "blog"), QuotaStatus::Exceeded);
+        assert_eq!(meter.check_at(period("2026-11-01"), "blog"), QuotaStatus::Ok);
+    }
+
+    #[test]
+    fn test_usage_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = BandwidthConfig {
+            state_path: dir.path().join("bandwidth.json").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let meter = BandwidthMeter::new(config.clone()).unwrap();
+        meter.record("blog", 512, 4096);
+        meter.save().unwrap();
+
+        let reloaded = BandwidthMeter::new(config).unwrap();
+        assert_eq!(reloaded.site_report("blog").total.total(), 4608);
+    }
+}
@@ -0,0 +1,403 @@
+// Billing Records
+// Per-tenant fuel, wall time and egress of guest calls, kept per calendar month for invoicing or showback
+
+use super::bandwidth::Period;
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{info, error};
+
+/// Months of usage records kept per site
+const MONTHLY_HISTORY: usize = 24;
+
+/// Billing configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BillingConfig {
+    /// Count guest calls, their wall time and egress per tenant
+    pub enabled: bool,
+
+    /// Meter guest instructions as fuel; costs a little on every guest call
+    pub fuel: bool,
+
+    /// Fuel a single guest call may burn before it is stopped (0 = unlimited)
+    pub max_fuel_per_call: u64,
+
+    /// File usage records are persisted to ("" = memory only)
+    pub state_path: String,
+
+    /// Seconds between writes of the usage file
+    pub flush_interval_secs: u64,
+}
+
+impl Default for BillingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            fuel: false,
+            max_fuel_per_call: 0,
+            state_path: "billing.json".to_string(),
+            flush_interval_secs: 60,
+        }
+    }
+}
+
+impl BillingConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.flush_interval_secs == 0 {
+            anyhow::bail!("flush_interval_secs must be at least 1");
+        }
+        if self.max_fuel_per_call > 0 && !self.fuel {
+            anyhow::bail!("max_fuel_per_call needs fuel = true");
+        }
+        Ok(())
+    }
+
+    /// Fuel each guest call starts with, if fuel is metered
+    pub fn fuel_per_call(&self) -> Option<u64> {
+        match (self.fuel, self.max_fuel_per_call) {
+            (false, _) => None,
+            (true, 0) => Some(u64::MAX),
+            (true, limit) => Some(limit),
+        }
+    }
+}
+
+/// Usage counted over one period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Guest calls: requests, scheduled jobs and queued tasks
+    pub calls: u64,
+
+    /// Fuel burned by guest code (0 unless fuel is metered)
+    pub fuel: u64,
+
+    /// Time spent in guest calls, in microseconds
+    pub wall_time_us: u64,
+
+    /// Response bytes sent to clients
+    pub egress_bytes: u64,
+}
+
+impl UsageRecord {
+    fn add(&mut self, other: &UsageRecord) {
+        self.calls += other.calls;
+        self.fuel += other.fuel;
+        self.wall_time_us += other.wall_time_us;
+        self.egress_bytes += other.egress_bytes;
+    }
+}
+
+/// A site's owner and monthly records
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SiteLedger {
+    tenant: Option<String>,
+
+    /// Records keyed by YYYYMM
+    monthly: BTreeMap<u32, UsageRecord>,
+}
+
+impl SiteLedger {
+    fn record(&mut self, month: u32, usage: &UsageRecord) {
+        self.monthly.entry(month).or_default().add(usage);
+        while self.monthly.len() > MONTHLY_HISTORY {
+            self.monthly.pop_first();
+        }
+    }
+}
+
+/// A tenant's usage in one month, and its sites'
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantBill {
+    /// Owning tenant; None for sites that no tenant has claimed
+    pub tenant: Option<String>,
+
+    /// Month as YYYYMM
+    pub period: u32,
+
+    pub usage: UsageRecord,
+
+    pub sites: BTreeMap<String, UsageRecord>,
+}
+
+/// Persisted usage file
+#[derive(Default, Serialize, Deserialize)]
+struct BillingState {
+    sites: HashMap<String, SiteLedger>,
+}
+
+/// Counts guest usage per site and attributes it to the sites' tenants
+pub struct BillingMeter {
+    config: BillingConfig,
+    state_path: Option<PathBuf>,
+    sites: DashMap<String, SiteLedger>,
+
+    /// Usage changed since the last save
+    dirty: AtomicBool,
+
+    /// Serializes saves from the flush loop and shutdown
+    save_lock: Mutex<()>,
+}
+
+impl BillingMeter {
+    /// Create a meter, loading persisted records if present
+    pub fn new(config: BillingConfig) -> Result<Self> {
+        let state_path = Some(&config.state_path)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+
+        let state = match &state_path {
+            Some(path) if path.exists() => load_state(path)?,
+            _ => BillingState::default(),
+        };
+
+        info!(sites = state.sites.len(), fuel = config.fuel, state_path = ?state_path, "Billing meter initialized");
+
+        Ok(Self {
+            config,
+            state_path,
+            sites: state.sites.into_iter().collect(),
+            dirty: AtomicBool::new(false),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Fuel each guest call starts with, if fuel is metered
+    pub fn fuel_per_call(&self) -> Option<u64> {
+        self.config.fuel_per_call()
+    }
+
+    /// Handle a site's Cages count their calls through, attributed to `tenant_id`
+    pub fn guest(self: &Arc<Self>, site_id: &str, tenant_id: &str) -> GuestUsage {
+        self.assign_tenant(site_id, tenant_id);
+        GuestUsage { meter: self.clone(), site_id: site_id.into() }
+    }
+
+    /// Attribute a site's usage, past records of the site included, to a tenant
+    pub fn assign_tenant(&self, site_id: &str, tenant_id: &str) {
+        let mut ledger = self.sites.entry(site_id.to_string()).or_default();
+        if ledger.tenant.as_deref() != Some(tenant_id) {
+            ledger.tenant = Some(tenant_id.to_string());
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Count one guest call
+    pub fn record_call(&self, site_id: &str, fuel: u64, wall_time: Duration) {
+        self.record(Period::now(), site_id, UsageRecord {
+            calls: 1,
+            fuel,
+            wall_time_us: wall_time.as_micros() as u64,
+            egress_bytes: 0,
+        });
+    }
+
+    /// Count response bytes sent for a site
+    pub fn record_egress(&self, site_id: &str, bytes: u64) {
+        self.record(Period::now(), site_id, UsageRecord { egress_bytes: bytes, ..Default::default() });
+    }
+
+    fn record(&self, period: Period, site_id: &str, usage: UsageRecord) {
+        match self.sites.get_mut(site_id) {
+            Some(mut ledger) => ledger.record(period.month, &usage),
+            None => self.sites.entry(site_id.to_string()).or_default().record(period.month, &usage),
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Usage of every tenant in a month (YYYYMM), unclaimed sites last
+    pub fn report(&self, period: u32) -> Vec<TenantBill> {
+        let mut bills: BTreeMap<Option<String>, TenantBill> = BTreeMap::new();
+        for ledger in self.sites.iter() {
+            let Some(usage) = ledger.monthly.get(&period) else { continue };
+            let bill = bills.entry(ledger.tenant.clone()).or_insert_with(|| TenantBill {
+                tenant: ledger.tenant.clone(),
+                period,
+                usage: UsageRecord::default(),
+                sites: BTreeMap::new(),
+            });
+            bill.usage.add(usage);
+            bill.sites.insert(ledger.key().clone(), *usage);
+        }
+
+        // None sorts first
+        let mut bills: Vec<TenantBill> = bills.into_values().collect();
+        let unclaimed = bills.iter().take_while(|bill| bill.tenant.is_none()).count();
+        bills.rotate_left(unclaimed);
+        bills
+    }
+
+    /// A tenant's usage in a month (YYYYMM)
+    pub fn tenant_bill(&self, tenant_id: &str, period: u32) -> TenantBill {
+        self.report(period).into_iter()
+            .find(|bill| bill.tenant.as_deref() == Some(tenant_id))
+            .unwrap_or_else(|| TenantBill {
+                tenant: Some(tenant_id.to_string()),
+                period,
+                usage: UsageRecord::default(),
+                sites: BTreeMap::new(),
+            })
+    }
+
+    /// Months (YYYYMM) with records, oldest first
+    pub fn periods(&self) -> Vec<u32> {
+        let mut periods: Vec<u32> = self.sites.iter()
+            .flat_map(|ledger| ledger.monthly.keys().copied().collect::<Vec<_>>())
+            .collect();
+        periods.sort_unstable();
+        periods.dedup();
+        periods
+    }
+
+    /// Write records to the state file if they changed since the last save
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+
+        let _guard = self.save_lock.lock();
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let state = BillingState {
+            sites: self.sites.iter().map(|e| (e.key().clone(), e.value().clone())).collect(),
+        };
+
+        let result = save_state(path, &state);
+        if result.is_err() {
+            // Try again on the next flush
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Periodically persist usage records
+    pub fn start(self: &Arc<Self>) {
+        let meter = self.clone();
+        let interval_secs = self.config.flush_interval_secs.max(1);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(interval_secs));
+
+            loop {
+                interval.tick().await;
+
+                let meter = meter.clone();
+                let result = tokio::task::spawn_blocking(move || meter.save()).await;
+                match result {
+                    Ok(Err(e)) => error!(error = %e, "Failed to persist billing records"),
+                    Err(e) => error!(error = %e, "Billing flush task failed"),
+                    Ok(Ok(())) => {}
+                }
+            }
+        });
+    }
+}
+
+/// A site's handle on the billing meter, kept in its Cages' config
+#[derive(Clone)]
+pub struct GuestUsage {
+    meter: Arc<BillingMeter>,
+    site_id: Arc<str>,
+}
+
+impl GuestUsage {
+    /// Count one guest call of the site
+    pub fn record(&self, fuel: u64, wall_time: Duration) {
+        self.meter.record_call(&self.site_id, fuel, wall_time);
+    }
+}
+
+impl std::fmt::Debug for GuestUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuestUsage").field("site_id", &self.site_id).finish()
+    }
+}
+
+fn load_state(path: &Path) -> Result<BillingState> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_state(path: &Path, state: &BillingState) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, serde_json::to_vec(state)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn period(date: &str) -> Period {
+        Period::from_date(date.parse().unwrap())
+    }
+
+    #[test]
+    fn test_usage_attributed_to_tenants() {
+        let meter = BillingMeter::new(BillingConfig { state_path: String::new(), ..Default::default() }).unwrap();
+        let call = |fuel| UsageRecord { calls: 1, fuel, wall_time_us: 1500, egress_bytes: 0 };
+
+        // Usage recorded before a site is claimed moves to its tenant
+        meter.record(period("2026-10-03"), "blog", call(100));
+        meter.assign_tenant("blog", "acme");
+        meter.assign_tenant("shop", "acme");
+        meter.record(period("2026-10-20"), "shop", call(50));
+        meter.record(period("2026-10-20"), "shop", UsageRecord { egress_bytes: 4096, ..Default::default() });
+        meter.record(period("2026-11-01"), "blog", call(7));
+        meter.record(period("2026-10-05"), "stray", call(1));
+
+        let bills = meter.report(202610);
+        assert_eq!(bills.len(), 2);
+        assert_eq!(bills[0].tenant.as_deref(), Some("acme"));
+        assert_eq!(bills[0].usage, UsageRecord { calls: 2, fuel: 150, wall_time_us: 3000, egress_bytes: 4096 });
+        assert_eq!(bills[0].sites["blog"].fuel, 100);
+        assert_eq!(bills[1].tenant, None);
+
+        assert_eq!(meter.tenant_bill("acme", 202611).usage.fuel, 7);
+        assert_eq!(meter.tenant_bill("other", 202610).usage, UsageRecord::default());
+        assert_eq!(meter.periods(), vec![202610, 202611]);
+    }
+
+    #[test]
+    fn test_records_persisted() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config = BillingConfig {
+            state_path: temp.path().join("billing.json").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let meter = Arc::new(BillingMeter::new(config.clone()).unwrap());
+        meter.guest("blog", "acme").record(40, Duration::from_millis(2));
+        meter.record_egress("blog", 512);
+        meter.save().unwrap();
+
+        let reloaded = BillingMeter::new(config).unwrap();
+        let bill = reloaded.tenant_bill("acme", Period::now().month);
+        assert_eq!(bill.usage, UsageRecord { calls: 1, fuel: 40, wall_time_us: 2000, egress_bytes: 512 });
+    }
+
+    #[test]
+    fn test_fuel_per_call() {
+        let config = |fuel, max_fuel_per_call| BillingConfig { fuel, max_fuel_per_call, ..Default::default() };
+        assert_eq!(config(false, 0).fuel_per_call(), None);
+        assert_eq!(config(true, 0).fuel_per_call(), Some(u64::MAX));
+        assert_eq!(config(true, 1000).fuel_per_call(), Some(1000));
+        assert!(config(false, 1000).validate().is_err());
+    }
+}
@@ -0,0 +1,577 @@
+// Domain Management
+// Hostname claims per site, ownership verification by DNS TXT record or HTTP challenge, and certificates
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::{header, Request, Response, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::config::acme::AcmeManager;
+
+/// Path prefix the HTTP challenge token is served under
+pub const CHALLENGE_PATH: &str = "/.well-known/pear-challenge/";
+
+/// Label prepended to a hostname for its TXT record
+pub const TXT_LABEL: &str = "_pear-challenge";
+
+/// How often pending domains are checked again
+pub const VERIFY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// An unverified claim lapses after this long, and another tenant may take the hostname
+const CLAIM_EXPIRY_SECS: i64 = 7 * 24 * 3600;
+
+/// Time allowed for one DNS lookup or challenge fetch
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a tenant proves it controls a hostname
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationMethod {
+    /// A TXT record at `_pear-challenge.<hostname>`
+    Dns,
+
+    /// The hostname points at this server, which answers the challenge itself
+    #[default]
+    Http,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainStatus {
+    Pending,
+    Verified,
+
+    /// Never verified before the claim lapsed
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CertificateStatus {
+    /// Automatic certificates are off
+    #[default]
+    None,
+    Requested,
+    Issued,
+    Failed,
+}
+
+/// A hostname claimed by a site
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Domain {
+    pub hostname: String,
+    pub tenant_id: Uuid,
+    pub site_id: String,
+    pub method: VerificationMethod,
+
+    /// Expected in the TXT record or challenge response
+    pub token: String,
+    pub status: DomainStatus,
+
+    /// Another of the site's hostnames to redirect to instead of serving the site
+    pub redirect_to: Option<String>,
+
+    /// Unix seconds
+    pub claimed_at: i64,
+    pub verified_at: Option<i64>,
+    pub last_checked: Option<i64>,
+
+    /// Why the last check failed
+    pub last_error: Option<String>,
+    pub certificate: CertificateStatus,
+}
+
+impl Domain {
+    /// Name of the TXT record proving ownership
+    pub fn txt_name(&self) -> String {
+        format!("{}.{}", TXT_LABEL, self.hostname)
+    }
+
+    /// Value the TXT record must hold
+    pub fn txt_value(&self) -> String {
+        format!("pear-verify={}", self.token)
+    }
+
+    /// URL the HTTP challenge is fetched from
+    pub fn challenge_url(&self) -> String {
+        format!("http://{}{}{}", self.hostname, CHALLENGE_PATH, self.token)
+    }
+
+    fn lapsed(&self, now: i64) -> bool {
+        self.status == DomainStatus::Failed
+            || (self.status == DomainStatus::Pending && now - self.claimed_at > CLAIM_EXPIRY_SECS)
+    }
+}
+
+/// How to answer a request for a claimed hostname
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostRoute {
+    /// Serve the site
+    Site(String),
+
+    /// Permanently redirect to this hostname
+    Redirect(String),
+
+    /// Answer an HTTP challenge with this token
+    Challenge(String),
+}
+
+/// Hostnames claimed by every tenant's sites
+/// A hostname belongs to at most one site; only verified hostnames are routed.
+pub struct DomainManager {
+    domains: DashMap<String, Domain>,
+
+    /// Contact for ACME accounts; certificates are requested on verification when set
+    acme_email: Option<String>,
+}
+
+impl DomainManager {
+    pub fn new() -> Self {
+        Self { domains: DashMap::new(), acme_email: None }
+    }
+
+    /// Request a certificate for every hostname once it is verified
+    pub fn with_acme(mut self, email: String) -> Self {
+        self.acme_email = Some(email);
+        self
+    }
+
+    /// Claim `hostname` for a site, or update the site's existing claim
+    /// Fails while another site holds the hostname, unless that site's tenant let an unverified claim lapse.
+    pub fn claim(
+        &self,
+        tenant_id: Uuid,
+        site_id: &str,
+        hostname: &str,
+        method: VerificationMethod,
+        redirect_to: Option<&str>,
+    ) -> Result<Domain> {
+        let hostname = normalize_hostname(hostname)?;
+        let redirect_to = redirect_to.map(|target| self.redirect_target(site_id, &hostname, target)).transpose()?;
+        let now = Utc::now().timestamp();
+
+        let domain = match self.domains.entry(hostname.clone()) {
+            Entry::Occupied(mut existing) => {
+                let domain = existing.get_mut();
+                if domain.site_id != site_id && !domain.lapsed(now) {
+                    if domain.tenant_id != tenant_id {
+                        bail!("Domain {} is claimed by another tenant", hostname);
+                    }
+                    bail!("Domain {} is already attached to another site", hostname);
+                }
+                if domain.site_id != site_id {
+                    info!(hostname = %hostname, previous_site = %domain.site_id, "Lapsed domain claim taken over");
+                    *domain = new_domain(tenant_id, site_id, &hostname, method, now)?;
+                } else if domain.status == DomainStatus::Failed {
+                    // Claiming again restarts the verification window
+                    domain.status = DomainStatus::Pending;
+                    domain.claimed_at = now;
+                }
+                domain.method = method;
+                domain.redirect_to = redirect_to;
+                domain.clone()
+            }
+            Entry::Vacant(vacant) => {
+                let mut domain = new_domain(tenant_id, site_id, &hostname, method, now)?;
+                domain.redirect_to = redirect_to;
+                vacant.insert(domain).clone()
+            }
+        };
+        info!(hostname = %hostname, site_id = %site_id, method = ?method, "Domain claimed");
+        Ok(domain)
+    }
+
+    /// Drop a site's claim on `hostname`
+    pub fn release(&self, site_id: &str, hostname: &str) -> Result<()> {
+        let hostname = normalize_hostname(hostname)?;
+        let redirected_from: Vec<_> = self.domains.iter()
+            .filter(|domain| domain.redirect_to.as_deref() == Some(hostname.as_str()))
+            .map(|domain| domain.hostname.clone())
+            .collect();
+        if !redirected_from.is_empty() {
+            bail!("{} redirect to {}; remove them first", redirected_from.join(", "), hostname);
+        }
+        if self.domains.remove_if(&hostname, |_, domain| domain.site_id == site_id).is_none() {
+            bail!("Domain {} is not attached to site {}", hostname, site_id);
+        }
+        info!(hostname = %hostname, site_id = %site_id, "Domain released");
+        Ok(())
+    }
+
+    /// Drop every claim of a removed site
+    pub fn release_site(&self, site_id: &str) {
+        self.domains.retain(|_, domain| domain.site_id != site_id);
+    }
+
+    pub fn get(&self, hostname: &str) -> Option<Domain> {
+        let hostname = normalize_hostname(hostname).ok()?;
+        self.domains.get(&hostname).map(|domain| domain.clone())
+    }
+
+    /// A site's hostnames, sorted
+    pub fn site_domains(&self, site_id: &str) -> Vec<Domain> {
+        let mut domains: Vec<_> = self.domains.iter()
+            .filter(|domain| domain.site_id == site_id)
+            .map(|domain| domain.clone())
+            .collect();
+        domains.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        domains
+    }
+
+    /// How to answer a request for `host` (a Host header value, port allowed) and `path`
+    /// `None` for hostnames that aren't claimed and verified, other than their challenges.
+    pub fn route(&self, host: &str, path: &str) -> Option<HostRoute> {
+        let hostname = host_without_port(host).to_ascii_lowercase();
+        let domain = self.domains.get(hostname.trim_end_matches('.'))?;
+        if let Some(token) = path.strip_prefix(CHALLENGE_PATH) {
+            if token == domain.token {
+                return Some(HostRoute::Challenge(domain.token.clone()));
+            }
+        }
+        if domain.status != DomainStatus::Verified {
+            return None;
+        }
+        Some(match &domain.redirect_to {
+            Some(target) => HostRoute::Redirect(target.clone()),
+            None => HostRoute::Site(domain.site_id.clone()),
+        })
+    }
+
+    /// Check ownership of `hostname` now, requesting its certificate once verified
+    pub async fn verify(&self, hostname: &str) -> Result<Domain> {
+        let domain = self.get(hostname).with_context(|| format!("Domain {} is not claimed", hostname))?;
+        if domain.status == DomainStatus::Verified {
+            return Ok(domain);
+        }
+
+        let result = match domain.method {
+            VerificationMethod::Dns => check_txt(&domain).await,
+            VerificationMethod::Http => check_challenge(&domain.hostname, &domain).await,
+        };
+        let now = Utc::now().timestamp();
+        let updated = {
+            let mut current = self.domains.get_mut(&domain.hostname)
+                .filter(|current| current.token == domain.token)
+                .with_context(|| format!("Domain {} was released during verification", domain.hostname))?;
+            current.last_checked = Some(now);
+            match &result {
+                Ok(()) => {
+                    current.status = DomainStatus::Verified;
+                    current.verified_at = Some(now);
+                    current.last_error = None;
+                    info!(hostname = %current.hostname, site_id = %current.site_id, "Domain verified");
+                }
+                Err(e) => {
+                    current.last_error = Some(format!("{:#}", e));
+                    if current.lapsed(now) {
+                        current.status = DomainStatus::Failed;
+                        warn!(hostname = %current.hostname, error = %e, "Domain claim lapsed unverified");
+                    }
+                }
+            }
+            current.clone()
+        };
+
+        if updated.status == DomainStatus::Verified {
+            self.request_certificate(&updated.hostname).await;
+            return Ok(self.get(&updated.hostname).unwrap_or(updated));
+        }
+        Ok(updated)
+    }
+
+    /// Keep checking pending domains in the background
+    pub fn start(self: &Arc<Self>, interval: Duration) {
+        let domains = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(domains) = domains.upgrade() else { break };
+                let pending: Vec<_> = domains.domains.iter()
+                    .filter(|domain| domain.status == DomainStatus::Pending)
+                    .map(|domain| domain.hostname.clone())
+                    .collect();
+                for hostname in pending {
+                    if let Err(e) = domains.verify(&hostname).await {
+                        debug!(hostname = %hostname, error = %e, "Domain check skipped");
+                    }
+                }
+            }
+        });
+    }
+
+    async fn request_certificate(&self, hostname: &str) {
+        let Some(email) = &self.acme_email else { return };
+        if let Some(mut domain) = self.domains.get_mut(hostname) {
+            domain.certificate = CertificateStatus::Requested;
+        }
+
+        let result = AcmeManager::new(email.clone(), vec![hostname.to_string()]).provision_certificate().await;
+        let status = match result {
+            Ok(()) => CertificateStatus::Issued,
+            Err(e) => {
+                warn!(hostname = %hostname, error = %e, "Certificate request failed");
+                let site_id = self.domains.get(hostname).map(|domain| domain.site_id.clone());
+                crate::notifications::notify(crate::notifications::Notification::new(
+                    crate::notifications::EventKind::CertificateFailure,
+                    site_id.as_deref(),
+                    format!("Certificate for {} could not be issued", hostname),
+                    serde_json::json!({ "hostname": hostname, "error": format!("{:#}", e) }),
+                ));
+                CertificateStatus::Failed
+            }
+        };
+        if let Some(mut domain) = self.domains.get_mut(hostname) {
+            domain.certificate = status;
+        }
+    }
+
+    /// A redirect target must be another of the site's hostnames, and not a redirect itself
+    fn redirect_target(&self, site_id: &str, hostname: &str, target: &str) -> Result<String> {
+        let target = normalize_hostname(target)?;
+        if target == hostname {
+            bail!("Domain {} can't redirect to itself", hostname);
+        }
+        match self.domains.get(&target) {
+            Some(domain) if domain.site_id == site_id && domain.redirect_to.is_none() => Ok(target),
+            Some(domain) if domain.site_id == site_id => {
+                bail!("{} is a redirect itself; redirect to {} instead", target, domain.redirect_to.as_deref().unwrap_or_default())
+            }
+            _ => bail!("Redirect target {} is not one of the site's domains", target),
+        }
+    }
+}
+
+impl Default for DomainManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn new_domain(tenant_id: Uuid, site_id: &str, hostname: &str, method: VerificationMethod, now: i64) -> Result<Domain> {
+    let mut token = [0u8; 16];
+    SystemRandom::new().fill(&mut token).map_err(|_| anyhow::anyhow!("Failed to generate a challenge token"))?;
+    Ok(Domain {
+        hostname: hostname.to_string(),
+        tenant_id,
+        site_id: site_id.to_string(),
+        method,
+        token: hex::encode(token),
+        status: DomainStatus::Pending,
+        redirect_to: None,
+        claimed_at: now,
+        verified_at: None,
+        last_checked: None,
+        last_error: None,
+        certificate: CertificateStatus::None,
+    })
+}
+
+/// Lowercase, without a trailing dot, and made of valid DNS labels
+pub fn normalize_hostname(hostname: &str) -> Result<String> {
+    let hostname = hostname.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    let labels: Vec<_> = hostname.split('.').collect();
+    let numeric = hostname.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+    if hostname.len() > 253 || labels.len() < 2 || numeric || !labels.iter().all(|label| valid_label(label)) {
+        bail!("Invalid domain '{}'", hostname);
+    }
+    Ok(hostname)
+}
+
+fn host_without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+/// Host a request was sent to: `:authority` over HTTP/2, the Host header over HTTP/1.1
+pub fn request_host<B>(req: &Request<B>) -> Option<&str> {
+    match req.uri().authority() {
+        Some(authority) => Some(authority.as_str()),
+        None => req.headers().get(header::HOST)?.to_str().ok(),
+    }
+}
+
+/// Plain-text answer to an HTTP challenge
+pub fn challenge_response(token: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Full::new(Bytes::from(token)))
+        .unwrap()
+}
+
+/// 308 to the same path on `target`, keeping the request's scheme and port
+pub fn redirect_response<B>(req: &Request<B>, target: &str, secure: bool) -> Response<Full<Bytes>> {
+    let scheme = if secure { "https" } else { "http" };
+    let port = request_host(req)
+        .and_then(|host| host.rsplit_once(':'))
+        .filter(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()))
+        .map(|(_, port)| format!(":{}", port))
+        .unwrap_or_default();
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Response::builder()
+        .status(StatusCode::PERMANENT_REDIRECT)
+        .header(header::LOCATION, format!("{}://{}{}{}", scheme, target, port, path))
+        .body(Full::new(Bytes::new()))
+        .unwrap()
+}
+
+async fn check_txt(domain: &Domain) -> Result<()> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()
+        .unwrap_or_else(|_| TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()));
+    let name = format!("{}.", domain.txt_name());
+    let lookup = tokio::time::timeout(CHECK_TIMEOUT, resolver.txt_lookup(name.as_str())).await
+        .context("TXT lookup timed out")?
+        .with_context(|| format!("No TXT record at {}", domain.txt_name()))?;
+
+    let expected = domain.txt_value();
+    let found = lookup.iter()
+        .map(|txt| txt.txt_data().iter().map(|part| String::from_utf8_lossy(part)).collect::<String>())
+        .any(|value| value.trim() == expected);
+    if !found {
+        bail!("TXT record at {} doesn't contain {}", domain.txt_name(), expected);
+    }
+    Ok(())
+}
+
+/// Fetch the challenge from `authority` (normally the hostname itself, on port 80)
+async fn check_challenge(authority: &str, domain: &Domain) -> Result<()> {
+    use http_body_util::{BodyExt, Empty, Limited};
+
+    let authority = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+    let fetch = async {
+        let stream = tokio::net::TcpStream::connect(&authority).await
+            .with_context(|| format!("Cannot connect to {}", authority))?;
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+
+        let request = Request::get(format!("{}{}", CHALLENGE_PATH, domain.token))
+            .header(header::HOST, &domain.hostname)
+            .body(Empty::<Bytes>::new())?;
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+        let body = Limited::new(response.into_body(), 1024).collect().await
+            .map_err(|e| anyhow::anyhow!("Challenge response unreadable: {}", e))?
+            .to_bytes();
+        anyhow::Ok((status, body))
+    };
+    let (status, body) = tokio::time::timeout(CHECK_TIMEOUT, fetch).await.context("Challenge request timed out")??;
+
+    if !status.is_success() {
+        bail!("{} answered the challenge with {}", domain.hostname, status);
+    }
+    if String::from_utf8_lossy(&body).trim() != domain.token {
+        bail!("{} answered the challenge with the wrong token; is it pointed at this server?", domain.hostname);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claims_are_exclusive() {
+        let domains = DomainManager::new();
+        let (acme, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let claimed = domains.claim(acme, "blog", "Blog.Example.com.", VerificationMethod::Dns, None).unwrap();
+        assert_eq!(claimed.hostname, "blog.example.com");
+        assert_eq!(claimed.status, DomainStatus::Pending);
+
+        let taken = domains.claim(other, "shop", "blog.example.com", VerificationMethod::Http, None);
+        assert!(taken.unwrap_err().to_string().contains("another tenant"));
+        assert!(domains.claim(acme, "shop", "blog.example.com", VerificationMethod::Http, None).is_err());
+        assert!(domains.claim(acme, "blog", "not a domain", VerificationMethod::Http, None).is_err());
+        assert!(domains.claim(acme, "blog", "10.0.0.1", VerificationMethod::Http, None).is_err());
+
+        // Updating keeps the token; a lapsed claim can be taken over
+        let updated = domains.claim(acme, "blog", "blog.example.com", VerificationMethod::Http, None).unwrap();
+        assert_eq!(updated.token, claimed.token);
+        domains.domains.get_mut("blog.example.com").unwrap().claimed_at -= CLAIM_EXPIRY_SECS + 1;
+        let taken = domains.claim(other, "shop", "blog.example.com", VerificationMethod::Http, None).unwrap();
+        assert_ne!(taken.token, claimed.token);
+
+        domains.release_site("shop");
+        assert!(domains.get("blog.example.com").is_none());
+    }
+
+    #[test]
+    fn test_routes_verified_domains_and_redirects() {
+        let domains = DomainManager::new();
+        let tenant = Uuid::new_v4();
+        let apex = domains.claim(tenant, "blog", "example.com", VerificationMethod::Http, None).unwrap();
+        assert!(domains.claim(tenant, "blog", "www.example.com", VerificationMethod::Http, Some("nowhere.com")).is_err());
+        domains.claim(tenant, "blog", "www.example.com", VerificationMethod::Http, Some("example.com")).unwrap();
+
+        // Only challenges are answered before verification
+        let challenge = format!("{}{}", CHALLENGE_PATH, apex.token);
+        assert_eq!(domains.route("example.com:8080", &challenge), Some(HostRoute::Challenge(apex.token.clone())));
+        assert_eq!(domains.route("example.com", "/"), None);
+        assert_eq!(domains.route("unknown.com", &challenge), None);
+
+        for mut domain in domains.domains.iter_mut() {
+            domain.status = DomainStatus::Verified;
+        }
+        assert_eq!(domains.route("EXAMPLE.com", "/"), Some(HostRoute::Site("blog".to_string())));
+        assert_eq!(domains.route("www.example.com", "/"), Some(HostRoute::Redirect("example.com".to_string())));
+        let req = Request::get("/post?id=7").header(header::HOST, "www.example.com:8443").body(()).unwrap();
+        let response = redirect_response(&req, "example.com", true);
+        assert_eq!(response.headers()[header::LOCATION], "https://example.com:8443/post?id=7");
+
+        assert!(domains.release("blog", "example.com").is_err());
+        domains.release("blog", "www.example.com").unwrap();
+        domains.release("blog", "example.com").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_challenge() {
+        let domains = DomainManager::new();
+        let domain = domains.claim(Uuid::new_v4(), "blog", "example.com", VerificationMethod::Http, None).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Answer the way the router does for the claimed hostname
+        let served = Arc::new(domains);
+        let router = served.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = hyper::service::service_fn(move |req: Request<hyper::body::Incoming>| {
+                let response = match router.route(request_host(&req).unwrap(), req.uri().path()) {
+                    Some(HostRoute::Challenge(token)) => challenge_response(token),
+                    _ => Response::builder().status(StatusCode::NOT_FOUND).body(Full::new(Bytes::new())).unwrap(),
+                };
+                async move { Ok::<_, std::convert::Infallible>(response) }
+            });
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                .await;
+        });
+
+        check_challenge(&addr.to_string(), &domain).await.unwrap();
+        let mut wrong = domain.clone();
+        wrong.token = "0".repeat(32);
+        assert!(check_challenge("127.0.0.1:1", &wrong).await.is_err());
+    }
+}
@@ -0,0 +1,256 @@
+// Login Lockout
+// Counts failed management logins per client address and per account, locking them out for doubling periods
+
+use anyhow::{Result, bail};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::network::peer::client_key;
+
+/// How often idle counters are dropped
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `[lockout]`: brute-force protection for the management API and single sign-on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LockoutConfig {
+    pub enabled: bool,
+
+    /// Failed attempts within `window_secs` that lock a client or account out
+    pub max_failures: u32,
+
+    pub window_secs: u64,
+
+    /// First lockout; each further one doubles it
+    pub lockout_secs: u64,
+
+    /// Longest lockout; counters are also forgotten after this long without failures
+    pub max_lockout_secs: u64,
+
+    /// Lockouts after which the client address is put on the ban list (0 = never)
+    pub ban_after_lockouts: u32,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_failures: 5,
+            window_secs: 300,
+            lockout_secs: 60,
+            max_lockout_secs: 3600,
+            ban_after_lockouts: 3,
+        }
+    }
+}
+
+impl LockoutConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if self.max_failures == 0 {
+            bail!("max_failures must be at least 1");
+        }
+        if self.window_secs == 0 || self.lockout_secs == 0 {
+            bail!("window_secs and lockout_secs must be at least 1");
+        }
+        if self.max_lockout_secs < self.lockout_secs {
+            bail!("max_lockout_secs must be at least lockout_secs");
+        }
+        Ok(())
+    }
+}
+
+/// A lockout that a failure just started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lockout {
+    pub duration: Duration,
+
+    /// Lockouts of this client or account so far, this one included
+    pub count: u32,
+}
+
+/// Failures of one client or account
+struct Attempts {
+    failures: u32,
+    window_start: Instant,
+    last_failure: Instant,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Failed login counters, kept until restart
+pub struct LoginGuard {
+    config: LockoutConfig,
+    attempts: DashMap<String, Attempts>,
+}
+
+impl LoginGuard {
+    pub fn new(config: LockoutConfig) -> Self {
+        Self {
+            config,
+            attempts: DashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &LockoutConfig {
+        &self.config
+    }
+
+    /// Key counting the failures of a client address (IPv6 clients by /64)
+    pub fn ip_key(ip: IpAddr) -> String {
+        format!("ip:{}", client_key(ip))
+    }
+
+    /// Key counting the failures of a single sign-on account
+    pub fn account_key(subject: &str) -> String {
+        format!("account:{}", subject)
+    }
+
+    /// Periodically drop counters of clients and accounts that stopped failing
+    pub fn start(self: &Arc<Self>) {
+        let guard = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                guard.cleanup(Instant::now());
+            }
+        });
+    }
+
+    /// Time left on the key's lockout, if it is locked out
+    pub fn check(&self, key: &str) -> Option<Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    /// Count a failed attempt, returning the lockout it started, if any
+    pub fn record_failure(&self, key: &str) -> Option<Lockout> {
+        self.record_failure_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Option<Duration> {
+        let attempts = self.attempts.get(key)?;
+        attempts.locked_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    fn record_failure_at(&self, key: &str, now: Instant) -> Option<Lockout> {
+        let mut attempts = self.attempts.entry(key.to_string()).or_insert_with(|| Attempts {
+            failures: 0,
+            window_start: now,
+            last_failure: now,
+            lockouts: 0,
+            locked_until: None,
+        });
+        attempts.last_failure = now;
+        if attempts.locked_until.is_some_and(|until| until > now) {
+            return None;
+        }
+        if now.duration_since(attempts.window_start) >= Duration::from_secs(self.config.window_secs) {
+            attempts.failures = 0;
+            attempts.window_start = now;
+        }
+        attempts.failures += 1;
+        if attempts.failures < self.config.max_failures {
+            return None;
+        }
+
+        attempts.failures = 0;
+        attempts.lockouts += 1;
+        let doubling = 1u64 << (attempts.lockouts - 1).min(32);
+        let duration = Duration::from_secs(
+            self.config.lockout_secs.saturating_mul(doubling).min(self.config.max_lockout_secs),
+        );
+        attempts.locked_until = Some(now + duration);
+        Some(Lockout { duration, count: attempts.lockouts })
+    }
+
+    /// Forget keys that are not locked out and haven't failed for `max_lockout_secs`
+    fn cleanup(&self, now: Instant) {
+        let idle = Duration::from_secs(self.config.max_lockout_secs.max(self.config.window_secs));
+        self.attempts.retain(|_, attempts| {
+            attempts.locked_until.is_some_and(|until| until > now)
+                || now.duration_since(attempts.last_failure) < idle
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> LoginGuard {
+        LoginGuard::new(LockoutConfig {
+            max_failures: 3,
+            window_secs: 60,
+            lockout_secs: 10,
+            max_lockout_secs: 25,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(LockoutConfig::default().validate().is_ok());
+        assert!(LockoutConfig { max_failures: 0, ..Default::default() }.validate().is_err());
+        assert!(LockoutConfig { lockout_secs: 600, max_lockout_secs: 60, ..Default::default() }.validate().is_err());
+        assert!(LockoutConfig { enabled: false, max_failures: 0, ..Default::default() }.validate().is_ok());
+    }
+
+    #[test]
+    fn test_lockouts_double_up_to_the_limit() {
+        let guard = guard();
+        let key = LoginGuard::ip_key("203.0.113.7".parse().unwrap());
+        let start = Instant::now();
+
+        assert_eq!(guard.record_failure_at(&key, start), None);
+        assert_eq!(guard.record_failure_at(&key, start), None);
+        assert_eq!(guard.check_at(&key, start), None);
+        let first = guard.record_failure_at(&key, start).unwrap();
+        assert_eq!(first, Lockout { duration: Duration::from_secs(10), count: 1 });
+        assert_eq!(guard.check_at(&key, start + Duration::from_secs(4)), Some(Duration::from_secs(6)));
+
+        // Failures while locked out don't count
+        assert_eq!(guard.record_failure_at(&key, start + Duration::from_secs(5)), None);
+        let later = start + Duration::from_secs(10);
+        assert_eq!(guard.check_at(&key, later), None);
+        for _ in 0..2 {
+            assert_eq!(guard.record_failure_at(&key, later), None);
+        }
+        assert_eq!(guard.record_failure_at(&key, later).unwrap().duration, Duration::from_secs(20));
+
+        let last = later + Duration::from_secs(20);
+        for _ in 0..2 {
+            guard.record_failure_at(&key, last);
+        }
+        assert_eq!(
+            guard.record_failure_at(&key, last),
+            Some(Lockout { duration: Duration::from_secs(25), count: 3 }),
+        );
+    }
+
+    #[test]
+    fn test_failures_expire_with_the_window() {
+        let guard = guard();
+        let key = LoginGuard::account_key("alice@example.com");
+        let start = Instant::now();
+
+        guard.record_failure_at(&key, start);
+        guard.record_failure_at(&key, start);
+        assert_eq!(guard.record_failure_at(&key, start + Duration::from_secs(61)), None);
+        assert_eq!(guard.record_failure_at(&key, start + Duration::from_secs(62)), None);
+        assert!(guard.record_failure_at(&key, start + Duration::from_secs(63)).is_some());
+
+        // Other keys are counted separately
+        assert_eq!(guard.check_at(&LoginGuard::account_key("bob@example.com"), start), None);
+
+        guard.cleanup(start + Duration::from_secs(63 + 60));
+        assert_eq!(guard.attempts.len(), 0);
+    }
+}
@@ -2,15 +2,41 @@
 // Complete tenant isolation and resource management
 
 pub mod auth;
+pub mod bandwidth;
+pub mod billing;
+pub mod domains;
+pub mod lockout;
+pub mod oidc;
 pub mod quota;
+pub mod secrets;
+pub mod signing;
 
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::sync::Arc;
 use dashmap::DashMap;
 use uuid::Uuid;
+use crate::ai::policy::AiPolicy;
+use crate::cage::partition::{PartitionHandle, PartitionLimits, ResourcePartitions};
 use chrono::{DateTime, Utc};
 use anyhow::{Result, Context};
-use tracing::{info, warn};
+use tracing::{info, warn, error};
+use crate::storage::artifacts::{self, Artifact, ArtifactSourceRequest, ArtifactStore, TenantArtifactSource};
+use crate::deployment::git::{self, GitSource, GitSourceRequest};
+use crate::storage::encryption::Keyring;
+use crate::storage::s3::S3Source;
+use crate::storage::usage::{self, SiteUsage};
+use crate::storage::{StorageConfig, StorageManager};
+use crate::storage::database::{DatabaseManager, GuestDatabase};
+use domains::{Domain, DomainManager, VerificationMethod};
+use quota::StorageLevel;
+use secrets::{EnvEntry, SecretKey, SiteEnv};
+use signing::{SignatureCheck, TenantKey};
+use crate::cage::config::CageEnv;
+use crate::mail::MailRelay;
+
+/// How long file events on a site settle before it is rescanned
+const CHANGE_SETTLE: std::time::Duration = std::time::Duration::from_secs(2);
 
 /// Tenant manager
 pub struct TenantManager {
@@ -19,6 +45,27 @@ pub struct TenantManager {
     
     /// Default tenant (for backward compatibility)
     default_tenant_id: Uuid,
+    
+    /// Last storage level seen per tenant, so threshold crossings are logged once
+    storage_levels: Arc<DashMap<Uuid, StorageLevel>>,
+    
+    /// Master key for site secrets; secrets cannot be set without one
+    secret_key: Option<Arc<SecretKey>>,
+    
+    /// Hostnames claimed by sites, across all tenants
+    domains: Arc<DomainManager>,
+    
+    /// Owner of tenant and site directories: their layout, usage and deletion
+    storage: Option<Arc<StorageManager>>,
+    
+    /// Keys of tenants that encrypt their files and secrets at rest
+    keyring: Option<Arc<Keyring>>,
+    
+    /// Cache of modules and site content pulled from object storage
+    artifacts: Option<Arc<ArtifactStore>>,
+    
+    /// Key each fetched artifact came from, by SHA-256
+    artifact_keys: Arc<DashMap<String, String>>,
 }
 
 /// Tenant data
@@ -32,6 +79,18 @@ pub struct Tenant {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub status: TenantStatus,
+    
+    /// AI policy applied to every site of the tenant
+    #[serde(default)]
+    pub ai_policy: AiPolicy,
+    
+    /// Keys the tenant's module signatures are checked against
+    #[serde(default)]
+    pub signing_keys: Vec<TenantKey>,
+    
+    /// The tenant's own bucket for modules and site content, instead of the node's
+    #[serde(default)]
+    pub artifact_source: Option<TenantArtifactSource>,
 }
 
 /// Site within a tenant
@@ -43,6 +102,26 @@ pub struct Site {
     pub cage_count: usize,
     pub storage_used_mb: usize,
     pub created_at: DateTime<Utc>,
+    
+    /// Unix time `storage_used_mb` was counted, if it has been
+    #[serde(default)]
+    pub storage_scanned_at: Option<i64>,
+    
+    /// AI policy overrides for this site, on top of the tenant's
+    #[serde(default)]
+    pub ai_policy: AiPolicy,
+    
+    /// Environment variables and encrypted secrets passed to the site's Cages
+    #[serde(default)]
+    pub env: SiteEnv,
+    
+    /// Refuse modules not signed by one of the tenant's keys
+    #[serde(default)]
+    pub require_signature: bool,
+    
+    /// Repository the site is built and deployed from
+    #[serde(default)]
+    pub git: Option<GitSource>,
 }
 
 /// Resource quota per tenant
@@ -53,6 +132,37 @@ pub struct ResourceQuota {
     pub max_memory_per_cage_mb: usize,
     pub max_cages_per_site: usize,
     pub max_requests_per_second: Option<usize>,
+    
+    /// Ingress plus egress across all sites per calendar month
+    #[serde(default)]
+    pub max_bandwidth_gb_month: Option<u64>,
+    
+    /// Memory limits of all the tenant's running Cages combined
+    #[serde(default)]
+    pub max_total_memory_mb: Option<usize>,
+    
+    /// Running Cages across all of the tenant's sites
+    #[serde(default)]
+    pub max_instances: Option<usize>,
+    
+    /// Combined size of the tenant's site databases
+    #[serde(default)]
+    pub max_database_mb: Option<usize>,
+    
+    /// Email recipients per day across all sites
+    #[serde(default)]
+    pub max_emails_per_day: Option<u64>,
+}
+
+impl ResourceQuota {
+    /// Limits for the tenant's share of the Cage runtime
+    pub fn partition_limits(&self) -> PartitionLimits {
+        PartitionLimits {
+            max_memory_per_cage_bytes: Some(self.max_memory_per_cage_mb * 1024 * 1024),
+            max_total_memory_bytes: self.max_total_memory_mb.map(|mb| mb * 1024 * 1024),
+            max_instances: self.max_instances,
+        }
+    }
 }
 
 impl Default for ResourceQuota {
@@ -63,12 +173,17 @@ impl Default for ResourceQuota {
             max_memory_per_cage_mb: 128,
             max_cages_per_site: 3,
             max_requests_per_second: None,
+            max_bandwidth_gb_month: None,
+            max_total_memory_mb: None,
+            max_instances: None,
+            max_database_mb: None,
+            max_emails_per_day: None,
         }
     }
 }
 
 /// Tenant status
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TenantStatus {
     Active,
     Suspended,
@@ -93,6 +208,9 @@ impl TenantManager {
             created_at: Utc::now(),
             updated_at: Utc::now(),
             status: TenantStatus::Active,
+            ai_policy: AiPolicy::default(),
+            signing_keys: Vec::new(),
+            artifact_source: None,
         };
         
         tenants.insert(default_tenant_id, default_tenant);
@@ -100,9 +218,55 @@ impl TenantManager {
         Self {
             tenants,
             default_tenant_id,
+            storage_levels: Arc::new(DashMap::new()),
+            secret_key: None,
+            domains: Arc::new(DomainManager::new()),
+            storage: None,
+            keyring: None,
+            artifacts: None,
+            artifact_keys: Arc::new(DashMap::new()),
         }
     }
 
+    /// Keep tenant and site files in `storage`; without it the manager never touches the filesystem
+    pub fn with_storage(mut self, storage: Arc<StorageManager>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    fn storage(&self) -> Result<&Arc<StorageManager>> {
+        self.storage.as_ref().context("Tenant storage is not configured")
+    }
+
+    /// Let tenants encrypt their files and secrets at rest with keys from `keyring`
+    pub fn with_keyring(mut self, keyring: Arc<Keyring>) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    /// Pull modules and site content from object storage through `artifacts`
+    pub fn with_artifacts(mut self, artifacts: Arc<ArtifactStore>) -> Self {
+        self.artifacts = Some(artifacts);
+        self
+    }
+
+    /// Use a master key for sealing and opening site secrets
+    pub fn with_secret_key(mut self, key: Arc<SecretKey>) -> Self {
+        self.secret_key = Some(key);
+        self
+    }
+
+    /// Keep site domains in a shared manager, such as one the Router also consults
+    pub fn with_domains(mut self, domains: Arc<DomainManager>) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    /// Hostnames claimed by sites
+    pub fn domains(&self) -> &Arc<DomainManager> {
+        &self.domains
+    }
+
     /// Create a new tenant
     pub fn create_tenant(&self, name: String, email: String, quota: ResourceQuota) -> Result<Uuid> {
         let tenant_id = Uuid::new_v4();
@@ -116,14 +280,18 @@ impl TenantManager {
             created_at: Utc::now(),
             updated_at: Utc::now(),
             status: TenantStatus::Active,
+            ai_policy: AiPolicy::default(),
+            signing_keys: Vec::new(),
+            artifact_source: None,
         };
         
         self.tenants.insert(tenant_id, tenant);
         
         info!(tenant_id = %tenant_id, name = %name, "Tenant created");
         
-        // Create tenant directory
-        self.create_tenant_directory(tenant_id)?;
+        if let Some(storage) = &self.storage {
+            storage.create_tenant_storage(tenant_id)?;
+        }
         
         Ok(tenant_id)
     }
@@ -154,19 +322,34 @@ impl TenantManager {
         let site = Site {
             id: site_id.clone(),
             name: site_name.clone(),
-            domain,
+            domain: None,
             cage_count: 0,
             storage_used_mb: 0,
             created_at: Utc::now(),
+            storage_scanned_at: None,
+            ai_policy: AiPolicy::default(),
+            env: SiteEnv::default(),
+            require_signature: false,
+            git: None,
         };
         
         tenant.sites.push(site);
         tenant.updated_at = Utc::now();
+        drop(tenant_entry);
+        
+        // Claimed like any other domain, and the site isn't added if that fails
+        if let Some(domain) = domain {
+            if let Err(e) = self.set_site_domain(tenant_id, &site_id, Some(domain)) {
+                self.remove_site(tenant_id, &site_id)?;
+                return Err(e);
+            }
+        }
         
         info!(tenant_id = %tenant_id, site_id = %site_id, "Site added to tenant");
         
-        // Create site directory
-        self.create_site_directory(tenant_id, &site_id)?;
+        if let Some(storage) = &self.storage {
+            storage.create_site_storage(tenant_id, &site_id)?;
+        }
         
         Ok(site_id)
     }
@@ -179,13 +362,159 @@ impl TenantManager {
         let tenant = tenant_entry.value_mut();
         tenant.sites.retain(|s| s.id != site_id);
         tenant.updated_at = Utc::now();
+        drop(tenant_entry);
+        self.domains.release_site(site_id);
+        if let Some(keyring) = &self.keyring {
+            keyring.drop_view(tenant_id, site_id);
+        }
+        if let Some(storage) = &self.storage {
+            storage.delete_site_storage(tenant_id, site_id)?;
+        }
         
         info!(tenant_id = %tenant_id, site_id = %site_id, "Site removed from tenant");
         
         Ok(())
     }
 
+    /// Set a site's primary domain, replacing its previous one, or detach it with `None`
+    /// The domain is claimed with HTTP verification if the site doesn't hold it yet.
+    pub fn set_site_domain(&self, tenant_id: Uuid, site_id: &str, domain: Option<String>) -> Result<()> {
+        let previous = self.site(tenant_id, site_id)?.domain;
+        let domain = match domain {
+            Some(domain) => {
+                let method = self.domains.get(&domain)
+                    .filter(|claimed| claimed.site_id == site_id)
+                    .map_or(VerificationMethod::default(), |claimed| claimed.method);
+                Some(self.domains.claim(tenant_id, site_id, &domain, method, None)?.hostname)
+            }
+            None => None,
+        };
+        if let Some(previous) = previous.filter(|previous| Some(previous) != domain.as_ref()) {
+            self.domains.release(site_id, &previous)?;
+        }
+        
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        let site = tenant.sites.iter_mut()
+            .find(|s| s.id == site_id)
+            .context("Site not found")?;
+        site.domain = domain;
+        tenant.updated_at = Utc::now();
+        
+        info!(tenant_id = %tenant_id, site_id = %site_id, domain = ?site.domain, "Site domain updated");
+        
+        Ok(())
+    }
+
+    /// Claim another hostname for a site, optionally redirecting to one of its other hostnames
+    pub fn add_site_domain(
+        &self,
+        tenant_id: Uuid,
+        site_id: &str,
+        hostname: &str,
+        method: VerificationMethod,
+        redirect_to: Option<&str>,
+    ) -> Result<Domain> {
+        self.site(tenant_id, site_id)?;
+        self.domains.claim(tenant_id, site_id, hostname, method, redirect_to)
+    }
+
+    /// Release one of a site's hostnames, detaching it as the primary domain if it was
+    pub fn remove_site_domain(&self, tenant_id: Uuid, site_id: &str, hostname: &str) -> Result<()> {
+        self.site(tenant_id, site_id)?;
+        self.domains.release(site_id, hostname)?;
+        
+        if let Some(mut tenant) = self.tenants.get_mut(&tenant_id) {
+            let hostname = domains::normalize_hostname(hostname)?;
+            for site in tenant.sites.iter_mut().filter(|s| s.id == site_id && s.domain.as_ref() == Some(&hostname)) {
+                site.domain = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn site(&self, tenant_id: Uuid, site_id: &str) -> Result<Site> {
+        let tenant = self.tenants.get(&tenant_id).context("Tenant not found")?;
+        tenant.sites.iter().find(|s| s.id == site_id).cloned().context("Site not found")
+    }
+
+    /// Register a public key the tenant signs modules with, replacing one of the same name
+    pub fn add_signing_key(&self, tenant_id: Uuid, name: &str, public_key: &str) -> Result<TenantKey> {
+        let key = TenantKey::new(name, public_key)?;
+        
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        tenant.signing_keys.retain(|existing| existing.name != key.name);
+        tenant.signing_keys.push(key.clone());
+        tenant.updated_at = Utc::now();
+        
+        info!(tenant_id = %tenant_id, key = %key.name, "Signing key added");
+        
+        Ok(key)
+    }
+
+    /// Remove a tenant signing key
+    pub fn remove_signing_key(&self, tenant_id: Uuid, name: &str) -> Result<()> {
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        let before = tenant.signing_keys.len();
+        tenant.signing_keys.retain(|key| key.name != name);
+        if tenant.signing_keys.len() == before {
+            anyhow::bail!("Signing key {} not found", name);
+        }
+        tenant.updated_at = Utc::now();
+        
+        info!(tenant_id = %tenant_id, key = %name, "Signing key removed");
+        
+        Ok(())
+    }
+
+    /// Require, or stop requiring, signed modules for a site
+    pub fn set_site_require_signature(&self, tenant_id: Uuid, site_id: &str, required: bool) -> Result<()> {
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        if required && tenant.signing_keys.is_empty() {
+            anyhow::bail!("Tenant has no signing keys; add one before requiring signatures");
+        }
+        let site = tenant.sites.iter_mut()
+            .find(|s| s.id == site_id)
+            .context("Site not found")?;
+        site.require_signature = required;
+        tenant.updated_at = Utc::now();
+        
+        info!(tenant_id = %tenant_id, site_id = %site_id, required, "Site signature requirement updated");
+        
+        Ok(())
+    }
+
+    /// Check a module about to be deployed to a site against its tenant's signing keys
+    /// Sites that belong to no tenant accept unsigned modules only.
+    pub fn check_module_signature(&self, site_id: &str, module: &[u8], signature: Option<&str>) -> SignatureCheck {
+        let tenant = self.find_site_tenant(site_id).and_then(|tenant_id| self.get_tenant(tenant_id));
+        let (keys, required) = match &tenant {
+            Some(tenant) => {
+                let required = tenant.sites.iter().any(|s| s.id == site_id && s.require_signature);
+                (tenant.signing_keys.as_slice(), required)
+            }
+            None => (&[][..], false),
+        };
+        let check = signing::check(keys, required, module, signature);
+        if let Some(problem) = check.problem() {
+            warn!(site_id = %site_id, problem = %problem, "Module signature rejected");
+        }
+        check
+    }
+
     /// Update tenant quota
+    /// Call `sync_partitions` afterwards for new Cage limits to take effect
     pub fn update_quota(&self, tenant_id: Uuid, quota: ResourceQuota) -> Result<()> {
         let mut tenant_entry = self.tenants.get_mut(&tenant_id)
             .context("Tenant not found")?;
@@ -199,6 +528,561 @@ impl TenantManager {
         Ok(())
     }
 
+    /// Update the AI policy for all of a tenant's sites
+    pub fn update_ai_policy(&self, tenant_id: Uuid, policy: AiPolicy) -> Result<()> {
+        policy.validate()?;
+        
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        tenant.ai_policy = policy;
+        tenant.updated_at = Utc::now();
+        
+        info!(tenant_id = %tenant_id, "AI policy updated");
+        
+        Ok(())
+    }
+
+    /// Update the AI policy overrides for a single site
+    pub fn update_site_ai_policy(&self, tenant_id: Uuid, site_id: &str, policy: AiPolicy) -> Result<()> {
+        policy.validate()?;
+        
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        let site = tenant.sites.iter_mut()
+            .find(|s| s.id == site_id)
+            .context("Site not found")?;
+        site.ai_policy = policy;
+        tenant.updated_at = Utc::now();
+        
+        info!(tenant_id = %tenant_id, site_id = %site_id, "Site AI policy updated");
+        
+        Ok(())
+    }
+
+    /// Site policies merged over their tenant's, for loading into the AI module
+    pub fn effective_ai_policies(&self, tenant_id: Uuid) -> Vec<(String, AiPolicy)> {
+        self.tenants.get(&tenant_id)
+            .map(|tenant| {
+                tenant.sites.iter()
+                    .map(|site| (site.id.clone(), site.ai_policy.or(&tenant.ai_policy)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Find the tenant owning a site
+    pub fn find_site_tenant(&self, site_id: &str) -> Option<Uuid> {
+        self.tenants.iter()
+            .find(|tenant| tenant.sites.iter().any(|s| s.id == site_id))
+            .map(|tenant| tenant.id)
+    }
+
+    /// Set a site environment variable, encrypting it when `secret` is set
+    /// Running Cages keep their environment; new values apply to Cages created afterwards
+    pub fn set_site_env(&self, tenant_id: Uuid, site_id: &str, name: &str, value: &str, secret: bool) -> Result<()> {
+        let key = self.sealing_key(tenant_id);
+        self.with_site_env(tenant_id, site_id, |env| {
+            if secret {
+                let key = key.as_ref().context("No secret key configured")?;
+                env.set_secret(key, name, value)
+            } else {
+                env.set_var(name, value)
+            }
+        })?;
+        
+        info!(tenant_id = %tenant_id, site_id = %site_id, name = %name, secret, "Site environment variable set");
+        
+        Ok(())
+    }
+
+    /// Remove a site environment variable or secret, returning whether it existed
+    pub fn unset_site_env(&self, tenant_id: Uuid, site_id: &str, name: &str) -> Result<bool> {
+        let removed = self.with_site_env(tenant_id, site_id, |env| Ok(env.unset(name)))?;
+        if removed {
+            info!(tenant_id = %tenant_id, site_id = %site_id, name = %name, "Site environment variable removed");
+        }
+        Ok(removed)
+    }
+
+    /// List a site's variables with secret values masked
+    pub fn site_env(&self, tenant_id: Uuid, site_id: &str) -> Result<Vec<EnvEntry>> {
+        let tenant = self.tenants.get(&tenant_id).context("Tenant not found")?;
+        let site = tenant.sites.iter().find(|s| s.id == site_id).context("Site not found")?;
+        Ok(site.env.entries())
+    }
+
+    /// Decrypted environment for the site's Cages, to put into their `CageConfig`
+    pub fn cage_env(&self, tenant_id: Uuid, site_id: &str) -> Result<CageEnv> {
+        let tenant = self.tenants.get(&tenant_id).context("Tenant not found")?;
+        let site = tenant.sites.iter().find(|s| s.id == site_id).context("Site not found")?;
+        site.env.resolve(self.sealing_key(tenant_id).as_deref())
+    }
+
+    /// Key a tenant's secrets are sealed with: its own once it encrypts at rest, the node's before
+    fn sealing_key(&self, tenant_id: Uuid) -> Option<Arc<SecretKey>> {
+        match self.keyring.as_ref().and_then(|keyring| keyring.tenant_keys(tenant_id)) {
+            Some(keys) => Some(keys.current().clone()),
+            None => self.secret_key.clone(),
+        }
+    }
+
+    /// Turn on encryption at rest for a tenant, or with `rotate` move it to a new key
+    /// Secrets are resealed under the new key at once, then site files are re-encrypted; returns how many files were.
+    pub async fn encrypt_tenant(&self, tenant_id: Uuid, rotate: bool) -> Result<usize> {
+        let keyring = self.keyring.as_ref().context("Encryption at rest is not configured")?;
+        let tenant = self.get_tenant(tenant_id).context("Tenant not found")?;
+        match (keyring.is_encrypted(tenant_id), rotate) {
+            (true, false) => anyhow::bail!("Tenant {} already encrypts its files", tenant.name),
+            (false, true) => anyhow::bail!("Tenant {} doesn't encrypt its files yet", tenant.name),
+            _ => {}
+        }
+        
+        // Every secret must open before any key changes
+        let from = self.sealing_key(tenant_id);
+        for site in &tenant.sites {
+            site.env.resolve(from.as_deref())
+                .with_context(|| format!("Cannot reseal the secrets of site {}", site.id))?;
+        }
+        let keys = keyring.create_key(tenant_id).await?;
+        self.reseal_secrets(tenant_id, from.as_deref(), keys.current())?;
+        
+        let files = keyring.encrypt_files(tenant_id).await?;
+        info!(tenant_id = %tenant_id, key_version = keys.version(), files, "Tenant encrypted at rest");
+        Ok(files)
+    }
+
+    /// Reseal every secret of the tenant's sites from one key to another
+    fn reseal_secrets(&self, tenant_id: Uuid, from: Option<&SecretKey>, to: &SecretKey) -> Result<()> {
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        let mut resealed = Vec::with_capacity(tenant.sites.len());
+        for site in &tenant.sites {
+            let mut secrets = std::collections::BTreeMap::new();
+            for (name, sealed) in &site.env.secrets {
+                let from = from.context("Secrets are set but no secret key is configured")?;
+                secrets.insert(name.clone(), to.seal(name, &from.open(name, sealed)?)?);
+            }
+            let webhook_secret = match &site.git {
+                Some(source) => {
+                    let from = from.context("A git source is set but no secret key is configured")?;
+                    let secret = from.open(git::WEBHOOK_SECRET_NAME, &source.webhook_secret)?;
+                    Some(to.seal(git::WEBHOOK_SECRET_NAME, &secret)?)
+                }
+                None => None,
+            };
+            resealed.push((secrets, webhook_secret));
+        }
+        let artifact_source = match &tenant.artifact_source {
+            Some(source) => {
+                let from = from.context("An artifact source is set but no secret key is configured")?;
+                let secret = from.open(artifacts::SECRET_NAME, &source.secret_access_key)?;
+                Some(TenantArtifactSource {
+                    secret_access_key: to.seal(artifacts::SECRET_NAME, &secret)?,
+                    ..source.clone()
+                })
+            }
+            None => None,
+        };
+        for (site, (secrets, webhook_secret)) in tenant.sites.iter_mut().zip(resealed) {
+            site.env.secrets = secrets;
+            if let (Some(source), Some(webhook_secret)) = (&mut site.git, webhook_secret) {
+                source.webhook_secret = webhook_secret;
+            }
+        }
+        tenant.artifact_source = artifact_source;
+        tenant.updated_at = Utc::now();
+        
+        Ok(())
+    }
+
+    /// Whether a tenant encrypts at rest, and with which key
+    pub fn tenant_encryption(&self, tenant_id: Uuid) -> TenantEncryption {
+        let keys = self.keyring.as_ref().and_then(|keyring| keyring.tenant_keys(tenant_id));
+        TenantEncryption {
+            available: self.keyring.is_some(),
+            enabled: keys.is_some(),
+            key_version: keys.map(|keys| keys.version()),
+            master: self.keyring.as_ref().map(|keyring| keyring.master_name()),
+        }
+    }
+
+    /// Rewrap every tenant key with the master key as it is now, such as after rotating it in Vault
+    pub async fn rewrap_keys(&self) -> Result<usize> {
+        self.keyring.as_ref().context("Encryption at rest is not configured")?.rewrap().await
+    }
+
+    /// Directory with a site's files in plaintext, to preopen read-only in its Cages
+    pub fn site_files_view(&self, tenant_id: Uuid, site_id: &str) -> Result<PathBuf> {
+        match &self.keyring {
+            Some(keyring) => keyring.plaintext_view(tenant_id, site_id),
+            None => Ok(self.storage()?.site_dir(tenant_id, site_id)),
+        }
+    }
+
+    /// Give a tenant its own bucket for modules and site content, or with `None` go back to the node's
+    pub fn set_artifact_source(&self, tenant_id: Uuid, request: Option<&ArtifactSourceRequest>) -> Result<()> {
+        let source = match request {
+            Some(request) => {
+                let key = self.sealing_key(tenant_id).context("No secret key configured")?;
+                Some(TenantArtifactSource::seal(request, &key)?)
+            }
+            None => None,
+        };
+        
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        match &source {
+            Some(source) => info!(tenant_id = %tenant_id, endpoint = %source.endpoint, bucket = %source.bucket, "Artifact source set"),
+            None => info!(tenant_id = %tenant_id, "Artifact source removed"),
+        }
+        tenant.artifact_source = source;
+        tenant.updated_at = Utc::now();
+        
+        Ok(())
+    }
+
+    /// Bucket a tenant's artifacts come from: its own, else the node's
+    pub fn artifact_source(&self, tenant_id: Uuid) -> Result<S3Source> {
+        let tenant = self.tenants.get(&tenant_id).context("Tenant not found")?;
+        if let Some(source) = &tenant.artifact_source {
+            let key = self.sealing_key(tenant_id).context("No secret key configured")?;
+            return source.resolve(&key);
+        }
+        self.artifacts()?.node_source().cloned()
+            .context("Tenant has no artifact source and the node has no [artifacts] endpoint")
+    }
+
+    fn artifacts(&self) -> Result<&Arc<ArtifactStore>> {
+        self.artifacts.as_ref().context("Artifact storage is not configured")
+    }
+
+    /// An object from the tenant's artifact source, through the local cache
+    pub async fn fetch_artifact(&self, tenant_id: Uuid, key: &str, sha256: Option<&str>) -> Result<Artifact> {
+        let source = self.artifact_source(tenant_id)?;
+        let artifact = self.artifacts()?.fetch(&source, key, sha256).await
+            .with_context(|| format!("Failed to fetch {}", source.object_name(key)))?;
+        info!(tenant_id = %tenant_id, key = %key, sha256 = %artifact.sha256, "Artifact fetched");
+        self.artifact_keys.insert(artifact.sha256.clone(), key.to_string());
+        Ok(artifact)
+    }
+
+    /// Key of the last fetched artifact with this SHA-256
+    pub fn artifact_key(&self, sha256: &str) -> Option<String> {
+        self.artifact_keys.get(sha256).map(|key| key.clone())
+    }
+
+    /// Mirror the objects under `prefix` into the site's content directory, removing files no longer listed
+    pub async fn sync_site_content(&self, tenant_id: Uuid, site_id: &str, prefix: &str) -> Result<ContentSync> {
+        self.site(tenant_id, site_id)?;
+        let source = self.artifact_source(tenant_id)?;
+        let store = self.artifacts()?;
+        let objects = store.list(&source, prefix).await
+            .with_context(|| format!("Failed to list {}", source.object_name(prefix)))?;
+        
+        let mut sync = ContentSync::default();
+        let mut listed = std::collections::HashSet::new();
+        for object in &objects {
+            let Some(relative) = object.key.strip_prefix(prefix).map(|key| key.trim_start_matches('/')) else { continue };
+            if relative.is_empty() || relative.ends_with('/') {
+                continue;
+            }
+            if !is_plain_relative(relative) {
+                warn!(tenant_id = %tenant_id, site_id = %site_id, key = %object.key, "Skipping object with an unsafe path");
+                continue;
+            }
+            let path = format!("{}/{}", crate::storage::SITE_CONTENT_DIR, relative);
+            listed.insert(path.clone());
+            
+            // Objects already on disk as listed are left alone
+            if let Some(cached) = store.known(&source, object)? {
+                if self.site_file_sha256(tenant_id, site_id, &path).as_deref() == Some(cached.sha256.as_str()) {
+                    sync.unchanged += 1;
+                    continue;
+                }
+            }
+            let artifact = store.fetch_listed(&source, object).await
+                .with_context(|| format!("Failed to fetch {}", source.object_name(&object.key)))?;
+            self.write_site_file(tenant_id, site_id, &path, &artifact.read()?)?;
+            sync.fetched += 1;
+            sync.bytes += artifact.size;
+        }
+        
+        sync.removed = self.prune_site_content(tenant_id, site_id, &listed)?;
+        
+        info!(
+            tenant_id = %tenant_id,
+            site_id = %site_id,
+            fetched = sync.fetched,
+            unchanged = sync.unchanged,
+            removed = sync.removed,
+            "Site content synced"
+        );
+        
+        Ok(sync)
+    }
+
+    /// Mirror a local directory, such as a build's output, into the site's content directory
+    pub fn import_site_content(&self, tenant_id: Uuid, site_id: &str, dir: &std::path::Path) -> Result<ContentSync> {
+        self.site(tenant_id, site_id)?;
+        
+        let mut sync = ContentSync::default();
+        let mut listed = std::collections::HashSet::new();
+        let entries = walkdir::WalkDir::new(dir).into_iter()
+            .filter_entry(|entry| entry.file_name() != ".git");
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read {}", dir.display()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Some(relative) = entry.path().strip_prefix(dir).ok().and_then(|relative| relative.to_str()) else { continue };
+            let path = format!("{}/{}", crate::storage::SITE_CONTENT_DIR, relative);
+            listed.insert(path.clone());
+            
+            let contents = std::fs::read(entry.path())
+                .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+            let sha256 = hex::encode(ring::digest::digest(&ring::digest::SHA256, &contents));
+            if self.site_file_sha256(tenant_id, site_id, &path).as_deref() == Some(sha256.as_str()) {
+                sync.unchanged += 1;
+                continue;
+            }
+            self.write_site_file(tenant_id, site_id, &path, &contents)?;
+            sync.fetched += 1;
+            sync.bytes += contents.len() as u64;
+        }
+        sync.removed = self.prune_site_content(tenant_id, site_id, &listed)?;
+        
+        info!(
+            tenant_id = %tenant_id,
+            site_id = %site_id,
+            copied = sync.fetched,
+            unchanged = sync.unchanged,
+            removed = sync.removed,
+            "Site content imported"
+        );
+        
+        Ok(sync)
+    }
+
+    /// Write one file of the site's content, such as a save picked up by `pear dev`
+    pub fn write_site_content(&self, tenant_id: Uuid, site_id: &str, relative: &str, contents: &[u8]) -> Result<()> {
+        self.site(tenant_id, site_id)?;
+        if !is_plain_relative(relative) {
+            anyhow::bail!("Invalid content path: {}", relative);
+        }
+        let path = format!("{}/{}", crate::storage::SITE_CONTENT_DIR, relative);
+        self.write_site_file(tenant_id, site_id, &path, contents)?;
+        self.storage()?.usage().invalidate(tenant_id, site_id);
+        Ok(())
+    }
+    
+    /// Remove one file of the site's content, returning whether it existed
+    pub fn remove_site_content(&self, tenant_id: Uuid, site_id: &str, relative: &str) -> Result<bool> {
+        self.site(tenant_id, site_id)?;
+        if !is_plain_relative(relative) {
+            anyhow::bail!("Invalid content path: {}", relative);
+        }
+        let storage = self.storage()?;
+        let path = storage.site_dir(tenant_id, site_id).join(crate::storage::SITE_CONTENT_DIR).join(relative);
+        if !path.is_file() {
+            return Ok(false);
+        }
+        std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        if let Some(keyring) = &self.keyring {
+            keyring.drop_view(tenant_id, site_id);
+        }
+        storage.usage().invalidate(tenant_id, site_id);
+        Ok(true)
+    }
+    
+    /// Remove content files not in `kept`, returning how many were
+    fn prune_site_content(&self, tenant_id: Uuid, site_id: &str, kept: &std::collections::HashSet<String>) -> Result<usize> {
+        let storage = self.storage()?;
+        let site_dir = storage.site_dir(tenant_id, site_id);
+        let content_dir = site_dir.join(crate::storage::SITE_CONTENT_DIR);
+        let mut removed = 0;
+        for entry in walkdir::WalkDir::new(&content_dir).into_iter().filter_map(|entry| entry.ok()) {
+            let Ok(relative) = entry.path().strip_prefix(&site_dir) else { continue };
+            if entry.file_type().is_file() && !kept.contains(relative.to_string_lossy().as_ref()) {
+                std::fs::remove_file(entry.path())
+                    .with_context(|| format!("Failed to remove {}", entry.path().display()))?;
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            if let Some(keyring) = &self.keyring {
+                keyring.drop_view(tenant_id, site_id);
+            }
+        }
+        storage.usage().invalidate(tenant_id, site_id);
+        Ok(removed)
+    }
+
+    /// Deploy a site from a repository, returning the secret its push webhooks must carry
+    pub fn set_site_git(&self, tenant_id: Uuid, site_id: &str, request: &GitSourceRequest) -> Result<String> {
+        request.validate()?;
+        let key = self.sealing_key(tenant_id).context("No secret key configured")?;
+        let secret = git::generate_webhook_secret()?;
+        let source = GitSource {
+            url: request.url.clone(),
+            reference: request.reference.clone(),
+            build: request.build.clone(),
+            output: request.output.clone(),
+            switch: request.switch,
+            webhook_secret: key.seal(git::WEBHOOK_SECRET_NAME, &secret)?,
+        };
+        
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        let site = tenant.sites.iter_mut()
+            .find(|s| s.id == site_id)
+            .context("Site not found")?;
+        site.git = Some(source);
+        tenant.updated_at = Utc::now();
+        
+        info!(tenant_id = %tenant_id, site_id = %site_id, url = %request.url, reference = %request.reference, "Site git source set");
+        
+        Ok(secret)
+    }
+
+    /// Stop deploying a site from git, returning whether it was
+    pub fn remove_site_git(&self, tenant_id: Uuid, site_id: &str) -> Result<bool> {
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        let site = tenant.sites.iter_mut()
+            .find(|s| s.id == site_id)
+            .context("Site not found")?;
+        let removed = site.git.take().is_some();
+        tenant.updated_at = Utc::now();
+        
+        if removed {
+            info!(tenant_id = %tenant_id, site_id = %site_id, "Site git source removed");
+        }
+        
+        Ok(removed)
+    }
+
+    /// The repository a site is deployed from, if any
+    pub fn site_git(&self, tenant_id: Uuid, site_id: &str) -> Result<Option<GitSource>> {
+        Ok(self.site(tenant_id, site_id)?.git)
+    }
+
+    /// Secret the site's push webhooks must carry
+    pub fn git_webhook_secret(&self, tenant_id: Uuid, site_id: &str) -> Result<String> {
+        let source = self.site_git(tenant_id, site_id)?.context("Site has no git source")?;
+        let key = self.sealing_key(tenant_id).context("No secret key configured")?;
+        key.open(git::WEBHOOK_SECRET_NAME, &source.webhook_secret)
+    }
+
+    /// Write a site file, encrypted when the tenant encrypts at rest
+    fn write_site_file(&self, tenant_id: Uuid, site_id: &str, relative: &str, contents: &[u8]) -> Result<()> {
+        if let Some(keyring) = &self.keyring {
+            return keyring.write_site_file(tenant_id, site_id, relative, contents);
+        }
+        let path = self.storage()?.site_dir(tenant_id, site_id).join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// SHA-256 of a site file's plaintext, if it can be read
+    fn site_file_sha256(&self, tenant_id: Uuid, site_id: &str, relative: &str) -> Option<String> {
+        let contents = match &self.keyring {
+            Some(keyring) => keyring.read_site_file(tenant_id, site_id, relative).ok()?,
+            None => std::fs::read(self.storage().ok()?.site_dir(tenant_id, site_id).join(relative)).ok()?,
+        };
+        Some(hex::encode(ring::digest::digest(&ring::digest::SHA256, &contents)))
+    }
+
+    fn with_site_env<T>(&self, tenant_id: Uuid, site_id: &str, f: impl FnOnce(&mut SiteEnv) -> Result<T>) -> Result<T> {
+        let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+            .context("Tenant not found")?;
+        
+        let tenant = tenant_entry.value_mut();
+        let site = tenant.sites.iter_mut()
+            .find(|s| s.id == site_id)
+            .context("Site not found")?;
+        let result = f(&mut site.env)?;
+        tenant.updated_at = Utc::now();
+        
+        Ok(result)
+    }
+
+    /// Load tenant bandwidth quotas and site ownership into a meter
+    pub fn sync_bandwidth(&self, meter: &bandwidth::BandwidthMeter) {
+        for tenant in self.tenants.iter() {
+            let tenant_id = tenant.id.to_string();
+            meter.set_tenant_quota(&tenant_id, bandwidth::BandwidthQuota {
+                daily_gb: None,
+                monthly_gb: tenant.quota.max_bandwidth_gb_month,
+            });
+            
+            for site in &tenant.sites {
+                meter.assign_tenant(&site.id, &tenant_id);
+            }
+        }
+    }
+
+    /// Load tenant Cage limits into the runtime's resource partitions
+    pub fn sync_partitions(&self, partitions: &ResourcePartitions) {
+        for tenant in self.tenants.iter() {
+            partitions.set_limits(&tenant.id.to_string(), tenant.quota.partition_limits());
+        }
+    }
+
+    /// Load tenant database quotas into the database manager
+    pub fn sync_databases(&self, databases: &DatabaseManager) {
+        for tenant in self.tenants.iter() {
+            let max_bytes = tenant.quota.max_database_mb.map(|mb| mb as u64 * 1024 * 1024);
+            databases.set_tenant_quota(&tenant.id.to_string(), max_bytes);
+        }
+    }
+
+    /// Load tenant email quotas into the mail relay
+    pub fn sync_mail(&self, relay: &MailRelay) {
+        for tenant in self.tenants.iter() {
+            relay.set_tenant_quota(&tenant.id.to_string(), tenant.quota.max_emails_per_day);
+        }
+    }
+
+    /// Open a site's database in its storage directory, to put into its `CageConfig`
+    pub fn open_site_database(
+        &self,
+        databases: &Arc<DatabaseManager>,
+        tenant_id: Uuid,
+        site_id: &str,
+    ) -> Result<GuestDatabase> {
+        let tenant = self.tenants.get(&tenant_id).context("Tenant not found")?;
+        if !tenant.sites.iter().any(|s| s.id == site_id) {
+            anyhow::bail!("Site not found");
+        }
+        let max_bytes = tenant.quota.max_database_mb.map(|mb| mb as u64 * 1024 * 1024);
+        drop(tenant);
+        
+        databases.set_tenant_quota(&tenant_id.to_string(), max_bytes);
+        let site_dir = self.storage()?.create_site_storage(tenant_id, site_id)?;
+        databases.open(&tenant_id.to_string(), site_id, &site_dir)
+    }
+
+    /// Partition a tenant's Cage pools should be created in
+    pub fn partition_handle(&self, partitions: &Arc<ResourcePartitions>, tenant_id: Uuid) -> Result<PartitionHandle> {
+        let tenant = self.tenants.get(&tenant_id).context("Tenant not found")?;
+        partitions.set_limits(&tenant_id.to_string(), tenant.quota.partition_limits());
+        Ok(PartitionHandle::new(partitions.clone(), tenant_id.to_string()))
+    }
+
     /// Suspend tenant
     pub fn suspend_tenant(&self, tenant_id: Uuid) -> Result<()> {
         let mut tenant_entry = self.tenants.get_mut(&tenant_id)
@@ -223,38 +1107,74 @@ impl TenantManager {
         tenant.updated_at = Utc::now();
         
         info!(tenant_id = %tenant_id, "Tenant activated");
-        
+
         Ok(())
     }
 
+    /// Delete a tenant with its sites, domains and storage, returning the IDs of the removed sites
+    /// Sites of a deleted tenant stop being served once the Router drops their pools.
+    pub fn delete_tenant(&self, tenant_id: Uuid) -> Result<Vec<String>> {
+        if tenant_id == self.default_tenant_id {
+            anyhow::bail!("The default tenant cannot be deleted");
+        }
+        let (_, tenant) = self.tenants.remove(&tenant_id)
+            .context("Tenant not found")?;
+
+        let site_ids: Vec<String> = tenant.sites.into_iter().map(|s| s.id).collect();
+        for site_id in &site_ids {
+            self.domains.release_site(site_id);
+            if let Some(keyring) = &self.keyring {
+                keyring.drop_view(tenant_id, site_id);
+            }
+            if let Some(storage) = &self.storage {
+                storage.delete_site_storage(tenant_id, site_id)?;
+            }
+        }
+        if let Some(storage) = &self.storage {
+            storage.delete_tenant_storage(tenant_id)?;
+        }
+
+        warn!(tenant_id = %tenant_id, sites = site_ids.len(), "Tenant deleted");
+
+        Ok(site_ids)
+    }
+
     /// List all tenants (Root Admin only)
     pub fn list_tenants(&self) -> Vec<Tenant> {
         self.tenants.iter().map(|e| e.value().clone()).collect()
     }
 
-    /// Get tenant directory path
-    pub fn tenant_directory(&self, tenant_id: Uuid) -> String {
-        format!("/srv/tenants/{}", tenant_id)
-    }
+    /// Replace a tenant with a saved copy, such as one from a snapshot, and reclaim its sites' domains
+    /// A domain another site holds by now is dropped from the restored site rather than taken over.
+    pub fn restore_tenant(&self, mut tenant: Tenant) -> Result<()> {
+        let tenant_id = tenant.id;
+        if let Some(previous) = self.tenants.get(&tenant_id).map(|t| t.clone()) {
+            for site in previous.sites.iter().filter(|s| !tenant.sites.iter().any(|restored| restored.id == s.id)) {
+                self.domains.release_site(&site.id);
+            }
+        }
 
-    /// Get site directory path
-    pub fn site_directory(&self, tenant_id: Uuid, site_id: &str) -> String {
-        format!("/srv/tenants/{}/sites/{}", tenant_id, site_id)
-    }
+        for site in &mut tenant.sites {
+            let Some(domain) = site.domain.clone() else { continue };
+            let method = self.domains.get(&domain)
+                .filter(|claimed| claimed.site_id == site.id)
+                .map_or(VerificationMethod::default(), |claimed| claimed.method);
+            if let Err(e) = self.domains.claim(tenant_id, &site.id, &domain, method, None) {
+                warn!(tenant_id = %tenant_id, site_id = %site.id, domain = %domain, error = %e, "Domain not restored");
+                site.domain = None;
+            }
+        }
 
-    /// Create tenant directory
-    fn create_tenant_directory(&self, tenant_id: Uuid) -> Result<()> {
-        let path = self.tenant_directory(tenant_id);
-        std::fs::create_dir_all(&path)
-            .with_context(|| format!("Failed to create tenant directory: {}", path))?;
-        Ok(())
-    }
+        let site_ids: Vec<String> = tenant.sites.iter().map(|s| s.id.clone()).collect();
+        self.tenants.insert(tenant_id, tenant);
+        if let Some(storage) = &self.storage {
+            storage.create_tenant_storage(tenant_id)?;
+            for site_id in &site_ids {
+                storage.create_site_storage(tenant_id, site_id)?;
+            }
+        }
 
-    /// Create site directory
-    fn create_site_directory(&self, tenant_id: Uuid, site_id: &str) -> Result<()> {
-        let path = self.site_directory(tenant_id, site_id);
-        std::fs::create_dir_all(&path)
-            .with_context(|| format!("Failed to create site directory: {}", path))?;
+        info!(tenant_id = %tenant_id, sites = site_ids.len(), "Tenant restored");
         Ok(())
     }
 
@@ -277,16 +1197,211 @@ impl TenantManager {
             let total_cages: usize = tenant.sites.iter()
                 .map(|s| s.cage_count)
                 .sum();
+            
+            // Only as fresh as the stalest site, and unknown while any site is unscanned
+            let scanned_at = tenant.sites.iter()
+                .map(|s| s.storage_scanned_at)
+                .collect::<Option<Vec<i64>>>()
+                .and_then(|times| times.into_iter().min());
 
             TenantUsage {
                 sites_used: tenant.sites.len(),
                 sites_limit: tenant.quota.max_sites,
                 storage_used_mb: total_storage_mb,
                 storage_limit_mb: tenant.quota.max_storage_gb * 1024,
+                storage_scanned_at: scanned_at,
                 cages_running: total_cages,
+                bandwidth_used_mb: 0,
+                bandwidth_limit_mb: tenant.quota.max_bandwidth_gb_month.map(|gb| gb * 1024),
             }
         })
     }
+
+    /// A tenant's quota with its current usage
+    pub fn quota_enforcer(&self, tenant_id: Uuid) -> Result<quota::QuotaEnforcer> {
+        let quota = self.get_tenant(tenant_id).context("Tenant not found")?.quota;
+        let mut enforcer = quota::QuotaEnforcer::new(quota);
+        enforcer.update_usage(self.get_usage(tenant_id).context("Tenant not found")?);
+        Ok(enforcer)
+    }
+
+    /// Quota the pool of a site owned by a tenant should be created with
+    pub fn site_quota(self: &Arc<Self>, site_id: &str) -> Option<quota::SiteQuota> {
+        self.find_site_tenant(site_id).map(|tenant_id| quota::SiteQuota::new(self.clone(), tenant_id, site_id))
+    }
+
+    /// Record how many Cages a site runs
+    pub fn set_site_cage_count(&self, tenant_id: Uuid, site_id: &str, count: usize) {
+        let Some(mut tenant) = self.tenants.get_mut(&tenant_id) else { return };
+        if let Some(site) = tenant.sites.iter_mut().find(|site| site.id == site_id) {
+            site.cage_count = count;
+        }
+    }
+
+    /// Bring a site's storage usage up to date, walking its directory only if files changed since the last scan
+    pub fn refresh_site_storage(&self, tenant_id: Uuid, site_id: &str) -> Result<usize> {
+        // Walk the directory before taking the tenant lock
+        let usage = self.storage()?.site_usage(tenant_id, site_id)?;
+        self.set_site_storage(tenant_id, site_id, usage)?;
+        Ok(usage.mb())
+    }
+
+    fn set_site_storage(&self, tenant_id: Uuid, site_id: &str, usage: SiteUsage) -> Result<()> {
+        {
+            let mut tenant_entry = self.tenants.get_mut(&tenant_id)
+                .context("Tenant not found")?;
+            let site = tenant_entry.sites.iter_mut()
+                .find(|s| s.id == site_id)
+                .context("Site not found")?;
+            site.storage_used_mb = usage.mb();
+            site.storage_scanned_at = Some(usage.scanned_at);
+        }
+        
+        self.report_storage_level(tenant_id);
+        Ok(())
+    }
+
+    /// Walk every site of every tenant again, cached or not
+    pub fn refresh_storage(&self) {
+        let Ok(storage) = self.storage() else { return };
+        let sites: Vec<(Uuid, String)> = self.tenants.iter()
+            .flat_map(|tenant| {
+                tenant.sites.iter()
+                    .map(|site| (tenant.id, site.id.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        
+        for (tenant_id, site_id) in sites {
+            let scanned = storage.scan_site(tenant_id, &site_id)
+                .and_then(|usage| self.set_site_storage(tenant_id, &site_id, usage));
+            if let Err(e) = scanned {
+                warn!(tenant_id = %tenant_id, site_id = %site_id, error = %e, "Failed to compute site storage usage");
+            }
+        }
+    }
+
+    /// Rescan the sites whose files changed since they were last scanned
+    pub fn refresh_changed_storage(&self) {
+        let Ok(storage) = self.storage() else { return };
+        for (tenant_id, site_id) in storage.usage().take_dirty() {
+            // Sites removed since are skipped; their directories are gone
+            if self.site(tenant_id, &site_id).is_err() {
+                continue;
+            }
+            if let Err(e) = self.refresh_site_storage(tenant_id, &site_id) {
+                warn!(tenant_id = %tenant_id, site_id = %site_id, error = %e, "Failed to compute site storage usage");
+            }
+        }
+    }
+
+    /// Keep storage usage current in the background so runtime writes count against quotas
+    /// Every site is rescanned at a jittered interval, and changed sites shortly after file events.
+    pub fn start_storage_scans(self: &Arc<Self>, config: &StorageConfig) {
+        let Some(storage) = self.storage.clone() else { return };
+        let watcher = config.watch
+            .then(|| usage::watch(storage.tenants_path(), storage.usage().clone()))
+            .flatten();
+        let (interval, jitter) = (config.scan_interval(), config.scan_jitter_percent);
+        let manager = self.clone();
+        
+        tokio::spawn(async move {
+            // Dropping the watcher would stop file events
+            let _watcher = watcher;
+            let mut next_full = tokio::time::Instant::now();
+            
+            loop {
+                let full = tokio::select! {
+                    _ = tokio::time::sleep_until(next_full) => true,
+                    _ = storage.usage().changed().notified() => false,
+                };
+                if full {
+                    next_full = tokio::time::Instant::now() + usage::jittered(interval, jitter);
+                } else {
+                    // Let a burst of writes settle into one rescan
+                    tokio::time::sleep(CHANGE_SETTLE).await;
+                }
+                
+                let manager = manager.clone();
+                let scan = tokio::task::spawn_blocking(move || {
+                    if full {
+                        manager.refresh_storage();
+                    } else {
+                        manager.refresh_changed_storage();
+                    }
+                });
+                if let Err(e) = scan.await {
+                    error!(error = %e, "Storage scan task failed");
+                }
+            }
+        });
+        
+        info!(interval_secs = interval.as_secs(), jitter_percent = jitter, "Storage usage scans started");
+    }
+
+    /// Storage quota threshold a tenant has reached, logging when it changes
+    fn report_storage_level(&self, tenant_id: Uuid) -> StorageLevel {
+        let level = self.storage_level(tenant_id);
+        let previous = self.storage_levels.insert(tenant_id, level).unwrap_or_default();
+        
+        if level != previous {
+            match level {
+                StorageLevel::Exceeded => error!(tenant_id = %tenant_id, "Storage quota reached (100%), refusing deploys and writes"),
+                StorageLevel::Critical => warn!(tenant_id = %tenant_id, "Storage usage above 90% of quota"),
+                StorageLevel::Warning => warn!(tenant_id = %tenant_id, "Storage usage above 80% of quota"),
+                StorageLevel::Ok => info!(tenant_id = %tenant_id, "Storage usage back below 80% of quota"),
+            }
+        }
+        
+        level
+    }
+
+    /// Storage quota threshold a tenant has reached, from the last computed usage
+    pub fn storage_level(&self, tenant_id: Uuid) -> StorageLevel {
+        self.get_usage(tenant_id)
+            .map(|usage| StorageLevel::from_usage(usage.storage_used_mb, usage.storage_limit_mb))
+            .unwrap_or_default()
+    }
+
+    /// Whether a tenant's sites may still write to their storage
+    pub fn storage_writable(&self, tenant_id: Uuid) -> bool {
+        self.storage_level(tenant_id).allows_writes()
+    }
+
+    /// Store a site's new module, rejecting the deploy if it would exceed the tenant's storage quota
+    /// The site's file links to the shared module store, so identical modules take space once on disk.
+    pub fn deploy_site_module(&self, tenant_id: Uuid, site_id: &str, module: &[u8]) -> Result<PathBuf> {
+        let storage = self.storage()?;
+        self.refresh_site_storage(tenant_id, site_id)?;
+        
+        let module_path = storage.site_module_path(tenant_id, site_id);
+        
+        // Only the growth over the module being replaced counts against the quota
+        let replaced = std::fs::metadata(&module_path).map_or(0, |m| m.len());
+        let growth_mb = (module.len() as u64).saturating_sub(replaced).div_ceil(1024 * 1024) as usize;
+        
+        self.quota_enforcer(tenant_id)?
+            .can_allocate_storage(growth_mb)
+            .with_context(|| format!("Deploy to site {} rejected", site_id))?;
+        
+        storage.create_site_storage(tenant_id, site_id)?;
+        let stored = storage.modules().put(module)?;
+        storage.modules().link(&stored, &module_path)?;
+        storage.usage().invalidate(tenant_id, site_id);
+        
+        let used_mb = self.refresh_site_storage(tenant_id, site_id)?;
+        info!(tenant_id = %tenant_id, site_id = %site_id, bytes = module.len(), used_mb = used_mb, "Site module deployed");
+        
+        Ok(module_path)
+    }
+
+    /// Usage statistics including this month's traffic from a bandwidth meter
+    pub fn get_usage_with_bandwidth(&self, tenant_id: Uuid, meter: &bandwidth::BandwidthMeter) -> Option<TenantUsage> {
+        let mut usage = self.get_usage(tenant_id)?;
+        let report = meter.tenant_report(&tenant_id.to_string());
+        usage.bandwidth_used_mb = report.this_month.total() / (1024 * 1024);
+        Some(usage)
+    }
 }
 
 impl Default for TenantManager {
@@ -295,6 +1410,35 @@ impl Default for TenantManager {
     }
 }
 
+/// What a site content sync did
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContentSync {
+    pub fetched: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    
+    /// Size of the fetched files
+    pub bytes: u64,
+}
+
+/// A key that names a file below the site directory: no `..`, no root, no empty segments
+fn is_plain_relative(relative: &str) -> bool {
+    !relative.starts_with('/')
+        && relative.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+/// Encryption at rest of one tenant
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantEncryption {
+    /// Whether the node has a keyring to encrypt with
+    pub available: bool,
+    pub enabled: bool,
+    pub key_version: Option<u32>,
+    
+    /// What wraps tenant keys: the node key file or a Vault Transit key
+    pub master: Option<String>,
+}
+
 /// Tenant usage statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TenantUsage {
@@ -302,7 +1446,19 @@ pub struct TenantUsage {
     pub sites_limit: usize,
     pub storage_used_mb: usize,
     pub storage_limit_mb: usize,
+    
+    /// Unix time of the oldest site scan `storage_used_mb` adds up, if every site was scanned
+    #[serde(default)]
+    pub storage_scanned_at: Option<i64>,
+    
     pub cages_running: usize,
+    
+    /// Traffic this calendar month
+    #[serde(default)]
+    pub bandwidth_used_mb: u64,
+    
+    #[serde(default)]
+    pub bandwidth_limit_mb: Option<u64>,
 }
 
 #[cfg(test)]
@@ -330,6 +1486,208 @@ mod tests {
         assert_eq!(tenant.status, TenantStatus::Active);
     }
 
+    #[test]
+    fn test_site_ai_policy_inherits_tenant() {
+        let manager = TenantManager::new();
+        let tenant_id = manager.default_tenant_id();
+        let site_id = manager.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        
+        manager.update_ai_policy(tenant_id, AiPolicy {
+            sample_rate: Some(1.0),
+            ..Default::default()
+        }).unwrap();
+        manager.update_site_ai_policy(tenant_id, &site_id, AiPolicy {
+            anomaly_threshold: Some(0.6),
+            ..Default::default()
+        }).unwrap();
+        
+        let policies = manager.effective_ai_policies(tenant_id);
+        assert_eq!(policies[0].1.sample_rate, Some(1.0));
+        assert_eq!(policies[0].1.anomaly_threshold, Some(0.6));
+    }
+
+    #[test]
+    fn test_bandwidth_counts_against_tenant() {
+        let manager = TenantManager::new();
+        let tenant_id = manager.default_tenant_id();
+        let site_id = manager.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        manager.update_quota(tenant_id, ResourceQuota {
+            max_bandwidth_gb_month: Some(1),
+            ..Default::default()
+        }).unwrap();
+        
+        let meter = bandwidth::BandwidthMeter::new(bandwidth::BandwidthConfig {
+            state_path: String::new(),
+            ..Default::default()
+        }).unwrap();
+        manager.sync_bandwidth(&meter);
+        meter.record(&site_id, 0, 3 * 1024 * 1024);
+        
+        let usage = manager.get_usage_with_bandwidth(tenant_id, &meter).unwrap();
+        assert_eq!(usage.bandwidth_used_mb, 3);
+        assert_eq!(usage.bandwidth_limit_mb, Some(1024));
+    }
+
+    #[test]
+    fn test_deploy_rejected_over_storage_quota() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let storage = Arc::new(StorageManager::new(temp.path()).unwrap());
+        let manager = TenantManager::new().with_storage(storage.clone());
+        let tenant_id = manager.default_tenant_id();
+        let site_id = manager.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        
+        let path = manager.deploy_site_module(tenant_id, &site_id, b"\0asm").unwrap();
+        assert!(path.exists());
+        assert!(manager.storage_writable(tenant_id));
+        assert!(manager.get_usage(tenant_id).unwrap().storage_scanned_at.is_some());
+        
+        // A tenant without storage quota can't grow its sites
+        manager.update_quota(tenant_id, ResourceQuota {
+            max_storage_gb: 0,
+            ..Default::default()
+        }).unwrap();
+        let result = manager.deploy_site_module(tenant_id, &site_id, &vec![0u8; 2 * 1024 * 1024]);
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"\0asm");
+        assert!(!manager.storage_writable(tenant_id));
+        
+        // Another site deploying the same module shares the stored copy
+        let shop_id = manager.add_site(tenant_id, "Shop".to_string(), None).unwrap();
+        assert_eq!(manager.get_usage(tenant_id).unwrap().storage_scanned_at, None);
+        manager.update_quota(tenant_id, ResourceQuota::default()).unwrap();
+        let shop_path = manager.deploy_site_module(tenant_id, &shop_id, b"\0asm").unwrap();
+        let stored = storage.modules().path(&crate::storage::modules::ModuleHash::of(b"\0asm"));
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(std::fs::metadata(&stored).unwrap().ino(), std::fs::metadata(&shop_path).unwrap().ino());
+        assert_eq!(storage.modules().collect_garbage().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_encryption_reseals_secrets() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let storage = Arc::new(StorageManager::new(temp.path().join("storage")).unwrap());
+        let config = crate::storage::encryption::EncryptionConfig {
+            key_file: temp.path().join("encryption.key").to_string_lossy().into_owned(),
+            plaintext_dir: temp.path().join("plain").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let keyring = Arc::new(Keyring::open(&config, storage.clone()).await.unwrap());
+        let manager = TenantManager::new()
+            .with_storage(storage)
+            .with_keyring(keyring)
+            .with_secret_key(Arc::new(SecretKey::generate().unwrap()));
+        let tenant_id = manager.default_tenant_id();
+        let site_id = manager.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        manager.set_site_env(tenant_id, &site_id, "API_KEY", "s3cr3t", true).unwrap();
+        let bucket = ArtifactSourceRequest {
+            endpoint: "http://minio:9000".to_string(),
+            region: String::new(),
+            bucket: "builds".to_string(),
+            prefix: String::new(),
+            path_style: true,
+            access_key_id: "AKID".to_string(),
+            secret_access_key: "bucket-s3cr3t".to_string(),
+        };
+        manager.set_artifact_source(tenant_id, Some(&bucket)).unwrap();
+        let has_secret = |manager: &TenantManager| {
+            manager.artifact_source(tenant_id).unwrap().secret_access_key == "bucket-s3cr3t"
+                && manager.cage_env(tenant_id, &site_id).unwrap().as_pairs()
+                    .contains(&("API_KEY".to_string(), "s3cr3t".to_string()))
+        };
+        
+        assert!(manager.encrypt_tenant(tenant_id, true).await.is_err());
+        manager.encrypt_tenant(tenant_id, false).await.unwrap();
+        assert_eq!(manager.tenant_encryption(tenant_id).key_version, Some(1));
+        assert!(has_secret(&manager));
+        assert!(manager.encrypt_tenant(tenant_id, false).await.is_err());
+        
+        manager.encrypt_tenant(tenant_id, true).await.unwrap();
+        assert_eq!(manager.tenant_encryption(tenant_id).key_version, Some(2));
+        assert!(has_secret(&manager));
+    }
+
+    #[test]
+    fn test_site_env_reaches_cage_config() {
+        let manager = TenantManager::new();
+        let tenant_id = manager.default_tenant_id();
+        let site_id = manager.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        
+        manager.set_site_env(tenant_id, &site_id, "MODE", "production", false).unwrap();
+        // Secrets need a master key
+        assert!(manager.set_site_env(tenant_id, &site_id, "API_KEY", "s3cr3t", true).is_err());
+        
+        let manager = manager.with_secret_key(Arc::new(SecretKey::generate().unwrap()));
+        manager.set_site_env(tenant_id, &site_id, "API_KEY", "s3cr3t", true).unwrap();
+        assert_eq!(manager.find_site_tenant(&site_id), Some(tenant_id));
+        
+        let listing = manager.site_env(tenant_id, &site_id).unwrap();
+        assert_eq!(listing.len(), 2);
+        assert!(listing.iter().all(|entry| entry.value.as_deref() != Some("s3cr3t")));
+        
+        let env = manager.cage_env(tenant_id, &site_id).unwrap();
+        assert!(env.as_pairs().contains(&("API_KEY".to_string(), "s3cr3t".to_string())));
+        
+        assert!(manager.unset_site_env(tenant_id, &site_id, "MODE").unwrap());
+        assert_eq!(manager.cage_env(tenant_id, &site_id).unwrap().names(), vec!["API_KEY"]);
+    }
+
+    #[test]
+    fn test_site_database_lives_in_site_storage() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let storage = Arc::new(StorageManager::new(temp.path()).unwrap());
+        let databases = Arc::new(DatabaseManager::new(Default::default()).unwrap());
+        let manager = TenantManager::new().with_storage(storage.clone());
+        let tenant_id = manager.default_tenant_id();
+        let site_id = manager.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        
+        let db = manager.open_site_database(&databases, tenant_id, &site_id).unwrap();
+        db.exec("CREATE TABLE t (v TEXT)", &[]).unwrap();
+        assert!(storage.site_dir(tenant_id, &site_id).join("site.db").exists());
+        assert!(manager.open_site_database(&databases, tenant_id, "missing").is_err());
+        
+        // Removing the site removes its files, through the same layout
+        drop(db);
+        manager.remove_site(tenant_id, &site_id).unwrap();
+        assert!(!storage.site_dir(tenant_id, &site_id).exists());
+        assert!(storage.tenant_dir(tenant_id).exists());
+    }
+
+    #[test]
+    fn test_delete_tenant() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let storage = Arc::new(StorageManager::new(temp.path()).unwrap());
+        let manager = TenantManager::new().with_storage(storage.clone());
+        let tenant_id = manager.create_tenant("Acme".to_string(), "ops@acme.test".to_string(), ResourceQuota::default()).unwrap();
+        let site_id = manager.add_site(tenant_id, "Blog".to_string(), Some("blog.acme.test".to_string())).unwrap();
+
+        assert_eq!(manager.delete_tenant(tenant_id).unwrap(), vec![site_id.clone()]);
+        assert!(manager.get_tenant(tenant_id).is_none());
+        assert!(manager.find_site_tenant(&site_id).is_none());
+        assert!(manager.domains().get("blog.acme.test").is_none());
+        assert!(!storage.tenant_dir(tenant_id).exists());
+
+        assert!(manager.delete_tenant(tenant_id).is_err());
+        assert!(manager.delete_tenant(manager.default_tenant_id()).is_err());
+    }
+
+    #[test]
+    fn test_quota_partition_limits() {
+        let manager = TenantManager::new();
+        let tenant_id = manager.default_tenant_id();
+        manager.update_quota(tenant_id, ResourceQuota {
+            max_memory_per_cage_mb: 64,
+            max_total_memory_mb: Some(128),
+            ..Default::default()
+        }).unwrap();
+        
+        let partitions = Arc::new(ResourcePartitions::new());
+        let handle = manager.partition_handle(&partitions, tenant_id).unwrap();
+        
+        assert!(handle.can_reserve(1, 128 * 1024 * 1024).is_err());
+        assert!(handle.can_reserve(2, 64 * 1024 * 1024).is_ok());
+        assert!(handle.can_reserve(3, 64 * 1024 * 1024).is_err());
+    }
+
     #[test]
     fn test_site_quota() {
         let manager = TenantManager::new();
@@ -353,4 +1711,47 @@ mod tests {
         let result = manager.add_site(tenant_id, "Site 3".to_string(), None);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_site_domain_is_unique() {
+        let manager = TenantManager::new();
+        let tenant_id = manager.default_tenant_id();
+        let blog = manager.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        let shop = manager.add_site(tenant_id, "Shop".to_string(), None).unwrap();
+        
+        manager.set_site_domain(tenant_id, &blog, Some("Blog.Example.com.".to_string())).unwrap();
+        let tenant = manager.get_tenant(tenant_id).unwrap();
+        assert_eq!(tenant.sites[0].domain.as_deref(), Some("blog.example.com"));
+        
+        assert!(manager.set_site_domain(tenant_id, &shop, Some("blog.example.com".to_string())).is_err());
+        assert!(manager.set_site_domain(tenant_id, &shop, Some("not a domain".to_string())).is_err());
+        
+        // Detaching frees the domain for another site
+        manager.set_site_domain(tenant_id, &blog, None).unwrap();
+        manager.set_site_domain(tenant_id, &shop, Some("blog.example.com".to_string())).unwrap();
+        assert_eq!(manager.domains().get("blog.example.com").unwrap().site_id, shop);
+        
+        // Removing the site releases its domains
+        manager.remove_site(tenant_id, &shop).unwrap();
+        assert!(manager.domains().get("blog.example.com").is_none());
+    }
+
+    #[test]
+    fn test_site_can_require_signed_modules() {
+        let manager = TenantManager::new();
+        let tenant_id = manager.default_tenant_id();
+        let site_id = manager.add_site(tenant_id, "Blog".to_string(), None).unwrap();
+        let module = b"\0asm\x01\0\0\0";
+        assert_eq!(manager.check_module_signature(&site_id, module, None), SignatureCheck::Unsigned);
+        
+        // Requiring signatures needs a key to check them against
+        assert!(manager.set_site_require_signature(tenant_id, &site_id, true).is_err());
+        manager.add_signing_key(tenant_id, "ci", &"ab".repeat(32)).unwrap();
+        manager.set_site_require_signature(tenant_id, &site_id, true).unwrap();
+        assert_eq!(manager.check_module_signature(&site_id, module, None), SignatureCheck::Missing);
+        assert_eq!(manager.check_module_signature(&site_id, module, Some(&"00".repeat(64))), SignatureCheck::Invalid);
+        
+        manager.remove_signing_key(tenant_id, "ci").unwrap();
+        assert!(manager.remove_signing_key(tenant_id, "ci").is_err());
+    }
 }
@@ -0,0 +1,660 @@
+// OpenID Connect Single Sign-On
+// Dashboard login through an identity provider, whose claims map to tenants and roles
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use parking_lot::{Mutex, RwLock};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::auth::Role;
+use super::TenantManager;
+
+/// How long a started login may take to come back from the provider
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Clock skew tolerated on token timestamps
+const LEEWAY_SECS: i64 = 60;
+
+/// How often the provider's signing keys are refetched
+const KEY_REFRESH: Duration = Duration::from_secs(3600);
+
+/// Largest discovery document, key set or token response read
+const MAX_RESPONSE: usize = 256 * 1024;
+
+/// `[oidc]`: sign in to the dashboard through an OpenID Connect provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OidcConfig {
+    pub enabled: bool,
+
+    /// Issuer URL; `<issuer>/.well-known/openid-configuration` must be served
+    pub issuer: String,
+
+    pub client_id: String,
+    pub client_secret: String,
+
+    /// The dashboard's `/auth/callback` URL, as registered with the provider
+    pub redirect_url: String,
+
+    pub scopes: Vec<String>,
+
+    /// Shown on the login button
+    pub provider_name: String,
+
+    /// ID token claim listing the user's groups
+    pub groups_claim: String,
+
+    /// How long a dashboard login lasts
+    pub session_ttl_secs: u64,
+
+    /// Keep the token form on the login screen, for the admin token and `pear token` tokens
+    pub local_login: bool,
+
+    /// Also accept the provider's ID tokens as API bearer tokens
+    pub accept_id_tokens: bool,
+
+    /// Who gets which role; the first matching entry wins and users matching none are refused
+    pub mappings: Vec<ClaimMapping>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            redirect_url: String::new(),
+            scopes: vec!["openid".to_string(), "email".to_string(), "profile".to_string()],
+            provider_name: "SSO".to_string(),
+            groups_claim: "groups".to_string(),
+            session_ttl_secs: 8 * 3600,
+            local_login: true,
+            accept_id_tokens: false,
+            mappings: Vec::new(),
+        }
+    }
+}
+
+impl OidcConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let local = self.issuer.starts_with("http://localhost") || self.issuer.starts_with("http://127.0.0.1");
+        if !self.issuer.starts_with("https://") && !local {
+            bail!("issuer must be an https:// URL");
+        }
+        if self.client_id.is_empty() {
+            bail!("client_id is required");
+        }
+        if self.redirect_url.is_empty() {
+            bail!("redirect_url is required");
+        }
+        if !self.scopes.iter().any(|scope| scope == "openid") {
+            bail!("scopes must include openid");
+        }
+        if self.session_ttl_secs == 0 {
+            bail!("session_ttl_secs must be at least 1");
+        }
+        if self.mappings.is_empty() {
+            bail!("at least one [[oidc.mappings]] entry is needed, or nobody can sign in");
+        }
+        for (i, mapping) in self.mappings.iter().enumerate() {
+            mapping.validate().with_context(|| format!("mappings[{}]", i))?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps users with a group, an email address or an email domain to a role
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClaimMapping {
+    pub group: Option<String>,
+
+    /// Only matched when the provider says the address is verified
+    pub email: Option<String>,
+    pub email_domain: Option<String>,
+
+    /// `root-admin`, `tenant-admin` or a custom role
+    pub role: String,
+
+    /// Tenant name or ID (none = node-wide)
+    pub tenant: Option<String>,
+
+    /// Limit to these sites of the tenant
+    pub sites: Vec<String>,
+
+    pub read_only: bool,
+}
+
+impl ClaimMapping {
+    fn validate(&self) -> Result<()> {
+        let matchers = [&self.group, &self.email, &self.email_domain].iter().filter(|m| m.is_some()).count();
+        if matchers != 1 {
+            bail!("set exactly one of group, email and email_domain");
+        }
+        if self.role.is_empty() {
+            bail!("role is required");
+        }
+        match (Role::parse(&self.role), &self.tenant) {
+            (Role::RootAdmin, Some(_)) => bail!("root-admin can't be limited to a tenant"),
+            (Role::TenantAdmin, None) => bail!("tenant-admin needs a tenant"),
+            _ => {}
+        }
+        if self.tenant.is_none() && !self.sites.is_empty() {
+            bail!("sites need a tenant");
+        }
+        Ok(())
+    }
+
+    fn matches(&self, identity: &Identity) -> bool {
+        let verified_email = identity.email.as_deref().filter(|_| identity.email_verified);
+        if let Some(group) = &self.group {
+            return identity.groups.iter().any(|g| g == group);
+        }
+        if let Some(email) = &self.email {
+            return verified_email.is_some_and(|address| address.eq_ignore_ascii_case(email));
+        }
+        if let Some(domain) = &self.email_domain {
+            return verified_email
+                .and_then(|address| address.rsplit_once('@'))
+                .is_some_and(|(_, host)| host.eq_ignore_ascii_case(domain));
+        }
+        false
+    }
+}
+
+/// A user as the provider vouched for them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub groups: Vec<String>,
+
+    /// When the ID token it came from expires (Unix timestamp)
+    pub expires_at: i64,
+}
+
+impl Identity {
+    /// Email address if any, else the subject, for logs and the audit trail
+    pub fn display_name(&self) -> &str {
+        self.email.as_deref().unwrap_or(&self.subject)
+    }
+}
+
+/// What a signed-in user may do, from the first mapping they match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    pub role: Role,
+    pub tenant_id: Option<Uuid>,
+    pub sites: Vec<String>,
+    pub read_only: bool,
+}
+
+/// Endpoints from the provider's discovery document
+#[derive(Debug, Clone, Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// A public key from the provider's key set
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    n: String,
+    e: String,
+    crv: String,
+    x: String,
+    y: String,
+}
+
+#[derive(Deserialize)]
+struct KeySet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// A login waiting for the provider to redirect back
+struct PendingLogin {
+    nonce: String,
+    verifier: String,
+    started: Instant,
+}
+
+/// Talks to the provider and checks what it signs
+pub struct OidcProvider {
+    config: OidcConfig,
+    tenants: Arc<TenantManager>,
+    metadata: RwLock<Option<Metadata>>,
+    keys: RwLock<Vec<Jwk>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcConfig, tenants: Arc<TenantManager>) -> Self {
+        Self {
+            config,
+            tenants,
+            metadata: RwLock::new(None),
+            keys: RwLock::new(Vec::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// Fetch the provider's endpoints and keys, then refresh the keys periodically
+    pub fn start(self: &Arc<Self>) {
+        let provider = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(KEY_REFRESH);
+            loop {
+                interval.tick().await;
+                match provider.discover().await {
+                    Ok(()) => info!(issuer = %provider.config.issuer, "OpenID Connect provider discovered"),
+                    Err(e) => warn!(issuer = %provider.config.issuer, error = %format!("{:#}", e), "OpenID Connect discovery failed"),
+                }
+            }
+        });
+    }
+
+    /// Read the discovery document and the key set it points at
+    pub async fn discover(&self) -> Result<()> {
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+        let metadata: Metadata = fetch_json(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != self.config.issuer.trim_end_matches('/') {
+            bail!("Provider reports issuer {} instead of {}", metadata.issuer, self.config.issuer);
+        }
+        let keys: KeySet = fetch_json(&metadata.jwks_uri).await?;
+        *self.keys.write() = keys.keys;
+        *self.metadata.write() = Some(metadata);
+        Ok(())
+    }
+
+    async fn metadata(&self) -> Result<Metadata> {
+        if self.metadata.read().is_none() {
+            self.discover().await?;
+        }
+        self.metadata.read().clone().context("Provider not discovered")
+    }
+
+    /// Start a login, returning the provider URL to send the browser to
+    pub async fn login_url(&self) -> Result<String> {
+        let metadata = self.metadata().await?;
+        let state = random_token();
+        let nonce = random_token();
+        let verifier = random_token();
+        let challenge = URL_SAFE_NO_PAD.encode(ring::digest::digest(&ring::digest::SHA256, verifier.as_bytes()));
+
+        let url = format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+            metadata.authorization_endpoint,
+            if metadata.authorization_endpoint.contains('?') { '&' } else { '?' },
+            form_encode(&self.config.client_id),
+            form_encode(&self.config.redirect_url),
+            form_encode(&self.config.scopes.join(" ")),
+            state,
+            nonce,
+            challenge,
+        );
+
+        let mut pending = self.pending.lock();
+        pending.retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+        pending.insert(state, PendingLogin { nonce, verifier, started: Instant::now() });
+        Ok(url)
+    }
+
+    /// Finish a login the provider redirected back with, returning who signed in
+    pub async fn complete(&self, code: &str, state: &str) -> Result<Identity> {
+        let login = self.pending.lock().remove(state)
+            .filter(|login| login.started.elapsed() < LOGIN_TIMEOUT)
+            .context("Unknown or expired login; start again")?;
+        let metadata = self.metadata().await?;
+
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("client_secret", self.config.client_secret.as_str()),
+            ("code_verifier", login.verifier.as_str()),
+        ];
+        let body = form.iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| format!("{}={}", name, form_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let (status, response) = crate::ai::alerts::request_for_response(
+            hyper::Method::POST,
+            &metadata.token_endpoint,
+            Some("application/x-www-form-urlencoded"),
+            body.into_bytes(),
+            &[("accept", "application/json".to_string())],
+            MAX_RESPONSE,
+        ).await?;
+        if !status.is_success() {
+            bail!("Token endpoint returned {}: {}", status, String::from_utf8_lossy(&response));
+        }
+        let tokens: TokenResponse = serde_json::from_slice(&response)
+            .context("Token endpoint sent no ID token")?;
+
+        match self.verify(&tokens.id_token, Some(&login.nonce)) {
+            Err(e) if e.is::<UnknownKey>() => {
+                // The provider may have rotated its keys since they were fetched
+                self.discover().await?;
+                self.verify(&tokens.id_token, Some(&login.nonce))
+            }
+            result => result,
+        }
+    }
+
+    /// Check an ID token's signature, issuer, audience, lifetime and (for logins) nonce
+    pub fn verify(&self, id_token: &str, nonce: Option<&str>) -> Result<Identity> {
+        let keys = self.keys.read();
+        verify_id_token(id_token, &keys, &self.config, nonce, chrono::Utc::now().timestamp())
+    }
+
+    /// The role and tenant a user gets
+    pub fn grant(&self, identity: &Identity) -> Result<Grant> {
+        let mapping = self.config.mappings.iter()
+            .find(|mapping| mapping.matches(identity))
+            .with_context(|| format!("{} is not allowed to sign in", identity.display_name()))?;
+        let tenant_id = mapping.tenant.as_deref()
+            .map(|tenant| {
+                tenant.parse().ok()
+                    .filter(|id| self.tenants.get_tenant(*id).is_some())
+                    .or_else(|| self.tenants.list_tenants().into_iter().find(|t| t.name == tenant).map(|t| t.id))
+                    .with_context(|| format!("Tenant '{}' in [oidc] mappings does not exist", tenant))
+            })
+            .transpose()?;
+        Ok(Grant {
+            role: Role::parse(&mapping.role),
+            tenant_id,
+            sites: mapping.sites.clone(),
+            read_only: mapping.read_only,
+        })
+    }
+}
+
+/// The token names a key the provider's key set doesn't have (yet)
+#[derive(Debug)]
+struct UnknownKey;
+
+impl std::fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ID token signed with an unknown key")
+    }
+}
+
+impl std::error::Error for UnknownKey {}
+
+fn verify_id_token(id_token: &str, keys: &[Jwk], config: &OidcConfig, nonce: Option<&str>, now: i64) -> Result<Identity> {
+    let mut parts = id_token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        bail!("Malformed ID token");
+    };
+    let signed = &id_token[..header.len() + 1 + payload.len()];
+    let header: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header)?)
+        .context("Malformed ID token header")?;
+    let signature = URL_SAFE_NO_PAD.decode(signature)?;
+
+    let kid = header["kid"].as_str();
+    let alg = header["alg"].as_str().unwrap_or_default();
+    let kty = match alg {
+        "RS256" => "RSA",
+        "ES256" => "EC",
+        other => bail!("Unsupported ID token algorithm '{}'", other),
+    };
+    let key = keys.iter()
+        .find(|key| key.kty == kty && (kid.is_none() || key.kid.as_deref() == kid))
+        .ok_or(UnknownKey)?;
+    let verified = match alg {
+        "RS256" => ring::signature::RsaPublicKeyComponents {
+            n: URL_SAFE_NO_PAD.decode(&key.n)?,
+            e: URL_SAFE_NO_PAD.decode(&key.e)?,
+        }.verify(&ring::signature::RSA_PKCS1_2048_8192_SHA256, signed.as_bytes(), &signature),
+        _ => {
+            if key.crv != "P-256" {
+                bail!("Unsupported EC curve '{}'", key.crv);
+            }
+            let mut point = vec![0x04];
+            point.extend(URL_SAFE_NO_PAD.decode(&key.x)?);
+            point.extend(URL_SAFE_NO_PAD.decode(&key.y)?);
+            ring::signature::UnparsedPublicKey::new(&ring::signature::ECDSA_P256_SHA256_FIXED, point)
+                .verify(signed.as_bytes(), &signature)
+        }
+    };
+    if verified.is_err() {
+        bail!("ID token signature is invalid");
+    }
+
+    let claims: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)
+        .context("Malformed ID token claims")?;
+    let issuer = claims["iss"].as_str().unwrap_or_default();
+    if issuer.trim_end_matches('/') != config.issuer.trim_end_matches('/') {
+        bail!("ID token issued by {} instead of {}", issuer, config.issuer);
+    }
+    let audience_ok = match &claims["aud"] {
+        serde_json::Value::String(aud) => *aud == config.client_id,
+        serde_json::Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(config.client_id.as_str())),
+        _ => false,
+    };
+    if !audience_ok {
+        bail!("ID token is meant for another client");
+    }
+    let expires_at = claims["exp"].as_i64().unwrap_or_default();
+    if expires_at + LEEWAY_SECS < now {
+        bail!("ID token has expired");
+    }
+    if claims["nbf"].as_i64().is_some_and(|nbf| nbf - LEEWAY_SECS > now) {
+        bail!("ID token is not valid yet");
+    }
+    if let Some(nonce) = nonce {
+        if claims["nonce"].as_str() != Some(nonce) {
+            bail!("ID token nonce does not match the login");
+        }
+    }
+
+    let subject = claims["sub"].as_str().context("ID token has no subject")?.to_string();
+    let groups = match &claims[config.groups_claim.as_str()] {
+        serde_json::Value::Array(groups) => groups.iter().filter_map(|g| g.as_str()).map(str::to_string).collect(),
+        serde_json::Value::String(group) => vec![group.clone()],
+        _ => Vec::new(),
+    };
+    Ok(Identity {
+        subject,
+        email: claims["email"].as_str().map(str::to_string),
+        email_verified: claims["email_verified"].as_bool().unwrap_or(false)
+            || claims["email_verified"].as_str() == Some("true"),
+        groups,
+        expires_at,
+    })
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T> {
+    let (status, body) = crate::ai::alerts::get_for_response(url, MAX_RESPONSE).await
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    if !status.is_success() {
+        bail!("{} returned {}", url, status);
+    }
+    serde_json::from_slice(&body).with_context(|| format!("Failed to parse {}", url))
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Percent-encode a query or form value
+fn form_encode(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    fn config() -> OidcConfig {
+        OidcConfig {
+            enabled: true,
+            issuer: "https://idp.example.com/realms/pear".to_string(),
+            client_id: "pear-dashboard".to_string(),
+            redirect_url: "https://pear.example.com:9000/auth/callback".to_string(),
+            mappings: vec![
+                ClaimMapping { group: Some("pear-admins".to_string()), role: "root-admin".to_string(), ..Default::default() },
+                ClaimMapping {
+                    email_domain: Some("acme.com".to_string()),
+                    role: "tenant-admin".to_string(),
+                    tenant: Some("acme".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn sign(key: &EcdsaKeyPair, claims: serde_json::Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"ES256","kid":"k1"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signed = format!("{}.{}", header, payload);
+        let signature = key.sign(&ring::rand::SystemRandom::new(), signed.as_bytes()).unwrap();
+        format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(OidcConfig::default().validate().is_ok());
+        assert!(config().validate().is_ok());
+
+        let mut insecure = config();
+        insecure.issuer = "http://idp.example.com".to_string();
+        assert!(insecure.validate().is_err());
+
+        let mut ambiguous = config();
+        ambiguous.mappings[0].email = Some("ops@example.com".to_string());
+        assert!(ambiguous.validate().is_err());
+
+        let mut tenantless = config();
+        tenantless.mappings[1].tenant = None;
+        assert!(tenantless.validate().is_err());
+    }
+
+    #[test]
+    fn test_sessions() {
+        use super::super::auth::AuthManager;
+
+        let provider = Arc::new(OidcProvider::new(config(), Arc::new(TenantManager::new())));
+        let auth = AuthManager::with_root_token("").with_oidc(provider);
+        assert!(auth.validate_token("").is_err());
+
+        let admin = Identity {
+            subject: "u-1".to_string(),
+            email: Some("ops@example.com".to_string()),
+            email_verified: true,
+            groups: vec!["pear-admins".to_string()],
+            expires_at: 0,
+        };
+        let secret = auth.start_session(&admin).unwrap();
+        let claims = auth.validate_token(&secret).unwrap();
+        assert_eq!(claims.role, Role::RootAdmin);
+        assert_eq!(claims.subject.as_deref(), Some("ops@example.com"));
+
+        assert!(auth.end_session(&secret));
+        assert!(auth.validate_token(&secret).is_err());
+
+        // Users matching no mapping, or a tenant that doesn't exist, can't sign in
+        let stranger = Identity { groups: Vec::new(), email: None, ..admin.clone() };
+        assert!(auth.start_session(&stranger).is_err());
+        let acme = Identity { groups: Vec::new(), email: Some("dev@acme.com".to_string()), ..admin };
+        assert!(auth.start_session(&acme).is_err());
+    }
+
+    #[test]
+    fn test_id_token_verification_and_mapping() {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+        let point = key.public_key().as_ref();
+        let jwk = Jwk {
+            kty: "EC".to_string(),
+            kid: Some("k1".to_string()),
+            crv: "P-256".to_string(),
+            x: URL_SAFE_NO_PAD.encode(&point[1..33]),
+            y: URL_SAFE_NO_PAD.encode(&point[33..]),
+            ..Default::default()
+        };
+        let keys = [jwk.clone()];
+        let config = config();
+        let now = 1_800_000_000;
+        let claims = serde_json::json!({
+            "iss": "https://idp.example.com/realms/pear",
+            "aud": "pear-dashboard",
+            "sub": "u-1",
+            "exp": now + 300,
+            "nonce": "n-1",
+            "email": "dev@acme.com",
+            "email_verified": true,
+            "groups": ["developers"],
+        });
+
+        let identity = verify_id_token(&sign(&key, claims.clone()), &keys, &config, Some("n-1"), now).unwrap();
+        assert_eq!(identity.subject, "u-1");
+        assert_eq!(identity.groups, ["developers"]);
+        assert!(config.mappings[1].matches(&identity));
+        assert!(!config.mappings[0].matches(&identity));
+
+        // Wrong nonce, audience, expiry and tampering are refused
+        assert!(verify_id_token(&sign(&key, claims.clone()), &keys, &config, Some("n-2"), now).is_err());
+        let mut other = claims.clone();
+        other["aud"] = "someone-else".into();
+        assert!(verify_id_token(&sign(&key, other), &keys, &config, None, now).is_err());
+        assert!(verify_id_token(&sign(&key, claims.clone()), &keys, &config, None, now + 3600).is_err());
+        let token = sign(&key, claims.clone());
+        let (signed, signature) = token.rsplit_once('.').unwrap();
+        let (header, _) = signed.split_once('.').unwrap();
+        let mut forged = claims.clone();
+        forged["groups"] = serde_json::json!(["pear-admins"]);
+        let forged = format!("{}.{}.{}", header, URL_SAFE_NO_PAD.encode(forged.to_string()), signature);
+        assert!(verify_id_token(&forged, &keys, &config, None, now).is_err());
+
+        // Unverified addresses don't match email mappings
+        let mut unverified = claims.clone();
+        unverified["email_verified"] = false.into();
+        let identity = verify_id_token(&sign(&key, unverified), &keys, &config, None, now).unwrap();
+        assert!(!config.mappings[1].matches(&identity));
+
+        // Unknown keys are reported so the key set can be refetched
+        let mut rotated = jwk;
+        rotated.kid = Some("k2".to_string());
+        let error = verify_id_token(&sign(&key, claims), &[rotated], &config, None, now).unwrap_err();
+        assert!(error.is::<UnknownKey>());
+    }
+}
@@ -1,8 +1,11 @@
 // Resource Quota Enforcement
 // Tracks and enforces tenant resource limits
 
-use super::{ResourceQuota, TenantUsage};
+use super::{ResourceQuota, TenantManager, TenantUsage};
 use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
 
 /// Quota enforcer
 pub struct QuotaEnforcer {
@@ -20,7 +23,10 @@ impl QuotaEnforcer {
                 sites_limit: quota.max_sites,
                 storage_used_mb: 0,
                 storage_limit_mb: quota.max_storage_gb * 1024,
+                storage_scanned_at: None,
                 cages_running: 0,
+                bandwidth_used_mb: 0,
+                bandwidth_limit_mb: quota.max_bandwidth_gb_month.map(|gb| gb * 1024),
             },
         }
     }
@@ -62,6 +68,18 @@ impl QuotaEnforcer {
         Ok(())
     }
 
+    /// Check that a site may run `count` Cages at once
+    pub fn can_run_cages(&self, count: usize) -> Result<()> {
+        if count > self.quota.max_cages_per_site {
+            bail!(
+                "Cage quota exceeded for site: {} cages requested, limit is {}",
+                count,
+                self.quota.max_cages_per_site
+            );
+        }
+        Ok(())
+    }
+
     /// Check memory quota for Cage
     pub fn validate_cage_memory(&self, requested_mb: usize) -> Result<()> {
         if requested_mb > self.quota.max_memory_per_cage_mb {
@@ -79,6 +97,11 @@ impl QuotaEnforcer {
         self.current_usage = usage;
     }
 
+    /// Storage quota threshold currently reached
+    pub fn storage_level(&self) -> StorageLevel {
+        StorageLevel::from_usage(self.current_usage.storage_used_mb, self.current_usage.storage_limit_mb)
+    }
+
     /// Get current usage percentage
     pub fn usage_percentage(&self) -> QuotaUsagePercentage {
         QuotaUsagePercentage {
@@ -88,6 +111,39 @@ impl QuotaEnforcer {
     }
 }
 
+/// A site's Cage limits from its tenant's quota, checked by the site's pool as it grows
+#[derive(Clone)]
+pub struct SiteQuota {
+    tenants: Arc<TenantManager>,
+    tenant_id: Uuid,
+    site_id: String,
+}
+
+impl SiteQuota {
+    pub fn new(tenants: Arc<TenantManager>, tenant_id: Uuid, site_id: impl Into<String>) -> Self {
+        Self { tenants, tenant_id, site_id: site_id.into() }
+    }
+
+    /// Check that the site may run `count` Cages of `memory_bytes` each
+    pub fn can_run(&self, count: usize, memory_bytes: usize) -> Result<()> {
+        let enforcer = self.tenants.quota_enforcer(self.tenant_id)?;
+        enforcer.validate_cage_memory(memory_bytes.div_ceil(1024 * 1024))?;
+        enforcer.can_run_cages(count)
+    }
+
+    /// Check that one more Cage of `memory_bytes` fits next to the `running` ones
+    pub fn can_spawn(&self, running: usize, memory_bytes: usize) -> Result<()> {
+        let enforcer = self.tenants.quota_enforcer(self.tenant_id)?;
+        enforcer.validate_cage_memory(memory_bytes.div_ceil(1024 * 1024))?;
+        enforcer.can_create_cage(running)
+    }
+
+    /// Record how many Cages the site runs, for its tenant's usage
+    pub fn set_cage_count(&self, count: usize) {
+        self.tenants.set_site_cage_count(self.tenant_id, &self.site_id, count);
+    }
+}
+
 /// Quota usage percentage
 #[derive(Debug, Clone)]
 pub struct QuotaUsagePercentage {
@@ -95,6 +151,44 @@ pub struct QuotaUsagePercentage {
     pub storage: u8,
 }
 
+/// Storage usage thresholds that trigger warnings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageLevel {
+    /// Below 80% of quota
+    #[default]
+    Ok,
+
+    /// At least 80% of quota
+    Warning,
+
+    /// At least 90% of quota
+    Critical,
+
+    /// Quota reached; deploys and runtime writes are refused
+    Exceeded,
+}
+
+impl StorageLevel {
+    /// Level for a usage against a limit, both in MB
+    pub fn from_usage(used_mb: usize, limit_mb: usize) -> Self {
+        if used_mb >= limit_mb {
+            return StorageLevel::Exceeded;
+        }
+
+        match used_mb * 100 / limit_mb {
+            90.. => StorageLevel::Critical,
+            80.. => StorageLevel::Warning,
+            _ => StorageLevel::Ok,
+        }
+    }
+
+    /// Whether sites may still write to their storage
+    pub fn allows_writes(&self) -> bool {
+        *self != StorageLevel::Exceeded
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,6 +227,22 @@ mod tests {
         assert!(enforcer.can_allocate_storage(2048).is_err());
     }
 
+    #[test]
+    fn test_storage_levels() {
+        assert_eq!(StorageLevel::from_usage(100, 1024), StorageLevel::Ok);
+        assert_eq!(StorageLevel::from_usage(820, 1024), StorageLevel::Warning);
+        assert_eq!(StorageLevel::from_usage(930, 1024), StorageLevel::Critical);
+        assert_eq!(StorageLevel::from_usage(1024, 1024), StorageLevel::Exceeded);
+        assert!(!StorageLevel::from_usage(0, 0).allows_writes());
+        
+        let mut enforcer = QuotaEnforcer::new(ResourceQuota {
+            max_storage_gb: 1,
+            ..Default::default()
+        });
+        enforcer.current_usage.storage_used_mb = 950;
+        assert_eq!(enforcer.storage_level(), StorageLevel::Critical);
+    }
+
     #[test]
     fn test_memory_quota_enforcement() {
         let quota = ResourceQuota {
@@ -0,0 +1,309 @@
+// Site Environment and Secrets
+// Per-site environment variables and encrypted secrets injected into Cages
+
+use anyhow::{Context, Result, bail};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::info;
+
+use crate::cage::config::CageEnv;
+
+/// Environment variable holding a hex master key, used instead of the key file
+pub const SECRET_KEY_ENV: &str = "PEAR_SECRET_KEY";
+
+/// Longest accepted variable name
+const MAX_NAME_LEN: usize = 256;
+
+/// Master key secrets are sealed with
+pub struct SecretKey {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl SecretKey {
+    /// Build a key from 32 raw bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, bytes)
+            .map_err(|_| anyhow::anyhow!("Secret key must be {} bytes", CHACHA20_POLY1305.key_len()))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Generate a fresh random key
+    pub fn generate() -> Result<Self> {
+        Self::from_bytes(&random_key()?)
+    }
+
+    /// Load the master key from `PEAR_SECRET_KEY` or `path`, creating the file on first start
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        if let Ok(hex) = std::env::var(SECRET_KEY_ENV) {
+            let bytes = hex::decode(hex.trim()).with_context(|| format!("Invalid {}", SECRET_KEY_ENV))?;
+            return Self::from_bytes(&bytes);
+        }
+        Self::load_or_create_file(path)
+    }
+
+    /// Load a key from `path`, creating the file with a random key if there is none
+    pub fn load_or_create_file(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(hex) => {
+                let bytes = hex::decode(hex.trim())
+                    .with_context(|| format!("Invalid secret key file {}", path.display()))?;
+                Self::from_bytes(&bytes)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let bytes = random_key()?;
+                write_key_file(path, &hex::encode(&bytes))?;
+                info!(path = %path.display(), "Generated new secret key");
+                Self::from_bytes(&bytes)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to read secret key file {}", path.display())),
+        }
+    }
+
+    /// Encrypt a secret value, bound to its variable name
+    pub fn seal(&self, name: &str, value: &str) -> Result<SealedSecret> {
+        let sealed = self.seal_bytes(name.as_bytes(), value.as_bytes())
+            .with_context(|| format!("Failed to encrypt secret {}", name))?;
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        Ok(SealedSecret {
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Decrypt a secret value sealed under `name`
+    pub fn open(&self, name: &str, sealed: &SealedSecret) -> Result<String> {
+        let mut bytes = hex::decode(&sealed.nonce).context("Invalid hex string")?;
+        if bytes.len() != NONCE_LEN {
+            bail!("Invalid nonce for secret {}", name);
+        }
+        bytes.extend(hex::decode(&sealed.ciphertext).context("Invalid hex string")?);
+        let plaintext = self.open_bytes(name.as_bytes(), &bytes)
+            .with_context(|| format!("Failed to decrypt secret {}", name))?;
+        String::from_utf8(plaintext).context("Secret is not valid UTF-8")
+    }
+
+    /// Encrypt bytes under a fresh random nonce, bound to `aad`; the nonce leads the output
+    pub fn seal_bytes(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce)
+            .map_err(|_| anyhow::anyhow!("Failed to generate nonce"))?;
+
+        let mut in_out = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut in_out)
+            .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(in_out);
+        Ok(sealed)
+    }
+
+    /// Decrypt the output of `seal_bytes`, failing unless it was sealed with this key and `aad`
+    pub fn open_bytes(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            bail!("Sealed data is truncated");
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow::anyhow!("Invalid nonce"))?;
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = self.key
+            .open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .map_err(|_| anyhow::anyhow!("Decryption failed (wrong key or tampered data)"))?;
+        let len = plaintext.len();
+        in_out.truncate(len);
+        Ok(in_out)
+    }
+}
+
+/// Encrypted secret value as stored with the site record
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedSecret {
+    nonce: String,
+    ciphertext: String,
+}
+
+impl std::fmt::Debug for SealedSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SealedSecret(..)")
+    }
+}
+
+/// Environment variables and secrets of one site
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteEnv {
+    /// Plain variables
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+
+    /// Encrypted variables
+    #[serde(default)]
+    pub secrets: BTreeMap<String, SealedSecret>,
+}
+
+/// One variable as shown in listings; secret values are never included
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EnvEntry {
+    pub name: String,
+    pub value: Option<String>,
+    pub secret: bool,
+}
+
+impl SiteEnv {
+    /// Set a plain variable, replacing a secret of the same name
+    pub fn set_var(&mut self, name: &str, value: &str) -> Result<()> {
+        validate_var(name, value)?;
+        self.secrets.remove(name);
+        self.vars.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Encrypt and set a secret, replacing a plain variable of the same name
+    pub fn set_secret(&mut self, key: &SecretKey, name: &str, value: &str) -> Result<()> {
+        validate_var(name, value)?;
+        let sealed = key.seal(name, value)?;
+        self.vars.remove(name);
+        self.secrets.insert(name.to_string(), sealed);
+        Ok(())
+    }
+
+    /// Remove a variable or secret, returning whether it existed
+    pub fn unset(&mut self, name: &str) -> bool {
+        self.vars.remove(name).is_some() | self.secrets.remove(name).is_some()
+    }
+
+    /// Every variable sorted by name, with secret values masked
+    pub fn entries(&self) -> Vec<EnvEntry> {
+        let vars = self.vars.iter().map(|(name, value)| EnvEntry {
+            name: name.clone(),
+            value: Some(value.clone()),
+            secret: false,
+        });
+        let secrets = self.secrets.keys().map(|name| EnvEntry {
+            name: name.clone(),
+            value: None,
+            secret: true,
+        });
+
+        let mut entries: Vec<_> = vars.chain(secrets).collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
+
+    /// Decrypt everything into the environment handed to a Cage
+    pub fn resolve(&self, key: Option<&SecretKey>) -> Result<CageEnv> {
+        let mut env = CageEnv::default();
+        for (name, value) in &self.vars {
+            env.push(name, value);
+        }
+        for (name, sealed) in &self.secrets {
+            let key = key.context("Secrets are set but no secret key is configured")?;
+            env.push(name, &key.open(name, sealed)?);
+        }
+        Ok(env)
+    }
+}
+
+/// Check that a variable can be passed through WASI
+pub fn validate_var(name: &str, value: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid_start = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if !valid_start || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') || name.len() > MAX_NAME_LEN {
+        bail!("Invalid variable name '{}': use letters, digits and underscores", name);
+    }
+    if value.contains('\0') {
+        bail!("Value of {} contains a NUL byte", name);
+    }
+    Ok(())
+}
+
+/// Fresh random bytes for a `SecretKey`
+pub fn random_key() -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; CHACHA20_POLY1305.key_len()];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("Failed to generate secret key"))?;
+    Ok(bytes)
+}
+
+/// Write a new key file readable only by the server user
+fn write_key_file(path: &Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to create secret key file {}", path.display()))?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_round_trip_and_stay_masked() {
+        let key = SecretKey::generate().unwrap();
+        let mut env = SiteEnv::default();
+        env.set_var("DATABASE_URL", "postgres://db/app").unwrap();
+        env.set_secret(&key, "API_KEY", "s3cr3t").unwrap();
+
+        let entries = env.entries();
+        assert_eq!(entries[0], EnvEntry { name: "API_KEY".into(), value: None, secret: true });
+        assert_eq!(entries[1].value.as_deref(), Some("postgres://db/app"));
+
+        // Neither the stored record nor Debug output carry the plaintext
+        let stored = serde_json::to_string(&env).unwrap();
+        assert!(!stored.contains("s3cr3t"));
+        assert!(!format!("{:?}", env).contains("s3cr3t"));
+
+        let resolved = env.resolve(Some(&key)).unwrap();
+        assert!(resolved.as_pairs().contains(&("API_KEY".to_string(), "s3cr3t".to_string())));
+        assert!(!format!("{:?}", resolved).contains("s3cr3t"));
+        assert!(env.resolve(None).is_err());
+    }
+
+    #[test]
+    fn test_secrets_are_bound_to_key_and_name() {
+        let key = SecretKey::generate().unwrap();
+        let sealed = key.seal("API_KEY", "s3cr3t").unwrap();
+
+        assert!(key.open("OTHER_KEY", &sealed).is_err());
+        assert!(SecretKey::generate().unwrap().open("API_KEY", &sealed).is_err());
+        assert_eq!(key.open("API_KEY", &sealed).unwrap(), "s3cr3t");
+    }
+
+    #[test]
+    fn test_key_file_is_created_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.key");
+
+        let first = SecretKey::load_or_create(&path).unwrap();
+        let sealed = first.seal("TOKEN", "value").unwrap();
+        let second = SecretKey::load_or_create(&path).unwrap();
+        assert_eq!(second.open("TOKEN", &sealed).unwrap(), "value");
+    }
+
+    #[test]
+    fn test_variable_names_are_validated() {
+        let mut env = SiteEnv::default();
+        assert!(env.set_var("1ABC", "x").is_err());
+        assert!(env.set_var("A-B", "x").is_err());
+        assert!(env.set_var("OK", "a\0b").is_err());
+        assert!(env.set_var("_OK_1", "x").is_ok());
+        assert!(env.unset("_OK_1"));
+        assert!(!env.unset("_OK_1"));
+    }
+}
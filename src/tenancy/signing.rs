@@ -0,0 +1,161 @@
+// Module Signing
+// Tenant public keys and detached ed25519 signatures checked before a module is deployed
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+
+
+/// Header a deploy carries the module's detached signature in
+pub const SIGNATURE_HEADER: &str = "x-pear-signature";
+
+/// DER prefix of an ed25519 SubjectPublicKeyInfo, as in `openssl pkey -pubout` PEM files
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+/// A public key a tenant signs its modules with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantKey {
+    pub name: String,
+
+    /// The raw 32-byte ed25519 key, base64
+    pub public_key: String,
+
+    /// Unix seconds
+    pub added_at: i64,
+}
+
+impl TenantKey {
+    /// Accepts base64 or hex raw keys and PEM public key files
+    pub fn new(name: &str, public_key: &str) -> Result<Self> {
+        let name = name.trim();
+        if name.is_empty() || name.len() > 64 || name.contains(|c: char| c.is_whitespace() || c == '/') {
+            bail!("Invalid key name '{}'", name);
+        }
+        let key = parse_public_key(public_key)?;
+        Ok(Self { name: name.to_string(), public_key: STANDARD.encode(key), added_at: Utc::now().timestamp() })
+    }
+
+    fn verifies(&self, module: &[u8], signature: &[u8]) -> bool {
+        let Ok(key) = STANDARD.decode(&self.public_key) else { return false };
+        UnparsedPublicKey::new(&ED25519, key).verify(module, signature).is_ok()
+    }
+}
+
+/// Outcome of checking a module's signature against its tenant's keys
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum SignatureCheck {
+    /// No signature, and the site doesn't require one
+    #[default]
+    Unsigned,
+
+    /// Signed by the named tenant key
+    Verified { key: String },
+
+    /// A signature was given but matches none of the tenant's keys
+    Invalid,
+
+    /// The site requires a signature and none was given
+    Missing,
+}
+
+impl SignatureCheck {
+    /// Why the module may not be deployed, if it may not
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            SignatureCheck::Unsigned | SignatureCheck::Verified { .. } => None,
+            SignatureCheck::Invalid => Some("Module signature matches none of the tenant's signing keys".to_string()),
+            SignatureCheck::Missing => Some("Site requires signed modules and the module is unsigned".to_string()),
+        }
+    }
+}
+
+/// Check `module` against `signature` (base64 or hex)
+/// A signature that doesn't verify is rejected even where signatures are optional.
+pub fn check(keys: &[TenantKey], required: bool, module: &[u8], signature: Option<&str>) -> SignatureCheck {
+    let Some(signature) = signature.map(str::trim).filter(|signature| !signature.is_empty()) else {
+        return if required { SignatureCheck::Missing } else { SignatureCheck::Unsigned };
+    };
+    let Ok(signature) = decode(signature, 64) else {
+        return SignatureCheck::Invalid;
+    };
+    keys.iter()
+        .find(|key| key.verifies(module, &signature))
+        .map_or(SignatureCheck::Invalid, |key| SignatureCheck::Verified { key: key.name.clone() })
+}
+
+fn parse_public_key(text: &str) -> Result<Vec<u8>> {
+    let text = text.trim();
+    if let Some(body) = text.strip_prefix("-----BEGIN PUBLIC KEY-----") {
+        let body = body.trim_end().strip_suffix("-----END PUBLIC KEY-----").context("Unterminated PEM public key")?;
+        let der = STANDARD.decode(body.split_whitespace().collect::<String>()).context("Invalid PEM public key")?;
+        return match der.strip_prefix(&ED25519_SPKI_PREFIX[..]) {
+            Some(key) if key.len() == 32 => Ok(key.to_vec()),
+            _ => bail!("PEM public key is not an ed25519 key"),
+        };
+    }
+    decode(text, 32).context("Public key must be a 32-byte ed25519 key in base64, hex or PEM")
+}
+
+/// Hex or base64 bytes of an expected length
+fn decode(text: &str, len: usize) -> Result<Vec<u8>> {
+    let bytes = if text.len() == len * 2 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        hex::decode(text)?
+    } else {
+        STANDARD.decode(text).context("Not valid base64 or hex")?
+    };
+    if bytes.len() != len {
+        bail!("Expected {} bytes, got {}", len, bytes.len());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn key_pair() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_key_formats() {
+        let pair = key_pair();
+        let raw = pair.public_key().as_ref();
+        let from_base64 = TenantKey::new("ci", &STANDARD.encode(raw)).unwrap();
+        assert_eq!(TenantKey::new("ci", &hex::encode(raw)).unwrap().public_key, from_base64.public_key);
+
+        let der = [&ED25519_SPKI_PREFIX[..], raw].concat();
+        let pem = format!("-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n", STANDARD.encode(der));
+        assert_eq!(TenantKey::new("ci", &pem).unwrap().public_key, from_base64.public_key);
+
+        assert!(TenantKey::new("ci", "c2hvcnQ=").is_err());
+        assert!(TenantKey::new("has space", &hex::encode(raw)).is_err());
+    }
+
+    #[test]
+    fn test_signature_check() {
+        let pair = key_pair();
+        let other = key_pair();
+        let keys = vec![
+            TenantKey::new("old", &STANDARD.encode(other.public_key().as_ref())).unwrap(),
+            TenantKey::new("ci", &STANDARD.encode(pair.public_key().as_ref())).unwrap(),
+        ];
+        let module = b"\0asm\x01\0\0\0";
+        let signature = STANDARD.encode(pair.sign(module).as_ref());
+
+        assert_eq!(check(&keys, true, module, Some(&signature)), SignatureCheck::Verified { key: "ci".to_string() });
+        assert_eq!(check(&keys, false, b"\0asm tampered", Some(&signature)), SignatureCheck::Invalid);
+        assert_eq!(check(&keys, false, module, Some("not a signature")), SignatureCheck::Invalid);
+        assert_eq!(check(&[], false, module, Some(&signature)), SignatureCheck::Invalid);
+        assert_eq!(check(&keys, false, module, None), SignatureCheck::Unsigned);
+        assert_eq!(check(&keys, true, module, Some(" ")), SignatureCheck::Missing);
+        assert!(SignatureCheck::Missing.problem().is_some());
+    }
+}
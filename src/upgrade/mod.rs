@@ -0,0 +1,297 @@
+// Graceful binary upgrade via listening socket handover
+// Passes listener FDs to a freshly exec'd binary in systemd socket-activation style
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Number of passed FDs (systemd convention)
+const LISTEN_FDS: &str = "LISTEN_FDS";
+
+/// PID the FDs are intended for (checked when present)
+const LISTEN_PID: &str = "LISTEN_PID";
+
+/// Colon-separated names for each passed FD
+const LISTEN_FDNAMES: &str = "LISTEN_FDNAMES";
+
+/// First passed FD (systemd convention)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// FDs inherited from a parent process or socket activation, keyed by name
+static INHERITED: OnceLock<Mutex<HashMap<String, Vec<RawFd>>>> = OnceLock::new();
+
+/// Listener FDs bound by this process, in handover order
+static REGISTERED: Mutex<Vec<(String, RawFd)>> = Mutex::new(Vec::new());
+
+/// Handover name for a TCP listener
+/// Colons are replaced since LISTEN_FDNAMES is colon-separated
+pub fn tcp_name(addr: &SocketAddr) -> String {
+    format!("tcp-{}", addr).replace(':', "_")
+}
+
+/// Handover name for a UDP (QUIC) socket
+pub fn udp_name(addr: &SocketAddr) -> String {
+    format!("udp-{}", addr).replace(':', "_")
+}
+
+/// Take all inherited TCP listeners for an address
+pub fn inherited_tcp_listeners(addr: &SocketAddr) -> Vec<std::net::TcpListener> {
+    take_inherited(&tcp_name(addr))
+        .into_iter()
+        .map(|fd| unsafe { std::net::TcpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+/// Take all inherited UDP sockets for an address
+/// Reusing them keeps the port's SO_REUSEPORT group unchanged, so QUIC packets keep reaching a socket.
+pub fn inherited_udp_sockets(addr: &SocketAddr) -> Vec<std::net::UdpSocket> {
+    take_inherited(&udp_name(addr))
+        .into_iter()
+        .map(|fd| unsafe { std::net::UdpSocket::from_raw_fd(fd) })
+        .collect()
+}
+
+/// Record a bound listener so it can be handed to a successor
+pub fn register_listener(name: String, fd: RawFd) {
+    REGISTERED.lock().push((name, fd));
+}
+
+/// Number of listeners that would be handed over
+pub fn registered_count() -> usize {
+    REGISTERED.lock().len()
+}
+
+/// Take inherited FDs registered under a name
+fn take_inherited(name: &str) -> Vec<RawFd> {
+    INHERITED
+        .get_or_init(|| Mutex::new(parse_inherited()))
+        .lock()
+        .remove(name)
+        .unwrap_or_default()
+}
+
+/// Parse LISTEN_FDS / LISTEN_FDNAMES from the environment
+/// The variables are cleared so they don't leak into child processes
+fn parse_inherited() -> HashMap<String, Vec<RawFd>> {
+    let count = std::env::var(LISTEN_FDS).ok().and_then(|v| v.parse::<RawFd>().ok());
+    let pid = std::env::var(LISTEN_PID).ok();
+    let names = std::env::var(LISTEN_FDNAMES).unwrap_or_default();
+
+    std::env::remove_var(LISTEN_FDS);
+    std::env::remove_var(LISTEN_PID);
+    std::env::remove_var(LISTEN_FDNAMES);
+
+    let Some(count) = count else {
+        return HashMap::new();
+    };
+
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            warn!(listen_pid = %pid, "Ignoring LISTEN_FDS intended for another process");
+            return HashMap::new();
+        }
+    }
+
+    let inherited = group_fds(count, &names);
+    for fd in inherited.values().flatten() {
+        // Keep inherited FDs from leaking into anything we spawn later
+        unsafe {
+            libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+    }
+
+    info!(count = count, "Inherited listening sockets");
+
+    inherited
+}
+
+/// Group passed FDs by name, starting at LISTEN_FDS_START
+fn group_fds(count: RawFd, names: &str) -> HashMap<String, Vec<RawFd>> {
+    let mut names = names.split(':');
+    let mut grouped: HashMap<String, Vec<RawFd>> = HashMap::new();
+
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        let name = names.next().filter(|n| !n.is_empty()).unwrap_or("unknown");
+        grouped.entry(name.to_string()).or_default().push(fd);
+    }
+
+    grouped
+}
+
+/// Exec a successor binary with all registered listeners passed to it
+pub fn spawn_successor(binary: &Path, args: &[OsString]) -> Result<Child> {
+    let listeners = REGISTERED.lock().clone();
+    let fds: Vec<RawFd> = listeners.iter().map(|(_, fd)| *fd).collect();
+    let names: Vec<&str> = listeners.iter().map(|(name, _)| name.as_str()).collect();
+
+    let mut command = Command::new(binary);
+    command
+        .args(args)
+        .env(LISTEN_FDS, fds.len().to_string())
+        .env(LISTEN_FDNAMES, names.join(":"))
+        .env_remove(LISTEN_PID);
+
+    unsafe {
+        command.pre_exec(move || remap_fds(&fds));
+    }
+
+    let child = command
+        .spawn()
+        .with_context(|| format!("Failed to exec successor {}", binary.display()))?;
+
+    info!(
+        pid = child.id(),
+        binary = %binary.display(),
+        listeners = listeners.len(),
+        "Successor process started"
+    );
+
+    Ok(child)
+}
+
+/// Move FDs into the contiguous range starting at LISTEN_FDS_START
+/// Runs between fork and exec, so only async-signal-safe calls are allowed
+fn remap_fds(fds: &[RawFd]) -> std::io::Result<()> {
+    // Duplicate above the target range first so dup2 never clobbers a source
+    let floor = LISTEN_FDS_START + fds.len() as RawFd;
+    let mut staged = [0 as RawFd; 64];
+    if fds.len() > staged.len() {
+        return Err(std::io::Error::from_raw_os_error(libc::EMFILE));
+    }
+
+    for (i, fd) in fds.iter().enumerate() {
+        let dup = unsafe { libc::fcntl(*fd, libc::F_DUPFD, floor) };
+        if dup < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        staged[i] = dup;
+    }
+
+    for (i, dup) in staged[..fds.len()].iter().enumerate() {
+        // dup2 clears FD_CLOEXEC on the target, so it survives exec
+        if unsafe { libc::dup2(*dup, LISTEN_FDS_START + i as RawFd) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        unsafe {
+            libc::close(*dup);
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait until the successor has written its PID file
+/// Kills the successor if it exits early or doesn't become ready in time
+pub async fn wait_for_successor(child: &mut Child, pid_file: &Path, timeout: Duration) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!("Successor exited during startup: {}", status);
+        }
+
+        if read_pid_file(pid_file).ok() == Some(child.id()) {
+            info!(pid = child.id(), "Successor is ready");
+            return Ok(());
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            let _ = child.kill();
+            anyhow::bail!("Successor did not become ready within {:?}", timeout);
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Write this process's PID
+pub fn write_pid_file(path: &Path) -> Result<()> {
+    std::fs::write(path, format!("{}\n", std::process::id()))
+        .with_context(|| format!("Failed to write PID file {}", path.display()))
+}
+
+/// Read a PID from a PID file
+pub fn read_pid_file(path: &Path) -> Result<u32> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read PID file {}", path.display()))?;
+
+    contents.trim().parse().context("Invalid PID file contents")
+}
+
+/// Remove the PID file if it still belongs to this process
+pub fn remove_pid_file(path: &Path) {
+    if read_pid_file(path).ok() == Some(std::process::id()) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Path of the file naming the binary to exec on the next upgrade
+pub fn next_binary_path(pid_file: &Path) -> PathBuf {
+    let mut path = pid_file.as_os_str().to_owned();
+    path.push(".next");
+    PathBuf::from(path)
+}
+
+/// Ask the daemon at `pid` to upgrade itself
+pub fn request_upgrade(pid: u32) -> Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGUSR2) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to signal process {}", pid));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_fds_by_name() {
+        let grouped = group_fds(3, "tcp-0.0.0.0_8080:tcp-0.0.0.0_8080:tcp-0.0.0.0_9000");
+        assert_eq!(grouped["tcp-0.0.0.0_8080"], vec![3, 4]);
+        assert_eq!(grouped["tcp-0.0.0.0_9000"], vec![5]);
+    }
+
+    #[test]
+    fn test_tcp_name_has_no_separator() {
+        let v4: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        let v6: SocketAddr = "[::1]:8080".parse().unwrap();
+        assert_eq!(tcp_name(&v4), "tcp-0.0.0.0_8080");
+        assert!(!tcp_name(&v6).contains(':'));
+        assert_eq!(udp_name(&v4), "udp-0.0.0.0_8080");
+        assert!(!udp_name(&v6).contains(':'));
+    }
+
+    #[test]
+    fn test_group_fds_missing_names() {
+        let grouped = group_fds(2, "");
+        assert_eq!(grouped["unknown"], vec![3, 4]);
+    }
+
+    #[test]
+    fn test_pid_file_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pear.pid");
+
+        write_pid_file(&path).unwrap();
+        assert_eq!(read_pid_file(&path).unwrap(), std::process::id());
+
+        remove_pid_file(&path);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_next_binary_path() {
+        let path = next_binary_path(Path::new("/run/pear.pid"));
+        assert_eq!(path, PathBuf::from("/run/pear.pid.next"));
+    }
+}
@@ -5,26 +5,24 @@ let ws = null;
 let reconnectAttempts = 0;
 const MAX_RECONNECT_ATTEMPTS = 5;
 let currentUser = null;
+let lastSecurityEventCount = -1;
+let lastBandwidthRefresh = 0;
+const BANDWIDTH_REFRESH_MS = 30000;
+let lastTenantRefresh = 0;
+const TENANT_REFRESH_MS = 10000;
+let lastHistoryRefresh = 0;
+const HISTORY_REFRESH_MS = 60000;
+const HEALING_TIMELINE_LEN = 50;
 
 // Initialize dashboard
 document.addEventListener('DOMContentLoaded', () => {
     showLoginScreen();
     setupLoginHandler();
+    loadLoginOptions();
 });
 
 // Setup login form handler
 function setupLoginHandler() {
-    const roleSelect = document.getElementById('role-select');
-    const tenantSelectGroup = document.getElementById('tenant-select-group');
-
-    roleSelect.addEventListener('change', (e) => {
-        if (e.target.value === 'tenant') {
-            tenantSelectGroup.style.display = 'block';
-        } else {
-            tenantSelectGroup.style.display = 'none';
-        }
-    });
-
     document.getElementById('login-form').addEventListener('submit', (e) => {
         e.preventDefault();
         handleLogin();
@@ -33,23 +31,98 @@ function setupLoginHandler() {
     document.getElementById('logout-btn').addEventListener('click', handleLogout);
 }
 
-// Handle login
-function handleLogin() {
-    const role = document.getElementById('role-select').value;
-    const tenant = document.getElementById('tenant-select').value;
+// Offer single sign-on when configured, and resume a login it just completed
+async function loadLoginOptions() {
+    try {
+        const response = await fetch('/auth/config');
+        const options = await response.json();
+        if (options.sso) {
+            const button = document.getElementById('sso-login');
+            button.textContent = `Sign in with ${options.provider_name}`;
+            button.style.display = 'block';
+        }
+        if (!options.local_login) {
+            document.getElementById('login-form').style.display = 'none';
+        }
+    } catch (error) {
+        console.error('Failed to load login options:', error);
+    }
 
-    currentUser = {
-        role: role,
-        tenant: role === 'tenant' ? tenant : null,
-        token: role === 'root' ? 'root_admin_secret_token' : `tenant_${tenant}`
-    };
+    const session = sessionStorage.getItem('pear_session');
+    if (session) {
+        handleLogin(session);
+    }
+}
+
+// Handle login: ask the server what the token may do
+// Node-wide tokens get the root view, tenant tokens their tenant's view
+async function handleLogin(session) {
+    const sso = typeof session === 'string';
+    const token = sso ? session : document.getElementById('token-input').value.trim();
+    const error = document.getElementById('login-error');
+    error.style.display = 'none';
+
+    try {
+        const response = await fetch('/api/whoami', {
+            headers: { 'Authorization': `Bearer ${token}` }
+        });
+        const result = await response.json();
+
+        if (response.status === 403 && !result.banned) {
+            // Management is disabled: the dashboard is read-only
+            currentUser = { role: 'root', roleName: 'Viewer', tenant: null, tenantName: null, token: '' };
+        } else if (!response.ok) {
+            if (sso) {
+                sessionStorage.removeItem('pear_session');
+            }
+            error.textContent = result.error || 'Invalid token';
+            error.style.display = 'block';
+            return;
+        } else if (result.tenant_id) {
+            currentUser = {
+                role: 'tenant',
+                roleName: result.role,
+                tenant: result.tenant_id,
+                tenantName: await fetchTenantName(result.tenant_id, token),
+                token: token
+            };
+        } else {
+            currentUser = { role: 'root', roleName: result.role, tenant: null, tenantName: null, token: token };
+        }
+        currentUser.subject = result.subject || null;
+    } catch (err) {
+        console.error('Login failed:', err);
+        return;
+    }
 
+    currentUser.sso = sso;
+    lastTenantRefresh = 0;
     showDashboard();
     connectWebSocket();
 }
 
+// Name of the tenant a token belongs to
+async function fetchTenantName(tenantId, token) {
+    try {
+        const response = await fetch(`/api/tenants/${encodeURIComponent(tenantId)}/overview`, {
+            headers: { 'Authorization': `Bearer ${token}` }
+        });
+        const result = await response.json();
+        return result.tenant.name;
+    } catch (error) {
+        return tenantId;
+    }
+}
+
 // Handle logout
 function handleLogout() {
+    if (currentUser && currentUser.sso) {
+        fetch('/auth/logout', {
+            method: 'POST',
+            headers: { 'Authorization': `Bearer ${currentUser.token}` }
+        });
+        sessionStorage.removeItem('pear_session');
+    }
     currentUser = null;
     if (ws) {
         ws.close();
@@ -73,21 +146,25 @@ function showDashboard() {
     const tenantSpan = document.getElementById('current-tenant');
 
     if (currentUser.role === 'root') {
-        roleBadge.textContent = 'Root Admin';
+        roleBadge.textContent = currentUser.roleName === 'root-admin' ? 'Root Admin' : currentUser.roleName;
         roleBadge.className = 'role-badge root';
-        tenantSpan.textContent = '';
+        tenantSpan.textContent = currentUser.subject ? `(${currentUser.subject})` : '';
 
         // Show root-only panels
         document.getElementById('tenant-management').style.display = 'block';
         document.getElementById('global-security').style.display = 'block';
+        document.getElementById('connections-panel').style.display = 'block';
+        document.getElementById('tenant-view').style.display = 'none';
     } else {
-        roleBadge.textContent = 'Tenant Admin';
+        roleBadge.textContent = currentUser.roleName === 'tenant-admin' ? 'Tenant Admin' : currentUser.roleName;
         roleBadge.className = 'role-badge tenant';
-        tenantSpan.textContent = `(${currentUser.tenant})`;
+        tenantSpan.textContent = `(${currentUser.tenantName})`;
 
         // Hide root-only panels
         document.getElementById('tenant-management').style.display = 'none';
         document.getElementById('global-security').style.display = 'none';
+        document.getElementById('connections-panel').style.display = 'none';
+        document.getElementById('tenant-view').style.display = 'block';
     }
 }
 
@@ -160,6 +237,7 @@ function updateDashboard(data) {
     document.getElementById('uptime').textContent = formatUptime(3627);
     document.getElementById('total-requests').textContent = formatNumber(data.router.total_requests);
     document.getElementById('success-rate').textContent = `${data.router.success_rate.toFixed(1)}%`;
+    document.getElementById('latency-p99').textContent = formatLatency(data.router.latency.p99_ms);
 
     // Traffic stats
     document.getElementById('stat-total').textContent = formatNumber(data.router.total_requests);
@@ -170,11 +248,40 @@ function updateDashboard(data) {
     // AI Security
     document.getElementById('ai-status').textContent = data.ai.anomaly_detection_enabled ? 'ACTIVE' : 'DISABLED';
     document.getElementById('threats-count').textContent = data.ai.threats_detected;
+    if (data.ai.security_events !== lastSecurityEventCount) {
+        lastSecurityEventCount = data.ai.security_events;
+        refreshSecurityEvents();
+    }
+
+    // Bandwidth totals change with every request, so poll them on a slower cadence
+    if (Date.now() - lastBandwidthRefresh > BANDWIDTH_REFRESH_MS) {
+        lastBandwidthRefresh = Date.now();
+        refreshBandwidth();
+        refreshLatency();
+        if (currentUser && currentUser.role === 'root') {
+            refreshConnections();
+        }
+    }
+
+    // History gains one rollup a minute
+    if (Date.now() - lastHistoryRefresh > HISTORY_REFRESH_MS) {
+        lastHistoryRefresh = Date.now();
+        refreshMetricsHistory();
+    }
+
+    // Tenant graphs are sampled server-side, so polling faster than the samples gains nothing
+    if (currentUser && currentUser.role === 'tenant' && Date.now() - lastTenantRefresh > TENANT_REFRESH_MS) {
+        lastTenantRefresh = Date.now();
+        refreshTenantView();
+    }
 
     // Supervisor
     document.getElementById('supervisor-status').textContent = data.supervisor.is_running ? 'RUNNING' : 'STOPPED';
     document.getElementById('healing-events').textContent = data.supervisor.healing_events;
     document.getElementById('supervised-pools').textContent = data.supervisor.supervised_pools;
+    if (data.healing && data.healing.length > 0) {
+        addHealingEvents(data.healing);
+    }
 
     // Global Security (Root only)
     if (currentUser && currentUser.role === 'root' && data.security) {
@@ -183,6 +290,9 @@ function updateDashboard(data) {
         document.getElementById('anomalies').textContent = data.security.anomalies || 8;
         document.getElementById('banned-ips').textContent = data.security.banned_ips || 15;
     }
+    if (currentUser && currentUser.role === 'root') {
+        document.getElementById('banned-ips').textContent = data.ai.banned_ips;
+    }
 
     // Cage Pool
     updateCageGrid(data.cages);
@@ -340,6 +450,542 @@ async function deleteTenant(tenantId) {
     }
 }
 
+// Load recent security events into the threat log
+async function refreshSecurityEvents() {
+    const severity = document.getElementById('event-severity').value;
+    const log = document.getElementById('threat-log');
+
+    try {
+        const response = await fetch(`/api/security/events?limit=50&min_severity=${severity}`);
+        const events = await response.json();
+
+        if (events.length === 0) {
+            log.innerHTML = '<div class="log-placeholder">No threats detected</div>';
+            return;
+        }
+
+        log.innerHTML = '';
+        events.forEach(event => {
+            const entry = document.createElement('div');
+            entry.className = `event-entry ${event.severity}`;
+
+            const time = new Date(event.timestamp * 1000).toLocaleTimeString();
+            const score = event.score !== null ? ` (score ${event.score.toFixed(2)})` : '';
+            entry.innerHTML = `
+                <span class="event-time">${time}</span>
+                <span class="event-kind">${event.kind.replace('_', ' ')}</span>
+                <span class="event-action ${event.action}">${event.action}</span>
+                <div class="event-detail"></div>
+            `;
+            // Paths and details come from clients, so never render them as HTML
+            entry.querySelector('.event-detail').textContent =
+                `${event.site_id} ${event.path} from ${event.ip || 'unknown'}${score}${event.details ? ' - ' + event.details : ''}`;
+
+            log.appendChild(entry);
+        });
+    } catch (error) {
+        console.error('Failed to load security events:', error);
+    }
+}
+
+// Load per-site bandwidth usage and quota state
+async function refreshBandwidth() {
+    const table = document.getElementById('bandwidth-sites');
+
+    try {
+        const response = await fetch('/api/bandwidth');
+        if (!response.ok) {
+            table.innerHTML = '<tr><td colspan="5" class="log-placeholder">Bandwidth accounting disabled</td></tr>';
+            return;
+        }
+        const report = await response.json();
+
+        if (report.sites.length === 0) {
+            table.innerHTML = '<tr><td colspan="5" class="log-placeholder">No traffic recorded</td></tr>';
+            return;
+        }
+
+        table.innerHTML = '';
+        report.sites.forEach(site => {
+            const row = document.createElement('tr');
+            const quota = [
+                site.quota.daily_gb !== null ? `${site.quota.daily_gb} GB/day` : null,
+                site.quota.monthly_gb !== null ? `${site.quota.monthly_gb} GB/month` : null,
+            ].filter(Boolean).join(', ') || 'Unlimited';
+
+            [
+                site.id,
+                formatBytes(site.today.ingress_bytes + site.today.egress_bytes),
+                formatBytes(site.this_month.ingress_bytes + site.this_month.egress_bytes),
+                quota,
+                site.status,
+            ].forEach(value => {
+                const cell = document.createElement('td');
+                cell.textContent = value;
+                row.appendChild(cell);
+            });
+            row.lastChild.className = `quota-status ${site.status}`;
+
+            table.appendChild(row);
+        });
+    } catch (error) {
+        console.error('Failed to load bandwidth usage:', error);
+    }
+}
+
+// Load latency percentiles per site, with a row per Cage under each site
+async function refreshLatency() {
+    const table = document.getElementById('latency-sites');
+
+    try {
+        const response = await fetch('/api/latency');
+        const report = await response.json();
+
+        if (report.sites.length === 0) {
+            table.innerHTML = '<tr><td colspan="6" class="log-placeholder">No traffic recorded</td></tr>';
+            return;
+        }
+
+        table.innerHTML = '';
+        const addRow = (label, latency, baseline, className) => {
+            const row = document.createElement('tr');
+            row.className = className;
+            [
+                label,
+                formatNumber(latency.count),
+                formatLatency(latency.p50_ms),
+                formatLatency(latency.p90_ms),
+                formatLatency(latency.p99_ms),
+                baseline ? `${formatLatency(baseline.request_latency_mean_ms)} ± ${formatLatency(baseline.request_latency_std_dev)}` : '--',
+            ].forEach(value => {
+                const cell = document.createElement('td');
+                cell.textContent = value;
+                row.appendChild(cell);
+            });
+            table.appendChild(row);
+        };
+
+        report.sites.forEach(site => {
+            addRow(site.site_id, site.latency, site.baseline, 'latency-site');
+            site.cages.forEach(cage => addRow(`Cage #${cage.cage_id}`, cage.latency, null, 'latency-cage'));
+        });
+    } catch (error) {
+        console.error('Failed to load latency:', error);
+    }
+}
+
+// Load open connections, longest-lived first (root admin only, since it lists client addresses)
+async function refreshConnections() {
+    const table = document.getElementById('connections-table');
+
+    try {
+        const response = await fetch('/api/connections', {
+            headers: { 'Authorization': `Bearer ${currentUser.token}` }
+        });
+        if (!response.ok) {
+            return;
+        }
+        const report = await response.json();
+
+        document.getElementById('connections-reaped').textContent =
+            `Closed by the reaper: ${formatNumber(report.reaped_idle)} idle, ${formatNumber(report.reaped_request_limit)} at their request limit, ${formatNumber(report.reaped_shed)} shed to rebalance`;
+
+        if (report.connections.length === 0) {
+            table.innerHTML = '<tr><td colspan="6" class="log-placeholder">No open connections</td></tr>';
+            return;
+        }
+
+        table.innerHTML = '';
+        report.connections.forEach(connection => {
+            const row = document.createElement('tr');
+            [
+                connection.remote_addr,
+                connection.protocol,
+                formatUptime(connection.age_secs),
+                connection.active_requests > 0 ? '--' : formatUptime(connection.idle_secs),
+                formatNumber(connection.requests),
+                connection.active_requests,
+            ].forEach(value => {
+                const cell = document.createElement('td');
+                cell.textContent = value;
+                row.appendChild(cell);
+            });
+            table.appendChild(row);
+        });
+    } catch (error) {
+        console.error('Failed to load connections:', error);
+    }
+}
+
+// Load rollups for the selected range and graph them
+async function refreshMetricsHistory() {
+    const range = document.getElementById('history-range').value;
+    const graphs = document.getElementById('history-graphs');
+
+    try {
+        const response = await fetch(`/api/metrics/history?range_secs=${range}&points=120`);
+        if (!response.ok) {
+            graphs.innerHTML = '<div class="log-placeholder">Metrics history disabled</div>';
+            return;
+        }
+        const history = await response.json();
+        const rollups = history.rollups;
+
+        if (rollups.length < 2) {
+            graphs.innerHTML = '<div class="log-placeholder">Collecting samples...</div>';
+            return;
+        }
+
+        graphs.innerHTML = '';
+        [
+            ['Requests / errors', [rollups.map(r => r.requests), rollups.map(r => r.errors)], ['graph-requests', 'graph-errors'],
+                `${formatNumber(sum(rollups.map(r => r.requests)))} requests, ${formatNumber(sum(rollups.map(r => r.errors)))} errors`],
+            ['Latency p50 / p99', [rollups.map(r => r.p50_ms), rollups.map(r => r.p99_ms)], ['graph-bandwidth', 'graph-errors'],
+                `worst p99 ${Math.max(...rollups.map(r => r.p99_ms)).toFixed(1)} ms`],
+            ['Cage restarts / threats', [rollups.map(r => r.cage_restarts), rollups.map(r => r.threats)], ['graph-requests', 'graph-errors'],
+                `${formatNumber(sum(rollups.map(r => r.cage_restarts)))} restarts, ${formatNumber(sum(rollups.map(r => r.threats)))} threats`],
+        ].forEach(([title, series, classes, legend]) => {
+            const card = document.createElement('div');
+            card.className = 'tenant-graph-card';
+
+            const heading = document.createElement('div');
+            heading.className = 'tenant-graph-title';
+            heading.textContent = title;
+            card.appendChild(heading);
+
+            card.appendChild(sparkline(series, classes));
+            card.appendChild(graphLegend(legend));
+            graphs.appendChild(card);
+        });
+    } catch (error) {
+        console.error('Failed to load metrics history:', error);
+    }
+}
+
+// Load the logged-in tenant's sites, quotas, graphs and security events
+async function refreshTenantView() {
+    const tenantId = encodeURIComponent(currentUser.tenant);
+
+    try {
+        const [overviewResponse, telemetryResponse] = await Promise.all([
+            fetch(`/api/tenants/${tenantId}/overview`, { headers: { 'Authorization': `Bearer ${currentUser.token}` } }),
+            fetch(`/api/tenants/${tenantId}/telemetry`, { headers: { 'Authorization': `Bearer ${currentUser.token}` } }),
+        ]);
+        if (!overviewResponse.ok || !telemetryResponse.ok) {
+            return;
+        }
+        const overview = await overviewResponse.json();
+        const telemetry = await telemetryResponse.json();
+
+        renderTenantGauges(overview.usage);
+        renderTenantSites(overview.sites);
+        renderTenantGraphs(overview.sites, telemetry);
+        renderTenantEvents(overview.events);
+    } catch (error) {
+        console.error('Failed to load tenant view:', error);
+    }
+}
+
+function renderTenantGauges(usage) {
+    const gauges = document.getElementById('tenant-gauges');
+    gauges.innerHTML = '';
+    if (!usage) {
+        return;
+    }
+
+    [
+        ['Sites', usage.sites_used, usage.sites_limit, formatNumber],
+        ['Storage', usage.storage_used_mb, usage.storage_limit_mb, mb => formatBytes(mb * 1024 * 1024)],
+        ['Bandwidth (month)', usage.bandwidth_used_mb, usage.bandwidth_limit_mb, mb => formatBytes(mb * 1024 * 1024)],
+    ].forEach(([label, used, limit, format]) => {
+        const item = document.createElement('div');
+        item.className = 'quota-item';
+
+        const text = document.createElement('span');
+        text.textContent = limit !== null ? `${label}: ${format(used)} / ${format(limit)}` : `${label}: ${format(used)} (unlimited)`;
+        item.appendChild(text);
+
+        if (limit !== null) {
+            const percent = limit > 0 ? Math.min(100, (used / limit) * 100) : 100;
+            const bar = document.createElement('div');
+            bar.className = 'progress-bar';
+            const progress = document.createElement('div');
+            progress.className = percent >= 90 ? 'progress exceeded' : 'progress';
+            progress.style.width = `${percent}%`;
+            bar.appendChild(progress);
+            item.appendChild(bar);
+        }
+        gauges.appendChild(item);
+    });
+}
+
+function renderTenantSites(sites) {
+    const table = document.getElementById('tenant-sites');
+    if (sites.length === 0) {
+        table.innerHTML = '<tr><td colspan="4" class="log-placeholder">No sites</td></tr>';
+        return;
+    }
+
+    table.innerHTML = '';
+    sites.forEach(site => {
+        const row = document.createElement('tr');
+        const cages = site.cages
+            ? `${site.cages.healthy_cages}/${site.cages.total_cages} healthy`
+            : 'Not running';
+        const month = site.bandwidth
+            ? formatBytes(site.bandwidth.this_month.ingress_bytes + site.bandwidth.this_month.egress_bytes)
+            : '--';
+
+        [site.name, site.domain || '--', cages, month].forEach(value => {
+            const cell = document.createElement('td');
+            cell.textContent = value;
+            row.appendChild(cell);
+        });
+        if (site.cages && site.cages.healthy_cages < site.cages.total_cages) {
+            row.children[2].className = 'quota-status warning';
+        }
+        table.appendChild(row);
+    });
+}
+
+// One card per site with request and bandwidth graphs over the sampled window
+function renderTenantGraphs(sites, telemetry) {
+    const graphs = document.getElementById('tenant-graphs');
+    graphs.innerHTML = '';
+
+    sites.forEach(site => {
+        const samples = telemetry.sites[site.id] || [];
+        const card = document.createElement('div');
+        card.className = 'tenant-graph-card';
+
+        const title = document.createElement('div');
+        title.className = 'tenant-graph-title';
+        title.textContent = site.name;
+        card.appendChild(title);
+
+        if (samples.length < 2) {
+            const placeholder = document.createElement('div');
+            placeholder.className = 'log-placeholder';
+            placeholder.textContent = 'Collecting samples...';
+            card.appendChild(placeholder);
+            graphs.appendChild(card);
+            return;
+        }
+
+        const requests = samples.map(s => s.requests);
+        const errors = samples.map(s => s.errors);
+        const bytes = samples.map(s => s.ingress_bytes + s.egress_bytes);
+        const span = samples.length * telemetry.interval_secs;
+
+        card.appendChild(graphLegend(`Requests (last ${formatUptime(span)}): ${formatNumber(sum(requests))}, errors: ${formatNumber(sum(errors))}`));
+        card.appendChild(sparkline([requests, errors], ['graph-requests', 'graph-errors']));
+        card.appendChild(graphLegend(`Bandwidth: ${formatBytes(sum(bytes))}`));
+        card.appendChild(sparkline([bytes], ['graph-bandwidth']));
+        graphs.appendChild(card);
+    });
+}
+
+function renderTenantEvents(events) {
+    const log = document.getElementById('tenant-events');
+    if (events.length === 0) {
+        log.innerHTML = '<div class="log-placeholder">No threats detected</div>';
+        return;
+    }
+
+    log.innerHTML = '';
+    events.forEach(event => {
+        const entry = document.createElement('div');
+        entry.className = `event-entry ${event.severity}`;
+
+        const time = new Date(event.timestamp * 1000).toLocaleTimeString();
+        entry.innerHTML = `
+            <span class="event-time">${time}</span>
+            <span class="event-kind">${event.kind.replace('_', ' ')}</span>
+            <span class="event-action ${event.action}">${event.action}</span>
+            <div class="event-detail"></div>
+        `;
+        // Paths and details come from clients, so never render them as HTML
+        entry.querySelector('.event-detail').textContent =
+            `${event.site_id} ${event.path} from ${event.ip || 'unknown'}${event.details ? ' - ' + event.details : ''}`;
+
+        log.appendChild(entry);
+    });
+}
+
+// Prepend healing events to the Supervisor timeline, newest first
+function addHealingEvents(events) {
+    const log = document.getElementById('healing-timeline');
+    const placeholder = log.querySelector('.log-placeholder');
+    if (placeholder) {
+        placeholder.remove();
+    }
+
+    events.forEach(event => {
+        const entry = document.createElement('div');
+        entry.className = `event-entry ${event.outcome === 'healed' ? '' : event.outcome === 'partial' ? 'warning' : 'critical'}`;
+
+        const time = new Date(event.timestamp * 1000).toLocaleTimeString();
+        entry.innerHTML = `
+            <span class="event-time">${time}</span>
+            <span class="event-kind">${event.outcome}</span>
+            <div class="event-detail"></div>
+        `;
+        entry.querySelector('.event-detail').textContent =
+            `${event.site_id} attempt ${event.attempt}: removed ${event.cages_removed}, spawned ${event.cages_spawned}` +
+            ` after ${event.backoff_ms}ms backoff${event.error ? ' - ' + event.error : ''}`;
+
+        log.insertBefore(entry, log.firstChild);
+    });
+
+    while (log.children.length > HEALING_TIMELINE_LEN) {
+        log.removeChild(log.lastChild);
+    }
+}
+
+// Draw series as polylines sharing one vertical scale
+function sparkline(series, classes) {
+    const width = 300;
+    const height = 60;
+    const svgNs = 'http://www.w3.org/2000/svg';
+    const svg = document.createElementNS(svgNs, 'svg');
+    svg.setAttribute('viewBox', `0 0 ${width} ${height}`);
+    svg.setAttribute('preserveAspectRatio', 'none');
+    svg.setAttribute('class', 'sparkline');
+
+    const max = Math.max(1, ...series.flat());
+    series.forEach((values, i) => {
+        const step = width / Math.max(1, values.length - 1);
+        const points = values
+            .map((value, x) => `${(x * step).toFixed(1)},${(height - (value / max) * (height - 2) - 1).toFixed(1)}`)
+            .join(' ');
+        const line = document.createElementNS(svgNs, 'polyline');
+        line.setAttribute('points', points);
+        line.setAttribute('class', classes[i]);
+        svg.appendChild(line);
+    });
+    return svg;
+}
+
+function graphLegend(text) {
+    const legend = document.createElement('div');
+    legend.className = 'graph-legend';
+    legend.textContent = text;
+    return legend;
+}
+
+function sum(values) {
+    return values.reduce((total, value) => total + value, 0);
+}
+
+// Load a site's path access rules into the table and editor
+async function loadSiteAcl() {
+    const siteId = document.getElementById('acl-site').value.trim();
+    if (!siteId) {
+        return;
+    }
+
+    try {
+        const response = await fetch(`/api/sites/${encodeURIComponent(siteId)}/acl`);
+        const result = await response.json();
+        if (!response.ok) {
+            setAclStatus(result.error, true);
+            return;
+        }
+        renderAclRules(result.rules);
+        document.getElementById('acl-editor').value = JSON.stringify(result.rules, null, 2);
+        setAclStatus('');
+    } catch (error) {
+        console.error('Failed to load access rules:', error);
+    }
+}
+
+// Replace a site's rules with the editor contents
+// Credentials shown as ******** keep their stored hashes
+async function saveSiteAcl() {
+    const siteId = document.getElementById('acl-site').value.trim();
+    if (!siteId) {
+        return;
+    }
+
+    let rules;
+    try {
+        rules = JSON.parse(document.getElementById('acl-editor').value || '[]');
+    } catch (error) {
+        setAclStatus('Rules are not valid JSON', true);
+        return;
+    }
+
+    try {
+        const response = await fetch(`/api/sites/${encodeURIComponent(siteId)}/acl`, {
+            method: 'PUT',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify(rules)
+        });
+        const result = await response.json();
+        if (!response.ok) {
+            setAclStatus(result.error, true);
+            return;
+        }
+        renderAclRules(result.rules);
+        setAclStatus('Saved');
+    } catch (error) {
+        console.error('Failed to save access rules:', error);
+    }
+}
+
+function renderAclRules(rules) {
+    const table = document.getElementById('acl-rules');
+    if (rules.length === 0) {
+        table.innerHTML = '<tr><td colspan="4" class="log-placeholder">No rules for this site</td></tr>';
+        return;
+    }
+
+    table.innerHTML = '';
+    rules.forEach(rule => {
+        const row = document.createElement('tr');
+        const users = Object.keys(rule.users);
+        const credentials = [
+            users.length > 0 ? `Basic: ${users.join(', ')}` : null,
+            rule.tokens.length > 0 ? `${rule.tokens.length} token(s)` : null,
+        ].filter(Boolean).join('; ') || 'None';
+
+        [
+            rule.path,
+            rule.allow.join(', ') || 'Any',
+            rule.deny.join(', ') || 'None',
+            credentials,
+        ].forEach(value => {
+            const cell = document.createElement('td');
+            cell.textContent = value;
+            row.appendChild(cell);
+        });
+        table.appendChild(row);
+    });
+}
+
+function setAclStatus(message, isError = false) {
+    const status = document.getElementById('acl-status');
+    status.textContent = message;
+    status.className = isError ? 'acl-status error' : 'acl-status';
+}
+
+// Format byte counts with binary units
+function formatBytes(bytes) {
+    const units = ['B', 'KB', 'MB', 'GB', 'TB'];
+    let value = bytes;
+    let unit = 0;
+    while (value >= 1024 && unit < units.length - 1) {
+        value /= 1024;
+        unit++;
+    }
+    return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
+// Format milliseconds, switching to seconds for slow responses
+function formatLatency(ms) {
+    return ms >= 1000 ? `${(ms / 1000).toFixed(2)} s` : `${ms.toFixed(1)} ms`;
+}
+
 // Format large numbers with commas
 function formatNumber(num) {
     return num.toLocaleString();
@@ -0,0 +1,19 @@
+<?php
+// Entry point for php-cgi; files in the site directory are read-only at /var/www.
+// `pear dev --site <site>` pushes every save.
+require __DIR__ . '/lib/greeting.php';
+
+$path = parse_url($_SERVER['REQUEST_URI'] ?? '/', PHP_URL_PATH);
+?>
+<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>{{name}}</title>
+    <link rel="stylesheet" href="/assets/style.css">
+</head>
+<body>
+    <h1><?= htmlspecialchars(greeting('{{name}}')) ?></h1>
+    <p>You asked for <code><?= htmlspecialchars($path) ?></code>.</p>
+</body>
+</html>
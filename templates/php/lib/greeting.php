@@ -0,0 +1,6 @@
+<?php
+
+function greeting(string $site): string
+{
+    return "Hello from {$site}!";
+}
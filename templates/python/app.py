@@ -0,0 +1,22 @@
+"""{{name}}: a WSGI application for Pear Server.
+
+The Python runtime runs this file once per request as a CGI script; `application`
+is any WSGI callable, so a Flask or Bottle app can take its place. Files in the site
+directory are read-only at /var/www. `pear dev --site <site>` pushes every save.
+"""
+
+from wsgiref.handlers import CGIHandler
+
+
+def application(environ, start_response):
+    path = environ.get("PATH_INFO") or "/"
+    body = f"Hello from {{name}}! You asked for {path}\n".encode()
+    start_response("200 OK", [
+        ("Content-Type", "text/plain; charset=utf-8"),
+        ("Content-Length", str(len(body))),
+    ])
+    return [body]
+
+
+if __name__ == "__main__":
+    CGIHandler().run(application)
@@ -0,0 +1,45 @@
+//! {{name}}: a Pear Server site
+//!
+//! Cages call `handle_stream` once per request, with the length of the request. The
+//! request is JSON (`method`, `uri`, `request_id`) copied in by `read_request`; the
+//! response body is written with `write` and sent to the client with `flush`.
+//!
+//! Build with `pear build`, or run `pear dev --site <site>` to redeploy on every save.
+
+#[link(wasm_import_module = "pear_stream")]
+extern "C" {
+    fn read_request(ptr: *mut u8, len: i32) -> i32;
+    fn write(ptr: *const u8, len: i32);
+    fn flush() -> i32;
+}
+
+/// Send a chunk of the response; false once the client has gone away
+fn send(chunk: &str) -> bool {
+    unsafe {
+        write(chunk.as_ptr(), chunk.len() as i32);
+        flush() == 0
+    }
+}
+
+/// A string field of the request
+fn field<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("\"{}\":\"", name);
+    let start = request.find(&key)? + key.len();
+    let end = start + request[start..].find('"')?;
+    Some(&request[start..end])
+}
+
+#[no_mangle]
+pub extern "C" fn handle_stream(request_len: i32) -> i32 {
+    let mut request = vec![0u8; request_len.max(0) as usize];
+    let read = unsafe { read_request(request.as_mut_ptr(), request_len) };
+    request.truncate(read.max(0) as usize);
+    let request = String::from_utf8_lossy(&request);
+
+    let method = field(&request, "method").unwrap_or("GET");
+    let uri = field(&request, "uri").unwrap_or("/");
+    if !send(&format!("Hello from {{name}}! {} {}\n", method, uri)) {
+        return 1;
+    }
+    0
+}